@@ -0,0 +1,188 @@
+//! Errors of system calls, independent of the ABI that reports them
+//!
+//! The kernel fails in several vocabularies: VFS errors
+//! ([`FileSystemErrorKind`]), errors of stream objects ([`StreamError`]),
+//! and the `&'static str` messages of the rest of the kernel. They are
+//! brought to one [`KernelError`], which each ABI turns into its own error
+//! value with [`AbiModule::error_code`](super::AbiModule::error_code): the
+//! Linux ABI returns `-errno`, the native ABIs usize::MAX.
+//!
+//! Native system calls return only usize::MAX on failure. The reason is
+//! recorded on the task with [`fail`], so that an ABI delegating to a
+//! native call can report the error the call actually met (see
+//! [`take_error`]).
+
+use crate::fs::FileSystemErrorKind;
+use crate::object::capability::StreamError;
+use crate::task::mytask;
+
+/// Why a system call failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KernelError {
+    /// The caller lacks a privilege the operation needs
+    NotPermitted,
+    /// The access is denied by the permissions of the object
+    AccessDenied,
+    NotFound,
+    NoSuchTask,
+    Interrupted,
+    Io,
+    BadHandle,
+    NoChild,
+    WouldBlock,
+    NoMemory,
+    /// A user pointer is not mapped or not writable
+    BadAddress,
+    Busy,
+    Exists,
+    CrossDevice,
+    NotADirectory,
+    IsADirectory,
+    InvalidArgument,
+    TooManyHandles,
+    NotATerminal,
+    FileTooLarge,
+    NoSpace,
+    NotSeekable,
+    ReadOnly,
+    BrokenPipe,
+    OutOfRange,
+    NameTooLong,
+    NotImplemented,
+    NotEmpty,
+    NotSupported,
+    NotExecutable,
+    /// A cycle, such as an epoll set that would contain itself
+    Loop,
+    /// The object is not a socket
+    NotASocket,
+    /// A datagram larger than the socket can hold
+    MessageTooLong,
+    /// The name is taken by another socket
+    AddressInUse,
+    /// The socket is already connected
+    AlreadyConnected,
+    /// The socket is not connected, and no destination was given
+    NotConnected,
+    /// Nothing listens or receives under the name
+    ConnectionRefused,
+    /// The time given to wait ran out
+    TimedOut,
+    /// No route leads to the destination
+    NetworkUnreachable,
+    /// The peer reset the connection
+    ConnectionReset,
+    /// A connection was started and completes later
+    InProgress,
+}
+
+impl KernelError {
+    /// The error described by a kernel error message
+    ///
+    /// The messages of the kernel are free text; the words they use for the
+    /// common failures are recognized, anything else is an invalid argument.
+    pub fn from_message(message: &str) -> Self {
+        let says = |word: &str| message.as_bytes().windows(word.len()).any(|w| w.eq_ignore_ascii_case(word.as_bytes()));
+        if message == "Operation not permitted" || says("privilege") {
+            KernelError::NotPermitted
+        } else if says("permission denied") {
+            KernelError::AccessDenied
+        } else if says("no such task") || says("task not found") {
+            KernelError::NoSuchTask
+        } else if says("not found") || says("no such") {
+            KernelError::NotFound
+        } else if says("pointer") || says("address") {
+            KernelError::BadAddress
+        } else if says("out of memory") || says("allocate") {
+            KernelError::NoMemory
+        } else if says("unsupported") || says("not supported") {
+            KernelError::NotSupported
+        } else if says("not implemented") {
+            KernelError::NotImplemented
+        } else if says("broken pipe") {
+            KernelError::BrokenPipe
+        } else if says("interrupted") {
+            KernelError::Interrupted
+        } else if says("busy") {
+            KernelError::Busy
+        } else if says("exists") {
+            KernelError::Exists
+        } else {
+            KernelError::InvalidArgument
+        }
+    }
+}
+
+impl From<&FileSystemErrorKind> for KernelError {
+    fn from(kind: &FileSystemErrorKind) -> Self {
+        match kind {
+            FileSystemErrorKind::NotFound | FileSystemErrorKind::InvalidPath => KernelError::NotFound,
+            FileSystemErrorKind::NoSpace => KernelError::NoSpace,
+            FileSystemErrorKind::PermissionDenied => KernelError::AccessDenied,
+            FileSystemErrorKind::AlreadyExists | FileSystemErrorKind::FileExists => KernelError::Exists,
+            FileSystemErrorKind::NotADirectory => KernelError::NotADirectory,
+            FileSystemErrorKind::IsADirectory => KernelError::IsADirectory,
+            FileSystemErrorKind::ReadOnly => KernelError::ReadOnly,
+            FileSystemErrorKind::NotSupported => KernelError::NotSupported,
+            FileSystemErrorKind::Busy => KernelError::Busy,
+            FileSystemErrorKind::DirectoryNotEmpty => KernelError::NotEmpty,
+            FileSystemErrorKind::InvalidOperation | FileSystemErrorKind::NotAFile => KernelError::InvalidArgument,
+            FileSystemErrorKind::CrossDevice => KernelError::CrossDevice,
+            FileSystemErrorKind::IoError
+            | FileSystemErrorKind::InvalidData
+            | FileSystemErrorKind::DeviceError
+            | FileSystemErrorKind::BrokenFileSystem => KernelError::Io,
+        }
+    }
+}
+
+impl From<&StreamError> for KernelError {
+    fn from(err: &StreamError) -> Self {
+        match err {
+            StreamError::WouldBlock => KernelError::WouldBlock,
+            StreamError::Closed => KernelError::BadHandle,
+            StreamError::InvalidArgument | StreamError::NotSupported => KernelError::InvalidArgument,
+            StreamError::Interrupted => KernelError::Interrupted,
+            StreamError::PermissionDenied => KernelError::AccessDenied,
+            StreamError::NotPermitted => KernelError::NotPermitted,
+            StreamError::NoSpace => KernelError::NoSpace,
+            StreamError::BrokenPipe => KernelError::BrokenPipe,
+            StreamError::SeekError => KernelError::NotSeekable,
+            StreamError::FileSystemError(fs_err) => KernelError::from(&fs_err.kind),
+            StreamError::IoError | StreamError::EndOfStream | StreamError::DeviceError | StreamError::Other(_) => {
+                KernelError::Io
+            }
+        }
+    }
+}
+
+/// Fail a native system call with `error`: the error is recorded on the
+/// current task and usize::MAX is returned
+pub fn fail(error: KernelError) -> usize {
+    if let Some(task) = mytask() {
+        task.last_error = Some(error);
+    }
+    usize::MAX
+}
+
+/// Take the error recorded by the last native system call that failed
+/// during the current system call
+pub fn take_error() -> Option<KernelError> {
+    mytask().and_then(|task| task.last_error.take())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_kernel_error_conversions() {
+        assert_eq!(KernelError::from(&FileSystemErrorKind::InvalidPath), KernelError::NotFound);
+        assert_eq!(KernelError::from(&StreamError::Closed), KernelError::BadHandle);
+        assert_eq!(KernelError::from_message("Operation not permitted"), KernelError::NotPermitted);
+        assert_eq!(KernelError::from_message("Permission denied"), KernelError::AccessDenied);
+        assert_eq!(KernelError::from_message("Invalid user pointer"), KernelError::BadAddress);
+        assert_eq!(KernelError::from_message("Unsupported control command"), KernelError::NotSupported);
+        assert_eq!(KernelError::from_message("Bad value"), KernelError::InvalidArgument);
+    }
+}
@@ -0,0 +1,12 @@
+//! Linux ABI module.
+//!
+//! Runs Linux programs on Scarlet. The reference target is a statically
+//! linked busybox: the system calls it needs to start a shell, run applets
+//! and fork and exec other programs are implemented, most of them on top
+//! of the native system calls.
+//!
+//! Programs see the Linux syscall numbers and structure layouts and get
+//! `-errno` back on failure. File descriptors are kept by the ABI and refer
+//! to handles in the task's handle table, as in the xv6 ABI.
+
+pub mod riscv64;
@@ -0,0 +1,285 @@
+//! RV32 compatibility layer of the Linux ABI
+//!
+//! Runs 32-bit RISC-V Linux programs on the 64-bit kernel. The hart runs
+//! them with a 32-bit U-mode (sstatus.UXL, see [`Xlen`]) and they make the
+//! system calls of the riscv32 port of Linux: the generic table without the
+//! calls that have a 64-bit time variant (`clock_gettime64`, ...), with
+//! `_llseek`, `mmap2` and `statx` in place of `lseek`, `mmap` and `fstat`.
+//!
+//! Most calls are the riscv64 ones, run on the same state. In 32-bit mode a
+//! register holds its value sign-extended to 64 bits, so the arguments are
+//! first normalized as the syscall table describes them: `Int` arguments
+//! keep their sign, all others are zero-extended. Calls passing structures
+//! with words or pointers in them have compat variants that read the 32-bit
+//! layout.
+//!
+//! The images of these programs stay below 2 GiB, stack included (see
+//! `aslr::compat_stack_top`), where an address reads the same however the
+//! register holding it is extended.
+
+use alloc::{boxed::Box, string::{String, ToString}, sync::Arc};
+
+use crate::{
+    abi::{
+        error::KernelError,
+        linux::riscv64::{
+            errno, execute_elf, elf_confidence, negotiate_linux_version,
+            fs::{
+                compat_sys_ftruncate64, compat_sys_ioctl, compat_sys_llseek, compat_sys_readv, compat_sys_writev,
+                sys_chdir, sys_close, sys_dup, sys_dup3, sys_eventfd2, sys_faccessat, sys_fcntl, sys_getcwd,
+                sys_getdents64, sys_memfd_create, sys_mkdirat, sys_openat, sys_pipe2, sys_read, sys_splice, sys_statx,
+                sys_unlinkat, sys_write,
+            },
+            mm::{compat_sys_mmap2, sys_brk, sys_madvise, sys_mprotect, sys_munmap},
+            poll::{compat_sys_pselect6, sys_epoll_create1, sys_epoll_ctl, sys_epoll_pwait, sys_ppoll},
+            proc::{
+                compat_sys_execve, compat_sys_waitid, sys_clock_gettime, sys_clock_nanosleep, sys_clock_settime, sys_clone, sys_exit,
+                sys_getegid, sys_geteuid, sys_getgid, sys_getgroups, sys_getpgid, sys_getpid, sys_getppid,
+                sys_getrandom, sys_getresgid, sys_getresuid, sys_getsid, sys_gettid, sys_getuid, sys_kill, sys_sched_yield,
+                sys_set_tid_address, sys_setgid, sys_setgroups, sys_sethostname, sys_setpgid, sys_setregid,
+                sys_setresgid, sys_setresuid, sys_setreuid, sys_setsid, sys_setuid, sys_timerfd_create,
+                sys_timerfd_gettime, sys_timerfd_settime, sys_uname,
+            },
+            signal::{compat_sys_rt_sigaction, sys_rt_sigprocmask},
+            shm::{compat_sys_shmctl, sys_shmat, sys_shmdt, sys_shmget},
+            socket::{
+                compat_sys_recvmsg, compat_sys_sendmsg, sys_accept, sys_accept4, sys_bind, sys_connect,
+                sys_getpeername, sys_getsockname, sys_getsockopt, sys_listen, sys_recvfrom, sys_sendto,
+                sys_setsockopt, sys_shutdown, sys_socket, sys_socketpair,
+            },
+            LinuxRiscv64Abi,
+        },
+        AbiModule, AbiVersion, PersonalityNode,
+    },
+    arch::{vcpu::Xlen, Trapframe},
+    fs::VfsManager,
+    task::{elf_loader::{note::AbiNote, ELFCLASS32}, strace::ArgKind, Task},
+};
+
+#[derive(Clone, Default)]
+pub struct LinuxRiscv32Abi {
+    /// Descriptors and signal dispositions, used by the riscv64 calls
+    linux: LinuxRiscv64Abi,
+}
+
+/// Extend the 32-bit arguments of a call to what the riscv64 calls expect
+fn normalize_args(trapframe: &mut Trapframe) {
+    let kinds = syscall_info(trapframe.get_arg(7)).and_then(|info| info.args).unwrap_or(&[]);
+    for i in 0..6 {
+        let value = trapframe.get_arg(i) as u32;
+        let value = match kinds.get(i) {
+            Some(ArgKind::Int) => value as i32 as isize as usize,
+            _ => value as usize,
+        };
+        trapframe.set_arg(i, value);
+    }
+}
+
+impl AbiModule for LinuxRiscv32Abi {
+    fn name() -> &'static str {
+        "linux-riscv32"
+    }
+
+    fn get_name(&self) -> String {
+        Self::name().to_string()
+    }
+
+    fn clone_boxed(&self) -> Box<dyn AbiModule + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn handle_syscall(&mut self, trapframe: &mut Trapframe) -> Result<usize, &'static str> {
+        // The upper halves of the registers are not seen by the program
+        normalize_args(trapframe);
+        syscall_handler(&mut self.linux, trapframe)
+    }
+
+    fn syscall_info(&self, number: usize) -> Option<crate::task::strace::SyscallInfo> {
+        syscall_info(number)
+    }
+
+    fn syscall_error_formatter(&self) -> crate::task::strace::SyscallErrorFn {
+        errno::format_error
+    }
+
+    fn error_code(&self, error: KernelError) -> usize {
+        errno::error(errno::from_error(error))
+    }
+
+    fn can_execute_binary(
+        &self,
+        file_object: &crate::object::KernelObject,
+        file_path: &str,
+        current_abi: Option<&(dyn AbiModule + Send + Sync)>
+    ) -> Option<u8> {
+        elf_confidence(Self::name(), ELFCLASS32, file_object, file_path, current_abi)
+    }
+
+    fn version(&self) -> AbiVersion {
+        self.linux.version()
+    }
+
+    fn negotiate_version(&self, note: &AbiNote) -> Option<AbiVersion> {
+        negotiate_linux_version(Self::name(), note)
+    }
+
+    fn execute_binary(
+        &self,
+        file_object: &crate::object::KernelObject,
+        argv: &[&str],
+        envp: &[&str],
+        task: &mut Task,
+        trapframe: &mut Trapframe
+    ) -> Result<(), &'static str> {
+        execute_elf(file_object, argv, envp, task, trapframe, Xlen::X32)
+    }
+
+    fn get_default_cwd(&self) -> &str {
+        "/"
+    }
+
+    fn setup_overlay_environment(
+        &self,
+        target_vfs: &Arc<VfsManager>,
+        base_vfs: &Arc<VfsManager>,
+        system_path: &str,
+        config_path: &str,
+    ) -> Result<(), &'static str> {
+        self.linux.setup_overlay_environment(target_vfs, base_vfs, system_path, config_path)
+    }
+
+    fn personality_nodes(&self) -> &'static [PersonalityNode] {
+        self.linux.personality_nodes()
+    }
+
+    fn initialize_from_existing_handles(&mut self, task: &mut Task) -> Result<(), &'static str> {
+        self.linux.initialize_from_existing_handles(task)
+    }
+
+    fn get_interpreter_path(&self, requested_interpreter: &str) -> String {
+        requested_interpreter.to_string()
+    }
+}
+
+// Arguments that may be negative are described as `Int` so that they keep
+// their sign (see `normalize_args`)
+syscall_table! {
+    Getcwd = 17 (Hex, Uint) -> Hex => sys_getcwd,
+    Eventfd2 = 19 (Uint, Hex) -> Int => sys_eventfd2,
+    EpollCreate1 = 20 (Hex) -> Int => sys_epoll_create1,
+    EpollCtl = 21 (Int, Int, Int, Hex) -> Int => sys_epoll_ctl,
+    EpollPwait = 22 (Int, Hex, Int, Int, Hex, Uint) -> Int => sys_epoll_pwait,
+    Dup = 23 (Int) -> Int => sys_dup,
+    Dup3 = 24 (Int, Int, Hex) -> Int => sys_dup3,
+    Fcntl64 = 25 (Int, Int, Hex) -> Int => sys_fcntl,
+    Ioctl = 29 (Int, Hex, Hex) -> Int => compat_sys_ioctl,
+    Mkdirat = 34 (Int, Str, Hex) -> Int => sys_mkdirat,
+    Unlinkat = 35 (Int, Str, Hex) -> Int => sys_unlinkat,
+    Ftruncate64 = 46 (Int, Uint, Uint) -> Int => compat_sys_ftruncate64,
+    Faccessat = 48 (Int, Str, Hex) -> Int => sys_faccessat,
+    Chdir = 49 (Str) -> Int => sys_chdir,
+    Openat = 56 (Int, Str, Hex, Hex) -> Int => sys_openat,
+    Close = 57 (Int) -> Int => sys_close,
+    Pipe2 = 59 (Hex, Hex) -> Int => sys_pipe2,
+    Getdents64 = 61 (Int, Hex, Uint) -> Int => sys_getdents64,
+    Llseek = 62 (Int, Hex, Hex, Hex, Uint) -> Int => compat_sys_llseek,
+    Read = 63 (Int, Hex, Uint) -> Int => sys_read,
+    Write = 64 (Int, Buf, Uint) -> Int => sys_write,
+    Readv = 65 (Int, Hex, Uint) -> Int => compat_sys_readv,
+    Writev = 66 (Int, Hex, Uint) -> Int => compat_sys_writev,
+    Splice = 76 (Int, Hex, Int, Hex, Uint, Hex) -> Int => sys_splice,
+    TimerfdCreate = 85 (Int, Hex) -> Int => sys_timerfd_create,
+    Exit = 93 (Int) -> Int => sys_exit,
+    ExitGroup = 94 (Int) -> Int => sys_exit,
+    Waitid = 95 (Int, Int, Hex, Hex, Hex) -> Int => compat_sys_waitid,
+    SetTidAddress = 96 (Hex) -> Int => sys_set_tid_address,
+    SchedYield = 124 => sys_sched_yield,
+    Kill = 129 (Int, Int) -> Int => sys_kill,
+    RtSigaction = 134 (Int, Hex, Hex, Uint) -> Int => compat_sys_rt_sigaction,
+    RtSigprocmask = 135 (Int, Hex, Hex, Uint) -> Int => sys_rt_sigprocmask,
+    Setregid = 143 => sys_setregid,
+    Setgid = 144 => sys_setgid,
+    Setreuid = 145 => sys_setreuid,
+    Setuid = 146 => sys_setuid,
+    Setresuid = 147 => sys_setresuid,
+    Getresuid = 148 => sys_getresuid,
+    Setresgid = 149 => sys_setresgid,
+    Getresgid = 150 => sys_getresgid,
+    Setpgid = 154 (Int, Int) -> Int => sys_setpgid,
+    Getpgid = 155 (Int) -> Int => sys_getpgid,
+    Getsid = 156 (Int) -> Int => sys_getsid,
+    Setsid = 157 => sys_setsid,
+    Getgroups = 158 (Int, Hex) -> Int => sys_getgroups,
+    Setgroups = 159 (Uint, Hex) -> Int => sys_setgroups,
+    Uname = 160 (Hex) -> Int => sys_uname,
+    Sethostname = 161 (Buf, Uint) -> Int => sys_sethostname,
+    Getpid = 172 => sys_getpid,
+    Getppid = 173 => sys_getppid,
+    Getuid = 174 => sys_getuid,
+    Geteuid = 175 => sys_geteuid,
+    Getgid = 176 => sys_getgid,
+    Getegid = 177 => sys_getegid,
+    Gettid = 178 => sys_gettid,
+    Shmget = 194 (Hex, Uint, Hex) -> Int => sys_shmget,
+    Shmctl = 195 (Int, Int, Hex) -> Int => compat_sys_shmctl,
+    Shmat = 196 (Int, Hex, Hex) -> Hex => sys_shmat,
+    Shmdt = 197 (Hex) -> Int => sys_shmdt,
+    Socket = 198 (Int, Hex, Int) -> Int => sys_socket,
+    Socketpair = 199 (Int, Hex, Int, Hex) -> Int => sys_socketpair,
+    Bind = 200 (Int, Hex, Uint) -> Int => sys_bind,
+    Listen = 201 (Int, Int) -> Int => sys_listen,
+    Accept = 202 (Int, Hex, Hex) -> Int => sys_accept,
+    Connect = 203 (Int, Hex, Uint) -> Int => sys_connect,
+    Getsockname = 204 (Int, Hex, Hex) -> Int => sys_getsockname,
+    Getpeername = 205 (Int, Hex, Hex) -> Int => sys_getpeername,
+    Sendto = 206 (Int, Buf, Uint, Hex, Hex, Uint) -> Int => sys_sendto,
+    Recvfrom = 207 (Int, Hex, Uint, Hex, Hex, Hex) -> Int => sys_recvfrom,
+    Setsockopt = 208 (Int, Int, Int, Hex, Uint) -> Int => sys_setsockopt,
+    Getsockopt = 209 (Int, Int, Int, Hex, Hex) -> Int => sys_getsockopt,
+    Shutdown = 210 (Int, Int) -> Int => sys_shutdown,
+    Sendmsg = 211 (Int, Hex, Hex) -> Int => compat_sys_sendmsg,
+    Recvmsg = 212 (Int, Hex, Hex) -> Int => compat_sys_recvmsg,
+    Brk = 214 (Hex) -> Hex => sys_brk,
+    Munmap = 215 (Hex, Uint) -> Int => sys_munmap,
+    Clone = 220 (Hex, Hex, Hex, Hex, Hex) -> Int => sys_clone,
+    // argv and envp are shown as addresses: tracing reads 64-bit arrays
+    Execve = 221 (Str, Hex, Hex) -> Int => compat_sys_execve,
+    Mmap2 = 222 (Hex, Uint, Hex, Hex, Int, Uint) -> Hex => compat_sys_mmap2,
+    Mprotect = 226 (Hex, Uint, Hex) -> Int => sys_mprotect,
+    Madvise = 233 (Hex, Uint, Int) -> Int => sys_madvise,
+    Accept4 = 242 (Int, Hex, Hex, Hex) -> Int => sys_accept4,
+    Getrandom = 278 (Hex, Uint, Hex) -> Int => sys_getrandom,
+    MemfdCreate = 279 (Str, Hex) -> Int => sys_memfd_create,
+    Statx = 291 (Int, Str, Hex, Hex, Hex) -> Int => sys_statx,
+    ClockGettime64 = 403 (Int, Hex) -> Int => sys_clock_gettime,
+    ClockSettime64 = 404 (Int, Hex) -> Int => sys_clock_settime,
+    ClockNanosleepTime64 = 407 (Int, Hex, Hex, Hex) -> Int => sys_clock_nanosleep,
+    TimerfdGettime64 = 410 (Int, Hex) -> Int => sys_timerfd_gettime,
+    TimerfdSettime64 = 411 (Int, Hex, Hex, Hex) -> Int => sys_timerfd_settime,
+    Pselect6Time64 = 413 (Int, Hex, Hex, Hex, Hex, Hex) -> Int => compat_sys_pselect6,
+    PpollTime64 = 414 (Hex, Uint, Hex, Hex, Uint) -> Int => sys_ppoll,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_compat_args_normalized() {
+        let mut trapframe = Trapframe::new();
+        // openat(AT_FDCWD, 0x8000_1000, O_RDONLY) with sign-extended registers
+        trapframe.set_arg(7, 56);
+        trapframe.set_arg(0, -100isize as usize);
+        trapframe.set_arg(1, 0xffff_ffff_8000_1000);
+        trapframe.set_arg(2, 0);
+        normalize_args(&mut trapframe);
+        assert_eq!(trapframe.get_arg(0), -100isize as usize);
+        assert_eq!(trapframe.get_arg(1), 0x8000_1000);
+
+        // Undescribed calls get every argument zero-extended
+        trapframe.set_arg(7, 143);
+        trapframe.set_arg(0, usize::MAX);
+        normalize_args(&mut trapframe);
+        assert_eq!(trapframe.get_arg(0), 0xffff_ffff);
+    }
+}
@@ -0,0 +1,190 @@
+//! Linux error numbers
+//!
+//! System calls of the Linux ABI return `-errno` on failure. Errors are
+//! brought to a `KernelError` first (see `crate::abi::error`), which
+//! [`from_error`] maps onto the closest number.
+
+use alloc::{format, string::String};
+
+use crate::abi::error::KernelError;
+use crate::fs::FileSystemErrorKind;
+use crate::object::capability::StreamError;
+
+pub const EPERM: usize = 1;
+pub const ENOENT: usize = 2;
+pub const ESRCH: usize = 3;
+pub const EINTR: usize = 4;
+pub const EIO: usize = 5;
+pub const ENOEXEC: usize = 8;
+pub const EBADF: usize = 9;
+pub const ECHILD: usize = 10;
+pub const EAGAIN: usize = 11;
+pub const ENOMEM: usize = 12;
+pub const EACCES: usize = 13;
+pub const EFAULT: usize = 14;
+pub const EBUSY: usize = 16;
+pub const EEXIST: usize = 17;
+pub const EXDEV: usize = 18;
+pub const ENODEV: usize = 19;
+pub const ENOTDIR: usize = 20;
+pub const EISDIR: usize = 21;
+pub const EINVAL: usize = 22;
+pub const EMFILE: usize = 24;
+pub const ENOTTY: usize = 25;
+pub const EFBIG: usize = 27;
+pub const ENOSPC: usize = 28;
+pub const ESPIPE: usize = 29;
+pub const EROFS: usize = 30;
+pub const EPIPE: usize = 32;
+pub const ERANGE: usize = 34;
+pub const ENAMETOOLONG: usize = 36;
+pub const ENOSYS: usize = 38;
+pub const ENOTEMPTY: usize = 39;
+pub const ELOOP: usize = 40;
+pub const ENOTSOCK: usize = 88;
+pub const EMSGSIZE: usize = 90;
+pub const EPROTOTYPE: usize = 91;
+pub const ENOPROTOOPT: usize = 92;
+pub const EPROTONOSUPPORT: usize = 93;
+pub const ESOCKTNOSUPPORT: usize = 94;
+pub const EOPNOTSUPP: usize = 95;
+pub const EAFNOSUPPORT: usize = 97;
+pub const EADDRINUSE: usize = 98;
+pub const ENETUNREACH: usize = 101;
+pub const ECONNRESET: usize = 104;
+pub const EISCONN: usize = 106;
+pub const ENOTCONN: usize = 107;
+pub const ETIMEDOUT: usize = 110;
+pub const ECONNREFUSED: usize = 111;
+pub const EINPROGRESS: usize = 115;
+
+/// Return value of a system call failing with `errno`
+pub const fn error(errno: usize) -> usize {
+    errno.wrapping_neg()
+}
+
+/// The name of an errno, e.g. `ENOENT`
+pub fn name(errno: usize) -> Option<&'static str> {
+    Some(match errno {
+        EPERM => "EPERM",
+        ENOENT => "ENOENT",
+        ESRCH => "ESRCH",
+        EINTR => "EINTR",
+        EIO => "EIO",
+        ENOEXEC => "ENOEXEC",
+        EBADF => "EBADF",
+        ECHILD => "ECHILD",
+        EAGAIN => "EAGAIN",
+        ENOMEM => "ENOMEM",
+        EACCES => "EACCES",
+        EFAULT => "EFAULT",
+        EBUSY => "EBUSY",
+        EEXIST => "EEXIST",
+        EXDEV => "EXDEV",
+        ENODEV => "ENODEV",
+        ENOTDIR => "ENOTDIR",
+        EISDIR => "EISDIR",
+        EINVAL => "EINVAL",
+        EMFILE => "EMFILE",
+        ENOTTY => "ENOTTY",
+        EFBIG => "EFBIG",
+        ENOSPC => "ENOSPC",
+        ESPIPE => "ESPIPE",
+        EROFS => "EROFS",
+        EPIPE => "EPIPE",
+        ERANGE => "ERANGE",
+        ENAMETOOLONG => "ENAMETOOLONG",
+        ENOSYS => "ENOSYS",
+        ENOTEMPTY => "ENOTEMPTY",
+        ENOTSOCK => "ENOTSOCK",
+        EMSGSIZE => "EMSGSIZE",
+        EPROTOTYPE => "EPROTOTYPE",
+        ENOPROTOOPT => "ENOPROTOOPT",
+        EPROTONOSUPPORT => "EPROTONOSUPPORT",
+        ESOCKTNOSUPPORT => "ESOCKTNOSUPPORT",
+        EOPNOTSUPP => "EOPNOTSUPP",
+        EAFNOSUPPORT => "EAFNOSUPPORT",
+        EADDRINUSE => "EADDRINUSE",
+        EISCONN => "EISCONN",
+        ENOTCONN => "ENOTCONN",
+        ETIMEDOUT => "ETIMEDOUT",
+        ECONNREFUSED => "ECONNREFUSED",
+        ECONNRESET => "ECONNRESET",
+        EINPROGRESS => "EINPROGRESS",
+        _ => return None,
+    })
+}
+
+/// Render a failed system call as strace does, e.g. `-1 ENOENT`
+pub fn format_error(ret: usize) -> Option<String> {
+    let errno = ret.wrapping_neg();
+    if !(1..4096).contains(&errno) {
+        return None;
+    }
+    Some(match name(errno) {
+        Some(name) => format!("-1 {}", name),
+        None => format!("-1 errno {}", errno),
+    })
+}
+
+/// The errno of a kernel error: the errno table of the Linux ABI
+pub fn from_error(error: KernelError) -> usize {
+    match error {
+        KernelError::NotPermitted => EPERM,
+        KernelError::AccessDenied => EACCES,
+        KernelError::NotFound => ENOENT,
+        KernelError::NoSuchTask => ESRCH,
+        KernelError::Interrupted => EINTR,
+        KernelError::Io => EIO,
+        KernelError::BadHandle => EBADF,
+        KernelError::NoChild => ECHILD,
+        KernelError::WouldBlock => EAGAIN,
+        KernelError::NoMemory => ENOMEM,
+        KernelError::BadAddress => EFAULT,
+        KernelError::Busy => EBUSY,
+        KernelError::Exists => EEXIST,
+        KernelError::CrossDevice => EXDEV,
+        KernelError::NotADirectory => ENOTDIR,
+        KernelError::IsADirectory => EISDIR,
+        KernelError::InvalidArgument => EINVAL,
+        KernelError::TooManyHandles => EMFILE,
+        KernelError::NotATerminal => ENOTTY,
+        KernelError::FileTooLarge => EFBIG,
+        KernelError::NoSpace => ENOSPC,
+        KernelError::NotSeekable => ESPIPE,
+        KernelError::ReadOnly => EROFS,
+        KernelError::BrokenPipe => EPIPE,
+        KernelError::OutOfRange => ERANGE,
+        KernelError::NameTooLong => ENAMETOOLONG,
+        KernelError::NotImplemented => ENOSYS,
+        KernelError::NotEmpty => ENOTEMPTY,
+        KernelError::NotSupported => EOPNOTSUPP,
+        KernelError::NotExecutable => ENOEXEC,
+        KernelError::Loop => ELOOP,
+        KernelError::NotASocket => ENOTSOCK,
+        KernelError::MessageTooLong => EMSGSIZE,
+        KernelError::AddressInUse => EADDRINUSE,
+        KernelError::AlreadyConnected => EISCONN,
+        KernelError::NotConnected => ENOTCONN,
+        KernelError::ConnectionRefused => ECONNREFUSED,
+        KernelError::TimedOut => ETIMEDOUT,
+        KernelError::NetworkUnreachable => ENETUNREACH,
+        KernelError::ConnectionReset => ECONNRESET,
+        KernelError::InProgress => EINPROGRESS,
+    }
+}
+
+/// The errno for a kernel error message (see `KernelError::from_message`)
+pub fn from_message(message: &str) -> usize {
+    from_error(KernelError::from_message(message))
+}
+
+/// The errno for a VFS error
+pub fn from_fs_error(kind: &FileSystemErrorKind) -> usize {
+    from_error(kind.into())
+}
+
+/// The errno for an error of a stream object
+pub fn from_stream_error(err: &StreamError) -> usize {
+    from_error(err.into())
+}
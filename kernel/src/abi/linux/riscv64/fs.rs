@@ -0,0 +1,1144 @@
+//! File system calls of the Linux ABI
+//!
+//! Paths are resolved against the working directory of the task; a
+//! directory file descriptor other than `AT_FDCWD` is only accepted with an
+//! absolute path, since open directories do not know their path. File
+//! modes given to `openat` and `mkdirat` are not applied: new files get the
+//! defaults of the file system.
+
+use alloc::{string::String, vec, vec::Vec};
+
+use crate::{
+    abi::{
+        error::KernelError,
+        linux::riscv64::{errno, FileDescriptor, LinuxRiscv64Abi, MAX_FDS},
+    },
+    arch::Trapframe,
+    device::{
+        char::tty::tty_commands::{
+            FIONREAD, TCFLSH, TCGETS, TCSETS, TCSETSF, TCSETSW, TIOCGPGRP, TIOCGSID, TIOCGWINSZ, TIOCNOTTY,
+            TIOCSCTTY, TIOCSPGRP, TIOCSWINSZ,
+        },
+        graphics::framebuffer_device::framebuffer_commands::{
+            FBIOGET_FSCREENINFO, FBIOGET_VSCREENINFO, FBIOPUT_VSCREENINFO, FBIO_FLUSH,
+        },
+        gpio::{gpio_commands::GPIO_V2_GET_LINE_IOCTL, is_gpio_command, LINE_REQUEST_FD},
+        input::is_evdev_command,
+        sound::is_pcm_command,
+    },
+    fs::{DirectoryEntry, FileMetadata, FileType, SeekFrom, MAX_PATH_LENGTH},
+    ipc::{
+        eventfd::EventFdObject,
+        memfd::{MemFdObject, MEMFD_NAME_MAX},
+        pipe::{splice, PIPE_DEFAULT_SIZE},
+        UnidirectionalPipe,
+    },
+    library::std::string::parse_c_string_from_userspace,
+    object::{
+        capability::{
+            poll::{POLLERR, POLLHUP, POLLIN, POLLOUT},
+            StreamError,
+        },
+        handle::{AccessMode, HandleMetadata, HandleType},
+        KernelObject,
+    },
+    task::{
+        cred::{MAY_EXEC, MAY_READ, MAY_WRITE},
+        mytask,
+        signal::{self, copy_from_user, copy_to_user, send_signal, SIGPIPE},
+        Task,
+    },
+};
+
+pub const O_RDONLY: usize = 0;
+pub const O_WRONLY: usize = 1;
+pub const O_RDWR: usize = 2;
+const O_ACCMODE: usize = 3;
+const O_CREAT: usize = 0o100;
+const O_EXCL: usize = 0o200;
+const O_TRUNC: usize = 0o1000;
+const O_APPEND: usize = 0o2000;
+pub const O_NONBLOCK: usize = 0o4000;
+const O_DIRECTORY: usize = 0o200000;
+pub const O_CLOEXEC: usize = 0o2000000;
+
+/// Directory file descriptor meaning the working directory
+const AT_FDCWD: usize = -100isize as usize;
+const AT_REMOVEDIR: usize = 0x200;
+const AT_EMPTY_PATH: usize = 0x1000;
+
+/// `fcntl` commands
+const F_DUPFD: usize = 0;
+const F_GETFD: usize = 1;
+const F_SETFD: usize = 2;
+const F_GETFL: usize = 3;
+const F_SETFL: usize = 4;
+const F_DUPFD_CLOEXEC: usize = 1030;
+const F_SETPIPE_SZ: usize = 1031;
+const F_GETPIPE_SZ: usize = 1032;
+const F_ADD_SEALS: usize = 1033;
+const F_GET_SEALS: usize = 1034;
+const FD_CLOEXEC: usize = 1;
+
+/// `ioctl` commands passed on to devices as control operations: the TTY
+/// and the framebuffer take the Linux numbers and structures, and so do the
+/// input devices, whose evdev commands are passed on too
+const DEVICE_IOCTLS: [u32; 17] = [
+    TCGETS, TCSETS, TCSETSW, TCSETSF, TCFLSH, FIONREAD, TIOCGWINSZ, TIOCSWINSZ,
+    TIOCSCTTY, TIOCGPGRP, TIOCSPGRP, TIOCNOTTY, TIOCGSID,
+    FBIOGET_VSCREENINFO, FBIOPUT_VSCREENINFO, FBIOGET_FSCREENINFO, FBIO_FLUSH,
+];
+
+/// Job control commands, which fail with ENOTTY on a terminal that is not
+/// the controlling terminal of the caller
+const JOB_CONTROL_IOCTLS: [u32; 5] = [TIOCSCTTY, TIOCGPGRP, TIOCSPGRP, TIOCNOTTY, TIOCGSID];
+
+/// Largest transfer of a single read or write; longer ones are short
+const MAX_IO_SIZE: usize = 64 * 1024;
+/// Largest number of buffers of readv and writev
+const IOV_MAX: usize = 1024;
+
+/// Size of a `linux_dirent64` header (ino, off, reclen, type)
+const DIRENT64_HEADER_SIZE: usize = 19;
+/// Size of the largest `linux_dirent64` record, with a 255-byte name
+const DIRENT64_MAX_SIZE: usize = (DIRENT64_HEADER_SIZE + 256).next_multiple_of(8);
+
+fn user_path(task: &Task, ptr: usize) -> Result<String, usize> {
+    parse_c_string_from_userspace(task, ptr, MAX_PATH_LENGTH).map_err(|_| errno::EFAULT)
+}
+
+/// Absolute path of `path` relative to the directory file descriptor `dirfd`
+fn resolve_at(abi: &LinuxRiscv64Abi, task: &Task, dirfd: usize, path: &str) -> Result<String, usize> {
+    if path.is_empty() {
+        return Err(errno::ENOENT);
+    }
+    if !path.starts_with('/') && dirfd != AT_FDCWD {
+        if abi.get_fd(dirfd).is_none() {
+            return Err(errno::EBADF);
+        }
+        return Err(errno::EOPNOTSUPP);
+    }
+    let vfs = task.get_vfs().ok_or(errno::ENOENT)?;
+    Ok(vfs.resolve_path_to_absolute(path))
+}
+
+/// The descriptor `fd` and the object it refers to
+///
+/// The object is cloned out of the handle table, so it stays valid while
+/// the call blocks even if another thread closes `fd`.
+pub fn fd_object(abi: &LinuxRiscv64Abi, task: &Task, fd: usize) -> Result<(FileDescriptor, KernelObject), usize> {
+    let desc = abi.get_fd(fd).ok_or(errno::EBADF)?;
+    let obj = task.handle_table.read().get(desc.handle).cloned().ok_or(errno::EBADF)?;
+    Ok((desc, obj))
+}
+
+fn file_metadata(obj: &KernelObject) -> Option<FileMetadata> {
+    obj.as_file().and_then(|file| file.metadata().ok())
+}
+
+fn access_mode(status_flags: usize) -> AccessMode {
+    match status_flags & O_ACCMODE {
+        O_RDONLY => AccessMode::ReadOnly,
+        O_WRONLY => AccessMode::WriteOnly,
+        _ => AccessMode::ReadWrite,
+    }
+}
+
+/// Insert `obj` into the handle table and give it the lowest free descriptor
+pub fn install(abi: &mut LinuxRiscv64Abi, task: &mut Task, obj: KernelObject, close_on_exec: bool, status_flags: usize) -> Result<usize, usize> {
+    let metadata = HandleMetadata {
+        handle_type: HandleType::Regular,
+        access_mode: access_mode(status_flags),
+        special_semantics: None,
+    };
+    let handle = task.handle_table.write().insert_with_metadata(obj, metadata).map_err(|_| errno::EMFILE)?;
+    abi.allocate_fd(handle, 0, close_on_exec, status_flags).inspect_err(|_| {
+        task.handle_table.write().remove(handle);
+    })
+}
+
+/// A new handle for the object of `desc`
+fn duplicate_handle(task: &mut Task, desc: &FileDescriptor) -> Result<u32, usize> {
+    let obj = task.handle_table.read().get(desc.handle).cloned().ok_or(errno::EBADF)?;
+    let metadata = task.handle_table.read().get_metadata(desc.handle).cloned().ok_or(errno::EBADF)?;
+    task.handle_table.write().insert_with_metadata(obj, metadata).map_err(|_| errno::EMFILE)
+}
+
+pub fn result(value: Result<usize, usize>) -> usize {
+    value.unwrap_or_else(errno::error)
+}
+
+/// Like [`result`] for a transfer that may block: one interrupted by a
+/// signal is restarted or fails with EINTR as `crate::task::signal` says
+pub fn io_result(task: &mut Task, trapframe: &Trapframe, value: Result<usize, usize>) -> usize {
+    match value {
+        Err(errno::EINTR) => signal::interrupt_syscall(task, trapframe),
+        value => result(value),
+    }
+}
+
+pub fn sys_openat(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let dirfd = trapframe.get_arg(0);
+    let path_ptr = trapframe.get_arg(1);
+    let flags = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+    result(openat(abi, task, dirfd, path_ptr, flags))
+}
+
+fn openat(abi: &mut LinuxRiscv64Abi, task: &mut Task, dirfd: usize, path_ptr: usize, flags: usize) -> Result<usize, usize> {
+    let path = user_path(task, path_ptr)?;
+    let path = resolve_at(abi, task, dirfd, &path)?;
+    let access = flags & O_ACCMODE;
+    if access == O_ACCMODE {
+        return Err(errno::EINVAL);
+    }
+    let vfs = task.get_vfs().ok_or(errno::ENOENT)?.clone();
+
+    match vfs.metadata(&path) {
+        Ok(metadata) => {
+            if flags & O_CREAT != 0 && flags & O_EXCL != 0 {
+                return Err(errno::EEXIST);
+            }
+            let is_dir = metadata.file_type == FileType::Directory;
+            if flags & O_DIRECTORY != 0 && !is_dir {
+                return Err(errno::ENOTDIR);
+            }
+            if is_dir && access != O_RDONLY {
+                return Err(errno::EISDIR);
+            }
+            let want = match access {
+                O_RDONLY => MAY_READ,
+                O_WRONLY => MAY_WRITE,
+                _ => MAY_READ | MAY_WRITE,
+            };
+            task.cred.check_path(&vfs, &path, want).map_err(errno::from_message)?;
+        }
+        Err(e) if e.kind == crate::fs::FileSystemErrorKind::NotFound && flags & O_CREAT != 0 => {
+            task.cred.check_parent(&vfs, &path).map_err(errno::from_message)?;
+            vfs.create_file(&path, FileType::RegularFile).map_err(|e| errno::from_fs_error(&e.kind))?;
+        }
+        Err(e) => return Err(errno::from_fs_error(&e.kind)),
+    }
+
+    let obj = vfs.open(&path, 0).map_err(|e| errno::from_fs_error(&e.kind))?;
+    if flags & O_TRUNC != 0 && access != O_RDONLY {
+        if let Some(file) = obj.as_file() {
+            if file_metadata(&obj).is_some_and(|metadata| metadata.file_type == FileType::RegularFile) {
+                file.truncate(0).map_err(|e| errno::from_stream_error(&e))?;
+            }
+        }
+    }
+    install(abi, task, obj, flags & O_CLOEXEC != 0, flags & (O_ACCMODE | O_APPEND | O_NONBLOCK))
+}
+
+pub fn sys_close(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    match abi.remove_fd(fd) {
+        Some(desc) => {
+            task.handle_table.write().remove(desc.handle);
+            0
+        }
+        None => errno::error(errno::EBADF),
+    }
+}
+
+/// EAGAIN if `desc` is non-blocking and none of `events` is ready on `obj`
+///
+/// Objects that cannot be polled never block.
+fn would_block(desc: &FileDescriptor, obj: &KernelObject, events: u32) -> Result<(), usize> {
+    if desc.status_flags & O_NONBLOCK != 0 && obj.as_pollable().is_some_and(|pollable| pollable.poll_events() & events == 0) {
+        return Err(errno::EAGAIN);
+    }
+    Ok(())
+}
+
+/// Read up to `count` bytes from `fd` into user memory
+fn read_fd(abi: &LinuxRiscv64Abi, task: &Task, fd: usize, buf_ptr: usize, count: usize) -> Result<usize, usize> {
+    let (desc, obj) = fd_object(abi, task, fd)?;
+    if desc.status_flags & O_ACCMODE == O_WRONLY {
+        return Err(errno::EBADF);
+    }
+    if file_metadata(&obj).is_some_and(|metadata| metadata.file_type == FileType::Directory) {
+        return Err(errno::EISDIR);
+    }
+    let stream = obj.as_stream().ok_or(errno::EINVAL)?;
+    would_block(&desc, &obj, POLLIN | POLLHUP | POLLERR)?;
+    let mut buffer = vec![0u8; count.min(MAX_IO_SIZE)];
+    let n = match stream.read(&mut buffer) {
+        Ok(n) => n,
+        Err(StreamError::EndOfStream) => 0,
+        Err(e) => return Err(errno::from_stream_error(&e)),
+    };
+    copy_to_user(task, buf_ptr, &buffer[..n]).map_err(|_| errno::EFAULT)?;
+    Ok(n)
+}
+
+/// Write up to `count` bytes of user memory to `fd`
+fn write_fd(abi: &LinuxRiscv64Abi, task: &Task, fd: usize, buf_ptr: usize, count: usize) -> Result<usize, usize> {
+    let (desc, obj) = fd_object(abi, task, fd)?;
+    if desc.status_flags & O_ACCMODE == O_RDONLY {
+        return Err(errno::EBADF);
+    }
+    let stream = obj.as_stream().ok_or(errno::EINVAL)?;
+    would_block(&desc, &obj, POLLOUT | POLLERR)?;
+    let count = task.check_file_write(&obj, count.min(MAX_IO_SIZE)).map_err(|_| errno::EFBIG)?;
+    let mut buffer = vec![0u8; count];
+    copy_from_user(task, buf_ptr, &mut buffer).map_err(|_| errno::EFAULT)?;
+    if desc.status_flags & O_APPEND != 0 {
+        if let Some(file) = obj.as_file() {
+            file.seek(SeekFrom::End(0)).map_err(|e| errno::from_stream_error(&e))?;
+        }
+    }
+    match stream.write(&buffer) {
+        Ok(n) => Ok(n),
+        Err(StreamError::BrokenPipe) => {
+            let _ = send_signal(task.get_id(), SIGPIPE);
+            Err(errno::EPIPE)
+        }
+        Err(e) => Err(errno::from_stream_error(&e)),
+    }
+}
+
+pub fn sys_read(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let buf_ptr = trapframe.get_arg(1);
+    let count = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+    let value = read_fd(abi, task, fd, buf_ptr, count);
+    io_result(task, trapframe, value)
+}
+
+pub fn sys_write(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let buf_ptr = trapframe.get_arg(1);
+    let count = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+    let value = write_fd(abi, task, fd, buf_ptr, count);
+    io_result(task, trapframe, value)
+}
+
+/// Run `transfer` over the buffers of an iovec array until one falls short
+///
+/// The base and the length of an entry are `word`-byte words.
+pub fn transfer_iov(
+    task: &Task,
+    iov_ptr: usize,
+    iovcnt: usize,
+    word: usize,
+    mut transfer: impl FnMut(usize, usize) -> Result<usize, usize>,
+) -> Result<usize, usize> {
+    if iovcnt > IOV_MAX {
+        return Err(errno::EINVAL);
+    }
+    let mut iov = vec![0u8; iovcnt * 2 * word];
+    copy_from_user(task, iov_ptr, &mut iov).map_err(|_| errno::EFAULT)?;
+    let read_word = |bytes: &[u8]| {
+        let mut value = [0u8; 8];
+        value[..word].copy_from_slice(bytes);
+        usize::from_le_bytes(value)
+    };
+    let mut total = 0;
+    for entry in iov.chunks_exact(2 * word) {
+        let base = read_word(&entry[..word]);
+        let len = read_word(&entry[word..]);
+        if len == 0 {
+            continue;
+        }
+        match transfer(base, len) {
+            Ok(n) => {
+                total += n;
+                if n < len {
+                    break;
+                }
+            }
+            // What was transferred before the error is reported instead
+            Err(_) if total > 0 => break,
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(total)
+}
+
+pub fn sys_readv(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    readv(abi, trapframe, 8)
+}
+
+/// `readv` of riscv32
+pub fn compat_sys_readv(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    readv(abi, trapframe, 4)
+}
+
+fn readv(abi: &LinuxRiscv64Abi, trapframe: &mut Trapframe, word: usize) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let iov_ptr = trapframe.get_arg(1);
+    let iovcnt = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+    let value = transfer_iov(task, iov_ptr, iovcnt, word, |base, len| read_fd(abi, task, fd, base, len));
+    io_result(task, trapframe, value)
+}
+
+pub fn sys_writev(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    writev(abi, trapframe, 8)
+}
+
+/// `writev` of riscv32
+pub fn compat_sys_writev(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    writev(abi, trapframe, 4)
+}
+
+fn writev(abi: &LinuxRiscv64Abi, trapframe: &mut Trapframe, word: usize) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let iov_ptr = trapframe.get_arg(1);
+    let iovcnt = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+    let value = transfer_iov(task, iov_ptr, iovcnt, word, |base, len| write_fd(abi, task, fd, base, len));
+    io_result(task, trapframe, value)
+}
+
+fn lseek(abi: &LinuxRiscv64Abi, task: &Task, fd: usize, offset: i64, whence: usize) -> Result<usize, usize> {
+    let (_, obj) = fd_object(abi, task, fd)?;
+    let file = obj.as_file().ok_or(errno::ESPIPE)?;
+    let whence = match whence {
+        0 if offset >= 0 => SeekFrom::Start(offset as u64),
+        1 => SeekFrom::Current(offset),
+        2 => SeekFrom::End(offset),
+        _ => return Err(errno::EINVAL),
+    };
+    file.seek(whence).map(|pos| pos as usize).map_err(|e| errno::from_stream_error(&e))
+}
+
+pub fn sys_lseek(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let offset = trapframe.get_arg(1) as i64;
+    let whence = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+    result(lseek(abi, task, fd, offset, whence))
+}
+
+/// `ioctl` of riscv32: the structures of the TTY commands have the same
+/// layout, `fb_fix_screeninfo` has 32-bit addresses and is not supported
+pub fn compat_sys_ioctl(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    if trapframe.get_arg(1) == FBIOGET_FSCREENINFO as usize {
+        trapframe.increment_pc_next(mytask().unwrap());
+        return errno::error(errno::ENOTTY);
+    }
+    sys_ioctl(abi, trapframe)
+}
+
+/// `_llseek` of riscv32: the 64-bit offset comes in two words and the new
+/// position is written to user memory
+pub fn compat_sys_llseek(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let offset = ((trapframe.get_arg(1) as u64) << 32 | trapframe.get_arg(2) as u32 as u64) as i64;
+    let result_ptr = trapframe.get_arg(3);
+    let whence = trapframe.get_arg(4);
+    trapframe.increment_pc_next(task);
+
+    let seek = || -> Result<usize, usize> {
+        let pos = lseek(abi, task, fd, offset, whence)? as u64;
+        copy_to_user(task, result_ptr, &pos.to_le_bytes()).map_err(|_| errno::EFAULT)?;
+        Ok(0)
+    };
+    result(seek())
+}
+
+pub fn sys_getdents64(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let dirp = trapframe.get_arg(1);
+    let count = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+    result(getdents64(abi, task, fd, dirp, count))
+}
+
+fn getdents64(abi: &LinuxRiscv64Abi, task: &Task, fd: usize, dirp: usize, count: usize) -> Result<usize, usize> {
+    let (_, obj) = fd_object(abi, task, fd)?;
+    if !file_metadata(&obj).is_some_and(|metadata| metadata.file_type == FileType::Directory) {
+        return Err(errno::ENOTDIR);
+    }
+    let stream = obj.as_stream().ok_or(errno::ENOTDIR)?;
+    if count < DIRENT64_MAX_SIZE {
+        return Err(errno::EINVAL);
+    }
+
+    // Directory streams return one entry per read and cannot seek back, so
+    // an entry is only read while any entry would still fit
+    let mut out = Vec::new();
+    let mut raw = vec![0u8; core::mem::size_of::<DirectoryEntry>()];
+    while count - out.len() >= DIRENT64_MAX_SIZE {
+        let n = stream.read(&mut raw).map_err(|e| errno::from_stream_error(&e))?;
+        let Some(entry) = (n > 0).then(|| DirectoryEntry::parse(&raw[..n])).flatten() else {
+            break;
+        };
+        let name = &entry.name[..entry.name_len as usize];
+        let reclen = (DIRENT64_HEADER_SIZE + name.len() + 1).next_multiple_of(8);
+        let d_off = obj.as_file().and_then(|file| file.seek(SeekFrom::Current(0)).ok()).unwrap_or(0);
+        let d_type: u8 = match entry.file_type {
+            0 => 8,  // DT_REG
+            1 => 4,  // DT_DIR
+            2 => 10, // DT_LNK
+            3 => 2,  // DT_CHR
+            4 => 6,  // DT_BLK
+            5 => 1,  // DT_FIFO
+            6 => 12, // DT_SOCK
+            _ => 0,  // DT_UNKNOWN
+        };
+        let start = out.len();
+        out.extend_from_slice(&entry.file_id.to_le_bytes());
+        out.extend_from_slice(&d_off.to_le_bytes());
+        out.extend_from_slice(&(reclen as u16).to_le_bytes());
+        out.push(d_type);
+        out.extend_from_slice(name);
+        out.resize(start + reclen, 0);
+    }
+    copy_to_user(task, dirp, &out).map_err(|_| errno::EFAULT)?;
+    Ok(out.len())
+}
+
+/// `struct stat` of riscv64
+#[repr(C)]
+#[derive(Default)]
+struct LinuxStat {
+    st_dev: u64,
+    st_ino: u64,
+    st_mode: u32,
+    st_nlink: u32,
+    st_uid: u32,
+    st_gid: u32,
+    st_rdev: u64,
+    __pad1: u64,
+    st_size: i64,
+    st_blksize: i32,
+    __pad2: i32,
+    st_blocks: i64,
+    st_atime: i64,
+    st_atime_nsec: u64,
+    st_mtime: i64,
+    st_mtime_nsec: u64,
+    st_ctime: i64,
+    st_ctime_nsec: u64,
+    __unused: [u32; 2],
+}
+
+/// Size of `struct statx`
+const STATX_SIZE: usize = 256;
+/// `stx_mask` of the fields filled by `statx`
+const STATX_BASIC_STATS: u32 = 0x7ff;
+
+const S_IFIFO: u32 = 0o010000;
+const S_IFCHR: u32 = 0o020000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFBLK: u32 = 0o060000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+const S_IFSOCK: u32 = 0o140000;
+
+impl LinuxStat {
+    fn from_metadata(metadata: &FileMetadata) -> Self {
+        let (file_type, rdev) = match &metadata.file_type {
+            FileType::RegularFile => (S_IFREG, 0),
+            FileType::Directory => (S_IFDIR, 0),
+            FileType::SymbolicLink(_) => (S_IFLNK, 0),
+            FileType::CharDevice(info) => (S_IFCHR, info.device_id as u64),
+            FileType::BlockDevice(info) => (S_IFBLK, info.device_id as u64),
+            FileType::Pipe => (S_IFIFO, 0),
+            FileType::Socket => (S_IFSOCK, 0),
+            FileType::Unknown => (0, 0),
+        };
+        let (uid, gid, mode) = match &metadata.owner {
+            Some(owner) => (owner.uid, owner.gid, owner.mode & 0o7777),
+            // Without recorded modes, the permissions apply to everyone
+            None => {
+                let permissions = &metadata.permissions;
+                let mode = (permissions.read as u32 * 0o444)
+                    | (permissions.write as u32 * 0o222)
+                    | (permissions.execute as u32 * 0o111);
+                (0, 0, mode)
+            }
+        };
+        Self {
+            st_ino: metadata.file_id,
+            st_mode: file_type | mode,
+            st_nlink: metadata.link_count,
+            st_uid: uid,
+            st_gid: gid,
+            st_rdev: rdev,
+            st_size: metadata.size as i64,
+            st_blksize: 4096,
+            st_blocks: metadata.size.div_ceil(512) as i64,
+            st_atime: metadata.accessed_time as i64,
+            st_mtime: metadata.modified_time as i64,
+            st_ctime: metadata.modified_time as i64,
+            ..Default::default()
+        }
+    }
+
+    /// The same status as a `struct statx`
+    fn to_statx(&self) -> [u8; STATX_SIZE] {
+        let mut bytes = [0u8; STATX_SIZE];
+        let mut put = |offset: usize, value: &[u8]| bytes[offset..offset + value.len()].copy_from_slice(value);
+        put(0, &STATX_BASIC_STATS.to_le_bytes());
+        put(4, &(self.st_blksize as u32).to_le_bytes());
+        put(16, &self.st_nlink.to_le_bytes());
+        put(20, &self.st_uid.to_le_bytes());
+        put(24, &self.st_gid.to_le_bytes());
+        put(28, &(self.st_mode as u16).to_le_bytes());
+        put(32, &self.st_ino.to_le_bytes());
+        put(40, &self.st_size.to_le_bytes());
+        put(48, &self.st_blocks.to_le_bytes());
+        // atime, ctime and mtime; the birth time at 80 is left out of the mask
+        put(64, &self.st_atime.to_le_bytes());
+        put(72, &(self.st_atime_nsec as u32).to_le_bytes());
+        put(96, &self.st_ctime.to_le_bytes());
+        put(104, &(self.st_ctime_nsec as u32).to_le_bytes());
+        put(112, &self.st_mtime.to_le_bytes());
+        put(120, &(self.st_mtime_nsec as u32).to_le_bytes());
+        // Split as by major() and minor() of the C library
+        let rdev = self.st_rdev;
+        put(128, &((((rdev >> 8) & 0xfff) | ((rdev >> 32) & !0xfff)) as u32).to_le_bytes());
+        put(132, &(((rdev & 0xff) | ((rdev >> 12) & !0xff)) as u32).to_le_bytes());
+        bytes
+    }
+
+    fn copy_to_user(&self, task: &Task, ptr: usize) -> Result<usize, usize> {
+        let bytes = unsafe {
+            core::slice::from_raw_parts(self as *const Self as *const u8, core::mem::size_of::<Self>())
+        };
+        copy_to_user(task, ptr, bytes).map(|_| 0).map_err(|_| errno::EFAULT)
+    }
+}
+
+fn fstat(abi: &LinuxRiscv64Abi, task: &Task, fd: usize) -> Result<LinuxStat, usize> {
+    let (_, obj) = fd_object(abi, task, fd)?;
+    Ok(match file_metadata(&obj) {
+        Some(metadata) => LinuxStat::from_metadata(&metadata),
+        None if obj.as_pipe().is_some() => LinuxStat { st_mode: S_IFIFO | 0o600, st_nlink: 1, ..Default::default() },
+        None => LinuxStat::default(),
+    })
+}
+
+/// The status of `path` relative to `dirfd`, or of `dirfd` itself for an
+/// empty path with `AT_EMPTY_PATH`
+fn stat_at(abi: &LinuxRiscv64Abi, task: &Task, dirfd: usize, path_ptr: usize, flags: usize) -> Result<LinuxStat, usize> {
+    let path = user_path(task, path_ptr)?;
+    if path.is_empty() && flags & AT_EMPTY_PATH != 0 {
+        return fstat(abi, task, dirfd);
+    }
+    let path = resolve_at(abi, task, dirfd, &path)?;
+    let vfs = task.get_vfs().ok_or(errno::ENOENT)?;
+    let metadata = vfs.metadata(&path).map_err(|e| errno::from_fs_error(&e.kind))?;
+    Ok(LinuxStat::from_metadata(&metadata))
+}
+
+pub fn sys_fstat(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let stat_ptr = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+    result(fstat(abi, task, fd).and_then(|stat| stat.copy_to_user(task, stat_ptr)))
+}
+
+pub fn sys_newfstatat(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let dirfd = trapframe.get_arg(0);
+    let path_ptr = trapframe.get_arg(1);
+    let stat_ptr = trapframe.get_arg(2);
+    let flags = trapframe.get_arg(3);
+    trapframe.increment_pc_next(task);
+    result(stat_at(abi, task, dirfd, path_ptr, flags).and_then(|stat| stat.copy_to_user(task, stat_ptr)))
+}
+
+/// `statx`, the only stat call of riscv32
+///
+/// Every field of `STATX_BASIC_STATS` is filled whatever the mask asks for;
+/// the birth time is not known.
+pub fn sys_statx(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let dirfd = trapframe.get_arg(0);
+    let path_ptr = trapframe.get_arg(1);
+    let flags = trapframe.get_arg(2);
+    let statx_ptr = trapframe.get_arg(4);
+    trapframe.increment_pc_next(task);
+
+    let statx = || -> Result<usize, usize> {
+        let stat = stat_at(abi, task, dirfd, path_ptr, flags)?;
+        copy_to_user(task, statx_ptr, &stat.to_statx()).map_err(|_| errno::EFAULT)?;
+        Ok(0)
+    };
+    result(statx())
+}
+
+pub fn sys_faccessat(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let dirfd = trapframe.get_arg(0);
+    let path_ptr = trapframe.get_arg(1);
+    let mode = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    let access = || -> Result<usize, usize> {
+        if mode & !0o7 != 0 {
+            return Err(errno::EINVAL);
+        }
+        let path = user_path(task, path_ptr)?;
+        let path = resolve_at(abi, task, dirfd, &path)?;
+        let vfs = task.get_vfs().ok_or(errno::ENOENT)?;
+        vfs.metadata(&path).map_err(|e| errno::from_fs_error(&e.kind))?;
+        // R_OK, W_OK and X_OK have the values of MAY_READ, MAY_WRITE and MAY_EXEC
+        let want = mode as u32 & (MAY_READ | MAY_WRITE | MAY_EXEC);
+        if want != 0 {
+            task.cred.check_path(vfs, &path, want).map_err(errno::from_message)?;
+        }
+        Ok(0)
+    };
+    result(access())
+}
+
+pub fn sys_getcwd(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let buf_ptr = trapframe.get_arg(0);
+    let size = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    let Some(vfs) = task.get_vfs() else {
+        return errno::error(errno::ENOENT);
+    };
+    let mut cwd = vfs.get_cwd_path().into_bytes();
+    cwd.push(0);
+    if cwd.len() > size {
+        return errno::error(errno::ERANGE);
+    }
+    match copy_to_user(task, buf_ptr, &cwd) {
+        Ok(()) => cwd.len(),
+        Err(_) => errno::error(errno::EFAULT),
+    }
+}
+
+pub fn sys_chdir(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let path_ptr = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    let chdir = || -> Result<usize, usize> {
+        let path = user_path(task, path_ptr)?;
+        if path.is_empty() {
+            return Err(errno::ENOENT);
+        }
+        let vfs = task.get_vfs().ok_or(errno::ENOENT)?;
+        let path = vfs.resolve_path_to_absolute(&path);
+        let metadata = vfs.metadata(&path).map_err(|e| errno::from_fs_error(&e.kind))?;
+        if metadata.file_type != FileType::Directory {
+            return Err(errno::ENOTDIR);
+        }
+        task.cred.check_path(vfs, &path, MAY_EXEC).map_err(errno::from_message)?;
+        vfs.set_cwd_by_path(&path).map_err(|e| errno::from_fs_error(&e.kind))?;
+        Ok(0)
+    };
+    result(chdir())
+}
+
+pub fn sys_mkdirat(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let dirfd = trapframe.get_arg(0);
+    let path_ptr = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    let mkdir = || -> Result<usize, usize> {
+        let path = user_path(task, path_ptr)?;
+        let path = resolve_at(abi, task, dirfd, &path)?;
+        let vfs = task.get_vfs().ok_or(errno::ENOENT)?;
+        if vfs.metadata(&path).is_ok() {
+            return Err(errno::EEXIST);
+        }
+        task.cred.check_parent(vfs, &path).map_err(errno::from_message)?;
+        vfs.create_dir(&path).map_err(|e| errno::from_fs_error(&e.kind))?;
+        Ok(0)
+    };
+    result(mkdir())
+}
+
+pub fn sys_unlinkat(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let dirfd = trapframe.get_arg(0);
+    let path_ptr = trapframe.get_arg(1);
+    let flags = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    let unlink = || -> Result<usize, usize> {
+        let path = user_path(task, path_ptr)?;
+        let path = resolve_at(abi, task, dirfd, &path)?;
+        let vfs = task.get_vfs().ok_or(errno::ENOENT)?;
+        let metadata = vfs.metadata(&path).map_err(|e| errno::from_fs_error(&e.kind))?;
+        let is_dir = metadata.file_type == FileType::Directory;
+        if flags & AT_REMOVEDIR == 0 && is_dir {
+            return Err(errno::EISDIR);
+        }
+        if flags & AT_REMOVEDIR != 0 && !is_dir {
+            return Err(errno::ENOTDIR);
+        }
+        task.cred.check_parent(vfs, &path).map_err(errno::from_message)?;
+        vfs.remove(&path).map_err(|e| errno::from_fs_error(&e.kind))?;
+        Ok(0)
+    };
+    result(unlink())
+}
+
+pub fn sys_dup(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    let mut dup = || -> Result<usize, usize> {
+        let desc = abi.get_fd(fd).ok_or(errno::EBADF)?;
+        let handle = duplicate_handle(task, &desc)?;
+        abi.allocate_fd(handle, 0, false, desc.status_flags).inspect_err(|_| {
+            task.handle_table.write().remove(handle);
+        })
+    };
+    result(dup())
+}
+
+pub fn sys_dup3(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let oldfd = trapframe.get_arg(0);
+    let newfd = trapframe.get_arg(1);
+    let flags = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    let mut dup3 = || -> Result<usize, usize> {
+        if oldfd == newfd || flags & !O_CLOEXEC != 0 {
+            return Err(errno::EINVAL);
+        }
+        let desc = abi.get_fd(oldfd).ok_or(errno::EBADF)?;
+        if newfd >= MAX_FDS {
+            return Err(errno::EBADF);
+        }
+        let handle = duplicate_handle(task, &desc)?;
+        let new_desc = FileDescriptor { handle, close_on_exec: flags & O_CLOEXEC != 0, status_flags: desc.status_flags };
+        if let Some(replaced) = abi.install_fd(newfd, new_desc)? {
+            task.handle_table.write().remove(replaced.handle);
+        }
+        Ok(newfd)
+    };
+    result(dup3())
+}
+
+pub fn sys_fcntl(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let cmd = trapframe.get_arg(1);
+    let arg = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    let mut fcntl = || -> Result<usize, usize> {
+        let desc = abi.get_fd(fd).ok_or(errno::EBADF)?;
+        match cmd {
+            F_DUPFD | F_DUPFD_CLOEXEC => {
+                if arg >= MAX_FDS {
+                    return Err(errno::EINVAL);
+                }
+                let handle = duplicate_handle(task, &desc)?;
+                abi.allocate_fd(handle, arg, cmd == F_DUPFD_CLOEXEC, desc.status_flags).inspect_err(|_| {
+                    task.handle_table.write().remove(handle);
+                })
+            }
+            F_GETFD => Ok(if desc.close_on_exec { FD_CLOEXEC } else { 0 }),
+            F_SETFD => {
+                abi.get_fd_mut(fd).unwrap().close_on_exec = arg & FD_CLOEXEC != 0;
+                Ok(0)
+            }
+            F_GETFL => Ok(desc.status_flags),
+            F_SETFL => {
+                // The access mode cannot be changed
+                let flags = &mut abi.get_fd_mut(fd).unwrap().status_flags;
+                *flags = (*flags & O_ACCMODE) | (arg & (O_APPEND | O_NONBLOCK));
+                if let Some(pipe) = task.handle_table.read().get(desc.handle).and_then(|obj| obj.as_pipe()) {
+                    pipe.set_nonblocking(arg & O_NONBLOCK != 0);
+                }
+                Ok(0)
+            }
+            F_SETPIPE_SZ | F_GETPIPE_SZ => {
+                let obj = task.handle_table.read().get(desc.handle).cloned().ok_or(errno::EBADF)?;
+                let pipe = obj.as_pipe().ok_or(errno::EBADF)?;
+                if cmd == F_GETPIPE_SZ {
+                    return Ok(pipe.buffer_size());
+                }
+                pipe.set_buffer_size(arg & 0xffff_ffff).map_err(errno::from_error)
+            }
+            F_ADD_SEALS | F_GET_SEALS => {
+                let obj = task.handle_table.read().get(desc.handle).cloned().ok_or(errno::EINVAL)?;
+                let memfd = MemFdObject::of(&obj).ok_or(errno::EINVAL)?;
+                if cmd == F_GET_SEALS {
+                    return Ok(memfd.seals() as usize);
+                }
+                if desc.status_flags & O_ACCMODE == O_RDONLY {
+                    return Err(errno::EPERM);
+                }
+                memfd.add_seals(arg as u32).map(|()| 0).map_err(errno::from_error)
+            }
+            _ => Err(errno::EINVAL),
+        }
+    };
+    result(fcntl())
+}
+
+pub fn sys_pipe2(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let pipefd_ptr = trapframe.get_arg(0);
+    let flags = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    let mut pipe = || -> Result<usize, usize> {
+        if flags & !(O_CLOEXEC | O_NONBLOCK) != 0 {
+            return Err(errno::EINVAL);
+        }
+        let close_on_exec = flags & O_CLOEXEC != 0;
+        let (read_end, write_end) = UnidirectionalPipe::create_pair(PIPE_DEFAULT_SIZE);
+        // The pipe itself follows O_NONBLOCK, which `fcntl` keeps in step
+        for end in [&read_end, &write_end] {
+            if let Some(pipe) = end.as_pipe() {
+                pipe.set_nonblocking(flags & O_NONBLOCK != 0);
+            }
+        }
+        let read_fd = install(abi, task, read_end, close_on_exec, O_RDONLY | (flags & O_NONBLOCK))?;
+        let write_fd = match install(abi, task, write_end, close_on_exec, O_WRONLY | (flags & O_NONBLOCK)) {
+            Ok(fd) => fd,
+            Err(err) => {
+                if let Some(desc) = abi.remove_fd(read_fd) {
+                    task.handle_table.write().remove(desc.handle);
+                }
+                return Err(err);
+            }
+        };
+        let mut fds = [0u8; 8];
+        fds[0..4].copy_from_slice(&(read_fd as i32).to_le_bytes());
+        fds[4..8].copy_from_slice(&(write_fd as i32).to_le_bytes());
+        if copy_to_user(task, pipefd_ptr, &fds).is_err() {
+            for fd in [read_fd, write_fd] {
+                if let Some(desc) = abi.remove_fd(fd) {
+                    task.handle_table.write().remove(desc.handle);
+                }
+            }
+            return Err(errno::EFAULT);
+        }
+        Ok(0)
+    };
+    result(pipe())
+}
+
+/// Flags of `splice`; all but `SPLICE_F_NONBLOCK` are hints
+const SPLICE_F_MOVE: usize = 1;
+const SPLICE_F_NONBLOCK: usize = 2;
+const SPLICE_F_MORE: usize = 4;
+const SPLICE_F_GIFT: usize = 8;
+
+/// Move `obj` to the offset at `off_ptr`, for `splice`
+///
+/// Returns the position to move back to, `None` without an offset.
+fn seek_offset(task: &Task, obj: &KernelObject, off_ptr: usize) -> Result<Option<u64>, usize> {
+    if off_ptr == 0 {
+        return Ok(None);
+    }
+    let file = obj.as_file().ok_or(errno::ESPIPE)?;
+    let mut offset = [0u8; 8];
+    copy_from_user(task, off_ptr, &mut offset).map_err(|_| errno::EFAULT)?;
+    let offset = i64::from_le_bytes(offset);
+    if offset < 0 {
+        return Err(errno::EINVAL);
+    }
+    let position = file.seek(SeekFrom::Current(0)).map_err(|e| errno::from_stream_error(&e))?;
+    file.seek(SeekFrom::Start(offset as u64)).map_err(|e| errno::from_stream_error(&e))?;
+    Ok(Some(position))
+}
+
+/// Store where `obj` got at `off_ptr` and move it back to `position`
+fn restore_offset(task: &Task, obj: &KernelObject, off_ptr: usize, position: Option<u64>) -> Result<(), usize> {
+    let (Some(position), Some(file)) = (position, obj.as_file()) else {
+        return Ok(());
+    };
+    let offset = file.seek(SeekFrom::Current(0)).map_err(|e| errno::from_stream_error(&e))?;
+    file.seek(SeekFrom::Start(position)).map_err(|e| errno::from_stream_error(&e))?;
+    copy_to_user(task, off_ptr, &offset.to_le_bytes()).map_err(|_| errno::EFAULT)
+}
+
+/// `splice`: move data between a pipe and another descriptor
+///
+/// The offset of a file, if given, is where it is read or written; its
+/// position stays where it was and the offset is advanced instead. A pipe
+/// waits unless it is non-blocking (`O_NONBLOCK`) or `SPLICE_F_NONBLOCK`
+/// is given.
+pub fn sys_splice(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fd_in = trapframe.get_arg(0);
+    let off_in = trapframe.get_arg(1);
+    let fd_out = trapframe.get_arg(2);
+    let off_out = trapframe.get_arg(3);
+    let len = trapframe.get_arg(4);
+    let flags = trapframe.get_arg(5);
+    trapframe.increment_pc_next(task);
+
+    let transfer = || -> Result<usize, usize> {
+        if flags & !(SPLICE_F_MOVE | SPLICE_F_NONBLOCK | SPLICE_F_MORE | SPLICE_F_GIFT) != 0 {
+            return Err(errno::EINVAL);
+        }
+        let (desc_in, obj_in) = fd_object(abi, task, fd_in)?;
+        let (desc_out, obj_out) = fd_object(abi, task, fd_out)?;
+        if desc_in.status_flags & O_ACCMODE == O_WRONLY || desc_out.status_flags & O_ACCMODE == O_RDONLY {
+            return Err(errno::EBADF);
+        }
+        if obj_in.as_pipe().is_none() && obj_out.as_pipe().is_none() {
+            return Err(errno::EINVAL);
+        }
+
+        let position_in = seek_offset(task, &obj_in, off_in)?;
+        let position_out = match seek_offset(task, &obj_out, off_out) {
+            Ok(position) => position,
+            Err(err) => {
+                restore_offset(task, &obj_in, off_in, position_in)?;
+                return Err(err);
+            }
+        };
+        let moved = match task.check_file_write(&obj_out, len) {
+            Ok(len) => splice(&obj_in, &obj_out, len, flags & SPLICE_F_NONBLOCK != 0).map_err(|error| {
+                if error == KernelError::BrokenPipe {
+                    let _ = send_signal(task.get_id(), SIGPIPE);
+                }
+                errno::from_error(error)
+            }),
+            Err(_) => Err(errno::EFBIG),
+        };
+        restore_offset(task, &obj_in, off_in, position_in)?;
+        restore_offset(task, &obj_out, off_out, position_out)?;
+        moved
+    };
+    let value = transfer();
+    io_result(task, trapframe, value)
+}
+
+/// Reads take one at a time (`eventfd2` flag)
+const EFD_SEMAPHORE: usize = 1;
+
+/// `eventfd2`
+///
+/// The counter itself always blocks: `EFD_NONBLOCK` is the `O_NONBLOCK`
+/// status flag of the descriptor, so `fcntl` can change it.
+pub fn sys_eventfd2(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let initval = trapframe.get_arg(0) as u32;
+    let flags = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    if flags & !(EFD_SEMAPHORE | O_CLOEXEC | O_NONBLOCK) != 0 {
+        return errno::error(errno::EINVAL);
+    }
+    let eventfd = EventFdObject::new(initval as u64, flags & EFD_SEMAPHORE != 0, false);
+    result(install(abi, task, KernelObject::from_eventfd(eventfd), flags & O_CLOEXEC != 0, O_RDWR | (flags & O_NONBLOCK)))
+}
+
+/// Flags of `memfd_create`
+const MFD_CLOEXEC: usize = 1;
+const MFD_ALLOW_SEALING: usize = 2;
+
+/// `memfd_create`: an anonymous file in memory (see `crate::ipc::memfd`)
+pub fn sys_memfd_create(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let name_ptr = trapframe.get_arg(0);
+    let flags = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    let mut create = || -> Result<usize, usize> {
+        if flags & !(MFD_CLOEXEC | MFD_ALLOW_SEALING) != 0 {
+            return Err(errno::EINVAL);
+        }
+        let name = parse_c_string_from_userspace(task, name_ptr, MEMFD_NAME_MAX + 1).map_err(|_| errno::EFAULT)?;
+        let memfd = MemFdObject::new(&name, flags & MFD_ALLOW_SEALING != 0).map_err(|_| errno::EINVAL)?;
+        install(abi, task, KernelObject::from_file_object(memfd), flags & MFD_CLOEXEC != 0, O_RDWR)
+    };
+    result(create())
+}
+
+fn ftruncate(abi: &LinuxRiscv64Abi, task: &Task, fd: usize, length: i64) -> Result<usize, usize> {
+    let (desc, obj) = fd_object(abi, task, fd)?;
+    if length < 0 || desc.status_flags & O_ACCMODE == O_RDONLY {
+        return Err(errno::EINVAL);
+    }
+    let file = obj.as_file().ok_or(errno::EINVAL)?;
+    file.truncate(length as u64).map_err(|e| errno::from_stream_error(&e))?;
+    Ok(0)
+}
+
+pub fn sys_ftruncate(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let length = trapframe.get_arg(1) as i64;
+    trapframe.increment_pc_next(task);
+    result(ftruncate(abi, task, fd, length))
+}
+
+/// `ftruncate64` of riscv32: the 64-bit length comes in two words, the low
+/// one first
+pub fn compat_sys_ftruncate64(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let length = ((trapframe.get_arg(2) as u64) << 32 | trapframe.get_arg(1) as u32 as u64) as i64;
+    trapframe.increment_pc_next(task);
+    result(ftruncate(abi, task, fd, length))
+}
+
+/// errno of a failed device control operation
+fn control_error(cmd: u32, message: &str) -> usize {
+    match KernelError::from_message(message) {
+        KernelError::NotSupported => errno::ENOTTY,
+        _ if JOB_CONTROL_IOCTLS.contains(&cmd) => errno::ENOTTY,
+        error => errno::from_error(error),
+    }
+}
+
+pub fn sys_ioctl(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let cmd = trapframe.get_arg(1);
+    let arg = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    let mut ioctl = || -> Result<usize, usize> {
+        let (_, obj) = fd_object(abi, task, fd)?;
+        // GPIO line requests are controlled like the chip they come from
+        let is_device = matches!(obj, KernelObject::GpioLines(_))
+            || file_metadata(&obj).is_some_and(|metadata| matches!(metadata.file_type, FileType::CharDevice(_)));
+        if !is_device {
+            return Err(errno::ENOTTY);
+        }
+        let cmd = u32::try_from(cmd)
+            .ok()
+            .filter(|&cmd| DEVICE_IOCTLS.contains(&cmd) || is_evdev_command(cmd) || is_gpio_command(cmd) || is_pcm_command(cmd))
+            .ok_or(errno::ENOTTY)?;
+        let control = obj.as_control().ok_or(errno::ENOTTY)?;
+        let ret = control.control(cmd, arg).map(|ret| ret as usize).map_err(|e| control_error(cmd, e))?;
+        if cmd == GPIO_V2_GET_LINE_IOCTL {
+            // The chip hands back a handle; Linux programs expect an fd
+            let mut field = [0u8; 4];
+            copy_from_user(task, arg + LINE_REQUEST_FD, &mut field).map_err(|_| errno::EFAULT)?;
+            let handle = i32::from_le_bytes(field) as u32;
+            let fd = match abi.allocate_fd(handle, 0, true, 0) {
+                Ok(fd) => fd,
+                Err(error) => {
+                    task.handle_table.write().remove(handle);
+                    return Err(error);
+                }
+            };
+            copy_to_user(task, arg + LINE_REQUEST_FD, &(fd as i32).to_le_bytes()).map_err(|_| errno::EFAULT)?;
+        }
+        Ok(ret)
+    };
+    result(ioctl())
+}
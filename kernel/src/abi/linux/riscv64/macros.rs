@@ -0,0 +1,60 @@
+/// Define syscall table and syscall handler for linux-riscv64 (or its riscv32 compat layer)
+///
+/// Unlike the other ABIs, a syscall missing from the table is not an error
+/// of the task: Linux programs probe for syscalls and fall back to others
+/// when they get `ENOSYS`.
+///
+/// # Example
+/// ```
+/// syscall_table! {
+///    Getpid = 172 => sys_getpid,
+///    Close = 57 (Int) -> Int => sys_close,
+/// }
+/// ```
+///
+/// The optional `(args) -> ret` of an entry gives the
+/// [`ArgKind`](crate::task::strace::ArgKind)s shown when the call is traced.
+macro_rules! syscall_table {
+    ( $( $name:ident = $num:literal $( ( $($kind:ident),* ) -> $ret:ident )? => $func:expr ),* $(,)? ) => {
+        #[derive(Debug)]
+        pub enum Syscall {
+            $(
+                $name = $num,
+            )*
+        }
+
+        /// Syscall handler
+        ///
+        /// # Arguments
+        /// * `abi` - The ABI module instance
+        /// * `trapframe` - The trapframe
+        ///
+        /// # Returns
+        /// The result of the syscall handler, `-ENOSYS` for an unknown syscall
+        pub fn syscall_handler(abi: &mut crate::abi::linux::riscv64::LinuxRiscv64Abi, trapframe: &mut crate::arch::Trapframe) -> Result<usize, &'static str> {
+            let syscall_number = trapframe.get_arg(7);
+            match syscall_number {
+                $(
+                    $num => {
+                        Ok($func(abi, trapframe))
+                    }
+                )*
+                _ => {
+                    crate::println!("Unsupported Linux syscall number: {}", syscall_number);
+                    trapframe.increment_pc_next(crate::task::mytask().unwrap());
+                    Ok(crate::abi::linux::riscv64::errno::error(crate::abi::linux::riscv64::errno::ENOSYS))
+                }
+            }
+        }
+
+        /// Describe a syscall for tracing (see `crate::task::strace`)
+        pub fn syscall_info(number: usize) -> Option<crate::task::strace::SyscallInfo> {
+            match number {
+                $(
+                    $num => Some(crate::syscall_info!($name $( ($($kind),*) -> $ret )?)),
+                )*
+                _ => None,
+            }
+        }
+    };
+}
@@ -0,0 +1,81 @@
+//! Memory system calls of the Linux ABI
+//!
+//! The mapping calls take the native protection and mapping flags, which
+//! have the Linux values.
+
+use crate::{
+    abi::linux::riscv64::{call_native, errno, native_result, LinuxRiscv64Abi},
+    arch::Trapframe,
+    environment::PAGE_SIZE,
+    object::capability::memory_mapping::syscall::{
+        sys_memory_advise, sys_memory_map, sys_memory_protect, sys_memory_unmap,
+    },
+    task::mytask,
+};
+
+const MAP_ANONYMOUS: usize = 0x20;
+/// Unit of the offset of `mmap2`, whatever the page size
+const MMAP2_UNIT: usize = 4096;
+
+/// Set the program break; returns the new break, or the old one on failure
+pub fn sys_brk(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let brk = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    // brk(0) asks for the current break
+    if brk != 0 {
+        let _ = task.set_brk(brk);
+    }
+    task.get_brk()
+}
+
+pub fn sys_mmap(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let offset = trapframe.get_arg(5);
+    mmap(abi, trapframe, offset)
+}
+
+/// `mmap2` of riscv32: the offset is given in 4096-byte units
+pub fn compat_sys_mmap2(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let offset = trapframe.get_arg(5) * MMAP2_UNIT;
+    mmap(abi, trapframe, offset)
+}
+
+fn mmap(abi: &LinuxRiscv64Abi, trapframe: &mut Trapframe, offset: usize) -> usize {
+    let addr = trapframe.get_arg(0);
+    let length = trapframe.get_arg(1);
+    let prot = trapframe.get_arg(2);
+    let flags = trapframe.get_arg(3);
+    let fd = trapframe.get_arg(4);
+
+    let handle = if flags & MAP_ANONYMOUS != 0 {
+        0
+    } else {
+        match abi.get_fd(fd) {
+            Some(desc) => desc.handle as usize,
+            None => {
+                trapframe.increment_pc_next(mytask().unwrap());
+                return errno::error(errno::EBADF);
+            }
+        }
+    };
+    if offset % PAGE_SIZE != 0 || length == 0 {
+        trapframe.increment_pc_next(mytask().unwrap());
+        return errno::error(errno::EINVAL);
+    }
+
+    let result = call_native(trapframe, &[handle, addr, length, prot, flags, offset], sys_memory_map);
+    native_result(result, errno::ENOMEM)
+}
+
+pub fn sys_munmap(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    native_result(sys_memory_unmap(trapframe), errno::EINVAL)
+}
+
+pub fn sys_mprotect(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    native_result(sys_memory_protect(trapframe), errno::ENOMEM)
+}
+
+pub fn sys_madvise(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    native_result(sys_memory_advise(trapframe), errno::EINVAL)
+}
@@ -0,0 +1,616 @@
+#[macro_use]
+mod macros;
+pub mod compat;
+pub mod errno;
+mod fs;
+mod mm;
+mod poll;
+mod proc;
+mod shm;
+mod signal;
+mod socket;
+
+use alloc::{boxed::Box, collections::btree_map::BTreeMap, string::{String, ToString}, sync::Arc, vec::Vec};
+
+use crate::{
+    abi::{
+        error::KernelError,
+        linux::riscv64::{
+            fs::{
+                sys_chdir, sys_close, sys_dup, sys_dup3, sys_eventfd2, sys_faccessat, sys_fcntl, sys_fstat, sys_ftruncate,
+                sys_getcwd, sys_getdents64, sys_ioctl, sys_lseek, sys_memfd_create, sys_mkdirat, sys_newfstatat, sys_openat, sys_pipe2,
+                sys_read, sys_readv, sys_splice, sys_statx, sys_unlinkat, sys_write, sys_writev,
+            },
+            mm::{sys_brk, sys_madvise, sys_mmap, sys_mprotect, sys_munmap},
+            poll::{sys_epoll_create1, sys_epoll_ctl, sys_epoll_pwait, sys_ppoll, sys_pselect6},
+            proc::{
+                sys_clock_gettime, sys_clock_nanosleep, sys_clock_settime, sys_clone, sys_execve, sys_exit, sys_getegid, sys_geteuid,
+                sys_getgid, sys_getgroups, sys_getpgid, sys_getpid, sys_getppid, sys_getrandom, sys_getresgid, sys_getresuid,
+                sys_getsid, sys_gettid, sys_gettimeofday, sys_getuid, sys_kill, sys_nanosleep, sys_sched_yield,
+                sys_set_tid_address, sys_setgid, sys_setgroups, sys_sethostname, sys_setpgid, sys_setregid,
+                sys_setresgid, sys_setresuid, sys_setreuid, sys_setsid, sys_settimeofday, sys_setuid, sys_timerfd_create,
+                sys_timerfd_gettime, sys_timerfd_settime, sys_uname, sys_wait4, sys_waitid,
+            },
+            signal::{sys_rt_sigaction, sys_rt_sigprocmask, LinuxSigAction},
+            shm::{sys_shmat, sys_shmctl, sys_shmdt, sys_shmget},
+            socket::{
+                sys_accept, sys_accept4, sys_bind, sys_connect, sys_getpeername, sys_getsockname, sys_getsockopt,
+                sys_listen, sys_recvfrom, sys_recvmsg, sys_sendmsg, sys_sendto, sys_setsockopt, sys_shutdown,
+                sys_socket, sys_socketpair,
+            },
+            compat::LinuxRiscv32Abi,
+        },
+        AbiModule, AbiVersion, PersonalityNode,
+    },
+    arch::{self, vcpu::Xlen, Trapframe},
+    early_initcall,
+    environment::PAGE_SIZE,
+    fs::{drivers::overlayfs::OverlayFS, SeekFrom, VfsManager},
+    random::fill_random_bytes,
+    register_abi,
+    task::{
+        elf_loader::{
+            analyze_and_load_elf_with_strategy, build_auxiliary_vector, AuxVec, LoadStrategy, AT_EXECFN,
+            AT_RANDOM, ELFCLASS64,
+            note::{AbiNote, GNU_ABI_TAG_LINUX},
+        },
+        signal::{copy_to_user, NSIG},
+        Task,
+    },
+    vm::{aslr, setup_trampoline, setup_user_stack, setup_user_stack_at, vdso},
+};
+
+/// Number of file descriptors a task can have open
+const MAX_FDS: usize = 1024;
+
+/// `e_machine` of RISC-V ELF files
+const EM_RISCV: u16 = 243;
+/// `EI_OSABI` values used by Linux toolchains
+const ELFOSABI_SYSV: u8 = 0;
+const ELFOSABI_GNU: u8 = 3;
+/// `EI_OSABI` of native Scarlet programs
+const ELFOSABI_SCARLET: u8 = 83;
+
+/// Linux kernel version whose system call interface this ABI implements
+const LINUX_VERSION: AbiVersion = AbiVersion::new(6, 1, 0);
+
+/// What Linux programs look for: home directories, devices such as
+/// `/dev/null` and `/dev/urandom`, `/proc/self`, and the native root at
+/// `/scarlet`
+const LINUX_PERSONALITY: &[PersonalityNode] = &[
+    PersonalityNode::Bind { source: "/home", target: "/home" },
+    PersonalityNode::Bind { source: "/dev", target: "/dev" },
+    PersonalityNode::Mount { driver: "procfs", target: "/proc" },
+    PersonalityNode::Bind { source: "/", target: "/scarlet" },
+];
+
+/// An open file descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileDescriptor {
+    /// Handle of the open object in the task's handle table
+    pub handle: u32,
+    /// `FD_CLOEXEC`: closed when the task execs another program
+    pub close_on_exec: bool,
+    /// File status flags given to `open` (access mode, `O_APPEND`, `O_NONBLOCK`)
+    ///
+    /// Unlike on Linux they belong to the descriptor, not to the open file
+    /// shared by its duplicates.
+    pub status_flags: usize,
+}
+
+#[derive(Clone)]
+pub struct LinuxRiscv64Abi {
+    /// Open file descriptors
+    fds: BTreeMap<usize, FileDescriptor>,
+    /// Signal dispositions as set by the program (see [`signal`])
+    sigactions: [LinuxSigAction; NSIG],
+}
+
+impl Default for LinuxRiscv64Abi {
+    fn default() -> Self {
+        Self {
+            fds: BTreeMap::new(),
+            sigactions: [LinuxSigAction::default(); NSIG],
+        }
+    }
+}
+
+impl LinuxRiscv64Abi {
+    /// Map `handle` to the lowest free file descriptor not below `min`
+    ///
+    /// # Errors
+    /// `EMFILE` if no descriptor is free.
+    pub fn allocate_fd(&mut self, handle: u32, min: usize, close_on_exec: bool, status_flags: usize) -> Result<usize, usize> {
+        let fd = (min..MAX_FDS).find(|fd| !self.fds.contains_key(fd)).ok_or(errno::EMFILE)?;
+        self.fds.insert(fd, FileDescriptor { handle, close_on_exec, status_flags });
+        Ok(fd)
+    }
+
+    /// Map `descriptor` to the file descriptor `fd`, returning what `fd` referred to
+    ///
+    /// # Errors
+    /// `EBADF` if `fd` is out of range.
+    pub fn install_fd(&mut self, fd: usize, descriptor: FileDescriptor) -> Result<Option<FileDescriptor>, usize> {
+        if fd >= MAX_FDS {
+            return Err(errno::EBADF);
+        }
+        Ok(self.fds.insert(fd, descriptor))
+    }
+
+    pub fn get_fd(&self, fd: usize) -> Option<FileDescriptor> {
+        self.fds.get(&fd).copied()
+    }
+
+    pub fn get_fd_mut(&mut self, fd: usize) -> Option<&mut FileDescriptor> {
+        self.fds.get_mut(&fd)
+    }
+
+    /// Remove the file descriptor `fd`; the caller closes its handle
+    pub fn remove_fd(&mut self, fd: usize) -> Option<FileDescriptor> {
+        self.fds.remove(&fd)
+    }
+
+    /// Prepare the ABI state for the next program after an exec
+    ///
+    /// Descriptors marked close-on-exec are closed and the dispositions of
+    /// caught signals go back to the default, as the kernel did for its own.
+    pub fn exec_done(&mut self, task: &mut Task) {
+        let closing: Vec<usize> = self.fds.iter().filter(|(_, desc)| desc.close_on_exec).map(|(&fd, _)| fd).collect();
+        for fd in closing {
+            if let Some(desc) = self.fds.remove(&fd) {
+                task.handle_table.write().remove(desc.handle);
+            }
+        }
+        for action in self.sigactions.iter_mut() {
+            if !action.is_ignored() {
+                *action = LinuxSigAction::default();
+            }
+        }
+    }
+
+    /// Lay out the initial stack of a program: argc, argv, envp and the
+    /// auxiliary vector, with the strings, the `AT_EXECFN` path and the
+    /// `AT_RANDOM` bytes above
+    ///
+    /// The words are `word` bytes wide: 8, or 4 for a 32-bit program.
+    ///
+    /// # Returns
+    /// The stack pointer, pointing at argc
+    fn setup_initial_stack(task: &mut Task, stack_top: usize, argv: &[&str], envp: &[&str], auxv: &[AuxVec], word: usize) -> Result<usize, &'static str> {
+        let mut sp = stack_top;
+
+        let push_string = |task: &Task, sp: &mut usize, s: &str| -> Result<usize, &'static str> {
+            *sp -= s.len() + 1;
+            copy_to_user(task, *sp, s.as_bytes())?;
+            copy_to_user(task, *sp + s.len(), &[0])?;
+            Ok(*sp)
+        };
+        let execfn = task.exec_path.clone();
+        let execfn_addr = push_string(task, &mut sp, &execfn)?;
+        let mut argv_ptrs = Vec::with_capacity(argv.len());
+        for arg in argv {
+            argv_ptrs.push(push_string(task, &mut sp, arg)?);
+        }
+        let mut envp_ptrs = Vec::with_capacity(envp.len());
+        for env in envp {
+            envp_ptrs.push(push_string(task, &mut sp, env)?);
+        }
+
+        let mut random = [0u8; 16];
+        fill_random_bytes(&mut random);
+        sp -= random.len();
+        copy_to_user(task, sp, &random)?;
+        let random_addr = sp;
+
+        let mut words: Vec<usize> = Vec::new();
+        words.push(argv.len());
+        words.extend(argv_ptrs);
+        words.push(0);
+        words.extend(envp_ptrs);
+        words.push(0);
+        for entry in auxv {
+            let value = match entry.a_type {
+                AT_RANDOM => random_addr as u64,
+                AT_EXECFN => execfn_addr as u64,
+                _ => entry.a_val,
+            };
+            words.push(entry.a_type as usize);
+            words.push(value as usize);
+        }
+
+        let size = words.len() * word;
+        sp = (sp - size) & !15;
+        let bytes: Vec<u8> = words.iter().flat_map(|value| value.to_le_bytes()[..word].to_vec()).collect();
+        copy_to_user(task, sp, &bytes)?;
+        Ok(sp)
+    }
+}
+
+impl AbiModule for LinuxRiscv64Abi {
+    fn name() -> &'static str {
+        "linux-riscv64"
+    }
+
+    fn get_name(&self) -> String {
+        Self::name().to_string()
+    }
+
+    fn clone_boxed(&self) -> Box<dyn AbiModule + Send + Sync> {
+        Box::new(self.clone())
+    }
+
+    fn handle_syscall(&mut self, trapframe: &mut Trapframe) -> Result<usize, &'static str> {
+        syscall_handler(self, trapframe)
+    }
+
+    fn syscall_info(&self, number: usize) -> Option<crate::task::strace::SyscallInfo> {
+        syscall_info(number)
+    }
+
+    fn syscall_error_formatter(&self) -> crate::task::strace::SyscallErrorFn {
+        errno::format_error
+    }
+
+    fn error_code(&self, error: KernelError) -> usize {
+        errno::error(errno::from_error(error))
+    }
+
+    fn can_execute_binary(
+        &self,
+        file_object: &crate::object::KernelObject,
+        file_path: &str,
+        current_abi: Option<&(dyn AbiModule + Send + Sync)>
+    ) -> Option<u8> {
+        elf_confidence(Self::name(), ELFCLASS64, file_object, file_path, current_abi)
+    }
+
+    fn version(&self) -> AbiVersion {
+        LINUX_VERSION
+    }
+
+    fn negotiate_version(&self, note: &AbiNote) -> Option<AbiVersion> {
+        negotiate_linux_version(Self::name(), note)
+    }
+
+    fn execute_binary(
+        &self,
+        file_object: &crate::object::KernelObject,
+        argv: &[&str],
+        envp: &[&str],
+        task: &mut Task,
+        trapframe: &mut Trapframe
+    ) -> Result<(), &'static str> {
+        execute_elf(file_object, argv, envp, task, trapframe, Xlen::X64)
+    }
+
+    fn get_default_cwd(&self) -> &str {
+        "/"
+    }
+
+    fn setup_overlay_environment(
+        &self,
+        target_vfs: &Arc<VfsManager>,
+        base_vfs: &Arc<VfsManager>,
+        system_path: &str,
+        config_path: &str,
+    ) -> Result<(), &'static str> {
+        // The Linux root is the system tree of the ABI, with changes kept in its config directory
+        let lower_vfs_list = alloc::vec![(base_vfs, system_path)];
+        let fs = match OverlayFS::new_from_paths_and_vfs(Some((base_vfs, config_path)), lower_vfs_list, "/") {
+            Ok(fs) => fs,
+            Err(e) => {
+                crate::println!("Failed to create overlay filesystem for Linux ABI: {}", e.message);
+                return Err("Failed to create Linux overlay environment");
+            }
+        };
+        target_vfs.mount(fs, "/", 0).map_err(|e| {
+            crate::println!("Failed to mount overlay for Linux ABI: {}", e.message);
+            "Failed to create Linux overlay environment"
+        })
+    }
+
+    fn personality_nodes(&self) -> &'static [PersonalityNode] {
+        LINUX_PERSONALITY
+    }
+
+    fn initialize_from_existing_handles(&mut self, task: &mut Task) -> Result<(), &'static str> {
+        // Every open handle becomes the file descriptor of the same number,
+        // so the standard streams of the caller are 0, 1 and 2
+        self.fds.clear();
+        let handle_table = task.handle_table.read();
+        for handle in handle_table.active_handles() {
+            let status_flags = match handle_table.get_metadata(handle).map(|metadata| metadata.access_mode) {
+                Some(crate::object::handle::AccessMode::ReadOnly) => fs::O_RDONLY,
+                Some(crate::object::handle::AccessMode::WriteOnly) => fs::O_WRONLY,
+                _ => fs::O_RDWR,
+            };
+            self.install_fd(handle as usize, FileDescriptor { handle, close_on_exec: false, status_flags })
+                .map_err(|_| "Handle number out of the file descriptor range")?;
+        }
+        Ok(())
+    }
+
+    fn get_interpreter_path(&self, requested_interpreter: &str) -> String {
+        requested_interpreter.to_string()
+    }
+}
+
+syscall_table! {
+    Getcwd = 17 (Hex, Uint) -> Hex => sys_getcwd,
+    Eventfd2 = 19 (Uint, Hex) -> Int => sys_eventfd2,
+    EpollCreate1 = 20 (Hex) -> Int => sys_epoll_create1,
+    EpollCtl = 21 (Int, Int, Int, Hex) -> Int => sys_epoll_ctl,
+    EpollPwait = 22 (Int, Hex, Int, Int, Hex, Uint) -> Int => sys_epoll_pwait,
+    Dup = 23 (Int) -> Int => sys_dup,
+    Dup3 = 24 (Int, Int, Hex) -> Int => sys_dup3,
+    Fcntl = 25 (Int, Int, Hex) -> Int => sys_fcntl,
+    Ioctl = 29 (Int, Hex, Hex) -> Int => sys_ioctl,
+    Mkdirat = 34 (Int, Str, Hex) -> Int => sys_mkdirat,
+    Unlinkat = 35 (Int, Str, Hex) -> Int => sys_unlinkat,
+    Ftruncate = 46 (Int, Uint) -> Int => sys_ftruncate,
+    Faccessat = 48 (Int, Str, Hex) -> Int => sys_faccessat,
+    Chdir = 49 (Str) -> Int => sys_chdir,
+    Openat = 56 (Int, Str, Hex, Hex) -> Int => sys_openat,
+    Close = 57 (Int) -> Int => sys_close,
+    Pipe2 = 59 (Hex, Hex) -> Int => sys_pipe2,
+    Getdents64 = 61 (Int, Hex, Uint) -> Int => sys_getdents64,
+    Lseek = 62 (Int, Int, Int) -> Int => sys_lseek,
+    Read = 63 (Int, Hex, Uint) -> Int => sys_read,
+    Write = 64 (Int, Buf, Uint) -> Int => sys_write,
+    Readv = 65 (Int, Hex, Uint) -> Int => sys_readv,
+    Writev = 66 (Int, Hex, Uint) -> Int => sys_writev,
+    Pselect6 = 72 (Int, Hex, Hex, Hex, Hex, Hex) -> Int => sys_pselect6,
+    Ppoll = 73 (Hex, Uint, Hex, Hex, Uint) -> Int => sys_ppoll,
+    Splice = 76 (Int, Hex, Int, Hex, Uint, Hex) -> Int => sys_splice,
+    Newfstatat = 79 (Int, Str, Hex, Hex) -> Int => sys_newfstatat,
+    Fstat = 80 (Int, Hex) -> Int => sys_fstat,
+    TimerfdCreate = 85 (Int, Hex) -> Int => sys_timerfd_create,
+    TimerfdSettime = 86 (Int, Hex, Hex, Hex) -> Int => sys_timerfd_settime,
+    TimerfdGettime = 87 (Int, Hex) -> Int => sys_timerfd_gettime,
+    Exit = 93 (Int) -> Int => sys_exit,
+    ExitGroup = 94 (Int) -> Int => sys_exit,
+    Waitid = 95 (Int, Int, Hex, Hex, Hex) -> Int => sys_waitid,
+    SetTidAddress = 96 => sys_set_tid_address,
+    Nanosleep = 101 (Hex, Hex) -> Int => sys_nanosleep,
+    ClockSettime = 112 (Int, Hex) -> Int => sys_clock_settime,
+    ClockGettime = 113 => sys_clock_gettime,
+    ClockNanosleep = 115 (Int, Hex, Hex, Hex) -> Int => sys_clock_nanosleep,
+    SchedYield = 124 => sys_sched_yield,
+    Kill = 129 (Int, Int) -> Int => sys_kill,
+    RtSigaction = 134 => sys_rt_sigaction,
+    RtSigprocmask = 135 => sys_rt_sigprocmask,
+    Setregid = 143 => sys_setregid,
+    Setgid = 144 => sys_setgid,
+    Setreuid = 145 => sys_setreuid,
+    Setuid = 146 => sys_setuid,
+    Setresuid = 147 => sys_setresuid,
+    Getresuid = 148 => sys_getresuid,
+    Setresgid = 149 => sys_setresgid,
+    Getresgid = 150 => sys_getresgid,
+    Setpgid = 154 (Int, Int) -> Int => sys_setpgid,
+    Getpgid = 155 => sys_getpgid,
+    Getsid = 156 => sys_getsid,
+    Setsid = 157 => sys_setsid,
+    Getgroups = 158 => sys_getgroups,
+    Setgroups = 159 => sys_setgroups,
+    Uname = 160 => sys_uname,
+    Sethostname = 161 (Buf, Uint) -> Int => sys_sethostname,
+    Gettimeofday = 169 => sys_gettimeofday,
+    Settimeofday = 170 (Hex, Hex) -> Int => sys_settimeofday,
+    Getpid = 172 => sys_getpid,
+    Getppid = 173 => sys_getppid,
+    Getuid = 174 => sys_getuid,
+    Geteuid = 175 => sys_geteuid,
+    Getgid = 176 => sys_getgid,
+    Getegid = 177 => sys_getegid,
+    Gettid = 178 => sys_gettid,
+    Shmget = 194 (Hex, Uint, Hex) -> Int => sys_shmget,
+    Shmctl = 195 (Int, Int, Hex) -> Int => sys_shmctl,
+    Shmat = 196 (Int, Hex, Hex) -> Hex => sys_shmat,
+    Shmdt = 197 (Hex) -> Int => sys_shmdt,
+    Socket = 198 (Int, Hex, Int) -> Int => sys_socket,
+    Socketpair = 199 (Int, Hex, Int, Hex) -> Int => sys_socketpair,
+    Bind = 200 (Int, Hex, Uint) -> Int => sys_bind,
+    Listen = 201 (Int, Int) -> Int => sys_listen,
+    Accept = 202 (Int, Hex, Hex) -> Int => sys_accept,
+    Connect = 203 (Int, Hex, Uint) -> Int => sys_connect,
+    Getsockname = 204 (Int, Hex, Hex) -> Int => sys_getsockname,
+    Getpeername = 205 (Int, Hex, Hex) -> Int => sys_getpeername,
+    Sendto = 206 (Int, Buf, Uint, Hex, Hex, Uint) -> Int => sys_sendto,
+    Recvfrom = 207 (Int, Hex, Uint, Hex, Hex, Hex) -> Int => sys_recvfrom,
+    Setsockopt = 208 (Int, Int, Int, Hex, Uint) -> Int => sys_setsockopt,
+    Getsockopt = 209 (Int, Int, Int, Hex, Hex) -> Int => sys_getsockopt,
+    Shutdown = 210 (Int, Int) -> Int => sys_shutdown,
+    Sendmsg = 211 (Int, Hex, Hex) -> Int => sys_sendmsg,
+    Recvmsg = 212 (Int, Hex, Hex) -> Int => sys_recvmsg,
+    Brk = 214 (Hex) -> Hex => sys_brk,
+    Munmap = 215 (Hex, Uint) -> Int => sys_munmap,
+    Clone = 220 (Hex, Hex, Hex, Hex, Hex) -> Int => sys_clone,
+    Execve = 221 (Str, StrArray, Hex) -> Int => sys_execve,
+    Mmap = 222 (Hex, Uint, Hex, Hex, Int, Hex) -> Hex => sys_mmap,
+    Mprotect = 226 (Hex, Uint, Hex) -> Int => sys_mprotect,
+    Madvise = 233 => sys_madvise,
+    Accept4 = 242 (Int, Hex, Hex, Hex) -> Int => sys_accept4,
+    Wait4 = 260 (Int, Hex, Hex, Hex) -> Int => sys_wait4,
+    Getrandom = 278 (Hex, Uint, Hex) -> Int => sys_getrandom,
+    MemfdCreate = 279 (Str, Hex) -> Int => sys_memfd_create,
+    Statx = 291 (Int, Str, Hex, Hex, Hex) -> Int => sys_statx,
+}
+
+/// Call a native system call with the argument registers `args`
+///
+/// The Linux syscall ABI preserves every register but a0, so the argument
+/// registers are restored afterwards.
+fn call_native(trapframe: &mut Trapframe, args: &[usize], syscall: fn(&mut Trapframe) -> usize) -> usize {
+    let saved: Vec<usize> = (0..args.len()).map(|i| trapframe.get_arg(i)).collect();
+    for (i, &arg) in args.iter().enumerate() {
+        trapframe.set_arg(i, arg);
+    }
+    let result = syscall(trapframe);
+    for (i, &arg) in saved.iter().enumerate() {
+        trapframe.set_arg(i, arg);
+    }
+    result
+}
+
+/// Result of a delegated native system call: usize::MAX becomes `-errno`
+/// of the error the call recorded, or of `errno` if it recorded none
+fn native_result(result: usize, errno: usize) -> usize {
+    let recorded = crate::abi::error::take_error();
+    if result != usize::MAX {
+        return result;
+    }
+    errno::error(recorded.map_or(errno, errno::from_error))
+}
+
+/// Confidence that a program is a Linux RISC-V program of the ELF class
+/// `class`, for `AbiModule::can_execute_binary` of the ABI `abi_name`
+fn elf_confidence(
+    abi_name: &str,
+    class: u8,
+    file_object: &crate::object::KernelObject,
+    file_path: &str,
+    current_abi: Option<&(dyn AbiModule + Send + Sync)>,
+) -> Option<u8> {
+    let file_obj = file_object.as_file()?;
+    let mut header = [0u8; 20];
+    file_obj.seek(SeekFrom::Start(0)).ok();
+    match file_obj.read(&mut header) {
+        Ok(bytes_read) if bytes_read == header.len() => {}
+        _ => return None,
+    }
+    if header[..4] != [0x7F, b'E', b'L', b'F'] {
+        return None;
+    }
+    // RISC-V of the given class only
+    if header[4] != class || u16::from_le_bytes([header[18], header[19]]) != EM_RISCV {
+        return None;
+    }
+
+    // Stage 1: Basic format validation (slightly lower than Scarlet and xv6)
+    let mut confidence = 20;
+
+    // Stage 2: OSABI - GNU/Linux is a strong indicator (it must beat the
+    // inheritance bonus of the Scarlet ABI), SYSV is shared with xv6 programs
+    match header[7] {
+        ELFOSABI_GNU => confidence += 60,
+        ELFOSABI_SYSV => confidence += 10,
+        ELFOSABI_SCARLET => return None,
+        _ => {}
+    }
+
+    // Stage 3: File path hints
+    if file_path.contains("linux") {
+        confidence += 30;
+    }
+
+    // Stage 4: ABI inheritance bonus - programs started from Linux programs stay Linux
+    if let Some(current) = current_abi {
+        if current.get_name() == abi_name {
+            confidence += 40;
+        }
+    }
+
+    Some(confidence.min(100))
+}
+
+/// The version a Linux program runs with: the kernel version of its GNU
+/// ABI tag, or the version of a Scarlet ABI tag naming `abi_name`
+fn negotiate_linux_version(abi_name: &str, note: &AbiNote) -> Option<AbiVersion> {
+    let version = match note {
+        AbiNote::Gnu { os: GNU_ABI_TAG_LINUX, version } => *version,
+        AbiNote::Scarlet { abi, version } if abi == abi_name => *version,
+        _ => return None,
+    };
+    (version <= LINUX_VERSION).then_some(version)
+}
+
+/// Load a Linux ELF program into `task` and start it with `argv` and `envp`
+///
+/// A 32-bit program ([`Xlen::X32`]) gets its stack and mappings below
+/// 2 GiB, 32-bit words on its initial stack and no vDSO, whose data is laid
+/// out for 64-bit code.
+fn execute_elf(
+    file_object: &crate::object::KernelObject,
+    argv: &[&str],
+    envp: &[&str],
+    task: &mut Task,
+    trapframe: &mut Trapframe,
+    xlen: Xlen,
+) -> Result<(), &'static str> {
+    let file_obj = file_object.as_file().ok_or("Invalid file object type for Linux binary execution")?;
+    if xlen == Xlen::X32 && !arch::user_xlen_supported(Xlen::X32) {
+        return Err("This CPU cannot run 32-bit programs");
+    }
+    task.text_size = 0;
+    task.data_size = 0;
+    task.stack_size = 0;
+
+    let elf_result = analyze_and_load_elf_with_strategy(file_obj, task, &LoadStrategy::default())
+        .map_err(|_| "Failed to load Linux ELF binary")?;
+    task.name = argv.first().map_or("linux".to_string(), |s| s.to_string());
+
+    // Clear page table entries
+    let idx = arch::vm::get_root_pagetable_ptr(task.vm_manager.read().get_asid()).unwrap();
+    let root_page_table = arch::vm::get_pagetable(idx).unwrap();
+    root_page_table.unmap_all();
+    setup_trampoline(&mut task.vm_manager.write());
+    let (stack_top, word) = match xlen {
+        Xlen::X64 => {
+            // Not announced with AT_SYSINFO_EHDR: the pages are not an ELF image
+            vdso::map_vdso(task)?;
+            (setup_user_stack(task).1, 8)
+        }
+        Xlen::X32 => {
+            let (_, stack_top) = setup_user_stack_at(task, aslr::compat_stack_top());
+            // Mappings stay clear of the stack as far as it may grow
+            let stack_limit = stack_top - task.max_stack_size - PAGE_SIZE;
+            task.vm_manager.write().set_mmap_limit(stack_limit);
+            (stack_top, 4)
+        }
+    };
+    aslr::randomize_task_layout(task);
+
+    let auxv = build_auxiliary_vector(&elf_result, &task.cred);
+    let stack_pointer = LinuxRiscv64Abi::setup_initial_stack(task, stack_top, argv, envp, &auxv, word)?;
+    debug_assert!(stack_top - stack_pointer < 16 * PAGE_SIZE);
+
+    task.set_entry_point(elf_result.entry_point as usize);
+    task.vcpu.reset_iregs();
+    task.vcpu.set_sp(stack_pointer);
+    task.vcpu.set_xlen(xlen);
+    // The C library sets up its own TLS; this keeps the task's TLS state consistent
+    task.setup_tls()?;
+    task.vcpu.switch(trapframe);
+    Ok(())
+}
+
+fn register_linux_abi() {
+    register_abi!(LinuxRiscv64Abi);
+    register_abi!(LinuxRiscv32Abi);
+}
+
+early_initcall!(register_linux_abi);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_linux_fd_allocation() {
+        let mut abi = LinuxRiscv64Abi::default();
+        assert_eq!(abi.allocate_fd(10, 0, false, fs::O_RDONLY), Ok(0));
+        assert_eq!(abi.allocate_fd(11, 0, false, fs::O_RDONLY), Ok(1));
+        assert_eq!(abi.allocate_fd(12, 0, true, fs::O_RDONLY), Ok(2));
+        // The lowest free descriptor is reused first
+        assert_eq!(abi.remove_fd(0).map(|desc| desc.handle), Some(10));
+        assert_eq!(abi.allocate_fd(13, 0, false, fs::O_RDONLY), Ok(0));
+        // F_DUPFD-style lower bound
+        assert_eq!(abi.allocate_fd(14, 1, false, fs::O_RDONLY), Ok(3));
+
+        let replaced = abi.install_fd(1, FileDescriptor { handle: 15, close_on_exec: false, status_flags: fs::O_WRONLY });
+        assert_eq!(replaced.unwrap().map(|desc| desc.handle), Some(11));
+        assert!(abi.install_fd(MAX_FDS, FileDescriptor { handle: 16, close_on_exec: false, status_flags: 0 }).is_err());
+        assert_eq!(abi.get_fd(2).map(|desc| desc.close_on_exec), Some(true));
+    }
+}
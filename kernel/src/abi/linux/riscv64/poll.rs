@@ -0,0 +1,398 @@
+//! Readiness system calls of the Linux ABI: `ppoll`, `pselect6` and epoll
+//!
+//! Descriptors are waited on through the poll capability of their objects
+//! (see `crate::object::capability::poll`), whose events are the Linux
+//! ones. A signal mask passed to `ppoll`, `pselect6` or `epoll_pwait`
+//! replaces the mask while the call waits; when a signal interrupts the
+//! call, it is delivered under that mask and a handler returns to the
+//! original one.
+//!
+//! Like Linux, `ppoll` and `pselect6` write the remaining time back to their
+//! timeout, so a restarted call waits only for what is left.
+
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    abi::linux::riscv64::{
+        errno,
+        fs::{fd_object, install, result, O_CLOEXEC, O_RDWR},
+        proc::{read_timespec, write_timespec},
+        signal::SIGSET_SIZE,
+        LinuxRiscv64Abi, MAX_FDS,
+    },
+    arch::Trapframe,
+    object::{
+        capability::poll::{
+            poll, timeout_ticks, wait_for, PollOps, PollRequest, PollWait, POLLERR, POLLHUP, POLLIN, POLLOUT,
+            POLLPRI, POLL_ALWAYS,
+        },
+        epoll::{
+            syscall::{write_events, EPOLL_MAX_EVENTS},
+            EpollEvent, EpollObject, EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLL_CTL_MOD,
+        },
+        KernelObject,
+    },
+    task::{
+        mytask,
+        signal::{self, copy_from_user, copy_to_user, SigSet},
+        Task,
+    },
+    timer::get_time_ns,
+};
+
+/// Events of `poll(2)` that are aliases of `POLLIN` and `POLLOUT`
+const POLLRDNORM: u32 = 0x040;
+const POLLWRNORM: u32 = 0x100;
+
+/// Size of `struct pollfd`
+const POLLFD_SIZE: usize = 8;
+
+/// Flag of `epoll_create1`
+const EPOLL_CLOEXEC: usize = O_CLOEXEC;
+
+/// Events `select` reports a descriptor of each set for
+const SELECT_READ: u32 = POLLIN | POLLHUP | POLLERR;
+const SELECT_WRITE: u32 = POLLOUT | POLLERR;
+const SELECT_EXCEPT: u32 = POLLPRI;
+
+/// The native events asked for by the Linux events `events`
+fn requested_events(events: u32) -> u32 {
+    let mut requested = events;
+    if events & POLLRDNORM != 0 {
+        requested |= POLLIN;
+    }
+    if events & POLLWRNORM != 0 {
+        requested |= POLLOUT;
+    }
+    requested
+}
+
+/// The Linux events reported for a request of `events` when `ready` are
+fn reported_events(events: u32, ready: u32) -> u32 {
+    let mut reported = ready & (events | POLL_ALWAYS);
+    if ready & POLLIN != 0 {
+        reported |= events & POLLRDNORM;
+    }
+    if ready & POLLOUT != 0 {
+        reported |= events & POLLWRNORM;
+    }
+    reported
+}
+
+/// Replace the signal mask of `task` by the `sigset_t` at `sigmask_ptr`
+/// until the call returns, if the pointer is not null
+fn set_sigmask(task: &mut Task, sigmask_ptr: usize, sigsetsize: usize) -> Result<(), usize> {
+    if sigmask_ptr == 0 {
+        return Ok(());
+    }
+    if sigsetsize != SIGSET_SIZE {
+        return Err(errno::EINVAL);
+    }
+    let mut bytes = [0u8; SIGSET_SIZE];
+    copy_from_user(task, sigmask_ptr, &mut bytes).map_err(|_| errno::EFAULT)?;
+    task.signals.set_temporary_mask(SigSet::from_bits(u64::from_le_bytes(bytes)));
+    Ok(())
+}
+
+/// Timeout of a `struct timespec` at `ptr`: the deadline in nanoseconds
+/// since boot and the ticks to wait, `None` for a null pointer
+fn read_deadline(ptr: usize) -> Result<Option<(u64, u64)>, usize> {
+    if ptr == 0 {
+        return Ok(None);
+    }
+    let timeout = read_timespec(ptr)?;
+    Ok(Some((get_time_ns().saturating_add(timeout), timeout_ticks(timeout))))
+}
+
+/// Write what remains until `deadline` back to the timeout at `ptr`
+fn write_remaining(ptr: usize, deadline: Option<(u64, u64)>) {
+    if let Some((deadline, _)) = deadline {
+        let _ = write_timespec(ptr, deadline.saturating_sub(get_time_ns()));
+    }
+}
+
+/// `ppoll`, also `ppoll_time64` of riscv32
+///
+/// Entries with a negative descriptor are ignored; a descriptor that is not
+/// open is reported with `POLLNVAL`.
+pub fn sys_ppoll(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fds_ptr = trapframe.get_arg(0);
+    let nfds = trapframe.get_arg(1);
+    let tsp = trapframe.get_arg(2);
+    let sigmask_ptr = trapframe.get_arg(3);
+    let sigsetsize = trapframe.get_arg(4);
+    trapframe.increment_pc_next(task);
+
+    if nfds > MAX_FDS {
+        return errno::error(errno::EINVAL);
+    }
+    let mut fds = vec![0u8; nfds * POLLFD_SIZE];
+    if copy_from_user(task, fds_ptr, &mut fds).is_err() {
+        return errno::error(errno::EFAULT);
+    }
+    let deadline = match read_deadline(tsp) {
+        Ok(deadline) => deadline,
+        Err(err) => return errno::error(err),
+    };
+
+    let mut polled = Vec::new();
+    let mut requests = Vec::new();
+    for (index, entry) in fds.chunks_exact(POLLFD_SIZE).enumerate() {
+        let fd = i32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]);
+        if fd < 0 {
+            continue;
+        }
+        let events = u16::from_le_bytes([entry[4], entry[5]]) as u32;
+        let object = abi
+            .get_fd(fd as usize)
+            .and_then(|desc| task.handle_table.read().get(desc.handle).map(KernelObject::downgrade));
+        polled.push((index, events));
+        requests.push(PollRequest { object, events: requested_events(events) });
+    }
+
+    if let Err(err) = set_sigmask(task, sigmask_ptr, sigsetsize) {
+        return errno::error(err);
+    }
+    let revents = poll(&requests, deadline.map(|(_, ticks)| ticks));
+    write_remaining(tsp, deadline);
+    let revents = match revents {
+        Ok(revents) => revents,
+        Err(_) => return signal::interrupt_sleep(task, fds_ptr),
+    };
+    task.signals.restore_mask();
+
+    for entry in fds.chunks_exact_mut(POLLFD_SIZE) {
+        entry[6..8].fill(0);
+    }
+    let mut ready = 0;
+    for ((index, events), revents) in polled.into_iter().zip(revents) {
+        let revents = reported_events(events, revents);
+        let offset = index * POLLFD_SIZE;
+        fds[offset + 6..offset + 8].copy_from_slice(&(revents as u16).to_le_bytes());
+        if revents != 0 {
+            ready += 1;
+        }
+    }
+    if copy_to_user(task, fds_ptr, &fds).is_err() {
+        return errno::error(errno::EFAULT);
+    }
+    ready
+}
+
+pub fn sys_pselect6(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    pselect6(abi, trapframe, 8)
+}
+
+/// `pselect6_time64` of riscv32, whose signal mask argument holds 32-bit
+/// words
+pub fn compat_sys_pselect6(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    pselect6(abi, trapframe, 4)
+}
+
+/// Read the `fd_set` of `nfds` descriptors at `ptr`, empty for a null pointer
+fn read_fd_set(task: &Task, ptr: usize, nfds: usize) -> Result<Vec<u8>, usize> {
+    let mut set = vec![0u8; nfds.div_ceil(8)];
+    if ptr != 0 {
+        copy_from_user(task, ptr, &mut set).map_err(|_| errno::EFAULT)?;
+    }
+    Ok(set)
+}
+
+fn is_set(set: &[u8], fd: usize) -> bool {
+    set[fd / 8] & (1 << (fd % 8)) != 0
+}
+
+/// `word` is the size of the words of the signal mask argument, a pointer
+/// to `{ const sigset_t *ss; size_t ss_len; }`
+fn pselect6(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe, word: usize) -> usize {
+    let task = mytask().unwrap();
+    let nfds = trapframe.get_arg(0);
+    let set_ptrs = [trapframe.get_arg(1), trapframe.get_arg(2), trapframe.get_arg(3)];
+    let tsp = trapframe.get_arg(4);
+    let sig_ptr = trapframe.get_arg(5);
+    trapframe.increment_pc_next(task);
+
+    if nfds > MAX_FDS {
+        return errno::error(errno::EINVAL);
+    }
+    let mut sets = Vec::with_capacity(set_ptrs.len());
+    for &ptr in &set_ptrs {
+        match read_fd_set(task, ptr, nfds) {
+            Ok(set) => sets.push(set),
+            Err(err) => return errno::error(err),
+        }
+    }
+    let deadline = match read_deadline(tsp) {
+        Ok(deadline) => deadline,
+        Err(err) => return errno::error(err),
+    };
+    let (sigmask_ptr, sigsetsize) = if sig_ptr == 0 {
+        (0, 0)
+    } else {
+        let mut bytes = [0u8; 16];
+        if copy_from_user(task, sig_ptr, &mut bytes[..2 * word]).is_err() {
+            return errno::error(errno::EFAULT);
+        }
+        let read_word = |offset: usize| {
+            let mut value = [0u8; 8];
+            value[..word].copy_from_slice(&bytes[offset..offset + word]);
+            u64::from_le_bytes(value) as usize
+        };
+        (read_word(0), read_word(word))
+    };
+
+    // The descriptors in any set, with the events of each set they are in
+    let mut watched = Vec::new();
+    for fd in 0..nfds {
+        let events = [SELECT_READ, SELECT_WRITE, SELECT_EXCEPT]
+            .iter()
+            .zip(&sets)
+            .filter(|(_, set)| is_set(set, fd))
+            .fold(0, |events, (select, _)| events | select);
+        if events == 0 {
+            continue;
+        }
+        match fd_object(abi, task, fd) {
+            Ok((_, object)) => watched.push((fd, object, events)),
+            Err(err) => return errno::error(err),
+        }
+    }
+    let sources: Vec<&dyn PollOps> = watched.iter().filter_map(|(_, object, _)| object.as_pollable()).collect();
+
+    if let Err(err) = set_sigmask(task, sigmask_ptr, sigsetsize) {
+        return errno::error(err);
+    }
+    let mut ready_sets = vec![vec![0u8; nfds.div_ceil(8)]; set_ptrs.len()];
+    let mut ready = 0;
+    let wait = wait_for(&sources, deadline.map(|(_, ticks)| ticks), || {
+        ready = 0;
+        ready_sets.iter_mut().for_each(|set| set.fill(0));
+        for (fd, object, events) in &watched {
+            let events_ready = object.poll_events();
+            for ((select, set), ready_set) in [SELECT_READ, SELECT_WRITE, SELECT_EXCEPT].iter().zip(&sets).zip(&mut ready_sets) {
+                if is_set(set, *fd) && events & events_ready & select != 0 {
+                    ready_set[fd / 8] |= 1 << (fd % 8);
+                    ready += 1;
+                }
+            }
+        }
+        ready != 0
+    });
+    write_remaining(tsp, deadline);
+    if wait == PollWait::Interrupted {
+        return signal::interrupt_sleep(task, nfds);
+    }
+    task.signals.restore_mask();
+
+    for (&ptr, set) in set_ptrs.iter().zip(&ready_sets) {
+        if ptr != 0 && copy_to_user(task, ptr, set).is_err() {
+            return errno::error(errno::EFAULT);
+        }
+    }
+    ready
+}
+
+pub fn sys_epoll_create1(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let flags = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    if flags & !EPOLL_CLOEXEC != 0 {
+        return errno::error(errno::EINVAL);
+    }
+    let epoll = KernelObject::from_epoll(EpollObject::new());
+    result(install(abi, task, epoll, flags & EPOLL_CLOEXEC != 0, O_RDWR))
+}
+
+/// `epoll_ctl`; descriptors are the keys of the set
+pub fn sys_epoll_ctl(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let epfd = trapframe.get_arg(0);
+    let op = trapframe.get_arg(1);
+    let fd = trapframe.get_arg(2);
+    let event_ptr = trapframe.get_arg(3);
+    trapframe.increment_pc_next(task);
+
+    let (epoll, object) = match (fd_object(abi, task, epfd), fd_object(abi, task, fd)) {
+        (Ok((_, epoll)), Ok((_, object))) => (epoll, object),
+        (Err(err), _) | (_, Err(err)) => return errno::error(err),
+    };
+    let Some(epoll) = epoll.as_epoll() else {
+        return errno::error(errno::EINVAL);
+    };
+    let event = if op == EPOLL_CTL_DEL {
+        EpollEvent { events: 0, data: 0 }
+    } else {
+        let mut bytes = [0u8; EpollEvent::SIZE];
+        if copy_from_user(task, event_ptr, &mut bytes).is_err() {
+            return errno::error(errno::EFAULT);
+        }
+        let event = EpollEvent::from_bytes(&bytes);
+        EpollEvent { events: requested_events(event.events), ..event }
+    };
+    let key = fd as u32;
+    let outcome = match op {
+        EPOLL_CTL_ADD => epoll.add(key, object, event),
+        EPOLL_CTL_DEL => epoll.remove(key),
+        EPOLL_CTL_MOD => epoll.modify(key, event),
+        _ => return errno::error(errno::EINVAL),
+    };
+    match outcome {
+        Ok(()) => 0,
+        Err(error) => errno::error(errno::from_error(error)),
+    }
+}
+
+/// `epoll_pwait`; a negative timeout in milliseconds waits forever
+///
+/// Like on Linux, a wait interrupted by a handler fails with EINTR even
+/// with `SA_RESTART`.
+pub fn sys_epoll_pwait(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let epfd = trapframe.get_arg(0);
+    let events_ptr = trapframe.get_arg(1);
+    let max_events = trapframe.get_arg(2) as i32;
+    let timeout = trapframe.get_arg(3) as i32;
+    let sigmask_ptr = trapframe.get_arg(4);
+    let sigsetsize = trapframe.get_arg(5);
+    trapframe.increment_pc_next(task);
+
+    if max_events <= 0 || max_events as usize > EPOLL_MAX_EVENTS {
+        return errno::error(errno::EINVAL);
+    }
+    let epoll = match fd_object(abi, task, epfd) {
+        Ok((_, KernelObject::Epoll(epoll))) => epoll,
+        Ok(_) => return errno::error(errno::EINVAL),
+        Err(err) => return errno::error(err),
+    };
+    let timeout = (timeout >= 0).then(|| timeout_ticks(timeout as u64 * 1_000_000));
+
+    if let Err(err) = set_sigmask(task, sigmask_ptr, sigsetsize) {
+        return errno::error(err);
+    }
+    let events = match epoll.wait(max_events as usize, timeout) {
+        Ok(events) => events,
+        Err(_) => return signal::interrupt_sleep(task, epfd),
+    };
+    task.signals.restore_mask();
+    match write_events(events_ptr, &events) {
+        Ok(()) => events.len(),
+        Err(error) => errno::error(errno::from_error(error)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_poll_normal_events() {
+        // POLLRDNORM and POLLWRNORM stand for POLLIN and POLLOUT
+        assert_eq!(requested_events(POLLRDNORM), POLLRDNORM | POLLIN);
+        assert_eq!(reported_events(POLLRDNORM, POLLIN | POLLOUT), POLLRDNORM);
+        assert_eq!(reported_events(POLLIN | POLLWRNORM, POLLIN | POLLOUT), POLLIN | POLLWRNORM);
+        // Hang-ups are reported whether asked for or not
+        assert_eq!(reported_events(POLLOUT, POLLHUP), POLLHUP);
+    }
+}
@@ -0,0 +1,670 @@
+//! Process, identity and time system calls of the Linux ABI
+//!
+//! Most of them are the native system calls with Linux error numbers.
+//! Processes are supported but not threads: `clone` creates a copy of the
+//! caller, and `vfork` is a fork.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+use crate::{
+    abi::{
+        linux::riscv64::{
+            call_native, compat::LinuxRiscv32Abi, errno,
+            fs::{fd_object, install, result, O_CLOEXEC, O_NONBLOCK, O_RDONLY},
+            native_result, LinuxRiscv64Abi,
+        },
+        AbiModule,
+    },
+    arch::Trapframe,
+    executor::TransparentExecutor,
+    fs::MAX_PATH_LENGTH,
+    library::std::string::{parse_c_string_from_userspace, parse_string_array_from_userspace},
+    object::{
+        timerfd::{TimerFdObject, TimerSetting},
+        KernelObject,
+    },
+    sched::scheduler::get_scheduler,
+    task::{
+        cred::MAY_EXEC,
+        mytask,
+        signal::{self, copy_from_user, copy_to_user, SIGCHLD, SIGCONT},
+        syscall::{self, WCONTINUED, WNOHANG},
+        CloneFlags, Task,
+    },
+    timer::{get_time_ns, ns_to_ticks},
+};
+
+/// Limits for the arguments and environment passed to execve
+const MAX_ARG_COUNT: usize = 256;
+const MAX_ARG_LENGTH: usize = 4096;
+
+/// `clone` flags
+const CLONE_VM: usize = 0x0000_0100;
+const CLONE_VFORK: usize = 0x0000_4000;
+const CLONE_THREAD: usize = 0x0001_0000;
+const CLONE_SETTLS: usize = 0x0008_0000;
+const CLONE_PARENT_SETTID: usize = 0x0010_0000;
+const CLONE_CHILD_SETTID: usize = 0x0100_0000;
+
+/// Clocks of `clock_gettime`
+const CLOCK_REALTIME: usize = 0;
+const CLOCK_MONOTONIC: usize = 1;
+const CLOCK_MONOTONIC_RAW: usize = 4;
+const CLOCK_REALTIME_COARSE: usize = 5;
+const CLOCK_MONOTONIC_COARSE: usize = 6;
+const CLOCK_BOOTTIME: usize = 7;
+
+const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// Size of `struct rusage`
+const RUSAGE_SIZE: usize = 144;
+/// Size of `siginfo_t`
+const SIGINFO_SIZE: usize = 128;
+
+/// `idtype` of `waitid`
+const P_ALL: usize = 0;
+const P_PID: usize = 1;
+const P_PGID: usize = 2;
+
+/// `waitid` options besides the native `WNOHANG` and `WCONTINUED`
+const WSTOPPED: usize = 2;
+const WEXITED: usize = 4;
+
+/// `si_code` of `SIGCHLD`
+const CLD_EXITED: i32 = 1;
+const CLD_KILLED: i32 = 2;
+const CLD_DUMPED: i32 = 3;
+const CLD_STOPPED: i32 = 5;
+const CLD_CONTINUED: i32 = 6;
+
+/// `flags` of `clock_nanosleep`
+const TIMER_ABSTIME: usize = 1;
+
+pub fn sys_exit(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    // There are no threads, so exit and exit_group are the same
+    syscall::sys_exit(trapframe)
+}
+
+pub fn sys_clone(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let parent_task = mytask().unwrap();
+    let flags = trapframe.get_arg(0);
+    let newsp = trapframe.get_arg(1);
+    let parent_tid_ptr = trapframe.get_arg(2);
+    let tls = trapframe.get_arg(3);
+    let child_tid_ptr = trapframe.get_arg(4);
+    trapframe.increment_pc_next(parent_task);
+
+    // A vfork child gets a copy of the address space instead of sharing it
+    if flags & CLONE_THREAD != 0 || (flags & CLONE_VM != 0 && flags & CLONE_VFORK == 0) {
+        return errno::error(errno::EINVAL);
+    }
+
+    /* Save the trapframe to the task before cloning */
+    parent_task.vcpu.store(trapframe);
+    let mut child_task = match parent_task.clone_task(CloneFlags::default()) {
+        Ok(child_task) => child_task,
+        Err(_) => return errno::error(errno::ENOMEM),
+    };
+    let child_id = child_task.get_id();
+    child_task.vcpu.iregs.reg[10] = 0; /* a0 */
+    if newsp != 0 {
+        child_task.vcpu.set_sp(newsp);
+    }
+    if flags & CLONE_SETTLS != 0 {
+        child_task.vcpu.iregs.reg[4] = tls; /* tp */
+    }
+    // As on Linux, a bad TID address does not fail the clone
+    let tid = (child_id as u32).to_le_bytes();
+    if flags & CLONE_CHILD_SETTID != 0 {
+        let _ = copy_to_user(&child_task, child_tid_ptr, &tid);
+    }
+    if flags & CLONE_PARENT_SETTID != 0 {
+        let _ = copy_to_user(parent_task, parent_tid_ptr, &tid);
+    }
+    let cpu_id = get_scheduler().select_cpu(child_task.cpu_affinity);
+    get_scheduler().add_task(child_task, cpu_id);
+    child_id
+}
+
+pub fn sys_execve(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    execve(abi, trapframe, LinuxRiscv64Abi::name(), |task, ptr| {
+        parse_string_array_from_userspace(task, ptr, MAX_ARG_COUNT, MAX_ARG_LENGTH).map_err(|_| ())
+    })
+}
+
+/// `execve` of riscv32, whose argv and envp are arrays of 32-bit pointers
+pub fn compat_sys_execve(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    execve(abi, trapframe, LinuxRiscv32Abi::name(), compat_string_array)
+}
+
+/// Read a NULL-terminated array of 32-bit string pointers
+fn compat_string_array(task: &Task, ptr: usize) -> Result<Vec<String>, ()> {
+    let mut strings = Vec::new();
+    if ptr == 0 {
+        return Ok(strings);
+    }
+    for i in 0..=MAX_ARG_COUNT {
+        let mut entry = [0u8; 4];
+        copy_from_user(task, ptr + i * 4, &mut entry).map_err(|_| ())?;
+        let str_ptr = u32::from_le_bytes(entry) as usize;
+        if str_ptr == 0 {
+            return Ok(strings);
+        }
+        strings.push(parse_c_string_from_userspace(task, str_ptr, MAX_ARG_LENGTH).map_err(|_| ())?);
+    }
+    Err(())
+}
+
+/// Execute a program for the ABI called `abi_name`, whose state is `abi`
+fn execve(
+    abi: &mut LinuxRiscv64Abi,
+    trapframe: &mut Trapframe,
+    abi_name: &str,
+    parse_array: fn(&Task, usize) -> Result<Vec<String>, ()>,
+) -> usize {
+    let task = mytask().unwrap();
+    let path_ptr = trapframe.get_arg(0);
+    let argv_ptr = trapframe.get_arg(1);
+    let envp_ptr = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    let path = match parse_c_string_from_userspace(task, path_ptr, MAX_PATH_LENGTH) {
+        Ok(path) => path,
+        Err(_) => return errno::error(errno::EFAULT),
+    };
+    let (argv, envp) = match (parse_array(task, argv_ptr), parse_array(task, envp_ptr)) {
+        (Ok(argv), Ok(envp)) => (argv, envp),
+        _ => return errno::error(errno::EFAULT),
+    };
+
+    // The executor does not tell why a program could not be opened
+    let Some(vfs) = task.get_vfs() else {
+        return errno::error(errno::ENOENT);
+    };
+    let abs_path = vfs.resolve_path_to_absolute(&path);
+    if let Err(e) = vfs.metadata(&abs_path) {
+        return errno::error(errno::from_fs_error(&e.kind));
+    }
+    if task.cred.check_path(vfs, &abs_path, MAY_EXEC).is_err() {
+        return errno::error(errno::EACCES);
+    }
+
+    let argv_refs: Vec<&str> = argv.iter().map(|s| s.as_str()).collect();
+    let envp_refs: Vec<&str> = envp.iter().map(|s| s.as_str()).collect();
+    match TransparentExecutor::execute_binary(&abs_path, &argv_refs, &envp_refs, task, trapframe, false) {
+        Ok(()) => {
+            // Unless the program runs under another ABI, this instance stays the task's ABI
+            if task.default_abi.get_name() == abi_name {
+                abi.exec_done(task);
+            }
+            trapframe.get_return_value()
+        }
+        Err(_) => errno::error(errno::ENOEXEC),
+    }
+}
+
+pub fn sys_wait4(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let rusage_ptr = trapframe.get_arg(3);
+
+    // pid, status and options are the native arguments of waitpid
+    let result = syscall::sys_waitpid(trapframe);
+    if result == usize::MAX {
+        // An interrupted wait is turned into EINTR by the dispatcher
+        return errno::error(errno::ECHILD);
+    }
+    // Resource usage of children is not accounted
+    if rusage_ptr != 0 && copy_to_user(task, rusage_ptr, &[0u8; RUSAGE_SIZE]).is_err() {
+        return errno::error(errno::EFAULT);
+    }
+    result
+}
+
+pub fn sys_waitid(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    waitid(trapframe, 8)
+}
+
+/// `waitid` of riscv32, whose `siginfo_t` and `struct rusage` have 32-bit words
+pub fn compat_sys_waitid(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    waitid(trapframe, 4)
+}
+
+/// `waitid` on top of the native `waitpid`
+///
+/// Exits are always reported, so `WEXITED` is required. The UID of the
+/// child is given as the caller's, which it is unless the child changed it.
+fn waitid(trapframe: &mut Trapframe, word: usize) -> usize {
+    let task = mytask().unwrap();
+    let idtype = trapframe.get_arg(0);
+    let id = trapframe.get_arg(1);
+    let infop = trapframe.get_arg(2);
+    let options = trapframe.get_arg(3);
+    let rusage_ptr = trapframe.get_arg(4);
+
+    // The pid argument of waitpid
+    let pid = match idtype {
+        P_ALL => Some(-1isize as usize),
+        P_PID if id as i32 > 0 => Some(id),
+        // The caller's own group for 0, as since Linux 5.4
+        P_PGID if id as i32 >= 0 => Some(-(id as i32) as isize as usize),
+        _ => None,
+    };
+    let Some(pid) = pid.filter(|_| options & WEXITED != 0 && options & !(WNOHANG | WSTOPPED | WEXITED | WCONTINUED) == 0) else {
+        trapframe.increment_pc_next(task);
+        return errno::error(errno::EINVAL);
+    };
+    // The native call stores the wait status at the start of the siginfo,
+    // which is checked to be writable before a child is reaped
+    if infop != 0 && copy_to_user(task, infop, &[0u8; SIGINFO_SIZE]).is_err() {
+        trapframe.increment_pc_next(task);
+        return errno::error(errno::EFAULT);
+    }
+    let native_options = options & (WNOHANG | WSTOPPED | WCONTINUED);
+    let result = call_native(trapframe, &[pid, infop, native_options], syscall::sys_waitpid);
+    if signal::syscall_interrupted(task) {
+        // Restarted with the Linux arguments, not the native ones
+        return signal::interrupt_syscall(task, trapframe);
+    }
+    if result == usize::MAX {
+        return errno::error(errno::ECHILD);
+    }
+    if infop == 0 {
+        return 0;
+    }
+
+    let mut siginfo = [0u8; SIGINFO_SIZE];
+    if result != 0 {
+        let mut status = [0u8; 4];
+        let _ = copy_from_user(task, infop, &mut status);
+        let status = i32::from_le_bytes(status);
+        let (code, value) = match status {
+            0xffff => (CLD_CONTINUED, SIGCONT as i32),
+            status if status & 0xff == 0x7f => (CLD_STOPPED, (status >> 8) & 0xff),
+            status if status & 0x7f == 0 => (CLD_EXITED, (status >> 8) & 0xff),
+            status if status & 0x80 != 0 => (CLD_DUMPED, status & 0x7f),
+            status => (CLD_KILLED, status & 0x7f),
+        };
+        // The union after si_signo, si_errno and si_code is word aligned
+        let fields = (3 * 4usize).next_multiple_of(word);
+        siginfo[0..4].copy_from_slice(&(SIGCHLD as i32).to_le_bytes());
+        siginfo[8..12].copy_from_slice(&code.to_le_bytes());
+        siginfo[fields..fields + 4].copy_from_slice(&(result as i32).to_le_bytes());
+        siginfo[fields + 4..fields + 8].copy_from_slice(&task.cred.uid.to_le_bytes());
+        siginfo[fields + 8..fields + 12].copy_from_slice(&value.to_le_bytes());
+    }
+    if copy_to_user(task, infop, &siginfo).is_err() {
+        return errno::error(errno::EFAULT);
+    }
+    // Resource usage of children is not accounted
+    if rusage_ptr != 0 && copy_to_user(task, rusage_ptr, &[0u8; RUSAGE_SIZE][..RUSAGE_SIZE / 8 * word]).is_err() {
+        return errno::error(errno::EFAULT);
+    }
+    0
+}
+
+pub fn sys_kill(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let sig = trapframe.get_arg(1);
+    if sig != 0 && !signal::is_valid(sig) {
+        trapframe.increment_pc_next(mytask().unwrap());
+        return errno::error(errno::EINVAL);
+    }
+    native_result(syscall::sys_kill(trapframe), errno::ESRCH)
+}
+
+pub fn sys_getpid(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    syscall::sys_getpid(trapframe)
+}
+
+pub fn sys_getppid(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    syscall::sys_getppid(trapframe)
+}
+
+pub fn sys_gettid(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    // Every task is a process of its own
+    syscall::sys_getpid(trapframe)
+}
+
+pub fn sys_set_tid_address(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    // The address is only used to wake threads joining this one; there are none
+    syscall::sys_getpid(trapframe)
+}
+
+pub fn sys_getuid(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    syscall::sys_getuid(trapframe)
+}
+
+pub fn sys_geteuid(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    syscall::sys_geteuid(trapframe)
+}
+
+pub fn sys_getgid(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    syscall::sys_getgid(trapframe)
+}
+
+pub fn sys_getegid(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    syscall::sys_getegid(trapframe)
+}
+
+pub fn sys_setuid(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    native_result(syscall::sys_setuid(trapframe), errno::EPERM)
+}
+
+pub fn sys_setgid(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    native_result(syscall::sys_setgid(trapframe), errno::EPERM)
+}
+
+pub fn sys_setreuid(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    native_result(syscall::sys_setreuid(trapframe), errno::EPERM)
+}
+
+pub fn sys_setregid(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    native_result(syscall::sys_setregid(trapframe), errno::EPERM)
+}
+
+pub fn sys_setresuid(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    native_result(syscall::sys_setresuid(trapframe), errno::EPERM)
+}
+
+pub fn sys_setresgid(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    native_result(syscall::sys_setresgid(trapframe), errno::EPERM)
+}
+
+pub fn sys_getresuid(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    native_result(syscall::sys_getresuid(trapframe), errno::EFAULT)
+}
+
+pub fn sys_getresgid(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    native_result(syscall::sys_getresgid(trapframe), errno::EFAULT)
+}
+
+pub fn sys_getgroups(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    native_result(syscall::sys_getgroups(trapframe), errno::EINVAL)
+}
+
+pub fn sys_setgroups(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    native_result(syscall::sys_setgroups(trapframe), errno::EPERM)
+}
+
+pub fn sys_setpgid(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    native_result(syscall::sys_setpgid(trapframe), errno::EPERM)
+}
+
+pub fn sys_getpgid(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    native_result(syscall::sys_getpgid(trapframe), errno::ESRCH)
+}
+
+pub fn sys_getsid(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    native_result(syscall::sys_getsid(trapframe), errno::ESRCH)
+}
+
+pub fn sys_setsid(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    native_result(syscall::sys_setsid(trapframe), errno::EPERM)
+}
+
+pub fn sys_uname(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    // `UtsName` has the layout of the Linux `struct utsname`
+    native_result(syscall::sys_uname(trapframe), errno::EFAULT)
+}
+
+pub fn sys_getrandom(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    // The GRND_* flags have the Linux values
+    native_result(syscall::sys_getrandom(trapframe), errno::EFAULT)
+}
+
+pub fn sys_sethostname(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    native_result(syscall::sys_sethostname(trapframe), errno::EPERM)
+}
+
+pub fn sys_sched_yield(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    // The task is switched at the next tick anyway
+    trapframe.increment_pc_next(mytask().unwrap());
+    0
+}
+
+/// Read a `struct timespec` from user memory as nanoseconds
+pub fn read_timespec(ptr: usize) -> Result<u64, usize> {
+    let mut bytes = [0u8; 16];
+    copy_from_user(mytask().unwrap(), ptr, &mut bytes).map_err(|_| errno::EFAULT)?;
+    let sec = i64::from_le_bytes(bytes[0..8].try_into().unwrap());
+    let nsec = i64::from_le_bytes(bytes[8..16].try_into().unwrap());
+    if sec < 0 || !(0..NSEC_PER_SEC as i64).contains(&nsec) {
+        return Err(errno::EINVAL);
+    }
+    Ok((sec as u64).saturating_mul(NSEC_PER_SEC).saturating_add(nsec as u64))
+}
+
+/// Write nanoseconds to user memory as a `struct timespec`
+pub fn write_timespec(ptr: usize, ns: u64) -> Result<(), usize> {
+    let mut bytes = [0u8; 16];
+    bytes[0..8].copy_from_slice(&(ns / NSEC_PER_SEC).to_le_bytes());
+    bytes[8..16].copy_from_slice(&(ns % NSEC_PER_SEC).to_le_bytes());
+    copy_to_user(mytask().unwrap(), ptr, &bytes).map_err(|_| errno::EFAULT)
+}
+
+pub fn sys_nanosleep(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let req_ptr = trapframe.get_arg(0);
+    let rem_ptr = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    let duration = match read_timespec(req_ptr) {
+        Ok(duration) => duration,
+        Err(err) => return errno::error(err),
+    };
+    let deadline = get_time_ns().saturating_add(duration);
+    if task.sleep(trapframe, ns_to_ticks(duration)) {
+        return 0;
+    }
+    if rem_ptr != 0 {
+        let _ = write_timespec(rem_ptr, deadline.saturating_sub(get_time_ns()));
+    }
+    // Restarted with the whole request: the remaining time cannot be passed
+    // in place of the pointer
+    signal::interrupt_sleep(task, req_ptr)
+}
+
+/// `clock_nanosleep`, also `clock_nanosleep_time64` of riscv32
+///
+/// The sleep is measured against the time since boot: an absolute time of
+/// the realtime clock is converted with the wall-clock time at boot, and
+/// does not follow the clock if it is set during the sleep.
+pub fn sys_clock_nanosleep(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let clock = trapframe.get_arg(0);
+    let flags = trapframe.get_arg(1);
+    let req_ptr = trapframe.get_arg(2);
+    let rem_ptr = trapframe.get_arg(3);
+    trapframe.increment_pc_next(task);
+
+    match clock {
+        CLOCK_REALTIME | CLOCK_MONOTONIC | CLOCK_BOOTTIME => {}
+        _ => return errno::error(errno::EINVAL),
+    }
+    let request = match read_timespec(req_ptr) {
+        Ok(request) => request,
+        Err(err) => return errno::error(err),
+    };
+    let now = get_time_ns();
+    let deadline = match flags & TIMER_ABSTIME != 0 {
+        true if clock == CLOCK_REALTIME => request.saturating_sub(crate::time::boot_epoch_ns()),
+        true => request,
+        false => now.saturating_add(request),
+    };
+    if deadline <= now || task.sleep(trapframe, ns_to_ticks(deadline - now)) {
+        return 0;
+    }
+    // The remaining time is only reported for a relative sleep
+    if rem_ptr != 0 && flags & TIMER_ABSTIME == 0 {
+        let _ = write_timespec(rem_ptr, deadline.saturating_sub(get_time_ns()));
+    }
+    // Restarted with the same arguments: the whole request again, or the
+    // same deadline for an absolute sleep
+    signal::interrupt_sleep(task, clock)
+}
+
+/// The realtime clocks tell the wall-clock time, the others count from boot
+pub fn sys_clock_gettime(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let clock = trapframe.get_arg(0);
+    let tp_ptr = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    match clock {
+        CLOCK_REALTIME | CLOCK_MONOTONIC | CLOCK_MONOTONIC_RAW | CLOCK_REALTIME_COARSE
+        | CLOCK_MONOTONIC_COARSE | CLOCK_BOOTTIME => {}
+        _ => return errno::error(errno::EINVAL),
+    }
+    let ns = match clock {
+        CLOCK_REALTIME | CLOCK_REALTIME_COARSE => crate::time::realtime_ns(),
+        _ => get_time_ns(),
+    };
+    match write_timespec(tp_ptr, ns) {
+        Ok(()) => 0,
+        Err(err) => errno::error(err),
+    }
+}
+
+pub fn sys_gettimeofday(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let tv_ptr = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    if tv_ptr == 0 {
+        return 0;
+    }
+    let us = crate::time::realtime_ns() / 1_000;
+    let mut bytes = [0u8; 16];
+    bytes[0..8].copy_from_slice(&(us / 1_000_000).to_le_bytes());
+    bytes[8..16].copy_from_slice(&(us % 1_000_000).to_le_bytes());
+    match copy_to_user(task, tv_ptr, &bytes) {
+        Ok(()) => 0,
+        Err(_) => errno::error(errno::EFAULT),
+    }
+}
+
+/// `clock_settime`, also `clock_settime64` of riscv32; only the realtime
+/// clock can be set
+pub fn sys_clock_settime(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let clock = trapframe.get_arg(0);
+    let tp_ptr = trapframe.get_arg(1);
+
+    if clock != CLOCK_REALTIME {
+        trapframe.increment_pc_next(task);
+        return errno::error(errno::EINVAL);
+    }
+    let ns = match read_timespec(tp_ptr) {
+        Ok(ns) => ns,
+        Err(err) => {
+            trapframe.increment_pc_next(task);
+            return errno::error(err);
+        }
+    };
+    native_result(call_native(trapframe, &[ns as usize], syscall::sys_clock_set_realtime), errno::EPERM)
+}
+
+/// `settimeofday`; the time zone is ignored
+pub fn sys_settimeofday(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let tv_ptr = trapframe.get_arg(0);
+
+    if tv_ptr == 0 {
+        trapframe.increment_pc_next(task);
+        return 0;
+    }
+    let mut bytes = [0u8; 16];
+    if copy_from_user(task, tv_ptr, &mut bytes).is_err() {
+        trapframe.increment_pc_next(task);
+        return errno::error(errno::EFAULT);
+    }
+    let sec = i64::from_le_bytes(bytes[0..8].try_into().unwrap());
+    let usec = i64::from_le_bytes(bytes[8..16].try_into().unwrap());
+    if sec < 0 || !(0..1_000_000).contains(&usec) {
+        trapframe.increment_pc_next(task);
+        return errno::error(errno::EINVAL);
+    }
+    let ns = (sec as u64).saturating_mul(NSEC_PER_SEC).saturating_add(usec as u64 * 1_000);
+    native_result(call_native(trapframe, &[ns as usize], syscall::sys_clock_set_realtime), errno::EPERM)
+}
+
+/// `flags` of `timerfd_settime` besides `TFD_TIMER_ABSTIME` (the same as
+/// `TIMER_ABSTIME`): accepted and ignored, the clock is never set
+const TFD_TIMER_CANCEL_ON_SET: usize = 2;
+
+/// Size of `struct itimerspec`: the interval, then the value
+const ITIMERSPEC_SIZE: usize = 32;
+
+/// `timerfd_create`
+///
+/// Like `clock_nanosleep`, the timers of every clock count from boot, and
+/// an absolute time of the realtime clock is converted when the timer is
+/// armed. `TFD_NONBLOCK` is the `O_NONBLOCK` status flag of the descriptor.
+pub fn sys_timerfd_create(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let clock = trapframe.get_arg(0);
+    let flags = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    match clock {
+        CLOCK_REALTIME | CLOCK_MONOTONIC | CLOCK_BOOTTIME => {}
+        _ => return errno::error(errno::EINVAL),
+    }
+    if flags & !(O_CLOEXEC | O_NONBLOCK) != 0 {
+        return errno::error(errno::EINVAL);
+    }
+    let timer = match clock {
+        CLOCK_REALTIME => TimerFdObject::new_realtime(false),
+        _ => TimerFdObject::new(false),
+    };
+    let timer = KernelObject::from_timerfd(timer);
+    result(install(abi, task, timer, flags & O_CLOEXEC != 0, O_RDONLY | (flags & O_NONBLOCK)))
+}
+
+/// The timer object of the descriptor `fd`
+fn timerfd_object(abi: &LinuxRiscv64Abi, task: &Task, fd: usize) -> Result<Arc<TimerFdObject>, usize> {
+    match fd_object(abi, task, fd)? {
+        (_, KernelObject::TimerFd(timer)) => Ok(timer),
+        _ => Err(errno::EINVAL),
+    }
+}
+
+/// Write `setting` to user memory as a `struct itimerspec`
+fn write_itimerspec(ptr: usize, setting: TimerSetting) -> Result<(), usize> {
+    write_timespec(ptr, setting.interval)?;
+    write_timespec(ptr + ITIMERSPEC_SIZE / 2, setting.value)
+}
+
+/// `timerfd_settime`, also `timerfd_settime64` of riscv32
+pub fn sys_timerfd_settime(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let flags = trapframe.get_arg(1);
+    let new_ptr = trapframe.get_arg(2);
+    let old_ptr = trapframe.get_arg(3);
+    trapframe.increment_pc_next(task);
+
+    let settime = || -> Result<usize, usize> {
+        if flags & !(TIMER_ABSTIME | TFD_TIMER_CANCEL_ON_SET) != 0 {
+            return Err(errno::EINVAL);
+        }
+        let timer = timerfd_object(abi, task, fd)?;
+        let interval = read_timespec(new_ptr)?;
+        let value = read_timespec(new_ptr + ITIMERSPEC_SIZE / 2)?;
+        let old = timer.set(value, interval, flags & TIMER_ABSTIME != 0);
+        if old_ptr != 0 {
+            write_itimerspec(old_ptr, old)?;
+        }
+        Ok(0)
+    };
+    result(settime())
+}
+
+/// `timerfd_gettime`, also `timerfd_gettime64` of riscv32
+pub fn sys_timerfd_gettime(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let curr_ptr = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    result(timerfd_object(abi, task, fd).and_then(|timer| write_itimerspec(curr_ptr, timer.get()).map(|()| 0)))
+}
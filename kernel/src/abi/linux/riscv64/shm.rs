@@ -0,0 +1,273 @@
+//! System V shared memory of the Linux ABI (`shmget`, `shmat`, `shmdt`,
+//! `shmctl`)
+//!
+//! Segments are shared memory objects of `crate::ipc::shm`. Their ids and
+//! keys are kept in one table for the whole system; the table holds an
+//! object for each segment, and attaching maps it like `mmap` of a shared
+//! memory handle does. A removed segment leaves the table at once, and its
+//! memory lives on while it is attached.
+//!
+//! Permissions of segments are recorded but not checked.
+
+use alloc::{collections::btree_map::BTreeMap, sync::Arc};
+use spin::Mutex;
+
+use crate::{
+    abi::linux::riscv64::{call_native, errno, native_result, LinuxRiscv64Abi},
+    arch::Trapframe,
+    environment::PAGE_SIZE,
+    ipc::shm::{SharedMemoryObject, ShmError},
+    object::{
+        capability::memory_mapping::syscall::{sys_memory_map, sys_memory_unmap},
+        KernelObject,
+    },
+    task::{mytask, signal::{copy_from_user, copy_to_user}},
+};
+
+/// Key of a segment that no other `shmget` finds
+const IPC_PRIVATE: usize = 0;
+
+/// Flags of `shmget`; the low nine bits are the permissions
+const IPC_CREAT: usize = 0o1000;
+const IPC_EXCL: usize = 0o2000;
+const MODE_MASK: usize = 0o777;
+
+/// Flags of `shmat`
+const SHM_RDONLY: usize = 0o10000;
+const SHM_RND: usize = 0o20000;
+const SHM_REMAP: usize = 0o40000;
+const SHM_EXEC: usize = 0o100000;
+
+/// Commands of `shmctl`, and the flag of the 64-bit layouts or'ed into them
+const IPC_RMID: usize = 0;
+const IPC_SET: usize = 1;
+const IPC_STAT: usize = 2;
+const IPC_64: usize = 0x100;
+
+/// Flags of the native mapping call
+const PROT_READ: usize = 0x1;
+const PROT_WRITE: usize = 0x2;
+const PROT_EXEC: usize = 0x4;
+const MAP_SHARED: usize = 0x01;
+const MAP_FIXED: usize = 0x10;
+
+/// Offset of the mode in `struct ipc64_perm`
+const PERM_MODE: usize = 20;
+
+struct Segment {
+    key: usize,
+    object: Arc<SharedMemoryObject>,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    creator_pid: u32,
+}
+
+struct Segments {
+    by_id: BTreeMap<usize, Segment>,
+    /// Ids of the segments with a key other than `IPC_PRIVATE`
+    by_key: BTreeMap<usize, usize>,
+    next_id: usize,
+}
+
+static SEGMENTS: Mutex<Segments> = Mutex::new(Segments {
+    by_id: BTreeMap::new(),
+    by_key: BTreeMap::new(),
+    next_id: 0,
+});
+
+fn shm_error(error: ShmError) -> usize {
+    match error {
+        ShmError::NotFound => errno::ENOENT,
+        ShmError::AlreadyExists => errno::EEXIST,
+        ShmError::InvalidSize | ShmError::InvalidName => errno::EINVAL,
+    }
+}
+
+/// The object of the segment `id`
+fn segment_object(id: usize) -> Result<Arc<SharedMemoryObject>, usize> {
+    SEGMENTS.lock().by_id.get(&id).map(|segment| segment.object.clone()).ok_or(errno::EINVAL)
+}
+
+pub fn sys_shmget(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let key = trapframe.get_arg(0) & 0xffff_ffff;
+    let size = trapframe.get_arg(1);
+    let flags = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    let mut segments = SEGMENTS.lock();
+    if key != IPC_PRIVATE {
+        if let Some(&id) = segments.by_key.get(&key) {
+            if flags & IPC_CREAT != 0 && flags & IPC_EXCL != 0 {
+                return errno::error(errno::EEXIST);
+            }
+            if size > segments.by_id[&id].object.size() {
+                return errno::error(errno::EINVAL);
+            }
+            return id;
+        }
+        if flags & IPC_CREAT == 0 {
+            return errno::error(errno::ENOENT);
+        }
+    }
+    let object = match SharedMemoryObject::create_anonymous(size) {
+        Ok(object) => object,
+        Err(error) => return errno::error(shm_error(error)),
+    };
+    let id = segments.next_id;
+    segments.next_id += 1;
+    segments.by_id.insert(id, Segment {
+        key,
+        object,
+        mode: (flags & MODE_MASK) as u32,
+        uid: task.cred.euid,
+        gid: task.cred.egid,
+        creator_pid: task.get_id() as u32,
+    });
+    if key != IPC_PRIVATE {
+        segments.by_key.insert(key, id);
+    }
+    id
+}
+
+/// `shmat`: map the whole segment, shared, at `shmaddr` or where the
+/// kernel chooses
+pub fn sys_shmat(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let id = trapframe.get_arg(0);
+    let mut addr = trapframe.get_arg(1);
+    let flags = trapframe.get_arg(2);
+
+    let object = match segment_object(id) {
+        Ok(object) => object,
+        Err(errno) => {
+            trapframe.increment_pc_next(task);
+            return errno::error(errno);
+        }
+    };
+    let size = object.size();
+    let mut map_flags = MAP_SHARED;
+    if addr != 0 {
+        if flags & SHM_RND != 0 {
+            addr &= !(PAGE_SIZE - 1);
+        }
+        // Without SHM_REMAP an attach does not replace what is mapped
+        let taken = (addr..addr.saturating_add(size))
+            .step_by(PAGE_SIZE)
+            .any(|page| task.vm_manager.read().search_memory_map(page).is_some());
+        if addr % PAGE_SIZE != 0 || (taken && flags & SHM_REMAP == 0) {
+            trapframe.increment_pc_next(task);
+            return errno::error(errno::EINVAL);
+        }
+        map_flags |= MAP_FIXED;
+    }
+    let mut prot = PROT_READ;
+    if flags & SHM_RDONLY == 0 {
+        prot |= PROT_WRITE;
+    }
+    if flags & SHM_EXEC != 0 {
+        prot |= PROT_EXEC;
+    }
+
+    // The mapping is made from a handle held only for the call
+    let handle = match task.handle_table.write().insert(KernelObject::from_shared_memory(object)) {
+        Ok(handle) => handle,
+        Err(_) => {
+            trapframe.increment_pc_next(task);
+            return errno::error(errno::EMFILE);
+        }
+    };
+    let result = call_native(trapframe, &[handle as usize, addr, size, prot, map_flags, 0], sys_memory_map);
+    task.handle_table.write().remove(handle);
+    native_result(result, errno::ENOMEM)
+}
+
+/// `shmdt`: unmap the shared mapping that starts at `shmaddr`
+pub fn sys_shmdt(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let addr = trapframe.get_arg(0);
+
+    let size = match task.vm_manager.read().get_memory_map_by_addr(addr) {
+        Some(map) if map.is_shared && map.owner.is_some() => map.vmarea.size(),
+        _ => {
+            trapframe.increment_pc_next(task);
+            return errno::error(errno::EINVAL);
+        }
+    };
+    let result = call_native(trapframe, &[addr, size], sys_memory_unmap);
+    native_result(result, errno::EINVAL)
+}
+
+pub fn sys_shmctl(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    shmctl(abi, trapframe, 8)
+}
+
+/// `shmctl` of riscv32
+pub fn compat_sys_shmctl(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    shmctl(abi, trapframe, 4)
+}
+
+/// `shmctl`: `IPC_STAT`, `IPC_SET` (of the permissions) and `IPC_RMID`
+///
+/// `word` is the size of the words of `struct shmid64_ds`. The time fields
+/// take 24 bytes in both layouts and are reported as zero.
+fn shmctl(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe, word: usize) -> usize {
+    let task = mytask().unwrap();
+    let id = trapframe.get_arg(0);
+    let cmd = trapframe.get_arg(1) & !IPC_64;
+    let buf = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    // struct ipc64_perm, then the size, the times, the pids and the number
+    // of attaches
+    let perm_size = if word == 8 { 48 } else { 36 };
+    let segsz = perm_size;
+    let cpid = segsz + word + 24;
+    let nattch = cpid + 8;
+    let ds_size = nattch + 3 * word;
+
+    let mut segments = SEGMENTS.lock();
+    let segment = match segments.by_id.get_mut(&id) {
+        Some(segment) => segment,
+        None => return errno::error(errno::EINVAL),
+    };
+    match cmd {
+        IPC_STAT => {
+            let mut ds = alloc::vec![0u8; ds_size];
+            let mut put = |offset: usize, value: u32| ds[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+            put(0, segment.key as u32);
+            put(4, segment.uid);
+            put(8, segment.gid);
+            put(12, segment.uid);
+            put(16, segment.gid);
+            put(PERM_MODE, segment.mode);
+            put(cpid, segment.creator_pid);
+            let size = segment.object.size();
+            let attaches = segment.object.segment().mapped_pages() / (size / PAGE_SIZE).max(1);
+            ds[segsz..segsz + word].copy_from_slice(&size.to_le_bytes()[..word]);
+            ds[nattch..nattch + word].copy_from_slice(&attaches.to_le_bytes()[..word]);
+            match copy_to_user(task, buf, &ds) {
+                Ok(()) => 0,
+                Err(_) => errno::error(errno::EFAULT),
+            }
+        }
+        IPC_SET => {
+            let mut mode = [0u8; 4];
+            if copy_from_user(task, buf + PERM_MODE, &mut mode).is_err() {
+                return errno::error(errno::EFAULT);
+            }
+            segment.mode = u32::from_le_bytes(mode) & MODE_MASK as u32;
+            0
+        }
+        IPC_RMID => {
+            let key = segment.key;
+            segments.by_id.remove(&id);
+            if key != IPC_PRIVATE {
+                segments.by_key.remove(&key);
+            }
+            0
+        }
+        _ => errno::error(errno::EINVAL),
+    }
+}
@@ -0,0 +1,129 @@
+//! Signal system calls of the Linux ABI
+//!
+//! Signal numbers, sets and the `SIG_BLOCK` family match the native ones,
+//! so masks are handled by the native system calls. Handlers are not run
+//! yet: a Linux handler returns through `rt_sigreturn` with a Linux signal
+//! frame, which the kernel does not build. Dispositions set to `SIG_DFL` or
+//! `SIG_IGN` take effect; for a handler the disposition is recorded and
+//! reported back by `rt_sigaction`, but the kernel keeps the default action.
+
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    abi::linux::riscv64::{errno, LinuxRiscv64Abi},
+    arch::Trapframe,
+    task::{
+        mytask,
+        signal::{self, copy_from_user, copy_to_user, SigAction, SigSet, SIG_DFL, SIG_IGN, SIGKILL, SIGSTOP},
+        syscall::sys_sigprocmask,
+    },
+};
+
+/// Size of `sigset_t` as passed by the C library
+pub const SIGSET_SIZE: usize = 8;
+
+/// `struct sigaction` as passed to `rt_sigaction` on riscv64 (no `sa_restorer`)
+///
+/// On riscv32 the handler and the flags are 32-bit words, the mask is the
+/// same.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LinuxSigAction {
+    pub handler: usize,
+    pub flags: usize,
+    pub mask: u64,
+}
+
+impl LinuxSigAction {
+    /// Size in user memory with `word`-byte handler and flags
+    const fn size(word: usize) -> usize {
+        2 * word + 8
+    }
+
+    pub fn is_ignored(&self) -> bool {
+        self.handler == SIG_IGN
+    }
+
+    fn to_bytes(self, word: usize) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::size(word));
+        bytes.extend_from_slice(&self.handler.to_le_bytes()[..word]);
+        bytes.extend_from_slice(&self.flags.to_le_bytes()[..word]);
+        bytes.extend_from_slice(&self.mask.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8], word: usize) -> Self {
+        let field = |i: usize| {
+            let mut value = [0u8; 8];
+            value[..word].copy_from_slice(&bytes[i * word..(i + 1) * word]);
+            u64::from_le_bytes(value)
+        };
+        Self {
+            handler: field(0) as usize,
+            flags: field(1) as usize,
+            mask: u64::from_le_bytes(bytes[2 * word..2 * word + 8].try_into().unwrap()),
+        }
+    }
+}
+
+pub fn sys_rt_sigaction(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    rt_sigaction(abi, trapframe, 8)
+}
+
+/// `rt_sigaction` of riscv32
+pub fn compat_sys_rt_sigaction(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    rt_sigaction(abi, trapframe, 4)
+}
+
+fn rt_sigaction(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe, word: usize) -> usize {
+    let task = mytask().unwrap();
+    let sig = trapframe.get_arg(0);
+    let act_ptr = trapframe.get_arg(1);
+    let oldact_ptr = trapframe.get_arg(2);
+    let sigsetsize = trapframe.get_arg(3);
+    trapframe.increment_pc_next(task);
+
+    if sigsetsize != SIGSET_SIZE || !signal::is_valid(sig) {
+        return errno::error(errno::EINVAL);
+    }
+    let old = abi.sigactions[sig - 1];
+    if act_ptr != 0 {
+        if sig == SIGKILL || sig == SIGSTOP {
+            return errno::error(errno::EINVAL);
+        }
+        let mut bytes = vec![0u8; LinuxSigAction::size(word)];
+        if copy_from_user(task, act_ptr, &mut bytes).is_err() {
+            return errno::error(errno::EFAULT);
+        }
+        let act = LinuxSigAction::from_bytes(&bytes, word);
+        let handler = match act.handler {
+            SIG_DFL | SIG_IGN => act.handler,
+            // See the module documentation
+            _ => SIG_DFL,
+        };
+        let kernel_action = SigAction {
+            handler,
+            flags: act.flags,
+            restorer: 0,
+            mask: SigSet::from_bits(act.mask),
+        };
+        if task.signals.set_action(sig, kernel_action).is_err() {
+            return errno::error(errno::EINVAL);
+        }
+        abi.sigactions[sig - 1] = act;
+    }
+    if oldact_ptr != 0 && copy_to_user(task, oldact_ptr, &old.to_bytes(word)).is_err() {
+        return errno::error(errno::EFAULT);
+    }
+    0
+}
+
+pub fn sys_rt_sigprocmask(_abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    if trapframe.get_arg(3) != SIGSET_SIZE {
+        trapframe.increment_pc_next(mytask().unwrap());
+        return errno::error(errno::EINVAL);
+    }
+    // The arguments are the native ones
+    let result = sys_sigprocmask(trapframe);
+    super::native_result(result, errno::EINVAL)
+}
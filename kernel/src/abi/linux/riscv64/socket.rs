@@ -0,0 +1,997 @@
+//! Socket system calls of the Linux ABI: local sockets (`AF_UNIX`), TCP,
+//! UDP and raw sockets (`AF_INET`, `AF_INET6`) and packet sockets
+//! (`AF_PACKET`)
+//!
+//! Sockets are those of `crate::ipc::socket`. Their names live in a
+//! namespace of the kernel rather than in the file system: a path name is
+//! made absolute against the working directory but no file is created, and
+//! it is free again once the socket is closed. Abstract names (starting
+//! with a NUL byte) are names of the same namespace. Internet sockets are
+//! named by the `a.b.c.d:port` of their `struct sockaddr_in`, or the
+//! `[v6]:port` of their `struct sockaddr_in6`; IPv4 peers of an `AF_INET6`
+//! socket have IPv4-mapped addresses. Packet sockets are named by the
+//! interface of the `sll_ifindex` of their `struct sockaddr_ll`, one more
+//! than the id of the interface; `SOCK_DGRAM` packet sockets, without the
+//! link header, and classic BPF filters are not supported.
+//!
+//! Descriptors sent with `SCM_RIGHTS` travel as the objects they refer to
+//! and are installed as new descriptors by `recvmsg`.
+
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+
+use crate::{
+    abi::{error::KernelError, linux::riscv64::{
+        errno,
+        fs::{fd_object, install, io_result, result, transfer_iov, O_CLOEXEC, O_NONBLOCK, O_RDWR},
+        FileDescriptor, LinuxRiscv64Abi,
+    }},
+    arch::Trapframe,
+    ipc::socket::{
+        create_socket, Received, Shutdown, SocketDomain, SocketObject, SocketOption, SocketType, UnixSocket, SOCKET_BUFFER_SIZE,
+        SOCKET_MAX_OBJECTS,
+    },
+    network::address::{IpAddress, IpEndpoint, Ipv4Address, Ipv6Address},
+    network::manager::get_network_manager,
+    network::namespace,
+    object::{
+        capability::poll::{POLLERR, POLLHUP, POLLIN, POLLOUT},
+        KernelObject,
+    },
+    task::{
+        mytask,
+        signal::{copy_from_user, copy_to_user, send_signal, SIGPIPE},
+        Task,
+    },
+};
+
+const AF_UNIX: u16 = 1;
+const AF_INET: u16 = 2;
+const AF_INET6: u16 = 10;
+const AF_PACKET: u16 = 17;
+
+/// Protocols of `AF_INET` and `AF_INET6` sockets
+const IPPROTO_TCP: usize = 6;
+const IPPROTO_UDP: usize = 17;
+
+/// Protocol of a packet socket taking the frames of every EtherType
+const ETH_P_ALL: u16 = 0x0003;
+
+/// Types of `socket`, and flags or'ed into them
+const SOCK_STREAM: usize = 1;
+const SOCK_DGRAM: usize = 2;
+const SOCK_RAW: usize = 3;
+const SOCK_TYPE_MASK: usize = 0xf;
+const SOCK_NONBLOCK: usize = O_NONBLOCK;
+const SOCK_CLOEXEC: usize = O_CLOEXEC;
+
+/// Size of `struct sockaddr_un`: the family and a 108-byte path
+const SOCKADDR_UN_SIZE: usize = 110;
+
+/// Size of `struct sockaddr_in`: the family, a big-endian port, the address
+/// and 8 bytes of padding
+const SOCKADDR_IN_SIZE: usize = 16;
+
+/// Size of `struct sockaddr_in6`: the family, a big-endian port, the flow
+/// information, the address and the scope
+const SOCKADDR_IN6_SIZE: usize = 28;
+
+/// Size of `struct sockaddr_ll`: the family, a big-endian EtherType, the
+/// interface index, the hardware type, the packet type and the length and
+/// bytes of the hardware address
+const SOCKADDR_LL_SIZE: usize = 20;
+/// `sll_hatype` of Ethernet
+const ARPHRD_ETHER: u16 = 1;
+
+/// Flags of the send and receive calls
+const MSG_OOB: usize = 0x1;
+const MSG_PEEK: usize = 0x2;
+const MSG_CTRUNC: u32 = 0x8;
+const MSG_TRUNC: u32 = 0x20;
+const MSG_DONTWAIT: usize = 0x40;
+const MSG_NOSIGNAL: usize = 0x4000;
+const MSG_CMSG_CLOEXEC: usize = 0x4000_0000;
+
+/// Socket options
+const SOL_SOCKET: usize = 1;
+const SO_REUSEADDR: usize = 2;
+const SO_TYPE: usize = 3;
+const SO_ERROR: usize = 4;
+const SO_SNDBUF: usize = 7;
+const SO_RCVBUF: usize = 8;
+const SO_KEEPALIVE: usize = 9;
+const SO_DOMAIN: usize = 39;
+/// Options of `IPPROTO_TCP`
+const TCP_NODELAY: usize = 1;
+const TCP_KEEPIDLE: usize = 4;
+const TCP_KEEPINTVL: usize = 5;
+const TCP_KEEPCNT: usize = 6;
+
+/// Type of a control message passing descriptors
+const SCM_RIGHTS: u32 = 1;
+
+/// `how` of `shutdown`
+const SHUT_RD: usize = 0;
+const SHUT_WR: usize = 1;
+const SHUT_RDWR: usize = 2;
+
+/// The socket of the descriptor `fd`
+fn socket_of(abi: &LinuxRiscv64Abi, task: &Task, fd: usize) -> Result<(FileDescriptor, Arc<dyn SocketObject>), usize> {
+    match fd_object(abi, task, fd)? {
+        (desc, KernelObject::Socket(socket)) => Ok((desc, socket)),
+        _ => Err(errno::ENOTSOCK),
+    }
+}
+
+fn socket_error(error: KernelError) -> usize {
+    errno::from_error(error)
+}
+
+/// The socket option of `level` and `optname`
+fn socket_option(level: usize, optname: usize) -> Option<SocketOption> {
+    Some(match (level, optname) {
+        (SOL_SOCKET, SO_REUSEADDR) => SocketOption::ReuseAddress,
+        (SOL_SOCKET, SO_SNDBUF) => SocketOption::SendBuffer,
+        (SOL_SOCKET, SO_RCVBUF) => SocketOption::ReceiveBuffer,
+        (SOL_SOCKET, SO_KEEPALIVE) => SocketOption::KeepAlive,
+        (IPPROTO_TCP, TCP_NODELAY) => SocketOption::NoDelay,
+        (IPPROTO_TCP, TCP_KEEPIDLE) => SocketOption::KeepAliveIdle,
+        (IPPROTO_TCP, TCP_KEEPINTVL) => SocketOption::KeepAliveInterval,
+        (IPPROTO_TCP, TCP_KEEPCNT) => SocketOption::KeepAliveCount,
+        _ => return None,
+    })
+}
+
+/// EAGAIN if the call must not wait and none of `events` is ready
+fn would_block(desc: &FileDescriptor, socket: &dyn SocketObject, flags: usize, events: u32) -> Result<(), usize> {
+    let nonblocking = desc.status_flags & O_NONBLOCK != 0 || flags & MSG_DONTWAIT != 0;
+    if nonblocking && socket.poll_events() & events == 0 {
+        return Err(errno::EAGAIN);
+    }
+    Ok(())
+}
+
+/// The name in the address of `len` bytes at `ptr` for a socket of
+/// `domain`
+fn read_address(task: &Task, domain: SocketDomain, ptr: usize, len: usize) -> Result<String, usize> {
+    match domain {
+        SocketDomain::Local => read_local_address(task, ptr, len),
+        SocketDomain::Inet => read_inet_address(task, ptr, len),
+        SocketDomain::Inet6 => read_inet6_address(task, ptr, len),
+        SocketDomain::Packet => read_packet_address(task, ptr, len),
+    }
+}
+
+/// The name of the interface of the `struct sockaddr_ll` of `len` bytes at
+/// `ptr`, empty for index 0
+fn read_packet_address(task: &Task, ptr: usize, len: usize) -> Result<String, usize> {
+    if len < SOCKADDR_LL_SIZE {
+        return Err(errno::EINVAL);
+    }
+    let mut bytes = [0u8; 8];
+    copy_from_user(task, ptr, &mut bytes).map_err(|_| errno::EFAULT)?;
+    if u16::from_le_bytes([bytes[0], bytes[1]]) != AF_PACKET {
+        return Err(errno::EAFNOSUPPORT);
+    }
+    match i32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]) {
+        0 => Ok(String::new()),
+        index if index < 0 => Err(errno::ENODEV),
+        index => get_network_manager()
+            .interface(index as u32 - 1)
+            .map(|interface| interface.name().to_string())
+            .ok_or(errno::ENODEV),
+    }
+}
+
+/// The `a.b.c.d:port` of the `struct sockaddr_in` of `len` bytes at `ptr`
+fn read_inet_address(task: &Task, ptr: usize, len: usize) -> Result<String, usize> {
+    if len < SOCKADDR_IN_SIZE {
+        return Err(errno::EINVAL);
+    }
+    let mut bytes = [0u8; 8];
+    copy_from_user(task, ptr, &mut bytes).map_err(|_| errno::EFAULT)?;
+    if u16::from_le_bytes([bytes[0], bytes[1]]) != AF_INET {
+        return Err(errno::EAFNOSUPPORT);
+    }
+    let port = u16::from_be_bytes([bytes[2], bytes[3]]);
+    let address = Ipv4Address([bytes[4], bytes[5], bytes[6], bytes[7]]);
+    Ok(IpEndpoint::new(address.into(), port).to_string())
+}
+
+/// The `[v6]:port` of the `struct sockaddr_in6` of `len` bytes at `ptr`;
+/// the flow information and scope are ignored
+fn read_inet6_address(task: &Task, ptr: usize, len: usize) -> Result<String, usize> {
+    if len < SOCKADDR_IN6_SIZE {
+        return Err(errno::EINVAL);
+    }
+    let mut bytes = [0u8; 24];
+    copy_from_user(task, ptr, &mut bytes).map_err(|_| errno::EFAULT)?;
+    if u16::from_le_bytes([bytes[0], bytes[1]]) != AF_INET6 {
+        return Err(errno::EAFNOSUPPORT);
+    }
+    let port = u16::from_be_bytes([bytes[2], bytes[3]]);
+    let address = Ipv6Address::from_slice(&bytes[8..24]).ok_or(errno::EINVAL)?;
+    Ok(IpEndpoint::new(address.into(), port).to_string())
+}
+
+/// The name in the `struct sockaddr_un` of `len` bytes at `ptr`
+fn read_local_address(task: &Task, ptr: usize, len: usize) -> Result<String, usize> {
+    if !(3..=SOCKADDR_UN_SIZE).contains(&len) {
+        return Err(errno::EINVAL);
+    }
+    let mut bytes = vec![0u8; len];
+    copy_from_user(task, ptr, &mut bytes).map_err(|_| errno::EFAULT)?;
+    if u16::from_le_bytes([bytes[0], bytes[1]]) != AF_UNIX {
+        return Err(errno::EINVAL);
+    }
+    let path = &bytes[2..];
+    if path[0] == 0 {
+        // An abstract name is all the bytes given
+        return String::from_utf8(path.to_vec()).map_err(|_| errno::EINVAL);
+    }
+    let end = path.iter().position(|&byte| byte == 0).unwrap_or(path.len());
+    let path = core::str::from_utf8(&path[..end]).map_err(|_| errno::EINVAL)?;
+    let vfs = task.get_vfs().ok_or(errno::ENOENT)?;
+    Ok(vfs.resolve_path_to_absolute(path))
+}
+
+/// The address of a name for a socket of `domain`: a `struct sockaddr_un`
+/// with only the family for none, a `struct sockaddr_in` of 0.0.0.0:0
+/// (`struct sockaddr_in6` of [::]:0) or a `struct sockaddr_ll` of index 0
+fn address_bytes(domain: SocketDomain, name: Option<&str>) -> Vec<u8> {
+    let endpoint = |unspecified: IpAddress| {
+        name.and_then(|name| IpEndpoint::parse(name).ok()).unwrap_or(IpEndpoint::new(unspecified, 0))
+    };
+    match domain {
+        SocketDomain::Inet => {
+            let endpoint = endpoint(Ipv4Address::UNSPECIFIED.into());
+            let address = match endpoint.address {
+                IpAddress::V4(address) => address,
+                IpAddress::V6(address) => address.to_ipv4_mapped().unwrap_or(Ipv4Address::UNSPECIFIED),
+            };
+            let mut bytes = vec![0u8; SOCKADDR_IN_SIZE];
+            bytes[0..2].copy_from_slice(&AF_INET.to_le_bytes());
+            bytes[2..4].copy_from_slice(&endpoint.port.to_be_bytes());
+            bytes[4..8].copy_from_slice(&address.octets());
+            return bytes;
+        }
+        SocketDomain::Inet6 => {
+            let endpoint = endpoint(Ipv6Address::UNSPECIFIED.into());
+            let address = match endpoint.address {
+                IpAddress::V4(address) => Ipv6Address::from_ipv4_mapped(address),
+                IpAddress::V6(address) => address,
+            };
+            let mut bytes = vec![0u8; SOCKADDR_IN6_SIZE];
+            bytes[0..2].copy_from_slice(&AF_INET6.to_le_bytes());
+            bytes[2..4].copy_from_slice(&endpoint.port.to_be_bytes());
+            bytes[8..24].copy_from_slice(&address.octets());
+            return bytes;
+        }
+        SocketDomain::Packet => {
+            let mut bytes = vec![0u8; SOCKADDR_LL_SIZE];
+            bytes[0..2].copy_from_slice(&AF_PACKET.to_le_bytes());
+            if let Some(interface) = name.and_then(|name| get_network_manager().interface_by_name_in(namespace::current().id(), name)) {
+                bytes[4..8].copy_from_slice(&(interface.id() as i32 + 1).to_le_bytes());
+                bytes[8..10].copy_from_slice(&ARPHRD_ETHER.to_le_bytes());
+                if let Ok(mac) = interface.mac_address() {
+                    bytes[11] = 6;
+                    bytes[12..18].copy_from_slice(mac.as_bytes());
+                }
+            }
+            return bytes;
+        }
+        SocketDomain::Local => {}
+    }
+    let mut bytes = AF_UNIX.to_le_bytes().to_vec();
+    if let Some(name) = name {
+        bytes.extend_from_slice(name.as_bytes());
+        if !name.starts_with('\0') {
+            bytes.push(0);
+        }
+    }
+    bytes
+}
+
+/// Write the address of `name` to the buffer at `ptr`, whose size is the
+/// `socklen_t` at `len_ptr`, and its full size to `len_ptr`
+fn write_address(task: &Task, domain: SocketDomain, ptr: usize, len_ptr: usize, name: Option<&str>) -> Result<(), usize> {
+    if ptr == 0 {
+        return Ok(());
+    }
+    let mut len = [0u8; 4];
+    copy_from_user(task, len_ptr, &mut len).map_err(|_| errno::EFAULT)?;
+    let bytes = address_bytes(domain, name);
+    let room = (u32::from_le_bytes(len) as usize).min(bytes.len());
+    copy_to_user(task, ptr, &bytes[..room]).map_err(|_| errno::EFAULT)?;
+    copy_to_user(task, len_ptr, &(bytes.len() as u32).to_le_bytes()).map_err(|_| errno::EFAULT)
+}
+
+fn socket_type(kind: usize) -> Result<SocketType, usize> {
+    match kind & SOCK_TYPE_MASK {
+        SOCK_STREAM => Ok(SocketType::Stream),
+        SOCK_DGRAM => Ok(SocketType::Datagram),
+        SOCK_RAW => Ok(SocketType::Raw),
+        _ => Err(errno::EPROTONOSUPPORT),
+    }
+}
+
+/// Check the domain, type and protocol of `socket` and `socketpair`
+fn check_socket(domain: usize, kind: usize, protocol: usize) -> Result<(SocketDomain, SocketType), usize> {
+    let domain = match u16::try_from(domain) {
+        Ok(AF_UNIX) => SocketDomain::Local,
+        Ok(AF_INET) => SocketDomain::Inet,
+        Ok(AF_INET6) => SocketDomain::Inet6,
+        Ok(AF_PACKET) => SocketDomain::Packet,
+        _ => return Err(errno::EAFNOSUPPORT),
+    };
+    if kind & !(SOCK_TYPE_MASK | SOCK_NONBLOCK | SOCK_CLOEXEC) != 0 {
+        return Err(errno::EINVAL);
+    }
+    let socket_type = socket_type(kind)?;
+    let protocol_ok = match (domain, socket_type) {
+        (SocketDomain::Inet | SocketDomain::Inet6, SocketType::Stream) => matches!(protocol, 0 | IPPROTO_TCP),
+        (SocketDomain::Inet | SocketDomain::Inet6, SocketType::Datagram) => matches!(protocol, 0 | IPPROTO_UDP),
+        (SocketDomain::Inet | SocketDomain::Inet6, SocketType::Raw) => (1..=255).contains(&protocol),
+        (SocketDomain::Packet, SocketType::Raw) => protocol <= u16::MAX as usize,
+        (SocketDomain::Local, SocketType::Raw) | (SocketDomain::Packet, _) => return Err(errno::ESOCKTNOSUPPORT),
+        (SocketDomain::Local, _) => protocol == 0,
+    };
+    if !protocol_ok {
+        return Err(errno::EPROTONOSUPPORT);
+    }
+    Ok((domain, socket_type))
+}
+
+/// Give `socket` a descriptor with the flags of `kind`
+fn install_socket(abi: &mut LinuxRiscv64Abi, task: &mut Task, socket: Arc<dyn SocketObject>, kind: usize) -> Result<usize, usize> {
+    install(abi, task, KernelObject::from_socket(socket), kind & SOCK_CLOEXEC != 0, O_RDWR | (kind & SOCK_NONBLOCK))
+}
+
+/// `socket`; `AF_UNIX`, `AF_INET` and `AF_INET6` stream and datagram
+/// sockets, raw `AF_INET` and `AF_INET6` sockets and `AF_PACKET` ones
+///
+/// Sockets always block: `SOCK_NONBLOCK` is the `O_NONBLOCK` status flag
+/// of the descriptor. Raw and packet sockets take privilege; the protocol
+/// of a packet socket is a big-endian EtherType, or `ETH_P_ALL`.
+pub fn sys_socket(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let domain = trapframe.get_arg(0);
+    let kind = trapframe.get_arg(1);
+    let protocol = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    let mut socket = || -> Result<usize, usize> {
+        let (domain, socket_type) = check_socket(domain, kind, protocol)?;
+        let protocol = match (domain, socket_type) {
+            (SocketDomain::Packet, _) => match u16::from_be(protocol as u16) {
+                ETH_P_ALL => 0,
+                ethertype => ethertype,
+            },
+            (_, SocketType::Raw) => protocol as u16,
+            _ => 0,
+        };
+        if socket_type == SocketType::Raw && !task.cred.is_privileged() {
+            crate::audit::privilege_denied("raw socket");
+            return Err(errno::EPERM);
+        }
+        let socket = create_socket(domain, socket_type, protocol, false).map_err(socket_error)?;
+        install_socket(abi, task, socket, kind)
+    };
+    result(socket())
+}
+
+/// `socketpair`; only `AF_UNIX` sockets come in pairs
+pub fn sys_socketpair(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let domain = trapframe.get_arg(0);
+    let kind = trapframe.get_arg(1);
+    let protocol = trapframe.get_arg(2);
+    let sv_ptr = trapframe.get_arg(3);
+    trapframe.increment_pc_next(task);
+
+    let mut socketpair = || -> Result<usize, usize> {
+        let (a, b) = match check_socket(domain, kind, protocol)? {
+            (SocketDomain::Local, socket_type) => UnixSocket::pair(socket_type, false),
+            (SocketDomain::Inet | SocketDomain::Inet6 | SocketDomain::Packet, _) => return Err(errno::EOPNOTSUPP),
+        };
+        let first = install_socket(abi, task, a, kind)?;
+        let second = install_socket(abi, task, b, kind);
+        let fds = match second {
+            Ok(second) => [first, second],
+            Err(err) => {
+                if let Some(desc) = abi.remove_fd(first) {
+                    task.handle_table.write().remove(desc.handle);
+                }
+                return Err(err);
+            }
+        };
+        let mut bytes = [0u8; 8];
+        bytes[0..4].copy_from_slice(&(fds[0] as i32).to_le_bytes());
+        bytes[4..8].copy_from_slice(&(fds[1] as i32).to_le_bytes());
+        if copy_to_user(task, sv_ptr, &bytes).is_err() {
+            for fd in fds {
+                if let Some(desc) = abi.remove_fd(fd) {
+                    task.handle_table.write().remove(desc.handle);
+                }
+            }
+            return Err(errno::EFAULT);
+        }
+        Ok(0)
+    };
+    result(socketpair())
+}
+
+pub fn sys_bind(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let addr = trapframe.get_arg(1);
+    let addrlen = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    let bind = || -> Result<usize, usize> {
+        let (_, socket) = socket_of(abi, task, fd)?;
+        let name = read_address(task, socket.domain(), addr, addrlen)?;
+        socket.bind(&name).map_err(socket_error)?;
+        Ok(0)
+    };
+    result(bind())
+}
+
+pub fn sys_listen(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let backlog = trapframe.get_arg(1) as i32;
+    trapframe.increment_pc_next(task);
+
+    let listen = || -> Result<usize, usize> {
+        let (_, socket) = socket_of(abi, task, fd)?;
+        socket.listen(backlog.max(0) as usize).map_err(socket_error)?;
+        Ok(0)
+    };
+    result(listen())
+}
+
+/// `connect`; on a non-blocking descriptor a TCP connection fails with
+/// `EINPROGRESS` and `SO_ERROR` tells how it ended once writable
+pub fn sys_connect(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let addr = trapframe.get_arg(1);
+    let addrlen = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    let connect = || -> Result<usize, usize> {
+        let (desc, socket) = socket_of(abi, task, fd)?;
+        let name = read_address(task, socket.domain(), addr, addrlen)?;
+        match desc.status_flags & O_NONBLOCK {
+            0 => socket.connect(&name),
+            _ => socket.start_connect(&name),
+        }
+        .map_err(socket_error)?;
+        Ok(0)
+    };
+    result(connect())
+}
+
+pub fn sys_accept(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    accept(abi, trapframe, 0)
+}
+
+/// `accept4`; the flags are `SOCK_NONBLOCK` and `SOCK_CLOEXEC`
+pub fn sys_accept4(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let flags = trapframe.get_arg(3);
+    if flags & !(SOCK_NONBLOCK | SOCK_CLOEXEC) != 0 {
+        trapframe.increment_pc_next(mytask().unwrap());
+        return errno::error(errno::EINVAL);
+    }
+    accept(abi, trapframe, flags)
+}
+
+fn accept(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe, flags: usize) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let addr = trapframe.get_arg(1);
+    let addrlen_ptr = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    let mut accept = || -> Result<usize, usize> {
+        let (desc, listener) = socket_of(abi, task, fd)?;
+        would_block(&desc, listener.as_ref(), 0, POLLIN)?;
+        let socket = listener.accept().map_err(socket_error)?;
+        let (domain, peer_name) = (socket.domain(), socket.peer_name());
+        let new_fd = install_socket(abi, task, socket, flags)?;
+        if let Err(err) = write_address(task, domain, addr, addrlen_ptr, peer_name.as_deref()) {
+            if let Some(desc) = abi.remove_fd(new_fd) {
+                task.handle_table.write().remove(desc.handle);
+            }
+            return Err(err);
+        }
+        Ok(new_fd)
+    };
+    let value = accept();
+    io_result(task, trapframe, value)
+}
+
+pub fn sys_getsockname(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    socket_name(abi, trapframe, |socket| Ok(socket.name()))
+}
+
+/// `getpeername`: the name of the peer when the connection was made
+pub fn sys_getpeername(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    socket_name(abi, trapframe, |socket| match socket.is_connected() {
+        true => Ok(socket.peer_name()),
+        false => Err(errno::ENOTCONN),
+    })
+}
+
+/// Write the address `name` gives for the socket of the descriptor
+fn socket_name(
+    abi: &mut LinuxRiscv64Abi,
+    trapframe: &mut Trapframe,
+    name: impl Fn(&dyn SocketObject) -> Result<Option<String>, usize>,
+) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let addr = trapframe.get_arg(1);
+    let addrlen_ptr = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    let socket_name = || -> Result<usize, usize> {
+        let (_, socket) = socket_of(abi, task, fd)?;
+        let name = name(socket.as_ref())?;
+        write_address(task, socket.domain(), addr, addrlen_ptr, name.as_deref())?;
+        Ok(0)
+    };
+    result(socket_name())
+}
+
+/// Bytes of a send: a datagram larger than a socket holds is refused, a
+/// stream sends what fits
+fn send_len(socket: &dyn SocketObject, len: usize) -> Result<usize, usize> {
+    match socket.socket_type() {
+        SocketType::Datagram | SocketType::Raw if len > SOCKET_BUFFER_SIZE => Err(errno::EMSGSIZE),
+        _ => Ok(len.min(SOCKET_BUFFER_SIZE)),
+    }
+}
+
+/// Send `data` with `objects` through `socket`, to `to` or to its peer
+fn send(
+    task: &Task,
+    desc: &FileDescriptor,
+    socket: &dyn SocketObject,
+    data: &[u8],
+    objects: Vec<KernelObject>,
+    to: Option<String>,
+    flags: usize,
+) -> Result<usize, usize> {
+    if flags & MSG_OOB != 0 {
+        return Err(errno::EOPNOTSUPP);
+    }
+    would_block(desc, socket, flags, POLLOUT | POLLERR)?;
+    match socket.send(data, objects, to.as_deref()) {
+        Ok(sent) => Ok(sent),
+        Err(KernelError::BrokenPipe) => {
+            if flags & MSG_NOSIGNAL == 0 {
+                let _ = send_signal(task.get_id(), SIGPIPE);
+            }
+            Err(errno::EPIPE)
+        }
+        Err(error) => Err(socket_error(error)),
+    }
+}
+
+/// Receive up to `len` bytes from `socket`
+///
+/// The name of the sender is that of a datagram, or the peer of a stream.
+fn receive(desc: &FileDescriptor, socket: &dyn SocketObject, len: usize, flags: usize) -> Result<(Vec<u8>, Received), usize> {
+    if flags & (MSG_OOB | MSG_PEEK) != 0 {
+        return Err(errno::EOPNOTSUPP);
+    }
+    would_block(desc, socket, flags, POLLIN | POLLHUP | POLLERR)?;
+    let mut data = vec![0u8; len.min(SOCKET_BUFFER_SIZE)];
+    let mut received = socket.receive(&mut data).map_err(socket_error)?;
+    data.truncate(received.len);
+    if socket.socket_type() == SocketType::Stream {
+        received.from = socket.peer_name();
+    }
+    Ok((data, received))
+}
+
+/// `sendto`; a destination is only accepted by datagram sockets
+pub fn sys_sendto(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let buf = trapframe.get_arg(1);
+    let len = trapframe.get_arg(2);
+    let flags = trapframe.get_arg(3);
+    let dest_addr = trapframe.get_arg(4);
+    let addrlen = trapframe.get_arg(5);
+    trapframe.increment_pc_next(task);
+
+    let sendto = || -> Result<usize, usize> {
+        let (desc, socket) = socket_of(abi, task, fd)?;
+        let mut data = vec![0u8; send_len(socket.as_ref(), len)?];
+        copy_from_user(task, buf, &mut data).map_err(|_| errno::EFAULT)?;
+        let to = match dest_addr {
+            0 => None,
+            addr => Some(read_address(task, socket.domain(), addr, addrlen)?),
+        };
+        send(task, &desc, socket.as_ref(), &data, Vec::new(), to, flags)
+    };
+    let value = sendto();
+    io_result(task, trapframe, value)
+}
+
+/// `recvfrom`; descriptors sent with the data are closed
+pub fn sys_recvfrom(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let buf = trapframe.get_arg(1);
+    let len = trapframe.get_arg(2);
+    let flags = trapframe.get_arg(3);
+    let src_addr = trapframe.get_arg(4);
+    let addrlen_ptr = trapframe.get_arg(5);
+    trapframe.increment_pc_next(task);
+
+    let recvfrom = || -> Result<usize, usize> {
+        let (desc, socket) = socket_of(abi, task, fd)?;
+        let (data, received) = receive(&desc, socket.as_ref(), len, flags)?;
+        copy_to_user(task, buf, &data).map_err(|_| errno::EFAULT)?;
+        write_address(task, socket.domain(), src_addr, addrlen_ptr, received.from.as_deref())?;
+        Ok(data.len())
+    };
+    let value = recvfrom();
+    io_result(task, trapframe, value)
+}
+
+/// Size of the header of a control message with `word`-byte words
+const fn cmsg_header(word: usize) -> usize {
+    (word + 8).next_multiple_of(word)
+}
+
+fn read_word(bytes: &[u8], offset: usize, word: usize) -> usize {
+    let mut value = [0u8; 8];
+    value[..word].copy_from_slice(&bytes[offset..offset + word]);
+    usize::from_le_bytes(value)
+}
+
+fn write_word(bytes: &mut [u8], offset: usize, word: usize, value: usize) {
+    bytes[offset..offset + word].copy_from_slice(&value.to_le_bytes()[..word]);
+}
+
+/// Fields of a `struct msghdr`, each at the offset of its index in words
+const MSG_NAME: usize = 0;
+const MSG_NAMELEN: usize = 1;
+const MSG_IOV: usize = 2;
+const MSG_IOVLEN: usize = 3;
+const MSG_CONTROL: usize = 4;
+const MSG_CONTROLLEN: usize = 5;
+const MSG_FLAGS: usize = 6;
+
+/// Most bytes of control messages read by `sendmsg`
+const MAX_CONTROL_SIZE: usize = 4096;
+
+/// The objects of the descriptors in the `SCM_RIGHTS` control messages of
+/// `control`
+fn read_rights(abi: &LinuxRiscv64Abi, task: &Task, control: &[u8], word: usize) -> Result<Vec<KernelObject>, usize> {
+    let header = cmsg_header(word);
+    let mut objects = Vec::new();
+    let mut offset = 0;
+    while offset + header <= control.len() {
+        let len = read_word(control, offset, word);
+        let level = u32::from_le_bytes(control[offset + word..offset + word + 4].try_into().unwrap());
+        let kind = u32::from_le_bytes(control[offset + word + 4..offset + word + 8].try_into().unwrap());
+        // `len` is the caller's: compared so that it cannot overflow
+        if len < header || len > control.len() - offset || level as usize != SOL_SOCKET || kind != SCM_RIGHTS {
+            return Err(errno::EINVAL);
+        }
+        for fd in control[offset + header..offset + len].chunks_exact(4) {
+            let fd = i32::from_le_bytes(fd.try_into().unwrap());
+            let (_, object) = fd_object(abi, task, fd as usize)?;
+            objects.push(object);
+        }
+        offset += len.checked_next_multiple_of(word).ok_or(errno::EINVAL)?;
+    }
+    if objects.len() > SOCKET_MAX_OBJECTS {
+        return Err(errno::EINVAL);
+    }
+    Ok(objects)
+}
+
+pub fn sys_sendmsg(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    sendmsg(abi, trapframe, 8)
+}
+
+/// `sendmsg` of riscv32
+pub fn compat_sys_sendmsg(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    sendmsg(abi, trapframe, 4)
+}
+
+/// `word` is the size of the words and pointers of `struct msghdr` and
+/// `struct cmsghdr`
+fn sendmsg(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe, word: usize) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let msg_ptr = trapframe.get_arg(1);
+    let flags = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    let sendmsg = || -> Result<usize, usize> {
+        let (desc, socket) = socket_of(abi, task, fd)?;
+        let mut msg = vec![0u8; 7 * word];
+        copy_from_user(task, msg_ptr, &mut msg).map_err(|_| errno::EFAULT)?;
+        let field = |index: usize| read_word(&msg, index * word, word);
+
+        // One byte more than a socket holds tells a datagram too long
+        let mut data = Vec::new();
+        transfer_iov(task, field(MSG_IOV), field(MSG_IOVLEN), word, |base, len| {
+            let len = len.min(SOCKET_BUFFER_SIZE + 1 - data.len());
+            let start = data.len();
+            data.resize(start + len, 0);
+            copy_from_user(task, base, &mut data[start..]).map_err(|_| errno::EFAULT)?;
+            Ok(len)
+        })?;
+        data.truncate(send_len(socket.as_ref(), data.len())?);
+
+        let controllen = field(MSG_CONTROLLEN);
+        let objects = match field(MSG_CONTROL) {
+            0 => Vec::new(),
+            _ if controllen > MAX_CONTROL_SIZE => return Err(errno::EINVAL),
+            control_ptr => {
+                let mut control = vec![0u8; controllen];
+                copy_from_user(task, control_ptr, &mut control).map_err(|_| errno::EFAULT)?;
+                read_rights(abi, task, &control, word)?
+            }
+        };
+        let to = match field(MSG_NAME) {
+            0 => None,
+            name_ptr => Some(read_address(task, socket.domain(), name_ptr, field(MSG_NAMELEN) & 0xffff_ffff)?),
+        };
+        send(task, &desc, socket.as_ref(), &data, objects, to, flags)
+    };
+    let value = sendmsg();
+    io_result(task, trapframe, value)
+}
+
+pub fn sys_recvmsg(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    recvmsg(abi, trapframe, 8)
+}
+
+/// `recvmsg` of riscv32
+pub fn compat_sys_recvmsg(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    recvmsg(abi, trapframe, 4)
+}
+
+/// `word` is the size of the words and pointers of `struct msghdr` and
+/// `struct cmsghdr`
+///
+/// Descriptors received are passed in one `SCM_RIGHTS` message; those that
+/// do not fit the control buffer are closed and `MSG_CTRUNC` is set.
+fn recvmsg(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe, word: usize) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let msg_ptr = trapframe.get_arg(1);
+    let flags = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    let mut recvmsg = || -> Result<usize, usize> {
+        let (desc, socket) = socket_of(abi, task, fd)?;
+        let mut msg = vec![0u8; 7 * word];
+        copy_from_user(task, msg_ptr, &mut msg).map_err(|_| errno::EFAULT)?;
+        let field = |index: usize| read_word(&msg, index * word, word);
+        let (iov, iovlen) = (field(MSG_IOV), field(MSG_IOVLEN));
+        let (control_ptr, controllen) = (field(MSG_CONTROL), field(MSG_CONTROLLEN));
+        let name_ptr = field(MSG_NAME);
+
+        let capacity = transfer_iov(task, iov, iovlen, word, |_, len| Ok(len))?;
+        let (data, received) = receive(&desc, socket.as_ref(), capacity, flags)?;
+        let mut copied = 0;
+        transfer_iov(task, iov, iovlen, word, |base, len| {
+            let len = len.min(data.len() - copied);
+            copy_to_user(task, base, &data[copied..copied + len]).map_err(|_| errno::EFAULT)?;
+            copied += len;
+            Ok(len)
+        })?;
+
+        let mut msg_flags = if received.truncated { MSG_TRUNC } else { 0 };
+        let header = cmsg_header(word);
+        let room = match control_ptr {
+            0 => 0,
+            _ => controllen.saturating_sub(header) / 4,
+        };
+        if received.objects.len() > room {
+            msg_flags |= MSG_CTRUNC;
+        }
+        let mut fds = Vec::new();
+        for object in received.objects.into_iter().take(room) {
+            match install(abi, task, object, flags & MSG_CMSG_CLOEXEC != 0, O_RDWR) {
+                Ok(fd) => fds.extend_from_slice(&(fd as i32).to_le_bytes()),
+                Err(_) => {
+                    msg_flags |= MSG_CTRUNC;
+                    break;
+                }
+            }
+        }
+        let mut controllen = 0;
+        if !fds.is_empty() {
+            let len = header + fds.len();
+            let mut control = vec![0u8; len];
+            write_word(&mut control, 0, word, len);
+            control[word..word + 4].copy_from_slice(&(SOL_SOCKET as u32).to_le_bytes());
+            control[word + 4..word + 8].copy_from_slice(&SCM_RIGHTS.to_le_bytes());
+            control[header..].copy_from_slice(&fds);
+            copy_to_user(task, control_ptr, &control).map_err(|_| errno::EFAULT)?;
+            controllen = len.next_multiple_of(word).min(field(MSG_CONTROLLEN));
+        }
+
+        let mut namelen = 0;
+        if name_ptr != 0 {
+            let address = address_bytes(socket.domain(), received.from.as_deref());
+            let room = (field(MSG_NAMELEN) & 0xffff_ffff).min(address.len());
+            copy_to_user(task, name_ptr, &address[..room]).map_err(|_| errno::EFAULT)?;
+            namelen = address.len();
+        }
+        let mut updated = msg.clone();
+        updated[MSG_NAMELEN * word..MSG_NAMELEN * word + 4].copy_from_slice(&(namelen as u32).to_le_bytes());
+        write_word(&mut updated, MSG_CONTROLLEN * word, word, controllen);
+        updated[MSG_FLAGS * word..MSG_FLAGS * word + 4].copy_from_slice(&msg_flags.to_le_bytes());
+        copy_to_user(task, msg_ptr, &updated).map_err(|_| errno::EFAULT)?;
+        Ok(data.len())
+    };
+    let value = recvmsg();
+    io_result(task, trapframe, value)
+}
+
+pub fn sys_shutdown(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let how = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    let shutdown = || -> Result<usize, usize> {
+        let (_, socket) = socket_of(abi, task, fd)?;
+        let how = match how {
+            SHUT_RD => Shutdown::Read,
+            SHUT_WR => Shutdown::Write,
+            SHUT_RDWR => Shutdown::Both,
+            _ => return Err(errno::EINVAL),
+        };
+        socket.shutdown(how).map_err(socket_error)?;
+        Ok(0)
+    };
+    result(shutdown())
+}
+
+/// `getsockopt`: the type, domain and pending error of `SOL_SOCKET`, and
+/// the options [`sys_setsockopt`] sets; reading the error clears it
+pub fn sys_getsockopt(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let level = trapframe.get_arg(1);
+    let optname = trapframe.get_arg(2);
+    let optval = trapframe.get_arg(3);
+    let optlen_ptr = trapframe.get_arg(4);
+    trapframe.increment_pc_next(task);
+
+    let getsockopt = || -> Result<usize, usize> {
+        let (_, socket) = socket_of(abi, task, fd)?;
+        let value = match (level, optname) {
+            (SOL_SOCKET, SO_TYPE) => match socket.socket_type() {
+                SocketType::Stream => SOCK_STREAM,
+                SocketType::Datagram => SOCK_DGRAM,
+                SocketType::Raw => SOCK_RAW,
+            },
+            (SOL_SOCKET, SO_DOMAIN) => match socket.domain() {
+                SocketDomain::Local => AF_UNIX as usize,
+                SocketDomain::Inet => AF_INET as usize,
+                SocketDomain::Inet6 => AF_INET6 as usize,
+                SocketDomain::Packet => AF_PACKET as usize,
+            },
+            (SOL_SOCKET, SO_ERROR) => socket.take_error().map_or(0, errno::from_error),
+            _ => match socket_option(level, optname).map(|option| socket.get_option(option)) {
+                Some(Ok(value)) => value,
+                // Other sockets buffer a fixed size
+                Some(Err(KernelError::NotSupported)) if matches!(optname, SO_SNDBUF | SO_RCVBUF) && level == SOL_SOCKET => {
+                    SOCKET_BUFFER_SIZE
+                }
+                Some(Err(KernelError::NotSupported)) | None => return Err(errno::ENOPROTOOPT),
+                Some(Err(error)) => return Err(socket_error(error)),
+            },
+        };
+        let mut optlen = [0u8; 4];
+        copy_from_user(task, optlen_ptr, &mut optlen).map_err(|_| errno::EFAULT)?;
+        let bytes = (value as i32).to_le_bytes();
+        let len = (u32::from_le_bytes(optlen) as usize).min(bytes.len());
+        copy_to_user(task, optval, &bytes[..len]).map_err(|_| errno::EFAULT)?;
+        copy_to_user(task, optlen_ptr, &(len as u32).to_le_bytes()).map_err(|_| errno::EFAULT)?;
+        Ok(0)
+    };
+    result(getsockopt())
+}
+
+/// `setsockopt`: `SO_REUSEADDR`, `SO_KEEPALIVE` and the buffer sizes of
+/// `SOL_SOCKET`, and `TCP_NODELAY` and the keepalive times and count of
+/// `IPPROTO_TCP`, for TCP sockets; the reuse and sizes are accepted with no
+/// effect on the others
+pub fn sys_setsockopt(abi: &mut LinuxRiscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let fd = trapframe.get_arg(0);
+    let level = trapframe.get_arg(1);
+    let optname = trapframe.get_arg(2);
+    let optval = trapframe.get_arg(3);
+    let optlen = trapframe.get_arg(4);
+    trapframe.increment_pc_next(task);
+
+    let setsockopt = || -> Result<usize, usize> {
+        let (_, socket) = socket_of(abi, task, fd)?;
+        let option = socket_option(level, optname).ok_or(errno::ENOPROTOOPT)?;
+        if optlen < 4 {
+            return Err(errno::EINVAL);
+        }
+        let mut value = [0u8; 4];
+        copy_from_user(task, optval, &mut value).map_err(|_| errno::EFAULT)?;
+        let value = i32::from_le_bytes(value).max(0) as usize;
+        match socket.set_option(option, value) {
+            Ok(()) => Ok(0),
+            Err(KernelError::NotSupported) if level == SOL_SOCKET && optname != SO_KEEPALIVE => Ok(0),
+            Err(KernelError::NotSupported) => Err(errno::ENOPROTOOPT),
+            Err(error) => Err(socket_error(error)),
+        }
+    };
+    result(setsockopt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_socket_addresses() {
+        // Path names end with a NUL, abstract names are their bytes
+        assert_eq!(address_bytes(SocketDomain::Local, None), [1, 0]);
+        assert_eq!(address_bytes(SocketDomain::Local, Some("/a")), [1, 0, b'/', b'a', 0]);
+        assert_eq!(address_bytes(SocketDomain::Local, Some("\0a")), [1, 0, 0, b'a']);
+        // Internet addresses have a big-endian port
+        assert_eq!(
+            address_bytes(SocketDomain::Inet, Some("10.0.2.15:80")),
+            [2, 0, 0, 80, 10, 0, 2, 15, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+        assert_eq!(address_bytes(SocketDomain::Inet, None)[..8], [2, 0, 0, 0, 0, 0, 0, 0]);
+        // IPv4 names of IPv6 sockets are mapped
+        let mapped = address_bytes(SocketDomain::Inet6, Some("10.0.2.15:80"));
+        assert_eq!((mapped.len(), &mapped[..4]), (SOCKADDR_IN6_SIZE, &[10, 0, 0, 80][..]));
+        assert_eq!(mapped[8..24], [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 10, 0, 2, 15]);
+        assert_eq!(address_bytes(SocketDomain::Inet6, Some("[fe80::1]:7"))[22..24], [0, 1]);
+        // Raw sockets are named by an address of port 0
+        assert_eq!(address_bytes(SocketDomain::Inet, Some("192.0.2.30:0"))[..8], [2, 0, 0, 0, 192, 0, 2, 30]);
+        assert_eq!(address_bytes(SocketDomain::Packet, None), [17, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(check_socket(17, SOCK_DGRAM, 0), Err(errno::ESOCKTNOSUPPORT));
+        assert_eq!(check_socket(2, SOCK_RAW, 0), Err(errno::EPROTONOSUPPORT));
+        assert_eq!((cmsg_header(8), cmsg_header(4)), (16, 12));
+    }
+
+    #[test_case]
+    fn test_read_rights_length() {
+        let abi = LinuxRiscv64Abi::default();
+        let task = crate::task::new_user_task("test_read_rights".into(), 0);
+        let mut control = vec![0u8; 24];
+        control[8..12].copy_from_slice(&(SOL_SOCKET as u32).to_le_bytes());
+        control[12..16].copy_from_slice(&SCM_RIGHTS.to_le_bytes());
+
+        // A length reaching past the end of memory is refused, not wrapped
+        write_word(&mut control, 0, 8, usize::MAX);
+        assert_eq!(read_rights(&abi, &task, &control, 8).err(), Some(errno::EINVAL));
+        write_word(&mut control, 0, 8, usize::MAX - 7);
+        assert_eq!(read_rights(&abi, &task, &control, 8).err(), Some(errno::EINVAL));
+        // Past the end of the control data
+        write_word(&mut control, 0, 8, 25);
+        assert_eq!(read_rights(&abi, &task, &control, 8).err(), Some(errno::EINVAL));
+        // A message without descriptors
+        write_word(&mut control, 0, 8, 16);
+        assert_eq!(read_rights(&abi, &task, &control[..16], 8).map(|objects| objects.len()), Ok(0));
+    }
+}
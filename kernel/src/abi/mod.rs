@@ -6,16 +6,112 @@
 //! interfaces.
 //! 
 
-use crate::{arch::Trapframe, fs::{drivers::overlayfs::OverlayFS, VfsManager}, task::mytask};
+use crate::{arch::Trapframe, fs::{drivers::overlayfs::OverlayFS, VfsManager}, task::{elf_loader::note::AbiNote, mytask, signal, strace::{self, minus_one_error, SyscallErrorFn, SyscallInfo}}};
 use alloc::{boxed::Box, string::{String, ToString}, sync::Arc, vec::Vec};
 use hashbrown::HashMap;
 use spin::Mutex;
 
+use error::KernelError;
+
+pub mod error;
 pub mod scarlet;
 pub mod xv6;
+pub mod linux;
 
 pub const MAX_ABI_LENGTH: usize = 64;
 
+/// Version of an ABI, ordered as `major.minor.patch`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AbiVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl AbiVersion {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self { major, minor, patch }
+    }
+}
+
+impl core::fmt::Display for AbiVersion {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// A node of the view of the system an ABI gives its tasks
+///
+/// The executor adds the nodes of [`AbiModule::personality_nodes`] to the
+/// VFS of a task when the task enters the ABI, creating the directories
+/// they are shown at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PersonalityNode {
+    /// A directory of the base VFS, shown at `target`; left out if the base
+    /// VFS does not have it
+    Bind { source: &'static str, target: &'static str },
+    /// An instance of the filesystem driver `driver` of the task's own,
+    /// mounted at `target`
+    Mount { driver: &'static str, target: &'static str },
+}
+
+/// The view of an ABI that does not give its own: home directories,
+/// devices, and the native Scarlet root at `/scarlet`
+pub const DEFAULT_PERSONALITY: &[PersonalityNode] = &[
+    PersonalityNode::Bind { source: "/home", target: "/home" },
+    PersonalityNode::Bind { source: "/dev", target: "/dev" },
+    PersonalityNode::Bind { source: "/", target: "/scarlet" },
+];
+
+/// Add the nodes of an ABI view to the VFS of a task
+///
+/// # Arguments
+/// * `nodes` - The nodes, applied in order
+/// * `target_vfs` - VFS of the task
+/// * `base_vfs` - VFS the bound directories are taken from
+pub fn apply_personality(
+    nodes: &[PersonalityNode],
+    target_vfs: &Arc<VfsManager>,
+    base_vfs: &Arc<VfsManager>,
+) -> Result<(), &'static str> {
+    for node in nodes {
+        match *node {
+            PersonalityNode::Bind { source, target } => {
+                if base_vfs.metadata(source).is_err() {
+                    continue;
+                }
+                create_dir_all(target_vfs, target)?;
+                target_vfs.bind_mount_from(base_vfs, source, target)
+                    .map_err(|_| "Failed to bind mount a directory of the ABI view")?;
+            }
+            PersonalityNode::Mount { driver, target } => {
+                let filesystem = crate::fs::get_fs_driver_manager().create_from_option_string(driver, "")
+                    .map_err(|_| "Failed to create a filesystem of the ABI view")?;
+                create_dir_all(target_vfs, target)?;
+                target_vfs.mount(filesystem, target, 0)
+                    .map_err(|_| "Failed to mount a filesystem of the ABI view")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Create a directory and its missing parents
+fn create_dir_all(vfs: &Arc<VfsManager>, path: &str) -> Result<(), &'static str> {
+    let mut prefix = String::new();
+    for component in path.split('/').filter(|component| !component.is_empty()) {
+        prefix.push('/');
+        prefix.push_str(component);
+        match vfs.create_dir(&prefix) {
+            Err(e) if e.kind != crate::fs::FileSystemErrorKind::AlreadyExists => {
+                return Err("Failed to create a directory of the ABI view");
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
 /// ABI module trait.
 /// 
 /// This trait defines the interface for ABI modules in the Scarlet kernel.
@@ -39,6 +135,37 @@ pub trait AbiModule: Send + Sync + 'static {
     fn clone_boxed(&self) -> Box<dyn AbiModule + Send + Sync>;
 
     fn handle_syscall(&mut self, trapframe: &mut Trapframe) -> Result<usize, &'static str>;
+
+    /// Describe a system call for tracing (see `crate::task::strace`)
+    ///
+    /// # Arguments
+    /// * `number` - The system call number
+    ///
+    /// # Returns
+    /// * `None` - The call is unknown to this ABI
+    fn syscall_info(&self, _number: usize) -> Option<SyscallInfo> {
+        None
+    }
+
+    /// How the errors returned by the system calls of this ABI are shown
+    /// when they are traced
+    fn syscall_error_formatter(&self) -> SyscallErrorFn {
+        minus_one_error
+    }
+
+    /// The value a system call of this ABI returns when it fails with
+    /// `error` (see `crate::abi::error`)
+    ///
+    /// The native ABIs fail every call with usize::MAX.
+    fn error_code(&self, _error: KernelError) -> usize {
+        usize::MAX
+    }
+
+    /// The value a system call of this ABI returns when a signal interrupts
+    /// it and it is not restarted (see `crate::task::signal`)
+    fn interrupted_error(&self) -> usize {
+        self.error_code(KernelError::Interrupted)
+    }
     
     /// Determine if a binary can be executed by this ABI and return confidence
     /// 
@@ -94,6 +221,26 @@ pub trait AbiModule: Send + Sync + 'static {
         // Default implementation: cannot determine
         None
     }
+
+    /// Version of the interface this ABI implements
+    fn version(&self) -> AbiVersion {
+        AbiVersion::new(1, 0, 0)
+    }
+
+    /// Agree on the version a program marked with an ABI note runs with
+    ///
+    /// A program gets the version it was built for, so that it sees the
+    /// behavior it expects, as long as this ABI implements that version.
+    /// The default accepts Scarlet ABI tags naming this ABI.
+    ///
+    /// # Returns
+    /// * `None` - The note is not for this ABI, or asks for a newer version
+    fn negotiate_version(&self, note: &AbiNote) -> Option<AbiVersion> {
+        match note {
+            AbiNote::Scarlet { abi, version } if *abi == self.get_name() && *version <= self.version() => Some(*version),
+            _ => None,
+        }
+    }
     
     /// Handle conversion when switching ABIs
     fn initialize_from_existing_handles(&mut self, _task: &mut crate::task::Task) -> Result<(), &'static str> {
@@ -246,9 +393,18 @@ pub trait AbiModule: Send + Sync + 'static {
         Err("overlay_mount_from (cross-vfs) is not supported in v2")
     }
     
-    /// Setup shared resources accessible across all ABIs
+    /// Nodes of the base VFS and virtual filesystems the tasks of this ABI
+    /// see, such as `/dev` and `/proc` (see [`PersonalityNode`])
+    ///
+    /// The TransparentExecutor applies them after the overlay environment
+    /// is set up.
+    fn personality_nodes(&self) -> &'static [PersonalityNode] {
+        DEFAULT_PERSONALITY
+    }
+
+    /// Setup shared resources that the personality nodes cannot describe
     /// 
-    /// Bind mounts common directories that should be shared from base VFS.
+    /// Called after [`AbiModule::personality_nodes`] are applied.
     /// The TransparentExecutor is responsible for providing base_vfs.
     /// 
     /// # Arguments
@@ -259,15 +415,6 @@ pub trait AbiModule: Send + Sync + 'static {
         _target_vfs: &Arc<VfsManager>,
         _base_vfs: &Arc<VfsManager>,
     ) -> Result<(), &'static str> {
-        // TODO: VFS v2 migration - update bind_mount_from API usage
-        // Current limitation: function signature uses VFS v1 types
-        // Bind mount shared directories from base VFS
-        // target_vfs.bind_mount_from(&base_vfs, "/home", "/home")
-        //     .map_err(|_| "Failed to bind mount /home")?;
-        // target_vfs.bind_mount_from(&base_vfs, "/data/shared", "/data/shared")
-        //     .map_err(|_| "Failed to bind mount /data/shared")?;
-        // target_vfs.bind_mount_from(&base_vfs, "/", "/scarlet") // Read-onlyは未サポート
-        //     .map_err(|_| "Failed to bind mount native Scarlet root to /scarlet")
         Ok(())
     }
     
@@ -348,16 +495,19 @@ impl AbiRegistry {
     /// 
     /// This method tries all registered ABIs and selects the one with the highest
     /// confidence score. Each ABI internally handles inheritance bonuses and
-    /// compatibility logic based on the current task's ABI.
+    /// compatibility logic based on the current task's ABI. An ABI that
+    /// accepts the ABI note of the binary (see [`AbiModule::negotiate_version`])
+    /// wins over the heuristics.
     /// 
     /// # Arguments
     /// * `file_object` - Binary file to check
     /// * `file_path` - File path
+    /// * `note` - ABI note of the binary, if it has one
     /// 
     /// # Returns
     /// * `Some((abi_name, confidence))` - Best ABI name and confidence level
     /// * `None` - No executable ABI found
-    pub fn detect_best_abi(file_object: &crate::object::KernelObject, file_path: &str) -> Option<(String, u8)> {
+    pub fn detect_best_abi(file_object: &crate::object::KernelObject, file_path: &str, note: Option<&AbiNote>) -> Option<(String, u8)> {
         let registry = Self::global().lock();
         
         // Get current task's ABI reference for inheritance consideration
@@ -377,9 +527,14 @@ impl AbiRegistry {
             .filter_map(|(name, factory)| {
                 let abi = factory();
                 abi.can_execute_binary(file_object, file_path, current_abi)
-                    .map(|confidence| (name.clone(), confidence))
+                    .map(|confidence| {
+                        // Ranked above any confidence
+                        let noted = note.is_some_and(|note| abi.negotiate_version(note).is_some());
+                        (name.clone(), confidence, noted)
+                    })
             })
-            .max_by_key(|(_, confidence)| *confidence)
+            .max_by_key(|(_, confidence, noted)| (*noted, *confidence))
+            .map(|(name, confidence, _)| (name, confidence))
     }
 }
 
@@ -397,9 +552,29 @@ pub fn syscall_dispatcher(trapframe: &mut Trapframe) -> Result<usize, &'static s
     // 2. Get mutable reference to current task
     let task = mytask().unwrap();
     
-    // 3. Resolve the appropriate ABI based on PC address
-    let abi_module = task.resolve_abi_mut(pc);
+    // 3. Record the call if the task is traced, before its arguments change
+    let traced = task.strace.is_traced();
+    if traced {
+        let abi_module = task.resolve_abi_mut(pc);
+        let info = abi_module.syscall_info(trapframe.get_arg(7));
+        let error = abi_module.syscall_error_formatter();
+        strace::syscall_enter(task, info, error, trapframe);
+    }
     
-    // 4. Handle the system call with the resolved ABI
-    abi_module.handle_syscall(trapframe)
+    // 4. Resolve the appropriate ABI based on PC address and handle the
+    //    system call with it; an error left by an earlier call is stale
+    task.last_error = None;
+    let mut result = task.resolve_abi_mut(pc).handle_syscall(trapframe);
+
+    // 5. A call interrupted by a signal fails the same way in every ABI if
+    //    it is not restarted
+    let task = mytask().unwrap();
+    if result.is_ok() && signal::syscall_interrupted(task) {
+        result = Ok(task.resolve_abi_mut(pc).interrupted_error());
+    }
+    if traced {
+        // An error of the handler is returned to the task as usize::MAX
+        strace::syscall_exit(mytask().unwrap(), *result.as_ref().unwrap_or(&usize::MAX));
+    }
+    result
 }
\ No newline at end of file
@@ -7,9 +7,19 @@
 
 use alloc::{boxed::Box, collections::btree_map::BTreeMap, format, string::{String, ToString}, sync::Arc, vec::Vec};
 
-use crate::{arch::{vm, IntRegisters, Trapframe}, early_initcall, fs::{drivers::overlayfs::OverlayFS, FileSystemError, FileSystemErrorKind, SeekFrom, VfsManager}, register_abi, syscall::syscall_handler, task::elf_loader::{analyze_and_load_elf_with_strategy, build_auxiliary_vector, ExecutionMode, LoadStrategy, LoadTarget, setup_auxiliary_vector_on_stack}, vm::{setup_trampoline, setup_user_stack}};
+use crate::{arch::{vm, IntRegisters, Trapframe}, early_initcall, environment::VDSO_BASE, fs::{drivers::overlayfs::OverlayFS, SeekFrom, VfsManager}, register_abi, syscall::syscall_handler, task::elf_loader::{analyze_and_load_elf_with_strategy, build_auxiliary_vector, ExecutionMode, LoadStrategy, LoadTarget, setup_auxiliary_vector_on_stack}, vm::{aslr, setup_trampoline, setup_user_stack, vdso}};
 
-use super::AbiModule;
+use super::{AbiModule, PersonalityNode};
+
+/// Home directories, the shared data directory, devices, kernel
+/// information and the native root at `/scarlet`
+const SCARLET_PERSONALITY: &[PersonalityNode] = &[
+    PersonalityNode::Bind { source: "/home", target: "/home" },
+    PersonalityNode::Bind { source: "/data/shared", target: "/data/shared" },
+    PersonalityNode::Bind { source: "/dev", target: "/dev" },
+    PersonalityNode::Mount { driver: "procfs", target: "/proc" },
+    PersonalityNode::Bind { source: "/", target: "/scarlet" },
+];
 
 #[derive(Default, Copy, Clone)]
 pub struct ScarletAbi;
@@ -31,6 +41,10 @@ impl AbiModule for ScarletAbi {
         syscall_handler(trapframe)
     }
 
+    fn syscall_info(&self, number: usize) -> Option<crate::task::strace::SyscallInfo> {
+        crate::syscall::syscall_info(number)
+    }
+
     fn can_execute_binary(&self, file_object: &crate::object::KernelObject, file_path: &str, current_abi: Option<&(dyn crate::abi::AbiModule + Send + Sync)>) -> Option<u8> {
         // Stage 1: Basic format validation
         let magic_score = match file_object.as_file() {
@@ -100,16 +114,15 @@ impl AbiModule for ScarletAbi {
                 task.text_size = 0;
                 task.data_size = 0;
                 task.stack_size = 0;
-                task.brk = None;
 
                 // Create Scarlet-specific loading strategy
                 let strategy = LoadStrategy {
                     choose_base_address: |target, needs_relocation| {
                         match (target, needs_relocation) {
                             (LoadTarget::MainProgram, false) => 0,        // ET_EXEC: absolute
-                            (LoadTarget::MainProgram, true) => 0x10000,   // ET_DYN: PIE
-                            (LoadTarget::Interpreter, _) => 0x40000000,   // Dynamic linker
-                            (LoadTarget::SharedLib, _) => 0x50000000,     // Shared libraries
+                            (LoadTarget::MainProgram, true) => aslr::load_base(0x10000),   // ET_DYN: PIE
+                            (LoadTarget::Interpreter, _) => aslr::load_base(0x40000000),   // Dynamic linker
+                            (LoadTarget::SharedLib, _) => aslr::load_base(0x50000000),     // Shared libraries
                         }
                     },
                     resolve_interpreter: |requested| {
@@ -125,44 +138,37 @@ impl AbiModule for ScarletAbi {
                         task.name = argv.get(0).map_or("Unnamed Task".to_string(), |s| s.to_string());
                         
                         // Clear old page table entries
-                        let root_page_table = vm::get_root_pagetable(task.vm_manager.get_asid()).unwrap();
+                        let root_page_table = vm::get_root_pagetable(task.vm_manager.read().get_asid()).unwrap();
                         root_page_table.unmap_all();
                         
                         // Setup the new memory environment
-                        setup_trampoline(&mut task.vm_manager);
+                        setup_trampoline(&mut task.vm_manager.write());
+                        vdso::map_vdso(task)?;
                         let stack_pointer = setup_user_stack(task).1;
+                        aslr::randomize_task_layout(task);
 
-                        // Handle different execution modes
-                        match elf_result.mode {
-                            ExecutionMode::Static => {
-                                // Static linking - direct execution
-                                task.set_entry_point(elf_result.entry_point as usize);
-                            }
-                            ExecutionMode::Dynamic { ref interpreter_path } => {
-                                // Dynamic linking - setup auxiliary vector and jump to interpreter
-                                crate::println!("Scarlet ABI: Using dynamic linker at {}", interpreter_path);
-                                
-                                // Build auxiliary vector for dynamic linking
-                                let auxv = build_auxiliary_vector(&elf_result);
-                                
-                                // Setup auxiliary vector on stack
-                                match setup_auxiliary_vector_on_stack(task, &auxv) {
-                                    Ok(_auxv_addr) => {
-                                        crate::println!("Scarlet ABI: Auxiliary vector setup complete");
-                                    }
-                                    Err(e) => {
-                                        crate::println!("Scarlet ABI: Failed to setup auxiliary vector: {}", e.message);
-                                        return Err("Failed to setup auxiliary vector");
-                                    }
-                                }
-                                
-                                task.set_entry_point(elf_result.entry_point as usize);
-                            }
+                        if let ExecutionMode::Dynamic { ref interpreter_path } = elf_result.mode {
+                            crate::println!("Scarlet ABI: Using dynamic linker at {}", interpreter_path);
                         }
+
+                        // Static and dynamic programs both get an auxiliary
+                        // vector; the arguments go below it
+                        let auxv = build_auxiliary_vector(&elf_result, &task.cred);
+                        let stack_pointer = match setup_auxiliary_vector_on_stack(task, &auxv, stack_pointer) {
+                            Ok(auxv_addr) => auxv_addr,
+                            Err(e) => {
+                                crate::println!("Scarlet ABI: Failed to setup auxiliary vector: {}", e.message);
+                                return Err("Failed to setup auxiliary vector");
+                            }
+                        };
+                        // The program or, for a dynamic program, the interpreter
+                        task.set_entry_point(elf_result.entry_point as usize);
                         
                         // Reset task's registers for clean start
                         task.vcpu.reset_iregs();
                         task.vcpu.set_sp(stack_pointer);
+                        // Point tp at the TLS block of the program
+                        task.setup_tls()?;
 
                         // Setup argv/envp on stack following Unix and RISC-V conventions
                         let (adjusted_sp, argv_ptr) = self.setup_arguments_on_stack(task, argv, envp, stack_pointer)?;
@@ -173,6 +179,8 @@ impl AbiModule for ScarletAbi {
                         // a1 (reg[11]) = argv pointer
                         task.vcpu.iregs.reg[10] = argv.len(); // argc
                         task.vcpu.iregs.reg[11] = argv_ptr; // argv array pointer
+                        // a2 (reg[12]) = vDSO base
+                        task.vcpu.iregs.reg[12] = VDSO_BASE;
 
                         // crate::println!("Executing binary: {} with entry point: {:#x}", task.name, entry_point);
                         // crate::println!("Arguments: {:?}", argv);
@@ -311,74 +319,8 @@ impl AbiModule for ScarletAbi {
         }
     }
     
-    fn setup_shared_resources(
-        &self,
-        target_vfs: &Arc<VfsManager>,
-        base_vfs: &Arc<VfsManager>,
-    ) -> Result<(), &'static str> {
-        // crate::println!("Setting up Scarlet shared resources with base VFS");
-        // Scarlet shared resource setup: bind mount common directories and Scarlet gateway
-        match create_dir_if_not_exists(target_vfs, "/home") {
-            Ok(()) => {}
-            Err(e) => {
-                crate::println!("Failed to create /home directory for Scarlet: {}", e.message);
-                return Err("Failed to create /home directory for Scarlet");
-            }
-        }
-
-        match target_vfs.bind_mount_from(base_vfs, "/home", "/home") {
-            Ok(()) => {}
-            Err(_e) => {
-                // crate::println!("Failed to bind mount /home for Scarlet: {}", e.message);
-            }
-        }
-
-        match create_dir_if_not_exists(target_vfs, "/data") {
-            Ok(()) => {}
-            Err(e) => {
-                crate::println!("Failed to create /data directory for Scarlet: {}", e.message);
-                return Err("Failed to create /data directory for Scarlet");
-            }
-        }
-
-        match target_vfs.bind_mount_from(base_vfs, "/data/shared", "/data/shared") {
-            Ok(()) => {}
-            Err(_e) => {
-                // crate::println!("Failed to bind mount /data/shared for Scarlet: {}", e.message);
-            }
-        }
-
-        // Bind mount /dev for device access
-        match create_dir_if_not_exists(target_vfs, "/dev") {
-            Ok(()) => {}
-            Err(e) => {
-                crate::println!("Failed to create /dev directory for Scarlet: {}", e.message);
-                return Err("Failed to create /dev directory for Scarlet");
-            }
-        }
-        match target_vfs.bind_mount_from(base_vfs, "/dev", "/dev") {
-            Ok(()) => {}
-            Err(e) => {
-                crate::println!("Failed to bind mount /dev for Scarlet: {}", e.message);
-                return Err("Failed to bind mount /dev for Scarlet");
-            }
-        }
-
-        // Setup gateway to native Scarlet environment (read-only for security)
-        match create_dir_if_not_exists(target_vfs, "/scarlet") {
-            Ok(()) => {}
-            Err(e) => {
-                crate::println!("Failed to create /scarlet directory for Scarlet: {}", e.message);
-                return Err("Failed to create /scarlet directory for Scarlet");
-            }
-        }
-        match target_vfs.bind_mount_from(base_vfs, "/", "/scarlet") {
-            Ok(()) => Ok(()),
-            Err(e) => {
-                crate::println!("Failed to bind mount native Scarlet root to /scarlet for Scarlet: {}", e.message);
-                return Err("Failed to bind mount native Scarlet root to /scarlet for Scarlet");
-            }
-        }
+    fn personality_nodes(&self) -> &'static [PersonalityNode] {
+        SCARLET_PERSONALITY
     }
 }
 
@@ -487,26 +429,15 @@ impl ScarletAbi {
         Ok((new_sp, argv_ptr))
     }
     
-    /// Write bytes to stack memory using virtual memory translation
+    /// Write bytes to stack memory, page by page
     fn write_to_stack_memory(
         &self,
         task: &mut crate::task::Task,
         vaddr: usize,
         data: &[u8]
     ) -> Result<(), &'static str> {
-        match task.vm_manager.translate_vaddr(vaddr) {
-            Some(paddr) => {
-                unsafe {
-                    core::ptr::copy_nonoverlapping(
-                        data.as_ptr(),
-                        paddr as *mut u8,
-                        data.len()
-                    );
-                }
-                Ok(())
-            }
-            None => Err("Failed to translate virtual address for stack write")
-        }
+        crate::task::signal::copy_to_user(task, vaddr, data)
+            .map_err(|_| "Failed to translate virtual address for stack write")
     }
     
     /// Write a null-terminated string to stack memory
@@ -562,19 +493,6 @@ impl ScarletAbi {
     }
 }
 
-fn create_dir_if_not_exists(vfs: &Arc<VfsManager>, path: &str) -> Result<(), FileSystemError> {
-    match vfs.create_dir(path) {
-        Ok(()) => Ok(()),
-        Err(e) => {
-            if e.kind == FileSystemErrorKind::AlreadyExists {
-                Ok(()) // Directory already exists, nothing to do
-            } else {
-                Err(e) // Some other error occurred
-            }
-        }
-    }
-}
-
 fn register_scarlet_abi() {
     register_abi!(ScarletAbi);
 }
@@ -1,6 +1,6 @@
 use alloc::{string::{String, ToString}, sync::Arc, vec::Vec, vec};
 use crate::{
-    abi::xv6::riscv64::fs::xv6fs::{Dirent, Stat}, 
+    abi::xv6::riscv64::fs::xv6fs::{Dirent, Stat, T_DEVICE, T_DIR, T_FILE}, 
     arch::Trapframe, 
     device::manager::DeviceManager, 
     executor::TransparentExecutor, 
@@ -11,49 +11,29 @@ use crate::{
         DeviceFileInfo,
     }, 
     library::std::string::{
-        cstring_to_string, 
         parse_c_string_from_userspace, 
         parse_string_array_from_userspace, 
     }, 
-    object::capability::StreamError, 
-    sched::scheduler::get_scheduler, 
-    task::mytask, 
+    object::{
+        capability::StreamError,
+        handle::{AccessMode, HandleMetadata, HandleType},
+    },
+    task::{mytask, signal::{copy_from_user, copy_to_user, interrupt_syscall}, Task},
 };
 
-/// Convert Scarlet DirectoryEntry to xv6 Dirent and write to buffer
-fn read_directory_as_xv6_dirent(buf_ptr: *mut u8, count: usize, buffer_data: &[u8]) -> usize {
-    if count < Dirent::DIRENT_SIZE {
-        return 0; // Buffer too small for even one entry
-    }
-
-    // Parse DirectoryEntry from buffer data
-    if let Some(dir_entry) = DirectoryEntry::parse(buffer_data) {
-        // Convert Scarlet DirectoryEntry to xv6 Dirent
-        let inum = (dir_entry.file_id & 0xFFFF) as u16; // Use lower 16 bits as inode number
-        let name = dir_entry.name_str().unwrap_or("");
-        
-        let xv6_dirent = Dirent::new(inum, name);
-        
-        // Check if we have enough space
-        if count >= Dirent::DIRENT_SIZE {
-            // Copy the dirent to the buffer
-            let dirent_bytes = xv6_dirent.as_bytes();
-            unsafe {
-                core::ptr::copy_nonoverlapping(
-                    dirent_bytes.as_ptr(),
-                    buf_ptr,
-                    Dirent::DIRENT_SIZE
-                );
-            }
-            return Dirent::DIRENT_SIZE;
-        }
-    }
-    
-    0 // No data or error
+/// Convert a Scarlet DirectoryEntry to an xv6 Dirent
+fn read_directory_as_xv6_dirent(buffer_data: &[u8]) -> Option<Dirent> {
+    let dir_entry = DirectoryEntry::parse(buffer_data)?;
+    // Use lower 16 bits as inode number; 0 marks a free slot in xv6
+    let inum = ((dir_entry.file_id & 0xFFFF) as u16).max(1);
+    let name = dir_entry.name_str().unwrap_or("");
+    Some(Dirent::new(inum, name))
 }
 
 const MAX_PATH_LENGTH: usize = 128;
 const MAX_ARG_COUNT: usize = 64;
+/// Largest transfer done with one kernel buffer
+const MAX_IO_CHUNK: usize = 64 * 1024;
 
 pub fn sys_exec(_abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
@@ -110,69 +90,76 @@ enum OpenMode {
 
 pub fn sys_open(abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
-    let path_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(0)).unwrap() as *const u8;
+    let path_ptr = trapframe.get_arg(0);
     let mode = trapframe.get_arg(1) as i32;
 
     // Increment PC to avoid infinite loop if open fails
     trapframe.increment_pc_next(task);
 
-    // Convert path bytes to string
-    let path_str = match cstring_to_string(path_ptr, MAX_PATH_LENGTH) {
-        Ok((path, _)) => match to_absolute_path_v2(&task, &path) {
-            Ok(abs_path) => abs_path,
-            Err(_) => return usize::MAX,
-        },
-        Err(_) => return usize::MAX, // Invalid UTF-8
+    let path = match user_path(task, path_ptr) {
+        Some(path) => path,
+        None => return usize::MAX, // Invalid path
     };
-
-    // Use task's VFS manager
-    let vfs = task.vfs.as_ref().unwrap();
-
-    // Try to open the file
-    let file = vfs.open(&path_str, 0);
-
-    match file {
-        Ok(kernel_obj) => {
-            // Register the file with the task using HandleTable
-            let handle = task.handle_table.insert(kernel_obj);
-            match handle {
-                Ok(handle) => {
-                    match abi.allocate_fd(handle as u32) {
-                        Ok(fd) => fd,
-                        Err(_) => usize::MAX, // Too many open files
-                    }
-                },
-                Err(_) => usize::MAX, // Handle table full
+    let vfs = match task.vfs.as_ref() {
+        Some(vfs) => vfs.clone(),
+        None => return usize::MAX, // VFS not initialized
+    };
+    let access = mode & 0x3;
+    let create = mode & OpenMode::Create as i32 != 0;
+
+    match vfs.metadata(&path) {
+        // Directories are only opened read-only, and O_CREATE does not open them
+        Ok(metadata) if metadata.file_type == FileType::Directory => {
+            if create || access != OpenMode::ReadOnly as i32 {
+                return usize::MAX;
             }
         }
-        Err(_) =>{
-            // If the file does not exist and we are trying to create it
-            if mode & OpenMode::Create as i32 != 0 {
-                let res = vfs.create_file(&path_str, FileType::RegularFile);
-                if res.is_err() {
-                    return usize::MAX; // File creation error
-                }
-                match vfs.open(&path_str, 0) {
-                    Ok(kernel_obj) => {
-                        // Register the file with the task using HandleTable
-                        let handle = task.handle_table.insert(kernel_obj);
-                        match handle {
-                            Ok(handle) => {
-                                match abi.allocate_fd(handle as u32) {
-                                    Ok(fd) => fd,
-                                    Err(_) => usize::MAX, // Too many open files
-                                }
-                            },
-                            Err(_) => usize::MAX, // Handle table full
-                        }
-                    }
-                    Err(_) => usize::MAX, // File open error
-                }
-            } else {
-                return usize::MAX; // VFS not initialized
+        Ok(_) => {}
+        Err(_) if create => {
+            if vfs.create_file(&path, FileType::RegularFile).is_err() {
+                return usize::MAX; // File creation error
+            }
+        }
+        Err(_) => return usize::MAX, // File not found
+    }
+
+    // The access mode lets an overlay copy the file up before it is written
+    let kernel_obj = match vfs.open(&path, access as u32) {
+        Ok(obj) => obj,
+        Err(_) => return usize::MAX, // File open error
+    };
+    if mode & OpenMode::Truncate as i32 != 0 {
+        if let Some(file) = kernel_obj.as_file() {
+            let is_file = file.metadata().map_or(false, |metadata| metadata.file_type == FileType::RegularFile);
+            if is_file && file.truncate(0).is_err() {
+                return usize::MAX;
             }
         }
     }
+
+    let access_mode = if access == OpenMode::WriteOnly as i32 {
+        AccessMode::WriteOnly
+    } else if access == OpenMode::ReadWrite as i32 {
+        AccessMode::ReadWrite
+    } else {
+        AccessMode::ReadOnly
+    };
+    let metadata = HandleMetadata {
+        handle_type: HandleType::Regular,
+        access_mode,
+        special_semantics: None,
+    };
+    let handle = match task.handle_table.write().insert_with_metadata(kernel_obj, metadata) {
+        Ok(handle) => handle,
+        Err(_) => return usize::MAX, // Handle table full
+    };
+    match abi.allocate_fd(handle as u32) {
+        Ok(fd) => fd,
+        Err(_) => {
+            task.handle_table.write().remove(handle);
+            usize::MAX // Too many open files
+        }
+    }
 }
 
 pub fn sys_dup(abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &mut Trapframe) -> usize {
@@ -182,14 +169,22 @@ pub fn sys_dup(abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &mu
 
     // Get handle from XV6 fd
     if let Some(old_handle) = abi.get_handle(fd) {
-        if let Some(old_kernel_obj) = task.handle_table.get(old_handle) {
-            let kernel_obj = old_kernel_obj.clone();
-            let handle = task.handle_table.insert(kernel_obj);
+        let old_kernel_obj = task.handle_table.read().get(old_handle).cloned();
+        if let Some(kernel_obj) = old_kernel_obj {
+            // The duplicate keeps the access mode of the original
+            let metadata = task.handle_table.read().get_metadata(old_handle).cloned();
+            let handle = match metadata {
+                Some(metadata) => task.handle_table.write().insert_with_metadata(kernel_obj, metadata),
+                None => task.handle_table.write().insert(kernel_obj),
+            };
             match handle {
                 Ok(new_handle) => {
                     match abi.allocate_fd(new_handle as u32) {
                         Ok(fd) => fd,
-                        Err(_) => usize::MAX, // Too many open files
+                        Err(_) => {
+                            task.handle_table.write().remove(new_handle);
+                            usize::MAX // Too many open files
+                        }
                     }
                 },
                 Err(_) => usize::MAX, // Handle table full
@@ -209,7 +204,7 @@ pub fn sys_close(abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &
     
     // Get handle from XV6 fd and remove mapping
     if let Some(handle) = abi.remove_fd(fd) {
-        if task.handle_table.remove(handle).is_some() {
+        if task.handle_table.write().remove(handle).is_some() {
             0 // Success
         } else {
             usize::MAX // Handle not found in handle table
@@ -222,94 +217,110 @@ pub fn sys_close(abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &
 pub fn sys_read(abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
     let fd = trapframe.get_arg(0) as usize;
-    let buf_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(1)).unwrap() as *mut u8;
-    let count = trapframe.get_arg(2) as usize;
+    let buf_ptr = trapframe.get_arg(1);
+    let count = trapframe.get_arg(2) as i32;
+
+    // Increment PC to avoid infinite loop if read fails
+    trapframe.increment_pc_next(task);
+
+    if count < 0 {
+        return usize::MAX; // Negative count
+    }
+    let count = count as usize;
 
     // Get handle from XV6 fd
     let handle = match abi.get_handle(fd) {
         Some(h) => h,
-        None => {
-            trapframe.increment_pc_next(task);
-            return usize::MAX; // Invalid file descriptor
-        }
+        None => return usize::MAX, // Invalid file descriptor
     };
+    if matches!(task.handle_table.read().get_metadata(handle), Some(metadata) if metadata.access_mode == AccessMode::WriteOnly) {
+        return usize::MAX; // Not open for reading
+    }
 
-    let kernel_obj = match task.handle_table.get(handle) {
+    let kernel_obj = match task.handle_table.read().get(handle).cloned() {
         Some(obj) => obj,
-        None => {
-            trapframe.increment_pc_next(task);
-            return usize::MAX; // Invalid file descriptor
-        }
+        None => return usize::MAX, // Invalid file descriptor
     };
 
     // Check if this is a directory by getting file metadata
-    let is_directory = if let Some(file_obj) = kernel_obj.as_file() {
-        if let Ok(metadata) = file_obj.metadata() {
-            matches!(metadata.file_type, FileType::Directory)
-        } else {
-            false
-        }
-    } else {
-        false
-    };
+    let file_type = kernel_obj.as_file()
+        .and_then(|file_obj| file_obj.metadata().ok())
+        .map(|metadata| metadata.file_type);
 
     let stream = match kernel_obj.as_stream() {
         Some(stream) => stream,
-        None => {
-            trapframe.increment_pc_next(task);
-            return usize::MAX; // Not a stream object
-        }
+        None => return usize::MAX, // Not a stream object
     };
 
-    if is_directory {
-        // For directories, we need a larger buffer to read DirectoryEntry, then convert to Dirent
+    if file_type == Some(FileType::Directory) {
+        // Directories yield one DirectoryEntry per read, converted to a Dirent
+        if count < Dirent::DIRENT_SIZE {
+            return 0; // Buffer too small for even one entry
+        }
         let directory_entry_size = core::mem::size_of::<DirectoryEntry>();
         let mut temp_buffer = vec![0u8; directory_entry_size];
-        
-        match stream.read(&mut temp_buffer) {
-            Ok(n) => {
-                trapframe.increment_pc_next(task); // Increment PC to avoid infinite loop
-                if n > 0 && n >= directory_entry_size {
-                    // Convert DirectoryEntry to xv6 Dirent
-                    let converted_bytes = read_directory_as_xv6_dirent(buf_ptr, count, &temp_buffer[..n]);
-                    if converted_bytes > 0 {
-                        return converted_bytes; // Return converted xv6 dirent size
-                    }
+        return match stream.read(&mut temp_buffer) {
+            Ok(n) if n >= directory_entry_size => {
+                match read_directory_as_xv6_dirent(&temp_buffer[..n]) {
+                    Some(dirent) => match copy_to_user(task, buf_ptr, dirent.as_bytes()) {
+                        Ok(()) => Dirent::DIRENT_SIZE,
+                        Err(_) => usize::MAX, // Bad buffer
+                    },
+                    None => 0,
                 }
-                0 // EOF or no valid directory entry
-            },
+            }
+            Ok(_) | Err(StreamError::EndOfStream) => 0, // EOF
             Err(_) => usize::MAX, // Read error
+        };
+    }
+
+    // Regular files are read until the count is met; other streams return
+    // what the first read gives, as a second one could block
+    let mut total = 0;
+    while total < count {
+        let chunk = (count - total).min(MAX_IO_CHUNK);
+        let mut buffer = vec![0u8; chunk];
+        let n = match stream.read(&mut buffer) {
+            Ok(n) => n,
+            Err(StreamError::EndOfStream) => 0,
+            Err(_) if total > 0 => break,
+            Err(StreamError::Interrupted) => return interrupt_syscall(task, trapframe),
+            Err(_) => return usize::MAX, // Read error
+        };
+        if copy_to_user(task, buf_ptr + total, &buffer[..n]).is_err() {
+            return usize::MAX; // Bad buffer
         }
-    } else {
-        // For regular files, use the user-provided buffer directly
-        let mut buffer = unsafe { core::slice::from_raw_parts_mut(buf_ptr, count) };
-        
-        match stream.read(&mut buffer) {
-            Ok(n) => {
-                trapframe.increment_pc_next(task); // Increment PC to avoid infinite loop
-                n
-            }, // Return original read size for regular files
-            Err(_) => usize::MAX, // Read error
+        total += n;
+        if n < chunk || file_type != Some(FileType::RegularFile) {
+            break;
         }
     }
+    total
 }
 
 pub fn sys_write(abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
     let fd = trapframe.get_arg(0) as usize;
-    let buf_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(1)).unwrap() as *const u8;
-    let count = trapframe.get_arg(2) as usize;
+    let buf_ptr = trapframe.get_arg(1);
+    let count = trapframe.get_arg(2) as i32;
 
     // Increment PC to avoid infinite loop if write fails
     trapframe.increment_pc_next(task);
 
+    if count < 0 {
+        return usize::MAX; // Negative count
+    }
+
     // Get handle from XV6 fd
     let handle = match abi.get_handle(fd) {
         Some(h) => h,
         None => return usize::MAX, // Invalid file descriptor
     };
+    if matches!(task.handle_table.read().get_metadata(handle), Some(metadata) if metadata.access_mode == AccessMode::ReadOnly) {
+        return usize::MAX; // Not open for writing
+    }
 
-    let kernel_obj = match task.handle_table.get(handle) {
+    let kernel_obj = match task.handle_table.read().get(handle).cloned() {
         Some(obj) => obj,
         None => return usize::MAX, // Invalid file descriptor
     };
@@ -319,12 +330,30 @@ pub fn sys_write(abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &
         None => return usize::MAX, // Not a stream object
     };
 
-    let buffer = unsafe { core::slice::from_raw_parts(buf_ptr, count) };
+    let count = match task.check_file_write(&kernel_obj, count as usize) {
+        Ok(count) => count,
+        Err(_) => return usize::MAX, // File size limit exceeded
+    };
 
-    match stream.write(buffer) {
-        Ok(n) => n,
-        Err(_) => usize::MAX, // Write error
+    let mut total = 0;
+    while total < count {
+        let mut buffer = vec![0u8; (count - total).min(MAX_IO_CHUNK)];
+        if copy_from_user(task, buf_ptr + total, &mut buffer).is_err() {
+            return usize::MAX; // Bad buffer
+        }
+        match stream.write(&buffer) {
+            Ok(n) => {
+                total += n;
+                if n < buffer.len() {
+                    break;
+                }
+            }
+            Err(_) if total > 0 => break,
+            Err(StreamError::Interrupted) => return interrupt_syscall(task, trapframe),
+            Err(_) => return usize::MAX, // Write error
+        }
     }
+    total
 }
 
 pub fn sys_lseek(abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &mut Trapframe) -> usize {
@@ -342,7 +371,7 @@ pub fn sys_lseek(abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &
         None => return usize::MAX, // Invalid file descriptor
     };
 
-    let kernel_obj = match task.handle_table.get(handle) {
+    let kernel_obj = match task.handle_table.read().get(handle).cloned() {
         Some(obj) => obj,
         None => return usize::MAX, // Invalid file descriptor
     };
@@ -369,9 +398,10 @@ pub fn sys_lseek(abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &
 pub fn sys_mknod(_abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
     trapframe.increment_pc_next(task);
-    let name_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(0)).unwrap() as *const u8;
-    let name = get_path_str_v2(name_ptr).unwrap();
-    let path = to_absolute_path_v2(&task, &name).unwrap();
+    let path = match user_path(task, trapframe.get_arg(0)) {
+        Some(path) => path,
+        None => return usize::MAX, // Invalid path
+    };
 
     let major = trapframe.get_arg(1) as u32;
     let minor = trapframe.get_arg(2) as u32;
@@ -400,21 +430,19 @@ pub fn sys_mknod(_abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe:
 
 pub fn sys_fstat(abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &mut crate::arch::Trapframe) -> usize {
     let fd = trapframe.get_arg(0) as usize;
+    let stat_ptr = trapframe.get_arg(1);
 
     let task = mytask()
         .expect("sys_fstat: No current task found");
     trapframe.increment_pc_next(task); // Increment the program counter
 
-    let stat_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(1) as usize)
-        .expect("sys_fstat: Failed to translate stat pointer") as *mut Stat;
-    
     // Get handle from XV6 fd
     let handle = match abi.get_handle(fd) {
         Some(h) => h,
         None => return usize::MAX, // Invalid file descriptor
     };
     
-    let kernel_obj = match task.handle_table.get(handle) {
+    let kernel_obj = match task.handle_table.read().get(handle).cloned() {
         Some(obj) => obj,
         None => return usize::MAX, // Return -1 on error
     };
@@ -424,40 +452,38 @@ pub fn sys_fstat(abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &
         None => return usize::MAX, // Not a file object
     };
 
-    let metadata = file.metadata()
-        .expect("sys_fstat: Failed to get file metadata");
-
-    if stat_ptr.is_null() {
-        return usize::MAX; // Return -1 if stat pointer is null
-    }
-    
-    let stat = unsafe { &mut *stat_ptr };
+    let metadata = match file.metadata() {
+        Ok(metadata) => metadata,
+        Err(_) => return usize::MAX,
+    };
 
-    *stat = Stat {
+    let stat = Stat {
         dev: 0,
         ino: metadata.file_id as u32,
         file_type: match metadata.file_type {
-            FileType::Directory => 1, // T_DIR
-            FileType::RegularFile => 2,      // T_FILE
-            FileType::CharDevice(_) => 3, // T_DEVICE
-            FileType::BlockDevice(_) => 3, // T_DEVICE
+            FileType::Directory => T_DIR,
+            FileType::RegularFile => T_FILE,
+            FileType::CharDevice(_) => T_DEVICE,
+            FileType::BlockDevice(_) => T_DEVICE,
             _ => 0, // Unknown type
         },
-        nlink: 1,
+        nlink: metadata.link_count.min(u16::MAX as u32) as u16,
         size: metadata.size as u64,
     };
 
-    0
+    match copy_to_user(task, stat_ptr, &stat.to_bytes()) {
+        Ok(()) => 0,
+        Err(_) => usize::MAX, // Bad stat pointer
+    }
 }
 
 pub fn sys_mkdir(_abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
     trapframe.increment_pc_next(task);
     
-    let path_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(0)).unwrap() as *const u8;
-    let path = match get_path_str_v2(path_ptr) {
-        Ok(p) => to_absolute_path_v2(&task, &p).unwrap(),
-        Err(_) => return usize::MAX, // Invalid path
+    let path = match user_path(task, trapframe.get_arg(0)) {
+        Some(path) => path,
+        None => return usize::MAX, // Invalid path
     };
 
     // Try to create the directory
@@ -471,14 +497,22 @@ pub fn sys_mkdir(_abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe:
 pub fn sys_unlink(_abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
     trapframe.increment_pc_next(task);
-    
-    let path_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(0)).unwrap() as *const u8;
-    let path = match cstring_to_string(path_ptr, MAX_PATH_LENGTH) {
-        Ok((p, _)) => to_absolute_path_v2(&task, &p).unwrap(),
+
+    let raw_path = match parse_c_string_from_userspace(task, trapframe.get_arg(0), MAX_PATH_LENGTH) {
+        Ok(path) => path,
         Err(_) => return usize::MAX, // Invalid path
     };
+    // "." and ".." name the directory itself, which xv6 never unlinks
+    let name = raw_path.trim_end_matches('/').rsplit('/').next().unwrap_or("");
+    if name == "." || name == ".." {
+        return usize::MAX;
+    }
+    let path = match to_absolute_path_v2(task, &raw_path) {
+        Ok(path) => path,
+        Err(_) => return usize::MAX,
+    };
 
-    // Try to remove the file or directory
+    // Try to remove the file or directory; non-empty directories are refused
     let vfs = task.vfs.as_mut().unwrap();
     match vfs.remove(&path) {
         Ok(_) => 0, // Success
@@ -489,56 +523,30 @@ pub fn sys_unlink(_abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe:
 pub fn sys_link(_abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
     trapframe.increment_pc_next(task);
-    
-    let src_path_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(0)).unwrap() as *const u8;
-    let dst_path_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(1)).unwrap() as *const u8;
 
-    let src_path = match cstring_to_string(src_path_ptr, MAX_PATH_LENGTH) {
-        Ok((p, _)) => to_absolute_path_v2(&task, &p).unwrap(),
-        Err(_) => return usize::MAX, // Invalid path
+    let src_path = match user_path(task, trapframe.get_arg(0)) {
+        Some(path) => path,
+        None => return usize::MAX, // Invalid path
     };
-
-    let dst_path = match cstring_to_string(dst_path_ptr, MAX_PATH_LENGTH) {
-        Ok((p, _)) => to_absolute_path_v2(&task, &p).unwrap(),
-        Err(_) => return usize::MAX, // Invalid path
+    let dst_path = match user_path(task, trapframe.get_arg(1)) {
+        Some(path) => path,
+        None => return usize::MAX, // Invalid path
     };
 
+    // Directories, existing targets and links across file systems all fail
     let vfs = task.vfs.as_ref().unwrap();
     match vfs.create_hardlink(&src_path, &dst_path) {
         Ok(_) => 0, // Success
-        Err(err) => {
-            use crate::fs::FileSystemErrorKind;
-            
-            // Map VFS errors to appropriate errno values for xv6
-            match err.kind {
-                FileSystemErrorKind::NotFound => {
-                    // Source file doesn't exist
-                    2 // ENOENT
-                },
-                FileSystemErrorKind::FileExists => {
-                    // Destination already exists
-                    17 // EEXIST
-                },
-                FileSystemErrorKind::CrossDevice => {
-                    // Hard links across devices not supported
-                    18 // EXDEV
-                },
-                FileSystemErrorKind::InvalidOperation => {
-                    // Operation not supported (e.g., directory hardlink)
-                    1 // EPERM
-                },
-                FileSystemErrorKind::PermissionDenied => {
-                    13 // EACCES
-                },
-                _ => {
-                    // Other errors
-                    5 // EIO
-                }
-            }
-        }
+        Err(_) => usize::MAX, // Error
     }
 }
 
+/// Read a path from user space and make it absolute
+fn user_path(task: &Task, ptr: usize) -> Option<String> {
+    let path = parse_c_string_from_userspace(task, ptr, MAX_PATH_LENGTH).ok()?;
+    to_absolute_path_v2(task, &path).ok()
+}
+
 /// VFS v2 helper function for path absolutization
 /// TODO: Move this to a shared helper module when VFS v2 provides public API
 fn to_absolute_path_v2(task: &crate::task::Task, path: &str) -> Result<String, ()> {
@@ -549,10 +557,3 @@ fn to_absolute_path_v2(task: &crate::task::Task, path: &str) -> Result<String, (
         Ok(vfs.resolve_path_to_absolute(path))
     }
 }
-
-/// Helper function to replace the missing get_path_str function
-/// TODO: This should be moved to a shared helper when VFS v2 provides public API
-fn get_path_str_v2(ptr: *const u8) -> Result<String, ()> {
-    const MAX_PATH_LENGTH: usize = 128;
-    cstring_to_string(ptr, MAX_PATH_LENGTH).map(|(s, _)| s).map_err(|_| ())
-}
\ No newline at end of file
@@ -59,6 +59,21 @@ pub struct Stat {
     pub size: u64,    // Size of file in bytes
 }
 
+impl Stat {
+    pub const STAT_SIZE: usize = mem::size_of::<Stat>();
+
+    /// Convert Stat to the byte layout of `struct stat`, padding zeroed
+    pub fn to_bytes(&self) -> [u8; Self::STAT_SIZE] {
+        let mut bytes = [0u8; Self::STAT_SIZE];
+        bytes[0..4].copy_from_slice(&self.dev.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.ino.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.file_type.to_le_bytes());
+        bytes[10..12].copy_from_slice(&self.nlink.to_le_bytes());
+        bytes[16..24].copy_from_slice(&self.size.to_le_bytes());
+        bytes
+    }
+}
+
 // xv6 file type constants
 pub const T_DIR: u16 = 1;    // Directory
 pub const T_FILE: u16 = 2;   // File
@@ -7,10 +7,14 @@
 ///       0
 ///   },
 ///   SomeSyscall = 1 => sys_somecall,
+///   OtherSyscall = 2 (Int, Str) -> Int => sys_othercall,
 /// }
 /// ```
+///
+/// The optional `(args) -> ret` of an entry gives the
+/// [`ArgKind`](crate::task::strace::ArgKind)s shown when the call is traced.
 macro_rules! syscall_table {
-    ( $( $name:ident = $num:expr => $func:expr ),* $(,)? ) => {
+    ( $( $name:ident = $num:literal $( ( $($kind:ident),* ) -> $ret:ident )? => $func:expr ),* $(,)? ) => {
         #[derive(Debug)]
         pub enum Syscall {
             $(
@@ -47,5 +51,15 @@ macro_rules! syscall_table {
                 }
             }
         }
+
+        /// Describe a syscall for tracing (see `crate::task::strace`)
+        pub fn syscall_info(number: usize) -> Option<crate::task::strace::SyscallInfo> {
+            match number {
+                $(
+                    $num => Some(crate::syscall_info!($name $( ($($kind),*) -> $ret )?)),
+                )*
+                _ => None,
+            }
+        }
     };
-}
\ No newline at end of file
+}
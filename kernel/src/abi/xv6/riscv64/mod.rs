@@ -10,7 +10,7 @@ mod pipe;
 use alloc::{boxed::Box, string::{String, ToString}, sync::Arc, vec::Vec};
 use hashbrown::HashMap;
 use file::{sys_dup, sys_exec, sys_mknod, sys_open, sys_write};
-use proc::{sys_exit, sys_fork, sys_wait, sys_getpid, sys_kill, sys_sleep};
+use proc::{sys_exit, sys_fork, sys_wait, sys_getpid, sys_kill, sys_sleep, sys_uptime};
 
 use crate::{
     abi::{
@@ -19,18 +19,26 @@ use crate::{
             pipe::sys_pipe, 
             proc::{sys_chdir, sys_sbrk}
         }, 
-        AbiModule
+        AbiModule, PersonalityNode
     }, 
     arch::{self, IntRegisters}, 
     early_initcall, 
-    fs::{drivers::overlayfs::OverlayFS, FileSystemError, FileSystemErrorKind, SeekFrom, VfsManager}, 
+    fs::{drivers::overlayfs::OverlayFS, SeekFrom, VfsManager}, 
     register_abi, 
-    task::elf_loader::load_elf_into_task, 
-    vm::{setup_trampoline, setup_user_stack}
+    task::{elf_loader::load_elf_into_task, signal::copy_to_user}, 
+    vm::{aslr, setup_trampoline, setup_user_stack, vdso}
 };
 
 const MAX_FDS: usize = 1024; // Maximum number of file descriptors
 
+/// Home directories, the shared data directory and the native root at
+/// `/scarlet`; xv6 programs make their own device files with mknod
+const XV6_PERSONALITY: &[PersonalityNode] = &[
+    PersonalityNode::Bind { source: "/home", target: "/home" },
+    PersonalityNode::Bind { source: "/data/shared", target: "/data/shared" },
+    PersonalityNode::Bind { source: "/", target: "/scarlet" },
+];
+
 #[derive(Clone)]
 pub struct Xv6Riscv64Abi {
     /// File descriptor to handle mapping (fd -> handle)
@@ -151,6 +159,10 @@ impl AbiModule for Xv6Riscv64Abi {
         syscall_handler(self, trapframe)
     }
 
+    fn syscall_info(&self, number: usize) -> Option<crate::task::strace::SyscallInfo> {
+        syscall_info(number)
+    }
+
     fn can_execute_binary(
         &self, 
         file_object: &crate::object::KernelObject, 
@@ -214,7 +226,6 @@ impl AbiModule for Xv6Riscv64Abi {
                 task.text_size = 0;
                 task.data_size = 0;
                 task.stack_size = 0;
-                task.brk = None;
                 
                 // Load ELF using XV6-compatible method
                 match load_elf_into_task(file_obj, task) {
@@ -222,13 +233,15 @@ impl AbiModule for Xv6Riscv64Abi {
                         // Set the name
                         task.name = argv.get(0).map_or("xv6".to_string(), |s| s.to_string());
                         // Clear page table entries
-                        let idx = arch::vm::get_root_pagetable_ptr(task.vm_manager.get_asid()).unwrap();
+                        let idx = arch::vm::get_root_pagetable_ptr(task.vm_manager.read().get_asid()).unwrap();
                         let root_page_table = arch::vm::get_pagetable(idx).unwrap();
                         root_page_table.unmap_all();
                         // Setup the trapframe
-                        setup_trampoline(&mut task.vm_manager);
+                        setup_trampoline(&mut task.vm_manager.write());
+                        vdso::map_vdso(task)?;
                         // Setup the stack
                         let (_, stack_top) = setup_user_stack(task);
+                        aslr::randomize_task_layout(task);
                         let mut stack_pointer = stack_top as usize;
 
                         let mut arg_ptrs: Vec<u64> = Vec::new();
@@ -237,14 +250,9 @@ impl AbiModule for Xv6Riscv64Abi {
                             stack_pointer -= arg_bytes.len() + 1; // +1 for null terminator
                             stack_pointer -= stack_pointer % 16; // Align to 16 bytes
 
-                            unsafe {
-                                let translated_stack_pointer = task.vm_manager
-                                    .translate_vaddr(stack_pointer)
-                                    .unwrap();
-                                let stack_slice = core::slice::from_raw_parts_mut(translated_stack_pointer as *mut u8, arg_bytes.len() + 1);
-                                stack_slice[..arg_bytes.len()].copy_from_slice(arg_bytes);
-                                stack_slice[arg_bytes.len()] = 0; // Null terminator
-                            }
+                            // The string may cross into another stack page
+                            copy_to_user(task, stack_pointer, arg_bytes)?;
+                            copy_to_user(task, stack_pointer + arg_bytes.len(), &[0])?; // Null terminator
 
                             arg_ptrs.push(stack_pointer as u64); // Store the address of the argument
                         }
@@ -255,13 +263,8 @@ impl AbiModule for Xv6Riscv64Abi {
                         stack_pointer -= stack_pointer % 16; // Align to 16 bytes
 
                         // Push the addresses of the arguments onto the stack
-                        unsafe {
-                            let translated_stack_pointer = task.vm_manager
-                                .translate_vaddr(stack_pointer)
-                                .unwrap() as *mut u64;
-                            for (i, &arg_ptr) in arg_ptrs.iter().enumerate() {
-                                *(translated_stack_pointer.add(i)) = arg_ptr;
-                            }
+                        for (i, &arg_ptr) in arg_ptrs.iter().enumerate() {
+                            copy_to_user(task, stack_pointer + i * 8, &arg_ptr.to_le_bytes())?;
                         }
 
                         // Set the new entry point for the task
@@ -273,6 +276,8 @@ impl AbiModule for Xv6Riscv64Abi {
                         task.vcpu.set_sp(stack_pointer);
                         task.vcpu.iregs.reg[11] = stack_pointer as usize; // Set the return value (a0) to 0 in the new proc
                         task.vcpu.iregs.reg[10] = argc; // Set argc in a0
+                        // Point tp at the TLS block of the program
+                        task.setup_tls()?;
 
                         // Switch to the new task
                         task.vcpu.switch(trapframe);
@@ -319,62 +324,12 @@ impl AbiModule for Xv6Riscv64Abi {
         }
     }
     
-    fn setup_shared_resources(
-        &self,
-        target_vfs: &Arc<VfsManager>,
-        base_vfs: &Arc<VfsManager>,
-    ) -> Result<(), &'static str> {
-        // crate::println!("Setting up XV6 shared resources with base VFS");
-        // XV6 shared resource setup: bind mount common directories and Scarlet gateway
-        match create_dir_if_not_exists(target_vfs, "/home") {
-            Ok(()) => {}
-            Err(e) => {
-                // crate::println!("Failed to create /home directory for XV6: {}", e.message);
-                return Err("Failed to create /home directory for XV6");
-            }
-        }
-
-        match target_vfs.bind_mount_from(base_vfs, "/home", "/home") {
-            Ok(()) => {}
-            Err(e) => {
-                // crate::println!("Failed to bind mount /home for XV6: {}", e.message);
-            }
-        }
-
-        match create_dir_if_not_exists(target_vfs, "/data") {
-            Ok(()) => {}
-            Err(e) => {
-                crate::println!("Failed to create /data directory for XV6: {}", e.message);
-                return Err("Failed to create /data directory for XV6");
-            }
-        }
-
-        match target_vfs.bind_mount_from(base_vfs, "/data/shared", "/data/shared") {
-            Ok(()) => {}
-            Err(e) => {
-                // crate::println!("Failed to bind mount /data/shared for XV6: {}", e.message);
-            }
-        }
-
-        // Setup gateway to native Scarlet environment (read-only for security)
-        match create_dir_if_not_exists(target_vfs, "/scarlet") {
-            Ok(()) => {}
-            Err(e) => {
-                crate::println!("Failed to create /scarlet directory for XV6: {}", e.message);
-                return Err("Failed to create /scarlet directory for XV6");
-            }
-        }
-        match target_vfs.bind_mount_from(base_vfs, "/", "/scarlet") {
-            Ok(()) => Ok(()),
-            Err(e) => {
-                crate::println!("Failed to bind mount native Scarlet root to /scarlet for XV6: {}", e.message);
-                return Err("Failed to bind mount native Scarlet root to /scarlet for XV6");
-            }
-        }
+    fn personality_nodes(&self) -> &'static [PersonalityNode] {
+        XV6_PERSONALITY
     }
 
     fn initialize_from_existing_handles(&mut self, task: &mut crate::task::Task) -> Result<(), &'static str> {
-        task.handle_table.close_all();
+        task.handle_table.write().close_all();
         Ok(())
     }
     
@@ -396,40 +351,27 @@ syscall_table! {
     Invalid = 0 => |_abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, _trapframe: &mut crate::arch::Trapframe| {
         0
     },
-    Fork = 1 => sys_fork,
-    Exit = 2 => sys_exit,
-    Wait = 3 => sys_wait,
-    Pipe = 4 => sys_pipe,
-    Read = 5 => sys_read,
-    //    Kill = 6 => sys_kill,
-    Exec = 7 => sys_exec,
-    Fstat = 8 => sys_fstat,
-    Chdir = 9 => sys_chdir,
-    Dup = 10 => sys_dup,
-    Getpid = 11 => sys_getpid,
-    Sbrk = 12 => sys_sbrk,
-    Sleep = 13 => sys_sleep,
-    // Uptime = 14 => sys_uptime,
-    Open = 15 => sys_open,
-    Write = 16 => sys_write,
-    Mknod = 17 => sys_mknod,
-    Unlink = 18 => sys_unlink,
-    Link = 19 => sys_link,
-    Mkdir = 20 => sys_mkdir,
-    Close = 21 => sys_close,
-}
-
-fn create_dir_if_not_exists(vfs: &Arc<VfsManager>, path: &str) -> Result<(), FileSystemError> {
-    match vfs.create_dir(path) {
-        Ok(()) => Ok(()),
-        Err(e) => {
-            if e.kind == FileSystemErrorKind::AlreadyExists {
-                Ok(()) // Directory already exists, nothing to do
-            } else {
-                Err(e) // Some other error occurred
-            }
-        }
-    }
+    Fork = 1 () -> Int => sys_fork,
+    Exit = 2 (Int) -> Int => sys_exit,
+    Wait = 3 (Hex) -> Int => sys_wait,
+    Pipe = 4 (Hex) -> Int => sys_pipe,
+    Read = 5 (Int, Hex, Uint) -> Int => sys_read,
+    Kill = 6 (Int) -> Int => sys_kill,
+    Exec = 7 (Str, StrArray) -> Int => sys_exec,
+    Fstat = 8 (Int, Hex) -> Int => sys_fstat,
+    Chdir = 9 (Str) -> Int => sys_chdir,
+    Dup = 10 (Int) -> Int => sys_dup,
+    Getpid = 11 () -> Int => sys_getpid,
+    Sbrk = 12 (Int) -> Hex => sys_sbrk,
+    Sleep = 13 (Int) -> Int => sys_sleep,
+    Uptime = 14 () -> Int => sys_uptime,
+    Open = 15 (Str, Hex) -> Int => sys_open,
+    Write = 16 (Int, Buf, Uint) -> Int => sys_write,
+    Mknod = 17 (Str, Int, Int) -> Int => sys_mknod,
+    Unlink = 18 (Str) -> Int => sys_unlink,
+    Link = 19 (Str, Str) -> Int => sys_link,
+    Mkdir = 20 (Str) -> Int => sys_mkdir,
+    Close = 21 (Int) -> Int => sys_close,
 }
 
 fn register_xv6_abi() {
@@ -1,35 +1,57 @@
-use crate::{arch::Trapframe, ipc::UnidirectionalPipe, task::mytask};
+use crate::{arch::Trapframe, ipc::UnidirectionalPipe, task::{mytask, signal::copy_to_user}};
 
 pub fn sys_pipe(abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
     trapframe.increment_pc_next(task);
 
-    let pipefd_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(0))
-        .expect("Invalid pipefd pointer");
-    let pipefd = unsafe { &mut *(pipefd_ptr as *mut [u32; 2]) };
+    let pipefd_ptr = trapframe.get_arg(0);
 
     let (read_end, write_end) = UnidirectionalPipe::create_pair(4096);
 
-    let read_handle = task.handle_table.insert(read_end).expect("Failed to insert read end");
-    let write_handle = task.handle_table.insert(write_end).expect("Failed to insert write end");
+    let read_handle = match task.handle_table.write().insert(read_end) {
+        Ok(handle) => handle,
+        Err(_) => return usize::MAX, // Handle table full
+    };
+    let inserted = task.handle_table.write().insert(write_end);
+    let write_handle = match inserted {
+        Ok(handle) => handle,
+        Err(_) => {
+            task.handle_table.write().remove(read_handle);
+            return usize::MAX; // Handle table full
+        }
+    };
 
     // Allocate XV6 file descriptors and store them in the array
     let read_fd = match abi.allocate_fd(read_handle as u32) {
         Ok(fd) => fd,
-        Err(_) => return usize::MAX, // Too many open files
+        Err(_) => {
+            task.handle_table.write().remove(read_handle);
+            task.handle_table.write().remove(write_handle);
+            return usize::MAX; // Too many open files
+        }
     };
     let write_fd = match abi.allocate_fd(write_handle as u32) {
         Ok(fd) => fd,
         Err(_) => {
             // Clean up the read_fd allocation if write_fd fails
             abi.remove_fd(read_fd);
-            task.handle_table.remove(read_handle);
+            task.handle_table.write().remove(read_handle);
+            task.handle_table.write().remove(write_handle);
             return usize::MAX; // Too many open files
         }
     };
 
-    pipefd[0] = read_fd as u32;
-    pipefd[1] = write_fd as u32;
+    let mut pipefd = [0u8; 8];
+    pipefd[0..4].copy_from_slice(&(read_fd as u32).to_le_bytes());
+    pipefd[4..8].copy_from_slice(&(write_fd as u32).to_le_bytes());
+    if copy_to_user(task, pipefd_ptr, &pipefd).is_err() {
+        // Nothing is left open if the descriptors cannot be reported
+        abi.remove_fd(read_fd);
+        abi.remove_fd(write_fd);
+        task.handle_table.write().remove(read_handle);
+        task.handle_table.write().remove(write_handle);
+        return usize::MAX; // Bad pipefd pointer
+    }
 
     0
-}
\ No newline at end of file
+}
@@ -1,12 +1,16 @@
 use alloc::string::{String, ToString};
 use crate::{
-    arch::{get_cpu, Trapframe}, 
+    arch::Trapframe, 
     fs::FileType, 
-    library::std::string::cstring_to_string,
+    library::std::string::parse_c_string_from_userspace,
     sched::scheduler::get_scheduler, 
-    task::{get_parent_waitpid_waker, mytask, CloneFlags, WaitError}
+    task::{get_parent_waitpid_waker, mytask, signal::{copy_to_user, interrupt_sleep, interrupt_syscall, send_signal, SIGKILL}, CloneFlags, WaitError},
+    timer::{get_time_us, us_to_ticks},
 };
 
+/// Length of an xv6 clock tick, about 1/10th second as on QEMU
+const XV6_TICK_US: u64 = 100_000;
+
 /// VFS v2 helper function for path absolutization using VfsManager
 fn to_absolute_path_v2(task: &crate::task::Task, path: &str) -> Result<String, ()> {
     if path.starts_with('/') {
@@ -17,12 +21,6 @@ fn to_absolute_path_v2(task: &crate::task::Task, path: &str) -> Result<String, (
     }
 }
 
-/// Helper function to replace the missing get_path_str function
-/// TODO: This should be moved to a shared helper when VFS v2 provides public API
-fn get_path_str_v2(ptr: *const u8) -> Result<String, ()> {
-    const MAX_PATH_LENGTH: usize = 128;
-    cstring_to_string(ptr, MAX_PATH_LENGTH).map(|(s, _)| s).map_err(|_| ())
-}
 
 pub fn sys_fork(_abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &mut Trapframe) -> usize {
     let parent_task = mytask().unwrap();
@@ -37,7 +35,8 @@ pub fn sys_fork(_abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &
         Ok(mut child_task) => {
             let child_id = child_task.get_id();
             child_task.vcpu.iregs.reg[10] = 0; /* Set the return value (a0) to 0 in the child proc */
-            get_scheduler().add_task(child_task, get_cpu().get_cpuid());
+            let cpu_id = get_scheduler().select_cpu(child_task.cpu_affinity);
+            get_scheduler().add_task(child_task, cpu_id);
             /* Return the child task ID as pid to the parent proc */
             child_id
         },
@@ -58,22 +57,25 @@ pub fn sys_exit(_abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &
 
 pub fn sys_wait(_abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
-    let status_ptr = trapframe.get_arg(0) as *mut i32;
+    let status_ptr = trapframe.get_arg(0);
 
     // Loop until a child exits or an error occurs
     loop {
+        // Without children nothing can ever be waited for
+        if task.get_children().is_empty() {
+            trapframe.increment_pc_next(task);
+            return usize::MAX;
+        }
+
         // Wait for any child process
         for child_pid in task.get_children().clone() {
             match task.wait(child_pid) {
                 Ok(status) => {
                     // Child has exited, return the status
-                    if status_ptr != core::ptr::null_mut() {
-                        let status_ptr = task.vm_manager.translate_vaddr(status_ptr as usize).unwrap() as *mut i32;
-                        unsafe {
-                            *status_ptr = status;
-                        }
-                    }
                     trapframe.increment_pc_next(task);
+                    if status_ptr != 0 && copy_to_user(task, status_ptr, &status.to_le_bytes()).is_err() {
+                        return usize::MAX;
+                    }
                     return child_pid;
                 },
                 Err(error) => {
@@ -89,6 +91,10 @@ pub fn sys_wait(_abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &
         }
         
         // No child has exited yet, block until one does
+        if task.signals.has_pending() {
+            trapframe.increment_pc_next(task);
+            return interrupt_syscall(task, trapframe);
+        }
         let parent_waker = get_parent_waitpid_waker(task.get_id());
         parent_waker.wait(task.get_id(), trapframe);
         // Continue the loop to re-check after waking up
@@ -99,32 +105,28 @@ pub fn sys_wait(_abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &
 pub fn sys_kill(_abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
     let pid = trapframe.get_arg(0) as usize;
-    let signal = trapframe.get_arg(1) as i32;
 
     trapframe.increment_pc_next(task);
 
-    // For xv6 compatibility, only signal 9 (SIGKILL) is implemented for now
-    if signal != 9 {
-        return usize::MAX; // -1 (unsupported signal)
-    }
-
-    // Find the target task via scheduler
-    let scheduler = get_scheduler();
-    if let Some(target_task) = scheduler.get_task_by_id(pid) {
-        // For xv6 compatibility, immediately terminate the target task
-        target_task.exit(9); // SIGKILL equivalent - exit with signal 9
-        0 // Success
-    } else {
-        usize::MAX // -1 (no such process)
+    // xv6 kill(pid) has no signal number: the target is always killed, and
+    // terminates when it next returns to user space
+    match send_signal(pid, SIGKILL) {
+        Ok(()) => 0,
+        Err(_) => usize::MAX, // -1 (no such process)
     }
 }
 
 pub fn sys_sbrk(_abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
-    let increment = trapframe.get_arg(0);
+    // The increment is a C int, so a negative value shrinks the heap
+    let increment = trapframe.get_arg(0) as i32 as isize;
     let brk = task.get_brk();
     trapframe.increment_pc_next(task);
-    match task.set_brk(unsafe { brk.unchecked_add(increment) }) {
+    let new_brk = match brk.checked_add_signed(increment) {
+        Some(new_brk) => new_brk,
+        None => return usize::MAX, /* -1 */
+    };
+    match task.set_brk(new_brk) {
         Ok(_) => brk,
         Err(_) => usize::MAX, /* -1 */
     }
@@ -134,8 +136,7 @@ pub fn sys_chdir(_abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe:
     let task = mytask().unwrap();
     trapframe.increment_pc_next(task);
     
-    let path_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(0) as usize).unwrap() as *const u8;
-    let path = match get_path_str_v2(path_ptr) {
+    let path = match parse_c_string_from_userspace(task, trapframe.get_arg(0), 128) {
         Ok(p) => match to_absolute_path_v2(&task, &p) {
             Ok(abs_path) => abs_path,
             Err(_) => return usize::MAX,
@@ -173,15 +174,29 @@ pub fn sys_getpid(_abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe:
 }
 
 pub fn sys_sleep(_abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &mut Trapframe) -> usize {
-    let ticks = trapframe.get_arg(0) as u64;
+    let xv6_ticks = trapframe.get_arg(0) as i32;
     let task = mytask().unwrap();
 
     // Increment PC before sleeping to avoid infinite loop
     trapframe.increment_pc_next(task);
 
     // Call the blocking sleep method - this will return when sleep completes
-    task.sleep(trapframe, ticks);
+    // or a signal arrives, in which case it goes on for the rest of the time
+    // if restarted
+    let duration_us = xv6_ticks.max(0) as u64 * XV6_TICK_US;
+    let deadline = get_time_us().saturating_add(duration_us);
+    if !task.sleep(trapframe, us_to_ticks(duration_us)) {
+        let remaining = deadline.saturating_sub(get_time_us()).div_ceil(XV6_TICK_US);
+        return interrupt_sleep(task, remaining as usize);
+    }
 
     // Set return value to 0 for successful sleep
     0
-}
\ No newline at end of file
+}
+
+/// Number of xv6 clock ticks since boot
+pub fn sys_uptime(_abi: &mut crate::abi::xv6::riscv64::Xv6Riscv64Abi, trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    trapframe.increment_pc_next(task);
+    (get_time_us() / XV6_TICK_US) as usize
+}
@@ -1,6 +1,6 @@
-use core::{arch::naked_asm, mem::transmute};
+use core::{arch::naked_asm, mem::transmute, sync::atomic::{fence, Ordering}};
 
-use crate::{arch::{Riscv64, riscv64::{CPUS, trap_init}}, device::fdt::{init_fdt, relocate_fdt, create_bootinfo_from_fdt}, environment::STACK_SIZE, mem::{__FDT_RESERVED_START, init_bss}, start_kernel};
+use crate::{arch::{Riscv64, instruction::sbi::sbi_hart_start, riscv64::{CPUS, trap_init}}, device::fdt::{init_fdt, relocate_fdt, create_bootinfo_from_fdt}, environment::{NUM_OF_CPUS, STACK_SIZE}, mem::{__FDT_RESERVED_START, init_bss}, start_ap, start_kernel};
 
 /// Entry point for the primary core
 #[unsafe(link_section = ".init")]
@@ -13,11 +13,11 @@ pub extern "C" fn _entry() {
         .option norvc
         .option norelax
         .align 8
-                // a0 = hartid     
+                // a0 = hartid
+                // sp = KERNEL_STACK + STACK_SIZE * (hartid + 1)
                 li      t0, {}
-                mv      t1, a0
-                addi    t1, t1, 1
-                mul     t1, t1, a0          
+                addi    t1, a0, 1
+                mul     t0, t0, t1
                 la      sp, KERNEL_STACK
                 add     sp, sp, t0
 
@@ -39,16 +39,16 @@ pub extern "C" fn _entry_ap() {
         .option norvc
         .option norelax
         .align 8
-                // a0 = hartid     
+                // a0 = hartid
+                // sp = KERNEL_STACK + STACK_SIZE * (hartid + 1)
                 li      t0, {}
-                mv      t1, a0
-                addi    t1, t1, 1
-                mul     t1, t1, a0          
+                addi    t1, a0, 1
+                mul     t0, t0, t1
                 la      sp, KERNEL_STACK
                 add     sp, sp, t0
 
                 // Use indirect jump to avoid JAL range limitation
-                la      t0, start_ap
+                la      t0, arch_start_ap
                 jr      t0
         ", const STACK_SIZE
         );
@@ -75,7 +75,34 @@ pub extern "C" fn arch_start_kernel(hartid: usize, fdt_ptr: usize) {
     crate::early_println!("Hart {}: Initializing core....", hartid);
     // Get raw Riscv64 struct
     let riscv: &mut Riscv64 = unsafe { transmute(&CPUS[hartid] as *const _ as usize ) };
+    riscv.hartid = hartid as u64;
     trap_init(riscv);
 
     start_kernel(&bootinfo);
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn arch_start_ap(hartid: usize) {
+    crate::early_println!("Hart {}: Initializing core....", hartid);
+    // Get raw Riscv64 struct
+    let riscv: &mut Riscv64 = unsafe { transmute(&CPUS[hartid] as *const _ as usize ) };
+    riscv.hartid = hartid as u64;
+    trap_init(riscv);
+
+    start_ap(hartid);
+}
+
+/// Start the secondary harts
+///
+/// Each hart is started through the SBI HSM extension at `_entry_ap`, which
+/// continues in `start_ap` on the hart's own boot stack. Harts the platform
+/// does not have are skipped.
+pub fn start_secondary_harts(boot_hartid: usize) {
+    // Everything the boot hart has set up must be visible to the others
+    fence(Ordering::SeqCst);
+    for hartid in (0..NUM_OF_CPUS).filter(|&hartid| hartid != boot_hartid) {
+        if sbi_hart_start(hartid, _entry_ap as usize, 0).is_ok() {
+            crate::early_println!("Hart {}: Starting...", hartid);
+        }
+    }
 }
\ No newline at end of file
@@ -1 +1,3 @@
-mod entry;
\ No newline at end of file
+mod entry;
+
+pub use entry::start_secondary_harts;
@@ -4,9 +4,19 @@ pub mod sbi;
 
 pub fn idle() -> ! {
     loop {
-        unsafe {
-            asm!("wfi", options(nostack));
-        }
+        wait_for_interrupt();
+    }
+}
+
+/// Stall the hart until an interrupt is pending
+///
+/// Returns when an interrupt enabled in sie becomes pending, even if
+/// interrupts are disabled globally; the interrupt is then taken once they
+/// are enabled again. It may also return spuriously.
+#[inline]
+pub fn wait_for_interrupt() {
+    unsafe {
+        asm!("wfi", options(nostack));
     }
 }
 
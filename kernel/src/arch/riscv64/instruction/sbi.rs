@@ -78,6 +78,46 @@ pub fn sbi_call(extension: Extension, function: usize, arg0: usize, arg1: usize)
     }
 }
 
+/// Base extension functions
+pub mod base {
+    pub const GET_SPEC_VERSION: usize = 0;
+    pub const GET_IMPL_ID: usize = 1;
+    pub const GET_IMPL_VERSION: usize = 2;
+    pub const PROBE_EXTENSION: usize = 3;
+    pub const GET_MVENDORID: usize = 4;
+    pub const GET_MARCHID: usize = 5;
+    pub const GET_MIMPID: usize = 6;
+}
+
+/// Version of the SBI specification the firmware implements, as
+/// `(major, minor)`
+pub fn sbi_get_spec_version() -> (usize, usize) {
+    // SBI v0.1 has no Base extension
+    let version = sbi_call(Extension::Base, base::GET_SPEC_VERSION, 0, 0).unwrap_or(1);
+    ((version >> 24) & 0x7f, version & 0xff_ffff)
+}
+
+/// ID of the SBI implementation, 1 for OpenSBI
+pub fn sbi_get_impl_id() -> Option<usize> {
+    sbi_call(Extension::Base, base::GET_IMPL_ID, 0, 0).ok()
+}
+
+/// Version of the SBI implementation, in an encoding of its own
+pub fn sbi_get_impl_version() -> Option<usize> {
+    sbi_call(Extension::Base, base::GET_IMPL_VERSION, 0, 0).ok()
+}
+
+/// Whether the firmware implements the extension `extension_id`
+pub fn sbi_probe_extension(extension_id: usize) -> bool {
+    matches!(sbi_call(Extension::Base, base::PROBE_EXTENSION, extension_id, 0), Ok(value) if value != 0)
+}
+
+/// The `mvendorid`, `marchid` and `mimpid` CSRs of the calling hart
+pub fn sbi_get_machine_ids() -> (usize, usize, usize) {
+    let read = |function| sbi_call(Extension::Base, function, 0, 0).unwrap_or(0);
+    (read(base::GET_MVENDORID), read(base::GET_MARCHID), read(base::GET_MIMPID))
+}
+
 pub fn sbi_console_putchar(c: char) {
     let _ = sbi_call(Extension::ConsolePutChar, 0, c as usize, 0);
 }
@@ -94,6 +134,40 @@ pub fn sbi_set_timer(stime_value: u64) {
     let _ = sbi_call(Extension::Timer, 0, stime_value as usize, 0);
 }
 
+/// Start `hartid` at `start_addr` in supervisor mode (HSM `hart_start`)
+///
+/// The hart enters `start_addr` with its hart ID in a0 and `opaque` in a1,
+/// with the MMU off.
+pub fn sbi_hart_start(hartid: usize, start_addr: usize, opaque: usize) -> Result<(), SbiError> {
+    let error: usize;
+
+    unsafe {
+        asm!(
+            "ecall",
+            inout("a0") hartid => error,
+            inout("a1") start_addr => _,
+            inout("a2") opaque => _,
+            inout("a6") 0 => _,
+            inout("a7") Extension::Hsm as usize => _,
+            clobber_abi("C"),
+            options(nostack),
+        );
+    }
+
+    match error {
+        0 => Ok(()),
+        error_code if (error_code as isize) >= -8 => Err(SbiError::from_error(error_code)),
+        _ => Err(SbiError::Failed),
+    }
+}
+
+/// Raise a supervisor software interrupt on the harts in `hart_mask`
+///
+/// Bit `n` of `hart_mask` selects hart `hart_mask_base + n`.
+pub fn sbi_send_ipi(hart_mask: usize, hart_mask_base: usize) -> Result<(), SbiError> {
+    sbi_call(Extension::Ipi, 0, hart_mask, hart_mask_base).map(|_| ())
+}
+
 pub fn sbi_system_reset(reset_type: u32, reset_reason: u32) -> ! {
     let _ = sbi_call(Extension::Srst, 0, reset_type as usize, reset_reason as usize);
     loop {}
@@ -111,6 +111,27 @@ pub fn disable_software_interrupts() {
     }
 }
 
+/// Send an inter-processor interrupt to `cpu_id`
+///
+/// The target takes a supervisor software interrupt, or leaves `wfi` if it
+/// has software interrupts enabled in sie.
+pub fn send_ipi(cpu_id: usize) {
+    let _ = super::instruction::sbi::sbi_send_ipi(1, cpu_id);
+}
+
+/// Acknowledge a pending software interrupt
+///
+/// Clears the SSIP (Supervisor Software Interrupt Pending) bit in the sip register.
+pub fn clear_software_interrupt() {
+    unsafe {
+        core::arch::asm!(
+            "csrc sip, {0}",
+            in(reg) 1 << 1, // Clear SSIP bit
+            options(nostack)
+        );
+    }
+}
+
 /// Check if software interrupts are enabled
 /// 
 /// Returns true if the SSIE (Supervisor Software Interrupt Enable) bit is set in the sie register.
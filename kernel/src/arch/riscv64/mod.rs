@@ -5,7 +5,7 @@ use trap::kernel::arch_kernel_trap_handler;
 use trap::kernel::_kernel_trap_entry;
 use trap::user::_user_trap_entry;
 use trap::user::arch_user_trap_handler;
-use vcpu::Mode;
+use vcpu::{Mode, Xlen};
 
 use crate::arch::instruction::Instruction;
 use crate::arch::vm::get_root_pagetable;
@@ -128,7 +128,7 @@ impl Trapframe {
     /// 
     pub fn increment_pc_next(&mut self, task: &Task) {
         let instruction = Instruction::fetch(
-            task.vm_manager.translate_vaddr(self.epc as usize).unwrap()
+            task.vm_manager.read().translate_vaddr(self.epc as usize).unwrap()
         );
         let len = instruction.len();
         if len == 0 {
@@ -223,6 +223,12 @@ pub fn disable_interrupt() {
     }
 }
 
+/// Get the CPU structure of `cpu_id` by its physical address
+#[allow(static_mut_refs)]
+pub fn get_paddr_cpu(cpu_id: usize) -> &'static mut Riscv64 {
+    unsafe { &mut CPUS[cpu_id] }
+}
+
 pub fn get_cpu() -> &'static mut Riscv64 {
     let scratch: usize;
 
@@ -271,6 +277,61 @@ pub fn set_next_mode(mode: Mode) {
     }
 }
 
+/// sstatus.UXL: the register width of U-mode (1 = 32-bit, 2 = 64-bit)
+const SSTATUS_UXL_SHIFT: usize = 32;
+const SSTATUS_UXL_MASK: usize = 0b11 << SSTATUS_UXL_SHIFT;
+
+fn uxl_bits(xlen: Xlen) -> usize {
+    match xlen {
+        Xlen::X32 => 1 << SSTATUS_UXL_SHIFT,
+        Xlen::X64 => 2 << SSTATUS_UXL_SHIFT,
+    }
+}
+
+/// Set the register width the next return to U-mode runs with
+pub fn set_user_xlen(xlen: Xlen) {
+    unsafe {
+        let mut sstatus: usize;
+        asm!(
+            "csrr {sstatus}, sstatus",
+            sstatus = out(reg) sstatus,
+        );
+        if sstatus & SSTATUS_UXL_MASK == uxl_bits(xlen) {
+            return;
+        }
+        sstatus = (sstatus & !SSTATUS_UXL_MASK) | uxl_bits(xlen);
+        asm!(
+            "csrw sstatus, {sstatus}",
+            sstatus = in(reg) sstatus,
+        );
+    }
+}
+
+/// Whether the hart can run U-mode with the given register width
+///
+/// UXL is WARL: a hart that only supports 64-bit U-mode keeps the field at
+/// 2 whatever is written to it, so the value is written and read back.
+pub fn user_xlen_supported(xlen: Xlen) -> bool {
+    unsafe {
+        let saved: usize;
+        asm!(
+            "csrr {sstatus}, sstatus",
+            sstatus = out(reg) saved,
+        );
+        let probe = (saved & !SSTATUS_UXL_MASK) | uxl_bits(xlen);
+        let readback: usize;
+        asm!(
+            "csrw sstatus, {probe}",
+            "csrr {readback}, sstatus",
+            "csrw sstatus, {saved}",
+            probe = in(reg) probe,
+            readback = out(reg) readback,
+            saved = in(reg) saved,
+        );
+        readback & SSTATUS_UXL_MASK == uxl_bits(xlen)
+    }
+}
+
 pub fn shutdown() -> ! {
     sbi_system_reset(0, 0);
 }
@@ -282,4 +343,59 @@ pub fn shutdown_with_code(exit_code: u32) -> ! {
 
 pub fn reboot() -> ! {
     sbi_system_reset(1, 0);
-}
\ No newline at end of file
+}
+/// The SBI firmware the kernel runs on
+pub fn firmware_info() -> crate::device::board::FirmwareInfo {
+    use alloc::format;
+    use instruction::sbi::*;
+
+    // Extensions worth telling about, with the names the specification gives
+    const EXTENSIONS: [(usize, &str); 6] = [
+        (Extension::Timer as usize, "time"),
+        (Extension::Ipi as usize, "ipi"),
+        (Extension::Rfence as usize, "rfence"),
+        (Extension::Hsm as usize, "hsm"),
+        (Extension::Srst as usize, "srst"),
+        (Extension::Pmu as usize, "pmu"),
+    ];
+
+    let (major, minor) = sbi_get_spec_version();
+    let impl_id = sbi_get_impl_id();
+    let name = match impl_id {
+        Some(0) => "BBL".into(),
+        Some(1) => "OpenSBI".into(),
+        Some(2) => "Xvisor".into(),
+        Some(3) => "KVM".into(),
+        Some(4) => "RustSBI".into(),
+        Some(5) => "Diosix".into(),
+        Some(6) => "Coffer".into(),
+        Some(7) => "Xen".into(),
+        Some(8) => "PolarFire HSS".into(),
+        Some(9) => "coreboot".into(),
+        Some(10) => "oreboot".into(),
+        Some(11) => "bhyve".into(),
+        Some(id) => format!("unknown ({})", id),
+        None => "unknown".into(),
+    };
+    let version = match (impl_id, sbi_get_impl_version()) {
+        // OpenSBI puts the major version in the upper 16 bits
+        (Some(1), Some(version)) => format!("{}.{}", version >> 16, version & 0xffff),
+        (_, Some(version)) => format!("{:#x}", version),
+        (_, None) => "unknown".into(),
+    };
+    let (vendor_id, arch_id, machine_impl_id) = sbi_get_machine_ids();
+    crate::device::board::FirmwareInfo {
+        interface: format!("SBI {}.{}", major, minor),
+        name,
+        version,
+        extensions: EXTENSIONS.into_iter()
+            .filter(|&(extension, _)| sbi_probe_extension(extension))
+            .map(|(_, name)| name)
+            .collect(),
+        machine_ids: alloc::vec![
+            ("mvendorid", vendor_id),
+            ("marchid", arch_id),
+            ("mimpid", machine_impl_id),
+        ],
+    }
+}
@@ -6,7 +6,8 @@ use crate::arch::trap::print_traplog;
 use crate::arch::{Trapframe, get_cpu};
 use crate::println;
 use crate::sched::scheduler::get_scheduler;
-use crate::task::mytask;
+use crate::task::{cgroup, mytask, TaskType};
+use crate::task::signal::{self, SIGBUS, SIGILL, SIGSEGV, SIGTRAP};
 
 pub fn arch_exception_handler(trapframe: &mut Trapframe, cause: usize) {
     match cause {
@@ -28,15 +29,9 @@ pub fn arch_exception_handler(trapframe: &mut Trapframe, cause: usize) {
         /* Instruction page fault */
         12 => {
             let mut vaddr = trapframe.epc as usize;
-            let task = get_scheduler().get_current_task(get_cpu().get_cpuid()).unwrap();
-            let manager = &mut task.vm_manager;
             loop {
-                match manager.lazy_map_page(vaddr) {
-                    Ok(_) => (),
-                    Err(_) => {
-                        print_traplog(trapframe);
-                        panic!("Failed to map page for instruction page fault at vaddr: {:#x}", vaddr);
-                    }
+                if !handle_user_page_fault(trapframe, vaddr, "instruction", false) {
+                    return;
                 }
 
                 if vaddr & 0b11 == 0 {
@@ -48,19 +43,14 @@ pub fn arch_exception_handler(trapframe: &mut Trapframe, cause: usize) {
         }
         /* Load/Store page fault */
         13 | 15 => {
+            let write = cause == 15;
             let mut vaddr;
             unsafe {
                 asm!("csrr {}, stval", out(reg) vaddr);
             }
-            let task = get_scheduler().get_current_task(get_cpu().get_cpuid()).unwrap();
-            let manager = &mut task.vm_manager;
             loop {
-                match manager.lazy_map_page(vaddr) {
-                    Ok(_) => (),
-                    Err(_) => {
-                        print_traplog(trapframe);
-                        panic!("Failed to map page for load/store page fault at vaddr: {:#x}", vaddr);
-                    }
+                if !handle_user_page_fault(trapframe, vaddr, "load/store", write) {
+                    return;
                 }
 
                 if vaddr & 0b11 == 0 {
@@ -71,9 +61,71 @@ pub fn arch_exception_handler(trapframe: &mut Trapframe, cause: usize) {
             }
         },
         _ => {
-            print_traplog(trapframe);
-            panic!("Unhandled exception: {}", cause);
-            
+            let task = get_scheduler().get_current_task(get_cpu().get_cpuid()).unwrap();
+            match exception_signal(cause) {
+                Some(sig) if task.task_type == TaskType::User => {
+                    // A handler runs on the way back to user space
+                    if task.signals.post_fault(sig) {
+                        return;
+                    }
+                    println!("[Task {}] Unhandled exception {}, terminating with signal {}", task.get_id(), cause, sig);
+                    print_traplog(trapframe);
+                    task.vcpu.store(trapframe);
+                    signal::terminate(task, trapframe, sig);
+                }
+                _ => {
+                    print_traplog(trapframe);
+                    panic!("Unhandled exception: {}", cause);
+                }
+            }
         }
     }
-}
\ No newline at end of file
+}
+
+/// Resolve a user page fault at `vaddr`
+///
+/// The page is mapped lazily from the task's memory maps; a fault just below
+/// the user stack grows the stack. A write fault gives an untouched
+/// anonymous page its own frame, charged to the task's resource group. If
+/// the fault cannot be resolved the task gets SIGSEGV: its handler runs if
+/// it has one, otherwise the task is terminated.
+///
+/// # Returns
+/// `true` if the page is now mapped, `false` if it was not
+fn handle_user_page_fault(trapframe: &mut Trapframe, vaddr: usize, kind: &str, write: bool) -> bool {
+    let task = get_scheduler().get_current_task(get_cpu().get_cpuid()).unwrap();
+    let map_page = |task: &mut crate::task::Task| if write {
+        task.vm_manager.write().lazy_map_page_for_write(vaddr)
+    } else {
+        task.vm_manager.write().lazy_map_page(vaddr)
+    };
+    if map_page(task).is_ok() || task.grow_stack(vaddr).is_ok() && map_page(task).is_ok() {
+        // A new frame may have taken a resource group over its ceiling
+        cgroup::enforce_memory_limits();
+        return true;
+    }
+    if task.signals.post_fault(SIGSEGV) {
+        return false;
+    }
+
+    println!("[Task {}] Segmentation fault ({} page fault at vaddr: {:#x})", task.get_id(), kind, vaddr);
+    print_traplog(trapframe);
+    task.vcpu.store(trapframe);
+    signal::terminate(task, trapframe, SIGSEGV);
+    false
+}
+
+/// Signal sent to a user task for an exception the kernel cannot resolve
+fn exception_signal(cause: usize) -> Option<usize> {
+    match cause {
+        /* Illegal instruction */
+        2 => Some(SIGILL),
+        /* Breakpoint */
+        3 => Some(SIGTRAP),
+        /* Misaligned instruction fetch, load or store */
+        0 | 4 | 6 => Some(SIGBUS),
+        /* Instruction, load or store access fault */
+        1 | 5 | 7 => Some(SIGSEGV),
+        _ => None,
+    }
+}
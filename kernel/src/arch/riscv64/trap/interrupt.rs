@@ -17,11 +17,12 @@ pub fn arch_interrupt_handler(trapframe: &mut Trapframe, cause: usize) {
 }
 
 /// Handle software interrupt (IPI)
-/// TODO: Implement inter-processor interrupt handling
+///
+/// IPIs only wake an idle CPU (see [`crate::sched::scheduler::Scheduler::kick_cpu`]);
+/// the woken CPU looks for work itself, so there is nothing to do but
+/// acknowledge it.
 fn handle_software_interrupt() {
-    crate::early_println!("[interrupt] Software interrupt received - TODO: implement IPI");
-    // TODO: CLINT software interrupt handling
-    // TODO: Inter-processor interrupt (IPI) support
+    crate::arch::interrupt::clear_software_interrupt();
 }
 
 /// Handle timer interrupt from CLINT
@@ -35,9 +36,7 @@ fn handle_external_interrupt(trapframe: &mut Trapframe) {
     let cpu_id = get_cpu().get_cpuid() as u32;
 
     // Claim and handle external interrupt through PLIC
-    match InterruptManager::with_manager(|mgr| {
-        mgr.claim_and_handle_external_interrupt(cpu_id)
-    }) {
+    match InterruptManager::dispatch_external_interrupt(cpu_id) {
         Ok(Some(interrupt_id)) => {
             // crate::early_println!("[interrupt] Handled external interrupt {} on CPU {}", interrupt_id, cpu_id);
         }
@@ -5,6 +5,9 @@ use super::exception::arch_exception_handler;
 use super::interrupt::arch_interrupt_handler;
 
 use crate::arch::{Trapframe, get_kernel_trapvector_paddr, set_trapvector};
+use crate::task::{mytask, TaskType};
+use crate::timer::get_time_us;
+use crate::task::signal::deliver_signals;
 
 #[unsafe(link_section = ".trampoline.text")]
 #[unsafe(export_name = "_user_trap_entry")]
@@ -177,6 +180,9 @@ pub extern "C" fn _user_trap_exit(trapframe: &mut Trapframe) -> ! {
 pub extern "C" fn arch_user_trap_handler(addr: usize) -> ! {
     let trapframe: &mut Trapframe = unsafe { transmute(addr) };
     set_trapvector(get_kernel_trapvector_paddr());
+    if let Some(task) = mytask() {
+        task.cpu_times.enter_kernel(get_time_us());
+    }
 
     // let cpu = crate::arch::get_cpu();
     // crate::early_println!("CPU: {:#x?}", cpu);
@@ -197,6 +203,10 @@ pub extern "C" fn arch_user_trap_handler(addr: usize) -> ! {
         arch_exception_handler(trapframe, cause);
         // crate::println!("Exiting exception handler for cause: {}", cause);
     }
+    // Act on pending signals before going back to user space
+    if let Some(task) = mytask() {
+        deliver_signals(task, trapframe);
+    }
     // Jump directly to user trap exit via trampoline
     arch_switch_to_user_space(trapframe);
 }
@@ -215,6 +225,11 @@ pub extern "C" fn arch_user_trap_handler(addr: usize) -> ! {
 pub fn arch_switch_to_user_space(trapframe: &mut Trapframe) -> ! {
     let addr = trapframe as *mut Trapframe as usize;
 
+    // Kernel tasks leave through here too but stay in the kernel
+    if let Some(task) = mytask().filter(|task| task.task_type == TaskType::User) {
+        task.cpu_times.exit_kernel(get_time_us());
+    }
+
     // Get the trampoline address for _user_trap_exit
     let trap_exit_offset = _user_trap_exit as usize - _user_trap_entry as usize;
     // crate::early_println!("_user_trap_entry: {:#x}, _user_trap_exit: {:#x}, offset: {:#x}", _user_trap_entry as usize, _user_trap_exit as usize, trap_exit_offset);
@@ -14,12 +14,20 @@ pub enum Mode {
     Kernel,
 }
 
+/// Register width of the code run in user mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Xlen {
+    X32,
+    X64,
+}
+
 #[derive(Debug, Clone)]
 pub struct Vcpu {
     pub iregs: IntRegisters,
     pc: u64,
     asid: usize,
     mode: Mode,
+    xlen: Xlen,
 }
 
 impl Vcpu {
@@ -29,6 +37,7 @@ impl Vcpu {
             pc: 0,
             asid: 0,
             mode,
+            xlen: Xlen::X64,
         }
     }
 
@@ -52,6 +61,14 @@ impl Vcpu {
         self.mode
     }
 
+    pub fn set_xlen(&mut self, xlen: Xlen) {
+        self.xlen = xlen;
+    }
+
+    pub fn get_xlen(&self) -> Xlen {
+        self.xlen
+    }
+
     pub fn reset_iregs(&mut self) {
         self.iregs = IntRegisters::new();
     }
@@ -8,6 +8,9 @@ use crate::{vm::vmem::VirtualMemoryMap};
 
 const MAX_PAGING_LEVEL: usize = 3;
 
+/// Size of a megapage (a leaf entry at level 1)
+pub const MEGAPAGE_SIZE: usize = 0x20_0000; // 2MiB
+
 #[repr(align(8))]
 #[derive(Clone, Copy, Debug)]
 pub struct PageTableEntry {
@@ -130,6 +133,10 @@ impl PageTable {
         (mode << 60 | asid << 44 | ppn) as u64
     }
 
+    /// Map a whole memory area
+    ///
+    /// Parts of the area where the virtual and physical addresses are both
+    /// megapage aligned are mapped with megapages; the rest uses base pages.
     pub fn map_memory_area(&mut self, asid: u16, mmap: VirtualMemoryMap) -> Result<(), &'static str> {
         // Check if the address and size is aligned to PAGE_SIZE
         if mmap.vmarea.start % PAGE_SIZE != 0 || mmap.pmarea.start % PAGE_SIZE != 0 ||
@@ -140,12 +147,19 @@ impl PageTable {
         let mut vaddr = mmap.vmarea.start;
         let mut paddr = mmap.pmarea.start;
         while vaddr + (PAGE_SIZE - 1) <= mmap.vmarea.end {
-            self.map(asid, vaddr, paddr, mmap.permissions);    
-            match vaddr.checked_add(PAGE_SIZE) {
+            let step = if vaddr % MEGAPAGE_SIZE == 0 && paddr % MEGAPAGE_SIZE == 0 &&
+                vaddr.checked_add(MEGAPAGE_SIZE - 1).is_some_and(|end| end <= mmap.vmarea.end) {
+                self.map_megapage(asid, vaddr, paddr, mmap.permissions);
+                MEGAPAGE_SIZE
+            } else {
+                self.map(asid, vaddr, paddr, mmap.permissions);
+                PAGE_SIZE
+            };
+            match vaddr.checked_add(step) {
                 Some(addr) => vaddr = addr,
                 None => break,
             }
-            match paddr.checked_add(PAGE_SIZE) {
+            match paddr.checked_add(step) {
                 Some(addr) => paddr = addr,
                 None => break,
             }
@@ -154,6 +168,35 @@ impl PageTable {
         Ok(())
     }
 
+    /// Map a 2MiB megapage
+    ///
+    /// Both addresses must be megapage aligned. Any page table that
+    /// previously covered the range is dropped from the walk (its pages stay
+    /// owned by the address space and are freed with it).
+    pub fn map_megapage(&mut self, asid: u16, vaddr: usize, paddr: usize, permissions: usize) {
+        if vaddr % MEGAPAGE_SIZE != 0 || paddr % MEGAPAGE_SIZE != 0 {
+            panic!("map_megapage: unaligned address {:#x} -> {:#x}", vaddr, paddr);
+        }
+
+        let pte = match self.walk_level(vaddr, 1, true, asid) {
+            Some(pte) => pte,
+            None => panic!("map_megapage: walk() couldn't allocate a needed page-table page"),
+        };
+        pte.clear_all();
+        Self::set_leaf_permissions(pte, permissions);
+        pte.set_ppn((paddr >> 12) & 0xfffffffffff);
+        pte.validate();
+        unsafe { asm!("sfence.vma") };
+    }
+
+    /// Check whether `vaddr` is mapped by a megapage
+    pub fn is_megapage_mapped(&mut self, vaddr: usize) -> bool {
+        match self.walk_level(vaddr, 1, false, 0) {
+            Some(pte) => pte.is_leaf(),
+            None => false,
+        }
+    }
+
     /* Only for root page table */
     pub fn map(&mut self, asid: u16, vaddr: usize, paddr: usize, permissions: usize) {
         // Check if the virtual address is properly canonicalized for Sv48
@@ -178,7 +221,13 @@ impl PageTable {
         
         // Clear existing flags before setting new ones
         pte.clear_all();
-        
+        Self::set_leaf_permissions(pte, permissions);
+        pte.set_ppn(ppn);
+        pte.validate();
+        unsafe { asm!("sfence.vma") };
+    }
+
+    fn set_leaf_permissions(pte: &mut PageTableEntry, permissions: usize) {
         if VirtualMemoryPermission::Read.contained_in(permissions) {
             pte.readable();
         }
@@ -191,9 +240,31 @@ impl PageTable {
         if VirtualMemoryPermission::User.contained_in(permissions) {
             pte.accesible_from_user();
         }
-        pte.set_ppn(ppn);
+    }
+
+    /// Replace the megapage leaf `pte` with a table of 512 base pages that
+    /// map the same memory with the same permissions
+    ///
+    /// # Returns
+    /// The new level-0 table, or None if it could not be allocated
+    fn split_megapage(pte: &mut PageTableEntry, asid: u16) -> Option<*mut PageTable> {
+        let new_table = unsafe { new_raw_pagetable(asid) };
+        if new_table.is_null() {
+            return None;
+        }
+        let base_ppn = pte.get_ppn();
+        let flags = pte.get_flags();
+        let table = unsafe { &mut *new_table };
+        for (i, entry) in table.entries.iter_mut().enumerate() {
+            entry.clear_all();
+            entry.set_ppn(base_ppn + i);
+            entry.set_flags(flags);
+        }
+        pte.clear_all();
+        pte.set_ppn(new_table as usize >> 12);
         pte.validate();
         unsafe { asm!("sfence.vma") };
+        Some(new_table)
     }
 
     // Find the address of the PTE in page table that corresponds to virtual address vaddr.
@@ -209,7 +280,15 @@ impl PageTable {
     //   21..29 -- 9 bits of level-1 index.
     //   12..20 -- 9 bits of level-0 index.
     //    0..11 -- 12 bits of byte offset within the page.
+    //
+    // A megapage in the way is split into base pages when alloc == true.
     pub fn walk(&mut self, vaddr: usize, alloc: bool, asid: u16) -> Option<&mut PageTableEntry> {
+        self.walk_level(vaddr, 0, alloc, asid)
+    }
+
+    // Like walk(), but stop at `target_level` and return the PTE there
+    // (level 1 holds megapage leaves).
+    fn walk_level(&mut self, vaddr: usize, target_level: usize, alloc: bool, asid: u16) -> Option<&mut PageTableEntry> {
         let mut pagetable = self as *mut PageTable;
         
         // Check if virtual address is within valid canonical range for Sv48
@@ -222,15 +301,19 @@ impl PageTable {
         }
 
         unsafe {
-            // Walk through levels 3, 2, 1
-            for level in (1..=MAX_PAGING_LEVEL).rev() {
+            // Walk through the levels above the target level
+            for level in ((target_level + 1)..=MAX_PAGING_LEVEL).rev() {
                 let vpn = (vaddr >> (12 + 9 * level)) & 0x1ff;
                 let pte = &mut (*pagetable).entries[vpn];
                 
                 if pte.is_valid() {
-                    // At an intermediate level, a PTE must not be a leaf (no huge page support).
                     if pte.is_leaf() {
-                        return None; // Fail because it's an invalid state.
+                        // Only megapages (level 1 leaves) are ever created
+                        if level != 1 || !alloc {
+                            return None;
+                        }
+                        pagetable = Self::split_megapage(pte, asid)?;
+                        continue;
                     }
                     // If not a leaf, it's a pointer to the next level table.
                     pagetable = (pte.get_ppn() << 12) as *mut PageTable;
@@ -250,13 +333,17 @@ impl PageTable {
                 }
             }
             
-            // Return the PTE at level 0
-            let vpn = (vaddr >> 12) & 0x1ff;
+            // Return the PTE at the target level
+            let vpn = (vaddr >> (12 + 9 * target_level)) & 0x1ff;
             Some(&mut (*pagetable).entries[vpn])
         }
     }
 
-    pub fn unmap(&mut self, _asid: u16, vaddr: usize) {
+    /// Unmap a single base page
+    ///
+    /// A megapage covering `vaddr` is split first so the rest of it stays
+    /// mapped.
+    pub fn unmap(&mut self, asid: u16, vaddr: usize) {
         // Check if the virtual address is properly canonicalized for Sv48
         let canonical_check = (vaddr >> 47) & 1;
         let upper_bits = (vaddr >> 48) & 0xffff;
@@ -268,7 +355,18 @@ impl PageTable {
         
         let vaddr = vaddr & 0xffff_ffff_ffff_f000; // Page align
         
-        match self.walk(vaddr, false, 0) {
+        if self.is_megapage_mapped(vaddr) {
+            // Splitting needs a new table; without one, drop the whole megapage
+            // (the remaining pages fault back in)
+            let pte = self.walk_level(vaddr, 1, false, asid).unwrap();
+            if Self::split_megapage(pte, asid).is_none() {
+                pte.clear_all();
+                unsafe { asm!("sfence.vma") };
+                return;
+            }
+        }
+
+        match self.walk(vaddr, false, asid) {
             Some(pte) => {
                 if pte.is_valid() {
                     pte.clear_all();
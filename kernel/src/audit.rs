@@ -0,0 +1,379 @@
+//! Security audit log
+//!
+//! Security-relevant events are recorded as audit records on a channel of
+//! their own, separate from the console, which a userspace audit daemon
+//! consumes by reading `/dev/audit`. Each read returns whole records as long
+//! as they fit and removes them from the queue; a read with nothing queued
+//! blocks until the next record. Only privileged tasks may read the log.
+//!
+//! A record is one line of `key=value` fields after a header with the time
+//! since boot in microseconds and a sequence number, followed by the task
+//! that caused it:
+//!
+//! ```text
+//! audit(1234567:42): type=ACCESS_DENIED pid=7 uid=1000 euid=1000 comm="sh" path="/etc/shadow" want=r
+//! ```
+//!
+//! The record types are:
+//! - `ACCESS_DENIED`: a file permission check failed
+//! - `PRIV_DENIED`: an operation reserved to privileged tasks was refused
+//! - `MOUNT`: a mount, unmount or `pivot_root`, with its result
+//! - `EXEC_SETID`: a task executed a set-user-ID or set-group-ID binary, or
+//!   executed a program while its effective IDs differ from its real ones
+//! - `NAMESPACE`: a task got a new UTS or VFS namespace
+//! - `LOST`: the number of records dropped because the queue was full
+//!
+//! The queue holds [`AUDIT_QUEUE_RECORDS`] records; when nobody reads it,
+//! the oldest ones are dropped and counted in the next `LOST` record.
+
+extern crate alloc;
+
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::any::Any;
+use core::fmt::{self, Write};
+use spin::Mutex;
+
+use crate::device::char::CharDevice;
+use crate::device::manager::DeviceManager;
+use crate::device::{Device, DeviceType};
+use crate::fs::FileOwner;
+use crate::late_initcall;
+use crate::object::capability::{ControlOps, MemoryMappingOps};
+use crate::sync::waker::Waker;
+use crate::task::cred::{MAY_EXEC, MAY_READ, MAY_WRITE};
+use crate::task::{mytask, Task};
+use crate::timer::get_time_us;
+
+/// Records kept for the daemon before the oldest are dropped
+pub const AUDIT_QUEUE_RECORDS: usize = 512;
+
+/// Set-user-ID bit of a file mode
+const MODE_SETUID: u32 = 0o4000;
+/// Set-group-ID bit of a file mode
+const MODE_SETGID: u32 = 0o2000;
+
+/// Type of an audit record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditType {
+    AccessDenied,
+    PrivilegeDenied,
+    Mount,
+    ExecSetid,
+    Namespace,
+    Lost,
+}
+
+impl AuditType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AuditType::AccessDenied => "ACCESS_DENIED",
+            AuditType::PrivilegeDenied => "PRIV_DENIED",
+            AuditType::Mount => "MOUNT",
+            AuditType::ExecSetid => "EXEC_SETID",
+            AuditType::Namespace => "NAMESPACE",
+            AuditType::Lost => "LOST",
+        }
+    }
+}
+
+/// A string shown as a quoted field value, with quotes, backslashes and
+/// unprintable bytes escaped
+pub struct Quoted<'a>(pub &'a str);
+
+impl fmt::Display for Quoted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_char('"')?;
+        for byte in self.0.bytes() {
+            match byte {
+                b'"' => f.write_str("\\\"")?,
+                b'\\' => f.write_str("\\\\")?,
+                0x20..=0x7e => f.write_char(byte as char)?,
+                _ => write!(f, "\\x{:02x}", byte)?,
+            }
+        }
+        f.write_char('"')
+    }
+}
+
+/// Result field of a record for an operation
+pub fn result_name<T, E>(result: &Result<T, E>) -> &'static str {
+    if result.is_ok() { "success" } else { "failed" }
+}
+
+struct AuditQueue {
+    records: VecDeque<String>,
+    next_seq: u64,
+    /// Records dropped since the last `LOST` record
+    lost: u64,
+    /// Bytes of the front record already read
+    offset: usize,
+}
+
+impl AuditQueue {
+    const fn new() -> Self {
+        AuditQueue { records: VecDeque::new(), next_seq: 0, lost: 0, offset: 0 }
+    }
+
+    fn header(&mut self, kind: AuditType) -> String {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        format!("audit({}:{}): type={}", get_time_us(), seq, kind.name())
+    }
+
+    fn push(&mut self, kind: AuditType, body: fmt::Arguments) {
+        let mut record = self.header(kind);
+        let _ = write!(record, " {}\n", body);
+        if self.records.len() >= AUDIT_QUEUE_RECORDS {
+            self.records.pop_front();
+            self.offset = 0;
+            self.lost += 1;
+        }
+        self.records.push_back(record);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.records.is_empty() && self.lost == 0
+    }
+
+    /// Move queued bytes into `buffer`, whole records first
+    fn drain(&mut self, buffer: &mut [u8]) -> usize {
+        if self.lost > 0 && self.offset == 0 {
+            let mut record = self.header(AuditType::Lost);
+            let _ = write!(record, " records={}\n", self.lost);
+            self.records.push_front(record);
+            self.lost = 0;
+        }
+        let mut written = 0;
+        while let Some(front) = self.records.front() {
+            let rest = &front.as_bytes()[self.offset..];
+            let space = buffer.len() - written;
+            // Split a record only if it is the first one and does not fit
+            if rest.len() > space && written > 0 {
+                break;
+            }
+            let n = rest.len().min(space);
+            buffer[written..written + n].copy_from_slice(&rest[..n]);
+            written += n;
+            if n < rest.len() {
+                self.offset += n;
+                break;
+            }
+            self.records.pop_front();
+            self.offset = 0;
+        }
+        written
+    }
+}
+
+static AUDIT_QUEUE: Mutex<AuditQueue> = Mutex::new(AuditQueue::new());
+static AUDIT_WAKER: Waker = Waker::new_interruptible("audit");
+
+/// Append a record for the running task
+///
+/// `body` holds the fields of the record type, e.g.
+/// `format_args!("op=mount target={}", Quoted(target))`.
+pub fn log(kind: AuditType, body: fmt::Arguments) {
+    let subject = match mytask() {
+        Some(task) => format!(
+            "pid={} uid={} euid={} comm={}",
+            task.get_id(), task.cred.uid, task.cred.euid, Quoted(&task.name)
+        ),
+        None => String::from("pid=0 uid=0 euid=0 comm=\"kernel\""),
+    };
+    AUDIT_QUEUE.lock().push(kind, format_args!("{} {}", subject, body));
+    AUDIT_WAKER.wake_all();
+}
+
+/// Record a failed file permission check (`want` holds `MAY_*` bits)
+pub fn access_denied(path: &str, want: u32) {
+    let mut access = String::new();
+    for (bit, c) in [(MAY_READ, 'r'), (MAY_WRITE, 'w'), (MAY_EXEC, 'x')] {
+        if want & bit != 0 {
+            access.push(c);
+        }
+    }
+    log(AuditType::AccessDenied, format_args!("path={} want={}", Quoted(path), access));
+}
+
+/// Record an operation refused to an unprivileged task
+pub fn privilege_denied(op: &str) {
+    log(AuditType::PrivilegeDenied, format_args!("op={}", op));
+}
+
+/// Record an exec if it runs with IDs the task did not have by itself
+///
+/// `owner` is the owner of the binary as it was found before the exec.
+/// Scarlet does not apply the set-ID bits yet, so such an exec is recorded
+/// whether or not it gains anything.
+pub fn exec(task: &Task, path: &str, owner: Option<FileOwner>) {
+    let mode = owner.map_or(0, |owner| owner.mode);
+    let setid = mode & (MODE_SETUID | MODE_SETGID) != 0;
+    let elevated = task.cred.euid != task.cred.uid || task.cred.egid != task.cred.gid;
+    if setid || elevated {
+        log(AuditType::ExecSetid, format_args!(
+            "path={} mode={:o} owner={} egid={}",
+            Quoted(path), mode, owner.map_or(0, |owner| owner.uid), task.cred.egid
+        ));
+    }
+}
+
+/// Record a task getting a new namespace
+pub fn namespace(kind: &str, op: &str, task_id: usize) {
+    log(AuditType::Namespace, format_args!("ns={} op={} task={}", kind, op, task_id));
+}
+
+/// The `/dev/audit` device the audit daemon reads
+pub struct AuditDevice;
+
+impl AuditDevice {
+    fn may_read() -> bool {
+        mytask().map_or(true, |task| task.cred.is_privileged())
+    }
+}
+
+impl Device for AuditDevice {
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Char
+    }
+
+    fn name(&self) -> &'static str {
+        "audit"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn as_char_device(&self) -> Option<&dyn CharDevice> {
+        Some(self)
+    }
+}
+
+impl CharDevice for AuditDevice {
+    fn read_byte(&self) -> Option<u8> {
+        let mut byte = [0u8; 1];
+        (Self::may_read() && AUDIT_QUEUE.lock().drain(&mut byte) == 1).then_some(byte[0])
+    }
+
+    fn write_byte(&self, _byte: u8) -> Result<(), &'static str> {
+        Err("The audit log is read-only")
+    }
+
+    /// Read queued records, blocking while there are none
+    ///
+    /// An unprivileged task reads nothing.
+    fn read(&self, buffer: &mut [u8]) -> usize {
+        if !Self::may_read() || buffer.is_empty() {
+            return 0;
+        }
+        loop {
+            let n = AUDIT_QUEUE.lock().drain(buffer);
+            if n > 0 {
+                return n;
+            }
+            let Some(task) = mytask() else {
+                return 0;
+            };
+            let task_id = task.get_id();
+            AUDIT_WAKER.wait_unless(task_id, task.get_trapframe(), || {
+                !AUDIT_QUEUE.lock().is_empty() || mytask().is_some_and(|task| task.signals.has_pending())
+            });
+            if task.signals.has_pending() {
+                return 0;
+            }
+        }
+    }
+
+    fn can_read(&self) -> bool {
+        !AUDIT_QUEUE.lock().is_empty()
+    }
+
+    fn can_write(&self) -> bool {
+        false
+    }
+}
+
+impl ControlOps for AuditDevice {
+    fn control(&self, _command: u32, _arg: usize) -> Result<i32, &'static str> {
+        Err("Control operations not supported")
+    }
+}
+
+impl MemoryMappingOps for AuditDevice {
+    fn get_mapping_info(&self, _offset: usize, _length: usize)
+                       -> Result<(usize, usize, bool), &'static str> {
+        Err("Memory mapping not supported by the audit log")
+    }
+
+    fn on_mapped(&self, _vaddr: usize, _paddr: usize, _length: usize, _offset: usize) {}
+
+    fn on_unmapped(&self, _vaddr: usize, _length: usize) {}
+
+    fn supports_mmap(&self) -> bool {
+        false
+    }
+}
+
+fn init_audit_device() {
+    DeviceManager::get_manager().register_device_with_name("audit".into(), Arc::new(AuditDevice));
+}
+
+late_initcall!(init_audit_device);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_audit_record_format() {
+        let mut queue = AuditQueue::new();
+        queue.push(AuditType::Mount, format_args!("op=mount target={} res=success", Quoted("/mnt/\"x\"\n")));
+        let mut buffer = [0u8; 256];
+        let n = queue.drain(&mut buffer);
+        let record = core::str::from_utf8(&buffer[..n]).unwrap();
+        assert!(record.starts_with("audit("));
+        assert!(record.ends_with(":0): type=MOUNT op=mount target=\"/mnt/\\\"x\\\"\\x0a\" res=success\n"));
+        assert!(queue.is_empty());
+    }
+
+    #[test_case]
+    fn test_audit_queue_overflow_and_partial_reads() {
+        let mut queue = AuditQueue::new();
+        for i in 0..AUDIT_QUEUE_RECORDS + 3 {
+            queue.push(AuditType::PrivilegeDenied, format_args!("op=test{}", i));
+        }
+        assert_eq!(queue.records.len(), AUDIT_QUEUE_RECORDS);
+
+        // The loss is reported first, in a record of its own
+        let mut buffer = [0u8; 4096];
+        let n = queue.drain(&mut buffer);
+        let text = core::str::from_utf8(&buffer[..n]).unwrap();
+        let first = text.lines().next().unwrap();
+        assert!(first.contains("type=LOST records=3"));
+        assert!(text.lines().nth(1).unwrap().ends_with("op=test3"));
+        assert!(text.ends_with('\n'));
+
+        // A record larger than the buffer is split across reads
+        let mut queue = AuditQueue::new();
+        queue.push(AuditType::AccessDenied, format_args!("path=\"/a/long/path\""));
+        queue.push(AuditType::AccessDenied, format_args!("path=\"/b\""));
+        let mut small = [0u8; 8];
+        let mut record = alloc::vec::Vec::new();
+        loop {
+            let n = queue.drain(&mut small);
+            record.extend_from_slice(&small[..n]);
+            if record.ends_with(b"\n") {
+                break;
+            }
+        }
+        assert!(record.ends_with(b"path=\"/a/long/path\"\n"));
+        assert_eq!(queue.records.len(), 1);
+    }
+}
@@ -1,4 +1,5 @@
 use core::any::Any;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use alloc::boxed::Box;
 use alloc::vec;
@@ -17,6 +18,7 @@ pub struct MockBlockDevice {
     disk_size: usize,
     data: Mutex<Vec<Vec<u8>>>,
     request_queue: Mutex<Vec<Box<BlockIORequest>>>,
+    removed: AtomicBool,
 }
 
 impl MockBlockDevice {
@@ -31,6 +33,7 @@ impl MockBlockDevice {
             disk_size: sector_size * sector_count,
             data: Mutex::new(data),
             request_queue: Mutex::new(Vec::new()),
+            removed: AtomicBool::new(false),
         }
     }
 }
@@ -94,6 +97,10 @@ impl BlockDevice for MockBlockDevice {
             let mut queue = self.request_queue.lock();
             core::mem::replace(&mut *queue, Vec::new())
         }; // request_queue lock is automatically released here
+
+        if self.removed.load(Ordering::Acquire) {
+            return fail_removed(requests);
+        }
         
         // Process all requests without holding the request_queue lock
         for mut request in requests {
@@ -125,6 +132,19 @@ impl BlockDevice for MockBlockDevice {
                         Err("Invalid sector")
                     }
                     // data lock is automatically released here
+                },
+                BlockIORequestType::Discard => {
+                    let sector = request.sector;
+                    // Discarded sectors read back as zeroes
+                    let mut data = self.data.lock();
+                    if sector + request.sector_count <= data.len() {
+                        for sector_data in data[sector..sector + request.sector_count].iter_mut() {
+                            sector_data.fill(0);
+                        }
+                        Ok(())
+                    } else {
+                        Err("Invalid sector")
+                    }
                 }
             };
             
@@ -136,6 +156,14 @@ impl BlockDevice for MockBlockDevice {
         
         results
     }
+
+    fn supports_discard(&self) -> bool {
+        true
+    }
+
+    fn detach(&self) {
+        self.removed.store(true, Ordering::Release);
+    }
 }
 
 impl ControlOps for MockBlockDevice {
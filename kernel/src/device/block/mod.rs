@@ -1,13 +1,19 @@
 use core::any::Any;
+use core::sync::atomic::{AtomicBool, Ordering};
 
-use alloc::{boxed::Box, vec::Vec};
-use spin::Mutex;
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use spin::{Mutex, Once};
 use request::{BlockIORequest, BlockIOResult};
+use stats::BlockIoStats;
 
 use super::Device;
+use super::events::{DeviceEvent, DeviceEventListener, DeviceHotplugEvent, HotplugAction};
+use super::manager::DeviceManager;
+use crate::early_initcall;
 use crate::object::capability::{ControlOps, MemoryMappingOps};
 
 pub mod request;
+pub mod stats;
 
 extern crate alloc;
 
@@ -31,19 +37,78 @@ pub trait BlockDevice: Device {
     /// 
     /// A vector of results for all processed requests
     fn process_requests(&self) -> Vec<BlockIOResult>;
+
+    /// Check whether the device accepts `BlockIORequestType::Discard` requests
+    ///
+    /// Filesystems use this to decide whether to issue discards when they free
+    /// blocks. Devices that cannot reclaim storage keep the default.
+    fn supports_discard(&self) -> bool {
+        false
+    }
+
+    /// Get the I/O statistics of this device, if it tracks them
+    fn io_stats(&self) -> Option<&BlockIoStats> {
+        None
+    }
+
+    /// Tear down the request queue of a device that has been removed
+    ///
+    /// After this call the device must not touch the hardware again: queued
+    /// and future requests complete with an error instead. Called by the block
+    /// layer when the device manager reports the device as removed.
+    fn detach(&self) {}
 }
 
+/// Fail every request in `requests` because the device is gone
+pub(crate) fn fail_removed(requests: impl IntoIterator<Item = Box<BlockIORequest>>) -> Vec<BlockIOResult> {
+    requests.into_iter()
+        .map(|request| BlockIOResult { request, result: Err("Device removed") })
+        .collect()
+}
+
+/// Detaches block devices when they are hot-removed
+struct BlockHotplugListener;
+
+impl DeviceEventListener for BlockHotplugListener {
+    fn on_device_event(&self, event: &dyn DeviceEvent) {
+        if let Some(event) = event.as_any().downcast_ref::<DeviceHotplugEvent>() {
+            if event.action == HotplugAction::Removed {
+                if let Some(block_device) = event.device.as_block_device() {
+                    crate::early_println!("[block] Detaching removed device {} ({})", event.device_id, block_device.get_disk_name());
+                    block_device.detach();
+                }
+            }
+        }
+    }
+
+    fn interested_in(&self, event_type: &str) -> bool {
+        event_type == "hotplug"
+    }
+}
+
+static BLOCK_HOTPLUG_LISTENER: Once<Arc<BlockHotplugListener>> = Once::new();
+
+fn register_block_hotplug_listener() {
+    let listener = BLOCK_HOTPLUG_LISTENER.call_once(|| Arc::new(BlockHotplugListener));
+    let weak = Arc::downgrade(listener);
+    DeviceManager::get_manager().register_hotplug_listener(weak);
+}
+
+early_initcall!(register_block_hotplug_listener);
+
 /// A generic implementation of a block device
 pub struct GenericBlockDevice {
     disk_name: &'static str,
     disk_size: usize,
     request_fn: fn(&mut BlockIORequest) -> Result<(), &'static str>,
     request_queue: Mutex<Vec<Box<BlockIORequest>>>,
+    stats: BlockIoStats,
+    removed: AtomicBool,
 }
 
 impl GenericBlockDevice {
     pub fn new(disk_name: &'static str, disk_size: usize, request_fn: fn(&mut BlockIORequest) -> Result<(), &'static str>) -> Self {
-        Self { disk_name, disk_size, request_fn, request_queue: Mutex::new(Vec::new()) }
+        Self { disk_name, disk_size, request_fn, request_queue: Mutex::new(Vec::new()), stats: BlockIoStats::new(), removed: AtomicBool::new(false) }
     }
 }
 
@@ -133,18 +198,33 @@ impl BlockDevice for GenericBlockDevice {
             let mut queue = self.request_queue.lock();
             core::mem::replace(&mut *queue, Vec::new())
         }; // Lock is automatically released here
+
+        if self.removed.load(Ordering::Acquire) {
+            return fail_removed(requests);
+        }
         
         // Process all requests without holding any locks
         for mut request in requests {
+            let started = crate::time::current_time();
+
             // Process the request using the function pointer
             let result = (self.request_fn)(&mut *request);
             
             // Add the result to the results vector
             results.push(BlockIOResult { request, result });
+            self.stats.record_results(&results[results.len() - 1..], started);
         }
         
         results
     }
+
+    fn io_stats(&self) -> Option<&BlockIoStats> {
+        Some(&self.stats)
+    }
+
+    fn detach(&self) {
+        self.removed.store(true, Ordering::Release);
+    }
 }
 
 #[cfg(test)]
@@ -14,6 +14,9 @@ pub struct BlockIORequest {
 pub enum BlockIORequestType {
     Read,
     Write,
+    /// Tell the device that `sector_count` sectors starting at `sector` no longer
+    /// hold live data. The buffer is unused and should be empty.
+    Discard,
 }
 
 pub struct BlockIOResult {
@@ -0,0 +1,226 @@
+//! Block device I/O statistics
+//!
+//! Per-device counters (operations, bytes, errors) and a latency histogram,
+//! updated by block device implementations as requests complete. The counters
+//! are lock-free so they can be updated from the I/O path without contention.
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::request::{BlockIORequestType, BlockIOResult};
+use crate::device::manager::DeviceManager;
+use crate::device::DeviceType;
+
+/// Number of latency histogram buckets
+///
+/// Bucket `i` counts requests that completed in less than `2^i` microseconds
+/// (and at least `2^(i-1)`); the last bucket collects everything slower.
+pub const LATENCY_BUCKET_COUNT: usize = 20;
+
+/// Sector size used to convert discard sector counts into bytes
+const SECTOR_SIZE: u64 = 512;
+
+/// Live I/O counters for a single block device
+pub struct BlockIoStats {
+    read_ops: AtomicU64,
+    write_ops: AtomicU64,
+    discard_ops: AtomicU64,
+    read_bytes: AtomicU64,
+    write_bytes: AtomicU64,
+    discard_bytes: AtomicU64,
+    errors: AtomicU64,
+    total_latency_us: AtomicU64,
+    max_latency_us: AtomicU64,
+    latency_buckets: [AtomicU64; LATENCY_BUCKET_COUNT],
+}
+
+/// Point-in-time copy of a device's I/O counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BlockIoStatsSnapshot {
+    pub read_ops: u64,
+    pub write_ops: u64,
+    pub discard_ops: u64,
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    pub discard_bytes: u64,
+    pub errors: u64,
+    pub total_latency_us: u64,
+    pub max_latency_us: u64,
+    pub latency_buckets: [u64; LATENCY_BUCKET_COUNT],
+}
+
+impl BlockIoStats {
+    pub const fn new() -> Self {
+        Self {
+            read_ops: AtomicU64::new(0),
+            write_ops: AtomicU64::new(0),
+            discard_ops: AtomicU64::new(0),
+            read_bytes: AtomicU64::new(0),
+            write_bytes: AtomicU64::new(0),
+            discard_bytes: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            total_latency_us: AtomicU64::new(0),
+            max_latency_us: AtomicU64::new(0),
+            latency_buckets: [const { AtomicU64::new(0) }; LATENCY_BUCKET_COUNT],
+        }
+    }
+
+    /// Record a single completed request
+    ///
+    /// # Arguments
+    /// * `request_type` - The type of the completed request
+    /// * `bytes` - Number of bytes transferred (or discarded)
+    /// * `latency_us` - Time from submission to completion in microseconds
+    /// * `success` - Whether the request completed without error
+    pub fn record(&self, request_type: BlockIORequestType, bytes: u64, latency_us: u64, success: bool) {
+        if !success {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let (ops, total_bytes) = match request_type {
+            BlockIORequestType::Read => (&self.read_ops, &self.read_bytes),
+            BlockIORequestType::Write => (&self.write_ops, &self.write_bytes),
+            BlockIORequestType::Discard => (&self.discard_ops, &self.discard_bytes),
+        };
+        ops.fetch_add(1, Ordering::Relaxed);
+        if success {
+            total_bytes.fetch_add(bytes, Ordering::Relaxed);
+        }
+
+        self.total_latency_us.fetch_add(latency_us, Ordering::Relaxed);
+        self.max_latency_us.fetch_max(latency_us, Ordering::Relaxed);
+        self.latency_buckets[latency_bucket(latency_us)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a batch of completed requests that were submitted together
+    ///
+    /// All requests in the batch are charged the same latency, measured from
+    /// `started_us` (a `crate::time::current_time()` timestamp taken before
+    /// submission) to now.
+    pub fn record_results(&self, results: &[BlockIOResult], started_us: u64) {
+        if results.is_empty() {
+            return;
+        }
+        let latency_us = crate::time::current_time().saturating_sub(started_us);
+        for result in results {
+            let request = &result.request;
+            let bytes = match request.request_type {
+                BlockIORequestType::Discard => request.sector_count as u64 * SECTOR_SIZE,
+                _ => request.buffer.len() as u64,
+            };
+            self.record(request.request_type, bytes, latency_us, result.result.is_ok());
+        }
+    }
+
+    /// Take a consistent-enough copy of all counters
+    pub fn snapshot(&self) -> BlockIoStatsSnapshot {
+        let mut latency_buckets = [0u64; LATENCY_BUCKET_COUNT];
+        for (dst, src) in latency_buckets.iter_mut().zip(self.latency_buckets.iter()) {
+            *dst = src.load(Ordering::Relaxed);
+        }
+        BlockIoStatsSnapshot {
+            read_ops: self.read_ops.load(Ordering::Relaxed),
+            write_ops: self.write_ops.load(Ordering::Relaxed),
+            discard_ops: self.discard_ops.load(Ordering::Relaxed),
+            read_bytes: self.read_bytes.load(Ordering::Relaxed),
+            write_bytes: self.write_bytes.load(Ordering::Relaxed),
+            discard_bytes: self.discard_bytes.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            total_latency_us: self.total_latency_us.load(Ordering::Relaxed),
+            max_latency_us: self.max_latency_us.load(Ordering::Relaxed),
+            latency_buckets,
+        }
+    }
+
+    /// Reset all counters to zero
+    pub fn reset(&self) {
+        for counter in [
+            &self.read_ops, &self.write_ops, &self.discard_ops,
+            &self.read_bytes, &self.write_bytes, &self.discard_bytes,
+            &self.errors, &self.total_latency_us, &self.max_latency_us,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        for bucket in &self.latency_buckets {
+            bucket.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+impl BlockIoStatsSnapshot {
+    /// Total number of completed requests of any type
+    pub fn total_ops(&self) -> u64 {
+        self.read_ops + self.write_ops + self.discard_ops
+    }
+
+    /// Average request latency in microseconds
+    pub fn average_latency_us(&self) -> u64 {
+        let ops = self.total_ops();
+        if ops == 0 { 0 } else { self.total_latency_us / ops }
+    }
+
+    /// Upper bound (exclusive) of a latency bucket in microseconds
+    ///
+    /// Returns `None` for the last bucket, which is unbounded.
+    pub fn bucket_upper_bound_us(bucket: usize) -> Option<u64> {
+        if bucket + 1 >= LATENCY_BUCKET_COUNT {
+            None
+        } else {
+            Some(1u64 << bucket)
+        }
+    }
+}
+
+/// Map a latency to its histogram bucket
+fn latency_bucket(latency_us: u64) -> usize {
+    // Number of significant bits: 0 -> 0, 1 -> 1, 2..3 -> 2, 4..7 -> 3, ...
+    let bits = (u64::BITS - latency_us.leading_zeros()) as usize;
+    bits.min(LATENCY_BUCKET_COUNT - 1)
+}
+
+/// Collect statistics for every registered block device that tracks them
+///
+/// # Returns
+/// A vector of (device id, device name, snapshot) tuples ordered by device id
+pub fn collect_io_stats() -> Vec<(usize, &'static str, BlockIoStatsSnapshot)> {
+    DeviceManager::get_manager()
+        .get_devices_by_type(DeviceType::Block)
+        .into_iter()
+        .filter_map(|(id, device)| {
+            let block_device = device.as_block_device()?;
+            let stats = block_device.io_stats()?;
+            Some((id, block_device.get_disk_name(), stats.snapshot()))
+        })
+        .collect()
+}
+
+/// Render statistics for all block devices as text
+///
+/// One line per device with counters, followed by a line with the latency
+/// histogram. This is the format served by the statistics pseudo-file.
+pub fn format_io_stats() -> String {
+    let mut out = String::new();
+    for (id, name, stats) in collect_io_stats() {
+        let _ = writeln!(
+            out,
+            "{} {} rd_ops={} rd_bytes={} wr_ops={} wr_bytes={} discard_ops={} discard_bytes={} errors={} avg_lat_us={} max_lat_us={}",
+            id, name,
+            stats.read_ops, stats.read_bytes,
+            stats.write_ops, stats.write_bytes,
+            stats.discard_ops, stats.discard_bytes,
+            stats.errors, stats.average_latency_us(), stats.max_latency_us,
+        );
+        let _ = write!(out, "{} {} lat_hist_us:", id, name);
+        for (bucket, count) in stats.latency_buckets.iter().enumerate() {
+            match BlockIoStatsSnapshot::bucket_upper_bound_us(bucket) {
+                Some(bound) => { let _ = write!(out, " <{}={}", bound, count); },
+                None => { let _ = write!(out, " inf={}", count); },
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
@@ -68,6 +68,9 @@ impl TestDisk {
                 BlockIORequestType::Write => {
                     disk.write(sector, &request.buffer[..count * SECTOR_SIZE])
                 }
+                BlockIORequestType::Discard => {
+                    disk.write(sector, &vec![0; count * SECTOR_SIZE])
+                }
             }
         })
     }
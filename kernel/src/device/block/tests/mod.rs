@@ -85,4 +85,125 @@ fn test_read_write() {
         assert_eq!(read_request.buffer[i], 0xff);
     }
     
-}
\ No newline at end of file
+}
+#[test_case]
+fn test_discard_zeroes_sectors() {
+    let device = mockblk::MockBlockDevice::new("test_disk", 512, 8);
+    assert!(device.supports_discard());
+
+    for sector in 0..4 {
+        device.enqueue_request(Box::new(BlockIORequest {
+            request_type: request::BlockIORequestType::Write,
+            sector,
+            sector_count: 1,
+            head: 0,
+            cylinder: 0,
+            buffer: vec![0xaa; 512],
+        }));
+    }
+    device.process_requests();
+
+    device.enqueue_request(Box::new(BlockIORequest {
+        request_type: request::BlockIORequestType::Discard,
+        sector: 1,
+        sector_count: 2,
+        head: 0,
+        cylinder: 0,
+        buffer: Vec::new(),
+    }));
+    let results = device.process_requests();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].result, Ok(()));
+
+    for sector in 0..4 {
+        device.enqueue_request(Box::new(BlockIORequest {
+            request_type: request::BlockIORequestType::Read,
+            sector,
+            sector_count: 1,
+            head: 0,
+            cylinder: 0,
+            buffer: vec![0; 512],
+        }));
+    }
+    let results = device.process_requests();
+    let expected = [0xaa, 0x00, 0x00, 0xaa];
+    for (result, &byte) in results.iter().zip(expected.iter()) {
+        assert!(result.request.buffer.iter().all(|&b| b == byte));
+    }
+}
+
+#[test_case]
+fn test_discard_out_of_range() {
+    let device = mockblk::MockBlockDevice::new("test_disk", 512, 8);
+    device.enqueue_request(Box::new(BlockIORequest {
+        request_type: request::BlockIORequestType::Discard,
+        sector: 6,
+        sector_count: 4,
+        head: 0,
+        cylinder: 0,
+        buffer: Vec::new(),
+    }));
+    let results = device.process_requests();
+    assert!(results[0].result.is_err());
+}
+
+#[test_case]
+fn test_io_stats_counts_requests() {
+    let device = GenericBlockDevice::new("test_disk", 1024, dummy_request_fn);
+    for request_type in [request::BlockIORequestType::Read, request::BlockIORequestType::Write] {
+        device.enqueue_request(Box::new(BlockIORequest {
+            request_type,
+            sector: 0,
+            sector_count: 1,
+            head: 0,
+            cylinder: 0,
+            buffer: vec![0; 512],
+        }));
+    }
+    device.process_requests();
+
+    let stats = device.io_stats().unwrap().snapshot();
+    assert_eq!(stats.read_ops, 1);
+    assert_eq!(stats.write_ops, 1);
+    assert_eq!(stats.read_bytes, 512);
+    assert_eq!(stats.write_bytes, 512);
+    assert_eq!(stats.errors, 0);
+    assert_eq!(stats.latency_buckets.iter().sum::<u64>(), 2);
+
+    device.io_stats().unwrap().reset();
+    assert_eq!(device.io_stats().unwrap().snapshot(), stats::BlockIoStatsSnapshot::default());
+}
+
+#[test_case]
+fn test_io_stats_latency_buckets() {
+    let io_stats = stats::BlockIoStats::new();
+    io_stats.record(request::BlockIORequestType::Read, 512, 0, true);
+    io_stats.record(request::BlockIORequestType::Read, 512, 3, true);
+    io_stats.record(request::BlockIORequestType::Write, 512, u64::MAX, false);
+
+    let snapshot = io_stats.snapshot();
+    assert_eq!(snapshot.latency_buckets[0], 1);
+    assert_eq!(snapshot.latency_buckets[2], 1);
+    assert_eq!(snapshot.latency_buckets[stats::LATENCY_BUCKET_COUNT - 1], 1);
+    assert_eq!(snapshot.errors, 1);
+    assert_eq!(snapshot.write_bytes, 0);
+    assert_eq!(snapshot.max_latency_us, u64::MAX);
+}
+
+#[test_case]
+fn test_detach_fails_queued_requests() {
+    let device = mockblk::MockBlockDevice::new("test_disk", 512, 8);
+    device.enqueue_request(Box::new(BlockIORequest {
+        request_type: request::BlockIORequestType::Read,
+        sector: 0,
+        sector_count: 1,
+        head: 0,
+        cylinder: 0,
+        buffer: vec![0; 512],
+    }));
+    device.detach();
+
+    let results = device.process_requests();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].result, Err("Device removed"));
+}
@@ -0,0 +1,309 @@
+//! Board information
+//!
+//! What the platform is, for installers and diagnostics that adapt to it:
+//! the model, compatible strings and serial number of the board from the
+//! root of the device tree, the ISA of its harts from `/cpus`, and the
+//! firmware the kernel runs on, as the SBI implementation and the
+//! extensions it offers.
+//!
+//! They are gathered once, when first asked for, into a [`BoardInfo`] and
+//! read as text from the read-only character device `/dev/board`, one
+//! `key: value` line each, lists separated by spaces:
+//!
+//! ```text
+//! model: riscv-virtio,qemu
+//! compatible: riscv-virtio
+//! isa: rv64imafdch_zicsr_zifencei
+//! isa-extensions: i m a f d c h zicsr zifencei
+//! firmware: OpenSBI 1.5
+//! firmware-interface: SBI 2.0
+//! firmware-extensions: time ipi rfence hsm srst pmu
+//! mvendorid: 0x0
+//! ```
+//!
+//! A key whose value is unknown, as the serial number of most boards, is
+//! left out.
+
+use core::any::Any;
+use core::fmt::Write;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Once;
+
+use super::char::CharDevice;
+use super::fdt::FdtManager;
+use super::manager::DeviceManager;
+use super::{Device, DeviceType};
+use crate::late_initcall;
+use crate::object::capability::{ControlOps, MemoryMappingOps};
+
+/// The firmware the kernel runs on, as the architecture describes it
+#[derive(Debug, Clone, Default)]
+pub struct FirmwareInfo {
+    /// Interface to the firmware and its version, as `SBI 2.0`
+    pub interface: String,
+    /// Name of the implementation
+    pub name: String,
+    /// Version of the implementation
+    pub version: String,
+    /// Optional parts of the interface the firmware implements
+    pub extensions: Vec<&'static str>,
+    /// Identification registers of the boot hart, by name
+    pub machine_ids: Vec<(&'static str, usize)>,
+}
+
+/// What the board is
+#[derive(Debug, Clone, Default)]
+pub struct BoardInfo {
+    pub model: Option<String>,
+    pub compatible: Vec<String>,
+    pub serial: Option<String>,
+    /// ISA string of the first hart, as `rv64imafdc_zicsr`
+    pub isa: Option<String>,
+    /// Extensions of the first hart, single letters first
+    pub isa_extensions: Vec<String>,
+    pub firmware: FirmwareInfo,
+}
+
+impl BoardInfo {
+    /// Read the board from the device tree and the firmware
+    fn probe() -> Self {
+        let mut info = BoardInfo {
+            firmware: crate::arch::firmware_info(),
+            ..Default::default()
+        };
+        let Some(fdt) = FdtManager::get_manager().get_fdt() else {
+            return info;
+        };
+        let root = fdt.root();
+        let string = |name: &str| root.property(name).and_then(|property| property.as_str()).map(String::from);
+        info.model = string("model");
+        info.serial = string("serial-number");
+        if let Some(compatible) = root.property("compatible") {
+            info.compatible = string_list(compatible.value);
+        }
+        if let Some(cpu) = fdt.cpus().next() {
+            info.isa = cpu.property("riscv,isa").and_then(|property| property.as_str()).map(String::from);
+            // Newer device trees list the extensions on their own
+            info.isa_extensions = match cpu.property("riscv,isa-extensions") {
+                Some(extensions) => string_list(extensions.value),
+                None => info.isa.as_deref().map(parse_isa).unwrap_or_default(),
+            };
+        }
+        info
+    }
+
+    /// The `key: value` lines of `/dev/board`
+    pub fn format(&self) -> String {
+        let mut text = String::new();
+        let mut line = |key: &str, value: &str| {
+            if !value.is_empty() {
+                let _ = writeln!(text, "{}: {}", key, value);
+            }
+        };
+        line("model", self.model.as_deref().unwrap_or_default());
+        line("compatible", &self.compatible.join(" "));
+        line("serial", self.serial.as_deref().unwrap_or_default());
+        line("isa", self.isa.as_deref().unwrap_or_default());
+        line("isa-extensions", &self.isa_extensions.join(" "));
+        let firmware = &self.firmware;
+        line("firmware", format!("{} {}", firmware.name, firmware.version).trim());
+        line("firmware-interface", &firmware.interface);
+        line("firmware-extensions", &firmware.extensions.join(" "));
+        for (name, value) in &firmware.machine_ids {
+            line(name, &format!("{:#x}", value));
+        }
+        text
+    }
+}
+
+/// The strings of a device tree string list
+fn string_list(value: &[u8]) -> Vec<String> {
+    value.split(|&byte| byte == 0)
+        .filter(|string| !string.is_empty())
+        .filter_map(|string| core::str::from_utf8(string).ok())
+        .map(String::from)
+        .collect()
+}
+
+/// The extensions of a RISC-V ISA string, as `rv64imac_zicsr` has `i`, `m`,
+/// `a`, `c` and `zicsr`
+///
+/// `g` stands for `imafd` with `zicsr` and `zifencei`. Version numbers of
+/// extensions are dropped.
+pub fn parse_isa(isa: &str) -> Vec<String> {
+    let isa = isa.to_ascii_lowercase();
+    let Some(isa) = isa.strip_prefix("rv32").or_else(|| isa.strip_prefix("rv64")) else {
+        return Vec::new();
+    };
+    let mut extensions = Vec::new();
+    let mut multi_letter: Vec<String> = Vec::new();
+    let mut parts = isa.split('_');
+    for letter in parts.next().unwrap_or_default().chars().filter(|c| c.is_ascii_alphabetic()) {
+        if letter == 'g' {
+            extensions.extend("imafd".chars().map(String::from));
+            multi_letter.extend(["zicsr".into(), "zifencei".into()]);
+        } else if letter != 'p' {
+            extensions.push(String::from(letter));
+        }
+    }
+    for extension in parts {
+        let name = strip_version(extension);
+        if !name.is_empty() && !multi_letter.iter().any(|known| known == name) {
+            multi_letter.push(name.into());
+        }
+    }
+    extensions.extend(multi_letter);
+    extensions
+}
+
+/// `extension` without a version number, as `zba` of `zba1p0`
+fn strip_version(extension: &str) -> &str {
+    let name = extension.trim_end_matches(|c: char| c.is_ascii_digit());
+    if name.len() == extension.len() {
+        return name;
+    }
+    // The major version, then `p` and the minor one
+    match name.strip_suffix('p') {
+        Some(major) if major.ends_with(|c: char| c.is_ascii_digit()) => {
+            major.trim_end_matches(|c: char| c.is_ascii_digit())
+        }
+        _ => name,
+    }
+}
+
+static BOARD_INFO: Once<BoardInfo> = Once::new();
+
+/// What the board is, read when first asked for
+pub fn board_info() -> &'static BoardInfo {
+    BOARD_INFO.call_once(BoardInfo::probe)
+}
+
+/// `/dev/board`, reading the board information as text
+pub struct BoardDevice;
+
+impl Device for BoardDevice {
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Char
+    }
+
+    fn name(&self) -> &'static str {
+        "board"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn as_char_device(&self) -> Option<&dyn CharDevice> {
+        Some(self)
+    }
+}
+
+impl CharDevice for BoardDevice {
+    fn read_byte(&self) -> Option<u8> {
+        None
+    }
+
+    fn write_byte(&self, _byte: u8) -> Result<(), &'static str> {
+        Err("Board information is read-only")
+    }
+
+    fn read(&self, _buffer: &mut [u8]) -> usize {
+        0
+    }
+
+    fn read_at(&self, position: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let text = board_info().format();
+        let start = (position as usize).min(text.len());
+        let count = buffer.len().min(text.len() - start);
+        buffer[..count].copy_from_slice(&text.as_bytes()[start..start + count]);
+        Ok(count)
+    }
+
+    fn write_at(&self, _position: u64, _buffer: &[u8]) -> Result<usize, &'static str> {
+        Err("Board information is read-only")
+    }
+
+    fn can_read(&self) -> bool {
+        true
+    }
+
+    fn can_write(&self) -> bool {
+        false
+    }
+
+    fn can_seek(&self) -> bool {
+        true
+    }
+}
+
+impl ControlOps for BoardDevice {
+    fn control(&self, _command: u32, _arg: usize) -> Result<i32, &'static str> {
+        Err("Control operations not supported")
+    }
+}
+
+impl MemoryMappingOps for BoardDevice {
+    fn get_mapping_info(&self, _offset: usize, _length: usize)
+                       -> Result<(usize, usize, bool), &'static str> {
+        Err("Memory mapping not supported by the board device")
+    }
+
+    fn on_mapped(&self, _vaddr: usize, _paddr: usize, _length: usize, _offset: usize) {}
+
+    fn on_unmapped(&self, _vaddr: usize, _length: usize) {}
+
+    fn supports_mmap(&self) -> bool {
+        false
+    }
+}
+
+fn init_board_device() {
+    DeviceManager::get_manager().register_device_with_name("board".into(), Arc::new(BoardDevice));
+}
+
+late_initcall!(init_board_device);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_parse_isa() {
+        assert_eq!(parse_isa("rv64imafdch_zicsr_zifencei_sstc"),
+                   ["i", "m", "a", "f", "d", "c", "h", "zicsr", "zifencei", "sstc"]);
+        assert_eq!(parse_isa("RV64GC_Zba1p0_zicbop"),
+                   ["i", "m", "a", "f", "d", "c", "zicsr", "zifencei", "zba", "zicbop"]);
+        assert!(parse_isa("x86").is_empty());
+    }
+
+    #[test_case]
+    fn test_board_device() {
+        let info = BoardInfo {
+            model: Some("riscv-virtio,qemu".into()),
+            compatible: alloc::vec!["riscv-virtio".into()],
+            isa_extensions: alloc::vec!["i".into(), "m".into()],
+            ..Default::default()
+        };
+        let text = info.format();
+        assert!(text.starts_with("model: riscv-virtio,qemu\ncompatible: riscv-virtio\n"));
+        assert!(text.contains("isa-extensions: i m\n"));
+        assert!(!text.contains("serial"));
+
+        // Read as a file, in pieces
+        let text = board_info().format();
+        let mut buffer = alloc::vec![0u8; text.len() + 8];
+        assert_eq!(BoardDevice.read_at(0, &mut buffer[..4]), Ok(4.min(text.len())));
+        assert_eq!(BoardDevice.read_at(0, &mut buffer), Ok(text.len()));
+        assert_eq!(&buffer[..text.len()], text.as_bytes());
+        assert_eq!(BoardDevice.read_at(text.len() as u64, &mut buffer), Ok(0));
+    }
+}
@@ -0,0 +1,176 @@
+//! Memory character devices
+//!
+//! Devices that are not backed by hardware, which programs expect to find
+//! in `/dev` under these names:
+//!
+//! - **null**: reads nothing, accepts and discards any write
+//! - **zero**: reads zero bytes, discards writes
+//! - **random**, **urandom**: read the output of the kernel CSPRNG (see
+//!   [`crate::random`]); what is written is mixed into it, uncredited.
+//!   `random` blocks until the generator is seeded, `urandom` never does
+
+use alloc::sync::Arc;
+use core::any::Any;
+
+use super::CharDevice;
+use crate::device::{manager::DeviceManager, Device, DeviceType};
+use crate::late_initcall;
+use crate::object::capability::{ControlOps, MemoryMappingOps};
+
+/// What a memory device reads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemDeviceKind {
+    Null,
+    Zero,
+    /// Blocks until the generator is seeded
+    Random,
+    Urandom,
+}
+
+pub struct MemDevice {
+    name: &'static str,
+    kind: MemDeviceKind,
+}
+
+impl MemDevice {
+    pub const fn new(name: &'static str, kind: MemDeviceKind) -> Self {
+        Self { name, kind }
+    }
+}
+
+impl Device for MemDevice {
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Char
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn as_char_device(&self) -> Option<&dyn CharDevice> {
+        Some(self)
+    }
+}
+
+impl CharDevice for MemDevice {
+    fn read_byte(&self) -> Option<u8> {
+        let mut byte = [0u8; 1];
+        (self.read(&mut byte) == 1).then_some(byte[0])
+    }
+
+    fn write_byte(&self, byte: u8) -> Result<(), &'static str> {
+        self.write(&[byte]).map(|_| ())
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> usize {
+        match self.kind {
+            MemDeviceKind::Null => 0,
+            MemDeviceKind::Zero => {
+                buffer.fill(0);
+                buffer.len()
+            }
+            MemDeviceKind::Random => {
+                if crate::random::wait_until_seeded(false).is_err() {
+                    return 0;
+                }
+                crate::random::fill_random_bytes(buffer);
+                buffer.len()
+            }
+            MemDeviceKind::Urandom => {
+                crate::random::fill_random_bytes(buffer);
+                buffer.len()
+            }
+        }
+    }
+
+    fn write(&self, buffer: &[u8]) -> Result<usize, &'static str> {
+        if matches!(self.kind, MemDeviceKind::Random | MemDeviceKind::Urandom) {
+            crate::random::add_entropy(buffer);
+        }
+        Ok(buffer.len())
+    }
+
+    fn read_at(&self, _position: u64, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        Ok(self.read(buffer))
+    }
+
+    fn write_at(&self, _position: u64, buffer: &[u8]) -> Result<usize, &'static str> {
+        self.write(buffer)
+    }
+
+    fn can_read(&self) -> bool {
+        self.kind != MemDeviceKind::Random || crate::random::is_seeded()
+    }
+
+    fn can_write(&self) -> bool {
+        true
+    }
+
+    /// Seeking succeeds and changes nothing, as on other systems
+    fn can_seek(&self) -> bool {
+        true
+    }
+}
+
+impl ControlOps for MemDevice {
+    fn control(&self, _command: u32, _arg: usize) -> Result<i32, &'static str> {
+        Err("Control operations not supported")
+    }
+}
+
+impl MemoryMappingOps for MemDevice {
+    fn get_mapping_info(&self, _offset: usize, _length: usize)
+                       -> Result<(usize, usize, bool), &'static str> {
+        Err("Memory mapping not supported by memory devices")
+    }
+
+    fn on_mapped(&self, _vaddr: usize, _paddr: usize, _length: usize, _offset: usize) {}
+
+    fn on_unmapped(&self, _vaddr: usize, _length: usize) {}
+
+    fn supports_mmap(&self) -> bool {
+        false
+    }
+}
+
+fn init_mem_devices() {
+    let manager = DeviceManager::get_manager();
+    for (name, kind) in [
+        ("null", MemDeviceKind::Null),
+        ("zero", MemDeviceKind::Zero),
+        ("random", MemDeviceKind::Random),
+        ("urandom", MemDeviceKind::Urandom),
+    ] {
+        manager.register_device_with_name(name.into(), Arc::new(MemDevice::new(name, kind)));
+    }
+}
+
+late_initcall!(init_mem_devices);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_mem_devices() {
+        let mut buffer = [0xaau8; 16];
+        let null = MemDevice::new("null", MemDeviceKind::Null);
+        assert_eq!(null.read(&mut buffer), 0);
+        assert_eq!(null.write(&buffer), Ok(16));
+
+        let zero = MemDevice::new("zero", MemDeviceKind::Zero);
+        assert_eq!(zero.read(&mut buffer), 16);
+        assert!(buffer.iter().all(|&byte| byte == 0));
+
+        let random = MemDevice::new("urandom", MemDeviceKind::Urandom);
+        assert_eq!(random.read_at(0, &mut buffer), Ok(16));
+    }
+}
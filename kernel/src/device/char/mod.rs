@@ -1,7 +1,9 @@
 use core::any::Any;
 
 use super::Device;
-use crate::object::capability::{ControlOps, MemoryMappingOps};
+use crate::object::capability::poll::{PollOps, POLLIN, POLLOUT};
+use crate::object::capability::{ControlOps, MemoryMappingOps, StreamError};
+use crate::task::mytask;
 
 extern crate alloc;
 
@@ -136,6 +138,42 @@ pub trait CharDevice: Device {
     fn can_seek(&self) -> bool {
         false
     }
+
+    /// Readiness of the device for poll and epoll
+    ///
+    /// Devices that wake their readers return themselves (like TTY); for
+    /// the others [`poll_events`] checks `can_read()` and `can_write()`,
+    /// at every tick while a task waits.
+    fn as_pollable(&self) -> Option<&dyn PollOps> {
+        None
+    }
+}
+
+/// The poll events of a character device
+pub fn poll_events(device: &dyn CharDevice) -> u32 {
+    if let Some(pollable) = device.as_pollable() {
+        return pollable.poll_events();
+    }
+    let mut events = 0;
+    if device.can_read() {
+        events |= POLLIN;
+    }
+    if device.can_write() {
+        events |= POLLOUT;
+    }
+    events
+}
+
+/// Turn the number of bytes a character device read into the result of a
+/// stream read of `requested` bytes
+///
+/// A blocking device stops waiting without data when a signal arrives for
+/// the reading task: such a read is interrupted, not at the end of the data.
+pub fn read_result(bytes_read: usize, requested: usize) -> Result<usize, StreamError> {
+    if bytes_read == 0 && requested > 0 && mytask().is_some_and(|task| task.signals.has_pending()) {
+        return Err(StreamError::Interrupted);
+    }
+    Ok(bytes_read)
 }
 
 /// A generic implementation of a character device
@@ -237,4 +275,6 @@ mod tests;
 #[cfg(test)]
 pub mod mockchar;
 
+pub mod mem;
 pub mod tty;
+pub mod pty;
@@ -0,0 +1,347 @@
+//! Pseudo-terminals
+//!
+//! A pseudo-terminal is a pair of devices. The slave, `/dev/pts/N`, is a
+//! TTY like the console; the master plays the terminal: what is written to
+//! it is typed on the slave, and what the slave writes is read from it.
+//! Each open of `/dev/ptmx` makes a new pair and gets its master, whose
+//! `TIOCGPTN` gives `N`. The other control commands of the master apply to
+//! the slave, as the line discipline settings and the window size do.
+//!
+//! Closing the last handle of the master hangs up the slave (see
+//! [`TtyDevice::hangup_terminal`]) and removes it from `/dev`.
+
+use alloc::{collections::{BTreeSet, VecDeque}, format, sync::Arc, vec::Vec};
+use core::any::Any;
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::Mutex;
+
+use super::tty::{self, write_user_i32, TtyDevice, TtyDriver};
+use super::CharDevice;
+use crate::device::{manager::DeviceManager, Device, DeviceType};
+use crate::late_initcall;
+use crate::object::capability::poll::{wait_for, PollOps, POLLIN, POLLOUT};
+use crate::object::capability::{ControlOps, MemoryMappingOps};
+use crate::sync::waker::Waker;
+use crate::task::mytask;
+
+/// Pseudo-terminal control commands (Linux ioctl numbers)
+pub mod pty_commands {
+    /// Get the number of the slave (arg: *mut u32)
+    pub const TIOCGPTN: u32 = 0x80045430;
+    /// Lock or unlock the slave (arg: *const i32); slaves are never locked
+    pub const TIOCSPTLCK: u32 = 0x40045431;
+}
+
+/// Bytes written by the slave that the master holds unread
+pub const PTY_BUFFER_SIZE: usize = 4096;
+
+/// Numbers of the pseudo-terminals in use
+static NUMBERS: Mutex<BTreeSet<usize>> = Mutex::new(BTreeSet::new());
+
+/// What the slave writes, for the master to read
+struct PtyOutput {
+    buffer: Mutex<VecDeque<u8>>,
+    /// Master readers waiting for output
+    readable: Waker,
+    /// Slave writers waiting for room
+    writable: Waker,
+    /// The master is closed
+    closed: AtomicBool,
+}
+
+impl PtyOutput {
+    fn room(&self) -> usize {
+        PTY_BUFFER_SIZE.saturating_sub(self.buffer.lock().len())
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+}
+
+/// The driver of a slave, sending its output to the master
+struct PtyDriver {
+    output: Arc<PtyOutput>,
+}
+
+impl TtyDriver for PtyDriver {
+    fn write_byte(&self, byte: u8) -> Result<(), &'static str> {
+        if self.output.is_closed() {
+            return Err("Terminal hung up");
+        }
+        {
+            let mut buffer = self.output.buffer.lock();
+            if buffer.len() >= PTY_BUFFER_SIZE {
+                return Err("Pseudo-terminal output is full");
+            }
+            buffer.push_back(byte);
+        }
+        self.output.readable.wake_all();
+        Ok(())
+    }
+
+    fn wait_writable(&self, len: usize) -> bool {
+        let Some(task) = mytask() else { return true };
+        let writable = || self.output.room() >= len || self.output.is_closed();
+        while !self.output.writable.wait_unless(task.get_id(), task.get_trapframe(), writable) {
+            if task.signals.has_pending() {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn can_write(&self) -> bool {
+        self.output.room() > 0
+    }
+}
+
+/// The master of a pseudo-terminal
+pub struct PtyMaster {
+    number: usize,
+    slave: Arc<TtyDevice>,
+    slave_id: usize,
+    output: Arc<PtyOutput>,
+}
+
+impl PtyMaster {
+    /// Make a new pseudo-terminal, registering its slave as `pts/N`
+    pub fn open() -> Arc<Self> {
+        let number = {
+            let mut numbers = NUMBERS.lock();
+            let number = (0..).find(|number| !numbers.contains(number)).unwrap_or_default();
+            numbers.insert(number);
+            number
+        };
+        let output = Arc::new(PtyOutput {
+            buffer: Mutex::new(VecDeque::new()),
+            readable: Waker::new_interruptible("pty_read"),
+            writable: Waker::new_interruptible("pty_write"),
+            closed: AtomicBool::new(false),
+        });
+        let slave = Arc::new(TtyDevice::new("pts", Arc::new(PtyDriver { output: output.clone() })));
+        tty::register_controlling_tty(&slave);
+        let slave_id = DeviceManager::get_manager().register_device_with_name(format!("pts/{}", number), slave.clone());
+        Arc::new(Self { number, slave, slave_id, output })
+    }
+
+    /// Number `N` of the slave, `/dev/pts/N`
+    pub fn number(&self) -> usize {
+        self.number
+    }
+}
+
+impl Drop for PtyMaster {
+    fn drop(&mut self) {
+        self.output.closed.store(true, Ordering::Release);
+        self.output.writable.wake_all();
+        self.slave.hangup_terminal();
+        DeviceManager::get_manager().unregister_device(self.slave_id);
+        NUMBERS.lock().remove(&self.number);
+    }
+}
+
+impl Device for PtyMaster {
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Char
+    }
+
+    fn name(&self) -> &'static str {
+        "ptmx"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn as_char_device(&self) -> Option<&dyn CharDevice> {
+        Some(self)
+    }
+}
+
+impl CharDevice for PtyMaster {
+    fn read_byte(&self) -> Option<u8> {
+        let mut byte = [0u8; 1];
+        (self.read(&mut byte) == 1).then_some(byte[0])
+    }
+
+    /// Type `byte` on the slave
+    fn write_byte(&self, byte: u8) -> Result<(), &'static str> {
+        self.slave.handle_input_byte(byte);
+        Ok(())
+    }
+
+    /// Read what the slave wrote, waiting for it
+    fn read(&self, buffer: &mut [u8]) -> usize {
+        if buffer.is_empty() {
+            return 0;
+        }
+        let mut count = 0;
+        wait_for(&[self as &dyn PollOps], None, || {
+            let mut output = self.output.buffer.lock();
+            count = buffer.len().min(output.len());
+            for (slot, byte) in buffer.iter_mut().zip(output.drain(..count)) {
+                *slot = byte;
+            }
+            count > 0
+        });
+        if count > 0 {
+            self.output.writable.wake_all();
+        }
+        count
+    }
+
+    fn can_read(&self) -> bool {
+        !self.output.buffer.lock().is_empty()
+    }
+
+    fn can_write(&self) -> bool {
+        true
+    }
+
+    fn as_pollable(&self) -> Option<&dyn PollOps> {
+        Some(self)
+    }
+}
+
+impl PollOps for PtyMaster {
+    /// Readable once the slave wrote; what is written is typed at once
+    fn poll_events(&self) -> u32 {
+        if self.can_read() { POLLIN | POLLOUT } else { POLLOUT }
+    }
+
+    fn poll_register(&self, task_id: usize) -> bool {
+        self.output.readable.register(task_id);
+        true
+    }
+
+    fn poll_unregister(&self, task_id: usize) {
+        self.output.readable.unregister(task_id);
+    }
+}
+
+impl ControlOps for PtyMaster {
+    fn control(&self, command: u32, arg: usize) -> Result<i32, &'static str> {
+        use pty_commands::*;
+
+        match command {
+            TIOCGPTN => write_user_i32(arg, self.number as i32).map(|_| 0),
+            TIOCSPTLCK => Ok(0),
+            _ => self.slave.control(command, arg),
+        }
+    }
+
+    fn supported_control_commands(&self) -> Vec<(u32, &'static str)> {
+        use pty_commands::*;
+        let mut commands = alloc::vec![
+            (TIOCGPTN, "Get the number of the pseudo-terminal"),
+            (TIOCSPTLCK, "Lock or unlock the pseudo-terminal"),
+        ];
+        commands.extend(self.slave.supported_control_commands());
+        commands
+    }
+}
+
+impl MemoryMappingOps for PtyMaster {
+    fn get_mapping_info(&self, _offset: usize, _length: usize)
+                       -> Result<(usize, usize, bool), &'static str> {
+        Err("Memory mapping not supported by pseudo-terminals")
+    }
+
+    fn on_mapped(&self, _vaddr: usize, _paddr: usize, _length: usize, _offset: usize) {}
+
+    fn on_unmapped(&self, _vaddr: usize, _length: usize) {}
+
+    fn supports_mmap(&self) -> bool {
+        false
+    }
+}
+
+/// `/dev/ptmx`, whose every open gets the master of a new pseudo-terminal
+pub struct PtmxDevice;
+
+impl Device for PtmxDevice {
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Char
+    }
+
+    fn name(&self) -> &'static str {
+        "ptmx"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn open_instance(&self) -> Result<Option<Arc<dyn Device>>, &'static str> {
+        Ok(Some(PtyMaster::open()))
+    }
+}
+
+impl ControlOps for PtmxDevice {
+    fn control(&self, _command: u32, _arg: usize) -> Result<i32, &'static str> {
+        Err("Control operations not supported")
+    }
+}
+
+impl MemoryMappingOps for PtmxDevice {
+    fn get_mapping_info(&self, _offset: usize, _length: usize)
+                       -> Result<(usize, usize, bool), &'static str> {
+        Err("Memory mapping not supported by pseudo-terminals")
+    }
+
+    fn on_mapped(&self, _vaddr: usize, _paddr: usize, _length: usize, _offset: usize) {}
+
+    fn on_unmapped(&self, _vaddr: usize, _length: usize) {}
+
+    fn supports_mmap(&self) -> bool {
+        false
+    }
+}
+
+fn init_ptmx() {
+    DeviceManager::get_manager().register_device_with_name("ptmx".into(), Arc::new(PtmxDevice));
+}
+
+late_initcall!(init_ptmx);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_pty_pair() {
+        let master = PtyMaster::open();
+        let slave = master.slave.clone();
+        let name = format!("pts/{}", master.number());
+        assert!(DeviceManager::get_manager().get_device_by_name(&name).is_some());
+
+        // Typed on the master, echoed back and read as a line on the slave
+        assert_eq!(CharDevice::write(&*master, b"ls\r"), Ok(3));
+        let mut buffer = [0u8; 16];
+        assert_eq!(slave.read(&mut buffer), 3);
+        assert_eq!(&buffer[..3], b"ls\n");
+        assert_eq!(master.read(&mut buffer), 4);
+        assert_eq!(&buffer[..4], b"ls\r\n");
+
+        // Written by the slave, read on the master
+        assert_eq!(CharDevice::write(&*slave, b"ok\n"), Ok(3));
+        assert!(master.can_read());
+        assert_eq!(master.read(&mut buffer), 4);
+        assert_eq!(&buffer[..4], b"ok\r\n");
+
+        // Closing the master hangs up the slave
+        drop(master);
+        assert!(DeviceManager::get_manager().get_device_by_name(&name).is_none());
+        assert_eq!(slave.read(&mut buffer), 0);
+        assert!(slave.write_byte(b'x').is_err());
+    }
+}
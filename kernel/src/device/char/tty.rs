@@ -1,10 +1,47 @@
 //! TTY (Terminal) device implementation.
 //! 
-//! This module implements a TTY device that acts as a terminal interface
-//! providing line discipline, echo, and basic terminal I/O operations.
+//! This module implements the TTY core, which sits between the driver of a
+//! terminal and the programs using it. The driver ([`TtyDriver`]), a serial
+//! port or a pseudo-terminal, carries output to the terminal and hands the
+//! bytes typed on it to [`TtyDevice::handle_input_byte`]; the line
+//! discipline in between edits lines, echoes and sends signals.
+//!
+//! A TTY can be the controlling terminal of one session. A session leader
+//! acquires it with `TIOCSCTTY`, and the session picks its foreground
+//! process group with `TIOCSPGRP`. The VINTR, VQUIT and VSUSP characters
+//! (Ctrl+C, Ctrl+\ and Ctrl+Z) send SIGINT, SIGQUIT and SIGTSTP to the
+//! foreground group. A background group of the session that tries to read
+//! gets SIGTTIN, and one that changes the settings or, with `TOSTOP`,
+//! writes gets SIGTTOU unless it blocks or ignores it. When the session
+//! leader exits, the foreground group gets SIGHUP and the terminal is
+//! released. When the terminal itself goes away, as the master of a
+//! pseudo-terminal closes, the TTY is hung up: the session leader gets
+//! SIGHUP too, reads return the end of file and writes fail.
+//!
+//! The line discipline is set with a `struct termios` of the Linux layout
+//! (`TCGETS`/`TCSETS`). With `ICANON` input is edited a line at a time:
+//! VERASE, VWERASE and VKILL take back a character, a word or the line,
+//! a newline, VEOL or VEOL2 ends it, and VEOF hands it over as it is, so
+//! that VEOF on an empty line reads as the end of file. A read returns at
+//! most one line. Without `ICANON` bytes are read as they come; a read
+//! waits for `VMIN` of them, and with `VTIME` for that many tenths of a
+//! second at most between them. `ECHO` echoes typed characters (control
+//! characters as `^X` with `ECHOCTL`), `ISIG` makes the control characters
+//! send signals, and `ICRNL`, `INLCR`, `IGNCR` and `ISTRIP` translate
+//! input. Output is translated by `OPOST` with `ONLCR` and `OCRNL`. The
+//! window size is kept for programs to read with `TIOCGWINSZ`; setting it
+//! sends SIGWINCH to the foreground group.
+//!
+//! Software flow control follows `c_iflag`: with `IXON` a typed VSTOP
+//! (Ctrl+S) stops output to the terminal and VSTART (Ctrl+Q), or with
+//! `IXANY` any character, resumes it; writers block meanwhile. With `IXOFF`
+//! the TTY sends VSTOP to the terminal once its input buffer is three
+//! quarters full, and VSTART once reads have drained it to a quarter. Input
+//! past [`TTY_BUFFER_SIZE`] bytes is dropped.
 
 extern crate alloc;
 use core::any::Any;
+use core::sync::atomic::{AtomicBool, Ordering};
 use alloc::collections::VecDeque;
 use alloc::sync::Arc;
 use spin::Mutex;
@@ -16,7 +53,244 @@ use crate::drivers::uart;
 use crate::sync::waker::Waker;
 use crate::late_initcall;
 use crate::task::mytask;
-use crate::object::capability::{ControlOps, MemoryMappingOps};
+use crate::task::process_group_members;
+use crate::task::signal::{
+    self, send_signal, send_signal_to_group, SIGCONT, SIGHUP, SIGINT, SIGQUIT, SIGTSTP, SIGTTIN, SIGTTOU, SIGWINCH,
+    SIG_IGN,
+};
+use crate::object::capability::{ControlOps, MemoryMappingOps, PollOps};
+use crate::object::capability::poll::{timeout_ticks, wait_for, PollWait, POLLHUP, POLLIN, POLLOUT};
+use crate::sched::scheduler::get_scheduler;
+use alloc::sync::Weak;
+use alloc::vec::Vec;
+
+/// TTY control commands (Linux ioctl numbers)
+pub mod tty_commands {
+    /// Make the TTY the controlling terminal of the caller's session
+    pub const TIOCSCTTY: u32 = 0x540E;
+    /// Get the foreground process group (arg: *mut i32)
+    pub const TIOCGPGRP: u32 = 0x540F;
+    /// Set the foreground process group (arg: *const i32)
+    pub const TIOCSPGRP: u32 = 0x5410;
+    /// Give up the controlling terminal
+    pub const TIOCNOTTY: u32 = 0x5422;
+    /// Get the session the TTY controls (arg: *mut i32)
+    pub const TIOCGSID: u32 = 0x5429;
+    /// Get the line discipline settings (arg: *mut Termios)
+    pub const TCGETS: u32 = 0x5401;
+    /// Set the line discipline settings (arg: *const Termios)
+    pub const TCSETS: u32 = 0x5402;
+    /// Set them once output is written; output is never queued
+    pub const TCSETSW: u32 = 0x5403;
+    /// Set them and discard unread input
+    pub const TCSETSF: u32 = 0x5404;
+    /// Discard unread input (arg: TCIFLUSH, TCOFLUSH or TCIOFLUSH)
+    pub const TCFLSH: u32 = 0x540B;
+    /// Get the bytes a read would return (arg: *mut i32)
+    pub const FIONREAD: u32 = 0x541B;
+    /// Get the window size (arg: *mut WinSize)
+    pub const TIOCGWINSZ: u32 = 0x5413;
+    /// Set the window size (arg: *const WinSize)
+    pub const TIOCSWINSZ: u32 = 0x5414;
+}
+
+/// Queues of `TCFLSH`
+pub const TCIFLUSH: usize = 0;
+pub const TCOFLUSH: usize = 1;
+pub const TCIOFLUSH: usize = 2;
+
+/// `c_iflag` bits of input translation
+pub const ISTRIP: u32 = 0o40;
+pub const INLCR: u32 = 0o100;
+pub const IGNCR: u32 = 0o200;
+pub const ICRNL: u32 = 0o400;
+
+/// `c_iflag` bits of software flow control
+pub const IXON: u32 = 0o2000;
+pub const IXANY: u32 = 0o4000;
+pub const IXOFF: u32 = 0o10000;
+
+/// `c_oflag` bits of output translation
+pub const OPOST: u32 = 0o1;
+pub const ONLCR: u32 = 0o4;
+pub const OCRNL: u32 = 0o10;
+
+/// `c_lflag` bits the line discipline follows
+pub const ISIG: u32 = 0o1;
+pub const ICANON: u32 = 0o2;
+pub const ECHO: u32 = 0o10;
+pub const ECHOE: u32 = 0o20;
+pub const ECHOK: u32 = 0o40;
+pub const ECHONL: u32 = 0o100;
+pub const NOFLSH: u32 = 0o200;
+pub const TOSTOP: u32 = 0o400;
+pub const ECHOCTL: u32 = 0o1000;
+pub const ECHOKE: u32 = 0o4000;
+pub const IEXTEN: u32 = 0o100000;
+
+/// Indices of the control characters in `c_cc`
+pub const VINTR: usize = 0;
+pub const VQUIT: usize = 1;
+pub const VERASE: usize = 2;
+pub const VKILL: usize = 3;
+pub const VEOF: usize = 4;
+pub const VTIME: usize = 5;
+pub const VMIN: usize = 6;
+pub const VSTART: usize = 8;
+pub const VSTOP: usize = 9;
+pub const VSUSP: usize = 10;
+pub const VEOL: usize = 11;
+pub const VWERASE: usize = 14;
+pub const VEOL2: usize = 16;
+
+/// Bytes of unread input a TTY holds
+pub const TTY_BUFFER_SIZE: usize = 4096;
+
+/// Line discipline settings (Linux `struct termios`)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Termios {
+    pub c_iflag: u32,
+    pub c_oflag: u32,
+    pub c_cflag: u32,
+    pub c_lflag: u32,
+    pub c_line: u8,
+    pub c_cc: [u8; 19],
+}
+
+impl Termios {
+    /// Whether `byte` is the control character at `index`; a zero
+    /// character is disabled
+    fn is_char(&self, byte: u8, index: usize) -> bool {
+        byte != 0 && self.c_cc[index] == byte
+    }
+}
+
+impl Default for Termios {
+    /// Canonical mode with echo and signals, CR read as NL, NL written as
+    /// CRLF, and XON/XOFF output flow control
+    fn default() -> Self {
+        const CS8_CREAD_B38400: u32 = 0o60 | 0o200 | 0o17;
+        let mut c_cc = [0u8; 19];
+        // VINTR, VQUIT, VERASE, VKILL, VEOF, VTIME, VMIN, VSWTC, VSTART, VSTOP, VSUSP, VEOL,
+        // VREPRINT, VDISCARD, VWERASE
+        c_cc[..15].copy_from_slice(&[0x03, 0x1c, 0x7f, 0x15, 0x04, 0, 1, 0, 0x11, 0x13, 0x1a, 0, 0x12, 0x0f, 0x17]);
+        Self {
+            c_iflag: ICRNL | IXON,
+            c_oflag: OPOST | ONLCR,
+            c_cflag: CS8_CREAD_B38400,
+            c_lflag: ISIG | ICANON | ECHO | ECHOE | ECHOK | ECHOCTL | ECHOKE | IEXTEN,
+            c_line: 0,
+            c_cc,
+        }
+    }
+}
+
+/// Window size (Linux `struct winsize`)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WinSize {
+    pub ws_row: u16,
+    pub ws_col: u16,
+    pub ws_xpixel: u16,
+    pub ws_ypixel: u16,
+}
+
+impl Default for WinSize {
+    fn default() -> Self {
+        Self { ws_row: 24, ws_col: 80, ws_xpixel: 0, ws_ypixel: 0 }
+    }
+}
+
+/// The lower half of a TTY
+///
+/// A serial port or a pseudo-terminal carrying the output of the TTY to the
+/// terminal. It hands the bytes typed on the terminal to
+/// [`TtyDevice::handle_input_byte`].
+pub trait TtyDriver: Send + Sync {
+    /// Send `byte` to the terminal
+    fn write_byte(&self, byte: u8) -> Result<(), &'static str>;
+
+    /// Block the calling task until output is not stopped and `len` bytes
+    /// can be sent; `false` if a signal interrupted the wait
+    fn wait_writable(&self, _len: usize) -> bool {
+        true
+    }
+
+    /// Whether output is accepted now
+    fn can_write(&self) -> bool {
+        true
+    }
+
+    /// Stop or resume output, for a typed VSTOP or VSTART
+    fn set_output_stopped(&self, _stopped: bool) {}
+
+    /// Send the flow control character `c` ahead of the queued output,
+    /// even while output is stopped
+    fn send_flow_char(&self, c: u8) {
+        let _ = self.write_byte(c);
+    }
+}
+
+/// The driver of a TTY on a UART
+pub struct SerialTtyDriver {
+    uart_device_id: usize,
+}
+
+impl SerialTtyDriver {
+    pub fn new(uart_device_id: usize) -> Self {
+        Self { uart_device_id }
+    }
+
+    /// Call `f` with the UART
+    fn with_uart<R>(&self, f: impl FnOnce(&uart::virt::Uart) -> R) -> Option<R> {
+        let device = DeviceManager::get_manager().get_device(self.uart_device_id)?;
+        device.as_any().downcast_ref::<uart::virt::Uart>().map(f)
+    }
+}
+
+impl TtyDriver for SerialTtyDriver {
+    fn write_byte(&self, byte: u8) -> Result<(), &'static str> {
+        let device = DeviceManager::get_manager().get_device(self.uart_device_id).ok_or("UART device not available")?;
+        device.as_char_device().ok_or("UART device not available")?.write_byte(byte)
+    }
+
+    fn wait_writable(&self, len: usize) -> bool {
+        self.with_uart(|uart| uart.wait_writable(len)) != Some(false)
+    }
+
+    fn can_write(&self) -> bool {
+        self.with_uart(|uart| uart.can_write()).unwrap_or(false)
+    }
+
+    fn set_output_stopped(&self, stopped: bool) {
+        self.with_uart(|uart| if stopped { uart.stop_tx() } else { uart.start_tx() });
+    }
+
+    fn send_flow_char(&self, c: u8) {
+        self.with_uart(|uart| uart.send_flow_char(c));
+    }
+}
+
+/// TTYs that can become controlling terminals
+static TTYS: Mutex<Vec<Weak<TtyDevice>>> = Mutex::new(Vec::new());
+
+/// Let `tty` become a controlling terminal
+pub fn register_controlling_tty(tty: &Arc<TtyDevice>) {
+    let mut ttys = TTYS.lock();
+    ttys.retain(|tty| tty.strong_count() > 0);
+    ttys.push(Arc::downgrade(tty));
+}
+
+/// Hang up the controlling terminal of the session `sid`, if any
+///
+/// Called when the session leader exits.
+pub fn hangup_session(sid: usize) {
+    let ttys: Vec<_> = TTYS.lock().iter().filter_map(Weak::upgrade).collect();
+    for tty in ttys {
+        tty.hangup(sid);
+    }
+}
 
 /// TTY subsystem initialization
 fn init_tty_subsystem() {
@@ -31,8 +305,8 @@ fn try_init_tty_subsystem() -> Result<(), &'static str> {
     
     // Find the first UART device and use its ID for TTY initialization
     if let Some(uart_device_id) = device_manager.get_first_device_by_type(crate::device::DeviceType::Char) {
-        // Create TTY device with UART device ID for lookup
-        let tty_device = Arc::new(TtyDevice::new("tty0", uart_device_id));
+        // Create TTY device on the UART
+        let tty_device = Arc::new(TtyDevice::new("tty0", Arc::new(SerialTtyDriver::new(uart_device_id))));
         let uart_device = device_manager.get_device(uart_device_id).ok_or("UART device not found")?;
         
         // Register TTY device as event listener for UART
@@ -45,6 +319,8 @@ fn try_init_tty_subsystem() -> Result<(), &'static str> {
             crate::early_println!("Failed to cast UART device to specific type");
         }
         
+        register_controlling_tty(&tty_device);
+
         // Register TTY device with device manager
         let _tty_id = device_manager.register_device_with_name("tty0".into(), tty_device);
         
@@ -63,122 +339,652 @@ late_initcall!(init_tty_subsystem);
 /// echo, and basic terminal I/O operations.
 pub struct TtyDevice {
     name: &'static str,
-    uart_device_id: usize,
+    driver: Arc<dyn TtyDriver>,
     
-    // Input buffer for line discipline
-    input_buffer: Arc<Mutex<VecDeque<u8>>>,
+    // Input of the line discipline
+    input: Mutex<TtyInput>,
     
     // Waker for blocking reads
     input_waker: Waker,
     
-    // Line discipline flags (Phase 2 expansion)
-    canonical_mode: bool,
-    echo_enabled: bool,
+    // Line discipline settings and window size
+    termios: Mutex<Termios>,
+    winsize: Mutex<WinSize>,
     
-    // Terminal state (placeholder for future features)
-    // process_group: Option<ProcessGroupId>,  // Job control placeholder
-    // session_id: Option<SessionId>,           // Session management placeholder
+    // Job control state
+    job_control: Mutex<JobControl>,
+
+    // Flow control state
+    flow: Mutex<FlowControl>,
+
+    // The terminal went away
+    hung_up: AtomicBool,
+}
+
+/// Typed input
+#[derive(Debug, Default)]
+struct TtyInput {
+    /// Bytes to read: whole lines in canonical mode
+    queue: VecDeque<u8>,
+    /// Bytes of each line in `queue` in canonical mode, 0 for VEOF typed
+    /// on an empty line
+    lines: VecDeque<usize>,
+    /// Line being edited in canonical mode
+    line: Vec<u8>,
+}
+
+impl TtyInput {
+    /// Bytes held, read or not
+    fn len(&self) -> usize {
+        self.queue.len() + self.line.len()
+    }
+
+    /// Hand the edited line to readers
+    fn commit(&mut self) {
+        self.lines.push_back(self.line.len());
+        self.queue.extend(self.line.drain(..));
+    }
+
+    /// Read the rest of the first line, as much of it as `buffer` holds
+    fn take_line(&mut self, buffer: &mut [u8]) -> Option<usize> {
+        let rest = self.lines.front_mut()?;
+        let count = (*rest).min(buffer.len());
+        *rest -= count;
+        if *rest == 0 {
+            self.lines.pop_front();
+        }
+        Some(self.take(&mut buffer[..count]))
+    }
+
+    /// Read the bytes of the queue `buffer` holds
+    fn take(&mut self, buffer: &mut [u8]) -> usize {
+        let count = buffer.len().min(self.queue.len());
+        for (slot, byte) in buffer.iter_mut().zip(self.queue.drain(..count)) {
+            *slot = byte;
+        }
+        count
+    }
+
+    fn flush(&mut self) {
+        self.queue.clear();
+        self.lines.clear();
+        self.line.clear();
+    }
+}
+
+/// Software flow control state
+#[derive(Debug, Default, Clone, Copy)]
+struct FlowControl {
+    /// Output stopped by a typed VSTOP
+    output_stopped: bool,
+    /// VSTOP sent to the terminal for a filling input buffer
+    input_throttled: bool,
+}
+
+/// Session controlling a TTY and its foreground process group
+#[derive(Debug, Default, Clone, Copy)]
+struct JobControl {
+    session: Option<usize>,
+    foreground: Option<usize>,
 }
 
 impl TtyDevice {
-    pub fn new(name: &'static str, uart_device_id: usize) -> Self {
+    pub fn new(name: &'static str, driver: Arc<dyn TtyDriver>) -> Self {
         Self {
             name,
-            uart_device_id,
-            input_buffer: Arc::new(Mutex::new(VecDeque::new())),
+            driver,
+            input: Mutex::new(TtyInput::default()),
             input_waker: Waker::new_interruptible("tty_input"),
-            canonical_mode: true,
-            echo_enabled: true,
+            termios: Mutex::new(Termios::default()),
+            winsize: Mutex::new(WinSize::default()),
+            job_control: Mutex::new(JobControl::default()),
+            flow: Mutex::new(FlowControl::default()),
+            hung_up: AtomicBool::new(false),
         }
     }
-    
-    /// Handle input byte from UART device.
-    /// 
-    /// This method processes incoming bytes and applies line discipline.
-    fn handle_input_byte(&self, byte: u8) {
-        // crate::early_println!("TTY processing byte: {:02x}", byte);
-        
-        // Phase 2: Canonical mode processing
-        if self.canonical_mode {
-            match byte {
-                // Backspace/DEL
-                0x08 | 0x7F => {
-                    // crate::early_println!("TTY: Backspace detected");
-                    let mut input_buffer = self.input_buffer.lock();
-                    if input_buffer.pop_back().is_some() && self.echo_enabled {
-                        self.echo_backspace();
-                    }
-                }
-                // Enter/Line feed
-                b'\r' | b'\n' => {
-                    // crate::early_println!("TTY: Enter/newline detected");
-                    if self.echo_enabled {
-                        self.echo_char(b'\r');
-                        self.echo_char(b'\n');
-                    }
-                    let mut input_buffer = self.input_buffer.lock();
-                    input_buffer.push_back(b'\n');
-                    // crate::early_println!("TTY: Line added to buffer, size now: {}", input_buffer.len());
-                    // Wake up waiting processes
-                    drop(input_buffer);
-                    self.input_waker.wake_all();
-                }
-                // Control characters (placeholder for signal processing)
-                0x03 => {
-                    crate::early_println!("TTY: Ctrl+C detected");
-                    // Ctrl+C: Send SIGINT (placeholder)
-                    // TODO: Implement signal processing when process management is ready
-                }
-                0x1A => {
-                    crate::early_println!("TTY: Ctrl+Z detected");
-                    // Ctrl+Z: Send SIGTSTP (placeholder)
-                    // TODO: Implement job control when process management is ready
+
+    /// Stop or resume output for a typed flow control character
+    fn set_output_stopped(&self, stopped: bool) {
+        self.flow.lock().output_stopped = stopped;
+        self.driver.set_output_stopped(stopped);
+    }
+
+    /// Handle `byte` as a flow control character if `IXON` makes it one;
+    /// returns whether it was consumed
+    fn handle_flow_char(&self, byte: u8, termios: &Termios) -> bool {
+        if termios.c_iflag & IXON == 0 {
+            return false;
+        }
+        if termios.is_char(byte, VSTOP) {
+            self.set_output_stopped(true);
+            return true;
+        }
+        let stopped = self.flow.lock().output_stopped;
+        if termios.is_char(byte, VSTART) {
+            if stopped {
+                self.set_output_stopped(false);
+            }
+            return true;
+        }
+        // IXANY: any other character resumes output too, and is read
+        if stopped && termios.c_iflag & IXANY != 0 {
+            self.set_output_stopped(false);
+        }
+        false
+    }
+
+    /// Update the input with `f`, then throttle the terminal if `IXOFF`
+    /// asks to
+    fn update_input<R>(&self, f: impl FnOnce(&mut TtyInput) -> R) -> R {
+        let (result, len) = {
+            let mut input = self.input.lock();
+            let result = f(&mut input);
+            (result, input.len())
+        };
+        if len >= TTY_BUFFER_SIZE * 3 / 4 {
+            self.send_flow_char(true);
+        }
+        result
+    }
+
+    /// Let a throttled terminal send again once the input buffer drained
+    fn unthrottle_input(&self) {
+        if self.input.lock().len() <= TTY_BUFFER_SIZE / 4 {
+            self.send_flow_char(false);
+        }
+    }
+
+    /// Send VSTOP (`throttle`) or VSTART to the terminal if `IXOFF` is set
+    /// and it changes the state
+    fn send_flow_char(&self, throttle: bool) {
+        let termios = *self.termios.lock();
+        {
+            let mut flow = self.flow.lock();
+            if termios.c_iflag & IXOFF == 0 || flow.input_throttled == throttle {
+                return;
+            }
+            flow.input_throttled = throttle;
+        }
+        let c = termios.c_cc[if throttle { VSTOP } else { VSTART }];
+        self.driver.send_flow_char(c);
+    }
+
+    /// Local modes of the line discipline
+    fn lflag(&self) -> u32 {
+        self.termios.lock().c_lflag
+    }
+
+    /// Send `sig` to the foreground process group, as typed control
+    /// characters do
+    fn signal_foreground(&self, sig: usize) {
+        let foreground = self.job_control.lock().foreground;
+        if let Some(pgid) = foreground {
+            let _ = send_signal_to_group(pgid, sig);
+        }
+    }
+
+    /// Handle a control character that generates `sig`
+    fn handle_signal_char(&self, byte: u8, sig: usize, termios: &Termios) {
+        if termios.c_lflag & ECHO != 0 {
+            self.echo(byte, termios);
+            self.echo(b'\n', termios);
+        }
+        // The input typed so far is discarded
+        if termios.c_lflag & NOFLSH == 0 {
+            self.input.lock().flush();
+        }
+        self.signal_foreground(sig);
+    }
+
+    /// Release the TTY if `sid` controls it, sending SIGHUP to the
+    /// foreground process group
+    fn hangup(&self, sid: usize) {
+        let foreground = {
+            let mut job_control = self.job_control.lock();
+            if job_control.session != Some(sid) {
+                return;
+            }
+            core::mem::take(&mut *job_control).foreground
+        };
+        if let Some(pgid) = foreground {
+            let _ = send_signal_to_group(pgid, SIGHUP);
+            let _ = send_signal_to_group(pgid, SIGCONT);
+        }
+    }
+
+    /// Hang up the TTY, as its driver lost the terminal
+    ///
+    /// The session leader and the foreground process group get SIGHUP and
+    /// the TTY is released. From then on reads return the end of file and
+    /// writes fail.
+    pub fn hangup_terminal(&self) {
+        self.hung_up.store(true, Ordering::Release);
+        if let Some(sid) = self.job_control.lock().session {
+            let _ = send_signal(sid, SIGHUP);
+            self.hangup(sid);
+        }
+        self.input_waker.wake_all();
+    }
+
+    fn is_hung_up(&self) -> bool {
+        self.hung_up.load(Ordering::Acquire)
+    }
+
+    /// Whether the calling task may read now
+    ///
+    /// A task of the controlling session that is not in the foreground
+    /// group is sent SIGTTIN with the rest of its group instead.
+    fn check_foreground_read(&self) -> bool {
+        let Some(task) = mytask() else { return true };
+        let job_control = *self.job_control.lock();
+        if job_control.session != Some(task.get_sid()) || job_control.foreground == Some(task.get_pgid()) {
+            return true;
+        }
+        let _ = send_signal_to_group(task.get_pgid(), SIGTTIN);
+        false
+    }
+
+    /// Whether the calling task may change the TTY or, with `TOSTOP`,
+    /// write to it now
+    ///
+    /// A task of the controlling session that is not in the foreground
+    /// group is sent SIGTTOU with the rest of its group instead, unless it
+    /// blocks or ignores the signal.
+    fn check_foreground_output(&self) -> bool {
+        let Some(task) = mytask() else { return true };
+        let job_control = *self.job_control.lock();
+        if job_control.session != Some(task.get_sid()) || job_control.foreground == Some(task.get_pgid()) {
+            return true;
+        }
+        if task.signals.mask.contains(SIGTTOU) || task.signals.action(SIGTTOU).handler == SIG_IGN {
+            return true;
+        }
+        let _ = send_signal_to_group(task.get_pgid(), SIGTTOU);
+        false
+    }
+
+    fn control_set_ctty(&self) -> Result<i32, &'static str> {
+        let task = mytask().ok_or("No current task")?;
+        if !task.is_session_leader() {
+            return Err("Only a session leader can acquire a controlling terminal");
+        }
+        let mut job_control = self.job_control.lock();
+        match job_control.session {
+            Some(sid) if sid == task.get_sid() => Ok(0),
+            Some(_) => Err("TTY is the controlling terminal of another session"),
+            None => {
+                job_control.session = Some(task.get_sid());
+                job_control.foreground = Some(task.get_pgid());
+                Ok(0)
+            }
+        }
+    }
+
+    /// Session and foreground group, checking that the caller belongs to
+    /// the session
+    fn caller_job_control(&self) -> Result<JobControl, &'static str> {
+        let task = mytask().ok_or("No current task")?;
+        let job_control = *self.job_control.lock();
+        if job_control.session != Some(task.get_sid()) {
+            return Err("Not the controlling terminal of the caller");
+        }
+        Ok(job_control)
+    }
+
+    fn control_get_pgrp(&self, arg: usize) -> Result<i32, &'static str> {
+        let pgid = self.caller_job_control()?.foreground.unwrap_or(0);
+        write_user_i32(arg, pgid as i32)?;
+        Ok(0)
+    }
+
+    fn control_set_pgrp(&self, arg: usize) -> Result<i32, &'static str> {
+        let sid = self.caller_job_control()?.session;
+        let pgid = read_user_i32(arg)?;
+        if pgid <= 0 {
+            return Err("Invalid process group");
+        }
+        let pgid = pgid as usize;
+        let in_session = process_group_members(pgid).into_iter().any(|id| {
+            get_scheduler().get_task_by_id(id).is_some_and(|task| Some(task.get_sid()) == sid)
+        });
+        if !in_session {
+            return Err("No such process group in the session");
+        }
+        self.job_control.lock().foreground = Some(pgid);
+        Ok(0)
+    }
+
+    fn control_no_tty(&self) -> Result<i32, &'static str> {
+        let task = mytask().ok_or("No current task")?;
+        let job_control = self.caller_job_control()?;
+        if task.is_session_leader() {
+            if let Some(sid) = job_control.session {
+                self.hangup(sid);
+            }
+        }
+        Ok(0)
+    }
+
+    fn control_get_sid(&self, arg: usize) -> Result<i32, &'static str> {
+        let sid = self.caller_job_control()?.session.unwrap_or(0);
+        write_user_i32(arg, sid as i32)?;
+        Ok(0)
+    }
+
+    fn control_set_termios(&self, arg: usize, flush: bool) -> Result<i32, &'static str> {
+        let termios = read_user::<Termios>(arg)?;
+        let old = core::mem::replace(&mut *self.termios.lock(), termios);
+        {
+            let mut input = self.input.lock();
+            if flush {
+                input.flush();
+            }
+            match (old.c_lflag & ICANON != 0, termios.c_lflag & ICANON != 0) {
+                // The line being edited can be read at once
+                (true, false) => {
+                    input.commit();
+                    input.lines.clear();
                 }
-                // Regular characters
-                byte => {
-                    // crate::early_println!("TTY: Regular character: {:02x}", byte);
-                    if self.echo_enabled {
-                        self.echo_char(byte);
-                    }
-                    let mut input_buffer = self.input_buffer.lock();
-                    input_buffer.push_back(byte);
-                    // crate::early_println!("TTY: Character added to buffer, size now: {}", input_buffer.len());
-                    // Wake up waiting processes in RAW mode or for immediate input
-                    drop(input_buffer);
-                    if !self.canonical_mode {
-                        self.input_waker.wake_all();
-                    }
+                // What was typed before is one line
+                (false, true) if !input.queue.is_empty() => {
+                    let len = input.queue.len();
+                    input.lines.clear();
+                    input.lines.push_back(len);
                 }
+                _ => {}
+            }
+        }
+        // Flow control turned off lets output and input go again
+        if termios.c_iflag & IXON == 0 && self.flow.lock().output_stopped {
+            self.set_output_stopped(false);
+        }
+        if termios.c_iflag & IXOFF == 0 {
+            self.flow.lock().input_throttled = false;
+        } else {
+            self.unthrottle_input();
+        }
+        // Readers waiting for a line get what was typed in raw mode
+        if termios.c_lflag & ICANON == 0 {
+            self.input_waker.wake_all();
+        }
+        Ok(0)
+    }
+
+    fn control_flush(&self, queue: usize) -> Result<i32, &'static str> {
+        match queue {
+            // Output is handed to the driver as it is written
+            TCOFLUSH => {}
+            TCIFLUSH | TCIOFLUSH => {
+                self.input.lock().flush();
+                self.unthrottle_input();
+            }
+            _ => return Err("Invalid flush queue"),
+        }
+        Ok(0)
+    }
+
+    /// Bytes a read may return without waiting: whole lines in canonical
+    /// mode
+    fn readable_bytes(&self) -> usize {
+        let canonical = self.lflag() & ICANON != 0;
+        let input = self.input.lock();
+        if canonical {
+            input.lines.iter().sum()
+        } else {
+            input.queue.len()
+        }
+    }
+
+    fn control_set_winsize(&self, arg: usize) -> Result<i32, &'static str> {
+        let winsize = read_user::<WinSize>(arg)?;
+        let changed = core::mem::replace(&mut *self.winsize.lock(), winsize) != winsize;
+        if changed {
+            self.signal_foreground(SIGWINCH);
+        }
+        Ok(0)
+    }
+}
+
+fn read_user_i32(arg: usize) -> Result<i32, &'static str> {
+    read_user(arg)
+}
+
+pub(super) fn write_user_i32(arg: usize, value: i32) -> Result<(), &'static str> {
+    write_user(arg, value)
+}
+
+/// Read a structure from a user pointer, or from `arg` itself in kernel
+/// context
+fn read_user<T: Copy>(arg: usize) -> Result<T, &'static str> {
+    let mut value = core::mem::MaybeUninit::<T>::uninit();
+    let bytes = unsafe { core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, core::mem::size_of::<T>()) };
+    signal::read_user(arg, bytes)?;
+    Ok(unsafe { value.assume_init() })
+}
+
+/// Write a structure to a user pointer, or to `arg` itself in kernel context
+fn write_user<T: Copy>(arg: usize, value: T) -> Result<(), &'static str> {
+    let bytes = unsafe { core::slice::from_raw_parts(&value as *const T as *const u8, core::mem::size_of::<T>()) };
+    signal::write_user(arg, bytes)
+}
+
+impl TtyDevice {
+    /// Handle a byte typed on the terminal
+    ///
+    /// Called by the driver, from any context.
+    pub fn handle_input_byte(&self, byte: u8) {
+        let termios = *self.termios.lock();
+        let byte = if termios.c_iflag & ISTRIP != 0 { byte & 0x7f } else { byte };
+        if self.handle_flow_char(byte, &termios) {
+            return;
+        }
+
+        let byte = match byte {
+            b'\r' if termios.c_iflag & IGNCR != 0 => return,
+            b'\r' if termios.c_iflag & ICRNL != 0 => b'\n',
+            b'\n' if termios.c_iflag & INLCR != 0 => b'\r',
+            byte => byte,
+        };
+
+        if termios.c_lflag & ISIG != 0 {
+            let signal = [(VINTR, SIGINT), (VQUIT, SIGQUIT), (VSUSP, SIGTSTP)]
+                .into_iter()
+                .find(|&(index, _)| termios.is_char(byte, index));
+            if let Some((_, sig)) = signal {
+                self.handle_signal_char(byte, sig, &termios);
+                return;
             }
+        }
+
+        if termios.c_lflag & ICANON != 0 {
+            self.edit_line(byte, &termios);
         } else {
             // RAW mode: Pass through directly
-            let mut input_buffer = self.input_buffer.lock();
-            input_buffer.push_back(byte);
-            drop(input_buffer);
+            if termios.c_lflag & ECHO != 0 {
+                self.echo(byte, &termios);
+            }
+            self.update_input(|input| {
+                if input.len() < TTY_BUFFER_SIZE {
+                    input.queue.push_back(byte);
+                }
+            });
             // Wake up waiting processes immediately in RAW mode
             self.input_waker.wake_all();
         }
     }
-    
-    /// Echo character back to output.
-    fn echo_char(&self, byte: u8) {
-        // Get actual UART device and output
-        let device_manager = DeviceManager::get_manager();
-        if let Some(uart_device) = device_manager.get_device(self.uart_device_id) {
-            // Use the new CharDevice API with internal mutability
-            if let Some(char_device) = uart_device.as_char_device() {
-                let _ = char_device.write_byte(byte);
+
+    /// Apply `byte` to the line being edited in canonical mode
+    fn edit_line(&self, byte: u8, termios: &Termios) {
+        let echo = termios.c_lflag & ECHO != 0;
+        let lflag = termios.c_lflag;
+        if termios.is_char(byte, VERASE) {
+            let erased = self.input.lock().line.pop();
+            if let Some(erased) = erased.filter(|_| echo) {
+                self.echo_erase(erased, byte, termios);
+            }
+        } else if termios.is_char(byte, VWERASE) && lflag & IEXTEN != 0 {
+            // Blanks after the word go with it
+            let mut erased = Vec::new();
+            {
+                let mut input = self.input.lock();
+                while input.line.last().is_some_and(|c| c.is_ascii_whitespace()) {
+                    erased.extend(input.line.pop());
+                }
+                while input.line.last().is_some_and(|c| !c.is_ascii_whitespace()) {
+                    erased.extend(input.line.pop());
+                }
+            }
+            for erased in erased.into_iter().filter(|_| echo) {
+                self.echo_erase(erased, byte, termios);
+            }
+        } else if termios.is_char(byte, VKILL) {
+            let erased = core::mem::take(&mut self.input.lock().line);
+            if !echo {
+                return;
+            }
+            if lflag & ECHOKE != 0 && lflag & ECHOE != 0 {
+                for erased in erased.into_iter().rev() {
+                    self.echo_erase(erased, byte, termios);
+                }
+            } else {
+                self.echo(byte, termios);
+                if lflag & ECHOK != 0 {
+                    self.echo(b'\n', termios);
+                }
+            }
+        } else if termios.is_char(byte, VEOF) {
+            // The line is read as it is, without the character
+            self.update_input(TtyInput::commit);
+            self.input_waker.wake_all();
+        } else if byte == b'\n' || termios.is_char(byte, VEOL) || termios.is_char(byte, VEOL2) {
+            if echo || (byte == b'\n' && lflag & ECHONL != 0) {
+                self.echo(byte, termios);
+            }
+            self.update_input(|input| {
+                if input.len() < TTY_BUFFER_SIZE {
+                    input.line.push(byte);
+                    input.commit();
+                }
+            });
+            // Wake up waiting processes
+            self.input_waker.wake_all();
+        } else {
+            // A byte is kept for the end of the line
+            let added = self.update_input(|input| {
+                let fits = input.len() < TTY_BUFFER_SIZE - 1;
+                if fits {
+                    input.line.push(byte);
+                }
+                fits
+            });
+            if added && echo {
+                self.echo(byte, termios);
             }
         }
     }
-    
-    /// Echo backspace sequence.
-    fn echo_backspace(&self) {
-        // Backspace echo: BS + space + BS
-        self.echo_char(0x08);
-        self.echo_char(b' ');
-        self.echo_char(0x08);
+
+    /// Read typed input into `buffer`
+    ///
+    /// In canonical mode the read waits for a line and returns at most one;
+    /// an empty one is the end of file. In raw mode it waits for `VMIN`
+    /// bytes, with `VTIME` for at most that many tenths of a second between
+    /// them, and with neither returns at once.
+    fn read_input(&self, buffer: &mut [u8]) -> usize {
+        if buffer.is_empty() || self.is_hung_up() || !self.check_foreground_read() {
+            return 0;
+        }
+        let termios = *self.termios.lock();
+        let count = if termios.c_lflag & ICANON != 0 {
+            let mut count = 0;
+            wait_for(&[self as &dyn PollOps], None, || {
+                if self.is_hung_up() {
+                    return true;
+                }
+                let canonical = self.lflag() & ICANON != 0;
+                let mut input = self.input.lock();
+                let taken = if canonical {
+                    input.take_line(buffer)
+                } else {
+                    // Switched to raw mode while waiting
+                    Some(input.take(buffer)).filter(|&count| count > 0)
+                };
+                taken.map(|taken| count = taken).is_some()
+            });
+            count
+        } else {
+            self.read_raw(buffer, termios.c_cc[VMIN] as usize, termios.c_cc[VTIME])
+        };
+        if count > 0 {
+            self.unthrottle_input();
+        }
+        count
+    }
+
+    fn read_raw(&self, buffer: &mut [u8], min: usize, time: u8) -> usize {
+        // A hung up TTY has all there is
+        let available = || if self.is_hung_up() { usize::MAX } else { self.input.lock().queue.len() };
+        let wanted = min.min(buffer.len());
+        let timeout = (time > 0).then(|| timeout_ticks(time as u64 * 100_000_000));
+        if min == 0 {
+            // VTIME bounds the wait for the first byte
+            wait_for(&[self as &dyn PollOps], timeout.or(Some(0)), || available() > 0);
+        } else if timeout.is_none() {
+            wait_for(&[self as &dyn PollOps], None, || available() >= wanted);
+        } else {
+            // VTIME bounds the wait between bytes, from the first one on
+            let mut waited = wait_for(&[self as &dyn PollOps], None, || available() > 0);
+            while waited == PollWait::Ready && available() < wanted {
+                let seen = available();
+                waited = wait_for(&[self as &dyn PollOps], timeout, || available() > seen);
+            }
+        }
+        self.input.lock().take(buffer)
+    }
+
+    /// Write `byte` to the terminal, translated as `OPOST` asks
+    fn output_byte(&self, byte: u8, oflag: u32) -> Result<(), &'static str> {
+        if oflag & OPOST == 0 {
+            return self.driver.write_byte(byte);
+        }
+        match byte {
+            b'\n' if oflag & ONLCR != 0 => {
+                self.driver.write_byte(b'\r')?;
+                self.driver.write_byte(b'\n')
+            }
+            b'\r' if oflag & OCRNL != 0 => self.driver.write_byte(b'\n'),
+            byte => self.driver.write_byte(byte),
+        }
+    }
+
+    /// Echo a typed byte, control characters as `^X` with `ECHOCTL`
+    fn echo(&self, byte: u8, termios: &Termios) {
+        if is_echoed_as_control(byte, termios) {
+            let _ = self.output_byte(b'^', termios.c_oflag);
+            let _ = self.output_byte(byte ^ 0x40, termios.c_oflag);
+        } else {
+            let _ = self.output_byte(byte, termios.c_oflag);
+        }
     }
+
+    /// Echo the erasure of `erased` by the erase character `byte`
+    fn echo_erase(&self, erased: u8, byte: u8, termios: &Termios) {
+        if termios.c_lflag & ECHOE == 0 {
+            self.echo(byte, termios);
+            return;
+        }
+        // Backspace echo: BS + space + BS, for each column of the character
+        let columns = if is_echoed_as_control(erased, termios) { 2 } else { 1 };
+        for _ in 0..columns {
+            for c in [0x08, b' ', 0x08] {
+                let _ = self.driver.write_byte(c);
+            }
+        }
+    }
+}
+
+/// Whether `byte` echoes as `^X`
+fn is_echoed_as_control(byte: u8, termios: &Termios) -> bool {
+    termios.c_lflag & ECHOCTL != 0 && (byte < 0x20 || byte == 0x7f) && byte != b'\n' && byte != b'\t'
 }
 
 impl DeviceEventListener for TtyDevice {
@@ -243,68 +1049,271 @@ impl Device for TtyDevice {
 
 impl CharDevice for TtyDevice {
     fn read_byte(&self) -> Option<u8> {
-        // Loop until data becomes available
-        loop {
-            let mut input_buffer = self.input_buffer.lock();
-            if let Some(byte) = input_buffer.pop_front() {
-                return Some(byte);
-            }
-            drop(input_buffer);
-            
-            // No data available, block the current task
-            if let Some(task) = mytask() {
-                // Wait for input to become available
-                // This will return when the task is woken up by input_waker.wake_all()
-                self.input_waker.wait(task.get_id(), task.get_trapframe());
-                
-                // Continue the loop to re-check if data is available
-                continue;
-            } else {
-                // No current task context, return None
-                return None;
-            }
-        }
+        let mut byte = [0u8];
+        (self.read_input(&mut byte) == 1).then_some(byte[0])
+    }
+
+    fn read(&self, buffer: &mut [u8]) -> usize {
+        self.read_input(buffer)
     }
     
     fn write_byte(&self, byte: u8) -> Result<(), &'static str> {
-        // Forward to UART device with line ending conversion
-        let device_manager = DeviceManager::get_manager();
-        if let Some(uart_device) = device_manager.get_device(self.uart_device_id) {
-            // Use the new CharDevice API with internal mutability
-            if let Some(char_device) = uart_device.as_char_device() {
-                // Handle line ending conversion for terminals
-                if byte == b'\n' {
-                    char_device.write_byte(b'\r')?;
-                    char_device.write_byte(b'\n')?;
-                } else {
-                    char_device.write_byte(byte)?;
-                }
-                return Ok(());
-            }
+        if self.is_hung_up() {
+            return Err("Terminal hung up");
+        }
+        let termios = *self.termios.lock();
+        if termios.c_lflag & TOSTOP != 0 && !self.check_foreground_output() {
+            return Err("Interrupted");
+        }
+        // Wait while output is stopped or the driver is behind
+        if !self.driver.wait_writable(2) {
+            return Err("Interrupted");
         }
-        Err("UART device not available")
+        self.output_byte(byte, termios.c_oflag)
     }
     
     fn can_read(&self) -> bool {
-        let input_buffer = self.input_buffer.lock();
-        !input_buffer.is_empty()
+        self.is_hung_up() || self.readable_bytes() > 0 || !self.input.lock().lines.is_empty()
     }
     
     fn can_write(&self) -> bool {
-        // Check if UART device is available
-        let device_manager = DeviceManager::get_manager();
-        if let Some(uart_device) = device_manager.get_device(self.uart_device_id) {
-            if let Some(uart) = uart_device.as_any().downcast_ref::<crate::drivers::uart::virt::Uart>() {
-                return uart.can_write();
-            }
+        self.driver.can_write()
+    }
+
+    fn as_pollable(&self) -> Option<&dyn PollOps> {
+        Some(self)
+    }
+}
+
+impl PollOps for TtyDevice {
+    /// Readable once a line is typed, or a character in raw mode: readers
+    /// are woken then. Output is always accepted. A hung up TTY reads the
+    /// end of file.
+    fn poll_events(&self) -> u32 {
+        if self.is_hung_up() {
+            return POLLIN | POLLHUP;
         }
-        false
+        if self.can_read() { POLLIN | POLLOUT } else { POLLOUT }
+    }
+
+    fn poll_register(&self, task_id: usize) -> bool {
+        self.input_waker.register(task_id);
+        true
+    }
+
+    fn poll_unregister(&self, task_id: usize) {
+        self.input_waker.unregister(task_id);
     }
 }
 
 impl ControlOps for TtyDevice {
-    // TTY devices don't support control operations by default
-    fn control(&self, _command: u32, _arg: usize) -> Result<i32, &'static str> {
-        Err("Control operations not supported")
+    fn control(&self, command: u32, arg: usize) -> Result<i32, &'static str> {
+        use tty_commands::*;
+
+        // Only the foreground group changes the TTY
+        if matches!(command, TIOCSPGRP | TCSETS | TCSETSW | TCSETSF | TCFLSH | TIOCSWINSZ)
+            && !self.check_foreground_output()
+        {
+            return Err("Interrupted");
+        }
+        match command {
+            TIOCSCTTY => self.control_set_ctty(),
+            TIOCGPGRP => self.control_get_pgrp(arg),
+            TIOCSPGRP => self.control_set_pgrp(arg),
+            TIOCNOTTY => self.control_no_tty(),
+            TIOCGSID => self.control_get_sid(arg),
+            TCGETS => write_user(arg, *self.termios.lock()).map(|_| 0),
+            TCSETS | TCSETSW => self.control_set_termios(arg, false),
+            TCSETSF => self.control_set_termios(arg, true),
+            TCFLSH => self.control_flush(arg),
+            FIONREAD => write_user_i32(arg, self.readable_bytes() as i32).map(|_| 0),
+            TIOCGWINSZ => write_user(arg, *self.winsize.lock()).map(|_| 0),
+            TIOCSWINSZ => self.control_set_winsize(arg),
+            _ => Err("Unsupported TTY control command"),
+        }
+    }
+
+    fn supported_control_commands(&self) -> Vec<(u32, &'static str)> {
+        use tty_commands::*;
+        alloc::vec![
+            (TIOCSCTTY, "Make this the controlling terminal"),
+            (TIOCGPGRP, "Get the foreground process group"),
+            (TIOCSPGRP, "Set the foreground process group"),
+            (TIOCNOTTY, "Give up the controlling terminal"),
+            (TIOCGSID, "Get the session of the terminal"),
+            (TCGETS, "Get the line discipline settings"),
+            (TCSETS, "Set the line discipline settings"),
+            (TCSETSW, "Set the line discipline settings after output"),
+            (TCSETSF, "Set the line discipline settings and flush input"),
+            (TCFLSH, "Discard unread input"),
+            (FIONREAD, "Get the number of bytes to read"),
+            (TIOCGWINSZ, "Get the window size"),
+            (TIOCSWINSZ, "Set the window size"),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A driver keeping what the TTY sends to the terminal
+    #[derive(Default)]
+    struct TestDriver {
+        output: Mutex<Vec<u8>>,
+    }
+
+    impl TtyDriver for TestDriver {
+        fn write_byte(&self, byte: u8) -> Result<(), &'static str> {
+            self.output.lock().push(byte);
+            Ok(())
+        }
+    }
+
+    /// A raw TTY without echo
+    fn test_tty(c_iflag: u32) -> TtyDevice {
+        let tty = TtyDevice::new("tty-test", Arc::new(TestDriver::default()));
+        let mut termios = tty.termios.lock();
+        termios.c_iflag = c_iflag;
+        termios.c_lflag &= !(ECHO | ICANON);
+        drop(termios);
+        tty
+    }
+
+    /// A TTY with the default settings, and its driver
+    fn canonical_tty() -> (TtyDevice, Arc<TestDriver>) {
+        let driver = Arc::new(TestDriver::default());
+        (TtyDevice::new("tty-test", driver.clone()), driver)
+    }
+
+    fn type_bytes(tty: &TtyDevice, bytes: &[u8]) {
+        for &byte in bytes {
+            tty.handle_input_byte(byte);
+        }
+    }
+
+    #[test_case]
+    fn test_ixon_stops_and_starts_output() {
+        let tty = test_tty(IXON);
+        tty.handle_input_byte(0x13);
+        assert!(tty.flow.lock().output_stopped);
+        tty.handle_input_byte(b'a');
+        assert!(tty.flow.lock().output_stopped);
+        tty.handle_input_byte(0x11);
+        assert!(!tty.flow.lock().output_stopped);
+        // The flow control characters are not read
+        assert_eq!(tty.input.lock().queue.iter().copied().collect::<Vec<_>>(), [b'a']);
+
+        let tty = test_tty(IXON | IXANY);
+        tty.handle_input_byte(0x13);
+        tty.handle_input_byte(b'b');
+        assert!(!tty.flow.lock().output_stopped);
+        assert_eq!(tty.input.lock().queue.len(), 1);
+
+        let tty = test_tty(0);
+        tty.handle_input_byte(0x13);
+        assert!(!tty.flow.lock().output_stopped);
+        assert_eq!(tty.input.lock().queue.len(), 1);
+    }
+
+    #[test_case]
+    fn test_ixoff_throttles_input() {
+        let tty = test_tty(IXOFF);
+        for _ in 0..TTY_BUFFER_SIZE * 3 / 4 - 1 {
+            tty.handle_input_byte(b'x');
+        }
+        assert!(!tty.flow.lock().input_throttled);
+        tty.handle_input_byte(b'x');
+        assert!(tty.flow.lock().input_throttled);
+
+        // Past the end input is dropped
+        for _ in 0..TTY_BUFFER_SIZE {
+            tty.handle_input_byte(b'x');
+        }
+        assert_eq!(tty.input.lock().queue.len(), TTY_BUFFER_SIZE);
+
+        while tty.input.lock().queue.len() > TTY_BUFFER_SIZE / 4 + 1 {
+            tty.input.lock().queue.pop_front();
+        }
+        tty.unthrottle_input();
+        assert!(tty.flow.lock().input_throttled);
+        tty.input.lock().queue.pop_front();
+        tty.unthrottle_input();
+        assert!(!tty.flow.lock().input_throttled);
+    }
+
+    #[test_case]
+    fn test_canonical_line_editing() {
+        let (tty, driver) = canonical_tty();
+        // Ctrl+W takes back "wor", then Ctrl+U the line, DEL one character
+        type_bytes(&tty, b"bad wor\x17\x15hello x\x7f\r");
+        let mut buffer = [0u8; 16];
+        assert_eq!(tty.read(&mut buffer), 7);
+        assert_eq!(&buffer[..7], b"hello \n");
+        assert_eq!(&driver.output.lock()[..7], b"bad wor");
+        assert!(driver.output.lock().ends_with(b"x\x08 \x08\r\n"));
+
+        // Nothing is read before the line ends, and a line at a time after
+        type_bytes(&tty, b"one");
+        assert!(!tty.can_read());
+        type_bytes(&tty, b"\rtwo\r");
+        assert_eq!(tty.read(&mut buffer), 4);
+        assert_eq!(&buffer[..4], b"one\n");
+        let mut short = [0u8; 2];
+        assert_eq!(tty.read(&mut short), 2);
+        assert_eq!(tty.read(&mut buffer), 2);
+        assert_eq!(&buffer[..2], b"o\n");
+
+        // VEOF hands over the line without a newline, and alone ends the file
+        type_bytes(&tty, b"end\x04\x04");
+        assert_eq!(tty.read(&mut buffer), 3);
+        assert!(tty.can_read());
+        assert_eq!(tty.read(&mut buffer), 0);
+        assert!(!tty.can_read());
+    }
+
+    #[test_case]
+    fn test_control_characters_follow_termios() {
+        let (tty, driver) = canonical_tty();
+        tty.termios.lock().c_cc[VINTR] = 0x07;
+        type_bytes(&tty, b"abc\x07");
+        // The line is discarded and the character echoed as ^G
+        assert!(tty.input.lock().line.is_empty());
+        assert!(driver.output.lock().ends_with(b"^G\r\n"));
+        // Ctrl+C is an ordinary character now
+        type_bytes(&tty, b"\x03\r");
+        let mut buffer = [0u8; 4];
+        assert_eq!(tty.read(&mut buffer), 2);
+        assert_eq!(buffer[0], 0x03);
+
+        tty.termios.lock().c_lflag &= !ISIG;
+        type_bytes(&tty, b"\x07\r");
+        assert_eq!(tty.read(&mut buffer), 2);
+        assert_eq!(buffer[0], 0x07);
+    }
+
+    #[test_case]
+    fn test_raw_mode_reads() {
+        let tty = test_tty(0);
+        let mut buffer = [0u8; 8];
+        // VMIN 0 and VTIME 0 return at once
+        tty.termios.lock().c_cc[VMIN] = 0;
+        assert_eq!(tty.read(&mut buffer), 0);
+        type_bytes(&tty, b"ab\r");
+        assert_eq!(tty.read(&mut buffer), 3);
+        assert_eq!(&buffer[..3], b"ab\r");
+
+        // Typed in raw mode, read as a line in canonical mode
+        #[repr(C, align(64))]
+        struct Aligned(Termios);
+        type_bytes(&tty, b"xy");
+        let mut termios = Aligned(*tty.termios.lock());
+        termios.0.c_lflag |= ICANON;
+        tty.control(tty_commands::TCSETS, &termios.0 as *const Termios as usize).unwrap();
+        assert_eq!(tty.read(&mut buffer), 2);
+        type_bytes(&tty, b"half");
+        termios.0.c_lflag &= !ICANON;
+        tty.control(tty_commands::TCSETS, &termios.0 as *const Termios as usize).unwrap();
+        assert_eq!(tty.read(&mut buffer), 4);
     }
 }
@@ -5,10 +5,13 @@
 
 extern crate alloc;
 use core::any::Any;
+use alloc::string::String;
 use alloc::sync::Weak;
 use alloc::vec::Vec;
 use spin::Mutex;
 
+use super::manager::SharedDevice;
+
 /// Generic device event trait.
 /// 
 /// All device events must implement this trait to be handled by the event system.
@@ -42,7 +45,7 @@ pub struct DeviceEventEmitter {
 }
 
 impl DeviceEventEmitter {
-    pub fn new() -> Self {
+    pub const fn new() -> Self {
         Self {
             listeners: Mutex::new(Vec::new()),
         }
@@ -53,6 +56,11 @@ impl DeviceEventEmitter {
         listeners.push(listener);
     }
     
+    /// Whether a listener is registered, which may be gone already
+    pub fn has_listeners(&self) -> bool {
+        !self.listeners.lock().is_empty()
+    }
+
     pub fn emit(&self, event: &dyn DeviceEvent) {
         let mut listeners = self.listeners.lock();
         
@@ -123,6 +131,40 @@ impl DeviceEvent for ErrorEvent {
     }
 }
 
+/// Hotplug action reported by `DeviceHotplugEvent`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HotplugAction {
+    /// The device was registered with the device manager
+    Added,
+    /// The device was unregistered and must no longer be used
+    Removed,
+    /// A property of the device changed, such as the size of its medium
+    Changed,
+}
+
+/// Device hotplug event.
+/// 
+/// This event is emitted by the device manager when a device is added,
+/// removed or changed at runtime. Listeners holding on to the device (block
+/// layer, mounted filesystems) use it to release their references.
+pub struct DeviceHotplugEvent {
+    pub action: HotplugAction,
+    pub device_id: usize,
+    pub device: SharedDevice,
+    /// Name the device is registered under, if any
+    pub name: Option<String>,
+}
+
+impl DeviceEvent for DeviceHotplugEvent {
+    fn event_type(&self) -> &'static str {
+        "hotplug"
+    }
+    
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
 /// Interrupt capable device trait.
 /// 
 /// Devices that can handle interrupts must implement this trait.
@@ -0,0 +1,941 @@
+//! GPIO
+//!
+//! A GPIO controller drives and reads a number of lines: pins that are
+//! outputs (an LED, a reset line) or inputs (a button, a card detect). A
+//! driver implements [`GpioChip`] for its controller, wraps it in a
+//! [`GpioDevice`] and registers it with [`register`]; its interrupt handler
+//! reports the edges seen on the lines with [`GpioDevice::report_edge`].
+//!
+//! Every controller is the character device `/dev/gpiochipN` and speaks the
+//! v2 GPIO character device protocol of Linux, so that libgpiod and the
+//! tools built on it work unmodified:
+//!
+//! - `GPIO_GET_CHIPINFO_IOCTL` and `GPIO_V2_GET_LINEINFO_IOCTL` describe the
+//!   controller and its lines.
+//! - `GPIO_V2_GET_LINE_IOCTL` requests lines for the caller, who gets a
+//!   handle to a [`GpioLineRequest`] back in the `fd` field. A line is held
+//!   by one request at a time; it is released when the request is closed.
+//! - on a request, `GPIO_V2_LINE_GET_VALUES_IOCTL` and
+//!   `GPIO_V2_LINE_SET_VALUES_IOCTL` read and drive its lines, and
+//!   `GPIO_V2_LINE_SET_CONFIG_IOCTL` changes their configuration.
+//! - a request of lines with edge detection is readable: a read returns
+//!   whole `struct gpio_v2_line_event` records ([`LineEvent`]), blocking
+//!   until an edge is seen. A reader that falls behind loses the oldest.
+//!
+//! Values are logical: an active-low line reads 1 when its pin is low, and
+//! its rising edges are those of the pin going low. Debouncing is not
+//! supported.
+
+use core::any::Any;
+use core::mem::size_of;
+
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::{Mutex, Once};
+
+use super::char::CharDevice;
+use super::manager::DeviceManager;
+use super::{Device, DeviceType};
+use crate::object::capability::poll::POLLIN;
+use crate::object::capability::{ControlOps, MemoryMappingOps, PollOps, StreamError, StreamOps};
+use crate::object::KernelObject;
+use crate::sync::waker::Waker;
+use crate::task::mytask;
+use crate::task::signal::{read_user, write_user};
+use crate::timer::get_time_ns;
+
+pub const GPIO_MAX_NAME_SIZE: usize = 32;
+/// Most lines in a request
+pub const GPIO_V2_LINES_MAX: usize = 64;
+/// Most attributes in a line configuration
+pub const GPIO_V2_LINE_NUM_ATTRS_MAX: usize = 10;
+
+/// Line flags
+pub const GPIO_V2_LINE_FLAG_USED: u64 = 1 << 0;
+pub const GPIO_V2_LINE_FLAG_ACTIVE_LOW: u64 = 1 << 1;
+pub const GPIO_V2_LINE_FLAG_INPUT: u64 = 1 << 2;
+pub const GPIO_V2_LINE_FLAG_OUTPUT: u64 = 1 << 3;
+pub const GPIO_V2_LINE_FLAG_EDGE_RISING: u64 = 1 << 4;
+pub const GPIO_V2_LINE_FLAG_EDGE_FALLING: u64 = 1 << 5;
+pub const GPIO_V2_LINE_FLAG_OPEN_DRAIN: u64 = 1 << 6;
+pub const GPIO_V2_LINE_FLAG_OPEN_SOURCE: u64 = 1 << 7;
+pub const GPIO_V2_LINE_FLAG_BIAS_PULL_UP: u64 = 1 << 8;
+pub const GPIO_V2_LINE_FLAG_BIAS_PULL_DOWN: u64 = 1 << 9;
+pub const GPIO_V2_LINE_FLAG_BIAS_DISABLED: u64 = 1 << 10;
+pub const GPIO_V2_LINE_FLAG_EVENT_CLOCK_REALTIME: u64 = 1 << 11;
+
+const EDGE_FLAGS: u64 = GPIO_V2_LINE_FLAG_EDGE_RISING | GPIO_V2_LINE_FLAG_EDGE_FALLING;
+const DRIVE_FLAGS: u64 = GPIO_V2_LINE_FLAG_OPEN_DRAIN | GPIO_V2_LINE_FLAG_OPEN_SOURCE;
+const BIAS_FLAGS: u64 =
+    GPIO_V2_LINE_FLAG_BIAS_PULL_UP | GPIO_V2_LINE_FLAG_BIAS_PULL_DOWN | GPIO_V2_LINE_FLAG_BIAS_DISABLED;
+/// Flags a request may ask for
+const REQUEST_FLAGS: u64 = GPIO_V2_LINE_FLAG_ACTIVE_LOW
+    | GPIO_V2_LINE_FLAG_INPUT
+    | GPIO_V2_LINE_FLAG_OUTPUT
+    | EDGE_FLAGS
+    | DRIVE_FLAGS
+    | BIAS_FLAGS
+    | GPIO_V2_LINE_FLAG_EVENT_CLOCK_REALTIME;
+
+/// Line attributes
+pub const GPIO_V2_LINE_ATTR_ID_FLAGS: u32 = 1;
+pub const GPIO_V2_LINE_ATTR_ID_OUTPUT_VALUES: u32 = 2;
+pub const GPIO_V2_LINE_ATTR_ID_DEBOUNCE: u32 = 3;
+
+/// Edge events
+pub const GPIO_V2_LINE_EVENT_RISING_EDGE: u32 = 1;
+pub const GPIO_V2_LINE_EVENT_FALLING_EDGE: u32 = 2;
+
+/// Events a request holds per line unless it asks for another size
+const EVENTS_PER_LINE: usize = 16;
+
+/// GPIO control commands (Linux ioctl numbers)
+pub mod gpio_commands {
+    /// The type of every GPIO command
+    pub const GPIO_IOCTL_TYPE: u32 = 0xb4;
+
+    /// Describe the controller (arg: *mut `struct gpiochip_info`)
+    pub const GPIO_GET_CHIPINFO_IOCTL: u32 = 0x8044b401;
+    /// Describe the line of the `offset` field (arg: *mut `struct gpio_v2_line_info`)
+    pub const GPIO_V2_GET_LINEINFO_IOCTL: u32 = 0xc100b405;
+    /// Request lines (arg: *mut `struct gpio_v2_line_request`)
+    pub const GPIO_V2_GET_LINE_IOCTL: u32 = 0xc250b407;
+    /// Reconfigure the lines of a request (arg: *const `struct gpio_v2_line_config`)
+    pub const GPIO_V2_LINE_SET_CONFIG_IOCTL: u32 = 0xc110b40d;
+    /// Read the lines of a request (arg: *mut `struct gpio_v2_line_values`)
+    pub const GPIO_V2_LINE_GET_VALUES_IOCTL: u32 = 0xc010b40e;
+    /// Drive the lines of a request (arg: *const `struct gpio_v2_line_values`)
+    pub const GPIO_V2_LINE_SET_VALUES_IOCTL: u32 = 0xc010b40f;
+}
+
+use gpio_commands::*;
+
+/// Whether `command` is a GPIO control command
+pub fn is_gpio_command(command: u32) -> bool {
+    (command >> 8) & 0xff == GPIO_IOCTL_TYPE
+}
+
+/// Sizes of the structures of the protocol
+const CHIP_INFO_SIZE: usize = 68;
+const LINE_INFO_SIZE: usize = 256;
+const LINE_CONFIG_SIZE: usize = 272;
+const LINE_REQUEST_SIZE: usize = 592;
+const LINE_VALUES_SIZE: usize = 16;
+/// Offsets in `struct gpio_v2_line_request`
+const REQUEST_CONSUMER: usize = 256;
+const REQUEST_CONFIG: usize = 288;
+const REQUEST_NUM_LINES: usize = 560;
+const REQUEST_EVENT_BUFFER_SIZE: usize = 564;
+/// Offset of the `fd` field, which takes the handle of the request
+pub const LINE_REQUEST_FD: usize = 588;
+
+/// Pull resistor of an input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bias {
+    PullUp,
+    PullDown,
+    Disabled,
+}
+
+/// A GPIO controller
+///
+/// Lines are numbered from 0 and values are those of the pins; the GPIO
+/// layer takes care of active-low lines and of which request holds a line.
+/// Only the direction, value and edge detection are required; a controller
+/// without pull resistors keeps the defaults.
+pub trait GpioChip: Send + Sync {
+    /// Name of the controller in the hardware (`sifive-gpio@10060000`)
+    fn label(&self) -> &str;
+
+    fn line_count(&self) -> usize;
+
+    /// Name of the line on the board, if known
+    fn line_name(&self, offset: usize) -> Option<&str> {
+        let _ = offset;
+        None
+    }
+
+    fn is_output(&self, offset: usize) -> bool;
+
+    fn direction_input(&self, offset: usize) -> Result<(), &'static str>;
+
+    /// Make `offset` an output driving `value`
+    fn direction_output(&self, offset: usize, value: bool) -> Result<(), &'static str>;
+
+    fn get(&self, offset: usize) -> bool;
+
+    fn set(&self, offset: usize, value: bool);
+
+    fn set_bias(&self, offset: usize, bias: Bias) -> Result<(), &'static str> {
+        let _ = (offset, bias);
+        Err("Bias not supported")
+    }
+
+    /// Make `offset` an open drain (`open_drain`) or open source output
+    fn set_open_drive(&self, offset: usize, open_drain: bool) -> Result<(), &'static str> {
+        let _ = (offset, open_drain);
+        Err("Open drain and open source not supported")
+    }
+
+    /// Report the edges of `offset` through [`GpioDevice::report_edge`]
+    fn set_edges(&self, offset: usize, rising: bool, falling: bool) -> Result<(), &'static str> {
+        let _ = (offset, rising, falling);
+        if rising || falling { Err("Edge detection not supported") } else { Ok(()) }
+    }
+}
+
+/// An edge seen on a line: `struct gpio_v2_line_event` of Linux
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LineEvent {
+    pub timestamp_ns: u64,
+    pub id: u32,
+    pub offset: u32,
+    /// Number of the event in the request
+    pub seqno: u32,
+    /// Number of the event on the line
+    pub line_seqno: u32,
+    pub padding: [u32; 6],
+}
+
+pub const LINE_EVENT_SIZE: usize = size_of::<LineEvent>();
+
+impl LineEvent {
+    pub fn to_bytes(&self) -> [u8; LINE_EVENT_SIZE] {
+        let mut bytes = [0; LINE_EVENT_SIZE];
+        bytes[0..8].copy_from_slice(&self.timestamp_ns.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.id.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.offset.to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.seqno.to_le_bytes());
+        bytes[20..24].copy_from_slice(&self.line_seqno.to_le_bytes());
+        bytes
+    }
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+/// Copy `text` into the NUL-terminated name field `field`
+fn put_name(field: &mut [u8], text: &str) {
+    let len = text.len().min(field.len() - 1);
+    field[..len].copy_from_slice(&text.as_bytes()[..len]);
+}
+
+/// `struct gpio_v2_line_config`: flags for all lines, overridden for some
+/// by attributes
+#[derive(Debug, Clone, Default)]
+struct LineConfig {
+    flags: u64,
+    /// (id, value, mask of the lines of the request it applies to)
+    attributes: Vec<(u32, u64, u64)>,
+}
+
+impl LineConfig {
+    fn parse(bytes: &[u8]) -> Result<Self, &'static str> {
+        let count = u32_at(bytes, 8) as usize;
+        if count > GPIO_V2_LINE_NUM_ATTRS_MAX || bytes[12..32].iter().any(|&byte| byte != 0) {
+            return Err("Invalid line configuration");
+        }
+        let attributes = (0..count)
+            .map(|index| {
+                let attribute = &bytes[32 + index * 24..56 + index * 24];
+                (u32_at(attribute, 0), u64_at(attribute, 8), u64_at(attribute, 16))
+            })
+            .collect();
+        Ok(Self { flags: u64_at(bytes, 0), attributes })
+    }
+
+    /// The last attribute `id` applying to line `index` of the request
+    fn attribute(&self, id: u32, index: usize) -> Option<u64> {
+        self.attributes
+            .iter()
+            .rev()
+            .find(|&&(found, _, mask)| found == id && mask & 1 << index != 0)
+            .map(|&(_, value, _)| value)
+    }
+
+    fn line_flags(&self, index: usize) -> u64 {
+        self.attribute(GPIO_V2_LINE_ATTR_ID_FLAGS, index).unwrap_or(self.flags)
+    }
+
+    fn output_value(&self, index: usize) -> bool {
+        self.attribute(GPIO_V2_LINE_ATTR_ID_OUTPUT_VALUES, index).is_some_and(|values| values & 1 << index != 0)
+    }
+
+    fn validate(&self, lines: usize) -> Result<(), &'static str> {
+        for index in 0..lines {
+            let flags = self.line_flags(index);
+            let output = flags & GPIO_V2_LINE_FLAG_OUTPUT != 0;
+            let input = flags & GPIO_V2_LINE_FLAG_INPUT != 0;
+            let valid = flags & !REQUEST_FLAGS == 0
+                && !(input && output)
+                && (flags & EDGE_FLAGS == 0 || input)
+                && (flags & DRIVE_FLAGS == 0 || output)
+                && (flags & DRIVE_FLAGS).count_ones() <= 1
+                && (flags & BIAS_FLAGS).count_ones() <= 1
+                && (flags & BIAS_FLAGS == 0 || input || output);
+            if !valid {
+                return Err("Invalid line flags");
+            }
+            if self.attribute(GPIO_V2_LINE_ATTR_ID_DEBOUNCE, index).is_some_and(|period| period != 0) {
+                return Err("Debounce not supported");
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Who holds a line and how
+#[derive(Debug, Clone, Default)]
+struct LineState {
+    consumer: Option<String>,
+    flags: u64,
+}
+
+/// A GPIO controller and its character device
+pub struct GpioDevice {
+    chip: Arc<dyn GpioChip>,
+    lines: Mutex<Vec<LineState>>,
+    /// Requests holding lines, which take the edges
+    requests: Mutex<Vec<Weak<GpioLineRequest>>>,
+    this: Weak<GpioDevice>,
+    /// Id in the device manager and number, once registered
+    device_id: Once<(usize, usize)>,
+}
+
+impl GpioDevice {
+    pub fn new(chip: Arc<dyn GpioChip>) -> Arc<Self> {
+        let lines = alloc::vec![LineState::default(); chip.line_count()];
+        Arc::new_cyclic(|this| Self {
+            chip,
+            lines: Mutex::new(lines),
+            requests: Mutex::new(Vec::new()),
+            this: this.clone(),
+            device_id: Once::new(),
+        })
+    }
+
+    pub fn chip(&self) -> &Arc<dyn GpioChip> {
+        &self.chip
+    }
+
+    /// The pin of `offset` went high (`rising`) or low
+    ///
+    /// Called by the driver for the lines it detects edges of, from any
+    /// context; the request holding the line queues an event if it asked
+    /// for this edge.
+    pub fn report_edge(&self, offset: usize, rising: bool) {
+        let requests: Vec<Arc<GpioLineRequest>> = self.requests.lock().iter().filter_map(Weak::upgrade).collect();
+        for request in requests {
+            if let Some(index) = request.offsets.iter().position(|&line| line as usize == offset) {
+                request.push_event(index, rising);
+            }
+        }
+    }
+
+    /// `gpiochipN`, or the label before the controller is registered
+    fn chip_name(&self) -> String {
+        match self.device_id.get() {
+            Some(&(_, number)) => format!("gpiochip{}", number),
+            None => self.chip.label().into(),
+        }
+    }
+
+    fn chip_info(&self) -> [u8; CHIP_INFO_SIZE] {
+        let mut bytes = [0; CHIP_INFO_SIZE];
+        put_name(&mut bytes[0..32], &self.chip_name());
+        put_name(&mut bytes[32..64], self.chip.label());
+        bytes[64..68].copy_from_slice(&(self.chip.line_count() as u32).to_le_bytes());
+        bytes
+    }
+
+    fn line_info(&self, offset: usize) -> Result<[u8; LINE_INFO_SIZE], &'static str> {
+        let state = self.lines.lock().get(offset).cloned().ok_or("Invalid line offset")?;
+        let mut bytes = [0; LINE_INFO_SIZE];
+        put_name(&mut bytes[0..32], self.chip.line_name(offset).unwrap_or(""));
+        let flags = match &state.consumer {
+            Some(consumer) => {
+                put_name(&mut bytes[32..64], consumer);
+                state.flags | GPIO_V2_LINE_FLAG_USED
+            }
+            None if self.chip.is_output(offset) => GPIO_V2_LINE_FLAG_OUTPUT,
+            None => GPIO_V2_LINE_FLAG_INPUT,
+        };
+        bytes[64..68].copy_from_slice(&(offset as u32).to_le_bytes());
+        bytes[72..80].copy_from_slice(&flags.to_le_bytes());
+        Ok(bytes)
+    }
+
+    /// Take the lines `offsets` for `consumer` and configure them
+    fn request_lines(
+        &self,
+        offsets: &[u32],
+        consumer: &str,
+        config: &LineConfig,
+        event_buffer_size: usize,
+    ) -> Result<Arc<GpioLineRequest>, &'static str> {
+        if offsets.is_empty() || offsets.len() > GPIO_V2_LINES_MAX {
+            return Err("Invalid number of lines");
+        }
+        config.validate(offsets.len())?;
+        let device = self.this.upgrade().ok_or("GPIO controller is gone")?;
+        {
+            let mut lines = self.lines.lock();
+            for (index, &offset) in offsets.iter().enumerate() {
+                let offset = offset as usize;
+                if offset >= lines.len() || offsets[..index].contains(&(offset as u32)) {
+                    return Err("Invalid line offset");
+                }
+                if lines[offset].consumer.is_some() {
+                    return Err("Line is busy");
+                }
+            }
+            let consumer = if consumer.is_empty() { "?" } else { consumer };
+            for &offset in offsets {
+                lines[offset as usize].consumer = Some(consumer.into());
+            }
+        }
+
+        let capacity = match event_buffer_size {
+            0 => EVENTS_PER_LINE * offsets.len(),
+            size => size.min(EVENTS_PER_LINE * GPIO_V2_LINES_MAX),
+        };
+        let request = Arc::new(GpioLineRequest {
+            device,
+            offsets: offsets.to_vec(),
+            state: Mutex::new(RequestState {
+                flags: alloc::vec![0; offsets.len()],
+                events: VecDeque::new(),
+                capacity,
+                seqno: 0,
+                line_seqnos: alloc::vec![0; offsets.len()],
+            }),
+            waker: Waker::new_interruptible("gpio_line"),
+        });
+        // Dropping the request on failure releases the lines
+        request.configure(config)?;
+        let mut requests = self.requests.lock();
+        requests.retain(|request| request.strong_count() > 0);
+        requests.push(Arc::downgrade(&request));
+        Ok(request)
+    }
+
+    /// Give back the lines of a request
+    fn release(&self, offsets: &[u32], flags: &[u64]) {
+        let mut lines = self.lines.lock();
+        for (&offset, &flags) in offsets.iter().zip(flags) {
+            if flags & EDGE_FLAGS != 0 {
+                let _ = self.chip.set_edges(offset as usize, false, false);
+            }
+            if let Some(line) = lines.get_mut(offset as usize) {
+                *line = LineState::default();
+            }
+        }
+    }
+
+    /// `GPIO_V2_GET_LINE_IOCTL`: request lines and hand the caller a handle
+    fn control_get_line(&self, arg: usize) -> Result<i32, &'static str> {
+        let mut bytes = [0u8; LINE_REQUEST_SIZE];
+        read_user(arg, &mut bytes)?;
+        let count = u32_at(&bytes, REQUEST_NUM_LINES) as usize;
+        if count > GPIO_V2_LINES_MAX {
+            return Err("Invalid number of lines");
+        }
+        let offsets: Vec<u32> = (0..count).map(|index| u32_at(&bytes, index * 4)).collect();
+        let consumer = &bytes[REQUEST_CONSUMER..REQUEST_CONSUMER + GPIO_MAX_NAME_SIZE];
+        let consumer = consumer.split(|&byte| byte == 0).next().unwrap_or(&[]);
+        let consumer = core::str::from_utf8(consumer).map_err(|_| "Invalid consumer name")?;
+        let config = LineConfig::parse(&bytes[REQUEST_CONFIG..REQUEST_CONFIG + LINE_CONFIG_SIZE])?;
+        let event_buffer_size = u32_at(&bytes, REQUEST_EVENT_BUFFER_SIZE) as usize;
+
+        let request = self.request_lines(&offsets, consumer, &config, event_buffer_size)?;
+        let task = mytask().ok_or("No task to hold the line request")?;
+        let handle = task
+            .handle_table
+            .write()
+            .insert(KernelObject::from_gpio_lines(request))
+            .map_err(|_| "Too many handles")?;
+        if let Err(error) = write_user(arg + LINE_REQUEST_FD, &(handle as i32).to_le_bytes()) {
+            task.handle_table.write().remove(handle);
+            return Err(error);
+        }
+        Ok(0)
+    }
+}
+
+impl Device for GpioDevice {
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Char
+    }
+
+    fn name(&self) -> &'static str {
+        "gpiochip"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn as_char_device(&self) -> Option<&dyn CharDevice> {
+        Some(self)
+    }
+}
+
+impl CharDevice for GpioDevice {
+    fn read_byte(&self) -> Option<u8> {
+        // Lines are read through requests
+        None
+    }
+
+    fn write_byte(&self, _byte: u8) -> Result<(), &'static str> {
+        Err("Lines are driven through requests")
+    }
+
+    fn can_read(&self) -> bool {
+        false
+    }
+
+    fn can_write(&self) -> bool {
+        false
+    }
+}
+
+impl ControlOps for GpioDevice {
+    fn control(&self, command: u32, arg: usize) -> Result<i32, &'static str> {
+        match command {
+            GPIO_GET_CHIPINFO_IOCTL => write_user(arg, &self.chip_info()).map(|_| 0),
+            GPIO_V2_GET_LINEINFO_IOCTL => {
+                let mut bytes = [0u8; LINE_INFO_SIZE];
+                read_user(arg, &mut bytes)?;
+                if bytes.iter().enumerate().any(|(at, &byte)| !(64..68).contains(&at) && byte != 0) {
+                    return Err("Invalid line info query");
+                }
+                let info = self.line_info(u32_at(&bytes, 64) as usize)?;
+                write_user(arg, &info).map(|_| 0)
+            }
+            GPIO_V2_GET_LINE_IOCTL => self.control_get_line(arg),
+            _ => Err("Unsupported GPIO control command"),
+        }
+    }
+}
+
+impl MemoryMappingOps for GpioDevice {
+    fn get_mapping_info(&self, _offset: usize, _length: usize) -> Result<(usize, usize, bool), &'static str> {
+        Err("Memory mapping not supported")
+    }
+
+    fn on_mapped(&self, _vaddr: usize, _paddr: usize, _length: usize, _offset: usize) {}
+
+    fn on_unmapped(&self, _vaddr: usize, _length: usize) {}
+
+    fn supports_mmap(&self) -> bool {
+        false
+    }
+}
+
+struct RequestState {
+    /// Flags of each line of the request
+    flags: Vec<u64>,
+    events: VecDeque<LineEvent>,
+    /// Most events queued
+    capacity: usize,
+    seqno: u32,
+    line_seqnos: Vec<u32>,
+}
+
+/// Lines held by a task
+pub struct GpioLineRequest {
+    device: Arc<GpioDevice>,
+    /// Line of each bit of the values
+    offsets: Vec<u32>,
+    state: Mutex<RequestState>,
+    /// Readers waiting for an edge
+    waker: Waker,
+}
+
+impl GpioLineRequest {
+    /// Apply `config` to the lines, as far as the controller goes along
+    fn configure(&self, config: &LineConfig) -> Result<(), &'static str> {
+        config.validate(self.offsets.len())?;
+        let chip = &self.device.chip;
+        let mut state = self.state.lock();
+        for (index, &offset) in self.offsets.iter().enumerate() {
+            let offset = offset as usize;
+            let flags = config.line_flags(index);
+            let active_low = flags & GPIO_V2_LINE_FLAG_ACTIVE_LOW != 0;
+            if flags & GPIO_V2_LINE_FLAG_OUTPUT != 0 {
+                if flags & DRIVE_FLAGS != 0 {
+                    chip.set_open_drive(offset, flags & GPIO_V2_LINE_FLAG_OPEN_DRAIN != 0)?;
+                }
+                chip.direction_output(offset, config.output_value(index) != active_low)?;
+            } else if flags & GPIO_V2_LINE_FLAG_INPUT != 0 {
+                chip.direction_input(offset)?;
+            }
+            match flags & BIAS_FLAGS {
+                GPIO_V2_LINE_FLAG_BIAS_PULL_UP => chip.set_bias(offset, Bias::PullUp)?,
+                GPIO_V2_LINE_FLAG_BIAS_PULL_DOWN => chip.set_bias(offset, Bias::PullDown)?,
+                GPIO_V2_LINE_FLAG_BIAS_DISABLED => chip.set_bias(offset, Bias::Disabled)?,
+                _ => {}
+            }
+            // The pin edges of the logical ones
+            let (rising, falling) = (flags & GPIO_V2_LINE_FLAG_EDGE_RISING != 0, flags & GPIO_V2_LINE_FLAG_EDGE_FALLING != 0);
+            let (rising, falling) = if active_low { (falling, rising) } else { (rising, falling) };
+            if flags & EDGE_FLAGS != 0 || state.flags[index] & EDGE_FLAGS != 0 {
+                chip.set_edges(offset, rising, falling)?;
+            }
+            state.flags[index] = flags;
+            if let Some(line) = self.device.lines.lock().get_mut(offset) {
+                line.flags = flags;
+            }
+        }
+        if state.flags.iter().all(|flags| flags & EDGE_FLAGS == 0) {
+            state.events.clear();
+        }
+        Ok(())
+    }
+
+    /// Logical values of the lines in `mask`
+    fn get_values(&self, mask: u64) -> u64 {
+        let state = self.state.lock();
+        let mut bits = 0;
+        for (index, &offset) in self.offsets.iter().enumerate() {
+            if mask & 1 << index == 0 {
+                continue;
+            }
+            let active_low = state.flags[index] & GPIO_V2_LINE_FLAG_ACTIVE_LOW != 0;
+            if self.device.chip.get(offset as usize) != active_low {
+                bits |= 1 << index;
+            }
+        }
+        bits
+    }
+
+    /// Drive the lines in `mask` to the logical values of `bits`
+    fn set_values(&self, bits: u64, mask: u64) -> Result<(), &'static str> {
+        let state = self.state.lock();
+        if mask == 0 || (self.offsets.len() < 64 && mask >> self.offsets.len() != 0) {
+            return Err("Invalid line mask");
+        }
+        let lines = || self.offsets.iter().enumerate().filter(|(index, _)| mask & 1 << index != 0);
+        if lines().any(|(index, _)| state.flags[index] & GPIO_V2_LINE_FLAG_OUTPUT == 0) {
+            return Err("Operation not permitted");
+        }
+        for (index, &offset) in lines() {
+            let active_low = state.flags[index] & GPIO_V2_LINE_FLAG_ACTIVE_LOW != 0;
+            self.device.chip.set(offset as usize, (bits & 1 << index != 0) != active_low);
+        }
+        Ok(())
+    }
+
+    /// The pin of line `index` went high (`rising`) or low
+    fn push_event(&self, index: usize, rising: bool) {
+        let mut state = self.state.lock();
+        let flags = state.flags[index];
+        // A rising pin is a falling line when it is active low
+        let rising = rising != (flags & GPIO_V2_LINE_FLAG_ACTIVE_LOW != 0);
+        let (id, wanted) = match rising {
+            true => (GPIO_V2_LINE_EVENT_RISING_EDGE, GPIO_V2_LINE_FLAG_EDGE_RISING),
+            false => (GPIO_V2_LINE_EVENT_FALLING_EDGE, GPIO_V2_LINE_FLAG_EDGE_FALLING),
+        };
+        if flags & wanted == 0 {
+            return;
+        }
+        let timestamp_ns = match flags & GPIO_V2_LINE_FLAG_EVENT_CLOCK_REALTIME {
+            0 => get_time_ns(),
+            _ => crate::time::realtime_ns(),
+        };
+        state.seqno = state.seqno.wrapping_add(1);
+        state.line_seqnos[index] = state.line_seqnos[index].wrapping_add(1);
+        let event = LineEvent {
+            timestamp_ns,
+            id,
+            offset: self.offsets[index],
+            seqno: state.seqno,
+            line_seqno: state.line_seqnos[index],
+            padding: [0; 6],
+        };
+        if state.events.len() >= state.capacity {
+            state.events.pop_front();
+        }
+        state.events.push_back(event);
+        drop(state);
+        self.waker.wake_all();
+    }
+
+    fn has_events(&self) -> bool {
+        !self.state.lock().events.is_empty()
+    }
+}
+
+impl Drop for GpioLineRequest {
+    fn drop(&mut self) {
+        let flags = core::mem::take(&mut self.state.get_mut().flags);
+        self.device.release(&self.offsets, &flags);
+    }
+}
+
+impl StreamOps for GpioLineRequest {
+    /// Read the edge events that fit in `buffer`, blocking while there are
+    /// none
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, StreamError> {
+        let count = buffer.len() / LINE_EVENT_SIZE;
+        if count == 0 {
+            return Err(StreamError::InvalidArgument);
+        }
+        if self.state.lock().flags.iter().all(|flags| flags & EDGE_FLAGS == 0) {
+            return Err(StreamError::NotSupported);
+        }
+        loop {
+            {
+                let mut state = self.state.lock();
+                if !state.events.is_empty() {
+                    let count = count.min(state.events.len());
+                    for (chunk, event) in buffer.chunks_exact_mut(LINE_EVENT_SIZE).zip(state.events.drain(..count)) {
+                        chunk.copy_from_slice(&event.to_bytes());
+                    }
+                    return Ok(count * LINE_EVENT_SIZE);
+                }
+            }
+            let Some(task) = mytask() else {
+                return Err(StreamError::WouldBlock);
+            };
+            let task_id = task.get_id();
+            self.waker.wait_unless(task_id, task.get_trapframe(), || {
+                self.has_events() || mytask().is_some_and(|task| task.signals.has_pending())
+            });
+            if task.signals.has_pending() {
+                return Err(StreamError::Interrupted);
+            }
+        }
+    }
+
+    fn write(&self, _buffer: &[u8]) -> Result<usize, StreamError> {
+        Err(StreamError::NotSupported)
+    }
+}
+
+impl PollOps for GpioLineRequest {
+    fn poll_events(&self) -> u32 {
+        if self.has_events() { POLLIN } else { 0 }
+    }
+
+    fn poll_register(&self, task_id: usize) -> bool {
+        self.waker.register(task_id);
+        true
+    }
+
+    fn poll_unregister(&self, task_id: usize) {
+        self.waker.unregister(task_id);
+    }
+}
+
+impl ControlOps for GpioLineRequest {
+    fn control(&self, command: u32, arg: usize) -> Result<i32, &'static str> {
+        match command {
+            GPIO_V2_LINE_GET_VALUES_IOCTL => {
+                let mut bytes = [0u8; LINE_VALUES_SIZE];
+                read_user(arg, &mut bytes)?;
+                let mask = u64_at(&bytes, 8);
+                if mask == 0 {
+                    return Err("Invalid line mask");
+                }
+                bytes[0..8].copy_from_slice(&self.get_values(mask).to_le_bytes());
+                write_user(arg, &bytes).map(|_| 0)
+            }
+            GPIO_V2_LINE_SET_VALUES_IOCTL => {
+                let mut bytes = [0u8; LINE_VALUES_SIZE];
+                read_user(arg, &mut bytes)?;
+                self.set_values(u64_at(&bytes, 0), u64_at(&bytes, 8)).map(|_| 0)
+            }
+            GPIO_V2_LINE_SET_CONFIG_IOCTL => {
+                let mut bytes = [0u8; LINE_CONFIG_SIZE];
+                read_user(arg, &mut bytes)?;
+                self.configure(&LineConfig::parse(&bytes)?).map(|_| 0)
+            }
+            _ => Err("Unsupported GPIO control command"),
+        }
+    }
+}
+
+impl core::fmt::Debug for GpioLineRequest {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("GpioLineRequest").field("offsets", &self.offsets).finish()
+    }
+}
+
+/// Add the controller `device` as `/dev/gpiochipN` and return N
+pub fn register(device: Arc<GpioDevice>) -> usize {
+    let manager = DeviceManager::get_manager();
+    let number = (0..).find(|number| manager.get_device_by_name(&format!("gpiochip{}", number)).is_none()).unwrap();
+    let device_id = manager.register_device_with_name(format!("gpiochip{}", number), device.clone());
+    device.device_id.call_once(|| (device_id, number));
+    number
+}
+
+/// Remove the controller `device`; its requests keep their lines until
+/// closed but no longer see edges
+pub fn unregister(device: &GpioDevice) {
+    if let Some(&(device_id, _)) = device.device_id.get() {
+        DeviceManager::get_manager().unregister_device(device_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Eight lines of memory
+    struct MockChip {
+        values: Mutex<u8>,
+        outputs: Mutex<u8>,
+        edges: Mutex<u8>,
+    }
+
+    impl GpioChip for MockChip {
+        fn label(&self) -> &str {
+            "mock-gpio"
+        }
+
+        fn line_count(&self) -> usize {
+            8
+        }
+
+        fn is_output(&self, offset: usize) -> bool {
+            *self.outputs.lock() & 1 << offset != 0
+        }
+
+        fn direction_input(&self, offset: usize) -> Result<(), &'static str> {
+            *self.outputs.lock() &= !(1 << offset);
+            Ok(())
+        }
+
+        fn direction_output(&self, offset: usize, value: bool) -> Result<(), &'static str> {
+            *self.outputs.lock() |= 1 << offset;
+            self.set(offset, value);
+            Ok(())
+        }
+
+        fn get(&self, offset: usize) -> bool {
+            *self.values.lock() & 1 << offset != 0
+        }
+
+        fn set(&self, offset: usize, value: bool) {
+            let mut values = self.values.lock();
+            *values = *values & !(1 << offset) | (value as u8) << offset;
+        }
+
+        fn set_edges(&self, offset: usize, rising: bool, falling: bool) -> Result<(), &'static str> {
+            let mut edges = self.edges.lock();
+            *edges = *edges & !(1 << offset) | ((rising || falling) as u8) << offset;
+            Ok(())
+        }
+    }
+
+    fn mock() -> (Arc<MockChip>, Arc<GpioDevice>) {
+        let chip = Arc::new(MockChip { values: Mutex::new(0), outputs: Mutex::new(0), edges: Mutex::new(0) });
+        let device = GpioDevice::new(chip.clone());
+        (chip, device)
+    }
+
+    fn config(flags: u64, attributes: &[(u32, u64, u64)]) -> LineConfig {
+        LineConfig { flags, attributes: attributes.to_vec() }
+    }
+
+    #[test_case]
+    fn test_gpio_outputs() {
+        let (chip, device) = mock();
+        // Line 1 is active low and starts logically on
+        let outputs = config(
+            GPIO_V2_LINE_FLAG_OUTPUT,
+            &[
+                (GPIO_V2_LINE_ATTR_ID_FLAGS, GPIO_V2_LINE_FLAG_OUTPUT | GPIO_V2_LINE_FLAG_ACTIVE_LOW, 0b10),
+                (GPIO_V2_LINE_ATTR_ID_OUTPUT_VALUES, 0b10, 0b10),
+            ],
+        );
+        let request = device.request_lines(&[0, 1], "led", &outputs, 0).unwrap();
+        assert_eq!(*chip.outputs.lock(), 0b11);
+        assert_eq!(*chip.values.lock(), 0b00);
+        assert_eq!(request.get_values(0b11), 0b10);
+
+        request.set_values(0b01, 0b11).unwrap();
+        assert_eq!(*chip.values.lock(), 0b11);
+        assert_eq!(request.get_values(0b11), 0b01);
+
+        // Held lines are busy and described as used
+        assert_eq!(device.request_lines(&[1], "other", &outputs, 0).err(), Some("Line is busy"));
+        let info = device.line_info(1).unwrap();
+        assert_eq!(&info[32..36], b"led\0");
+        assert_eq!(u64_at(&info, 72), GPIO_V2_LINE_FLAG_USED | GPIO_V2_LINE_FLAG_OUTPUT | GPIO_V2_LINE_FLAG_ACTIVE_LOW);
+
+        drop(request);
+        assert!(device.lines.lock()[1].consumer.is_none());
+        assert!(device.request_lines(&[1], "other", &outputs, 0).is_ok());
+    }
+
+    #[test_case]
+    fn test_gpio_invalid_requests() {
+        let (_, device) = mock();
+        let both = config(GPIO_V2_LINE_FLAG_INPUT | GPIO_V2_LINE_FLAG_OUTPUT, &[]);
+        assert!(device.request_lines(&[0], "test", &both, 0).is_err());
+        let edge_output = config(GPIO_V2_LINE_FLAG_OUTPUT | GPIO_V2_LINE_FLAG_EDGE_RISING, &[]);
+        assert!(device.request_lines(&[0], "test", &edge_output, 0).is_err());
+        let input = config(GPIO_V2_LINE_FLAG_INPUT, &[]);
+        assert!(device.request_lines(&[8], "test", &input, 0).is_err());
+        assert!(device.request_lines(&[2, 2], "test", &input, 0).is_err());
+        // Failed requests hold nothing
+        assert!(device.lines.lock().iter().all(|line| line.consumer.is_none()));
+
+        let request = device.request_lines(&[3], "test", &input, 0).unwrap();
+        assert_eq!(request.set_values(1, 1), Err("Operation not permitted"));
+    }
+
+    #[test_case]
+    fn test_gpio_edge_events() {
+        let (chip, device) = mock();
+        let buttons = config(
+            GPIO_V2_LINE_FLAG_INPUT | GPIO_V2_LINE_FLAG_EDGE_RISING,
+            &[(
+                GPIO_V2_LINE_ATTR_ID_FLAGS,
+                GPIO_V2_LINE_FLAG_INPUT | GPIO_V2_LINE_FLAG_ACTIVE_LOW | EDGE_FLAGS,
+                0b10,
+            )],
+        );
+        let request = device.request_lines(&[4, 5], "button", &buttons, 2).unwrap();
+        assert_eq!(*chip.edges.lock(), 0b110000);
+        assert_eq!(request.poll_events(), 0);
+
+        device.report_edge(4, false);
+        device.report_edge(4, true);
+        // Pressing the active-low button pulls its pin low
+        device.report_edge(5, false);
+        device.report_edge(6, true);
+        assert_eq!(request.poll_events(), POLLIN);
+
+        // The oldest event was dropped for the last
+        let mut buffer = [0u8; LINE_EVENT_SIZE * 3];
+        assert_eq!(request.read(&mut buffer).unwrap(), LINE_EVENT_SIZE * 2);
+        assert_eq!(u32_at(&buffer, 8), GPIO_V2_LINE_EVENT_RISING_EDGE);
+        assert_eq!(u32_at(&buffer, 12), 4);
+        assert_eq!(u32_at(&buffer, 16), 1);
+        let second = &buffer[LINE_EVENT_SIZE..];
+        assert_eq!(u32_at(second, 8), GPIO_V2_LINE_EVENT_RISING_EDGE);
+        assert_eq!(u32_at(second, 12), 5);
+        assert_eq!((u32_at(second, 16), u32_at(second, 20)), (2, 1));
+        assert!(matches!(request.read(&mut buffer), Err(StreamError::WouldBlock)));
+
+        drop(request);
+        assert_eq!(*chip.edges.lock(), 0);
+    }
+}
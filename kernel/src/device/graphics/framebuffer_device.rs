@@ -8,6 +8,8 @@
 //!
 //! The FramebufferCharDevice provides:
 //! - Basic read/write operations to framebuffer memory
+//! - Memory mapping of the framebuffer into user space, keeping the
+//!   graphics device active while it is mapped
 //! - Control operations (ioctl-equivalent) for device configuration
 //! - Integration with GraphicsManager for resource management
 //! - Standard character device interface for user programs
@@ -20,9 +22,11 @@ use alloc::{sync::Arc, vec::Vec, vec, collections::BTreeMap};
 use spin::RwLock;
 
 use crate::device::{
-    char::CharDevice, graphics::manager::FramebufferResource, manager::DeviceManager, Device, DeviceType
+    char::CharDevice, graphics::{manager::FramebufferResource, FramebufferRect}, manager::DeviceManager, power, Device, DeviceType
 };
+use crate::environment::PAGE_SIZE;
 use crate::object::capability::{ControlOps, MemoryMappingOps};
+use crate::task::signal::{read_user, write_user};
 
 /// Linux framebuffer ioctl command constants
 /// These provide compatibility with Linux framebuffer applications
@@ -33,8 +37,32 @@ pub mod framebuffer_commands {
     pub const FBIOPUT_VSCREENINFO: u32 = 0x4601;
     /// Get fixed screen information
     pub const FBIOGET_FSCREENINFO: u32 = 0x4602;
+    /// Show the framebuffer from `yoffset` on at the next vertical blank
+    /// (arg: *const FbVarScreenInfo)
+    pub const FBIOPAN_DISPLAY: u32 = 0x4606;
     /// Flush framebuffer to display
     pub const FBIO_FLUSH: u32 = 0x4620;
+    /// Flush the changed rectangles of the framebuffer to display
+    /// (arg: *const FbDamage)
+    pub const FBIO_FLUSH_RECTS: u32 = 0x4621;
+}
+
+/// Most rectangles flushed one by one by `FBIO_FLUSH_RECTS`; more are
+/// flushed as the one rectangle holding them all
+pub const FB_MAX_DAMAGE_RECTS: usize = 64;
+
+/// Most rectangles `FBIO_FLUSH_RECTS` takes
+pub const FB_DAMAGE_RECTS_LIMIT: usize = 4096;
+
+/// Changed rectangles of the framebuffer, for `FBIO_FLUSH_RECTS`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FbDamage {
+    /// Address of `count` [`FramebufferRect`]s
+    pub rects: u64,
+    /// Number of rectangles
+    pub count: u32,
+    pub reserved: u32,
 }
 
 /// Variable screen information structure (Linux fb_var_screeninfo compatible)
@@ -139,7 +167,7 @@ impl Default for FbVarScreenInfo {
 
 /// Fixed screen information structure (Linux fb_fix_screeninfo compatible)
 #[repr(C)]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 pub struct FbFixScreenInfo {
     /// Identification string
     pub id: [u8; 16],
@@ -217,13 +245,6 @@ impl Default for FbBitfield {
     }
 }
 
-/// Mock mapping for testing purposes
-#[derive(Debug, Clone)]
-struct MockMapping {
-    vaddr: usize,
-    length: usize,
-}
-
 /// Framebuffer character device implementation
 /// 
 /// This device provides character-based access to framebuffer memory.
@@ -235,8 +256,11 @@ struct MockMapping {
 pub struct FramebufferCharDevice {
     /// The framebuffer resource this device represents
     fb_resource: Arc<FramebufferResource>,
-    /// Track mappings for testing purposes  
-    mappings: RwLock<BTreeMap<usize, MockMapping>>, // virtual_start -> MockMapping
+    /// Lengths of the mappings into user space, by start address
+    ///
+    /// Each holds a runtime PM reference on the graphics device: programs
+    /// draw into the mapped memory without telling the device.
+    mappings: RwLock<BTreeMap<usize, usize>>,
 }
 
 impl FramebufferCharDevice {
@@ -398,6 +422,7 @@ impl CharDevice for FramebufferCharDevice {
 
         let available = fb_resource.size - start_pos;
         let to_write = buffer.len().min(available);
+        self.mark_busy()?;
 
         // Write data to framebuffer memory
         unsafe {
@@ -424,9 +449,15 @@ impl ControlOps for FramebufferCharDevice {
             FBIO_FLUSH => {
                 self.handle_flush(arg)
             }
+            FBIO_FLUSH_RECTS => {
+                self.handle_flush_rects(arg)
+            }
             FBIOPUT_VSCREENINFO => {
                 self.handle_put_vscreeninfo(arg)
             }
+            FBIOPAN_DISPLAY => {
+                self.handle_pan_display(arg)
+            }
             _ => {
                 Err("Unsupported framebuffer control command")
             }
@@ -439,7 +470,9 @@ impl ControlOps for FramebufferCharDevice {
             (FBIOGET_VSCREENINFO, "Get variable screen information"),
             (FBIOGET_FSCREENINFO, "Get fixed screen information"),
             (FBIO_FLUSH, "Flush framebuffer to display"),
+            (FBIO_FLUSH_RECTS, "Flush changed rectangles of the framebuffer to display"),
             (FBIOPUT_VSCREENINFO, "Set variable screen information"),
+            (FBIOPAN_DISPLAY, "Pan the display to another part of the framebuffer"),
         ]
     }
 }
@@ -458,6 +491,9 @@ impl MemoryMappingOps for FramebufferCharDevice {
         if offset >= fb_resource.size {
             return Err("Offset exceeds framebuffer size");
         }
+        if offset % PAGE_SIZE != 0 {
+            return Err("Offset is not page aligned");
+        }
         
         let available_size = fb_resource.size - offset;
         if length > available_size {
@@ -473,14 +509,25 @@ impl MemoryMappingOps for FramebufferCharDevice {
     }
     
     fn on_mapped(&self, vaddr: usize, _paddr: usize, length: usize, _offset: usize) {
-        // Record this mapping in our tracking structure
-        let mapping = MockMapping { vaddr, length };
-        self.mappings.write().insert(vaddr, mapping);
+        // The device stays active while the memory is drawn into
+        let device = DeviceManager::get_manager().get_device(self.fb_resource.source_device_id);
+        if let Some(device) = &device {
+            let _ = power::runtime_get(&**device);
+        }
+        if self.mappings.write().insert(vaddr, length).is_some() {
+            // Mapped again at the same address without being unmapped
+            if let Some(device) = &device {
+                power::runtime_put(&**device);
+            }
+        }
     }
     
     fn on_unmapped(&self, vaddr: usize, _length: usize) {
-        // Remove the mapping from our tracking
-        self.mappings.write().remove(&vaddr);
+        if self.mappings.write().remove(&vaddr).is_some() {
+            if let Some(device) = DeviceManager::get_manager().get_device(self.fb_resource.source_device_id) {
+                power::runtime_put(&*device);
+            }
+        }
     }
     
     fn supports_mmap(&self) -> bool {
@@ -489,23 +536,30 @@ impl MemoryMappingOps for FramebufferCharDevice {
 }
 
 impl FramebufferCharDevice {
+    /// Mark the graphics device behind this framebuffer busy, resuming it
+    /// if it was suspended for being idle (see [`crate::device::power`])
+    fn mark_busy(&self) -> Result<(), &'static str> {
+        if let Some(device) = DeviceManager::get_manager().get_device(self.fb_resource.source_device_id) {
+            power::runtime_get(&*device)?;
+            power::runtime_put(&*device);
+        }
+        Ok(())
+    }
+
+    /// Row and column of the framebuffer shown at the top left of the
+    /// display
+    fn display_offset(&self) -> (u32, u32) {
+        DeviceManager::get_manager().get_device(self.fb_resource.source_device_id)
+            .and_then(|device| device.as_graphics_device().map(|graphics_device| graphics_device.display_offset()))
+            .unwrap_or((0, 0))
+    }
+
     /// Handle FBIOGET_VSCREENINFO control command
     fn handle_get_vscreeninfo(&self, arg: usize) -> Result<i32, &'static str> {
         if arg == 0 {
             return Err("Invalid argument pointer");
         }
         
-        // Try to get current task for user pointer translation
-        // If no task (kernel context), use pointer directly
-        let target_ptr = if let Some(current_task) = crate::task::mytask() {
-            // User space: translate virtual address to physical
-            current_task.vm_manager.translate_vaddr(arg)
-                .ok_or("Invalid user pointer - not mapped")?
-        } else {
-            // Kernel space: use pointer directly
-            arg
-        };
-        
         let fb_resource = &self.fb_resource;
         let config = &fb_resource.config;
         
@@ -514,7 +568,8 @@ impl FramebufferCharDevice {
         var_info.xres = config.width;
         var_info.yres = config.height;
         var_info.xres_virtual = config.width;
-        var_info.yres_virtual = config.height;
+        var_info.yres_virtual = config.virtual_height;
+        (var_info.xoffset, var_info.yoffset) = self.display_offset();
         var_info.bits_per_pixel = (config.format.bytes_per_pixel() * 8) as u32;
         
         // Set color bitfields based on format
@@ -545,11 +600,8 @@ impl FramebufferCharDevice {
             }
         }
         
-        // Safely copy to user space using translated physical address
-        unsafe {
-            let user_ptr = target_ptr as *mut FbVarScreenInfo;
-            core::ptr::write(user_ptr, var_info);
-        }
+        // Copy to the caller, page by page
+        write_user(arg, as_bytes_mut(core::slice::from_mut(&mut var_info)))?;
         
         Ok(0) // Success
     }
@@ -560,17 +612,6 @@ impl FramebufferCharDevice {
             return Err("Invalid argument pointer");
         }
         
-        // Try to get current task for user pointer translation
-        // If no task (kernel context), use pointer directly
-        let target_ptr = if let Some(current_task) = crate::task::mytask() {
-            // User space: translate virtual address to physical
-            current_task.vm_manager.translate_vaddr(arg)
-                .ok_or("Invalid user pointer - not mapped")?
-        } else {
-            // Kernel space: use pointer directly
-            arg
-        };
-        
         let fb_resource = &self.fb_resource;
         let config = &fb_resource.config;
         
@@ -587,13 +628,13 @@ impl FramebufferCharDevice {
         fix_info.line_length = config.stride;
         fix_info.type_ = 0; // FB_TYPE_PACKED_PIXELS
         fix_info.visual = 2; // FB_VISUAL_TRUECOLOR
-        
-        // Safely copy to user space using translated physical address
-        unsafe {
-            let user_ptr = target_ptr as *mut FbFixScreenInfo;
-            core::ptr::write(user_ptr, fix_info);
+        if config.virtual_height > config.height {
+            fix_info.ypanstep = 1;
         }
         
+        // Copy to the caller, page by page
+        write_user(arg, as_bytes_mut(core::slice::from_mut(&mut fix_info)))?;
+        
         Ok(0) // Success
     }
     
@@ -617,6 +658,7 @@ impl FramebufferCharDevice {
         
         // Trigger display controller update if needed
         // For some hardware, writing to framebuffer memory doesn't immediately update the display
+        self.mark_busy()?;
         self.trigger_display_update()?;
         
         Ok(0) // Success
@@ -634,7 +676,7 @@ impl FramebufferCharDevice {
             if let Some(graphics_device) = device.as_graphics_device() {
                 // Trigger a full framebuffer flush to ensure display is updated
                 let config = &self.fb_resource.config;
-                graphics_device.flush_framebuffer(0, 0, config.width, config.height)?;
+                graphics_device.flush_framebuffer(0, 0, config.width, config.virtual_height)?;
                 
                 // Verify that the framebuffer address is still valid
                 match graphics_device.get_framebuffer_address() {
@@ -657,12 +699,82 @@ impl FramebufferCharDevice {
         Ok(())
     }
     
+    /// Handle FBIO_FLUSH_RECTS control command
+    ///
+    /// Only the given rectangles are sent to the display, clipped to the
+    /// framebuffer. Past [`FB_MAX_DAMAGE_RECTS`] they are merged into one.
+    fn handle_flush_rects(&self, arg: usize) -> Result<i32, &'static str> {
+        if arg == 0 {
+            return Err("Invalid argument pointer");
+        }
+        let mut damage = FbDamage::default();
+        read_user(arg, as_bytes_mut(core::slice::from_mut(&mut damage)))?;
+        let count = damage.count as usize;
+        if count > FB_DAMAGE_RECTS_LIMIT {
+            return Err("Too many rectangles");
+        }
+        let mut rects = vec![FramebufferRect::default(); count];
+        if count > 0 {
+            read_user(damage.rects as usize, as_bytes_mut(&mut rects))?;
+        }
+
+        let config = &self.fb_resource.config;
+        rects = rects.iter()
+            .map(|rect| rect.clip(config.width, config.virtual_height))
+            .filter(|rect| !rect.is_empty())
+            .collect();
+        if rects.len() > FB_MAX_DAMAGE_RECTS {
+            let bounds = rects.iter().fold(FramebufferRect::default(), |bounds, rect| bounds.union(rect));
+            rects = vec![bounds];
+        }
+        if rects.is_empty() {
+            return Ok(0);
+        }
+
+        self.mark_busy()?;
+        if let Some(device) = DeviceManager::get_manager().get_device(self.fb_resource.source_device_id) {
+            if let Some(graphics_device) = device.as_graphics_device() {
+                graphics_device.flush_regions(&rects)?;
+            }
+        }
+        Ok(0)
+    }
+
     /// Handle FBIOPUT_VSCREENINFO control command  
     fn handle_put_vscreeninfo(&self, _arg: usize) -> Result<i32, &'static str> {
         // Setting screen info is not supported in this basic implementation
         // In a real implementation, this would validate and apply new settings
         Err("Setting screen information not supported")
     }
+
+    /// Handle FBIOPAN_DISPLAY control command
+    ///
+    /// Of the screen information passed only `xoffset` and `yoffset` are
+    /// used. Returns once the display shows the framebuffer from there on.
+    fn handle_pan_display(&self, arg: usize) -> Result<i32, &'static str> {
+        if arg == 0 {
+            return Err("Invalid argument pointer");
+        }
+        let mut var_info = FbVarScreenInfo::default();
+        read_user(arg, as_bytes_mut(core::slice::from_mut(&mut var_info)))?;
+
+        let config = &self.fb_resource.config;
+        if var_info.xoffset != 0 || var_info.yoffset > config.virtual_height - config.height {
+            return Err("Display offset out of range");
+        }
+
+        self.mark_busy()?;
+        let device = DeviceManager::get_manager().get_device(self.fb_resource.source_device_id)
+            .ok_or("Graphics device not found")?;
+        let graphics_device = device.as_graphics_device().ok_or("Not a graphics device")?;
+        graphics_device.pan_display(var_info.xoffset, var_info.yoffset)?;
+        Ok(0)
+    }
+}
+
+/// The bytes of `values`
+fn as_bytes_mut<T: Copy>(values: &mut [T]) -> &mut [u8] {
+    unsafe { core::slice::from_raw_parts_mut(values.as_mut_ptr() as *mut u8, core::mem::size_of_val(values)) }
 }
 
 #[cfg(test)]
@@ -1142,6 +1254,9 @@ mod tests {
         // Test invalid offset
         let result = fb_device.get_mapping_info(fb_size + 1, 32);
         assert!(result.is_err());
+
+        // Mappings start on a page
+        assert!(fb_device.get_mapping_info(4, 32).is_err());
         
         // Test invalid length  
         let result = fb_device.get_mapping_info(0, fb_size + 1);
@@ -1213,4 +1328,49 @@ mod tests {
         assert_eq!(permissions, 0x3);
         assert!(is_shared);
     }
-}
\ No newline at end of file
+
+    #[test_case]
+    fn test_framebuffer_pan_display() {
+        use framebuffer_commands::*;
+
+        let graphics_manager = setup_clean_graphics_manager();
+        let mut test_device = GenericGraphicsDevice::new("test-pan");
+        let config = FramebufferConfig::new(4, 4, PixelFormat::RGBA8888).with_virtual_height(8);
+        test_device.set_framebuffer_config(config.clone());
+
+        let fb_size = config.size();
+        assert_eq!(fb_size, 4 * 4 * 8);
+        let fb_pages = (fb_size + 4095) / 4096;
+        let fb_addr = crate::mem::page::allocate_raw_pages(fb_pages) as usize;
+        test_device.set_framebuffer_address(fb_addr);
+
+        let shared_device: Arc<dyn Device> = Arc::new(test_device);
+        let device_manager = DeviceManager::get_manager();
+        let device_id = device_manager.register_device_with_name("test-pan-device".to_string(), shared_device.clone());
+        graphics_manager.register_framebuffer_from_device(device_id, shared_device).unwrap();
+
+        let fb_resource = graphics_manager.get_framebuffer_names().iter()
+            .filter_map(|name| graphics_manager.get_framebuffer(name))
+            .find(|fb_resource| fb_resource.source_device_id == device_id)
+            .expect("Should have framebuffer for this device");
+        let char_device = FramebufferCharDevice::new(fb_resource);
+
+        let mut var_info = FbVarScreenInfo::default();
+        char_device.control(FBIOGET_VSCREENINFO, &mut var_info as *mut _ as usize).unwrap();
+        assert_eq!((var_info.yres, var_info.yres_virtual), (4, 8));
+        assert_eq!(var_info.yoffset, 0);
+
+        let mut fix_info = FbFixScreenInfo::default();
+        char_device.control(FBIOGET_FSCREENINFO, &mut fix_info as *mut _ as usize).unwrap();
+        assert_eq!(fix_info.ypanstep, 1);
+
+        // Past the last screen
+        var_info.yoffset = 5;
+        assert!(char_device.control(FBIOPAN_DISPLAY, &var_info as *const _ as usize).is_err());
+        // The generic device only shows the first screen
+        var_info.yoffset = 4;
+        assert!(char_device.control(FBIOPAN_DISPLAY, &var_info as *const _ as usize).is_err());
+        var_info.yoffset = 0;
+        assert_eq!(char_device.control(FBIOPAN_DISPLAY, &var_info as *const _ as usize), Ok(0));
+    }
+}
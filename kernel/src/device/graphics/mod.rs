@@ -66,18 +66,78 @@ pub struct FramebufferConfig {
     pub format: PixelFormat,
     /// Stride (bytes per row)
     pub stride: u32,
+    /// Height of the framebuffer memory in pixels
+    ///
+    /// Past `height` it holds further screens the display can be panned to.
+    pub virtual_height: u32,
 }
 
 impl FramebufferConfig {
     /// Create a new framebuffer configuration
     pub fn new(width: u32, height: u32, format: PixelFormat) -> Self {
         let stride = width * format.bytes_per_pixel() as u32;
-        Self { width, height, format, stride }
+        Self { width, height, format, stride, virtual_height: height }
+    }
+
+    /// The configuration with `virtual_height` rows of framebuffer memory
+    pub fn with_virtual_height(mut self, virtual_height: u32) -> Self {
+        self.virtual_height = virtual_height.max(self.height);
+        self
     }
     
     /// Get the total size of the framebuffer in bytes
     pub fn size(&self) -> usize {
-        (self.stride * self.height) as usize
+        (self.stride * self.virtual_height) as usize
+    }
+}
+
+/// A rectangle of the framebuffer, in pixels
+///
+/// It has the layout of the rectangles user space passes with
+/// `FBIO_FLUSH_RECTS`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FramebufferRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FramebufferRect {
+    pub const fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self { x, y, width, height }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.width == 0 || self.height == 0
+    }
+
+    /// The part of the rectangle inside a `width` by `height` screen
+    pub fn clip(&self, width: u32, height: u32) -> Self {
+        let x = self.x.min(width);
+        let y = self.y.min(height);
+        Self {
+            x,
+            y,
+            width: self.width.min(width - x),
+            height: self.height.min(height - y),
+        }
+    }
+
+    /// The smallest rectangle holding both
+    pub fn union(&self, other: &Self) -> Self {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+        Self { x, y, width: right - x, height: bottom - y }
     }
 }
 
@@ -126,6 +186,36 @@ pub trait GraphicsDevice: Device {
     
     /// Flush framebuffer region to display
     fn flush_framebuffer(&self, x: u32, y: u32, width: u32, height: u32) -> Result<(), &'static str>;
+
+    /// Flush the changed regions `rects` of the framebuffer to display
+    ///
+    /// Devices that update several regions at once override this; by
+    /// default each region is flushed on its own.
+    fn flush_regions(&self, rects: &[FramebufferRect]) -> Result<(), &'static str> {
+        for rect in rects {
+            self.flush_framebuffer(rect.x, rect.y, rect.width, rect.height)?;
+        }
+        Ok(())
+    }
+
+    /// Show the framebuffer from row `y` and column `x` on
+    ///
+    /// The new offset takes effect at the next vertical blank, so a program
+    /// drawing into the screen not shown and panning to it never shows a
+    /// half-drawn frame. Devices whose framebuffer is no larger than the
+    /// display only accept the origin.
+    fn pan_display(&self, x: u32, y: u32) -> Result<(), &'static str> {
+        if x == 0 && y == 0 {
+            Ok(())
+        } else {
+            Err("Panning not supported")
+        }
+    }
+
+    /// The row and column of the framebuffer shown at the top left
+    fn display_offset(&self) -> (u32, u32) {
+        (0, 0)
+    }
     
     /// Initialize the graphics device
     fn init_graphics(&mut self) -> Result<(), &'static str>;
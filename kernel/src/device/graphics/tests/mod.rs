@@ -19,6 +19,24 @@ fn test_framebuffer_config() {
     assert_eq!(config.format, PixelFormat::RGBA8888);
     assert_eq!(config.stride, 1920 * 4);
     assert_eq!(config.size(), 1920 * 1080 * 4);
+    assert_eq!(config.virtual_height, 1080);
+
+    let config = config.with_virtual_height(2160);
+    assert_eq!(config.size(), 1920 * 2160 * 4);
+    // Never less than the visible height
+    assert_eq!(config.with_virtual_height(10).virtual_height, 1080);
+}
+
+#[test_case]
+fn test_framebuffer_rect() {
+    let rect = FramebufferRect::new(10, 20, 30, 40);
+    assert_eq!(rect.clip(100, 100), rect);
+    assert_eq!(rect.clip(25, 50), FramebufferRect::new(10, 20, 15, 30));
+    assert!(FramebufferRect::new(120, 0, 10, 10).clip(100, 100).is_empty());
+
+    let other = FramebufferRect::new(0, 50, 5, 5);
+    assert_eq!(rect.union(&other), FramebufferRect::new(0, 20, 40, 40));
+    assert_eq!(FramebufferRect::default().union(&other), other);
 }
 
 #[test_case]
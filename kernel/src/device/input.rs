@@ -0,0 +1,776 @@
+//! Input devices
+//!
+//! An input device is a keyboard, a mouse, a tablet or anything else that
+//! reports events: a key going down, the pointer moving by some amount, an
+//! axis taking a value. A driver describes what its device can report
+//! ([`Capabilities`]), registers an [`EventDevice`] and then reports events
+//! with [`EventDevice::report`], ending each set of changes that belong
+//! together with [`EventDevice::sync`].
+//!
+//! Every device is the character device `/dev/input/eventN` and speaks the
+//! evdev protocol of Linux, so that input libraries and the compositor read
+//! it unmodified:
+//!
+//! - a read returns whole `struct input_event` records ([`EventRecord`]),
+//!   blocking until a packet is synced. A packet ends with `SYN_REPORT`.
+//! - events that change nothing are dropped: a key already down, an axis at
+//!   its value, a relative motion of 0, codes the device does not report.
+//! - a reader that falls [`EVDEV_QUEUE_LEN`] events behind loses them and
+//!   reads `SYN_DROPPED`; it gets the state back with `EVIOCGKEY` and
+//!   `EVIOCGABS`.
+//! - a write injects records as if the driver reported them.
+//! - the `EVIOCG*` queries of the name, id, capabilities and state are
+//!   answered, and `EVIOCSCLOCKID` is accepted for the clocks, which are
+//!   all the time since boot.
+//!
+//! Readers of a device share its queue: one reader, such as the
+//! compositor, is expected per device.
+
+use core::any::Any;
+use core::mem::size_of;
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::{Mutex, Once};
+
+use super::char::CharDevice;
+use super::manager::DeviceManager;
+use super::{Device, DeviceType};
+use crate::object::capability::poll::{POLLIN, POLLOUT};
+use crate::object::capability::{ControlOps, MemoryMappingOps, PollOps};
+use crate::sync::waker::Waker;
+use crate::task::mytask;
+use crate::task::signal::{read_user, write_user};
+use crate::timer::get_time_us;
+
+/// Event types
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_REL: u16 = 0x02;
+pub const EV_ABS: u16 = 0x03;
+pub const EV_MSC: u16 = 0x04;
+pub const EV_SW: u16 = 0x05;
+pub const EV_LED: u16 = 0x11;
+pub const EV_SND: u16 = 0x12;
+pub const EV_REP: u16 = 0x14;
+pub const EV_FF: u16 = 0x15;
+pub const EV_CNT: usize = 0x20;
+
+/// Synchronization events
+pub const SYN_REPORT: u16 = 0;
+pub const SYN_DROPPED: u16 = 3;
+
+/// Relative axes
+pub const REL_X: u16 = 0x00;
+pub const REL_Y: u16 = 0x01;
+pub const REL_HWHEEL: u16 = 0x06;
+pub const REL_WHEEL: u16 = 0x08;
+
+/// Absolute axes
+pub const ABS_X: u16 = 0x00;
+pub const ABS_Y: u16 = 0x01;
+pub const ABS_PRESSURE: u16 = 0x18;
+
+/// Buttons, which are keys
+pub const BTN_LEFT: u16 = 0x110;
+pub const BTN_RIGHT: u16 = 0x111;
+pub const BTN_MIDDLE: u16 = 0x112;
+pub const BTN_TOUCH: u16 = 0x14a;
+
+/// Device properties
+pub const INPUT_PROP_POINTER: u16 = 0x00;
+pub const INPUT_PROP_DIRECT: u16 = 0x01;
+
+/// Number of codes of each event type
+const KEY_CNT: usize = 0x300;
+const REL_CNT: usize = 0x10;
+const ABS_CNT: usize = 0x40;
+const MSC_CNT: usize = 0x08;
+const SW_CNT: usize = 0x11;
+const LED_CNT: usize = 0x10;
+const SND_CNT: usize = 0x08;
+const FF_CNT: usize = 0x80;
+const INPUT_PROP_CNT: usize = 0x20;
+
+/// Buses of [`InputId`]
+pub const BUS_USB: u16 = 0x03;
+pub const BUS_VIRTUAL: u16 = 0x06;
+pub const BUS_HOST: u16 = 0x19;
+
+/// Version of the evdev protocol spoken
+pub const EV_VERSION: i32 = 0x010001;
+
+/// Most events queued for the reader of a device
+pub const EVDEV_QUEUE_LEN: usize = 1024;
+
+/// evdev control commands (Linux ioctl numbers); the variable ones carry
+/// the size of the caller's buffer and are matched by number
+pub mod evdev_commands {
+    /// The type of every evdev command
+    pub const EVDEV_IOCTL_TYPE: u32 = b'E' as u32;
+
+    /// Get the protocol version (arg: *mut i32)
+    pub const EVIOCGVERSION: u32 = 0x80044501;
+    /// Get the device id (arg: *mut InputId)
+    pub const EVIOCGID: u32 = 0x80084502;
+    /// Set the clock of the timestamps (arg: *const i32)
+    pub const EVIOCSCLOCKID: u32 = 0x400445a0;
+
+    /// Numbers of the commands with a buffer: `EVIOCGNAME(len)` and others
+    pub const NR_NAME: u32 = 0x06;
+    pub const NR_PHYS: u32 = 0x07;
+    pub const NR_UNIQ: u32 = 0x08;
+    pub const NR_PROP: u32 = 0x09;
+    pub const NR_KEY: u32 = 0x18;
+    pub const NR_LED: u32 = 0x19;
+    pub const NR_SND: u32 = 0x1a;
+    pub const NR_SW: u32 = 0x1b;
+    /// `EVIOCGBIT(type, len)` is `NR_BIT + type`
+    pub const NR_BIT: u32 = 0x20;
+    /// `EVIOCGABS(axis)` is `NR_ABS + axis`
+    pub const NR_ABS: u32 = 0x40;
+}
+
+/// Whether `command` is an evdev control command
+pub fn is_evdev_command(command: u32) -> bool {
+    (command >> 8) & 0xff == evdev_commands::EVDEV_IOCTL_TYPE
+}
+
+/// An event as read and written: `struct input_event` of Linux on 64-bit
+/// targets
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EventRecord {
+    pub seconds: u64,
+    pub microseconds: u64,
+    pub kind: u16,
+    pub code: u16,
+    pub value: i32,
+}
+
+/// Size of an [`EventRecord`]
+pub const EVENT_RECORD_SIZE: usize = size_of::<EventRecord>();
+
+impl EventRecord {
+    fn new(time_us: u64, kind: u16, code: u16, value: i32) -> Self {
+        Self { seconds: time_us / 1_000_000, microseconds: time_us % 1_000_000, kind, code, value }
+    }
+
+    pub fn to_bytes(&self) -> [u8; EVENT_RECORD_SIZE] {
+        let mut bytes = [0; EVENT_RECORD_SIZE];
+        bytes[0..8].copy_from_slice(&self.seconds.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.microseconds.to_le_bytes());
+        bytes[16..18].copy_from_slice(&self.kind.to_le_bytes());
+        bytes[18..20].copy_from_slice(&self.code.to_le_bytes());
+        bytes[20..24].copy_from_slice(&self.value.to_le_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8; EVENT_RECORD_SIZE]) -> Self {
+        let u64_at = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+        Self {
+            seconds: u64_at(0),
+            microseconds: u64_at(8),
+            kind: u16::from_le_bytes([bytes[16], bytes[17]]),
+            code: u16::from_le_bytes([bytes[18], bytes[19]]),
+            value: i32::from_le_bytes([bytes[20], bytes[21], bytes[22], bytes[23]]),
+        }
+    }
+}
+
+/// Identity of a device: `struct input_id` of Linux
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InputId {
+    pub bus: u16,
+    pub vendor: u16,
+    pub product: u16,
+    pub version: u16,
+}
+
+/// Range of an absolute axis: `struct input_absinfo` of Linux
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AbsInfo {
+    pub value: i32,
+    pub minimum: i32,
+    pub maximum: i32,
+    pub fuzz: i32,
+    pub flat: i32,
+    pub resolution: i32,
+}
+
+impl AbsInfo {
+    /// An axis from `minimum` to `maximum`
+    pub fn new(minimum: i32, maximum: i32) -> Self {
+        Self { minimum, maximum, ..Self::default() }
+    }
+
+    fn to_bytes(&self) -> [u8; size_of::<AbsInfo>()] {
+        let mut bytes = [0; size_of::<AbsInfo>()];
+        let fields = [self.value, self.minimum, self.maximum, self.fuzz, self.flat, self.resolution];
+        for (chunk, field) in bytes.chunks_exact_mut(4).zip(fields) {
+            chunk.copy_from_slice(&field.to_le_bytes());
+        }
+        bytes
+    }
+}
+
+/// A bitmap of codes, laid out as the `unsigned long` arrays of Linux
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Bits(Vec<u64>);
+
+impl Bits {
+    fn new(count: usize) -> Self {
+        Bits(alloc::vec![0; count.div_ceil(64)])
+    }
+
+    fn set(&mut self, code: u16, on: bool) {
+        if let Some(word) = self.0.get_mut(code as usize / 64) {
+            let bit = 1 << (code % 64);
+            if on { *word |= bit } else { *word &= !bit }
+        }
+    }
+
+    fn contains(&self, code: u16) -> bool {
+        self.0.get(code as usize / 64).is_some_and(|word| word & (1 << (code % 64)) != 0)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.iter().all(|&word| word == 0)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.0.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+}
+
+/// What a device reports
+#[derive(Debug, Clone)]
+pub struct Capabilities {
+    keys: Bits,
+    relative: Bits,
+    absolute: BTreeMap<u16, AbsInfo>,
+    leds: Bits,
+    properties: Bits,
+}
+
+impl Capabilities {
+    pub fn new() -> Self {
+        Self {
+            keys: Bits::new(KEY_CNT),
+            relative: Bits::new(REL_CNT),
+            absolute: BTreeMap::new(),
+            leds: Bits::new(LED_CNT),
+            properties: Bits::new(INPUT_PROP_CNT),
+        }
+    }
+
+    /// Report the keys or buttons `codes`
+    pub fn keys(mut self, codes: impl IntoIterator<Item = u16>) -> Self {
+        codes.into_iter().for_each(|code| self.keys.set(code, true));
+        self
+    }
+
+    /// Report the relative axes `codes`
+    pub fn relative(mut self, codes: impl IntoIterator<Item = u16>) -> Self {
+        codes.into_iter().for_each(|code| self.relative.set(code, true));
+        self
+    }
+
+    /// Report the absolute axis `code` over the range of `info`, starting
+    /// at its value
+    pub fn absolute(mut self, code: u16, info: AbsInfo) -> Self {
+        if (code as usize) < ABS_CNT {
+            self.absolute.insert(code, info);
+        }
+        self
+    }
+
+    /// Have the LEDs `codes`, which readers set by writing
+    pub fn leds(mut self, codes: impl IntoIterator<Item = u16>) -> Self {
+        codes.into_iter().for_each(|code| self.leds.set(code, true));
+        self
+    }
+
+    pub fn property(mut self, property: u16) -> Self {
+        self.properties.set(property, true);
+        self
+    }
+
+    fn absolute_bits(&self) -> Bits {
+        let mut bits = Bits::new(ABS_CNT);
+        self.absolute.keys().for_each(|&code| bits.set(code, true));
+        bits
+    }
+
+    /// The event types reported
+    fn types(&self) -> Bits {
+        let mut types = Bits::new(EV_CNT);
+        types.set(EV_SYN, true);
+        types.set(EV_KEY, !self.keys.is_empty());
+        types.set(EV_REL, !self.relative.is_empty());
+        types.set(EV_ABS, !self.absolute.is_empty());
+        types.set(EV_LED, !self.leds.is_empty());
+        types
+    }
+
+    /// The codes of `kind` reported, `None` for a type no device reports
+    fn codes(&self, kind: u16) -> Option<Bits> {
+        Some(match kind {
+            0 => self.types(),
+            EV_KEY => self.keys.clone(),
+            EV_REL => self.relative.clone(),
+            EV_ABS => self.absolute_bits(),
+            EV_LED => self.leds.clone(),
+            EV_MSC => Bits::new(MSC_CNT),
+            EV_SW => Bits::new(SW_CNT),
+            EV_SND => Bits::new(SND_CNT),
+            EV_FF => Bits::new(FF_CNT),
+            _ => return None,
+        })
+    }
+}
+
+impl Default for Capabilities {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What the readers of a device have to know
+struct State {
+    /// Synced events not read yet
+    queue: VecDeque<EventRecord>,
+    /// Events reported since the last sync
+    packet: Vec<EventRecord>,
+    keys: Bits,
+    leds: Bits,
+    absolute: BTreeMap<u16, i32>,
+}
+
+/// An input device and its evdev character device
+pub struct EventDevice {
+    name: String,
+    id: InputId,
+    capabilities: Capabilities,
+    state: Mutex<State>,
+    /// Readers waiting for a packet
+    waker: Waker,
+    /// Id in the device manager, once registered
+    device_id: Once<usize>,
+}
+
+impl EventDevice {
+    pub fn new(name: &str, id: InputId, capabilities: Capabilities) -> Arc<Self> {
+        let state = State {
+            queue: VecDeque::new(),
+            packet: Vec::new(),
+            keys: Bits::new(KEY_CNT),
+            leds: Bits::new(LED_CNT),
+            absolute: capabilities.absolute.iter().map(|(&code, info)| (code, info.value)).collect(),
+        };
+        Arc::new(Self {
+            name: name.into(),
+            id,
+            capabilities,
+            state: Mutex::new(state),
+            waker: Waker::new_interruptible("evdev"),
+            device_id: Once::new(),
+        })
+    }
+
+    pub fn input_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn input_id(&self) -> InputId {
+        self.id
+    }
+
+    /// Report that `code` of `kind` took `value`; it is read once synced
+    ///
+    /// `EV_SYN`/`SYN_REPORT` syncs. Events that change nothing or that the
+    /// device does not report are dropped.
+    pub fn report(&self, kind: u16, code: u16, value: i32) {
+        if kind == EV_SYN {
+            if code == SYN_REPORT {
+                self.sync();
+            }
+            return;
+        }
+        let mut state = self.state.lock();
+        let changes = match kind {
+            // 2 is a key repeating, which leaves it down
+            EV_KEY if self.capabilities.keys.contains(code) => {
+                let down = value != 0;
+                let changes = value == 2 || state.keys.contains(code) != down;
+                state.keys.set(code, down);
+                changes
+            }
+            EV_REL => value != 0 && self.capabilities.relative.contains(code),
+            EV_ABS => match state.absolute.get_mut(&code) {
+                Some(current) => core::mem::replace(current, value) != value,
+                None => false,
+            },
+            EV_LED if self.capabilities.leds.contains(code) => {
+                let on = value != 0;
+                let changes = state.leds.contains(code) != on;
+                state.leds.set(code, on);
+                changes
+            }
+            _ => false,
+        };
+        if changes {
+            state.packet.push(EventRecord::new(0, kind, code, value));
+        }
+    }
+
+    /// Hand the events reported since the last sync to the reader, as one
+    /// packet ending with `SYN_REPORT`
+    pub fn sync(&self) {
+        let mut state = self.state.lock();
+        if state.packet.is_empty() {
+            return;
+        }
+        let now = get_time_us();
+        let mut packet = core::mem::take(&mut state.packet);
+        packet.push(EventRecord::new(now, EV_SYN, SYN_REPORT, 0));
+        if state.queue.len() + packet.len() > EVDEV_QUEUE_LEN {
+            state.queue.clear();
+            state.queue.push_back(EventRecord::new(now, EV_SYN, SYN_DROPPED, 0));
+        }
+        for mut event in packet {
+            event.seconds = now / 1_000_000;
+            event.microseconds = now % 1_000_000;
+            state.queue.push_back(event);
+        }
+        drop(state);
+        self.waker.wake_all();
+    }
+
+    /// The answer to the query `command`, whose buffer holds `size` bytes
+    fn query(&self, command: u32, size: usize) -> Result<Vec<u8>, &'static str> {
+        use evdev_commands::*;
+
+        let number = command & 0xff;
+        let mut bytes = match number {
+            NR_NAME => {
+                let mut name = self.name.as_bytes().to_vec();
+                name.push(0);
+                name
+            }
+            NR_PHYS | NR_UNIQ => return Err("Input device path not found"),
+            NR_PROP => self.capabilities.properties.to_bytes(),
+            NR_KEY => self.state.lock().keys.to_bytes(),
+            NR_LED => self.state.lock().leds.to_bytes(),
+            NR_SND => Bits::new(SND_CNT).to_bytes(),
+            NR_SW => Bits::new(SW_CNT).to_bytes(),
+            _ if (NR_BIT..NR_BIT + EV_CNT as u32).contains(&number) => {
+                self.capabilities.codes((number - NR_BIT) as u16).ok_or("Unsupported event type")?.to_bytes()
+            }
+            _ if (NR_ABS..NR_ABS + ABS_CNT as u32).contains(&number) => {
+                let axis = (number - NR_ABS) as u16;
+                let mut info = *self.capabilities.absolute.get(&axis).ok_or("Not an absolute axis")?;
+                info.value = self.state.lock().absolute.get(&axis).copied().unwrap_or(info.value);
+                info.to_bytes().to_vec()
+            }
+            _ => return Err("Unsupported evdev control command"),
+        };
+        bytes.truncate(size);
+        Ok(bytes)
+    }
+}
+
+fn read_user_i32(arg: usize) -> Result<i32, &'static str> {
+    let mut bytes = [0; 4];
+    read_user(arg, &mut bytes)?;
+    Ok(i32::from_le_bytes(bytes))
+}
+
+impl Device for EventDevice {
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Char
+    }
+
+    fn name(&self) -> &'static str {
+        "evdev"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn as_char_device(&self) -> Option<&dyn CharDevice> {
+        Some(self)
+    }
+}
+
+impl CharDevice for EventDevice {
+    fn read_byte(&self) -> Option<u8> {
+        // Events are read whole
+        None
+    }
+
+    fn write_byte(&self, _byte: u8) -> Result<(), &'static str> {
+        Err("Events are written whole")
+    }
+
+    /// Read the synced events that fit in `buffer`, blocking while there
+    /// are none
+    fn read(&self, buffer: &mut [u8]) -> usize {
+        let count = buffer.len() / EVENT_RECORD_SIZE;
+        if count == 0 {
+            return 0;
+        }
+        loop {
+            {
+                let mut state = self.state.lock();
+                if !state.queue.is_empty() {
+                    let count = count.min(state.queue.len());
+                    for (chunk, event) in buffer.chunks_exact_mut(EVENT_RECORD_SIZE).zip(state.queue.drain(..count)) {
+                        chunk.copy_from_slice(&event.to_bytes());
+                    }
+                    return count * EVENT_RECORD_SIZE;
+                }
+            }
+            let Some(task) = mytask() else {
+                return 0;
+            };
+            let task_id = task.get_id();
+            self.waker.wait_unless(task_id, task.get_trapframe(), || {
+                !self.state.lock().queue.is_empty() || mytask().is_some_and(|task| task.signals.has_pending())
+            });
+            if task.signals.has_pending() {
+                return 0;
+            }
+        }
+    }
+
+    /// Report the records in `buffer` as if the driver did
+    fn write(&self, buffer: &[u8]) -> Result<usize, &'static str> {
+        if buffer.len() % EVENT_RECORD_SIZE != 0 {
+            return Err("Not whole input events");
+        }
+        for chunk in buffer.chunks_exact(EVENT_RECORD_SIZE) {
+            let record = EventRecord::from_bytes(chunk.try_into().unwrap());
+            self.report(record.kind, record.code, record.value);
+        }
+        Ok(buffer.len())
+    }
+
+    fn can_read(&self) -> bool {
+        !self.state.lock().queue.is_empty()
+    }
+
+    fn can_write(&self) -> bool {
+        true
+    }
+
+    fn as_pollable(&self) -> Option<&dyn PollOps> {
+        Some(self)
+    }
+}
+
+impl PollOps for EventDevice {
+    /// Readable once a packet is synced; events are always accepted
+    fn poll_events(&self) -> u32 {
+        if self.can_read() { POLLIN | POLLOUT } else { POLLOUT }
+    }
+
+    fn poll_register(&self, task_id: usize) -> bool {
+        self.waker.register(task_id);
+        true
+    }
+
+    fn poll_unregister(&self, task_id: usize) {
+        self.waker.unregister(task_id);
+    }
+}
+
+impl ControlOps for EventDevice {
+    fn control(&self, command: u32, arg: usize) -> Result<i32, &'static str> {
+        use evdev_commands::*;
+
+        match command {
+            EVIOCGVERSION => write_user(arg, &EV_VERSION.to_le_bytes()).map(|_| 0),
+            EVIOCGID => {
+                let id = [self.id.bus, self.id.vendor, self.id.product, self.id.version];
+                let bytes: Vec<u8> = id.iter().flat_map(|field| field.to_le_bytes()).collect();
+                write_user(arg, &bytes).map(|_| 0)
+            }
+            // Every clock is the time since boot
+            EVIOCSCLOCKID => match read_user_i32(arg)? {
+                0 | 1 | 7 => Ok(0),
+                _ => Err("Invalid clock"),
+            },
+            _ if is_evdev_command(command) && command >> 30 == 2 => {
+                let bytes = self.query(command, ((command >> 16) & 0x3fff) as usize)?;
+                write_user(arg, &bytes)?;
+                Ok(bytes.len() as i32)
+            }
+            _ => Err("Unsupported evdev control command"),
+        }
+    }
+
+    fn supported_control_commands(&self) -> Vec<(u32, &'static str)> {
+        use evdev_commands::*;
+        alloc::vec![
+            (EVIOCGVERSION, "Get the protocol version"),
+            (EVIOCGID, "Get the device id"),
+            (EVIOCSCLOCKID, "Set the clock of the timestamps"),
+        ]
+    }
+}
+
+impl MemoryMappingOps for EventDevice {
+    fn get_mapping_info(&self, _offset: usize, _length: usize)
+                       -> Result<(usize, usize, bool), &'static str> {
+        Err("Memory mapping not supported by input devices")
+    }
+
+    fn on_mapped(&self, _vaddr: usize, _paddr: usize, _length: usize, _offset: usize) {}
+
+    fn on_unmapped(&self, _vaddr: usize, _length: usize) {}
+
+    fn supports_mmap(&self) -> bool {
+        false
+    }
+}
+
+/// Register `device` as `/dev/input/eventN` with the lowest free `N`, and
+/// return `N`
+pub fn register(device: Arc<EventDevice>) -> usize {
+    let manager = DeviceManager::get_manager();
+    let number = (0..).find(|number| manager.get_device_by_name(&format!("input/event{}", number)).is_none()).unwrap();
+    let device_id = manager.register_device_with_name(format!("input/event{}", number), device.clone());
+    device.device_id.call_once(|| device_id);
+    number
+}
+
+/// Remove the device of `device`; readers see no more events
+pub fn unregister(device: &EventDevice) {
+    if let Some(&device_id) = device.device_id.get() {
+        DeviceManager::get_manager().unregister_device(device_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mouse() -> Arc<EventDevice> {
+        let capabilities = Capabilities::new()
+            .keys([BTN_LEFT, BTN_RIGHT])
+            .relative([REL_X, REL_Y])
+            .absolute(ABS_PRESSURE, AbsInfo::new(0, 255))
+            .property(INPUT_PROP_POINTER);
+        EventDevice::new("Test Mouse", InputId { bus: BUS_VIRTUAL, vendor: 1, product: 2, version: 3 }, capabilities)
+    }
+
+    fn read_events(device: &EventDevice) -> Vec<(u16, u16, i32)> {
+        let mut buffer = [0u8; EVENT_RECORD_SIZE * 16];
+        let len = CharDevice::read(device, &mut buffer);
+        buffer[..len]
+            .chunks_exact(EVENT_RECORD_SIZE)
+            .map(|chunk| EventRecord::from_bytes(chunk.try_into().unwrap()))
+            .map(|record| (record.kind, record.code, record.value))
+            .collect()
+    }
+
+    #[test_case]
+    fn test_evdev_packets() {
+        let device = mouse();
+        device.report(EV_REL, REL_X, 5);
+        device.report(EV_KEY, BTN_LEFT, 1);
+        // Nothing is read before the sync
+        assert!(!device.can_read());
+        device.sync();
+        assert_eq!(device.poll_events() & POLLIN, POLLIN);
+        assert_eq!(read_events(&device), [(EV_REL, REL_X, 5), (EV_KEY, BTN_LEFT, 1), (EV_SYN, SYN_REPORT, 0)]);
+
+        // Changing nothing, or what the device does not have, is dropped
+        device.report(EV_REL, REL_Y, 0);
+        device.report(EV_KEY, BTN_LEFT, 1);
+        device.report(EV_KEY, BTN_MIDDLE, 1);
+        device.report(EV_ABS, ABS_X, 3);
+        device.sync();
+        assert!(!device.can_read());
+
+        // Written records are reported, a sync included
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&EventRecord::new(0, EV_ABS, ABS_PRESSURE, 40).to_bytes());
+        bytes.extend_from_slice(&EventRecord::new(0, EV_SYN, SYN_REPORT, 0).to_bytes());
+        assert_eq!(CharDevice::write(&*device, &bytes), Ok(bytes.len()));
+        assert!(CharDevice::write(&*device, &bytes[..10]).is_err());
+        assert_eq!(read_events(&device), [(EV_ABS, ABS_PRESSURE, 40), (EV_SYN, SYN_REPORT, 0)]);
+    }
+
+    #[test_case]
+    fn test_evdev_overflow() {
+        let device = mouse();
+        for _ in 0..EVDEV_QUEUE_LEN {
+            device.report(EV_REL, REL_X, 1);
+            device.sync();
+        }
+        // The reader that fell behind learns it lost events
+        assert_eq!(read_events(&device)[0], (EV_SYN, SYN_DROPPED, 0));
+    }
+
+    #[test_case]
+    fn test_evdev_queries() {
+        use evdev_commands::*;
+
+        let device = mouse();
+        device.report(EV_KEY, BTN_RIGHT, 1);
+        device.report(EV_ABS, ABS_PRESSURE, 9);
+        device.sync();
+
+        // EVIOCGNAME(5) cuts the name to the buffer
+        let name = (2 << 30) | (5 << 16) | (EVDEV_IOCTL_TYPE << 8) | NR_NAME;
+        assert_eq!(device.query(name, 5).unwrap(), b"Test ");
+        assert_eq!(device.query(name, 32).unwrap(), b"Test Mouse\0");
+        assert!(is_evdev_command(name) && !is_evdev_command(0x5401));
+
+        // EVIOCGBIT(0) lists the types, EVIOCGBIT(EV_KEY) the keys
+        let types = device.query(NR_BIT, 4).unwrap();
+        assert_eq!(types[0], 1 << EV_SYN | 1 << EV_KEY | 1 << EV_REL | 1 << EV_ABS);
+        let keys = device.query(NR_BIT + EV_KEY as u32, 96).unwrap();
+        assert_eq!(keys.len(), 96);
+        assert_eq!(keys[BTN_LEFT as usize / 8], 0b11);
+        let state = device.query(NR_KEY, 96).unwrap();
+        assert_eq!(state[BTN_LEFT as usize / 8], 0b10);
+
+        let info = device.query(NR_ABS + ABS_PRESSURE as u32, 24).unwrap();
+        assert_eq!(info[0..4], 9i32.to_le_bytes());
+        assert_eq!(info[8..12], 255i32.to_le_bytes());
+        assert!(device.query(NR_ABS + ABS_X as u32, 24).is_err());
+        assert!(device.query(NR_PHYS, 16).is_err());
+        assert!(device.control(0x5401, 0).is_err());
+    }
+
+    #[test_case]
+    fn test_evdev_register() {
+        let first = mouse();
+        let second = mouse();
+        let number = register(first.clone());
+        let next = register(second.clone());
+        assert_ne!(number, next);
+        let manager = DeviceManager::get_manager();
+        assert!(manager.get_device_by_name(&format!("input/event{}", number)).is_some());
+        // The number of a removed device is given again
+        unregister(&first);
+        assert!(manager.get_device_by_name(&format!("input/event{}", number)).is_none());
+        let third = mouse();
+        assert_eq!(register(third.clone()), number);
+        unregister(&second);
+        unregister(&third);
+    }
+}
@@ -45,7 +45,7 @@ use core::sync::atomic::AtomicUsize;
 use core::sync::atomic::Ordering;
 
 use alloc::collections::btree_map::BTreeMap;
-use alloc::sync::Arc;
+use alloc::sync::{Arc, Weak};
 use alloc::vec::Vec;
 use alloc::boxed::Box;
 use alloc::string::String;
@@ -57,6 +57,7 @@ use crate::device::platform::PlatformDeviceInfo;
 use crate::early_println;
 
 use crate::DeviceSource;
+use super::events::{DeviceEventEmitter, DeviceEventListener, DeviceHotplugEvent, HotplugAction};
 use super::Device;
 use super::DeviceDriver;
 use super::DeviceInfo;
@@ -112,6 +113,8 @@ static mut MANAGER: DeviceManager = DeviceManager::new();
 /// - `name_to_id`: A mutex-protected map from device name to device ID.
 /// - `drivers`: A mutex-protected map of device drivers organized by priority.
 /// - `next_device_id`: Atomic counter for generating unique device IDs.
+/// - `hotplug`: Emitter for device add/remove/change notifications.
+/// - `bound`: Devices probed by a driver, for its power management hooks.
 pub struct DeviceManager {
     /* Devices stored by ID */
     devices: Mutex<BTreeMap<usize, SharedDevice>>,
@@ -123,6 +126,10 @@ pub struct DeviceManager {
     drivers: Mutex<BTreeMap<DriverPriority, Vec<Box<dyn DeviceDriver>>>>,
     /* Next device ID to assign */
     next_device_id: AtomicUsize,
+    /* Hotplug event listeners */
+    hotplug: DeviceEventEmitter,
+    /* Probed devices in probe order, by driver priority and index */
+    bound: Mutex<Vec<(DriverPriority, usize, Box<dyn DeviceInfo>)>>,
 }
 
 impl DeviceManager {
@@ -133,6 +140,8 @@ impl DeviceManager {
             name_to_id: Mutex::new(BTreeMap::new()),
             drivers: Mutex::new(BTreeMap::new()),
             next_device_id: AtomicUsize::new(1), // Start from 1, reserve 0 for invalid
+            hotplug: DeviceEventEmitter::new(),
+            bound: Mutex::new(Vec::new()),
         }
     }
 
@@ -162,9 +171,9 @@ impl DeviceManager {
     /// ```
     /// 
     pub fn register_device(&self, device: Arc<dyn Device>) -> usize {
-        let mut devices = self.devices.lock();
         let id = self.next_device_id.fetch_add(1, Ordering::SeqCst);
-        devices.insert(id, device);
+        self.devices.lock().insert(id, device.clone());
+        self.emit_hotplug(HotplugAction::Added, id, device, None);
         id
     }
 
@@ -184,11 +193,78 @@ impl DeviceManager {
         
         let id = self.next_device_id.fetch_add(1, Ordering::SeqCst);
         devices.insert(id, device.clone());
-        device_by_name.insert(name.clone(), device);
-        name_to_id.insert(name, id);
+        device_by_name.insert(name.clone(), device.clone());
+        name_to_id.insert(name.clone(), id);
+
+        // Listeners may call back into the manager, so release the maps first
+        drop(name_to_id);
+        drop(device_by_name);
+        drop(devices);
+        self.emit_hotplug(HotplugAction::Added, id, device, Some(name));
         id
     }
 
+    /// Unregister a device from the manager
+    /// 
+    /// The device is removed from all lookup tables and a `Removed` hotplug
+    /// event is emitted so that its users can release it. The returned
+    /// reference is the last one held by the manager.
+    /// 
+    /// # Arguments
+    /// * `id`: The id of the device to unregister.
+    /// 
+    /// # Returns
+    /// * The removed device, or None if no device has this id.
+    /// 
+    pub fn unregister_device(&self, id: usize) -> Option<SharedDevice> {
+        let device = self.devices.lock().remove(&id)?;
+        let names: Vec<String> = {
+            let mut device_by_name = self.device_by_name.lock();
+            let mut name_to_id = self.name_to_id.lock();
+            let names: Vec<String> = name_to_id.iter()
+                .filter(|(_, device_id)| **device_id == id)
+                .map(|(name, _)| name.clone())
+                .collect();
+            for name in &names {
+                name_to_id.remove(name);
+                device_by_name.remove(name);
+            }
+            names
+        };
+        self.emit_hotplug(HotplugAction::Removed, id, device.clone(), names.into_iter().next());
+        Some(device)
+    }
+
+    /// Tell the hotplug listeners that a property of the device `id` changed
+    /// 
+    /// Drivers call this when something user space may want to react to
+    /// changes without the device going away, such as the size of a disk.
+    /// 
+    /// # Returns
+    /// * Whether a device has this id.
+    /// 
+    pub fn notify_device_changed(&self, id: usize) -> bool {
+        let Some(device) = self.get_device(id) else {
+            return false;
+        };
+        self.emit_hotplug(HotplugAction::Changed, id, device, self.get_device_name(id));
+        true
+    }
+
+    /// Register a listener for device hotplug events
+    /// 
+    /// The listener receives a `DeviceHotplugEvent` (event type `"hotplug"`)
+    /// whenever a device is registered, unregistered or changed. Only a weak reference
+    /// is kept, so the caller must keep the listener alive.
+    pub fn register_hotplug_listener(&self, listener: Weak<dyn DeviceEventListener>) {
+        self.hotplug.register_listener(listener);
+    }
+
+    fn emit_hotplug(&self, action: HotplugAction, device_id: usize, device: SharedDevice, name: Option<String>) {
+        let event = DeviceHotplugEvent { action, device_id, device, name };
+        self.hotplug.emit(&event);
+    }
+
     /// Get a device by ID
     /// 
     /// # Arguments
@@ -202,6 +278,11 @@ impl DeviceManager {
         devices.get(&id).cloned()
     }
 
+    /// Get all devices in registration order
+    pub fn get_all_devices(&self) -> Vec<(usize, SharedDevice)> {
+        self.devices.lock().iter().map(|(id, device)| (*id, device.clone())).collect()
+    }
+
     /// Get a device by name
     /// 
     /// # Arguments
@@ -228,6 +309,19 @@ impl DeviceManager {
         name_to_id.get(name).cloned()
     }
 
+    /// Get the name a device is registered under
+    ///
+    /// # Arguments
+    /// * `id`: The id of the device.
+    ///
+    /// # Returns
+    /// * The first name of the device, or None if it has none.
+    ///
+    pub fn get_device_name(&self, id: usize) -> Option<String> {
+        let name_to_id = self.name_to_id.lock();
+        name_to_id.iter().find(|(_, device_id)| **device_id == id).map(|(name, _)| name.clone())
+    }
+
     /// Get the number of devices
     /// 
     /// # Returns
@@ -257,6 +351,22 @@ impl DeviceManager {
         None
     }
 
+    /// Get all devices of a specific type
+    /// 
+    /// # Arguments
+    /// * `device_type`: The device type to find.
+    /// 
+    /// # Returns
+    /// * A vector of (device ID, device) pairs ordered by device ID.
+    /// 
+    pub fn get_devices_by_type(&self, device_type: super::DeviceType) -> Vec<(usize, SharedDevice)> {
+        let devices = self.devices.lock();
+        devices.iter()
+            .filter(|(_, device)| device.device_type() == device_type)
+            .map(|(id, device)| (*id, device.clone()))
+            .collect()
+    }
+
     /// Get all devices registered by name
     /// 
     /// Returns an iterator over (name, device) pairs for all devices
@@ -357,7 +467,7 @@ impl DeviceManager {
                 // Get drivers for this priority level
                 let drivers = self.drivers.lock();
                 if let Some(driver_list) = drivers.get(&priority) {
-                    for driver in driver_list.iter() {
+                    for (driver_index, driver) in driver_list.iter().enumerate() {
                         if driver.match_table().iter().any(|&c| compatible.contains(&c)) {
                             let mut resources = Vec::new();
                             
@@ -396,6 +506,7 @@ impl DeviceManager {
                             } else {
                                 early_println!("Successfully probed {} device: {}", priority.description(), device.name());
                                 idx += 1;
+                                self.bound.lock().push((priority, driver_index, device));
                             }
                             break; // Found matching driver, move to next device
                         }
@@ -485,6 +596,43 @@ impl DeviceManager {
         self.register_driver(driver, DriverPriority::Standard);
     }
 
+    /// Suspend the probed devices through their drivers, last probed first
+    ///
+    /// Part of [`crate::device::power::suspend_devices`]. If a driver
+    /// refuses, the devices already suspended are resumed again.
+    pub fn suspend_drivers(&self) -> Result<(), &'static str> {
+        let drivers = self.drivers.lock();
+        let bound = self.bound.lock();
+        for (done, (priority, index, device)) in bound.iter().rev().enumerate() {
+            let Some(driver) = drivers.get(priority).and_then(|list| list.get(*index)) else {
+                continue;
+            };
+            if let Err(error) = driver.suspend(&**device) {
+                early_println!("[pm] Cannot suspend {} ({}): {}", device.name(), driver.name(), error);
+                for (priority, index, device) in bound.iter().rev().take(done).rev() {
+                    if let Some(driver) = drivers.get(priority).and_then(|list| list.get(*index)) {
+                        let _ = driver.resume(&**device);
+                    }
+                }
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    /// Resume the devices suspended by [`DeviceManager::suspend_drivers`],
+    /// first probed first
+    pub fn resume_drivers(&self) {
+        let drivers = self.drivers.lock();
+        for (priority, index, device) in self.bound.lock().iter() {
+            if let Some(driver) = drivers.get(priority).and_then(|list| list.get(*index)) {
+                if let Err(error) = driver.resume(&**device) {
+                    early_println!("[pm] Cannot resume {} ({}): {}", device.name(), driver.name(), error);
+                }
+            }
+        }
+    }
+
     /// Clear all devices and reset the manager state (for testing only)
     ///
     /// This method is only available in test builds and should only be used
@@ -582,4 +730,49 @@ mod tests {
         let device = manager.get_device_by_name("non_existent");
         assert!(device.is_none());
     }
+
+    #[test_case]
+    fn test_unregister_device_emits_hotplug() {
+        use core::sync::atomic::AtomicUsize;
+        use crate::device::events::DeviceEvent;
+
+        struct Counter {
+            added: AtomicUsize,
+            removed: AtomicUsize,
+            changed: AtomicUsize,
+        }
+
+        impl DeviceEventListener for Counter {
+            fn on_device_event(&self, event: &dyn DeviceEvent) {
+                let event = event.as_any().downcast_ref::<DeviceHotplugEvent>().unwrap();
+                match event.action {
+                    HotplugAction::Added => self.added.fetch_add(1, Ordering::SeqCst),
+                    HotplugAction::Removed => self.removed.fetch_add(1, Ordering::SeqCst),
+                    HotplugAction::Changed => self.changed.fetch_add(1, Ordering::SeqCst),
+                };
+                assert_eq!(event.name.as_deref(), Some("hotplug0"));
+            }
+
+            fn interested_in(&self, event_type: &str) -> bool {
+                event_type == "hotplug"
+            }
+        }
+
+        let counter = Arc::new(Counter { added: AtomicUsize::new(0), removed: AtomicUsize::new(0), changed: AtomicUsize::new(0) });
+        let manager = DeviceManager::new();
+        let weak: Weak<dyn DeviceEventListener> = Arc::downgrade(&counter) as Weak<dyn DeviceEventListener>;
+        manager.register_hotplug_listener(weak);
+
+        let id = manager.register_device_with_name("hotplug0".into(), Arc::new(GenericDevice::new("hotplug")));
+        assert_eq!(counter.added.load(Ordering::SeqCst), 1);
+        assert!(manager.notify_device_changed(id));
+        assert_eq!(counter.changed.load(Ordering::SeqCst), 1);
+
+        assert!(manager.unregister_device(id).is_some());
+        assert_eq!(counter.removed.load(Ordering::SeqCst), 1);
+        assert!(manager.get_device(id).is_none());
+        assert!(manager.get_device_by_name("hotplug0").is_none());
+        assert!(manager.unregister_device(id).is_none());
+        assert!(!manager.notify_device_changed(id));
+    }
 }
@@ -12,7 +12,15 @@ pub mod block;
 pub mod char;
 pub mod graphics;
 pub mod network;
+pub mod input;
+pub mod gpio;
+pub mod sound;
+pub mod usb;
 pub mod events;
+pub mod power;
+pub mod syscall;
+pub mod uevent;
+pub mod board;
 
 extern crate alloc;
 use core::any::Any;
@@ -37,6 +45,19 @@ pub trait DeviceDriver {
     fn match_table(&self) -> Vec<&'static str>;
     fn probe(&self, device: &dyn DeviceInfo) -> Result<(), &'static str>;
     fn remove(&self, device: &dyn DeviceInfo) -> Result<(), &'static str>;
+
+    /// Quiesce a device this driver probed, for system sleep
+    ///
+    /// Called after every [`Device`] has been suspended, for hardware such
+    /// as interrupt controllers that is set up by the driver alone.
+    fn suspend(&self, _device: &dyn DeviceInfo) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    /// Bring a device back from [`DeviceDriver::suspend`]
+    fn resume(&self, _device: &dyn DeviceInfo) -> Result<(), &'static str> {
+        Ok(())
+    }
 }
 
 /// Device type enumeration.
@@ -68,6 +89,31 @@ pub trait Device: Send + Sync + ControlOps + MemoryMappingOps {
     fn name(&self) -> &'static str;
     fn as_any(&self) -> &dyn Any;
     fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    /// Quiesce the device, for system sleep or, with runtime PM, while idle
+    ///
+    /// See [`power`] for when this is called; it must not sleep.
+    fn suspend(&self) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    /// Bring the device back from [`Device::suspend`]
+    fn resume(&self) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    /// Runtime power management state, for devices suspended while idle
+    fn runtime_pm(&self) -> Option<&power::RuntimePm> {
+        None
+    }
+
+    /// The device an open of this one gets, for devices giving each open
+    /// its own, as `/dev/ptmx` gives a new pseudo-terminal
+    ///
+    /// The instance lives as long as the file opened on it.
+    fn open_instance(&self) -> Result<Option<alloc::sync::Arc<dyn Device>>, &'static str> {
+        Ok(None)
+    }
     
     /// Cast to CharDevice if this device is a character device
     fn as_char_device(&self) -> Option<&dyn char::CharDevice> {
@@ -10,6 +10,7 @@ use spin::Mutex;
 use alloc::sync::Arc;
 
 use super::{Device, DeviceType, manager::DeviceManager};
+use crate::network::buffer::PacketBuffer;
 use crate::object::capability::{ControlOps, MemoryMappingOps};
 
 /// Get the first available network device
@@ -44,6 +45,15 @@ impl MacAddress {
         Ok(Self(mac))
     }
     
+    /// Create a random locally administered unicast MAC address, for
+    /// devices without one of their own
+    pub fn random_local() -> Self {
+        let mut bytes = [0u8; 6];
+        crate::random::fill_random_bytes(&mut bytes);
+        bytes[0] = (bytes[0] & !0x01) | 0x02;
+        Self(bytes)
+    }
+
     /// Get the MAC address as bytes
     pub fn as_bytes(&self) -> &[u8; 6] {
         &self.0
@@ -65,59 +75,125 @@ impl MacAddress {
     }
 }
 
+impl core::fmt::Display for MacAddress {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        let [a, b, c, d, e, g] = self.0;
+        write!(f, "{:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}", a, b, c, d, e, g)
+    }
+}
+
+/// Checksum work a packet leaves to the device, or the device did
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PacketChecksum {
+    /// Sent as it is; received without the transport checksum checked
+    #[default]
+    None,
+    /// Sent with a partial transport checksum for the device to complete:
+    /// the checksum of the bytes from `start` on goes at `start + offset`
+    Partial { start: usize, offset: usize },
+    /// Received with the transport checksum checked by the device
+    Verified,
+}
+
+/// Segmentation a packet leaves to the device (TCP segmentation offload)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PacketSegmentation {
+    /// Payload bytes of each TCP segment the device sends
+    pub segment_size: usize,
+    /// Length of the link, IPv4 and TCP headers repeated on every segment
+    pub header_len: usize,
+}
+
+/// Work a device does for the stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NetworkOffloads {
+    /// Completes partial transport checksums of sent packets
+    pub tx_checksum: bool,
+    /// Checks the transport checksums of received packets
+    pub rx_checksum: bool,
+    /// Cuts large TCP/IPv4 packets into segments
+    pub tso4: bool,
+}
+
 /// Device-level network packet for raw data transmission
+///
+/// The bytes are a [`PacketBuffer`] shared with the stack: a device that
+/// gathers segments sends [`segments`](DevicePacket::segments) as they are,
+/// one that does not takes them in one piece.
 #[derive(Debug, Clone)]
 pub struct DevicePacket {
-    /// Raw packet data
-    pub data: Vec<u8>,
-    /// Length of valid data in the packet
-    pub len: usize,
+    /// Bytes of the packet
+    pub buffer: PacketBuffer,
+    /// Checksum offload of the packet
+    pub checksum: PacketChecksum,
+    /// Segmentation offload of a sent packet
+    pub segmentation: Option<PacketSegmentation>,
 }
 
 impl DevicePacket {
     /// Create a new empty packet
     pub fn new() -> Self {
-        Self {
-            data: Vec::new(),
-            len: 0,
-        }
+        Self::with_data(Vec::new())
     }
     
     /// Create a new packet with the given data
     pub fn with_data(data: Vec<u8>) -> Self {
-        let len = data.len();
-        Self { data, len }
+        Self::with_buffer(PacketBuffer::from(data))
+    }
+
+    /// Create a new packet of the bytes of `buffer`, sharing them
+    pub fn with_buffer(buffer: PacketBuffer) -> Self {
+        Self { buffer, checksum: PacketChecksum::None, segmentation: None }
     }
     
     /// Create a new packet with the given capacity
     pub fn with_capacity(capacity: usize) -> Self {
-        Self {
-            data: Vec::with_capacity(capacity),
-            len: 0,
-        }
+        Self::with_buffer(PacketBuffer::with_room(0, capacity))
+    }
+
+    /// Length of the packet in bytes
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// The pieces of the packet, in order
+    pub fn segments(&self) -> impl Iterator<Item = &[u8]> {
+        self.buffer.segments()
     }
     
-    /// Get the packet data as a slice
+    /// Get the packet data as a slice; only the first segment of a packet
+    /// that is in several
     pub fn as_slice(&self) -> &[u8] {
-        &self.data[..self.len]
+        self.buffer.linear()
     }
     
-    /// Get the packet data as a mutable slice
+    /// Get the packet data as a mutable slice, in one piece
     pub fn as_mut_slice(&mut self) -> &mut [u8] {
-        &mut self.data[..self.len]
+        self.buffer.linearize()
+    }
+
+    /// The bytes of the packet in one piece
+    pub fn into_data(self) -> Vec<u8> {
+        self.buffer.into_vec()
     }
     
     /// Set the packet data
     pub fn set_data(&mut self, data: &[u8]) {
-        self.data.clear();
-        self.data.extend_from_slice(data);
-        self.len = data.len();
+        self.buffer = PacketBuffer::from(data.to_vec());
     }
     
     /// Resize the packet data buffer
     pub fn resize(&mut self, new_len: usize) {
-        self.data.resize(new_len, 0);
-        self.len = new_len;
+        let len = self.len();
+        if new_len <= len {
+            self.buffer.truncate(new_len);
+        } else {
+            self.buffer.extend_back(&alloc::vec![0; new_len - len]);
+        }
     }
 }
 
@@ -220,6 +296,11 @@ pub trait NetworkDevice: Device {
     
     /// Get network device statistics
     fn get_stats(&self) -> NetworkStats;
+
+    /// Offloads the device does; packets use only these
+    fn offloads(&self) -> NetworkOffloads {
+        NetworkOffloads::default()
+    }
 }
 
 /// Network device statistics
@@ -280,6 +361,11 @@ impl GenericNetworkDevice {
     pub fn add_received_packet(&self, packet: DevicePacket) {
         self.rx_queue.lock().push(packet);
     }
+
+    /// Take the packets sent so far (for testing)
+    pub fn take_transmitted_packets(&self) -> Vec<DevicePacket> {
+        core::mem::take(&mut *self.tx_queue.lock())
+    }
 }
 
 impl Device for GenericNetworkDevice {
@@ -302,6 +388,10 @@ impl Device for GenericNetworkDevice {
     fn as_network_device(&self) -> Option<&dyn NetworkDevice> {
         Some(self)
     }
+
+    fn into_network_device(self: Arc<Self>) -> Option<Arc<dyn NetworkDevice>> {
+        Some(self)
+    }
 }
 
 impl ControlOps for GenericNetworkDevice {
@@ -360,7 +450,7 @@ impl NetworkDevice for GenericNetworkDevice {
         {
             let mut stats = self.stats.lock();
             stats.tx_packets += 1;
-            stats.tx_bytes += packet.len as u64;
+            stats.tx_bytes += packet.len() as u64;
         }
         
         // In a real implementation, this would send the packet to hardware
@@ -383,7 +473,7 @@ impl NetworkDevice for GenericNetworkDevice {
         {
             let mut stats = self.stats.lock();
             stats.rx_packets += packets.len() as u64;
-            stats.rx_bytes += packets.iter().map(|p| p.len as u64).sum::<u64>();
+            stats.rx_bytes += packets.iter().map(|p| p.len() as u64).sum::<u64>();
         }
         
         Ok(packets)
@@ -455,22 +545,22 @@ mod tests {
     #[test_case]
     fn test_network_packet() {
         let mut packet = DevicePacket::new();
-        assert_eq!(packet.len, 0);
+        assert_eq!(packet.len(), 0);
         assert_eq!(packet.as_slice().len(), 0);
         
         let data = vec![0x00, 0x11, 0x22, 0x33];
         packet.set_data(&data);
-        assert_eq!(packet.len, 4);
+        assert_eq!(packet.len(), 4);
         assert_eq!(packet.as_slice(), &data);
         
         let packet2 = DevicePacket::with_data(data.clone());
-        assert_eq!(packet2.len, 4);
+        assert_eq!(packet2.len(), 4);
         assert_eq!(packet2.as_slice(), &data);
         
         let mut packet3 = DevicePacket::with_capacity(10);
         packet3.resize(6);
-        assert_eq!(packet3.len, 6);
-        assert_eq!(packet3.data.len(), 6);
+        assert_eq!(packet3.len(), 6);
+        assert_eq!(packet3.as_slice(), &[0; 6]);
     }
 
     #[test_case]
@@ -146,6 +146,8 @@ pub struct PlatformDeviceDriver {
     probe_fn: fn(&PlatformDeviceInfo) -> Result<(), &'static str>,
     remove_fn: fn(&PlatformDeviceInfo) -> Result<(), &'static str>,
     compatible: Vec<&'static str>, // Change to Vec<&'static str>
+    suspend_fn: Option<fn(&PlatformDeviceInfo) -> Result<(), &'static str>>,
+    resume_fn: Option<fn(&PlatformDeviceInfo) -> Result<(), &'static str>>,
 }
 
 impl PlatformDeviceDriver {
@@ -160,8 +162,21 @@ impl PlatformDeviceDriver {
             probe_fn,           
             remove_fn,
             compatible,
+            suspend_fn: None,
+            resume_fn: None,
         }
     }
+
+    /// Add system sleep hooks (see [`DeviceDriver::suspend`])
+    pub fn with_power(
+        mut self,
+        suspend_fn: fn(&PlatformDeviceInfo) -> Result<(), &'static str>,
+        resume_fn: fn(&PlatformDeviceInfo) -> Result<(), &'static str>,
+    ) -> Self {
+        self.suspend_fn = Some(suspend_fn);
+        self.resume_fn = Some(resume_fn);
+        self
+    }
 }
 
 impl DeviceDriver for PlatformDeviceDriver {
@@ -185,6 +200,20 @@ impl DeviceDriver for PlatformDeviceDriver {
     fn remove(&self, _device: &dyn DeviceInfo) -> Result<(), &'static str> {
         Ok(())
     }
+
+    fn suspend(&self, device: &dyn DeviceInfo) -> Result<(), &'static str> {
+        match (self.suspend_fn, device.as_any().downcast_ref::<PlatformDeviceInfo>()) {
+            (Some(suspend_fn), Some(device)) => suspend_fn(device),
+            _ => Ok(()),
+        }
+    }
+
+    fn resume(&self, device: &dyn DeviceInfo) -> Result<(), &'static str> {
+        match (self.resume_fn, device.as_any().downcast_ref::<PlatformDeviceInfo>()) {
+            (Some(resume_fn), Some(device)) => resume_fn(device),
+            _ => Ok(()),
+        }
+    }
 }
 
 
@@ -0,0 +1,392 @@
+//! Device power management
+//!
+//! Devices are powered down at two levels:
+//!
+//! - **System sleep**: [`suspend_devices`] quiesces every device through
+//!   [`Device::suspend`], newest first, then the drivers of platform devices
+//!   that have no [`Device`] of their own (interrupt controllers, clocks)
+//!   through [`DeviceDriver::suspend`](super::DeviceDriver::suspend).
+//!   [`resume_devices`] brings them back in the opposite order.
+//! - **Runtime PM**: a device that embeds a [`RuntimePm`] is suspended while
+//!   idle. Its users hold a reference ([`runtime_ref`]) while they need the
+//!   device; taking the first one resumes it, and once the last is dropped
+//!   the device is suspended by `kpmd` after its autosuspend delay.
+//!
+//! Runtime suspend uses the same [`Device::suspend`] and [`Device::resume`]
+//! hooks. A device that is runtime suspended when the system goes to sleep
+//! is already quiet and is left alone; after the system resumes it stays
+//! suspended until it is used again.
+//!
+//! The hooks run with the runtime PM state of the device locked, from any
+//! context. They must not sleep and must not take runtime references.
+
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use super::manager::{DeviceManager, SharedDevice};
+use super::Device;
+use crate::late_initcall;
+use crate::object::capability::poll::wait_for;
+use crate::task::kthread;
+use crate::timer::{get_tick, ms_to_ticks};
+
+/// How often `kpmd` looks for idle devices when none is about to expire
+const IDLE_SCAN_MS: u64 = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerState {
+    Active,
+    Suspended,
+}
+
+struct RuntimePmState {
+    usage: usize,
+    state: PowerState,
+    /// Tick the last reference was dropped
+    last_busy: u64,
+    /// Suspended by [`suspend_devices`] rather than for being idle
+    system_suspended: bool,
+}
+
+/// Runtime power management state of a device
+///
+/// Embedded in devices that can be suspended while idle and returned from
+/// [`Device::runtime_pm`]. Devices start out active.
+pub struct RuntimePm {
+    state: Mutex<RuntimePmState>,
+    autosuspend_ms: u64,
+}
+
+impl RuntimePm {
+    /// Suspend the device once it has been idle for `autosuspend_ms`
+    pub const fn new(autosuspend_ms: u64) -> Self {
+        Self {
+            state: Mutex::new(RuntimePmState { usage: 0, state: PowerState::Active, last_busy: 0, system_suspended: false }),
+            autosuspend_ms,
+        }
+    }
+
+    pub fn state(&self) -> PowerState {
+        self.state.lock().state
+    }
+
+    /// Runtime references held
+    pub fn usage_count(&self) -> usize {
+        self.state.lock().usage
+    }
+}
+
+/// Take a runtime reference on `device`, resuming it if it is suspended
+///
+/// Devices without runtime PM are always active.
+pub fn runtime_get(device: &dyn Device) -> Result<(), &'static str> {
+    let Some(pm) = device.runtime_pm() else {
+        return Ok(());
+    };
+    let mut state = pm.state.lock();
+    if state.system_suspended {
+        return Err("Device is suspended for system sleep");
+    }
+    if state.state == PowerState::Suspended {
+        device.resume()?;
+        state.state = PowerState::Active;
+    }
+    state.usage += 1;
+    Ok(())
+}
+
+/// Drop a runtime reference taken with [`runtime_get`]
+///
+/// The last one starts the autosuspend delay.
+pub fn runtime_put(device: &dyn Device) {
+    let Some(pm) = device.runtime_pm() else {
+        return;
+    };
+    let mut state = pm.state.lock();
+    state.usage = state.usage.saturating_sub(1);
+    if state.usage == 0 {
+        state.last_busy = get_tick();
+    }
+}
+
+/// A runtime reference, dropped when this goes out of scope
+pub struct RuntimeRef<'a> {
+    device: &'a dyn Device,
+}
+
+impl Drop for RuntimeRef<'_> {
+    fn drop(&mut self) {
+        runtime_put(self.device);
+    }
+}
+
+/// Take a runtime reference on `device` for as long as the result lives
+pub fn runtime_ref(device: &dyn Device) -> Result<RuntimeRef<'_>, &'static str> {
+    runtime_get(device)?;
+    Ok(RuntimeRef { device })
+}
+
+/// Suspend the devices whose autosuspend delay has passed at `now`
+///
+/// Returns the tick the next idle device is due, if any.
+fn autosuspend(devices: &[SharedDevice], now: u64) -> Option<u64> {
+    let mut next = None;
+    for device in devices {
+        let Some(pm) = device.runtime_pm() else {
+            continue;
+        };
+        let mut state = pm.state.lock();
+        if state.usage > 0 || state.state == PowerState::Suspended || state.system_suspended {
+            continue;
+        }
+        let due = state.last_busy.saturating_add(ms_to_ticks(pm.autosuspend_ms));
+        if now < due {
+            next = Some(next.map_or(due, |next: u64| next.min(due)));
+        } else if device.suspend().is_ok() {
+            state.state = PowerState::Suspended;
+        }
+    }
+    next
+}
+
+fn kpmd_thread() {
+    while !kthread::should_stop() {
+        let devices: Vec<SharedDevice> =
+            DeviceManager::get_manager().get_all_devices().into_iter().map(|(_, device)| device).collect();
+        let now = get_tick();
+        let next = autosuspend(&devices, now);
+        drop(devices);
+        let scan = ms_to_ticks(IDLE_SCAN_MS);
+        let ticks = next.map_or(scan, |next| (next - now).min(scan)).max(1);
+        wait_for(&[], Some(ticks), || false);
+    }
+}
+
+/// Start `kpmd` if a device uses runtime PM; they are all probed by now
+fn init_runtime_pm() {
+    let devices = DeviceManager::get_manager().get_all_devices();
+    if devices.iter().any(|(_, device)| device.runtime_pm().is_some()) {
+        kthread::spawn("kpmd", kpmd_thread);
+    }
+}
+
+late_initcall!(init_runtime_pm);
+
+/// Devices suspended by [`suspend_devices`], with whether their hooks ran
+static SYSTEM_SUSPENDED: Mutex<Vec<(SharedDevice, bool)>> = Mutex::new(Vec::new());
+
+/// Quiesce `device` for system sleep
+///
+/// Returns whether its hooks were called, that is, whether it needs
+/// [`resume_device`] afterwards.
+fn suspend_device(device: &dyn Device) -> Result<bool, &'static str> {
+    let Some(pm) = device.runtime_pm() else {
+        return device.suspend().map(|()| true);
+    };
+    let mut state = pm.state.lock();
+    let was_active = state.state == PowerState::Active;
+    if was_active {
+        device.suspend()?;
+        state.state = PowerState::Suspended;
+    }
+    state.system_suspended = true;
+    Ok(was_active)
+}
+
+/// Undo [`suspend_device`]; `active` is what it returned
+fn resume_device(device: &dyn Device, active: bool) -> Result<(), &'static str> {
+    let Some(pm) = device.runtime_pm() else {
+        return device.resume();
+    };
+    let mut state = pm.state.lock();
+    state.system_suspended = false;
+    if active {
+        device.resume()?;
+        state.state = PowerState::Active;
+    }
+    Ok(())
+}
+
+/// Quiesce `devices` in reverse order, undoing it all on a failure
+fn suspend_all(devices: &[SharedDevice]) -> Result<Vec<(SharedDevice, bool)>, &'static str> {
+    let mut suspended = Vec::new();
+    for device in devices.iter().rev() {
+        match suspend_device(&**device) {
+            Ok(active) => suspended.push((device.clone(), active)),
+            Err(error) => {
+                crate::early_println!("[pm] Cannot suspend {}: {}", device.name(), error);
+                resume_all(suspended);
+                return Err(error);
+            }
+        }
+    }
+    Ok(suspended)
+}
+
+/// Resume what [`suspend_all`] suspended, the last suspended first
+fn resume_all(suspended: Vec<(SharedDevice, bool)>) {
+    for (device, active) in suspended.into_iter().rev() {
+        if let Err(error) = resume_device(&*device, active) {
+            crate::early_println!("[pm] Cannot resume {}: {}", device.name(), error);
+        }
+    }
+}
+
+/// Quiesce all devices for system sleep
+///
+/// Devices are suspended newest first, so that a device goes down before
+/// those it was probed on top of, then the platform drivers. If one
+/// refuses, those already suspended are resumed again and its error is
+/// returned.
+pub fn suspend_devices() -> Result<(), &'static str> {
+    let manager = DeviceManager::get_manager();
+    let devices: Vec<SharedDevice> = manager.get_all_devices().into_iter().map(|(_, device)| device).collect();
+    let suspended = suspend_all(&devices)?;
+    if let Err(error) = manager.suspend_drivers() {
+        resume_all(suspended);
+        return Err(error);
+    }
+    *SYSTEM_SUSPENDED.lock() = suspended;
+    Ok(())
+}
+
+/// Bring the devices back from [`suspend_devices`]
+///
+/// Devices that were runtime suspended stay so until they are used.
+pub fn resume_devices() {
+    DeviceManager::get_manager().resume_drivers();
+    let suspended = core::mem::take(&mut *SYSTEM_SUSPENDED.lock());
+    resume_all(suspended);
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::sync::Arc;
+    use core::any::Any;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::device::DeviceType;
+    use crate::object::capability::{ControlOps, MemoryMappingOps};
+
+    struct PmDevice {
+        pm: Option<RuntimePm>,
+        suspends: AtomicUsize,
+        resumes: AtomicUsize,
+    }
+
+    impl PmDevice {
+        fn new(pm: Option<RuntimePm>) -> Arc<Self> {
+            Arc::new(Self { pm, suspends: AtomicUsize::new(0), resumes: AtomicUsize::new(0) })
+        }
+
+        fn counts(&self) -> (usize, usize) {
+            (self.suspends.load(Ordering::SeqCst), self.resumes.load(Ordering::SeqCst))
+        }
+    }
+
+    impl Device for PmDevice {
+        fn device_type(&self) -> DeviceType {
+            DeviceType::Generic
+        }
+
+        fn name(&self) -> &'static str {
+            "pm-test"
+        }
+
+        fn as_any(&self) -> &dyn Any {
+            self
+        }
+
+        fn as_any_mut(&mut self) -> &mut dyn Any {
+            self
+        }
+
+        fn suspend(&self) -> Result<(), &'static str> {
+            self.suspends.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn resume(&self) -> Result<(), &'static str> {
+            self.resumes.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn runtime_pm(&self) -> Option<&RuntimePm> {
+            self.pm.as_ref()
+        }
+    }
+
+    impl ControlOps for PmDevice {
+        fn control(&self, _command: u32, _arg: usize) -> Result<i32, &'static str> {
+            Err("Control operations not supported")
+        }
+    }
+
+    impl MemoryMappingOps for PmDevice {
+        fn get_mapping_info(&self, _offset: usize, _length: usize) -> Result<(usize, usize, bool), &'static str> {
+            Err("Memory mapping not supported")
+        }
+
+        fn on_mapped(&self, _vaddr: usize, _paddr: usize, _length: usize, _offset: usize) {}
+
+        fn on_unmapped(&self, _vaddr: usize, _length: usize) {}
+
+        fn supports_mmap(&self) -> bool {
+            false
+        }
+    }
+
+    #[test_case]
+    fn test_runtime_autosuspend() {
+        let device = PmDevice::new(Some(RuntimePm::new(100)));
+        let devices: [SharedDevice; 1] = [device.clone()];
+        let pm = device.runtime_pm().unwrap();
+
+        let reference = runtime_ref(&*device).unwrap();
+        assert_eq!(pm.usage_count(), 1);
+        // In use: never suspended
+        assert_eq!(autosuspend(&devices, u64::MAX), None);
+        drop(reference);
+
+        let idle = pm.state.lock().last_busy;
+        let due = idle + ms_to_ticks(100);
+        assert_eq!(autosuspend(&devices, idle), Some(due));
+        assert_eq!(pm.state(), PowerState::Active);
+        assert_eq!(autosuspend(&devices, due), None);
+        assert_eq!(pm.state(), PowerState::Suspended);
+        assert_eq!(device.counts(), (1, 0));
+
+        // The next user resumes it
+        runtime_get(&*device).unwrap();
+        assert_eq!(pm.state(), PowerState::Active);
+        assert_eq!(device.counts(), (1, 1));
+        runtime_put(&*device);
+    }
+
+    #[test_case]
+    fn test_system_suspend_leaves_runtime_suspended() {
+        let plain = PmDevice::new(None);
+        let idle = PmDevice::new(Some(RuntimePm::new(0)));
+        let busy = PmDevice::new(Some(RuntimePm::new(0)));
+        let devices: [SharedDevice; 3] = [plain.clone(), idle.clone(), busy.clone()];
+        autosuspend(&devices[1..2], u64::MAX);
+        assert_eq!(idle.counts(), (1, 0));
+        runtime_get(&*busy).unwrap();
+
+        let suspended = suspend_all(&devices).unwrap();
+        assert_eq!(plain.counts(), (1, 0));
+        assert_eq!(idle.counts(), (1, 0));
+        assert_eq!(busy.counts(), (1, 0));
+        // No new users while the system sleeps
+        assert!(runtime_get(&*idle).is_err());
+
+        resume_all(suspended);
+        assert_eq!(plain.counts(), (1, 1));
+        assert_eq!(idle.counts(), (1, 0));
+        assert_eq!(busy.counts(), (1, 1));
+        assert_eq!(idle.runtime_pm().unwrap().state(), PowerState::Suspended);
+        assert_eq!(busy.runtime_pm().unwrap().state(), PowerState::Active);
+        runtime_put(&*busy);
+    }
+}
@@ -0,0 +1,1200 @@
+//! Sound
+//!
+//! A sound driver plays PCM audio: frames of samples, one per channel, at a
+//! fixed rate. It describes what its hardware takes ([`PcmHardware`]),
+//! implements [`PcmDriver`] for each playback stream, wraps it in a
+//! [`PcmDevice`] and registers it with [`register`]. The PCM core owns the
+//! buffer of the stream and hands it to the driver a period at a time; the
+//! driver reports each period played with [`PcmDevice::period_elapsed`],
+//! which is what paces the stream.
+//!
+//! Every stream is the character device `/dev/snd/pcmCxDyp` and speaks
+//! the PCM protocol of ALSA, so that tinyalsa and the `hw` plugin of
+//! alsa-lib play through it:
+//!
+//! - `SNDRV_PCM_IOCTL_HW_REFINE` narrows each parameter to what the
+//!   hardware takes; `SNDRV_PCM_IOCTL_HW_PARAMS` settles on the smallest
+//!   value left of each and allocates the buffer.
+//! - `SNDRV_PCM_IOCTL_PREPARE`, `START`, `DROP` and `DRAIN` move the stream
+//!   through the ALSA states. A stream that runs out of frames is in
+//!   `XRUN` and writes fail with `EPIPE` until it is prepared again.
+//! - frames are written with `write` or `SNDRV_PCM_IOCTL_WRITEI_FRAMES`,
+//!   blocking while the buffer is full, or into the buffer mapped with
+//!   `mmap` and committed with `SNDRV_PCM_IOCTL_SYNC_PTR`.
+//! - the stream starts by itself once `start_threshold` frames are
+//!   buffered, a period unless the software parameters say otherwise.
+//!
+//! Only interleaved playback is supported, and a stream is opened by one
+//! player at a time: it is not reset when its file is closed.
+
+use core::any::Any;
+
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::{Mutex, Once};
+
+use super::char::CharDevice;
+use super::manager::DeviceManager;
+use super::{Device, DeviceType};
+use crate::environment::PAGE_SIZE;
+use crate::mem::page::{allocate_raw_pages, free_raw_pages, Page};
+use crate::object::capability::{ControlOps, MemoryMappingOps};
+use crate::sync::waker::Waker;
+use crate::task::mytask;
+use crate::task::signal::{read_user, write_user};
+
+/// Version of the PCM protocol: 2.0.15
+pub const SNDRV_PCM_VERSION: i32 = 0x2000f;
+
+/// ALSA stream states
+pub const SNDRV_PCM_STATE_OPEN: i32 = 0;
+pub const SNDRV_PCM_STATE_SETUP: i32 = 1;
+pub const SNDRV_PCM_STATE_PREPARED: i32 = 2;
+pub const SNDRV_PCM_STATE_RUNNING: i32 = 3;
+pub const SNDRV_PCM_STATE_XRUN: i32 = 4;
+pub const SNDRV_PCM_STATE_DRAINING: i32 = 5;
+
+/// Access types
+pub const SNDRV_PCM_ACCESS_MMAP_INTERLEAVED: u32 = 0;
+pub const SNDRV_PCM_ACCESS_RW_INTERLEAVED: u32 = 3;
+
+/// Hardware parameters: the masks, then the intervals
+pub const SNDRV_PCM_HW_PARAM_ACCESS: usize = 0;
+pub const SNDRV_PCM_HW_PARAM_FORMAT: usize = 1;
+pub const SNDRV_PCM_HW_PARAM_SUBFORMAT: usize = 2;
+pub const SNDRV_PCM_HW_PARAM_SAMPLE_BITS: usize = 8;
+pub const SNDRV_PCM_HW_PARAM_FRAME_BITS: usize = 9;
+pub const SNDRV_PCM_HW_PARAM_CHANNELS: usize = 10;
+pub const SNDRV_PCM_HW_PARAM_RATE: usize = 11;
+pub const SNDRV_PCM_HW_PARAM_PERIOD_TIME: usize = 12;
+pub const SNDRV_PCM_HW_PARAM_PERIOD_SIZE: usize = 13;
+pub const SNDRV_PCM_HW_PARAM_PERIOD_BYTES: usize = 14;
+pub const SNDRV_PCM_HW_PARAM_PERIODS: usize = 15;
+pub const SNDRV_PCM_HW_PARAM_BUFFER_TIME: usize = 16;
+pub const SNDRV_PCM_HW_PARAM_BUFFER_SIZE: usize = 17;
+pub const SNDRV_PCM_HW_PARAM_BUFFER_BYTES: usize = 18;
+pub const SNDRV_PCM_HW_PARAM_TICK_TIME: usize = 19;
+
+/// What the stream can do, in the `info` of the hardware parameters
+pub const SNDRV_PCM_INFO_MMAP: u32 = 0x1;
+pub const SNDRV_PCM_INFO_MMAP_VALID: u32 = 0x2;
+pub const SNDRV_PCM_INFO_INTERLEAVED: u32 = 0x100;
+pub const SNDRV_PCM_INFO_BLOCK_TRANSFER: u32 = 0x10000;
+
+/// `flags` of `struct snd_pcm_sync_ptr`: what is read rather than written
+pub const SNDRV_PCM_SYNC_PTR_HWSYNC: u32 = 1 << 0;
+pub const SNDRV_PCM_SYNC_PTR_APPL: u32 = 1 << 1;
+pub const SNDRV_PCM_SYNC_PTR_AVAIL_MIN: u32 = 1 << 2;
+
+/// PCM control commands (Linux ioctl numbers)
+pub mod pcm_commands {
+    /// The type of every PCM command
+    pub const SNDRV_PCM_IOCTL_TYPE: u32 = 0x41;
+
+    /// Protocol version (arg: *mut i32)
+    pub const SNDRV_PCM_IOCTL_PVERSION: u32 = 0x80044100;
+    /// Describe the stream (arg: *mut `struct snd_pcm_info`)
+    pub const SNDRV_PCM_IOCTL_INFO: u32 = 0x81204101;
+    /// Narrow the hardware parameters (arg: *mut `struct snd_pcm_hw_params`)
+    pub const SNDRV_PCM_IOCTL_HW_REFINE: u32 = 0xc2604110;
+    /// Set the hardware parameters (arg: *mut `struct snd_pcm_hw_params`)
+    pub const SNDRV_PCM_IOCTL_HW_PARAMS: u32 = 0xc2604111;
+    /// Free the buffer
+    pub const SNDRV_PCM_IOCTL_HW_FREE: u32 = 0x4112;
+    /// Set the software parameters (arg: *mut `struct snd_pcm_sw_params`)
+    pub const SNDRV_PCM_IOCTL_SW_PARAMS: u32 = 0xc0884113;
+    /// Frames buffered but not played (arg: *mut i64)
+    pub const SNDRV_PCM_IOCTL_DELAY: u32 = 0x80084121;
+    /// Bring the hardware pointer up to date
+    pub const SNDRV_PCM_IOCTL_HWSYNC: u32 = 0x4122;
+    /// Exchange the pointers (arg: *mut `struct snd_pcm_sync_ptr`)
+    pub const SNDRV_PCM_IOCTL_SYNC_PTR: u32 = 0xc0884123;
+    pub const SNDRV_PCM_IOCTL_PREPARE: u32 = 0x4140;
+    pub const SNDRV_PCM_IOCTL_RESET: u32 = 0x4141;
+    pub const SNDRV_PCM_IOCTL_START: u32 = 0x4142;
+    pub const SNDRV_PCM_IOCTL_DROP: u32 = 0x4143;
+    pub const SNDRV_PCM_IOCTL_DRAIN: u32 = 0x4144;
+    /// Write interleaved frames (arg: *mut `struct snd_xferi`)
+    pub const SNDRV_PCM_IOCTL_WRITEI_FRAMES: u32 = 0x40184150;
+}
+
+use pcm_commands::*;
+
+/// Whether `command` is a PCM control command
+pub fn is_pcm_command(command: u32) -> bool {
+    (command >> 8) & 0xff == SNDRV_PCM_IOCTL_TYPE
+}
+
+/// Sizes and offsets of the structures of the protocol
+const PCM_INFO_SIZE: usize = 288;
+const HW_PARAMS_SIZE: usize = 608;
+const HW_PARAMS_MASKS: usize = 4;
+const HW_PARAMS_INTERVALS: usize = 260;
+const HW_PARAMS_INFO: usize = 520;
+const HW_PARAMS_MSBITS: usize = 524;
+const HW_PARAMS_RATE_NUM: usize = 528;
+const HW_PARAMS_RATE_DEN: usize = 532;
+const SW_PARAMS_SIZE: usize = 136;
+const SYNC_PTR_SIZE: usize = 136;
+const XFERI_SIZE: usize = 24;
+
+/// Flags of an interval
+const INTERVAL_INTEGER: u32 = 1 << 2;
+const INTERVAL_EMPTY: u32 = 1 << 3;
+
+/// Format of the samples, in memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PcmFormat {
+    S8,
+    U8,
+    S16Le,
+    U16Le,
+    /// 24 bits in the low bytes of 32
+    S24Le,
+    S32Le,
+    FloatLe,
+}
+
+impl PcmFormat {
+    pub const ALL: [PcmFormat; 7] = [
+        PcmFormat::S8,
+        PcmFormat::U8,
+        PcmFormat::S16Le,
+        PcmFormat::U16Le,
+        PcmFormat::S24Le,
+        PcmFormat::S32Le,
+        PcmFormat::FloatLe,
+    ];
+
+    /// `SNDRV_PCM_FORMAT_*`
+    pub fn alsa(self) -> u32 {
+        match self {
+            PcmFormat::S8 => 0,
+            PcmFormat::U8 => 1,
+            PcmFormat::S16Le => 2,
+            PcmFormat::U16Le => 4,
+            PcmFormat::S24Le => 6,
+            PcmFormat::S32Le => 10,
+            PcmFormat::FloatLe => 14,
+        }
+    }
+
+    pub fn from_alsa(format: u32) -> Option<Self> {
+        Self::ALL.into_iter().find(|candidate| candidate.alsa() == format)
+    }
+
+    /// Bits of a sample that carry sound
+    pub fn width(self) -> u32 {
+        match self {
+            PcmFormat::S8 | PcmFormat::U8 => 8,
+            PcmFormat::S16Le | PcmFormat::U16Le => 16,
+            PcmFormat::S24Le => 24,
+            PcmFormat::S32Le | PcmFormat::FloatLe => 32,
+        }
+    }
+
+    /// Bytes a sample takes
+    pub fn physical_bytes(self) -> usize {
+        match self {
+            PcmFormat::S24Le => 4,
+            format => format.width() as usize / 8,
+        }
+    }
+
+    /// Byte of silence at `index` within a sample
+    fn silence(self, index: usize) -> u8 {
+        match self {
+            PcmFormat::U8 => 0x80,
+            PcmFormat::U16Le if index == 1 => 0x80,
+            _ => 0,
+        }
+    }
+}
+
+/// What the hardware of a stream takes
+#[derive(Debug, Clone)]
+pub struct PcmHardware {
+    pub formats: Vec<PcmFormat>,
+    /// Frame rates, ascending
+    pub rates: Vec<u32>,
+    pub channels_min: u32,
+    pub channels_max: u32,
+    pub buffer_bytes_max: usize,
+    pub period_bytes_min: usize,
+    pub period_bytes_max: usize,
+    pub periods_min: usize,
+    pub periods_max: usize,
+}
+
+/// Settled parameters of a stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PcmParams {
+    pub access: u32,
+    pub format: PcmFormat,
+    pub channels: u32,
+    pub rate: u32,
+    pub period_frames: usize,
+    pub periods: usize,
+}
+
+impl PcmParams {
+    pub fn frame_bytes(&self) -> usize {
+        self.format.physical_bytes() * self.channels as usize
+    }
+
+    pub fn period_bytes(&self) -> usize {
+        self.period_frames * self.frame_bytes()
+    }
+
+    pub fn buffer_frames(&self) -> usize {
+        self.period_frames * self.periods
+    }
+
+    pub fn buffer_bytes(&self) -> usize {
+        self.buffer_frames() * self.frame_bytes()
+    }
+}
+
+/// The hardware of a playback stream
+///
+/// The core calls [`prepare`](Self::prepare) before the stream is started
+/// and [`release`](Self::release) before it is prepared again; the driver
+/// is only stopped and released by the core, never from
+/// [`PcmDevice::period_elapsed`].
+pub trait PcmDriver: Send + Sync {
+    /// Name of the stream (`virtio-snd stream 0`)
+    fn name(&self) -> &str;
+
+    fn hardware(&self) -> PcmHardware;
+
+    /// Get ready to play with `params`
+    fn prepare(&self, params: &PcmParams) -> Result<(), &'static str>;
+
+    fn start(&self) -> Result<(), &'static str>;
+
+    fn stop(&self) -> Result<(), &'static str>;
+
+    /// Give back the periods still queued, without reporting them
+    fn release(&self) -> Result<(), &'static str>;
+
+    /// Play `data` after what is queued
+    ///
+    /// `data` is in the buffer of the stream, which stays allocated until
+    /// the stream is released; the driver reports it played with
+    /// [`PcmDevice::period_elapsed`], in order.
+    fn queue(&self, data: &[u8]) -> Result<(), &'static str>;
+}
+
+/// Pages of the buffer of a stream, shared with the hardware and mapped
+/// by players
+struct PcmBuffer {
+    pages: *mut Page,
+    count: usize,
+}
+
+unsafe impl Send for PcmBuffer {}
+
+impl PcmBuffer {
+    fn new(bytes: usize) -> Result<Self, &'static str> {
+        let count = bytes.div_ceil(PAGE_SIZE).max(1);
+        let pages = allocate_raw_pages(count);
+        if pages.is_null() {
+            return Err("Failed to allocate sound buffer");
+        }
+        unsafe { core::ptr::write_bytes(pages as *mut u8, 0, count * PAGE_SIZE) };
+        Ok(Self { pages, count })
+    }
+
+    fn addr(&self) -> usize {
+        self.pages as usize
+    }
+
+    fn bytes(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.pages as *mut u8, self.count * PAGE_SIZE) }
+    }
+}
+
+impl Drop for PcmBuffer {
+    fn drop(&mut self) {
+        free_raw_pages(self.pages, self.count);
+    }
+}
+
+struct PcmState {
+    state: i32,
+    params: Option<PcmParams>,
+    buffer: Option<PcmBuffer>,
+    /// Frames played, queued to the driver and written, since prepared
+    hw_ptr: u64,
+    queued_ptr: u64,
+    appl_ptr: u64,
+    /// Frames of each chunk queued to the driver, oldest first
+    in_flight: VecDeque<u64>,
+    /// Whether the driver was prepared and not released, and started and
+    /// not stopped
+    driver_prepared: bool,
+    driver_started: bool,
+    avail_min: u64,
+    start_threshold: u64,
+    /// Mappings of the buffer
+    mapped: usize,
+}
+
+impl PcmState {
+    fn buffer_frames(&self) -> u64 {
+        self.params.map_or(0, |params| params.buffer_frames() as u64)
+    }
+
+    /// Frames that can be written
+    fn space(&self) -> u64 {
+        self.buffer_frames() - (self.appl_ptr - self.hw_ptr)
+    }
+}
+
+/// A playback stream and its character device
+pub struct PcmDevice {
+    driver: Arc<dyn PcmDriver>,
+    card: usize,
+    device: usize,
+    state: Mutex<PcmState>,
+    /// Players waiting for room in the buffer or for a drain
+    waker: Waker,
+    /// Id in the device manager, once registered
+    device_id: Once<usize>,
+}
+
+impl PcmDevice {
+    /// Stream `device` of sound card `card`, see [`next_card`]
+    pub fn new(driver: Arc<dyn PcmDriver>, card: usize, device: usize) -> Arc<Self> {
+        Arc::new(Self {
+            driver,
+            card,
+            device,
+            state: Mutex::new(PcmState {
+                state: SNDRV_PCM_STATE_OPEN,
+                params: None,
+                buffer: None,
+                hw_ptr: 0,
+                queued_ptr: 0,
+                appl_ptr: 0,
+                in_flight: VecDeque::new(),
+                driver_prepared: false,
+                driver_started: false,
+                avail_min: 1,
+                start_threshold: 1,
+                mapped: 0,
+            }),
+            waker: Waker::new_interruptible("pcm"),
+            device_id: Once::new(),
+        })
+    }
+
+    /// `snd/pcmCxDyp`
+    pub fn node_name(&self) -> String {
+        format!("snd/pcmC{}D{}p", self.card, self.device)
+    }
+
+    /// The oldest chunk queued to the driver was played
+    ///
+    /// Called by the driver for each chunk, from any context. The next are
+    /// queued; a stream with nothing left to play is in `XRUN`, or done
+    /// when draining.
+    pub fn period_elapsed(&self) {
+        let mut state = self.state.lock();
+        let Some(frames) = state.in_flight.pop_front() else {
+            return;
+        };
+        state.hw_ptr += frames;
+        self.feed(&mut state);
+        if state.in_flight.is_empty() {
+            match state.state {
+                SNDRV_PCM_STATE_RUNNING => state.state = SNDRV_PCM_STATE_XRUN,
+                SNDRV_PCM_STATE_DRAINING => state.state = SNDRV_PCM_STATE_SETUP,
+                _ => {}
+            }
+        }
+        drop(state);
+        self.waker.wake_all();
+    }
+
+    /// Queue to the driver the frames written, a period at a time and the
+    /// rest when draining
+    fn feed(&self, state: &mut PcmState) {
+        let Some(params) = state.params else {
+            return;
+        };
+        if !matches!(state.state, SNDRV_PCM_STATE_PREPARED | SNDRV_PCM_STATE_RUNNING | SNDRV_PCM_STATE_DRAINING) {
+            return;
+        }
+        let frame_bytes = params.frame_bytes();
+        let buffer_frames = params.buffer_frames() as u64;
+        while state.in_flight.len() < params.periods {
+            let ready = state.appl_ptr - state.queued_ptr;
+            let frames = match ready.min(params.period_frames as u64) {
+                0 => break,
+                frames if frames < params.period_frames as u64 && state.state != SNDRV_PCM_STATE_DRAINING => break,
+                frames => frames,
+            };
+            let start = (state.queued_ptr % buffer_frames) as usize * frame_bytes;
+            let Some(buffer) = state.buffer.as_mut() else {
+                return;
+            };
+            let data = &buffer.bytes()[start..start + frames as usize * frame_bytes];
+            if self.driver.queue(data).is_err() {
+                // Retried when the next period is played
+                break;
+            }
+            state.queued_ptr += frames;
+            state.in_flight.push_back(frames);
+        }
+    }
+
+    fn start_locked(&self, state: &mut PcmState) -> Result<(), &'static str> {
+        if state.state != SNDRV_PCM_STATE_PREPARED {
+            return Err("Stream is not prepared");
+        }
+        if state.appl_ptr == state.hw_ptr {
+            // Nothing to play would be an immediate underrun
+            state.state = SNDRV_PCM_STATE_XRUN;
+            return Err("Broken pipe");
+        }
+        self.driver.start()?;
+        state.driver_started = true;
+        state.state = SNDRV_PCM_STATE_RUNNING;
+        self.feed(state);
+        Ok(())
+    }
+
+    /// Frames were written: queue them, and start once enough are buffered
+    fn committed(&self, state: &mut PcmState) {
+        self.feed(state);
+        let buffered = state.appl_ptr - state.hw_ptr;
+        if state.state == SNDRV_PCM_STATE_PREPARED && buffered >= state.start_threshold.min(state.buffer_frames()) {
+            let _ = self.start_locked(state);
+        }
+    }
+
+    /// Stop the hardware and forget what it was given
+    fn stop_locked(&self, state: &mut PcmState) -> Result<(), &'static str> {
+        if state.driver_started {
+            let _ = self.driver.stop();
+            state.driver_started = false;
+        }
+        if state.driver_prepared {
+            self.driver.release()?;
+            state.driver_prepared = false;
+        }
+        state.in_flight.clear();
+        state.queued_ptr = state.hw_ptr;
+        Ok(())
+    }
+
+    fn prepare(&self) -> Result<(), &'static str> {
+        let mut state = self.state.lock();
+        let params = state.params.ok_or("Stream is not set up")?;
+        self.stop_locked(&mut state)?;
+        self.driver.prepare(&params)?;
+        state.driver_prepared = true;
+        state.hw_ptr = 0;
+        state.queued_ptr = 0;
+        state.appl_ptr = 0;
+        state.state = SNDRV_PCM_STATE_PREPARED;
+        drop(state);
+        self.waker.wake_all();
+        Ok(())
+    }
+
+    fn drop_stream(&self) -> Result<(), &'static str> {
+        let mut state = self.state.lock();
+        if state.params.is_none() {
+            return Err("Stream is not set up");
+        }
+        self.stop_locked(&mut state)?;
+        state.state = SNDRV_PCM_STATE_SETUP;
+        drop(state);
+        self.waker.wake_all();
+        Ok(())
+    }
+
+    /// Play what was written and wait until it is played
+    fn drain(&self) -> Result<(), &'static str> {
+        {
+            let mut state = self.state.lock();
+            match state.state {
+                SNDRV_PCM_STATE_PREPARED if state.appl_ptr > state.hw_ptr => {
+                    self.start_locked(&mut state)?;
+                }
+                SNDRV_PCM_STATE_RUNNING => {}
+                SNDRV_PCM_STATE_PREPARED | SNDRV_PCM_STATE_XRUN => {
+                    state.state = SNDRV_PCM_STATE_SETUP;
+                    return Ok(());
+                }
+                SNDRV_PCM_STATE_OPEN => return Err("Stream is not set up"),
+                _ => return Ok(()),
+            }
+            state.state = SNDRV_PCM_STATE_DRAINING;
+            self.feed(&mut state);
+            if state.in_flight.is_empty() {
+                state.state = SNDRV_PCM_STATE_SETUP;
+            }
+        }
+        self.wait(|state| state.state != SNDRV_PCM_STATE_DRAINING)
+    }
+
+    /// Sleep until `ready` holds of the stream
+    fn wait(&self, ready: impl Fn(&PcmState) -> bool) -> Result<(), &'static str> {
+        loop {
+            if ready(&self.state.lock()) {
+                return Ok(());
+            }
+            let Some(task) = mytask() else {
+                return Err("Operation would block");
+            };
+            let task_id = task.get_id();
+            self.waker.wait_unless(task_id, task.get_trapframe(), || {
+                ready(&self.state.lock()) || mytask().is_some_and(|task| task.signals.has_pending())
+            });
+            if task.signals.has_pending() {
+                return Err("Interrupted");
+            }
+        }
+    }
+
+    /// Write `frames` frames, taking the bytes of each run of them from
+    /// `copy`, which is given the offset in the source
+    fn write_frames(
+        &self,
+        frames: usize,
+        copy: impl Fn(usize, &mut [u8]) -> Result<(), &'static str>,
+    ) -> Result<usize, &'static str> {
+        let mut done = 0;
+        while done < frames {
+            let mut state = self.state.lock();
+            let params = state.params.ok_or("Stream is not set up")?;
+            match state.state {
+                SNDRV_PCM_STATE_PREPARED | SNDRV_PCM_STATE_RUNNING => {}
+                SNDRV_PCM_STATE_XRUN => return Err("Broken pipe"),
+                _ => return Err("Stream is not prepared"),
+            }
+            if state.space() == 0 {
+                if state.state == SNDRV_PCM_STATE_PREPARED {
+                    // A full buffer starts the stream whatever the threshold
+                    self.start_locked(&mut state)?;
+                }
+                drop(state);
+                let waited = self.wait(|state| state.space() > 0 || state.state != SNDRV_PCM_STATE_RUNNING);
+                if let Err(error) = waited {
+                    return if done > 0 { Ok(done) } else { Err(error) };
+                }
+                continue;
+            }
+            let buffer_frames = params.buffer_frames() as u64;
+            let offset = (state.appl_ptr % buffer_frames) as usize;
+            let count = (state.space() as usize).min(frames - done).min(buffer_frames as usize - offset);
+            let frame_bytes = params.frame_bytes();
+            let buffer = state.buffer.as_mut().ok_or("Stream is not set up")?;
+            copy(done * frame_bytes, &mut buffer.bytes()[offset * frame_bytes..(offset + count) * frame_bytes])?;
+            state.appl_ptr += count as u64;
+            done += count;
+            self.committed(&mut state);
+        }
+        Ok(done)
+    }
+
+    /// Narrow `bytes`, a `struct snd_pcm_hw_params`, to what the hardware
+    /// takes, and settle each parameter when `settle`
+    fn refine(&self, bytes: &mut [u8], settle: bool) -> Result<Option<PcmParams>, &'static str> {
+        let hardware = self.driver.hardware();
+        let requested = bytes.to_vec();
+        let mut access = mask(bytes, SNDRV_PCM_HW_PARAM_ACCESS)
+            & (1 << SNDRV_PCM_ACCESS_MMAP_INTERLEAVED | 1 << SNDRV_PCM_ACCESS_RW_INTERLEAVED);
+        let formats = hardware.formats.iter().fold(0u64, |mask, format| mask | 1 << format.alsa());
+        let mut format = mask(bytes, SNDRV_PCM_HW_PARAM_FORMAT) & formats;
+        if access == 0 || format == 0 {
+            return Err("Unsupported access or format");
+        }
+        let frame_bits = |format: u64| {
+            let widths = PcmFormat::ALL.iter().filter(|candidate| format & 1 << candidate.alsa() != 0);
+            let bits = widths.map(|candidate| candidate.physical_bytes() as u32 * 8);
+            (bits.clone().min().unwrap_or(8), bits.max().unwrap_or(32))
+        };
+        let rate_range = (*hardware.rates.first().unwrap_or(&0), *hardware.rates.last().unwrap_or(&0));
+        let channels = narrow(bytes, SNDRV_PCM_HW_PARAM_CHANNELS, (hardware.channels_min, hardware.channels_max))?;
+        let mut rate = narrow(bytes, SNDRV_PCM_HW_PARAM_RATE, rate_range)?;
+        let mut period_bytes = narrow(
+            bytes,
+            SNDRV_PCM_HW_PARAM_PERIOD_BYTES,
+            (hardware.period_bytes_min as u32, hardware.period_bytes_max as u32),
+        )?;
+        let mut periods = narrow(bytes, SNDRV_PCM_HW_PARAM_PERIODS, (hardware.periods_min as u32, hardware.periods_max as u32))?;
+        narrow(bytes, SNDRV_PCM_HW_PARAM_BUFFER_BYTES, (0, hardware.buffer_bytes_max as u32))?;
+        narrow(bytes, SNDRV_PCM_HW_PARAM_SAMPLE_BITS, (frame_bits(format).0, frame_bits(format).1))?;
+        put_u32(bytes, HW_PARAMS_INFO, SNDRV_PCM_INFO_MMAP | SNDRV_PCM_INFO_MMAP_VALID | SNDRV_PCM_INFO_INTERLEAVED | SNDRV_PCM_INFO_BLOCK_TRANSFER);
+        if !settle {
+            set_mask(bytes, SNDRV_PCM_HW_PARAM_ACCESS, access);
+            set_mask(bytes, SNDRV_PCM_HW_PARAM_FORMAT, format);
+            return Ok(None);
+        }
+
+        // The smallest of what is left, RW before MMAP access
+        access = match access & 1 << SNDRV_PCM_ACCESS_RW_INTERLEAVED {
+            0 => 1 << SNDRV_PCM_ACCESS_MMAP_INTERLEAVED,
+            rw => rw,
+        };
+        format &= format.wrapping_neg();
+        let chosen = PcmFormat::from_alsa(format.trailing_zeros()).ok_or("Unsupported format")?;
+        let chosen_rate = *hardware.rates.iter().find(|&&candidate| candidate >= rate.0 && candidate <= rate.1).ok_or("Unsupported rate")?;
+        rate = (chosen_rate, chosen_rate);
+        let frame_bytes = chosen.physical_bytes() * channels.0 as usize;
+        // A period of 10ms unless the player asks for one
+        let wanted = |param: usize| interval(&requested, param).0 as usize;
+        let period_frames = [
+            wanted(SNDRV_PCM_HW_PARAM_PERIOD_SIZE),
+            wanted(SNDRV_PCM_HW_PARAM_PERIOD_BYTES) / frame_bytes,
+            wanted(SNDRV_PCM_HW_PARAM_PERIOD_TIME) * chosen_rate as usize / 1_000_000,
+            chosen_rate as usize / 100,
+        ]
+        .into_iter()
+        .find(|&frames| frames > 0)
+        .unwrap_or(1);
+        let period_frames = period_frames
+            .clamp(period_bytes.0 as usize / frame_bytes, period_bytes.1 as usize / frame_bytes)
+            .max(1);
+        period_bytes = ((period_frames * frame_bytes) as u32, (period_frames * frame_bytes) as u32);
+        let wanted_periods = match wanted(SNDRV_PCM_HW_PARAM_BUFFER_SIZE) {
+            0 => wanted(SNDRV_PCM_HW_PARAM_PERIODS).max(4),
+            frames => frames.div_ceil(period_frames),
+        };
+        let most_periods = (hardware.buffer_bytes_max / period_bytes.0 as usize).min(periods.1 as usize);
+        let count = wanted_periods.clamp(periods.0 as usize, most_periods.max(periods.0 as usize));
+        periods = (count as u32, count as u32);
+        let params = PcmParams {
+            access: access.trailing_zeros(),
+            format: chosen,
+            channels: channels.0,
+            rate: chosen_rate,
+            period_frames,
+            periods: count,
+        };
+
+        // Write everything back as single values, checking they were allowed
+        set_mask(bytes, SNDRV_PCM_HW_PARAM_ACCESS, access);
+        set_mask(bytes, SNDRV_PCM_HW_PARAM_FORMAT, format);
+        set_mask(bytes, SNDRV_PCM_HW_PARAM_SUBFORMAT, 1);
+        let frame_time = |frames: usize| (frames as u64 * 1_000_000 / chosen_rate as u64) as u32;
+        let values = [
+            (SNDRV_PCM_HW_PARAM_SAMPLE_BITS, chosen.physical_bytes() as u32 * 8),
+            (SNDRV_PCM_HW_PARAM_FRAME_BITS, frame_bytes as u32 * 8),
+            (SNDRV_PCM_HW_PARAM_CHANNELS, channels.0),
+            (SNDRV_PCM_HW_PARAM_RATE, rate.0),
+            (SNDRV_PCM_HW_PARAM_PERIOD_TIME, frame_time(period_frames)),
+            (SNDRV_PCM_HW_PARAM_PERIOD_SIZE, period_frames as u32),
+            (SNDRV_PCM_HW_PARAM_PERIOD_BYTES, period_bytes.0),
+            (SNDRV_PCM_HW_PARAM_PERIODS, periods.0),
+            (SNDRV_PCM_HW_PARAM_BUFFER_TIME, frame_time(params.buffer_frames())),
+            (SNDRV_PCM_HW_PARAM_BUFFER_SIZE, params.buffer_frames() as u32),
+            (SNDRV_PCM_HW_PARAM_BUFFER_BYTES, params.buffer_bytes() as u32),
+        ];
+        for (param, value) in values {
+            let (min, max) = interval(bytes, param);
+            // Times are rounded, so only their frame counts are checked
+            let timed = matches!(param, SNDRV_PCM_HW_PARAM_PERIOD_TIME | SNDRV_PCM_HW_PARAM_BUFFER_TIME);
+            if !timed && (value < min || value > max) {
+                return Err("Invalid hardware parameters");
+            }
+            set_interval(bytes, param, (value, value));
+        }
+        set_interval(bytes, SNDRV_PCM_HW_PARAM_TICK_TIME, (0, 0));
+        put_u32(bytes, HW_PARAMS_MSBITS, chosen.width());
+        put_u32(bytes, HW_PARAMS_RATE_NUM, chosen_rate);
+        put_u32(bytes, HW_PARAMS_RATE_DEN, 1);
+        Ok(Some(params))
+    }
+
+    /// Settle the parameters and allocate the buffer for them
+    fn hw_params(&self, bytes: &mut [u8]) -> Result<(), &'static str> {
+        let params = self.refine(bytes, true)?.ok_or("Invalid hardware parameters")?;
+        let mut state = self.state.lock();
+        if state.mapped > 0 {
+            return Err("Device busy: buffer is mapped");
+        }
+        self.stop_locked(&mut state)?;
+        let mut buffer = PcmBuffer::new(params.buffer_bytes())?;
+        let sample_bytes = params.format.physical_bytes();
+        for (index, byte) in buffer.bytes().iter_mut().enumerate() {
+            *byte = params.format.silence(index % sample_bytes);
+        }
+        state.buffer = Some(buffer);
+        state.params = Some(params);
+        state.state = SNDRV_PCM_STATE_SETUP;
+        state.start_threshold = params.period_frames as u64;
+        state.avail_min = params.period_frames as u64;
+        Ok(())
+    }
+
+    fn hw_free(&self) -> Result<(), &'static str> {
+        let mut state = self.state.lock();
+        if state.mapped > 0 {
+            return Err("Device busy: buffer is mapped");
+        }
+        self.stop_locked(&mut state)?;
+        state.buffer = None;
+        state.params = None;
+        state.state = SNDRV_PCM_STATE_OPEN;
+        Ok(())
+    }
+
+    fn sw_params(&self, bytes: &mut [u8]) -> Result<(), &'static str> {
+        let mut state = self.state.lock();
+        let buffer_frames = state.params.ok_or("Stream is not set up")?.buffer_frames() as u64;
+        state.avail_min = u64_at(bytes, 16).max(1);
+        state.start_threshold = u64_at(bytes, 32).max(1);
+        // The pointers never wrap
+        let mut boundary = buffer_frames;
+        while boundary * 2 <= i64::MAX as u64 - buffer_frames {
+            boundary *= 2;
+        }
+        bytes[64..72].copy_from_slice(&boundary.to_le_bytes());
+        Ok(())
+    }
+
+    fn sync_ptr(&self, bytes: &mut [u8]) -> Result<(), &'static str> {
+        let flags = u32_at(bytes, 0);
+        let mut state = self.state.lock();
+        if flags & SNDRV_PCM_SYNC_PTR_APPL == 0 {
+            let appl_ptr = u64_at(bytes, 72);
+            if appl_ptr != state.appl_ptr {
+                if appl_ptr < state.appl_ptr || appl_ptr - state.hw_ptr > state.buffer_frames() {
+                    return Err("Invalid application pointer");
+                }
+                state.appl_ptr = appl_ptr;
+                self.committed(&mut state);
+            }
+        }
+        if flags & SNDRV_PCM_SYNC_PTR_AVAIL_MIN == 0 {
+            state.avail_min = u64_at(bytes, 80).max(1);
+        }
+        bytes[8..12].copy_from_slice(&state.state.to_le_bytes());
+        bytes[16..24].copy_from_slice(&state.hw_ptr.to_le_bytes());
+        bytes[72..80].copy_from_slice(&state.appl_ptr.to_le_bytes());
+        bytes[80..88].copy_from_slice(&state.avail_min.to_le_bytes());
+        Ok(())
+    }
+
+    fn pcm_info(&self) -> [u8; PCM_INFO_SIZE] {
+        let mut bytes = [0u8; PCM_INFO_SIZE];
+        bytes[0..4].copy_from_slice(&(self.device as u32).to_le_bytes());
+        bytes[12..16].copy_from_slice(&(self.card as u32).to_le_bytes());
+        put_name(&mut bytes[16..80], self.driver.name());
+        put_name(&mut bytes[80..160], self.driver.name());
+        put_name(&mut bytes[160..192], "subdevice #0");
+        // One subdevice, available
+        bytes[200..204].copy_from_slice(&1u32.to_le_bytes());
+        bytes[204..208].copy_from_slice(&1u32.to_le_bytes());
+        bytes
+    }
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+}
+
+fn put_u32(bytes: &mut [u8], at: usize, value: u32) {
+    bytes[at..at + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Copy `text` into the NUL-terminated name field `field`
+fn put_name(field: &mut [u8], text: &str) {
+    let len = text.len().min(field.len() - 1);
+    field[..len].copy_from_slice(&text.as_bytes()[..len]);
+}
+
+/// Low 64 bits of mask `param` of hardware parameters
+fn mask(bytes: &[u8], param: usize) -> u64 {
+    u64_at(bytes, HW_PARAMS_MASKS + param * 32)
+}
+
+fn set_mask(bytes: &mut [u8], param: usize, value: u64) {
+    let at = HW_PARAMS_MASKS + param * 32;
+    bytes[at..at + 32].fill(0);
+    bytes[at..at + 8].copy_from_slice(&value.to_le_bytes());
+}
+
+fn interval(bytes: &[u8], param: usize) -> (u32, u32) {
+    let at = HW_PARAMS_INTERVALS + (param - SNDRV_PCM_HW_PARAM_SAMPLE_BITS) * 12;
+    (u32_at(bytes, at), u32_at(bytes, at + 4))
+}
+
+fn set_interval(bytes: &mut [u8], param: usize, (min, max): (u32, u32)) {
+    let at = HW_PARAMS_INTERVALS + (param - SNDRV_PCM_HW_PARAM_SAMPLE_BITS) * 12;
+    put_u32(bytes, at, min);
+    put_u32(bytes, at + 4, max);
+    put_u32(bytes, at + 8, INTERVAL_INTEGER);
+}
+
+/// Intersect interval `param` with `range`
+fn narrow(bytes: &mut [u8], param: usize, range: (u32, u32)) -> Result<(u32, u32), &'static str> {
+    let at = HW_PARAMS_INTERVALS + (param - SNDRV_PCM_HW_PARAM_SAMPLE_BITS) * 12;
+    if u32_at(bytes, at + 8) & INTERVAL_EMPTY != 0 {
+        return Err("Invalid hardware parameters");
+    }
+    let (min, max) = interval(bytes, param);
+    let narrowed = (min.max(range.0), max.min(range.1));
+    if narrowed.0 > narrowed.1 {
+        return Err("Invalid hardware parameters");
+    }
+    set_interval(bytes, param, narrowed);
+    Ok(narrowed)
+}
+
+impl Device for PcmDevice {
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Char
+    }
+
+    fn name(&self) -> &'static str {
+        "pcm"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn as_char_device(&self) -> Option<&dyn CharDevice> {
+        Some(self)
+    }
+}
+
+impl CharDevice for PcmDevice {
+    fn read_byte(&self) -> Option<u8> {
+        // Playback streams have nothing to read
+        None
+    }
+
+    fn write_byte(&self, _byte: u8) -> Result<(), &'static str> {
+        Err("Frames are written whole")
+    }
+
+    /// Write whole frames, blocking while the buffer is full
+    fn write(&self, buffer: &[u8]) -> Result<usize, &'static str> {
+        let frame_bytes = self.state.lock().params.ok_or("Stream is not set up")?.frame_bytes();
+        let frames = self.write_frames(buffer.len() / frame_bytes, |offset, destination| {
+            destination.copy_from_slice(&buffer[offset..offset + destination.len()]);
+            Ok(())
+        })?;
+        Ok(frames * frame_bytes)
+    }
+
+    fn can_read(&self) -> bool {
+        false
+    }
+
+    fn can_write(&self) -> bool {
+        let state = self.state.lock();
+        state.params.is_some() && state.space() >= state.avail_min
+    }
+}
+
+impl ControlOps for PcmDevice {
+    fn control(&self, command: u32, arg: usize) -> Result<i32, &'static str> {
+        match command {
+            SNDRV_PCM_IOCTL_PVERSION => write_user(arg, &SNDRV_PCM_VERSION.to_le_bytes()).map(|_| 0),
+            SNDRV_PCM_IOCTL_INFO => write_user(arg, &self.pcm_info()).map(|_| 0),
+            SNDRV_PCM_IOCTL_HW_REFINE | SNDRV_PCM_IOCTL_HW_PARAMS => {
+                let mut bytes = [0u8; HW_PARAMS_SIZE];
+                read_user(arg, &mut bytes)?;
+                if command == SNDRV_PCM_IOCTL_HW_PARAMS {
+                    self.hw_params(&mut bytes)?;
+                } else {
+                    self.refine(&mut bytes, false)?;
+                }
+                write_user(arg, &bytes).map(|_| 0)
+            }
+            SNDRV_PCM_IOCTL_HW_FREE => self.hw_free().map(|_| 0),
+            SNDRV_PCM_IOCTL_SW_PARAMS => {
+                let mut bytes = [0u8; SW_PARAMS_SIZE];
+                read_user(arg, &mut bytes)?;
+                self.sw_params(&mut bytes)?;
+                write_user(arg, &bytes).map(|_| 0)
+            }
+            SNDRV_PCM_IOCTL_DELAY => {
+                let state = self.state.lock();
+                let delay = (state.appl_ptr - state.hw_ptr) as i64;
+                drop(state);
+                write_user(arg, &delay.to_le_bytes()).map(|_| 0)
+            }
+            SNDRV_PCM_IOCTL_HWSYNC => match self.state.lock().state {
+                SNDRV_PCM_STATE_XRUN => Err("Broken pipe"),
+                _ => Ok(0),
+            },
+            SNDRV_PCM_IOCTL_SYNC_PTR => {
+                let mut bytes = [0u8; SYNC_PTR_SIZE];
+                read_user(arg, &mut bytes)?;
+                self.sync_ptr(&mut bytes)?;
+                write_user(arg, &bytes).map(|_| 0)
+            }
+            SNDRV_PCM_IOCTL_PREPARE | SNDRV_PCM_IOCTL_RESET => self.prepare().map(|_| 0),
+            SNDRV_PCM_IOCTL_START => {
+                let mut state = self.state.lock();
+                self.start_locked(&mut state).map(|_| 0)
+            }
+            SNDRV_PCM_IOCTL_DROP => self.drop_stream().map(|_| 0),
+            SNDRV_PCM_IOCTL_DRAIN => self.drain().map(|_| 0),
+            SNDRV_PCM_IOCTL_WRITEI_FRAMES => {
+                let mut bytes = [0u8; XFERI_SIZE];
+                read_user(arg, &mut bytes)?;
+                let source = u64_at(&bytes, 8) as usize;
+                let frames = self.write_frames(u64_at(&bytes, 16) as usize, |offset, destination| {
+                    read_user(source + offset, destination)
+                })?;
+                write_user(arg, &(frames as i64).to_le_bytes()).map(|_| 0)
+            }
+            _ => Err("Unsupported PCM control command"),
+        }
+    }
+}
+
+impl MemoryMappingOps for PcmDevice {
+    /// The buffer, at offset 0; the status and control pages of ALSA are
+    /// not mapped, which has players use `SNDRV_PCM_IOCTL_SYNC_PTR`
+    fn get_mapping_info(&self, offset: usize, length: usize) -> Result<(usize, usize, bool), &'static str> {
+        let state = self.state.lock();
+        let buffer = state.buffer.as_ref().ok_or("Stream is not set up")?;
+        if offset % PAGE_SIZE != 0 || offset + length > buffer.count * PAGE_SIZE {
+            return Err("Invalid mapping of the sound buffer");
+        }
+        Ok((buffer.addr() + offset, 0x3, true))
+    }
+
+    fn on_mapped(&self, _vaddr: usize, _paddr: usize, _length: usize, _offset: usize) {
+        self.state.lock().mapped += 1;
+    }
+
+    fn on_unmapped(&self, _vaddr: usize, _length: usize) {
+        let mut state = self.state.lock();
+        state.mapped = state.mapped.saturating_sub(1);
+    }
+
+    fn supports_mmap(&self) -> bool {
+        true
+    }
+}
+
+static NEXT_CARD: Mutex<usize> = Mutex::new(0);
+
+/// Number a new sound card
+pub fn next_card() -> usize {
+    let mut next = NEXT_CARD.lock();
+    *next += 1;
+    *next - 1
+}
+
+/// Add the stream `device` as `/dev/snd/pcmCxDyp`
+pub fn register(device: Arc<PcmDevice>) {
+    let device_id = DeviceManager::get_manager().register_device_with_name(device.node_name(), device.clone());
+    device.device_id.call_once(|| device_id);
+}
+
+/// Remove the stream `device`
+pub fn unregister(device: &PcmDevice) {
+    if let Some(&device_id) = device.device_id.get() {
+        DeviceManager::get_manager().unregister_device(device_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// Hardware that records what it is given
+    #[derive(Default)]
+    struct MockDriver {
+        queued: Mutex<Vec<Vec<u8>>>,
+        started: Mutex<bool>,
+    }
+
+    impl PcmDriver for MockDriver {
+        fn name(&self) -> &str {
+            "mock"
+        }
+
+        fn hardware(&self) -> PcmHardware {
+            PcmHardware {
+                formats: vec![PcmFormat::S16Le, PcmFormat::S32Le],
+                rates: vec![44100, 48000],
+                channels_min: 1,
+                channels_max: 2,
+                buffer_bytes_max: 64 * 1024,
+                period_bytes_min: 64,
+                period_bytes_max: 8192,
+                periods_min: 2,
+                periods_max: 8,
+            }
+        }
+
+        fn prepare(&self, _params: &PcmParams) -> Result<(), &'static str> {
+            Ok(())
+        }
+
+        fn start(&self) -> Result<(), &'static str> {
+            *self.started.lock() = true;
+            Ok(())
+        }
+
+        fn stop(&self) -> Result<(), &'static str> {
+            *self.started.lock() = false;
+            Ok(())
+        }
+
+        fn release(&self) -> Result<(), &'static str> {
+            self.queued.lock().clear();
+            Ok(())
+        }
+
+        fn queue(&self, data: &[u8]) -> Result<(), &'static str> {
+            self.queued.lock().push(data.to_vec());
+            Ok(())
+        }
+    }
+
+    /// `struct snd_pcm_hw_params` allowing anything
+    fn any_params() -> [u8; HW_PARAMS_SIZE] {
+        let mut bytes = [0u8; HW_PARAMS_SIZE];
+        for param in SNDRV_PCM_HW_PARAM_ACCESS..=SNDRV_PCM_HW_PARAM_SUBFORMAT {
+            bytes[HW_PARAMS_MASKS + param * 32..HW_PARAMS_MASKS + param * 32 + 32].fill(0xff);
+        }
+        for param in SNDRV_PCM_HW_PARAM_SAMPLE_BITS..=SNDRV_PCM_HW_PARAM_TICK_TIME {
+            set_interval(&mut bytes, param, (0, u32::MAX));
+        }
+        bytes
+    }
+
+    /// A prepared stream of stereo S16_LE at 48 kHz, 4 periods of 16 frames
+    fn prepared() -> (Arc<MockDriver>, Arc<PcmDevice>) {
+        let driver = Arc::new(MockDriver::default());
+        let device = PcmDevice::new(driver.clone(), 0, 0);
+        let mut bytes = any_params();
+        set_mask(&mut bytes, SNDRV_PCM_HW_PARAM_FORMAT, 1 << PcmFormat::S16Le.alsa());
+        set_interval(&mut bytes, SNDRV_PCM_HW_PARAM_CHANNELS, (2, 2));
+        set_interval(&mut bytes, SNDRV_PCM_HW_PARAM_RATE, (48000, 48000));
+        set_interval(&mut bytes, SNDRV_PCM_HW_PARAM_PERIOD_SIZE, (16, 16));
+        set_interval(&mut bytes, SNDRV_PCM_HW_PARAM_PERIODS, (4, 4));
+        device.control(SNDRV_PCM_IOCTL_HW_PARAMS, bytes.as_mut_ptr() as usize).unwrap();
+        device.control(SNDRV_PCM_IOCTL_PREPARE, 0).unwrap();
+        (driver, device)
+    }
+
+    #[test_case]
+    fn test_pcm_hw_params() {
+        let driver = Arc::new(MockDriver::default());
+        let device = PcmDevice::new(driver, 0, 0);
+
+        // Refining leaves what the hardware takes
+        let mut bytes = any_params();
+        device.control(SNDRV_PCM_IOCTL_HW_REFINE, bytes.as_mut_ptr() as usize).unwrap();
+        assert_eq!(mask(&bytes, SNDRV_PCM_HW_PARAM_FORMAT), 1 << 2 | 1 << 10);
+        assert_eq!(interval(&bytes, SNDRV_PCM_HW_PARAM_CHANNELS), (1, 2));
+        assert_eq!(interval(&bytes, SNDRV_PCM_HW_PARAM_RATE), (44100, 48000));
+
+        // Settling takes the smallest and a period of 10ms
+        let mut bytes = any_params();
+        device.control(SNDRV_PCM_IOCTL_HW_PARAMS, bytes.as_mut_ptr() as usize).unwrap();
+        assert_eq!(mask(&bytes, SNDRV_PCM_HW_PARAM_ACCESS), 1 << SNDRV_PCM_ACCESS_RW_INTERLEAVED);
+        assert_eq!(mask(&bytes, SNDRV_PCM_HW_PARAM_FORMAT), 1 << PcmFormat::S16Le.alsa());
+        assert_eq!(interval(&bytes, SNDRV_PCM_HW_PARAM_RATE), (44100, 44100));
+        assert_eq!(interval(&bytes, SNDRV_PCM_HW_PARAM_PERIOD_SIZE), (441, 441));
+        assert_eq!(interval(&bytes, SNDRV_PCM_HW_PARAM_PERIOD_BYTES), (882, 882));
+        assert_eq!(interval(&bytes, SNDRV_PCM_HW_PARAM_PERIODS), (4, 4));
+        assert_eq!(device.state.lock().state, SNDRV_PCM_STATE_SETUP);
+
+        // What the hardware does not take is refused
+        let mut bytes = any_params();
+        set_interval(&mut bytes, SNDRV_PCM_HW_PARAM_RATE, (8000, 8000));
+        assert!(device.control(SNDRV_PCM_IOCTL_HW_PARAMS, bytes.as_mut_ptr() as usize).is_err());
+    }
+
+    #[test_case]
+    fn test_pcm_playback() {
+        let (driver, device) = prepared();
+        let period = vec![1u8; 16 * 4];
+
+        // Frames are queued a period at a time and start the stream
+        assert_eq!(device.write(&period[..40]), Ok(40));
+        assert!(driver.queued.lock().is_empty());
+        assert_eq!(device.write(&period[..24]), Ok(24));
+        assert!(*driver.started.lock());
+        assert_eq!(driver.queued.lock().len(), 1);
+        assert_eq!(device.state.lock().state, SNDRV_PCM_STATE_RUNNING);
+
+        // Playing a period makes room
+        assert_eq!(device.write(&vec![2u8; 16 * 4 * 3]), Ok(16 * 4 * 3));
+        assert_eq!(device.state.lock().space(), 0);
+        device.period_elapsed();
+        assert_eq!(device.state.lock().space(), 16);
+
+        // Running out of frames is an underrun
+        for _ in 0..3 {
+            device.period_elapsed();
+        }
+        assert_eq!(device.state.lock().state, SNDRV_PCM_STATE_XRUN);
+        assert_eq!(device.write(&period), Err("Broken pipe"));
+        device.control(SNDRV_PCM_IOCTL_PREPARE, 0).unwrap();
+        assert!(driver.queued.lock().is_empty());
+        assert_eq!(device.write(&period), Ok(period.len()));
+    }
+
+    #[test_case]
+    fn test_pcm_sync_ptr() {
+        let (driver, device) = prepared();
+        let mut sw_params = [0u8; SW_PARAMS_SIZE];
+        sw_params[32..40].copy_from_slice(&32u64.to_le_bytes());
+        device.control(SNDRV_PCM_IOCTL_SW_PARAMS, sw_params.as_mut_ptr() as usize).unwrap();
+
+        // Frames written into the mapping are committed with the pointer
+        let mut sync = [0u8; SYNC_PTR_SIZE];
+        sync[72..80].copy_from_slice(&20u64.to_le_bytes());
+        device.control(SNDRV_PCM_IOCTL_SYNC_PTR, sync.as_mut_ptr() as usize).unwrap();
+        assert_eq!(u32_at(&sync, 8) as i32, SNDRV_PCM_STATE_PREPARED);
+        assert_eq!(driver.queued.lock().len(), 1);
+
+        sync[72..80].copy_from_slice(&40u64.to_le_bytes());
+        device.control(SNDRV_PCM_IOCTL_SYNC_PTR, sync.as_mut_ptr() as usize).unwrap();
+        assert_eq!(u32_at(&sync, 8) as i32, SNDRV_PCM_STATE_RUNNING);
+        device.period_elapsed();
+        sync[0..4].copy_from_slice(&SNDRV_PCM_SYNC_PTR_APPL.to_le_bytes());
+        device.control(SNDRV_PCM_IOCTL_SYNC_PTR, sync.as_mut_ptr() as usize).unwrap();
+        assert_eq!(u64_at(&sync, 16), 16);
+        assert_eq!(u64_at(&sync, 72), 40);
+
+        // The pointer cannot pass the buffer
+        sync[0..4].fill(0);
+        sync[72..80].copy_from_slice(&(16 + 64 + 1u64).to_le_bytes());
+        assert!(device.control(SNDRV_PCM_IOCTL_SYNC_PTR, sync.as_mut_ptr() as usize).is_err());
+
+        // Draining plays the partial period last
+        let mut delay = [0u8; 8];
+        device.control(SNDRV_PCM_IOCTL_DELAY, delay.as_mut_ptr() as usize).unwrap();
+        assert_eq!(i64::from_le_bytes(delay), 24);
+        {
+            let mut state = device.state.lock();
+            state.state = SNDRV_PCM_STATE_DRAINING;
+            device.feed(&mut state);
+        }
+        assert_eq!(driver.queued.lock().last().unwrap().len(), 8 * 4);
+        device.period_elapsed();
+        device.period_elapsed();
+        assert_eq!(device.state.lock().state, SNDRV_PCM_STATE_SETUP);
+    }
+}
@@ -0,0 +1,30 @@
+//! Device system calls
+//!
+//! Native interface of the device manager: a task opens a
+//! [`DeviceEventsObject`] and reads the devices being added, removed and
+//! changed through the stream system calls, polling it to wait for them.
+
+use crate::{
+    abi::error::{fail, KernelError},
+    arch::Trapframe,
+    object::KernelObject,
+    task::mytask,
+};
+
+use super::uevent::DeviceEventsObject;
+
+/// sys_device_events_open - Open a channel of the device events
+///
+/// Returns: the handle of the channel, usize::MAX on error
+pub fn sys_device_events_open(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+    trapframe.increment_pc_next(task);
+
+    match task.handle_table.write().insert(KernelObject::from_device_events(DeviceEventsObject::new())) {
+        Ok(handle) => handle as usize,
+        Err(_) => fail(KernelError::TooManyHandles),
+    }
+}
@@ -0,0 +1,252 @@
+//! Device event channel
+//!
+//! A [`DeviceEventsObject`] is how user space follows the devices of the
+//! kernel, as a udev-like daemon does to create device nodes and mount
+//! disks as they come and go. It reports every device added to, removed
+//! from or changed in the device manager as a [`DeviceEventRecord`], one
+//! record per read.
+//!
+//! A new channel first reports the devices already registered as added, so
+//! that a daemon started after the devices were probed sees them all. A
+//! device added while the channel is being opened may be reported twice.
+//!
+//! A reader that falls [`DEVICE_EVENT_QUEUE_LEN`] records behind loses them
+//! and reads [`ACTION_DROPPED`]; it then rescans the devices by opening a
+//! new channel.
+
+use core::mem::size_of;
+
+use alloc::collections::VecDeque;
+use alloc::sync::{Arc, Weak};
+use spin::Mutex;
+
+use super::events::{DeviceEvent, DeviceEventListener, DeviceHotplugEvent, HotplugAction};
+use super::manager::DeviceManager;
+use super::DeviceType;
+use crate::object::capability::poll::{PollOps, POLLIN};
+use crate::object::capability::{StreamError, StreamOps};
+use crate::sync::waker::Waker;
+
+/// Records a channel holds before the oldest are dropped
+pub const DEVICE_EVENT_QUEUE_LEN: usize = 256;
+
+/// Actions
+pub const ACTION_ADDED: u32 = 1;
+pub const ACTION_REMOVED: u32 = 2;
+pub const ACTION_CHANGED: u32 = 3;
+/// Records were lost because the reader fell behind
+pub const ACTION_DROPPED: u32 = 4;
+
+/// Device classes
+pub const CLASS_BLOCK: u32 = 1;
+pub const CLASS_CHAR: u32 = 2;
+pub const CLASS_NETWORK: u32 = 3;
+pub const CLASS_GRAPHICS: u32 = 4;
+pub const CLASS_GENERIC: u32 = 5;
+
+/// Bytes of the name of a device in a record, NUL padded
+pub const DEVICE_NAME_LEN: usize = 48;
+
+fn class_of(device_type: DeviceType) -> u32 {
+    match device_type {
+        DeviceType::Block => CLASS_BLOCK,
+        DeviceType::Char => CLASS_CHAR,
+        DeviceType::Network => CLASS_NETWORK,
+        DeviceType::Graphics => CLASS_GRAPHICS,
+        _ => CLASS_GENERIC,
+    }
+}
+
+/// What a read returns, little-endian
+///
+/// The name is the one the device is registered under, the path of its
+/// node below `/dev` (`input/event0`), or empty for a device without one.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceEventRecord {
+    pub action: u32,
+    pub class: u32,
+    pub device_id: u64,
+    pub name: [u8; DEVICE_NAME_LEN],
+}
+
+pub const DEVICE_EVENT_RECORD_SIZE: usize = size_of::<DeviceEventRecord>();
+
+impl DeviceEventRecord {
+    fn new(action: u32, class: u32, device_id: usize, name: &str) -> Self {
+        let mut bytes = [0u8; DEVICE_NAME_LEN];
+        // Keep a NUL at the end
+        let len = name.len().min(DEVICE_NAME_LEN - 1);
+        bytes[..len].copy_from_slice(&name.as_bytes()[..len]);
+        Self { action, class, device_id: device_id as u64, name: bytes }
+    }
+
+    fn from_event(event: &DeviceHotplugEvent) -> Self {
+        let action = match event.action {
+            HotplugAction::Added => ACTION_ADDED,
+            HotplugAction::Removed => ACTION_REMOVED,
+            HotplugAction::Changed => ACTION_CHANGED,
+        };
+        let class = class_of(event.device.device_type());
+        Self::new(action, class, event.device_id, event.name.as_deref().unwrap_or(""))
+    }
+
+    pub fn to_bytes(&self) -> [u8; DEVICE_EVENT_RECORD_SIZE] {
+        let mut bytes = [0u8; DEVICE_EVENT_RECORD_SIZE];
+        bytes[0..4].copy_from_slice(&self.action.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.class.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.device_id.to_le_bytes());
+        bytes[16..].copy_from_slice(&self.name);
+        bytes
+    }
+}
+
+/// A channel of the device events since it was opened
+pub struct DeviceEventsObject {
+    queue: Mutex<VecDeque<DeviceEventRecord>>,
+    /// Readers waiting for a record
+    waker: Waker,
+}
+
+impl DeviceEventsObject {
+    /// A channel reporting the devices registered now, then the events of
+    /// the device manager
+    pub fn new() -> Arc<Self> {
+        let object = Arc::new(Self {
+            queue: Mutex::new(VecDeque::new()),
+            waker: Waker::new_interruptible("device_events"),
+        });
+        let manager = DeviceManager::get_manager();
+        let weak: Weak<dyn DeviceEventListener> = Arc::downgrade(&object) as Weak<dyn DeviceEventListener>;
+        manager.register_hotplug_listener(weak);
+
+        for (id, device) in manager.get_all_devices() {
+            let name = manager.get_device_name(id).unwrap_or_default();
+            object.push(DeviceEventRecord::new(ACTION_ADDED, class_of(device.device_type()), id, &name));
+        }
+        object
+    }
+
+    fn push(&self, record: DeviceEventRecord) {
+        let mut queue = self.queue.lock();
+        if queue.len() >= DEVICE_EVENT_QUEUE_LEN {
+            queue.clear();
+            queue.push_back(DeviceEventRecord::new(ACTION_DROPPED, 0, 0, ""));
+        }
+        queue.push_back(record);
+        drop(queue);
+        self.waker.wake_all();
+    }
+}
+
+impl DeviceEventListener for DeviceEventsObject {
+    fn on_device_event(&self, event: &dyn DeviceEvent) {
+        if let Some(event) = event.as_any().downcast_ref::<DeviceHotplugEvent>() {
+            self.push(DeviceEventRecord::from_event(event));
+        }
+    }
+
+    fn interested_in(&self, event_type: &str) -> bool {
+        event_type == "hotplug"
+    }
+}
+
+impl StreamOps for DeviceEventsObject {
+    /// Take the next record; the buffer must hold it whole, and nothing is
+    /// read while none is pending
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, StreamError> {
+        if buffer.len() < DEVICE_EVENT_RECORD_SIZE {
+            return Err(StreamError::InvalidArgument);
+        }
+        let Some(record) = self.queue.lock().pop_front() else {
+            return Ok(0);
+        };
+        buffer[..DEVICE_EVENT_RECORD_SIZE].copy_from_slice(&record.to_bytes());
+        Ok(DEVICE_EVENT_RECORD_SIZE)
+    }
+
+    fn write(&self, _buffer: &[u8]) -> Result<usize, StreamError> {
+        Err(StreamError::NotSupported)
+    }
+}
+
+impl PollOps for DeviceEventsObject {
+    fn poll_events(&self) -> u32 {
+        if self.queue.lock().is_empty() { 0 } else { POLLIN }
+    }
+
+    fn poll_register(&self, task_id: usize) -> bool {
+        self.waker.register(task_id);
+        true
+    }
+
+    fn poll_unregister(&self, task_id: usize) {
+        self.waker.unregister(task_id);
+    }
+}
+
+impl core::fmt::Debug for DeviceEventsObject {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("DeviceEventsObject").field("queue", &self.queue.lock().len()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::GenericDevice;
+
+    fn read_record(object: &DeviceEventsObject) -> Option<DeviceEventRecord> {
+        let mut buffer = [0u8; DEVICE_EVENT_RECORD_SIZE];
+        match object.read(&mut buffer).unwrap() {
+            0 => None,
+            _ => {
+                let field = |offset: usize| u32::from_le_bytes(buffer[offset..offset + 4].try_into().unwrap());
+                let mut name = [0u8; DEVICE_NAME_LEN];
+                name.copy_from_slice(&buffer[16..]);
+                Some(DeviceEventRecord {
+                    action: field(0),
+                    class: field(4),
+                    device_id: u64::from_le_bytes(buffer[8..16].try_into().unwrap()),
+                    name,
+                })
+            }
+        }
+    }
+
+    #[test_case]
+    fn test_device_events() {
+        let manager = DeviceManager::get_manager();
+        let object = DeviceEventsObject::new();
+        // The devices already registered come first
+        let existing = manager.get_devices_count();
+        for _ in 0..existing {
+            assert_eq!(read_record(&object).unwrap().action, ACTION_ADDED);
+        }
+        assert_eq!(object.poll_events(), 0);
+
+        let id = manager.register_device_with_name("uevent0".into(), Arc::new(GenericDevice::new("uevent")));
+        assert!(manager.notify_device_changed(id));
+        manager.unregister_device(id);
+        assert_eq!(object.poll_events(), POLLIN);
+
+        for action in [ACTION_ADDED, ACTION_CHANGED, ACTION_REMOVED] {
+            let expected = DeviceEventRecord::new(action, CLASS_GENERIC, id, "uevent0");
+            assert_eq!(read_record(&object), Some(expected));
+        }
+        assert_eq!(read_record(&object), None);
+        assert!(matches!(object.read(&mut [0u8; 8]), Err(StreamError::InvalidArgument)));
+    }
+
+    #[test_case]
+    fn test_device_events_dropped() {
+        let object = DeviceEventsObject::new();
+        while read_record(&object).is_some() {}
+        for id in 0..=DEVICE_EVENT_QUEUE_LEN {
+            object.push(DeviceEventRecord::new(ACTION_CHANGED, CLASS_BLOCK, id, "vda"));
+        }
+        assert_eq!(read_record(&object).unwrap().action, ACTION_DROPPED);
+        assert_eq!(read_record(&object).unwrap().device_id, DEVICE_EVENT_QUEUE_LEN as u64);
+        assert_eq!(read_record(&object), None);
+    }
+}
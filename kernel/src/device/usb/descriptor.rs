@@ -0,0 +1,270 @@
+//! USB descriptors
+//!
+//! The descriptors a device hands out with `GET_DESCRIPTOR`, parsed from
+//! their little-endian wire format. A configuration descriptor is read
+//! together with the interface, endpoint and class descriptors that follow
+//! it, and is parsed into a [`ConfigurationDescriptor`] holding its
+//! interfaces and their endpoints.
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::UsbError;
+
+/// Descriptor types
+pub const DESCRIPTOR_DEVICE: u8 = 0x01;
+pub const DESCRIPTOR_CONFIGURATION: u8 = 0x02;
+pub const DESCRIPTOR_STRING: u8 = 0x03;
+pub const DESCRIPTOR_INTERFACE: u8 = 0x04;
+pub const DESCRIPTOR_ENDPOINT: u8 = 0x05;
+pub const DESCRIPTOR_HID: u8 = 0x21;
+
+pub const DEVICE_DESCRIPTOR_LEN: usize = 18;
+pub const CONFIGURATION_DESCRIPTOR_LEN: usize = 9;
+
+/// Transfer type of an endpoint, bits 0-1 of `bmAttributes`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferType {
+    Control,
+    Isochronous,
+    Bulk,
+    Interrupt,
+}
+
+fn le16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DeviceDescriptor {
+    pub usb_version: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub max_packet_size0: u8,
+    pub vendor: u16,
+    pub product: u16,
+    pub device_version: u16,
+    pub manufacturer_index: u8,
+    pub product_index: u8,
+    pub serial_index: u8,
+    pub num_configurations: u8,
+}
+
+impl DeviceDescriptor {
+    pub fn parse(bytes: &[u8]) -> Result<Self, UsbError> {
+        if bytes.len() < DEVICE_DESCRIPTOR_LEN || bytes[1] != DESCRIPTOR_DEVICE {
+            return Err(UsbError::InvalidDescriptor);
+        }
+        Ok(Self {
+            usb_version: le16(bytes, 2),
+            class: bytes[4],
+            subclass: bytes[5],
+            protocol: bytes[6],
+            max_packet_size0: bytes[7],
+            vendor: le16(bytes, 8),
+            product: le16(bytes, 10),
+            device_version: le16(bytes, 12),
+            manufacturer_index: bytes[14],
+            product_index: bytes[15],
+            serial_index: bytes[16],
+            num_configurations: bytes[17],
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EndpointDescriptor {
+    /// Endpoint number, with bit 7 set for IN endpoints
+    pub address: u8,
+    pub attributes: u8,
+    pub max_packet_size: u16,
+    pub interval: u8,
+}
+
+impl EndpointDescriptor {
+    pub fn number(&self) -> u8 {
+        self.address & 0x0f
+    }
+
+    pub fn is_in(&self) -> bool {
+        self.address & 0x80 != 0
+    }
+
+    pub fn transfer_type(&self) -> TransferType {
+        match self.attributes & 0x03 {
+            0 => TransferType::Control,
+            1 => TransferType::Isochronous,
+            2 => TransferType::Bulk,
+            _ => TransferType::Interrupt,
+        }
+    }
+
+    /// Bytes in one packet; bits 11-12 are the extra transactions of
+    /// high-bandwidth endpoints
+    pub fn packet_size(&self) -> u16 {
+        self.max_packet_size & 0x07ff
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceDescriptor {
+    pub number: u8,
+    pub alternate_setting: u8,
+    pub class: u8,
+    pub subclass: u8,
+    pub protocol: u8,
+    pub string_index: u8,
+    pub endpoints: Vec<EndpointDescriptor>,
+    /// Length of the report descriptor, for HID interfaces
+    pub hid_report_length: Option<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigurationDescriptor {
+    pub value: u8,
+    pub attributes: u8,
+    /// In units of 2 mA
+    pub max_power: u8,
+    /// All interfaces, with every alternate setting
+    pub interfaces: Vec<InterfaceDescriptor>,
+}
+
+impl ConfigurationDescriptor {
+    /// `wTotalLength` of the configuration descriptor header in `bytes`,
+    /// which is the size to read the whole of it
+    pub fn total_length(bytes: &[u8]) -> Result<usize, UsbError> {
+        if bytes.len() < CONFIGURATION_DESCRIPTOR_LEN || bytes[1] != DESCRIPTOR_CONFIGURATION {
+            return Err(UsbError::InvalidDescriptor);
+        }
+        Ok(le16(bytes, 2) as usize)
+    }
+
+    /// Parse a configuration descriptor and the descriptors that follow it
+    ///
+    /// Descriptors of unknown types are skipped; endpoints belong to the
+    /// interface before them.
+    pub fn parse(bytes: &[u8]) -> Result<Self, UsbError> {
+        let total = Self::total_length(bytes)?.min(bytes.len());
+        let mut configuration = Self {
+            value: bytes[5],
+            attributes: bytes[7],
+            max_power: bytes[8],
+            interfaces: Vec::new(),
+        };
+        let mut offset = bytes[0] as usize;
+        while offset + 2 <= total {
+            let len = bytes[offset] as usize;
+            if len < 2 || offset + len > total {
+                return Err(UsbError::InvalidDescriptor);
+            }
+            let descriptor = &bytes[offset..offset + len];
+            match descriptor[1] {
+                DESCRIPTOR_INTERFACE if len >= 9 => configuration.interfaces.push(InterfaceDescriptor {
+                    number: descriptor[2],
+                    alternate_setting: descriptor[3],
+                    class: descriptor[5],
+                    subclass: descriptor[6],
+                    protocol: descriptor[7],
+                    string_index: descriptor[8],
+                    endpoints: Vec::new(),
+                    hid_report_length: None,
+                }),
+                DESCRIPTOR_ENDPOINT if len >= 7 => {
+                    let interface = configuration.interfaces.last_mut().ok_or(UsbError::InvalidDescriptor)?;
+                    interface.endpoints.push(EndpointDescriptor {
+                        address: descriptor[2],
+                        attributes: descriptor[3],
+                        max_packet_size: le16(descriptor, 4),
+                        interval: descriptor[6],
+                    });
+                }
+                // The first class descriptor of a HID descriptor is the report descriptor
+                DESCRIPTOR_HID if len >= 9 => {
+                    if let Some(interface) = configuration.interfaces.last_mut() {
+                        interface.hid_report_length = Some(le16(descriptor, 7));
+                    }
+                }
+                _ => {}
+            }
+            offset += len;
+        }
+        Ok(configuration)
+    }
+
+    /// The interfaces in their default (first) alternate setting
+    pub fn default_interfaces(&self) -> impl Iterator<Item = &InterfaceDescriptor> {
+        self.interfaces.iter().filter(|interface| interface.alternate_setting == 0)
+    }
+}
+
+/// Text of a string descriptor, which is UTF-16LE
+pub fn parse_string(bytes: &[u8]) -> Result<String, UsbError> {
+    if bytes.len() < 2 || bytes[1] != DESCRIPTOR_STRING {
+        return Err(UsbError::InvalidDescriptor);
+    }
+    let len = (bytes[0] as usize).min(bytes.len());
+    let units = bytes[2..len].chunks_exact(2).map(|unit| u16::from_le_bytes([unit[0], unit[1]]));
+    Ok(char::decode_utf16(units).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A boot keyboard: one interface, a HID descriptor and an interrupt IN
+    /// endpoint
+    const KEYBOARD_CONFIGURATION: [u8; 34] = [
+        9, 2, 34, 0, 1, 1, 0, 0xa0, 50,
+        9, 4, 0, 0, 1, 3, 1, 1, 0,
+        9, 0x21, 0x11, 0x01, 0, 1, 0x22, 63, 0,
+        7, 5, 0x81, 3, 8, 0, 10,
+    ];
+
+    #[test_case]
+    fn test_parse_device_descriptor() {
+        let bytes = [18, 1, 0x00, 0x02, 0, 0, 0, 64, 0x6d, 0x04, 0x1c, 0xc3, 0x10, 0x01, 1, 2, 0, 1];
+        let descriptor = DeviceDescriptor::parse(&bytes).unwrap();
+        assert_eq!(descriptor.usb_version, 0x0200);
+        assert_eq!(descriptor.max_packet_size0, 64);
+        assert_eq!(descriptor.vendor, 0x046d);
+        assert_eq!(descriptor.product, 0xc31c);
+        assert_eq!(descriptor.product_index, 2);
+        assert_eq!(descriptor.num_configurations, 1);
+        assert_eq!(DeviceDescriptor::parse(&bytes[..8]), Err(UsbError::InvalidDescriptor));
+    }
+
+    #[test_case]
+    fn test_parse_configuration() {
+        assert_eq!(ConfigurationDescriptor::total_length(&KEYBOARD_CONFIGURATION[..9]), Ok(34));
+        let configuration = ConfigurationDescriptor::parse(&KEYBOARD_CONFIGURATION).unwrap();
+        assert_eq!(configuration.value, 1);
+        assert_eq!(configuration.max_power, 50);
+        assert_eq!(configuration.interfaces.len(), 1);
+
+        let interface = &configuration.interfaces[0];
+        assert_eq!((interface.class, interface.subclass, interface.protocol), (3, 1, 1));
+        assert_eq!(interface.hid_report_length, Some(63));
+        assert_eq!(interface.endpoints.len(), 1);
+        let endpoint = interface.endpoints[0];
+        assert!(endpoint.is_in());
+        assert_eq!(endpoint.number(), 1);
+        assert_eq!(endpoint.transfer_type(), TransferType::Interrupt);
+        assert_eq!(endpoint.packet_size(), 8);
+        assert_eq!(endpoint.interval, 10);
+    }
+
+    #[test_case]
+    fn test_parse_truncated_configuration() {
+        let mut bytes = KEYBOARD_CONFIGURATION;
+        // An endpoint descriptor running past the end
+        bytes[27] = 9;
+        assert_eq!(ConfigurationDescriptor::parse(&bytes), Err(UsbError::InvalidDescriptor));
+    }
+
+    #[test_case]
+    fn test_parse_string() {
+        let bytes = [8, 3, b'K', 0, b'b', 0, b'd', 0];
+        assert_eq!(parse_string(&bytes).unwrap(), "Kbd");
+    }
+}
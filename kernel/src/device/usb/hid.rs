@@ -0,0 +1,257 @@
+//! USB HID class driver
+//!
+//! Binds to keyboards and mice that speak the boot protocol, which every
+//! one of them does for the sake of firmware, and turns their reports into
+//! events of an input device (`/dev/input/eventN`). The boot protocol has
+//! fixed report formats, so no report descriptor is parsed:
+//!
+//! - keyboard: a byte of modifier bits, a reserved byte and up to six HID
+//!   usages of the keys down. Keys are reported as they appear in or leave
+//!   the report.
+//! - mouse: a byte of button bits, then X, Y and, on most mice, the wheel
+//!   as signed relative motion.
+//!
+//! Key repeat is left to the reader, and the keyboard LEDs are not driven.
+
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use super::descriptor::{InterfaceDescriptor, TransferType};
+use super::{
+    register_class_driver, SetupPacket, UsbClassDriver, UsbDevice, UsbError, UsbInterfaceDriver, CLASS_HID,
+    REQUEST_RECIPIENT_INTERFACE, REQUEST_TYPE_CLASS,
+};
+use crate::device::input::{
+    self, Capabilities, EventDevice, InputId, BTN_LEFT, BTN_MIDDLE, BTN_RIGHT, BUS_USB, EV_KEY, EV_REL,
+    INPUT_PROP_POINTER, REL_WHEEL, REL_X, REL_Y,
+};
+use crate::driver_initcall;
+
+const SUBCLASS_BOOT: u8 = 1;
+const PROTOCOL_KEYBOARD: u8 = 1;
+const PROTOCOL_MOUSE: u8 = 2;
+
+/// Class requests
+const REQUEST_SET_IDLE: u8 = 0x0a;
+const REQUEST_SET_PROTOCOL: u8 = 0x0b;
+
+const BOOT_PROTOCOL: u16 = 0;
+
+/// Usage of every key down when too many are, which says nothing of them
+const USAGE_ERROR_ROLL_OVER: u8 = 0x01;
+
+/// Key codes of the HID keyboard usages 0x00-0x73, 0 for none
+#[rustfmt::skip]
+const USAGE_KEYS: [u8; 0x74] = [
+      0,   0,   0,   0,  30,  48,  46,  32,  18,  33,  34,  35,  23,  36,  37,  38,
+     50,  49,  24,  25,  16,  19,  31,  20,  22,  47,  17,  45,  21,  44,   2,   3,
+      4,   5,   6,   7,   8,   9,  10,  11,  28,   1,  14,  15,  57,  12,  13,  26,
+     27,  43,  43,  39,  40,  41,  51,  52,  53,  58,  59,  60,  61,  62,  63,  64,
+     65,  66,  67,  68,  87,  88,  99,  70, 119, 110, 102, 104, 111, 107, 109, 106,
+    105, 108, 103,  69,  98,  55,  74,  78,  96,  79,  80,  81,  75,  76,  77,  71,
+     72,  73,  82,  83,  86, 127, 116, 117, 183, 184, 185, 186, 187, 188, 189, 190,
+    191, 192, 193, 194,
+];
+
+/// Key codes of the modifier bits: left Ctrl, Shift, Alt, Meta, then right
+const MODIFIER_KEYS: [u16; 8] = [29, 42, 56, 125, 97, 54, 100, 126];
+
+fn usage_key(usage: u8) -> Option<u16> {
+    USAGE_KEYS.get(usage as usize).map(|&key| key as u16).filter(|&key| key != 0)
+}
+
+/// State of a boot keyboard between reports
+#[derive(Default)]
+struct BootKeyboard {
+    modifiers: u8,
+    usages: [u8; 6],
+}
+
+impl BootKeyboard {
+    /// Key changes from the last report to `report`, as (code, down)
+    fn update(&mut self, report: &[u8]) -> Vec<(u16, bool)> {
+        let mut changes = Vec::new();
+        if report.len() < 8 || report[2] == USAGE_ERROR_ROLL_OVER {
+            return changes;
+        }
+        let mut usages = [0u8; 6];
+        usages.copy_from_slice(&report[2..8]);
+
+        for (bit, &key) in MODIFIER_KEYS.iter().enumerate() {
+            let down = report[0] & 1 << bit != 0;
+            if down != (self.modifiers & 1 << bit != 0) {
+                changes.push((key, down));
+            }
+        }
+        for &usage in self.usages.iter().filter(|usage| !usages.contains(usage)) {
+            changes.extend(usage_key(usage).map(|key| (key, false)));
+        }
+        for &usage in usages.iter().filter(|usage| !self.usages.contains(usage)) {
+            changes.extend(usage_key(usage).map(|key| (key, true)));
+        }
+        self.modifiers = report[0];
+        self.usages = usages;
+        changes
+    }
+}
+
+/// Events of a boot mouse report, as (type, code, value)
+fn mouse_events(report: &[u8]) -> Vec<(u16, u16, i32)> {
+    let mut events = Vec::new();
+    if report.len() < 3 {
+        return events;
+    }
+    for (bit, button) in [BTN_LEFT, BTN_RIGHT, BTN_MIDDLE].into_iter().enumerate() {
+        events.push((EV_KEY, button, (report[0] >> bit & 1) as i32));
+    }
+    events.push((EV_REL, REL_X, report[1] as i8 as i32));
+    events.push((EV_REL, REL_Y, report[2] as i8 as i32));
+    if let Some(&wheel) = report.get(3) {
+        events.push((EV_REL, REL_WHEEL, wheel as i8 as i32));
+    }
+    events
+}
+
+/// A keyboard or mouse interface and its input device
+struct HidInterface {
+    input: Arc<EventDevice>,
+}
+
+impl UsbInterfaceDriver for HidInterface {
+    fn disconnect(&self) {
+        input::unregister(&self.input);
+    }
+}
+
+fn class_request(request: u8, value: u16, interface: u8) -> SetupPacket {
+    SetupPacket {
+        request_type: REQUEST_TYPE_CLASS | REQUEST_RECIPIENT_INTERFACE,
+        request,
+        value,
+        index: interface as u16,
+        length: 0,
+    }
+}
+
+struct HidDriver;
+
+impl UsbClassDriver for HidDriver {
+    fn name(&self) -> &'static str {
+        "usbhid"
+    }
+
+    fn probe(&self, device: &Arc<UsbDevice>, interface: &InterfaceDescriptor) -> Result<Box<dyn UsbInterfaceDriver>, UsbError> {
+        if interface.class != CLASS_HID || interface.subclass != SUBCLASS_BOOT {
+            return Err(UsbError::NotSupported);
+        }
+        let (kind, capabilities) = match interface.protocol {
+            PROTOCOL_KEYBOARD => {
+                let keys = USAGE_KEYS.iter().filter(|&&key| key != 0).map(|&key| key as u16).chain(MODIFIER_KEYS);
+                ("Keyboard", Capabilities::new().keys(keys))
+            }
+            PROTOCOL_MOUSE => (
+                "Mouse",
+                Capabilities::new()
+                    .keys([BTN_LEFT, BTN_RIGHT, BTN_MIDDLE])
+                    .relative([REL_X, REL_Y, REL_WHEEL])
+                    .property(INPUT_PROP_POINTER),
+            ),
+            _ => return Err(UsbError::NotSupported),
+        };
+        let endpoint = interface
+            .endpoints
+            .iter()
+            .find(|endpoint| endpoint.is_in() && endpoint.transfer_type() == TransferType::Interrupt)
+            .ok_or(UsbError::NotSupported)?;
+
+        device.control_out(class_request(REQUEST_SET_PROTOCOL, BOOT_PROTOCOL, interface.number), &[])?;
+        // Report only on changes; many mice stall this, which is harmless
+        let _ = device.control_out(class_request(REQUEST_SET_IDLE, 0, interface.number), &[]);
+
+        let descriptor = device.descriptor();
+        let name = match device.product() {
+            Some(product) if !product.is_empty() => product.into(),
+            _ => format!("USB {}", kind),
+        };
+        let id = InputId { bus: BUS_USB, vendor: descriptor.vendor, product: descriptor.product, version: descriptor.device_version };
+        let input = EventDevice::new(&name, id, capabilities);
+        let number = input::register(input.clone());
+
+        let reporter = input.clone();
+        let handler: super::InterruptHandler = match interface.protocol {
+            PROTOCOL_KEYBOARD => {
+                let keyboard = Mutex::new(BootKeyboard::default());
+                Arc::new(move |report: &[u8]| {
+                    for (key, down) in keyboard.lock().update(report) {
+                        reporter.report(EV_KEY, key, down as i32);
+                    }
+                    reporter.sync();
+                })
+            }
+            _ => Arc::new(move |report: &[u8]| {
+                for (kind, code, value) in mouse_events(report) {
+                    reporter.report(kind, code, value);
+                }
+                reporter.sync();
+            }),
+        };
+        if let Err(error) = device.interrupt_in(endpoint.address, handler) {
+            input::unregister(&input);
+            return Err(error);
+        }
+        crate::early_println!("[usbhid] {} \"{}\" is input/event{}", kind, name, number);
+        Ok(Box::new(HidInterface { input }))
+    }
+}
+
+fn register_driver() {
+    register_class_driver(Arc::new(HidDriver));
+}
+
+driver_initcall!(register_driver);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_keyboard_keys() {
+        let mut keyboard = BootKeyboard::default();
+        // a down, then a and b, then left Shift with b, then nothing
+        assert_eq!(keyboard.update(&[0, 0, 0x04, 0, 0, 0, 0, 0]), [(30, true)]);
+        assert_eq!(keyboard.update(&[0, 0, 0x04, 0x05, 0, 0, 0, 0]), [(48, true)]);
+        assert_eq!(keyboard.update(&[0x02, 0, 0x05, 0, 0, 0, 0, 0]), [(42, true), (30, false)]);
+        assert_eq!(keyboard.update(&[0, 0, 0, 0, 0, 0, 0, 0]), [(42, false), (48, false)]);
+    }
+
+    #[test_case]
+    fn test_keyboard_roll_over() {
+        let mut keyboard = BootKeyboard::default();
+        assert_eq!(keyboard.update(&[0, 0, 0x28, 0, 0, 0, 0, 0]), [(28, true)]);
+        // Too many keys: the report says nothing, Enter stays down
+        assert!(keyboard.update(&[0, 0, 1, 1, 1, 1, 1, 1]).is_empty());
+        assert_eq!(keyboard.update(&[0, 0, 0, 0, 0, 0, 0, 0]), [(28, false)]);
+        // Usages without a key are ignored
+        assert!(keyboard.update(&[0, 0, 0xe0, 0, 0, 0, 0, 0]).is_empty());
+    }
+
+    #[test_case]
+    fn test_mouse_events() {
+        let events = mouse_events(&[0x05, 0xfe, 3, 0xff]);
+        assert_eq!(
+            events,
+            [
+                (EV_KEY, BTN_LEFT, 1),
+                (EV_KEY, BTN_RIGHT, 0),
+                (EV_KEY, BTN_MIDDLE, 1),
+                (EV_REL, REL_X, -2),
+                (EV_REL, REL_Y, 3),
+                (EV_REL, REL_WHEEL, -1),
+            ]
+        );
+        assert_eq!(mouse_events(&[0, 1, 0]).len(), 5);
+    }
+}
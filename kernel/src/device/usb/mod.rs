@@ -0,0 +1,466 @@
+//! USB
+//!
+//! The USB core sits between host controller drivers and class drivers. A
+//! host controller driver implements [`UsbHostController`] and registers
+//! it with [`register_host_controller`]; a class driver implements
+//! [`UsbClassDriver`] and registers it with [`register_class_driver`].
+//!
+//! The `usbd` kernel thread watches the root hub ports of every controller.
+//! When a device is plugged in it enumerates it: resets the port, has the
+//! controller give it an address, reads its device and configuration
+//! descriptors, configures the endpoints of the first configuration and
+//! offers each interface to the class drivers. When the device is unplugged
+//! the class drivers bound to it are disconnected and the controller forgets
+//! it. In between the thread polls the controllers, which hands completed
+//! interrupt transfers to their handlers.
+//!
+//! Control and bulk transfers are synchronous; interrupt IN endpoints are
+//! read continuously once started with [`UsbDevice::interrupt_in`]. External
+//! hubs are not supported.
+
+pub mod descriptor;
+pub mod hid;
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::{Mutex, RwLock};
+
+use crate::late_initcall;
+use crate::object::capability::poll::wait_for;
+use crate::task::kthread;
+use crate::timer::ms_to_ticks;
+use descriptor::{
+    ConfigurationDescriptor, DeviceDescriptor, EndpointDescriptor, InterfaceDescriptor, CONFIGURATION_DESCRIPTOR_LEN,
+    DESCRIPTOR_CONFIGURATION, DESCRIPTOR_DEVICE, DESCRIPTOR_STRING, DEVICE_DESCRIPTOR_LEN,
+};
+
+/// Standard requests
+pub const REQUEST_GET_STATUS: u8 = 0x00;
+pub const REQUEST_CLEAR_FEATURE: u8 = 0x01;
+pub const REQUEST_SET_FEATURE: u8 = 0x03;
+pub const REQUEST_SET_ADDRESS: u8 = 0x05;
+pub const REQUEST_GET_DESCRIPTOR: u8 = 0x06;
+pub const REQUEST_GET_CONFIGURATION: u8 = 0x08;
+pub const REQUEST_SET_CONFIGURATION: u8 = 0x09;
+pub const REQUEST_SET_INTERFACE: u8 = 0x0b;
+
+/// `bmRequestType`: direction, type and recipient
+pub const REQUEST_DIR_IN: u8 = 0x80;
+pub const REQUEST_TYPE_STANDARD: u8 = 0x00;
+pub const REQUEST_TYPE_CLASS: u8 = 0x20;
+pub const REQUEST_TYPE_VENDOR: u8 = 0x40;
+pub const REQUEST_RECIPIENT_DEVICE: u8 = 0x00;
+pub const REQUEST_RECIPIENT_INTERFACE: u8 = 0x01;
+pub const REQUEST_RECIPIENT_ENDPOINT: u8 = 0x02;
+
+/// Interface classes
+pub const CLASS_HID: u8 = 0x03;
+pub const CLASS_MASS_STORAGE: u8 = 0x08;
+pub const CLASS_HUB: u8 = 0x09;
+
+/// US English, the language strings are asked in
+const LANGUAGE_ID: u16 = 0x0409;
+
+/// How often `usbd` polls ports and controllers
+const POLL_INTERVAL_MS: u64 = 10;
+/// Time for the contacts of a newly plugged device to settle (USB 2.0 7.1.7.3)
+const DEBOUNCE_MS: u64 = 100;
+/// Time a device gets after its port is reset (USB 2.0 7.1.7.5)
+const RESET_RECOVERY_MS: u64 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsbError {
+    /// The device did not answer in time
+    Timeout,
+    /// The endpoint stalled: the request is not supported
+    Stall,
+    /// The transfer failed on the bus
+    TransactionError,
+    /// The device sent more than asked for
+    Babble,
+    /// The controller ran out of slots, rings or memory
+    NoResources,
+    /// A descriptor is malformed
+    InvalidDescriptor,
+    /// The driver does not handle the device or interface
+    NotSupported,
+    /// The device is gone
+    Disconnected,
+    /// The controller reported this completion code
+    Controller(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsbSpeed {
+    Low,
+    Full,
+    High,
+    Super,
+}
+
+impl UsbSpeed {
+    /// Max packet size of endpoint 0 until the device descriptor tells
+    pub fn default_max_packet_size0(self) -> u16 {
+        match self {
+            UsbSpeed::Low => 8,
+            UsbSpeed::Full | UsbSpeed::High => 64,
+            UsbSpeed::Super => 512,
+        }
+    }
+}
+
+/// The 8 bytes of the setup stage of a control transfer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetupPacket {
+    pub request_type: u8,
+    pub request: u8,
+    pub value: u16,
+    pub index: u16,
+    pub length: u16,
+}
+
+impl SetupPacket {
+    pub fn is_in(&self) -> bool {
+        self.request_type & REQUEST_DIR_IN != 0
+    }
+
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let mut bytes = [0u8; 8];
+        bytes[0] = self.request_type;
+        bytes[1] = self.request;
+        bytes[2..4].copy_from_slice(&self.value.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.index.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.length.to_le_bytes());
+        bytes
+    }
+}
+
+/// Called with the data of every completed interrupt IN transfer
+pub type InterruptHandler = Arc<dyn Fn(&[u8]) + Send + Sync>;
+
+/// A USB host controller and its root hub
+///
+/// Ports are numbered from 1. Devices are named by the handle
+/// [`attach_device`](Self::attach_device) returns, and endpoints by their
+/// address, with bit 7 set for IN.
+pub trait UsbHostController: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Number of root hub ports
+    fn port_count(&self) -> usize;
+
+    /// Whether a device is plugged into `port`
+    fn port_connected(&self, port: usize) -> bool;
+
+    /// Reset `port`, which enables it, and return the speed of its device
+    fn reset_port(&self, port: usize) -> Result<UsbSpeed, UsbError>;
+
+    /// Give the device on the reset `port` an address and return its handle
+    fn attach_device(&self, port: usize, speed: UsbSpeed) -> Result<usize, UsbError>;
+
+    /// Forget a device, which is unplugged or failed to enumerate
+    fn detach_device(&self, device: usize);
+
+    /// Change the max packet size of endpoint 0 to the one of the device
+    /// descriptor
+    fn set_max_packet_size0(&self, device: usize, size: u16) -> Result<(), UsbError>;
+
+    /// Set up the endpoints of the configuration about to be selected
+    fn configure_endpoints(&self, device: usize, endpoints: &[EndpointDescriptor]) -> Result<(), UsbError>;
+
+    /// Do a control transfer on endpoint 0 and return the bytes moved by
+    /// its data stage, which reads or writes `data` depending on the
+    /// direction of `setup`
+    fn control_transfer(&self, device: usize, setup: SetupPacket, data: &mut [u8]) -> Result<usize, UsbError>;
+
+    /// Do a bulk transfer and return the bytes moved
+    fn bulk_transfer(&self, device: usize, endpoint: u8, data: &mut [u8]) -> Result<usize, UsbError>;
+
+    /// Keep reading the interrupt IN `endpoint`, handing each transfer to
+    /// `handler`
+    fn interrupt_in(&self, device: usize, endpoint: u8, handler: InterruptHandler) -> Result<(), UsbError>;
+
+    /// Take completed transfers and call the handlers of interrupt
+    /// endpoints; called by `usbd` every few milliseconds
+    fn poll(&self);
+}
+
+/// An interface a class driver has bound to
+pub trait UsbInterfaceDriver: Send + Sync {
+    /// The device is gone, or the interface is released
+    fn disconnect(&self);
+}
+
+/// A driver for a class of interfaces
+pub trait UsbClassDriver: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Bind to `interface` of `device`, or fail with
+    /// [`UsbError::NotSupported`] to leave it to the other drivers
+    fn probe(&self, device: &Arc<UsbDevice>, interface: &InterfaceDescriptor) -> Result<Box<dyn UsbInterfaceDriver>, UsbError>;
+}
+
+/// An enumerated device, in its first configuration
+pub struct UsbDevice {
+    controller: Arc<dyn UsbHostController>,
+    handle: usize,
+    port: usize,
+    speed: UsbSpeed,
+    descriptor: DeviceDescriptor,
+    configuration: ConfigurationDescriptor,
+    product: Option<String>,
+    /// Interfaces bound by class drivers
+    bindings: Mutex<Vec<Box<dyn UsbInterfaceDriver>>>,
+}
+
+impl UsbDevice {
+    pub fn port(&self) -> usize {
+        self.port
+    }
+
+    pub fn speed(&self) -> UsbSpeed {
+        self.speed
+    }
+
+    pub fn descriptor(&self) -> &DeviceDescriptor {
+        &self.descriptor
+    }
+
+    pub fn configuration(&self) -> &ConfigurationDescriptor {
+        &self.configuration
+    }
+
+    /// Product string of the device, if it has one
+    pub fn product(&self) -> Option<&str> {
+        self.product.as_deref()
+    }
+
+    pub fn control_in(&self, setup: SetupPacket, data: &mut [u8]) -> Result<usize, UsbError> {
+        self.controller.control_transfer(self.handle, setup, data)
+    }
+
+    pub fn control_out(&self, setup: SetupPacket, data: &[u8]) -> Result<usize, UsbError> {
+        let mut data = data.to_vec();
+        self.controller.control_transfer(self.handle, setup, &mut data)
+    }
+
+    pub fn bulk_transfer(&self, endpoint: u8, data: &mut [u8]) -> Result<usize, UsbError> {
+        self.controller.bulk_transfer(self.handle, endpoint, data)
+    }
+
+    pub fn interrupt_in(&self, endpoint: u8, handler: InterruptHandler) -> Result<(), UsbError> {
+        self.controller.interrupt_in(self.handle, endpoint, handler)
+    }
+
+    fn disconnect(&self) {
+        for binding in self.bindings.lock().drain(..) {
+            binding.disconnect();
+        }
+        self.controller.detach_device(self.handle);
+    }
+}
+
+/// `GET_DESCRIPTOR` of `descriptor_type` and `index` into `data`
+fn get_descriptor(
+    controller: &dyn UsbHostController,
+    handle: usize,
+    descriptor_type: u8,
+    index: u8,
+    language: u16,
+    data: &mut [u8],
+) -> Result<usize, UsbError> {
+    let setup = SetupPacket {
+        request_type: REQUEST_DIR_IN | REQUEST_TYPE_STANDARD | REQUEST_RECIPIENT_DEVICE,
+        request: REQUEST_GET_DESCRIPTOR,
+        value: (descriptor_type as u16) << 8 | index as u16,
+        index: language,
+        length: data.len() as u16,
+    };
+    controller.control_transfer(handle, setup, data)
+}
+
+fn get_string(controller: &dyn UsbHostController, handle: usize, index: u8) -> Option<String> {
+    if index == 0 {
+        return None;
+    }
+    let mut data = [0u8; 255];
+    let len = get_descriptor(controller, handle, DESCRIPTOR_STRING, index, LANGUAGE_ID, &mut data).ok()?;
+    descriptor::parse_string(&data[..len]).ok()
+}
+
+/// Read the descriptors of the addressed device `handle` and select its
+/// first configuration
+fn configure(controller: &Arc<dyn UsbHostController>, handle: usize, port: usize, speed: UsbSpeed) -> Result<Arc<UsbDevice>, UsbError> {
+    // The first 8 bytes fit in any max packet size and hold the real one
+    let mut data = [0u8; DEVICE_DESCRIPTOR_LEN];
+    get_descriptor(&**controller, handle, DESCRIPTOR_DEVICE, 0, 0, &mut data[..8])?;
+    let max_packet_size0 = match speed {
+        // bMaxPacketSize0 is an exponent from USB 3.0 on
+        UsbSpeed::Super => 1 << data[7].min(9),
+        _ => data[7] as u16,
+    };
+    if max_packet_size0 != speed.default_max_packet_size0() {
+        controller.set_max_packet_size0(handle, max_packet_size0)?;
+    }
+    let len = get_descriptor(&**controller, handle, DESCRIPTOR_DEVICE, 0, 0, &mut data)?;
+    let descriptor = DeviceDescriptor::parse(&data[..len])?;
+
+    let mut header = [0u8; CONFIGURATION_DESCRIPTOR_LEN];
+    get_descriptor(&**controller, handle, DESCRIPTOR_CONFIGURATION, 0, 0, &mut header)?;
+    let mut data = vec![0u8; ConfigurationDescriptor::total_length(&header)?];
+    let len = get_descriptor(&**controller, handle, DESCRIPTOR_CONFIGURATION, 0, 0, &mut data)?;
+    let configuration = ConfigurationDescriptor::parse(&data[..len])?;
+
+    let endpoints: Vec<EndpointDescriptor> = configuration
+        .default_interfaces()
+        .flat_map(|interface| interface.endpoints.iter().copied())
+        .collect();
+    controller.configure_endpoints(handle, &endpoints)?;
+    let setup = SetupPacket {
+        request_type: REQUEST_TYPE_STANDARD | REQUEST_RECIPIENT_DEVICE,
+        request: REQUEST_SET_CONFIGURATION,
+        value: configuration.value as u16,
+        index: 0,
+        length: 0,
+    };
+    controller.control_transfer(handle, setup, &mut [])?;
+
+    let product = get_string(&**controller, handle, descriptor.product_index);
+    Ok(Arc::new(UsbDevice {
+        controller: controller.clone(),
+        handle,
+        port,
+        speed,
+        descriptor,
+        configuration,
+        product,
+        bindings: Mutex::new(Vec::new()),
+    }))
+}
+
+/// Offer every interface of `device` to the class drivers
+fn bind(device: &Arc<UsbDevice>) {
+    let drivers = CLASS_DRIVERS.read().clone();
+    for interface in device.configuration.default_interfaces() {
+        for driver in &drivers {
+            match driver.probe(device, interface) {
+                Ok(binding) => {
+                    crate::early_println!("[usb] {} bound to interface {} of port {}", driver.name(), interface.number, device.port);
+                    device.bindings.lock().push(binding);
+                    break;
+                }
+                Err(UsbError::NotSupported) => {}
+                Err(error) => {
+                    crate::early_println!("[usb] {} failed on interface {} of port {}: {:?}", driver.name(), interface.number, device.port, error);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Enumerate the device just plugged into `port`
+fn enumerate(controller: &Arc<dyn UsbHostController>, port: usize) -> Result<Arc<UsbDevice>, UsbError> {
+    sleep_ms(DEBOUNCE_MS);
+    let speed = controller.reset_port(port)?;
+    sleep_ms(RESET_RECOVERY_MS);
+    let handle = controller.attach_device(port, speed)?;
+    let device = configure(controller, handle, port, speed).inspect_err(|_| controller.detach_device(handle))?;
+    let descriptor = device.descriptor();
+    crate::early_println!(
+        "[usb] {} port {}: {:04x}:{:04x} {} ({:?} speed)",
+        controller.name(),
+        port,
+        descriptor.vendor,
+        descriptor.product,
+        device.product().unwrap_or(""),
+        speed
+    );
+    bind(&device);
+    Ok(device)
+}
+
+fn sleep_ms(ms: u64) {
+    wait_for(&[], Some(ms_to_ticks(ms).max(1)), || false);
+}
+
+static HOST_CONTROLLERS: Mutex<Vec<Arc<dyn UsbHostController>>> = Mutex::new(Vec::new());
+static CLASS_DRIVERS: RwLock<Vec<Arc<dyn UsbClassDriver>>> = RwLock::new(Vec::new());
+
+/// Add a controller; `usbd` picks up the devices on its ports
+pub fn register_host_controller(controller: Arc<dyn UsbHostController>) {
+    crate::early_println!("[usb] Registered host controller {} with {} ports", controller.name(), controller.port_count());
+    HOST_CONTROLLERS.lock().push(controller);
+}
+
+/// Add a class driver; it is offered the interfaces of devices plugged in
+/// from then on
+pub fn register_class_driver(driver: Arc<dyn UsbClassDriver>) {
+    CLASS_DRIVERS.write().push(driver);
+}
+
+/// Body of the `usbd` kernel thread
+fn usbd_thread() {
+    // Device on each (controller, port), `None` for one that failed to
+    // enumerate and is left alone until unplugged
+    let mut devices: BTreeMap<(usize, usize), Option<Arc<UsbDevice>>> = BTreeMap::new();
+    while !kthread::should_stop() {
+        let controllers = HOST_CONTROLLERS.lock().clone();
+        for (index, controller) in controllers.iter().enumerate() {
+            for port in 1..=controller.port_count() {
+                let connected = controller.port_connected(port);
+                match (connected, devices.contains_key(&(index, port))) {
+                    (true, false) => {
+                        let device = enumerate(controller, port)
+                            .inspect_err(|error| {
+                                crate::early_println!("[usb] {} port {}: cannot enumerate: {:?}", controller.name(), port, error)
+                            })
+                            .ok();
+                        devices.insert((index, port), device);
+                    }
+                    (false, true) => {
+                        if let Some(Some(device)) = devices.remove(&(index, port)) {
+                            crate::early_println!("[usb] {} port {}: disconnected", controller.name(), port);
+                            device.disconnect();
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            controller.poll();
+        }
+        sleep_ms(POLL_INTERVAL_MS);
+    }
+}
+
+/// Start `usbd` if there are controllers, which are all probed by now
+fn init_usb() {
+    if HOST_CONTROLLERS.lock().is_empty() {
+        return;
+    }
+    kthread::spawn("usbd", usbd_thread);
+}
+
+late_initcall!(init_usb);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_setup_packet_bytes() {
+        let setup = SetupPacket {
+            request_type: REQUEST_DIR_IN | REQUEST_TYPE_STANDARD | REQUEST_RECIPIENT_DEVICE,
+            request: REQUEST_GET_DESCRIPTOR,
+            value: (DESCRIPTOR_DEVICE as u16) << 8,
+            index: 0,
+            length: 18,
+        };
+        assert!(setup.is_in());
+        assert_eq!(setup.to_bytes(), [0x80, 0x06, 0x00, 0x01, 0x00, 0x00, 0x12, 0x00]);
+    }
+}
@@ -11,13 +11,14 @@
 //! The driver checks for and handles the following VirtIO block device features:
 //! - `VIRTIO_BLK_F_BLK_SIZE`: Custom sector size
 //! - `VIRTIO_BLK_F_RO`: Read-only device detection
+//! - `VIRTIO_BLK_F_DISCARD`: Discard (TRIM) requests for freed sectors
 //!
 //! ## Implementation Details
 //!
 //! The driver uses a single virtqueue for processing block I/O requests. Each request
 //! consists of three parts:
 //! 1. Request header (specifying operation type and sector)
-//! 2. Data buffer (for read/write content)
+//! 2. Data buffer (for read/write content, or discard segments)
 //! 3. Status byte (for operation result)
 //!
 //! Requests are processed through the VirtIO descriptor chain mechanism, with proper
@@ -28,13 +29,15 @@ use alloc::vec;
 use spin::{Mutex, RwLock};
 
 use core::{mem, ptr};
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use crate::defer;
+use crate::device::power::{self, RuntimePm};
 use crate::device::{Device, DeviceType};
 use crate::drivers::virtio::features::{VIRTIO_F_ANY_LAYOUT, VIRTIO_RING_F_EVENT_IDX, VIRTIO_RING_F_INDIRECT_DESC};
 use crate::object::capability::MemoryMappingOps;
 use crate::{
-    device::block::{request::{BlockIORequest, BlockIORequestType, BlockIOResult}, BlockDevice}, 
+    device::block::{request::{BlockIORequest, BlockIORequestType, BlockIOResult}, stats::BlockIoStats, fail_removed, BlockDevice}, 
     drivers::virtio::{device::VirtioDevice, queue::{DescriptorFlag, VirtQueue}}, object::capability::ControlOps
 };
 
@@ -42,6 +45,7 @@ use crate::{
 const VIRTIO_BLK_T_IN: u32 = 0;     // Read
 const VIRTIO_BLK_T_OUT: u32 = 1;    // Write
 // const VIRTIO_BLK_T_FLUSH: u32 = 4;  // Flush
+const VIRTIO_BLK_T_DISCARD: u32 = 11; // Discard
 
 // VirtIO Block Status Codes
 const VIRTIO_BLK_S_OK: u8 = 0;
@@ -58,6 +62,10 @@ const VIRTIO_BLK_F_SCSI: u32 = 7;
 // const VIRTIO_BLK_F_FLUSH: u32 = 9;
 const VIRTIO_BLK_F_CONFIG_WCE: u32 = 11;
 const VIRTIO_BLK_F_MQ: u32 = 12;
+const VIRTIO_BLK_F_DISCARD: u32 = 13;
+
+/// Idle time before the device is runtime suspended
+const AUTOSUSPEND_MS: u64 = 2000;
 
 // #define VIRTIO_BLK_F_RO              5	/* Disk is read-only */
 // #define VIRTIO_BLK_F_SCSI            7	/* Supports scsi command passthru */
@@ -100,6 +108,14 @@ pub struct VirtioBlkReqHeader {
     pub sector: u64,
 }
 
+/// Payload segment of a VIRTIO_BLK_T_DISCARD request
+#[repr(C)]
+pub struct VirtioBlkDiscardSegment {
+    pub sector: u64,
+    pub num_sectors: u32,
+    pub flags: u32,
+}
+
 pub struct VirtioBlockDevice {
     base_addr: usize,
     virtqueues: Mutex<[VirtQueue<'static>; 1]>, // Only one queue for request/response
@@ -107,7 +123,12 @@ pub struct VirtioBlockDevice {
     sector_size: RwLock<u32>,
     features: RwLock<u32>,
     read_only: RwLock<bool>,
+    max_discard_sectors: RwLock<u32>,
+    max_discard_seg: RwLock<u32>,
     request_queue: Mutex<VecDeque<Box<BlockIORequest>>>,
+    stats: BlockIoStats,
+    removed: AtomicBool,
+    pm: RuntimePm,
 }
 
 impl VirtioBlockDevice {
@@ -124,7 +145,12 @@ impl VirtioBlockDevice {
             sector_size: RwLock::new(512), // Default sector size
             features: RwLock::new(0),
             read_only: RwLock::new(false),
+            max_discard_sectors: RwLock::new(0),
+            max_discard_seg: RwLock::new(0),
             request_queue: Mutex::new(VecDeque::new()),
+            stats: BlockIoStats::new(),
+            removed: AtomicBool::new(false),
+            pm: RuntimePm::new(AUTOSUSPEND_MS),
         };
         
         // Initialize the device
@@ -156,21 +182,80 @@ impl VirtioBlockDevice {
         // Check if device is read-only
         *device.read_only.write() = negotiated_features & (1 << VIRTIO_BLK_F_RO) != 0;
 
+        // Read discard limits if the device supports discard
+        if negotiated_features & (1 << VIRTIO_BLK_F_DISCARD) != 0 {
+            *device.max_discard_sectors.write() = device.read_config::<u32>(36); // max_discard_sectors at offset 36
+            *device.max_discard_seg.write() = device.read_config::<u32>(40); // max_discard_seg at offset 40
+        }
+
         device
     }
+
+    /// Map a block I/O request type to the VirtIO request type
+    fn request_header_type(request_type: BlockIORequestType) -> u32 {
+        match request_type {
+            BlockIORequestType::Read => VIRTIO_BLK_T_IN,
+            BlockIORequestType::Write => VIRTIO_BLK_T_OUT,
+            BlockIORequestType::Discard => VIRTIO_BLK_T_DISCARD,
+        }
+    }
+
+    /// Build the data buffer for a request
+    ///
+    /// Reads get a zeroed buffer for the device to fill, writes get a copy of
+    /// the request buffer, and discards get an array of discard segments, each
+    /// no larger than the device's `max_discard_sectors`.
+    fn build_request_data(&self, req: &BlockIORequest) -> Result<Box<[u8]>, &'static str> {
+        match req.request_type {
+            BlockIORequestType::Read => Ok(vec![0u8; req.buffer.len()].into_boxed_slice()),
+            BlockIORequestType::Write => Ok(req.buffer.clone().into_boxed_slice()),
+            BlockIORequestType::Discard => {
+                if !self.supports_discard() {
+                    return Err("Discard not supported by device");
+                }
+                if req.sector_count == 0 {
+                    return Err("Empty discard range");
+                }
+
+                let max_sectors = *self.max_discard_sectors.read() as usize;
+                let max_segments = (*self.max_discard_seg.read()).max(1) as usize;
+                let segment_count = (req.sector_count + max_sectors - 1) / max_sectors;
+                if segment_count > max_segments {
+                    return Err("Discard range too large");
+                }
+
+                let segment_size = mem::size_of::<VirtioBlkDiscardSegment>();
+                let mut data = vec![0u8; segment_count * segment_size];
+                let mut sector = req.sector;
+                let mut remaining = req.sector_count;
+                for chunk in data.chunks_exact_mut(segment_size) {
+                    let num_sectors = remaining.min(max_sectors);
+                    let segment = VirtioBlkDiscardSegment {
+                        sector: sector as u64,
+                        num_sectors: num_sectors as u32,
+                        flags: 0,
+                    };
+                    unsafe {
+                        ptr::write_unaligned(chunk.as_mut_ptr() as *mut VirtioBlkDiscardSegment, segment);
+                    }
+                    sector += num_sectors;
+                    remaining -= num_sectors;
+                }
+                Ok(data.into_boxed_slice())
+            }
+        }
+    }
     
     fn process_request(&self, req: &mut BlockIORequest) -> Result<(), &'static str> {
         crate::profile_scope!("virtio_blk::process_request");
         // Allocate memory for request header, data, and status
         let header = Box::new(VirtioBlkReqHeader {
-            type_: match req.request_type {
-                BlockIORequestType::Read => VIRTIO_BLK_T_IN,
-                BlockIORequestType::Write => VIRTIO_BLK_T_OUT,
-            },
+            type_: Self::request_header_type(req.request_type),
             reserved: 0,
             sector: req.sector as u64,
         });
-        let data = vec![0u8; req.buffer.len()].into_boxed_slice();
+        let data = self.build_request_data(req)?;
+        let data_len = data.len();
         let status = Box::new(0u8);
                 
         // Cast pages to appropriate types
@@ -187,18 +272,6 @@ impl VirtioBlockDevice {
             }
         }
 
-        // Set up request header
-        unsafe {
-            // Copy data for write requests
-            if let BlockIORequestType::Write = req.request_type {
-                ptr::copy_nonoverlapping(
-                    req.buffer.as_ptr(),
-                    data_ptr as *mut u8,
-                    req.buffer.len()
-                );
-            }
-        }
-        
         // Lock the virtqueues for processing
         let mut virtqueues = self.virtqueues.lock();
         
@@ -228,7 +301,7 @@ impl VirtioBlockDevice {
         
         // Set up data descriptor
         virtqueues[0].desc[data_desc].addr = (data_ptr as *mut u8 as usize) as u64;
-        virtqueues[0].desc[data_desc].len = req.buffer.len() as u32;
+        virtqueues[0].desc[data_desc].len = data_len as u32;
         
         // Set flags based on request type
         match req.request_type {
@@ -236,7 +309,7 @@ impl VirtioBlockDevice {
                 DescriptorFlag::Next.set(&mut virtqueues[0].desc[data_desc].flags);
                 DescriptorFlag::Write.set(&mut virtqueues[0].desc[data_desc].flags);
             },
-            BlockIORequestType::Write => {
+            BlockIORequestType::Write | BlockIORequestType::Discard => {
                 DescriptorFlag::Next.set(&mut virtqueues[0].desc[data_desc].flags);
             }
         }
@@ -289,6 +362,7 @@ impl VirtioBlockDevice {
             VIRTIO_BLK_S_OK => {
                 // For read requests, copy data to the buffer
                 if let BlockIORequestType::Read = req.request_type {
+                    crate::mem::kasan::check_read(data_ptr as *const u8 as usize, virtqueues[0].desc[data_desc].len as usize);
                     unsafe {
                         req.buffer.clear();
                         req.buffer.extend_from_slice(core::slice::from_raw_parts(
@@ -379,32 +453,25 @@ impl VirtioBlockDevice {
         // First pass: Submit all requests
         for (idx, req) in requests.iter_mut().enumerate() {
             // Allocate memory for request header, data, and status
+            let data = match self.build_request_data(req) {
+                Ok(data) => data,
+                Err(e) => {
+                    results[idx] = Err(e);
+                    continue;
+                }
+            };
+            let data_len = data.len();
             let header = Box::new(VirtioBlkReqHeader {
-                type_: match req.request_type {
-                    BlockIORequestType::Read => VIRTIO_BLK_T_IN,
-                    BlockIORequestType::Write => VIRTIO_BLK_T_OUT,
-                },
+                type_: Self::request_header_type(req.request_type),
                 reserved: 0,
                 sector: req.sector as u64,
             });
-            let data = vec![0u8; req.buffer.len()].into_boxed_slice();
             let status = Box::new(0u8);
             
             let header_ptr = Box::into_raw(header);
             let data_ptr = Box::into_raw(data) as *mut [u8];
             let status_ptr = Box::into_raw(status);
             
-            // Copy data for write requests
-            if let BlockIORequestType::Write = req.request_type {
-                unsafe {
-                    core::ptr::copy_nonoverlapping(
-                        req.buffer.as_ptr(),
-                        data_ptr as *mut u8,
-                        req.buffer.len()
-                    );
-                }
-            }
-            
             // Try to allocate descriptors
             if let (Some(header_desc), Some(data_desc), Some(status_desc)) = (
                 virtqueues[0].alloc_desc(),
@@ -418,14 +485,14 @@ impl VirtioBlockDevice {
                 virtqueues[0].desc[header_desc].next = data_desc as u16;
                 
                 virtqueues[0].desc[data_desc].addr = (data_ptr as *mut u8 as usize) as u64;
-                virtqueues[0].desc[data_desc].len = req.buffer.len() as u32;
+                virtqueues[0].desc[data_desc].len = data_len as u32;
                 
                 match req.request_type {
                     BlockIORequestType::Read => {
                         DescriptorFlag::Next.set(&mut virtqueues[0].desc[data_desc].flags);
                         DescriptorFlag::Write.set(&mut virtqueues[0].desc[data_desc].flags);
                     },
-                    BlockIORequestType::Write => {
+                    BlockIORequestType::Write | BlockIORequestType::Discard => {
                         DescriptorFlag::Next.set(&mut virtqueues[0].desc[data_desc].flags);
                     }
                 }
@@ -494,6 +561,7 @@ impl VirtioBlockDevice {
                         VIRTIO_BLK_S_OK => {
                             // For read requests, copy data back to the buffer
                             if let BlockIORequestType::Read = requests[req_idx].request_type {
+                                crate::mem::kasan::check_read(data_ptr as *const u8 as usize, virtqueues[0].desc[data_desc].len as usize);
                                 unsafe {
                                     requests[req_idx].buffer.clear();
                                     requests[req_idx].buffer.extend_from_slice(core::slice::from_raw_parts(
@@ -572,6 +640,20 @@ impl Device for VirtioBlockDevice {
     fn into_block_device(self: alloc::sync::Arc<Self>) -> Option<alloc::sync::Arc<dyn crate::device::block::BlockDevice>> {
         Some(self)
     }
+
+    /// Virtio has no low-power state; the device is quiet once no request
+    /// is waiting, as each batch completes before `process_requests` returns
+    fn suspend(&self) -> Result<(), &'static str> {
+        if self.request_queue.lock().is_empty() {
+            Ok(())
+        } else {
+            Err("Requests pending")
+        }
+    }
+
+    fn runtime_pm(&self) -> Option<&RuntimePm> {
+        Some(&self.pm)
+    }
 }
 
 impl VirtioDevice for VirtioBlockDevice {
@@ -649,6 +731,8 @@ impl BlockDevice for VirtioBlockDevice {
     
     fn process_requests(&self) -> Vec<BlockIOResult> {
         crate::profile_scope!("virtio_blk::process_requests");
+        // Taken before the queue is emptied, which would let it suspend
+        let active = power::runtime_ref(self);
         let mut queue = self.request_queue.lock();
         
         // Collect all requests first
@@ -661,15 +745,38 @@ impl BlockDevice for VirtioBlockDevice {
         if requests.is_empty() {
             return Vec::new();
         }
-        
+
+        // The device is gone; do not touch the virtqueue again
+        if self.removed.load(Ordering::Acquire) {
+            return fail_removed(requests);
+        }
+        if let Err(error) = active {
+            return requests.into_iter().map(|request| BlockIOResult { request, result: Err(error) }).collect();
+        }
+
         // Process all requests in true batch
+        let started = crate::time::current_time();
         let batch_results = self.process_requests_batch(&mut requests);
         
         // Convert results back to the expected format
-        requests.into_iter()
+        let results: Vec<BlockIOResult> = requests.into_iter()
             .zip(batch_results.into_iter())
             .map(|(request, result)| BlockIOResult { request, result })
-            .collect()
+            .collect();
+        self.stats.record_results(&results, started);
+        results
+    }
+
+    fn supports_discard(&self) -> bool {
+        *self.features.read() & (1 << VIRTIO_BLK_F_DISCARD) != 0 && *self.max_discard_sectors.read() != 0
+    }
+
+    fn io_stats(&self) -> Option<&BlockIoStats> {
+        Some(&self.stats)
+    }
+
+    fn detach(&self) {
+        self.removed.store(true, Ordering::Release);
     }
 }
 
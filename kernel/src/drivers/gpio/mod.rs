@@ -0,0 +1,6 @@
+//! GPIO controller drivers module.
+//! 
+//! This module contains drivers for the GPIO controllers of boards, which
+//! are exposed through the GPIO layer in `crate::device::gpio`.
+
+pub mod sifive;
@@ -0,0 +1,236 @@
+//! # SiFive GPIO Driver
+//!
+//! The GPIO controller of the SiFive SoCs (FE310, FU540, FU740) and of the
+//! boards built on them has up to 32 lines, each with its own interrupt.
+//! The driver registers it as a [`GpioDevice`], `/dev/gpiochipN`.
+//!
+//! Every register holds a bit per line. Inputs are always enabled, so
+//! that an output reads back the level of its pin. Edges are detected by
+//! the rise and fall interrupts of the line, whose pending bits are
+//! cleared by writing them back. The only pull resistor is a pull-up.
+
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec, vec::Vec};
+use core::ptr::{read_volatile, write_volatile};
+use spin::{Mutex, Once};
+
+use crate::device::events::InterruptCapableDevice;
+use crate::device::gpio::{self, Bias, GpioChip, GpioDevice};
+use crate::device::manager::{DeviceManager, DriverPriority};
+use crate::device::platform::{resource::PlatformDeviceResourceType, PlatformDeviceDriver, PlatformDeviceInfo};
+use crate::driver_initcall;
+use crate::interrupt::{InterruptId, InterruptManager, InterruptResult, PRIORITY_NORMAL};
+use crate::sched::affinity::CpuMask;
+
+const GPIO_INPUT_VAL: usize = 0x00;
+const GPIO_INPUT_EN: usize = 0x04;
+const GPIO_OUTPUT_EN: usize = 0x08;
+const GPIO_OUTPUT_VAL: usize = 0x0c;
+const GPIO_PUE: usize = 0x10;
+const GPIO_RISE_IE: usize = 0x18;
+const GPIO_RISE_IP: usize = 0x1c;
+const GPIO_FALL_IE: usize = 0x20;
+const GPIO_FALL_IP: usize = 0x24;
+const GPIO_HIGH_IE: usize = 0x28;
+const GPIO_LOW_IE: usize = 0x30;
+const GPIO_IOF_EN: usize = 0x38;
+const GPIO_OUTPUT_XOR: usize = 0x40;
+
+const GPIO_MAX_LINES: usize = 32;
+
+pub struct SifiveGpio {
+    base_addr: usize,
+    lines: usize,
+    label: String,
+    /// Keeps read-modify-write of the registers whole
+    lock: Mutex<()>,
+    /// The GPIO device reporting the edges
+    device: Once<Arc<GpioDevice>>,
+}
+
+impl SifiveGpio {
+    pub fn new(base_addr: usize, lines: usize) -> Self {
+        let device = Self {
+            base_addr,
+            lines: lines.min(GPIO_MAX_LINES),
+            label: format!("sifive-gpio@{:x}", base_addr),
+            lock: Mutex::new(()),
+            device: Once::new(),
+        };
+        // No alternate functions, interrupts or inverted outputs
+        for offset in [GPIO_IOF_EN, GPIO_RISE_IE, GPIO_FALL_IE, GPIO_HIGH_IE, GPIO_LOW_IE, GPIO_OUTPUT_XOR] {
+            device.write_reg(offset, 0);
+        }
+        device.write_reg(GPIO_RISE_IP, u32::MAX);
+        device.write_reg(GPIO_FALL_IP, u32::MAX);
+        device.write_reg(GPIO_INPUT_EN, device.mask());
+        device
+    }
+
+    /// Bits of the lines of the controller
+    fn mask(&self) -> u32 {
+        (((1u64 << self.lines) - 1) & u32::MAX as u64) as u32
+    }
+
+    fn read_reg(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base_addr + offset) as *const u32) }
+    }
+
+    fn write_reg(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.base_addr + offset) as *mut u32, value) }
+    }
+
+    fn update_bit(&self, offset: usize, line: usize, value: bool) {
+        let _guard = self.lock.lock();
+        let reg = self.read_reg(offset);
+        self.write_reg(offset, if value { reg | 1 << line } else { reg & !(1 << line) });
+    }
+
+    /// Report the pending edges of `line` and clear them
+    fn handle_line(&self, line: usize) {
+        let bit = 1 << line;
+        let rising = self.read_reg(GPIO_RISE_IP) & bit != 0;
+        let falling = self.read_reg(GPIO_FALL_IP) & bit != 0;
+        self.write_reg(GPIO_RISE_IP, if rising { bit } else { 0 });
+        self.write_reg(GPIO_FALL_IP, if falling { bit } else { 0 });
+        let Some(device) = self.device.get() else {
+            return;
+        };
+        // Both edges at once were a pulse; report them in the order they
+        // restore the level
+        match (rising, falling, self.get(line)) {
+            (true, true, true) => {
+                device.report_edge(line, false);
+                device.report_edge(line, true);
+            }
+            (true, true, false) => {
+                device.report_edge(line, true);
+                device.report_edge(line, false);
+            }
+            (true, false, _) => device.report_edge(line, true),
+            (false, true, _) => device.report_edge(line, false),
+            (false, false, _) => {}
+        }
+    }
+}
+
+impl GpioChip for SifiveGpio {
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    fn line_count(&self) -> usize {
+        self.lines
+    }
+
+    fn is_output(&self, offset: usize) -> bool {
+        self.read_reg(GPIO_OUTPUT_EN) & 1 << offset != 0
+    }
+
+    fn direction_input(&self, offset: usize) -> Result<(), &'static str> {
+        self.update_bit(GPIO_OUTPUT_EN, offset, false);
+        Ok(())
+    }
+
+    fn direction_output(&self, offset: usize, value: bool) -> Result<(), &'static str> {
+        // Latch the value first so the pin never glitches
+        self.update_bit(GPIO_OUTPUT_VAL, offset, value);
+        self.update_bit(GPIO_OUTPUT_EN, offset, true);
+        Ok(())
+    }
+
+    fn get(&self, offset: usize) -> bool {
+        self.read_reg(GPIO_INPUT_VAL) & 1 << offset != 0
+    }
+
+    fn set(&self, offset: usize, value: bool) {
+        self.update_bit(GPIO_OUTPUT_VAL, offset, value);
+    }
+
+    fn set_bias(&self, offset: usize, bias: Bias) -> Result<(), &'static str> {
+        match bias {
+            Bias::PullUp => self.update_bit(GPIO_PUE, offset, true),
+            Bias::Disabled => self.update_bit(GPIO_PUE, offset, false),
+            Bias::PullDown => return Err("Pull-down not supported"),
+        }
+        Ok(())
+    }
+
+    fn set_edges(&self, offset: usize, rising: bool, falling: bool) -> Result<(), &'static str> {
+        // Drop the edges seen while they were not wanted
+        self.write_reg(GPIO_RISE_IP, 1 << offset);
+        self.write_reg(GPIO_FALL_IP, 1 << offset);
+        self.update_bit(GPIO_RISE_IE, offset, rising);
+        self.update_bit(GPIO_FALL_IE, offset, falling);
+        Ok(())
+    }
+}
+
+/// The interrupt of one line
+struct SifiveGpioIrq {
+    gpio: Arc<SifiveGpio>,
+    line: usize,
+    interrupt_id: InterruptId,
+}
+
+impl InterruptCapableDevice for SifiveGpioIrq {
+    fn handle_interrupt(&self) -> InterruptResult<()> {
+        self.gpio.handle_line(self.line);
+        Ok(())
+    }
+
+    fn interrupt_id(&self) -> Option<InterruptId> {
+        Some(self.interrupt_id)
+    }
+}
+
+fn probe_fn(device: &PlatformDeviceInfo) -> Result<(), &'static str> {
+    let resources = device.get_resources();
+    let base_addr = resources
+        .iter()
+        .find(|r| r.res_type == PlatformDeviceResourceType::MEM)
+        .ok_or("Memory resource not found")?
+        .start;
+    // One interrupt per line, in line order
+    let irqs: Vec<InterruptId> = resources
+        .iter()
+        .filter(|r| r.res_type == PlatformDeviceResourceType::IRQ)
+        .map(|r| r.start as InterruptId)
+        .take(GPIO_MAX_LINES)
+        .collect();
+    let lines = if irqs.is_empty() { GPIO_MAX_LINES } else { irqs.len() };
+
+    let gpio = Arc::new(SifiveGpio::new(base_addr, lines));
+    let gpio_device = GpioDevice::new(gpio.clone());
+    gpio.device.call_once(|| gpio_device.clone());
+
+    for (line, &interrupt_id) in irqs.iter().enumerate() {
+        let irq = Arc::new(SifiveGpioIrq { gpio: gpio.clone(), line, interrupt_id });
+        let result = InterruptManager::with_manager(|mgr| {
+            mgr.register_interrupt_device(interrupt_id, irq)?;
+            mgr.request_external_interrupt(interrupt_id, PRIORITY_NORMAL, CpuMask::all()).map(|_| ())
+        });
+        if result.is_err() {
+            crate::early_println!("[GPIO] No edge events on line {}: interrupt {} unavailable", line, interrupt_id);
+        }
+    }
+
+    let number = gpio::register(gpio_device);
+    crate::early_println!("[GPIO] Detected SiFive GPIO at {:#x} with {} lines as gpiochip{}", base_addr, lines, number);
+    Ok(())
+}
+
+fn remove_fn(_device: &PlatformDeviceInfo) -> Result<(), &'static str> {
+    Ok(())
+}
+
+fn register_driver() {
+    let driver = PlatformDeviceDriver::new(
+        "sifive-gpio",
+        probe_fn,
+        remove_fn,
+        vec!["sifive,gpio0"],
+    );
+    DeviceManager::get_mut_manager().register_driver(Box::new(driver), DriverPriority::Standard)
+}
+
+driver_initcall!(register_driver);
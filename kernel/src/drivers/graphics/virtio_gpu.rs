@@ -5,16 +5,28 @@
 //!
 //! The driver supports basic framebuffer operations and display management
 //! according to the VirtIO GPU specification.
+//!
+//! Flushes transfer only the rectangles asked for to the host resource. A
+//! shadow copy of the framebuffer holds what the host was last sent, and
+//! every 16 ms the area differing from it is sent, so that programs that
+//! never flush are shown too.
+//!
+//! The framebuffer holds two screens, one above the other. The display can
+//! be panned to either; the new offset is applied at the next of those
+//! 16 ms updates, which serves as the vertical blank, after the screen
+//! panned to has been sent.
 
 use alloc::{boxed::Box, sync::Arc};
 use spin::{Mutex, RwLock};
 
 use crate::{
-    device::{graphics::{FramebufferConfig, GraphicsDevice, PixelFormat}, Device, DeviceType},
+    device::{graphics::{FramebufferConfig, FramebufferRect, GraphicsDevice, PixelFormat}, Device, DeviceType},
     drivers::virtio::{device::VirtioDevice, queue::{DescriptorFlag, VirtQueue}},
-    mem::page::{allocate_raw_pages, Page}, object::capability::{ControlOps, MemoryMappingOps}, timer::{add_timer, get_tick, ms_to_ticks, SoftwareTimer, TimerHandler},
+    interrupt::with_interrupts_disabled, mem::page::{allocate_raw_pages, Page}, object::capability::{ControlOps, MemoryMappingOps},
+    sync::waker::Waker, task::mytask, timer::{add_timer, get_tick, ms_to_ticks, SoftwareTimer, TimerHandler},
 };
-use core::{ptr, sync::atomic::fence};
+use core::{ptr, sync::atomic::{fence, AtomicBool, Ordering}};
+use crate::device::power::RuntimePm;
 
 // VirtIO GPU Constants
 const VIRTIO_GPU_F_VIRGL: u32 = 0;
@@ -44,9 +56,18 @@ const VIRTIO_GPU_FORMAT_X8B8G8R8_UNORM: u32 = 68;
 const VIRTIO_GPU_FORMAT_A8B8G8R8_UNORM: u32 = 121;
 const VIRTIO_GPU_FORMAT_R8G8B8X8_UNORM: u32 = 134;
 
+/// Bytes of a pixel of the framebuffer, B8G8R8X8
+const BYTES_PER_PIXEL: u32 = 4;
+
+/// Screens the framebuffer holds, for programs flipping between them
+const VIRTUAL_SCREENS: u32 = 2;
+
 // Maximum number of scanouts
 const VIRTIO_GPU_MAX_SCANOUTS: usize = 16;
 
+/// Time without framebuffer access before the periodic flush stops
+const AUTOSUSPEND_MS: u64 = 5000;
+
 /// VirtIO GPU command header
 #[repr(C)]
 struct VirtioGpuCtrlHdr {
@@ -144,6 +165,8 @@ pub struct VirtioGpuDeviceCore {
     display_info: RwLock<Option<VirtioGpuRespDisplayInfo>>,
     framebuffer_addr: RwLock<Option<usize>>,
     shadow_framebuffer_addr: RwLock<Option<usize>>,
+    /// Row of the framebuffer shown at the top of the display
+    display_offset: RwLock<u32>,
     boxed_framebuffer: RwLock<Option<Box<[Page]>>>, // Boxed framebuffer for easier management
     boxed_shadow_framebuffer: RwLock<Option<Box<[Page]>>>, // Boxed shadow framebuffer
     resource_id: Mutex<u32>,
@@ -169,6 +192,7 @@ impl VirtioGpuDeviceCore {
             display_info: RwLock::new(None),
             framebuffer_addr: RwLock::new(None),
             shadow_framebuffer_addr: RwLock::new(None),
+            display_offset: RwLock::new(0),
             boxed_framebuffer: RwLock::new(None),
             boxed_shadow_framebuffer: RwLock::new(None), 
             resource_id: Mutex::new(1),
@@ -387,8 +411,9 @@ impl VirtioGpuDeviceCore {
         }
         let width = primary_display.r.width;
         let height = primary_display.r.height;
-        let resource_id = self.create_2d_resource(width, height, VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM)?;
-        let fb_size = (width * height * 4) as usize;
+        let virtual_height = height * VIRTUAL_SCREENS;
+        let resource_id = self.create_2d_resource(width, virtual_height, VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM)?;
+        let fb_size = (width * virtual_height * 4) as usize;
         let fb_pages = (fb_size + 4095) / 4096;
         let fb_pages_ptr = allocate_raw_pages(fb_pages);
         if fb_pages_ptr.is_null() {
@@ -451,7 +476,7 @@ impl VirtioGpuDeviceCore {
             primary_display.r.width,
             primary_display.r.height,
             PixelFormat::BGRA8888, // VirtIO GPU typically uses BGRA format
-        ))
+        ).with_virtual_height(primary_display.r.height * VIRTUAL_SCREENS))
     }
 
     fn get_framebuffer_address(&self) -> Result<usize, &'static str> {
@@ -460,41 +485,78 @@ impl VirtioGpuDeviceCore {
     }
 
     fn flush_framebuffer(&self, x: u32, y: u32, width: u32, height: u32) -> Result<(), &'static str> {
+        self.flush_rects(&[FramebufferRect::new(x, y, width, height)])
+    }
+
+    /// Size of the primary display in pixels
+    fn display_size(&self) -> Result<(u32, u32), &'static str> {
         let display_info = self.display_info.read();
-        let _display_info = display_info.as_ref().ok_or("Device not initialized")?;
-        
-        // Get the resource ID from our tracked resources
-        let resource_id = {
-            let resources = self.resources.lock();
-            if let Some((_, _)) = resources.get(&1) {
-                1 // Use primary framebuffer resource
-            } else {
-                return Err("No framebuffer resource found");
-            }
-        };
+        let display_info = display_info.as_ref().ok_or("Device not initialized")?;
+        let rect = &display_info.pmodes[0].r;
+        Ok((rect.width, rect.height))
+    }
 
-        // crate::early_println!("[Virtio GPU] Flushing framebuffer region: ({},{}) {}x{} for resource {}", 
-        //     x, y, width, height, resource_id);
+    /// Size of the framebuffer in pixels, all its screens
+    fn virtual_size(&self) -> Result<(u32, u32), &'static str> {
+        let (width, height) = self.display_size()?;
+        Ok((width, height * VIRTUAL_SCREENS))
+    }
 
-        // Transfer to host - copies data from guest memory to host
-        // This is necessary because the host GPU driver needs to know
-        // that the framebuffer contents have changed
-        let transfer_cmd = VirtioGpuTransferToHost2d {
+    /// The resource the display shows
+    fn primary_resource(&self) -> Result<u32, &'static str> {
+        let resources = self.resources.lock();
+        if let Some((_, _)) = resources.get(&1) {
+            Ok(1) // Use primary framebuffer resource
+        } else {
+            Err("No framebuffer resource found")
+        }
+    }
+
+    /// Show the framebuffer from row `y` on
+    fn set_display_offset(&self, y: u32) -> Result<(), &'static str> {
+        let (width, height) = self.display_size()?;
+        let resource_id = self.primary_resource()?;
+        let scanout_cmd = VirtioGpuSetScanout {
             hdr: VirtioGpuCtrlHdr {
-                hdr_type: VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D,
+                hdr_type: VIRTIO_GPU_CMD_SET_SCANOUT,
                 flags: 0,
                 fence_id: 0,
                 ctx_id: 0,
                 padding: 0,
             },
-            r: VirtioGpuRect { x, y, width, height },
-            offset: 0,
+            r: VirtioGpuRect { x: 0, y, width, height },
+            scanout_id: 0,
             resource_id,
-            padding: 0,
         };
+        self.send_control_command(&scanout_cmd)?;
+        *self.display_offset.write() = y;
+        self.resource_flush(resource_id, &FramebufferRect::new(0, y, width, height))
+    }
 
-        self.send_control_command(&transfer_cmd)?;
+    /// Send the rectangles `rects` of the framebuffer to the host and show
+    /// them
+    ///
+    /// Each rectangle is transferred on its own, then the display is
+    /// updated once for the area holding them all.
+    fn flush_rects(&self, rects: &[FramebufferRect]) -> Result<(), &'static str> {
+        let (width, height) = self.virtual_size()?;
+        let resource_id = self.primary_resource()?;
+
+        let mut bounds = FramebufferRect::default();
+        for rect in rects.iter().map(|rect| rect.clip(width, height)).filter(|rect| !rect.is_empty()) {
+            // The shadow is updated first: what the host gets is never older
+            self.update_shadow(&rect, width);
+            self.transfer_to_host(resource_id, &rect, width)?;
+            bounds = bounds.union(&rect);
+        }
+        if bounds.is_empty() {
+            return Ok(());
+        }
+        self.resource_flush(resource_id, &bounds)
+    }
 
+    /// Update the rectangle `rect` of the resource on the display
+    fn resource_flush(&self, resource_id: u32, rect: &FramebufferRect) -> Result<(), &'static str> {
         // Flush resource - tells the display to update the specified region
         // This actually triggers the display update
         let flush_cmd = VirtioGpuResourceFlush {
@@ -505,14 +567,80 @@ impl VirtioGpuDeviceCore {
                 ctx_id: 0,
                 padding: 0,
             },
-            r: VirtioGpuRect { x, y, width, height },
+            r: VirtioGpuRect { x: rect.x, y: rect.y, width: rect.width, height: rect.height },
             resource_id,
             padding: 0,
         };
 
-        self.send_control_command(&flush_cmd)?;
-        // crate::early_println!("[Virtio GPU] Framebuffer flush completed");
-        Ok(())
+        self.send_control_command(&flush_cmd)
+    }
+
+    /// Copy the rectangle `rect` of the framebuffer, `width` pixels wide,
+    /// to the host resource
+    fn transfer_to_host(&self, resource_id: u32, rect: &FramebufferRect, width: u32) -> Result<(), &'static str> {
+        // The offset is that of the first pixel of the rectangle in the
+        // backing memory; the host steps through it by the resource stride
+        let transfer_cmd = VirtioGpuTransferToHost2d {
+            hdr: VirtioGpuCtrlHdr {
+                hdr_type: VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D,
+                flags: 0,
+                fence_id: 0,
+                ctx_id: 0,
+                padding: 0,
+            },
+            r: VirtioGpuRect { x: rect.x, y: rect.y, width: rect.width, height: rect.height },
+            offset: (rect.y as u64 * width as u64 + rect.x as u64) * BYTES_PER_PIXEL as u64,
+            resource_id,
+            padding: 0,
+        };
+
+        self.send_control_command(&transfer_cmd)
+    }
+
+    /// Addresses of the framebuffer and its shadow, and their size in
+    /// pixels
+    fn framebuffers(&self) -> Option<(usize, usize, usize)> {
+        let fb_addr = (*self.framebuffer_addr.read())?;
+        let shadow_addr = (*self.shadow_framebuffer_addr.read())?;
+        let (width, height) = self.virtual_size().ok()?;
+        Some((fb_addr, shadow_addr, (width * height) as usize))
+    }
+
+    /// Record the rectangle `rect` of the framebuffer, `width` pixels wide,
+    /// as sent to the host
+    fn update_shadow(&self, rect: &FramebufferRect, width: u32) {
+        let Some((fb_addr, shadow_addr, pixels)) = self.framebuffers() else {
+            return;
+        };
+        let framebuffer = unsafe { core::slice::from_raw_parts(fb_addr as *const u32, pixels) };
+        let shadow = unsafe { core::slice::from_raw_parts_mut(shadow_addr as *mut u32, pixels) };
+        for y in rect.y..rect.y + rect.height {
+            let start = (y * width + rect.x) as usize;
+            let end = start + rect.width as usize;
+            shadow[start..end].copy_from_slice(&framebuffer[start..end]);
+        }
+    }
+
+    /// The area of the framebuffer changed since it was last sent to the
+    /// host, found by comparing it with the shadow
+    fn find_damage(&self) -> Option<FramebufferRect> {
+        let (width, _) = self.display_size().ok()?;
+        let (fb_addr, shadow_addr, pixels) = self.framebuffers()?;
+        let framebuffer = unsafe { core::slice::from_raw_parts(fb_addr as *const u32, pixels) };
+        let shadow = unsafe { core::slice::from_raw_parts(shadow_addr as *const u32, pixels) };
+        let mut damage = FramebufferRect::default();
+        let rows = framebuffer.chunks_exact(width as usize).zip(shadow.chunks_exact(width as usize));
+        for (y, (row, shadow_row)) in rows.enumerate() {
+            if row == shadow_row {
+                continue;
+            }
+            let changed = |x: &usize| row[*x] != shadow_row[*x];
+            let first = (0..row.len()).find(changed).unwrap_or(0);
+            let last = (0..row.len()).rev().find(changed).unwrap_or(first);
+            let rect = FramebufferRect::new(first as u32, y as u32, (last - first + 1) as u32, 1);
+            damage = damage.union(&rect);
+        }
+        (!damage.is_empty()).then_some(damage)
     }
 }
 
@@ -567,9 +695,21 @@ impl VirtioDevice for VirtioGpuDeviceCore {
     }
 }
 
+/// A display offset waiting for the next vertical blank
+struct PendingPan {
+    /// Row of the framebuffer to show from
+    offset: Mutex<Option<u32>>,
+    /// Tasks waiting for the offset to be applied
+    waker: Waker,
+}
+
 pub struct VirtioGpuDevice {
     core: Arc<Mutex<VirtioGpuDeviceCore>>,
     handler: Option<Arc<dyn TimerHandler>>,
+    /// Runtime suspended: the framebuffer is not flushed to the host
+    idle: Arc<AtomicBool>,
+    pan: Arc<PendingPan>,
+    pm: RuntimePm,
 }
 
 impl VirtioGpuDevice {
@@ -586,6 +726,12 @@ impl VirtioGpuDevice {
         Self {
             core: Arc::new(Mutex::new(VirtioGpuDeviceCore::new(base_addr))),
             handler: None,
+            idle: Arc::new(AtomicBool::new(false)),
+            pan: Arc::new(PendingPan {
+                offset: Mutex::new(None),
+                waker: Waker::new_interruptible("virtio_gpu_vblank"),
+            }),
+            pm: RuntimePm::new(AUTOSUSPEND_MS),
         }
     }
 }
@@ -610,6 +756,21 @@ impl Device for VirtioGpuDevice {
     fn as_graphics_device(&self) -> Option<&dyn GraphicsDevice> {
         Some(self)
     }
+
+    /// Stop flushing the framebuffer; the host keeps showing the last frame
+    fn suspend(&self) -> Result<(), &'static str> {
+        self.idle.store(true, Ordering::Release);
+        Ok(())
+    }
+
+    fn resume(&self) -> Result<(), &'static str> {
+        self.idle.store(false, Ordering::Release);
+        Ok(())
+    }
+
+    fn runtime_pm(&self) -> Option<&RuntimePm> {
+        Some(&self.pm)
+    }
 }
 
 impl ControlOps for VirtioGpuDevice {
@@ -655,6 +816,34 @@ impl GraphicsDevice for VirtioGpuDevice {
         self.core.lock().flush_framebuffer(x, y, width, height)
     }
 
+    fn flush_regions(&self, rects: &[FramebufferRect]) -> Result<(), &'static str> {
+        self.core.lock().flush_rects(rects)
+    }
+
+    /// Pan at the next update of the display, waiting for it
+    fn pan_display(&self, x: u32, y: u32) -> Result<(), &'static str> {
+        let config = self.get_framebuffer_config()?;
+        if x != 0 || y > config.virtual_height - config.height {
+            return Err("Display offset out of range");
+        }
+        let Some(task) = mytask() else {
+            // No task to wait: pan right away
+            return self.core.lock().set_display_offset(y);
+        };
+        with_interrupts_disabled(|| *self.pan.offset.lock() = Some(y));
+        let panned = || with_interrupts_disabled(|| self.pan.offset.lock().is_none());
+        while !self.pan.waker.wait_unless(task.get_id(), task.get_trapframe(), panned) {
+            if task.signals.has_pending() {
+                return Err("Interrupted");
+            }
+        }
+        Ok(())
+    }
+
+    fn display_offset(&self) -> (u32, u32) {
+        (0, *self.core.lock().display_offset.read())
+    }
+
     fn init_graphics(&mut self) -> Result<(), &'static str> {
         {
             let core = self.core.lock();
@@ -675,6 +864,8 @@ impl GraphicsDevice for VirtioGpuDevice {
 
         let handler: Arc<dyn TimerHandler> = Arc::new(FramebufferUpdateHandler {
             device: self.core.clone(),
+            idle: self.idle.clone(),
+            pan: self.pan.clone(),
         });
 
         add_timer(get_tick() + ms_to_ticks(16), &handler, 0);
@@ -688,51 +879,37 @@ impl GraphicsDevice for VirtioGpuDevice {
 
 struct FramebufferUpdateHandler {
     device: Arc<Mutex<VirtioGpuDeviceCore>>,
+    idle: Arc<AtomicBool>,
+    pan: Arc<PendingPan>,
 }
 
 impl FramebufferUpdateHandler {
+    /// Send what changed in the framebuffer since the last flush
     fn compare_and_flush(&self) {
-        let (_fb_addr, _shadow_addr, width, height, fb_size) = {
-            let core = self.device.lock();
-            let fb_addr = match *core.framebuffer_addr.read() {
-                Some(addr) => addr,
-                None => return,
-            };
-            let shadow_addr = match *core.shadow_framebuffer_addr.read() {
-                Some(addr) => addr,
-                None => return,
-            };
-            let display_info_guard = core.display_info.read();
-            let display_info = match display_info_guard.as_ref() {
-                Some(info) => info,
-                None => return,
-            };
-            let width = display_info.pmodes[0].r.width;
-            let height = display_info.pmodes[0].r.height;
-            let fb_size = (width * height * 4) as usize;
-            (fb_addr, shadow_addr, width, height, fb_size)
+        if self.idle.load(Ordering::Acquire) {
+            return;
+        }
+        let core = self.device.lock();
+        if let Some(damage) = core.find_damage() {
+            let _ = core.flush_rects(&[damage]);
+        }
+    }
+
+    /// Apply the display offset asked for, now the screen panned to is on
+    /// the host
+    fn apply_pan(&self) {
+        let Some(offset) = self.pan.offset.lock().take() else {
+            return;
         };
-        let _ = self.device.lock().flush_framebuffer(0, 0, width, height);
-
-        // // Determine if the framebuffer has changed
-        // let fb_ptr = fb_addr as *const u8;
-        // let shadow_ptr = shadow_addr as *const u8;
-        // let fb_slice = unsafe { core::slice::from_raw_parts(fb_ptr, fb_size) };
-        // let shadow_slice = unsafe { core::slice::from_raw_parts(shadow_ptr, fb_size) };
-        // let changed = fb_slice != shadow_slice;
-
-        // if changed {
-        //     let _ = self.device.lock().flush_framebuffer(0, 0, width, height);
-        //     unsafe {
-        //         ptr::copy_nonoverlapping(fb_addr as *const u8, shadow_addr as *mut u8, fb_size);
-        //     }
-        // }
+        let _ = self.device.lock().set_display_offset(offset);
+        self.pan.waker.wake_all();
     }
 }
 
 impl TimerHandler for FramebufferUpdateHandler {
     fn on_timer_expired(self: Arc<Self>, context: usize) {
         self.compare_and_flush();
+        self.apply_pan();
         let handler = self as Arc<dyn TimerHandler>;
         add_timer(get_tick() + ms_to_ticks(16), &handler, context);
     }
@@ -1200,4 +1377,27 @@ mod tests {
         
         crate::early_println!("[Test] Resource management test completed");
     }
-}
\ No newline at end of file
+
+    #[test_case]
+    fn test_virtio_gpu_display_panning() {
+        let mut device = VirtioGpuDevice::new(0x10002000);
+        device.init_graphics().unwrap();
+
+        let config = device.get_framebuffer_config().unwrap();
+        assert_eq!(config.virtual_height, config.height * VIRTUAL_SCREENS);
+        assert_eq!(config.size(), (config.stride * config.height * VIRTUAL_SCREENS) as usize);
+        assert_eq!(device.display_offset(), (0, 0));
+
+        // The second screen can be drawn and flushed like the first
+        device.flush_framebuffer(0, config.height, config.width, config.height).unwrap();
+
+        // Past the last screen or sideways is refused
+        assert!(device.pan_display(0, config.virtual_height - config.height + 1).is_err());
+        assert!(device.pan_display(1, 0).is_err());
+
+        device.core.lock().set_display_offset(config.height).unwrap();
+        assert_eq!(device.display_offset(), (0, config.height));
+        device.core.lock().set_display_offset(0).unwrap();
+        assert_eq!(device.display_offset(), (0, 0));
+    }
+}
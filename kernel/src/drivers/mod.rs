@@ -7,4 +7,9 @@ pub mod uart;
 pub mod block;
 pub mod pic;
 pub mod graphics;
-pub mod network;
\ No newline at end of file
+pub mod network;
+pub mod rng;
+pub mod rtc;
+pub mod usb;
+pub mod gpio;
+pub mod sound;
\ No newline at end of file
@@ -6,6 +6,7 @@
 use crate::{device::{manager::{DeviceManager, DriverPriority}, platform::{resource::PlatformDeviceResourceType, PlatformDeviceDriver, PlatformDeviceInfo}}, driver_initcall, early_initcall, interrupt::{
     controllers::{ExternalInterruptController, LocalInterruptType}, CpuId, InterruptError, InterruptId, InterruptManager, InterruptResult, Priority
 }};
+use crate::environment::NUM_OF_CPUS;
 use alloc::{boxed::Box, vec};
 use core::ptr::{read_volatile, write_volatile};
 
@@ -118,11 +119,9 @@ impl ExternalInterruptController for Plic {
         for cpu_id in 0..self.max_cpus {
             // Disable all interrupts for this CPU's context
             for word in 0..=(self.max_interrupts / 32) {
-                let interrupt_id_base = word * 32;
-                if interrupt_id_base > 0 { // Interrupt ID 0 is not used
-                    let addr = self.enable_addr(cpu_id, interrupt_id_base);
-                    unsafe { write_volatile(addr as *mut u32, 0); }
-                }
+                // The first word also holds the unused interrupt ID 0
+                let addr = self.enable_addr(cpu_id, word * 32);
+                unsafe { write_volatile(addr as *mut u32, 0); }
             }
             // Set threshold to 0 (allow all priorities)
             let _ = self.set_threshold(cpu_id, 0);
@@ -291,7 +290,8 @@ fn probe_fn(device: &PlatformDeviceInfo) -> Result<(), &'static str> {
     
     let base_addr = mem_res.start as usize;
 
-    let controller = Box::new(Plic::new(base_addr, 1023, 4)); // Example values for max interrupts and CPUs
+    // One supervisor context per hart the kernel runs on
+    let controller = Box::new(Plic::new(base_addr, 1023, NUM_OF_CPUS as CpuId));
 
     match InterruptManager::global().lock().register_external_controller(controller) {
         Ok(_) => {
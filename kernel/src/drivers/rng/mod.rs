@@ -0,0 +1,6 @@
+//! Entropy source drivers module.
+//! 
+//! This module contains hardware random number generator drivers for the
+//! Scarlet kernel.
+
+pub mod virtio_rng;
\ No newline at end of file
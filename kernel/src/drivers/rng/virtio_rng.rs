@@ -0,0 +1,151 @@
+//! # VirtIO Entropy Device Driver
+//!
+//! This module provides a driver for VirtIO entropy devices (virtio-rng),
+//! which hand out random bytes gathered by the host. The device is an
+//! [`EntropySource`] of the kernel random number generator, which draws a
+//! seed from it when it is probed and again at every reseed.
+//!
+//! ## Implementation Details
+//!
+//! The device has a single request queue. Each request is one
+//! device-writable buffer, which the device fills with as many random bytes
+//! as it has at hand; the used ring tells how many. Requests are polled
+//! like those of the block driver.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use spin::Mutex;
+
+use crate::defer;
+use crate::drivers::virtio::features::{VIRTIO_RING_F_EVENT_IDX, VIRTIO_RING_F_INDIRECT_DESC};
+use crate::drivers::virtio::{device::VirtioDevice, queue::{DescriptorFlag, VirtQueue}};
+use crate::random::EntropySource;
+
+/// Most bytes asked for in one request
+const MAX_REQUEST_LEN: usize = 64;
+
+pub struct VirtioRngDevice {
+    base_addr: usize,
+    virtqueues: Mutex<[VirtQueue<'static>; 1]>,
+}
+
+impl VirtioRngDevice {
+    /// Create and initialize the device at `base_addr`
+    pub fn new(base_addr: usize) -> Result<Self, &'static str> {
+        let mut device = Self {
+            base_addr,
+            // One request is in flight at a time
+            virtqueues: Mutex::new([VirtQueue::new(8)]),
+        };
+        device.init()?;
+        Ok(device)
+    }
+
+    /// Ask the device for up to `buffer.len()` random bytes
+    ///
+    /// Fails with "Device busy" if another request is in flight, which
+    /// keeps a draw made in an interrupt from waiting on it.
+    pub fn request(&self, buffer: &mut [u8]) -> Result<usize, &'static str> {
+        let len = buffer.len().min(MAX_REQUEST_LEN);
+        if len == 0 {
+            return Ok(0);
+        }
+        let mut virtqueues = self.virtqueues.try_lock().ok_or("Device busy")?;
+
+        let data_ptr = Box::into_raw(vec![0u8; len].into_boxed_slice()) as *mut u8;
+        defer! {
+            unsafe {
+                drop(Box::from_raw(core::ptr::slice_from_raw_parts_mut(data_ptr, len)));
+            }
+        }
+
+        let desc = virtqueues[0].alloc_desc().ok_or("Failed to allocate descriptor")?;
+        virtqueues[0].desc[desc].addr = data_ptr as u64;
+        virtqueues[0].desc[desc].len = len as u32;
+        virtqueues[0].desc[desc].flags = DescriptorFlag::Write as u16;
+
+        if let Err(e) = virtqueues[0].push(desc) {
+            virtqueues[0].free_desc(desc);
+            return Err(e);
+        }
+
+        self.notify(0);
+
+        // Wait for the response (polling)
+        while virtqueues[0].is_busy() {}
+
+        let result = match virtqueues[0].pop_with_len() {
+            Some((idx, written)) if idx == desc => {
+                let written = written.min(len);
+                crate::mem::kasan::check_read(data_ptr as usize, written);
+                buffer[..written].copy_from_slice(unsafe { core::slice::from_raw_parts(data_ptr, written) });
+                Ok(written)
+            }
+            Some(_) => Err("Invalid descriptor index"),
+            None => Err("No response from device"),
+        };
+        virtqueues[0].free_desc(desc);
+        result
+    }
+}
+
+impl EntropySource for VirtioRngDevice {
+    fn name(&self) -> &'static str {
+        "virtio-rng"
+    }
+
+    fn read_entropy(&self, buffer: &mut [u8]) -> usize {
+        self.request(buffer).unwrap_or(0)
+    }
+}
+
+impl VirtioDevice for VirtioRngDevice {
+    fn get_base_addr(&self) -> usize {
+        self.base_addr
+    }
+
+    fn get_virtqueue_count(&self) -> usize {
+        1
+    }
+
+    fn get_virtqueue_size(&self, queue_idx: usize) -> usize {
+        if queue_idx >= 1 {
+            panic!("Invalid queue index for VirtIO entropy device: {}", queue_idx);
+        }
+
+        let virtqueues = self.virtqueues.lock();
+        virtqueues[queue_idx].get_queue_size()
+    }
+
+    fn get_supported_features(&self, device_features: u32) -> u32 {
+        // The device has no features of its own
+        device_features & !(1 << VIRTIO_RING_F_EVENT_IDX | 1 << VIRTIO_RING_F_INDIRECT_DESC)
+    }
+
+    fn get_queue_desc_addr(&self, queue_idx: usize) -> Option<u64> {
+        if queue_idx >= 1 {
+            return None;
+        }
+
+        let virtqueues = self.virtqueues.lock();
+        Some(virtqueues[queue_idx].get_raw_ptr() as u64)
+    }
+
+    fn get_queue_driver_addr(&self, queue_idx: usize) -> Option<u64> {
+        if queue_idx >= 1 {
+            return None;
+        }
+
+        let virtqueues = self.virtqueues.lock();
+        Some(virtqueues[queue_idx].avail.flags as *const _ as u64)
+    }
+
+    fn get_queue_device_addr(&self, queue_idx: usize) -> Option<u64> {
+        if queue_idx >= 1 {
+            return None;
+        }
+
+        let virtqueues = self.virtqueues.lock();
+        Some(virtqueues[queue_idx].used.flags as *const _ as u64)
+    }
+}
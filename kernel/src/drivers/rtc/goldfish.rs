@@ -0,0 +1,91 @@
+//! # Goldfish RTC Driver
+//!
+//! The goldfish real-time clock of the QEMU `virt` machine counts
+//! nanoseconds since the Unix epoch. The driver registers it as the
+//! [`RealTimeClock`] of the kernel, which takes the boot epoch from it and
+//! writes it back when the wall-clock time is set.
+//!
+//! Reading the low half of the time latches the high half, and writing the
+//! low half sets the time to it together with the high half written
+//! before, so both are always accessed low first to read and high first to
+//! write. The alarm is not used.
+
+use alloc::{boxed::Box, sync::Arc, vec};
+use core::ptr::{read_volatile, write_volatile};
+use spin::Mutex;
+
+use crate::device::manager::{DeviceManager, DriverPriority};
+use crate::device::platform::{resource::PlatformDeviceResourceType, PlatformDeviceDriver, PlatformDeviceInfo};
+use crate::driver_initcall;
+use crate::time::RealTimeClock;
+
+const RTC_TIME_LOW: usize = 0x00;
+const RTC_TIME_HIGH: usize = 0x04;
+
+pub struct GoldfishRtc {
+    base_addr: usize,
+    /// Keeps the two halves of an access together
+    lock: Mutex<()>,
+}
+
+impl GoldfishRtc {
+    pub fn new(base_addr: usize) -> Self {
+        Self { base_addr, lock: Mutex::new(()) }
+    }
+
+    fn read_reg(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base_addr + offset) as *const u32) }
+    }
+
+    fn write_reg(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.base_addr + offset) as *mut u32, value) }
+    }
+}
+
+impl RealTimeClock for GoldfishRtc {
+    fn name(&self) -> &'static str {
+        "goldfish-rtc"
+    }
+
+    fn read_ns(&self) -> u64 {
+        let _guard = self.lock.lock();
+        let low = self.read_reg(RTC_TIME_LOW) as u64;
+        let high = self.read_reg(RTC_TIME_HIGH) as u64;
+        high << 32 | low
+    }
+
+    fn write_ns(&self, ns: u64) -> Result<(), &'static str> {
+        let _guard = self.lock.lock();
+        self.write_reg(RTC_TIME_HIGH, (ns >> 32) as u32);
+        self.write_reg(RTC_TIME_LOW, ns as u32);
+        Ok(())
+    }
+}
+
+fn probe_fn(device: &PlatformDeviceInfo) -> Result<(), &'static str> {
+    let base_addr = device.get_resources()
+        .iter()
+        .find(|r| r.res_type == PlatformDeviceResourceType::MEM)
+        .ok_or("Memory resource not found")?
+        .start;
+    crate::early_println!("[RTC] Detected Goldfish RTC at {:#x}", base_addr);
+    crate::time::register_rtc(Arc::new(GoldfishRtc::new(base_addr)));
+    Ok(())
+}
+
+fn remove_fn(_device: &PlatformDeviceInfo) -> Result<(), &'static str> {
+    Ok(())
+}
+
+fn register_driver() {
+    let driver = PlatformDeviceDriver::new(
+        "goldfish-rtc",
+        probe_fn,
+        remove_fn,
+        vec!["google,goldfish-rtc"],
+    );
+    // Core: the wall clock is known before filesystems are mounted
+    DeviceManager::get_mut_manager().register_driver(Box::new(driver), DriverPriority::Core)
+}
+
+driver_initcall!(register_driver);
@@ -0,0 +1,6 @@
+//! Real-time clock drivers module.
+//! 
+//! This module contains drivers for the clocks that keep the wall-clock
+//! time while the machine is off.
+
+pub mod goldfish;
\ No newline at end of file
@@ -0,0 +1,6 @@
+//! Sound drivers module.
+//! 
+//! This module contains drivers for sound hardware, whose streams are
+//! exposed through the PCM core in `crate::device::sound`.
+
+pub mod virtio_snd;
@@ -0,0 +1,449 @@
+//! # VirtIO Sound Device Driver
+//!
+//! This module provides a driver for VirtIO sound devices (virtio-snd).
+//! Every output stream of the device is a [`PcmDevice`] of the PCM core,
+//! `/dev/snd/pcmCxDyp`, with the card numbered for the device and the
+//! device numbered for the stream among the outputs.
+//!
+//! ## Implementation Details
+//!
+//! The device has four queues: control, event, transmit and receive.
+//! Control requests (setting the parameters of a stream, preparing,
+//! starting, stopping and releasing it) are polled like those of the block
+//! driver. Each period the PCM core queues is one message on the transmit
+//! queue: the stream id, the frames and a status the device writes back.
+//! The device gives the message back once it has played the frames, and
+//! the interrupt handler reports it to the stream, which paces playback.
+//!
+//! Messages still queued when a stream is released are given back by the
+//! device later; a generation count per stream has the handler drop them.
+//! The event queue is not read: jack and period events add nothing to the
+//! completions of the transmit queue. Capture streams are not exposed.
+
+use alloc::{boxed::Box, collections::BTreeMap, format, string::String, sync::{Arc, Weak}, vec, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::{Mutex, Once, RwLock};
+
+use crate::device::events::InterruptCapableDevice;
+use crate::device::sound::{self, PcmDevice, PcmDriver, PcmFormat, PcmHardware, PcmParams};
+use crate::drivers::virtio::features::{VIRTIO_RING_F_EVENT_IDX, VIRTIO_RING_F_INDIRECT_DESC};
+use crate::drivers::virtio::{device::{Register, VirtioDevice}, queue::{DescriptorFlag, VirtQueue}};
+use crate::interrupt::{InterruptId, InterruptManager, InterruptResult, PRIORITY_HIGH};
+use crate::sched::affinity::CpuMask;
+
+/// Queues
+const CONTROL_QUEUE: usize = 0;
+const EVENT_QUEUE: usize = 1;
+const TX_QUEUE: usize = 2;
+const RX_QUEUE: usize = 3;
+const QUEUE_COUNT: usize = RX_QUEUE + 1;
+
+const CONTROL_QUEUE_SIZE: usize = 8;
+const EVENT_QUEUE_SIZE: usize = 8;
+/// Three descriptors per message
+const TX_QUEUE_SIZE: usize = 128;
+const RX_QUEUE_SIZE: usize = 8;
+
+/// Offsets in the device configuration
+const CONFIG_STREAMS: usize = 4;
+
+/// Request codes
+const VIRTIO_SND_R_PCM_INFO: u32 = 0x0100;
+const VIRTIO_SND_R_PCM_SET_PARAMS: u32 = 0x0101;
+const VIRTIO_SND_R_PCM_PREPARE: u32 = 0x0102;
+const VIRTIO_SND_R_PCM_RELEASE: u32 = 0x0103;
+const VIRTIO_SND_R_PCM_START: u32 = 0x0104;
+const VIRTIO_SND_R_PCM_STOP: u32 = 0x0105;
+
+/// Status codes
+const VIRTIO_SND_S_OK: u32 = 0x8000;
+const VIRTIO_SND_S_BAD_MSG: u32 = 0x8001;
+const VIRTIO_SND_S_NOT_SUPP: u32 = 0x8002;
+
+const VIRTIO_SND_D_OUTPUT: u8 = 0;
+
+/// Bytes of `struct virtio_snd_pcm_info`
+const PCM_INFO_SIZE: usize = 32;
+
+/// Formats, by the bit of the device
+const FORMATS: [(u32, PcmFormat); 7] = [
+    (3, PcmFormat::S8),
+    (4, PcmFormat::U8),
+    (5, PcmFormat::S16Le),
+    (6, PcmFormat::U16Le),
+    (15, PcmFormat::S24Le),
+    (17, PcmFormat::S32Le),
+    (19, PcmFormat::FloatLe),
+];
+
+/// Frame rates, by the bit of the device
+const RATES: [u32; 14] = [
+    5512, 8000, 11025, 16000, 22050, 32000, 44100, 48000, 64000, 88200, 96000, 176400, 192000, 384000,
+];
+
+/// Limits of the buffer the PCM core allocates
+const BUFFER_BYTES_MAX: usize = 128 * 1024;
+const PERIOD_BYTES_MIN: usize = 64;
+const PERIOD_BYTES_MAX: usize = 32 * 1024;
+const PERIODS_MIN: usize = 2;
+const PERIODS_MAX: usize = 16;
+
+/// `struct virtio_snd_pcm_info`
+#[derive(Debug, Clone, Copy)]
+struct PcmInfo {
+    formats: u64,
+    rates: u64,
+    direction: u8,
+    channels_min: u8,
+    channels_max: u8,
+}
+
+impl PcmInfo {
+    fn parse(bytes: &[u8]) -> Self {
+        Self {
+            formats: u64::from_le_bytes(bytes[8..16].try_into().unwrap()),
+            rates: u64::from_le_bytes(bytes[16..24].try_into().unwrap()),
+            direction: bytes[24],
+            channels_min: bytes[25],
+            channels_max: bytes[26],
+        }
+    }
+}
+
+/// A message on the transmit queue: the header and the status around the
+/// frames
+struct TxMessage {
+    /// Stream id, then the status and latency the device writes
+    words: Box<[u32; 3]>,
+    generation: u64,
+}
+
+/// What the interrupt handler needs of a stream
+struct StreamSlot {
+    pcm: Once<Weak<PcmDevice>>,
+    /// Bumped when the stream is released
+    generation: AtomicU64,
+}
+
+pub struct VirtioSoundDevice {
+    base_addr: usize,
+    control_queue: Mutex<VirtQueue<'static>>,
+    event_queue: Mutex<VirtQueue<'static>>,
+    tx_queue: Mutex<VirtQueue<'static>>,
+    rx_queue: Mutex<VirtQueue<'static>>,
+    /// Messages on the transmit queue, by head descriptor
+    tx_messages: Mutex<BTreeMap<usize, TxMessage>>,
+    slots: Vec<StreamSlot>,
+    interrupt_id: RwLock<Option<InterruptId>>,
+}
+
+impl VirtioSoundDevice {
+    /// Create and initialize the device at `base_addr`
+    pub fn new(base_addr: usize) -> Result<Self, &'static str> {
+        let mut device = Self {
+            base_addr,
+            control_queue: Mutex::new(VirtQueue::new(CONTROL_QUEUE_SIZE)),
+            event_queue: Mutex::new(VirtQueue::new(EVENT_QUEUE_SIZE)),
+            tx_queue: Mutex::new(VirtQueue::new(TX_QUEUE_SIZE)),
+            rx_queue: Mutex::new(VirtQueue::new(RX_QUEUE_SIZE)),
+            tx_messages: Mutex::new(BTreeMap::new()),
+            slots: Vec::new(),
+            interrupt_id: RwLock::new(None),
+        };
+        device.init()?;
+        let streams = device.read_config::<u32>(CONFIG_STREAMS) as usize;
+        device.slots = (0..streams)
+            .map(|_| StreamSlot { pcm: Once::new(), generation: AtomicU64::new(0) })
+            .collect();
+        Ok(device)
+    }
+
+    /// Send a control request and fill `response` with the reply
+    fn request(&self, request: &[u8], response: &mut [u8]) -> Result<(), &'static str> {
+        let mut queue = self.control_queue.lock();
+        let request_buffer: Box<[u8]> = request.into();
+        let response_buffer: Box<[u8]> = vec![0u8; response.len()].into_boxed_slice();
+
+        let head = queue.alloc_desc().ok_or("Failed to allocate descriptor")?;
+        let Some(tail) = queue.alloc_desc() else {
+            queue.free_desc(head);
+            return Err("Failed to allocate descriptor");
+        };
+        queue.desc[head].addr = request_buffer.as_ptr() as u64;
+        queue.desc[head].len = request_buffer.len() as u32;
+        queue.desc[head].flags = DescriptorFlag::Next as u16;
+        queue.desc[head].next = tail as u16;
+        queue.desc[tail].addr = response_buffer.as_ptr() as u64;
+        queue.desc[tail].len = response_buffer.len() as u32;
+        queue.desc[tail].flags = DescriptorFlag::Write as u16;
+
+        if let Err(e) = queue.push(head) {
+            queue.free_desc(tail);
+            queue.free_desc(head);
+            return Err(e);
+        }
+        self.notify(CONTROL_QUEUE);
+
+        // Wait for the response (polling)
+        while queue.is_busy() {}
+        let used = queue.pop();
+        queue.free_desc(tail);
+        queue.free_desc(head);
+        if used != Some(head) {
+            return Err("Invalid descriptor index");
+        }
+
+        crate::mem::kasan::check_read(response_buffer.as_ptr() as usize, response_buffer.len());
+        response.copy_from_slice(&response_buffer);
+        match u32::from_le_bytes(response[0..4].try_into().unwrap()) {
+            VIRTIO_SND_S_OK => Ok(()),
+            VIRTIO_SND_S_BAD_MSG => Err("Invalid sound device request"),
+            VIRTIO_SND_S_NOT_SUPP => Err("Operation not supported by the sound device"),
+            _ => Err("Sound device I/O error"),
+        }
+    }
+
+    /// Send the request `code` about stream `stream_id`
+    fn stream_request(&self, code: u32, stream_id: u32) -> Result<(), &'static str> {
+        let mut request = [0u8; 8];
+        request[0..4].copy_from_slice(&code.to_le_bytes());
+        request[4..8].copy_from_slice(&stream_id.to_le_bytes());
+        self.request(&request, &mut [0u8; 4])
+    }
+
+    fn pcm_info(&self) -> Result<Vec<PcmInfo>, &'static str> {
+        if self.slots.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut request = [0u8; 16];
+        request[0..4].copy_from_slice(&VIRTIO_SND_R_PCM_INFO.to_le_bytes());
+        request[8..12].copy_from_slice(&(self.slots.len() as u32).to_le_bytes());
+        request[12..16].copy_from_slice(&(PCM_INFO_SIZE as u32).to_le_bytes());
+        let mut response = vec![0u8; 4 + self.slots.len() * PCM_INFO_SIZE];
+        self.request(&request, &mut response)?;
+        Ok(response[4..].chunks_exact(PCM_INFO_SIZE).map(PcmInfo::parse).collect())
+    }
+
+    /// Queue `data` for playback on stream `stream_id`
+    fn transmit(&self, stream_id: u32, data: &[u8]) -> Result<(), &'static str> {
+        let mut queue = self.tx_queue.lock();
+        let words = Box::new([stream_id, 0, 0]);
+        let head = queue.alloc_desc_chain(3).ok_or("Failed to allocate descriptor")?;
+        let body = queue.desc[head].next as usize;
+        let status = queue.desc[body].next as usize;
+        queue.desc[head].addr = words.as_ptr() as u64;
+        queue.desc[head].len = 4;
+        queue.desc[body].addr = data.as_ptr() as u64;
+        queue.desc[body].len = data.len() as u32;
+        queue.desc[status].addr = words[1..].as_ptr() as u64;
+        queue.desc[status].len = 8;
+        queue.desc[status].flags = DescriptorFlag::Write as u16;
+
+        let generation = self.slots[stream_id as usize].generation.load(Ordering::Acquire);
+        self.tx_messages.lock().insert(head, TxMessage { words, generation });
+        if let Err(e) = queue.push(head) {
+            self.tx_messages.lock().remove(&head);
+            queue.free_desc_chain(head);
+            return Err(e);
+        }
+        self.notify(TX_QUEUE);
+        Ok(())
+    }
+
+    /// Take interrupts on `interrupt_id`
+    pub fn enable_interrupts(&self, interrupt_id: InterruptId) -> Result<(), &'static str> {
+        self.interrupt_id.write().replace(interrupt_id);
+        InterruptManager::with_manager(|mgr| mgr.request_external_interrupt(interrupt_id, PRIORITY_HIGH, CpuMask::all()))
+            .map(|_| ())
+            .map_err(|_| "Failed to enable interrupt")
+    }
+
+    fn with_queue<R>(&self, queue_idx: usize, f: impl FnOnce(&VirtQueue<'static>) -> R) -> R {
+        match queue_idx {
+            CONTROL_QUEUE => f(&self.control_queue.lock()),
+            EVENT_QUEUE => f(&self.event_queue.lock()),
+            TX_QUEUE => f(&self.tx_queue.lock()),
+            RX_QUEUE => f(&self.rx_queue.lock()),
+            _ => panic!("Invalid queue index for VirtIO sound device: {}", queue_idx),
+        }
+    }
+}
+
+impl InterruptCapableDevice for VirtioSoundDevice {
+    fn handle_interrupt(&self) -> InterruptResult<()> {
+        let status = self.read32_register(Register::InterruptStatus);
+        if status == 0 {
+            return Ok(());
+        }
+        self.write32_register(Register::InterruptAck, status & 0x03);
+
+        // Report played periods once the queue is unlocked, as the streams
+        // queue the next ones
+        let mut elapsed = Vec::new();
+        {
+            let mut queue = self.tx_queue.lock();
+            let mut messages = self.tx_messages.lock();
+            while let Some(head) = queue.pop() {
+                queue.free_desc_chain(head);
+                let Some(message) = messages.remove(&head) else {
+                    continue;
+                };
+                let slot = &self.slots[message.words[0] as usize];
+                if message.generation == slot.generation.load(Ordering::Acquire) {
+                    if let Some(pcm) = slot.pcm.get().and_then(Weak::upgrade) {
+                        elapsed.push(pcm);
+                    }
+                }
+            }
+        }
+        for pcm in elapsed {
+            pcm.period_elapsed();
+        }
+        Ok(())
+    }
+
+    fn interrupt_id(&self) -> Option<InterruptId> {
+        *self.interrupt_id.read()
+    }
+}
+
+impl VirtioDevice for VirtioSoundDevice {
+    fn get_base_addr(&self) -> usize {
+        self.base_addr
+    }
+
+    fn get_virtqueue_count(&self) -> usize {
+        QUEUE_COUNT
+    }
+
+    fn get_virtqueue_size(&self, queue_idx: usize) -> usize {
+        self.with_queue(queue_idx, |queue| queue.get_queue_size())
+    }
+
+    fn get_supported_features(&self, device_features: u32) -> u32 {
+        // Channel maps are not used
+        device_features & !(1 << VIRTIO_RING_F_EVENT_IDX | 1 << VIRTIO_RING_F_INDIRECT_DESC)
+    }
+
+    fn get_queue_desc_addr(&self, queue_idx: usize) -> Option<u64> {
+        if queue_idx >= QUEUE_COUNT {
+            return None;
+        }
+
+        Some(self.with_queue(queue_idx, |queue| queue.get_raw_ptr() as u64))
+    }
+
+    fn get_queue_driver_addr(&self, queue_idx: usize) -> Option<u64> {
+        if queue_idx >= QUEUE_COUNT {
+            return None;
+        }
+
+        Some(self.with_queue(queue_idx, |queue| queue.avail.flags as *const _ as u64))
+    }
+
+    fn get_queue_device_addr(&self, queue_idx: usize) -> Option<u64> {
+        if queue_idx >= QUEUE_COUNT {
+            return None;
+        }
+
+        Some(self.with_queue(queue_idx, |queue| queue.used.flags as *const _ as u64))
+    }
+}
+
+/// An output stream of a device
+pub struct VirtioSoundStream {
+    device: Arc<VirtioSoundDevice>,
+    stream_id: u32,
+    info: PcmInfo,
+    name: String,
+}
+
+impl PcmDriver for VirtioSoundStream {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn hardware(&self) -> PcmHardware {
+        PcmHardware {
+            formats: FORMATS
+                .iter()
+                .filter(|(bit, _)| self.info.formats & 1 << bit != 0)
+                .map(|&(_, format)| format)
+                .collect(),
+            rates: RATES
+                .iter()
+                .enumerate()
+                .filter(|(bit, _)| self.info.rates & 1 << bit != 0)
+                .map(|(_, &rate)| rate)
+                .collect(),
+            channels_min: self.info.channels_min as u32,
+            channels_max: self.info.channels_max as u32,
+            buffer_bytes_max: BUFFER_BYTES_MAX,
+            period_bytes_min: PERIOD_BYTES_MIN,
+            period_bytes_max: PERIOD_BYTES_MAX,
+            periods_min: PERIODS_MIN,
+            periods_max: PERIODS_MAX,
+        }
+    }
+
+    fn prepare(&self, params: &PcmParams) -> Result<(), &'static str> {
+        let format = FORMATS.iter().find(|(_, format)| *format == params.format).ok_or("Unsupported format")?.0;
+        let rate = RATES.iter().position(|&rate| rate == params.rate).ok_or("Unsupported rate")?;
+        let mut request = [0u8; 24];
+        request[0..4].copy_from_slice(&VIRTIO_SND_R_PCM_SET_PARAMS.to_le_bytes());
+        request[4..8].copy_from_slice(&self.stream_id.to_le_bytes());
+        request[8..12].copy_from_slice(&(params.buffer_bytes() as u32).to_le_bytes());
+        request[12..16].copy_from_slice(&(params.period_bytes() as u32).to_le_bytes());
+        request[20] = params.channels as u8;
+        request[21] = format as u8;
+        request[22] = rate as u8;
+        self.device.request(&request, &mut [0u8; 4])?;
+        self.device.stream_request(VIRTIO_SND_R_PCM_PREPARE, self.stream_id)
+    }
+
+    fn start(&self) -> Result<(), &'static str> {
+        self.device.stream_request(VIRTIO_SND_R_PCM_START, self.stream_id)
+    }
+
+    fn stop(&self) -> Result<(), &'static str> {
+        self.device.stream_request(VIRTIO_SND_R_PCM_STOP, self.stream_id)
+    }
+
+    fn release(&self) -> Result<(), &'static str> {
+        // The messages the device gives back from now on are stale
+        self.device.slots[self.stream_id as usize].generation.fetch_add(1, Ordering::AcqRel);
+        self.device.stream_request(VIRTIO_SND_R_PCM_RELEASE, self.stream_id)
+    }
+
+    fn queue(&self, data: &[u8]) -> Result<(), &'static str> {
+        self.device.transmit(self.stream_id, data)
+    }
+}
+
+/// Bring up the device at `base_addr` and register its output streams
+pub fn probe(base_addr: usize, interrupt_id: InterruptId) -> Result<(), &'static str> {
+    let device = Arc::new(VirtioSoundDevice::new(base_addr)?);
+    InterruptManager::with_manager(|mgr| mgr.register_interrupt_device(interrupt_id, device.clone()))
+        .map_err(|_| "Failed to register interrupt device")?;
+    device.enable_interrupts(interrupt_id)?;
+
+    let card = sound::next_card();
+    let outputs = device
+        .pcm_info()?
+        .into_iter()
+        .enumerate()
+        .filter(|(_, info)| info.direction == VIRTIO_SND_D_OUTPUT);
+    for (number, (stream_id, info)) in outputs.enumerate() {
+        let stream = Arc::new(VirtioSoundStream {
+            device: device.clone(),
+            stream_id: stream_id as u32,
+            info,
+            name: format!("virtio-snd stream {}", stream_id),
+        });
+        let pcm = PcmDevice::new(stream, card, number);
+        device.slots[stream_id].pcm.call_once(|| Arc::downgrade(&pcm));
+        crate::early_println!("[Virtio] Sound stream {} registered as /dev/{}", stream_id, pcm.node_name());
+        sound::register(pcm);
+    }
+    Ok(())
+}
@@ -1,16 +1,28 @@
 // UART driver for QEMU virt machine
+//
+// With an interrupt line the UART is interrupt driven: the interrupt handler
+// drains the receive FIFO into the receive buffer and hands the bytes to the
+// listeners (the TTY), and refills the transmit FIFO from the transmit
+// buffer, asking for the THRE interrupt as long as there is more to send.
+// Writers only queue bytes, so echoing typed characters never waits on the
+// line. Without one the UART is polled, as during early boot.
+//
+// Software flow control is driven by the TTY: output stops on a received
+// XOFF and resumes on XON (`stop_tx`/`start_tx`), and `send_flow_char` sends
+// XOFF or XON ahead of the queued output to throttle the other end.
 
 use core::{fmt, any::Any, ptr::{read_volatile, write_volatile}};
 use core::fmt::Write;
-use alloc::{boxed::Box, collections::VecDeque, sync::Arc};
+use alloc::{boxed::Box, collections::VecDeque, sync::Arc, vec::Vec};
 use spin::{Mutex, RwLock};
 
 use crate::{
+    interrupt::with_interrupts_disabled, sync::waker::Waker, task::mytask,
     device::{
         char::CharDevice, events::{DeviceEventEmitter, DeviceEventListener, EventCapableDevice, InputEvent, InterruptCapableDevice}, manager::{DeviceManager, DriverPriority}, platform::{
             resource::PlatformDeviceResourceType, PlatformDeviceDriver, PlatformDeviceInfo
         }, Device, DeviceInfo, DeviceType
-    }, driver_initcall, drivers::uart, interrupt::{InterruptId, InterruptManager}, traits::serial::Serial, object::capability::{ControlOps, MemoryMappingOps}
+    }, driver_initcall, drivers::uart, interrupt::{InterruptId, InterruptManager, PRIORITY_LOW}, sched::affinity::CpuMask, traits::serial::Serial, object::capability::{ControlOps, MemoryMappingOps}
 };
 
 pub struct Uart {
@@ -18,9 +30,30 @@ pub struct Uart {
     base: usize,
     interrupt_id: RwLock<Option<InterruptId>>,
     rx_buffer: Mutex<VecDeque<u8>>,
+    tx: Mutex<Transmitter>,
+    /// Writers waiting for room in the transmit buffer
+    tx_waker: Waker,
     event_emitter: Mutex<DeviceEventEmitter>,
 }
 
+/// Output side of an interrupt-driven UART
+struct Transmitter {
+    buffer: VecDeque<u8>,
+    /// Stopped by a received XOFF
+    stopped: bool,
+    /// XON or XOFF to send before anything else, even while stopped
+    flow_char: Option<u8>,
+    /// Suspended for system sleep; output is kept until resume
+    suspended: bool,
+}
+
+/// Bytes the receive buffer holds; more are dropped until it is read
+pub const RX_BUFFER_SIZE: usize = 4096;
+/// Bytes the transmit buffer holds
+pub const TX_BUFFER_SIZE: usize = 4096;
+/// Bytes the transmit FIFO takes once it is empty
+const TX_FIFO_SIZE: usize = 16;
+
 pub const RHR_OFFSET: usize = 0x00;
 pub const THR_OFFSET: usize = 0x00;
 pub const IER_OFFSET: usize = 0x01;  // Interrupt Enable Register
@@ -55,6 +88,8 @@ impl Uart {
             base,
             interrupt_id: RwLock::new(None),
             rx_buffer: Mutex::new(VecDeque::new()),
+            tx: Mutex::new(Transmitter { buffer: VecDeque::new(), stopped: false, flow_char: None, suspended: false }),
+            tx_waker: Waker::new_interruptible("uart_tx"),
             event_emitter: Mutex::new(DeviceEventEmitter::new()),
         }
     }
@@ -87,7 +122,7 @@ impl Uart {
 
         // Register interrupt with interrupt manager
         InterruptManager::with_manager(|mgr| {
-            mgr.enable_external_interrupt(interrupt_id, 0) // Enable for CPU 0
+            mgr.request_external_interrupt(interrupt_id, PRIORITY_LOW, CpuMask::all())
         }).map_err(|_| "Failed to enable interrupt")?;
         
         Ok(())
@@ -108,19 +143,137 @@ impl Uart {
         self.reg_write(THR_OFFSET, c);
     }
 
-    fn read_byte_internal(&self) -> u8 {
+    fn read_byte_internal(&self) -> Option<u8> {
         if self.reg_read(LSR_OFFSET) & LSR_DR == 0 {
-            return 0;
+            return None;
         }
-        self.reg_read(RHR_OFFSET)
+        Some(self.reg_read(RHR_OFFSET))
+    }
+
+    fn is_interrupt_driven(&self) -> bool {
+        self.interrupt_id.read().is_some()
     }
 
     fn can_read(&self) -> bool {
-        self.reg_read(LSR_OFFSET) & LSR_DR != 0
+        !self.rx_buffer.lock().is_empty() || self.reg_read(LSR_OFFSET) & LSR_DR != 0
     }
 
     fn can_write(&self) -> bool {
-        self.reg_read(LSR_OFFSET) & LSR_THRE != 0
+        if !self.is_interrupt_driven() {
+            return self.reg_read(LSR_OFFSET) & LSR_THRE != 0;
+        }
+        with_interrupts_disabled(|| {
+            let tx = self.tx.lock();
+            !tx.stopped && tx.buffer.len() < TX_BUFFER_SIZE
+        })
+    }
+
+    /// Refill the transmit FIFO if it is empty, and ask for the THRE
+    /// interrupt while there is more to send
+    ///
+    /// Called with interrupts disabled.
+    fn transmit(&self, tx: &mut Transmitter) {
+        if tx.suspended {
+            return;
+        }
+        let mut sent = false;
+        if self.reg_read(LSR_OFFSET) & LSR_THRE != 0 {
+            let mut room = TX_FIFO_SIZE;
+            if let Some(c) = tx.flow_char.take() {
+                self.reg_write(THR_OFFSET, c);
+                room -= 1;
+            }
+            while room > 0 && !tx.stopped {
+                let Some(c) = tx.buffer.pop_front() else { break };
+                self.reg_write(THR_OFFSET, c);
+                room -= 1;
+                sent = true;
+            }
+        }
+        let pending = tx.flow_char.is_some() || (!tx.stopped && !tx.buffer.is_empty());
+        self.reg_write(IER_OFFSET, if pending { IER_RDA | IER_THRE } else { IER_RDA });
+        if sent {
+            self.tx_waker.wake_all();
+        }
+    }
+
+    /// Queue `byte` for the interrupt handler to send
+    ///
+    /// With the buffer full the byte is pushed out by polling, as there may
+    /// be no task to block (this is also called from the interrupt handler,
+    /// for echo); while output is stopped it is dropped instead.
+    fn queue_byte(&self, byte: u8) -> Result<(), &'static str> {
+        with_interrupts_disabled(|| {
+            let mut tx = self.tx.lock();
+            while tx.buffer.len() >= TX_BUFFER_SIZE {
+                if tx.stopped {
+                    return Err("UART output stopped");
+                }
+                if tx.suspended {
+                    return Err("UART suspended");
+                }
+                while self.reg_read(LSR_OFFSET) & LSR_THRE == 0 {}
+                self.transmit(&mut tx);
+            }
+            tx.buffer.push_back(byte);
+            self.transmit(&mut tx);
+            Ok(())
+        })
+    }
+
+    /// Block the calling task until output is not stopped and `len` bytes
+    /// fit in the transmit buffer
+    ///
+    /// Returns `false` if a signal interrupted the wait.
+    pub fn wait_writable(&self, len: usize) -> bool {
+        if !self.is_interrupt_driven() {
+            return true;
+        }
+        let Some(task) = mytask() else { return true };
+        let writable = || {
+            with_interrupts_disabled(|| {
+                let tx = self.tx.lock();
+                !tx.stopped && tx.buffer.len() + len <= TX_BUFFER_SIZE
+            })
+        };
+        while !self.tx_waker.wait_unless(task.get_id(), task.get_trapframe(), writable) {
+            if task.signals.has_pending() {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Stop output, as on a received XOFF
+    pub fn stop_tx(&self) {
+        with_interrupts_disabled(|| self.tx.lock().stopped = true);
+    }
+
+    /// Resume output, as on a received XON
+    pub fn start_tx(&self) {
+        if !self.is_interrupt_driven() {
+            return;
+        }
+        with_interrupts_disabled(|| {
+            let mut tx = self.tx.lock();
+            tx.stopped = false;
+            self.transmit(&mut tx);
+        });
+        self.tx_waker.wake_all();
+    }
+
+    /// Send the flow control character `c` (XON or XOFF) ahead of the
+    /// queued output, even while output is stopped
+    pub fn send_flow_char(&self, c: u8) {
+        if !self.is_interrupt_driven() {
+            self.write_byte_internal(c);
+            return;
+        }
+        with_interrupts_disabled(|| {
+            let mut tx = self.tx.lock();
+            tx.flow_char = Some(c);
+            self.transmit(&mut tx);
+        });
     }
 }
 
@@ -136,8 +289,7 @@ impl Serial for Uart {
     /// A `fmt::Result` indicating success or failure.
     /// 
     fn put(&self, c: char) -> fmt::Result {
-        self.write_byte_internal(c as u8); // Block until ready
-        Ok(())
+        self.write_byte(c as u8).map_err(|_| fmt::Error)
     }
 
     /// Reads a character from the UART. (non-blocking)
@@ -147,13 +299,7 @@ impl Serial for Uart {
     /// Otherwise, falls back to polling mode.
     /// 
     fn get(&self) -> Option<char> {
-        let mut buffer = self.rx_buffer.lock();
-            // Try to read from interrupt buffer
-        if let Some(byte) = buffer.pop_front() {
-            return Some(byte as char);
-        }
-
-        None
+        self.read_byte().map(|byte| byte as char)
     }
 
     /// Get a mutable reference to Any for downcasting
@@ -201,16 +347,43 @@ impl Device for Uart {
     fn as_char_device(&self) -> Option<&dyn CharDevice> {
         Some(self)
     }
+
+    /// Mask the UART's interrupts; queued output waits for resume
+    fn suspend(&self) -> Result<(), &'static str> {
+        if self.is_interrupt_driven() {
+            with_interrupts_disabled(|| {
+                self.tx.lock().suspended = true;
+                self.reg_write(IER_OFFSET, 0);
+            });
+        }
+        Ok(())
+    }
+
+    fn resume(&self) -> Result<(), &'static str> {
+        if self.is_interrupt_driven() {
+            with_interrupts_disabled(|| {
+                let mut tx = self.tx.lock();
+                tx.suspended = false;
+                // Unmasks receive interrupts and sends what was queued
+                self.transmit(&mut tx);
+            });
+        }
+        Ok(())
+    }
 }
 
 impl CharDevice for Uart {
     fn read_byte(&self) -> Option<u8> {
-        let mut buffer = self.rx_buffer.lock();
-        // Try to read from interrupt buffer
-        buffer.pop_front()
+        if self.is_interrupt_driven() {
+            return with_interrupts_disabled(|| self.rx_buffer.lock().pop_front());
+        }
+        self.read_byte_internal()
     }
 
     fn write_byte(&self, byte: u8) -> Result<(), &'static str> {
+        if self.is_interrupt_driven() {
+            return self.queue_byte(byte);
+        }
         self.write_byte_internal(byte); // Block until ready
         Ok(())
     }
@@ -260,25 +433,32 @@ impl EventCapableDevice for Uart {
 
 impl InterruptCapableDevice for Uart {
     fn handle_interrupt(&self) -> crate::interrupt::InterruptResult<()> {
-        // let inner = self.inner.lock();
-        // Check interrupt identification register
-        let iir = self.reg_read(IIR_OFFSET);
-        
-        if iir & IIR_PENDING == 0 {
-            let c = self.read_byte_internal();
-            if c != 0 {
-                // Emit received character event
-                self.emit_event(
-                    &InputEvent {
-                        data: c as u8,
-                    }
-                );
+        // Reading IIR acknowledges THRE
+        if self.reg_read(IIR_OFFSET) & IIR_PENDING != 0 {
+            return Ok(());
+        }
+
+        // Take the whole receive FIFO at once, so that a fast paste does
+        // not overrun it while the bytes are processed
+        let received = {
+            let mut buffer = self.rx_buffer.lock();
+            while let Some(c) = self.read_byte_internal() {
+                if buffer.len() < RX_BUFFER_SIZE {
+                    buffer.push_back(c);
+                }
+            }
+            if self.event_emitter.lock().has_listeners() {
+                buffer.drain(..).collect()
             } else {
-                // No data available, return Ok
-                return Ok(());
+                Vec::new()
             }
+        };
+
+        self.transmit(&mut self.tx.lock());
+
+        for c in received {
+            self.emit_event(&InputEvent { data: c });
         }
-        
         Ok(())
     }
     
@@ -0,0 +1,6 @@
+//! USB host controller drivers module.
+//! 
+//! This module contains drivers for the host controllers that the USB core
+//! enumerates devices through.
+
+pub mod xhci;
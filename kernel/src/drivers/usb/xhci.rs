@@ -0,0 +1,1002 @@
+//! # xHCI Host Controller Driver
+//!
+//! This module drives USB host controllers that follow the eXtensible Host
+//! Controller Interface (xHCI 1.x) and sit on the platform bus, as the
+//! `generic-xhci` node of a device tree describes them. The controller is
+//! registered with the USB core, which enumerates the devices on its root
+//! hub ports.
+//!
+//! ## Implementation Details
+//!
+//! The controller shares with the driver:
+//!
+//! - the device context base address array, with the output device
+//!   context of every slot and, in entry 0, the scratchpad buffers the
+//!   controller may ask for.
+//! - the command ring, on which the driver places commands (enable slot,
+//!   address device, configure endpoint, ...) and rings doorbell 0.
+//! - one event ring, on which the controller reports completed commands and
+//!   transfers, for interrupter 0.
+//! - a transfer ring per endpoint, on which the driver places transfers
+//!   and rings the doorbell of the slot.
+//!
+//! Rings are a single segment closed by a link TRB, and the side that
+//! produces them flips the cycle bit at every pass. Events are polled:
+//! a command or a control or bulk transfer waits for its completion by
+//! taking events, with a timeout, and [`poll`](UsbHostController::poll)
+//! takes the events of interrupt endpoints. Each interrupt IN endpoint has
+//! one transfer outstanding, queued again when it completes.
+//!
+//! Memory shared with the controller is identity mapped, so its addresses
+//! are the physical addresses the controller uses.
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::alloc::Layout;
+use core::mem::size_of;
+use core::ptr::{read_volatile, write_volatile};
+use core::sync::atomic::{fence, Ordering};
+use spin::Mutex;
+
+use crate::device::manager::{DeviceManager, DriverPriority};
+use crate::device::platform::{resource::PlatformDeviceResourceType, PlatformDeviceDriver, PlatformDeviceInfo};
+use crate::device::usb::descriptor::{EndpointDescriptor, TransferType};
+use crate::device::usb::{register_host_controller, InterruptHandler, SetupPacket, UsbError, UsbHostController, UsbSpeed};
+use crate::driver_initcall;
+use crate::timer::get_time_us;
+
+// Capability registers
+const CAP_CAPLENGTH: usize = 0x00;
+const CAP_HCSPARAMS1: usize = 0x04;
+const CAP_HCSPARAMS2: usize = 0x08;
+const CAP_HCCPARAMS1: usize = 0x10;
+const CAP_DBOFF: usize = 0x14;
+const CAP_RTSOFF: usize = 0x18;
+
+const HCCPARAMS1_CSZ: u32 = 1 << 2;
+const HCCPARAMS1_PPC: u32 = 1 << 3;
+
+// Operational registers
+const OP_USBCMD: usize = 0x00;
+const OP_USBSTS: usize = 0x04;
+const OP_PAGESIZE: usize = 0x08;
+const OP_CRCR: usize = 0x18;
+const OP_DCBAAP: usize = 0x30;
+const OP_CONFIG: usize = 0x38;
+const OP_PORTSC: usize = 0x400;
+const PORT_REGS_SIZE: usize = 0x10;
+
+const USBCMD_RUN: u32 = 1 << 0;
+const USBCMD_HCRST: u32 = 1 << 1;
+
+const USBSTS_HCH: u32 = 1 << 0;
+const USBSTS_CNR: u32 = 1 << 11;
+
+const PORTSC_CCS: u32 = 1 << 0;
+const PORTSC_PED: u32 = 1 << 1;
+const PORTSC_PR: u32 = 1 << 4;
+const PORTSC_PP: u32 = 1 << 9;
+const PORTSC_SPEED_SHIFT: u32 = 10;
+const PORTSC_PRC: u32 = 1 << 21;
+/// Change bits, cleared by writing 1
+const PORTSC_CHANGES: u32 = 0x7f << 17;
+
+// Runtime registers of interrupter 0
+const IR0: usize = 0x20;
+const IR_IMAN: usize = 0x00;
+const IR_ERSTSZ: usize = 0x08;
+const IR_ERSTBA: usize = 0x10;
+const IR_ERDP: usize = 0x18;
+
+const ERDP_EHB: u64 = 1 << 3;
+
+// TRB types
+const TRB_NORMAL: u32 = 1;
+const TRB_SETUP: u32 = 2;
+const TRB_DATA: u32 = 3;
+const TRB_STATUS: u32 = 4;
+const TRB_LINK: u32 = 6;
+const TRB_ENABLE_SLOT: u32 = 9;
+const TRB_DISABLE_SLOT: u32 = 10;
+const TRB_ADDRESS_DEVICE: u32 = 11;
+const TRB_CONFIGURE_ENDPOINT: u32 = 12;
+const TRB_EVALUATE_CONTEXT: u32 = 13;
+const TRB_RESET_ENDPOINT: u32 = 14;
+const TRB_SET_TR_DEQUEUE: u32 = 16;
+const TRB_TRANSFER_EVENT: u32 = 32;
+const TRB_COMMAND_COMPLETION: u32 = 33;
+
+// TRB control bits
+const TRB_CYCLE: u32 = 1 << 0;
+const TRB_TOGGLE_CYCLE: u32 = 1 << 1;
+const TRB_ISP: u32 = 1 << 2;
+const TRB_IOC: u32 = 1 << 5;
+const TRB_IDT: u32 = 1 << 6;
+const TRB_DIR_IN: u32 = 1 << 16;
+const TRB_TRT_OUT: u32 = 2 << 16;
+const TRB_TRT_IN: u32 = 3 << 16;
+
+// Completion codes
+const COMPLETION_SUCCESS: u8 = 1;
+const COMPLETION_BABBLE: u8 = 3;
+const COMPLETION_TRANSACTION_ERROR: u8 = 4;
+const COMPLETION_STALL: u8 = 6;
+const COMPLETION_NO_SLOTS: u8 = 9;
+const COMPLETION_SHORT_PACKET: u8 = 13;
+
+// Endpoint types of endpoint contexts
+const EP_TYPE_CONTROL: u32 = 4;
+
+/// TRBs in a ring, the last of which is the link TRB
+const RING_LEN: usize = 256;
+const EVENT_RING_LEN: usize = 256;
+/// Most bytes one TRB moves
+const MAX_TRB_LEN: usize = 64 * 1024;
+
+const COMMAND_TIMEOUT_US: u64 = 1_000_000;
+const TRANSFER_TIMEOUT_US: u64 = 5_000_000;
+const PORT_RESET_TIMEOUT_US: u64 = 500_000;
+const HALT_TIMEOUT_US: u64 = 100_000;
+
+/// Transfer request block
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct Trb {
+    parameter: u64,
+    status: u32,
+    control: u32,
+}
+
+impl Trb {
+    fn trb_type(&self) -> u32 {
+        (self.control >> 10) & 0x3f
+    }
+
+    fn completion_code(&self) -> u8 {
+        (self.status >> 24) as u8
+    }
+
+    /// Bytes not transferred, for transfer events
+    fn residual(&self) -> usize {
+        (self.status & 0x00ff_ffff) as usize
+    }
+
+    fn slot(&self) -> u8 {
+        (self.control >> 24) as u8
+    }
+
+    fn endpoint(&self) -> u8 {
+        ((self.control >> 16) & 0x1f) as u8
+    }
+}
+
+/// Zeroed memory shared with the controller
+struct Dma {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+// The controller is the only other user of the memory
+unsafe impl Send for Dma {}
+unsafe impl Sync for Dma {}
+
+impl Dma {
+    fn new(size: usize, align: usize) -> Result<Self, UsbError> {
+        let layout = Layout::from_size_align(size.max(1), align).map_err(|_| UsbError::NoResources)?;
+        let ptr = unsafe { alloc::alloc::alloc_zeroed(layout) };
+        if ptr.is_null() {
+            return Err(UsbError::NoResources);
+        }
+        Ok(Self { ptr, layout })
+    }
+
+    fn addr(&self) -> u64 {
+        self.ptr as u64
+    }
+
+    fn read32(&self, offset: usize) -> u32 {
+        unsafe { read_volatile(self.ptr.add(offset) as *const u32) }
+    }
+
+    fn write32(&self, offset: usize, value: u32) {
+        unsafe { write_volatile(self.ptr.add(offset) as *mut u32, value) }
+    }
+
+    fn write64(&self, offset: usize, value: u64) {
+        unsafe { write_volatile(self.ptr.add(offset) as *mut u64, value) }
+    }
+
+    fn read_trb(&self, index: usize) -> Trb {
+        unsafe { read_volatile((self.ptr as *const Trb).add(index)) }
+    }
+
+    fn copy_from(&self, data: &[u8]) {
+        unsafe { core::ptr::copy_nonoverlapping(data.as_ptr(), self.ptr, data.len().min(self.layout.size())) }
+    }
+
+    fn copy_to(&self, data: &mut [u8]) {
+        let len = data.len().min(self.layout.size());
+        crate::mem::kasan::check_read(self.ptr as usize, len);
+        unsafe { core::ptr::copy_nonoverlapping(self.ptr, data.as_mut_ptr(), len) }
+    }
+}
+
+impl Drop for Dma {
+    fn drop(&mut self) {
+        unsafe { alloc::alloc::dealloc(self.ptr, self.layout) }
+    }
+}
+
+/// A command or transfer ring the driver produces
+struct Ring {
+    trbs: Dma,
+    enqueue: usize,
+    cycle: bool,
+}
+
+impl Ring {
+    fn new() -> Result<Self, UsbError> {
+        // Page aligned, so that the ring does not cross a 64 KiB boundary
+        let trbs = Dma::new(RING_LEN * size_of::<Trb>(), 4096)?;
+        let link = (RING_LEN - 1) * size_of::<Trb>();
+        trbs.write64(link, trbs.addr());
+        trbs.write32(link + 12, TRB_LINK << 10 | TRB_TOGGLE_CYCLE);
+        Ok(Self { trbs, enqueue: 0, cycle: true })
+    }
+
+    fn addr(&self) -> u64 {
+        self.trbs.addr()
+    }
+
+    /// Dequeue pointer for the controller: the enqueue position and cycle
+    fn dequeue_pointer(&self) -> u64 {
+        self.addr() + (self.enqueue * size_of::<Trb>()) as u64 | self.cycle as u64
+    }
+
+    /// Hand `trb` to the controller and return its address
+    fn push(&mut self, trb: Trb) -> u64 {
+        let offset = self.enqueue * size_of::<Trb>();
+        self.trbs.write64(offset, trb.parameter);
+        self.trbs.write32(offset + 8, trb.status);
+        // The cycle bit gives the TRB away, so it is written last
+        fence(Ordering::SeqCst);
+        self.trbs.write32(offset + 12, (trb.control & !TRB_CYCLE) | self.cycle as u32);
+        let addr = self.addr() + offset as u64;
+
+        self.enqueue += 1;
+        if self.enqueue == RING_LEN - 1 {
+            let link = self.enqueue * size_of::<Trb>();
+            let control = self.trbs.read32(link + 12);
+            fence(Ordering::SeqCst);
+            self.trbs.write32(link + 12, (control & !TRB_CYCLE) | self.cycle as u32);
+            self.enqueue = 0;
+            self.cycle = !self.cycle;
+        }
+        addr
+    }
+}
+
+/// The event ring of interrupter 0, which the controller produces
+struct EventRing {
+    trbs: Dma,
+    /// Event ring segment table, of one segment
+    segments: Dma,
+    dequeue: usize,
+    cycle: bool,
+}
+
+impl EventRing {
+    fn new() -> Result<Self, UsbError> {
+        let trbs = Dma::new(EVENT_RING_LEN * size_of::<Trb>(), 4096)?;
+        let segments = Dma::new(16, 64)?;
+        segments.write64(0, trbs.addr());
+        segments.write32(8, EVENT_RING_LEN as u32);
+        Ok(Self { trbs, segments, dequeue: 0, cycle: true })
+    }
+
+    fn dequeue_addr(&self) -> u64 {
+        self.trbs.addr() + (self.dequeue * size_of::<Trb>()) as u64
+    }
+
+    fn pop(&mut self) -> Option<Trb> {
+        let trb = self.trbs.read_trb(self.dequeue);
+        if (trb.control & TRB_CYCLE != 0) != self.cycle {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+        self.dequeue += 1;
+        if self.dequeue == EVENT_RING_LEN {
+            self.dequeue = 0;
+            self.cycle = !self.cycle;
+        }
+        Some(trb)
+    }
+}
+
+/// Interrupt IN endpoint being read
+struct InterruptPipe {
+    buffer: Dma,
+    len: usize,
+    /// The outstanding transfer
+    trb: u64,
+    handler: InterruptHandler,
+}
+
+/// A device slot
+struct Slot {
+    port: usize,
+    speed: UsbSpeed,
+    /// Output device context, which the controller keeps
+    output: Dma,
+    /// Input context of the commands
+    input: Dma,
+    /// Transfer rings by device context index
+    rings: BTreeMap<u8, Ring>,
+    interrupt_pipes: BTreeMap<u8, InterruptPipe>,
+}
+
+/// Completion of a command or transfer
+#[derive(Debug, Clone, Copy)]
+struct Completion {
+    code: u8,
+    residual: usize,
+    slot: u8,
+}
+
+impl Completion {
+    fn result(&self) -> Result<(), UsbError> {
+        match self.code {
+            COMPLETION_SUCCESS | COMPLETION_SHORT_PACKET => Ok(()),
+            COMPLETION_STALL => Err(UsbError::Stall),
+            COMPLETION_BABBLE => Err(UsbError::Babble),
+            COMPLETION_TRANSACTION_ERROR => Err(UsbError::TransactionError),
+            COMPLETION_NO_SLOTS => Err(UsbError::NoResources),
+            code => Err(UsbError::Controller(code)),
+        }
+    }
+}
+
+struct State {
+    command_ring: Ring,
+    event_ring: EventRing,
+    /// Device context base address array
+    dcbaa: Dma,
+    /// Scratchpad buffer array and buffers, only used by the controller
+    _scratchpad: Vec<Dma>,
+    slots: BTreeMap<u8, Slot>,
+    /// Completions not yet waited for, by TRB address
+    completions: BTreeMap<u64, Completion>,
+    /// Data of interrupt transfers for their handlers
+    reports: Vec<(InterruptHandler, Vec<u8>)>,
+}
+
+pub struct XhciController {
+    base_addr: usize,
+    op_base: usize,
+    runtime_base: usize,
+    doorbell_base: usize,
+    max_slots: u8,
+    max_ports: usize,
+    /// Bytes in a context: 32, or 64 with CSZ
+    context_size: usize,
+    state: Mutex<State>,
+}
+
+/// Device context index of the endpoint at `address`
+fn context_index(address: u8) -> u8 {
+    let number = address & 0x0f;
+    if number == 0 {
+        1
+    } else {
+        number * 2 + (address >> 7)
+    }
+}
+
+fn speed_value(speed: UsbSpeed) -> u32 {
+    match speed {
+        UsbSpeed::Full => 1,
+        UsbSpeed::Low => 2,
+        UsbSpeed::High => 3,
+        UsbSpeed::Super => 4,
+    }
+}
+
+/// Endpoint context interval, in 2^n frames of 125 us, of `endpoint`
+fn endpoint_interval(speed: UsbSpeed, endpoint: &EndpointDescriptor) -> u32 {
+    let interval = endpoint.interval.max(1) as u32;
+    match (speed, endpoint.transfer_type()) {
+        // bInterval in frames of 1 ms
+        (UsbSpeed::Low | UsbSpeed::Full, TransferType::Interrupt) => (31 - (interval * 8).leading_zeros()).clamp(3, 10),
+        (_, TransferType::Interrupt | TransferType::Isochronous) => (interval - 1).min(15),
+        _ => 0,
+    }
+}
+
+/// Endpoint context type of `endpoint`
+fn endpoint_type(endpoint: &EndpointDescriptor) -> u32 {
+    let base = match endpoint.transfer_type() {
+        TransferType::Control => return EP_TYPE_CONTROL,
+        TransferType::Isochronous => 1,
+        TransferType::Bulk => 2,
+        TransferType::Interrupt => 3,
+    };
+    if endpoint.is_in() { base + 4 } else { base }
+}
+
+impl XhciController {
+    /// Reset and start the controller at `base_addr`
+    pub fn new(base_addr: usize) -> Result<Self, &'static str> {
+        let read = |offset: usize| unsafe { read_volatile((base_addr + offset) as *const u32) };
+        let cap_length = read(CAP_CAPLENGTH) & 0xff;
+        let hcsparams1 = read(CAP_HCSPARAMS1);
+        let hccparams1 = read(CAP_HCCPARAMS1);
+        let controller = Self {
+            base_addr,
+            op_base: base_addr + cap_length as usize,
+            runtime_base: base_addr + (read(CAP_RTSOFF) & !0x1f) as usize,
+            doorbell_base: base_addr + (read(CAP_DBOFF) & !0x3) as usize,
+            max_slots: hcsparams1 as u8,
+            max_ports: (hcsparams1 >> 24) as usize,
+            context_size: if hccparams1 & HCCPARAMS1_CSZ != 0 { 64 } else { 32 },
+            state: Mutex::new(State {
+                command_ring: Ring::new().map_err(|_| "Out of memory")?,
+                event_ring: EventRing::new().map_err(|_| "Out of memory")?,
+                dcbaa: Dma::new(2048, 64).map_err(|_| "Out of memory")?,
+                _scratchpad: Vec::new(),
+                slots: BTreeMap::new(),
+                completions: BTreeMap::new(),
+                reports: Vec::new(),
+            }),
+        };
+        controller.init()?;
+        Ok(controller)
+    }
+
+    fn read_cap(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.base_addr + offset) as *const u32) }
+    }
+
+    fn read_op(&self, offset: usize) -> u32 {
+        unsafe { read_volatile((self.op_base + offset) as *const u32) }
+    }
+
+    fn write_op(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.op_base + offset) as *mut u32, value) }
+    }
+
+    /// 64-bit registers are written low half first
+    fn write_op64(&self, offset: usize, value: u64) {
+        self.write_op(offset, value as u32);
+        self.write_op(offset + 4, (value >> 32) as u32);
+    }
+
+    fn write_runtime(&self, offset: usize, value: u32) {
+        unsafe { write_volatile((self.runtime_base + IR0 + offset) as *mut u32, value) }
+    }
+
+    fn write_runtime64(&self, offset: usize, value: u64) {
+        self.write_runtime(offset, value as u32);
+        self.write_runtime(offset + 4, (value >> 32) as u32);
+    }
+
+    fn ring_doorbell(&self, slot: u8, target: u8) {
+        fence(Ordering::SeqCst);
+        unsafe { write_volatile((self.doorbell_base + slot as usize * 4) as *mut u32, target as u32) }
+    }
+
+    fn portsc(&self, port: usize) -> usize {
+        OP_PORTSC + (port - 1) * PORT_REGS_SIZE
+    }
+
+    /// Wait until `done` holds, for at most `timeout_us`
+    fn wait(&self, timeout_us: u64, mut done: impl FnMut() -> bool) -> bool {
+        let deadline = get_time_us() + timeout_us;
+        while !done() {
+            if get_time_us() >= deadline {
+                return false;
+            }
+            core::hint::spin_loop();
+        }
+        true
+    }
+
+    fn init(&self) -> Result<(), &'static str> {
+        if !self.wait(COMMAND_TIMEOUT_US, || self.read_op(OP_USBSTS) & USBSTS_CNR == 0) {
+            return Err("Controller not ready");
+        }
+        self.write_op(OP_USBCMD, self.read_op(OP_USBCMD) & !USBCMD_RUN);
+        if !self.wait(HALT_TIMEOUT_US, || self.read_op(OP_USBSTS) & USBSTS_HCH != 0) {
+            return Err("Controller does not halt");
+        }
+        self.write_op(OP_USBCMD, USBCMD_HCRST);
+        if !self.wait(COMMAND_TIMEOUT_US, || {
+            self.read_op(OP_USBCMD) & USBCMD_HCRST == 0 && self.read_op(OP_USBSTS) & USBSTS_CNR == 0
+        }) {
+            return Err("Controller reset timed out");
+        }
+
+        let mut state = self.state.lock();
+        let hcsparams2 = self.read_cap(CAP_HCSPARAMS2);
+        let scratchpad_count = ((hcsparams2 >> 21) & 0x1f) << 5 | (hcsparams2 >> 27) & 0x1f;
+        if scratchpad_count > 0 {
+            let page_size = ((self.read_op(OP_PAGESIZE) & 0xffff) as usize) << 12;
+            let array = Dma::new(scratchpad_count as usize * 8, 64).map_err(|_| "Out of memory")?;
+            for index in 0..scratchpad_count as usize {
+                let page = Dma::new(page_size, page_size).map_err(|_| "Out of memory")?;
+                array.write64(index * 8, page.addr());
+                state._scratchpad.push(page);
+            }
+            state.dcbaa.write64(0, array.addr());
+            state._scratchpad.push(array);
+        }
+
+        self.write_op(OP_CONFIG, self.max_slots as u32);
+        self.write_op64(OP_DCBAAP, state.dcbaa.addr());
+        self.write_op64(OP_CRCR, state.command_ring.addr() | 1);
+        self.write_runtime(IR_ERSTSZ, 1);
+        self.write_runtime64(IR_ERDP, state.event_ring.dequeue_addr());
+        self.write_runtime64(IR_ERSTBA, state.event_ring.segments.addr());
+        // Events are polled, interrupts stay off
+        self.write_runtime(IR_IMAN, 1);
+        drop(state);
+
+        if self.read_cap(CAP_HCCPARAMS1) & HCCPARAMS1_PPC != 0 {
+            for port in 1..=self.max_ports {
+                self.write_op(self.portsc(port), PORTSC_PP);
+            }
+        }
+        self.write_op(OP_USBCMD, USBCMD_RUN);
+        if !self.wait(HALT_TIMEOUT_US, || self.read_op(OP_USBSTS) & USBSTS_HCH == 0) {
+            return Err("Controller does not start");
+        }
+        Ok(())
+    }
+
+    /// Take the events on the event ring
+    fn process_events(&self, state: &mut State) {
+        let mut taken = false;
+        while let Some(event) = state.event_ring.pop() {
+            taken = true;
+            match event.trb_type() {
+                TRB_COMMAND_COMPLETION => {
+                    let completion = Completion { code: event.completion_code(), residual: 0, slot: event.slot() };
+                    state.completions.insert(event.parameter, completion);
+                }
+                TRB_TRANSFER_EVENT => {
+                    let completion = Completion { code: event.completion_code(), residual: event.residual(), slot: event.slot() };
+                    let State { slots, reports, completions, .. } = &mut *state;
+                    let slot = slots.get_mut(&event.slot());
+                    let is_pipe = slot.as_ref().and_then(|slot| slot.interrupt_pipes.get(&event.endpoint()))
+                        .is_some_and(|pipe| pipe.trb == event.parameter);
+                    if !is_pipe {
+                        completions.insert(event.parameter, completion);
+                        continue;
+                    }
+                    let slot = slot.unwrap();
+                    if completion.result().is_err() {
+                        crate::early_println!("[xhci] Interrupt endpoint {} of slot {} failed: {:?}", event.endpoint(), event.slot(), completion.result());
+                        slot.interrupt_pipes.remove(&event.endpoint());
+                        continue;
+                    }
+                    let pipe = slot.interrupt_pipes.get_mut(&event.endpoint()).unwrap();
+                    let mut data = vec![0u8; pipe.len.saturating_sub(completion.residual)];
+                    pipe.buffer.copy_to(&mut data);
+                    reports.push((pipe.handler.clone(), data));
+                    if let Some(ring) = slot.rings.get_mut(&event.endpoint()) {
+                        pipe.trb = ring.push(Trb {
+                            parameter: pipe.buffer.addr(),
+                            status: pipe.len as u32,
+                            control: TRB_NORMAL << 10 | TRB_ISP | TRB_IOC,
+                        });
+                        self.ring_doorbell(event.slot(), event.endpoint());
+                    }
+                }
+                // Port changes are seen in PORTSC
+                _ => {}
+            }
+        }
+        if taken {
+            self.write_runtime64(IR_ERDP, state.event_ring.dequeue_addr() | ERDP_EHB);
+        }
+    }
+
+    /// Wait for the completion of the first of `trbs` to complete
+    fn wait_completion(&self, state: &mut State, trbs: &[u64], timeout_us: u64) -> Result<(u64, Completion), UsbError> {
+        let deadline = get_time_us() + timeout_us;
+        loop {
+            self.process_events(state);
+            if let Some((&trb, completion)) = trbs.iter().find_map(|trb| state.completions.get_key_value(trb)) {
+                let completion = *completion;
+                state.completions.remove(&trb);
+                return Ok((trb, completion));
+            }
+            if get_time_us() >= deadline {
+                return Err(UsbError::Timeout);
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn command(&self, state: &mut State, trb: Trb) -> Result<Completion, UsbError> {
+        let addr = state.command_ring.push(trb);
+        self.ring_doorbell(0, 0);
+        let (_, completion) = self.wait_completion(state, &[addr], COMMAND_TIMEOUT_US)?;
+        completion.result().map(|_| completion)
+    }
+
+    fn input_context_command(&self, state: &mut State, slot_id: u8, trb_type: u32) -> Result<(), UsbError> {
+        let input = state.slots.get(&slot_id).ok_or(UsbError::Disconnected)?.input.addr();
+        self.command(state, Trb { parameter: input, status: 0, control: trb_type << 10 | (slot_id as u32) << 24 })?;
+        Ok(())
+    }
+
+    /// Offset of dword `dword` of context `index` of an input context,
+    /// where 0 is the input control context and 1 the slot context
+    fn input_offset(&self, index: usize, dword: usize) -> usize {
+        index * self.context_size + dword * 4
+    }
+
+    /// Fill the slot context of the input context of `slot`
+    fn write_slot_context(&self, slot: &Slot, context_entries: u32) {
+        slot.input.write32(self.input_offset(1, 0), speed_value(slot.speed) << 20 | context_entries << 27);
+        slot.input.write32(self.input_offset(1, 1), (slot.port as u32) << 16);
+    }
+
+    fn write_ep0_context(&self, slot: &Slot, max_packet_size: u16) {
+        let ring = &slot.rings[&1];
+        slot.input.write32(self.input_offset(2, 1), 3 << 1 | EP_TYPE_CONTROL << 3 | (max_packet_size as u32) << 16);
+        slot.input.write64(self.input_offset(2, 2), ring.dequeue_pointer());
+        slot.input.write32(self.input_offset(2, 4), 8);
+    }
+
+    /// Clear the input control context and the contexts after it
+    fn clear_input(&self, slot: &Slot) {
+        for offset in (0..33 * self.context_size).step_by(4) {
+            slot.input.write32(offset, 0);
+        }
+    }
+
+    /// Recover the endpoint `dci` of `slot_id` from a halt, skipping the
+    /// transfers left on its ring
+    fn reset_endpoint(&self, state: &mut State, slot_id: u8, dci: u8) {
+        let target = (slot_id as u32) << 24 | (dci as u32) << 16;
+        let _ = self.command(state, Trb { parameter: 0, status: 0, control: TRB_RESET_ENDPOINT << 10 | target });
+        let Some(dequeue) = state.slots.get(&slot_id).and_then(|slot| slot.rings.get(&dci)).map(Ring::dequeue_pointer) else {
+            return;
+        };
+        let _ = self.command(state, Trb { parameter: dequeue, status: 0, control: TRB_SET_TR_DEQUEUE << 10 | target });
+    }
+
+    /// Push `trbs` on the ring `dci` of `slot_id`, ring its doorbell and wait
+    /// for the TRB with IOC or one that fails; returns the completion and
+    /// the residual of a short data TRB at index `data`
+    fn transfer(&self, state: &mut State, slot_id: u8, dci: u8, trbs: &[Trb], data: Option<usize>) -> Result<usize, UsbError> {
+        let ring = state
+            .slots
+            .get_mut(&slot_id)
+            .and_then(|slot| slot.rings.get_mut(&dci))
+            .ok_or(UsbError::Disconnected)?;
+        let addrs: Vec<u64> = trbs.iter().map(|&trb| ring.push(trb)).collect();
+        self.ring_doorbell(slot_id, dci);
+
+        let mut residual = 0;
+        loop {
+            let (addr, completion) = match self.wait_completion(state, &addrs, TRANSFER_TIMEOUT_US) {
+                Ok(done) => done,
+                Err(error) => {
+                    self.reset_endpoint(state, slot_id, dci);
+                    return Err(error);
+                }
+            };
+            // A failed transfer halts the endpoint
+            if let Err(error) = completion.result() {
+                self.reset_endpoint(state, slot_id, dci);
+                return Err(error);
+            }
+            if data.is_some_and(|index| addrs[index] == addr) {
+                residual = completion.residual;
+            }
+            // The last TRB has IOC; an event before it is a short packet
+            if addr == *addrs.last().unwrap() {
+                return Ok(residual);
+            }
+        }
+    }
+
+    fn slot_id(&self, device: usize) -> Result<u8, UsbError> {
+        u8::try_from(device).map_err(|_| UsbError::Disconnected)
+    }
+}
+
+impl UsbHostController for XhciController {
+    fn name(&self) -> &'static str {
+        "xhci"
+    }
+
+    fn port_count(&self) -> usize {
+        self.max_ports
+    }
+
+    fn port_connected(&self, port: usize) -> bool {
+        self.read_op(self.portsc(port)) & PORTSC_CCS != 0
+    }
+
+    fn reset_port(&self, port: usize) -> Result<UsbSpeed, UsbError> {
+        let portsc = self.portsc(port);
+        let status = self.read_op(portsc);
+        self.write_op(portsc, (status & PORTSC_PP) | (status & PORTSC_CHANGES) | PORTSC_PR);
+        if !self.wait(PORT_RESET_TIMEOUT_US, || self.read_op(portsc) & PORTSC_PRC != 0) {
+            return Err(UsbError::Timeout);
+        }
+        let status = self.read_op(portsc);
+        self.write_op(portsc, (status & PORTSC_PP) | (status & PORTSC_CHANGES));
+        if status & PORTSC_CCS == 0 {
+            return Err(UsbError::Disconnected);
+        }
+        if status & PORTSC_PED == 0 {
+            return Err(UsbError::TransactionError);
+        }
+        match (status >> PORTSC_SPEED_SHIFT) & 0xf {
+            1 => Ok(UsbSpeed::Full),
+            2 => Ok(UsbSpeed::Low),
+            3 => Ok(UsbSpeed::High),
+            4 | 5 => Ok(UsbSpeed::Super),
+            _ => Err(UsbError::NotSupported),
+        }
+    }
+
+    fn attach_device(&self, port: usize, speed: UsbSpeed) -> Result<usize, UsbError> {
+        let mut state = self.state.lock();
+        let completion = self.command(&mut state, Trb { parameter: 0, status: 0, control: TRB_ENABLE_SLOT << 10 })?;
+        let slot_id = completion.slot;
+
+        let setup = || -> Result<Slot, UsbError> {
+            let mut rings = BTreeMap::new();
+            rings.insert(1, Ring::new()?);
+            Ok(Slot {
+                port,
+                speed,
+                output: Dma::new(32 * self.context_size, 64)?,
+                input: Dma::new(33 * self.context_size, 64)?,
+                rings,
+                interrupt_pipes: BTreeMap::new(),
+            })
+        };
+        let slot = match setup() {
+            Ok(slot) => slot,
+            Err(error) => {
+                let _ = self.command(&mut state, Trb { parameter: 0, status: 0, control: TRB_DISABLE_SLOT << 10 | (slot_id as u32) << 24 });
+                return Err(error);
+            }
+        };
+        // Add the slot context and endpoint 0
+        slot.input.write32(self.input_offset(0, 1), 0b11);
+        self.write_slot_context(&slot, 1);
+        self.write_ep0_context(&slot, speed.default_max_packet_size0());
+        state.dcbaa.write64(slot_id as usize * 8, slot.output.addr());
+        state.slots.insert(slot_id, slot);
+
+        if let Err(error) = self.input_context_command(&mut state, slot_id, TRB_ADDRESS_DEVICE) {
+            drop(state);
+            self.detach_device(slot_id as usize);
+            return Err(error);
+        }
+        Ok(slot_id as usize)
+    }
+
+    fn detach_device(&self, device: usize) {
+        let Ok(slot_id) = self.slot_id(device) else {
+            return;
+        };
+        let mut state = self.state.lock();
+        let _ = self.command(&mut state, Trb { parameter: 0, status: 0, control: TRB_DISABLE_SLOT << 10 | (slot_id as u32) << 24 });
+        state.dcbaa.write64(slot_id as usize * 8, 0);
+        state.slots.remove(&slot_id);
+        state.completions.retain(|_, completion| completion.slot != slot_id);
+    }
+
+    fn set_max_packet_size0(&self, device: usize, size: u16) -> Result<(), UsbError> {
+        let slot_id = self.slot_id(device)?;
+        let mut state = self.state.lock();
+        let slot = state.slots.get(&slot_id).ok_or(UsbError::Disconnected)?;
+        self.clear_input(slot);
+        slot.input.write32(self.input_offset(0, 1), 0b10);
+        self.write_ep0_context(slot, size);
+        self.input_context_command(&mut state, slot_id, TRB_EVALUATE_CONTEXT)
+    }
+
+    fn configure_endpoints(&self, device: usize, endpoints: &[EndpointDescriptor]) -> Result<(), UsbError> {
+        let slot_id = self.slot_id(device)?;
+        let mut state = self.state.lock();
+        let slot = state.slots.get_mut(&slot_id).ok_or(UsbError::Disconnected)?;
+        let mut add_flags = 1;
+        let mut last = 1;
+        let mut rings = Vec::new();
+        for endpoint in endpoints.iter().filter(|endpoint| endpoint.transfer_type() != TransferType::Control) {
+            let dci = context_index(endpoint.address);
+            let ring = Ring::new()?;
+            let max_packet_size = endpoint.packet_size() as u32;
+            let offset = |dword| self.input_offset(1 + dci as usize, dword);
+            let periodic = matches!(endpoint.transfer_type(), TransferType::Interrupt | TransferType::Isochronous);
+            slot.input.write32(offset(0), endpoint_interval(slot.speed, endpoint) << 16);
+            slot.input.write32(offset(1), 3 << 1 | endpoint_type(endpoint) << 3 | max_packet_size << 16);
+            slot.input.write64(offset(2), ring.dequeue_pointer());
+            let (average, esit) = if periodic { (max_packet_size, max_packet_size) } else { (3072, 0) };
+            slot.input.write32(offset(4), average | esit << 16);
+            add_flags |= 1 << dci;
+            last = last.max(dci as u32);
+            rings.push((dci, ring));
+        }
+        slot.input.write32(self.input_offset(0, 0), 0);
+        slot.input.write32(self.input_offset(0, 1), add_flags);
+        self.write_slot_context(slot, last);
+        slot.rings.extend(rings);
+        self.input_context_command(&mut state, slot_id, TRB_CONFIGURE_ENDPOINT)
+    }
+
+    fn control_transfer(&self, device: usize, setup: SetupPacket, data: &mut [u8]) -> Result<usize, UsbError> {
+        let slot_id = self.slot_id(device)?;
+        let len = (setup.length as usize).min(data.len());
+        let buffer = Dma::new(len, 64)?;
+        if !setup.is_in() {
+            buffer.copy_from(&data[..len]);
+        }
+        let mut trbs = vec![Trb {
+            parameter: u64::from_le_bytes(setup.to_bytes()),
+            status: 8,
+            control: TRB_SETUP << 10
+                | TRB_IDT
+                | match (len, setup.is_in()) {
+                    (0, _) => 0,
+                    (_, true) => TRB_TRT_IN,
+                    (_, false) => TRB_TRT_OUT,
+                },
+        }];
+        if len > 0 {
+            trbs.push(Trb {
+                parameter: buffer.addr(),
+                status: len as u32,
+                control: TRB_DATA << 10 | TRB_ISP | if setup.is_in() { TRB_DIR_IN } else { 0 },
+            });
+        }
+        // The status stage goes the other way from the data
+        let status_in = len == 0 || !setup.is_in();
+        trbs.push(Trb { parameter: 0, status: 0, control: TRB_STATUS << 10 | TRB_IOC | if status_in { TRB_DIR_IN } else { 0 } });
+
+        let mut state = self.state.lock();
+        let residual = self.transfer(&mut state, slot_id, 1, &trbs, (len > 0).then_some(1))?;
+        let transferred = len.saturating_sub(residual);
+        if setup.is_in() {
+            buffer.copy_to(&mut data[..transferred]);
+        }
+        Ok(transferred)
+    }
+
+    fn bulk_transfer(&self, device: usize, endpoint: u8, data: &mut [u8]) -> Result<usize, UsbError> {
+        let slot_id = self.slot_id(device)?;
+        let dci = context_index(endpoint);
+        let is_in = endpoint & 0x80 != 0;
+        let mut transferred = 0;
+        for chunk in data.chunks_mut(MAX_TRB_LEN) {
+            let buffer = Dma::new(chunk.len(), 64)?;
+            if !is_in {
+                buffer.copy_from(chunk);
+            }
+            let trb = Trb { parameter: buffer.addr(), status: chunk.len() as u32, control: TRB_NORMAL << 10 | TRB_ISP | TRB_IOC };
+            let residual = self.transfer(&mut self.state.lock(), slot_id, dci, &[trb], Some(0))?;
+            let len = chunk.len().saturating_sub(residual);
+            if is_in {
+                buffer.copy_to(&mut chunk[..len]);
+            }
+            transferred += len;
+            if len < chunk.len() {
+                break;
+            }
+        }
+        Ok(transferred)
+    }
+
+    fn interrupt_in(&self, device: usize, endpoint: u8, handler: InterruptHandler) -> Result<(), UsbError> {
+        let slot_id = self.slot_id(device)?;
+        let dci = context_index(endpoint);
+        let mut state = self.state.lock();
+        let slot = state.slots.get_mut(&slot_id).ok_or(UsbError::Disconnected)?;
+        // Max packet size of the endpoint, from its context
+        let len = (slot.output.read32(dci as usize * self.context_size + 4) >> 16) as usize;
+        let ring = slot.rings.get_mut(&dci).ok_or(UsbError::NotSupported)?;
+        let buffer = Dma::new(len, 64)?;
+        let trb = ring.push(Trb { parameter: buffer.addr(), status: len as u32, control: TRB_NORMAL << 10 | TRB_ISP | TRB_IOC });
+        slot.interrupt_pipes.insert(dci, InterruptPipe { buffer, len, trb, handler });
+        self.ring_doorbell(slot_id, dci);
+        Ok(())
+    }
+
+    fn poll(&self) {
+        // Handlers run unlocked, so that they may do transfers
+        let reports = {
+            let Some(mut state) = self.state.try_lock() else {
+                return;
+            };
+            self.process_events(&mut state);
+            core::mem::take(&mut state.reports)
+        };
+        for (handler, data) in reports {
+            handler(&data);
+        }
+    }
+}
+
+fn probe_fn(device: &PlatformDeviceInfo) -> Result<(), &'static str> {
+    let base_addr = device.get_resources()
+        .iter()
+        .find(|r| r.res_type == PlatformDeviceResourceType::MEM)
+        .ok_or("Memory resource not found")?
+        .start;
+    crate::early_println!("[xHCI] Detected xHCI controller at {:#x}", base_addr);
+    let controller = XhciController::new(base_addr)?;
+    register_host_controller(Arc::new(controller));
+    Ok(())
+}
+
+fn remove_fn(_device: &PlatformDeviceInfo) -> Result<(), &'static str> {
+    Ok(())
+}
+
+fn register_driver() {
+    let driver = PlatformDeviceDriver::new(
+        "xhci-platform",
+        probe_fn,
+        remove_fn,
+        vec!["generic-xhci", "xhci-platform"],
+    );
+    DeviceManager::get_mut_manager().register_driver(Box::new(driver), DriverPriority::Standard)
+}
+
+driver_initcall!(register_driver);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoint(address: u8, attributes: u8, interval: u8) -> EndpointDescriptor {
+        EndpointDescriptor { address, attributes, max_packet_size: 8, interval }
+    }
+
+    #[test_case]
+    fn test_context_index() {
+        assert_eq!(context_index(0x00), 1);
+        assert_eq!(context_index(0x01), 2);
+        assert_eq!(context_index(0x81), 3);
+        assert_eq!(context_index(0x8f), 31);
+    }
+
+    #[test_case]
+    fn test_endpoint_context_fields() {
+        // Interrupt IN every 10 ms on a full-speed device: 2^6 frames of 125 us
+        let keyboard = endpoint(0x81, 3, 10);
+        assert_eq!(endpoint_type(&keyboard), 7);
+        assert_eq!(endpoint_interval(UsbSpeed::Full, &keyboard), 6);
+        // High speed bInterval is already an exponent, of 2^(n-1) frames
+        assert_eq!(endpoint_interval(UsbSpeed::High, &endpoint(0x81, 3, 4)), 3);
+        assert_eq!(endpoint_type(&endpoint(0x02, 2, 0)), 2);
+        assert_eq!(endpoint_interval(UsbSpeed::High, &endpoint(0x02, 2, 0)), 0);
+    }
+
+    #[test_case]
+    fn test_ring_wraps() {
+        let mut ring = Ring::new().unwrap();
+        let base = ring.addr();
+        for index in 0..RING_LEN - 1 {
+            assert_eq!(ring.push(Trb::default()), base + (index * size_of::<Trb>()) as u64);
+        }
+        // Past the link TRB the cycle flips
+        assert!(!ring.cycle);
+        assert_eq!(ring.dequeue_pointer(), base);
+        assert_eq!(ring.push(Trb::default()), base);
+        assert_eq!(ring.trbs.read_trb(0).control & TRB_CYCLE, 0);
+        assert_eq!(ring.trbs.read_trb(RING_LEN - 1).control & TRB_CYCLE, 1);
+    }
+}
@@ -5,7 +5,7 @@ use core::{result::Result, sync::atomic::{AtomicUsize, Ordering}};
 
 use alloc::{boxed::Box, format, sync::Arc, vec};
 
-use crate::{device::{manager::{DeviceManager, DriverPriority}, platform::{resource::PlatformDeviceResourceType, PlatformDeviceDriver, PlatformDeviceInfo}, Device}, driver_initcall, drivers::{block::virtio_blk::VirtioBlockDevice, graphics::virtio_gpu::VirtioGpuDevice, network::virtio_net::VirtioNetDevice, virtio::queue}};
+use crate::{device::{manager::{DeviceManager, DriverPriority}, network::NetworkDevice, platform::{resource::PlatformDeviceResourceType, PlatformDeviceDriver, PlatformDeviceInfo}, Device}, driver_initcall, interrupt::InterruptManager, drivers::{block::virtio_blk::VirtioBlockDevice, graphics::virtio_gpu::VirtioGpuDevice, network::virtio_net::VirtioNetDevice, rng::virtio_rng::VirtioRngDevice, sound::virtio_snd, virtio::queue}};
 
 // Static counters for device naming
 static BLOCK_COUNTER: AtomicUsize = AtomicUsize::new(0);
@@ -605,6 +605,7 @@ pub enum VirtioDeviceType {
     Console = 3,
     Rng = 4,
     GPU = 16,
+    Sound = 25,
 }
 
 impl VirtioDeviceType {
@@ -627,6 +628,7 @@ impl VirtioDeviceType {
             3 => VirtioDeviceType::Console,
             4 => VirtioDeviceType::Rng,
             16 => VirtioDeviceType::GPU,
+            25 => VirtioDeviceType::Sound,
             _ => panic!("Not supported device type"),
         }
     }
@@ -722,7 +724,24 @@ fn probe_fn(device: &PlatformDeviceInfo) -> Result<(), &'static str> {
             let id = NET_COUNTER.fetch_add(1, Ordering::SeqCst);
             let name = format!("veth{}", id);
             crate::early_println!("[Virtio] Detected Virtio Network Device at {:#x}, registering as {}", base_addr, name);
-            let dev: Arc<dyn Device> = Arc::new(VirtioNetDevice::new(base_addr));
+            let mut net = VirtioNetDevice::new(base_addr);
+            // Receive buffers are set up before the device is shared
+            if let Err(error) = net.init_network() {
+                crate::early_println!("[Virtio] Cannot initialize {}: {}", name, error);
+            }
+            let net = Arc::new(net);
+            // Without an interrupt the network stack still polls the device
+            if let Some(irq) = res.iter().find(|r| r.res_type == PlatformDeviceResourceType::IRQ) {
+                let interrupt_id = irq.start as u32;
+                let registered = net.enable_interrupts(interrupt_id).and_then(|()| {
+                    InterruptManager::with_manager(|mgr| mgr.register_interrupt_device(interrupt_id, net.clone()))
+                        .map_err(|_| "Failed to register interrupt device")
+                });
+                if let Err(error) = registered {
+                    crate::early_println!("[Virtio] No interrupts for {}: {}", name, error);
+                }
+            }
+            let dev: Arc<dyn Device> = net;
             DeviceManager::get_mut_manager().register_device_with_name(name, dev);
         }
         VirtioDeviceType::GPU => {
@@ -732,6 +751,19 @@ fn probe_fn(device: &PlatformDeviceInfo) -> Result<(), &'static str> {
             let dev: Arc<dyn Device> = Arc::new(VirtioGpuDevice::new(base_addr));
             DeviceManager::get_mut_manager().register_device_with_name(name, dev);
         }
+        VirtioDeviceType::Rng => {
+            crate::early_println!("[Virtio] Detected Virtio Entropy Device at {:#x}", base_addr);
+            let rng = VirtioRngDevice::new(base_addr)?;
+            crate::random::register_source(Arc::new(rng));
+        }
+        VirtioDeviceType::Sound => {
+            crate::early_println!("[Virtio] Detected Virtio Sound Device at {:#x}", base_addr);
+            // Playback is paced by the interrupts of the transmit queue
+            let irq = res.iter()
+                .find(|r| r.res_type == PlatformDeviceResourceType::IRQ)
+                .ok_or("Sound device without interrupt")?;
+            virtio_snd::probe(base_addr, irq.start as u32)?;
+        }
         _ => {
             // Unsupported device type
             return Err("Unsupported device type");
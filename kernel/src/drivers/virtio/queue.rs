@@ -312,6 +312,40 @@ impl<'a> VirtQueue<'a> {
 
         Some(desc_idx)
     }
+
+    /// Pop a buffer from the used ring with the number of bytes the device wrote
+    ///
+    /// Like [`pop`](Self::pop), for buffers the device writes into.
+    ///
+    /// # Returns
+    ///
+    /// Option<(usize, usize)>: The index of the descriptor that was used and the length written,
+    /// or None if no descriptors are available.
+    pub fn pop_with_len(&mut self) -> Option<(usize, usize)> {
+        if self.last_used_idx == unsafe { core::ptr::read_volatile(self.used.idx) } {
+            return None;
+        }
+        let entry = &self.used.ring[self.last_used_idx as usize % self.desc.len()];
+        let (desc_idx, len) = (entry.id as usize, entry.len as usize);
+        self.last_used_idx = self.last_used_idx.wrapping_add(1);
+        Some((desc_idx, len))
+    }
+
+    /// Ask the device to interrupt when it uses buffers, or not to
+    ///
+    /// Without `VIRTIO_RING_F_EVENT_IDX` the device honours the flag of the
+    /// available ring; with it, an interrupt comes when the used index passes
+    /// the event index, which is left behind while interrupts are off.
+    pub fn set_interrupts(&mut self, enabled: bool) {
+        let flags = if enabled { 0 } else { AVAIL_F_NO_INTERRUPT };
+        unsafe {
+            core::ptr::write_volatile(self.avail.flags, flags);
+            if enabled {
+                core::ptr::write_volatile(self.avail.used_event, self.last_used_idx);
+            }
+        }
+        core::sync::atomic::fence(core::sync::atomic::Ordering::SeqCst);
+    }
 }
 
 impl<'a> Drop for VirtQueue<'a> {
@@ -400,6 +434,9 @@ impl DescriptorFlag {
     }
 }
 
+/// Flag of the available ring asking the device not to interrupt
+pub const AVAIL_F_NO_INTERRUPT: u16 = 0x1;
+
 /// Raw available ring structure
 /// 
 /// This structure represents the raw available ring.
@@ -3,11 +3,13 @@ pub const RISCV_STIMER_FREQ: u64 = 10000000; // 10MHz
 pub const VMMAX: usize = 0xffffffffffffffff;
 pub const STACK_SIZE: usize = 0x10000; // 64KiB
 pub const USER_STACK_END: usize = 0xffff_ffff_ffff_f000;
+pub const COMPAT_USER_STACK_END: usize = 0x8000_0000; // Stack of tasks running 32-bit code
+pub const VDSO_BASE: usize = 0xffff_ffff_f000_0000; // vDSO pages, clear of the user stack
 pub const PAGE_SIZE: usize = 0x1000; // 4KB
 pub const KERNEL_VM_STACK_SIZE: usize = 0x10000; // 64KiB
 pub const KERNEL_VM_STACK_END: usize = 0xffffffffffffefff;
 pub const KERNEL_VM_STACK_START: usize = KERNEL_VM_STACK_END - KERNEL_VM_STACK_SIZE + 1;
-pub const DEAFAULT_MAX_TASK_STACK_SIZE: usize = 0xffff_ffff_ffff_ffff; // Unlimited
+pub const DEAFAULT_MAX_TASK_STACK_SIZE: usize = 0x80_0000; // 8MiB (the user stack grows on demand up to this size)
 pub const DEAFAULT_MAX_TASK_DATA_SIZE: usize = 0xffff_ffff_ffff_ffff; // Unlimited
 pub const DEAFAULT_MAX_TASK_TEXT_SIZE: usize = 0xffff_ffff_ffff_ffff; // Unlimited
 // Per-task kernel stack configuration
@@ -0,0 +1,294 @@
+//! Registered interpreters for foreign binaries (binfmt_misc)
+//!
+//! When no ABI recognizes a binary, the exec is retried with an interpreter
+//! registered for it: a WebAssembly runtime for `.wasm` files, or an
+//! emulator for programs of another architecture. The interpreter is run
+//! with the path of the binary as its first argument, then the arguments
+//! of the binary without `argv[0]`.
+//!
+//! Entries are registered by writing a rule to `/proc/binfmt_misc`, in the
+//! format of Linux binfmt_misc:
+//!
+//! `:name:type:offset:magic:mask:interpreter:flags`
+//!
+//! - `type` is `M` to match `magic` at byte `offset` of the file, or `E`
+//!   to match the file name extension given as `magic`
+//! - `magic` and `mask` may contain `\xHH` escapes; the mask is ANDed with
+//!   the file bytes before they are compared, and may be empty
+//! - `flags` may contain `P` to pass the original `argv[0]` after the path
+//!   of the binary
+//!
+//! Writing `-name` removes an entry, `-1` removes all of them. Reading the
+//! file lists the entries in the order they are tried. Only the root user
+//! may change them.
+//!
+//! The path passed to the interpreter is the absolute path the binary had
+//! when the exec started; an interpreter of another ABI must see the same
+//! file under that path.
+
+extern crate alloc;
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::Write;
+use spin::RwLock;
+
+use crate::task::mytask;
+
+/// Bytes at the start of a binary that magic rules can look at
+pub const BINPRM_BUF_SIZE: usize = 128;
+
+/// Interpreters an exec may go through before it fails, as a chain of
+/// entries may point at each other
+pub const MAX_INTERPRETER_DEPTH: usize = 4;
+
+/// Largest number of entries
+const MAX_ENTRIES: usize = 64;
+
+/// What an entry matches
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum BinfmtMatch {
+    /// Bytes at an offset of the file, compared after the mask is applied
+    Magic { offset: usize, magic: Vec<u8>, mask: Vec<u8> },
+    /// The file name extension, without the dot
+    Extension(String),
+}
+
+/// A registered interpreter
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BinfmtEntry {
+    name: String,
+    matcher: BinfmtMatch,
+    interpreter: String,
+    /// `P`: pass the original argv[0] as well
+    preserve_argv0: bool,
+}
+
+/// The interpreter chosen for a binary
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinfmtInterpreter {
+    pub interpreter: String,
+    pub preserve_argv0: bool,
+}
+
+impl BinfmtInterpreter {
+    /// The arguments of the interpreter for `path` run with `argv`
+    pub fn build_argv<'a>(&'a self, path: &'a str, argv: &[&'a str]) -> Vec<&'a str> {
+        let mut args = alloc::vec![self.interpreter.as_str(), path];
+        let skip = if self.preserve_argv0 { 0 } else { 1 };
+        args.extend(argv.iter().skip(skip).copied());
+        args
+    }
+}
+
+static BINFMT_ENTRIES: RwLock<Vec<BinfmtEntry>> = RwLock::new(Vec::new());
+
+impl BinfmtEntry {
+    fn matches(&self, header: &[u8], path: &str) -> bool {
+        match &self.matcher {
+            BinfmtMatch::Magic { offset, magic, mask } => {
+                let Some(bytes) = header.get(*offset..*offset + magic.len()) else {
+                    return false;
+                };
+                bytes.iter().zip(magic).enumerate().all(|(i, (&byte, &expected))| {
+                    byte & mask.get(i).copied().unwrap_or(0xff) == expected
+                })
+            }
+            BinfmtMatch::Extension(extension) => {
+                let name = path.rsplit('/').next().unwrap_or(path);
+                name.rsplit_once('.').is_some_and(|(stem, ext)| !stem.is_empty() && ext == extension)
+            }
+        }
+    }
+}
+
+/// Decode `\xHH` escapes (and `\\`)
+fn unescape(field: &str) -> Result<Vec<u8>, &'static str> {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'\\' {
+            out.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        match bytes.get(i + 1) {
+            Some(b'\\') => {
+                out.push(b'\\');
+                i += 2;
+            }
+            Some(b'x') => {
+                let hex = field.get(i + 2..i + 4).ok_or("Truncated escape in binfmt rule")?;
+                out.push(u8::from_str_radix(hex, 16).map_err(|_| "Invalid escape in binfmt rule")?);
+                i += 4;
+            }
+            _ => return Err("Invalid escape in binfmt rule"),
+        }
+    }
+    Ok(out)
+}
+
+/// Parse a rule `:name:type:offset:magic:mask:interpreter:flags`
+fn parse_rule(rule: &str) -> Result<BinfmtEntry, &'static str> {
+    // The first character is the field separator, as on Linux
+    let mut chars = rule.chars();
+    let separator = chars.next().ok_or("Empty binfmt rule")?;
+    let fields: Vec<&str> = chars.as_str().split(separator).collect();
+    if fields.len() < 6 || fields.len() > 7 {
+        return Err("A binfmt rule needs name, type, offset, magic, mask and interpreter");
+    }
+    let name = fields[0];
+    if name.is_empty() || name.contains('/') || name == "." || name == ".." || name.starts_with('-') {
+        return Err("Invalid binfmt entry name");
+    }
+    let interpreter = fields[5];
+    if !interpreter.starts_with('/') {
+        return Err("The interpreter needs an absolute path");
+    }
+    let matcher = match fields[1] {
+        "M" => {
+            let offset = match fields[2] {
+                "" => 0,
+                offset => offset.parse::<usize>().map_err(|_| "Invalid binfmt offset")?,
+            };
+            let magic = unescape(fields[3])?;
+            let mask = unescape(fields[4])?;
+            if magic.is_empty() || offset + magic.len() > BINPRM_BUF_SIZE {
+                return Err("The magic must be within the first 128 bytes");
+            }
+            if !mask.is_empty() && mask.len() != magic.len() {
+                return Err("The mask must be as long as the magic");
+            }
+            // Compare masked bytes with a masked magic
+            let magic = magic.iter().enumerate().map(|(i, &b)| b & mask.get(i).copied().unwrap_or(0xff)).collect();
+            BinfmtMatch::Magic { offset, magic, mask }
+        }
+        "E" => {
+            let extension = fields[3];
+            if extension.is_empty() || extension.contains('/') {
+                return Err("Invalid binfmt extension");
+            }
+            BinfmtMatch::Extension(extension.to_string())
+        }
+        _ => return Err("The binfmt type must be M or E"),
+    };
+    let flags = fields.get(6).copied().unwrap_or("").trim_end();
+    if flags.chars().any(|flag| flag != 'P') {
+        return Err("Unsupported binfmt flag");
+    }
+    Ok(BinfmtEntry {
+        name: name.to_string(),
+        matcher,
+        interpreter: interpreter.to_string(),
+        preserve_argv0: flags.contains('P'),
+    })
+}
+
+/// The interpreter registered for a binary, given the first bytes of the
+/// file (up to [`BINPRM_BUF_SIZE`]) and its path
+pub fn find_interpreter(header: &[u8], path: &str) -> Option<BinfmtInterpreter> {
+    BINFMT_ENTRIES.read().iter().find(|entry| entry.matches(header, path)).map(|entry| BinfmtInterpreter {
+        interpreter: entry.interpreter.clone(),
+        preserve_argv0: entry.preserve_argv0,
+    })
+}
+
+/// Render `/proc/binfmt_misc`: one line per entry
+pub fn format_entries() -> String {
+    let mut out = String::new();
+    for entry in BINFMT_ENTRIES.read().iter() {
+        let _ = write!(out, "{} ", entry.name);
+        match &entry.matcher {
+            BinfmtMatch::Magic { offset, magic, mask } => {
+                let _ = write!(out, "magic offset={} magic=", offset);
+                for byte in magic {
+                    let _ = write!(out, "{:02x}", byte);
+                }
+                if !mask.is_empty() {
+                    out.push_str(" mask=");
+                    for byte in mask {
+                        let _ = write!(out, "{:02x}", byte);
+                    }
+                }
+            }
+            BinfmtMatch::Extension(extension) => {
+                let _ = write!(out, "extension .{}", extension);
+            }
+        }
+        let _ = write!(out, " interpreter={}", entry.interpreter);
+        if entry.preserve_argv0 {
+            out.push_str(" flags=P");
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Apply a rule or a removal written to `/proc/binfmt_misc`
+pub fn control(command: &[u8]) -> Result<(), &'static str> {
+    if mytask().is_some_and(|task| !task.cred.is_privileged()) {
+        crate::audit::privilege_denied("binfmt_misc");
+        return Err("Permission denied");
+    }
+    let command = core::str::from_utf8(command).map_err(|_| "Invalid binfmt command")?;
+    let command = command.trim_end_matches('\n');
+    let mut entries = BINFMT_ENTRIES.write();
+    match command.strip_prefix('-') {
+        Some("1") => entries.clear(),
+        Some(name) => {
+            let index = entries.iter().position(|entry| entry.name == name).ok_or("No such binfmt entry")?;
+            entries.remove(index);
+        }
+        None => {
+            let entry = parse_rule(command)?;
+            if entries.iter().any(|existing| existing.name == entry.name) {
+                return Err("A binfmt entry with this name exists");
+            }
+            if entries.len() >= MAX_ENTRIES {
+                return Err("Too many binfmt entries");
+            }
+            entries.push(entry);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_binfmt_rules() {
+        let wasm = parse_rule(":wasm:M::\\x00asm\\x01\\x00\\x00\\x00::/usr/bin/wasm-run:").unwrap();
+        assert!(wasm.matches(b"\0asm\x01\0\0\0rest", "/bin/hello"));
+        assert!(!wasm.matches(b"\0asm\x02\0\0\0", "/bin/hello"));
+        assert!(!wasm.matches(b"\0as", "/bin/hello"));
+
+        // Masked bytes are ignored; the flags give P
+        let masked = parse_rule(":x:M:2:\\x12\\x34:\\xff\\x0f:/emu:P").unwrap();
+        assert!(masked.preserve_argv0);
+        assert!(masked.matches(b"..\x12\xf4", "/a"));
+        assert!(!masked.matches(b"..\x13\x34", "/a"));
+
+        let ext = parse_rule(":py:E::py::/usr/bin/python:").unwrap();
+        assert!(ext.matches(b"", "/home/user/run.py"));
+        assert!(!ext.matches(b"", "/home/user/.py"));
+        assert!(!ext.matches(b"", "/home/user.py/run"));
+
+        assert!(parse_rule(":bad:M::\\x0::/emu:").is_err());
+        assert!(parse_rule(":bad:M::ab:c:/emu:").is_err());
+        assert!(parse_rule(":bad:M:200:ab::/emu:").is_err());
+        assert!(parse_rule(":bad:Q::ab::/emu:").is_err());
+        assert!(parse_rule(":bad:M::ab::emu:").is_err());
+        assert!(parse_rule(":bad:M::ab::/emu:X").is_err());
+    }
+
+    #[test_case]
+    fn test_binfmt_argv() {
+        let interpreter = BinfmtInterpreter { interpreter: "/emu".to_string(), preserve_argv0: false };
+        assert_eq!(interpreter.build_argv("/bin/prog", &["prog", "-v"]), ["/emu", "/bin/prog", "-v"]);
+        let interpreter = BinfmtInterpreter { preserve_argv0: true, ..interpreter };
+        assert_eq!(interpreter.build_argv("/bin/prog", &["prog", "-v"]), ["/emu", "/bin/prog", "prog", "-v"]);
+    }
+}
@@ -5,9 +5,14 @@
 //! its own binary format and conversion logic.
 
 use crate::{fs::manager::get_global_vfs_manager, task::Task};
-use crate::arch::Trapframe;
-use crate::vm::vmem::VirtualMemoryMap;
+use crate::arch::{Trapframe, vcpu::Xlen};
+use crate::vm::{manager::DEFAULT_MMAP_LIMIT, vmem::VirtualMemoryMap};
 use crate::task::ManagedPage;
+use crate::task::elf_loader::note::{read_abi_note, AbiNote};
+use crate::task::cred::MAY_EXEC;
+use crate::sched::preempt::PreemptGuard;
+use crate::fs::SeekFrom;
+use super::binfmt;
 use alloc::{boxed::Box, string::{String, ToString}, vec::Vec, sync::Arc};
 use core::fmt;
 
@@ -22,7 +27,13 @@ struct TaskStateBackup {
     text_size: usize,
     data_size: usize,
     stack_size: usize,
+    stack_top: usize,
+    brk_start: usize,
+    brk: usize,
     name: String,
+    exec_path: String,
+    mmap_limit: usize,
+    xlen: Xlen,
     trapframe: Trapframe,
 }
 
@@ -33,10 +44,10 @@ impl TaskStateBackup {
     fn create_backup(task: &mut Task, trapframe: &Trapframe) -> Self {
         // Move managed pages to backup (avoiding clone)
         let mut backup_pages = Vec::new();
-        backup_pages.append(&mut task.managed_pages);
+        backup_pages.append(&mut task.managed_pages.write());
         
         // Backup VM mapping - collect iterator into Vec for storage
-        let backup_vm_mapping = task.vm_manager.remove_all_memory_maps().collect();
+        let backup_vm_mapping = task.vm_manager.write().remove_all_memory_maps().collect();
         
         Self {
             managed_pages: backup_pages,
@@ -44,7 +55,13 @@ impl TaskStateBackup {
             text_size: task.text_size,
             data_size: task.data_size,
             stack_size: task.stack_size,
+            stack_top: task.stack_top,
+            brk_start: task.vm_manager.read().get_brk_start(),
+            brk: task.vm_manager.read().get_brk(),
             name: task.name.clone(),
+            exec_path: task.exec_path.clone(),
+            mmap_limit: task.vm_manager.read().get_mmap_limit(),
+            xlen: task.vcpu.get_xlen(),
             trapframe: trapframe.clone(),
         }
     }
@@ -55,22 +72,37 @@ impl TaskStateBackup {
     /// ensuring full rollback on exec failure.
     fn restore_to_task(self, task: &mut Task, trapframe: &mut Trapframe) -> Result<(), &'static str> {
         // Restore managed pages
-        task.managed_pages = self.managed_pages;
+        *task.managed_pages.write() = self.managed_pages;
         
         // Restore VM mapping
-        task.vm_manager.restore_memory_maps(self.vm_mapping)?;
+        task.vm_manager.write().restore_memory_maps(self.vm_mapping)?;
         
         // Restore sizes and name
         task.text_size = self.text_size;
         task.data_size = self.data_size;
         task.stack_size = self.stack_size;
+        task.stack_top = self.stack_top;
+        task.vm_manager.write().set_program_break(self.brk_start, self.brk);
         task.name = self.name;
+        task.exec_path = self.exec_path;
+        task.vm_manager.write().set_mmap_limit(self.mmap_limit);
+        task.vcpu.set_xlen(self.xlen);
         
         // Restore trapframe
         *trapframe = self.trapframe;
         
         Ok(())
     }
+
+    /// Discard the backup after a successful exec
+    ///
+    /// The old address space is gone for good, so mapping owners are told
+    /// that their mappings were removed.
+    fn discard(self) {
+        for map in &self.vm_mapping {
+            map.notify_owner_unmapped();
+        }
+    }
 }
 
 /// Errors that can occur during transparent execution
@@ -185,11 +217,26 @@ impl TransparentExecutor {
         trapframe: &mut Trapframe,
         force_abi_rebuild: bool,
     ) -> ExecutorResult<()> {
+        // Other threads would lose the address space under them
+        if task.vm_manager.is_shared() {
+            return Err(ExecutorError::ExecutionFailed("exec from a multi-threaded task is not supported".to_string()));
+        }
+
+        // The task is half replaced until the exec completes or is rolled
+        // back, so it must not be switched away at a preemption point
+        let _preempt = PreemptGuard::new();
+
         // Step 1: Create backup of current task state
         let backup = TaskStateBackup::create_backup(task, trapframe);
+
+        // The owner of the binary for the audit log, looked up before the
+        // exec may replace the VFS the path refers to
+        let owner = task.get_vfs()
+            .and_then(|vfs| vfs.metadata(&vfs.resolve_path_to_absolute(path)).ok())
+            .and_then(|metadata| metadata.owner);
         
         // Execute with unified error handling and restoration
-        let result = Self::execute_implementation(path, argv, envp, explicit_abi, task, trapframe, force_abi_rebuild);
+        let result = Self::execute_implementation(path, argv, envp, explicit_abi, task, trapframe, force_abi_rebuild, 0);
         
         // If execution failed, restore original state
         if result.is_err() {
@@ -197,6 +244,12 @@ impl TransparentExecutor {
                 // Log restore error but don't override original error
                 crate::early_println!("Warning: Failed to restore task state after exec failure: {}", restore_err);
             }
+        } else {
+            backup.discard();
+            // Handlers of the old program do not exist in the new one
+            task.signals.reset_on_exec();
+            crate::audit::exec(task, path, owner);
+            task.cred.exec();
         }
         
         result
@@ -205,6 +258,7 @@ impl TransparentExecutor {
     /// Core execution implementation with flags support
     /// 
     /// This method contains the actual execution logic without backup/restore handling.
+    /// `depth` counts the interpreters (see [`binfmt`]) the exec went through.
     fn execute_implementation(
         path: &str,
         argv: &[&str],
@@ -213,12 +267,21 @@ impl TransparentExecutor {
         task: &mut Task,
         trapframe: &mut Trapframe,
         force_abi_rebuild: bool,
+        depth: usize,
     ) -> ExecutorResult<()> {
         // Step 1: Open binary file and determine ABI
         let file_object = Self::open_file(path, task)?;
+        let note = file_object.as_file().and_then(read_abi_note);
         let abi_name = match explicit_abi {
             Some(name) => name.to_string(),
-            None => Self::detect_abi(&file_object, path)?,
+            None => match Self::detect_abi(&file_object, path, note.as_ref()) {
+                Ok(name) => name,
+                // No ABI knows the format: a registered interpreter may
+                Err(ExecutorError::UnknownBinaryFormat) => {
+                    return Self::execute_with_interpreter(path, &file_object, argv, envp, task, trapframe, force_abi_rebuild, depth);
+                }
+                Err(e) => return Err(e),
+            },
         };
         
         // Step 2: Get ABI module instance
@@ -236,8 +299,22 @@ impl TransparentExecutor {
         }
         
         // Step 5: Execute binary through ABI module (pass envp directly)
+        // A 64-bit layout unless the ABI sets up another one
+        task.vm_manager.write().set_mmap_limit(DEFAULT_MMAP_LIMIT);
+        task.vcpu.set_xlen(Xlen::X64);
+        // The path the exec was asked for, not that of an interpreter it
+        // goes through
+        if depth == 0 {
+            task.exec_path = path.to_string();
+        }
         abi.execute_binary(&file_object, argv, envp, task, trapframe)
             .map_err(|e| ExecutorError::ExecutionFailed(e.to_string()))?;
+
+        // The version the program was built for if the ABI implements it,
+        // the version of the ABI otherwise
+        task.abi_version = note.as_ref()
+            .and_then(|note| abi.negotiate_version(note))
+            .unwrap_or_else(|| abi.version());
         
         // Step 6: Update task's ABI if switch occurred
         if abi_switch_required {
@@ -255,6 +332,9 @@ impl TransparentExecutor {
     fn open_file(path: &str, task: &Task) -> ExecutorResult<crate::object::KernelObject> {
         if let Some(vfs) = task.get_vfs() {
             let absolute_path = vfs.resolve_path_to_absolute(path);
+            if task.cred.check_path(vfs, &absolute_path, MAY_EXEC).is_err() {
+                return Err(ExecutorError::ExecutionFailed("Permission denied".to_string()));
+            }
             
             match vfs.open(&absolute_path, 0) { // O_RDONLY
                 Ok(obj) => {
@@ -269,9 +349,39 @@ impl TransparentExecutor {
         }
     }
 
+    /// Execute a binary no ABI recognizes through the interpreter registered
+    /// for it (see [`binfmt`])
+    fn execute_with_interpreter(
+        path: &str,
+        file_object: &crate::object::KernelObject,
+        argv: &[&str],
+        envp: &[&str],
+        task: &mut Task,
+        trapframe: &mut Trapframe,
+        force_abi_rebuild: bool,
+        depth: usize,
+    ) -> ExecutorResult<()> {
+        if depth >= binfmt::MAX_INTERPRETER_DEPTH {
+            return Err(ExecutorError::UnknownBinaryFormat);
+        }
+        let file = file_object.as_file().ok_or(ExecutorError::UnknownBinaryFormat)?;
+        let mut header = [0u8; binfmt::BINPRM_BUF_SIZE];
+        let read = file.seek(SeekFrom::Start(0)).and_then(|_| file.read(&mut header)).unwrap_or(0);
+        let abs_path = match task.get_vfs() {
+            Some(vfs) => vfs.resolve_path_to_absolute(path),
+            None => path.to_string(),
+        };
+        let interpreter = binfmt::find_interpreter(&header[..read], &abs_path)
+            .ok_or(ExecutorError::UnknownBinaryFormat)?;
+        let interpreter_argv = interpreter.build_argv(&abs_path, argv);
+        Self::execute_implementation(
+            &interpreter.interpreter, &interpreter_argv, envp, None, task, trapframe, force_abi_rebuild, depth + 1,
+        )
+    }
+
     /// Detect ABI from file object
-    fn detect_abi(file_object: &crate::object::KernelObject, path: &str) -> ExecutorResult<String> {
-        match crate::abi::AbiRegistry::detect_best_abi(file_object, path) {
+    fn detect_abi(file_object: &crate::object::KernelObject, path: &str, note: Option<&AbiNote>) -> ExecutorResult<String> {
+        match crate::abi::AbiRegistry::detect_best_abi(file_object, path, note) {
             Some((abi_name, _confidence)) => Ok(abi_name),
             None => Err(ExecutorError::UnknownBinaryFormat),
         }
@@ -296,6 +406,7 @@ impl TransparentExecutor {
             .map_err(|e| ExecutorError::ExecutionFailed(e.to_string()))?;
         
         task.vfs = Some(clean_vfs);
+        crate::audit::namespace("vfs", "exec", task.get_id());
         
         // Get base VFS (global VFS) for overlay and shared resources
         let base_vfs = get_global_vfs_manager();
@@ -325,7 +436,13 @@ impl TransparentExecutor {
             abi.setup_overlay_environment(vfs_arc, &base_vfs, &system_path, &config_path)
                 .map_err(|e| ExecutorError::ExecutionFailed(e.to_string()))?;
             
-            // Step 2: Shared resources setup with base VFS
+            // Step 2: The ABI's view of the base VFS and of /proc and /dev
+            crate::abi::apply_personality(abi.personality_nodes(), vfs_arc, &base_vfs)
+                .map_err(|e| ExecutorError::ExecutionFailed(
+                    alloc::format!("Failed to set up the view of ABI {}: {}", abi_name, e)
+                ))?;
+
+            // Step 3: Shared resources setup with base VFS
             match abi.setup_shared_resources(vfs_arc, &base_vfs) {
                 Ok(()) => {}
                 Err(e) => {
@@ -16,6 +16,7 @@
 //! - **ABI Modules**: Handle their own binary formats and conversions
 //! - **No ABI knowledge in core**: Core does not know about specific ABIs
 
+pub mod binfmt;
 pub mod executor;
 
 #[cfg(test)]
@@ -294,6 +294,20 @@ pub struct FilePermission {
     pub execute: bool,
 }
 
+/// Owner and mode bits of a file, on filesystems that record them
+///
+/// Permission checks against the credentials of a task (see
+/// `crate::task::cred`) use these when present and fall back to
+/// [`FilePermission`] otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileOwner {
+    pub uid: u32,
+    pub gid: u32,
+    /// Permission bits (`0o7777`): owner, group and other rwx plus the
+    /// set-user-ID, set-group-ID and sticky bits
+    pub mode: u32,
+}
+
 #[derive(Debug, Clone)]
 pub struct FileMetadata {
     pub file_type: FileType,
@@ -309,6 +323,8 @@ pub struct FileMetadata {
     /// Number of hard links pointing to this file
     /// File data is only deleted when link_count reaches zero
     pub link_count: u32,
+    /// Owner and mode bits, `None` if the filesystem does not record them
+    pub owner: Option<FileOwner>,
 }
 
 /// Structure representing a directory entry (internal representation)
@@ -13,7 +13,7 @@ use core::{any::Any, fmt::Debug};
 use core::fmt;
 
 use crate::fs::{FileSystemError, FileSystemErrorKind, FileMetadata, FileObject, FileType, SeekFrom};
-use crate::object::capability::{StreamOps, ControlOps, MemoryMappingOps, StreamError};
+use crate::object::capability::{StreamOps, ControlOps, MemoryMappingOps, PollOps, StreamError};
 use super::mount_tree::MountPoint;
 
 /// DirectoryEntry structure used by readdir
@@ -265,6 +265,15 @@ pub trait FileSystemOperations: Send + Sync {
     /// Access to Any trait for downcasting
     fn as_any(&self) -> &dyn Any;
 
+    /// Check if this filesystem stores its data on the given device
+    /// 
+    /// Used to find the mounts that must be invalidated when a device is
+    /// hot-removed. Filesystems without a backing device keep the default.
+    fn is_backed_by(&self, device: &dyn crate::device::Device) -> bool {
+        let _ = device;
+        false
+    }
+
     /// Create a hard link to an existing file
     /// 
     /// This method creates a hard link from `link_name` in `link_parent` to the existing
@@ -409,6 +418,10 @@ impl FileObject for VfsFileObject {
         self.inner.truncate(size)
     }
 
+    fn as_pollable(&self) -> Option<&dyn PollOps> {
+        self.inner.as_pollable()
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
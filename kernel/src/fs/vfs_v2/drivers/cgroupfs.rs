@@ -0,0 +1,676 @@
+//! CgroupFS - Resource group control filesystem
+//!
+//! CgroupFS exposes the tree of resource groups (see
+//! `crate::task::cgroup`) as directories: the root of the filesystem is the
+//! root group and every subdirectory is a child group. Creating a directory
+//! creates a group and removing one removes the group, which must have no
+//! tasks, child groups or charged memory left. A container runtime creates
+//! a group per container, sets its limits and writes the container's first
+//! task to `cgroup.procs`; the tasks it starts stay in the group.
+//!
+//! ## Files
+//!
+//! Every group directory contains:
+//!
+//! - **cgroup.procs**: IDs of the tasks in the group, one per line; writing
+//!   a task ID moves that task into the group
+//! - **cpu.shares**: CPU weight of the group (1024 by default); accepts a
+//!   new weight
+//! - **memory.max**: memory ceiling in bytes or `max`; accepts either
+//! - **memory.current**: memory charged to the group and the groups below
+//!   it, in bytes
+//! - **memory.events**: `oom_kill` count of tasks killed for going over the
+//!   ceiling
+//!
+//! The root group has no CPU shares and no memory ceiling; writing them
+//! fails.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! // Mount cgroupfs at /sys/fs/cgroup
+//! let cgroupfs = CgroupFS::new();
+//! vfs.mount(cgroupfs, "/sys/fs/cgroup", 0)?;
+//!
+//! // Create a group limited to 64 MiB
+//! vfs.create_dir("/sys/fs/cgroup/box")?;
+//! let file = vfs.open("/sys/fs/cgroup/box/memory.max", O_WRONLY)?;
+//! ```
+
+use alloc::{
+    boxed::Box, format, string::{String, ToString}, sync::{Arc, Weak}, vec::Vec
+};
+use spin::RwLock;
+use core::any::Any;
+
+use crate::{driver_initcall, fs::{
+    get_fs_driver_manager, FileMetadata, FileObject, FilePermission, FileSystemDriver,
+    FileSystemError, FileSystemErrorKind, FileSystemType, FileType, SeekFrom
+}, object::capability::MemoryMappingOps};
+use crate::object::capability::{StreamOps, StreamError, ControlOps};
+
+use crate::task::cgroup::{self, GroupId, ROOT_GROUP};
+
+use super::super::core::{VfsNode, FileSystemOperations, DirectoryEntryInternal};
+
+/// Renders a file of a group directory, `None` if the group is gone
+type GroupReadFn = fn(GroupId) -> Option<String>;
+
+/// Applies data written to a file of a group directory
+type GroupWriteFn = fn(GroupId, &str) -> Result<(), &'static str>;
+
+/// Files in every group directory
+const GROUP_FILES: [(&str, GroupReadFn, Option<GroupWriteFn>); 5] = [
+    ("cgroup.procs", read_procs, Some(write_procs)),
+    ("cpu.shares", read_cpu_shares, Some(write_cpu_shares)),
+    ("memory.max", read_memory_max, Some(write_memory_max)),
+    ("memory.current", read_memory_current, None),
+    ("memory.events", read_memory_events, None),
+];
+
+fn read_procs(group: GroupId) -> Option<String> {
+    if !cgroup::exists(group) {
+        return None;
+    }
+    Some(cgroup::group_tasks(group).iter().map(|pid| format!("{}\n", pid)).collect())
+}
+
+fn write_procs(group: GroupId, data: &str) -> Result<(), &'static str> {
+    let pid = data.parse::<usize>().map_err(|_| "Invalid task ID")?;
+    cgroup::attach_task(group, pid)
+}
+
+fn read_cpu_shares(group: GroupId) -> Option<String> {
+    let shares = cgroup::cpu_shares(group)?;
+    Some(format!("{}\n", shares))
+}
+
+fn write_cpu_shares(group: GroupId, data: &str) -> Result<(), &'static str> {
+    let shares = data.parse::<u32>().map_err(|_| "Invalid CPU shares")?;
+    cgroup::set_cpu_shares(group, shares)
+}
+
+fn read_memory_max(group: GroupId) -> Option<String> {
+    if !cgroup::exists(group) {
+        return None;
+    }
+    Some(match cgroup::memory_max(group) {
+        Some(bytes) => format!("{}\n", bytes),
+        None => "max\n".to_string(),
+    })
+}
+
+fn write_memory_max(group: GroupId, data: &str) -> Result<(), &'static str> {
+    let bytes = match data {
+        "max" => None,
+        bytes => Some(bytes.parse::<usize>().map_err(|_| "Invalid memory ceiling")?),
+    };
+    cgroup::set_memory_max(group, bytes)
+}
+
+fn read_memory_current(group: GroupId) -> Option<String> {
+    let bytes = cgroup::memory_current(group)?;
+    Some(format!("{}\n", bytes))
+}
+
+fn read_memory_events(group: GroupId) -> Option<String> {
+    let kills = cgroup::oom_kills(group)?;
+    Some(format!("oom_kill {}\n", kills))
+}
+
+/// File ID of a group directory (`index` 0) or of a file in it
+fn group_file_id(group: GroupId, index: usize) -> u64 {
+    ((group as u64) << 8) | index as u64
+}
+
+/// CgroupFS - Resource group control filesystem
+pub struct CgroupFS {
+    /// Root directory node (the root group)
+    root: Arc<CgroupNode>,
+    /// Filesystem name
+    name: String,
+}
+
+impl CgroupFS {
+    /// Create a new CgroupFS instance
+    pub fn new() -> Arc<Self> {
+        let root = Arc::new(CgroupNode::new("/".to_string(), ROOT_GROUP, 0));
+        let fs = Arc::new(Self {
+            root: Arc::clone(&root),
+            name: "cgroupfs".to_string(),
+        });
+        let fs_weak = Arc::downgrade(&(fs.clone() as Arc<dyn FileSystemOperations>));
+        root.set_filesystem(fs_weak);
+        fs
+    }
+
+    fn downcast_node(node: &Arc<dyn VfsNode>) -> Result<Arc<CgroupNode>, FileSystemError> {
+        Arc::downcast::<CgroupNode>(node.clone())
+            .map_err(|_| FileSystemError::new(
+                FileSystemErrorKind::NotSupported,
+                "Invalid node type for CgroupFS"
+            ))
+    }
+
+    /// Downcast a node that must be a group directory
+    fn downcast_dir(node: &Arc<dyn VfsNode>) -> Result<Arc<CgroupNode>, FileSystemError> {
+        let node = Self::downcast_node(node)?;
+        if node.index != 0 {
+            return Err(FileSystemError::new(
+                FileSystemErrorKind::NotADirectory,
+                "Not a directory"
+            ));
+        }
+        Ok(node)
+    }
+
+    fn new_node(&self, name: String, group: GroupId, index: usize) -> Arc<dyn VfsNode> {
+        let node = Arc::new(CgroupNode::new(name, group, index));
+        if let Some(fs_ref) = self.root.filesystem() {
+            node.set_filesystem(fs_ref);
+        }
+        node as Arc<dyn VfsNode>
+    }
+}
+
+impl FileSystemOperations for CgroupFS {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn root_node(&self) -> Arc<dyn VfsNode> {
+        Arc::clone(&self.root) as Arc<dyn VfsNode>
+    }
+
+    fn lookup(&self, parent: &Arc<dyn VfsNode>, name: &String) -> Result<Arc<dyn VfsNode>, FileSystemError> {
+        let parent = Self::downcast_dir(parent)?;
+        if let Some(index) = GROUP_FILES.iter().position(|(file, _, _)| *file == name.as_str()) {
+            return Ok(self.new_node(name.clone(), parent.group, index + 1));
+        }
+        let group = cgroup::lookup_child(parent.group, name).ok_or_else(|| FileSystemError::new(
+            FileSystemErrorKind::NotFound,
+            format!("'{}' not found in cgroupfs", name)
+        ))?;
+        Ok(self.new_node(name.clone(), group, 0))
+    }
+
+    fn readdir(&self, node: &Arc<dyn VfsNode>) -> Result<Vec<DirectoryEntryInternal>, FileSystemError> {
+        Self::downcast_node(node)?.readdir()
+    }
+
+    fn open(&self, node: &Arc<dyn VfsNode>, _flags: u32) -> Result<Arc<dyn FileObject>, FileSystemError> {
+        let node = Self::downcast_node(node)?;
+        if node.index == 0 {
+            return Ok(Arc::new(CgroupDirectoryObject::new(node)));
+        }
+        let (_, read, write) = GROUP_FILES[node.index - 1];
+        let content = read(node.group).ok_or_else(|| FileSystemError::new(
+            FileSystemErrorKind::NotFound,
+            format!("The group of '{}' no longer exists", node.name)
+        ))?;
+        Ok(Arc::new(CgroupFileObject::new(node, content, write)))
+    }
+
+    fn is_read_only(&self) -> bool {
+        false
+    }
+
+    /// Create a child group; there are no other files to create
+    fn create(&self, parent: &Arc<dyn VfsNode>, name: &String, file_type: FileType, _mode: u32) -> Result<Arc<dyn VfsNode>, FileSystemError> {
+        let parent = Self::downcast_dir(parent)?;
+        if file_type != FileType::Directory {
+            return Err(FileSystemError::new(
+                FileSystemErrorKind::NotSupported,
+                "CgroupFS only supports creating directories"
+            ));
+        }
+        if GROUP_FILES.iter().any(|(file, _, _)| *file == name.as_str())
+            || cgroup::lookup_child(parent.group, name).is_some() {
+            return Err(FileSystemError::new(
+                FileSystemErrorKind::AlreadyExists,
+                format!("'{}' already exists", name)
+            ));
+        }
+        let group = cgroup::create_group(parent.group, name)
+            .map_err(|e| FileSystemError::new(FileSystemErrorKind::InvalidPath, e))?;
+        Ok(self.new_node(name.clone(), group, 0))
+    }
+
+    /// Remove a child group
+    fn remove(&self, parent: &Arc<dyn VfsNode>, name: &String) -> Result<(), FileSystemError> {
+        let parent = Self::downcast_dir(parent)?;
+        if GROUP_FILES.iter().any(|(file, _, _)| *file == name.as_str()) {
+            return Err(FileSystemError::new(
+                FileSystemErrorKind::PermissionDenied,
+                "Cannot remove group files"
+            ));
+        }
+        if cgroup::lookup_child(parent.group, name).is_none() {
+            return Err(FileSystemError::new(
+                FileSystemErrorKind::NotFound,
+                format!("'{}' not found in cgroupfs", name)
+            ));
+        }
+        cgroup::remove_group(parent.group, name)
+            .map_err(|e| FileSystemError::new(FileSystemErrorKind::Busy, e))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// A node in the CgroupFS filesystem
+pub struct CgroupNode {
+    /// Node name
+    name: String,
+    /// Group of the directory, or of the directory the file is in
+    group: GroupId,
+    /// 0 for the group directory, else the file `index - 1` of `GROUP_FILES`
+    index: usize,
+    /// Reference to filesystem
+    filesystem: RwLock<Option<Weak<dyn FileSystemOperations>>>,
+}
+
+impl CgroupNode {
+    fn new(name: String, group: GroupId, index: usize) -> Self {
+        Self {
+            name,
+            group,
+            index,
+            filesystem: RwLock::new(None),
+        }
+    }
+
+    fn file_type(&self) -> FileType {
+        if self.index == 0 { FileType::Directory } else { FileType::RegularFile }
+    }
+
+    /// Set filesystem reference
+    pub fn set_filesystem(&self, fs: Weak<dyn FileSystemOperations>) {
+        *self.filesystem.write() = Some(fs);
+    }
+
+    /// Read directory contents
+    pub fn readdir(&self) -> Result<Vec<DirectoryEntryInternal>, FileSystemError> {
+        if self.index != 0 {
+            return Err(FileSystemError::new(
+                FileSystemErrorKind::NotADirectory,
+                "Cannot read directory of non-directory node"
+            ));
+        }
+
+        // The root is its own parent
+        let parent = cgroup::parent(self.group).unwrap_or(self.group);
+        let mut entries = Vec::new();
+        for (name, group) in [(".", self.group), ("..", parent)] {
+            entries.push(DirectoryEntryInternal {
+                name: name.to_string(),
+                file_type: FileType::Directory,
+                file_id: group_file_id(group, 0),
+            });
+        }
+        for (index, (name, _, _)) in GROUP_FILES.iter().enumerate() {
+            entries.push(DirectoryEntryInternal {
+                name: name.to_string(),
+                file_type: FileType::RegularFile,
+                file_id: group_file_id(self.group, index + 1),
+            });
+        }
+        for (name, group) in cgroup::children(self.group) {
+            entries.push(DirectoryEntryInternal {
+                name,
+                file_type: FileType::Directory,
+                file_id: group_file_id(group, 0),
+            });
+        }
+        Ok(entries)
+    }
+}
+
+impl VfsNode for CgroupNode {
+    fn id(&self) -> u64 {
+        group_file_id(self.group, self.index)
+    }
+
+    fn metadata(&self) -> Result<FileMetadata, FileSystemError> {
+        let writable = self.index == 0 || GROUP_FILES[self.index - 1].2.is_some();
+        Ok(FileMetadata {
+            file_type: self.file_type(),
+            size: 0, // Generated files have no size until they are read
+            permissions: FilePermission {
+                read: true,
+                write: writable,
+                execute: self.index == 0,
+            },
+            created_time: 0,
+            modified_time: 0,
+            accessed_time: 0,
+            file_id: self.id(),
+            link_count: 1,
+            owner: None,
+        })
+    }
+
+    fn filesystem(&self) -> Option<Weak<dyn FileSystemOperations>> {
+        self.filesystem.read().clone()
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// An open group file
+///
+/// Holds the content generated at open time; reads and seeks work on that
+/// snapshot, each write is applied to the group as a whole.
+pub struct CgroupFileObject {
+    /// Reference to the CgroupNode
+    node: Arc<CgroupNode>,
+    /// Write handler of the file, if it accepts writes
+    write: Option<GroupWriteFn>,
+    /// Content generated when the file was opened
+    content: Vec<u8>,
+    /// Current read position
+    position: RwLock<u64>,
+}
+
+impl CgroupFileObject {
+    fn new(node: Arc<CgroupNode>, content: String, write: Option<GroupWriteFn>) -> Self {
+        Self {
+            node,
+            write,
+            content: content.into_bytes(),
+            position: RwLock::new(0),
+        }
+    }
+}
+
+impl StreamOps for CgroupFileObject {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, StreamError> {
+        let mut position = self.position.write();
+        let start = (*position as usize).min(self.content.len());
+        let len = buffer.len().min(self.content.len() - start);
+        buffer[..len].copy_from_slice(&self.content[start..start + len]);
+        *position += len as u64;
+        Ok(len)
+    }
+
+    fn write(&self, buffer: &[u8]) -> Result<usize, StreamError> {
+        let invalid = |e| StreamError::from(FileSystemError::new(FileSystemErrorKind::InvalidData, e));
+        let write = self.write.ok_or(StreamError::from(FileSystemError::new(
+            FileSystemErrorKind::PermissionDenied,
+            "Group file is read-only"
+        )))?;
+        let data = core::str::from_utf8(buffer).map_err(|_| invalid("Invalid UTF-8"))?;
+        write(self.node.group, data.trim()).map_err(invalid)?;
+        Ok(buffer.len())
+    }
+}
+
+impl ControlOps for CgroupFileObject {
+    // Group files don't support control operations
+    fn control(&self, _command: u32, _arg: usize) -> Result<i32, &'static str> {
+        Err("Control operations not supported on group files")
+    }
+}
+
+impl MemoryMappingOps for CgroupFileObject {
+    fn get_mapping_info(&self, _offset: usize, _length: usize)
+                       -> Result<(usize, usize, bool), &'static str> {
+        Err("Memory mapping not supported for group files")
+    }
+
+    fn supports_mmap(&self) -> bool {
+        false
+    }
+}
+
+impl FileObject for CgroupFileObject {
+    fn seek(&self, whence: SeekFrom) -> Result<u64, StreamError> {
+        let mut position = self.position.write();
+        let len = self.content.len() as u64;
+
+        let new_pos = match whence {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::Current(offset) => {
+                if offset >= 0 {
+                    *position + offset as u64
+                } else {
+                    position.saturating_sub((-offset) as u64)
+                }
+            }
+            SeekFrom::End(offset) => {
+                if offset >= 0 {
+                    len + offset as u64
+                } else {
+                    len.saturating_sub((-offset) as u64)
+                }
+            }
+        };
+
+        *position = new_pos;
+        Ok(new_pos)
+    }
+
+    fn metadata(&self) -> Result<FileMetadata, StreamError> {
+        let mut metadata = self.node.metadata().map_err(StreamError::from)?;
+        metadata.size = self.content.len();
+        Ok(metadata)
+    }
+
+    fn truncate(&self, _size: u64) -> Result<(), StreamError> {
+        Err(StreamError::from(FileSystemError::new(
+            FileSystemErrorKind::NotSupported,
+            "Cannot truncate group files"
+        )))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// A file object for a group directory
+pub struct CgroupDirectoryObject {
+    /// Reference to the CgroupNode
+    node: Arc<CgroupNode>,
+    /// Current position in directory entries (entry index)
+    position: RwLock<usize>,
+}
+
+impl CgroupDirectoryObject {
+    /// Create a new directory file object
+    pub fn new(node: Arc<CgroupNode>) -> Self {
+        Self {
+            node,
+            position: RwLock::new(0),
+        }
+    }
+}
+
+impl StreamOps for CgroupDirectoryObject {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, StreamError> {
+        let entries = self.node.readdir().map_err(StreamError::from)?;
+        let position = *self.position.read();
+
+        if position >= entries.len() {
+            return Ok(0); // EOF
+        }
+
+        let internal_entry = &entries[position];
+        let internal_with_size = crate::fs::DirectoryEntryInternal {
+            name: internal_entry.name.clone(),
+            file_type: internal_entry.file_type.clone(),
+            size: 0,
+            file_id: internal_entry.file_id,
+            metadata: None,
+        };
+
+        let dir_entry = crate::fs::DirectoryEntry::from_internal(&internal_with_size);
+        let entry_size = dir_entry.entry_size();
+
+        if buffer.len() < entry_size {
+            return Err(StreamError::InvalidArgument); // Buffer too small
+        }
+
+        let entry_bytes = unsafe {
+            core::slice::from_raw_parts(
+                &dir_entry as *const _ as *const u8,
+                entry_size
+            )
+        };
+        buffer[..entry_size].copy_from_slice(entry_bytes);
+
+        *self.position.write() += 1;
+        Ok(entry_size)
+    }
+
+    fn write(&self, _buffer: &[u8]) -> Result<usize, StreamError> {
+        Err(StreamError::from(FileSystemError::new(
+            FileSystemErrorKind::IsADirectory,
+            "Cannot write to directory in cgroupfs"
+        )))
+    }
+}
+
+impl ControlOps for CgroupDirectoryObject {
+    // Directory objects don't support control operations by default
+    fn control(&self, _command: u32, _arg: usize) -> Result<i32, &'static str> {
+        Err("Control operations not supported on directories")
+    }
+}
+
+impl MemoryMappingOps for CgroupDirectoryObject {
+    fn get_mapping_info(&self, _offset: usize, _length: usize)
+                       -> Result<(usize, usize, bool), &'static str> {
+        Err("Memory mapping not supported for directories")
+    }
+
+    fn supports_mmap(&self) -> bool {
+        false
+    }
+}
+
+impl FileObject for CgroupDirectoryObject {
+    fn seek(&self, whence: SeekFrom) -> Result<u64, StreamError> {
+        let entry_count = self.node.readdir().map_err(StreamError::from)?.len() as u64;
+        let mut position = self.position.write();
+
+        let new_pos = match whence {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::Current(offset) => {
+                if offset >= 0 {
+                    *position as u64 + offset as u64
+                } else {
+                    (*position as u64).saturating_sub((-offset) as u64)
+                }
+            },
+            SeekFrom::End(offset) => {
+                if offset >= 0 {
+                    entry_count + offset as u64
+                } else {
+                    entry_count.saturating_sub((-offset) as u64)
+                }
+            }
+        };
+
+        *position = new_pos as usize;
+        Ok(new_pos)
+    }
+
+    fn metadata(&self) -> Result<FileMetadata, StreamError> {
+        self.node.metadata().map_err(StreamError::from)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// CgroupFS filesystem driver
+pub struct CgroupFSDriver;
+
+impl FileSystemDriver for CgroupFSDriver {
+    fn name(&self) -> &'static str {
+        "cgroupfs"
+    }
+
+    fn filesystem_type(&self) -> FileSystemType {
+        FileSystemType::Virtual
+    }
+
+    fn create(&self) -> Result<Arc<dyn FileSystemOperations>, FileSystemError> {
+        Ok(CgroupFS::new() as Arc<dyn FileSystemOperations>)
+    }
+
+    fn create_from_option_string(&self, _options: &str) -> Result<Arc<dyn FileSystemOperations>, FileSystemError> {
+        // CgroupFS doesn't use options: every instance shows the one group tree
+        self.create()
+    }
+}
+
+/// Register the CgroupFS driver with the filesystem driver manager
+fn register_driver() {
+    let fs_driver_manager = get_fs_driver_manager();
+    fs_driver_manager.register_driver(Box::new(CgroupFSDriver));
+}
+
+driver_initcall!(register_driver);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(file: &Arc<dyn FileObject>) -> String {
+        let mut buffer = [0u8; 128];
+        let len = file.read(&mut buffer).unwrap();
+        String::from_utf8(buffer[..len].to_vec()).unwrap()
+    }
+
+    #[test_case]
+    fn test_cgroupfs_group_directories() {
+        let cgroupfs = CgroupFS::new();
+        let root = cgroupfs.root_node();
+
+        let dir = cgroupfs.create(&root, &"test_cgroupfs_dir".to_string(), FileType::Directory, 0o755).unwrap();
+        assert!(cgroupfs.create(&root, &"test_cgroupfs_dir".to_string(), FileType::Directory, 0o755).is_err());
+        assert!(cgroupfs.create(&root, &"file".to_string(), FileType::RegularFile, 0o644).is_err());
+        assert!(cgroupfs.readdir(&root).unwrap().iter().any(|entry| entry.name == "test_cgroupfs_dir"));
+        let names: Vec<String> = cgroupfs.readdir(&dir).unwrap().into_iter().map(|entry| entry.name).collect();
+        assert!(names.iter().any(|name| name == "cgroup.procs"));
+        assert!(names.iter().any(|name| name == "memory.max"));
+
+        assert!(cgroupfs.remove(&root, &"cgroup.procs".to_string()).is_err());
+        cgroupfs.remove(&root, &"test_cgroupfs_dir".to_string()).unwrap();
+        assert!(cgroupfs.lookup(&root, &"test_cgroupfs_dir".to_string()).is_err());
+    }
+
+    #[test_case]
+    fn test_cgroupfs_limit_files() {
+        let cgroupfs = CgroupFS::new();
+        let root = cgroupfs.root_node();
+        let dir = cgroupfs.create(&root, &"test_cgroupfs_limits".to_string(), FileType::Directory, 0o755).unwrap();
+
+        let node = cgroupfs.lookup(&dir, &"memory.max".to_string()).unwrap();
+        assert!(node.metadata().unwrap().permissions.write);
+        assert_eq!(read_all(&cgroupfs.open(&node, 0).unwrap()), "max\n");
+        cgroupfs.open(&node, 0).unwrap().write(b"1048576\n").unwrap();
+        assert_eq!(read_all(&cgroupfs.open(&node, 0).unwrap()), "1048576\n");
+        assert!(cgroupfs.open(&node, 0).unwrap().write(b"lots").is_err());
+
+        let node = cgroupfs.lookup(&dir, &"cpu.shares".to_string()).unwrap();
+        cgroupfs.open(&node, 0).unwrap().write(b"512").unwrap();
+        assert_eq!(read_all(&cgroupfs.open(&node, 0).unwrap()), "512\n");
+
+        // The root group takes no limits
+        let node = cgroupfs.lookup(&root, &"memory.max".to_string()).unwrap();
+        assert!(cgroupfs.open(&node, 0).unwrap().write(b"4096").is_err());
+
+        let node = cgroupfs.lookup(&dir, &"memory.current".to_string()).unwrap();
+        assert!(!node.metadata().unwrap().permissions.write);
+        assert_eq!(read_all(&cgroupfs.open(&node, 0).unwrap()), "0\n");
+        cgroupfs.remove(&root, &"test_cgroupfs_limits".to_string()).unwrap();
+    }
+}
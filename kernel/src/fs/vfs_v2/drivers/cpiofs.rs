@@ -123,6 +123,7 @@ impl VfsNode for CpioNode {
             },
             file_id: self.file_id as u64,
             link_count: 1,
+            owner: None,
         })
     }
     
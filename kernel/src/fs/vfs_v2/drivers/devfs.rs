@@ -11,6 +11,8 @@
 //! - **Dynamic Updates**: Reflects changes when devices are added/removed
 //! - **Device File Support**: Exposes character and block devices as device files
 //! - **Read-only Filesystem**: Device files cannot be created/deleted through VFS
+//! - **Subdirectories**: A device named with slashes is placed in
+//!   directories, `input/event0` at `/dev/input/event0`
 //!
 //! ## Usage
 //!
@@ -35,7 +37,8 @@ use crate::{driver_initcall, fs::{
     FileSystemError, FileSystemErrorKind, FileSystemType, FileType, SeekFrom
 }, object::capability::MemoryMappingOps};
 use crate::device::{manager::DeviceManager, DeviceType, Device};
-use crate::object::capability::{StreamOps, StreamError, ControlOps};
+use crate::object::capability::{StreamOps, StreamError, ControlOps, PollOps};
+use crate::object::capability::poll::{POLLIN, POLLOUT};
 
 use super::super::core::{VfsNode, FileSystemOperations, DirectoryEntryInternal};
 
@@ -96,8 +99,12 @@ impl DevFS {
                         _ => continue, // Skip other device types
                     };
                     
+                    let (directory, file_name) = match device_name.rsplit_once('/') {
+                        Some((path, file_name)) => (Self::directory(&root, path)?, file_name),
+                        None => (root.clone(), device_name.as_str()),
+                    };
                     let device_node = Arc::new(DevNode::new_device_file(
-                        device_name.clone(),
+                        file_name.to_string(),
                         file_type,
                         device_id as u64, // Use the device ID as file ID too
                     ));
@@ -107,7 +114,7 @@ impl DevFS {
                         device_node.set_filesystem(fs_ref);
                     }
                     
-                    root.add_child(device_name, device_node)?;
+                    directory.add_child(file_name.to_string(), device_node)?;
                 }
                 _ => {} // Skip non-device files
             }
@@ -117,6 +124,44 @@ impl DevFS {
     }
 }
 
+impl DevFS {
+    /// The directory at `path` under `root`, created with its parents if
+    /// missing
+    fn directory(root: &Arc<DevNode>, path: &str) -> Result<Arc<DevNode>, FileSystemError> {
+        let mut directory = root.clone();
+        let mut walked = String::new();
+        for component in path.split('/').filter(|component| !component.is_empty()) {
+            walked.push('/');
+            walked.push_str(component);
+            directory = match directory.get_child(component) {
+                Some(child) if child.file_type == FileType::Directory => child,
+                Some(_) => {
+                    return Err(FileSystemError::new(
+                        FileSystemErrorKind::NotADirectory,
+                        format!("Device '{}' is in the way of a directory", walked)
+                    ));
+                }
+                None => {
+                    let child = Arc::new(DevNode::new_subdirectory(component.to_string(), directory_id(&walked)));
+                    if let Some(fs_ref) = root.filesystem() {
+                        child.set_filesystem(fs_ref);
+                    }
+                    directory.add_child(component.to_string(), child.clone())?;
+                    child
+                }
+            };
+        }
+        Ok(directory)
+    }
+}
+
+/// File ID of the subdirectory at `path`: a hash of the path, kept apart
+/// from the device IDs of device files by its top bit
+fn directory_id(path: &str) -> u64 {
+    let hash = path.bytes().fold(0xcbf29ce484222325u64, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3));
+    hash | 1 << 63
+}
+
 impl FileSystemOperations for DevFS {
     fn name(&self) -> &str {
         &self.name
@@ -233,6 +278,11 @@ impl DevNode {
         }
     }
 
+    /// Create a new directory node below the root
+    pub fn new_subdirectory(name: String, file_id: u64) -> Self {
+        Self { file_id, ..Self::new_directory(name) }
+    }
+
     /// Create a new device file node
     pub fn new_device_file(name: String, file_type: FileType, file_id: u64) -> Self {
         Self {
@@ -358,6 +408,7 @@ impl VfsNode for DevNode {
             accessed_time: 0,
             file_id: self.file_id,
             link_count: 1,
+            owner: None,
         })
     }
 
@@ -398,6 +449,11 @@ impl DevFileObject {
         // Try to get the device from DeviceManager by ID
         match DeviceManager::get_manager().get_device(device_id) {
             Some(device_guard) => {
+                // A device may give each open its own instance
+                let device_guard = match device_guard.open_instance() {
+                    Ok(instance) => instance.unwrap_or(device_guard),
+                    Err(e) => return Err(FileSystemError::new(FileSystemErrorKind::DeviceError, e)),
+                };
                 Ok(Self {
                     node,
                     position: RwLock::new(0),
@@ -580,11 +636,17 @@ impl DevFileObject {
 
 impl StreamOps for DevFileObject {
     fn read(&self, buffer: &mut [u8]) -> Result<usize, StreamError> {
-        self.read_device(buffer).map_err(StreamError::from)
+        let requested = buffer.len();
+        let bytes_read = self.read_device(buffer).map_err(StreamError::from)?;
+        crate::device::char::read_result(bytes_read, requested)
     }
     
     fn write(&self, buffer: &[u8]) -> Result<usize, StreamError> {
-        self.write_device(buffer).map_err(StreamError::from)
+        self.write_device(buffer).map_err(|error| match crate::task::mytask() {
+            // A writer stopped for a signal is interrupted, not failing
+            Some(task) if task.signals.has_pending() => StreamError::Interrupted,
+            _ => StreamError::from(error),
+        })
     }
 }
 
@@ -687,11 +749,39 @@ impl FileObject for DevFileObject {
         )))
     }
 
+    fn as_pollable(&self) -> Option<&dyn PollOps> {
+        Some(self)
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
 }
 
+impl PollOps for DevFileObject {
+    /// The readiness of a character device; other devices are always ready
+    fn poll_events(&self) -> u32 {
+        match self.device_guard.as_ref().and_then(|device| device.as_char_device()) {
+            Some(char_device) => crate::device::char::poll_events(char_device),
+            None => POLLIN | POLLOUT,
+        }
+    }
+
+    fn poll_register(&self, task_id: usize) -> bool {
+        match self.device_guard.as_ref().and_then(|device| device.as_char_device()) {
+            Some(char_device) => char_device.as_pollable().is_some_and(|pollable| pollable.poll_register(task_id)),
+            None => true,
+        }
+    }
+
+    fn poll_unregister(&self, task_id: usize) {
+        let char_device = self.device_guard.as_ref().and_then(|device| device.as_char_device());
+        if let Some(pollable) = char_device.and_then(|char_device| char_device.as_pollable()) {
+            pollable.poll_unregister(task_id);
+        }
+    }
+}
+
 /// A file object for directories in DevFS
 /// 
 /// This struct provides a FileObject implementation for directories
@@ -1097,4 +1187,26 @@ mod tests {
         let metadata = metadata_result.unwrap();
         assert_eq!(metadata.file_type, FileType::Directory, "Should be directory type");
     }
+
+    #[test_case]
+    fn test_devfs_subdirectories() {
+        use crate::device::char::mockchar::MockCharDevice;
+
+        let device_manager = DeviceManager::get_manager();
+        let char_device = Arc::new(MockCharDevice::new("event"));
+        let device_id = device_manager.register_device_with_name("test_devfs_dir/event0".to_string(), char_device);
+
+        // A device named with a slash is in a directory
+        let devfs = DevFS::new();
+        let root = devfs.root_node();
+        let entries = devfs.readdir(&root).unwrap();
+        assert!(entries.iter().any(|entry| entry.name == "test_devfs_dir" && entry.file_type == FileType::Directory));
+        assert!(!entries.iter().any(|entry| entry.name.contains('/')));
+        let directory = devfs.lookup(&root, &"test_devfs_dir".to_string()).unwrap();
+        let device = devfs.lookup(&directory, &"event0".to_string()).unwrap();
+        assert!(matches!(device.metadata().unwrap().file_type, FileType::CharDevice(_)));
+        assert_ne!(directory.id(), root.id());
+
+        device_manager.unregister_device(device_id);
+    }
 }
\ No newline at end of file
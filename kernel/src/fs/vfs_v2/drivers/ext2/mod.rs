@@ -29,9 +29,9 @@ use spin::{rwlock::RwLock, Mutex};
 use core::{mem, any::Any};
 
 use crate::{
-    device::block::BlockDevice, driver_initcall, fs::{
+    device::block::BlockDevice, driver_initcall, environment::PAGE_SIZE, fs::{
         get_fs_driver_manager, params::FileSystemParams, FileObject, FileSystemError, FileSystemErrorKind, FileType
-    }, task::mytask, DeviceManager,
+    }, mem::reclaim::{self, LruLists, Shrinker}, sched::preempt::cond_resched, task::mytask, DeviceManager,
     profile_scope,
 };
 
@@ -51,6 +51,10 @@ pub use structures::*;
 pub use node::{Ext2Node, Ext2FileObject, Ext2DirectoryObject, Ext2CharDeviceFileObject};
 pub use driver::Ext2Driver;
 
+/// Most blocks of a file read in one go by `read_file_content` before it
+/// lets other tasks run
+const READ_BATCH_BLOCKS: usize = 64;
+
 /// ext2 filesystem parameters for mount options
 /// 
 /// This struct holds the parameters parsed from mount option strings
@@ -499,28 +503,17 @@ impl InodeLruCache {
 /// Node ID type for the LRU cache
 type NodeId = u32;
 
-/// Node in doubly-linked list for O(1) LRU operations
-#[derive(Debug)]
-struct LruNode {
-    block_num: u64,
-    data: Vec<u8>,
-    prev: Option<NodeId>,
-    next: Option<NodeId>,
-}
-
-/// O(1) LRU cache implementation using HashMap + doubly-linked list
-/// This implementation uses indices instead of raw pointers to be thread-safe
+/// Block cache on active/inactive LRU lists
+///
+/// Blocks enter on the inactive list and move to the active list when they
+/// are read again, so a large sequential read cannot flush the metadata
+/// blocks that are used over and over. The cache is write-through, so every
+/// cached block is clean and can be dropped when memory runs low.
 struct BlockLruCache {
-    /// Map from block number to node ID
-    map: HashMap<u64, NodeId>,
-    /// Storage for all nodes
-    nodes: HashMap<NodeId, LruNode>,
-    /// Head of doubly-linked list (most recently used)
-    head: Option<NodeId>,
-    /// Tail of doubly-linked list (least recently used)  
-    tail: Option<NodeId>,
-    /// Next available node ID
-    next_id: NodeId,
+    /// Cached block data by block number
+    blocks: HashMap<u64, Vec<u8>>,
+    /// LRU order of the cached blocks
+    lru: LruLists<u64>,
     /// Maximum cache size
     max_size: usize,
     /// Cache statistics
@@ -531,11 +524,8 @@ struct BlockLruCache {
 impl BlockLruCache {
     fn new(max_size: usize) -> Self {
         Self {
-            map: HashMap::new(),
-            nodes: HashMap::new(),
-            head: None,
-            tail: None,
-            next_id: 0,
+            blocks: HashMap::new(),
+            lru: LruLists::new(),
             max_size,
             hits: 0,
             misses: 0,
@@ -543,142 +533,79 @@ impl BlockLruCache {
     }
 
     fn get(&mut self, block_num: u64) -> Option<Vec<u8>> {
-        if let Some(&node_id) = self.map.get(&block_num) {
+        if let Some(data) = self.blocks.get(&block_num) {
             self.hits += 1;
-            // Move to head (most recently used)
-            self.move_to_head(node_id);
-            // Return cloned data
-            self.nodes.get(&node_id).map(|node| node.data.clone())
+            self.lru.mark_accessed(block_num);
+            Some(data.clone())
         } else {
             self.misses += 1;
             None
         }
     }
 
-    /// Move node to head of LRU list (O(1))
-    fn move_to_head(&mut self, node_id: NodeId) {
-        if Some(node_id) == self.head {
-            return; // Already at head
-        }
-
-        // Remove from current position
-        self.remove_from_list(node_id);
-        
-        // Add to head
-        self.add_to_head(node_id);
-    }
-
-    /// Remove node from doubly-linked list (O(1))
-    fn remove_from_list(&mut self, node_id: NodeId) {
-        if let Some(node) = self.nodes.get(&node_id) {
-            let prev = node.prev;
-            let next = node.next;
-
-            // Update prev node's next pointer
-            if let Some(prev_id) = prev {
-                if let Some(prev_node) = self.nodes.get_mut(&prev_id) {
-                    prev_node.next = next;
-                }
-            } else {
-                // This was the head
-                self.head = next;
-            }
-
-            // Update next node's prev pointer
-            if let Some(next_id) = next {
-                if let Some(next_node) = self.nodes.get_mut(&next_id) {
-                    next_node.prev = prev;
-                }
-            } else {
-                // This was the tail
-                self.tail = prev;
-            }
-        }
-    }
-
-    /// Add node to head of list (O(1))
-    fn add_to_head(&mut self, node_id: NodeId) {
-        if let Some(node) = self.nodes.get_mut(&node_id) {
-            node.prev = None;
-            node.next = self.head;
-        }
-
-        if let Some(old_head) = self.head {
-            if let Some(old_head_node) = self.nodes.get_mut(&old_head) {
-                old_head_node.prev = Some(node_id);
-            }
-        } else {
-            // List was empty
-            self.tail = Some(node_id);
+    fn insert(&mut self, block_num: u64, block_data: Vec<u8>) {
+        if !self.blocks.contains_key(&block_num) && self.blocks.len() >= self.max_size {
+            self.shrink(1);
         }
-
-        self.head = Some(node_id);
+        self.blocks.insert(block_num, block_data);
+        self.lru.insert(block_num);
     }
 
-    fn insert(&mut self, block_num: u64, block_data: Vec<u8>) {
-        // If already exists, update and move to head
-        if let Some(&existing_id) = self.map.get(&block_num) {
-            if let Some(existing_node) = self.nodes.get_mut(&existing_id) {
-                existing_node.data = block_data;
-            }
-            self.move_to_head(existing_id);
-            return;
-        }
-        
-        // If cache is full, remove LRU (tail) item
-        if self.nodes.len() >= self.max_size {
-            if let Some(tail_id) = self.tail {
-                if let Some(tail_node) = self.nodes.get(&tail_id) {
-                    let tail_block_num = tail_node.block_num;
-                    self.map.remove(&tail_block_num);
-                }
-                self.remove_from_list(tail_id);
-                self.nodes.remove(&tail_id);
-            }
+    fn remove(&mut self, block_num: u64) {
+        if self.blocks.remove(&block_num).is_some() {
+            self.lru.remove(block_num);
         }
-        
-        // Create new node
-        let node_id = self.next_id;
-        self.next_id += 1;
-        
-        let new_node = LruNode {
-            block_num,
-            data: block_data,
-            prev: None,
-            next: None,
-        };
-        
-        // Insert into data structures
-        self.nodes.insert(node_id, new_node);
-        self.map.insert(block_num, node_id);
-        
-        // Add to head of list
-        self.add_to_head(node_id);
     }
 
-    fn remove(&mut self, block_num: u64) {
-        if let Some(&node_id) = self.map.get(&block_num) {
-            self.map.remove(&block_num);
-            self.remove_from_list(node_id);
-            self.nodes.remove(&node_id);
+    /// Drop up to `count` blocks, least recently used first
+    ///
+    /// # Returns
+    /// The number of blocks dropped
+    fn shrink(&mut self, count: usize) -> usize {
+        let mut dropped = 0;
+        while dropped < count {
+            let Some(block_num) = self.lru.pop_victim() else { break };
+            self.blocks.remove(&block_num);
+            dropped += 1;
         }
+        dropped
     }
 
     fn len(&self) -> usize {
-        self.nodes.len()
+        self.blocks.len()
     }
 
     /// Get cache statistics for debugging and performance analysis
     fn get_stats(&self) -> (u64, u64, usize) {
-        (self.hits, self.misses, self.nodes.len())
+        (self.hits, self.misses, self.blocks.len())
     }
 
     /// Print cache statistics
     fn print_stats(&self, cache_name: &str) {
         let total = self.hits + self.misses;
         let hit_rate = if total > 0 { (self.hits * 100) / total } else { 0 };
-        crate::early_println!("[ext2] {} Cache Stats: hits={}, misses={}, size={}, hit_rate={}%", 
-            cache_name, self.hits, self.misses, self.nodes.len(), hit_rate);
+        crate::early_println!("[ext2] {} Cache Stats: hits={}, misses={}, size={} (active={}, inactive={}), hit_rate={}%", 
+            cache_name, self.hits, self.misses, self.blocks.len(), self.lru.active_len(), self.lru.inactive_len(), hit_rate);
+    }
+}
+
+/// Cached blocks are given back to the allocator under memory pressure
+impl Shrinker for Ext2FileSystem {
+    fn name(&self) -> &str {
+        "ext2"
+    }
+
+    fn lru_pages(&self) -> (usize, usize) {
+        let cache = self.block_cache.lock();
+        let to_pages = |blocks: usize| (blocks * self.block_size as usize).div_ceil(PAGE_SIZE);
+        (to_pages(cache.lru.active_len()), to_pages(cache.lru.inactive_len()))
+    }
+
+    fn reclaim(&self, nr_pages: usize) -> usize {
+        let Some(mut cache) = self.block_cache.try_lock() else { return 0 };
+        let blocks_per_page = (PAGE_SIZE / self.block_size as usize).max(1);
+        let dropped = cache.shrink(nr_pages.saturating_mul(blocks_per_page));
+        (dropped * self.block_size as usize).div_ceil(PAGE_SIZE)
     }
 }
 
@@ -741,6 +668,7 @@ impl Ext2FileSystem {
         // Set filesystem reference in root node
         let fs_weak = Arc::downgrade(&(fs.clone() as Arc<dyn FileSystemOperations>));
         fs.root.read().set_filesystem(fs_weak);
+        reclaim::register_shrinker(Arc::downgrade(&(fs.clone() as Arc<dyn Shrinker>)));
 
         Ok(fs)
     }
@@ -1167,35 +1095,32 @@ impl Ext2FileSystem {
             return Ok(content);
         }
 
-        // Use batched block reading for better performance
+        // Blocks are read in batches, with a preemption point after each:
+        // a large file would otherwise hold the CPU until it is all read
         let block_nums = self.get_inode_blocks(&inode, 0, num_blocks)?;
-        
-        let mut block_nums_to_read = Vec::new();
-        for &block_num in block_nums.iter() {
+        let mut block_nums_to_read = Vec::with_capacity(READ_BATCH_BLOCKS);
+        for (index, &block_num) in block_nums.iter().enumerate() {
             if block_num > 0 {
                 block_nums_to_read.push(block_num);
-            } else {
-                // If there are pending blocks to read, read them first
-                if !block_nums_to_read.is_empty() {
-                    let blocks_data = self.read_blocks_cached(&block_nums_to_read)?;
-                    for data in blocks_data {
-                        content.extend_from_slice(&data);
-                    }
-                    block_nums_to_read.clear();
+            }
+            let last = index + 1 == block_nums.len();
+            let flush = block_num == 0 || last || block_nums_to_read.len() == READ_BATCH_BLOCKS;
+            if flush && !block_nums_to_read.is_empty() {
+                for data in self.read_blocks_cached(&block_nums_to_read)? {
+                    content.extend_from_slice(&data);
+                }
+                block_nums_to_read.clear();
+                if !last {
+                    cond_resched();
                 }
+            }
+            if block_num == 0 {
                 // Handle sparse block by adding zeros
                 let len_to_add = core::cmp::min(self.block_size as usize, size - content.len());
                 content.extend(core::iter::repeat(0).take(len_to_add));
             }
         }
 
-        if !block_nums_to_read.is_empty() {
-            let blocks_data = self.read_blocks_cached(&block_nums_to_read)?;
-            for data in blocks_data {
-                content.extend_from_slice(&data);
-            }
-        }
-
         // Truncate to the exact size
         content.truncate(size);
         Ok(content)
@@ -1294,6 +1219,7 @@ impl Ext2FileSystem {
         };
         
         // Write the inode data into the block
+        crate::mem::kasan::check_read(inode as *const Ext2Inode as usize, core::mem::size_of::<Ext2Inode>());
         let inode_bytes = unsafe {
             core::slice::from_raw_parts(
                 inode as *const Ext2Inode as *const u8,
@@ -2309,11 +2235,14 @@ impl Ext2FileSystem {
         let blocks_to_free = self.get_inode_data_blocks(&inode)?;
         
         // Free all data blocks used by this inode
-        for block_num in blocks_to_free {
+        for &block_num in &blocks_to_free {
             // Debug: Freeing data block (disabled to reduce log noise)
             // crate::early_println!("EXT2: Freeing data block {}", block_num);
             self.free_block(block_num)?;
         }
+
+        // Let the device reclaim the storage behind the freed blocks
+        self.discard_blocks(&blocks_to_free);
         
         // Calculate which block group contains this inode
         let group = (inode_number - 1) / self.superblock.get_inodes_per_group();
@@ -2626,7 +2555,7 @@ impl Ext2FileSystem {
         
         // Update inode size, block count, and modification time
         inode.size = content.len() as u32;
-        inode.mtime = 0; // TODO: Use proper timestamp when available
+        inode.mtime = crate::time::realtime_s() as u32;
         
         // Update i_blocks field (count in 512-byte sectors)
         inode.blocks = blocks_needed * (self.block_size / 512);
@@ -2658,6 +2587,7 @@ impl Ext2FileSystem {
                 if size <= 60 {
                     // Fast symlink: target stored in inode.block array
                     // Use safe byte-level access to read the block data
+                    crate::mem::kasan::check_read(inode as *const Ext2Inode as usize, core::mem::size_of::<Ext2Inode>());
                     let inode_bytes = unsafe {
                         core::slice::from_raw_parts(
                             inode as *const Ext2Inode as *const u8,
@@ -2905,6 +2835,55 @@ impl Ext2FileSystem {
         Ok(())
     }
 
+    /// Issue discard requests for freed blocks
+    ///
+    /// Contiguous block numbers are coalesced into a single request. Discard is
+    /// advisory, so failures are logged and otherwise ignored.
+    fn discard_blocks(&self, blocks: &[u32]) {
+        if blocks.is_empty() || !self.block_device.supports_discard() {
+            return;
+        }
+
+        let mut sorted: Vec<u32> = blocks.iter().copied().filter(|&b| b != 0).collect();
+        sorted.sort_unstable();
+        sorted.dedup();
+
+        // Freed blocks no longer hold valid data
+        {
+            let mut block_cache = self.block_cache.lock();
+            for &block_num in &sorted {
+                block_cache.remove(block_num as u64);
+            }
+        }
+
+        let mut runs: Vec<(u32, u32)> = Vec::new(); // (first block, block count)
+        for &block_num in &sorted {
+            match runs.last_mut() {
+                Some((start, count)) if *start + *count == block_num => *count += 1,
+                _ => runs.push((block_num, 1)),
+            }
+        }
+
+        for &(start, count) in &runs {
+            let request = Box::new(crate::device::block::request::BlockIORequest {
+                request_type: crate::device::block::request::BlockIORequestType::Discard,
+                sector: self.block_to_sector(start as u64),
+                sector_count: (count as u64 * self.sectors_per_block()) as usize,
+                head: 0,
+                cylinder: 0,
+                buffer: Vec::new(),
+            });
+            self.block_device.enqueue_request(request);
+        }
+
+        for result in self.block_device.process_requests() {
+            if let Err(e) = result.result {
+                crate::early_println!("[ext2] discard of sector {} ({} sectors) failed: {}",
+                    result.request.sector, result.request.sector_count, e);
+            }
+        }
+    }
+
     /// Set the block number for a logical block within an inode
     fn set_inode_block(&self, inode: &mut Ext2Inode, logical_block: u64, block_number: u32) -> Result<(), FileSystemError> {
         profile_scope!("ext2::set_inode_block");
@@ -4117,13 +4096,14 @@ impl FileSystemOperations for Ext2FileSystem {
         
         // Create new inode with proper initialization
         let initial_nlinks: u16 = if file_type == FileType::Directory { 2 } else { 1 }; // Directory gets "." and initial link
+        let now = crate::time::realtime_s() as u32;
         let mut new_inode = Ext2Inode {
             mode: mode.to_le(),
             uid: 0_u16.to_le(),
             size: 0_u32.to_le(),
-            atime: 0_u32.to_le(),
-            ctime: 0_u32.to_le(),
-            mtime: 0_u32.to_le(),
+            atime: now.to_le(),
+            ctime: now.to_le(),
+            mtime: now.to_le(),
             dtime: 0_u32.to_le(),
             gid: 0_u16.to_le(),
             links_count: initial_nlinks.to_le(),
@@ -4137,6 +4117,9 @@ impl FileSystemOperations for Ext2FileSystem {
             faddr: 0_u32.to_le(),
             osd2: [0u8; 12],
         };
+        // Owned by the creating task
+        let (uid, gid) = crate::task::cred::current_fs_ids();
+        new_inode.set_owner(uid, gid);
         
         // Handle symbolic link target path storage
         if let FileType::SymbolicLink(target_path) = &file_type {
@@ -4338,9 +4321,30 @@ impl FileSystemOperations for Ext2FileSystem {
             Ok(FileType::Directory) => true,
             _ => false,
         };
+
+        // Only empty directories can be removed
+        let inode = self.read_inode(inode_number)?;
+        if is_directory {
+            let entries = self.read_directory_entries(&inode)?;
+            if entries.iter().any(|entry| entry.name != "." && entry.name != "..") {
+                return Err(FileSystemError::new(
+                    FileSystemErrorKind::DirectoryNotEmpty,
+                    "Directory is not empty"
+                ));
+            }
+        }
         
         // Remove the directory entry from the parent directory
         self.remove_directory_entry(ext2_parent.inode_number(), name)?;
+
+        // A file with other hard links keeps its inode
+        let links = u16::from_le(inode.links_count);
+        if !is_directory && links > 1 {
+            let mut inode = inode;
+            inode.links_count = (links - 1).to_le();
+            self.write_inode(inode_number, &inode)?;
+            return Ok(());
+        }
         
         // If deleting a directory, update parent directory's link count
         // (removing the ".." entry decrements parent's link count)
@@ -4359,6 +4363,77 @@ impl FileSystemOperations for Ext2FileSystem {
         Ok(())
     }
 
+    fn create_hardlink(
+        &self,
+        link_parent: &Arc<dyn VfsNode>,
+        link_name: &String,
+        target_node: &Arc<dyn VfsNode>,
+    ) -> Result<Arc<dyn VfsNode>, FileSystemError> {
+        let ext2_parent = link_parent.as_any()
+            .downcast_ref::<Ext2Node>()
+            .ok_or_else(|| FileSystemError::new(
+                FileSystemErrorKind::NotSupported,
+                "Invalid parent node type for ext2"
+            ))?;
+        let ext2_target = target_node.as_any()
+            .downcast_ref::<Ext2Node>()
+            .ok_or_else(|| FileSystemError::new(
+                FileSystemErrorKind::NotSupported,
+                "Invalid target node type for ext2"
+            ))?;
+
+        if ext2_parent.file_type()? != FileType::Directory {
+            return Err(FileSystemError::new(
+                FileSystemErrorKind::NotADirectory,
+                "Parent is not a directory"
+            ));
+        }
+        let file_type = ext2_target.file_type()?;
+        if file_type == FileType::Directory {
+            return Err(FileSystemError::new(
+                FileSystemErrorKind::InvalidOperation,
+                "Cannot create hard link to directory"
+            ));
+        }
+        if self.check_entry_exists(ext2_parent.inode_number(), link_name)? {
+            return Err(FileSystemError::new(
+                FileSystemErrorKind::FileExists,
+                "Link name already exists"
+            ));
+        }
+
+        // Count the new link before the entry becomes visible
+        let inode_number = ext2_target.inode_number();
+        let mut inode = self.read_inode(inode_number)?;
+        let links = u16::from_le(inode.links_count);
+        if links == u16::MAX {
+            return Err(FileSystemError::new(
+                FileSystemErrorKind::NoSpace,
+                "Too many links"
+            ));
+        }
+        inode.links_count = (links + 1).to_le();
+        self.write_inode(inode_number, &inode)?;
+
+        if let Err(e) = self.add_directory_entry(ext2_parent.inode_number(), link_name, inode_number, file_type.clone()) {
+            inode.links_count = links.to_le();
+            self.write_inode(inode_number, &inode)?;
+            return Err(e);
+        }
+
+        let file_id = {
+            let mut next_id = self.next_file_id.lock();
+            let id = *next_id;
+            *next_id += 1;
+            id
+        };
+        let node = Ext2Node::new(inode_number, file_type, file_id);
+        if let Some(fs_ref) = ext2_parent.filesystem() {
+            node.set_filesystem(fs_ref);
+        }
+        Ok(Arc::new(node))
+    }
+
     fn root_node(&self) -> Arc<dyn VfsNode> {
         self.root.read().clone()
     }
@@ -4370,6 +4445,10 @@ impl FileSystemOperations for Ext2FileSystem {
     fn as_any(&self) -> &dyn Any {
         self
     }
+
+    fn is_backed_by(&self, device: &dyn crate::device::Device) -> bool {
+        core::ptr::addr_eq(Arc::as_ptr(&self.block_device), device as *const dyn crate::device::Device)
+    }
 }
 
 /// Register the ext2 driver with the filesystem driver manager
@@ -12,7 +12,7 @@ use crate::{
         FileObject, FileSystemError, FileSystemErrorKind, FileType, SeekFrom,
         FileMetadata, FilePermission, DeviceFileInfo
     },
-    object::capability::{StreamOps, ControlOps, MemoryMappingOps, StreamError},
+    object::capability::{StreamOps, ControlOps, MemoryMappingOps, PollOps, StreamError},
     DeviceManager
 };
 
@@ -113,6 +113,7 @@ impl VfsNode for Ext2Node {
             accessed_time: 0,
             file_id: self.file_id,
             link_count: 1,
+            owner: Some(inode.owner()),
         })
     }
 
@@ -196,10 +197,9 @@ impl Ext2FileObject {
     fn ensure_content_loaded(&self) -> Result<(), StreamError> {
         crate::profile_scope!("ext2::node::ensure_content_loaded");
         
-        let mut cached = self.cached_content.write();
-        
-        // If already loaded, nothing to do
-        if cached.is_some() {
+        // If already loaded, nothing to do. The cache is not locked while
+        // the file is read, which reaches preemption points.
+        if self.cached_content.read().is_some() {
             return Ok(());
         }
         
@@ -226,7 +226,8 @@ impl Ext2FileObject {
             Vec::new()
         };
         
-        *cached = Some(content);
+        // A content loaded by someone else meanwhile wins
+        self.cached_content.write().get_or_insert(content);
         Ok(())
     }
 
@@ -429,6 +430,7 @@ impl FileObject for Ext2FileObject {
             accessed_time: inode.atime as u64,
             file_id: self.file_id,
             link_count: inode.links_count as u32,
+            owner: Some(inode.owner()),
         })
     }
 
@@ -723,6 +725,7 @@ impl FileObject for Ext2DirectoryObject {
             accessed_time: inode.atime as u64,
             file_id: self.file_id,
             link_count: inode.links_count as u32,
+            owner: Some(inode.owner()),
         })
     }
 
@@ -797,7 +800,7 @@ impl StreamOps for Ext2CharDeviceFileObject {
             #[cfg(test)]
             crate::early_println!("[ext2] CharDevice: Successfully cast to CharDevice");
             // Use the CharDevice read method
-            Ok(char_device.read(buffer))
+            crate::device::char::read_result(char_device.read(buffer), buffer.len())
         } else {
             #[cfg(test)]
             crate::early_println!("[ext2] CharDevice: Device is not a CharDevice");
@@ -870,6 +873,7 @@ impl FileObject for Ext2CharDeviceFileObject {
             accessed_time: 0,
             file_id: self.file_id,
             link_count: 1,
+            owner: None,
         })
     }
 
@@ -908,7 +912,39 @@ impl FileObject for Ext2CharDeviceFileObject {
         }
     }
 
+    fn as_pollable(&self) -> Option<&dyn PollOps> {
+        Some(self)
+    }
+
     fn as_any(&self) -> &dyn Any {
         self
     }
 }
+
+/// Readiness of the character device, looked up like the other operations
+impl PollOps for Ext2CharDeviceFileObject {
+    fn poll_events(&self) -> u32 {
+        let device = DeviceManager::get_manager().get_device(self.device_info.device_id);
+        match device.as_ref().and_then(|device| device.as_char_device()) {
+            Some(char_device) => crate::device::char::poll_events(char_device),
+            None => crate::object::capability::poll::POLLERR,
+        }
+    }
+
+    fn poll_register(&self, task_id: usize) -> bool {
+        let device = DeviceManager::get_manager().get_device(self.device_info.device_id);
+        device
+            .as_ref()
+            .and_then(|device| device.as_char_device())
+            .and_then(|char_device| char_device.as_pollable())
+            .is_some_and(|pollable| pollable.poll_register(task_id))
+    }
+
+    fn poll_unregister(&self, task_id: usize) {
+        let device = DeviceManager::get_manager().get_device(self.device_info.device_id);
+        let char_device = device.as_ref().and_then(|device| device.as_char_device());
+        if let Some(pollable) = char_device.and_then(|char_device| char_device.as_pollable()) {
+            pollable.poll_unregister(task_id);
+        }
+    }
+}
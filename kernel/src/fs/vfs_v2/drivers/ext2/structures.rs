@@ -5,7 +5,7 @@
 
 use core::mem;
 use alloc::{boxed::Box, vec, string::String, format};
-use crate::fs::{FileSystemError, FileSystemErrorKind};
+use crate::fs::{FileOwner, FileSystemError, FileSystemErrorKind};
 
 /// ext2 magic number
 pub const EXT2_SUPER_MAGIC: u16 = 0xEF53;
@@ -396,6 +396,31 @@ impl Ext2Inode {
         u16::from_le(self.mode)
     }
 
+    /// Get owner UID (the high 16 bits are kept in `osd2` on Linux)
+    pub fn get_uid(&self) -> u32 {
+        let high = u16::from_le_bytes([self.osd2[4], self.osd2[5]]);
+        u16::from_le(self.uid) as u32 | (high as u32) << 16
+    }
+
+    /// Get group ID (the high 16 bits are kept in `osd2` on Linux)
+    pub fn get_gid(&self) -> u32 {
+        let high = u16::from_le_bytes([self.osd2[6], self.osd2[7]]);
+        u16::from_le(self.gid) as u32 | (high as u32) << 16
+    }
+
+    /// Set owner UID and group ID
+    pub fn set_owner(&mut self, uid: u32, gid: u32) {
+        self.uid = (uid as u16).to_le();
+        self.gid = (gid as u16).to_le();
+        self.osd2[4..6].copy_from_slice(&((uid >> 16) as u16).to_le_bytes());
+        self.osd2[6..8].copy_from_slice(&((gid >> 16) as u16).to_le_bytes());
+    }
+
+    /// Owner, group and permission bits
+    pub fn owner(&self) -> FileOwner {
+        FileOwner { uid: self.get_uid(), gid: self.get_gid(), mode: self.get_mode() as u32 & 0o7777 }
+    }
+
     /// Get file size in bytes
     pub fn get_size(&self) -> u32 {
         u32::from_le(self.size)
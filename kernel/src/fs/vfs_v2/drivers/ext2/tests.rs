@@ -1279,6 +1279,40 @@ fn test_ext2_virtio_blk_delete_operations() {
     }
 }
 
+#[test_case]
+fn test_ext2_virtio_blk_hardlink_operations() {
+    use crate::drivers::block::virtio_blk::VirtioBlockDevice;
+
+    let fs_driver_manager = get_fs_driver_manager();
+    let virtio_device = VirtioBlockDevice::new(0x10006000);
+    let fs = fs_driver_manager.create_from_block("ext2", Arc::new(virtio_device), 512)
+        .expect("Failed to create ext2 filesystem from virtio-blk device");
+    let root_node = fs.root_node();
+
+    let file = fs.create(&root_node, &"hardlink_src.txt".to_string(), FileType::RegularFile, 0o644).unwrap();
+    fs.open(&file, 1).unwrap().write(b"linked data").unwrap();
+    let link = fs.create_hardlink(&root_node, &"hardlink_dst.txt".to_string(), &file).unwrap();
+    assert_eq!(link.metadata().unwrap().link_count, 2);
+    assert!(fs.create_hardlink(&root_node, &"hardlink_dst.txt".to_string(), &file).is_err());
+
+    // Removing one name keeps the data reachable through the other
+    fs.remove(&root_node, &"hardlink_src.txt".to_string()).unwrap();
+    let link = fs.lookup(&root_node, &"hardlink_dst.txt".to_string()).unwrap();
+    assert_eq!(link.metadata().unwrap().link_count, 1);
+    let mut buffer = [0u8; 16];
+    let n = fs.open(&link, 0).unwrap().read(&mut buffer).unwrap();
+    assert_eq!(&buffer[..n], b"linked data");
+    fs.remove(&root_node, &"hardlink_dst.txt".to_string()).unwrap();
+
+    // A directory with entries cannot be removed
+    let dir = fs.create(&root_node, &"hardlink_dir".to_string(), FileType::Directory, 0o755).unwrap();
+    fs.create(&dir, &"inner".to_string(), FileType::RegularFile, 0o644).unwrap();
+    let err = fs.remove(&root_node, &"hardlink_dir".to_string()).unwrap_err();
+    assert_eq!(err.kind, FileSystemErrorKind::DirectoryNotEmpty);
+    fs.remove(&dir, &"inner".to_string()).unwrap();
+    fs.remove(&root_node, &"hardlink_dir".to_string()).unwrap();
+}
+
 #[test_case]
 fn test_ext2_virtio_blk_symlink_operations() {
     use crate::drivers::block::virtio_blk::VirtioBlockDevice;
@@ -1810,4 +1844,31 @@ fn test_ext2_device_file_creation() {
             panic!("Failed to create ext2 filesystem from virtio-blk device");
         }
     }
-}
\ No newline at end of file
+}
+#[test_case]
+fn test_ext2_read_file_content_batches() {
+    let fs_driver_manager = get_fs_driver_manager();
+    fs_driver_manager.register_driver(Box::new(super::Ext2Driver));
+    let fs = fs_driver_manager.create_from_block("ext2", Arc::new(VirtioBlockDevice::new(0x10006000)), 1024)
+        .expect("Failed to create ext2 filesystem from virtio-blk device");
+    let ext2_fs = fs.as_any().downcast_ref::<Ext2FileSystem>().expect("Not an ext2 filesystem");
+
+    // A file of several read batches with no holes
+    let size = (READ_BATCH_BLOCKS * 2 + 3) * ext2_fs.block_size as usize + 100;
+    let data: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+    let root_node = fs.root_node();
+    let node = fs.create(&root_node, &String::from("batched_read.bin"), FileType::RegularFile, 0o644)
+        .expect("Failed to create file");
+    {
+        let file_obj = fs.open(&node, 0x01).expect("Failed to open file");
+        assert_eq!(file_obj.write(&data).unwrap(), size);
+        // Dropping the file object writes it to disk
+    }
+
+    let inode_number = node.as_any().downcast_ref::<Ext2Node>().expect("Not an ext2 node").inode_number();
+    let content = ext2_fs.read_file_content(inode_number, size).expect("Failed to read file");
+    assert_eq!(content.len(), size);
+    assert!(content == data, "Content read in batches should match what was written");
+
+    fs.remove(&root_node, &String::from("batched_read.bin")).expect("Failed to remove file");
+}
@@ -32,7 +32,8 @@ use crate::{
     driver_initcall,
     fs::{
         get_fs_driver_manager, FileObject, FileSystemError, FileSystemErrorKind, FileType
-    }
+    },
+    sched::preempt::cond_resched,
 };
 
 use super::super::core::{VfsNode, FileSystemOperations, DirectoryEntryInternal};
@@ -653,6 +654,8 @@ impl Fat32FileSystem {
             }
             
             current_cluster = fat_entry;
+            // Long chains would otherwise hold the CPU until the whole file is read
+            cond_resched();
         }
         
         // Truncate to exact size if needed
@@ -1176,6 +1179,7 @@ impl Fat32FileSystem {
         
         let mut current = start_cluster;
         let mut freed_count = 0;
+        let mut freed_clusters = Vec::new();
         
         // Only process valid cluster numbers (>= 2)
         while current >= 2 && current < 0x0FFFFFF0 {
@@ -1195,6 +1199,7 @@ impl Fat32FileSystem {
             
             self.write_fat_entry(current, 0)?; // Mark as free
             freed_count += 1;
+            freed_clusters.push(current);
             
             // Check if we've reached the end of chain or invalid cluster
             if next >= 0x0FFFFFF8 || next == 0 || next == 1 {
@@ -1206,6 +1211,7 @@ impl Fat32FileSystem {
                 break; // End of chain or invalid next cluster
             }
             current = next;
+            cond_resched();
         }
         
         // #[cfg(test)]
@@ -1216,9 +1222,51 @@ impl Fat32FileSystem {
 
         // Update FS Info sector with number of freed clusters
         self.update_fs_info_freed_cluster(freed_count)?;
+
+        // Let the device reclaim the storage behind the freed clusters
+        self.discard_clusters(&mut freed_clusters);
         
         Ok(())
     }
+
+    /// Issue discard requests for freed clusters
+    ///
+    /// Runs of adjacent clusters are coalesced into a single request. Discard is
+    /// advisory, so failures are logged and otherwise ignored.
+    fn discard_clusters(&self, clusters: &mut [u32]) {
+        if clusters.is_empty() || !self.block_device.supports_discard() {
+            return;
+        }
+
+        clusters.sort_unstable();
+
+        let mut runs: Vec<(u32, u32)> = Vec::new(); // (first cluster, cluster count)
+        for &cluster in clusters.iter() {
+            match runs.last_mut() {
+                Some((start, count)) if *start + *count == cluster => *count += 1,
+                _ => runs.push((cluster, 1)),
+            }
+        }
+
+        for &(start, count) in &runs {
+            let request = Box::new(crate::device::block::request::BlockIORequest {
+                request_type: crate::device::block::request::BlockIORequestType::Discard,
+                sector: self.cluster_to_sector(start) as usize,
+                sector_count: (count * self.sectors_per_cluster) as usize,
+                head: 0,
+                cylinder: 0,
+                buffer: Vec::new(),
+            });
+            self.block_device.enqueue_request(request);
+        }
+
+        for result in self.block_device.process_requests() {
+            if let Err(e) = result.result {
+                crate::early_println!("[FAT32] discard of sector {} ({} sectors) failed: {}",
+                    result.request.sector, result.request.sector_count, e);
+            }
+        }
+    }
     
     /// Read data to a cluster
     fn write_cluster_data(&self, cluster: u32, data: &[u8]) -> Result<(), FileSystemError> {
@@ -2795,6 +2843,10 @@ impl FileSystemOperations for Fat32FileSystem {
     fn as_any(&self) -> &dyn Any {
         self
     }
+
+    fn is_backed_by(&self, device: &dyn crate::device::Device) -> bool {
+        core::ptr::addr_eq(Arc::as_ptr(&self.block_device), device as *const dyn crate::device::Device)
+    }
 }
 
 /// Register the FAT32 driver with the filesystem driver manager
@@ -13,6 +13,7 @@ use crate::fs::{
     FileMetadata, FileObject, FilePermission, FileSystemError, FileType, SeekFrom
 };
 use crate::object::capability::{StreamOps, StreamError, ControlOps, MemoryMappingOps};
+use crate::sched::preempt::PreemptGuard;
 
 use crate::fs::vfs_v2::core::{VfsNode, FileSystemOperations};
 
@@ -72,6 +73,7 @@ impl Fat32Node {
                 accessed_time: 0,
                 file_id,
                 link_count: 1,
+                owner: None,
             }),
             children: RwLock::new(BTreeMap::new()),
             parent: RwLock::new(None),
@@ -99,6 +101,7 @@ impl Fat32Node {
                 accessed_time: 0,
                 file_id,
                 link_count: 1,
+                owner: None,
             }),
             children: RwLock::new(BTreeMap::new()),
             parent: RwLock::new(None),
@@ -192,11 +195,12 @@ impl Fat32FileObject {
     }
     
     /// Load file content from disk into cache if not already loaded
+    ///
+    /// The cache is not locked while the file is read, since reading reaches
+    /// preemption points; a content loaded by someone else meanwhile wins.
     fn ensure_content_loaded(&self) -> Result<(), StreamError> {
-        let mut cached = self.cached_content.write();
-        
         // If already loaded, nothing to do
-        if cached.is_some() {
+        if self.cached_content.read().is_some() {
             return Ok(());
         }
         
@@ -222,7 +226,7 @@ impl Fat32FileObject {
             Vec::new()
         };
         
-        *cached = Some(content);
+        self.cached_content.write().get_or_insert(content);
         Ok(())
     }
     
@@ -233,6 +237,8 @@ impl Fat32FileObject {
             return Ok(()); // Nothing to sync
         }
 
+        // The cache stays locked while the clusters are written
+        let _preempt = PreemptGuard::new();
         let cached = self.cached_content.read();
         let content = cached.as_ref().ok_or(StreamError::IoError)?;
 
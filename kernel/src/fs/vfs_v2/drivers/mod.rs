@@ -11,6 +11,8 @@
 //! - **overlayfs**: Union/overlay filesystem combining multiple layers
 //! - **initramfs**: Helper module for mounting initramfs during boot
 //! - **devfs**: Device filesystem that automatically exposes all registered devices
+//! - **procfs**: Kernel information filesystem with generated statistics files
+//! - **cgroupfs**: Resource group hierarchy with per-group CPU and memory controls
 //! - **fat32**: FAT32 filesystem driver for block devices
 //! - **ext2**: ext2 filesystem driver for block devices
 //!
@@ -33,5 +35,7 @@ pub mod cpiofs;
 pub mod tmpfs;
 pub mod initramfs;
 pub mod devfs;
+pub mod procfs;
+pub mod cgroupfs;
 pub mod fat32;
 pub mod ext2;
\ No newline at end of file
@@ -526,6 +526,21 @@ impl OverlayFS {
             .map(|_| ())
     }
 
+    /// Remove the whiteout file that hides `path`
+    fn remove_whiteout(&self, path: &str) -> Result<(), FileSystemError> {
+        let upper = self.get_upper_layer()?;
+        let whiteout_name = format!(".wh.{}", 
+            path.split('/').last().unwrap_or(path));
+        let parent_path = if let Some(pos) = path.rfind('/') {
+            &path[..pos]
+        } else {
+            "/"
+        };
+        let parent_node = self.resolve_in_layer(&upper.0, &upper.1, parent_path)?;
+        let fs = Self::fs_from_mount(&upper.0)?;
+        fs.remove(&parent_node, &whiteout_name)
+    }
+
     /// Perform copy-up operation: copy a file from lower layer to upper layer
     fn copy_up(&self, path: &str) -> Result<(), FileSystemError> {
         let upper = self.get_upper_layer()?;
@@ -757,7 +772,6 @@ impl FileSystemOperations for OverlayFS {
 
     fn create(&self, parent_node: &Arc<dyn VfsNode>, name: &String, file_type: FileType, mode: u32) -> Result<Arc<dyn VfsNode>, FileSystemError> {
         let upper = self.get_upper_layer()?;
-        let upper_fs = Self::fs_from_mount(&upper.0)?;
         let overlay_parent = parent_node.as_any()
             .downcast_ref::<OverlayNode>()
             .ok_or_else(|| FileSystemError::new(FileSystemErrorKind::NotSupported, "Invalid node type for OverlayFS"))?;
@@ -772,23 +786,11 @@ impl FileSystemOperations for OverlayFS {
         }
         // Remove any existing whiteout
         if self.is_whiteout(&child_path) {
-            // Remove whiteout file
-            let whiteout_name = format!(".wh.{}", name);
-            let parent_path = if let Some(pos) = overlay_parent.path.rfind('/') {
-                &overlay_parent.path[..pos]
-            } else {
-                "/"
-            };
-            if let Ok(whiteout_parent) = self.resolve_in_layer(&upper.0, &upper.1, parent_path) {
-                if upper_fs.remove(&whiteout_parent, &whiteout_name).is_err() {
-                    return Err(FileSystemError::new(FileSystemErrorKind::NotFound, "Whiteout file not found"));
-                }
-                // Successfully removed whiteout file
-            }
+            self.remove_whiteout(&child_path)?;
         }
         let upper_parent = self.resolve_in_layer(&upper.0, &upper.1, &overlay_parent.path)?;
-        let fs = Self::fs_from_mount(&upper.0)?;
-        let new_node = fs.create(&upper_parent, name, file_type, mode)?;
+        let upper_fs = Self::fs_from_mount(&upper.0)?;
+        let new_node = upper_fs.create(&upper_parent, name, file_type, mode)?;
         // Return overlay node
         let metadata = new_node.metadata()?;
         let overlay_node = OverlayNode::new(name.clone(), child_path, metadata.file_type, metadata.file_id);
@@ -837,6 +839,44 @@ impl FileSystemOperations for OverlayFS {
         Err(FileSystemError::new(FileSystemErrorKind::NotFound, "File not found"))
     }
 
+    fn create_hardlink(&self, link_parent: &Arc<dyn VfsNode>, link_name: &String, target_node: &Arc<dyn VfsNode>) -> Result<Arc<dyn VfsNode>, FileSystemError> {
+        let overlay_parent = link_parent.as_any()
+            .downcast_ref::<OverlayNode>()
+            .ok_or_else(|| FileSystemError::new(FileSystemErrorKind::NotSupported, "Invalid node type for OverlayFS"))?;
+        let overlay_target = target_node.as_any()
+            .downcast_ref::<OverlayNode>()
+            .ok_or_else(|| FileSystemError::new(FileSystemErrorKind::NotSupported, "Invalid node type for OverlayFS"))?;
+        let upper = self.get_upper_layer()?;
+        let link_path = if overlay_parent.path == "/" {
+            format!("/{}", link_name)
+        } else {
+            format!("{}/{}", overlay_parent.path, link_name)
+        };
+        if self.get_metadata_for_path(&link_path).is_ok() {
+            return Err(FileSystemError::new(FileSystemErrorKind::FileExists, "Link name already exists"));
+        }
+        // The link is made in the upper layer, so both ends are copied up
+        if self.file_exists_in_lower_only(&overlay_target.path) {
+            self.copy_up(&overlay_target.path)?;
+        }
+        if self.file_exists_in_lower_only(&overlay_parent.path) {
+            self.copy_up(&overlay_parent.path)?;
+        }
+        if self.is_whiteout(&link_path) {
+            self.remove_whiteout(&link_path)?;
+        }
+        let upper_parent = self.resolve_in_layer(&upper.0, &upper.1, &overlay_parent.path)?;
+        let upper_target = self.resolve_in_layer(&upper.0, &upper.1, &overlay_target.path)?;
+        let fs = Self::fs_from_mount(&upper.0)?;
+        let link_node = fs.create_hardlink(&upper_parent, link_name, &upper_target)?;
+        let metadata = link_node.metadata()?;
+        let overlay_node = OverlayNode::new(link_name.clone(), link_path, metadata.file_type, metadata.file_id);
+        if let Some(ref fs) = *overlay_parent.overlay_fs.read() {
+            overlay_node.set_overlay_fs(Arc::clone(fs));
+        }
+        Ok(overlay_node)
+    }
+
     fn root_node(&self) -> Arc<dyn VfsNode> {
         Arc::clone(&self.root_node) as Arc<dyn VfsNode>
     }
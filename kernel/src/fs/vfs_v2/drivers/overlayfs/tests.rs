@@ -941,3 +941,50 @@ fn test_overlayfs_directory_read_whiteout() {
     // Should have exactly 4 entries: ., .., visible_file, upper_file
     assert_eq!(found_entries.len(), 4);
 }
+
+#[test_case]
+fn test_overlayfs_hardlink_copy_up() {
+    /*
+    Directory structure:
+
+    lower:/
+    ├── orig (file, "shared")
+    upper:/
+    (empty)
+
+    After link("orig", "alias"):
+    upper:/
+    ├── orig (copied up)
+    ├── alias (same file as orig)
+    */
+    let lower = TmpFS::new(0);
+    let upper = TmpFS::new(0);
+
+    let lower_root = lower.root_node();
+    let lower_file = lower.create(&lower_root, &"orig".to_string(), FileType::RegularFile, 0o644).unwrap();
+    lower.open(&lower_file, 1).unwrap().write(b"shared").unwrap();
+
+    let (lower_mp, lower_entry) = make_mount_and_entry(lower.clone() as Arc<dyn FileSystemOperations>);
+    let (upper_mp, upper_entry) = make_mount_and_entry(upper.clone() as Arc<dyn FileSystemOperations>);
+    let overlay = OverlayFS::new(
+        Some((upper_mp, upper_entry)),
+        vec![(lower_mp, lower_entry)],
+        "overlayfs".to_string()
+    ).unwrap();
+
+    let root = overlay.root_node();
+    let orig = overlay.lookup(&root, &"orig".to_string()).unwrap();
+    overlay.create_hardlink(&root, &"alias".to_string(), &orig).unwrap();
+
+    // Both names are in the upper layer and share the file
+    let upper_root = upper.root_node();
+    let upper_orig = upper.lookup(&upper_root, &"orig".to_string()).unwrap();
+    assert_eq!(upper_orig.metadata().unwrap().link_count, 2);
+    let alias = overlay.lookup(&root, &"alias".to_string()).unwrap();
+    let mut buffer = [0u8; 16];
+    let n = overlay.open(&alias, 0).unwrap().read(&mut buffer).unwrap();
+    assert_eq!(&buffer[..n], b"shared");
+
+    // An existing name is refused
+    assert!(overlay.create_hardlink(&root, &"alias".to_string(), &orig).is_err());
+}
@@ -0,0 +1,905 @@
+//! ProcFS - Kernel information filesystem
+//!
+//! ProcFS is a virtual filesystem whose files are generated by the kernel
+//! when they are opened. It is the place where subsystems publish
+//! statistics and debugging knobs as plain text, similar to /proc in
+//! Unix-like systems.
+//!
+//! ## Entries
+//!
+//! Subsystems add files with [`register_proc_entry`]. Each entry has a
+//! generator that renders the file content and an optional write handler
+//! that receives whatever is written to the file. The content is generated
+//! once per open, so a reader always sees a consistent snapshot. A name
+//! with slashes is a file in subdirectories: `net/dev` is the file `dev` of
+//! the directory `net`, which exists as long as it has entries.
+//!
+//! Built-in entries:
+//!
+//! - **kmallocinfo**: per size-class kernel allocation counters
+//! - **kmalloc_sites**: live allocations by call site; accepts `on`, `off`,
+//!   `reset` and `depth=N` (`on` needs the `kmalloc-sites` feature)
+//! - **magazines**: per-CPU slab magazine counters and cached objects
+//! - **buddyinfo**: free blocks of the page allocator per order
+//! - **diskstats**: block device I/O statistics
+//! - **wx**: W^X policy and violation counters; accepts `off`, `log` and `kill`
+//! - **vmstat**: page cache LRU sizes, anonymous pages and reclaim counters
+//! - **core_pattern**: where core files of crashed tasks are written; empty
+//!   disables core dumps
+//! - **schedstat**: busy and idle time, utilization and context switches
+//!   per CPU
+//! - **strace**: system calls of traced tasks; accepts `trace <id>`,
+//!   `follow <id>`, `untrace <id>` and `clear`
+//! - **binfmt_misc**: interpreters of binaries no ABI recognizes; accepts
+//!   `:name:type:offset:magic:mask:interpreter:flags`, `-name` and `-1`
+//! - **firewall**: rules of the packet filter
+//! - **net/dev**: packet, byte, error and drop counters per interface
+//! - **net/snmp**: IP, ICMP, TCP and UDP counters
+//!
+//! ## Task directories
+//!
+//! Every live task also has a directory named after its ID with files
+//! describing the task:
+//!
+//! - **stat**: status line in the layout of Linux `/proc/<pid>/stat`; CPU
+//!   times are in clock ticks
+//! - **sched**: run and wait times in microseconds and context switches
+//! - **abi**: name of the ABI of the task and the version it runs with
+//!
+//! **self** is a symbolic link to the directory of the task looking at it.
+//!
+//! ## Usage
+//!
+//! ```rust
+//! // Mount procfs at /proc
+//! let procfs = ProcFS::new();
+//! vfs.mount(procfs, "/proc", 0)?;
+//!
+//! // Read allocator statistics
+//! let file = vfs.open("/proc/kmallocinfo", O_RDONLY)?;
+//! ```
+
+use alloc::{
+    boxed::Box, collections::BTreeMap, format, string::{String, ToString}, sync::{Arc, Weak}, vec::Vec
+};
+use spin::RwLock;
+use core::any::Any;
+
+use crate::{driver_initcall, fs::{
+    get_fs_driver_manager, FileMetadata, FileObject, FilePermission, FileSystemDriver,
+    FileSystemError, FileSystemErrorKind, FileSystemType, FileType, SeekFrom
+}, object::capability::MemoryMappingOps};
+use crate::object::capability::{StreamOps, StreamError, ControlOps};
+
+use crate::sched::scheduler::get_scheduler;
+use crate::sched::stats::format_task_sched;
+use crate::task::{mytask, BlockedType, TaskState};
+use crate::timer::{get_time_us, ticks_to_us};
+
+use super::super::core::{VfsNode, FileSystemOperations, DirectoryEntryInternal};
+
+/// Renders the content of a proc file
+pub type ProcReadFn = fn() -> String;
+
+/// Handles data written to a proc file
+pub type ProcWriteFn = fn(&[u8]) -> Result<(), &'static str>;
+
+/// A file published through procfs
+#[derive(Clone, Copy)]
+pub struct ProcEntry {
+    /// Generates the file content
+    pub read: ProcReadFn,
+    /// Accepts writes; read-only entries have none
+    pub write: Option<ProcWriteFn>,
+}
+
+/// Registered entries by file name
+static PROC_ENTRIES: RwLock<BTreeMap<&'static str, ProcEntry>> = RwLock::new(BTreeMap::new());
+
+/// Publish a file in procfs
+///
+/// Registering a name again replaces the previous entry.
+///
+/// # Arguments
+/// * `name` - Path of the file from the procfs root
+/// * `read` - Generator for the file content
+/// * `write` - Optional handler for data written to the file
+pub fn register_proc_entry(name: &'static str, read: ProcReadFn, write: Option<ProcWriteFn>) {
+    PROC_ENTRIES.write().insert(name, ProcEntry { read, write });
+}
+
+/// Remove a file from procfs
+pub fn unregister_proc_entry(name: &str) {
+    PROC_ENTRIES.write().remove(name);
+}
+
+/// Look up a registered entry
+pub fn get_proc_entry(name: &str) -> Option<ProcEntry> {
+    PROC_ENTRIES.read().get(name).copied()
+}
+
+/// File ID of an entry: its position in the registry, after the root (0)
+fn entry_file_id(name: &str) -> Option<u64> {
+    PROC_ENTRIES.read().keys().position(|key| *key == name).map(|index| index as u64 + 1)
+}
+
+/// File IDs of the directories of entries start here, after those of the
+/// entries and before the `self` link
+const ENTRY_DIRECTORY_FILE_ID: u64 = 0x8000;
+
+/// Paths of the directories the registered entries are in, sorted
+fn entry_directories() -> Vec<String> {
+    let mut directories: Vec<String> = PROC_ENTRIES
+        .read()
+        .keys()
+        .flat_map(|key| key.match_indices('/').map(|(end, _)| key[..end].to_string()))
+        .collect();
+    directories.sort();
+    directories.dedup();
+    directories
+}
+
+/// File ID of the directory of entries at `path`, the root for ""
+fn entry_directory_id(path: &str) -> Option<u64> {
+    if path.is_empty() {
+        return Some(0);
+    }
+    entry_directories().iter().position(|directory| directory == path).map(|index| ENTRY_DIRECTORY_FILE_ID + index as u64)
+}
+
+/// Renders a file of a task directory, `None` if the task is gone
+type TaskReadFn = fn(usize) -> Option<String>;
+
+/// Files in every task directory
+const TASK_ENTRIES: [(&str, TaskReadFn); 3] = [
+    ("stat", format_task_stat),
+    ("sched", format_task_sched),
+    ("abi", format_task_abi),
+];
+
+/// File ID of a task directory (`index` 0) or of a file in it
+///
+/// Task IDs are kept clear of the IDs of registered entries.
+fn task_file_id(pid: usize, index: usize) -> u64 {
+    ((pid as u64 + 1) << 16) | index as u64
+}
+
+/// Name of the link to the directory of the current task
+const SELF_LINK: &str = "self";
+
+/// File ID of the `self` link, between entry and task IDs
+const SELF_FILE_ID: u64 = 0xffff;
+
+/// Whether `pid` names a task that has not been reaped
+fn task_exists(pid: usize) -> bool {
+    get_scheduler().get_task_by_id(pid).is_some_and(|task| task.get_state() != TaskState::Terminated)
+}
+
+/// Render the `stat` file of a task
+///
+/// The fields follow Linux up to `rss`; those Scarlet does not track are 0.
+fn format_task_stat(pid: usize) -> Option<String> {
+    let task = get_scheduler().get_task_by_id(pid)?;
+    let state = match task.get_state() {
+        TaskState::Running => 'R',
+        TaskState::Ready | TaskState::NotInitialized => 'R',
+        TaskState::Blocked(_) if task.signals.is_stopped() => 'T',
+        TaskState::Blocked(BlockedType::Interruptible) => 'S',
+        TaskState::Blocked(BlockedType::Uninterruptible) => 'D',
+        TaskState::Zombie => 'Z',
+        TaskState::Terminated => return None,
+    };
+    let running = get_scheduler().is_running(pid);
+    let (user_us, system_us) = task.cpu_times.current(get_time_us(), running);
+    let ticks = |us: u64| us / ticks_to_us(1);
+    Some(format!(
+        "{} ({}) {} {} {} {} 0 -1 0 0 0 0 0 {} {} {} {} {} {} 1 0 0 {} {}\n",
+        pid,
+        task.name,
+        state,
+        task.get_parent_id().unwrap_or(0),
+        task.get_pgid(),
+        task.get_sid(),
+        ticks(user_us),
+        ticks(system_us),
+        ticks(task.cpu_times.children.user_us),
+        ticks(task.cpu_times.children.system_us),
+        20 + task.nice,
+        task.nice,
+        task.address_space_usage(),
+        task.managed_pages.read().len(),
+    ))
+}
+
+/// Render the `abi` file of a task: its ABI and the version it runs with
+fn format_task_abi(pid: usize) -> Option<String> {
+    let task = get_scheduler().get_task_by_id(pid)?;
+    if task.get_state() == TaskState::Terminated {
+        return None;
+    }
+    Some(format!("{} {}\n", task.default_abi.get_name(), task.abi_version))
+}
+
+/// ProcFS - Kernel information filesystem
+pub struct ProcFS {
+    /// Root directory node
+    root: Arc<ProcNode>,
+    /// Filesystem name
+    name: String,
+}
+
+impl ProcFS {
+    /// Create a new ProcFS instance
+    pub fn new() -> Arc<Self> {
+        let root = Arc::new(ProcNode::new("/".to_string(), FileType::Directory, 0));
+        let fs = Arc::new(Self {
+            root: Arc::clone(&root),
+            name: "procfs".to_string(),
+        });
+        let fs_weak = Arc::downgrade(&(fs.clone() as Arc<dyn FileSystemOperations>));
+        root.set_filesystem(fs_weak);
+        fs
+    }
+
+    fn downcast_node(node: &Arc<dyn VfsNode>) -> Result<Arc<ProcNode>, FileSystemError> {
+        Arc::downcast::<ProcNode>(node.clone())
+            .map_err(|_| FileSystemError::new(
+                FileSystemErrorKind::NotSupported,
+                "Invalid node type for ProcFS"
+            ))
+    }
+}
+
+impl FileSystemOperations for ProcFS {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn root_node(&self) -> Arc<dyn VfsNode> {
+        Arc::clone(&self.root) as Arc<dyn VfsNode>
+    }
+
+    fn lookup(&self, parent: &Arc<dyn VfsNode>, name: &String) -> Result<Arc<dyn VfsNode>, FileSystemError> {
+        let parent = Self::downcast_node(parent)?;
+        if parent.file_type != FileType::Directory {
+            return Err(FileSystemError::new(
+                FileSystemErrorKind::NotADirectory,
+                "Not a directory"
+            ));
+        }
+
+        let not_found = || FileSystemError::new(
+            FileSystemErrorKind::NotFound,
+            format!("'{}' not found in procfs", name)
+        );
+        let node = match parent.pid {
+            // A file in a task directory
+            Some(pid) => {
+                let index = TASK_ENTRIES.iter().position(|(entry, _)| *entry == name.as_str()).ok_or_else(not_found)?;
+                ProcNode::new_task(name.clone(), FileType::RegularFile, pid, index + 1)
+            }
+            None if parent.file_id == 0 && name.parse::<usize>().is_ok_and(task_exists) => {
+                ProcNode::new_task(name.clone(), FileType::Directory, name.parse().unwrap(), 0)
+            }
+            // The target is read on every resolution, see `read_link`
+            None if parent.file_id == 0 && name == SELF_LINK => {
+                ProcNode::new(name.clone(), FileType::SymbolicLink(String::new()), SELF_FILE_ID)
+            }
+            // Entries and their directories are named by their path
+            None => {
+                let path = parent.entry_path(name);
+                match entry_file_id(&path) {
+                    Some(file_id) => ProcNode::new(path, FileType::RegularFile, file_id),
+                    None => {
+                        let file_id = entry_directory_id(&path).ok_or_else(not_found)?;
+                        ProcNode::new(path, FileType::Directory, file_id)
+                    }
+                }
+            }
+        };
+        let node = Arc::new(node);
+        if let Some(fs_ref) = self.root.filesystem() {
+            node.set_filesystem(fs_ref);
+        }
+        Ok(node as Arc<dyn VfsNode>)
+    }
+
+    fn readdir(&self, node: &Arc<dyn VfsNode>) -> Result<Vec<DirectoryEntryInternal>, FileSystemError> {
+        Self::downcast_node(node)?.readdir()
+    }
+
+    fn open(&self, node: &Arc<dyn VfsNode>, _flags: u32) -> Result<Arc<dyn FileObject>, FileSystemError> {
+        let node = Self::downcast_node(node)?;
+        match (node.file_type.clone(), node.pid) {
+            (FileType::Directory, _) => Ok(Arc::new(ProcDirectoryObject::new(node))),
+            (_, Some(pid)) => {
+                let read = TASK_ENTRIES[node.file_id as usize % (1 << 16) - 1].1;
+                let content = read(pid).ok_or_else(|| FileSystemError::new(
+                    FileSystemErrorKind::NotFound,
+                    format!("Task {} no longer exists", pid)
+                ))?;
+                Ok(Arc::new(ProcFileObject::new(node, content, None)))
+            }
+            (_, None) => {
+                let entry = get_proc_entry(&node.name).ok_or_else(|| FileSystemError::new(
+                    FileSystemErrorKind::NotFound,
+                    format!("'{}' is no longer registered in procfs", node.name)
+                ))?;
+                Ok(Arc::new(ProcFileObject::new(node, (entry.read)(), entry.write)))
+            }
+        }
+    }
+
+    fn is_read_only(&self) -> bool {
+        true
+    }
+
+    // The set of files is defined by the kernel - these operations are not supported
+    fn create(&self, _parent: &Arc<dyn VfsNode>, _name: &String, _file_type: FileType, _mode: u32) -> Result<Arc<dyn VfsNode>, FileSystemError> {
+        Err(FileSystemError::new(
+            FileSystemErrorKind::ReadOnly,
+            "ProcFS is read-only: cannot create files"
+        ))
+    }
+
+    fn remove(&self, _parent: &Arc<dyn VfsNode>, _name: &String) -> Result<(), FileSystemError> {
+        Err(FileSystemError::new(
+            FileSystemErrorKind::ReadOnly,
+            "ProcFS is read-only: cannot remove files"
+        ))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// A node in the ProcFS filesystem
+pub struct ProcNode {
+    /// Node name (the path of the entry for entries and their directories)
+    name: String,
+    /// File type
+    file_type: FileType,
+    /// File ID
+    file_id: u64,
+    /// Task of a task directory or of a file in one
+    pid: Option<usize>,
+    /// Reference to filesystem
+    filesystem: RwLock<Option<Weak<dyn FileSystemOperations>>>,
+}
+
+impl ProcNode {
+    fn new(name: String, file_type: FileType, file_id: u64) -> Self {
+        Self {
+            name,
+            file_type,
+            file_id,
+            pid: None,
+            filesystem: RwLock::new(None),
+        }
+    }
+
+    /// A task directory (`index` 0) or the file `index - 1` of `TASK_ENTRIES` in it
+    fn new_task(name: String, file_type: FileType, pid: usize, index: usize) -> Self {
+        Self {
+            pid: Some(pid),
+            ..Self::new(name, file_type, task_file_id(pid, index))
+        }
+    }
+
+    /// Path of the entry `name` in this directory of entries
+    fn entry_path(&self, name: &str) -> String {
+        if self.file_id == 0 { name.to_string() } else { format!("{}/{}", self.name, name) }
+    }
+
+    /// Set filesystem reference
+    pub fn set_filesystem(&self, fs: Weak<dyn FileSystemOperations>) {
+        *self.filesystem.write() = Some(fs);
+    }
+
+    /// Read directory contents
+    pub fn readdir(&self) -> Result<Vec<DirectoryEntryInternal>, FileSystemError> {
+        if self.file_type != FileType::Directory {
+            return Err(FileSystemError::new(
+                FileSystemErrorKind::NotADirectory,
+                "Cannot read directory of non-directory node"
+            ));
+        }
+
+        let mut entries = Vec::new();
+        if let Some(pid) = self.pid {
+            entries.push(DirectoryEntryInternal {
+                name: ".".to_string(),
+                file_type: FileType::Directory,
+                file_id: self.file_id,
+            });
+            entries.push(DirectoryEntryInternal {
+                name: "..".to_string(),
+                file_type: FileType::Directory,
+                file_id: 0,
+            });
+            for (index, (name, _)) in TASK_ENTRIES.iter().enumerate() {
+                entries.push(DirectoryEntryInternal {
+                    name: name.to_string(),
+                    file_type: FileType::RegularFile,
+                    file_id: task_file_id(pid, index + 1),
+                });
+            }
+            return Ok(entries);
+        }
+
+        // The root is its own parent
+        let (prefix, parent) = match self.name.rsplit_once('/') {
+            _ if self.file_id == 0 => (String::new(), 0),
+            Some((parent, _)) => (format!("{}/", self.name), entry_directory_id(parent).unwrap_or(0)),
+            None => (format!("{}/", self.name), 0),
+        };
+        for (name, file_id) in [(".", self.file_id), ("..", parent)] {
+            entries.push(DirectoryEntryInternal {
+                name: name.to_string(),
+                file_type: FileType::Directory,
+                file_id,
+            });
+        }
+        for (index, key) in PROC_ENTRIES.read().keys().enumerate() {
+            match key.strip_prefix(prefix.as_str()) {
+                Some(name) if !name.contains('/') => entries.push(DirectoryEntryInternal {
+                    name: name.to_string(),
+                    file_type: FileType::RegularFile,
+                    file_id: index as u64 + 1,
+                }),
+                _ => {}
+            }
+        }
+        for directory in entry_directories() {
+            match directory.strip_prefix(prefix.as_str()) {
+                Some(name) if !name.contains('/') => entries.push(DirectoryEntryInternal {
+                    name: name.to_string(),
+                    file_type: FileType::Directory,
+                    file_id: entry_directory_id(&directory).unwrap_or(0),
+                }),
+                _ => {}
+            }
+        }
+        if self.file_id != 0 {
+            return Ok(entries);
+        }
+        entries.push(DirectoryEntryInternal {
+            name: SELF_LINK.to_string(),
+            file_type: FileType::SymbolicLink(String::new()),
+            file_id: SELF_FILE_ID,
+        });
+        for pid in get_scheduler().get_all_task_ids() {
+            if task_exists(pid) {
+                entries.push(DirectoryEntryInternal {
+                    name: pid.to_string(),
+                    file_type: FileType::Directory,
+                    file_id: task_file_id(pid, 0),
+                });
+            }
+        }
+        Ok(entries)
+    }
+}
+
+impl VfsNode for ProcNode {
+    fn id(&self) -> u64 {
+        self.file_id
+    }
+
+    fn metadata(&self) -> Result<FileMetadata, FileSystemError> {
+        let writable = match (&self.file_type, self.pid) {
+            (FileType::Directory, _) | (_, Some(_)) => false,
+            _ => get_proc_entry(&self.name).is_some_and(|entry| entry.write.is_some()),
+        };
+        Ok(FileMetadata {
+            file_type: self.file_type.clone(),
+            size: 0, // Generated files have no size until they are read
+            permissions: FilePermission {
+                read: true,
+                write: writable,
+                execute: self.file_type == FileType::Directory,
+            },
+            created_time: 0,
+            modified_time: 0,
+            accessed_time: 0,
+            file_id: self.file_id,
+            link_count: 1,
+            owner: None,
+        })
+    }
+
+    fn filesystem(&self) -> Option<Weak<dyn FileSystemOperations>> {
+        self.filesystem.read().clone()
+    }
+
+    /// `self` resolves to the directory of the task following it
+    fn read_link(&self) -> Result<String, FileSystemError> {
+        match (&self.file_type, mytask()) {
+            (FileType::SymbolicLink(_), Some(task)) => Ok(task.get_id().to_string()),
+            (FileType::SymbolicLink(_), None) => Err(FileSystemError::new(
+                FileSystemErrorKind::NotFound,
+                "No current task for /proc/self"
+            )),
+            _ => Err(FileSystemError::new(
+                FileSystemErrorKind::NotSupported,
+                "Not a symbolic link"
+            )),
+        }
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// An open proc file
+///
+/// Holds the content generated at open time; reads and seeks work on that
+/// snapshot, writes go to the entry's write handler.
+pub struct ProcFileObject {
+    /// Reference to the ProcNode
+    node: Arc<ProcNode>,
+    /// Write handler of the entry, if it accepts writes
+    write: Option<ProcWriteFn>,
+    /// Content generated when the file was opened
+    content: Vec<u8>,
+    /// Current read position
+    position: RwLock<u64>,
+}
+
+impl ProcFileObject {
+    fn new(node: Arc<ProcNode>, content: String, write: Option<ProcWriteFn>) -> Self {
+        Self {
+            node,
+            write,
+            content: content.into_bytes(),
+            position: RwLock::new(0),
+        }
+    }
+}
+
+impl StreamOps for ProcFileObject {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, StreamError> {
+        let mut position = self.position.write();
+        let start = (*position as usize).min(self.content.len());
+        let len = buffer.len().min(self.content.len() - start);
+        buffer[..len].copy_from_slice(&self.content[start..start + len]);
+        *position += len as u64;
+        Ok(len)
+    }
+
+    fn write(&self, buffer: &[u8]) -> Result<usize, StreamError> {
+        let write = self.write.ok_or(StreamError::from(FileSystemError::new(
+            FileSystemErrorKind::PermissionDenied,
+            "Proc file is read-only"
+        )))?;
+        write(buffer).map_err(|e| StreamError::from(FileSystemError::new(
+            FileSystemErrorKind::InvalidData,
+            e
+        )))?;
+        Ok(buffer.len())
+    }
+}
+
+impl ControlOps for ProcFileObject {
+    // Proc files don't support control operations
+    fn control(&self, _command: u32, _arg: usize) -> Result<i32, &'static str> {
+        Err("Control operations not supported on proc files")
+    }
+}
+
+impl MemoryMappingOps for ProcFileObject {
+    fn get_mapping_info(&self, _offset: usize, _length: usize)
+                       -> Result<(usize, usize, bool), &'static str> {
+        Err("Memory mapping not supported for proc files")
+    }
+
+    fn supports_mmap(&self) -> bool {
+        false
+    }
+}
+
+impl FileObject for ProcFileObject {
+    fn seek(&self, whence: SeekFrom) -> Result<u64, StreamError> {
+        let mut position = self.position.write();
+        let len = self.content.len() as u64;
+
+        let new_pos = match whence {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::Current(offset) => {
+                if offset >= 0 {
+                    *position + offset as u64
+                } else {
+                    position.saturating_sub((-offset) as u64)
+                }
+            }
+            SeekFrom::End(offset) => {
+                if offset >= 0 {
+                    len + offset as u64
+                } else {
+                    len.saturating_sub((-offset) as u64)
+                }
+            }
+        };
+
+        *position = new_pos;
+        Ok(new_pos)
+    }
+
+    fn metadata(&self) -> Result<FileMetadata, StreamError> {
+        let mut metadata = self.node.metadata().map_err(StreamError::from)?;
+        metadata.size = self.content.len();
+        Ok(metadata)
+    }
+
+    fn truncate(&self, _size: u64) -> Result<(), StreamError> {
+        Err(StreamError::from(FileSystemError::new(
+            FileSystemErrorKind::ReadOnly,
+            "Cannot truncate proc files"
+        )))
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// A file object for the ProcFS root directory
+pub struct ProcDirectoryObject {
+    /// Reference to the ProcNode
+    node: Arc<ProcNode>,
+    /// Current position in directory entries (entry index)
+    position: RwLock<usize>,
+}
+
+impl ProcDirectoryObject {
+    /// Create a new directory file object
+    pub fn new(node: Arc<ProcNode>) -> Self {
+        Self {
+            node,
+            position: RwLock::new(0),
+        }
+    }
+}
+
+impl StreamOps for ProcDirectoryObject {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, StreamError> {
+        let entries = self.node.readdir().map_err(StreamError::from)?;
+        let position = *self.position.read();
+
+        if position >= entries.len() {
+            return Ok(0); // EOF
+        }
+
+        let internal_entry = &entries[position];
+        let internal_with_size = crate::fs::DirectoryEntryInternal {
+            name: internal_entry.name.clone(),
+            file_type: internal_entry.file_type.clone(),
+            size: 0,
+            file_id: internal_entry.file_id,
+            metadata: None,
+        };
+
+        let dir_entry = crate::fs::DirectoryEntry::from_internal(&internal_with_size);
+        let entry_size = dir_entry.entry_size();
+
+        if buffer.len() < entry_size {
+            return Err(StreamError::InvalidArgument); // Buffer too small
+        }
+
+        let entry_bytes = unsafe {
+            core::slice::from_raw_parts(
+                &dir_entry as *const _ as *const u8,
+                entry_size
+            )
+        };
+        buffer[..entry_size].copy_from_slice(entry_bytes);
+
+        *self.position.write() += 1;
+        Ok(entry_size)
+    }
+
+    fn write(&self, _buffer: &[u8]) -> Result<usize, StreamError> {
+        Err(StreamError::from(FileSystemError::new(
+            FileSystemErrorKind::ReadOnly,
+            "Cannot write to directory in procfs"
+        )))
+    }
+}
+
+impl ControlOps for ProcDirectoryObject {
+    // Directory objects don't support control operations by default
+    fn control(&self, _command: u32, _arg: usize) -> Result<i32, &'static str> {
+        Err("Control operations not supported on directories")
+    }
+}
+
+impl MemoryMappingOps for ProcDirectoryObject {
+    fn get_mapping_info(&self, _offset: usize, _length: usize)
+                       -> Result<(usize, usize, bool), &'static str> {
+        Err("Memory mapping not supported for directories")
+    }
+
+    fn supports_mmap(&self) -> bool {
+        false
+    }
+}
+
+impl FileObject for ProcDirectoryObject {
+    fn seek(&self, whence: SeekFrom) -> Result<u64, StreamError> {
+        let entry_count = self.node.readdir().map_err(StreamError::from)?.len() as u64;
+        let mut position = self.position.write();
+
+        let new_pos = match whence {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::Current(offset) => {
+                if offset >= 0 {
+                    *position as u64 + offset as u64
+                } else {
+                    (*position as u64).saturating_sub((-offset) as u64)
+                }
+            },
+            SeekFrom::End(offset) => {
+                if offset >= 0 {
+                    entry_count + offset as u64
+                } else {
+                    entry_count.saturating_sub((-offset) as u64)
+                }
+            }
+        };
+
+        *position = new_pos as usize;
+        Ok(new_pos)
+    }
+
+    fn metadata(&self) -> Result<FileMetadata, StreamError> {
+        self.node.metadata().map_err(StreamError::from)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// ProcFS filesystem driver
+pub struct ProcFSDriver;
+
+impl FileSystemDriver for ProcFSDriver {
+    fn name(&self) -> &'static str {
+        "procfs"
+    }
+
+    fn filesystem_type(&self) -> FileSystemType {
+        FileSystemType::Virtual
+    }
+
+    fn create(&self) -> Result<Arc<dyn FileSystemOperations>, FileSystemError> {
+        Ok(ProcFS::new() as Arc<dyn FileSystemOperations>)
+    }
+
+    fn create_from_option_string(&self, _options: &str) -> Result<Arc<dyn FileSystemOperations>, FileSystemError> {
+        // ProcFS doesn't use options, just create a new instance
+        self.create()
+    }
+}
+
+/// Publish the statistics of the core kernel subsystems
+fn register_builtin_entries() {
+    use crate::mem::{buddy, kmalloc_stats};
+
+    register_proc_entry("kmallocinfo", kmalloc_stats::format_size_class_stats, None);
+    register_proc_entry("kmalloc_sites", kmalloc_stats::format_alloc_sites, Some(kmalloc_stats::control_alloc_sites));
+    register_proc_entry("magazines", crate::mem::magazine::format_magazine_stats, None);
+    register_proc_entry("buddyinfo", buddy::format_page_allocator_stats, None);
+    register_proc_entry("diskstats", crate::device::block::stats::format_io_stats, None);
+    register_proc_entry("wx", crate::vm::wx::format_status, Some(crate::vm::wx::control));
+    register_proc_entry("vmstat", crate::mem::reclaim::format_vmstat, None);
+    register_proc_entry("core_pattern", crate::task::coredump::format_core_pattern, Some(crate::task::coredump::set_core_pattern));
+    register_proc_entry("schedstat", crate::sched::stats::format_schedstat, None);
+    register_proc_entry("strace", crate::task::strace::format_trace_buffer, Some(crate::task::strace::control));
+    register_proc_entry("binfmt_misc", crate::executor::binfmt::format_entries, Some(crate::executor::binfmt::control));
+    register_proc_entry("firewall", crate::network::firewall::format_rules, Some(crate::network::firewall::control));
+    register_proc_entry("net/dev", crate::network::stats::format_interface_stats, None);
+    register_proc_entry("net/snmp", crate::network::stats::format_protocol_stats, None);
+}
+
+/// Register the ProcFS driver with the filesystem driver manager
+fn register_driver() {
+    let fs_driver_manager = get_fs_driver_manager();
+    fs_driver_manager.register_driver(Box::new(ProcFSDriver));
+    register_builtin_entries();
+}
+
+driver_initcall!(register_driver);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_entry_content() -> String {
+        "hello procfs\n".to_string()
+    }
+
+    fn test_entry_write(data: &[u8]) -> Result<(), &'static str> {
+        if data == b"ok" { Ok(()) } else { Err("rejected") }
+    }
+
+    #[test_case]
+    fn test_procfs_read_entry() {
+        register_proc_entry("test_procfs_read", test_entry_content, None);
+        let procfs = ProcFS::new();
+        let root = procfs.root_node();
+
+        let node = procfs.lookup(&root, &"test_procfs_read".to_string()).unwrap();
+        let file = procfs.open(&node, 0).unwrap();
+        let mut buffer = [0u8; 5];
+        assert_eq!(file.read(&mut buffer).unwrap(), 5);
+        assert_eq!(&buffer, b"hello");
+        let mut rest = [0u8; 64];
+        assert_eq!(file.read(&mut rest).unwrap(), 8);
+        assert_eq!(file.read(&mut rest).unwrap(), 0);
+        assert!(file.write(b"x").is_err());
+
+        let entries = procfs.readdir(&root).unwrap();
+        assert!(entries.iter().any(|entry| entry.name == "test_procfs_read"));
+        unregister_proc_entry("test_procfs_read");
+        assert!(procfs.lookup(&root, &"test_procfs_read".to_string()).is_err());
+    }
+
+    #[test_case]
+    fn test_procfs_write_entry() {
+        register_proc_entry("test_procfs_write", test_entry_content, Some(test_entry_write));
+        let procfs = ProcFS::new();
+        let root = procfs.root_node();
+
+        let node = procfs.lookup(&root, &"test_procfs_write".to_string()).unwrap();
+        assert!(node.metadata().unwrap().permissions.write);
+        let file = procfs.open(&node, 0).unwrap();
+        assert_eq!(file.write(b"ok").unwrap(), 2);
+        assert!(file.write(b"no").is_err());
+        unregister_proc_entry("test_procfs_write");
+    }
+
+    #[test_case]
+    fn test_procfs_entry_directories() {
+        register_proc_entry("test_procfs_dir/inner/file", test_entry_content, None);
+        let procfs = ProcFS::new();
+        let root = procfs.root_node();
+
+        assert!(procfs.readdir(&root).unwrap().iter().any(|entry| entry.name == "test_procfs_dir" && entry.file_type == FileType::Directory));
+        let directory = procfs.lookup(&root, &"test_procfs_dir".to_string()).unwrap();
+        let names: Vec<String> = procfs.readdir(&directory).unwrap().into_iter().map(|entry| entry.name).collect();
+        assert_eq!(names, [".", "..", "inner"]);
+        let inner = procfs.lookup(&directory, &"inner".to_string()).unwrap();
+        let node = procfs.lookup(&inner, &"file".to_string()).unwrap();
+        let mut buffer = [0u8; 32];
+        assert_eq!(procfs.open(&node, 0).unwrap().read(&mut buffer).unwrap(), 13);
+        assert!(procfs.lookup(&directory, &"file".to_string()).is_err());
+        assert!(procfs.lookup(&directory, &"self".to_string()).is_err());
+
+        unregister_proc_entry("test_procfs_dir/inner/file");
+        assert!(procfs.lookup(&root, &"test_procfs_dir".to_string()).is_err());
+    }
+
+    #[test_case]
+    fn test_procfs_builtin_entries() {
+        let fs_driver_manager = get_fs_driver_manager();
+        assert!(fs_driver_manager.has_driver("procfs"));
+        assert_eq!(fs_driver_manager.get_driver_type("procfs"), Some(FileSystemType::Virtual));
+        assert!(get_proc_entry("kmallocinfo").is_some());
+        assert!((get_proc_entry("kmallocinfo").unwrap().read)().contains("kmalloc-64"));
+    }
+
+    #[test_case]
+    fn test_procfs_self_link() {
+        let procfs = ProcFS::new();
+        let root = procfs.root_node();
+        let node = procfs.lookup(&root, &"self".to_string()).unwrap();
+        assert!(node.is_symlink().unwrap());
+        if let Some(task) = mytask() {
+            assert_eq!(node.read_link().unwrap(), task.get_id().to_string());
+        }
+        assert!(procfs.readdir(&root).unwrap().iter().any(|entry| entry.name == "self"));
+    }
+}
@@ -485,6 +485,7 @@ impl Debug for TmpNode {
 impl TmpNode {
     /// Create a new regular file node
     pub fn new_file(name: String, file_id: u64) -> Self {
+        let now = crate::time::realtime_s();
         Self {
             name: RwLock::new(name),
             file_type: RwLock::new(FileType::RegularFile),
@@ -496,11 +497,12 @@ impl TmpNode {
                     write: true,
                     execute: false,
                 },
-                created_time: 0, // TODO: actual timestamp
-                modified_time: 0,
-                accessed_time: 0,
+                created_time: now,
+                modified_time: now,
+                accessed_time: now,
                 file_id,
                 link_count: 1,
+                owner: None,
             }),
             content: RwLock::new(Vec::new()),
             children: RwLock::new(BTreeMap::new()),
@@ -511,6 +513,7 @@ impl TmpNode {
     
     /// Create a new directory node
     pub fn new_directory(name: String, file_id: u64) -> Self {
+        let now = crate::time::realtime_s();
         Self {
             name: RwLock::new(name),
             file_type: RwLock::new(FileType::Directory),
@@ -522,11 +525,12 @@ impl TmpNode {
                     write: true,
                     execute: true,
                 },
-                created_time: 0,
-                modified_time: 0,
-                accessed_time: 0,
+                created_time: now,
+                modified_time: now,
+                accessed_time: now,
                 file_id,
                 link_count: 1,
+                owner: None,
             }),
             content: RwLock::new(Vec::new()),
             children: RwLock::new(BTreeMap::new()),
@@ -537,6 +541,7 @@ impl TmpNode {
     
     /// Create a new device file node
     pub fn new_device(name: String, file_type: FileType, file_id: u64) -> Self {
+        let now = crate::time::realtime_s();
         Self {
             name: RwLock::new(name),
             file_type: RwLock::new(file_type.clone()),
@@ -548,11 +553,12 @@ impl TmpNode {
                     write: true,
                     execute: false,
                 },
-                created_time: 0,
-                modified_time: 0,
-                accessed_time: 0,
+                created_time: now,
+                modified_time: now,
+                accessed_time: now,
                 file_id,
                 link_count: 1,
+                owner: None,
             }),
             content: RwLock::new(Vec::new()),
             children: RwLock::new(BTreeMap::new()),
@@ -563,6 +569,7 @@ impl TmpNode {
     
     /// Create a new symbolic link node
     pub fn new_symlink(name: String, target: String, file_id: u64) -> Self {
+        let now = crate::time::realtime_s();
         Self {
             name: RwLock::new(name),
             file_type: RwLock::new(FileType::SymbolicLink(target.clone())),
@@ -574,11 +581,12 @@ impl TmpNode {
                     write: true,
                     execute: false,
                 },
-                created_time: 0, // TODO: actual timestamp
-                modified_time: 0,
-                accessed_time: 0,
+                created_time: now,
+                modified_time: now,
+                accessed_time: now,
                 file_id,
                 link_count: 1,
+                owner: None,
             }),
             // Store symlink target in content as UTF-8 bytes
             content: RwLock::new(target.into_bytes()),
@@ -597,7 +605,7 @@ impl TmpNode {
     pub fn update_size(&self, new_size: u64) {
         let mut metadata = self.metadata.write();
         metadata.size = new_size as usize;
-        metadata.modified_time = 0; // TODO: actual timestamp
+        metadata.modified_time = crate::time::realtime_s();
     }
     
     /// Set parent reference for this node
@@ -995,8 +1003,10 @@ impl StreamOps for TmpFileObject {
                 Ok(entry_size)
             },
             FileType::CharDevice(_) | FileType::BlockDevice(_) => {
-                self.read_device(buffer)
-                    .map_err(StreamError::from)
+                let requested = buffer.len();
+                let bytes_read = self.read_device(buffer)
+                    .map_err(StreamError::from)?;
+                crate::device::char::read_result(bytes_read, requested)
             }
             _ => Err(StreamError::NotSupported)
         }
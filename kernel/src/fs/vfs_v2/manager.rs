@@ -17,6 +17,10 @@ use crate::fs::{
     DeviceFileInfo
 };
 use crate::object::KernelObject;
+use crate::device::events::{DeviceEvent, DeviceEventListener, DeviceHotplugEvent, HotplugAction};
+use crate::device::manager::DeviceManager;
+use crate::late_initcall;
+use crate::sched::scheduler::get_scheduler;
 
 use super::{
     core::{VfsEntry, FileSystemOperations, DirectoryEntryInternal},
@@ -103,6 +107,20 @@ impl VfsManager {
             mounted_filesystems: RwLock::new(vec![root_fs.clone()]),
         }
     }
+
+    /// Create a manager over the same mounts with a working directory of its own
+    ///
+    /// Mounts made below the root through either manager are seen by both;
+    /// replacing the root filesystem is not. The working directory starts
+    /// out as this manager's.
+    pub fn share_mounts(&self) -> Self {
+        Self {
+            id: VfsManagerId::new(),
+            mount_tree: MountTree { root_mount: RwLock::new(self.mount_tree.root_mount.read().clone()) },
+            cwd: RwLock::new(self.get_cwd()),
+            mounted_filesystems: RwLock::new(self.mounted_filesystems.read().clone()),
+        }
+    }
     
     /// Mount a filesystem at the specified path
     /// 
@@ -924,6 +942,49 @@ impl VfsManager {
         Ok(KernelObject::File(Arc::new(vfs_file_obj)))
     }
 
+    /// Detach every mount whose filesystem is stored on `device`
+    /// 
+    /// Called when a device is hot-removed. Affected mounts are removed from
+    /// the mount tree together with everything mounted below them, and their
+    /// filesystems are dropped from the mounted filesystem list. The root
+    /// mount cannot be detached; I/O on it fails with device errors instead.
+    /// 
+    /// # Returns
+    /// The number of mounts that were detached
+    pub fn invalidate_device_mounts(&self, device: &dyn crate::device::Device) -> usize {
+        let root_mount = self.mount_tree.root_mount.read().clone();
+        if Self::mount_uses_device(&root_mount, device) {
+            crate::early_println!("[VFS] Root filesystem of VFS {:?} lost its device", self.id);
+        }
+
+        let mut removed = 0;
+        let mut pending = vec![root_mount];
+        while let Some(mount) = pending.pop() {
+            let mut children = mount.children.write();
+            children.retain(|_, child| {
+                if Self::mount_uses_device(child, device) {
+                    crate::early_println!("[VFS] Detaching mount {} of removed device", self.mount_tree.get_mount_absolute_path(child));
+                    removed += 1;
+                    false
+                } else {
+                    pending.push(child.clone());
+                    true
+                }
+            });
+        }
+
+        if removed > 0 {
+            self.mounted_filesystems.write().retain(|fs| !fs.is_backed_by(device));
+        }
+        removed
+    }
+
+    fn mount_uses_device(mount: &Arc<MountPoint>, device: &dyn crate::device::Device) -> bool {
+        mount.root.node().filesystem()
+            .and_then(|fs| fs.upgrade())
+            .map_or(false, |fs| fs.is_backed_by(device))
+    }
+
     /// Resolve a relative path to an absolute path using the current working directory
     /// 
     /// If the path is already absolute, returns it as-is.
@@ -955,3 +1016,51 @@ pub fn get_global_vfs_manager() -> Arc<VfsManager> {
     GLOBAL_VFS_MANAGER.get().expect("global VFS manager not initialized").clone()
 }
 
+/// Invalidates mounts backed by hot-removed devices
+/// 
+/// The global VFS and the VFS of every task are checked, so a removed disk
+/// disappears from all namespaces instead of leaving dangling mounts behind.
+struct VfsHotplugListener;
+
+impl DeviceEventListener for VfsHotplugListener {
+    fn on_device_event(&self, event: &dyn DeviceEvent) {
+        let Some(event) = event.as_any().downcast_ref::<DeviceHotplugEvent>() else {
+            return;
+        };
+        if event.action != HotplugAction::Removed {
+            return;
+        }
+
+        let mut managers: Vec<Arc<VfsManager>> = Vec::new();
+        if let Some(global) = GLOBAL_VFS_MANAGER.get() {
+            managers.push(global.clone());
+        }
+        let scheduler = get_scheduler();
+        for task_id in scheduler.get_all_task_ids() {
+            if let Some(vfs) = scheduler.get_task_by_id(task_id).and_then(|task| task.vfs.clone()) {
+                if !managers.iter().any(|m| Arc::ptr_eq(m, &vfs)) {
+                    managers.push(vfs);
+                }
+            }
+        }
+
+        for manager in managers {
+            manager.invalidate_device_mounts(&*event.device);
+        }
+    }
+
+    fn interested_in(&self, event_type: &str) -> bool {
+        event_type == "hotplug"
+    }
+}
+
+static VFS_HOTPLUG_LISTENER: Once<Arc<VfsHotplugListener>> = Once::new();
+
+fn register_vfs_hotplug_listener() {
+    let listener = VFS_HOTPLUG_LISTENER.call_once(|| Arc::new(VfsHotplugListener));
+    let weak = Arc::downgrade(listener);
+    DeviceManager::get_manager().register_hotplug_listener(weak);
+}
+
+late_initcall!(register_vfs_hotplug_listener);
+
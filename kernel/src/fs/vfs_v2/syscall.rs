@@ -28,6 +28,14 @@
 //! System calls operate within the task's namespace, enabling containerization
 //! and process isolation.
 //!
+//! ## Permissions
+//!
+//! Operations are checked against the credentials of the calling task (see
+//! `crate::task::cred`): opening and truncating need read or write access
+//! to the file, creating and removing need write and search access to the
+//! parent directory, and changing into a directory needs search access.
+//! Mounting, unmounting and pivot_root are reserved to privileged tasks.
+//!
 //! ## Error Handling
 //!
 //! System calls return usize::MAX (-1) on error and appropriate values on success.
@@ -35,9 +43,12 @@
 
 use alloc::{string::String, vec::Vec, string::ToString, sync::Arc};
 
-use crate::{arch::Trapframe, fs::FileType, library::std::string::cstring_to_string, task::mytask};
+use crate::{arch::Trapframe, fs::FileType, library::std::string::parse_c_string_from_userspace, task::mytask};
+use crate::task::signal::copy_to_user;
 
+use crate::audit::{self, AuditType, Quoted};
 use crate::fs::{VfsManager, MAX_PATH_LENGTH};
+use crate::task::cred::{MAY_EXEC, MAY_READ, MAY_WRITE};
 
 /// Open a file or directory using VFS (VfsOpen)
 /// 
@@ -55,7 +66,7 @@ use crate::fs::{VfsManager, MAX_PATH_LENGTH};
 /// * `usize::MAX` on error (file not found, permission denied, etc.)
 pub fn sys_vfs_open(trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
-    let path_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(0)).unwrap() as *const u8;
+    let path_ptr = trapframe.get_arg(0);
     let _flags = trapframe.get_arg(1) as i32;
     let _mode = trapframe.get_arg(2) as i32;
 
@@ -63,26 +74,8 @@ pub fn sys_vfs_open(trapframe: &mut Trapframe) -> usize {
     trapframe.increment_pc_next(task);
 
     // Parse path as a null-terminated C string
-    let mut path_bytes = Vec::new();
-    let mut i = 0;
-    unsafe {
-        loop {
-            let byte = *path_ptr.add(i);
-            if byte == 0 {
-                break;
-            }
-            path_bytes.push(byte);
-            i += 1;
-
-            if i > MAX_PATH_LENGTH {
-                return usize::MAX; // Path too long
-            }
-        }
-    }
-
-    // Convert path bytes to string
-    let path_str = match str::from_utf8(&path_bytes) {
-        Ok(s) => match to_absolute_path_v2(&task, s) {
+    let path_str = match parse_c_string_from_userspace(task, path_ptr, MAX_PATH_LENGTH) {
+        Ok(s) => match to_absolute_path_v2(&task, &s) {
             Ok(abs) => abs,
             Err(_) => return usize::MAX,
         },
@@ -94,6 +87,16 @@ pub fn sys_vfs_open(trapframe: &mut Trapframe) -> usize {
         Some(vfs) => vfs,
         None => return usize::MAX, // VFS not initialized
     };
+    let want = if _flags & 0x1 != 0 { // O_WRONLY-like
+        MAY_WRITE
+    } else if _flags & 0x2 != 0 { // O_RDWR-like
+        MAY_READ | MAY_WRITE
+    } else {
+        MAY_READ
+    };
+    if task.cred.check_path(vfs, &path_str, want).is_err() {
+        return usize::MAX; // Permission denied
+    }
     let file_obj = vfs.open(&path_str, 0);
     match file_obj {
         Ok(kernel_obj) => {
@@ -120,7 +123,7 @@ pub fn sys_vfs_open(trapframe: &mut Trapframe) -> usize {
                 special_semantics: None, // Could be inferred from flags like O_CLOEXEC
             };
             
-            let handle = task.handle_table.insert_with_metadata(kernel_obj, metadata);
+            let handle = task.handle_table.write().insert_with_metadata(kernel_obj, metadata);
             match handle {
                 Ok(handle) => handle as usize,
                 Err(_) => usize::MAX, // Handle table full
@@ -147,14 +150,14 @@ pub fn sys_vfs_open(trapframe: &mut Trapframe) -> usize {
 /// * `usize::MAX` on error (file not found, permission denied, etc.)
 pub fn sys_vfs_truncate(trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
-    let path_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(0)).unwrap() as *const u8;
+    let path_ptr = trapframe.get_arg(0);
     let length = trapframe.get_arg(1) as u64;
     
     trapframe.increment_pc_next(task);
 
     // Convert path bytes to string
-    let path_str: String = match cstring_to_string(path_ptr, MAX_PATH_LENGTH) {
-        Ok((s, _)) => match to_absolute_path_v2(&task, &s) {
+    let path_str: String = match parse_c_string_from_userspace(task, path_ptr, MAX_PATH_LENGTH) {
+        Ok(s) => match to_absolute_path_v2(&task, &s) {
             Ok(abs_path) => abs_path,
             Err(_) => return usize::MAX,
         },
@@ -166,6 +169,9 @@ pub fn sys_vfs_truncate(trapframe: &mut Trapframe) -> usize {
         None => return usize::MAX, // VFS not initialized
     };
 
+    if task.cred.check_path(vfs, &path_str, MAY_WRITE).is_err() {
+        return usize::MAX; // Permission denied
+    }
     let file_obj = match vfs.open(&path_str, 0) {
         Ok(obj) => obj,
         Err(_) => return usize::MAX,
@@ -195,14 +201,14 @@ pub fn sys_vfs_truncate(trapframe: &mut Trapframe) -> usize {
 /// * `usize::MAX` on error (path already exists, permission denied, etc.)
 pub fn sys_vfs_create_file(trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
-    let path_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(0)).unwrap() as *const u8;
+    let path_ptr = trapframe.get_arg(0);
     let _mode = trapframe.get_arg(1) as i32;
 
     trapframe.increment_pc_next(task);
 
     // Convert path bytes to string
-    let path_str = match cstring_to_string(path_ptr, MAX_PATH_LENGTH) {
-        Ok((s, _)) => match to_absolute_path_v2(&task, &s) {
+    let path_str = match parse_c_string_from_userspace(task, path_ptr, MAX_PATH_LENGTH) {
+        Ok(s) => match to_absolute_path_v2(&task, &s) {
             Ok(abs_path) => abs_path,
             Err(_) => return usize::MAX,
         },
@@ -214,6 +220,9 @@ pub fn sys_vfs_create_file(trapframe: &mut Trapframe) -> usize {
         None => return usize::MAX, // VFS not initialized
     };
 
+    if task.cred.check_parent(vfs, &path_str).is_err() {
+        return usize::MAX; // Permission denied
+    }
     match vfs.create_file(&path_str, FileType::RegularFile) {
         Ok(_) => 0,
         Err(_) => usize::MAX, // -1
@@ -234,13 +243,13 @@ pub fn sys_vfs_create_file(trapframe: &mut Trapframe) -> usize {
 /// * `usize::MAX` on error (path already exists, permission denied, etc.)
 pub fn sys_vfs_create_directory(trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
-    let path_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(0)).unwrap() as *const u8;
+    let path_ptr = trapframe.get_arg(0);
     
     trapframe.increment_pc_next(task);
 
     // Convert path bytes to string
-    let path_str = match cstring_to_string(path_ptr, MAX_PATH_LENGTH) {
-        Ok((s, _)) => match to_absolute_path_v2(&task, &s) {
+    let path_str = match parse_c_string_from_userspace(task, path_ptr, MAX_PATH_LENGTH) {
+        Ok(s) => match to_absolute_path_v2(&task, &s) {
             Ok(abs_path) => abs_path,
             Err(_) => return usize::MAX,
         },
@@ -252,6 +261,9 @@ pub fn sys_vfs_create_directory(trapframe: &mut Trapframe) -> usize {
         None => return usize::MAX, // VFS not initialized
     };
     
+    if task.cred.check_parent(vfs, &path_str).is_err() {
+        return usize::MAX; // Permission denied
+    }
     match vfs.create_dir(&path_str) {
         Ok(_) => 0,
         Err(_) => usize::MAX, // -1
@@ -276,37 +288,39 @@ pub fn sys_vfs_create_directory(trapframe: &mut Trapframe) -> usize {
 /// * `usize::MAX` on error (invalid path, filesystem not supported, etc.)
 pub fn sys_fs_mount(trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
-    let source_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(0)).unwrap() as *const u8;
-    let target_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(1)).unwrap() as *const u8;
-    let fstype_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(2)).unwrap() as *const u8;
+    let source_ptr = trapframe.get_arg(0);
+    let target_ptr = trapframe.get_arg(1);
+    let fstype_ptr = trapframe.get_arg(2);
     let flags = trapframe.get_arg(3) as u32;
-    let data_ptr = if trapframe.get_arg(4) == 0 {
-        core::ptr::null()
-    } else {
-        task.vm_manager.translate_vaddr(trapframe.get_arg(4)).unwrap() as *const u8
-    };
+    let data_ptr = trapframe.get_arg(4);
 
     trapframe.increment_pc_next(task);
 
+    // Changing mounts is reserved to privileged tasks
+    if !task.cred.is_privileged() {
+        audit::privilege_denied("mount");
+        return usize::MAX;
+    }
+
     // Convert paths and parameters to strings
-    let source_str = match cstring_to_string(source_ptr, MAX_PATH_LENGTH) {
-        Ok((s, _)) => s,
+    let source_str = match parse_c_string_from_userspace(task, source_ptr, MAX_PATH_LENGTH) {
+        Ok(s) => s,
         Err(_) => return usize::MAX,
     };
     
-    let target_str = match cstring_to_string(target_ptr, MAX_PATH_LENGTH) {
-        Ok((s, _)) => s,
+    let target_str = match parse_c_string_from_userspace(task, target_ptr, MAX_PATH_LENGTH) {
+        Ok(s) => s,
         Err(_) => return usize::MAX,
     };
     
-    let fstype_str = match cstring_to_string(fstype_ptr, MAX_PATH_LENGTH) {
-        Ok((s, _)) => s,
+    let fstype_str = match parse_c_string_from_userspace(task, fstype_ptr, MAX_PATH_LENGTH) {
+        Ok(s) => s,
         Err(_) => return usize::MAX,
     };
     
-    let data_str = if !data_ptr.is_null() {
-        match cstring_to_string(data_ptr, MAX_PATH_LENGTH) {
-            Ok((s, _)) => Some(s),
+    let data_str = if data_ptr != 0 {
+        match parse_c_string_from_userspace(task, data_ptr, MAX_PATH_LENGTH) {
+            Ok(s) => Some(s),
             Err(_) => return usize::MAX,
         }
     } else {
@@ -320,23 +334,25 @@ pub fn sys_fs_mount(trapframe: &mut Trapframe) -> usize {
     };
 
     // Handle different mount types
-    match fstype_str.as_str() {
+    let result = match fstype_str.as_str() {
         "bind" => {
             // Handle bind mount - this is a special case handled by VFS
             let _read_only = (flags & 1) != 0; // MS_RDONLY
-            match vfs.bind_mount(&source_str, &target_str) {
-                Ok(_) => 0,
-                Err(_) => usize::MAX,
-            }
+            vfs.bind_mount(&source_str, &target_str)
         },
         _ => {
             // Handle filesystem creation using drivers
             let options = data_str.unwrap_or_default();
-            match create_filesystem_and_mount(vfs, &fstype_str, &target_str, &options) {
-                Ok(_) => 0,
-                Err(_) => usize::MAX,
-            }
+            create_filesystem_and_mount(vfs, &fstype_str, &target_str, &options)
         }
+    };
+    audit::log(AuditType::Mount, format_args!(
+        "op=mount source={} target={} fstype={} res={}",
+        Quoted(&source_str), Quoted(&target_str), Quoted(&fstype_str), audit::result_name(&result)
+    ));
+    match result {
+        Ok(_) => 0,
+        Err(_) => usize::MAX,
     }
 }
 
@@ -398,14 +414,20 @@ fn create_filesystem_and_mount(
 /// * `usize::MAX` on error (path not found, filesystem busy, etc.)
 pub fn sys_fs_umount(trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
-    let target_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(0)).unwrap() as *const u8;
+    let target_ptr = trapframe.get_arg(0);
     let _flags = trapframe.get_arg(1) as u32; // Reserved for future use
 
     trapframe.increment_pc_next(task);
 
+    // Changing mounts is reserved to privileged tasks
+    if !task.cred.is_privileged() {
+        audit::privilege_denied("umount");
+        return usize::MAX;
+    }
+
     // Convert target path to string
-    let target_str: String = match cstring_to_string(target_ptr, MAX_PATH_LENGTH) {
-        Ok((s, _)) => match to_absolute_path_v2(&task, &s) {
+    let target_str: String = match parse_c_string_from_userspace(task, target_ptr, MAX_PATH_LENGTH) {
+        Ok(s) => match to_absolute_path_v2(&task, &s) {
             Ok(abs_path) => abs_path,
             Err(_) => return usize::MAX,
         },
@@ -419,7 +441,11 @@ pub fn sys_fs_umount(trapframe: &mut Trapframe) -> usize {
     };
 
     // Perform umount operation
-    match vfs.unmount(&target_str) {
+    let result = vfs.unmount(&target_str);
+    audit::log(AuditType::Mount, format_args!(
+        "op=umount target={} res={}", Quoted(&target_str), audit::result_name(&result)
+    ));
+    match result {
         Ok(_) => 0,
         Err(_) => usize::MAX,
     }
@@ -468,14 +494,20 @@ pub fn sys_fs_umount(trapframe: &mut Trapframe) -> usize {
 /// * `usize::MAX` on error (invalid path, operation not permitted, etc.)
 pub fn sys_fs_pivot_root(trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
-    let new_root_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(0)).unwrap() as *const u8;
-    let old_root_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(1)).unwrap() as *const u8;
+    let new_root_ptr = trapframe.get_arg(0);
+    let old_root_ptr = trapframe.get_arg(1);
 
     trapframe.increment_pc_next(&task);
 
+    // Changing mounts is reserved to privileged tasks
+    if !task.cred.is_privileged() {
+        audit::privilege_denied("pivot_root");
+        return usize::MAX;
+    }
+
     // Convert new_root path to string
-    let new_root_str: String = match cstring_to_string(new_root_ptr, MAX_PATH_LENGTH) {
-        Ok((s, _)) => match to_absolute_path_v2(&task, &s) {
+    let new_root_str: String = match parse_c_string_from_userspace(task, new_root_ptr, MAX_PATH_LENGTH) {
+        Ok(s) => match to_absolute_path_v2(&task, &s) {
             Ok(abs_path) => abs_path,
             Err(_) => return usize::MAX,
         },
@@ -483,8 +515,8 @@ pub fn sys_fs_pivot_root(trapframe: &mut Trapframe) -> usize {
     };
 
     // Convert old_root path to string
-    let old_root_str: String = match cstring_to_string(old_root_ptr, MAX_PATH_LENGTH) {
-        Ok((s, _)) => match to_absolute_path_v2(&task, &s) {
+    let old_root_str: String = match parse_c_string_from_userspace(task, old_root_ptr, MAX_PATH_LENGTH) {
+        Ok(s) => match to_absolute_path_v2(&task, &s) {
             Ok(abs_path) => abs_path,
             Err(_) => return usize::MAX,
         },
@@ -503,7 +535,12 @@ pub fn sys_fs_pivot_root(trapframe: &mut Trapframe) -> usize {
     };
 
     // Perform pivot_root by replacing the mount_tree inside the existing VfsManager
-    match pivot_root_in_place(&current_vfs, &new_root_str, &old_root_str) {
+    let result = pivot_root_in_place(&current_vfs, &new_root_str, &old_root_str);
+    audit::log(AuditType::Mount, format_args!(
+        "op=pivot_root new_root={} put_old={} res={}",
+        Quoted(&new_root_str), Quoted(&old_root_str), audit::result_name(&result)
+    ));
+    match result {
         Ok(_) => 0,
         Err(e) => {
             crate::println!("Failed to pivot root: {}", e.message);
@@ -611,14 +648,14 @@ fn pivot_root_in_place(
 /// * `usize::MAX` on error (path not found, not a directory, etc.)
 pub fn sys_vfs_change_directory(trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
-    let path_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(0)).unwrap() as *const u8;
+    let path_ptr = trapframe.get_arg(0);
     
     // Increment PC to avoid infinite loop if chdir fails
     trapframe.increment_pc_next(task);
     
     // Convert path pointer to string
-    let path = match cstring_to_string(path_ptr, MAX_PATH_LENGTH) {
-        Ok(p) => p.0,
+    let path = match parse_c_string_from_userspace(task, path_ptr, MAX_PATH_LENGTH) {
+        Ok(p) => p,
         Err(_) => return usize::MAX,
     };
     
@@ -638,6 +675,9 @@ pub fn sys_vfs_change_directory(trapframe: &mut Trapframe) -> usize {
     match vfs.resolve_path(&absolute_path) {
         Ok((entry, _mount_point)) => {
             if entry.node().file_type().unwrap() == FileType::Directory {
+                if task.cred.check_path(vfs, &absolute_path, MAY_EXEC).is_err() {
+                    return usize::MAX; // Permission denied
+                }
                 // Update the current working directory via VfsManager
                 match vfs.set_cwd_by_path(&absolute_path) {
                     Ok(()) => 0, // Success
@@ -669,14 +709,14 @@ pub fn sys_vfs_change_directory(trapframe: &mut Trapframe) -> usize {
 /// * `usize::MAX` on error (file/directory not found, permission denied, directory not empty, etc.)
 pub fn sys_vfs_remove(trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
-    let path_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(0)).unwrap() as *const u8;
+    let path_ptr = trapframe.get_arg(0);
 
     // Increment PC to avoid infinite loop if remove fails
     trapframe.increment_pc_next(task);
 
     // Convert path pointer to Rust string
-    let path = match cstring_to_string(path_ptr, MAX_PATH_LENGTH) {
-        Ok((s, _)) => s,
+    let path = match parse_c_string_from_userspace(task, path_ptr, MAX_PATH_LENGTH) {
+        Ok(s) => s,
         Err(_) => return usize::MAX,
     };
 
@@ -695,6 +735,9 @@ pub fn sys_vfs_remove(trapframe: &mut Trapframe) -> usize {
     // Try to resolve the path to check if it exists
     match vfs.resolve_path(&absolute_path) {
         Ok(_) => {
+            if task.cred.check_parent(vfs, &absolute_path).is_err() {
+                return usize::MAX; // Permission denied
+            }
             // Path exists, attempt to remove it using unified VFS remove method
             match vfs.remove(&absolute_path) {
                 Ok(_) => 0,
@@ -720,14 +763,14 @@ pub fn sys_vfs_remove(trapframe: &mut Trapframe) -> usize {
 /// * `usize::MAX` on error (path already exists, permission denied, etc.)
 pub fn sys_vfs_create_symlink(trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
-    let symlink_path_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(0)).unwrap() as *const u8;
-    let target_path_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(1)).unwrap() as *const u8;
+    let symlink_path_ptr = trapframe.get_arg(0);
+    let target_path_ptr = trapframe.get_arg(1);
     
     trapframe.increment_pc_next(task);
 
     // Convert symlink path bytes to string
-    let symlink_path_str = match cstring_to_string(symlink_path_ptr, MAX_PATH_LENGTH) {
-        Ok((s, _)) => match to_absolute_path_v2(&task, &s) {
+    let symlink_path_str = match parse_c_string_from_userspace(task, symlink_path_ptr, MAX_PATH_LENGTH) {
+        Ok(s) => match to_absolute_path_v2(&task, &s) {
             Ok(abs_path) => abs_path,
             Err(_) => return usize::MAX,
         },
@@ -735,8 +778,8 @@ pub fn sys_vfs_create_symlink(trapframe: &mut Trapframe) -> usize {
     };
     
     // Convert target path bytes to string (target can be relative, don't convert to absolute)
-    let target_path_str = match cstring_to_string(target_path_ptr, MAX_PATH_LENGTH) {
-        Ok((s, _)) => s,
+    let target_path_str = match parse_c_string_from_userspace(task, target_path_ptr, MAX_PATH_LENGTH) {
+        Ok(s) => s,
         Err(_) => return usize::MAX, // Invalid UTF-8
     };
     
@@ -745,6 +788,9 @@ pub fn sys_vfs_create_symlink(trapframe: &mut Trapframe) -> usize {
         None => return usize::MAX, // VFS not initialized
     };
     
+    if task.cred.check_parent(vfs, &symlink_path_str).is_err() {
+        return usize::MAX; // Permission denied
+    }
     match vfs.create_symlink(&symlink_path_str, &target_path_str) {
         Ok(_) => 0,
         Err(_) => usize::MAX, // -1
@@ -767,15 +813,15 @@ pub fn sys_vfs_create_symlink(trapframe: &mut Trapframe) -> usize {
 /// * `usize::MAX` on error (not a symlink, permission denied, etc.)
 pub fn sys_vfs_readlink(trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
-    let symlink_path_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(0)).unwrap() as *const u8;
-    let buffer_ptr = task.vm_manager.translate_vaddr(trapframe.get_arg(1)).unwrap() as *mut u8;
+    let symlink_path_ptr = trapframe.get_arg(0);
+    let buffer_ptr = trapframe.get_arg(1);
     let buffer_size = trapframe.get_arg(2);
     
     trapframe.increment_pc_next(task);
 
     // Convert symlink path bytes to string
-    let symlink_path_str = match cstring_to_string(symlink_path_ptr, MAX_PATH_LENGTH) {
-        Ok((s, _)) => match to_absolute_path_v2(&task, &s) {
+    let symlink_path_str = match parse_c_string_from_userspace(task, symlink_path_ptr, MAX_PATH_LENGTH) {
+        Ok(s) => match to_absolute_path_v2(&task, &s) {
             Ok(abs_path) => abs_path,
             Err(_) => return usize::MAX,
         },
@@ -815,12 +861,8 @@ pub fn sys_vfs_readlink(trapframe: &mut Trapframe) -> usize {
     let bytes_to_copy = core::cmp::min(target_bytes.len(), buffer_size);
     
     // Copy target to user buffer
-    unsafe {
-        core::ptr::copy_nonoverlapping(
-            target_bytes.as_ptr(),
-            buffer_ptr,
-            bytes_to_copy
-        );
+    if copy_to_user(task, buffer_ptr, &target_bytes[..bytes_to_copy]).is_err() {
+        return usize::MAX;
     }
     
     bytes_to_copy
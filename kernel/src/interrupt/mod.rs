@@ -7,8 +7,13 @@ use core::fmt;
 use hashbrown::HashMap;
 
 use crate::arch::{self, interrupt::enable_external_interrupts};
+use crate::environment::NUM_OF_CPUS;
+use crate::sched::affinity::CpuMask;
 
 pub mod controllers;
+pub mod routing;
+
+pub use routing::{InterruptRoute, PRIORITY_HIGH, PRIORITY_LOW, PRIORITY_NORMAL};
 
 /// Interrupt ID type
 pub type InterruptId = u32;
@@ -162,6 +167,8 @@ pub struct InterruptManager {
     controllers: controllers::InterruptControllers,
     external_handlers: spin::Mutex<HashMap<InterruptId, ExternalInterruptHandler>>,
     interrupt_devices: spin::Mutex<HashMap<InterruptId, alloc::sync::Arc<dyn crate::device::events::InterruptCapableDevice>>>,
+    /// Interrupts requested with [`InterruptManager::request_external_interrupt`]
+    routes: HashMap<InterruptId, InterruptRoute>,
 }
 
 impl InterruptManager {
@@ -172,6 +179,7 @@ impl InterruptManager {
             controllers: controllers::InterruptControllers::new(),
             external_handlers: spin::Mutex::new(HashMap::new()),
             interrupt_devices: spin::Mutex::new(HashMap::new()),
+            routes: HashMap::new(),
         }
     }
 
@@ -216,6 +224,12 @@ impl InterruptManager {
             }
         }
 
+        // Interrupts requested by drivers probed before this point
+        for (interrupt_id, route) in self.routes.clone() {
+            // Cannot fail: the route was applied once already
+            let _ = self.apply_route(interrupt_id, &route);
+        }
+
         
         enable_external_interrupts(); // Enable external interrupts
         // Timer interrupts are disabled by default, enable them if needed by scheduler or other components
@@ -267,6 +281,37 @@ impl InterruptManager {
         }
     }
 
+    /// Claim and handle the next pending external interrupt on `cpu_id`
+    ///
+    /// Unlike [`InterruptManager::claim_and_handle_external_interrupt`],
+    /// device handlers run with the global manager unlocked, so CPUs take
+    /// the interrupts routed to them in parallel. The interrupt stays
+    /// claimed, and is not delivered again, until the handler returns.
+    pub fn dispatch_external_interrupt(cpu_id: CpuId) -> InterruptResult<Option<InterruptId>> {
+        let (interrupt_id, device) = {
+            let mut manager = Self::get_manager();
+            let interrupt_id = match manager.controllers.external_controller_mut() {
+                Some(controller) => controller.claim_interrupt(cpu_id)?,
+                None => return Err(InterruptError::ControllerNotFound),
+            };
+            let Some(interrupt_id) = interrupt_id else {
+                return Ok(None);
+            };
+            let device = manager.interrupt_devices.lock().get(&interrupt_id).cloned();
+            match device {
+                Some(device) => (interrupt_id, device),
+                None => {
+                    // Function-based handlers get the manager through their handle
+                    manager.handle_external_interrupt(interrupt_id, cpu_id)?;
+                    return Ok(Some(interrupt_id));
+                }
+            }
+        };
+        let result = device.handle_interrupt();
+        Self::get_manager().complete_external_interrupt(cpu_id, interrupt_id)?;
+        result.map(|()| Some(interrupt_id))
+    }
+
     /// Enable a local interrupt type for a CPU
     pub fn enable_local_interrupt(&mut self, cpu_id: CpuId, interrupt_type: controllers::LocalInterruptType) -> InterruptResult<()> {
         if let Some(ref mut controller) = self.controllers.local_controller_mut_for_cpu(cpu_id) {
@@ -366,13 +411,119 @@ impl InterruptManager {
 
     /// Complete an external interrupt
     pub fn complete_external_interrupt(&mut self, cpu_id: CpuId, interrupt_id: InterruptId) -> InterruptResult<()> {
+        // An interrupt routed away while it was handled here: the controller
+        // ignores completions from a CPU the interrupt is not enabled on
+        let moved = self.routes.get(&interrupt_id).is_some_and(|route| route.cpu != cpu_id);
         if let Some(ref mut controller) = self.controllers.external_controller_mut() {
-            controller.complete_interrupt(cpu_id, interrupt_id)
+            if moved {
+                controller.enable_interrupt(interrupt_id, cpu_id)?;
+            }
+            let result = controller.complete_interrupt(cpu_id, interrupt_id);
+            if moved {
+                controller.disable_interrupt(interrupt_id, cpu_id)?;
+            }
+            result
         } else {
             Err(InterruptError::ControllerNotFound)
         }
     }
 
+    /// Request delivery of an external interrupt
+    ///
+    /// The interrupt is given `priority` and enabled on one CPU of
+    /// `affinity`, picked to spread interrupts over the online CPUs (see
+    /// [`routing`]). Returns the CPU it is routed to for now; the route may
+    /// change as CPUs come online, but stays within `affinity`.
+    pub fn request_external_interrupt(&mut self, interrupt_id: InterruptId, priority: Priority, affinity: CpuMask) -> InterruptResult<CpuId> {
+        let max_cpus = match self.controllers.external_controller_mut() {
+            Some(controller) => controller.max_cpus(),
+            None => return Err(InterruptError::ControllerNotFound),
+        };
+        let affinity = affinity.intersection(CpuMask::from_bits((1usize << max_cpus.min(usize::BITS - 1)) - 1));
+        let load = self.route_load(Some(interrupt_id));
+        let cpu = routing::pick_cpu(affinity, Self::routable_cpus(), &load).ok_or(InterruptError::InvalidCpuId)?;
+        let route = InterruptRoute { affinity, priority, cpu };
+        self.apply_route(interrupt_id, &route)?;
+        if let Some(old) = self.routes.insert(interrupt_id, route) {
+            if old.cpu != cpu {
+                self.disable_external_interrupt(interrupt_id, old.cpu)?;
+            }
+        }
+        Ok(cpu)
+    }
+
+    /// Change the CPUs a requested interrupt may be delivered to
+    pub fn set_external_affinity(&mut self, interrupt_id: InterruptId, affinity: CpuMask) -> InterruptResult<CpuId> {
+        let route = self.routes.get(&interrupt_id).ok_or(InterruptError::InvalidInterruptId)?;
+        self.request_external_interrupt(interrupt_id, route.priority, affinity)
+    }
+
+    /// Change the priority of a requested interrupt
+    pub fn set_external_priority(&mut self, interrupt_id: InterruptId, priority: Priority) -> InterruptResult<()> {
+        let route = self.routes.get_mut(&interrupt_id).ok_or(InterruptError::InvalidInterruptId)?;
+        let controller = self.controllers.external_controller_mut().ok_or(InterruptError::ControllerNotFound)?;
+        controller.set_priority(interrupt_id, priority)?;
+        route.priority = priority;
+        Ok(())
+    }
+
+    /// Route of a requested interrupt
+    pub fn external_route(&self, interrupt_id: InterruptId) -> Option<InterruptRoute> {
+        self.routes.get(&interrupt_id).copied()
+    }
+
+    /// Spread the requested interrupts over the online CPUs again
+    ///
+    /// Called as each CPU comes online. Higher priorities are placed first,
+    /// so that they are the ones spread when there are few interrupts.
+    pub fn rebalance_external_interrupts(&mut self) {
+        let mut routes: alloc::vec::Vec<_> = self.routes.iter().map(|(&id, &route)| (id, route)).collect();
+        routes.sort_by_key(|&(id, route)| (core::cmp::Reverse(route.priority), id));
+        let online = Self::routable_cpus();
+        let mut load = [0usize; NUM_OF_CPUS];
+        for (interrupt_id, route) in routes {
+            let Some(cpu) = routing::pick_cpu(route.affinity, online, &load) else {
+                continue;
+            };
+            load[cpu as usize] += 1;
+            if cpu == route.cpu {
+                continue;
+            }
+            let moved = InterruptRoute { cpu, ..route };
+            if self.apply_route(interrupt_id, &moved).is_ok() {
+                self.routes.insert(interrupt_id, moved);
+                let _ = self.disable_external_interrupt(interrupt_id, route.cpu);
+            }
+        }
+    }
+
+    /// Program the priority of a route and enable it on its CPU
+    fn apply_route(&mut self, interrupt_id: InterruptId, route: &InterruptRoute) -> InterruptResult<()> {
+        let controller = self.controllers.external_controller_mut().ok_or(InterruptError::ControllerNotFound)?;
+        controller.set_priority(interrupt_id, route.priority)?;
+        controller.enable_interrupt(interrupt_id, route.cpu)
+    }
+
+    /// Interrupts routed to each CPU, not counting `except`
+    fn route_load(&self, except: Option<InterruptId>) -> [usize; NUM_OF_CPUS] {
+        let mut load = [0; NUM_OF_CPUS];
+        for (&interrupt_id, route) in &self.routes {
+            if Some(interrupt_id) != except {
+                if let Some(count) = load.get_mut(route.cpu as usize) {
+                    *count += 1;
+                }
+            }
+        }
+        load
+    }
+
+    /// CPUs taking interrupts: the online ones and, while booting, this one
+    fn routable_cpus() -> CpuMask {
+        let online = crate::sched::scheduler::online_cpus();
+        let current = CpuMask::single(arch::get_cpu().get_cpuid());
+        CpuMask::from_bits(online.bits() | current.bits())
+    }
+
     /// Enable an external interrupt for a specific CPU
     pub fn enable_external_interrupt(&mut self, interrupt_id: InterruptId, cpu_id: CpuId) -> InterruptResult<()> {
         if let Some(ref mut controller) = self.controllers.external_controller_mut() {
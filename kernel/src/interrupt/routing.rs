@@ -0,0 +1,65 @@
+//! External interrupt routing
+//!
+//! Each external interrupt a driver requests is delivered to one CPU, picked
+//! from the CPUs the driver allows (its affinity). Interrupts are spread so
+//! that every online CPU takes about as many of them; until the secondary
+//! CPUs come online everything lands on the boot CPU, and the routes are
+//! rebalanced as each CPU joins the scheduler.
+//!
+//! A route also carries the priority of the interrupt at the controller:
+//! among interrupts pending on the same CPU, the highest priority is claimed
+//! first.
+
+use crate::sched::affinity::CpuMask;
+
+use super::{CpuId, Priority};
+
+/// Console and other interrupts that can wait
+pub const PRIORITY_LOW: Priority = 1;
+/// Most devices
+pub const PRIORITY_NORMAL: Priority = 2;
+/// Network and block devices, whose queues fill up quickly
+pub const PRIORITY_HIGH: Priority = 3;
+
+/// Where an external interrupt is delivered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptRoute {
+    /// CPUs the interrupt may be delivered to
+    pub affinity: CpuMask,
+    pub priority: Priority,
+    /// CPU the interrupt is enabled on
+    pub cpu: CpuId,
+}
+
+/// The CPU to route an interrupt with `affinity` to
+///
+/// This is the CPU in both `affinity` and `online` with the fewest
+/// interrupts routed to it by `load`, the lowest on ties. If none of the
+/// allowed CPUs is online, the interrupt goes to the first of them and is
+/// moved when the CPUs come online.
+pub fn pick_cpu(affinity: CpuMask, online: CpuMask, load: &[usize]) -> Option<CpuId> {
+    let candidates = affinity.intersection(online);
+    (0..load.len())
+        .filter(|&cpu_id| candidates.contains(cpu_id))
+        .min_by_key(|&cpu_id| (load[cpu_id], cpu_id))
+        .or(affinity.first())
+        .map(|cpu_id| cpu_id as CpuId)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_pick_cpu() {
+        let all = CpuMask::from_bits(0b11);
+        // Only the boot CPU is up
+        assert_eq!(pick_cpu(all, CpuMask::single(0), &[3, 0]), Some(0));
+        // The least loaded online CPU, the lowest on ties
+        assert_eq!(pick_cpu(all, all, &[1, 0]), Some(1));
+        assert_eq!(pick_cpu(all, all, &[1, 1]), Some(0));
+        // An affinity to an offline CPU is kept
+        assert_eq!(pick_cpu(CpuMask::single(1), CpuMask::single(0), &[0, 0]), Some(1));
+        assert_eq!(pick_cpu(CpuMask::from_bits(0), all, &[0, 0]), None);
+    }
+}
@@ -0,0 +1,487 @@
+//! Message bus
+//!
+//! The bus is a broker of messages between system services. A task takes
+//! part through a connection, which has an inbox of its own:
+//! - Channels: a connection subscribes to a channel by name, and what is
+//!   published on the channel is copied to the inbox of every subscriber.
+//! - Services: a connection registers a service name, and requests sent to
+//!   the name arrive in its inbox. The service answers each request once;
+//!   the reply goes to the inbox of the requester, which can wait for the
+//!   reply to one request while other messages stay queued.
+//!
+//! Messages carry data and kernel objects, like socket messages do: each
+//! receiver gets the objects and installs them as new handles. Names are
+//! not checked against any permission.
+//!
+//! A publisher does not wait for its subscribers: a message published to
+//! a full inbox is lost for that subscriber. A request to a service whose
+//! inbox is full fails with `WouldBlock`. Replies are always queued, and a
+//! service that goes away answers its open requests with `Failed`.
+
+use alloc::collections::{BTreeMap, BTreeSet, VecDeque};
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+
+use crate::abi::error::KernelError;
+use crate::object::capability::poll::{wait_for, PollOps, PollWait, POLLIN, POLLOUT};
+use crate::object::KernelObject;
+use crate::sync::waker::Waker;
+
+/// Longest name of a channel or a service
+pub const BUS_NAME_MAX: usize = 255;
+/// Largest data of a message
+pub const BUS_MESSAGE_MAX: usize = 64 * 1024;
+/// Most objects one message can carry
+pub const BUS_MAX_OBJECTS: usize = 64;
+/// Most messages an inbox holds, replies aside
+pub const BUS_QUEUE_MAX: usize = 256;
+
+/// Operations fail with `WouldBlock` instead of waiting (connection flag)
+pub const BUS_NONBLOCK: usize = 0x1;
+
+/// What a message is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusMessageKind {
+    /// Published on a channel
+    Publish = 1,
+    /// A request to a service of the receiver
+    Request = 2,
+    /// The reply to a request of the receiver
+    Reply = 3,
+    /// The service of a request of the receiver went away without a reply
+    Failed = 4,
+}
+
+/// What one receive returned
+pub struct BusReceived {
+    pub kind: BusMessageKind,
+    /// Serial of the request of a request, reply or failure
+    pub serial: u64,
+    /// Connection id of the sender
+    pub sender: u64,
+    /// The channel of a publish, the service of the other kinds
+    pub name: String,
+    /// Bytes written to the buffer
+    pub len: usize,
+    /// The data was longer than the buffer and the rest is lost
+    pub truncated: bool,
+    /// Objects sent with the data
+    pub objects: Vec<KernelObject>,
+}
+
+struct BusMessage {
+    kind: BusMessageKind,
+    serial: u64,
+    sender: u64,
+    name: String,
+    data: Vec<u8>,
+    objects: Vec<KernelObject>,
+}
+
+/// The inbox of a connection
+struct Mailbox {
+    messages: Mutex<VecDeque<BusMessage>>,
+    /// Woken whenever a message arrives
+    waker: Waker,
+}
+
+impl Mailbox {
+    /// Queue `message`, unless the inbox is full and the message is
+    /// `limited`
+    fn push(&self, message: BusMessage, limited: bool) -> bool {
+        {
+            let mut messages = self.messages.lock();
+            if limited && messages.len() >= BUS_QUEUE_MAX {
+                return false;
+            }
+            messages.push_back(message);
+        }
+        self.waker.wake_all();
+        true
+    }
+
+    /// Take the first message `wanted` picks
+    fn take(&self, wanted: impl Fn(&BusMessage) -> bool) -> Option<BusMessage> {
+        let mut messages = self.messages.lock();
+        let index = messages.iter().position(wanted)?;
+        messages.remove(index)
+    }
+}
+
+/// Subscribers of the channels and owners of the services
+struct Broker {
+    channels: BTreeMap<String, BTreeMap<u64, Weak<Mailbox>>>,
+    services: BTreeMap<String, Weak<BusConnection>>,
+}
+
+static BROKER: Mutex<Broker> = Mutex::new(Broker { channels: BTreeMap::new(), services: BTreeMap::new() });
+
+/// Ids of connections, and serials of requests
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+static NEXT_SERIAL: AtomicU64 = AtomicU64::new(1);
+
+fn check_name(name: &str) -> Result<(), KernelError> {
+    match name.len() {
+        0 => Err(KernelError::InvalidArgument),
+        len if len > BUS_NAME_MAX => Err(KernelError::NameTooLong),
+        _ => Ok(()),
+    }
+}
+
+fn check_message(data: &[u8], objects: &[KernelObject]) -> Result<(), KernelError> {
+    if data.len() > BUS_MESSAGE_MAX {
+        return Err(KernelError::MessageTooLong);
+    }
+    if objects.len() > BUS_MAX_OBJECTS {
+        return Err(KernelError::InvalidArgument);
+    }
+    Ok(())
+}
+
+/// A request that waits for the reply of the receiver
+struct PendingRequest {
+    requester: Weak<Mailbox>,
+    service: String,
+}
+
+#[derive(Default)]
+struct ConnectionState {
+    channels: BTreeSet<String>,
+    services: BTreeSet<String>,
+    /// Requests to this connection not answered yet, by serial
+    pending: BTreeMap<u64, PendingRequest>,
+    /// Requests of this connection whose reply is not taken yet
+    outstanding: BTreeSet<u64>,
+}
+
+/// A connection to the bus
+pub struct BusConnection {
+    id: u64,
+    nonblocking: bool,
+    mailbox: Arc<Mailbox>,
+    state: Mutex<ConnectionState>,
+    /// The connection itself, registered under its service names
+    this: Weak<BusConnection>,
+}
+
+impl BusConnection {
+    /// A connection with no subscription and no service
+    pub fn new(nonblocking: bool) -> Arc<Self> {
+        Arc::new_cyclic(|this| Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            nonblocking,
+            mailbox: Arc::new(Mailbox {
+                messages: Mutex::new(VecDeque::new()),
+                waker: Waker::new_interruptible("bus"),
+            }),
+            state: Mutex::new(ConnectionState::default()),
+            this: this.clone(),
+        })
+    }
+
+    /// The id of the connection, given to receivers as the sender
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Receive what is published on `channel` from now on
+    pub fn subscribe(&self, channel: &str) -> Result<(), KernelError> {
+        check_name(channel)?;
+        let mut state = self.state.lock();
+        if !state.channels.insert(channel.to_string()) {
+            return Err(KernelError::Exists);
+        }
+        BROKER.lock().channels.entry(channel.to_string()).or_default().insert(self.id, Arc::downgrade(&self.mailbox));
+        Ok(())
+    }
+
+    /// Stop receiving what is published on `channel`
+    pub fn unsubscribe(&self, channel: &str) -> Result<(), KernelError> {
+        if !self.state.lock().channels.remove(channel) {
+            return Err(KernelError::NotFound);
+        }
+        remove_subscriber(&mut BROKER.lock(), channel, self.id);
+        Ok(())
+    }
+
+    /// Take the service name `service`, failing with `AddressInUse` if
+    /// another connection has it
+    pub fn register(&self, service: &str) -> Result<(), KernelError> {
+        check_name(service)?;
+        let mut state = self.state.lock();
+        let mut broker = BROKER.lock();
+        if broker.services.get(service).is_some_and(|owner| owner.strong_count() > 0) {
+            return Err(KernelError::AddressInUse);
+        }
+        broker.services.insert(service.to_string(), self.this.clone());
+        state.services.insert(service.to_string());
+        Ok(())
+    }
+
+    /// Publish `data` with `objects` on `channel`
+    ///
+    /// Every subscriber gets its own copy of the objects.
+    ///
+    /// # Returns
+    /// The number of subscribers the message was queued for
+    pub fn publish(&self, channel: &str, data: &[u8], objects: Vec<KernelObject>) -> Result<usize, KernelError> {
+        check_name(channel)?;
+        check_message(data, &objects)?;
+        let subscribers: Vec<Arc<Mailbox>> = match BROKER.lock().channels.get(channel) {
+            Some(subscribers) => subscribers.values().filter_map(Weak::upgrade).collect(),
+            None => Vec::new(),
+        };
+        let mut delivered = 0;
+        for mailbox in subscribers {
+            let message = BusMessage {
+                kind: BusMessageKind::Publish,
+                serial: 0,
+                sender: self.id,
+                name: channel.to_string(),
+                data: data.to_vec(),
+                objects: objects.clone(),
+            };
+            if mailbox.push(message, true) {
+                delivered += 1;
+            }
+        }
+        Ok(delivered)
+    }
+
+    /// Send a request of `data` with `objects` to `service`
+    ///
+    /// # Returns
+    /// The serial of the request, which its reply carries
+    pub fn request(&self, service: &str, data: &[u8], objects: Vec<KernelObject>) -> Result<u64, KernelError> {
+        check_name(service)?;
+        check_message(data, &objects)?;
+        let owner = BROKER.lock().services.get(service).and_then(Weak::upgrade).ok_or(KernelError::NotFound)?;
+        let serial = NEXT_SERIAL.fetch_add(1, Ordering::Relaxed);
+
+        // Recorded first, so that the service can answer at once
+        owner.state.lock().pending.insert(serial, PendingRequest {
+            requester: Arc::downgrade(&self.mailbox),
+            service: service.to_string(),
+        });
+        self.state.lock().outstanding.insert(serial);
+        let message = BusMessage {
+            kind: BusMessageKind::Request,
+            serial,
+            sender: self.id,
+            name: service.to_string(),
+            data: data.to_vec(),
+            objects,
+        };
+        if !owner.mailbox.push(message, true) {
+            owner.state.lock().pending.remove(&serial);
+            self.state.lock().outstanding.remove(&serial);
+            return Err(KernelError::WouldBlock);
+        }
+        Ok(serial)
+    }
+
+    /// Answer the request `serial` with `data` and `objects`
+    ///
+    /// A reply to a requester that is gone is dropped.
+    pub fn reply(&self, serial: u64, data: &[u8], objects: Vec<KernelObject>) -> Result<(), KernelError> {
+        check_message(data, &objects)?;
+        let request = self.state.lock().pending.remove(&serial).ok_or(KernelError::NotFound)?;
+        if let Some(requester) = request.requester.upgrade() {
+            requester.push(BusMessage {
+                kind: BusMessageKind::Reply,
+                serial,
+                sender: self.id,
+                name: request.service,
+                data: data.to_vec(),
+                objects,
+            }, false);
+        }
+        Ok(())
+    }
+
+    /// Receive the next message into `buffer`, waiting while there is none
+    pub fn receive(&self, buffer: &mut [u8]) -> Result<BusReceived, KernelError> {
+        let message = self.wait_for_message(|_| true)?;
+        Ok(self.received(message, buffer))
+    }
+
+    /// Receive the reply to the request `serial` into `buffer`, waiting for
+    /// it; other messages stay queued
+    ///
+    /// # Errors
+    /// `InvalidArgument` if `serial` is not a request of this connection
+    /// whose reply is still to be taken, `BrokenPipe` if the service went
+    /// away without a reply
+    pub fn wait_reply(&self, serial: u64, buffer: &mut [u8]) -> Result<BusReceived, KernelError> {
+        if !self.state.lock().outstanding.contains(&serial) {
+            return Err(KernelError::InvalidArgument);
+        }
+        let message = self.wait_for_message(|message| {
+            message.serial == serial && matches!(message.kind, BusMessageKind::Reply | BusMessageKind::Failed)
+        })?;
+        if message.kind == BusMessageKind::Failed {
+            self.state.lock().outstanding.remove(&serial);
+            return Err(KernelError::BrokenPipe);
+        }
+        Ok(self.received(message, buffer))
+    }
+
+    /// Take the first message `wanted` picks, waiting for one unless the
+    /// connection is non-blocking
+    fn wait_for_message(&self, wanted: impl Fn(&BusMessage) -> bool) -> Result<BusMessage, KernelError> {
+        let mut message = None;
+        let mut attempt = || {
+            message = self.mailbox.take(&wanted);
+            message.is_some()
+        };
+        if self.nonblocking {
+            if !attempt() {
+                return Err(KernelError::WouldBlock);
+            }
+        } else {
+            let sources: [&dyn PollOps; 1] = [self];
+            match wait_for(&sources, None, attempt) {
+                PollWait::Ready => {}
+                PollWait::Interrupted => return Err(KernelError::Interrupted),
+                PollWait::TimedOut => return Err(KernelError::WouldBlock),
+            }
+        }
+        Ok(message.unwrap())
+    }
+
+    fn received(&self, message: BusMessage, buffer: &mut [u8]) -> BusReceived {
+        if matches!(message.kind, BusMessageKind::Reply | BusMessageKind::Failed) {
+            self.state.lock().outstanding.remove(&message.serial);
+        }
+        let len = message.data.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&message.data[..len]);
+        BusReceived {
+            kind: message.kind,
+            serial: message.serial,
+            sender: message.sender,
+            name: message.name,
+            len,
+            truncated: len < message.data.len(),
+            objects: message.objects,
+        }
+    }
+}
+
+fn remove_subscriber(broker: &mut Broker, channel: &str, id: u64) {
+    if let Some(subscribers) = broker.channels.get_mut(channel) {
+        subscribers.remove(&id);
+        if subscribers.is_empty() {
+            broker.channels.remove(channel);
+        }
+    }
+}
+
+impl PollOps for BusConnection {
+    /// Readable while a message is queued; sending never waits
+    fn poll_events(&self) -> u32 {
+        if self.mailbox.messages.lock().is_empty() { POLLOUT } else { POLLIN | POLLOUT }
+    }
+
+    fn poll_register(&self, task_id: usize) -> bool {
+        self.mailbox.waker.register(task_id);
+        true
+    }
+
+    fn poll_unregister(&self, task_id: usize) {
+        self.mailbox.waker.unregister(task_id);
+    }
+}
+
+impl Drop for BusConnection {
+    fn drop(&mut self) {
+        let state = core::mem::take(&mut *self.state.lock());
+        {
+            let mut broker = BROKER.lock();
+            for channel in &state.channels {
+                remove_subscriber(&mut broker, channel, self.id);
+            }
+            for service in &state.services {
+                if broker.services.get(service).is_some_and(|owner| owner.ptr_eq(&self.this)) {
+                    broker.services.remove(service);
+                }
+            }
+        }
+        for (serial, request) in state.pending {
+            if let Some(requester) = request.requester.upgrade() {
+                requester.push(BusMessage {
+                    kind: BusMessageKind::Failed,
+                    serial,
+                    sender: self.id,
+                    name: request.service,
+                    data: Vec::new(),
+                    objects: Vec::new(),
+                }, false);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_bus_publish_subscribe() {
+        let publisher = BusConnection::new(true);
+        let first = BusConnection::new(true);
+        let second = BusConnection::new(true);
+        first.subscribe("test_bus_devices").unwrap();
+        second.subscribe("test_bus_devices").unwrap();
+        assert_eq!(first.subscribe("test_bus_devices"), Err(KernelError::Exists));
+
+        assert_eq!(publisher.publish("test_bus_devices", b"added", Vec::new()), Ok(2));
+        let mut buffer = [0u8; 3];
+        for subscriber in [&first, &second] {
+            assert_eq!(subscriber.poll_events(), POLLIN | POLLOUT);
+            let received = subscriber.receive(&mut buffer).unwrap();
+            assert_eq!(received.kind, BusMessageKind::Publish);
+            assert_eq!(received.sender, publisher.id());
+            assert_eq!(received.name, "test_bus_devices");
+            assert_eq!((&buffer[..received.len], received.truncated), (&b"add"[..], true));
+        }
+
+        second.unsubscribe("test_bus_devices").unwrap();
+        drop(first);
+        assert_eq!(publisher.publish("test_bus_devices", b"removed", Vec::new()), Ok(0));
+        assert_eq!(second.receive(&mut buffer).err(), Some(KernelError::WouldBlock));
+    }
+
+    #[test_case]
+    fn test_bus_request_reply() {
+        let service = BusConnection::new(true);
+        let client = BusConnection::new(true);
+        service.register("test_bus_service").unwrap();
+        assert_eq!(client.register("test_bus_service"), Err(KernelError::AddressInUse));
+        client.subscribe("test_bus_news").unwrap();
+
+        let serial = client.request("test_bus_service", b"ping", Vec::new()).unwrap();
+        let mut buffer = [0u8; 16];
+        let request = service.receive(&mut buffer).unwrap();
+        assert_eq!((request.kind, request.serial, request.sender), (BusMessageKind::Request, serial, client.id()));
+        assert_eq!(&buffer[..request.len], b"ping");
+
+        // The reply is taken ahead of what came before it
+        service.publish("test_bus_news", b"news", Vec::new()).unwrap();
+        service.reply(serial, b"pong", Vec::new()).unwrap();
+        assert_eq!(service.reply(serial, b"pong", Vec::new()), Err(KernelError::NotFound));
+        let reply = client.wait_reply(serial, &mut buffer).unwrap();
+        assert_eq!(reply.kind, BusMessageKind::Reply);
+        assert_eq!(&buffer[..reply.len], b"pong");
+        assert_eq!(client.wait_reply(serial, &mut buffer).err(), Some(KernelError::InvalidArgument));
+        assert_eq!(client.receive(&mut buffer).unwrap().kind, BusMessageKind::Publish);
+
+        // A service that goes away fails its requests, and frees its name
+        let serial = client.request("test_bus_service", b"ping", Vec::new()).unwrap();
+        drop(service);
+        assert_eq!(client.wait_reply(serial, &mut buffer).err(), Some(KernelError::BrokenPipe));
+        assert_eq!(client.request("test_bus_service", b"ping", Vec::new()).err(), Some(KernelError::NotFound));
+    }
+}
@@ -0,0 +1,179 @@
+//! Event counters (eventfd)
+//!
+//! An event counter is a 64-bit counter behind a handle. Writing an 8-byte
+//! value adds it to the counter; reading takes the whole counter and resets
+//! it to zero, or in semaphore mode takes one. Reads wait while the counter
+//! is zero and writes wait while the sum would reach `u64::MAX`, unless the
+//! counter is non-blocking.
+//!
+//! The counter is pollable: readable while it is not zero and writable
+//! while at least one can be added, so a task waiting on many objects can
+//! be woken by another task or thread writing to it.
+
+use core::mem::size_of;
+
+use alloc::sync::Arc;
+use spin::Mutex;
+
+use crate::object::capability::poll::{wait_for, PollOps, PollWait, POLLIN, POLLOUT};
+use crate::object::capability::{StreamError, StreamOps};
+use crate::sync::waker::Waker;
+
+/// Reads take one at a time (creation flag)
+pub const EVENTFD_SEMAPHORE: usize = 0x1;
+/// Reads and writes fail with `WouldBlock` instead of waiting (creation flag)
+pub const EVENTFD_NONBLOCK: usize = 0x2;
+
+/// Largest value the counter can hold
+pub const EVENTFD_MAX: u64 = u64::MAX - 1;
+
+pub struct EventFdObject {
+    counter: Mutex<u64>,
+    semaphore: bool,
+    nonblocking: bool,
+    /// Woken whenever the counter changes
+    waker: Waker,
+}
+
+impl EventFdObject {
+    pub fn new(initial: u64, semaphore: bool, nonblocking: bool) -> Arc<Self> {
+        Arc::new(Self {
+            counter: Mutex::new(initial.min(EVENTFD_MAX)),
+            semaphore,
+            nonblocking,
+            waker: Waker::new_interruptible("eventfd"),
+        })
+    }
+
+    /// Take what a read returns, `None` while the counter is zero
+    fn take(&self) -> Option<u64> {
+        let mut counter = self.counter.lock();
+        let value = match *counter {
+            0 => return None,
+            _ if self.semaphore => 1,
+            value => value,
+        };
+        *counter -= value;
+        Some(value)
+    }
+
+    /// Add `value` to the counter, `false` if the sum would not fit
+    fn add(&self, value: u64) -> bool {
+        let mut counter = self.counter.lock();
+        match counter.checked_add(value) {
+            Some(sum) if sum <= EVENTFD_MAX => {
+                *counter = sum;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Run `attempt` until it succeeds, waiting on the counter in between
+    fn wait(&self, mut attempt: impl FnMut() -> bool) -> Result<(), StreamError> {
+        if self.nonblocking {
+            return if attempt() { Ok(()) } else { Err(StreamError::WouldBlock) };
+        }
+        let sources: [&dyn PollOps; 1] = [self];
+        match wait_for(&sources, None, attempt) {
+            PollWait::Ready => Ok(()),
+            PollWait::Interrupted => Err(StreamError::Interrupted),
+            PollWait::TimedOut => Err(StreamError::WouldBlock),
+        }
+    }
+}
+
+impl StreamOps for EventFdObject {
+    /// Read the counter as a native-endian `u64`
+    ///
+    /// A buffer shorter than 8 bytes is an `InvalidArgument`.
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, StreamError> {
+        if buffer.len() < size_of::<u64>() {
+            return Err(StreamError::InvalidArgument);
+        }
+        let mut value = 0;
+        self.wait(|| match self.take() {
+            Some(taken) => {
+                value = taken;
+                true
+            }
+            None => false,
+        })?;
+        self.waker.wake_all();
+        buffer[..size_of::<u64>()].copy_from_slice(&value.to_ne_bytes());
+        Ok(size_of::<u64>())
+    }
+
+    /// Add a native-endian `u64` to the counter
+    ///
+    /// A buffer shorter than 8 bytes or a value of `u64::MAX` is an
+    /// `InvalidArgument`.
+    fn write(&self, buffer: &[u8]) -> Result<usize, StreamError> {
+        let value = buffer
+            .get(..size_of::<u64>())
+            .map(|bytes| u64::from_ne_bytes(bytes.try_into().unwrap()))
+            .filter(|&value| value != u64::MAX)
+            .ok_or(StreamError::InvalidArgument)?;
+        self.wait(|| self.add(value))?;
+        if value != 0 {
+            self.waker.wake_all();
+        }
+        Ok(size_of::<u64>())
+    }
+}
+
+impl PollOps for EventFdObject {
+    fn poll_events(&self) -> u32 {
+        let counter = *self.counter.lock();
+        let mut events = 0;
+        if counter > 0 {
+            events |= POLLIN;
+        }
+        if counter < EVENTFD_MAX {
+            events |= POLLOUT;
+        }
+        events
+    }
+
+    fn poll_register(&self, task_id: usize) -> bool {
+        self.waker.register(task_id);
+        true
+    }
+
+    fn poll_unregister(&self, task_id: usize) {
+        self.waker.unregister(task_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_eventfd_counter_and_semaphore() {
+        let eventfd = EventFdObject::new(0, false, true);
+        let mut buffer = [0u8; 8];
+        assert!(matches!(eventfd.read(&mut buffer), Err(StreamError::WouldBlock)));
+        assert_eq!(eventfd.poll_events(), POLLOUT);
+
+        eventfd.write(&3u64.to_ne_bytes()).unwrap();
+        eventfd.write(&4u64.to_ne_bytes()).unwrap();
+        assert_eq!(eventfd.poll_events(), POLLIN | POLLOUT);
+        assert!(matches!(eventfd.read(&mut buffer), Ok(8)));
+        assert_eq!(u64::from_ne_bytes(buffer), 7);
+        assert_eq!(eventfd.poll_events(), POLLOUT);
+
+        // Too short, too large, and a sum that does not fit
+        assert!(matches!(eventfd.read(&mut buffer[..4]), Err(StreamError::InvalidArgument)));
+        assert!(matches!(eventfd.write(&u64::MAX.to_ne_bytes()), Err(StreamError::InvalidArgument)));
+        eventfd.write(&EVENTFD_MAX.to_ne_bytes()).unwrap();
+        assert_eq!(eventfd.poll_events(), POLLIN);
+        assert!(matches!(eventfd.write(&1u64.to_ne_bytes()), Err(StreamError::WouldBlock)));
+
+        let semaphore = EventFdObject::new(2, true, true);
+        assert!(matches!(semaphore.read(&mut buffer), Ok(8)));
+        assert_eq!(u64::from_ne_bytes(buffer), 1);
+        assert!(matches!(semaphore.read(&mut buffer), Ok(8)));
+        assert!(matches!(semaphore.read(&mut buffer), Err(StreamError::WouldBlock)));
+    }
+}
@@ -0,0 +1,303 @@
+//! Fast userspace mutexes (futexes)
+//!
+//! A futex is a 32-bit word in user memory. User space takes an uncontended
+//! lock with atomic instructions alone and only enters the kernel to sleep
+//! on the word ([`futex_wait`]) or to wake sleepers ([`futex_wake`]).
+//!
+//! Waiters are kept in a fixed set of hashed wait queues. A futex is
+//! identified by the physical address of its word, so the same futex is
+//! found from every address space that maps it: threads of a process and
+//! processes sharing memory with MAP_SHARED alike. The private flag of the
+//! Linux interface is therefore accepted and ignored.
+//!
+//! [`futex_wait`] checks the word and queues the task under the queue lock,
+//! so a wake that follows a change of the word is never missed. Waiters can
+//! be moved to another futex without waking them ([`futex_requeue`]), which
+//! condition variables use to avoid a thundering herd. Priority inheritance
+//! is not supported.
+
+extern crate alloc;
+
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::arch::Trapframe;
+use crate::sched::scheduler::get_scheduler;
+use crate::task::{BlockedType, Task, TaskState};
+use crate::timer::{add_timer, cancel_timer, get_tick, TimerHandler};
+
+/// Sleep if the futex word still holds the expected value
+pub const FUTEX_WAIT: usize = 0;
+/// Wake waiters of the futex
+pub const FUTEX_WAKE: usize = 1;
+/// Wake some waiters and move the others to another futex
+pub const FUTEX_REQUEUE: usize = 3;
+/// Like `FUTEX_REQUEUE`, if the futex word still holds the expected value
+pub const FUTEX_CMP_REQUEUE: usize = 4;
+/// The futex is only used within one process (ignored, see module docs)
+pub const FUTEX_PRIVATE_FLAG: usize = 128;
+
+/// Number of hashed wait queues
+const FUTEX_HASH_BUCKETS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FutexError {
+    /// The futex word did not hold the expected value
+    WouldBlock,
+    /// The timeout expired before the task was woken
+    TimedOut,
+    /// The task was woken for another reason
+    Interrupted,
+    /// The address is not a mapped, aligned futex word
+    Fault,
+}
+
+/// A task sleeping on a futex
+struct FutexWaiter {
+    task_id: usize,
+    /// Key of the futex the task waits on, changed by requeueing
+    key: AtomicUsize,
+    /// Dequeued by a wake or the timeout
+    dequeued: AtomicBool,
+    timed_out: AtomicBool,
+}
+
+type WaitQueue = VecDeque<Arc<FutexWaiter>>;
+
+static FUTEX_QUEUES: [Mutex<WaitQueue>; FUTEX_HASH_BUCKETS] =
+    [const { Mutex::new(VecDeque::new()) }; FUTEX_HASH_BUCKETS];
+
+fn bucket_index(key: usize) -> usize {
+    // Futex words are 4-byte aligned; mix in the page number as well
+    ((key >> 2) ^ (key >> 12)) % FUTEX_HASH_BUCKETS
+}
+
+/// Key of the futex word at `uaddr` in the address space of `task`
+///
+/// An untouched demand-zero page gets its frame here, so the key stays the
+/// same once the page is written.
+pub fn futex_key(task: &Task, uaddr: usize) -> Result<usize, FutexError> {
+    if uaddr % core::mem::align_of::<AtomicU32>() != 0 {
+        return Err(FutexError::Fault);
+    }
+    task.vm_manager.read().translate_vaddr(uaddr).ok_or(FutexError::Fault)
+}
+
+fn futex_value(key: usize) -> u32 {
+    unsafe { (*(key as *const AtomicU32)).load(Ordering::SeqCst) }
+}
+
+/// Remove `waiter` from its wait queue if it is still queued
+///
+/// # Returns
+/// `true` if the waiter was removed here
+fn dequeue(waiter: &Arc<FutexWaiter>) -> bool {
+    loop {
+        let key = waiter.key.load(Ordering::Acquire);
+        let mut queue = FUTEX_QUEUES[bucket_index(key)].lock();
+        // Requeued to another bucket meanwhile
+        if waiter.key.load(Ordering::Acquire) != key {
+            continue;
+        }
+        if waiter.dequeued.load(Ordering::Acquire) {
+            return false;
+        }
+        queue.retain(|queued| !Arc::ptr_eq(queued, waiter));
+        waiter.dequeued.store(true, Ordering::Release);
+        return true;
+    }
+}
+
+/// Wakes a futex waiter whose timeout expired
+struct FutexTimeout {
+    waiter: Arc<FutexWaiter>,
+}
+
+impl TimerHandler for FutexTimeout {
+    fn on_timer_expired(self: Arc<Self>, _context: usize) {
+        if dequeue(&self.waiter) {
+            self.waiter.timed_out.store(true, Ordering::Release);
+            get_scheduler().wake_task(self.waiter.task_id);
+        }
+    }
+}
+
+/// Sleep on the futex `key` while its word holds `expected`
+///
+/// # Arguments
+/// * `task` - The calling task
+/// * `key` - Futex key (see [`futex_key`])
+/// * `expected` - Value the word must hold for the task to sleep
+/// * `timeout_ticks` - Give up after this many ticks, `None` to wait forever
+/// * `trapframe` - Trapframe of the calling task
+///
+/// # Returns
+/// `Ok(())` once woken by [`futex_wake`] or [`futex_requeue`]
+pub fn futex_wait(
+    task: &mut Task,
+    key: usize,
+    expected: u32,
+    timeout_ticks: Option<u64>,
+    trapframe: &mut Trapframe,
+) -> Result<(), FutexError> {
+    let waiter = Arc::new(FutexWaiter {
+        task_id: task.get_id(),
+        key: AtomicUsize::new(key),
+        dequeued: AtomicBool::new(false),
+        timed_out: AtomicBool::new(false),
+    });
+    {
+        let mut queue = FUTEX_QUEUES[bucket_index(key)].lock();
+        if futex_value(key) != expected {
+            return Err(FutexError::WouldBlock);
+        }
+        queue.push_back(waiter.clone());
+        // Marked blocked before a waker can see the entry
+        task.set_state(TaskState::Blocked(BlockedType::Interruptible));
+    }
+
+    let timer = timeout_ticks.map(|ticks| {
+        let handler: Arc<dyn TimerHandler> = Arc::new(FutexTimeout { waiter: waiter.clone() });
+        let id = add_timer(get_tick() + ticks.max(1), &handler, 0);
+        (id, handler)
+    });
+
+    get_scheduler().schedule(trapframe);
+
+    if let Some((id, _handler)) = timer {
+        cancel_timer(id);
+    }
+    if dequeue(&waiter) {
+        // Still queued: nobody woke us through the futex
+        return Err(FutexError::Interrupted);
+    }
+    if waiter.timed_out.load(Ordering::Acquire) {
+        return Err(FutexError::TimedOut);
+    }
+    Ok(())
+}
+
+/// Take up to `count` waiters of `key` off `queue`
+fn take_waiters(queue: &mut WaitQueue, key: usize, count: usize) -> Vec<Arc<FutexWaiter>> {
+    let mut taken = Vec::new();
+    queue.retain(|waiter| {
+        if taken.len() < count && waiter.key.load(Ordering::Acquire) == key {
+            waiter.dequeued.store(true, Ordering::Release);
+            taken.push(waiter.clone());
+            false
+        } else {
+            true
+        }
+    });
+    taken
+}
+
+fn wake_waiters(waiters: Vec<Arc<FutexWaiter>>) -> usize {
+    for waiter in &waiters {
+        get_scheduler().wake_task(waiter.task_id);
+    }
+    waiters.len()
+}
+
+/// Wake up to `count` tasks sleeping on the futex `key`
+///
+/// # Returns
+/// The number of tasks woken
+pub fn futex_wake(key: usize, count: usize) -> usize {
+    let woken = take_waiters(&mut FUTEX_QUEUES[bucket_index(key)].lock(), key, count);
+    // Woken outside the queue lock
+    wake_waiters(woken)
+}
+
+/// Wake up to `wake_count` tasks sleeping on `key` and move up to
+/// `requeue_count` of the others to `target_key`
+///
+/// # Arguments
+/// * `expected` - With `Some`, nothing is done unless the word of `key`
+///   holds this value (`FUTEX_CMP_REQUEUE`)
+///
+/// # Returns
+/// The number of tasks woken or requeued
+pub fn futex_requeue(
+    key: usize,
+    wake_count: usize,
+    target_key: usize,
+    requeue_count: usize,
+    expected: Option<u32>,
+) -> Result<usize, FutexError> {
+    let (src, dst) = (bucket_index(key), bucket_index(target_key));
+    // Lock both queues in index order
+    let mut first = FUTEX_QUEUES[src.min(dst)].lock();
+    let mut second = (src != dst).then(|| FUTEX_QUEUES[src.max(dst)].lock());
+
+    if expected.is_some_and(|expected| futex_value(key) != expected) {
+        return Err(FutexError::WouldBlock);
+    }
+
+    let (woken, moved) = {
+        let (src_queue, dst_queue) = match second.as_mut() {
+            None => (&mut *first, None),
+            Some(second) if src < dst => (&mut *first, Some(&mut **second)),
+            Some(second) => (&mut **second, Some(&mut *first)),
+        };
+        let woken = take_waiters(src_queue, key, wake_count);
+        let moved = take_waiters(src_queue, key, requeue_count);
+        for waiter in &moved {
+            waiter.key.store(target_key, Ordering::Release);
+            waiter.dequeued.store(false, Ordering::Release);
+        }
+        match dst_queue {
+            Some(dst_queue) => dst_queue.extend(moved.iter().cloned()),
+            None => src_queue.extend(moved.iter().cloned()),
+        }
+        (woken, moved.len())
+    };
+    drop(second);
+    drop(first);
+    Ok(wake_waiters(woken) + moved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Queue a waiter for `task_id` without blocking
+    fn enqueue(key: usize, task_id: usize) -> Arc<FutexWaiter> {
+        let waiter = Arc::new(FutexWaiter {
+            task_id,
+            key: AtomicUsize::new(key),
+            dequeued: AtomicBool::new(false),
+            timed_out: AtomicBool::new(false),
+        });
+        FUTEX_QUEUES[bucket_index(key)].lock().push_back(waiter.clone());
+        waiter
+    }
+
+    #[test_case]
+    fn test_futex_wake_and_requeue() {
+        let words = alloc::boxed::Box::new([AtomicU32::new(0), AtomicU32::new(0)]);
+        let key = &words[0] as *const AtomicU32 as usize;
+        let target = &words[1] as *const AtomicU32 as usize;
+        // Task IDs that do not exist, so waking them is a no-op
+        let waiters = (0..3).map(|i| enqueue(key, usize::MAX - i)).collect::<Vec<_>>();
+
+        assert_eq!(futex_wake(key, 1), 1);
+        assert!(waiters[0].dequeued.load(Ordering::Acquire));
+
+        // The word changed: nothing happens
+        words[0].store(1, Ordering::SeqCst);
+        assert_eq!(futex_requeue(key, 0, target, 1, Some(0)), Err(FutexError::WouldBlock));
+        assert_eq!(futex_requeue(key, 0, target, 1, Some(1)), Ok(1));
+        assert_eq!(waiters[1].key.load(Ordering::Acquire), target);
+
+        // Each waiter is woken through the futex it now waits on
+        assert_eq!(futex_wake(key, usize::MAX), 1);
+        assert_eq!(futex_wake(target, usize::MAX), 1);
+        assert_eq!(futex_wake(target, usize::MAX), 0);
+        assert!(!dequeue(&waiters[1]));
+    }
+}
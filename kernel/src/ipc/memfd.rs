@@ -0,0 +1,395 @@
+//! Memory files (memfd)
+//!
+//! A memory file is an anonymous regular file whose data lives in memory:
+//! it is read, written, sized, and mapped through the file capability like
+//! any other file, but it has no path and goes away with its last handle
+//! and mapping. Handles to it are passed on like any other handle.
+//!
+//! Seals restrict what can be done to the file from then on, through every
+//! handle: `SEAL_SHRINK` and `SEAL_GROW` fix the size, `SEAL_WRITE` the
+//! contents, and `SEAL_SEAL` the set of seals. Seals are only ever added,
+//! so a task that is handed a sealed file can use it without guarding
+//! against the sender changing it. Operations a seal forbids fail with
+//! `NotPermitted`.
+//!
+//! The data is kept in physically contiguous pages so that a mapping is a
+//! single `VirtualMemoryMap`. Growing beyond the pages allocated moves the
+//! data to new pages, which cannot be done while any of the file is mapped
+//! (`Busy`); a file is sized before it is mapped. Like a shared memory
+//! segment, a memory file stays alive while any of its pages are mapped.
+
+use core::any::Any;
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use spin::Mutex;
+
+use crate::abi::error::KernelError;
+use crate::environment::PAGE_SIZE;
+use crate::fs::{FileMetadata, FilePermission, FileSystemError, FileSystemErrorKind, FileType, SeekFrom};
+use crate::mem::page::{allocate_raw_pages, free_raw_pages, Page};
+use crate::object::capability::{ControlOps, FileObject, MemoryMappingOps, StreamError, StreamOps};
+use crate::object::KernelObject;
+use crate::vm::vmem::VirtualMemoryPermission;
+
+/// Maximum length of a memory file name
+pub const MEMFD_NAME_MAX: usize = 249;
+
+/// Largest size of a memory file
+pub const MEMFD_MAX_SIZE: usize = 1 << 30;
+
+/// The set of seals cannot change any more
+pub const SEAL_SEAL: u32 = 0x1;
+/// The file cannot get smaller
+pub const SEAL_SHRINK: u32 = 0x2;
+/// The file cannot get larger
+pub const SEAL_GROW: u32 = 0x4;
+/// The contents of the file cannot change
+pub const SEAL_WRITE: u32 = 0x8;
+
+/// All seals
+pub const SEAL_ALL: u32 = SEAL_SEAL | SEAL_SHRINK | SEAL_GROW | SEAL_WRITE;
+
+/// Memory files with pages mapped into a task, kept alive by the mappings
+static MAPPED: Mutex<BTreeMap<u64, Arc<MemFdObject>>> = Mutex::new(BTreeMap::new());
+
+static NEXT_MEMFD_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The data of a memory file: `size` bytes at the start of `num_pages`
+/// contiguous pages
+struct MemFdData {
+    pages: *mut Page,
+    num_pages: usize,
+    size: usize,
+    seals: u32,
+}
+
+impl MemFdData {
+    fn bytes(&mut self) -> &mut [u8] {
+        if self.num_pages == 0 {
+            return &mut [];
+        }
+        unsafe { core::slice::from_raw_parts_mut(self.pages as *mut u8, self.num_pages * PAGE_SIZE) }
+    }
+}
+
+pub struct MemFdObject {
+    id: u64,
+    name: String,
+    data: Mutex<MemFdData>,
+    position: Mutex<u64>,
+    /// Number of pages currently mapped across all tasks
+    mapped_pages: AtomicUsize,
+    /// The object itself, to keep it alive while it is mapped
+    this: Weak<MemFdObject>,
+}
+
+// The page pointer is owned exclusively by the file
+unsafe impl Send for MemFdObject {}
+unsafe impl Sync for MemFdObject {}
+
+impl MemFdObject {
+    /// Create an empty memory file
+    ///
+    /// # Arguments
+    /// * `name` - Name of the file, for display only; names need not be
+    ///   unique
+    /// * `allow_sealing` - Whether seals can be added; without it the file
+    ///   starts sealed with `SEAL_SEAL`
+    pub fn new(name: &str, allow_sealing: bool) -> Result<Arc<Self>, KernelError> {
+        if name.len() > MEMFD_NAME_MAX {
+            return Err(KernelError::NameTooLong);
+        }
+        Ok(Arc::new_cyclic(|this| Self {
+            id: NEXT_MEMFD_ID.fetch_add(1, Ordering::Relaxed),
+            name: name.to_string(),
+            data: Mutex::new(MemFdData {
+                pages: core::ptr::null_mut(),
+                num_pages: 0,
+                size: 0,
+                seals: if allow_sealing { 0 } else { SEAL_SEAL },
+            }),
+            position: Mutex::new(0),
+            mapped_pages: AtomicUsize::new(0),
+            this: this.clone(),
+        }))
+    }
+
+    /// The memory file behind `object`, if it is one
+    pub fn of(object: &KernelObject) -> Option<&MemFdObject> {
+        match object {
+            KernelObject::File(file) => file.as_any().downcast_ref::<MemFdObject>(),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Size of the file in bytes
+    pub fn size(&self) -> usize {
+        self.data.lock().size
+    }
+
+    /// The seals of the file
+    pub fn seals(&self) -> u32 {
+        self.data.lock().seals
+    }
+
+    /// Add seals to the file
+    ///
+    /// Fails with `NotPermitted` once `SEAL_SEAL` is set. `SEAL_WRITE`
+    /// cannot be added while any of the file is mapped (`Busy`), since a
+    /// mapping could still be written.
+    pub fn add_seals(&self, seals: u32) -> Result<(), KernelError> {
+        if seals & !SEAL_ALL != 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+        let mut data = self.data.lock();
+        if data.seals & SEAL_SEAL != 0 {
+            return Err(KernelError::NotPermitted);
+        }
+        if seals & SEAL_WRITE != 0 && self.mapped_pages.load(Ordering::Acquire) != 0 {
+            return Err(KernelError::Busy);
+        }
+        data.seals |= seals;
+        Ok(())
+    }
+
+    /// Number of pages currently mapped across all tasks
+    pub fn mapped_pages(&self) -> usize {
+        self.mapped_pages.load(Ordering::Acquire)
+    }
+
+    /// Change the size of the file to `size`, zero filling what it gains
+    ///
+    /// The pages are kept when the file shrinks; when it grows beyond them
+    /// the data moves to twice as many pages as needed.
+    fn resize(&self, data: &mut MemFdData, size: usize) -> Result<(), StreamError> {
+        if size > MEMFD_MAX_SIZE {
+            return Err(StreamError::NoSpace);
+        }
+        let old_size = data.size;
+        let num_pages = size.div_ceil(PAGE_SIZE);
+        if num_pages > data.num_pages {
+            if self.mapped_pages.load(Ordering::Acquire) != 0 {
+                return Err(StreamError::from(FileSystemError::new(
+                    FileSystemErrorKind::Busy,
+                    "Memory file is mapped",
+                )));
+            }
+            let capacity = num_pages.next_power_of_two();
+            let pages = allocate_raw_pages(capacity);
+            unsafe {
+                core::ptr::write_bytes(pages as *mut u8, 0, capacity * PAGE_SIZE);
+                if data.num_pages != 0 {
+                    core::ptr::copy_nonoverlapping(data.pages as *const u8, pages as *mut u8, old_size);
+                    free_raw_pages(data.pages, data.num_pages);
+                }
+            }
+            data.pages = pages;
+            data.num_pages = capacity;
+        } else {
+            let (start, end) = (size.min(old_size), size.max(old_size));
+            data.bytes()[start..end].fill(0);
+        }
+        data.size = size;
+        Ok(())
+    }
+}
+
+impl Drop for MemFdObject {
+    fn drop(&mut self) {
+        let data = self.data.get_mut();
+        if data.num_pages != 0 {
+            free_raw_pages(data.pages, data.num_pages);
+        }
+    }
+}
+
+impl StreamOps for MemFdObject {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, StreamError> {
+        let mut position = self.position.lock();
+        let mut data = self.data.lock();
+        let start = (*position).min(data.size as u64) as usize;
+        let len = buffer.len().min(data.size - start);
+        buffer[..len].copy_from_slice(&data.bytes()[start..start + len]);
+        *position += len as u64;
+        Ok(len)
+    }
+
+    fn write(&self, buffer: &[u8]) -> Result<usize, StreamError> {
+        let mut position = self.position.lock();
+        let mut data = self.data.lock();
+        if data.seals & SEAL_WRITE != 0 {
+            return Err(StreamError::NotPermitted);
+        }
+        let start = usize::try_from(*position).map_err(|_| StreamError::NoSpace)?;
+        let end = start.checked_add(buffer.len()).ok_or(StreamError::NoSpace)?;
+        if end > data.size {
+            if data.seals & SEAL_GROW != 0 {
+                return Err(StreamError::NotPermitted);
+            }
+            self.resize(&mut data, end)?;
+        }
+        data.bytes()[start..end].copy_from_slice(buffer);
+        *position = end as u64;
+        Ok(buffer.len())
+    }
+}
+
+impl ControlOps for MemFdObject {
+    // Memory files have no control operations; seals are managed by
+    // dedicated system calls
+    fn control(&self, _command: u32, _arg: usize) -> Result<i32, &'static str> {
+        Err("Control operations not supported on memory files")
+    }
+}
+
+impl MemoryMappingOps for MemFdObject {
+    fn get_mapping_info(&self, offset: usize, length: usize) -> Result<(usize, usize, bool), &'static str> {
+        if offset % PAGE_SIZE != 0 {
+            return Err("Offset is not page aligned");
+        }
+        let end = offset.checked_add(length).ok_or("Mapping range overflows")?;
+        let data = self.data.lock();
+        if length == 0 || end > data.size.next_multiple_of(PAGE_SIZE) {
+            return Err("Mapping range exceeds memory file size");
+        }
+        // A file sealed against writes is only mapped for reading
+        let mut permissions = VirtualMemoryPermission::Read as usize;
+        if data.seals & SEAL_WRITE == 0 {
+            permissions |= VirtualMemoryPermission::Write as usize;
+        }
+        Ok((data.pages as usize + offset, permissions, true))
+    }
+
+    fn on_mapped(&self, _vaddr: usize, _paddr: usize, length: usize, _offset: usize) {
+        if self.mapped_pages.fetch_add(length.div_ceil(PAGE_SIZE), Ordering::AcqRel) == 0 {
+            if let Some(this) = self.this.upgrade() {
+                MAPPED.lock().insert(self.id, this);
+            }
+        }
+    }
+
+    fn on_unmapped(&self, _vaddr: usize, length: usize) {
+        let pages = length.div_ceil(PAGE_SIZE);
+        let previous = self.mapped_pages.fetch_update(Ordering::AcqRel, Ordering::Acquire, |mapped| {
+            Some(mapped.saturating_sub(pages))
+        });
+        if previous.is_ok_and(|mapped| mapped <= pages) {
+            // Dropped outside the lock: this may be the last reference
+            let unmapped = MAPPED.lock().remove(&self.id);
+            drop(unmapped);
+        }
+    }
+
+    fn supports_mmap(&self) -> bool {
+        true
+    }
+}
+
+impl FileObject for MemFdObject {
+    fn seek(&self, whence: SeekFrom) -> Result<u64, StreamError> {
+        let mut position = self.position.lock();
+        let new_position = match whence {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => position.checked_add_signed(offset),
+            SeekFrom::End(offset) => (self.size() as u64).checked_add_signed(offset),
+        }
+        .ok_or(StreamError::SeekError)?;
+        *position = new_position;
+        Ok(new_position)
+    }
+
+    fn metadata(&self) -> Result<FileMetadata, StreamError> {
+        let data = self.data.lock();
+        Ok(FileMetadata {
+            file_type: FileType::RegularFile,
+            size: data.size,
+            permissions: FilePermission {
+                read: true,
+                write: data.seals & SEAL_WRITE == 0,
+                execute: false,
+            },
+            created_time: 0,
+            modified_time: 0,
+            accessed_time: 0,
+            file_id: self.id,
+            link_count: 0,
+            owner: None,
+        })
+    }
+
+    fn truncate(&self, size: u64) -> Result<(), StreamError> {
+        let size = usize::try_from(size).map_err(|_| StreamError::NoSpace)?;
+        let mut data = self.data.lock();
+        let sealed = if size < data.size { SEAL_SHRINK } else { SEAL_GROW };
+        if size != data.size && data.seals & sealed != 0 {
+            return Err(StreamError::NotPermitted);
+        }
+        self.resize(&mut data, size)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_memfd_read_write() {
+        let memfd = MemFdObject::new("test_memfd_read_write", true).unwrap();
+        assert_eq!(memfd.write(b"hello").unwrap(), 5);
+        memfd.seek(SeekFrom::Start(PAGE_SIZE as u64 * 3)).unwrap();
+        assert_eq!(memfd.write(b"world").unwrap(), 5);
+        assert_eq!(memfd.size(), PAGE_SIZE * 3 + 5);
+
+        // The gap reads as zero, and data survives growing past the pages
+        memfd.seek(SeekFrom::Start(0)).unwrap();
+        let mut buffer = [0xffu8; 8];
+        assert_eq!(memfd.read(&mut buffer).unwrap(), 8);
+        assert_eq!(&buffer, b"hello\0\0\0");
+
+        memfd.truncate(2).unwrap();
+        memfd.truncate(5).unwrap();
+        memfd.seek(SeekFrom::Start(0)).unwrap();
+        assert_eq!(memfd.read(&mut buffer).unwrap(), 5);
+        assert_eq!(&buffer[..5], b"he\0\0\0");
+        assert_eq!(memfd.read(&mut buffer).unwrap(), 0);
+        assert_eq!(memfd.metadata().unwrap().size, 5);
+    }
+
+    #[test_case]
+    fn test_memfd_seals() {
+        let memfd = MemFdObject::new("test_memfd_seals", true).unwrap();
+        memfd.truncate(PAGE_SIZE as u64).unwrap();
+        memfd.add_seals(SEAL_SHRINK | SEAL_GROW).unwrap();
+        assert!(matches!(memfd.truncate(1), Err(StreamError::NotPermitted)));
+        assert!(matches!(memfd.truncate(PAGE_SIZE as u64 * 2), Err(StreamError::NotPermitted)));
+        memfd.seek(SeekFrom::End(-1)).unwrap();
+        assert!(matches!(memfd.write(b"ab"), Err(StreamError::NotPermitted)));
+        assert_eq!(memfd.write(b"a").unwrap(), 1);
+
+        // A mapping keeps the file from being sealed against writes
+        memfd.on_mapped(0, 0, PAGE_SIZE, 0);
+        assert_eq!(memfd.add_seals(SEAL_WRITE), Err(KernelError::Busy));
+        memfd.on_unmapped(0, PAGE_SIZE);
+        memfd.add_seals(SEAL_WRITE | SEAL_SEAL).unwrap();
+        memfd.seek(SeekFrom::Start(0)).unwrap();
+        assert!(matches!(memfd.write(b"a"), Err(StreamError::NotPermitted)));
+        let (_, permissions, _) = memfd.get_mapping_info(0, PAGE_SIZE).unwrap();
+        assert_eq!(permissions & VirtualMemoryPermission::Write as usize, 0);
+        assert_eq!(memfd.add_seals(SEAL_GROW), Err(KernelError::NotPermitted));
+        assert_eq!(memfd.seals(), SEAL_ALL);
+
+        let unsealable = MemFdObject::new("test_memfd_unsealable", false).unwrap();
+        assert_eq!(unsealable.add_seals(SEAL_WRITE), Err(KernelError::NotPermitted));
+    }
+}
@@ -8,14 +8,26 @@
 //!   - Subscription: Channel-based pub/sub
 //!   - Group: Broadcast delivery
 //! - Message Queues: Structured message passing (future)
-//! - Shared Memory: Memory-based communication (future)
-//! - Sockets: Network and local communication endpoints (future)
+//! - Shared Memory: Named or anonymous segments mapped with MAP_SHARED
+//! - Futexes: Sleeping and waking on 32-bit words in user memory
+//! - Event counters: Pollable counters for wakeups between tasks (eventfd)
+//! - Sockets: Local stream and datagram sockets that can pass handles (AF_UNIX)
+//! - Semaphores: Named or anonymous counting semaphores
+//! - Message bus: Channels and services for system services, passing handles
+//! - Memory files: Anonymous files in memory that can be sealed (memfd)
 
 use crate::object::capability::{StreamOps, StreamError};
 use alloc::string::String;
 
 pub mod pipe;
 pub mod event;
+pub mod shm;
+pub mod futex;
+pub mod eventfd;
+pub mod socket;
+pub mod semaphore;
+pub mod bus;
+pub mod memfd;
 pub mod syscall;
 
 /// Represents errors specific to IPC operations
@@ -86,11 +98,7 @@ pub trait SharedMemoryObject: StreamIpcOps {
     // Shared memory methods will be defined here
 }
 
-/// Socket operations (future implementation)
-pub trait SocketObject: StreamIpcOps {
-    // Socket-specific methods will be defined here
-}
-
 // Re-export commonly used types
 pub use pipe::{PipeEndpoint, UnidirectionalPipe, PipeError, PipeObject};
+pub use socket::{SocketObject, SocketType, UnixSocket};
 pub use event::{EventManager, Event, EventDelivery, EventContent, EventPayload, EventError, GroupTarget};
@@ -3,21 +3,38 @@
 //! This module provides unidirectional pipe implementations for data streaming between processes:
 //! - PipeEndpoint: Basic pipe endpoint with read/write capabilities
 //! - UnidirectionalPipe: Traditional unidirectional pipe (read-only or write-only)
+//!
+//! An endpoint can be made non-blocking, so that its reads and writes fail
+//! with `WouldBlock` instead of waiting. The capacity of a pipe can be
+//! changed while it is in use, and `splice` moves data between a pipe and
+//! another stream inside the kernel.
 
-use alloc::{collections::VecDeque, string::String, sync::Arc, format};
-#[cfg(test)]
-use alloc::vec::Vec;
-use spin::Mutex;
+use alloc::{collections::VecDeque, string::String, sync::Arc, format, vec, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+use spin::{Mutex, MutexGuard};
 
-use crate::object::capability::{StreamOps, StreamError, CloneOps};
+use crate::abi::error::KernelError;
+use crate::environment::PAGE_SIZE;
+use crate::object::capability::{StreamOps, StreamError, CloneOps, PollOps};
+use crate::object::capability::poll::{POLLERR, POLLHUP, POLLIN, POLLOUT};
 use crate::object::KernelObject;
 use crate::sync::waker::Waker;
+use crate::task::mytask;
 use super::{StreamIpcOps, IpcError};
 
+/// Capacity of a new pipe
+pub const PIPE_DEFAULT_SIZE: usize = 4096;
+
+/// Largest capacity a pipe can be given
+pub const PIPE_MAX_SIZE: usize = 1024 * 1024;
+
 /// Pipe-specific operations
 /// 
 /// This trait extends StreamIpcOps with pipe-specific functionality.
-pub trait PipeObject: StreamIpcOps + CloneOps {
+/// Pipes are pollable: the read end is readable while data is buffered and
+/// hung up once no writer is left, the write end is writable while there is
+/// room and in error once no reader is left.
+pub trait PipeObject: StreamIpcOps + CloneOps + PollOps {
     /// Check if there are readers on the other end
     fn has_readers(&self) -> bool;
     
@@ -35,6 +52,36 @@ pub trait PipeObject: StreamIpcOps + CloneOps {
     
     /// Check if this end of the pipe is writable
     fn is_writable(&self) -> bool;
+
+    /// Change the capacity of the pipe
+    ///
+    /// The size is rounded up to a power of two of at least a page. Returns
+    /// the capacity given.
+    ///
+    /// # Errors
+    /// `NotPermitted` beyond `PIPE_MAX_SIZE`, `Busy` if more than the new
+    /// capacity is buffered
+    fn set_buffer_size(&self, size: usize) -> Result<usize, KernelError>;
+
+    /// Make reads and writes of this endpoint fail with `WouldBlock`
+    /// instead of waiting
+    fn set_nonblocking(&self, nonblocking: bool);
+
+    /// Check if this endpoint is non-blocking
+    fn is_nonblocking(&self) -> bool;
+
+    /// Move up to `len` bytes from this pipe to `sink`
+    ///
+    /// Waits for data like `read`, unless `nonblocking` or the endpoint is
+    /// non-blocking. What `sink` does not take stays in the pipe. Returns
+    /// 0 at the end of the data.
+    fn splice_to(&self, sink: &dyn StreamOps, len: usize, nonblocking: bool) -> Result<usize, StreamError>;
+
+    /// Move up to `len` bytes from `source` into this pipe
+    ///
+    /// Waits for room like `write`, unless `nonblocking` or the endpoint is
+    /// non-blocking, and reads no more than there is room for.
+    fn splice_from(&self, source: &dyn StreamOps, len: usize, nonblocking: bool) -> Result<usize, StreamError>;
 }
 
 /// Represents errors specific to pipe operations
@@ -76,10 +123,6 @@ struct PipeState {
     writer_count: usize,
     /// Whether the pipe has been closed
     closed: bool,
-    /// Waker for tasks waiting to read from this pipe
-    read_waker: Waker,
-    /// Waker for tasks waiting to write to this pipe
-    write_waker: Waker,
 }
 
 impl PipeState {
@@ -90,10 +133,55 @@ impl PipeState {
             reader_count: 0,
             writer_count: 0,
             closed: false,
+        }
+    }
+}
+
+/// State of a pipe shared by its endpoints
+///
+/// The wakers are kept outside the lock, so that a task blocks without
+/// holding it.
+struct PipeShared {
+    state: Mutex<PipeState>,
+    /// Waker for tasks waiting to read from this pipe
+    read_waker: Waker,
+    /// Waker for tasks waiting to write to this pipe
+    write_waker: Waker,
+}
+
+impl PipeShared {
+    fn new(buffer_size: usize) -> Self {
+        Self {
+            state: Mutex::new(PipeState::new(buffer_size)),
             read_waker: Waker::new_interruptible("pipe_read"),
             write_waker: Waker::new_interruptible("pipe_write"),
         }
     }
+
+    fn lock(&self) -> MutexGuard<'_, PipeState> {
+        self.state.lock()
+    }
+
+    /// Block the current task on `waker` until `ready` holds or a signal
+    /// arrives
+    ///
+    /// # Errors
+    /// `Interrupted` if a signal is pending, `WouldBlock` if `nonblocking`
+    /// or without a task
+    fn wait(&self, waker: &Waker, nonblocking: bool, ready: impl Fn(&PipeState) -> bool) -> Result<(), StreamError> {
+        if nonblocking {
+            return Err(StreamError::WouldBlock);
+        }
+        let task = mytask().ok_or(StreamError::WouldBlock)?;
+        if task.signals.has_pending() {
+            return Err(StreamError::Interrupted);
+        }
+        let task_id = task.get_id();
+        waker.wait_unless(task_id, task.get_trapframe(), || {
+            ready(&self.lock()) || mytask().is_some_and(|task| task.signals.has_pending())
+        });
+        Ok(())
+    }
 }
 
 /// A generic pipe endpoint
@@ -102,18 +190,20 @@ impl PipeState {
 /// It can be configured for read-only, write-only, or bidirectional access.
 pub struct PipeEndpoint {
     /// Shared pipe state
-    state: Arc<Mutex<PipeState>>,
+    state: Arc<PipeShared>,
     /// Whether this endpoint can read
     can_read: bool,
     /// Whether this endpoint can write
     can_write: bool,
+    /// Whether reads and writes fail instead of waiting
+    nonblocking: AtomicBool,
     /// Unique identifier for debugging
     id: String,
 }
 
 impl PipeEndpoint {
     /// Create a new pipe endpoint with specified capabilities
-    fn new(state: Arc<Mutex<PipeState>>, can_read: bool, can_write: bool, id: String) -> Self {
+    fn new(state: Arc<PipeShared>, can_read: bool, can_write: bool, id: String) -> Self {
         // Register this endpoint in the state
         {
             let mut pipe_state = state.lock();
@@ -129,97 +219,103 @@ impl PipeEndpoint {
             state,
             can_read,
             can_write,
+            nonblocking: AtomicBool::new(false),
             id,
         }
     }
-}
 
-impl StreamOps for PipeEndpoint {
-    fn read(&self, buffer: &mut [u8]) -> Result<usize, StreamError> {
+    /// Wait until the pipe has data to read
+    ///
+    /// Returns the locked state with data in the buffer, or `None` once the
+    /// buffer is empty and no writer is left.
+    fn wait_readable(&self, nonblocking: bool) -> Result<Option<MutexGuard<'_, PipeState>>, StreamError> {
         if !self.can_read {
             return Err(StreamError::NotSupported);
         }
-        
-        let mut state = self.state.lock();
-        
-        if state.closed {
-            return Err(StreamError::Closed);
-        }
-        
-        if state.buffer.is_empty() {
+        loop {
+            let state = self.state.lock();
+            if state.closed {
+                return Err(StreamError::Closed);
+            }
+            if !state.buffer.is_empty() {
+                return Ok(Some(state));
+            }
             if state.writer_count == 0 {
-                // No writers left, return EOF
-                return Ok(0);
-            } else {
-                // Writers exist but no data available - block until data becomes available
-                // Block the current task using the pipe read waker
-                use crate::task::mytask;
-                if let Some(task) = mytask() {
-                    state.read_waker.wait(task.get_id(), task.get_trapframe());
-                    
-                    // After waking up, retry the read operation
-                    return self.read(buffer);
-                } else {
-                    // No current task context, return WouldBlock for non-blocking fallback
-                    return Err(StreamError::WouldBlock);
-                }
+                return Ok(None);
             }
+            drop(state);
+
+            self.state.wait(&self.state.read_waker, nonblocking, |state| {
+                !state.buffer.is_empty() || state.writer_count == 0 || state.closed
+            })?;
         }
-        
+    }
+
+    /// Wait until the pipe has room to write
+    ///
+    /// Returns the locked state with room in the buffer.
+    fn wait_writable(&self, nonblocking: bool) -> Result<MutexGuard<'_, PipeState>, StreamError> {
+        if !self.can_write {
+            return Err(StreamError::NotSupported);
+        }
+        loop {
+            let state = self.state.lock();
+            if state.closed {
+                return Err(StreamError::Closed);
+            }
+            if state.reader_count == 0 {
+                return Err(StreamError::BrokenPipe);
+            }
+            if state.buffer.len() < state.max_size {
+                return Ok(state);
+            }
+            drop(state);
+
+            self.state.wait(&self.state.write_waker, nonblocking, |state| {
+                state.buffer.len() < state.max_size || state.reader_count == 0 || state.closed
+            })?;
+        }
+    }
+}
+
+impl StreamOps for PipeEndpoint {
+    /// Read what is in the pipe, blocking while it is empty and has writers
+    ///
+    /// A signal ends the wait with `Interrupted`; a non-blocking endpoint
+    /// fails with `WouldBlock` instead of waiting.
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, StreamError> {
+        let mut state = match self.wait_readable(self.is_nonblocking())? {
+            Some(state) => state,
+            // No writers left, return EOF
+            None => return Ok(0),
+        };
         let bytes_to_read = buffer.len().min(state.buffer.len());
-        for i in 0..bytes_to_read {
-            buffer[i] = state.buffer.pop_front().unwrap();
+        for (slot, byte) in buffer.iter_mut().zip(state.buffer.drain(..bytes_to_read)) {
+            *slot = byte;
         }
-        
+        drop(state);
+
         // Data was consumed, wake up any waiting writers
         if bytes_to_read > 0 {
-            state.write_waker.wake_all();
+            self.state.write_waker.wake_all();
         }
-        
         Ok(bytes_to_read)
     }
     
+    /// Write what fits in the pipe, blocking while it is full
+    ///
+    /// A signal ends the wait with `Interrupted`; a non-blocking endpoint
+    /// fails with `WouldBlock` instead of waiting.
     fn write(&self, buffer: &[u8]) -> Result<usize, StreamError> {
-        if !self.can_write {
-            return Err(StreamError::NotSupported);
-        }
-        
-        let mut state = self.state.lock();
-        
-        if state.closed {
-            return Err(StreamError::Closed);
-        }
-        
-        if state.reader_count == 0 {
-            return Err(StreamError::BrokenPipe);
-        }
-        
-        let available_space = state.max_size - state.buffer.len();
-        if available_space == 0 {
-            // No space available - block until space becomes available
-            // Block the current task using the pipe write waker
-            use crate::task::mytask;
-            if let Some(mut task) = mytask() {
-                state.write_waker.wait(task.get_id(), task.get_trapframe());
-                
-                // After waking up, retry the write operation
-                return self.write(buffer);
-            } else {
-                // No current task context, return WouldBlock for non-blocking fallback
-                return Err(StreamError::WouldBlock);
-            }
-        }
-        
-        let bytes_to_write = buffer.len().min(available_space);
-        for &byte in &buffer[..bytes_to_write] {
-            state.buffer.push_back(byte);
-        }
-        
+        let mut state = self.wait_writable(self.is_nonblocking())?;
+        let bytes_to_write = buffer.len().min(state.max_size - state.buffer.len());
+        state.buffer.extend(&buffer[..bytes_to_write]);
+        drop(state);
+
         // Data was written, wake up any waiting readers
         if bytes_to_write > 0 {
-            state.read_waker.wake_all();
+            self.state.read_waker.wake_all();
         }
-        
         Ok(bytes_to_write)
     }
 }
@@ -294,6 +390,119 @@ impl PipeObject for PipeEndpoint {
     fn is_writable(&self) -> bool {
         self.can_write
     }
+
+    fn set_buffer_size(&self, size: usize) -> Result<usize, KernelError> {
+        if size > PIPE_MAX_SIZE {
+            return Err(KernelError::NotPermitted);
+        }
+        let size = size.max(PAGE_SIZE).next_power_of_two();
+        let mut state = self.state.lock();
+        if state.buffer.len() > size {
+            return Err(KernelError::Busy);
+        }
+        state.max_size = size;
+        state.buffer.shrink_to(size);
+        drop(state);
+
+        // A larger pipe has room for waiting writers
+        self.state.write_waker.wake_all();
+        Ok(size)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+    }
+
+    fn is_nonblocking(&self) -> bool {
+        self.nonblocking.load(Ordering::Relaxed)
+    }
+
+    /// The data is taken out of the pipe while `sink` writes it, so that
+    /// the pipe is not locked during the write; what `sink` does not take
+    /// is put back in front of the buffer.
+    fn splice_to(&self, sink: &dyn StreamOps, len: usize, nonblocking: bool) -> Result<usize, StreamError> {
+        let mut state = match self.wait_readable(nonblocking || self.is_nonblocking())? {
+            Some(state) => state,
+            None => return Ok(0),
+        };
+        let count = len.min(state.buffer.len());
+        let data: Vec<u8> = state.buffer.drain(..count).collect();
+        drop(state);
+
+        let result = sink.write(&data);
+        let written = *result.as_ref().unwrap_or(&0);
+        if written < data.len() {
+            let mut state = self.state.lock();
+            for &byte in data[written..].iter().rev() {
+                state.buffer.push_front(byte);
+            }
+        }
+        if written > 0 {
+            self.state.write_waker.wake_all();
+        }
+        result
+    }
+
+    /// Data other writers add while `source` is read can take the room;
+    /// the data read is kept even if the pipe ends up over its capacity.
+    fn splice_from(&self, source: &dyn StreamOps, len: usize, nonblocking: bool) -> Result<usize, StreamError> {
+        let state = self.wait_writable(nonblocking || self.is_nonblocking())?;
+        let room = state.max_size - state.buffer.len();
+        drop(state);
+
+        let mut data = vec![0u8; len.min(room)];
+        let count = match source.read(&mut data) {
+            Ok(count) => count,
+            Err(StreamError::EndOfStream) => 0,
+            Err(error) => return Err(error),
+        };
+        self.state.lock().buffer.extend(&data[..count]);
+        if count > 0 {
+            self.state.read_waker.wake_all();
+        }
+        Ok(count)
+    }
+}
+
+impl PollOps for PipeEndpoint {
+    fn poll_events(&self) -> u32 {
+        let state = self.state.lock();
+        if state.closed {
+            return POLLHUP | POLLERR;
+        }
+        let mut events = 0;
+        if self.can_read {
+            if !state.buffer.is_empty() {
+                events |= POLLIN;
+            }
+            if state.writer_count == 0 {
+                events |= POLLHUP;
+            }
+        }
+        if self.can_write {
+            if state.reader_count == 0 {
+                events |= POLLERR;
+            } else if state.buffer.len() < state.max_size {
+                events |= POLLOUT;
+            }
+        }
+        events
+    }
+
+    fn poll_register(&self, task_id: usize) -> bool {
+        if self.can_read {
+            self.state.read_waker.register(task_id);
+        }
+        if self.can_write {
+            self.state.write_waker.register(task_id);
+        }
+        true
+    }
+
+    fn poll_unregister(&self, task_id: usize) {
+        self.state.read_waker.unregister(task_id);
+        self.state.write_waker.unregister(task_id);
+    }
 }
 
 impl Drop for PipeEndpoint {
@@ -311,6 +520,11 @@ impl Drop for PipeEndpoint {
             state.closed = true;
             state.buffer.clear();
         }
+        drop(state);
+        
+        // Readers see the end of the data, writers a broken pipe
+        self.state.read_waker.wake_all();
+        self.state.write_waker.wake_all();
     }
 }
 
@@ -320,6 +534,7 @@ impl Clone for PipeEndpoint {
             state: self.state.clone(),
             can_read: self.can_read,
             can_write: self.can_write,
+            nonblocking: AtomicBool::new(self.is_nonblocking()),
             id: format!("{}_clone", self.id),
         };
         
@@ -346,7 +561,7 @@ pub struct UnidirectionalPipe {
 impl UnidirectionalPipe {
     /// Create a new pipe pair (read_end, write_end) as KernelObjects
     pub fn create_pair(buffer_size: usize) -> (KernelObject, KernelObject) {
-        let state = Arc::new(Mutex::new(PipeState::new(buffer_size)));
+        let state = Arc::new(PipeShared::new(buffer_size));
         
         let read_end = Self {
             endpoint: PipeEndpoint::new(state.clone(), true, false, "unidirectional_read".into()),
@@ -366,7 +581,7 @@ impl UnidirectionalPipe {
     /// Create a new pipe pair for internal testing (returns raw pipes)
     #[cfg(test)]
     pub fn create_pair_raw(buffer_size: usize) -> (Self, Self) {
-        let state = Arc::new(Mutex::new(PipeState::new(buffer_size)));
+        let state = Arc::new(PipeShared::new(buffer_size));
         
         let read_end = Self {
             endpoint: PipeEndpoint::new(state.clone(), true, false, "unidirectional_read".into()),
@@ -445,6 +660,71 @@ impl PipeObject for UnidirectionalPipe {
     fn is_writable(&self) -> bool {
         self.endpoint.is_writable()
     }
+
+    fn set_buffer_size(&self, size: usize) -> Result<usize, KernelError> {
+        self.endpoint.set_buffer_size(size)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) {
+        self.endpoint.set_nonblocking(nonblocking)
+    }
+
+    fn is_nonblocking(&self) -> bool {
+        self.endpoint.is_nonblocking()
+    }
+
+    fn splice_to(&self, sink: &dyn StreamOps, len: usize, nonblocking: bool) -> Result<usize, StreamError> {
+        self.endpoint.splice_to(sink, len, nonblocking)
+    }
+
+    fn splice_from(&self, source: &dyn StreamOps, len: usize, nonblocking: bool) -> Result<usize, StreamError> {
+        self.endpoint.splice_from(source, len, nonblocking)
+    }
+}
+
+impl PollOps for UnidirectionalPipe {
+    fn poll_events(&self) -> u32 {
+        self.endpoint.poll_events()
+    }
+
+    fn poll_register(&self, task_id: usize) -> bool {
+        self.endpoint.poll_register(task_id)
+    }
+
+    fn poll_unregister(&self, task_id: usize) {
+        self.endpoint.poll_unregister(task_id)
+    }
+}
+
+/// Move up to `len` bytes from `from` to `to`, one of which is a pipe
+///
+/// Data goes from the pipe to the other stream, or from the other stream
+/// into the pipe, without a copy in user memory. `nonblocking` makes the
+/// pipe fail with `WouldBlock` instead of waiting. Returns the number of
+/// bytes moved, 0 at the end of the data.
+///
+/// # Errors
+/// `InvalidArgument` if neither object is a pipe or the other one is not a
+/// stream, and the errors of the streams
+pub fn splice(from: &KernelObject, to: &KernelObject, len: usize, nonblocking: bool) -> Result<usize, KernelError> {
+    let result = match (from.as_pipe(), to.as_pipe()) {
+        (Some(pipe), _) => {
+            let sink = to.as_stream().ok_or(KernelError::InvalidArgument)?;
+            if len == 0 {
+                return Ok(0);
+            }
+            pipe.splice_to(sink, len, nonblocking)
+        }
+        (None, Some(pipe)) => {
+            let source = from.as_stream().ok_or(KernelError::InvalidArgument)?;
+            if len == 0 {
+                return Ok(0);
+            }
+            pipe.splice_from(source, len, nonblocking)
+        }
+        (None, None) => return Err(KernelError::InvalidArgument),
+    };
+    result.map_err(|error| KernelError::from(&error))
 }
 
 impl Clone for UnidirectionalPipe {
@@ -564,6 +844,27 @@ mod tests {
         assert_eq!(bytes_read, 0); // EOF
     }
     
+    #[test_case]
+    fn test_pipe_poll_events() {
+        let (read_end, write_end) = UnidirectionalPipe::create_pair_raw(4);
+        assert_eq!(read_end.poll_events(), 0);
+        assert_eq!(write_end.poll_events(), POLLOUT);
+
+        write_end.write(b"full").unwrap();
+        assert_eq!(read_end.poll_events(), POLLIN);
+        assert_eq!(write_end.poll_events(), 0);
+
+        drop(write_end);
+        assert_eq!(read_end.poll_events(), POLLIN | POLLHUP);
+        let mut buffer = [0u8; 4];
+        read_end.read(&mut buffer).unwrap();
+        assert_eq!(read_end.poll_events(), POLLHUP);
+
+        let (read_end, write_end) = UnidirectionalPipe::create_pair_raw(4);
+        drop(read_end);
+        assert_eq!(write_end.poll_events(), POLLERR);
+    }
+
     #[test_case]
     fn test_pipe_write_to_closed_pipe() {
         let (read_end, write_end) = UnidirectionalPipe::create_pair_raw(1024);
@@ -855,4 +1156,69 @@ mod tests {
             panic!("Pipe should implement CloneOps capability");
         }
     }
+    
+    #[test_case]
+    fn test_pipe_nonblocking() {
+        let (read_end, write_end) = UnidirectionalPipe::create_pair_raw(16);
+        read_end.set_nonblocking(true);
+        write_end.set_nonblocking(true);
+        
+        let mut buffer = [0u8; 32];
+        assert!(matches!(read_end.read(&mut buffer), Err(StreamError::WouldBlock)));
+        assert_eq!(write_end.write(&[1u8; 32]).unwrap(), 16);
+        assert!(matches!(write_end.write(&[1u8; 1]), Err(StreamError::WouldBlock)));
+        
+        // A duplicate keeps the mode, and can change it on its own
+        let read_clone = read_end.clone();
+        assert!(read_clone.is_nonblocking());
+        read_clone.set_nonblocking(false);
+        assert!(read_end.is_nonblocking());
+        assert_eq!(read_clone.read(&mut buffer).unwrap(), 16);
+    }
+    
+    #[test_case]
+    fn test_pipe_set_buffer_size() {
+        let (read_end, write_end) = UnidirectionalPipe::create_pair_raw(1024);
+        assert_eq!(write_end.write(&[0u8; 2048]).unwrap(), 1024);
+        assert_eq!(write_end.poll_events(), 0);
+        
+        // Rounded up to a page, with room for the writer again
+        assert_eq!(read_end.set_buffer_size(100), Ok(PAGE_SIZE));
+        assert_eq!(write_end.buffer_size(), PAGE_SIZE);
+        assert_eq!(write_end.poll_events(), POLLOUT);
+        assert_eq!(write_end.set_buffer_size(5000), Ok(2 * PAGE_SIZE));
+        assert_eq!(write_end.write(&[0u8; PAGE_SIZE]).unwrap(), PAGE_SIZE);
+        
+        // What is buffered must fit
+        assert_eq!(write_end.set_buffer_size(PAGE_SIZE), Err(KernelError::Busy));
+        assert_eq!(write_end.set_buffer_size(PIPE_MAX_SIZE + 1), Err(KernelError::NotPermitted));
+        assert_eq!(read_end.available_bytes(), 1024 + PAGE_SIZE);
+    }
+    
+    #[test_case]
+    fn test_pipe_splice() {
+        let (source_read, source_write) = UnidirectionalPipe::create_pair(1024);
+        let (sink_read, sink_write) = UnidirectionalPipe::create_pair(4);
+        source_write.as_stream().unwrap().write(b"hello world").unwrap();
+        
+        // The sink takes what fits, the rest stays in order
+        assert_eq!(splice(&source_read, &sink_write, 64, true), Ok(4));
+        let mut buffer = [0u8; 16];
+        assert_eq!(sink_read.as_stream().unwrap().read(&mut buffer).unwrap(), 4);
+        assert_eq!(&buffer[..4], b"hell");
+        assert_eq!(source_read.as_pipe().unwrap().available_bytes(), 7);
+        
+        // Into a pipe from another stream
+        let (other_read, other_write) = UnidirectionalPipe::create_pair(1024);
+        other_write.as_stream().unwrap().write(b"!").unwrap();
+        let source = other_read.as_stream().unwrap();
+        assert_eq!(source_write.as_pipe().unwrap().splice_from(source, 64, true).unwrap(), 1);
+        let count = source_read.as_stream().unwrap().read(&mut buffer).unwrap();
+        assert_eq!(&buffer[..count], b"o world!");
+        
+        assert_eq!(splice(&source_read, &sink_write, 64, true), Err(KernelError::WouldBlock));
+        assert_eq!(splice(&source_read, &sink_write, 0, true), Ok(0));
+        drop(source_write);
+        assert_eq!(splice(&source_read, &sink_write, 64, true), Ok(0));
+    }
 }
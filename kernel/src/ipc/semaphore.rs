@@ -0,0 +1,183 @@
+//! Counting semaphores
+//!
+//! A semaphore is a counter behind a handle: waiting takes one, waiting as
+//! long as the counter is zero, and posting adds one and wakes a waiter.
+//! Semaphores are shared between tasks by passing or inheriting handles,
+//! or by name (sem_open style). A named semaphore stays in the registry
+//! until it is unlinked, even while no handle refers to it; handles keep
+//! an unlinked semaphore alive.
+//!
+//! The semaphore is pollable: readable while the counter is not zero, so a
+//! task can wait on it together with other objects.
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use spin::Mutex;
+
+use crate::abi::error::KernelError;
+use crate::object::capability::poll::{wait_for, PollOps, PollWait, POLLIN};
+use crate::sync::waker::Waker;
+
+/// Maximum length of a semaphore name
+pub const SEM_NAME_MAX: usize = 255;
+
+/// Largest value of the counter
+pub const SEM_VALUE_MAX: u32 = i32::MAX as u32;
+
+/// Named semaphores
+static SEMAPHORES: Mutex<BTreeMap<String, Arc<SemaphoreObject>>> = Mutex::new(BTreeMap::new());
+
+pub struct SemaphoreObject {
+    value: Mutex<u32>,
+    /// Woken whenever the counter is posted
+    waker: Waker,
+}
+
+impl SemaphoreObject {
+    /// Create an anonymous semaphore
+    pub fn new(value: u32) -> Result<Arc<Self>, KernelError> {
+        if value > SEM_VALUE_MAX {
+            return Err(KernelError::InvalidArgument);
+        }
+        Ok(Arc::new(Self {
+            value: Mutex::new(value),
+            waker: Waker::new_interruptible("semaphore"),
+        }))
+    }
+
+    /// Open the semaphore named `name`
+    ///
+    /// With `create` a missing semaphore is created with `value`, and with
+    /// `exclusive` as well an existing one is an error.
+    pub fn open(name: &str, value: u32, create: bool, exclusive: bool) -> Result<Arc<Self>, KernelError> {
+        check_name(name)?;
+        let mut semaphores = SEMAPHORES.lock();
+        match semaphores.get(name) {
+            Some(_) if create && exclusive => Err(KernelError::Exists),
+            Some(semaphore) => Ok(semaphore.clone()),
+            None if create => {
+                let semaphore = Self::new(value)?;
+                semaphores.insert(name.to_string(), semaphore.clone());
+                Ok(semaphore)
+            }
+            None => Err(KernelError::NotFound),
+        }
+    }
+
+    /// Remove the name of a semaphore; handles to it keep working
+    pub fn unlink(name: &str) -> Result<(), KernelError> {
+        check_name(name)?;
+        SEMAPHORES.lock().remove(name).map(|_| ()).ok_or(KernelError::NotFound)
+    }
+
+    /// The current value of the counter
+    pub fn value(&self) -> u32 {
+        *self.value.lock()
+    }
+
+    /// Take one if the counter is not zero
+    pub fn try_wait(&self) -> bool {
+        let mut value = self.value.lock();
+        if *value == 0 {
+            return false;
+        }
+        *value -= 1;
+        true
+    }
+
+    /// Take one, waiting while the counter is zero
+    ///
+    /// # Arguments
+    /// * `timeout_ticks` - Give up after this many ticks with `TimedOut`,
+    ///   `None` to wait forever
+    pub fn wait(&self, timeout_ticks: Option<u64>) -> Result<(), KernelError> {
+        let sources: [&dyn PollOps; 1] = [self];
+        match wait_for(&sources, timeout_ticks, || self.try_wait()) {
+            PollWait::Ready => Ok(()),
+            PollWait::Interrupted => Err(KernelError::Interrupted),
+            PollWait::TimedOut => Err(KernelError::TimedOut),
+        }
+    }
+
+    /// Add one and wake the waiters
+    ///
+    /// The counter does not grow beyond `SEM_VALUE_MAX` (`OutOfRange`).
+    pub fn post(&self) -> Result<(), KernelError> {
+        {
+            let mut value = self.value.lock();
+            if *value == SEM_VALUE_MAX {
+                return Err(KernelError::OutOfRange);
+            }
+            *value += 1;
+        }
+        self.waker.wake_all();
+        Ok(())
+    }
+}
+
+fn check_name(name: &str) -> Result<(), KernelError> {
+    match name.len() {
+        0 => Err(KernelError::InvalidArgument),
+        len if len > SEM_NAME_MAX => Err(KernelError::NameTooLong),
+        _ => Ok(()),
+    }
+}
+
+impl PollOps for SemaphoreObject {
+    fn poll_events(&self) -> u32 {
+        if self.value() > 0 { POLLIN } else { 0 }
+    }
+
+    fn poll_register(&self, task_id: usize) -> bool {
+        self.waker.register(task_id);
+        true
+    }
+
+    fn poll_unregister(&self, task_id: usize) {
+        self.waker.unregister(task_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_semaphore_counting() {
+        let semaphore = SemaphoreObject::new(1).unwrap();
+        assert_eq!(semaphore.poll_events(), POLLIN);
+        assert!(semaphore.try_wait());
+        assert!(!semaphore.try_wait());
+        assert_eq!(semaphore.poll_events(), 0);
+        assert_eq!(semaphore.wait(Some(0)), Err(KernelError::TimedOut));
+
+        semaphore.post().unwrap();
+        semaphore.post().unwrap();
+        assert_eq!(semaphore.value(), 2);
+        assert_eq!(semaphore.wait(None), Ok(()));
+        assert_eq!(semaphore.value(), 1);
+
+        let full = SemaphoreObject::new(SEM_VALUE_MAX).unwrap();
+        assert_eq!(full.post(), Err(KernelError::OutOfRange));
+        assert!(SemaphoreObject::new(SEM_VALUE_MAX + 1).is_err());
+    }
+
+    #[test_case]
+    fn test_semaphore_names() {
+        let name = "test_semaphore_names";
+        assert_eq!(SemaphoreObject::open(name, 0, false, false).err(), Some(KernelError::NotFound));
+        let created = SemaphoreObject::open(name, 3, true, true).unwrap();
+        assert_eq!(SemaphoreObject::open(name, 0, true, true).err(), Some(KernelError::Exists));
+
+        // The same semaphore, not a new one with the value given
+        let opened = SemaphoreObject::open(name, 0, true, false).unwrap();
+        assert!(Arc::ptr_eq(&created, &opened));
+        assert_eq!(opened.value(), 3);
+
+        SemaphoreObject::unlink(name).unwrap();
+        assert_eq!(SemaphoreObject::unlink(name), Err(KernelError::NotFound));
+        assert!(created.try_wait());
+        assert_eq!(SemaphoreObject::open("", 0, true, false).err(), Some(KernelError::InvalidArgument));
+    }
+}
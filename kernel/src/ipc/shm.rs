@@ -0,0 +1,358 @@
+//! Shared memory objects
+//!
+//! This module provides shared memory segments that can be mapped into
+//! several tasks with MAP_SHARED semantics. Segments are physically
+//! contiguous so that a mapping is a single `VirtualMemoryMap`, and they can
+//! be named (shm_open style) so that unrelated tasks can find them. A named
+//! segment may be created empty and given its size once (ftruncate style).
+//!
+//! A segment stays alive while any of the following holds:
+//! - it is linked under a name in the global registry
+//! - a `SharedMemoryObject` (handle) refers to it
+//! - any of its pages are mapped into a task
+//!
+//! Mappings refer to the segment (not the handle object) as their owner, so
+//! a task may close its handle and keep using the mapping.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use spin::Mutex;
+
+use crate::environment::PAGE_SIZE;
+use crate::mem::page::{allocate_raw_pages, free_raw_pages, Page};
+use crate::object::capability::MemoryMappingOps;
+use crate::vm::vmem::VirtualMemoryPermission;
+
+/// Maximum length of a shared memory segment name
+pub const SHM_NAME_MAX: usize = 255;
+
+/// Errors returned by shared memory operations
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShmError {
+    /// No segment with the given name exists and creation was not requested
+    NotFound,
+    /// A segment with the given name exists and exclusive creation was requested
+    AlreadyExists,
+    /// The requested size is zero or does not match the existing segment
+    InvalidSize,
+    /// The name is empty or too long
+    InvalidName,
+}
+
+/// Global registry of live segments
+struct ShmRegistry {
+    /// Live segments by id; the registry keeps segments alive while in use
+    segments: BTreeMap<u64, Arc<ShmSegment>>,
+    /// Linked names
+    names: BTreeMap<String, u64>,
+}
+
+static SHM_REGISTRY: Mutex<ShmRegistry> = Mutex::new(ShmRegistry {
+    segments: BTreeMap::new(),
+    names: BTreeMap::new(),
+});
+
+static NEXT_SEGMENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Physically contiguous memory backing a shared memory object
+pub struct ShmSegment {
+    id: u64,
+    memory: Mutex<ShmMemory>,
+    /// Number of `SharedMemoryObject`s referring to this segment
+    open_handles: AtomicUsize,
+    /// Number of pages currently mapped across all tasks
+    mapped_pages: AtomicUsize,
+    /// Whether the segment is reachable by name
+    linked: AtomicBool,
+}
+
+/// The pages of a segment; none until an empty segment is given its size
+struct ShmMemory {
+    pages: *mut Page,
+    num_pages: usize,
+}
+
+impl ShmMemory {
+    fn allocate(size: usize) -> Self {
+        let num_pages = size.div_ceil(PAGE_SIZE);
+        if num_pages == 0 {
+            return Self { pages: core::ptr::null_mut(), num_pages };
+        }
+        let pages = allocate_raw_pages(num_pages);
+        // New segments read as zero
+        unsafe { core::ptr::write_bytes(pages as *mut u8, 0, num_pages * PAGE_SIZE) };
+        Self { pages, num_pages }
+    }
+}
+
+// The page pointer is owned exclusively by the segment
+unsafe impl Send for ShmSegment {}
+unsafe impl Sync for ShmSegment {}
+
+impl ShmSegment {
+    fn new(size: usize) -> Self {
+        Self {
+            id: NEXT_SEGMENT_ID.fetch_add(1, Ordering::Relaxed),
+            memory: Mutex::new(ShmMemory::allocate(size)),
+            open_handles: AtomicUsize::new(0),
+            mapped_pages: AtomicUsize::new(0),
+            linked: AtomicBool::new(false),
+        }
+    }
+
+    /// Size of the segment in bytes
+    pub fn size(&self) -> usize {
+        self.memory.lock().num_pages * PAGE_SIZE
+    }
+
+    /// Physical address of the first page
+    pub fn paddr(&self) -> usize {
+        self.memory.lock().pages as usize
+    }
+
+    /// Number of pages currently mapped across all tasks
+    pub fn mapped_pages(&self) -> usize {
+        self.mapped_pages.load(Ordering::Acquire)
+    }
+
+    /// Give an empty segment its size
+    ///
+    /// Nothing can be mapped from an empty segment, so no mapping refers to
+    /// the pages being replaced. A segment that has a size keeps it.
+    fn set_size(&self, size: usize) -> Result<(), ShmError> {
+        let mut memory = self.memory.lock();
+        if size == 0 || memory.num_pages != 0 {
+            return Err(ShmError::InvalidSize);
+        }
+        *memory = ShmMemory::allocate(size);
+        Ok(())
+    }
+
+    /// Drop the segment from the registry once nothing refers to it any more
+    fn release_if_unused(&self) {
+        let mut registry = SHM_REGISTRY.lock();
+        if self.open_handles.load(Ordering::Acquire) == 0
+            && self.mapped_pages.load(Ordering::Acquire) == 0
+            && !self.linked.load(Ordering::Acquire)
+        {
+            registry.segments.remove(&self.id);
+        }
+    }
+}
+
+impl Drop for ShmSegment {
+    fn drop(&mut self) {
+        let memory = self.memory.get_mut();
+        if memory.num_pages != 0 {
+            free_raw_pages(memory.pages, memory.num_pages);
+        }
+    }
+}
+
+impl MemoryMappingOps for ShmSegment {
+    fn get_mapping_info(&self, offset: usize, length: usize) -> Result<(usize, usize, bool), &'static str> {
+        if offset % PAGE_SIZE != 0 {
+            return Err("Offset is not page aligned");
+        }
+        let end = offset.checked_add(length).ok_or("Mapping range overflows")?;
+        let memory = self.memory.lock();
+        if length == 0 || end > memory.num_pages * PAGE_SIZE {
+            return Err("Mapping range exceeds shared memory size");
+        }
+        let permissions = VirtualMemoryPermission::Read as usize | VirtualMemoryPermission::Write as usize;
+        Ok((memory.pages as usize + offset, permissions, true))
+    }
+
+    fn on_mapped(&self, _vaddr: usize, _paddr: usize, length: usize, _offset: usize) {
+        self.mapped_pages.fetch_add(length.div_ceil(PAGE_SIZE), Ordering::AcqRel);
+    }
+
+    fn on_unmapped(&self, _vaddr: usize, length: usize) {
+        let pages = length.div_ceil(PAGE_SIZE);
+        let _ = self.mapped_pages.fetch_update(Ordering::AcqRel, Ordering::Acquire, |mapped| {
+            Some(mapped.saturating_sub(pages))
+        });
+        self.release_if_unused();
+    }
+}
+
+/// Handle-level shared memory object
+///
+/// Each successful open creates one of these; it is what the handle table
+/// holds. Dropping the last reference closes the handle side of the segment.
+pub struct SharedMemoryObject {
+    segment: Arc<ShmSegment>,
+}
+
+impl SharedMemoryObject {
+    fn new(segment: Arc<ShmSegment>) -> Arc<Self> {
+        segment.open_handles.fetch_add(1, Ordering::AcqRel);
+        Arc::new(Self { segment })
+    }
+
+    /// Create an anonymous (unnamed) shared memory object
+    ///
+    /// The object can be shared with other tasks by passing the handle on
+    /// (for example by inheriting it across clone).
+    pub fn create_anonymous(size: usize) -> Result<Arc<Self>, ShmError> {
+        if size == 0 {
+            return Err(ShmError::InvalidSize);
+        }
+        let segment = Arc::new(ShmSegment::new(size));
+        SHM_REGISTRY.lock().segments.insert(segment.id, segment.clone());
+        Ok(Self::new(segment))
+    }
+
+    /// Open (and optionally create) a named shared memory object
+    ///
+    /// # Arguments
+    /// * `name` - Name of the segment
+    /// * `size` - Size of the segment when it is created, 0 for an empty
+    ///   segment to be sized with [`set_size`](Self::set_size); when opening
+    ///   an existing segment it must be 0 or not larger than the segment
+    /// * `create` - Create the segment if it does not exist
+    /// * `exclusive` - Fail if the segment already exists
+    pub fn open(name: &str, size: usize, create: bool, exclusive: bool) -> Result<Arc<Self>, ShmError> {
+        if name.is_empty() || name.len() > SHM_NAME_MAX {
+            return Err(ShmError::InvalidName);
+        }
+
+        let mut registry = SHM_REGISTRY.lock();
+        if let Some(id) = registry.names.get(name) {
+            if exclusive {
+                return Err(ShmError::AlreadyExists);
+            }
+            let segment = registry.segments.get(id).cloned().ok_or(ShmError::NotFound)?;
+            if size > segment.size() {
+                return Err(ShmError::InvalidSize);
+            }
+            drop(registry);
+            return Ok(Self::new(segment));
+        }
+
+        if !create {
+            return Err(ShmError::NotFound);
+        }
+        let segment = Arc::new(ShmSegment::new(size));
+        segment.linked.store(true, Ordering::Release);
+        registry.segments.insert(segment.id, segment.clone());
+        registry.names.insert(String::from(name), segment.id);
+        drop(registry);
+        Ok(Self::new(segment))
+    }
+
+    /// Remove a name from the registry
+    ///
+    /// Existing handles and mappings stay valid; the memory is freed when
+    /// the last of them goes away.
+    pub fn unlink(name: &str) -> Result<(), ShmError> {
+        let segment = {
+            let mut registry = SHM_REGISTRY.lock();
+            let id = registry.names.remove(name).ok_or(ShmError::NotFound)?;
+            registry.segments.get(&id).cloned()
+        };
+        if let Some(segment) = segment {
+            segment.linked.store(false, Ordering::Release);
+            segment.release_if_unused();
+        }
+        Ok(())
+    }
+
+    /// Size of the shared memory in bytes
+    pub fn size(&self) -> usize {
+        self.segment.size()
+    }
+
+    /// Give an empty shared memory object its size (ftruncate style)
+    ///
+    /// Fails with `InvalidSize` for a size of 0 or an object that already
+    /// has a size: segments are contiguous and do not move under mappings.
+    pub fn set_size(&self, size: usize) -> Result<(), ShmError> {
+        self.segment.set_size(size)
+    }
+
+    /// The segment backing this object
+    pub fn segment(&self) -> &Arc<ShmSegment> {
+        &self.segment
+    }
+
+    /// Weak reference to the segment, used as the owner of mappings
+    pub fn segment_weak(&self) -> Weak<dyn MemoryMappingOps> {
+        let weak: Weak<ShmSegment> = Arc::downgrade(&self.segment);
+        weak
+    }
+}
+
+impl Drop for SharedMemoryObject {
+    fn drop(&mut self) {
+        self.segment.open_handles.fetch_sub(1, Ordering::AcqRel);
+        self.segment.release_if_unused();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_registered(segment: &Arc<ShmSegment>) -> bool {
+        SHM_REGISTRY.lock().segments.contains_key(&segment.id)
+    }
+
+    #[test_case]
+    fn test_shm_open_create_and_reopen() {
+        let obj = SharedMemoryObject::open("test_shm_reopen", 100, true, false).unwrap();
+        assert_eq!(obj.size(), PAGE_SIZE);
+
+        let again = SharedMemoryObject::open("test_shm_reopen", 0, false, false).unwrap();
+        assert_eq!(again.segment().paddr(), obj.segment().paddr());
+
+        assert_eq!(SharedMemoryObject::open("test_shm_reopen", 0, true, true).err(), Some(ShmError::AlreadyExists));
+        assert_eq!(SharedMemoryObject::open("test_shm_reopen", 2 * PAGE_SIZE, false, false).err(), Some(ShmError::InvalidSize));
+
+        SharedMemoryObject::unlink("test_shm_reopen").unwrap();
+        assert_eq!(SharedMemoryObject::open("test_shm_reopen", 0, false, false).err(), Some(ShmError::NotFound));
+    }
+
+    #[test_case]
+    fn test_shm_segment_outlives_handle_while_mapped() {
+        let obj = SharedMemoryObject::open("test_shm_lifetime", PAGE_SIZE, true, false).unwrap();
+        let segment = obj.segment().clone();
+        let (paddr, _, is_shared) = segment.get_mapping_info(0, PAGE_SIZE).unwrap();
+        assert!(is_shared);
+        segment.on_mapped(0x1000_0000, paddr, PAGE_SIZE, 0);
+
+        SharedMemoryObject::unlink("test_shm_lifetime").unwrap();
+        drop(obj);
+        assert!(is_registered(&segment));
+
+        segment.on_unmapped(0x1000_0000, PAGE_SIZE);
+        assert!(!is_registered(&segment));
+    }
+
+    #[test_case]
+    fn test_shm_empty_segment_is_sized_once() {
+        let obj = SharedMemoryObject::open("test_shm_sized", 0, true, true).unwrap();
+        assert_eq!(obj.size(), 0);
+        assert!(obj.segment().get_mapping_info(0, PAGE_SIZE).is_err());
+
+        let again = SharedMemoryObject::open("test_shm_sized", 0, false, false).unwrap();
+        again.set_size(PAGE_SIZE + 1).unwrap();
+        assert_eq!(obj.size(), 2 * PAGE_SIZE);
+        assert!(obj.segment().get_mapping_info(PAGE_SIZE, PAGE_SIZE).is_ok());
+        assert_eq!(obj.set_size(4 * PAGE_SIZE), Err(ShmError::InvalidSize));
+
+        SharedMemoryObject::unlink("test_shm_sized").unwrap();
+    }
+
+    #[test_case]
+    fn test_shm_mapping_bounds() {
+        let obj = SharedMemoryObject::create_anonymous(2 * PAGE_SIZE).unwrap();
+        let segment = obj.segment();
+        assert!(segment.get_mapping_info(PAGE_SIZE, PAGE_SIZE).is_ok());
+        assert!(segment.get_mapping_info(PAGE_SIZE, 2 * PAGE_SIZE).is_err());
+        assert!(segment.get_mapping_info(1, PAGE_SIZE).is_err());
+    }
+}
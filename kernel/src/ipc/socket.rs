@@ -0,0 +1,888 @@
+//! Sockets, and local sockets (AF_UNIX)
+//!
+//! [`SocketObject`] is the interface of every socket. Internet sockets are
+//! those of the network stack ([`TcpSocket`], [`UdpSocket`], [`RawSocket`]),
+//! named by their `a.b.c.d:port` or `[v6]:port` endpoint, and packet
+//! sockets ([`PacketSocket`]) by the interface they are bound to; this
+//! module has the local ones.
+//!
+//! A socket is an endpoint of local communication. Stream sockets carry a
+//! byte stream between two connected sockets: one binds a name and listens,
+//! others connect to the name, and each connection is accepted as a new
+//! socket. Datagram sockets carry messages, sent to the name of the
+//! receiver or to the socket they are connected to; a message is read
+//! whole, and what does not fit the buffer is lost.
+//!
+//! Names live in a namespace of the kernel, not in the file system: a name
+//! is taken while its socket is open and free again once it is closed.
+//!
+//! Data can carry kernel objects, like `SCM_RIGHTS` of Linux: the receiver
+//! gets them with the data they were sent with and installs them as new
+//! handles. On a stream socket, a read never joins the data of a send to
+//! the data of a later one that carries objects.
+//!
+//! What a socket receives is queued on the socket itself; a sender refers
+//! to the queue of its peer, not to the peer, so that closing the peer is
+//! seen at once. Sockets sent over each other keep each other open: such
+//! cycles are not collected.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::abi::error::KernelError;
+use crate::network::address::IpFamily;
+use crate::network::capture::{PacketFilter, PacketSocket};
+use crate::network::raw::RawSocket;
+use crate::network::tcp::TcpSocket;
+use crate::network::udp::UdpSocket;
+use crate::object::capability::poll::{wait_for, PollOps, PollWait, POLLERR, POLLHUP, POLLIN, POLLOUT};
+use crate::object::capability::{StreamError, StreamOps};
+use crate::object::KernelObject;
+use crate::sync::waker::Waker;
+use super::StreamIpcOps;
+
+/// Longest name of a socket
+pub const SOCKET_NAME_MAX: usize = 255;
+/// Bytes a socket holds before senders wait; also the largest datagram
+pub const SOCKET_BUFFER_SIZE: usize = 64 * 1024;
+/// Most objects one send can carry
+pub const SOCKET_MAX_OBJECTS: usize = 253;
+/// Most connections waiting to be accepted
+pub const SOCKET_MAX_BACKLOG: usize = 128;
+
+/// Domains of sockets (sys_socket_create)
+pub const SOCKET_DOMAIN_LOCAL: usize = 0;
+pub const SOCKET_DOMAIN_INET: usize = 1;
+pub const SOCKET_DOMAIN_INET6: usize = 2;
+pub const SOCKET_DOMAIN_PACKET: usize = 3;
+
+/// Types of sockets (sys_socket_create)
+pub const SOCKET_STREAM: usize = 1;
+pub const SOCKET_DATAGRAM: usize = 2;
+pub const SOCKET_RAW: usize = 3;
+/// Operations fail with `WouldBlock` instead of waiting (creation flag)
+pub const SOCKET_NONBLOCK: usize = 0x1;
+
+/// Directions of sys_socket_shutdown
+pub const SOCKET_SHUT_READ: usize = 0;
+pub const SOCKET_SHUT_WRITE: usize = 1;
+pub const SOCKET_SHUT_BOTH: usize = 2;
+
+/// Options of sys_socket_get_option
+pub const SOCKET_OPT_TYPE: usize = 0;
+pub const SOCKET_OPT_DOMAIN: usize = 1;
+/// Takes the pending error: the call fails with it if there is one
+pub const SOCKET_OPT_ERROR: usize = 2;
+pub const SOCKET_OPT_BUFFER_SIZE: usize = 3;
+pub const SOCKET_OPT_CONNECTED: usize = 4;
+
+/// Options of sys_socket_set_option
+///
+/// The capture filter of a raw or packet socket: the value is the address
+/// of a filter expression, or 0 to remove the filter
+pub const SOCKET_OPT_FILTER: usize = 5;
+
+/// Options of both sys_socket_get_option and sys_socket_set_option, for
+/// the sockets that have them (see [`SocketOption`])
+pub const SOCKET_OPT_REUSE_ADDRESS: usize = 6;
+pub const SOCKET_OPT_NO_DELAY: usize = 7;
+pub const SOCKET_OPT_RECEIVE_BUFFER: usize = 8;
+pub const SOCKET_OPT_SEND_BUFFER: usize = 9;
+pub const SOCKET_OPT_KEEPALIVE: usize = 10;
+pub const SOCKET_OPT_KEEPALIVE_IDLE: usize = 11;
+pub const SOCKET_OPT_KEEPALIVE_INTERVAL: usize = 12;
+pub const SOCKET_OPT_KEEPALIVE_COUNT: usize = 13;
+
+/// Where the names of a socket live
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketDomain {
+    /// Names of a namespace of the kernel
+    Local,
+    /// IPv4 endpoints, `a.b.c.d:port`
+    Inet,
+    /// IPv6 endpoints, `[v6]:port`, and the IPv4 ones
+    Inet6,
+    /// Names of network interfaces, for whole frames
+    Packet,
+}
+
+/// How a socket carries data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketType {
+    /// A connected byte stream
+    Stream,
+    /// Messages kept apart
+    Datagram,
+    /// Datagrams of a network protocol, or frames of a packet socket
+    Raw,
+}
+
+/// The directions closed by [`SocketObject::shutdown`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shutdown {
+    Read,
+    Write,
+    Both,
+}
+
+/// Options of [`SocketObject::get_option`] and [`SocketObject::set_option`];
+/// flags are 0 or 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketOption {
+    /// Bind a port whose other users do not listen and allow it too
+    ReuseAddress,
+    /// Send small segments at once instead of gathering them (Nagle)
+    NoDelay,
+    /// Bytes buffered for reading
+    ReceiveBuffer,
+    /// Bytes buffered for sending
+    SendBuffer,
+    /// Probe an idle connection and fail it if the peer is gone
+    KeepAlive,
+    /// Seconds of idleness before the first probe
+    KeepAliveIdle,
+    /// Seconds between probes
+    KeepAliveInterval,
+    /// Probes unanswered before the connection fails
+    KeepAliveCount,
+}
+
+impl SocketOption {
+    /// The option named `option` in sys_socket_get_option and
+    /// sys_socket_set_option
+    pub fn from_number(option: usize) -> Option<Self> {
+        Some(match option {
+            SOCKET_OPT_REUSE_ADDRESS => SocketOption::ReuseAddress,
+            SOCKET_OPT_NO_DELAY => SocketOption::NoDelay,
+            SOCKET_OPT_RECEIVE_BUFFER => SocketOption::ReceiveBuffer,
+            SOCKET_OPT_SEND_BUFFER => SocketOption::SendBuffer,
+            SOCKET_OPT_KEEPALIVE => SocketOption::KeepAlive,
+            SOCKET_OPT_KEEPALIVE_IDLE => SocketOption::KeepAliveIdle,
+            SOCKET_OPT_KEEPALIVE_INTERVAL => SocketOption::KeepAliveInterval,
+            SOCKET_OPT_KEEPALIVE_COUNT => SocketOption::KeepAliveCount,
+            _ => return None,
+        })
+    }
+}
+
+/// What one receive returned
+#[derive(Default)]
+pub struct Received {
+    /// Bytes written to the buffer, 0 at the end of a stream
+    pub len: usize,
+    /// The datagram was longer than the buffer and the rest is lost
+    pub truncated: bool,
+    /// Objects sent with the data
+    pub objects: Vec<KernelObject>,
+    /// Name of the sender of a datagram, if it has one
+    pub from: Option<String>,
+}
+
+/// Socket operations
+///
+/// Operations that wait do so unless the socket is non-blocking, and a
+/// signal ends the wait with `Interrupted`. Sockets are pollable: readable
+/// while data, the end of the stream or, when listening, a connection is
+/// waiting; writable while the peer has room.
+pub trait SocketObject: StreamIpcOps + PollOps {
+    fn domain(&self) -> SocketDomain;
+
+    fn socket_type(&self) -> SocketType;
+
+    /// Give the socket a name, failing with `AddressInUse` if it is taken
+    fn bind(&self, name: &str) -> Result<(), KernelError>;
+
+    /// Accept connections to the name of a stream socket, at most
+    /// `backlog` of them waiting at a time
+    fn listen(&self, backlog: usize) -> Result<(), KernelError>;
+
+    /// Connect to the socket named `name`
+    ///
+    /// A stream connection is complete before it is accepted; one to a
+    /// listener whose backlog is full fails with `WouldBlock`. A datagram
+    /// socket only records where its messages go.
+    fn connect(&self, name: &str) -> Result<(), KernelError>;
+
+    /// Connect without waiting for the connection to be made, failing with
+    /// `InProgress` while it is not; the socket is writable once it is
+    fn start_connect(&self, name: &str) -> Result<(), KernelError> {
+        self.connect(name)
+    }
+
+    /// Take the next connection to a listening socket, waiting for one
+    fn accept(&self) -> Result<Arc<dyn SocketObject>, KernelError>;
+
+    /// Send `data` with `objects` to the peer, or to the datagram socket
+    /// named `to`
+    ///
+    /// # Returns
+    /// The number of bytes sent: a stream sends what fits, waiting while
+    /// nothing does; a datagram is sent whole.
+    fn send(&self, data: &[u8], objects: Vec<KernelObject>, to: Option<&str>) -> Result<usize, KernelError>;
+
+    /// Receive into `buffer`, waiting while nothing is there
+    fn receive(&self, buffer: &mut [u8]) -> Result<Received, KernelError>;
+
+    /// Stop receiving, sending or both
+    fn shutdown(&self, how: Shutdown) -> Result<(), KernelError>;
+
+    /// The name of the socket; an accepted socket has the name of its
+    /// listener
+    fn name(&self) -> Option<String>;
+
+    /// The name of the peer when the connection was made
+    fn peer_name(&self) -> Option<String>;
+
+    /// Take the error the socket met in the background, such as a refused
+    /// connection
+    fn take_error(&self) -> Option<KernelError> {
+        None
+    }
+
+    /// Take only what `filter` accepts, or everything if `None`; raw and
+    /// packet sockets only
+    fn set_filter(&self, _filter: Option<Arc<dyn PacketFilter>>) -> Result<(), KernelError> {
+        Err(KernelError::NotSupported)
+    }
+
+    /// The value of `option`, for the sockets that have it
+    fn get_option(&self, _option: SocketOption) -> Result<usize, KernelError> {
+        Err(KernelError::NotSupported)
+    }
+
+    /// Set `option`, for the sockets that have it; sizes and times out of
+    /// range are clamped
+    fn set_option(&self, _option: SocketOption, _value: usize) -> Result<(), KernelError> {
+        Err(KernelError::NotSupported)
+    }
+}
+
+/// A new socket of `domain` carrying data as `kind`: TCP and UDP for
+/// internet sockets, or raw ones for the IP protocol `protocol`
+///
+/// `protocol` is 0 but for raw sockets: a packet socket takes the frames
+/// of the EtherType it names, or all if it is 0.
+pub fn create_socket(
+    domain: SocketDomain,
+    kind: SocketType,
+    protocol: u16,
+    nonblocking: bool,
+) -> Result<Arc<dyn SocketObject>, KernelError> {
+    if kind != SocketType::Raw && protocol != 0 {
+        return Err(KernelError::InvalidArgument);
+    }
+    let family = if domain == SocketDomain::Inet { IpFamily::V4 } else { IpFamily::V6 };
+    let socket: Arc<dyn SocketObject> = match (domain, kind) {
+        (SocketDomain::Local, SocketType::Raw) | (SocketDomain::Packet, SocketType::Stream | SocketType::Datagram) => {
+            return Err(KernelError::NotSupported);
+        }
+        (SocketDomain::Local, kind) => UnixSocket::new(kind, nonblocking),
+        (SocketDomain::Packet, SocketType::Raw) => PacketSocket::new((protocol != 0).then_some(protocol), nonblocking),
+        (_, SocketType::Stream) => TcpSocket::new(family, nonblocking),
+        (_, SocketType::Datagram) => UdpSocket::new(family, nonblocking),
+        (_, SocketType::Raw) => {
+            let protocol = u8::try_from(protocol).ok().filter(|protocol| *protocol != 0).ok_or(KernelError::InvalidArgument)?;
+            RawSocket::new(family, protocol, nonblocking)
+        }
+    };
+    Ok(socket)
+}
+
+/// Names of the bound sockets
+static NAMES: Mutex<BTreeMap<String, Weak<UnixSocket>>> = Mutex::new(BTreeMap::new());
+
+fn lookup(name: &str) -> Result<Arc<UnixSocket>, KernelError> {
+    NAMES.lock().get(name).and_then(Weak::upgrade).ok_or(KernelError::NotFound)
+}
+
+/// The data of one send
+struct Message {
+    data: Vec<u8>,
+    /// Bytes of a stream message already read
+    offset: usize,
+    objects: Vec<KernelObject>,
+    from: Option<String>,
+}
+
+struct Inbox {
+    messages: VecDeque<Message>,
+    /// Bytes not read yet
+    bytes: usize,
+    /// Nothing more arrives: the peer shut down writing or is gone
+    eof: bool,
+    /// Nothing more is taken: the socket shut down reading or is gone
+    closed: bool,
+}
+
+/// What a socket receives
+struct Queue {
+    inbox: Mutex<Inbox>,
+    /// Woken whenever the inbox changes, and for connections to a listener
+    waker: Waker,
+}
+
+impl Queue {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            inbox: Mutex::new(Inbox { messages: VecDeque::new(), bytes: 0, eof: false, closed: false }),
+            waker: Waker::new_interruptible("socket"),
+        })
+    }
+
+    /// Queue a message of `data` and `objects`, or of what fits of `data`
+    /// unless it must be queued `whole`
+    ///
+    /// # Returns
+    /// The number of bytes queued, `None` while they do not fit
+    fn push(&self, data: &[u8], objects: &mut Vec<KernelObject>, from: &Option<String>, whole: bool) -> Result<Option<usize>, KernelError> {
+        let mut inbox = self.inbox.lock();
+        if inbox.closed {
+            return Err(KernelError::BrokenPipe);
+        }
+        let room = SOCKET_BUFFER_SIZE - inbox.bytes;
+        let len = match whole {
+            true if data.len() > room => return Ok(None),
+            true => data.len(),
+            false if room == 0 => return Ok(None),
+            false => data.len().min(room),
+        };
+        inbox.messages.push_back(Message {
+            data: data[..len].to_vec(),
+            offset: 0,
+            objects: core::mem::take(objects),
+            from: from.clone(),
+        });
+        inbox.bytes += len;
+        drop(inbox);
+        self.waker.wake_all();
+        Ok(Some(len))
+    }
+
+    /// Take what a receive into `buffer` returns, `None` while nothing is
+    /// there
+    fn take(&self, kind: SocketType, buffer: &mut [u8]) -> Option<Received> {
+        let mut inbox = self.inbox.lock();
+        if inbox.messages.is_empty() || inbox.closed {
+            return (inbox.eof || inbox.closed).then(Received::default);
+        }
+        let mut received = Received::default();
+        match kind {
+            SocketType::Datagram | SocketType::Raw => {
+                let message = inbox.messages.pop_front().unwrap();
+                inbox.bytes -= message.data.len();
+                received.len = message.data.len().min(buffer.len());
+                buffer[..received.len].copy_from_slice(&message.data[..received.len]);
+                received.truncated = received.len < message.data.len();
+                received.objects = message.objects;
+                received.from = message.from;
+            }
+            SocketType::Stream => {
+                while let Some(message) = inbox.messages.front_mut() {
+                    if received.len == buffer.len() || (received.len > 0 && !message.objects.is_empty()) {
+                        break;
+                    }
+                    received.objects.append(&mut message.objects);
+                    let len = (message.data.len() - message.offset).min(buffer.len() - received.len);
+                    buffer[received.len..received.len + len]
+                        .copy_from_slice(&message.data[message.offset..message.offset + len]);
+                    message.offset += len;
+                    received.len += len;
+                    if message.offset == message.data.len() {
+                        inbox.messages.pop_front();
+                    }
+                    inbox.bytes -= len;
+                }
+            }
+        }
+        drop(inbox);
+        self.waker.wake_all();
+        Some(received)
+    }
+
+    /// End the data arriving at the queue, or stop taking it
+    fn close(&self, closed: bool) {
+        let mut inbox = self.inbox.lock();
+        if closed {
+            inbox.closed = true;
+        } else {
+            inbox.eof = true;
+        }
+        drop(inbox);
+        self.waker.wake_all();
+    }
+
+    /// POLLIN while something can be taken
+    fn readable(&self, kind: SocketType) -> u32 {
+        let inbox = self.inbox.lock();
+        let mut events = 0;
+        if !inbox.messages.is_empty() || inbox.eof || inbox.closed {
+            events |= POLLIN;
+        }
+        if inbox.eof && kind == SocketType::Stream {
+            events |= POLLHUP;
+        }
+        events
+    }
+
+    /// POLLOUT while there is room, POLLERR once nothing is taken
+    fn writable(&self) -> u32 {
+        let inbox = self.inbox.lock();
+        if inbox.closed {
+            POLLERR
+        } else if inbox.bytes < SOCKET_BUFFER_SIZE {
+            POLLOUT
+        } else {
+            0
+        }
+    }
+}
+
+impl PollOps for Queue {
+    fn poll_events(&self) -> u32 {
+        self.readable(SocketType::Datagram) | self.writable()
+    }
+
+    fn poll_register(&self, task_id: usize) -> bool {
+        self.waker.register(task_id);
+        true
+    }
+
+    fn poll_unregister(&self, task_id: usize) {
+        self.waker.unregister(task_id);
+    }
+}
+
+enum Connection {
+    None,
+    Listening { backlog: usize, pending: VecDeque<Arc<UnixSocket>> },
+    /// Sends go to `queue`
+    Connected { queue: Arc<Queue>, peer_name: Option<String> },
+}
+
+struct SocketState {
+    name: Option<String>,
+    connection: Connection,
+    /// Shut down for writing
+    write_shutdown: bool,
+}
+
+/// A local socket
+pub struct UnixSocket {
+    kind: SocketType,
+    nonblocking: bool,
+    state: Mutex<SocketState>,
+    queue: Arc<Queue>,
+    /// The socket itself, registered under its name
+    this: Weak<UnixSocket>,
+}
+
+impl UnixSocket {
+    /// An unnamed, unconnected socket
+    pub fn new(kind: SocketType, nonblocking: bool) -> Arc<Self> {
+        Arc::new_cyclic(|this| Self {
+            kind,
+            nonblocking,
+            state: Mutex::new(SocketState { name: None, connection: Connection::None, write_shutdown: false }),
+            queue: Queue::new(),
+            this: this.clone(),
+        })
+    }
+
+    /// Two unnamed sockets connected to each other
+    pub fn pair(kind: SocketType, nonblocking: bool) -> (Arc<Self>, Arc<Self>) {
+        let (a, b) = (Self::new(kind, nonblocking), Self::new(kind, nonblocking));
+        a.state.lock().connection = Connection::Connected { queue: b.queue.clone(), peer_name: None };
+        b.state.lock().connection = Connection::Connected { queue: a.queue.clone(), peer_name: None };
+        (a, b)
+    }
+
+    /// Run `attempt` until it succeeds, waiting on `sources` in between
+    fn wait(&self, sources: &[&dyn PollOps], mut attempt: impl FnMut() -> bool) -> Result<(), KernelError> {
+        if self.nonblocking {
+            return if attempt() { Ok(()) } else { Err(KernelError::WouldBlock) };
+        }
+        match wait_for(sources, None, attempt) {
+            PollWait::Ready => Ok(()),
+            PollWait::Interrupted => Err(KernelError::Interrupted),
+            PollWait::TimedOut => Err(KernelError::WouldBlock),
+        }
+    }
+
+    /// The queue of the peer, where sends go
+    fn peer_queue(&self) -> Option<Arc<Queue>> {
+        match &self.state.lock().connection {
+            Connection::Connected { queue, .. } => Some(queue.clone()),
+            _ => None,
+        }
+    }
+}
+
+impl SocketObject for UnixSocket {
+    fn domain(&self) -> SocketDomain {
+        SocketDomain::Local
+    }
+
+    fn socket_type(&self) -> SocketType {
+        self.kind
+    }
+
+    fn bind(&self, name: &str) -> Result<(), KernelError> {
+        if name.is_empty() || name.len() > SOCKET_NAME_MAX {
+            return Err(KernelError::InvalidArgument);
+        }
+        let mut state = self.state.lock();
+        if state.name.is_some() {
+            return Err(KernelError::InvalidArgument);
+        }
+        let mut names = NAMES.lock();
+        if names.get(name).is_some_and(|socket| socket.strong_count() > 0) {
+            return Err(KernelError::AddressInUse);
+        }
+        names.insert(name.to_string(), self.this.clone());
+        state.name = Some(name.to_string());
+        Ok(())
+    }
+
+    fn listen(&self, backlog: usize) -> Result<(), KernelError> {
+        if self.kind != SocketType::Stream {
+            return Err(KernelError::NotSupported);
+        }
+        let backlog = backlog.clamp(1, SOCKET_MAX_BACKLOG);
+        let mut state = self.state.lock();
+        if state.name.is_none() {
+            return Err(KernelError::InvalidArgument);
+        }
+        match &mut state.connection {
+            Connection::None => {
+                state.connection = Connection::Listening { backlog, pending: VecDeque::new() };
+            }
+            Connection::Listening { backlog: current, .. } => *current = backlog,
+            Connection::Connected { .. } => return Err(KernelError::InvalidArgument),
+        }
+        Ok(())
+    }
+
+    fn connect(&self, name: &str) -> Result<(), KernelError> {
+        let target = lookup(name)?;
+        if target.kind != self.kind {
+            return Err(KernelError::ConnectionRefused);
+        }
+        if self.kind == SocketType::Datagram {
+            self.state.lock().connection =
+                Connection::Connected { queue: target.queue.clone(), peer_name: Some(name.to_string()) };
+            return Ok(());
+        }
+        let own_name = {
+            let state = self.state.lock();
+            match state.connection {
+                Connection::None => {}
+                Connection::Listening { .. } => return Err(KernelError::InvalidArgument),
+                Connection::Connected { .. } => return Err(KernelError::AlreadyConnected),
+            }
+            state.name.clone()
+        };
+
+        // The socket of the other end, connected before it is accepted. The
+        // state of the listener is locked alone, so that sockets connecting
+        // to each other cannot deadlock.
+        let server = UnixSocket::new(SocketType::Stream, false);
+        *server.state.lock() = SocketState {
+            name: Some(name.to_string()),
+            connection: Connection::Connected { queue: self.queue.clone(), peer_name: own_name },
+            write_shutdown: false,
+        };
+        match &mut target.state.lock().connection {
+            Connection::Listening { backlog, pending } if pending.len() < *backlog => {
+                pending.push_back(server.clone());
+            }
+            Connection::Listening { .. } => return Err(KernelError::WouldBlock),
+            _ => return Err(KernelError::ConnectionRefused),
+        }
+        self.state.lock().connection = Connection::Connected { queue: server.queue.clone(), peer_name: Some(name.to_string()) };
+        target.queue.waker.wake_all();
+        Ok(())
+    }
+
+    fn accept(&self) -> Result<Arc<dyn SocketObject>, KernelError> {
+        let mut outcome = Err(KernelError::InvalidArgument);
+        let sources: [&dyn PollOps; 1] = [self.queue.as_ref()];
+        self.wait(&sources, || match &mut self.state.lock().connection {
+            Connection::Listening { pending, .. } => match pending.pop_front() {
+                Some(socket) => {
+                    outcome = Ok(socket);
+                    true
+                }
+                None => false,
+            },
+            _ => true,
+        })?;
+        outcome.map(|socket| socket as Arc<dyn SocketObject>)
+    }
+
+    fn send(&self, data: &[u8], mut objects: Vec<KernelObject>, to: Option<&str>) -> Result<usize, KernelError> {
+        if objects.len() > SOCKET_MAX_OBJECTS {
+            return Err(KernelError::InvalidArgument);
+        }
+        let (queue, from) = {
+            let state = self.state.lock();
+            if state.write_shutdown {
+                return Err(KernelError::BrokenPipe);
+            }
+            let queue = match (&state.connection, to) {
+                (Connection::Connected { .. }, Some(_)) if self.kind == SocketType::Stream => {
+                    return Err(KernelError::AlreadyConnected);
+                }
+                (_, Some(name)) if self.kind == SocketType::Datagram => {
+                    let target = lookup(name)?;
+                    if target.kind != SocketType::Datagram {
+                        return Err(KernelError::ConnectionRefused);
+                    }
+                    target.queue.clone()
+                }
+                (Connection::Connected { queue, .. }, None) => queue.clone(),
+                _ => return Err(KernelError::NotConnected),
+            };
+            (queue, state.name.clone())
+        };
+
+        let whole = self.kind == SocketType::Datagram;
+        if whole && data.len() > SOCKET_BUFFER_SIZE {
+            return Err(KernelError::MessageTooLong);
+        }
+        if !whole && data.is_empty() {
+            return Ok(0);
+        }
+        let mut outcome = Ok(0);
+        let sources: [&dyn PollOps; 1] = [queue.as_ref()];
+        self.wait(&sources, || match queue.push(data, &mut objects, &from, whole) {
+            Ok(Some(len)) => {
+                outcome = Ok(len);
+                true
+            }
+            Ok(None) => false,
+            Err(error) => {
+                outcome = Err(error);
+                true
+            }
+        })?;
+        match outcome {
+            // A datagram whose receiver is gone is refused
+            Err(KernelError::BrokenPipe) if whole => Err(KernelError::ConnectionRefused),
+            outcome => outcome,
+        }
+    }
+
+    fn receive(&self, buffer: &mut [u8]) -> Result<Received, KernelError> {
+        if self.kind == SocketType::Stream && self.peer_queue().is_none() {
+            return Err(KernelError::NotConnected);
+        }
+        let mut received = None;
+        let sources: [&dyn PollOps; 1] = [self.queue.as_ref()];
+        self.wait(&sources, || {
+            received = self.queue.take(self.kind, buffer);
+            received.is_some()
+        })?;
+        Ok(received.unwrap_or_default())
+    }
+
+    fn shutdown(&self, how: Shutdown) -> Result<(), KernelError> {
+        let peer_queue = {
+            let mut state = self.state.lock();
+            let Connection::Connected { queue, .. } = &state.connection else {
+                return Err(KernelError::NotConnected);
+            };
+            let queue = queue.clone();
+            if how != Shutdown::Read {
+                state.write_shutdown = true;
+            }
+            queue
+        };
+        if how != Shutdown::Read {
+            peer_queue.close(false);
+        }
+        if how != Shutdown::Write {
+            self.queue.close(true);
+        }
+        Ok(())
+    }
+
+    fn name(&self) -> Option<String> {
+        self.state.lock().name.clone()
+    }
+
+    fn peer_name(&self) -> Option<String> {
+        match &self.state.lock().connection {
+            Connection::Connected { peer_name, .. } => peer_name.clone(),
+            _ => None,
+        }
+    }
+}
+
+/// The stream error of a socket error
+fn stream_error(error: KernelError) -> StreamError {
+    match error {
+        KernelError::WouldBlock => StreamError::WouldBlock,
+        KernelError::Interrupted => StreamError::Interrupted,
+        KernelError::BrokenPipe => StreamError::BrokenPipe,
+        KernelError::NotConnected => StreamError::Closed,
+        _ => StreamError::InvalidArgument,
+    }
+}
+
+impl StreamOps for UnixSocket {
+    /// Receive data; objects sent with it are closed
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, StreamError> {
+        self.receive(buffer).map(|received| received.len).map_err(stream_error)
+    }
+
+    /// Send data to the peer
+    fn write(&self, buffer: &[u8]) -> Result<usize, StreamError> {
+        self.send(buffer, Vec::new(), None).map_err(stream_error)
+    }
+}
+
+impl StreamIpcOps for UnixSocket {
+    fn is_connected(&self) -> bool {
+        matches!(self.state.lock().connection, Connection::Connected { .. })
+    }
+
+    fn peer_count(&self) -> usize {
+        match &self.state.lock().connection {
+            Connection::Connected { .. } => 1,
+            Connection::Listening { pending, .. } => pending.len(),
+            Connection::None => 0,
+        }
+    }
+
+    fn description(&self) -> String {
+        let kind = match self.kind {
+            SocketType::Stream => "stream",
+            SocketType::Datagram | SocketType::Raw => "datagram",
+        };
+        match self.name() {
+            Some(name) => format!("unix_{}({})", kind, name),
+            None => format!("unix_{}", kind),
+        }
+    }
+}
+
+impl PollOps for UnixSocket {
+    fn poll_events(&self) -> u32 {
+        let state = self.state.lock();
+        match &state.connection {
+            Connection::Listening { pending, .. } => if pending.is_empty() { 0 } else { POLLIN },
+            Connection::Connected { queue, .. } => self.queue.readable(self.kind) | queue.writable(),
+            Connection::None if self.kind == SocketType::Datagram => self.queue.readable(self.kind) | POLLOUT,
+            Connection::None => POLLHUP,
+        }
+    }
+
+    /// Wait on the socket and on the room at its peer
+    fn poll_register(&self, task_id: usize) -> bool {
+        self.queue.waker.register(task_id);
+        if let Some(queue) = self.peer_queue() {
+            queue.waker.register(task_id);
+        }
+        true
+    }
+
+    fn poll_unregister(&self, task_id: usize) {
+        self.queue.waker.unregister(task_id);
+        if let Some(queue) = self.peer_queue() {
+            queue.waker.unregister(task_id);
+        }
+    }
+}
+
+impl Drop for UnixSocket {
+    fn drop(&mut self) {
+        let state = self.state.get_mut();
+        if let Some(name) = &state.name {
+            let mut names = NAMES.lock();
+            if names.get(name).is_some_and(|socket| socket.ptr_eq(&self.this)) {
+                names.remove(name);
+            }
+        }
+        if let Connection::Connected { queue, .. } = &state.connection {
+            queue.close(false);
+        }
+        // Objects still queued are closed outside the lock
+        let messages = core::mem::take(&mut self.queue.inbox.lock().messages);
+        self.queue.close(true);
+        drop(messages);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_socket_stream_connection() {
+        let listener = UnixSocket::new(SocketType::Stream, true);
+        assert_eq!(listener.connect("test_socket_stream").err(), Some(KernelError::NotFound));
+        listener.bind("test_socket_stream").unwrap();
+        listener.listen(1).unwrap();
+        assert_eq!(listener.accept().err(), Some(KernelError::WouldBlock));
+
+        let client = UnixSocket::new(SocketType::Stream, true);
+        client.connect("test_socket_stream").unwrap();
+        assert_eq!(listener.poll_events(), POLLIN);
+        assert_eq!(UnixSocket::new(SocketType::Stream, true).connect("test_socket_stream").err(), Some(KernelError::WouldBlock));
+        let server = listener.accept().unwrap();
+        assert_eq!(server.name().as_deref(), Some("test_socket_stream"));
+        assert_eq!(client.peer_name().as_deref(), Some("test_socket_stream"));
+
+        // Objects are received with the data they were sent with
+        let (end, _) = UnixSocket::pair(SocketType::Stream, true);
+        assert!(matches!(client.send(b"ab", Vec::new(), None), Ok(2)));
+        assert!(matches!(client.send(b"cd", alloc::vec![KernelObject::Socket(end)], None), Ok(2)));
+        let mut buffer = [0u8; 8];
+        let received = server.receive(&mut buffer).unwrap();
+        assert_eq!((&buffer[..received.len], received.objects.len()), (&b"ab"[..], 0));
+        let received = server.receive(&mut buffer).unwrap();
+        assert_eq!((&buffer[..received.len], received.objects.len()), (&b"cd"[..], 1));
+
+        // Closing one end is the end of the stream of the other
+        assert_eq!(server.poll_events(), POLLOUT);
+        drop(client);
+        assert_eq!(server.poll_events(), POLLIN | POLLHUP | POLLERR);
+        assert_eq!(server.receive(&mut buffer).map(|received| received.len).ok(), Some(0));
+        assert_eq!(server.send(b"x", Vec::new(), None).err(), Some(KernelError::BrokenPipe));
+
+        // The name is free once the listener is closed
+        drop(listener);
+        assert!(UnixSocket::new(SocketType::Stream, true).bind("test_socket_stream").is_ok());
+    }
+
+    #[test_case]
+    fn test_socket_datagrams() {
+        let receiver = UnixSocket::new(SocketType::Datagram, true);
+        receiver.bind("test_socket_datagram").unwrap();
+        let sender = UnixSocket::new(SocketType::Datagram, true);
+        sender.bind("test_socket_sender").unwrap();
+        assert_eq!(UnixSocket::new(SocketType::Datagram, true).bind("test_socket_datagram").err(), Some(KernelError::AddressInUse));
+        assert_eq!(sender.send(b"x", Vec::new(), None).err(), Some(KernelError::NotConnected));
+
+        assert!(matches!(sender.send(b"hello", Vec::new(), Some("test_socket_datagram")), Ok(5)));
+        assert!(matches!(sender.send(b"world", Vec::new(), Some("test_socket_datagram")), Ok(5)));
+        let mut buffer = [0u8; 3];
+        let received = receiver.receive(&mut buffer).unwrap();
+        assert_eq!((&buffer[..], received.truncated), (&b"hel"[..], true));
+        assert_eq!(received.from.as_deref(), Some("test_socket_sender"));
+        let mut buffer = [0u8; 8];
+        let received = receiver.receive(&mut buffer).unwrap();
+        assert_eq!((&buffer[..received.len], received.truncated), (&b"world"[..], false));
+        assert_eq!(receiver.receive(&mut buffer).err(), Some(KernelError::WouldBlock));
+
+        let too_long = alloc::vec![0u8; SOCKET_BUFFER_SIZE + 1];
+        assert_eq!(sender.send(&too_long, Vec::new(), Some("test_socket_datagram")).err(), Some(KernelError::MessageTooLong));
+        sender.connect("test_socket_datagram").unwrap();
+        drop(receiver);
+        assert_eq!(sender.send(b"x", Vec::new(), None).err(), Some(KernelError::ConnectionRefused));
+    }
+}
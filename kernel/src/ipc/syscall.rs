@@ -1,18 +1,39 @@
 //! IPC system calls
 //! 
 //! This module provides system call implementations for IPC operations
-//! such as pipe creation, message passing, and shared memory.
+//! such as pipe creation, message passing, shared memory, futexes, event
+//! counters, sockets, semaphores and the message bus.
 
 use crate::{
+    abi::error::{fail, KernelError},
     arch::Trapframe,
-    task::mytask,
-    ipc::pipe::UnidirectionalPipe,
+    task::{mytask, Task},
+    ipc::pipe::{splice, PipeObject, UnidirectionalPipe, PIPE_DEFAULT_SIZE},
     ipc::event::{EventManager, Event, EventContent, EventPayload, EventPriority, ProcessControlType},
+    ipc::shm::{SharedMemoryObject, SHM_NAME_MAX},
+    ipc::memfd::{MemFdObject, MEMFD_NAME_MAX},
+    ipc::eventfd::{EventFdObject, EVENTFD_NONBLOCK, EVENTFD_SEMAPHORE},
+    ipc::semaphore::{SemaphoreObject, SEM_NAME_MAX, SEM_VALUE_MAX},
+    ipc::bus::{BusConnection, BUS_MAX_OBJECTS, BUS_MESSAGE_MAX, BUS_NAME_MAX, BUS_NONBLOCK},
+    ipc::socket::{
+        create_socket, Shutdown, SocketDomain, SocketObject, SocketOption, SocketType, UnixSocket, SOCKET_BUFFER_SIZE,
+        SOCKET_DATAGRAM, SOCKET_DOMAIN_INET, SOCKET_DOMAIN_INET6, SOCKET_DOMAIN_LOCAL, SOCKET_DOMAIN_PACKET,
+        SOCKET_MAX_OBJECTS, SOCKET_NAME_MAX, SOCKET_NONBLOCK, SOCKET_OPT_BUFFER_SIZE, SOCKET_OPT_CONNECTED,
+        SOCKET_OPT_DOMAIN, SOCKET_OPT_ERROR, SOCKET_OPT_FILTER, SOCKET_OPT_TYPE, SOCKET_RAW, SOCKET_SHUT_BOTH,
+        SOCKET_SHUT_READ, SOCKET_SHUT_WRITE, SOCKET_STREAM,
+    },
+    network::capture::{FilterExpression, FILTER_EXPRESSION_MAX},
+    ipc::futex::{
+        futex_key, futex_wait, futex_wake, futex_requeue, FutexError,
+        FUTEX_WAIT, FUTEX_WAKE, FUTEX_REQUEUE, FUTEX_CMP_REQUEUE, FUTEX_PRIVATE_FLAG,
+    },
     object::KernelObject,
     object::capability::EventSubscriber,
     library::std::string::parse_c_string_from_userspace,
+    task::signal::{copy_from_user, copy_to_user, interrupt_syscall},
+    timer::ns_to_ticks,
 };
-use alloc::string::ToString;
+use alloc::{string::{String, ToString}, sync::Arc, vec, vec::Vec};
 
 /// sys_pipe - Create a pipe pair
 /// 
@@ -37,15 +58,45 @@ pub fn sys_pipe(trapframe: &mut Trapframe) -> usize {
     // Increment PC to avoid infinite loop if pipe creation fails
     trapframe.increment_pc_next(task);
     
-    // Translate the pointer to get access to the pipefd array
-    let pipefd_vaddr = match task.vm_manager.translate_vaddr(pipefd_ptr) {
-        Some(addr) => addr as *mut u32,
-        None => return usize::MAX, // Invalid pointer
+    create_pipe(task, pipefd_ptr, 0)
+}
+
+/// Both ends of the pipe are non-blocking (sys_pipe2 flag)
+pub const PIPE_NONBLOCK: usize = 0x1;
+
+/// sys_pipe2 - Create a pipe pair with flags
+/// 
+/// Extended version of sys_pipe that takes flags controlling the
+/// behavior of the pipe.
+/// 
+/// Arguments:
+/// - pipefd: Pointer to an array of 2 integers for the read and write ends
+/// - flags: PIPE_NONBLOCK
+/// 
+/// Returns: 0 on success, usize::MAX on error
+pub fn sys_pipe2(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
     };
-    
-    // Create pipe pair with default buffer size (4KB)
-    const DEFAULT_PIPE_BUFFER_SIZE: usize = 4096;
-    let (read_obj, write_obj) = UnidirectionalPipe::create_pair(DEFAULT_PIPE_BUFFER_SIZE);
+
+    let pipefd_ptr = trapframe.get_arg(0);
+    let flags = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    if flags & !PIPE_NONBLOCK != 0 {
+        return fail(KernelError::InvalidArgument);
+    }
+    create_pipe(task, pipefd_ptr, flags)
+}
+
+fn create_pipe(task: &mut Task, pipefd_ptr: usize, flags: usize) -> usize {
+    let (read_obj, write_obj) = UnidirectionalPipe::create_pair(PIPE_DEFAULT_SIZE);
+    for end in [&read_obj, &write_obj] {
+        if let Some(pipe) = end.as_pipe() {
+            pipe.set_nonblocking(flags & PIPE_NONBLOCK != 0);
+        }
+    }
     
     // Insert into handle table with explicit IPC metadata
     use crate::object::handle::{HandleMetadata, HandleType, AccessMode};
@@ -62,40 +113,158 @@ pub fn sys_pipe(trapframe: &mut Trapframe) -> usize {
         special_semantics: None,
     };
     
-    let read_handle = match task.handle_table.insert_with_metadata(read_obj, read_metadata) {
+    let read_handle = match task.handle_table.write().insert_with_metadata(read_obj, read_metadata) {
         Ok(handle) => handle,
-        Err(_) => return usize::MAX, // Too many open handles
+        Err(_) => return fail(KernelError::TooManyHandles),
     };
     
-    let write_handle = match task.handle_table.insert_with_metadata(write_obj, write_metadata) {
+    let inserted = task.handle_table.write().insert_with_metadata(write_obj, write_metadata);
+    let write_handle = match inserted {
         Ok(handle) => handle,
         Err(_) => {
             // Clean up the read handle if write handle allocation fails
-            let _ = task.handle_table.remove(read_handle);
-            return usize::MAX;
+            let _ = task.handle_table.write().remove(read_handle);
+            return fail(KernelError::TooManyHandles);
         }
     };
     
     // Write the handles to user space
-    unsafe {
-        *pipefd_vaddr = read_handle;
-        *pipefd_vaddr.add(1) = write_handle;
+    let mut handles = [0u8; 8];
+    handles[..4].copy_from_slice(&read_handle.to_ne_bytes());
+    handles[4..].copy_from_slice(&write_handle.to_ne_bytes());
+    if copy_to_user(task, pipefd_ptr, &handles).is_err() {
+        task.handle_table.write().remove(read_handle);
+        task.handle_table.write().remove(write_handle);
+        return fail(KernelError::BadAddress);
     }
     
     0 // Success
 }
 
-/// sys_pipe2 - Create a pipe pair with flags (future implementation)
-/// 
-/// Extended version of sys_pipe that supports flags for controlling
-/// pipe behavior (e.g., O_NONBLOCK, O_CLOEXEC).
-pub fn sys_pipe2(trapframe: &mut Trapframe) -> usize {
-    let _pipefd_ptr = trapframe.get_arg(0);
-    let _flags = trapframe.get_arg(1);
-    
-    // For now, just call the basic sys_pipe implementation
-    // TODO: Implement flag handling
-    sys_pipe(trapframe)
+/// The pipe behind `handle`
+fn pipe_of(task: &Task, handle: usize) -> Result<Arc<dyn PipeObject>, KernelError> {
+    match task.handle_table.read().get(handle as u32).cloned() {
+        Some(KernelObject::Pipe(pipe)) => Ok(pipe),
+        Some(_) => Err(KernelError::InvalidArgument),
+        None => Err(KernelError::BadHandle),
+    }
+}
+
+/// sys_pipe_set_size - Change the capacity of a pipe
+///
+/// Arguments:
+/// - handle: handle of either end of the pipe
+/// - size: capacity in bytes, rounded up to a power of two of at least a
+///   page and at most PIPE_MAX_SIZE
+///
+/// Returns: the capacity given, usize::MAX on error (also if more data is
+/// buffered than the new capacity holds)
+pub fn sys_pipe_set_size(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    let size = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    match pipe_of(task, handle).and_then(|pipe| pipe.set_buffer_size(size)) {
+        Ok(size) => size,
+        Err(error) => fail(error),
+    }
+}
+
+/// sys_pipe_get_size - Get the capacity of a pipe
+///
+/// Returns: the capacity in bytes, usize::MAX on error
+pub fn sys_pipe_get_size(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    match pipe_of(task, handle) {
+        Ok(pipe) => pipe.buffer_size(),
+        Err(error) => fail(error),
+    }
+}
+
+/// sys_pipe_set_nonblocking - Make an end of a pipe fail instead of waiting
+///
+/// Arguments:
+/// - handle: handle of the end
+/// - nonblocking: non-zero for reads and writes to fail with WouldBlock
+///   instead of waiting, 0 for them to wait
+///
+/// Returns: 0 on success, usize::MAX on error
+pub fn sys_pipe_set_nonblocking(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    let nonblocking = trapframe.get_arg(1) != 0;
+    trapframe.increment_pc_next(task);
+
+    match pipe_of(task, handle) {
+        Ok(pipe) => {
+            pipe.set_nonblocking(nonblocking);
+            0
+        }
+        Err(error) => fail(error),
+    }
+}
+
+/// The pipe does not wait for data or room (sys_splice flag)
+pub const SPLICE_NONBLOCK: usize = 0x1;
+
+/// sys_splice - Move data between a pipe and another stream
+///
+/// The data does not pass through user memory. A file is read or written
+/// at its current position.
+///
+/// Arguments:
+/// - from: handle to read from
+/// - to: handle to write to; one of the two is a pipe
+/// - len: most bytes to move
+/// - flags: SPLICE_NONBLOCK
+///
+/// Returns: the number of bytes moved (0 at the end of the data),
+/// usize::MAX on error
+///
+/// A wait interrupted by a signal is restarted or fails as described in
+/// `crate::task::signal`.
+pub fn sys_splice(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let from = trapframe.get_arg(0);
+    let to = trapframe.get_arg(1);
+    let len = trapframe.get_arg(2);
+    let flags = trapframe.get_arg(3);
+    trapframe.increment_pc_next(task);
+
+    if flags & !SPLICE_NONBLOCK != 0 {
+        return fail(KernelError::InvalidArgument);
+    }
+    let handles = task.handle_table.read();
+    let (from, to) = match (handles.get(from as u32).cloned(), handles.get(to as u32).cloned()) {
+        (Some(from), Some(to)) => (from, to),
+        _ => return fail(KernelError::BadHandle),
+    };
+    drop(handles);
+    match splice(&from, &to, len, flags & SPLICE_NONBLOCK != 0) {
+        Ok(moved) => moved,
+        Err(KernelError::Interrupted) => interrupt_syscall(task, trapframe),
+        Err(error) => fail(error),
+    }
 }
 
 // === Event IPC (Handle-based) ===
@@ -122,7 +291,7 @@ pub fn sys_event_channel_create(trapframe: &mut Trapframe) -> usize {
 
     let mgr = EventManager::get_manager();
     let ko = mgr.create_channel(name);
-    match task.handle_table.insert(ko) {
+    match task.handle_table.write().insert(ko) {
         Ok(h) => h as usize,
         Err(_) => usize::MAX,
     }
@@ -153,7 +322,7 @@ pub fn sys_event_subscribe(trapframe: &mut Trapframe) -> usize {
         Ok(ko) => ko,
         Err(_) => return usize::MAX,
     };
-    match task.handle_table.insert(ko) {
+    match task.handle_table.write().insert(ko) {
         Ok(h) => h as usize,
         Err(_) => usize::MAX,
     }
@@ -175,7 +344,7 @@ pub fn sys_event_unsubscribe(trapframe: &mut Trapframe) -> usize {
     trapframe.increment_pc_next(task);
 
     // Get the object first to extract identifiers
-    let (channel_name, subscription_id) = match task.handle_table.get(handle) {
+    let (channel_name, subscription_id) = match task.handle_table.read().get(handle).cloned() {
         Some(KernelObject::EventSubscription(sub)) => {
             (sub.channel_name().to_string(), sub.subscription_id().to_string())
         }
@@ -187,7 +356,7 @@ pub fn sys_event_unsubscribe(trapframe: &mut Trapframe) -> usize {
     let _ = mgr.remove_subscription_from_channel(&channel_name, &subscription_id);
 
     // Finally remove handle (drop Arc)
-    match task.handle_table.remove(handle) {
+    match task.handle_table.write().remove(handle) {
         Some(_) => 0,
         None => usize::MAX,
     }
@@ -212,7 +381,7 @@ pub fn sys_event_publish(trapframe: &mut Trapframe) -> usize {
     let payload_val = trapframe.get_arg(2) as isize as i64;
     trapframe.increment_pc_next(task);
 
-    let ko = match task.handle_table.get(channel_handle) {
+    let ko = match task.handle_table.read().get(channel_handle).cloned() {
         Some(obj) => obj,
         None => return usize::MAX,
     };
@@ -259,7 +428,7 @@ pub fn sys_event_handler_register(trapframe: &mut Trapframe) -> usize {
     let param0 = trapframe.get_arg(3) as u32;
     trapframe.increment_pc_next(task);
 
-    let ko = match task.handle_table.get(sub_handle) { Some(obj) => obj, None => return usize::MAX };
+    let ko = match task.handle_table.read().get(sub_handle).cloned() { Some(obj) => obj, None => return usize::MAX };
     let sub = match ko.as_event_subscription() { Some(s) => s, None => return usize::MAX };
 
     use crate::ipc::event::{EventFilter, EventTypeFilter};
@@ -317,3 +486,1366 @@ pub fn sys_event_send_direct(trapframe: &mut Trapframe) -> usize {
     let mgr = EventManager::get_manager();
     match mgr.send_event(event) { Ok(()) => 0, Err(_) => usize::MAX }
 }
+
+// === Shared Memory ===
+
+/// Create the segment if it does not exist (sys_shm_open flag)
+pub const SHM_O_CREAT: usize = 0x40;
+/// Fail if the segment already exists (sys_shm_open flag)
+pub const SHM_O_EXCL: usize = 0x80;
+
+/// Create or open a shared memory object and return a handle to it
+///
+/// The handle can be mapped with sys_memory_map and MAP_SHARED; mappings in
+/// different tasks see the same physical pages.
+///
+/// Arguments:
+/// - name_ptr: const char* (C-string) segment name, or 0 for an anonymous segment
+/// - size: size in bytes when creating (rounded up to pages); 0 creates an
+///   empty named segment to be sized with sys_shm_set_size
+/// - flags: SHM_O_CREAT / SHM_O_EXCL
+///
+/// Returns: handle on success, usize::MAX on error
+pub fn sys_shm_open(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let name_ptr = trapframe.get_arg(0);
+    let size = trapframe.get_arg(1);
+    let flags = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    let result = if name_ptr == 0 {
+        SharedMemoryObject::create_anonymous(size)
+    } else {
+        let name = match parse_c_string_from_userspace(task, name_ptr, SHM_NAME_MAX + 1) {
+            Ok(s) => s,
+            Err(_) => return usize::MAX,
+        };
+        SharedMemoryObject::open(&name, size, flags & SHM_O_CREAT != 0, flags & SHM_O_EXCL != 0)
+    };
+
+    let shm = match result {
+        Ok(shm) => shm,
+        Err(_) => return usize::MAX,
+    };
+    match task.handle_table.write().insert(KernelObject::from_shared_memory(shm)) {
+        Ok(h) => h as usize,
+        Err(_) => usize::MAX,
+    }
+}
+
+/// Remove the name of a shared memory object
+///
+/// Existing handles and mappings keep the memory alive until they are gone.
+///
+/// Arguments:
+/// - name_ptr: const char* (C-string) segment name
+///
+/// Returns: 0 on success, usize::MAX on error
+pub fn sys_shm_unlink(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let name_ptr = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    let name = match parse_c_string_from_userspace(task, name_ptr, SHM_NAME_MAX + 1) {
+        Ok(s) => s,
+        Err(_) => return usize::MAX,
+    };
+    match SharedMemoryObject::unlink(&name) {
+        Ok(()) => 0,
+        Err(_) => usize::MAX,
+    }
+}
+
+/// The shared memory object of `handle` in the current task
+fn shared_memory_of(task: &Task, handle: usize) -> Option<Arc<SharedMemoryObject>> {
+    match task.handle_table.read().get(handle as u32).cloned() {
+        Some(KernelObject::SharedMemory(shm)) => Some(shm),
+        _ => None,
+    }
+}
+
+/// Give an empty shared memory object its size
+///
+/// A segment is sized once: one that already has a size cannot be resized.
+///
+/// Arguments:
+/// - handle: handle of the shared memory object
+/// - size: size in bytes (rounded up to pages)
+///
+/// Returns: 0 on success, usize::MAX on error
+pub fn sys_shm_set_size(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    let size = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    match shared_memory_of(task, handle).map(|shm| shm.set_size(size)) {
+        Some(Ok(())) => 0,
+        _ => usize::MAX,
+    }
+}
+
+/// Get the size of a shared memory object
+///
+/// Arguments:
+/// - handle: handle of the shared memory object
+///
+/// Returns: the size in bytes (0 for an empty segment), usize::MAX on error
+pub fn sys_shm_get_size(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    shared_memory_of(task, handle).map_or(usize::MAX, |shm| shm.size())
+}
+
+// === Memory files ===
+
+/// Seals can be added to the memory file (sys_memfd_create flag)
+pub const MEMFD_ALLOW_SEALING: usize = 0x1;
+
+/// The memory file of `handle` in the current task
+fn memfd_of(task: &Task, handle: usize) -> Result<&MemFdObject, KernelError> {
+    let object = task.handle_table.read().get(handle as u32).cloned().ok_or(KernelError::BadHandle)?;
+    MemFdObject::of(object).ok_or(KernelError::InvalidArgument)
+}
+
+/// Create an empty memory file (see `crate::ipc::memfd`) and return a
+/// handle to it
+///
+/// The handle is a file: it is read and written with sys_stream_read /
+/// sys_stream_write, sized with sys_file_truncate and mapped with
+/// sys_memory_map (MAP_SHARED).
+///
+/// Arguments:
+/// - name_ptr: const char* (C-string) name of the file, for display only
+/// - flags: MEMFD_ALLOW_SEALING
+///
+/// Returns: handle on success, usize::MAX on error
+pub fn sys_memfd_create(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let name_ptr = trapframe.get_arg(0);
+    let flags = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    if flags & !MEMFD_ALLOW_SEALING != 0 {
+        return fail(KernelError::InvalidArgument);
+    }
+    let name = match parse_c_string_from_userspace(task, name_ptr, MEMFD_NAME_MAX + 1) {
+        Ok(name) => name,
+        Err(_) => return fail(KernelError::BadAddress),
+    };
+    match MemFdObject::new(&name, flags & MEMFD_ALLOW_SEALING != 0) {
+        Ok(memfd) => match task.handle_table.write().insert(KernelObject::from_file_object(memfd)) {
+            Ok(handle) => handle as usize,
+            Err(_) => fail(KernelError::TooManyHandles),
+        },
+        Err(error) => fail(error),
+    }
+}
+
+/// Add seals to a memory file
+///
+/// Arguments:
+/// - handle: handle of the memory file
+/// - seals: SEAL_SEAL / SEAL_SHRINK / SEAL_GROW / SEAL_WRITE
+///
+/// Returns: 0 on success, usize::MAX on error
+pub fn sys_memfd_add_seals(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    let seals = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    let seals = match u32::try_from(seals) {
+        Ok(seals) => seals,
+        Err(_) => return fail(KernelError::InvalidArgument),
+    };
+    unit_result(memfd_of(task, handle).and_then(|memfd| memfd.add_seals(seals)))
+}
+
+/// Get the seals of a memory file
+///
+/// Arguments:
+/// - handle: handle of the memory file
+///
+/// Returns: the seals, usize::MAX on error
+pub fn sys_memfd_get_seals(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    match memfd_of(task, handle) {
+        Ok(memfd) => memfd.seals() as usize,
+        Err(error) => fail(error),
+    }
+}
+
+// === Futex ===
+
+/// Wait on or wake a futex (see `crate::ipc::futex`)
+///
+/// Arguments:
+/// - uaddr: address of the 4-byte aligned futex word
+/// - op: FUTEX_WAIT / FUTEX_WAKE / FUTEX_REQUEUE / FUTEX_CMP_REQUEUE,
+///   optionally with FUTEX_PRIVATE_FLAG
+/// - val: FUTEX_WAIT: expected value of the word;
+///   otherwise: maximum number of tasks to wake
+/// - timeout: FUTEX_WAIT: relative timeout in nanoseconds, 0 to wait forever;
+///   requeue: maximum number of tasks to requeue
+/// - uaddr2: requeue: address of the futex word to requeue to
+/// - val3: FUTEX_CMP_REQUEUE: expected value of the word at uaddr
+///
+/// Returns:
+/// - FUTEX_WAIT: 0 once woken
+/// - otherwise: the number of tasks woken (and requeued)
+/// - usize::MAX on error, including a changed word and a timeout
+///
+/// A wait interrupted by a signal is restarted or fails as described in
+/// `crate::task::signal`.
+pub fn sys_futex(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let uaddr = trapframe.get_arg(0);
+    let op = trapframe.get_arg(1) & !FUTEX_PRIVATE_FLAG;
+    let val = trapframe.get_arg(2);
+    let timeout = trapframe.get_arg(3);
+    let uaddr2 = trapframe.get_arg(4);
+    let val3 = trapframe.get_arg(5);
+    trapframe.increment_pc_next(task);
+
+    let key = match futex_key(task, uaddr) {
+        Ok(key) => key,
+        Err(_) => return usize::MAX,
+    };
+    let result = match op {
+        FUTEX_WAIT => {
+            let timeout_ticks = (timeout != 0).then(|| ns_to_ticks(timeout as u64));
+            match futex_wait(task, key, val as u32, timeout_ticks, trapframe) {
+                Err(FutexError::Interrupted) if task.signals.has_pending() => {
+                    return interrupt_syscall(task, trapframe);
+                }
+                result => result.map(|()| 0),
+            }
+        }
+        FUTEX_WAKE => Ok(futex_wake(key, val)),
+        FUTEX_REQUEUE | FUTEX_CMP_REQUEUE => {
+            let expected = (op == FUTEX_CMP_REQUEUE).then_some(val3 as u32);
+            futex_key(task, uaddr2)
+                .and_then(|key2| futex_requeue(key, val, key2, timeout, expected))
+        }
+        _ => return usize::MAX,
+    };
+    result.unwrap_or(usize::MAX)
+}
+
+// === Event counters ===
+
+/// Create an event counter (see `crate::ipc::eventfd`) and return a handle
+/// to it
+///
+/// The handle is read and written with sys_stream_read / sys_stream_write
+/// and can be waited on with sys_handle_poll or an epoll object.
+///
+/// Arguments:
+/// - initial: initial value of the counter
+/// - flags: EVENTFD_SEMAPHORE / EVENTFD_NONBLOCK
+///
+/// Returns: handle on success, usize::MAX on error
+pub fn sys_eventfd_create(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let initial = trapframe.get_arg(0);
+    let flags = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    if flags & !(EVENTFD_SEMAPHORE | EVENTFD_NONBLOCK) != 0 {
+        return usize::MAX;
+    }
+    let eventfd = EventFdObject::new(
+        initial as u64,
+        flags & EVENTFD_SEMAPHORE != 0,
+        flags & EVENTFD_NONBLOCK != 0,
+    );
+    match task.handle_table.write().insert(KernelObject::from_eventfd(eventfd)) {
+        Ok(h) => h as usize,
+        Err(_) => usize::MAX,
+    }
+}
+
+// === Sockets ===
+
+/// The socket of `handle` in the current task
+fn socket_of(handle: usize) -> Result<Arc<dyn SocketObject>, KernelError> {
+    match mytask().and_then(|task| task.handle_table.read().get(handle as u32).cloned()) {
+        Some(KernelObject::Socket(socket)) => Ok(socket),
+        Some(_) => Err(KernelError::NotASocket),
+        None => Err(KernelError::BadHandle),
+    }
+}
+
+fn socket_domain(value: usize) -> Result<SocketDomain, KernelError> {
+    match value {
+        SOCKET_DOMAIN_LOCAL => Ok(SocketDomain::Local),
+        SOCKET_DOMAIN_INET => Ok(SocketDomain::Inet),
+        SOCKET_DOMAIN_INET6 => Ok(SocketDomain::Inet6),
+        SOCKET_DOMAIN_PACKET => Ok(SocketDomain::Packet),
+        _ => Err(KernelError::InvalidArgument),
+    }
+}
+
+fn socket_type(value: usize) -> Result<SocketType, KernelError> {
+    match value {
+        SOCKET_STREAM => Ok(SocketType::Stream),
+        SOCKET_DATAGRAM => Ok(SocketType::Datagram),
+        SOCKET_RAW => Ok(SocketType::Raw),
+        _ => Err(KernelError::InvalidArgument),
+    }
+}
+
+fn socket_name(task: &Task, name_ptr: usize) -> Result<String, KernelError> {
+    parse_c_string_from_userspace(task, name_ptr, SOCKET_NAME_MAX + 1).map_err(|_| KernelError::BadAddress)
+}
+
+/// The objects of the `count` u32 handles at `handles_ptr`
+fn objects_of_handles(task: &Task, handles_ptr: usize, count: usize) -> Result<Vec<KernelObject>, KernelError> {
+    let mut handles = vec![0u8; count * 4];
+    copy_from_user(task, handles_ptr, &mut handles).map_err(|_| KernelError::BadAddress)?;
+    handles
+        .chunks_exact(4)
+        .map(|bytes| {
+            let handle = u32::from_le_bytes(bytes.try_into().unwrap());
+            task.handle_table.read().get(handle).cloned().ok_or(KernelError::BadHandle)
+        })
+        .collect()
+}
+
+/// The number of handles the u32 at `count_ptr` makes room for, 0 if
+/// `count_ptr` is 0
+///
+/// Read before receiving, so that a bad pointer does not lose a message.
+fn handle_capacity(task: &Task, count_ptr: usize) -> Result<usize, KernelError> {
+    let mut capacity = [0u8; 4];
+    if count_ptr != 0 {
+        copy_from_user(task, count_ptr, &mut capacity).map_err(|_| KernelError::BadAddress)?;
+    }
+    Ok(u32::from_le_bytes(capacity) as usize)
+}
+
+/// Give up to `capacity` of `objects` new handles, stored in the u32 array
+/// at `handles_ptr`, and their number at `count_ptr`
+///
+/// Objects that do not fit are closed. A `count_ptr` of 0 takes no
+/// handles.
+fn install_objects(
+    task: &mut Task,
+    objects: Vec<KernelObject>,
+    capacity: usize,
+    handles_ptr: usize,
+    count_ptr: usize,
+) -> Result<(), KernelError> {
+    if count_ptr == 0 {
+        return Ok(());
+    }
+    let mut handles = Vec::new();
+    for object in objects.into_iter().take(capacity) {
+        match task.handle_table.write().insert(object) {
+            Ok(h) => handles.extend_from_slice(&h.to_le_bytes()),
+            Err(_) => break,
+        }
+    }
+    copy_to_user(task, handles_ptr, &handles).map_err(|_| KernelError::BadAddress)?;
+    let count = (handles.len() / 4) as u32;
+    copy_to_user(task, count_ptr, &count.to_le_bytes()).map_err(|_| KernelError::BadAddress)
+}
+
+/// Return 0 for `Ok`, or fail with the error
+fn unit_result(result: Result<(), KernelError>) -> usize {
+    match result {
+        Ok(()) => 0,
+        Err(error) => fail(error),
+    }
+}
+
+/// Create a socket (see `crate::ipc::socket`) and return a handle to it
+///
+/// The handle is read and written with sys_stream_read / sys_stream_write
+/// once connected, and can be waited on with sys_handle_poll or an epoll
+/// object. Internet sockets are TCP (stream) and UDP (datagram) sockets
+/// named `a.b.c.d:port`, or also `[v6]:port` for SOCKET_DOMAIN_INET6.
+/// Raw internet sockets carry the datagrams of one IP protocol, and packet
+/// sockets (SOCKET_DOMAIN_PACKET, raw only) whole frames, named by the
+/// interface; creating either takes privilege.
+///
+/// Arguments:
+/// - domain: SOCKET_DOMAIN_LOCAL / SOCKET_DOMAIN_INET / SOCKET_DOMAIN_INET6 /
+///   SOCKET_DOMAIN_PACKET
+/// - type: SOCKET_STREAM / SOCKET_DATAGRAM / SOCKET_RAW
+/// - flags: SOCKET_NONBLOCK
+/// - protocol: the IP protocol of a raw internet socket, the EtherType of a
+///   packet socket (0 for all), else 0
+///
+/// Returns: handle on success, usize::MAX on error
+pub fn sys_socket_create(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let domain = trapframe.get_arg(0);
+    let kind = trapframe.get_arg(1);
+    let flags = trapframe.get_arg(2);
+    let protocol = trapframe.get_arg(3);
+    trapframe.increment_pc_next(task);
+
+    let (domain, kind) = match socket_domain(domain).and_then(|domain| Ok((domain, socket_type(kind)?))) {
+        Ok(socket) => socket,
+        Err(error) => return fail(error),
+    };
+    if flags & !SOCKET_NONBLOCK != 0 || protocol > u16::MAX as usize {
+        return fail(KernelError::InvalidArgument);
+    }
+    if kind == SocketType::Raw && !task.cred.is_privileged() {
+        crate::audit::privilege_denied("raw socket");
+        return fail(KernelError::NotPermitted);
+    }
+    let socket = match create_socket(domain, kind, protocol as u16, flags & SOCKET_NONBLOCK != 0) {
+        Ok(socket) => socket,
+        Err(error) => return fail(error),
+    };
+    match task.handle_table.write().insert(KernelObject::from_socket(socket)) {
+        Ok(h) => h as usize,
+        Err(_) => fail(KernelError::TooManyHandles),
+    }
+}
+
+/// Create two unnamed local sockets connected to each other
+///
+/// Arguments:
+/// - type: SOCKET_STREAM / SOCKET_DATAGRAM
+/// - flags: SOCKET_NONBLOCK
+/// - handles_ptr: array of 2 u32 receiving the handles
+///
+/// Returns: 0 on success, usize::MAX on error
+pub fn sys_socket_pair(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let kind = trapframe.get_arg(0);
+    let flags = trapframe.get_arg(1);
+    let handles_ptr = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    let kind = match socket_type(kind) {
+        Ok(kind) => kind,
+        Err(error) => return fail(error),
+    };
+    if flags & !SOCKET_NONBLOCK != 0 {
+        return fail(KernelError::InvalidArgument);
+    }
+    let (a, b) = UnixSocket::pair(kind, flags & SOCKET_NONBLOCK != 0);
+    let first = match task.handle_table.write().insert(KernelObject::from_socket(a)) {
+        Ok(h) => h,
+        Err(_) => return fail(KernelError::TooManyHandles),
+    };
+    let inserted = task.handle_table.write().insert(KernelObject::from_socket(b));
+    let second = match inserted {
+        Ok(h) => h,
+        Err(_) => {
+            task.handle_table.write().remove(first);
+            return fail(KernelError::TooManyHandles);
+        }
+    };
+    let mut handles = [0u8; 8];
+    handles[0..4].copy_from_slice(&first.to_le_bytes());
+    handles[4..8].copy_from_slice(&second.to_le_bytes());
+    if copy_to_user(task, handles_ptr, &handles).is_err() {
+        task.handle_table.write().remove(first);
+        task.handle_table.write().remove(second);
+        return fail(KernelError::BadAddress);
+    }
+    0
+}
+
+/// Give a socket a name
+///
+/// Arguments:
+/// - handle: handle of the socket
+/// - name_ptr: const char* (C-string) name, at most SOCKET_NAME_MAX bytes;
+///   `a.b.c.d:port` for an internet socket, port 0 for any port
+///
+/// Returns: 0 on success, usize::MAX on error
+pub fn sys_socket_bind(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    let name_ptr = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    unit_result(socket_of(handle).and_then(|socket| socket.bind(&socket_name(task, name_ptr)?)))
+}
+
+/// Accept connections to the name of a stream socket
+///
+/// Arguments:
+/// - handle: handle of the bound socket
+/// - backlog: most connections waiting to be accepted
+///
+/// Returns: 0 on success, usize::MAX on error
+pub fn sys_socket_listen(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    let backlog = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    unit_result(socket_of(handle).and_then(|socket| socket.listen(backlog)))
+}
+
+/// Connect a socket to the socket of a name
+///
+/// A non-blocking TCP socket fails with `InProgress` while the connection
+/// is being made; it turns writable once made, and SOCKET_OPT_ERROR tells
+/// whether it failed.
+///
+/// Arguments:
+/// - handle: handle of the socket
+/// - name_ptr: const char* (C-string) name, `a.b.c.d:port` for an internet
+///   socket
+///
+/// Returns: 0 on success, usize::MAX on error
+pub fn sys_socket_connect(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    let name_ptr = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    unit_result(socket_of(handle).and_then(|socket| socket.connect(&socket_name(task, name_ptr)?)))
+}
+
+/// Take the next connection to a listening socket
+///
+/// The accepted socket always blocks.
+///
+/// Arguments:
+/// - handle: handle of the listening socket
+///
+/// Returns: handle of the accepted socket on success, usize::MAX on error
+///
+/// A wait interrupted by a signal is restarted or fails as described in
+/// `crate::task::signal`.
+pub fn sys_socket_accept(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    match socket_of(handle).and_then(|socket| socket.accept()) {
+        Ok(socket) => match task.handle_table.write().insert(KernelObject::from_socket(socket)) {
+            Ok(h) => h as usize,
+            Err(_) => fail(KernelError::TooManyHandles),
+        },
+        Err(KernelError::Interrupted) => interrupt_syscall(task, trapframe),
+        Err(error) => fail(error),
+    }
+}
+
+/// Send data and handles through a socket
+///
+/// The objects of the handles are sent; the handles stay open in the
+/// sender.
+///
+/// Arguments:
+/// - handle: handle of the socket
+/// - buf: data to send
+/// - len: length of the data
+/// - handles_ptr: array of u32 handles to send with the data
+/// - handle_count: number of handles, at most SOCKET_MAX_OBJECTS
+/// - name_ptr: const char* (C-string) name of the receiving datagram
+///   socket, 0 to send to the peer
+///
+/// Returns: the number of bytes sent, usize::MAX on error
+///
+/// A wait interrupted by a signal is restarted or fails as described in
+/// `crate::task::signal`.
+pub fn sys_socket_send(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    let buf = trapframe.get_arg(1);
+    let len = trapframe.get_arg(2);
+    let handles_ptr = trapframe.get_arg(3);
+    let handle_count = trapframe.get_arg(4);
+    let name_ptr = trapframe.get_arg(5);
+    trapframe.increment_pc_next(task);
+
+    let send = || -> Result<usize, KernelError> {
+        let socket = socket_of(handle)?;
+        if len > SOCKET_BUFFER_SIZE || handle_count > SOCKET_MAX_OBJECTS {
+            return Err(KernelError::InvalidArgument);
+        }
+        let mut data = vec![0u8; len];
+        copy_from_user(task, buf, &mut data).map_err(|_| KernelError::BadAddress)?;
+        let objects = objects_of_handles(task, handles_ptr, handle_count)?;
+        let to = match name_ptr {
+            0 => None,
+            ptr => Some(socket_name(task, ptr)?),
+        };
+        socket.send(&data, objects, to.as_deref())
+    };
+    match send() {
+        Ok(sent) => sent,
+        Err(KernelError::Interrupted) => interrupt_syscall(task, trapframe),
+        Err(error) => fail(error),
+    }
+}
+
+/// Receive data and handles from a socket
+///
+/// Received objects get new handles; those that do not fit the array are
+/// closed. The part of a datagram that does not fit the buffer is lost.
+///
+/// Arguments:
+/// - handle: handle of the socket
+/// - buf: buffer receiving the data
+/// - len: length of the buffer
+/// - handles_ptr: array of u32 receiving the new handles
+/// - handle_count_ptr: u32 holding the length of the array, replaced by the
+///   number of handles received; 0 to receive no handles
+/// - name_ptr: buffer of SOCKET_NAME_MAX + 1 bytes receiving the name of the
+///   sender of a datagram as a C-string (empty if it has none), 0 if not
+///   wanted
+///
+/// Returns: the number of bytes received, 0 at the end of a stream,
+/// usize::MAX on error
+///
+/// A wait interrupted by a signal is restarted or fails as described in
+/// `crate::task::signal`.
+pub fn sys_socket_receive(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    let buf = trapframe.get_arg(1);
+    let len = trapframe.get_arg(2);
+    let handles_ptr = trapframe.get_arg(3);
+    let handle_count_ptr = trapframe.get_arg(4);
+    let name_ptr = trapframe.get_arg(5);
+    trapframe.increment_pc_next(task);
+
+    let mut receive = || -> Result<usize, KernelError> {
+        let socket = socket_of(handle)?;
+        let capacity = handle_capacity(task, handle_count_ptr)?;
+        let mut data = vec![0u8; len.min(SOCKET_BUFFER_SIZE)];
+        let received = socket.receive(&mut data)?;
+        copy_to_user(task, buf, &data[..received.len]).map_err(|_| KernelError::BadAddress)?;
+        install_objects(task, received.objects, capacity, handles_ptr, handle_count_ptr)?;
+        if name_ptr != 0 {
+            let mut name = received.from.unwrap_or_default().into_bytes();
+            name.push(0);
+            copy_to_user(task, name_ptr, &name).map_err(|_| KernelError::BadAddress)?;
+        }
+        Ok(received.len)
+    };
+    match receive() {
+        Ok(received) => received,
+        Err(KernelError::Interrupted) => interrupt_syscall(task, trapframe),
+        Err(error) => fail(error),
+    }
+}
+
+/// Stop receiving, sending or both on a connected socket
+///
+/// Arguments:
+/// - handle: handle of the socket
+/// - how: SOCKET_SHUT_READ / SOCKET_SHUT_WRITE / SOCKET_SHUT_BOTH
+///
+/// Returns: 0 on success, usize::MAX on error
+pub fn sys_socket_shutdown(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    let how = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    let how = match how {
+        SOCKET_SHUT_READ => Shutdown::Read,
+        SOCKET_SHUT_WRITE => Shutdown::Write,
+        SOCKET_SHUT_BOTH => Shutdown::Both,
+        _ => return fail(KernelError::InvalidArgument),
+    };
+    unit_result(socket_of(handle).and_then(|socket| socket.shutdown(how)))
+}
+
+/// Get an option of a socket
+///
+/// Arguments:
+/// - handle: handle of the socket
+/// - option:
+///   - SOCKET_OPT_TYPE: SOCKET_STREAM / SOCKET_DATAGRAM / SOCKET_RAW
+///   - SOCKET_OPT_DOMAIN: SOCKET_DOMAIN_LOCAL / SOCKET_DOMAIN_INET /
+///     SOCKET_DOMAIN_INET6 / SOCKET_DOMAIN_PACKET
+///   - SOCKET_OPT_ERROR: 0, or fails with the error the socket met in the
+///     background (such as a refused connection), which is then cleared
+///   - SOCKET_OPT_BUFFER_SIZE: bytes the socket buffers
+///   - SOCKET_OPT_CONNECTED: 1 if connected, else 0
+///   - SOCKET_OPT_REUSE_ADDRESS ... SOCKET_OPT_KEEPALIVE_COUNT: see
+///     sys_socket_set_option; `NotSupported` for sockets without them
+///
+/// Returns: the value on success, usize::MAX on error
+pub fn sys_socket_get_option(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    let option = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    let socket = match socket_of(handle) {
+        Ok(socket) => socket,
+        Err(error) => return fail(error),
+    };
+    match option {
+        SOCKET_OPT_TYPE => match socket.socket_type() {
+            SocketType::Stream => SOCKET_STREAM,
+            SocketType::Datagram => SOCKET_DATAGRAM,
+            SocketType::Raw => SOCKET_RAW,
+        },
+        SOCKET_OPT_DOMAIN => match socket.domain() {
+            SocketDomain::Local => SOCKET_DOMAIN_LOCAL,
+            SocketDomain::Inet => SOCKET_DOMAIN_INET,
+            SocketDomain::Inet6 => SOCKET_DOMAIN_INET6,
+            SocketDomain::Packet => SOCKET_DOMAIN_PACKET,
+        },
+        SOCKET_OPT_ERROR => match socket.take_error() {
+            Some(error) => fail(error),
+            None => 0,
+        },
+        SOCKET_OPT_BUFFER_SIZE => SOCKET_BUFFER_SIZE,
+        SOCKET_OPT_CONNECTED => socket.is_connected() as usize,
+        _ => match SocketOption::from_number(option) {
+            Some(option) => socket.get_option(option).unwrap_or_else(fail),
+            None => fail(KernelError::InvalidArgument),
+        },
+    }
+}
+
+/// Set an option of a socket
+///
+/// Arguments:
+/// - handle: handle of the socket
+/// - option:
+///   - SOCKET_OPT_FILTER: value is the address of a capture filter
+///     expression (see `crate::network::capture`) for a raw or packet
+///     socket to take only what it accepts, or 0 to take everything
+///   - SOCKET_OPT_REUSE_ADDRESS: 1 to bind a port whose other users do not
+///     listen and set it too, such as connections in TIME-WAIT
+///   - SOCKET_OPT_NO_DELAY: 1 to send small segments at once
+///   - SOCKET_OPT_RECEIVE_BUFFER / SOCKET_OPT_SEND_BUFFER: bytes buffered
+///     each way
+///   - SOCKET_OPT_KEEPALIVE: 1 to probe the peer of an idle connection
+///   - SOCKET_OPT_KEEPALIVE_IDLE / SOCKET_OPT_KEEPALIVE_INTERVAL: seconds
+///     before the first probe and between probes
+///   - SOCKET_OPT_KEEPALIVE_COUNT: probes unanswered before the connection
+///     fails with `TimedOut`
+/// - value: the value of the option
+///
+/// Returns: 0 on success, usize::MAX on error
+pub fn sys_socket_set_option(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    let option = trapframe.get_arg(1);
+    let value = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    let socket = match socket_of(handle) {
+        Ok(socket) => socket,
+        Err(error) => return fail(error),
+    };
+    match option {
+        SOCKET_OPT_FILTER if value == 0 => unit_result(socket.set_filter(None)),
+        SOCKET_OPT_FILTER => {
+            let expression = match parse_c_string_from_userspace(task, value, FILTER_EXPRESSION_MAX + 1) {
+                Ok(expression) => expression,
+                Err(_) => return fail(KernelError::BadAddress),
+            };
+            match FilterExpression::parse(&expression) {
+                Ok(filter) => unit_result(socket.set_filter(Some(Arc::new(filter)))),
+                Err(error) => fail(error),
+            }
+        }
+        _ => match SocketOption::from_number(option) {
+            Some(option) => unit_result(socket.set_option(option, value)),
+            None => fail(KernelError::InvalidArgument),
+        },
+    }
+}
+
+// === Semaphores ===
+
+/// Create the semaphore if it does not exist (sys_sem_open flag)
+pub const SEM_O_CREAT: usize = 0x40;
+/// Fail if the semaphore already exists (sys_sem_open flag)
+pub const SEM_O_EXCL: usize = 0x80;
+
+/// The semaphore of `handle` in the current task
+fn semaphore_of(handle: usize) -> Result<Arc<SemaphoreObject>, KernelError> {
+    match mytask().and_then(|task| task.handle_table.read().get(handle as u32).cloned()) {
+        Some(KernelObject::Semaphore(semaphore)) => Ok(semaphore),
+        Some(_) => Err(KernelError::InvalidArgument),
+        None => Err(KernelError::BadHandle),
+    }
+}
+
+/// Create or open a semaphore (see `crate::ipc::semaphore`) and return a
+/// handle to it
+///
+/// The handle can be waited on with sys_handle_poll or an epoll object,
+/// and is ready while the semaphore can be taken.
+///
+/// Arguments:
+/// - name_ptr: const char* (C-string) semaphore name, or 0 for an anonymous semaphore
+/// - value: initial value when creating, at most SEM_VALUE_MAX
+/// - flags: SEM_O_CREAT / SEM_O_EXCL
+///
+/// Returns: handle on success, usize::MAX on error
+pub fn sys_sem_open(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let name_ptr = trapframe.get_arg(0);
+    let value = trapframe.get_arg(1);
+    let flags = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    if flags & !(SEM_O_CREAT | SEM_O_EXCL) != 0 || value > SEM_VALUE_MAX as usize {
+        return fail(KernelError::InvalidArgument);
+    }
+    let result = if name_ptr == 0 {
+        SemaphoreObject::new(value as u32)
+    } else {
+        match parse_c_string_from_userspace(task, name_ptr, SEM_NAME_MAX + 1) {
+            Ok(name) => SemaphoreObject::open(&name, value as u32, flags & SEM_O_CREAT != 0, flags & SEM_O_EXCL != 0),
+            Err(_) => Err(KernelError::BadAddress),
+        }
+    };
+    match result {
+        Ok(semaphore) => match task.handle_table.write().insert(KernelObject::from_semaphore(semaphore)) {
+            Ok(h) => h as usize,
+            Err(_) => fail(KernelError::TooManyHandles),
+        },
+        Err(error) => fail(error),
+    }
+}
+
+/// Remove the name of a semaphore
+///
+/// Existing handles keep the semaphore alive until they are closed.
+///
+/// Arguments:
+/// - name_ptr: const char* (C-string) semaphore name
+///
+/// Returns: 0 on success, usize::MAX on error
+pub fn sys_sem_unlink(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let name_ptr = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    match parse_c_string_from_userspace(task, name_ptr, SEM_NAME_MAX + 1) {
+        Ok(name) => unit_result(SemaphoreObject::unlink(&name)),
+        Err(_) => fail(KernelError::BadAddress),
+    }
+}
+
+/// Take one from a semaphore, waiting while it is zero
+///
+/// Arguments:
+/// - handle: handle of the semaphore
+/// - timeout: relative timeout in nanoseconds, 0 to wait forever
+///
+/// Returns: 0 on success, usize::MAX on error, including a timeout
+///
+/// A wait interrupted by a signal is restarted or fails as described in
+/// `crate::task::signal`.
+pub fn sys_sem_wait(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    let timeout = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    let timeout_ticks = (timeout != 0).then(|| ns_to_ticks(timeout as u64));
+    match semaphore_of(handle).and_then(|semaphore| semaphore.wait(timeout_ticks)) {
+        Ok(()) => 0,
+        Err(KernelError::Interrupted) => interrupt_syscall(task, trapframe),
+        Err(error) => fail(error),
+    }
+}
+
+/// Take one from a semaphore if it is not zero
+///
+/// Arguments:
+/// - handle: handle of the semaphore
+///
+/// Returns: 0 on success, usize::MAX on error or if the semaphore is zero
+pub fn sys_sem_try_wait(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    match semaphore_of(handle) {
+        Ok(semaphore) if semaphore.try_wait() => 0,
+        Ok(_) => fail(KernelError::WouldBlock),
+        Err(error) => fail(error),
+    }
+}
+
+/// Add one to a semaphore, waking a task waiting on it
+///
+/// Arguments:
+/// - handle: handle of the semaphore
+///
+/// Returns: 0 on success, usize::MAX on error
+pub fn sys_sem_post(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    unit_result(semaphore_of(handle).and_then(|semaphore| semaphore.post()))
+}
+
+/// Get the value of a semaphore
+///
+/// Arguments:
+/// - handle: handle of the semaphore
+///
+/// Returns: the value on success, usize::MAX on error
+pub fn sys_sem_get_value(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    match semaphore_of(handle) {
+        Ok(semaphore) => semaphore.value() as usize,
+        Err(error) => fail(error),
+    }
+}
+
+// === Message bus ===
+
+/// Size of the information sys_bus_receive stores about a message: the
+/// kind (u32), flags (u32, BUS_TRUNCATED), the serial (u64), the sender
+/// (u64) and the name as a C-string of BUS_NAME_MAX + 1 bytes
+pub const BUS_MESSAGE_INFO_SIZE: usize = 24 + BUS_NAME_MAX + 1;
+/// The data of the message was longer than the buffer (info flag)
+pub const BUS_TRUNCATED: u32 = 0x1;
+
+/// The bus connection of `handle` in the current task
+fn bus_of(handle: usize) -> Result<Arc<BusConnection>, KernelError> {
+    match mytask().and_then(|task| task.handle_table.read().get(handle as u32).cloned()) {
+        Some(KernelObject::Bus(bus)) => Ok(bus),
+        Some(_) => Err(KernelError::InvalidArgument),
+        None => Err(KernelError::BadHandle),
+    }
+}
+
+fn bus_name(task: &Task, name_ptr: usize) -> Result<String, KernelError> {
+    parse_c_string_from_userspace(task, name_ptr, BUS_NAME_MAX + 1).map_err(|_| KernelError::BadAddress)
+}
+
+/// The data at `buf` and the objects of the handles at `handles_ptr`, for
+/// a message
+fn bus_message(task: &Task, buf: usize, len: usize, handles_ptr: usize, handle_count: usize) -> Result<(Vec<u8>, Vec<KernelObject>), KernelError> {
+    if len > BUS_MESSAGE_MAX {
+        return Err(KernelError::MessageTooLong);
+    }
+    if handle_count > BUS_MAX_OBJECTS {
+        return Err(KernelError::InvalidArgument);
+    }
+    let mut data = vec![0u8; len];
+    copy_from_user(task, buf, &mut data).map_err(|_| KernelError::BadAddress)?;
+    Ok((data, objects_of_handles(task, handles_ptr, handle_count)?))
+}
+
+/// Connect to the message bus (see `crate::ipc::bus`)
+///
+/// The connection can be waited on with sys_handle_poll or an epoll
+/// object; it is readable while a message is queued.
+///
+/// Arguments:
+/// - flags: BUS_NONBLOCK
+///
+/// Returns: handle of the connection on success, usize::MAX on error
+pub fn sys_bus_connect(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let flags = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    if flags & !BUS_NONBLOCK != 0 {
+        return fail(KernelError::InvalidArgument);
+    }
+    let bus = BusConnection::new(flags & BUS_NONBLOCK != 0);
+    match task.handle_table.write().insert(KernelObject::from_bus(bus)) {
+        Ok(h) => h as usize,
+        Err(_) => fail(KernelError::TooManyHandles),
+    }
+}
+
+/// Receive what is published on a channel
+///
+/// Arguments:
+/// - handle: handle of the connection
+/// - name_ptr: const char* (C-string) name of the channel
+///
+/// Returns: 0 on success, usize::MAX on error
+pub fn sys_bus_subscribe(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    let name_ptr = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    unit_result(bus_of(handle).and_then(|bus| bus.subscribe(&bus_name(task, name_ptr)?)))
+}
+
+/// Stop receiving what is published on a channel
+///
+/// Arguments:
+/// - handle: handle of the connection
+/// - name_ptr: const char* (C-string) name of the channel
+///
+/// Returns: 0 on success, usize::MAX on error
+pub fn sys_bus_unsubscribe(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    let name_ptr = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    unit_result(bus_of(handle).and_then(|bus| bus.unsubscribe(&bus_name(task, name_ptr)?)))
+}
+
+/// Take a service name, to which requests are sent
+///
+/// The name is freed when the connection is closed.
+///
+/// Arguments:
+/// - handle: handle of the connection
+/// - name_ptr: const char* (C-string) name of the service
+///
+/// Returns: 0 on success, usize::MAX on error
+pub fn sys_bus_register(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    let name_ptr = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    unit_result(bus_of(handle).and_then(|bus| bus.register(&bus_name(task, name_ptr)?)))
+}
+
+/// Publish data and handles on a channel
+///
+/// The handles stay open in the sender; each subscriber gets the objects.
+///
+/// Arguments:
+/// - handle: handle of the connection
+/// - name_ptr: const char* (C-string) name of the channel
+/// - buf: data of the message, at most BUS_MESSAGE_MAX bytes
+/// - len: length of the data
+/// - handles_ptr: array of u32 handles to send with the data
+/// - handle_count: number of handles, at most BUS_MAX_OBJECTS
+///
+/// Returns: the number of subscribers the message was queued for,
+/// usize::MAX on error
+pub fn sys_bus_publish(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    let name_ptr = trapframe.get_arg(1);
+    let buf = trapframe.get_arg(2);
+    let len = trapframe.get_arg(3);
+    let handles_ptr = trapframe.get_arg(4);
+    let handle_count = trapframe.get_arg(5);
+    trapframe.increment_pc_next(task);
+
+    let publish = || -> Result<usize, KernelError> {
+        let bus = bus_of(handle)?;
+        let name = bus_name(task, name_ptr)?;
+        let (data, objects) = bus_message(task, buf, len, handles_ptr, handle_count)?;
+        bus.publish(&name, &data, objects)
+    };
+    match publish() {
+        Ok(delivered) => delivered,
+        Err(error) => fail(error),
+    }
+}
+
+/// Send a request of data and handles to a service
+///
+/// Arguments:
+/// - handle: handle of the connection
+/// - name_ptr: const char* (C-string) name of the service
+/// - buf, len, handles_ptr, handle_count: the message, as for
+///   sys_bus_publish
+///
+/// Returns: the serial of the request on success, usize::MAX on error
+pub fn sys_bus_request(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    let name_ptr = trapframe.get_arg(1);
+    let buf = trapframe.get_arg(2);
+    let len = trapframe.get_arg(3);
+    let handles_ptr = trapframe.get_arg(4);
+    let handle_count = trapframe.get_arg(5);
+    trapframe.increment_pc_next(task);
+
+    let request = || -> Result<u64, KernelError> {
+        let bus = bus_of(handle)?;
+        let name = bus_name(task, name_ptr)?;
+        let (data, objects) = bus_message(task, buf, len, handles_ptr, handle_count)?;
+        bus.request(&name, &data, objects)
+    };
+    match request() {
+        Ok(serial) => serial as usize,
+        Err(error) => fail(error),
+    }
+}
+
+/// Answer a request received by a service of the connection
+///
+/// Arguments:
+/// - handle: handle of the connection
+/// - serial: serial of the request
+/// - buf, len, handles_ptr, handle_count: the message, as for
+///   sys_bus_publish
+///
+/// Returns: 0 on success, usize::MAX on error
+pub fn sys_bus_reply(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    let serial = trapframe.get_arg(1);
+    let buf = trapframe.get_arg(2);
+    let len = trapframe.get_arg(3);
+    let handles_ptr = trapframe.get_arg(4);
+    let handle_count = trapframe.get_arg(5);
+    trapframe.increment_pc_next(task);
+
+    unit_result(bus_of(handle).and_then(|bus| {
+        let (data, objects) = bus_message(task, buf, len, handles_ptr, handle_count)?;
+        bus.reply(serial as u64, &data, objects)
+    }))
+}
+
+/// Receive the next message of a connection
+///
+/// Received objects get new handles; those that do not fit the array are
+/// closed. The part of the data that does not fit the buffer is lost.
+///
+/// Arguments:
+/// - handle: handle of the connection
+/// - buf: buffer receiving the data
+/// - len: length of the buffer
+/// - handles_ptr: array of u32 receiving the new handles
+/// - handle_count_ptr: u32 holding the length of the array, replaced by the
+///   number of handles received; 0 to receive no handles
+/// - info_ptr: buffer of BUS_MESSAGE_INFO_SIZE bytes receiving what the
+///   message is, 0 if not wanted
+///
+/// Returns: the number of bytes received, usize::MAX on error
+///
+/// A wait interrupted by a signal is restarted or fails as described in
+/// `crate::task::signal`.
+pub fn sys_bus_receive(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    let buf = trapframe.get_arg(1);
+    let len = trapframe.get_arg(2);
+    let handles_ptr = trapframe.get_arg(3);
+    let handle_count_ptr = trapframe.get_arg(4);
+    let info_ptr = trapframe.get_arg(5);
+    trapframe.increment_pc_next(task);
+
+    let mut receive = || -> Result<usize, KernelError> {
+        let bus = bus_of(handle)?;
+        let capacity = handle_capacity(task, handle_count_ptr)?;
+        let mut data = vec![0u8; len.min(BUS_MESSAGE_MAX)];
+        let received = bus.receive(&mut data)?;
+        copy_to_user(task, buf, &data[..received.len]).map_err(|_| KernelError::BadAddress)?;
+        if info_ptr != 0 {
+            let mut info = Vec::with_capacity(BUS_MESSAGE_INFO_SIZE);
+            let flags = if received.truncated { BUS_TRUNCATED } else { 0 };
+            info.extend_from_slice(&(received.kind as u32).to_le_bytes());
+            info.extend_from_slice(&flags.to_le_bytes());
+            info.extend_from_slice(&received.serial.to_le_bytes());
+            info.extend_from_slice(&received.sender.to_le_bytes());
+            info.extend_from_slice(received.name.as_bytes());
+            info.push(0);
+            copy_to_user(task, info_ptr, &info).map_err(|_| KernelError::BadAddress)?;
+        }
+        install_objects(task, received.objects, capacity, handles_ptr, handle_count_ptr)?;
+        Ok(received.len)
+    };
+    match receive() {
+        Ok(received) => received,
+        Err(KernelError::Interrupted) => interrupt_syscall(task, trapframe),
+        Err(error) => fail(error),
+    }
+}
+
+/// Receive the reply to a request of the connection, waiting for it
+///
+/// Other messages stay queued. The reply is received as by
+/// sys_bus_receive.
+///
+/// Arguments:
+/// - handle: handle of the connection
+/// - serial: serial of the request
+/// - buf, len, handles_ptr, handle_count_ptr: as for sys_bus_receive
+///
+/// Returns: the number of bytes received, usize::MAX on error (also if the
+/// service went away without a reply)
+///
+/// A wait interrupted by a signal is restarted or fails as described in
+/// `crate::task::signal`.
+pub fn sys_bus_wait_reply(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    let serial = trapframe.get_arg(1);
+    let buf = trapframe.get_arg(2);
+    let len = trapframe.get_arg(3);
+    let handles_ptr = trapframe.get_arg(4);
+    let handle_count_ptr = trapframe.get_arg(5);
+    trapframe.increment_pc_next(task);
+
+    let mut wait_reply = || -> Result<usize, KernelError> {
+        let bus = bus_of(handle)?;
+        let capacity = handle_capacity(task, handle_count_ptr)?;
+        let mut data = vec![0u8; len.min(BUS_MESSAGE_MAX)];
+        let received = bus.wait_reply(serial as u64, &mut data)?;
+        copy_to_user(task, buf, &data[..received.len]).map_err(|_| KernelError::BadAddress)?;
+        install_objects(task, received.objects, capacity, handles_ptr, handle_count_ptr)?;
+        Ok(received.len)
+    };
+    match wait_reply() {
+        Ok(received) => received,
+        Err(KernelError::Interrupted) => interrupt_syscall(task, trapframe),
+        Err(error) => fail(error),
+    }
+}
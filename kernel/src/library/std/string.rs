@@ -1,6 +1,9 @@
 use alloc::string::String;
 use alloc::vec::Vec;
 
+use crate::environment::PAGE_SIZE;
+use crate::task::signal::copy_from_user;
+
 #[derive(Debug, PartialEq)]
 pub enum StringConversionError {
     NullPointer,
@@ -36,6 +39,9 @@ pub fn cstring_to_string(cstr_ptr: *const u8, max_len: usize) -> Result<(String,
 }
 
 /// Parse a null-terminated C string from user space using task's VM manager
+///
+/// The string is copied page by page, so it may cross into a page backed by
+/// another frame. At most `max_len` bytes are read.
 pub fn parse_c_string_from_userspace(
     task: &crate::task::Task, 
     ptr: usize, 
@@ -44,12 +50,24 @@ pub fn parse_c_string_from_userspace(
     if ptr == 0 {
         return Err(StringConversionError::NullPointer);
     }
-    
-    let c_str_ptr = task.vm_manager.translate_vaddr(ptr)
-        .ok_or(StringConversionError::TranslationError)? as *const u8;
-    
-    let (string, _) = cstring_to_string(c_str_ptr, max_len)?;
-    Ok(string)
+
+    let mut bytes = Vec::new();
+    let mut addr = ptr;
+    while bytes.len() < max_len {
+        // Never read past the page: the next one may not be mapped
+        let len = (PAGE_SIZE - addr % PAGE_SIZE).min(max_len - bytes.len());
+        let start = bytes.len();
+        bytes.resize(start + len, 0);
+        copy_from_user(task, addr, &mut bytes[start..])
+            .map_err(|_| StringConversionError::TranslationError)?;
+        if let Some(end) = bytes[start..].iter().position(|&b| b == 0) {
+            bytes.truncate(start + end);
+            break;
+        }
+        addr += len;
+    }
+
+    String::from_utf8(bytes).map_err(|_| StringConversionError::Utf8Error)
 }
 
 /// Parse an array of string pointers (char **) from user space
@@ -63,26 +81,24 @@ pub fn parse_string_array_from_userspace(
         return Ok(Vec::new());
     }
     
-    let ptr_array = task.vm_manager.translate_vaddr(array_ptr)
-        .ok_or(StringConversionError::TranslationError)? as *const usize;
-    
     let mut strings = Vec::new();
     let mut i = 0;
     
-    unsafe {
-        loop {
-            let str_ptr = *ptr_array.add(i);
-            if str_ptr == 0 {
-                break; // Null pointer terminates the array
-            }
-            
-            let string = parse_c_string_from_userspace(task, str_ptr, max_string_len)?;
-            strings.push(string);
-            i += 1;
-            
-            if i > max_strings {
-                return Err(StringConversionError::TooManyStrings);
-            }
+    loop {
+        let mut word = [0u8; core::mem::size_of::<usize>()];
+        copy_from_user(task, array_ptr + i * word.len(), &mut word)
+            .map_err(|_| StringConversionError::TranslationError)?;
+        let str_ptr = usize::from_le_bytes(word);
+        if str_ptr == 0 {
+            break; // Null pointer terminates the array
+        }
+        
+        let string = parse_c_string_from_userspace(task, str_ptr, max_string_len)?;
+        strings.push(string);
+        i += 1;
+        
+        if i > max_strings {
+            return Err(StringConversionError::TooManyStrings);
         }
     }
     
@@ -272,8 +272,11 @@ pub mod device;
 pub mod fs;
 pub mod object;
 pub mod ipc;
+pub mod network;
 pub mod executor;
 pub mod profiler;
+pub mod random;
+pub mod audit;
 
 #[cfg(test)]
 pub mod test;
@@ -518,7 +521,7 @@ pub extern "C" fn start_kernel(boot_info: &BootInfo) -> ! {
     init_heap(MemoryArea::new(heap_start, heap_end));
 
     fence(Ordering::SeqCst);
-    early_println!("[Scarlet Kernel] Heap initialized at {:#x} - {:#x}", heap_start, heap_end);
+    early_println!("[Scarlet Kernel] Heap and page allocator initialized at {:#x} - {:#x}", heap_start, heap_end);
     
     {
         let test_vec = alloc::vec::Vec::<u8>::with_capacity(1024);
@@ -529,6 +532,10 @@ pub extern "C" fn start_kernel(boot_info: &BootInfo) -> ! {
     fence(Ordering::Release);
 
     /* After this point, we can use the heap */
+    crate::vm::aslr::parse_cmdline(boot_info.get_cmdline());
+    crate::vm::wx::parse_cmdline(boot_info.get_cmdline());
+    crate::network::dhcp::parse_cmdline(boot_info.get_cmdline());
+    crate::network::ndp::parse_cmdline(boot_info.get_cmdline());
     early_initcall_call();
     fence(Ordering::SeqCst); // Ensure early initcalls are completed before proceeding
     driver_initcall_call();
@@ -627,7 +634,10 @@ pub extern "C" fn start_kernel(boot_info: &BootInfo) -> ! {
 
     match load_elf_into_task(file_ref, &mut task) {
         Ok(_) => {
-            for map in task.vm_manager.memmap_iter() {
+            if let Err(e) = task.setup_tls() {
+                early_println!("[Scarlet Kernel] Failed to set up TLS for init: {}", e);
+            }
+            for map in task.vm_manager.read().memmap_iter() {
                 early_println!("[Scarlet Kernel] Task memory map: {:#x} - {:#x}", map.vmarea.start, map.vmarea.end);
             }
             early_println!("[Scarlet Kernel] Successfully loaded init ELF into task");
@@ -638,14 +648,25 @@ pub extern "C" fn start_kernel(boot_info: &BootInfo) -> ! {
 
     fence(Ordering::SeqCst); // Ensure task is added to scheduler before proceeding
 
+    /* Bring up the application processors */
+    println!("[Scarlet Kernel] Starting application processors...");
+    arch::boot::start_secondary_harts(cpu_id);
+
     println!("[Scarlet Kernel] Scheduler will start...");
     scheduler.start_scheduler();
     loop {} 
 }
 
+/// Application processor entry point
+///
+/// Called on each secondary CPU once the bootstrap processor has finished
+/// initializing the kernel. The CPU switches to the kernel address space and
+/// joins the scheduler, starting with its own idle task.
 #[unsafe(no_mangle)]
 pub extern "C" fn start_ap(cpu_id: usize) {
+    vm::switch_to_kernel_vm();
     println!("[Scarlet Kernel] CPU {} is up and running", cpu_id);
 
+    get_scheduler().start_scheduler();
     loop {}
 }
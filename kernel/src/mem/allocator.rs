@@ -3,9 +3,20 @@ use core::sync::atomic::{AtomicUsize, Ordering};
 
 use slab_allocator_rs::LockedHeap;
 
+use slab_allocator_rs::MIN_HEAP_SIZE;
+
+use super::buddy;
+use super::kasan;
+use super::kmalloc_stats;
+use super::magazine;
 use crate::early_println;
+use crate::environment::PAGE_SIZE;
 use crate::vm::vmem::MemoryArea;
 
+/// Share of usable memory given to the slab heap (1/N); the rest is managed
+/// by the buddy page allocator
+const HEAP_SHARE_DIVISOR: usize = 8;
+
 #[global_allocator]
 static mut ALLOCATOR: Allocator = Allocator::new();
 
@@ -14,13 +25,31 @@ struct Allocator {
   inner: Option<LockedHeap>,
   allocated_count: AtomicUsize,
   allocated_bytes: AtomicUsize,
+  heap_size: AtomicUsize,
 }
 
 unsafe impl GlobalAlloc for Allocator {
     unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
         if let Some(ref inner) = self.inner {
             // early_println!("Allocating {} bytes with alignment {}", layout.size(), layout.align());
-            let ptr = unsafe { inner.alloc(layout) };
+            let alloc_layout = kasan::padded_layout(layout);
+            let mut ptr = unsafe { self.alloc_from(inner, alloc_layout) };
+            // Empty the per-CPU magazines and drop clean cache pages until
+            // the allocation succeeds or neither frees anything
+            while ptr.is_null()
+                && (magazine::drain_all(inner) > 0
+                    || super::reclaim::try_to_free_pages(pages_for_layout(&alloc_layout)) > 0)
+            {
+                ptr = unsafe { self.alloc_from(inner, alloc_layout) };
+            }
+            if ptr.is_null() {
+                // Kill a task to make room for later allocations
+                super::oom::out_of_memory(alloc_layout);
+                kmalloc_stats::record_failure(&layout);
+                return ptr;
+            }
+            kasan::on_alloc(ptr, &layout, &alloc_layout);
+            kmalloc_stats::record_alloc(ptr, &layout);
             // early_println!("Allocated {} bytes at {:?}", layout.size(), ptr);
             self.allocated_count.fetch_add(1, Ordering::SeqCst);
             self.allocated_bytes.fetch_add(layout.size(), Ordering::SeqCst);
@@ -33,7 +62,16 @@ unsafe impl GlobalAlloc for Allocator {
 
     unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
         if let Some(ref inner) = self.inner {
-            unsafe { inner.dealloc(ptr, layout) }
+            // With KASAN the object may be held in quarantine and an older
+            // one released in its place
+            if let Some((block, block_layout)) = kasan::release(ptr, &layout, &kasan::padded_layout(layout)) {
+                if buddy::is_page_allocator_address(block as usize) {
+                    buddy::free_pages(block as usize, pages_for_layout(&block_layout));
+                } else if !magazine::free(inner, block, &block_layout) {
+                    unsafe { inner.dealloc(block, block_layout) }
+                }
+            }
+            kmalloc_stats::record_free(ptr, &layout);
             // early_println!("Deallocated {} bytes at {:?}", layout.size(), ptr);
             self.allocated_count.fetch_sub(1, Ordering::SeqCst);
             self.allocated_bytes.fetch_sub(layout.size(), Ordering::SeqCst);
@@ -44,9 +82,32 @@ unsafe impl GlobalAlloc for Allocator {
     }
 }
 
+/// Number of pages the page allocator hands out for `layout`
+///
+/// Alignments above a page are met by allocating at least as many pages as
+/// the alignment spans, since buddy blocks are naturally aligned.
+fn pages_for_layout(layout: &core::alloc::Layout) -> usize {
+    layout.size().div_ceil(PAGE_SIZE).max(layout.align() / PAGE_SIZE)
+}
+
 impl Allocator {
+    /// Allocate page-sized and larger blocks from the page allocator and
+    /// everything else (or anything the page allocator cannot satisfy) from
+    /// the slab heap, going through the current CPU's magazine first
+    unsafe fn alloc_from(&self, inner: &LockedHeap, layout: core::alloc::Layout) -> *mut u8 {
+        if layout.size() >= PAGE_SIZE && buddy::is_initialized() {
+            if let Some(addr) = buddy::alloc_pages(pages_for_layout(&layout)) {
+                return addr as *mut u8;
+            }
+        }
+        if let Some(ptr) = magazine::alloc(inner, &layout) {
+            return ptr;
+        }
+        unsafe { inner.alloc(layout) }
+    }
+
     pub const fn new() -> Self {
-        Allocator { inner: None, allocated_count: AtomicUsize::new(0), allocated_bytes: AtomicUsize::new(0) }
+        Allocator { inner: None, allocated_count: AtomicUsize::new(0), allocated_bytes: AtomicUsize::new(0), heap_size: AtomicUsize::new(0) }
     }
 
     pub unsafe fn init(&mut self, start: usize, size: usize) {
@@ -57,9 +118,41 @@ impl Allocator {
 
         let heap = unsafe { LockedHeap::new(start, size) };
         self.inner = Some(heap);
+        self.heap_size.store(size, Ordering::SeqCst);
     }
 }
 
+/// Total size of the kernel heap in bytes (0 before `init_heap`)
+#[allow(static_mut_refs)]
+pub fn heap_size() -> usize {
+    unsafe { ALLOCATOR.heap_size.load(Ordering::SeqCst) }
+}
+
+/// Total memory managed by the kernel allocators (slab heap and page
+/// allocator) in bytes
+pub fn total_memory() -> usize {
+    heap_size() + buddy::page_allocator_stats().total_pages * PAGE_SIZE
+}
+
+/// Rebalance the per-CPU slab magazines (called periodically by the timer)
+#[allow(static_mut_refs)]
+pub fn rebalance_magazines() {
+    if let Some(inner) = unsafe { &ALLOCATOR.inner } {
+        magazine::rebalance(inner);
+    }
+}
+
+/// Number of bytes currently allocated from the kernel heap
+#[allow(static_mut_refs)]
+pub fn allocated_bytes() -> usize {
+    unsafe { ALLOCATOR.allocated_bytes.load(Ordering::SeqCst) }
+}
+
+/// Initialize kernel memory allocation over `area`
+///
+/// The start of the area becomes the slab heap used for small objects; the
+/// remainder is handed to the buddy page allocator, which serves page-sized
+/// and larger allocations.
 #[allow(static_mut_refs)]
 pub fn init_heap(area: MemoryArea) {
     let size = area.size();
@@ -68,9 +161,14 @@ pub fn init_heap(area: MemoryArea) {
         return;
     }
 
+    let heap_size = ((size / HEAP_SHARE_DIVISOR) / MIN_HEAP_SIZE * MIN_HEAP_SIZE).max(MIN_HEAP_SIZE).min(size);
     unsafe {
-        ALLOCATOR.init(area.start, size);
+        ALLOCATOR.init(area.start, heap_size);
     }
+    early_println!("Heap initialized: {:#x} - {:#x}", area.start, area.start + heap_size - 1);
 
-    early_println!("Heap initialized: {:#x} - {:#x}", area.start, area.end);
+    if heap_size < size {
+        unsafe { buddy::init_page_allocator(area.start + heap_size, area.end + 1) };
+        early_println!("Page allocator initialized: {:#x} - {:#x}", area.start + heap_size, area.end);
+    }
 }
@@ -0,0 +1,384 @@
+//! Buddy allocator for physical pages.
+//!
+//! Usable physical memory that is not given to the kernel heap is managed
+//! here in naturally aligned power-of-two blocks of pages. Page-sized and
+//! larger allocations of the global allocator are served from this
+//! allocator, so `allocate_raw_pages` and friends always return physically
+//! contiguous memory (as needed for DMA buffers and huge pages).
+//!
+//! Any page-aligned range of an allocation may be freed independently (the
+//! kernel frees multi-page allocations page by page, e.g. through
+//! `ManagedPage`); freed ranges are split into aligned blocks and merged
+//! with their buddies.
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::environment::PAGE_SIZE;
+
+/// Number of block orders; the largest block is `2^(MAX_ORDER - 1)` pages (8 MiB)
+pub const MAX_ORDER: usize = 12;
+
+/// Page state flag marking the first page of a free block (low bits: order)
+const FREE_HEAD: u8 = 0x80;
+
+/// Intrusive free list node stored in the first bytes of a free block
+#[repr(C)]
+struct FreeBlock {
+    next: usize,
+    prev: usize,
+}
+
+/// Buddy allocator over a single contiguous range of physical pages
+pub struct BuddyAllocator {
+    /// First page frame number managed by the allocator
+    base_pfn: usize,
+    /// Number of pages managed by the allocator
+    num_pages: usize,
+    /// Head of the free list for each order (0 = empty)
+    free_heads: [usize; MAX_ORDER],
+    /// Number of free blocks for each order
+    free_blocks: [usize; MAX_ORDER],
+    /// Per-page state: `FREE_HEAD | order` for the first page of a free block, 0 otherwise
+    page_state: Vec<u8>,
+    /// Number of free pages
+    free_pages: usize,
+    /// Number of allocation requests that could not be satisfied
+    failed_allocs: usize,
+}
+
+/// Snapshot of the allocator state
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BuddyStats {
+    pub total_pages: usize,
+    pub free_pages: usize,
+    /// Number of free blocks of each order
+    pub free_blocks: [usize; MAX_ORDER],
+    pub failed_allocs: usize,
+}
+
+impl BuddyStats {
+    /// Largest order with a free block, if any
+    pub fn largest_free_order(&self) -> Option<usize> {
+        (0..MAX_ORDER).rev().find(|&order| self.free_blocks[order] > 0)
+    }
+
+    /// Fragmentation index for allocations of `order`, in permille
+    ///
+    /// The share of free memory that sits in blocks too small to satisfy an
+    /// allocation of this order: 0 means all free memory is usable, 1000
+    /// means none of it is.
+    pub fn fragmentation_index(&self, order: usize) -> usize {
+        if self.free_pages == 0 {
+            return 0;
+        }
+        let usable: usize = (order.min(MAX_ORDER)..MAX_ORDER)
+            .map(|o| self.free_blocks[o] << o)
+            .sum();
+        1000 - usable * 1000 / self.free_pages
+    }
+}
+
+/// Smallest order whose block holds `count` pages
+pub fn order_for_pages(count: usize) -> usize {
+    count.max(1).next_power_of_two().trailing_zeros() as usize
+}
+
+impl BuddyAllocator {
+    pub const fn empty() -> Self {
+        Self {
+            base_pfn: 0,
+            num_pages: 0,
+            free_heads: [0; MAX_ORDER],
+            free_blocks: [0; MAX_ORDER],
+            page_state: Vec::new(),
+            free_pages: 0,
+            failed_allocs: 0,
+        }
+    }
+
+    /// Take ownership of the pages in `[start, end)` and mark them free
+    ///
+    /// # Safety
+    /// The range must be page aligned, writable, and not used by anything else.
+    pub unsafe fn init(&mut self, start: usize, end: usize) {
+        self.base_pfn = start / PAGE_SIZE;
+        self.num_pages = (end - start) / PAGE_SIZE;
+        self.page_state = vec![0; self.num_pages];
+        self.free_range(start, self.num_pages);
+    }
+
+    /// Whether `addr` lies in the managed range
+    pub fn contains(&self, addr: usize) -> bool {
+        let pfn = addr / PAGE_SIZE;
+        pfn >= self.base_pfn && pfn < self.base_pfn + self.num_pages
+    }
+
+    fn index(&self, addr: usize) -> usize {
+        addr / PAGE_SIZE - self.base_pfn
+    }
+
+    fn push(&mut self, addr: usize, order: usize) {
+        let head = self.free_heads[order];
+        unsafe {
+            (addr as *mut FreeBlock).write(FreeBlock { next: head, prev: 0 });
+            if head != 0 {
+                (*(head as *mut FreeBlock)).prev = addr;
+            }
+        }
+        self.free_heads[order] = addr;
+        self.free_blocks[order] += 1;
+        let index = self.index(addr);
+        self.page_state[index] = FREE_HEAD | order as u8;
+    }
+
+    fn remove(&mut self, addr: usize, order: usize) {
+        let block = unsafe { (addr as *const FreeBlock).read() };
+        if block.prev != 0 {
+            unsafe { (*(block.prev as *mut FreeBlock)).next = block.next };
+        } else {
+            self.free_heads[order] = block.next;
+        }
+        if block.next != 0 {
+            unsafe { (*(block.next as *mut FreeBlock)).prev = block.prev };
+        }
+        self.free_blocks[order] -= 1;
+        let index = self.index(addr);
+        self.page_state[index] = 0;
+    }
+
+    /// Allocate a naturally aligned block of `2^order` pages
+    ///
+    /// # Returns
+    /// The physical address of the block
+    pub fn alloc_order(&mut self, order: usize) -> Option<usize> {
+        let Some(mut found) = (order..MAX_ORDER).find(|&o| self.free_heads[o] != 0) else {
+            self.failed_allocs += 1;
+            return None;
+        };
+        let addr = self.free_heads[found];
+        self.remove(addr, found);
+        // Split down to the requested order, returning the upper halves
+        while found > order {
+            found -= 1;
+            self.push(addr + (PAGE_SIZE << found), found);
+        }
+        self.free_pages -= 1 << order;
+        Some(addr)
+    }
+
+    /// Allocate `count` physically contiguous pages
+    ///
+    /// The pages beyond `count` in the underlying power-of-two block are
+    /// returned to the allocator immediately.
+    pub fn alloc_pages(&mut self, count: usize) -> Option<usize> {
+        let order = order_for_pages(count);
+        if order >= MAX_ORDER {
+            self.failed_allocs += 1;
+            return None;
+        }
+        let addr = self.alloc_order(order)?;
+        let tail = (1 << order) - count.max(1);
+        if tail > 0 {
+            self.free_range(addr + count.max(1) * PAGE_SIZE, tail);
+        }
+        Some(addr)
+    }
+
+    /// Free `count` pages starting at `addr`
+    pub fn free_range(&mut self, mut addr: usize, mut count: usize) {
+        while count > 0 {
+            // Largest naturally aligned block that starts at addr and fits
+            let pfn = addr / PAGE_SIZE;
+            let mut order = (pfn.trailing_zeros() as usize).min(MAX_ORDER - 1);
+            while (1 << order) > count {
+                order -= 1;
+            }
+            self.free_block(addr, order);
+            addr += PAGE_SIZE << order;
+            count -= 1 << order;
+        }
+    }
+
+    fn free_block(&mut self, mut addr: usize, mut order: usize) {
+        self.free_pages += 1 << order;
+        while order + 1 < MAX_ORDER {
+            let buddy = addr ^ (PAGE_SIZE << order);
+            if !self.contains(buddy) || self.page_state[self.index(buddy)] != FREE_HEAD | order as u8 {
+                break;
+            }
+            self.remove(buddy, order);
+            addr = addr.min(buddy);
+            order += 1;
+        }
+        self.push(addr, order);
+    }
+
+    pub fn stats(&self) -> BuddyStats {
+        BuddyStats {
+            total_pages: self.num_pages,
+            free_pages: self.free_pages,
+            free_blocks: self.free_blocks,
+            failed_allocs: self.failed_allocs,
+        }
+    }
+}
+
+static PAGE_ALLOCATOR: Mutex<BuddyAllocator> = Mutex::new(BuddyAllocator::empty());
+
+/// Managed physical range, readable without taking the allocator lock
+static ZONE_START: AtomicUsize = AtomicUsize::new(0);
+static ZONE_END: AtomicUsize = AtomicUsize::new(0);
+
+/// Hand the physical pages in `[start, end)` to the page allocator
+///
+/// # Safety
+/// The range must not be used by anything else. Must be called once, after
+/// the kernel heap is available (the page metadata is allocated from it).
+pub unsafe fn init_page_allocator(start: usize, end: usize) {
+    let start = (start + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+    let end = end & !(PAGE_SIZE - 1);
+    if end <= start {
+        return;
+    }
+    // Build the allocator (including the metadata allocation) before taking the lock
+    let mut allocator = BuddyAllocator::empty();
+    unsafe { allocator.init(start, end) };
+    *PAGE_ALLOCATOR.lock() = allocator;
+    ZONE_START.store(start, Ordering::Release);
+    ZONE_END.store(end, Ordering::Release);
+}
+
+/// Whether the page allocator has been initialized
+pub fn is_initialized() -> bool {
+    ZONE_END.load(Ordering::Acquire) != 0
+}
+
+/// Whether `addr` belongs to memory managed by the page allocator
+pub fn is_page_allocator_address(addr: usize) -> bool {
+    addr >= ZONE_START.load(Ordering::Acquire) && addr < ZONE_END.load(Ordering::Acquire)
+}
+
+/// Allocate `count` physically contiguous pages
+///
+/// # Returns
+/// The physical address of the first page, or `None` if no large enough
+/// block is free
+pub fn alloc_pages(count: usize) -> Option<usize> {
+    PAGE_ALLOCATOR.lock().alloc_pages(count)
+}
+
+/// Free `count` pages starting at `addr`
+pub fn free_pages(addr: usize, count: usize) {
+    PAGE_ALLOCATOR.lock().free_range(addr, count);
+}
+
+/// Snapshot of the page allocator statistics
+pub fn page_allocator_stats() -> BuddyStats {
+    PAGE_ALLOCATOR.lock().stats()
+}
+
+/// Render the page allocator state as text
+///
+/// The first line lists the number of free blocks per order (like Linux's
+/// buddyinfo), followed by totals and the fragmentation index per order.
+pub fn format_page_allocator_stats() -> String {
+    let stats = page_allocator_stats();
+    let mut out = String::new();
+    let _ = write!(out, "free_blocks:");
+    for count in stats.free_blocks.iter() {
+        let _ = write!(out, " {}", count);
+    }
+    let _ = writeln!(out);
+    let _ = writeln!(
+        out,
+        "total_pages={} free_pages={} largest_free_order={} failed_allocs={}",
+        stats.total_pages, stats.free_pages,
+        stats.largest_free_order().map_or(-1, |order| order as isize),
+        stats.failed_allocs,
+    );
+    let _ = write!(out, "fragmentation_index:");
+    for order in 0..MAX_ORDER {
+        let _ = write!(out, " {}", stats.fragmentation_index(order));
+    }
+    let _ = writeln!(out);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::page::{allocate_raw_pages, free_raw_pages};
+
+    const TEST_PAGES: usize = 64;
+
+    /// Build an allocator over a private, 32-page aligned region of 32 pages
+    fn with_allocator(test: impl FnOnce(&mut BuddyAllocator, usize)) {
+        let raw = allocate_raw_pages(TEST_PAGES);
+        let start = ((raw as usize) + (PAGE_SIZE << 5) - 1) & !((PAGE_SIZE << 5) - 1);
+        let mut allocator = BuddyAllocator::empty();
+        unsafe { allocator.init(start, start + 32 * PAGE_SIZE) };
+        test(&mut allocator, start);
+        free_raw_pages(raw, TEST_PAGES);
+    }
+
+    #[test_case]
+    fn test_buddy_split_and_merge() {
+        with_allocator(|allocator, start| {
+            assert_eq!(allocator.stats().free_pages, 32);
+            assert_eq!(allocator.stats().free_blocks[5], 1);
+
+            let page = allocator.alloc_order(0).unwrap();
+            assert_eq!(page, start);
+            assert_eq!(allocator.stats().free_pages, 31);
+            // Splitting leaves one free block of every lower order
+            assert!((0..5).all(|order| allocator.stats().free_blocks[order] == 1));
+
+            allocator.free_range(page, 1);
+            assert_eq!(allocator.stats().free_blocks[5], 1);
+            assert_eq!(allocator.stats().free_blocks[0], 0);
+        });
+    }
+
+    #[test_case]
+    fn test_buddy_alloc_pages_returns_tail() {
+        with_allocator(|allocator, _start| {
+            let addr = allocator.alloc_pages(3).unwrap();
+            assert_eq!(allocator.stats().free_pages, 29);
+
+            // Pages of a multi-page allocation can be freed one by one
+            for i in 0..3 {
+                allocator.free_range(addr + i * PAGE_SIZE, 1);
+            }
+            let stats = allocator.stats();
+            assert_eq!(stats.free_pages, 32);
+            assert_eq!(stats.free_blocks[5], 1);
+        });
+    }
+
+    #[test_case]
+    fn test_buddy_exhaustion_and_fragmentation() {
+        with_allocator(|allocator, _start| {
+            assert!(allocator.alloc_pages(64).is_none());
+            assert_eq!(allocator.stats().failed_allocs, 1);
+
+            let pages: Vec<usize> = (0..32).map(|_| allocator.alloc_order(0).unwrap()).collect();
+            assert!(allocator.alloc_order(0).is_none());
+
+            // Free every other page: plenty of memory, but no two-page block
+            for page in pages.iter().step_by(2) {
+                allocator.free_range(*page, 1);
+            }
+            let stats = allocator.stats();
+            assert_eq!(stats.free_pages, 16);
+            assert_eq!(stats.largest_free_order(), Some(0));
+            assert_eq!(stats.fragmentation_index(0), 0);
+            assert_eq!(stats.fragmentation_index(1), 1000);
+        });
+    }
+}
@@ -0,0 +1,218 @@
+//! KASAN-lite: a lightweight kernel address sanitizer
+//!
+//! Enabled with the `kasan` cargo feature (`cargo make test` runs the test
+//! suite a second time with it). Without the feature every function here is an
+//! empty inline stub, so the hooks cost nothing in normal builds.
+//!
+//! There is no shadow memory and no compiler instrumentation. Instead, for
+//! slab-sized allocations:
+//!
+//! - every object gets a redzone filled with [`REDZONE_BYTE`] behind it;
+//!   the redzone is verified when the object is freed, which catches
+//!   writes past the end of the object
+//! - freed objects are filled with [`FREED_BYTE`] and parked in a
+//!   quarantine instead of going back to the slab right away; when an
+//!   object leaves the quarantine its poison is verified, which catches
+//!   writes after free, and freeing a quarantined object again is reported
+//!   as a double free
+//! - code that handles raw pointers (drivers, filesystems) can call
+//!   [`check_read`] / [`check_write`] before an access, which catches reads
+//!   and writes of quarantined objects at the point of access
+//!
+//! Every detected bug panics with a report naming the kind of bug, the
+//! address and the object it hit.
+
+/// Byte written into the redzone behind each object
+pub const REDZONE_BYTE: u8 = 0xfc;
+
+/// Byte written over freed objects
+pub const FREED_BYTE: u8 = 0xfb;
+
+/// Size of the redzone behind each object
+pub const REDZONE_SIZE: usize = 32;
+
+/// Number of freed objects held back before they are reused
+pub const QUARANTINE_SLOTS: usize = 1024;
+
+#[cfg(feature = "kasan")]
+mod imp {
+    use core::alloc::Layout;
+
+    use spin::Mutex;
+
+    use super::*;
+    use crate::environment::PAGE_SIZE;
+
+    /// A freed object waiting in the quarantine
+    #[derive(Clone, Copy)]
+    struct QuarantinedObject {
+        addr: usize,
+        /// Size of the object as requested by its owner
+        size: usize,
+        /// Layout the block was allocated with (object and redzone)
+        layout: Layout,
+    }
+
+    /// FIFO of freed objects
+    struct Quarantine {
+        slots: [Option<QuarantinedObject>; QUARANTINE_SLOTS],
+        next: usize,
+    }
+
+    static QUARANTINE: Mutex<Quarantine> = Mutex::new(Quarantine {
+        slots: [None; QUARANTINE_SLOTS],
+        next: 0,
+    });
+
+    impl Quarantine {
+        fn find(&self, addr: usize, len: usize) -> Option<QuarantinedObject> {
+            let end = addr.saturating_add(len.max(1));
+            self.slots.iter().flatten()
+                .find(|object| addr < object.addr + object.size.max(1) && object.addr < end)
+                .copied()
+        }
+
+        /// Add an object, returning the one it displaced
+        fn push(&mut self, object: QuarantinedObject) -> Option<QuarantinedObject> {
+            let evicted = self.slots[self.next].replace(object);
+            self.next = (self.next + 1) % QUARANTINE_SLOTS;
+            evicted
+        }
+    }
+
+    fn report(kind: &str, addr: usize, object_addr: usize, object_size: usize) -> ! {
+        panic!(
+            "KASAN: {} at {:#x} (object {:#x}, size {}, offset {})",
+            kind, addr, object_addr, object_size, addr.wrapping_sub(object_addr),
+        );
+    }
+
+    fn instrumented_size(layout: &Layout) -> Option<usize> {
+        let padded = layout.size().div_ceil(8) * 8 + REDZONE_SIZE;
+        (padded < PAGE_SIZE && layout.align() < PAGE_SIZE).then_some(padded)
+    }
+
+    pub fn padded_layout(layout: Layout) -> Layout {
+        match instrumented_size(&layout) {
+            Some(padded) => Layout::from_size_align(padded, layout.align()).unwrap_or(layout),
+            None => layout,
+        }
+    }
+
+    pub fn on_alloc(ptr: *mut u8, layout: &Layout, alloc_layout: &Layout) {
+        if alloc_layout.size() == layout.size() {
+            return;
+        }
+        unsafe { core::ptr::write_bytes(ptr.add(layout.size()), REDZONE_BYTE, alloc_layout.size() - layout.size()) };
+    }
+
+    pub fn release(ptr: *mut u8, layout: &Layout, alloc_layout: &Layout) -> Option<(*mut u8, Layout)> {
+        if alloc_layout.size() == layout.size() {
+            return Some((ptr, *alloc_layout));
+        }
+        let addr = ptr as usize;
+        let mut quarantine = QUARANTINE.lock();
+        if let Some(object) = quarantine.find(addr, 1) {
+            drop(quarantine);
+            report("double-free", addr, object.addr, object.size);
+        }
+
+        let redzone = unsafe { core::slice::from_raw_parts(ptr.add(layout.size()), alloc_layout.size() - layout.size()) };
+        if let Some(offset) = redzone.iter().position(|&b| b != REDZONE_BYTE) {
+            drop(quarantine);
+            report("slab-out-of-bounds write", addr + layout.size() + offset, addr, layout.size());
+        }
+
+        unsafe { core::ptr::write_bytes(ptr, FREED_BYTE, layout.size()) };
+        let evicted = quarantine.push(QuarantinedObject { addr, size: layout.size(), layout: *alloc_layout })?;
+        drop(quarantine);
+
+        let object = unsafe { core::slice::from_raw_parts(evicted.addr as *const u8, evicted.size) };
+        if let Some(offset) = object.iter().position(|&b| b != FREED_BYTE) {
+            report("use-after-free write", evicted.addr + offset, evicted.addr, evicted.size);
+        }
+        Some((evicted.addr as *mut u8, evicted.layout))
+    }
+
+    pub fn check_access(addr: usize, len: usize, write: bool) {
+        if let Some(object) = QUARANTINE.lock().find(addr, len) {
+            report(if write { "use-after-free write" } else { "use-after-free read" }, addr.max(object.addr), object.addr, object.size);
+        }
+    }
+
+    pub fn is_quarantined(addr: usize) -> bool {
+        QUARANTINE.lock().find(addr, 1).is_some()
+    }
+}
+
+#[cfg(feature = "kasan")]
+pub use imp::is_quarantined;
+
+/// Layout to allocate for an object of `layout`, including its redzone
+#[inline(always)]
+pub fn padded_layout(layout: core::alloc::Layout) -> core::alloc::Layout {
+    #[cfg(feature = "kasan")]
+    return imp::padded_layout(layout);
+    #[cfg(not(feature = "kasan"))]
+    layout
+}
+
+/// Prepare a freshly allocated block (fill the redzone)
+///
+/// `layout` is what the owner asked for, `alloc_layout` what was allocated
+/// (see [`padded_layout`]).
+#[inline(always)]
+pub fn on_alloc(_ptr: *mut u8, _layout: &core::alloc::Layout, _alloc_layout: &core::alloc::Layout) {
+    #[cfg(feature = "kasan")]
+    imp::on_alloc(_ptr, _layout, _alloc_layout);
+}
+
+/// Take an object that is being freed
+///
+/// # Returns
+/// The block that should actually go back to the heap now: the object
+/// itself when it is not instrumented, the object displaced from the
+/// quarantine, or nothing while the quarantine is filling up
+#[inline(always)]
+pub fn release(ptr: *mut u8, _layout: &core::alloc::Layout, alloc_layout: &core::alloc::Layout) -> Option<(*mut u8, core::alloc::Layout)> {
+    #[cfg(feature = "kasan")]
+    return imp::release(ptr, _layout, alloc_layout);
+    #[cfg(not(feature = "kasan"))]
+    Some((ptr, *alloc_layout))
+}
+
+/// Check that `len` bytes at `addr` may be read
+#[inline(always)]
+pub fn check_read(_addr: usize, _len: usize) {
+    #[cfg(feature = "kasan")]
+    imp::check_access(_addr, _len, false);
+}
+
+/// Check that `len` bytes at `addr` may be written
+#[inline(always)]
+pub fn check_write(_addr: usize, _len: usize) {
+    #[cfg(feature = "kasan")]
+    imp::check_access(_addr, _len, true);
+}
+
+#[cfg(all(test, feature = "kasan"))]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+
+    #[test_case]
+    fn test_kasan_poisons_and_quarantines_freed_objects() {
+        let object = Box::new([0x11u8; 48]);
+        let addr = object.as_ptr() as usize;
+        // The redzone follows the object
+        assert_eq!(unsafe { *((addr + 48) as *const u8) }, REDZONE_BYTE);
+
+        drop(object);
+        assert!(is_quarantined(addr));
+        assert_eq!(unsafe { *(addr as *const u8) }, FREED_BYTE);
+
+        // Live memory passes the checks
+        let live = Box::new(0u64);
+        check_write(&*live as *const u64 as usize, 8);
+    }
+}
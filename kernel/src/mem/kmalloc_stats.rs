@@ -0,0 +1,494 @@
+//! Kernel allocation statistics and allocation-site tracking
+//!
+//! Every allocation served by the global allocator is charged to a size
+//! class (the slab object sizes, plus one class for everything larger).
+//! Each class keeps allocation/free counters and its current and peak live
+//! bytes, so a steadily growing class points at a leak.
+//!
+//! The optional allocation-site tracker goes one step further: while it is
+//! enabled, every allocation records the return address of its caller
+//! (found by walking the frame-pointer chain) and the tracker keeps live
+//! object and byte counts per site. The addresses can be resolved with
+//! `addr2line` against the kernel image. The tracker uses fixed-size tables
+//! only, so it never allocates and can be toggled at runtime. It is only
+//! built in with the `kmalloc-sites` feature, as it needs the kernel to be
+//! compiled with frame pointers (`-C force-frame-pointers=yes`).
+//!
+//! Both reports are served by procfs (`/proc/kmallocinfo` and
+//! `/proc/kmalloc_sites`).
+
+use core::alloc::Layout;
+use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+/// Object sizes of the slab size classes
+pub const SIZE_CLASSES: [usize; 7] = [64, 128, 256, 512, 1024, 2048, 4096];
+
+/// Number of size classes (the slab classes plus one for large allocations)
+pub const NUM_SIZE_CLASSES: usize = SIZE_CLASSES.len() + 1;
+
+/// Number of distinct allocation sites the tracker can hold
+pub const MAX_ALLOC_SITES: usize = 256;
+
+/// Number of live allocations the tracker can attribute to a site
+pub const MAX_TRACKED_ALLOCATIONS: usize = 4096;
+
+/// Default number of frames skipped so that the recorded site lies outside
+/// the allocation plumbing (stats hook, `GlobalAlloc::alloc`, the
+/// `__rust_alloc` shims and `alloc::alloc`)
+const DEFAULT_SITE_SKIP_FRAMES: usize = 4;
+
+/// Upper bound on how far above the current stack pointer a frame may lie
+#[cfg(feature = "kmalloc-sites")]
+const MAX_FRAME_WALK_SPAN: usize = 0x10_0000;
+
+/// Counters for a single size class
+struct SizeClassCounters {
+    allocs: AtomicU64,
+    frees: AtomicU64,
+    failures: AtomicU64,
+    live_bytes: AtomicUsize,
+    peak_bytes: AtomicUsize,
+}
+
+impl SizeClassCounters {
+    const fn new() -> Self {
+        Self {
+            allocs: AtomicU64::new(0),
+            frees: AtomicU64::new(0),
+            failures: AtomicU64::new(0),
+            live_bytes: AtomicUsize::new(0),
+            peak_bytes: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// Point-in-time copy of a size class's counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SizeClassStats {
+    /// Object size of the class (0 for the large class)
+    pub object_size: usize,
+    pub allocs: u64,
+    pub frees: u64,
+    pub failures: u64,
+    pub live_bytes: usize,
+    pub peak_bytes: usize,
+}
+
+impl SizeClassStats {
+    /// Number of objects currently allocated in this class
+    pub fn live_objects(&self) -> u64 {
+        self.allocs.saturating_sub(self.frees)
+    }
+}
+
+static SIZE_CLASS_COUNTERS: [SizeClassCounters; NUM_SIZE_CLASSES] =
+    [const { SizeClassCounters::new() }; NUM_SIZE_CLASSES];
+
+/// Live bytes across all classes and their high-water mark
+static TOTAL_LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+static TOTAL_PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+/// Size class an allocation with `layout` is charged to
+///
+/// The slab allocator serves a request from the smallest class whose object
+/// size covers both the size and the alignment of the layout.
+pub fn size_class_index(layout: &Layout) -> usize {
+    let needed = layout.size().max(layout.align());
+    SIZE_CLASSES.iter()
+        .position(|&object_size| needed <= object_size)
+        .unwrap_or(SIZE_CLASSES.len())
+}
+
+/// Record a successful allocation
+///
+/// Called by the global allocator after it obtained `ptr` for `layout`.
+pub fn record_alloc(ptr: *mut u8, layout: &Layout) {
+    let class = &SIZE_CLASS_COUNTERS[size_class_index(layout)];
+    class.allocs.fetch_add(1, Ordering::Relaxed);
+    let live = class.live_bytes.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+    class.peak_bytes.fetch_max(live, Ordering::Relaxed);
+
+    let total = TOTAL_LIVE_BYTES.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+    TOTAL_PEAK_BYTES.fetch_max(total, Ordering::Relaxed);
+
+    #[cfg(feature = "kmalloc-sites")]
+    if SITE_TRACKING.load(Ordering::Relaxed) {
+        let site = caller_address(SITE_SKIP_FRAMES.load(Ordering::Relaxed));
+        SITE_TRACKER.lock().track(ptr as usize, site, layout.size());
+    }
+}
+
+/// Record an allocation that could not be satisfied
+pub fn record_failure(layout: &Layout) {
+    SIZE_CLASS_COUNTERS[size_class_index(layout)].failures.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Record a deallocation
+pub fn record_free(ptr: *mut u8, layout: &Layout) {
+    let class = &SIZE_CLASS_COUNTERS[size_class_index(layout)];
+    class.frees.fetch_add(1, Ordering::Relaxed);
+    let _ = class.live_bytes.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |live| {
+        Some(live.saturating_sub(layout.size()))
+    });
+    let _ = TOTAL_LIVE_BYTES.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |live| {
+        Some(live.saturating_sub(layout.size()))
+    });
+
+    // Allocations made while tracking was on are still attributed after it
+    // is switched off, so the per-site counts stay balanced. With none left
+    // frees do not touch the tracker lock.
+    if TRACKED_ALLOCATIONS.load(Ordering::Acquire) > 0 {
+        SITE_TRACKER.lock().release(ptr as usize, layout.size());
+    }
+}
+
+/// Snapshot of every size class, smallest first
+pub fn size_class_stats() -> [SizeClassStats; NUM_SIZE_CLASSES] {
+    let mut stats = [SizeClassStats::default(); NUM_SIZE_CLASSES];
+    for (i, (dst, src)) in stats.iter_mut().zip(SIZE_CLASS_COUNTERS.iter()).enumerate() {
+        *dst = SizeClassStats {
+            object_size: SIZE_CLASSES.get(i).copied().unwrap_or(0),
+            allocs: src.allocs.load(Ordering::Relaxed),
+            frees: src.frees.load(Ordering::Relaxed),
+            failures: src.failures.load(Ordering::Relaxed),
+            live_bytes: src.live_bytes.load(Ordering::Relaxed),
+            peak_bytes: src.peak_bytes.load(Ordering::Relaxed),
+        };
+    }
+    stats
+}
+
+/// Bytes currently allocated across all size classes
+pub fn live_bytes() -> usize {
+    TOTAL_LIVE_BYTES.load(Ordering::Relaxed)
+}
+
+/// Highest number of bytes that were allocated at the same time
+pub fn peak_bytes() -> usize {
+    TOTAL_PEAK_BYTES.load(Ordering::Relaxed)
+}
+
+/// Render the size class counters as text
+///
+/// One line per class with object size, counters and live/peak bytes,
+/// followed by a totals line. This is the format of `/proc/kmallocinfo`.
+pub fn format_size_class_stats() -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "# class allocs frees failures live_objs live_bytes peak_bytes");
+    for stats in size_class_stats() {
+        let _ = match stats.object_size {
+            0 => write!(out, "kmalloc-large"),
+            size => write!(out, "kmalloc-{}", size),
+        };
+        let _ = writeln!(
+            out,
+            " {} {} {} {} {} {}",
+            stats.allocs, stats.frees, stats.failures,
+            stats.live_objects(), stats.live_bytes, stats.peak_bytes,
+        );
+    }
+    let _ = writeln!(out, "total live_bytes={} peak_bytes={}", live_bytes(), peak_bytes());
+    out
+}
+
+/// Whether new allocations are attributed to their call sites
+static SITE_TRACKING: AtomicBool = AtomicBool::new(false);
+
+/// Frames skipped when looking for the allocating call site
+static SITE_SKIP_FRAMES: AtomicUsize = AtomicUsize::new(DEFAULT_SITE_SKIP_FRAMES);
+
+/// Per-site counters kept by the tracker
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AllocSiteStats {
+    /// Return address of the allocating function (0 if it could not be found)
+    pub site: usize,
+    /// Allocations made from this site while tracking was enabled
+    pub total_allocs: u64,
+    /// Objects from this site that have not been freed yet
+    pub live_objects: usize,
+    /// Bytes from this site that have not been freed yet
+    pub live_bytes: usize,
+}
+
+/// Fixed-size allocation-site tracker
+///
+/// `sites` is a small open table of call sites; `allocations` maps a live
+/// pointer to the index of its site with open addressing. When either table
+/// is full the allocation is counted in `dropped` instead.
+struct SiteTracker {
+    sites: [AllocSiteStats; MAX_ALLOC_SITES],
+    num_sites: usize,
+    /// Live pointer -> site index + 1 (0 marks an empty slot, usize::MAX a tombstone)
+    allocations: [(usize, usize); MAX_TRACKED_ALLOCATIONS],
+    /// Number of live entries in `allocations`
+    tracked: usize,
+    /// Allocations that could not be recorded because a table was full
+    dropped: u64,
+}
+
+const TOMBSTONE: usize = usize::MAX;
+
+static SITE_TRACKER: Mutex<SiteTracker> = Mutex::new(SiteTracker::new());
+
+/// `tracked` of [`SITE_TRACKER`], read by frees without taking its lock
+static TRACKED_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+impl SiteTracker {
+    const fn new() -> Self {
+        Self {
+            sites: [AllocSiteStats { site: 0, total_allocs: 0, live_objects: 0, live_bytes: 0 }; MAX_ALLOC_SITES],
+            num_sites: 0,
+            allocations: [(0, 0); MAX_TRACKED_ALLOCATIONS],
+            tracked: 0,
+            dropped: 0,
+        }
+    }
+
+    #[cfg_attr(not(feature = "kmalloc-sites"), allow(dead_code))]
+    fn site_index(&mut self, site: usize) -> Option<usize> {
+        if let Some(index) = self.sites[..self.num_sites].iter().position(|s| s.site == site) {
+            return Some(index);
+        }
+        if self.num_sites == MAX_ALLOC_SITES {
+            return None;
+        }
+        self.sites[self.num_sites] = AllocSiteStats { site, ..AllocSiteStats::default() };
+        self.num_sites += 1;
+        Some(self.num_sites - 1)
+    }
+
+    fn slot_for(ptr: usize) -> usize {
+        // Allocations are at least 8-byte aligned; mix the remaining bits
+        ((ptr >> 3).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32) % MAX_TRACKED_ALLOCATIONS
+    }
+
+    #[cfg_attr(not(feature = "kmalloc-sites"), allow(dead_code))]
+    fn track(&mut self, ptr: usize, site: usize, size: usize) {
+        let Some(index) = self.site_index(site) else {
+            self.dropped += 1;
+            return;
+        };
+        let start = Self::slot_for(ptr);
+        for probe in 0..MAX_TRACKED_ALLOCATIONS {
+            let slot = (start + probe) % MAX_TRACKED_ALLOCATIONS;
+            let (entry_ptr, entry_site) = self.allocations[slot];
+            if entry_site == 0 || entry_site == TOMBSTONE || entry_ptr == ptr {
+                if entry_site == 0 || entry_site == TOMBSTONE {
+                    self.tracked += 1;
+                    TRACKED_ALLOCATIONS.store(self.tracked, Ordering::Release);
+                }
+                self.allocations[slot] = (ptr, index + 1);
+                let stats = &mut self.sites[index];
+                stats.total_allocs += 1;
+                stats.live_objects += 1;
+                stats.live_bytes += size;
+                return;
+            }
+        }
+        self.dropped += 1;
+    }
+
+    /// Forget `ptr` and charge its bytes back to the site that allocated it
+    fn release(&mut self, ptr: usize, size: usize) {
+        if let Some(index) = self.untrack(ptr) {
+            let stats = &mut self.sites[index];
+            stats.live_objects = stats.live_objects.saturating_sub(1);
+            stats.live_bytes = stats.live_bytes.saturating_sub(size);
+        }
+    }
+
+    fn untrack(&mut self, ptr: usize) -> Option<usize> {
+        let start = Self::slot_for(ptr);
+        for probe in 0..MAX_TRACKED_ALLOCATIONS {
+            let slot = (start + probe) % MAX_TRACKED_ALLOCATIONS;
+            let (entry_ptr, entry_site) = self.allocations[slot];
+            if entry_site == 0 {
+                return None;
+            }
+            if entry_site != TOMBSTONE && entry_ptr == ptr {
+                self.allocations[slot] = (0, TOMBSTONE);
+                self.tracked -= 1;
+                TRACKED_ALLOCATIONS.store(self.tracked, Ordering::Release);
+                return Some(entry_site - 1);
+            }
+        }
+        None
+    }
+
+    /// Clear both tables in place (the tracker is too large to rebuild on
+    /// a task's kernel stack)
+    fn reset(&mut self) {
+        self.sites[..self.num_sites].fill(AllocSiteStats::default());
+        self.num_sites = 0;
+        self.allocations.fill((0, 0));
+        self.tracked = 0;
+        TRACKED_ALLOCATIONS.store(0, Ordering::Release);
+        self.dropped = 0;
+    }
+}
+
+/// Enable or disable allocation-site tracking
+///
+/// Fails without the `kmalloc-sites` feature.
+pub fn set_site_tracking(enabled: bool) -> Result<(), &'static str> {
+    if enabled && !cfg!(feature = "kmalloc-sites") {
+        return Err("Allocation-site tracking is not built in");
+    }
+    SITE_TRACKING.store(enabled, Ordering::Release);
+    Ok(())
+}
+
+/// Whether allocation-site tracking is enabled
+pub fn site_tracking_enabled() -> bool {
+    SITE_TRACKING.load(Ordering::Acquire)
+}
+
+/// Forget every tracked site and allocation
+pub fn reset_site_tracking() {
+    SITE_TRACKER.lock().reset();
+}
+
+/// Sites with live allocations, largest live byte count first
+///
+/// # Returns
+/// The sites and the number of allocations that could not be attributed
+/// because the tracker tables were full
+pub fn alloc_site_stats() -> (Vec<AllocSiteStats>, u64) {
+    // Reserve before taking the lock: allocating under it would re-enter
+    // the tracker
+    let mut sites = Vec::with_capacity(MAX_ALLOC_SITES);
+    let dropped = {
+        let tracker = SITE_TRACKER.lock();
+        sites.extend(tracker.sites[..tracker.num_sites].iter().filter(|s| s.live_objects > 0).copied());
+        tracker.dropped
+    };
+    sites.sort_unstable_by(|a, b| b.live_bytes.cmp(&a.live_bytes));
+    (sites, dropped)
+}
+
+/// Render the allocation-site report as text
+///
+/// This is the format of `/proc/kmalloc_sites`.
+pub fn format_alloc_sites() -> String {
+    let (sites, dropped) = alloc_site_stats();
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "tracking={} depth={} dropped={}",
+        if site_tracking_enabled() { "on" } else { "off" },
+        SITE_SKIP_FRAMES.load(Ordering::Relaxed),
+        dropped,
+    );
+    let _ = writeln!(out, "# site live_objs live_bytes total_allocs");
+    for site in sites {
+        let _ = writeln!(out, "{:#x} {} {} {}", site.site, site.live_objects, site.live_bytes, site.total_allocs);
+    }
+    out
+}
+
+/// Apply a command written to `/proc/kmalloc_sites`
+///
+/// Accepts `on`, `off`, `reset` and `depth=N` (frames skipped before the
+/// recorded site; raise it when every site points into a common wrapper).
+pub fn control_alloc_sites(command: &[u8]) -> Result<(), &'static str> {
+    let command = core::str::from_utf8(command).map_err(|_| "Invalid command")?;
+    match command.trim() {
+        "on" | "1" => set_site_tracking(true)?,
+        "off" | "0" => set_site_tracking(false)?,
+        "reset" => reset_site_tracking(),
+        other => {
+            let depth = other.strip_prefix("depth=").ok_or("Unknown command")?;
+            let depth = depth.parse::<usize>().map_err(|_| "Invalid depth")?;
+            SITE_SKIP_FRAMES.store(depth, Ordering::Relaxed);
+        }
+    }
+    Ok(())
+}
+
+/// Return address `skip` frames above the caller, found by following the
+/// frame-pointer chain
+///
+/// Returns 0 when the chain leaves the current stack before reaching the
+/// requested frame.
+#[cfg(feature = "kmalloc-sites")]
+#[inline(never)]
+fn caller_address(skip: usize) -> usize {
+    let mut fp: usize;
+    let sp: usize;
+    unsafe {
+        core::arch::asm!("mv {}, s0", out(reg) fp);
+        core::arch::asm!("mv {}, sp", out(reg) sp);
+    }
+    let limit = sp.saturating_add(MAX_FRAME_WALK_SPAN);
+    for depth in 0..=skip {
+        // Frames live on the current stack, above the stack pointer
+        if fp <= sp || fp > limit || fp % 8 != 0 {
+            return 0;
+        }
+        // RISC-V frame record: return address at fp-8, caller's fp at fp-16
+        let ra = unsafe { *((fp - 8) as *const usize) };
+        if depth == skip {
+            return ra;
+        }
+        let next = unsafe { *((fp - 16) as *const usize) };
+        if next <= fp {
+            return 0;
+        }
+        fp = next;
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_size_class_index() {
+        assert_eq!(size_class_index(&Layout::from_size_align(1, 1).unwrap()), 0);
+        assert_eq!(size_class_index(&Layout::from_size_align(64, 8).unwrap()), 0);
+        assert_eq!(size_class_index(&Layout::from_size_align(65, 8).unwrap()), 1);
+        assert_eq!(size_class_index(&Layout::from_size_align(8, 256).unwrap()), 2);
+        assert_eq!(size_class_index(&Layout::from_size_align(4096, 8).unwrap()), 6);
+        assert_eq!(size_class_index(&Layout::from_size_align(4097, 8).unwrap()), SIZE_CLASSES.len());
+    }
+
+    #[test_case]
+    fn test_site_tracker_balances() {
+        // The tracker is too large for a test stack; use the global one
+        let mut tracker = SITE_TRACKER.lock();
+        tracker.reset();
+        tracker.track(0x8000_1000, 0x8020_0000, 100);
+        tracker.track(0x8000_2000, 0x8020_0000, 50);
+        tracker.track(0x8000_3000, 0x8030_0000, 10);
+        assert_eq!(tracker.num_sites, 2);
+        assert_eq!(tracker.sites[0].live_objects, 2);
+        assert_eq!(tracker.sites[0].live_bytes, 150);
+
+        tracker.release(0x8000_1000, 100);
+        assert_eq!(tracker.sites[0].live_objects, 1);
+        assert_eq!(tracker.sites[0].live_bytes, 50);
+        assert_eq!(tracker.sites[0].total_allocs, 2);
+
+        // Unknown pointers are ignored
+        tracker.release(0x8000_9000, 100);
+        assert_eq!(tracker.tracked, 2);
+        assert_eq!(TRACKED_ALLOCATIONS.load(Ordering::Relaxed), 2);
+        tracker.reset();
+        assert_eq!(TRACKED_ALLOCATIONS.load(Ordering::Relaxed), 0);
+    }
+
+    #[test_case]
+    fn test_alloc_sites_control() {
+        assert_eq!(control_alloc_sites(b"on\n").is_ok(), cfg!(feature = "kmalloc-sites"));
+        assert_eq!(site_tracking_enabled(), cfg!(feature = "kmalloc-sites"));
+        assert!(control_alloc_sites(b"off").is_ok());
+        assert!(!site_tracking_enabled());
+        assert!(control_alloc_sites(b"bogus").is_err());
+    }
+}
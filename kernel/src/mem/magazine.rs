@@ -0,0 +1,244 @@
+//! Per-CPU slab magazines
+//!
+//! Every slab allocation and free used to take the global heap lock. Each
+//! CPU now keeps a magazine (a small stack of free objects) per slab size
+//! class in front of the heap:
+//!
+//! - an allocation pops an object from the current CPU's magazine; only
+//!   when the magazine is empty is the global heap locked, and then it is
+//!   refilled with a batch of objects at once
+//! - a free pushes the object onto the magazine; a full magazine hands a
+//!   batch back to the heap under a single lock acquisition
+//!
+//! Magazines are locked with `try_lock`, so an interrupt that allocates
+//! while the interrupted code holds its CPU's magazine simply goes to the
+//! global heap instead of deadlocking.
+//!
+//! Objects parked in a magazine are unavailable to the other CPUs, so the
+//! timer periodically rebalances the magazines: classes a CPU has not used
+//! since the last pass are emptied and the rest are trimmed to a batch.
+//! When an allocation fails, all magazines are drained before any cache is
+//! reclaimed. The counters are published in `/proc/magazines`.
+
+use core::alloc::Layout;
+use core::fmt::Write;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use alloc::string::String;
+use slab_allocator_rs::{Heap, LockedHeap};
+use spin::Mutex;
+
+use super::kmalloc_stats::{size_class_index, SIZE_CLASSES};
+use crate::environment::NUM_OF_CPUS;
+
+/// Number of objects a magazine can hold
+pub const MAGAZINE_SIZE: usize = 32;
+
+/// Number of objects moved between a magazine and the heap at once
+pub const MAGAZINE_BATCH: usize = MAGAZINE_SIZE / 2;
+
+/// Timer ticks between two rebalancing passes
+pub const REBALANCE_INTERVAL_TICKS: u64 = 100;
+
+const NUM_CLASSES: usize = SIZE_CLASSES.len();
+
+#[derive(Clone, Copy)]
+struct Magazine {
+    objects: [usize; MAGAZINE_SIZE],
+    count: usize,
+    /// Used since the last rebalancing pass
+    used: bool,
+}
+
+impl Magazine {
+    const fn new() -> Self {
+        Self { objects: [0; MAGAZINE_SIZE], count: 0, used: false }
+    }
+
+    /// Give objects back to the heap until at most `keep` are left
+    ///
+    /// # Returns
+    /// The number of objects given back
+    fn flush(&mut self, heap: &mut Heap, class: usize, keep: usize) -> usize {
+        if self.count <= keep {
+            return 0;
+        }
+        let layout = class_layout(class);
+        let released = self.count - keep;
+        while self.count > keep {
+            self.count -= 1;
+            unsafe { heap.deallocate(NonNull::new_unchecked(self.objects[self.count] as *mut u8), layout) };
+        }
+        released
+    }
+}
+
+struct CpuMagazines {
+    magazines: [Magazine; NUM_CLASSES],
+}
+
+struct MagazineCounters {
+    hits: AtomicU64,
+    refills: AtomicU64,
+    flushes: AtomicU64,
+}
+
+static CPU_MAGAZINES: [Mutex<CpuMagazines>; NUM_OF_CPUS] =
+    [const { Mutex::new(CpuMagazines { magazines: [Magazine::new(); NUM_CLASSES] }) }; NUM_OF_CPUS];
+
+static COUNTERS: [MagazineCounters; NUM_OF_CPUS] = [const {
+    MagazineCounters { hits: AtomicU64::new(0), refills: AtomicU64::new(0), flushes: AtomicU64::new(0) }
+}; NUM_OF_CPUS];
+
+/// Layout that selects the slab of `class` in the heap
+fn class_layout(class: usize) -> Layout {
+    Layout::from_size_align(SIZE_CLASSES[class], SIZE_CLASSES[class]).unwrap()
+}
+
+/// Slab class served by magazines for `layout`, if any
+fn magazine_class(layout: &Layout) -> Option<usize> {
+    let class = size_class_index(layout);
+    (class < NUM_CLASSES).then_some(class)
+}
+
+fn current_cpu() -> Option<usize> {
+    let cpu_id = crate::arch::get_cpu().get_cpuid();
+    (cpu_id < NUM_OF_CPUS).then_some(cpu_id)
+}
+
+/// Allocate an object from the current CPU's magazine
+///
+/// # Returns
+/// `None` if `layout` is not served by a magazine, the magazine is busy or
+/// the heap has no objects of the class left; the caller then allocates
+/// from the heap directly
+pub fn alloc(heap: &LockedHeap, layout: &Layout) -> Option<*mut u8> {
+    let class = magazine_class(layout)?;
+    let cpu_id = current_cpu()?;
+    let mut cpu = CPU_MAGAZINES[cpu_id].try_lock()?;
+    let magazine = &mut cpu.magazines[class];
+    magazine.used = true;
+
+    if magazine.count == 0 {
+        let layout = class_layout(class);
+        let mut guard = heap.lock();
+        let heap = guard.as_mut()?;
+        while magazine.count < MAGAZINE_BATCH {
+            let Ok(object) = heap.allocate(layout) else { break };
+            magazine.objects[magazine.count] = object.as_ptr() as usize;
+            magazine.count += 1;
+        }
+        if magazine.count == 0 {
+            return None;
+        }
+        COUNTERS[cpu_id].refills.fetch_add(1, Ordering::Relaxed);
+    } else {
+        COUNTERS[cpu_id].hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    magazine.count -= 1;
+    Some(magazine.objects[magazine.count] as *mut u8)
+}
+
+/// Put a freed slab object into the current CPU's magazine
+///
+/// # Returns
+/// `false` if the object was not taken and must be freed to the heap
+pub fn free(heap: &LockedHeap, ptr: *mut u8, layout: &Layout) -> bool {
+    let Some(class) = magazine_class(layout) else { return false };
+    let Some(cpu_id) = current_cpu() else { return false };
+    let Some(mut cpu) = CPU_MAGAZINES[cpu_id].try_lock() else { return false };
+    let magazine = &mut cpu.magazines[class];
+    magazine.used = true;
+
+    if magazine.count == MAGAZINE_SIZE {
+        let mut guard = heap.lock();
+        let Some(heap) = guard.as_mut() else { return false };
+        magazine.flush(heap, class, MAGAZINE_SIZE - MAGAZINE_BATCH);
+        COUNTERS[cpu_id].flushes.fetch_add(1, Ordering::Relaxed);
+    }
+    magazine.objects[magazine.count] = ptr as usize;
+    magazine.count += 1;
+    true
+}
+
+/// Rebalance the magazines of all CPUs
+///
+/// Classes unused since the last pass are emptied, the others trimmed to
+/// [`MAGAZINE_BATCH`] objects, so that objects do not sit idle on one CPU
+/// while another one refills from the heap. Runs from the timer interrupt,
+/// so nothing is done if the heap or a magazine is busy.
+pub fn rebalance(heap: &LockedHeap) {
+    let Some(mut guard) = heap.try_lock() else { return };
+    let Some(heap) = guard.as_mut() else { return };
+    for cpu in CPU_MAGAZINES.iter() {
+        let Some(mut cpu) = cpu.try_lock() else { continue };
+        for (class, magazine) in cpu.magazines.iter_mut().enumerate() {
+            let keep = if magazine.used { MAGAZINE_BATCH } else { 0 };
+            magazine.flush(heap, class, keep);
+            magazine.used = false;
+        }
+    }
+}
+
+/// Give every object held in magazines back to the heap
+///
+/// # Returns
+/// The number of objects given back
+pub fn drain_all(heap: &LockedHeap) -> usize {
+    let mut guard = heap.lock();
+    let Some(heap) = guard.as_mut() else { return 0 };
+    let mut released = 0;
+    for cpu in CPU_MAGAZINES.iter() {
+        let Some(mut cpu) = cpu.try_lock() else { continue };
+        for (class, magazine) in cpu.magazines.iter_mut().enumerate() {
+            released += magazine.flush(heap, class, 0);
+        }
+    }
+    released
+}
+
+/// Render per-CPU magazine counters (the format of `/proc/magazines`)
+pub fn format_magazine_stats() -> String {
+    let mut out = String::new();
+    let _ = write!(out, "# cpu hits refills flushes");
+    for size in SIZE_CLASSES {
+        let _ = write!(out, " cached-{}", size);
+    }
+    out.push('\n');
+    for (cpu_id, counters) in COUNTERS.iter().enumerate() {
+        let _ = write!(
+            out, "cpu{} {} {} {}", cpu_id,
+            counters.hits.load(Ordering::Relaxed),
+            counters.refills.load(Ordering::Relaxed),
+            counters.flushes.load(Ordering::Relaxed),
+        );
+        // A busy magazine is reported as unknown rather than waited for
+        let cached = CPU_MAGAZINES[cpu_id].try_lock()
+            .map(|cpu| cpu.magazines.map(|magazine| magazine.count));
+        match cached {
+            Some(cached) => cached.iter().for_each(|count| { let _ = write!(out, " {}", count); }),
+            None => (0..NUM_CLASSES).for_each(|_| out.push_str(" -")),
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(all(test, not(feature = "kasan")))]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+
+    #[test_case]
+    fn test_magazine_reuses_freed_object() {
+        let first = Box::new([1u8; 200]);
+        let addr = first.as_ptr() as usize;
+        drop(first);
+        // The object went to this CPU's magazine and comes straight back
+        let second = Box::new([2u8; 200]);
+        assert_eq!(second.as_ptr() as usize, addr);
+        assert!(format_magazine_stats().starts_with("# cpu hits refills flushes cached-64"));
+    }
+}
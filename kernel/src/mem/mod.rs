@@ -4,7 +4,13 @@
 //! and other memory-related operations needed by the kernel.
 
 pub mod allocator;
+pub mod buddy;
+pub mod kasan;
+pub mod kmalloc_stats;
+pub mod magazine;
+pub mod oom;
 pub mod page;
+pub mod reclaim;
 
 use alloc::{boxed::Box, vec};
 
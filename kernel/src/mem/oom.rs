@@ -0,0 +1,379 @@
+//! Out-of-memory handling.
+//!
+//! When the kernel heap cannot satisfy an allocation, the allocator calls
+//! [`out_of_memory`]. It picks the task with the highest badness score and
+//! kills its whole process: every thread sharing the address space.
+//!
+//! The badness of a task is its resident set size in pages (pages owned by
+//! the task) plus `oom_score_adj` scaled to the total memory, so an
+//! adjustment of 1000 counts as much as all kernel-managed memory. Tasks with an
+//! adjustment of -1000 and kernel tasks are never selected.
+//!
+//! [`out_of_memory`] runs inside the failing allocation, where the heap is
+//! exhausted, so it must not allocate: the victim is picked by walking the
+//! runqueues in place and its threads only get SIGKILL pending. Nothing is
+//! freed there either, since a victim may be blocked in a system call that
+//! still uses its memory. The `oom_reaper` kernel thread reports the kill
+//! and wakes the victims, which exit on their way back to user space. Once
+//! none of them can return from the kernel any more, the reaper unmaps
+//! their address space from the page table and releases its pages.
+
+use core::alloc::Layout;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::environment::{NUM_OF_CPUS, PAGE_SIZE};
+use crate::late_initcall;
+use crate::object::capability::poll::wait_for;
+use crate::sched::scheduler::get_scheduler;
+use crate::sync::TaskShared;
+use crate::task::signal::{send_signal, SigAction, NSIG, SIGKILL};
+use crate::task::{kthread, Task, TaskState, TaskType};
+use crate::timer::ms_to_ticks;
+
+/// Lowest `oom_score_adj`; tasks with this value are never killed
+pub const OOM_SCORE_ADJ_MIN: i32 = -1000;
+/// Highest `oom_score_adj`
+pub const OOM_SCORE_ADJ_MAX: i32 = 1000;
+
+/// Most kills waiting for the reaper; no further task is killed until it
+/// has caught up
+const MAX_PENDING_KILLS: usize = 8;
+
+/// How often the reaper looks for kills to finish
+const REAP_INTERVAL_MS: u64 = 10;
+
+/// Set while the OOM killer runs so that a failed allocation inside it
+/// does not recurse
+static OOM_IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+
+/// Number of tasks killed by the OOM killer since boot
+static OOM_KILL_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Signal actions of a process, shared by all of its threads; identifies
+/// the thread group of a victim even after the victim itself is gone
+type ThreadGroup = TaskShared<[SigAction; NSIG]>;
+
+/// A kill the reaper is still to report and finish
+#[derive(Debug)]
+struct OomKill {
+    task_id: usize,
+    score: usize,
+    rss_pages: usize,
+    /// Size of the allocation that failed
+    request_size: usize,
+    group: ThreadGroup,
+    reported: bool,
+}
+
+/// Kills whose memory the reaper has not released yet
+static PENDING_KILLS: Mutex<[Option<OomKill>; MAX_PENDING_KILLS]> = Mutex::new([const { None }; MAX_PENDING_KILLS]);
+
+/// Number of entries of [`PENDING_KILLS`] in use
+static PENDING_KILL_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Set when a kill is made, to wake the reaper
+static NEW_KILL: AtomicBool = AtomicBool::new(false);
+
+/// Resident set size of a task in pages
+///
+/// Untouched pages of demand-zero mappings share the zero page and are not
+/// counted. The task that ran out of memory may be holding its own address
+/// space or page list while allocating; what is locked is left out instead
+/// of waiting for it.
+pub fn task_rss_pages(task: &Task) -> usize {
+    let zero_fill_pages: usize = task.vm_manager.try_read().map_or(0, |vm_manager| {
+        vm_manager.memmap_iter()
+            .filter_map(|map| map.zero_fill.as_ref().map(|pages| pages.resident_pages(map.vmarea.start, map.vmarea.end)))
+            .sum()
+    });
+    task.managed_pages.try_read().map_or(0, |pages| pages.len()) + zero_fill_pages
+}
+
+/// Compute the OOM badness score of a task
+///
+/// # Arguments
+/// * `task` - The task to score
+/// * `total_pages` - Total number of pages the score is relative to
+///
+/// # Returns
+/// `None` if the task must not be killed, otherwise its score (higher is a
+/// better victim)
+pub fn oom_badness(task: &Task, total_pages: usize) -> Option<usize> {
+    if task.task_type != TaskType::User || task.oom_score_adj <= OOM_SCORE_ADJ_MIN {
+        return None;
+    }
+    match task.get_state() {
+        TaskState::Zombie | TaskState::Terminated | TaskState::NotInitialized => return None,
+        _ => {}
+    }
+
+    let rss = task_rss_pages(task) as i64;
+    let adj = task.oom_score_adj.clamp(OOM_SCORE_ADJ_MIN, OOM_SCORE_ADJ_MAX) as i64;
+    let points = rss + adj * total_pages as i64 / 1000;
+    // A task that owns anything stays a candidate even with a negative adjustment
+    Some(points.max(1) as usize)
+}
+
+/// Pick the task to kill
+///
+/// Does not allocate. Tasks already killed and waiting to exit are passed
+/// over.
+///
+/// # Returns
+/// The ID and badness score of the selected task, or `None` if no task can
+/// be killed
+pub fn select_victim() -> Option<(usize, usize)> {
+    let total_pages = (crate::mem::allocator::total_memory() / PAGE_SIZE).max(1);
+
+    let mut victim: Option<(usize, usize)> = None;
+    get_scheduler().for_each_task(|task| {
+        if task.signals.pending().contains(SIGKILL) {
+            return;
+        }
+        let Some(score) = oom_badness(task, total_pages) else { return };
+        if victim.map_or(true, |(_, best)| score > best) {
+            victim = Some((task.get_id(), score));
+        }
+    });
+    victim
+}
+
+/// Make SIGKILL pending for every task of a thread group
+///
+/// Does not allocate; the tasks are not woken, see [`out_of_memory`].
+fn kill_thread_group(group: &ThreadGroup) {
+    get_scheduler().for_each_task(|task| {
+        if task.signals.actions.ptr_eq(group) {
+            task.signals.post_kill();
+        }
+    });
+}
+
+/// Handle an allocation failure of the kernel heap
+///
+/// The victims are left to the reaper to report, wake and release, as all
+/// of that allocates or has to wait for them to exit. The failed
+/// allocation still fails; the memory comes back for later ones. Nothing
+/// is killed while [`MAX_PENDING_KILLS`] kills are waiting for the reaper.
+///
+/// # Arguments
+/// * `layout` - The layout of the allocation that failed
+pub fn out_of_memory(layout: Layout) {
+    if OOM_IN_PROGRESS.swap(true, Ordering::AcqRel) {
+        return;
+    }
+
+    let mut pending = PENDING_KILLS.lock();
+    if let Some(slot) = pending.iter_mut().find(|slot| slot.is_none()) {
+        if let Some((task_id, score)) = select_victim() {
+            let victim = get_scheduler().get_task_by_id(task_id).map(|task| (task.signals.actions.share(), task_rss_pages(task)));
+            if let Some((group, rss_pages)) = victim {
+                kill_thread_group(&group);
+                *slot = Some(OomKill { task_id, score, rss_pages, request_size: layout.size(), group, reported: false });
+                PENDING_KILL_COUNT.fetch_add(1, Ordering::Release);
+                OOM_KILL_COUNT.fetch_add(1, Ordering::Relaxed);
+                NEW_KILL.store(true, Ordering::Release);
+            }
+        }
+    }
+    drop(pending);
+
+    OOM_IN_PROGRESS.store(false, Ordering::Release);
+}
+
+/// IDs of the tasks of a thread group, and whether any of them may still
+/// be in the kernel: not exited yet, or still switching away on some CPU
+fn group_members(group: &ThreadGroup) -> (Vec<usize>, bool) {
+    let scheduler = get_scheduler();
+    let running: [Option<usize>; NUM_OF_CPUS] =
+        core::array::from_fn(|cpu_id| scheduler.get_current_task_id(cpu_id));
+    let mut live = false;
+    let members = scheduler.get_all_task_ids().into_iter().filter(|&id| {
+        scheduler.get_task_by_id(id).is_some_and(|task| {
+            let member = task.signals.actions.ptr_eq(group);
+            live |= member
+                && (running.contains(&Some(id)) || !matches!(task.get_state(), TaskState::Zombie | TaskState::Terminated));
+            member
+        })
+    }).collect();
+    (members, live)
+}
+
+/// Unmap and free the user memory of an exited task
+///
+/// # Returns
+/// The number of pages released
+fn release_task_memory(task: &mut Task) -> usize {
+    let released = task_rss_pages(task);
+    task.vm_manager.write().unmap_user_memory_maps();
+    *task.managed_pages.write() = Vec::new();
+    released
+}
+
+/// Report new kills, wake the victims and release the memory of those
+/// that have exited
+fn reap() {
+    if PENDING_KILL_COUNT.load(Ordering::Acquire) == 0 {
+        return;
+    }
+    for index in 0..MAX_PENDING_KILLS {
+        // Allocating under the lock could end up in `out_of_memory`
+        let (group, report) = {
+            let mut pending = PENDING_KILLS.lock();
+            let Some(kill) = pending[index].as_mut() else { continue };
+            let report = (!kill.reported).then(|| (kill.task_id, kill.score, kill.rss_pages, kill.request_size));
+            kill.reported = true;
+            (kill.group.share(), report)
+        };
+
+        if let Some((task_id, score, rss_pages, request_size)) = report {
+            let name = get_scheduler().get_task_by_id(task_id).map(|task| task.name.clone()).unwrap_or_default();
+            crate::early_println!(
+                "[oom] Out of memory allocating {} bytes: killed task {} ({}) score={} rss={}KB",
+                request_size, task_id, name, score, rss_pages * PAGE_SIZE / 1024
+            );
+        }
+
+        let (members, live) = group_members(&group);
+        if live {
+            // Wake the victims from interruptible waits so that they exit
+            for &task_id in &members {
+                let _ = send_signal(task_id, SIGKILL);
+            }
+            continue;
+        }
+
+        // No victim can return from a system call any more, so nothing
+        // still uses the memory
+        let mut released = 0;
+        for &task_id in &members {
+            if let Some(task) = get_scheduler().get_task_by_id(task_id) {
+                if task.get_state() == TaskState::Zombie {
+                    released += release_task_memory(task);
+                }
+            }
+        }
+        let kill = PENDING_KILLS.lock()[index].take();
+        PENDING_KILL_COUNT.fetch_sub(1, Ordering::Release);
+        if let Some(kill) = kill {
+            crate::early_println!("[oom] Reaped task {}: released {}KB", kill.task_id, released * PAGE_SIZE / 1024);
+        }
+    }
+}
+
+fn oom_reaper_thread() {
+    while !kthread::should_stop() {
+        reap();
+        wait_for(&[], Some(ms_to_ticks(REAP_INTERVAL_MS).max(1)), || NEW_KILL.swap(false, Ordering::AcqRel));
+    }
+}
+
+fn init_oom_reaper() {
+    kthread::spawn("oom_reaper", oom_reaper_thread);
+}
+
+late_initcall!(init_oom_reaper);
+
+/// Number of tasks killed by the OOM killer since boot
+pub fn oom_kill_count() -> usize {
+    OOM_KILL_COUNT.load(Ordering::Relaxed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::page::Page;
+    use crate::task::{new_kernel_task, new_user_task, ManagedPage};
+    use alloc::boxed::Box;
+    use alloc::string::ToString;
+
+    fn task_with_pages(num_pages: usize) -> Task {
+        let mut task = new_user_task("oom_test".to_string(), 0);
+        task.set_state(TaskState::Ready);
+        for i in 0..num_pages {
+            task.add_managed_page(ManagedPage { vaddr: i * PAGE_SIZE, page: Box::new(Page::new()) });
+        }
+        task
+    }
+
+    #[test_case]
+    fn test_oom_badness_prefers_larger_rss() {
+        let small = task_with_pages(1);
+        let large = task_with_pages(4);
+        assert!(oom_badness(&large, 1000).unwrap() > oom_badness(&small, 1000).unwrap());
+    }
+
+    #[test_case]
+    fn test_oom_badness_respects_adjustment() {
+        let mut large = task_with_pages(4);
+        let mut small = task_with_pages(1);
+        small.oom_score_adj = 500;
+        assert!(oom_badness(&small, 1000).unwrap() > oom_badness(&large, 1000).unwrap());
+
+        large.oom_score_adj = OOM_SCORE_ADJ_MIN;
+        assert_eq!(oom_badness(&large, 1000), None);
+    }
+
+    #[test_case]
+    fn test_oom_badness_skips_kernel_tasks() {
+        let mut task = new_kernel_task("oom_kernel".to_string(), 0, || {});
+        task.set_state(TaskState::Ready);
+        assert_eq!(oom_badness(&task, 1000), None);
+    }
+
+    #[test_case]
+    fn test_oom_release_task_memory() {
+        use crate::vm::vmem::{MemoryArea, VirtualMemoryMap, VirtualMemoryPermission};
+
+        let mut task = task_with_pages(2);
+        task.init();
+        let vmarea = MemoryArea::new(0x4000_0000, 0x4000_0000 + PAGE_SIZE - 1);
+        task.vm_manager.write().add_memory_map(VirtualMemoryMap::new_zero_fill(vmarea, 0x0b)).unwrap();
+        assert!(task.vm_manager.write().lazy_map_page_for_write(0x4000_0000).is_ok());
+        task.set_state(TaskState::Zombie);
+
+        // The page table no longer reaches the frames that are freed
+        let rss = task_rss_pages(&task);
+        assert!(rss >= 3);
+        assert_eq!(release_task_memory(&mut task), rss);
+        assert_eq!(task_rss_pages(&task), 0);
+        assert!(task.vm_manager.read().memmap_iter().all(|map| !VirtualMemoryPermission::User.contained_in(map.permissions)));
+        let asid = task.vm_manager.read().get_asid();
+        let root = task.vm_manager.read().get_root_page_table().unwrap();
+        assert!(root.walk(0x4000_0000, false, asid).map_or(true, |pte| !pte.is_valid()));
+    }
+
+    #[test_case]
+    fn test_oom_exhausted_heap() {
+        // Fill the heap with small blocks, each holding the address of the
+        // one before, until an allocation fails instead of panicking
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let mut last: *mut u8 = core::ptr::null_mut();
+        let mut count = 0;
+        loop {
+            let block = unsafe { alloc::alloc::alloc(layout) };
+            if block.is_null() {
+                break;
+            }
+            unsafe { (block as *mut *mut u8).write(last) };
+            last = block;
+            count += 1;
+        }
+        assert!(count > 0);
+
+        // Picking a victim works without memory
+        let _ = select_victim();
+        assert!(!OOM_IN_PROGRESS.load(Ordering::Acquire));
+
+        while !last.is_null() {
+            let next = unsafe { (last as *mut *mut u8).read() };
+            unsafe { alloc::alloc::dealloc(last, layout) };
+            last = next;
+        }
+        // The heap is usable again
+        drop(Box::new([0u8; 64]));
+    }
+}
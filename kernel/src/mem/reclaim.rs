@@ -0,0 +1,387 @@
+//! Page reclaim
+//!
+//! Caches keep their pages on active/inactive LRU lists ([`LruLists`]).
+//! New pages start on the inactive list and are promoted to the active list
+//! when they are used again, so a single pass over a large file cannot push
+//! out the pages that are used over and over. The active list is aged into
+//! the inactive list whenever it grows larger, giving every page a second
+//! chance if it was referenced since the last scan.
+//!
+//! Caches that can give memory back register a [`Shrinker`]. When the kernel
+//! heap cannot satisfy an allocation, the allocator calls
+//! [`try_to_free_pages`] first, which asks the shrinkers to drop clean pages
+//! from the tail of their inactive lists. Only if nothing can be reclaimed
+//! does it fall back to the OOM killer (there is no swap, so anonymous pages
+//! are accounted but only reclaimed once userspace gives them back with
+//! `MADV_FREE`).
+//!
+//! The list sizes and counters are published in `/proc/vmstat`.
+
+extern crate alloc;
+
+use core::fmt::Write;
+use core::hash::Hash;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use alloc::string::String;
+use alloc::sync::Weak;
+use alloc::vec::Vec;
+use hashbrown::HashMap;
+use spin::Mutex;
+
+use crate::environment::PAGE_SIZE;
+
+/// Minimum number of pages a reclaim pass tries to free, so that a burst of
+/// allocations does not scan the caches once per page
+pub const RECLAIM_BATCH_PAGES: usize = 32;
+
+/// Which list an entry is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LruList {
+    Active,
+    Inactive,
+}
+
+#[derive(Debug)]
+struct LruNode<K> {
+    list: LruList,
+    /// Used since it was last moved
+    referenced: bool,
+    prev: Option<K>,
+    next: Option<K>,
+}
+
+#[derive(Debug)]
+struct ListEnds<K> {
+    /// Most recently added
+    head: Option<K>,
+    /// Next to be aged or reclaimed
+    tail: Option<K>,
+    len: usize,
+}
+
+impl<K> ListEnds<K> {
+    const fn new() -> Self {
+        Self { head: None, tail: None, len: 0 }
+    }
+}
+
+/// Active and inactive LRU lists of cache entries
+///
+/// The lists are doubly linked through the entries themselves, so moving an
+/// entry between lists never allocates (reclaim runs when memory is short).
+#[derive(Debug)]
+pub struct LruLists<K> {
+    nodes: HashMap<K, LruNode<K>>,
+    active: ListEnds<K>,
+    inactive: ListEnds<K>,
+}
+
+impl<K: Hash + Eq + Copy> LruLists<K> {
+    pub fn new() -> Self {
+        Self { nodes: HashMap::new(), active: ListEnds::new(), inactive: ListEnds::new() }
+    }
+
+    fn ends(&mut self, list: LruList) -> &mut ListEnds<K> {
+        match list {
+            LruList::Active => &mut self.active,
+            LruList::Inactive => &mut self.inactive,
+        }
+    }
+
+    fn unlink(&mut self, key: K) {
+        let Some(node) = self.nodes.get(&key) else { return };
+        let (list, prev, next) = (node.list, node.prev, node.next);
+        match prev {
+            Some(prev) => self.nodes.get_mut(&prev).unwrap().next = next,
+            None => self.ends(list).head = next,
+        }
+        match next {
+            Some(next) => self.nodes.get_mut(&next).unwrap().prev = prev,
+            None => self.ends(list).tail = prev,
+        }
+        self.ends(list).len -= 1;
+    }
+
+    fn push_head(&mut self, key: K, list: LruList) {
+        let old_head = self.ends(list).head;
+        let node = self.nodes.get_mut(&key).unwrap();
+        node.list = list;
+        node.referenced = false;
+        node.prev = None;
+        node.next = old_head;
+        match old_head {
+            Some(old_head) => self.nodes.get_mut(&old_head).unwrap().prev = Some(key),
+            None => self.ends(list).tail = Some(key),
+        }
+        let ends = self.ends(list);
+        ends.head = Some(key);
+        ends.len += 1;
+    }
+
+    fn move_to_head(&mut self, key: K, list: LruList) {
+        self.unlink(key);
+        self.push_head(key, list);
+    }
+
+    /// Add an entry to the inactive list, or mark it accessed if it is
+    /// already on a list
+    pub fn insert(&mut self, key: K) {
+        if self.nodes.contains_key(&key) {
+            self.mark_accessed(key);
+            return;
+        }
+        self.nodes.insert(key, LruNode { list: LruList::Inactive, referenced: false, prev: None, next: None });
+        self.push_head(key, LruList::Inactive);
+    }
+
+    /// Record a use of an entry
+    ///
+    /// The second use of an inactive entry promotes it to the active list.
+    pub fn mark_accessed(&mut self, key: K) {
+        let Some(node) = self.nodes.get_mut(&key) else { return };
+        if node.list == LruList::Inactive && node.referenced {
+            self.move_to_head(key, LruList::Active);
+        } else {
+            node.referenced = true;
+        }
+    }
+
+    /// Take an entry off the lists
+    pub fn remove(&mut self, key: K) -> bool {
+        if !self.nodes.contains_key(&key) {
+            return false;
+        }
+        self.unlink(key);
+        self.nodes.remove(&key);
+        true
+    }
+
+    /// The list an entry is on
+    pub fn list_of(&self, key: K) -> Option<LruList> {
+        self.nodes.get(&key).map(|node| node.list)
+    }
+
+    pub fn active_len(&self) -> usize {
+        self.active.len
+    }
+
+    pub fn inactive_len(&self) -> usize {
+        self.inactive.len
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Age the active list until it is no larger than the inactive list
+    ///
+    /// Entries referenced since the last scan get another round on the
+    /// active list; the others are demoted.
+    fn refill_inactive(&mut self) {
+        let mut budget = self.active.len;
+        while self.active.len > self.inactive.len && budget > 0 {
+            budget -= 1;
+            let Some(key) = self.active.tail else { break };
+            let referenced = self.nodes.get(&key).is_some_and(|node| node.referenced);
+            let list = if referenced { LruList::Active } else { LruList::Inactive };
+            self.move_to_head(key, list);
+        }
+    }
+
+    /// Remove and return the least recently used entry that has not been
+    /// referenced since it was last scanned
+    ///
+    /// Referenced inactive entries met on the way are promoted instead.
+    pub fn pop_victim(&mut self) -> Option<K> {
+        let mut budget = self.nodes.len() * 2;
+        while budget > 0 {
+            budget -= 1;
+            self.refill_inactive();
+            let key = self.inactive.tail.or(self.active.tail)?;
+            if self.nodes.get(&key).is_some_and(|node| node.referenced) {
+                self.move_to_head(key, LruList::Active);
+                continue;
+            }
+            self.remove(key);
+            return Some(key);
+        }
+        // Everything keeps getting referenced; fall back to plain LRU order
+        let key = self.inactive.tail.or(self.active.tail)?;
+        self.remove(key);
+        Some(key)
+    }
+}
+
+/// A cache that can give memory back under pressure
+pub trait Shrinker: Send + Sync {
+    /// Name shown in `/proc/vmstat`
+    fn name(&self) -> &str;
+
+    /// Pages on the (active, inactive) lists
+    fn lru_pages(&self) -> (usize, usize);
+
+    /// Drop up to `nr_pages` clean pages, least recently used first
+    ///
+    /// Called from the allocator when an allocation fails, so it must not
+    /// wait for locks (use `try_lock` and reclaim nothing if contended).
+    ///
+    /// # Returns
+    /// The number of pages freed
+    fn reclaim(&self, nr_pages: usize) -> usize;
+}
+
+static SHRINKERS: Mutex<Vec<Weak<dyn Shrinker>>> = Mutex::new(Vec::new());
+
+/// Set while a reclaim pass runs so that an allocation failure inside it
+/// does not recurse
+static RECLAIM_IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+
+/// Number of reclaim passes since boot
+static RECLAIM_RUNS: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of pages reclaimed since boot
+static PAGES_RECLAIMED: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of private anonymous pages
+static ANON_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+/// Register a cache with the reclaimer
+///
+/// The cache stays registered until it is dropped.
+pub fn register_shrinker(shrinker: Weak<dyn Shrinker>) {
+    let mut shrinkers = SHRINKERS.lock();
+    shrinkers.retain(|shrinker| shrinker.strong_count() > 0);
+    shrinkers.push(shrinker);
+}
+
+/// Reclaim clean cache pages
+///
+/// # Arguments
+/// * `nr_pages` - Number of pages needed (at least [`RECLAIM_BATCH_PAGES`]
+///   are reclaimed if possible)
+///
+/// # Returns
+/// The number of pages freed
+pub fn try_to_free_pages(nr_pages: usize) -> usize {
+    if RECLAIM_IN_PROGRESS.swap(true, Ordering::AcqRel) {
+        return 0;
+    }
+    let target = nr_pages.max(RECLAIM_BATCH_PAGES);
+    let mut freed = 0;
+    if let Some(shrinkers) = SHRINKERS.try_lock() {
+        for shrinker in shrinkers.iter().filter_map(Weak::upgrade) {
+            if freed >= target {
+                break;
+            }
+            freed += shrinker.reclaim(target - freed);
+        }
+    }
+    RECLAIM_RUNS.fetch_add(1, Ordering::Relaxed);
+    PAGES_RECLAIMED.fetch_add(freed, Ordering::Relaxed);
+    RECLAIM_IN_PROGRESS.store(false, Ordering::Release);
+    freed
+}
+
+/// Account anonymous pages that were given a frame
+pub fn anon_pages_added(count: usize) {
+    ANON_PAGES.fetch_add(count, Ordering::Relaxed);
+}
+
+/// Account anonymous pages whose frame was freed
+pub fn anon_pages_removed(count: usize) {
+    ANON_PAGES.fetch_sub(count, Ordering::Relaxed);
+}
+
+/// Number of private anonymous pages
+pub fn anon_pages() -> usize {
+    ANON_PAGES.load(Ordering::Relaxed)
+}
+
+/// Render the LRU sizes and reclaim counters (the format of `/proc/vmstat`)
+pub fn format_vmstat() -> String {
+    let mut active = 0;
+    let mut inactive = 0;
+    let mut caches = String::new();
+    for shrinker in SHRINKERS.lock().iter().filter_map(Weak::upgrade) {
+        let (cache_active, cache_inactive) = shrinker.lru_pages();
+        active += cache_active;
+        inactive += cache_inactive;
+        let _ = writeln!(caches, "{} {} {}", shrinker.name(), cache_active, cache_inactive);
+    }
+
+    let mut out = String::new();
+    let _ = writeln!(out, "nr_active_file {}", active);
+    let _ = writeln!(out, "nr_inactive_file {}", inactive);
+    let _ = writeln!(out, "nr_anon_pages {}", anon_pages());
+    let _ = writeln!(out, "pgsteal {}", PAGES_RECLAIMED.load(Ordering::Relaxed));
+    let _ = writeln!(out, "reclaim_runs {}", RECLAIM_RUNS.load(Ordering::Relaxed));
+    let _ = writeln!(out, "page_size {}", PAGE_SIZE);
+    out.push_str("# cache active inactive\n");
+    out.push_str(&caches);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::sync::Arc;
+
+    #[test_case]
+    fn test_lru_lists_promote_and_reclaim_in_order() {
+        let mut lru = LruLists::new();
+        for key in 0..4u32 {
+            lru.insert(key);
+        }
+        assert_eq!(lru.inactive_len(), 4);
+
+        // Two uses promote an entry
+        lru.mark_accessed(1);
+        lru.mark_accessed(1);
+        assert_eq!(lru.list_of(1), Some(LruList::Active));
+
+        // One use buys a second chance
+        lru.mark_accessed(0);
+        assert_eq!(lru.pop_victim(), Some(2));
+        assert_eq!(lru.pop_victim(), Some(3));
+        assert_eq!(lru.list_of(0), Some(LruList::Active));
+
+        assert!(lru.remove(0));
+        assert_eq!(lru.pop_victim(), Some(1));
+        assert_eq!(lru.pop_victim(), None);
+        assert_eq!(lru.len(), 0);
+    }
+
+    struct TestCache {
+        pages: Mutex<usize>,
+    }
+
+    impl Shrinker for TestCache {
+        fn name(&self) -> &str {
+            "test"
+        }
+
+        fn lru_pages(&self) -> (usize, usize) {
+            (0, *self.pages.lock())
+        }
+
+        fn reclaim(&self, nr_pages: usize) -> usize {
+            let mut pages = self.pages.lock();
+            let freed = nr_pages.min(*pages);
+            *pages -= freed;
+            freed
+        }
+    }
+
+    #[test_case]
+    fn test_try_to_free_pages_uses_shrinkers() {
+        let cache = Arc::new(TestCache { pages: Mutex::new(RECLAIM_BATCH_PAGES + 8) });
+        register_shrinker(Arc::downgrade(&(cache.clone() as Arc<dyn Shrinker>)));
+        assert!(format_vmstat().contains("test 0 40"));
+
+        // Other caches may be asked first; together they free a full batch
+        assert!(try_to_free_pages(1) >= RECLAIM_BATCH_PAGES);
+        drop(cache);
+        assert!(!format_vmstat().contains("test "));
+    }
+}
@@ -0,0 +1,551 @@
+//! Protocol addresses
+//!
+//! Hardware addresses are the `MacAddress` of the device layer; this module
+//! has the addresses of the protocols above it. IPv4-mapped IPv6 addresses
+//! (::ffff:a.b.c.d) are IPv4 addresses to the stack; sockets of either
+//! family name them as [`IpEndpoint`]s.
+
+use core::fmt;
+
+use alloc::vec::Vec;
+
+use crate::abi::error::KernelError;
+
+/// An IPv4 address, in network byte order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ipv4Address(pub [u8; 4]);
+
+impl Ipv4Address {
+    pub const UNSPECIFIED: Self = Self([0, 0, 0, 0]);
+    pub const BROADCAST: Self = Self([255, 255, 255, 255]);
+
+    pub const fn new(a: u8, b: u8, c: u8, d: u8) -> Self {
+        Self([a, b, c, d])
+    }
+
+    /// The address in the first four bytes of `bytes`
+    pub fn from_slice(bytes: &[u8]) -> Option<Self> {
+        Some(Self(bytes.get(..4)?.try_into().ok()?))
+    }
+
+    pub const fn from_u32(value: u32) -> Self {
+        Self(value.to_be_bytes())
+    }
+
+    pub const fn to_u32(self) -> u32 {
+        u32::from_be_bytes(self.0)
+    }
+
+    pub const fn octets(&self) -> [u8; 4] {
+        self.0
+    }
+
+    pub fn is_unspecified(&self) -> bool {
+        *self == Self::UNSPECIFIED
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        *self == Self::BROADCAST
+    }
+
+    /// 224.0.0.0/4
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] & 0xF0 == 0xE0
+    }
+
+    /// 127.0.0.0/8
+    pub fn is_loopback(&self) -> bool {
+        self.0[0] == 127
+    }
+
+    /// Parse dotted-decimal notation
+    pub fn parse(text: &str) -> Result<Self, KernelError> {
+        let mut octets = [0u8; 4];
+        let mut parts = text.split('.');
+        for octet in &mut octets {
+            let part = parts.next().ok_or(KernelError::InvalidArgument)?;
+            if part.is_empty() || part.len() > 3 || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(KernelError::InvalidArgument);
+            }
+            *octet = part.parse().map_err(|_| KernelError::InvalidArgument)?;
+        }
+        if parts.next().is_some() {
+            return Err(KernelError::InvalidArgument);
+        }
+        Ok(Self(octets))
+    }
+}
+
+impl fmt::Display for Ipv4Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [a, b, c, d] = self.0;
+        write!(f, "{}.{}.{}.{}", a, b, c, d)
+    }
+}
+
+/// An IPv4 address with the length of the prefix of its network
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Cidr {
+    pub address: Ipv4Address,
+    pub prefix_len: u8,
+}
+
+impl Ipv4Cidr {
+    /// Fails with `InvalidArgument` if `prefix_len` is over 32
+    pub fn new(address: Ipv4Address, prefix_len: u8) -> Result<Self, KernelError> {
+        if prefix_len > 32 {
+            return Err(KernelError::InvalidArgument);
+        }
+        Ok(Self { address, prefix_len })
+    }
+
+    pub fn netmask(&self) -> Ipv4Address {
+        Ipv4Address::from_u32(u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0))
+    }
+
+    /// The address with the host part cleared
+    pub fn network(&self) -> Ipv4Address {
+        Ipv4Address::from_u32(self.address.to_u32() & self.netmask().to_u32())
+    }
+
+    /// The broadcast address of the network
+    pub fn broadcast(&self) -> Ipv4Address {
+        Ipv4Address::from_u32(self.address.to_u32() | !self.netmask().to_u32())
+    }
+
+    /// Whether `address` is in the network
+    pub fn contains(&self, address: Ipv4Address) -> bool {
+        address.to_u32() & self.netmask().to_u32() == self.network().to_u32()
+    }
+
+    /// Parse `a.b.c.d/len`, or a lone address as a network of one
+    pub fn parse(text: &str) -> Result<Self, KernelError> {
+        let Some((address, prefix_len)) = text.split_once('/') else {
+            return Self::new(Ipv4Address::parse(text)?, 32);
+        };
+        if prefix_len.is_empty() || !prefix_len.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(KernelError::InvalidArgument);
+        }
+        Self::new(Ipv4Address::parse(address)?, prefix_len.parse().map_err(|_| KernelError::InvalidArgument)?)
+    }
+}
+
+impl fmt::Display for Ipv4Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+/// An IPv6 address, in network byte order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ipv6Address(pub [u8; 16]);
+
+impl Ipv6Address {
+    pub const UNSPECIFIED: Self = Self([0; 16]);
+    pub const LOOPBACK: Self = Self::new([0, 0, 0, 0, 0, 0, 0, 1]);
+    /// ff02::1, the nodes of the link
+    pub const ALL_NODES: Self = Self::new([0xFF02, 0, 0, 0, 0, 0, 0, 1]);
+    /// ff02::2, the routers of the link
+    pub const ALL_ROUTERS: Self = Self::new([0xFF02, 0, 0, 0, 0, 0, 0, 2]);
+
+    pub const fn new(segments: [u16; 8]) -> Self {
+        let mut bytes = [0u8; 16];
+        let mut index = 0;
+        while index < 8 {
+            let [high, low] = segments[index].to_be_bytes();
+            bytes[2 * index] = high;
+            bytes[2 * index + 1] = low;
+            index += 1;
+        }
+        Self(bytes)
+    }
+
+    /// The address in the first sixteen bytes of `bytes`
+    pub fn from_slice(bytes: &[u8]) -> Option<Self> {
+        Some(Self(bytes.get(..16)?.try_into().ok()?))
+    }
+
+    pub const fn octets(&self) -> [u8; 16] {
+        self.0
+    }
+
+    pub fn segments(&self) -> [u16; 8] {
+        core::array::from_fn(|index| u16::from_be_bytes([self.0[2 * index], self.0[2 * index + 1]]))
+    }
+
+    pub fn is_unspecified(&self) -> bool {
+        *self == Self::UNSPECIFIED
+    }
+
+    pub fn is_loopback(&self) -> bool {
+        *self == Self::LOOPBACK
+    }
+
+    /// ff00::/8
+    pub fn is_multicast(&self) -> bool {
+        self.0[0] == 0xFF
+    }
+
+    /// fe80::/10
+    pub fn is_link_local(&self) -> bool {
+        self.0[0] == 0xFE && self.0[1] & 0xC0 == 0x80
+    }
+
+    /// The solicited-node multicast group of the address, ff02::1:ffXX:XXXX
+    /// with its last 24 bits (RFC 4291)
+    pub fn solicited_node(&self) -> Self {
+        let mut group = Self::new([0xFF02, 0, 0, 0, 0, 1, 0xFF00, 0]);
+        group.0[13..].copy_from_slice(&self.0[13..]);
+        group
+    }
+
+    /// `prefix` with its last 64 bits replaced by `interface_id`
+    pub fn with_interface_id(prefix: Self, interface_id: [u8; 8]) -> Self {
+        let mut address = prefix;
+        address.0[8..].copy_from_slice(&interface_id);
+        address
+    }
+
+    /// The IPv4-mapped address of `address`, ::ffff:a.b.c.d
+    pub fn from_ipv4_mapped(address: Ipv4Address) -> Self {
+        let mut bytes = [0u8; 16];
+        bytes[10] = 0xFF;
+        bytes[11] = 0xFF;
+        bytes[12..].copy_from_slice(&address.octets());
+        Self(bytes)
+    }
+
+    /// The IPv4 address this one maps, if it is IPv4-mapped
+    pub fn to_ipv4_mapped(&self) -> Option<Ipv4Address> {
+        (self.0[..10] == [0; 10] && self.0[10..12] == [0xFF, 0xFF]).then(|| Ipv4Address::from_slice(&self.0[12..]).unwrap())
+    }
+
+    /// Parse the text form of RFC 4291: eight groups of hexadecimal digits,
+    /// the longest run of zero groups possibly written `::`, the last two
+    /// possibly in dotted-decimal notation
+    pub fn parse(text: &str) -> Result<Self, KernelError> {
+        /// The groups of a side of `::`
+        fn groups(text: &str, last: bool) -> Result<Vec<u16>, KernelError> {
+            let mut groups = Vec::new();
+            if text.is_empty() {
+                return Ok(groups);
+            }
+            let parts: Vec<&str> = text.split(':').collect();
+            for (index, part) in parts.iter().enumerate() {
+                if last && index + 1 == parts.len() && part.contains('.') {
+                    let [a, b, c, d] = Ipv4Address::parse(part)?.octets();
+                    groups.push(u16::from_be_bytes([a, b]));
+                    groups.push(u16::from_be_bytes([c, d]));
+                } else if part.is_empty() || part.len() > 4 || !part.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Err(KernelError::InvalidArgument);
+                } else {
+                    groups.push(u16::from_str_radix(part, 16).map_err(|_| KernelError::InvalidArgument)?);
+                }
+            }
+            Ok(groups)
+        }
+
+        let mut segments = [0u16; 8];
+        match text.split_once("::") {
+            Some((head, tail)) => {
+                let head = groups(head, false)?;
+                let tail = groups(tail, true)?;
+                if head.len() + tail.len() > 7 {
+                    return Err(KernelError::InvalidArgument);
+                }
+                segments[..head.len()].copy_from_slice(&head);
+                segments[8 - tail.len()..].copy_from_slice(&tail);
+            }
+            None => {
+                let all = groups(text, true)?;
+                if all.len() != 8 {
+                    return Err(KernelError::InvalidArgument);
+                }
+                segments.copy_from_slice(&all);
+            }
+        }
+        Ok(Self::new(segments))
+    }
+}
+
+impl fmt::Display for Ipv6Address {
+    /// The text form of RFC 5952: lowercase without leading zeros, the
+    /// longest run of two or more zero groups written `::`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(address) = self.to_ipv4_mapped() {
+            return write!(f, "::ffff:{}", address);
+        }
+        let segments = self.segments();
+        let mut longest = (0, 0);
+        let mut start = 0;
+        while start < 8 {
+            let len = segments[start..].iter().take_while(|&&segment| segment == 0).count();
+            if len > longest.1 {
+                longest = (start, len);
+            }
+            start += len.max(1);
+        }
+        let write_groups = |f: &mut fmt::Formatter<'_>, groups: &[u16]| {
+            for (index, group) in groups.iter().enumerate() {
+                if index > 0 {
+                    f.write_str(":")?;
+                }
+                write!(f, "{:x}", group)?;
+            }
+            Ok(())
+        };
+        if longest.1 < 2 {
+            return write_groups(f, &segments);
+        }
+        write_groups(f, &segments[..longest.0])?;
+        f.write_str("::")?;
+        write_groups(f, &segments[longest.0 + longest.1..])
+    }
+}
+
+/// An IPv6 address with the length of the prefix of its network
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv6Cidr {
+    pub address: Ipv6Address,
+    pub prefix_len: u8,
+}
+
+impl Ipv6Cidr {
+    /// Fails with `InvalidArgument` if `prefix_len` is over 128
+    pub fn new(address: Ipv6Address, prefix_len: u8) -> Result<Self, KernelError> {
+        if prefix_len > 128 {
+            return Err(KernelError::InvalidArgument);
+        }
+        Ok(Self { address, prefix_len })
+    }
+
+    /// The address with the interface part cleared
+    pub fn network(&self) -> Ipv6Address {
+        let mask = u128::MAX.checked_shl(128 - self.prefix_len as u32).unwrap_or(0);
+        Ipv6Address((u128::from_be_bytes(self.address.0) & mask).to_be_bytes())
+    }
+
+    /// Whether `address` is in the network
+    pub fn contains(&self, address: Ipv6Address) -> bool {
+        Self { address, prefix_len: self.prefix_len }.network() == self.network()
+    }
+
+    /// Parse `address/len`, or a lone address as a network of one
+    pub fn parse(text: &str) -> Result<Self, KernelError> {
+        let Some((address, prefix_len)) = text.split_once('/') else {
+            return Self::new(Ipv6Address::parse(text)?, 128);
+        };
+        if prefix_len.is_empty() || !prefix_len.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(KernelError::InvalidArgument);
+        }
+        Self::new(Ipv6Address::parse(address)?, prefix_len.parse().map_err(|_| KernelError::InvalidArgument)?)
+    }
+}
+
+impl fmt::Display for Ipv6Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+/// The version of IP an address or a socket is of
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IpFamily {
+    V4,
+    V6,
+}
+
+impl IpFamily {
+    /// The address that stands for any address of the family
+    pub fn unspecified(&self) -> IpAddress {
+        match self {
+            IpFamily::V4 => IpAddress::V4(Ipv4Address::UNSPECIFIED),
+            IpFamily::V6 => IpAddress::V6(Ipv6Address::UNSPECIFIED),
+        }
+    }
+}
+
+/// An address of either version of IP
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum IpAddress {
+    V4(Ipv4Address),
+    V6(Ipv6Address),
+}
+
+impl IpAddress {
+    pub fn family(&self) -> IpFamily {
+        match self {
+            IpAddress::V4(_) => IpFamily::V4,
+            IpAddress::V6(_) => IpFamily::V6,
+        }
+    }
+
+    pub fn is_unspecified(&self) -> bool {
+        match self {
+            IpAddress::V4(address) => address.is_unspecified(),
+            IpAddress::V6(address) => address.is_unspecified(),
+        }
+    }
+
+    /// A multicast address, or an IPv4 limited broadcast
+    pub fn is_group(&self) -> bool {
+        match self {
+            IpAddress::V4(address) => address.is_broadcast() || address.is_multicast(),
+            IpAddress::V6(address) => address.is_multicast(),
+        }
+    }
+
+    /// Whether a socket bound to this address receives what is sent to
+    /// `address`: the unspecified address stands for every address of its
+    /// family, and the unspecified IPv6 address for those of both
+    pub fn covers(&self, address: IpAddress) -> bool {
+        *self == address
+            || match self {
+                IpAddress::V4(bound) => bound.is_unspecified() && address.family() == IpFamily::V4,
+                IpAddress::V6(bound) => bound.is_unspecified(),
+            }
+    }
+
+    /// The address with IPv4-mapped IPv6 addresses turned into the IPv4
+    /// addresses they map, which is how the stack keeps them
+    pub fn canonical(self) -> Self {
+        match self {
+            IpAddress::V6(address) => address.to_ipv4_mapped().map_or(self, IpAddress::V4),
+            address => address,
+        }
+    }
+
+    /// Parse an IPv4 or IPv6 address
+    pub fn parse(text: &str) -> Result<Self, KernelError> {
+        if text.contains(':') {
+            Ipv6Address::parse(text).map(IpAddress::V6)
+        } else {
+            Ipv4Address::parse(text).map(IpAddress::V4)
+        }
+    }
+}
+
+impl From<Ipv4Address> for IpAddress {
+    fn from(address: Ipv4Address) -> Self {
+        IpAddress::V4(address)
+    }
+}
+
+impl From<Ipv6Address> for IpAddress {
+    fn from(address: Ipv6Address) -> Self {
+        IpAddress::V6(address)
+    }
+}
+
+impl fmt::Display for IpAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpAddress::V4(address) => address.fmt(f),
+            IpAddress::V6(address) => address.fmt(f),
+        }
+    }
+}
+
+/// An IP address and a port: the name of an internet socket, written
+/// `a.b.c.d:port` or `[address]:port`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IpEndpoint {
+    pub address: IpAddress,
+    pub port: u16,
+}
+
+impl IpEndpoint {
+    pub const fn new(address: IpAddress, port: u16) -> Self {
+        Self { address, port }
+    }
+
+    pub fn parse(text: &str) -> Result<Self, KernelError> {
+        let (address, port) = text.rsplit_once(':').ok_or(KernelError::InvalidArgument)?;
+        if port.is_empty() || !port.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(KernelError::InvalidArgument);
+        }
+        let address = match address.strip_prefix('[') {
+            Some(address) => {
+                IpAddress::V6(Ipv6Address::parse(address.strip_suffix(']').ok_or(KernelError::InvalidArgument)?)?)
+            }
+            None => IpAddress::V4(Ipv4Address::parse(address)?),
+        };
+        Ok(Self { address, port: port.parse().map_err(|_| KernelError::InvalidArgument)? })
+    }
+
+    /// The endpoint with its address made canonical, see
+    /// [`IpAddress::canonical`]
+    pub fn canonical(self) -> Self {
+        Self { address: self.address.canonical(), port: self.port }
+    }
+}
+
+impl fmt::Display for IpEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.address {
+            IpAddress::V4(address) => write!(f, "{}:{}", address, self.port),
+            IpAddress::V6(address) => write!(f, "[{}]:{}", address, self.port),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_ipv4_cidr() {
+        let cidr = Ipv4Cidr::new(Ipv4Address::new(10, 0, 2, 15), 24).unwrap();
+        assert_eq!(cidr.netmask(), Ipv4Address::new(255, 255, 255, 0));
+        assert_eq!(cidr.network(), Ipv4Address::new(10, 0, 2, 0));
+        assert_eq!(cidr.broadcast(), Ipv4Address::new(10, 0, 2, 255));
+        assert!(cidr.contains(Ipv4Address::new(10, 0, 2, 2)));
+        assert!(!cidr.contains(Ipv4Address::new(10, 0, 3, 2)));
+        assert!(Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 0).unwrap().contains(Ipv4Address::new(8, 8, 8, 8)));
+        assert_eq!(Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 33), Err(KernelError::InvalidArgument));
+        assert_eq!(alloc::format!("{}", cidr), "10.0.2.15/24");
+        assert_eq!(Ipv4Cidr::parse("10.0.2.15/24"), Ok(cidr));
+        assert_eq!(Ipv4Cidr::parse("10.0.2.15").map(|cidr| cidr.prefix_len), Ok(32));
+        assert_eq!(Ipv4Cidr::parse("10.0.2.15/"), Err(KernelError::InvalidArgument));
+
+        let endpoint = IpEndpoint::parse("10.0.2.15:68").unwrap();
+        assert_eq!(endpoint, IpEndpoint::new(Ipv4Address::new(10, 0, 2, 15).into(), 68));
+        assert_eq!(alloc::format!("{}", endpoint), "10.0.2.15:68");
+        for bad in ["10.0.2.15", "10.0.2:68", "10.0.2.256:68", "10.0.2.15:65536", "10.0.2.15:", "1.2.3.4.5:1"] {
+            assert_eq!(IpEndpoint::parse(bad), Err(KernelError::InvalidArgument));
+        }
+    }
+
+    #[test_case]
+    fn test_ipv6_address() {
+        let address = Ipv6Address::parse("2001:db8::1").unwrap();
+        assert_eq!(address, Ipv6Address::new([0x2001, 0xDB8, 0, 0, 0, 0, 0, 1]));
+        assert_eq!(Ipv6Address::parse("::"), Ok(Ipv6Address::UNSPECIFIED));
+        assert_eq!(Ipv6Address::parse("ff02::1"), Ok(Ipv6Address::ALL_NODES));
+        assert_eq!(Ipv6Address::parse("::ffff:10.0.2.15").unwrap().to_ipv4_mapped(), Some(Ipv4Address::new(10, 0, 2, 15)));
+        for bad in ["", ":", "1::2::3", "1:2:3:4:5:6:7", "1:2:3:4:5:6:7:8:9", "12345::", "fe80::g", "1:2:3:4:5:6:7::8"] {
+            assert_eq!(Ipv6Address::parse(bad), Err(KernelError::InvalidArgument));
+        }
+        // Written as RFC 5952 asks
+        for text in ["2001:db8::1", "::", "::1", "fe80::", "2001:db8:0:1:1:1:1:1", "2001:0:0:1::1", "::ffff:10.0.2.15"] {
+            assert_eq!(alloc::format!("{}", Ipv6Address::parse(text).unwrap()), text);
+        }
+        assert_eq!(alloc::format!("{}", Ipv6Address::parse("2001:DB8:0:0:1:0:0:1").unwrap()), "2001:db8::1:0:0:1");
+
+        assert!(Ipv6Address::parse("fe80::1").unwrap().is_link_local());
+        assert!(!Ipv6Address::parse("fec0::1").unwrap().is_link_local());
+        assert_eq!(address.solicited_node(), Ipv6Address::parse("ff02::1:ff00:1").unwrap());
+        let cidr = Ipv6Cidr::parse("2001:db8::1/64").unwrap();
+        assert_eq!(cidr.network(), Ipv6Address::parse("2001:db8::").unwrap());
+        assert!(cidr.contains(Ipv6Address::parse("2001:db8::abcd").unwrap()));
+        assert!(!cidr.contains(Ipv6Address::parse("2001:db8:1::1").unwrap()));
+        assert_eq!(Ipv6Cidr::parse("::/129"), Err(KernelError::InvalidArgument));
+
+        let endpoint = IpEndpoint::parse("[2001:db8::1]:80").unwrap();
+        assert_eq!(endpoint, IpEndpoint::new(address.into(), 80));
+        assert_eq!(alloc::format!("{}", endpoint), "[2001:db8::1]:80");
+        assert_eq!(IpEndpoint::parse("2001:db8::1:80"), Err(KernelError::InvalidArgument));
+        let mapped = IpEndpoint::parse("[::ffff:10.0.2.15]:68").unwrap();
+        assert_eq!(mapped.canonical(), IpEndpoint::parse("10.0.2.15:68").unwrap());
+    }
+}
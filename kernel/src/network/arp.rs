@@ -0,0 +1,476 @@
+//! Address resolution (ARP)
+//!
+//! The ARP processor answers requests for the IPv4 addresses of the
+//! interface and learns the hardware addresses of its neighbors into the
+//! neighbor cache. Entries stay reachable for [`NEIGHBOR_REACHABLE_MS`] after
+//! they were last confirmed.
+//!
+//! IPv4 packets are sent to their next hop with [`send_ipv4`]. When the
+//! next hop is not in the cache, the packets wait in an incomplete entry
+//! while a request is broadcast every [`ARP_RETRY_INTERVAL_MS`]; after
+//! [`ARP_MAX_REQUESTS`] unanswered requests the entry and its packets are
+//! dropped.
+//!
+//! Gratuitous ARP (a sender announcing its own address) updates an entry
+//! the cache already has but never creates one or gets a reply, as RFC 826
+//! and RFC 5227 ask. [`announce`] sends one for an address of ours.
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::abi::error::KernelError;
+use crate::device::network::MacAddress;
+use crate::timer::{get_tick, ms_to_ticks};
+
+use super::address::Ipv4Address;
+use super::ethernet::{self, BROADCAST_MAC, ETHERTYPE_ARP, ETHERTYPE_IPV4, ETHERTYPE_STAGE};
+use super::manager::{NetworkInterface, NetworkManager};
+use super::packet::NetworkPacket;
+use super::pipeline::{PacketProcessor, ProcessResult};
+use super::InterfaceId;
+
+/// ARP over Ethernet for IPv4
+pub const ARP_PACKET_LEN: usize = 28;
+
+const HARDWARE_ETHERNET: u16 = 1;
+pub const ARP_OP_REQUEST: u16 = 1;
+pub const ARP_OP_REPLY: u16 = 2;
+
+/// How long a resolved neighbor is used without being confirmed
+pub const NEIGHBOR_REACHABLE_MS: u64 = 60_000;
+/// Time between two requests for an unresolved neighbor
+pub const ARP_RETRY_INTERVAL_MS: u64 = 1_000;
+/// Requests sent before a neighbor is given up
+pub const ARP_MAX_REQUESTS: u32 = 3;
+/// Packets kept per unresolved neighbor; older ones are dropped first
+pub const ARP_MAX_PENDING: usize = 8;
+
+/// An ARP packet for IPv4 over Ethernet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArpPacket {
+    pub operation: u16,
+    pub sender_mac: MacAddress,
+    pub sender_ip: Ipv4Address,
+    pub target_mac: MacAddress,
+    pub target_ip: Ipv4Address,
+}
+
+impl ArpPacket {
+    /// Parse a packet; `None` if it is short or not IPv4 over Ethernet
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < ARP_PACKET_LEN {
+            return None;
+        }
+        let hardware = u16::from_be_bytes([bytes[0], bytes[1]]);
+        let protocol = u16::from_be_bytes([bytes[2], bytes[3]]);
+        if hardware != HARDWARE_ETHERNET || protocol != ETHERTYPE_IPV4 || bytes[4] != 6 || bytes[5] != 4 {
+            return None;
+        }
+        Some(Self {
+            operation: u16::from_be_bytes([bytes[6], bytes[7]]),
+            sender_mac: MacAddress::from_slice(&bytes[8..14]).ok()?,
+            sender_ip: Ipv4Address::from_slice(&bytes[14..18])?,
+            target_mac: MacAddress::from_slice(&bytes[18..24]).ok()?,
+            target_ip: Ipv4Address::from_slice(&bytes[24..28])?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; ARP_PACKET_LEN] {
+        let mut bytes = [0; ARP_PACKET_LEN];
+        bytes[0..2].copy_from_slice(&HARDWARE_ETHERNET.to_be_bytes());
+        bytes[2..4].copy_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        bytes[4] = 6;
+        bytes[5] = 4;
+        bytes[6..8].copy_from_slice(&self.operation.to_be_bytes());
+        bytes[8..14].copy_from_slice(self.sender_mac.as_bytes());
+        bytes[14..18].copy_from_slice(&self.sender_ip.octets());
+        bytes[18..24].copy_from_slice(self.target_mac.as_bytes());
+        bytes[24..28].copy_from_slice(&self.target_ip.octets());
+        bytes
+    }
+
+    /// Whether the sender announces its own address
+    pub fn is_gratuitous(&self) -> bool {
+        self.sender_ip == self.target_ip
+    }
+}
+
+enum NeighborState {
+    /// Requests are out; `pending` is sent when a reply comes
+    Incomplete { requests: u32, next_request: u64, pending: Vec<NetworkPacket> },
+    Reachable { mac: MacAddress, expires: u64 },
+}
+
+/// An entry of the neighbor cache, as listed by [`NeighborCache::neighbors`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Neighbor<A = Ipv4Address> {
+    pub interface: InterfaceId,
+    pub address: A,
+    /// `None` while the neighbor is being resolved
+    pub mac: Option<MacAddress>,
+}
+
+/// What [`NeighborCache::resolve`] found
+pub enum Resolution {
+    /// The packet can be sent to the address at once
+    Resolved(MacAddress, NetworkPacket),
+    /// The packet is queued; a request is to be sent if `request` is set
+    Queued { request: bool },
+}
+
+/// Hardware addresses of neighbors by their protocol address: IPv4 ones
+/// for ARP, IPv6 ones for NDP
+pub struct NeighborCache<A = Ipv4Address> {
+    entries: Mutex<BTreeMap<(InterfaceId, A), NeighborState>>,
+}
+
+static NEIGHBOR_CACHE: NeighborCache = NeighborCache::new();
+
+/// The neighbor cache of the system
+pub fn neighbor_cache() -> &'static NeighborCache {
+    &NEIGHBOR_CACHE
+}
+
+impl<A: Ord + Copy> NeighborCache<A> {
+    pub const fn new() -> Self {
+        Self { entries: Mutex::new(BTreeMap::new()) }
+    }
+
+    /// The hardware address of `address` on `interface`, if it is reachable
+    pub fn lookup(&self, interface: InterfaceId, address: A, now: u64) -> Option<MacAddress> {
+        match self.entries.lock().get(&(interface, address)) {
+            Some(NeighborState::Reachable { mac, expires }) if *expires > now => Some(*mac),
+            _ => None,
+        }
+    }
+
+    /// Find the hardware address of `address`, or queue `packet` until it is
+    /// found; the packet is handed back when the address is known
+    pub fn resolve(&self, interface: InterfaceId, address: A, packet: NetworkPacket, now: u64) -> Resolution {
+        let mut entries = self.entries.lock();
+        let entry = entries.entry((interface, address)).or_insert(NeighborState::Incomplete {
+            requests: 0,
+            next_request: now,
+            pending: Vec::new(),
+        });
+        if let NeighborState::Reachable { mac, expires } = entry {
+            if *expires > now {
+                return Resolution::Resolved(*mac, packet);
+            }
+            *entry = NeighborState::Incomplete { requests: 0, next_request: now, pending: Vec::new() };
+        }
+        let NeighborState::Incomplete { requests, next_request, pending } = entry else {
+            unreachable!();
+        };
+        if pending.len() >= ARP_MAX_PENDING {
+            pending.remove(0);
+        }
+        pending.push(packet);
+        let request = *requests == 0;
+        if request {
+            *requests = 1;
+            *next_request = now + ms_to_ticks(ARP_RETRY_INTERVAL_MS);
+        }
+        Resolution::Queued { request }
+    }
+
+    /// Record that `address` is at `mac`, creating the entry only if
+    /// `create` is set; returns the packets that waited for it
+    pub fn update(
+        &self,
+        interface: InterfaceId,
+        address: A,
+        mac: MacAddress,
+        create: bool,
+        now: u64,
+    ) -> Vec<NetworkPacket> {
+        let mut entries = self.entries.lock();
+        let reachable = NeighborState::Reachable { mac, expires: now + ms_to_ticks(NEIGHBOR_REACHABLE_MS) };
+        match entries.get_mut(&(interface, address)) {
+            Some(entry) => match core::mem::replace(entry, reachable) {
+                NeighborState::Incomplete { pending, .. } => pending,
+                NeighborState::Reachable { .. } => Vec::new(),
+            },
+            None => {
+                if create {
+                    entries.insert((interface, address), reachable);
+                }
+                Vec::new()
+            }
+        }
+    }
+
+    /// Drop expired entries and those out of requests; returns the
+    /// neighbors to send another request for
+    pub fn expire(&self, now: u64) -> Vec<(InterfaceId, A)> {
+        let mut retries = Vec::new();
+        self.entries.lock().retain(|&(interface, address), entry| match entry {
+            NeighborState::Reachable { expires, .. } => *expires > now,
+            NeighborState::Incomplete { requests, next_request, .. } => {
+                if *next_request > now {
+                    true
+                } else if *requests >= ARP_MAX_REQUESTS {
+                    false
+                } else {
+                    *requests += 1;
+                    *next_request = now + ms_to_ticks(ARP_RETRY_INTERVAL_MS);
+                    retries.push((interface, address));
+                    true
+                }
+            }
+        });
+        retries
+    }
+
+    /// Forget the neighbors of `interface`
+    pub fn flush_interface(&self, interface: InterfaceId) {
+        self.entries.lock().retain(|&(id, _), _| id != interface);
+    }
+
+    pub fn neighbors(&self) -> Vec<Neighbor<A>> {
+        self.entries
+            .lock()
+            .iter()
+            .map(|(&(interface, address), entry)| Neighbor {
+                interface,
+                address,
+                mac: match entry {
+                    NeighborState::Reachable { mac, .. } => Some(*mac),
+                    NeighborState::Incomplete { .. } => None,
+                },
+            })
+            .collect()
+    }
+}
+
+fn send_arp(interface: &NetworkInterface, destination: MacAddress, arp: &ArpPacket) -> Result<(), KernelError> {
+    ethernet::send_frame(interface, destination, ETHERTYPE_ARP, NetworkPacket::outgoing(arp.to_bytes().to_vec()))
+}
+
+/// Broadcast a request for `address` on `interface`
+pub fn send_request(interface: &NetworkInterface, address: Ipv4Address) -> Result<(), KernelError> {
+    let request = ArpPacket {
+        operation: ARP_OP_REQUEST,
+        sender_mac: interface.mac_address()?,
+        sender_ip: interface.ipv4_source_for(address).unwrap_or(Ipv4Address::UNSPECIFIED),
+        target_mac: MacAddress::new([0; 6]),
+        target_ip: address,
+    };
+    send_arp(interface, BROADCAST_MAC, &request)
+}
+
+/// Broadcast a gratuitous request announcing that `address` is at the
+/// interface, so neighbors update their caches
+pub fn announce(interface: &NetworkInterface, address: Ipv4Address) -> Result<(), KernelError> {
+    let announcement = ArpPacket {
+        operation: ARP_OP_REQUEST,
+        sender_mac: interface.mac_address()?,
+        sender_ip: address,
+        target_mac: MacAddress::new([0; 6]),
+        target_ip: address,
+    };
+    send_arp(interface, BROADCAST_MAC, &announcement)
+}
+
+/// The hardware address IPv4 multicast `address` maps to (RFC 1112)
+fn multicast_mac(address: Ipv4Address) -> MacAddress {
+    let [_, b, c, d] = address.octets();
+    MacAddress::new([0x01, 0x00, 0x5E, b & 0x7F, c, d])
+}
+
+/// Send the IPv4 `packet` to `next_hop` on `interface`
+///
+/// If the hardware address of the next hop is not known, the packet is
+/// queued and sent once a reply comes in.
+pub fn send_ipv4(interface: &NetworkInterface, next_hop: Ipv4Address, packet: NetworkPacket) -> Result<(), KernelError> {
+    send_ipv4_with(neighbor_cache(), interface, next_hop, packet)
+}
+
+fn send_ipv4_with(
+    cache: &NeighborCache,
+    interface: &NetworkInterface,
+    next_hop: Ipv4Address,
+    packet: NetworkPacket,
+) -> Result<(), KernelError> {
+    let subnet_broadcast = interface.ipv4_addresses().iter().any(|cidr| cidr.prefix_len < 31 && cidr.broadcast() == next_hop);
+    if next_hop.is_broadcast() || subnet_broadcast {
+        return ethernet::send_frame(interface, BROADCAST_MAC, ETHERTYPE_IPV4, packet);
+    }
+    if next_hop.is_multicast() {
+        return ethernet::send_frame(interface, multicast_mac(next_hop), ETHERTYPE_IPV4, packet);
+    }
+    match cache.resolve(interface.id(), next_hop, packet, get_tick()) {
+        Resolution::Resolved(mac, packet) => ethernet::send_frame(interface, mac, ETHERTYPE_IPV4, packet),
+        Resolution::Queued { request: true } => send_request(interface, next_hop),
+        Resolution::Queued { request: false } => Ok(()),
+    }
+}
+
+/// Processor of ARP at the "ethertype" stage
+pub struct ArpProcessor {
+    cache: &'static NeighborCache,
+}
+
+impl ArpProcessor {
+    pub const fn new(cache: &'static NeighborCache) -> Self {
+        Self { cache }
+    }
+
+    fn handle(&self, interface: &Arc<NetworkInterface>, arp: &ArpPacket) {
+        // A probe (RFC 5227) has no sender address to learn
+        if !arp.sender_ip.is_unspecified() {
+            let for_us = interface.has_ipv4_address(arp.target_ip) && !arp.is_gratuitous();
+            let pending = self.cache.update(interface.id(), arp.sender_ip, arp.sender_mac, for_us, get_tick());
+            for packet in pending {
+                let _ = ethernet::send_frame(interface, arp.sender_mac, ETHERTYPE_IPV4, packet);
+            }
+        }
+        if arp.operation != ARP_OP_REQUEST || arp.is_gratuitous() || !interface.has_ipv4_address(arp.target_ip) {
+            return;
+        }
+        let Ok(local) = interface.mac_address() else {
+            return;
+        };
+        let reply = ArpPacket {
+            operation: ARP_OP_REPLY,
+            sender_mac: local,
+            sender_ip: arp.target_ip,
+            target_mac: arp.sender_mac,
+            target_ip: arp.sender_ip,
+        };
+        let _ = send_arp(interface, arp.sender_mac, &reply);
+    }
+}
+
+impl PacketProcessor for ArpProcessor {
+    fn name(&self) -> &'static str {
+        "arp"
+    }
+
+    fn process(&self, packet: &mut NetworkPacket) -> ProcessResult {
+        let Some(arp) = packet.consume_header("arp", ARP_PACKET_LEN).ok().and_then(ArpPacket::parse) else {
+            return ProcessResult::Dropped("Malformed ARP packet");
+        };
+        let Some(interface) = packet.interface() else {
+            return ProcessResult::Dropped("No interface");
+        };
+        self.handle(interface, &arp);
+        ProcessResult::Consumed
+    }
+}
+
+/// Timer of the neighbor cache: expire entries and repeat requests
+fn age(manager: &NetworkManager, now: u64) {
+    for (id, address) in neighbor_cache().expire(now) {
+        if let Some(interface) = manager.interface(id) {
+            let _ = send_request(&interface, address);
+        }
+    }
+}
+
+/// Register ARP with the pipeline of `manager`
+pub fn register(manager: &NetworkManager) -> Result<(), KernelError> {
+    manager
+        .pipeline()
+        .register_processor(ETHERTYPE_STAGE, ETHERTYPE_ARP as u64, Arc::new(ArpProcessor::new(neighbor_cache())))?;
+    manager.register_timer(age);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::network::{GenericNetworkDevice, NetworkDevice};
+    use crate::network::address::Ipv4Cidr;
+    use crate::network::ethernet::{EthernetHeader, ETHERNET_HEADER_LEN};
+    use crate::network::pipeline::PacketFate;
+    use alloc::vec;
+
+    const REMOTE_MAC: MacAddress = MacAddress::new([2, 0, 0, 0, 0, 1]);
+    const REMOTE_IP: Ipv4Address = Ipv4Address::new(10, 0, 2, 2);
+    const LOCAL_IP: Ipv4Address = Ipv4Address::new(10, 0, 2, 15);
+
+    #[test_case]
+    fn test_neighbor_cache_resolution() {
+        let cache = NeighborCache::new();
+        let resolution = cache.resolve(0, REMOTE_IP, NetworkPacket::outgoing(vec![1]), 0);
+        assert!(matches!(resolution, Resolution::Queued { request: true }));
+        let resolution = cache.resolve(0, REMOTE_IP, NetworkPacket::outgoing(vec![2]), 0);
+        assert!(matches!(resolution, Resolution::Queued { request: false }));
+        assert_eq!(cache.neighbors(), vec![Neighbor { interface: 0, address: REMOTE_IP, mac: None }]);
+
+        // Requests are repeated until the limit, then the entry goes
+        let retry = ms_to_ticks(ARP_RETRY_INTERVAL_MS);
+        assert!(cache.expire(0).is_empty());
+        assert_eq!(cache.expire(retry), vec![(0, REMOTE_IP)]);
+        assert_eq!(cache.expire(retry * 2), vec![(0, REMOTE_IP)]);
+        assert!(cache.expire(retry * 3).is_empty());
+        assert!(cache.neighbors().is_empty());
+
+        // A reply hands the waiting packets back
+        cache.resolve(0, REMOTE_IP, NetworkPacket::outgoing(vec![3]), 0);
+        let pending = cache.update(0, REMOTE_IP, REMOTE_MAC, false, 0);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(cache.lookup(0, REMOTE_IP, 1), Some(REMOTE_MAC));
+        assert!(matches!(cache.resolve(0, REMOTE_IP, NetworkPacket::outgoing(vec![4]), 1), Resolution::Resolved(REMOTE_MAC, _)));
+        let reachable = ms_to_ticks(NEIGHBOR_REACHABLE_MS);
+        assert_eq!(cache.lookup(0, REMOTE_IP, reachable), None);
+        cache.expire(reachable);
+        assert!(cache.neighbors().is_empty());
+
+        // Updates for unknown neighbors only create entries when asked to
+        assert!(cache.update(1, REMOTE_IP, REMOTE_MAC, false, 0).is_empty());
+        assert_eq!(cache.lookup(1, REMOTE_IP, 0), None);
+    }
+
+    #[test_case]
+    fn test_arp_request_reply() {
+        static CACHE: NeighborCache = NeighborCache::new();
+        let manager = NetworkManager::new();
+        ethernet::register(&manager).unwrap();
+        manager.pipeline().register_processor(ETHERTYPE_STAGE, ETHERTYPE_ARP as u64, Arc::new(ArpProcessor::new(&CACHE))).unwrap();
+        let mut device = GenericNetworkDevice::new("test0");
+        device.init_network().unwrap();
+        let device = Arc::new(device);
+        let local_mac = device.get_mac_address().unwrap();
+        let interface = manager.attach_device("test0", device.clone(), None, ethernet::ETHERNET_STAGE).unwrap();
+        interface.add_ipv4_address(Ipv4Cidr::new(LOCAL_IP, 24).unwrap()).unwrap();
+
+        let frame = |arp: ArpPacket| {
+            let header = EthernetHeader { destination: BROADCAST_MAC, source: arp.sender_mac, ethertype: ETHERTYPE_ARP };
+            ethernet::build_frame(&header, &arp.to_bytes())
+        };
+        // A gratuitous announcement of an unknown neighbor is not learned
+        let announcement = ArpPacket {
+            operation: ARP_OP_REQUEST,
+            sender_mac: REMOTE_MAC,
+            sender_ip: REMOTE_IP,
+            target_mac: MacAddress::new([0; 6]),
+            target_ip: REMOTE_IP,
+        };
+        assert_eq!(manager.receive(&interface, frame(announcement)), PacketFate::Consumed);
+        assert!(CACHE.neighbors().is_empty());
+        assert!(device.take_transmitted_packets().is_empty());
+
+        // A request for our address is answered and its sender learned
+        let request = ArpPacket { target_ip: LOCAL_IP, ..announcement };
+        assert_eq!(manager.receive(&interface, frame(request)), PacketFate::Consumed);
+        assert_eq!(CACHE.neighbors(), vec![Neighbor { interface: interface.id(), address: REMOTE_IP, mac: Some(REMOTE_MAC) }]);
+        let sent = device.take_transmitted_packets();
+        assert_eq!(sent.len(), 1);
+        let header = EthernetHeader::parse(sent[0].as_slice()).unwrap();
+        assert_eq!((header.destination, header.source, header.ethertype), (REMOTE_MAC, local_mac, ETHERTYPE_ARP));
+        let reply = ArpPacket::parse(&sent[0].as_slice()[ETHERNET_HEADER_LEN..]).unwrap();
+        assert_eq!(reply.operation, ARP_OP_REPLY);
+        assert_eq!((reply.sender_mac, reply.sender_ip, reply.target_ip), (local_mac, LOCAL_IP, REMOTE_IP));
+
+        // Packets for a known neighbor go out directly
+        send_ipv4_with(&CACHE, &interface, REMOTE_IP, NetworkPacket::outgoing(vec![0x45])).unwrap();
+        let sent = device.take_transmitted_packets();
+        assert_eq!(EthernetHeader::parse(sent[0].as_slice()).unwrap().ethertype, ETHERTYPE_IPV4);
+
+        assert_eq!(manager.receive(&interface, frame(request)[..30].to_vec()), PacketFate::Dropped("Malformed ARP packet"));
+    }
+}
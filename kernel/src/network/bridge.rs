@@ -0,0 +1,376 @@
+//! Software bridge
+//!
+//! A bridge joins interfaces, its ports, into one ethernet segment. It
+//! learns on which port each source address was seen and forwards a frame
+//! to the port of its destination, or floods it to every other port when
+//! the destination is unknown, broadcast or multicast. Learned entries of
+//! the forwarding database expire after [`FDB_AGEING_MS`] without traffic.
+//!
+//! The bridge is an interface itself, which is given the addresses: frames
+//! received on a port for the address of the bridge, broadcasts and
+//! multicasts are received on it, and what the stack sends on it goes out
+//! of the ports as if received on none of them. A port takes the frames of
+//! its interface with an [`RxHandler`], so an enslaved interface receives
+//! nothing of its own until released.
+
+use core::any::Any;
+
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::{Mutex, Once, RwLock};
+
+use crate::abi::error::KernelError;
+use crate::device::network::{
+    DevicePacket, MacAddress, NetworkDevice, NetworkInterfaceConfig, NetworkStats,
+};
+use crate::device::{Device, DeviceType};
+use crate::object::capability::{ControlOps, MemoryMappingOps};
+use crate::timer::{get_tick, ms_to_ticks};
+
+use super::buffer::PacketBuffer;
+use super::ethernet::{EthernetHeader, ETHERNET_STAGE};
+use super::manager::{get_network_manager, NetworkInterface, NetworkManager, RxHandler};
+use super::packet::NetworkPacket;
+use super::pipeline::PacketFate;
+use super::InterfaceId;
+
+/// MTU of a bridge
+pub const BRIDGE_MTU: usize = 1500;
+
+/// Time a learned address stays in the forwarding database without traffic
+pub const FDB_AGEING_MS: u64 = 300_000;
+
+/// Where an address was last seen
+#[derive(Debug, Clone, Copy)]
+struct FdbEntry {
+    port: InterfaceId,
+    seen: u64,
+}
+
+/// A bridge, the device of its interface and the receive handler of its
+/// ports
+pub struct BridgeDevice {
+    /// The bridge itself, handed to its ports
+    this: Weak<BridgeDevice>,
+    mac: MacAddress,
+    /// The interface of the bridge, once attached
+    interface: Once<Weak<NetworkInterface>>,
+    ports: RwLock<Vec<Arc<NetworkInterface>>>,
+    /// Port of each address seen, by address
+    fdb: Mutex<BTreeMap<[u8; 6], FdbEntry>>,
+    stats: Mutex<NetworkStats>,
+}
+
+impl BridgeDevice {
+    fn new() -> Arc<Self> {
+        Arc::new_cyclic(|this| Self {
+            this: this.clone(),
+            mac: MacAddress::random_local(),
+            interface: Once::new(),
+            ports: RwLock::new(Vec::new()),
+            fdb: Mutex::new(BTreeMap::new()),
+            stats: Mutex::new(NetworkStats::default()),
+        })
+    }
+
+    pub fn ports(&self) -> Vec<Arc<NetworkInterface>> {
+        self.ports.read().clone()
+    }
+
+    /// The port `destination` was seen on
+    fn port_of(&self, destination: MacAddress) -> Option<InterfaceId> {
+        self.fdb.lock().get(destination.as_bytes()).map(|entry| entry.port)
+    }
+
+    fn learn(&self, source: MacAddress, port: InterfaceId, now: u64) {
+        if source.is_unicast() {
+            self.fdb.lock().insert(*source.as_bytes(), FdbEntry { port, seen: now });
+        }
+    }
+
+    /// Forget the addresses not seen for [`FDB_AGEING_MS`]
+    fn age(&self, now: u64) {
+        let lifetime = ms_to_ticks(FDB_AGEING_MS);
+        self.fdb.lock().retain(|_, entry| now.wrapping_sub(entry.seen) < lifetime);
+    }
+
+    /// Send `frame` out of the port of `destination`, or of every port but
+    /// `ingress` if it is not known; returns the number of ports sent on
+    fn forward(&self, frame: &PacketBuffer, destination: MacAddress, ingress: Option<InterfaceId>) -> usize {
+        let known = if destination.is_unicast() { self.port_of(destination) } else { None };
+        if known.is_some() && known == ingress {
+            // The destination is on the segment the frame came from
+            return 0;
+        }
+        let mut sent = 0;
+        for port in self.ports.read().iter() {
+            let wanted = match known {
+                Some(known) => port.id() == known,
+                None => Some(port.id()) != ingress,
+            };
+            if wanted && port.transmit(NetworkPacket::outgoing(frame.clone())).is_ok() {
+                sent += 1;
+            }
+        }
+        sent
+    }
+}
+
+impl RxHandler for BridgeDevice {
+    /// Learn the source of a frame received on a port, forward it and
+    /// receive it on the bridge if it is for the bridge
+    fn handle(&self, manager: &NetworkManager, port: &Arc<NetworkInterface>, packet: NetworkPacket) -> PacketFate {
+        let Some(bridge) = self.interface.get().and_then(Weak::upgrade) else {
+            return PacketFate::Dropped("Bridge gone");
+        };
+        if !bridge.is_up() {
+            return PacketFate::Dropped("Bridge down");
+        }
+        let Some(header) = EthernetHeader::parse(packet.data()) else {
+            return PacketFate::Dropped("Truncated Ethernet header");
+        };
+        self.learn(header.source, port.id(), get_tick());
+
+        let local = header.destination == self.mac;
+        let forwarded = if local { 0 } else { self.forward(packet.buffer(), header.destination, Some(port.id())) };
+        if local || header.destination.is_multicast() {
+            return manager.receive(&bridge, packet.into_buffer());
+        }
+        if forwarded > 0 { PacketFate::Consumed } else { PacketFate::Dropped("Not forwarded") }
+    }
+}
+
+impl Device for BridgeDevice {
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Network
+    }
+
+    fn name(&self) -> &'static str {
+        "bridge"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn as_network_device(&self) -> Option<&dyn NetworkDevice> {
+        Some(self)
+    }
+
+    fn into_network_device(self: Arc<Self>) -> Option<Arc<dyn NetworkDevice>> {
+        Some(self)
+    }
+}
+
+impl ControlOps for BridgeDevice {
+    fn control(&self, _command: u32, _arg: usize) -> Result<i32, &'static str> {
+        Err("Control operations not supported")
+    }
+}
+
+impl MemoryMappingOps for BridgeDevice {
+    fn get_mapping_info(&self, _offset: usize, _length: usize)
+                       -> Result<(usize, usize, bool), &'static str> {
+        Err("Memory mapping not supported by bridges")
+    }
+
+    fn on_mapped(&self, _vaddr: usize, _paddr: usize, _length: usize, _offset: usize) {}
+
+    fn on_unmapped(&self, _vaddr: usize, _length: usize) {}
+
+    fn supports_mmap(&self) -> bool {
+        false
+    }
+}
+
+impl NetworkDevice for BridgeDevice {
+    fn get_interface_name(&self) -> &'static str {
+        "bridge"
+    }
+
+    fn get_mac_address(&self) -> Result<MacAddress, &'static str> {
+        Ok(self.mac)
+    }
+
+    fn get_mtu(&self) -> Result<usize, &'static str> {
+        Ok(BRIDGE_MTU)
+    }
+
+    fn get_interface_config(&self) -> Result<NetworkInterfaceConfig, &'static str> {
+        Ok(NetworkInterfaceConfig::new(self.mac, BRIDGE_MTU, "bridge"))
+    }
+
+    /// Send a frame of the stack out of the ports
+    fn send_packet(&self, packet: DevicePacket) -> Result<(), &'static str> {
+        // The header pushed last is in the first segment
+        let header = EthernetHeader::parse(packet.as_slice()).ok_or("Truncated Ethernet header")?;
+        let frame = packet.buffer;
+        let sent = self.forward(&frame, header.destination, None);
+        let mut stats = self.stats.lock();
+        if sent == 0 {
+            stats.tx_errors += 1;
+            return Err("No port to send on");
+        }
+        stats.tx_packets += 1;
+        stats.tx_bytes += frame.len() as u64;
+        Ok(())
+    }
+
+    /// Frames for the bridge are received on it by its ports
+    fn receive_packets(&self) -> Result<Vec<DevicePacket>, &'static str> {
+        Ok(Vec::new())
+    }
+
+    fn set_promiscuous_mode(&self, _enabled: bool) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn init_network(&mut self) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn is_link_up(&self) -> bool {
+        !self.ports.read().is_empty()
+    }
+
+    fn get_stats(&self) -> NetworkStats {
+        self.stats.lock().clone()
+    }
+}
+
+/// The bridge device of `interface`, if it is a bridge
+fn bridge_of(interface: &NetworkInterface) -> Option<Arc<BridgeDevice>> {
+    interface.device().as_any().downcast_ref::<BridgeDevice>()?.this.upgrade()
+}
+
+pub fn is_bridge(interface: &NetworkInterface) -> bool {
+    interface.device().as_any().is::<BridgeDevice>()
+}
+
+/// Create a bridge without ports as the interface `name`
+pub fn create(name: &str) -> Result<Arc<NetworkInterface>, KernelError> {
+    let bridge = BridgeDevice::new();
+    let interface = get_network_manager().attach_device(name, bridge.clone(), None, ETHERNET_STAGE)?;
+    bridge.interface.call_once(|| Arc::downgrade(&interface));
+    Ok(interface)
+}
+
+/// Release the ports of the bridge `interface` and detach it
+pub fn delete(interface: &Arc<NetworkInterface>) -> Result<(), KernelError> {
+    let bridge = bridge_of(interface).ok_or(KernelError::InvalidArgument)?;
+    for port in bridge.ports() {
+        release(&port);
+    }
+    get_network_manager().detach(interface.id())?;
+    Ok(())
+}
+
+/// The bridge `port` is a port of
+pub fn master_of(port: &NetworkInterface) -> Option<Arc<NetworkInterface>> {
+    get_network_manager().interfaces().into_iter().find(|interface| {
+        bridge_of(interface).is_some_and(|bridge| bridge.ports.read().iter().any(|other| other.id() == port.id()))
+    })
+}
+
+/// Make `port` a port of the bridge `interface`
+///
+/// Fails with `Busy` if the port is already one, of this bridge or
+/// another.
+pub fn add_port(interface: &Arc<NetworkInterface>, port: &Arc<NetworkInterface>) -> Result<(), KernelError> {
+    let bridge = bridge_of(interface).ok_or(KernelError::InvalidArgument)?;
+    if is_bridge(port) {
+        return Err(KernelError::NotSupported);
+    }
+    port.set_rx_handler(bridge.clone())?;
+    bridge.ports.write().push(port.clone());
+    Ok(())
+}
+
+/// Take `port` out of its bridge, if it is in one
+pub fn release(port: &NetworkInterface) {
+    let Some(bridge) = master_of(port).and_then(|interface| bridge_of(&interface)) else {
+        return;
+    };
+    bridge.ports.write().retain(|other| other.id() != port.id());
+    bridge.fdb.lock().retain(|_, entry| entry.port != port.id());
+    port.clear_rx_handler();
+}
+
+/// Timer of the bridges: expire the learned addresses
+fn age(manager: &NetworkManager, now: u64) {
+    for bridge in manager.interfaces().iter().filter_map(|interface| bridge_of(interface)) {
+        bridge.age(now);
+    }
+}
+
+/// Register the bridges with `manager`
+pub fn register(manager: &NetworkManager) -> Result<(), KernelError> {
+    manager.register_timer(age);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::network::GenericNetworkDevice;
+    use crate::network::ethernet::ETHERNET_HEADER_LEN;
+
+    const HOST_A: MacAddress = MacAddress::new([2, 0, 0, 0, 0, 0xA]);
+    const HOST_B: MacAddress = MacAddress::new([2, 0, 0, 0, 0, 0xB]);
+    const HOST_C: MacAddress = MacAddress::new([2, 0, 0, 0, 0, 0xC]);
+
+    fn frame(destination: MacAddress, source: MacAddress) -> Vec<u8> {
+        let mut frame = EthernetHeader { destination, source, ethertype: 0x88B5 }.to_bytes().to_vec();
+        frame.extend_from_slice(&[0; 46]);
+        frame
+    }
+
+    #[test_case]
+    fn test_bridge_learning_and_forwarding() {
+        let manager = get_network_manager();
+        let mut devices = Vec::new();
+        let mut ports = Vec::new();
+        for name in ["brp0", "brp1"] {
+            let mut device = GenericNetworkDevice::new(name);
+            device.init_network().unwrap();
+            let device = Arc::new(device);
+            ports.push(manager.attach_device(name, device.clone(), None, ETHERNET_STAGE).unwrap());
+            devices.push(device);
+        }
+        let bridge = create("brt0").unwrap();
+        add_port(&bridge, &ports[0]).unwrap();
+        add_port(&bridge, &ports[1]).unwrap();
+        assert_eq!(add_port(&bridge, &ports[1]).err(), Some(KernelError::Busy));
+        assert_eq!(master_of(&ports[0]).map(|master| master.id()), Some(bridge.id()));
+
+        // Unknown destinations are flooded to the other ports only
+        manager.receive(&ports[0], frame(MacAddress::new([0xFF; 6]), HOST_A));
+        assert!(devices[0].take_transmitted_packets().is_empty());
+        assert_eq!(devices[1].take_transmitted_packets().len(), 1);
+
+        // A learned destination is sent to its port alone
+        assert_eq!(manager.receive(&ports[1], frame(HOST_A, HOST_B)), PacketFate::Consumed);
+        assert_eq!(devices[0].take_transmitted_packets()[0].as_slice()[..ETHERNET_HEADER_LEN], frame(HOST_A, HOST_B)[..ETHERNET_HEADER_LEN]);
+        assert!(devices[1].take_transmitted_packets().is_empty());
+        assert!(matches!(manager.receive(&ports[0], frame(HOST_A, HOST_C)), PacketFate::Dropped(_)));
+
+        // The stack sends through the bridge like any port
+        let to_b = frame(HOST_B, bridge.mac_address().unwrap());
+        bridge.device().send_packet(DevicePacket::with_data(to_b)).unwrap();
+        assert!(devices[0].take_transmitted_packets().is_empty());
+        assert_eq!(devices[1].take_transmitted_packets().len(), 1);
+
+        release(&ports[1]);
+        assert!(master_of(&ports[1]).is_none());
+        delete(&bridge).unwrap();
+        assert!(master_of(&ports[0]).is_none());
+        for port in ports {
+            manager.detach(port.id()).unwrap();
+        }
+    }
+}
@@ -0,0 +1,344 @@
+//! Packet buffers
+//!
+//! A [`PacketBuffer`] holds the bytes of a packet as it moves between the
+//! stages of the pipeline and the devices, without copying them on the way.
+//! Its storage is reference counted: a clone shares the bytes, and they are
+//! copied only when a clone that shares them writes (copy on write). Room
+//! is kept in front of the bytes, the headroom, so that the headers pushed
+//! on the way out are written in place, and behind them, the tailroom, for
+//! padding.
+//!
+//! After its linear part, a buffer can hold fragments: shared slices of
+//! other storage that follow it (scatter-gather). A payload is attached as
+//! fragments without being copied behind its headers. Devices that gather
+//! the segments of a packet send them as they are; the others, and stages
+//! that need the packet in one piece, [`linearize`](PacketBuffer::linearize)
+//! the buffer first.
+
+use core::fmt;
+use core::ops::{Deref, Range};
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Headroom of the buffers the stack builds for sending: an Ethernet
+/// header, an IPv6 header with extension headers and a TCP header with
+/// options
+pub const DEFAULT_HEADROOM: usize = 128;
+
+/// A shared, read-only slice of packet bytes
+#[derive(Clone)]
+pub struct BufferSlice {
+    storage: Arc<Vec<u8>>,
+    range: Range<usize>,
+}
+
+impl BufferSlice {
+    /// The part `range` of this slice, sharing its bytes
+    pub fn slice(&self, range: Range<usize>) -> Option<Self> {
+        if range.start > range.end || range.end > self.len() {
+            return None;
+        }
+        let start = self.range.start;
+        Some(Self { storage: self.storage.clone(), range: start + range.start..start + range.end })
+    }
+}
+
+impl From<Vec<u8>> for BufferSlice {
+    fn from(bytes: Vec<u8>) -> Self {
+        let range = 0..bytes.len();
+        Self { storage: Arc::new(bytes), range }
+    }
+}
+
+impl Deref for BufferSlice {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.storage[self.range.clone()]
+    }
+}
+
+impl fmt::Debug for BufferSlice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BufferSlice").field("len", &self.len()).finish()
+    }
+}
+
+/// The bytes of a packet: a linear part with room around it, followed by
+/// fragments
+#[derive(Clone)]
+pub struct PacketBuffer {
+    /// Holds the linear part; `storage[..start]` is the headroom
+    storage: Arc<Vec<u8>>,
+    start: usize,
+    end: usize,
+    fragments: Vec<BufferSlice>,
+}
+
+impl PacketBuffer {
+    /// An empty buffer with `headroom` bytes in front and room for
+    /// `capacity` bytes
+    pub fn with_room(headroom: usize, capacity: usize) -> Self {
+        let mut storage = Vec::with_capacity(headroom + capacity);
+        storage.resize(headroom, 0);
+        Self { storage: Arc::new(storage), start: headroom, end: headroom, fragments: Vec::new() }
+    }
+
+    /// A buffer of `bytes` with [`DEFAULT_HEADROOM`] in front
+    pub fn copy_from(bytes: &[u8]) -> Self {
+        let mut buffer = Self::with_room(DEFAULT_HEADROOM, bytes.len());
+        buffer.extend_back(bytes);
+        buffer
+    }
+
+    /// Number of bytes, fragments included
+    pub fn len(&self) -> usize {
+        self.end - self.start + self.fragments.iter().map(|fragment| fragment.len()).sum::<usize>()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes that can be pushed in front without moving the packet
+    pub fn headroom(&self) -> usize {
+        self.start
+    }
+
+    /// Bytes that can be appended without moving the packet
+    pub fn tailroom(&self) -> usize {
+        if self.fragments.is_empty() && !self.is_shared() {
+            self.storage.capacity() - self.end
+        } else {
+            0
+        }
+    }
+
+    /// Whether the bytes are all in the linear part
+    pub fn is_linear(&self) -> bool {
+        self.fragments.is_empty()
+    }
+
+    /// Whether another buffer shares the linear part
+    pub fn is_shared(&self) -> bool {
+        Arc::strong_count(&self.storage) > 1
+    }
+
+    /// The linear part: the whole packet if [`is_linear`](Self::is_linear)
+    pub fn linear(&self) -> &[u8] {
+        &self.storage[self.start..self.end]
+    }
+
+    /// The linear part to write, copied first if it is shared
+    pub fn linear_mut(&mut self) -> &mut [u8] {
+        let (start, end) = (self.start, self.end);
+        &mut self.storage_mut(0)[start..end]
+    }
+
+    pub fn fragments(&self) -> &[BufferSlice] {
+        &self.fragments
+    }
+
+    /// The pieces of the packet in order: the linear part, then the
+    /// fragments, the empty ones left out
+    pub fn segments(&self) -> impl Iterator<Item = &[u8]> {
+        core::iter::once(self.linear())
+            .chain(self.fragments.iter().map(|fragment| &**fragment))
+            .filter(|segment| !segment.is_empty())
+    }
+
+    /// Every byte of the packet in order
+    pub fn bytes(&self) -> impl Iterator<Item = u8> + '_ {
+        self.segments().flat_map(|segment| segment.iter().copied())
+    }
+
+    /// The storage to write, owned by this buffer alone, with at least
+    /// `headroom` bytes of headroom; the linear part moves if it must
+    fn storage_mut(&mut self, headroom: usize) -> &mut Vec<u8> {
+        if self.start < headroom || Arc::get_mut(&mut self.storage).is_none() {
+            let headroom = if self.start < headroom { headroom.max(DEFAULT_HEADROOM) } else { self.start };
+            let mut storage = Vec::with_capacity(headroom + self.end - self.start);
+            storage.resize(headroom, 0);
+            storage.extend_from_slice(self.linear());
+            self.end = storage.len();
+            self.start = headroom;
+            self.storage = Arc::new(storage);
+        }
+        Arc::get_mut(&mut self.storage).unwrap()
+    }
+
+    /// Put `bytes` in front of the packet, in the headroom if there is
+    /// enough of it
+    pub fn push_front(&mut self, bytes: &[u8]) {
+        let len = bytes.len();
+        self.storage_mut(len);
+        let end = self.start;
+        self.storage_mut(0)[end - len..end].copy_from_slice(bytes);
+        self.start = end - len;
+    }
+
+    /// Append `bytes` to the packet, in the tailroom if there is enough of
+    /// it, or as a fragment behind other fragments
+    pub fn extend_back(&mut self, bytes: &[u8]) {
+        if !self.fragments.is_empty() {
+            self.fragments.push(BufferSlice::from(bytes.to_vec()));
+            return;
+        }
+        let end = self.end;
+        let storage = self.storage_mut(0);
+        storage.truncate(end);
+        storage.extend_from_slice(bytes);
+        self.end = storage.len();
+    }
+
+    /// Attach `fragment` behind the packet without copying it
+    pub fn append(&mut self, fragment: BufferSlice) {
+        if !fragment.is_empty() {
+            self.fragments.push(fragment);
+        }
+    }
+
+    /// Cut the packet down to `len` bytes
+    pub fn truncate(&mut self, len: usize) {
+        let linear = self.end - self.start;
+        if len <= linear {
+            self.end = self.start + len;
+            self.fragments.clear();
+            return;
+        }
+        let mut left = len - linear;
+        let mut kept = 0;
+        for fragment in &mut self.fragments {
+            if left == 0 {
+                break;
+            }
+            if fragment.len() > left {
+                *fragment = fragment.slice(0..left).unwrap();
+            }
+            left -= fragment.len();
+            kept += 1;
+        }
+        self.fragments.truncate(kept);
+    }
+
+    /// Move the fragments into the linear part, so that the packet is in
+    /// one piece
+    pub fn linearize(&mut self) -> &mut [u8] {
+        if !self.fragments.is_empty() {
+            let fragments = core::mem::take(&mut self.fragments);
+            let end = self.end;
+            let storage = self.storage_mut(0);
+            storage.truncate(end);
+            for fragment in &fragments {
+                storage.extend_from_slice(fragment);
+            }
+            self.end = storage.len();
+        }
+        self.linear_mut()
+    }
+
+    /// A copy of the bytes of the packet
+    pub fn to_vec(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.len());
+        for segment in self.segments() {
+            bytes.extend_from_slice(segment);
+        }
+        bytes
+    }
+
+    /// The bytes of the packet, without copying them if the buffer owns
+    /// them alone in one piece and has no headroom
+    pub fn into_vec(mut self) -> Vec<u8> {
+        if self.fragments.is_empty() && self.start == 0 {
+            if let Some(storage) = Arc::get_mut(&mut self.storage) {
+                storage.truncate(self.end);
+                return core::mem::take(storage);
+            }
+        }
+        self.to_vec()
+    }
+}
+
+impl Default for PacketBuffer {
+    fn default() -> Self {
+        Self::with_room(0, 0)
+    }
+}
+
+impl From<Vec<u8>> for PacketBuffer {
+    /// The buffer of `bytes`, without headroom, taking them without a copy
+    fn from(bytes: Vec<u8>) -> Self {
+        let end = bytes.len();
+        Self { storage: Arc::new(bytes), start: 0, end, fragments: Vec::new() }
+    }
+}
+
+impl PartialEq for PacketBuffer {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.bytes().eq(other.bytes())
+    }
+}
+
+impl Eq for PacketBuffer {}
+
+impl fmt::Debug for PacketBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PacketBuffer")
+            .field("len", &self.len())
+            .field("headroom", &self.headroom())
+            .field("fragments", &self.fragments.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test_case]
+    fn test_buffer_headroom_and_sharing() {
+        let mut buffer = PacketBuffer::copy_from(&[5, 6]);
+        assert_eq!(buffer.headroom(), DEFAULT_HEADROOM);
+        buffer.push_front(&[3, 4]);
+        buffer.push_front(&[1, 2]);
+        assert_eq!((buffer.linear(), buffer.headroom()), (&[1u8, 2, 3, 4, 5, 6][..], DEFAULT_HEADROOM - 4));
+
+        // A clone shares the bytes until one of them writes
+        let clone = buffer.clone();
+        assert!(buffer.is_shared());
+        buffer.linear_mut()[0] = 9;
+        assert!(!buffer.is_shared());
+        assert_eq!((buffer.linear()[0], clone.linear()[0]), (9, 1));
+
+        // Without headroom, pushing moves the bytes once
+        let mut buffer = PacketBuffer::from(vec![7]);
+        assert_eq!(buffer.headroom(), 0);
+        buffer.push_front(&[6]);
+        assert_eq!((buffer.linear(), buffer.headroom()), (&[6u8, 7][..], DEFAULT_HEADROOM - 1));
+        assert_eq!(PacketBuffer::from(vec![1, 2, 3]).into_vec(), vec![1, 2, 3]);
+    }
+
+    #[test_case]
+    fn test_buffer_fragments() {
+        let payload = BufferSlice::from(vec![10, 11, 12, 13, 14, 15]);
+        let mut buffer = PacketBuffer::with_room(DEFAULT_HEADROOM, 2);
+        buffer.extend_back(&[1, 2]);
+        buffer.append(payload.slice(0..3).unwrap());
+        buffer.append(payload.slice(3..6).unwrap());
+        assert!(payload.slice(4..7).is_none());
+        assert_eq!((buffer.len(), buffer.is_linear()), (8, false));
+        assert_eq!(buffer.segments().collect::<Vec<_>>(), [&[1u8, 2][..], &[10, 11, 12], &[13, 14, 15]]);
+
+        // Padding follows the fragments, cutting keeps what is left of them
+        buffer.extend_back(&[0]);
+        assert_eq!(buffer.to_vec(), [1, 2, 10, 11, 12, 13, 14, 15, 0]);
+        buffer.truncate(4);
+        assert_eq!(buffer.segments().collect::<Vec<_>>(), [&[1u8, 2][..], &[10, 11]]);
+
+        assert_eq!(buffer.linearize(), &[1, 2, 10, 11]);
+        assert!(buffer.is_linear());
+        assert_eq!(buffer, PacketBuffer::from(vec![1, 2, 10, 11]));
+    }
+}
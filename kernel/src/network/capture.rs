@@ -0,0 +1,575 @@
+//! Packet capture and packet sockets
+//!
+//! Every frame an interface receives is tapped before the pipeline sees it,
+//! and every frame it sends as the device gets it, and copied to the
+//! packet sockets that take it. A [`PacketSocket`] takes the frames of one
+//! EtherType or of all, on one interface or on every one, and sends frames
+//! of its own, link header included; it sees only the interfaces of its
+//! network namespace. Frames sent with checksum or
+//! segmentation offload are captured as they are handed to the device:
+//! with the checksum not completed yet and the segments not cut up.
+//!
+//! A [`PacketFilter`] narrows further what a packet or raw socket takes: a
+//! callback run on each frame. The kernel can install any closure; user
+//! space sets a [`FilterExpression`], a small language after tcpdump's,
+//! parsed once and evaluated as it is rather than compiled to a virtual
+//! machine:
+//!
+//! - `ip`, `ip6`, `arp`, `ether proto N`: the network protocol
+//! - `tcp`, `udp`, `icmp`, `icmp6`, `proto N`: the transport protocol
+//! - `[src|dst] host ADDRESS`, `[src|dst] port N`
+//! - `inbound`, `outbound`: the direction of the frame
+//!
+//! Terms are joined by `and`, each may be negated with `not`, and numbers
+//! may be written in hexadecimal with `0x`. The empty expression takes
+//! everything.
+
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::{Mutex, RwLock};
+
+use crate::abi::error::KernelError;
+use crate::ipc::socket::{Received, Shutdown, SocketDomain, SocketObject, SocketType, SOCKET_BUFFER_SIZE};
+use crate::ipc::StreamIpcOps;
+use crate::object::capability::poll::{wait_for, PollOps, PollWait, POLLIN, POLLOUT};
+use crate::object::capability::{StreamError, StreamOps};
+use crate::object::KernelObject;
+use crate::sync::waker::Waker;
+
+use super::address::IpAddress;
+use super::buffer::PacketBuffer;
+use super::ethernet::{ETHERNET_HEADER_LEN, ETHERNET_STAGE, ETHERTYPE_ARP, ETHERTYPE_IPV4, ETHERTYPE_IPV6};
+use super::ip::{self, Datagram};
+use super::ipv4::{PROTOCOL_ICMP, PROTOCOL_TCP, PROTOCOL_UDP};
+use super::ipv6::NEXT_HEADER_ICMPV6;
+use super::manager::{get_network_manager, NetworkInterface};
+use super::namespace::{self, NetNamespace};
+use super::packet::{NetworkPacket, PacketDirection};
+use super::InterfaceId;
+
+/// Longest filter expression
+pub const FILTER_EXPRESSION_MAX: usize = 1024;
+
+/// A frame seen by the tap, or a datagram seen by a raw socket
+pub struct CapturedPacket<'a> {
+    pub interface: &'a NetworkInterface,
+    pub direction: PacketDirection,
+    /// The frame, link header included if the interface has one
+    pub data: &'a [u8],
+    /// EtherType of the network header, if known
+    pub ethertype: Option<u16>,
+    /// Where the network header starts in `data`
+    pub network_offset: usize,
+}
+
+impl<'a> CapturedPacket<'a> {
+    /// A frame of `interface`, with the link header its entry stage has
+    pub fn new(interface: &'a NetworkInterface, direction: PacketDirection, data: &'a [u8]) -> Self {
+        if interface.entry_stage() == ETHERNET_STAGE {
+            let ethertype = data.get(12..14).map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]));
+            return Self { interface, direction, data, ethertype, network_offset: ETHERNET_HEADER_LEN };
+        }
+        // Interfaces without a link header carry IP
+        let ethertype = match data.first().map(|byte| byte >> 4) {
+            Some(4) => Some(ETHERTYPE_IPV4),
+            Some(6) => Some(ETHERTYPE_IPV6),
+            _ => None,
+        };
+        Self { interface, direction, data, ethertype, network_offset: 0 }
+    }
+
+    /// The IP datagram the frame carries
+    pub fn datagram(&self) -> Option<Datagram<'a>> {
+        match self.ethertype {
+            Some(ETHERTYPE_IPV4 | ETHERTYPE_IPV6) => ip::parse_datagram(self.data.get(self.network_offset..)?),
+            _ => None,
+        }
+    }
+}
+
+/// Decides which frames a packet or raw socket takes
+pub trait PacketFilter: Send + Sync {
+    fn accepts(&self, packet: &CapturedPacket) -> bool;
+}
+
+impl<F: Fn(&CapturedPacket) -> bool + Send + Sync> PacketFilter for F {
+    fn accepts(&self, packet: &CapturedPacket) -> bool {
+        self(packet)
+    }
+}
+
+/// Which address or port of a datagram a term is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Side {
+    Source,
+    Destination,
+    Either,
+}
+
+impl Side {
+    fn holds(self, source: bool, destination: bool) -> bool {
+        match self {
+            Side::Source => source,
+            Side::Destination => destination,
+            Side::Either => source || destination,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Term {
+    EtherType(u16),
+    Protocol(u8),
+    Host(Side, IpAddress),
+    Port(Side, u16),
+    Direction(PacketDirection),
+}
+
+impl Term {
+    fn holds(&self, packet: &CapturedPacket, datagram: Option<&Datagram>) -> bool {
+        match *self {
+            Term::EtherType(ethertype) => packet.ethertype == Some(ethertype),
+            Term::Protocol(protocol) => datagram.is_some_and(|datagram| datagram.protocol == protocol),
+            Term::Host(side, address) => datagram.is_some_and(|datagram| {
+                side.holds(datagram.source.canonical() == address, datagram.destination.canonical() == address)
+            }),
+            Term::Port(side, port) => datagram
+                .filter(|datagram| matches!(datagram.protocol, PROTOCOL_TCP | PROTOCOL_UDP))
+                .and_then(|datagram| datagram.transport.get(..4))
+                .is_some_and(|ports| {
+                    side.holds(u16::from_be_bytes([ports[0], ports[1]]) == port, u16::from_be_bytes([ports[2], ports[3]]) == port)
+                }),
+            Term::Direction(direction) => packet.direction == direction,
+        }
+    }
+}
+
+/// A number, decimal or hexadecimal with `0x`
+fn number<T: TryFrom<u32>>(word: Option<&str>) -> Result<T, KernelError> {
+    let word = word.ok_or(KernelError::InvalidArgument)?;
+    let value = match word.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => word.parse(),
+    };
+    value.ok().and_then(|value| T::try_from(value).ok()).ok_or(KernelError::InvalidArgument)
+}
+
+/// A filter written in the expression language of the module
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterExpression {
+    /// Terms that must all hold, or not hold where negated
+    terms: Vec<(bool, Term)>,
+}
+
+impl FilterExpression {
+    pub fn parse(text: &str) -> Result<Self, KernelError> {
+        let mut words = text.split_whitespace().peekable();
+        let mut terms = Vec::new();
+        while words.peek().is_some() {
+            if !terms.is_empty() && words.next() != Some("and") {
+                return Err(KernelError::InvalidArgument);
+            }
+            let negated = words.next_if_eq(&"not").is_some();
+            terms.push((negated, Self::term(&mut words)?));
+        }
+        Ok(Self { terms })
+    }
+
+    fn term<'a>(words: &mut impl Iterator<Item = &'a str>) -> Result<Term, KernelError> {
+        let mut word = words.next().ok_or(KernelError::InvalidArgument)?;
+        let side = match word {
+            "src" => Side::Source,
+            "dst" => Side::Destination,
+            _ => Side::Either,
+        };
+        if side != Side::Either {
+            word = words.next().ok_or(KernelError::InvalidArgument)?;
+        }
+        let term = match (side, word) {
+            (_, "host") => Term::Host(side, IpAddress::parse(words.next().unwrap_or(""))?.canonical()),
+            (_, "port") => Term::Port(side, number(words.next())?),
+            (Side::Either, "ip") => Term::EtherType(ETHERTYPE_IPV4),
+            (Side::Either, "ip6") => Term::EtherType(ETHERTYPE_IPV6),
+            (Side::Either, "arp") => Term::EtherType(ETHERTYPE_ARP),
+            (Side::Either, "ether") if words.next() == Some("proto") => Term::EtherType(number(words.next())?),
+            (Side::Either, "tcp") => Term::Protocol(PROTOCOL_TCP),
+            (Side::Either, "udp") => Term::Protocol(PROTOCOL_UDP),
+            (Side::Either, "icmp") => Term::Protocol(PROTOCOL_ICMP),
+            (Side::Either, "icmp6") => Term::Protocol(NEXT_HEADER_ICMPV6),
+            (Side::Either, "proto") => Term::Protocol(number(words.next())?),
+            (Side::Either, "inbound") => Term::Direction(PacketDirection::Incoming),
+            (Side::Either, "outbound") => Term::Direction(PacketDirection::Outgoing),
+            _ => return Err(KernelError::InvalidArgument),
+        };
+        Ok(term)
+    }
+}
+
+impl PacketFilter for FilterExpression {
+    fn accepts(&self, packet: &CapturedPacket) -> bool {
+        let datagram = packet.datagram();
+        self.terms.iter().all(|(negated, term)| term.holds(packet, datagram.as_ref()) != *negated)
+    }
+}
+
+/// Packet sockets, for the tap to find
+static SOCKETS: RwLock<Vec<Weak<PacketSocket>>> = RwLock::new(Vec::new());
+/// Open packet sockets: the tap does nothing while there is none
+static OPEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Copy a frame `interface` received or sends to the packet sockets that
+/// take it
+pub fn tap(interface: &NetworkInterface, direction: PacketDirection, data: &[u8]) {
+    if OPEN.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    let packet = CapturedPacket::new(interface, direction, data);
+    let sockets: Vec<Arc<PacketSocket>> = SOCKETS.read().iter().filter_map(Weak::upgrade).collect();
+    for socket in sockets {
+        socket.capture(&packet);
+    }
+}
+
+/// [`tap`] a frame held in a buffer, which is copied in one piece only
+/// if it is in several and taken
+pub fn tap_buffer(interface: &NetworkInterface, direction: PacketDirection, buffer: &PacketBuffer) {
+    if OPEN.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    if buffer.is_linear() {
+        tap(interface, direction, buffer.linear());
+    } else {
+        tap(interface, direction, &buffer.to_vec());
+    }
+}
+
+struct Frame {
+    data: Vec<u8>,
+    /// Name of the interface that received or sent it
+    interface: String,
+}
+
+struct Inbox {
+    frames: VecDeque<Frame>,
+    bytes: usize,
+}
+
+struct PacketState {
+    /// The interface bound, frames of every one are taken if `None`
+    interface: Option<(InterfaceId, String)>,
+    read_shutdown: bool,
+    write_shutdown: bool,
+}
+
+/// A packet socket
+pub struct PacketSocket {
+    /// EtherType of the frames taken, those of all if `None`
+    ethertype: Option<u16>,
+    nonblocking: bool,
+    /// Namespace of the task that opened the socket
+    namespace: Arc<NetNamespace>,
+    state: Mutex<PacketState>,
+    filter: RwLock<Option<Arc<dyn PacketFilter>>>,
+    inbox: Mutex<Inbox>,
+    /// Frames lost because the socket was full
+    dropped: AtomicU64,
+    waker: Waker,
+}
+
+impl PacketSocket {
+    /// A socket capturing the frames of `ethertype`, or all, on every
+    /// interface of the namespace of the current task
+    pub fn new(ethertype: Option<u16>, nonblocking: bool) -> Arc<Self> {
+        let socket = Arc::new(Self {
+            ethertype,
+            nonblocking,
+            namespace: namespace::current(),
+            state: Mutex::new(PacketState { interface: None, read_shutdown: false, write_shutdown: false }),
+            filter: RwLock::new(None),
+            inbox: Mutex::new(Inbox { frames: VecDeque::new(), bytes: 0 }),
+            dropped: AtomicU64::new(0),
+            waker: Waker::new_interruptible("packet"),
+        });
+        let mut sockets = SOCKETS.write();
+        sockets.retain(|socket| socket.strong_count() > 0);
+        sockets.push(Arc::downgrade(&socket));
+        OPEN.fetch_add(1, Ordering::Relaxed);
+        socket
+    }
+
+    /// Number of frames lost because the socket was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    fn capture(&self, packet: &CapturedPacket) {
+        if self.ethertype.is_some_and(|ethertype| packet.ethertype != Some(ethertype))
+            || packet.interface.namespace() != self.namespace.id()
+        {
+            return;
+        }
+        {
+            let state = self.state.lock();
+            if state.read_shutdown || state.interface.as_ref().is_some_and(|(id, _)| *id != packet.interface.id()) {
+                return;
+            }
+        }
+        if self.filter.read().as_ref().is_some_and(|filter| !filter.accepts(packet)) {
+            return;
+        }
+        let mut inbox = self.inbox.lock();
+        if inbox.bytes + packet.data.len() > SOCKET_BUFFER_SIZE {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        inbox.bytes += packet.data.len();
+        inbox.frames.push_back(Frame { data: packet.data.to_vec(), interface: packet.interface.name().to_string() });
+        drop(inbox);
+        self.waker.wake_all();
+    }
+
+    /// Take a frame into `buffer`, `None` while nothing is there
+    fn take(&self, buffer: &mut [u8]) -> Option<Received> {
+        let read_shutdown = self.state.lock().read_shutdown;
+        let mut inbox = self.inbox.lock();
+        let Some(frame) = inbox.frames.pop_front() else {
+            return read_shutdown.then(Received::default);
+        };
+        inbox.bytes -= frame.data.len();
+        let len = frame.data.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&frame.data[..len]);
+        Some(Received { len, truncated: len < frame.data.len(), objects: Vec::new(), from: Some(frame.interface) })
+    }
+}
+
+impl SocketObject for PacketSocket {
+    fn domain(&self) -> SocketDomain {
+        SocketDomain::Packet
+    }
+
+    fn socket_type(&self) -> SocketType {
+        SocketType::Raw
+    }
+
+    /// Take only the frames of the interface `name`, and send out of it;
+    /// the empty name takes those of every interface again
+    fn bind(&self, name: &str) -> Result<(), KernelError> {
+        let interface = match name {
+            "" => None,
+            name => {
+                let interface = get_network_manager()
+                    .interface_by_name_in(self.namespace.id(), name)
+                    .ok_or(KernelError::NotFound)?;
+                Some((interface.id(), name.to_string()))
+            }
+        };
+        self.state.lock().interface = interface;
+        Ok(())
+    }
+
+    fn listen(&self, _backlog: usize) -> Result<(), KernelError> {
+        Err(KernelError::NotSupported)
+    }
+
+    fn connect(&self, _name: &str) -> Result<(), KernelError> {
+        Err(KernelError::NotSupported)
+    }
+
+    fn accept(&self) -> Result<Arc<dyn SocketObject>, KernelError> {
+        Err(KernelError::NotSupported)
+    }
+
+    /// Send the frame `data` as it is out of the interface `to`, or the
+    /// one bound
+    fn send(&self, data: &[u8], objects: Vec<KernelObject>, to: Option<&str>) -> Result<usize, KernelError> {
+        if !objects.is_empty() {
+            return Err(KernelError::NotSupported);
+        }
+        if data.len() > SOCKET_BUFFER_SIZE {
+            return Err(KernelError::MessageTooLong);
+        }
+        let manager = get_network_manager();
+        let interface = {
+            let state = self.state.lock();
+            if state.write_shutdown {
+                return Err(KernelError::BrokenPipe);
+            }
+            match (to, &state.interface) {
+                (Some(name), _) => manager.interface_by_name(name),
+                (None, Some((id, _))) => manager.interface(*id),
+                (None, None) => return Err(KernelError::NotConnected),
+            }
+        };
+        let interface = interface.filter(|interface| interface.namespace() == self.namespace.id());
+        interface.ok_or(KernelError::NotFound)?.transmit(NetworkPacket::outgoing(data.to_vec()))?;
+        Ok(data.len())
+    }
+
+    fn receive(&self, buffer: &mut [u8]) -> Result<Received, KernelError> {
+        let mut outcome = None;
+        let mut attempt = || {
+            outcome = self.take(buffer);
+            outcome.is_some()
+        };
+        if self.nonblocking {
+            if !attempt() {
+                return Err(KernelError::WouldBlock);
+            }
+        } else {
+            let sources: [&dyn PollOps; 1] = [self];
+            match wait_for(&sources, None, attempt) {
+                PollWait::Ready => {}
+                PollWait::Interrupted => return Err(KernelError::Interrupted),
+                PollWait::TimedOut => return Err(KernelError::WouldBlock),
+            }
+        }
+        Ok(outcome.unwrap_or_default())
+    }
+
+    fn shutdown(&self, how: Shutdown) -> Result<(), KernelError> {
+        let mut state = self.state.lock();
+        if how != Shutdown::Write {
+            state.read_shutdown = true;
+        }
+        if how != Shutdown::Read {
+            state.write_shutdown = true;
+        }
+        drop(state);
+        self.waker.wake_all();
+        Ok(())
+    }
+
+    fn name(&self) -> Option<String> {
+        self.state.lock().interface.as_ref().map(|(_, name)| name.clone())
+    }
+
+    fn peer_name(&self) -> Option<String> {
+        None
+    }
+
+    fn set_filter(&self, filter: Option<Arc<dyn PacketFilter>>) -> Result<(), KernelError> {
+        *self.filter.write() = filter;
+        Ok(())
+    }
+}
+
+/// The stream error of a socket error
+fn stream_error(error: KernelError) -> StreamError {
+    match error {
+        KernelError::WouldBlock => StreamError::WouldBlock,
+        KernelError::Interrupted => StreamError::Interrupted,
+        KernelError::BrokenPipe => StreamError::BrokenPipe,
+        KernelError::NotConnected => StreamError::Closed,
+        _ => StreamError::InvalidArgument,
+    }
+}
+
+impl StreamOps for PacketSocket {
+    /// Receive a frame
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, StreamError> {
+        self.receive(buffer).map(|received| received.len).map_err(stream_error)
+    }
+
+    /// Send a frame out of the interface bound
+    fn write(&self, buffer: &[u8]) -> Result<usize, StreamError> {
+        self.send(buffer, Vec::new(), None).map_err(stream_error)
+    }
+}
+
+impl StreamIpcOps for PacketSocket {
+    fn is_connected(&self) -> bool {
+        false
+    }
+
+    fn peer_count(&self) -> usize {
+        0
+    }
+
+    fn description(&self) -> String {
+        match self.name() {
+            Some(name) => format!("packet({})", name),
+            None => "packet".to_string(),
+        }
+    }
+}
+
+impl PollOps for PacketSocket {
+    fn poll_events(&self) -> u32 {
+        let state = self.state.lock();
+        let mut events = if state.write_shutdown { 0 } else { POLLOUT };
+        if state.read_shutdown || !self.inbox.lock().frames.is_empty() {
+            events |= POLLIN;
+        }
+        events
+    }
+
+    fn poll_register(&self, task_id: usize) -> bool {
+        self.waker.register(task_id);
+        true
+    }
+
+    fn poll_unregister(&self, task_id: usize) {
+        self.waker.unregister(task_id);
+    }
+}
+
+impl Drop for PacketSocket {
+    fn drop(&mut self) {
+        OPEN.fetch_sub(1, Ordering::Relaxed);
+        SOCKETS.write().retain(|socket| socket.strong_count() > 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::network::{GenericNetworkDevice, MacAddress, NetworkDevice};
+    use crate::network::address::{IpEndpoint, Ipv4Address};
+    use crate::network::ethernet::{self, EthernetHeader};
+    use crate::network::ipv4::Ipv4Header;
+    use crate::network::manager::NetworkManager;
+    use crate::network::udp;
+
+    #[test_case]
+    fn test_capture_filter() {
+        static MANAGER: NetworkManager = NetworkManager::new();
+        let mut device = GenericNetworkDevice::new("test0");
+        device.init_network().unwrap();
+        let local_mac = device.get_mac_address().unwrap();
+        let interface = MANAGER.attach_device("test0", Arc::new(device), None, ETHERNET_STAGE).unwrap();
+
+        let (client, server) = (Ipv4Address::new(192, 0, 2, 30), Ipv4Address::new(192, 0, 2, 25));
+        let datagram = udp::build_datagram(IpEndpoint::new(client.into(), 1234), IpEndpoint::new(server.into(), 53), b"query");
+        let mut packet = Ipv4Header::new(client, server, PROTOCOL_UDP, datagram.len()).to_bytes().to_vec();
+        packet.extend_from_slice(&datagram);
+        let header = EthernetHeader { destination: local_mac, source: MacAddress::new([2, 0, 0, 0, 0, 30]), ethertype: ETHERTYPE_IPV4 };
+        let query = ethernet::build_frame(&header, &packet);
+        let arp = ethernet::build_frame(&EthernetHeader { ethertype: ETHERTYPE_ARP, ..header }, &[0; 28]);
+
+        let captured = CapturedPacket::new(&interface, PacketDirection::Incoming, &query);
+        let accepts = |text: &str| FilterExpression::parse(text).unwrap().accepts(&captured);
+        assert!(accepts("") && accepts("ip and udp and dst port 53 and src host 192.0.2.30"));
+        assert!(accepts("port 1234 and not tcp and inbound and ether proto 0x800"));
+        assert!(!accepts("src port 53") && !accepts("host 192.0.2.1") && !accepts("ip6") && !accepts("outbound"));
+        for invalid in ["tcp or udp", "port", "src ip", "host 300.0.0.1", "udp and", "proto 256"] {
+            assert_eq!(FilterExpression::parse(invalid), Err(KernelError::InvalidArgument));
+        }
+
+        // The socket takes what its EtherType and filter let through
+        let socket = PacketSocket::new(Some(ETHERTYPE_IPV4), true);
+        socket.set_filter(Some(Arc::new(FilterExpression::parse("udp").unwrap()))).unwrap();
+        tap(&interface, PacketDirection::Incoming, &arp);
+        tap(&interface, PacketDirection::Outgoing, &query);
+        assert_eq!(socket.poll_events(), POLLIN | POLLOUT);
+        let mut buffer = [0; 128];
+        let received = socket.receive(&mut buffer).unwrap();
+        assert_eq!((&buffer[..received.len], received.from.as_deref()), (&query[..], Some("test0")));
+        assert_eq!(socket.receive(&mut buffer).err(), Some(KernelError::WouldBlock));
+        assert_eq!(socket.send(b"frame", Vec::new(), None), Err(KernelError::NotConnected));
+    }
+}
@@ -0,0 +1,123 @@
+//! Internet checksum (RFC 1071)
+//!
+//! The one's complement sum of 16-bit words used by IPv4, ICMP, ICMPv6, UDP
+//! and TCP.
+//! A [`Checksum`] is fed the pieces of what is covered (a pseudo-header,
+//! then the segment) and finished into the value to put in the header; a
+//! received header checks when the sum over it, checksum included, finishes
+//! to zero.
+
+use super::address::{IpAddress, Ipv4Address, Ipv6Address};
+
+/// A running internet checksum
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Checksum {
+    sum: u32,
+    /// A byte left over from an odd-length piece
+    odd: Option<u8>,
+}
+
+impl Checksum {
+    pub const fn new() -> Self {
+        Self { sum: 0, odd: None }
+    }
+
+    pub fn add_bytes(&mut self, mut bytes: &[u8]) {
+        if let (Some(high), Some((&low, rest))) = (self.odd, bytes.split_first()) {
+            self.add_word(u16::from_be_bytes([high, low]));
+            self.odd = None;
+            bytes = rest;
+        }
+        let mut words = bytes.chunks_exact(2);
+        for word in &mut words {
+            self.add_word(u16::from_be_bytes([word[0], word[1]]));
+        }
+        if let [last] = words.remainder() {
+            self.odd = Some(*last);
+        }
+    }
+
+    pub fn add_word(&mut self, word: u16) {
+        self.sum += word as u32;
+        // Fold before the sum can overflow
+        if self.sum > 0xFFFF {
+            self.sum = (self.sum & 0xFFFF) + (self.sum >> 16);
+        }
+    }
+
+    /// Add the pseudo-header UDP and TCP checksums cover
+    pub fn add_pseudo_header(&mut self, source: Ipv4Address, destination: Ipv4Address, protocol: u8, len: usize) {
+        self.add_bytes(&source.octets());
+        self.add_bytes(&destination.octets());
+        self.add_word(protocol as u16);
+        self.add_word(len as u16);
+    }
+
+    /// Add the pseudo-header of IPv6 (RFC 8200)
+    pub fn add_ipv6_pseudo_header(&mut self, source: Ipv6Address, destination: Ipv6Address, next_header: u8, len: usize) {
+        self.add_bytes(&source.octets());
+        self.add_bytes(&destination.octets());
+        self.add_bytes(&(len as u32).to_be_bytes());
+        self.add_word(next_header as u16);
+    }
+
+    /// Add the pseudo-header of the version of IP of the addresses; an IPv4
+    /// address with an IPv6 one is taken as IPv4-mapped
+    pub fn add_ip_pseudo_header(&mut self, source: IpAddress, destination: IpAddress, protocol: u8, len: usize) {
+        let v6 = |address: IpAddress| match address {
+            IpAddress::V4(address) => Ipv6Address::from_ipv4_mapped(address),
+            IpAddress::V6(address) => address,
+        };
+        match (source, destination) {
+            (IpAddress::V4(source), IpAddress::V4(destination)) => self.add_pseudo_header(source, destination, protocol, len),
+            _ => self.add_ipv6_pseudo_header(v6(source), v6(destination), protocol, len),
+        }
+    }
+
+    /// The sum of what was added, folded but not complemented: what a
+    /// partial checksum left to a device holds
+    pub fn partial(self) -> u16 {
+        !self.finish()
+    }
+
+    /// The checksum of what was added
+    pub fn finish(mut self) -> u16 {
+        if let Some(last) = self.odd.take() {
+            self.add_word(u16::from_be_bytes([last, 0]));
+        }
+        !(self.sum as u16)
+    }
+}
+
+/// The checksum of `bytes`
+pub fn checksum(bytes: &[u8]) -> u16 {
+    let mut sum = Checksum::new();
+    sum.add_bytes(bytes);
+    sum.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_checksum() {
+        // An IPv4 header with a valid checksum
+        let header = [
+            0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11, 0xB8, 0x61, 0xC0, 0xA8, 0x00, 0x01, 0xC0, 0xA8,
+            0x00, 0xC7,
+        ];
+        assert_eq!(checksum(&header), 0);
+        let mut zeroed = header;
+        zeroed[10] = 0;
+        zeroed[11] = 0;
+        assert_eq!(checksum(&zeroed), 0xB861);
+
+        // Pieces of odd length sum like the whole
+        let mut split = Checksum::new();
+        split.add_bytes(&zeroed[..3]);
+        split.add_bytes(&zeroed[3..]);
+        assert_eq!(split.finish(), 0xB861);
+        assert_eq!(checksum(&[0x01]), !0x0100);
+    }
+}
@@ -0,0 +1,761 @@
+//! DHCP client
+//!
+//! Interfaces are configured without any setup: every Ethernet interface
+//! that has no IPv4 address when the network timer first sees it gets a
+//! [`DhcpClient`], which negotiates an address (RFC 2131), gives it to the
+//! interface with a default route through the router the server names, and
+//! renews the lease at T1 with the server and at T2 with any server. A
+//! lease that runs out, or a NAK, takes the address away and starts over.
+//! `nodhcp` on the kernel command line leaves interfaces alone; [`start`]
+//! and [`stop`] still control the client of one interface.
+//!
+//! The client sends its own datagrams, from 0.0.0.0 out of its interface
+//! until it has an address, and takes replies from a UDP socket of the
+//! kernel bound to port 68. Requests ask for broadcast replies, so they are
+//! received before the interface has an address; replies are matched to
+//! their client by hardware address and transaction id.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::collections::BTreeMap;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::{Mutex, Once};
+
+use crate::abi::error::KernelError;
+use crate::device::network::MacAddress;
+use crate::ipc::socket::SocketObject;
+use crate::object::capability::poll::{PollOps, POLLIN};
+use crate::random::get_random_u64;
+use crate::timer::ms_to_ticks;
+
+use super::address::{IpEndpoint, IpFamily, Ipv4Address, Ipv4Cidr};
+use super::ethernet::ETHERNET_STAGE;
+use super::ipv4::{self, SendOptions, PROTOCOL_UDP};
+use super::manager::{NetworkInterface, NetworkManager};
+use super::route::{routing_table, Route};
+use super::udp::{build_datagram, UdpSocket};
+use super::InterfaceId;
+
+pub const DHCP_SERVER_PORT: u16 = 67;
+pub const DHCP_CLIENT_PORT: u16 = 68;
+
+const BOOTREQUEST: u8 = 1;
+const BOOTREPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+/// Asks the server to broadcast its reply
+const FLAG_BROADCAST: u16 = 0x8000;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Length of the fixed part of a message, up to the magic cookie
+const FIXED_LEN: usize = 236;
+/// Shortest message BOOTP relays accept
+const MIN_MESSAGE_LEN: usize = 300;
+
+/// Options
+pub const OPTION_PAD: u8 = 0;
+pub const OPTION_SUBNET_MASK: u8 = 1;
+pub const OPTION_ROUTER: u8 = 3;
+pub const OPTION_DNS_SERVERS: u8 = 6;
+pub const OPTION_REQUESTED_ADDRESS: u8 = 50;
+pub const OPTION_LEASE_TIME: u8 = 51;
+pub const OPTION_MESSAGE_TYPE: u8 = 53;
+pub const OPTION_SERVER_ID: u8 = 54;
+pub const OPTION_PARAMETERS: u8 = 55;
+pub const OPTION_RENEWAL_TIME: u8 = 58;
+pub const OPTION_REBINDING_TIME: u8 = 59;
+pub const OPTION_END: u8 = 255;
+
+/// Message types
+pub const DHCPDISCOVER: u8 = 1;
+pub const DHCPOFFER: u8 = 2;
+pub const DHCPREQUEST: u8 = 3;
+pub const DHCPACK: u8 = 5;
+pub const DHCPNAK: u8 = 6;
+pub const DHCPRELEASE: u8 = 7;
+
+/// Lease time of a lease that does not end
+pub const INFINITE_LEASE: u32 = u32::MAX;
+
+/// First wait for a reply to a DISCOVER or REQUEST, doubled on every
+/// retransmission up to [`MAX_RETRANSMIT_MS`]
+pub const INITIAL_RETRANSMIT_MS: u64 = 4_000;
+pub const MAX_RETRANSMIT_MS: u64 = 64_000;
+/// REQUESTs sent for an offer before discovering again
+pub const MAX_REQUESTS: u32 = 4;
+/// Shortest wait between two REQUESTs renewing a lease
+pub const MIN_RENEW_RETRANSMIT_MS: u64 = 60_000;
+
+/// A DHCP message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DhcpMessage {
+    pub op: u8,
+    pub xid: u32,
+    pub flags: u16,
+    /// Address of a client that has one
+    pub ciaddr: Ipv4Address,
+    /// Address offered or given to the client
+    pub yiaddr: Ipv4Address,
+    pub siaddr: Ipv4Address,
+    pub chaddr: MacAddress,
+    /// Options in order, without pad and end
+    pub options: Vec<(u8, Vec<u8>)>,
+}
+
+impl DhcpMessage {
+    /// A request of `message_type` from `chaddr`
+    pub fn request(message_type: u8, xid: u32, chaddr: MacAddress) -> Self {
+        Self {
+            op: BOOTREQUEST,
+            xid,
+            flags: 0,
+            ciaddr: Ipv4Address::UNSPECIFIED,
+            yiaddr: Ipv4Address::UNSPECIFIED,
+            siaddr: Ipv4Address::UNSPECIFIED,
+            chaddr,
+            options: alloc::vec![(OPTION_MESSAGE_TYPE, alloc::vec![message_type])],
+        }
+    }
+
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < FIXED_LEN + MAGIC_COOKIE.len() || bytes[FIXED_LEN..FIXED_LEN + 4] != MAGIC_COOKIE {
+            return None;
+        }
+        if bytes[1] != HTYPE_ETHERNET || bytes[2] != 6 {
+            return None;
+        }
+        let mut options = Vec::new();
+        let mut rest = &bytes[FIXED_LEN + 4..];
+        while let Some((&code, tail)) = rest.split_first() {
+            match code {
+                OPTION_PAD => rest = tail,
+                OPTION_END => break,
+                _ => {
+                    let (&len, tail) = tail.split_first()?;
+                    let value = tail.get(..len as usize)?;
+                    options.push((code, value.to_vec()));
+                    rest = &tail[len as usize..];
+                }
+            }
+        }
+        Some(Self {
+            op: bytes[0],
+            xid: u32::from_be_bytes(bytes[4..8].try_into().unwrap()),
+            flags: u16::from_be_bytes([bytes[10], bytes[11]]),
+            ciaddr: Ipv4Address::from_slice(&bytes[12..16])?,
+            yiaddr: Ipv4Address::from_slice(&bytes[16..20])?,
+            siaddr: Ipv4Address::from_slice(&bytes[20..24])?,
+            chaddr: MacAddress::from_slice(&bytes[28..34]).ok()?,
+            options,
+        })
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = alloc::vec![0u8; FIXED_LEN];
+        bytes[0] = self.op;
+        bytes[1] = HTYPE_ETHERNET;
+        bytes[2] = 6;
+        bytes[4..8].copy_from_slice(&self.xid.to_be_bytes());
+        bytes[10..12].copy_from_slice(&self.flags.to_be_bytes());
+        bytes[12..16].copy_from_slice(&self.ciaddr.octets());
+        bytes[16..20].copy_from_slice(&self.yiaddr.octets());
+        bytes[20..24].copy_from_slice(&self.siaddr.octets());
+        bytes[28..34].copy_from_slice(self.chaddr.as_bytes());
+        bytes.extend_from_slice(&MAGIC_COOKIE);
+        for (code, value) in &self.options {
+            bytes.push(*code);
+            bytes.push(value.len() as u8);
+            bytes.extend_from_slice(value);
+        }
+        bytes.push(OPTION_END);
+        if bytes.len() < MIN_MESSAGE_LEN {
+            bytes.resize(MIN_MESSAGE_LEN, OPTION_PAD);
+        }
+        bytes
+    }
+
+    pub fn option(&self, code: u8) -> Option<&[u8]> {
+        self.options.iter().find(|(option, _)| *option == code).map(|(_, value)| value.as_slice())
+    }
+
+    pub fn message_type(&self) -> Option<u8> {
+        self.option(OPTION_MESSAGE_TYPE).and_then(|value| value.first().copied())
+    }
+
+    /// The first address of an option listing addresses
+    pub fn address_option(&self, code: u8) -> Option<Ipv4Address> {
+        self.option(code).and_then(|value| Ipv4Address::from_slice(value.get(..4)?))
+    }
+
+    /// A time in seconds
+    pub fn seconds_option(&self, code: u8) -> Option<u32> {
+        self.option(code).and_then(|value| Some(u32::from_be_bytes(value.get(..4)?.try_into().ok()?)))
+    }
+}
+
+/// What a server gave an interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    pub address: Ipv4Cidr,
+    pub router: Option<Ipv4Address>,
+    pub dns_servers: Vec<Ipv4Address>,
+    pub server: Ipv4Address,
+    /// Length of the lease in seconds, [`INFINITE_LEASE`] if it does not end
+    pub lease_secs: u32,
+    /// Ticks at which the lease is renewed, rebound and lost; `None` for
+    /// an infinite lease
+    pub renew_at: Option<u64>,
+    pub rebind_at: Option<u64>,
+    pub expires_at: Option<u64>,
+}
+
+impl Lease {
+    /// The lease of an ACK received at `now`
+    fn from_ack(ack: &DhcpMessage, server: Ipv4Address, now: u64) -> Option<Self> {
+        if ack.yiaddr.is_unspecified() {
+            return None;
+        }
+        let prefix_len = match ack.address_option(OPTION_SUBNET_MASK) {
+            Some(mask) => prefix_len(mask)?,
+            None => classful_prefix_len(ack.yiaddr),
+        };
+        let lease_secs = ack.seconds_option(OPTION_LEASE_TIME).unwrap_or(INFINITE_LEASE);
+        let at = |secs: u32| (lease_secs != INFINITE_LEASE).then(|| now + ms_to_ticks(secs as u64 * 1000));
+        let renew_secs = ack.seconds_option(OPTION_RENEWAL_TIME).unwrap_or(lease_secs / 2);
+        let rebind_secs = ack.seconds_option(OPTION_REBINDING_TIME).unwrap_or((lease_secs as u64 * 7 / 8) as u32);
+        let dns_servers = ack
+            .option(OPTION_DNS_SERVERS)
+            .map(|value| value.chunks_exact(4).filter_map(Ipv4Address::from_slice).collect())
+            .unwrap_or_default();
+        Some(Self {
+            address: Ipv4Cidr::new(ack.yiaddr, prefix_len).ok()?,
+            router: ack.address_option(OPTION_ROUTER).filter(|router| !router.is_unspecified()),
+            dns_servers,
+            server: ack.address_option(OPTION_SERVER_ID).unwrap_or(server),
+            lease_secs,
+            renew_at: at(renew_secs.min(lease_secs)),
+            rebind_at: at(rebind_secs.min(lease_secs)),
+            expires_at: at(lease_secs),
+        })
+    }
+}
+
+/// The prefix length of a netmask, `None` if its ones are not contiguous
+fn prefix_len(mask: Ipv4Address) -> Option<u8> {
+    let mask = mask.to_u32();
+    let len = mask.leading_ones();
+    (mask.checked_shl(len).unwrap_or(0) == 0).then_some(len as u8)
+}
+
+/// The prefix length of the class of `address`, for a server that names
+/// no netmask
+fn classful_prefix_len(address: Ipv4Address) -> u8 {
+    match address.octets()[0] {
+        0..=127 => 8,
+        128..=191 => 16,
+        _ => 24,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DhcpState {
+    /// About to discover servers
+    Init,
+    /// Discovering, waiting for an offer
+    Selecting,
+    /// Requesting an offered address
+    Requesting,
+    Bound,
+    /// Extending the lease with the server that gave it
+    Renewing,
+    /// Extending the lease with any server
+    Rebinding,
+    /// Given up on by [`stop`]
+    Stopped,
+}
+
+impl DhcpState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DhcpState::Init => "INIT",
+            DhcpState::Selecting => "SELECTING",
+            DhcpState::Requesting => "REQUESTING",
+            DhcpState::Bound => "BOUND",
+            DhcpState::Renewing => "RENEWING",
+            DhcpState::Rebinding => "REBINDING",
+            DhcpState::Stopped => "STOPPED",
+        }
+    }
+}
+
+/// What a client needs done on its interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DhcpAction {
+    /// Send `message` from `source` to `destination`
+    Send { message: DhcpMessage, source: Ipv4Address, destination: Ipv4Address },
+    /// Give the interface the address and router of a lease
+    Configure(Lease),
+    /// Take the address and router of a lease from the interface
+    Deconfigure(Lease),
+}
+
+/// The DHCP state machine of one interface
+pub struct DhcpClient {
+    mac: MacAddress,
+    state: DhcpState,
+    xid: u32,
+    /// Server and address of the offer being requested
+    offer: Option<(Ipv4Address, Ipv4Address)>,
+    lease: Option<Lease>,
+    /// Tick of the next retransmission
+    retransmit_at: u64,
+    /// Messages sent in the current state
+    attempts: u32,
+}
+
+impl DhcpClient {
+    pub fn new(mac: MacAddress) -> Self {
+        Self {
+            mac,
+            state: DhcpState::Init,
+            xid: 0,
+            offer: None,
+            lease: None,
+            retransmit_at: 0,
+            attempts: 0,
+        }
+    }
+
+    pub fn state(&self) -> DhcpState {
+        self.state
+    }
+
+    pub fn lease(&self) -> Option<&Lease> {
+        self.lease.as_ref()
+    }
+
+    /// Wait after `attempts` unanswered DISCOVERs or REQUESTs
+    fn backoff(attempts: u32) -> u64 {
+        ms_to_ticks((INITIAL_RETRANSMIT_MS << attempts.min(4)).min(MAX_RETRANSMIT_MS))
+    }
+
+    fn request(&self, message_type: u8) -> DhcpMessage {
+        let mut message = DhcpMessage::request(message_type, self.xid, self.mac);
+        if message_type != DHCPRELEASE {
+            message.flags = FLAG_BROADCAST;
+            message.options.push((
+                OPTION_PARAMETERS,
+                alloc::vec![
+                    OPTION_SUBNET_MASK,
+                    OPTION_ROUTER,
+                    OPTION_DNS_SERVERS,
+                    OPTION_LEASE_TIME,
+                    OPTION_RENEWAL_TIME,
+                    OPTION_REBINDING_TIME,
+                ],
+            ));
+        }
+        message
+    }
+
+    fn broadcast(message: DhcpMessage) -> DhcpAction {
+        DhcpAction::Send { message, source: Ipv4Address::UNSPECIFIED, destination: Ipv4Address::BROADCAST }
+    }
+
+    /// Start over with a DISCOVER
+    fn discover(&mut self, now: u64, actions: &mut Vec<DhcpAction>) {
+        self.xid = get_random_u64() as u32;
+        self.offer = None;
+        self.state = DhcpState::Selecting;
+        self.attempts = 0;
+        self.send_discover(now, actions);
+    }
+
+    fn send_discover(&mut self, now: u64, actions: &mut Vec<DhcpAction>) {
+        actions.push(Self::broadcast(self.request(DHCPDISCOVER)));
+        self.retransmit_at = now + Self::backoff(self.attempts);
+        self.attempts += 1;
+    }
+
+    /// REQUEST the offered address
+    fn send_selecting_request(&mut self, now: u64, actions: &mut Vec<DhcpAction>) {
+        let Some((server, address)) = self.offer else {
+            return;
+        };
+        let mut message = self.request(DHCPREQUEST);
+        message.options.push((OPTION_REQUESTED_ADDRESS, address.octets().to_vec()));
+        message.options.push((OPTION_SERVER_ID, server.octets().to_vec()));
+        actions.push(Self::broadcast(message));
+        self.retransmit_at = now + Self::backoff(self.attempts);
+        self.attempts += 1;
+    }
+
+    /// REQUEST an extension of the lease, from the server that gave it
+    /// while renewing and from any while rebinding; the next is sent half
+    /// way to `until`
+    fn send_extension_request(&mut self, now: u64, until: u64, actions: &mut Vec<DhcpAction>) {
+        let Some(lease) = &self.lease else {
+            return;
+        };
+        let mut message = self.request(DHCPREQUEST);
+        message.ciaddr = lease.address.address;
+        let source = lease.address.address;
+        let destination = match self.state {
+            DhcpState::Renewing => {
+                message.flags = 0;
+                lease.server
+            }
+            _ => Ipv4Address::BROADCAST,
+        };
+        actions.push(DhcpAction::Send { message, source, destination });
+        self.retransmit_at = now + (until.saturating_sub(now) / 2).max(ms_to_ticks(MIN_RENEW_RETRANSMIT_MS));
+        self.attempts += 1;
+    }
+
+    /// Give up the lease, if there is one
+    fn lose_lease(&mut self, actions: &mut Vec<DhcpAction>) {
+        if let Some(lease) = self.lease.take() {
+            actions.push(DhcpAction::Deconfigure(lease));
+        }
+    }
+
+    /// Advance to the tick `now`: send what is due and let an expired
+    /// lease go
+    pub fn on_timer(&mut self, now: u64) -> Vec<DhcpAction> {
+        let mut actions = Vec::new();
+        let (renew_at, rebind_at, expires_at) = match &self.lease {
+            Some(lease) => (lease.renew_at, lease.rebind_at, lease.expires_at),
+            None => (None, None, None),
+        };
+        if expires_at.is_some_and(|expires_at| now >= expires_at) {
+            self.lose_lease(&mut actions);
+            self.discover(now, &mut actions);
+            return actions;
+        }
+        match self.state {
+            DhcpState::Init => self.discover(now, &mut actions),
+            DhcpState::Selecting if now >= self.retransmit_at => self.send_discover(now, &mut actions),
+            DhcpState::Requesting if now >= self.retransmit_at => {
+                if self.attempts >= MAX_REQUESTS {
+                    self.discover(now, &mut actions);
+                } else {
+                    self.send_selecting_request(now, &mut actions);
+                }
+            }
+            DhcpState::Bound if renew_at.is_some_and(|renew_at| now >= renew_at) => {
+                self.state = DhcpState::Renewing;
+                self.attempts = 0;
+                self.send_extension_request(now, rebind_at.unwrap_or(now), &mut actions);
+            }
+            DhcpState::Renewing if rebind_at.is_some_and(|rebind_at| now >= rebind_at) => {
+                self.state = DhcpState::Rebinding;
+                self.attempts = 0;
+                self.send_extension_request(now, expires_at.unwrap_or(now), &mut actions);
+            }
+            DhcpState::Renewing if now >= self.retransmit_at => {
+                self.send_extension_request(now, rebind_at.unwrap_or(now), &mut actions)
+            }
+            DhcpState::Rebinding if now >= self.retransmit_at => {
+                self.send_extension_request(now, expires_at.unwrap_or(now), &mut actions)
+            }
+            _ => {}
+        }
+        actions
+    }
+
+    /// Whether `message` is a reply to this client
+    pub fn is_for(&self, message: &DhcpMessage) -> bool {
+        message.op == BOOTREPLY && message.xid == self.xid && message.chaddr == self.mac
+    }
+
+    /// Take a reply from a server received at `now`
+    pub fn on_message(&mut self, message: &DhcpMessage, now: u64) -> Vec<DhcpAction> {
+        let mut actions = Vec::new();
+        if !self.is_for(message) {
+            return actions;
+        }
+        match (self.state, message.message_type()) {
+            (DhcpState::Selecting, Some(DHCPOFFER)) => {
+                let Some(server) = message.address_option(OPTION_SERVER_ID) else {
+                    return actions;
+                };
+                if message.yiaddr.is_unspecified() {
+                    return actions;
+                }
+                self.offer = Some((server, message.yiaddr));
+                self.state = DhcpState::Requesting;
+                self.attempts = 0;
+                self.send_selecting_request(now, &mut actions);
+            }
+            (DhcpState::Requesting | DhcpState::Renewing | DhcpState::Rebinding, Some(DHCPACK)) => {
+                let server = match (self.offer, &self.lease) {
+                    (Some((server, _)), _) => server,
+                    (None, Some(lease)) => lease.server,
+                    (None, None) => Ipv4Address::UNSPECIFIED,
+                };
+                let Some(lease) = Lease::from_ack(message, server, now) else {
+                    return actions;
+                };
+                if let Some(old) = self.lease.take() {
+                    if old.address != lease.address || old.router != lease.router {
+                        actions.push(DhcpAction::Deconfigure(old));
+                        actions.push(DhcpAction::Configure(lease.clone()));
+                    }
+                } else {
+                    actions.push(DhcpAction::Configure(lease.clone()));
+                }
+                self.lease = Some(lease);
+                self.offer = None;
+                self.state = DhcpState::Bound;
+            }
+            (DhcpState::Requesting | DhcpState::Renewing | DhcpState::Rebinding, Some(DHCPNAK)) => {
+                self.lose_lease(&mut actions);
+                self.discover(now, &mut actions);
+            }
+            _ => {}
+        }
+        actions
+    }
+
+    /// Stop: RELEASE the lease, if there is one, and let it go
+    pub fn stop(&mut self) -> Vec<DhcpAction> {
+        let mut actions = Vec::new();
+        if let Some(lease) = &self.lease {
+            let mut message = self.request(DHCPRELEASE);
+            message.xid = get_random_u64() as u32;
+            message.ciaddr = lease.address.address;
+            message.options.push((OPTION_SERVER_ID, lease.server.octets().to_vec()));
+            actions.push(DhcpAction::Send { message, source: lease.address.address, destination: lease.server });
+        }
+        self.lose_lease(&mut actions);
+        self.state = DhcpState::Stopped;
+        actions
+    }
+}
+
+/// Whether interfaces without an address are configured by DHCP
+static AUTO_CONFIGURE: AtomicBool = AtomicBool::new(true);
+
+/// Clients by interface; an interface DHCP left alone has none
+static CLIENTS: Mutex<BTreeMap<InterfaceId, DhcpClient>> = Mutex::new(BTreeMap::new());
+/// Interfaces the network timer has seen, so that it only starts clients
+/// for new ones
+static SEEN: Mutex<Vec<InterfaceId>> = Mutex::new(Vec::new());
+/// The socket replies are received from
+static SOCKET: Once<Option<Arc<UdpSocket>>> = Once::new();
+
+/// Apply DHCP options from the kernel command line
+pub fn parse_cmdline(cmdline: &str) {
+    if cmdline.split_whitespace().any(|arg| arg == "nodhcp") {
+        AUTO_CONFIGURE.store(false, Ordering::Relaxed);
+    }
+}
+
+fn socket() -> Option<&'static Arc<UdpSocket>> {
+    SOCKET
+        .call_once(|| {
+            let socket = UdpSocket::new(IpFamily::V4, true);
+            let local = IpEndpoint::new(Ipv4Address::UNSPECIFIED.into(), DHCP_CLIENT_PORT);
+            match socket.bind(&local.to_string()) {
+                Ok(()) => Some(socket),
+                Err(error) => {
+                    crate::early_println!("[dhcp] Cannot bind port {}: {:?}", DHCP_CLIENT_PORT, error);
+                    None
+                }
+            }
+        })
+        .as_ref()
+}
+
+/// Do what the client of `interface` needs done
+fn perform(interface: &Arc<NetworkInterface>, actions: Vec<DhcpAction>) {
+    let default = Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 0).unwrap();
+    for action in actions {
+        match action {
+            DhcpAction::Send { message, source, destination } => {
+                let datagram = build_datagram(
+                    IpEndpoint::new(source.into(), DHCP_CLIENT_PORT),
+                    IpEndpoint::new(destination.into(), DHCP_SERVER_PORT),
+                    &message.to_bytes(),
+                );
+                let options = SendOptions {
+                    source: Some(source.into()),
+                    interface: Some(interface.clone()),
+                    ..SendOptions::default()
+                };
+                let _ = ipv4::send(destination, PROTOCOL_UDP, &datagram, &options);
+            }
+            DhcpAction::Configure(lease) => {
+                let _ = ipv4::add_address(interface, lease.address);
+                if let Some(router) = lease.router {
+                    let route = Route { destination: default, gateway: Some(router), interface: interface.id(), metric: 0 };
+                    let _ = routing_table().add(route);
+                }
+                crate::early_println!(
+                    "[dhcp] {}: leased {} from {} for {} s",
+                    interface.name(),
+                    lease.address,
+                    lease.server,
+                    lease.lease_secs
+                );
+            }
+            DhcpAction::Deconfigure(lease) => {
+                let _ = routing_table().remove(default, Some(interface.id()));
+                let _ = ipv4::remove_address(interface, lease.address.address);
+                crate::early_println!("[dhcp] {}: lost {}", interface.name(), lease.address);
+            }
+        }
+    }
+}
+
+/// Configure `interface` by DHCP from now on
+///
+/// Fails with `Exists` if it already is.
+pub fn start(interface: &Arc<NetworkInterface>) -> Result<(), KernelError> {
+    let mac = interface.mac_address()?;
+    let mut clients = CLIENTS.lock();
+    if clients.get(&interface.id()).is_some_and(|client| client.state != DhcpState::Stopped) {
+        return Err(KernelError::Exists);
+    }
+    socket().ok_or(KernelError::AddressInUse)?;
+    let mut client = DhcpClient::new(mac);
+    let actions = client.on_timer(crate::timer::get_tick());
+    clients.insert(interface.id(), client);
+    drop(clients);
+    perform(interface, actions);
+    Ok(())
+}
+
+/// Stop configuring `interface` by DHCP, releasing its lease
+pub fn stop(interface: &Arc<NetworkInterface>) -> Result<(), KernelError> {
+    let actions = CLIENTS.lock().get_mut(&interface.id()).ok_or(KernelError::NotFound)?.stop();
+    perform(interface, actions);
+    Ok(())
+}
+
+/// State of the client of the interface `id`
+pub fn state(id: InterfaceId) -> Option<DhcpState> {
+    CLIENTS.lock().get(&id).map(|client| client.state)
+}
+
+/// Lease of the interface `id`
+pub fn lease(id: InterfaceId) -> Option<Lease> {
+    CLIENTS.lock().get(&id).and_then(|client| client.lease.clone())
+}
+
+/// Network timer: start clients for new interfaces, take replies and send
+/// what is due
+fn tick(manager: &NetworkManager, now: u64) {
+    let interfaces = manager.interfaces();
+    if AUTO_CONFIGURE.load(Ordering::Relaxed) {
+        let mut seen = SEEN.lock();
+        seen.retain(|id| interfaces.iter().any(|interface| interface.id() == *id));
+        let new: Vec<_> = interfaces.iter().filter(|interface| !seen.contains(&interface.id())).cloned().collect();
+        for interface in new {
+            seen.push(interface.id());
+            if interface.entry_stage() == ETHERNET_STAGE && interface.ipv4_addresses().is_empty() {
+                let _ = start(&interface);
+            }
+        }
+    }
+
+    let mut pending = Vec::new();
+    let mut clients = CLIENTS.lock();
+    clients.retain(|id, _| interfaces.iter().any(|interface| interface.id() == *id));
+    if clients.is_empty() {
+        return;
+    }
+    if let Some(socket) = socket().filter(|socket| socket.poll_events() & POLLIN != 0) {
+        let mut buffer = alloc::vec![0u8; 1500];
+        while let Ok(received) = socket.receive(&mut buffer) {
+            let Some(message) = DhcpMessage::parse(&buffer[..received.len]) else {
+                continue;
+            };
+            if let Some((id, client)) = clients.iter_mut().find(|(_, client)| client.is_for(&message)) {
+                pending.push((*id, client.on_message(&message, now)));
+            }
+        }
+    }
+    for (id, client) in clients.iter_mut() {
+        pending.push((*id, client.on_timer(now)));
+    }
+    drop(clients);
+
+    for (id, actions) in pending {
+        if let Some(interface) = interfaces.iter().find(|interface| interface.id() == id) {
+            if !actions.is_empty() {
+                perform(interface, actions);
+            }
+        }
+    }
+}
+
+/// Register the DHCP client with `manager`
+pub fn register(manager: &NetworkManager) -> Result<(), KernelError> {
+    manager.register_timer(tick);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(client: &DhcpClient, message_type: u8, options: Vec<(u8, Vec<u8>)>) -> DhcpMessage {
+        let mut message = DhcpMessage::request(message_type, client.xid, client.mac);
+        message.op = BOOTREPLY;
+        message.yiaddr = Ipv4Address::new(10, 0, 2, 15);
+        message.options.extend(options);
+        DhcpMessage::parse(&message.to_bytes()).unwrap()
+    }
+
+    #[test_case]
+    fn test_dhcp_lease() {
+        let mac = MacAddress::new([2, 0, 0, 0, 0, 1]);
+        let server = Ipv4Address::new(10, 0, 2, 2);
+        let mut client = DhcpClient::new(mac);
+
+        // DISCOVER, retransmitted with backoff
+        let actions = client.on_timer(0);
+        let DhcpAction::Send { message, destination, .. } = &actions[0] else { panic!() };
+        assert_eq!((message.message_type(), *destination), (Some(DHCPDISCOVER), Ipv4Address::BROADCAST));
+        assert_eq!(message.to_bytes().len(), MIN_MESSAGE_LEN);
+        assert!(client.on_timer(1).is_empty());
+        assert_eq!(client.on_timer(ms_to_ticks(INITIAL_RETRANSMIT_MS)).len(), 1);
+
+        // An OFFER is requested, and the ACK gives the lease
+        let offer = reply(&client, DHCPOFFER, alloc::vec![(OPTION_SERVER_ID, server.octets().to_vec())]);
+        let actions = client.on_message(&offer, 100);
+        let DhcpAction::Send { message, .. } = &actions[0] else { panic!() };
+        assert_eq!(message.address_option(OPTION_REQUESTED_ADDRESS), Some(offer.yiaddr));
+        assert_eq!(client.state(), DhcpState::Requesting);
+        let ack = reply(
+            &client,
+            DHCPACK,
+            alloc::vec![
+                (OPTION_SERVER_ID, server.octets().to_vec()),
+                (OPTION_SUBNET_MASK, alloc::vec![255, 255, 255, 0]),
+                (OPTION_ROUTER, server.octets().to_vec()),
+                (OPTION_LEASE_TIME, 3600u32.to_be_bytes().to_vec()),
+            ],
+        );
+        let actions = client.on_message(&ack, 100);
+        let DhcpAction::Configure(lease) = &actions[0] else { panic!() };
+        assert_eq!(lease.address, Ipv4Cidr::new(offer.yiaddr, 24).unwrap());
+        assert_eq!((lease.router, lease.renew_at), (Some(server), Some(100 + ms_to_ticks(1_800_000))));
+
+        // Renewed with the server at T1; the same lease changes nothing
+        let actions = client.on_timer(100 + ms_to_ticks(1_800_000));
+        let DhcpAction::Send { message, source, destination } = &actions[0] else { panic!() };
+        assert_eq!((message.ciaddr, *source, *destination), (offer.yiaddr, offer.yiaddr, server));
+        assert_eq!(client.state(), DhcpState::Renewing);
+        let ack = reply(&client, DHCPACK, ack.options.clone());
+        assert!(client.on_message(&ack, 200 + ms_to_ticks(1_800_000)).is_empty());
+        assert_eq!(client.state(), DhcpState::Bound);
+
+        // Expiry takes the address and starts over
+        let actions = client.on_timer(ms_to_ticks(10_000_000));
+        assert!(matches!(actions[0], DhcpAction::Deconfigure(_)));
+        assert_eq!(client.state(), DhcpState::Selecting);
+        assert_eq!(prefix_len(Ipv4Address::new(255, 0, 255, 0)), None);
+    }
+}
@@ -0,0 +1,180 @@
+//! Ethernet framing
+//!
+//! Frames received on an interface enter the pipeline at the "ethernet"
+//! stage, whose default processor consumes the Ethernet II header, drops
+//! frames addressed to other stations and passes the rest to the
+//! "ethertype" stage, keyed by their EtherType. Protocols above Ethernet
+//! register there (ARP under 0x0806, IPv4 under 0x0800, ...).
+//!
+//! On the way out, [`send_frame`] puts the header in front of a packet and
+//! pads it to the shortest frame Ethernet allows.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::abi::error::KernelError;
+use crate::device::network::MacAddress;
+
+use super::manager::{NetworkInterface, NetworkManager, DEFAULT_ENTRY_STAGE};
+use super::packet::{MetadataValue, NetworkPacket};
+use super::pipeline::{PacketProcessor, ProcessResult};
+
+pub const ETHERNET_HEADER_LEN: usize = 14;
+/// Shortest frame without the frame check sequence
+pub const ETHERNET_MIN_FRAME_LEN: usize = 60;
+
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+pub const ETHERTYPE_IPV6: u16 = 0x86DD;
+
+pub const BROADCAST_MAC: MacAddress = MacAddress::new([0xFF; 6]);
+
+/// Stage received frames enter at
+pub const ETHERNET_STAGE: &str = DEFAULT_ENTRY_STAGE;
+/// Stage of the protocols above Ethernet, keyed by EtherType
+pub const ETHERTYPE_STAGE: &str = "ethertype";
+
+/// Metadata set on received frames
+pub const META_SOURCE_MAC: &str = "ethernet.source";
+pub const META_DESTINATION_MAC: &str = "ethernet.destination";
+pub const META_ETHERTYPE: &str = "ethernet.type";
+
+/// An Ethernet II header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EthernetHeader {
+    pub destination: MacAddress,
+    pub source: MacAddress,
+    pub ethertype: u16,
+}
+
+impl EthernetHeader {
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < ETHERNET_HEADER_LEN {
+            return None;
+        }
+        Some(Self {
+            destination: MacAddress::from_slice(&bytes[0..6]).ok()?,
+            source: MacAddress::from_slice(&bytes[6..12]).ok()?,
+            ethertype: u16::from_be_bytes([bytes[12], bytes[13]]),
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; ETHERNET_HEADER_LEN] {
+        let mut bytes = [0; ETHERNET_HEADER_LEN];
+        bytes[0..6].copy_from_slice(self.destination.as_bytes());
+        bytes[6..12].copy_from_slice(self.source.as_bytes());
+        bytes[12..14].copy_from_slice(&self.ethertype.to_be_bytes());
+        bytes
+    }
+}
+
+/// The source address of a received frame
+pub fn source_mac(packet: &NetworkPacket) -> Option<MacAddress> {
+    match packet.metadata(META_SOURCE_MAC) {
+        Some(MetadataValue::Bytes(bytes)) => MacAddress::from_slice(bytes).ok(),
+        _ => None,
+    }
+}
+
+/// Default processor of the "ethernet" stage
+pub struct EthernetProcessor;
+
+impl PacketProcessor for EthernetProcessor {
+    fn name(&self) -> &'static str {
+        "ethernet"
+    }
+
+    fn process(&self, packet: &mut NetworkPacket) -> ProcessResult {
+        let Some(header) = packet.consume_header("ethernet", ETHERNET_HEADER_LEN).ok().and_then(EthernetHeader::parse) else {
+            return ProcessResult::Dropped("Truncated Ethernet frame");
+        };
+        let Some(local) = packet.interface().and_then(|interface| interface.mac_address().ok()) else {
+            return ProcessResult::Dropped("No interface address");
+        };
+        if header.destination != local && !header.destination.is_multicast() {
+            return ProcessResult::Dropped("Not addressed to the interface");
+        }
+        packet.set_metadata(META_SOURCE_MAC, MetadataValue::Bytes(header.source.as_bytes().to_vec()));
+        packet.set_metadata(META_DESTINATION_MAC, MetadataValue::Bytes(header.destination.as_bytes().to_vec()));
+        packet.set_metadata(META_ETHERTYPE, MetadataValue::Int(header.ethertype as u64));
+        ProcessResult::Forward { stage: ETHERTYPE_STAGE, key: header.ethertype as u64 }
+    }
+}
+
+/// Send `packet` to `destination` on `interface` as a frame of `ethertype`
+pub fn send_frame(
+    interface: &NetworkInterface,
+    destination: MacAddress,
+    ethertype: u16,
+    mut packet: NetworkPacket,
+) -> Result<(), KernelError> {
+    let header = EthernetHeader { destination, source: interface.mac_address()?, ethertype };
+    packet.push_header("ethernet", &header.to_bytes());
+    packet.pad(ETHERNET_MIN_FRAME_LEN);
+    interface.transmit(packet)
+}
+
+/// Build a frame from its parts, for callers outside the pipeline
+pub fn build_frame(header: &EthernetHeader, payload: &[u8]) -> Vec<u8> {
+    let mut packet = NetworkPacket::outgoing(payload.to_vec());
+    packet.push_header("ethernet", &header.to_bytes());
+    packet.pad(ETHERNET_MIN_FRAME_LEN);
+    packet.into_data()
+}
+
+/// Add the Ethernet stages to the pipeline of `manager`
+pub fn register(manager: &NetworkManager) -> Result<(), KernelError> {
+    let pipeline = manager.pipeline();
+    pipeline.add_stage(ETHERNET_STAGE)?;
+    pipeline.add_stage(ETHERTYPE_STAGE)?;
+    pipeline.set_default_processor(ETHERNET_STAGE, Some(Arc::new(EthernetProcessor)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::network::{GenericNetworkDevice, NetworkDevice};
+    use crate::network::pipeline::PacketFate;
+
+    struct Sink;
+
+    impl PacketProcessor for Sink {
+        fn name(&self) -> &'static str {
+            "sink"
+        }
+
+        fn process(&self, packet: &mut NetworkPacket) -> ProcessResult {
+            if packet.payload().starts_with(&[0xAB]) { ProcessResult::Consumed } else { ProcessResult::Dropped("Bad payload") }
+        }
+    }
+
+    #[test_case]
+    fn test_ethernet_demux() {
+        let manager = NetworkManager::new();
+        register(&manager).unwrap();
+        manager.pipeline().register_processor(ETHERTYPE_STAGE, ETHERTYPE_IPV4 as u64, Arc::new(Sink)).unwrap();
+        let mut device = GenericNetworkDevice::new("test0");
+        device.init_network().unwrap();
+        let local = device.get_mac_address().unwrap();
+        let interface = manager.attach_device("test0", Arc::new(device), None, ETHERNET_STAGE).unwrap();
+
+        let remote = MacAddress::new([2, 0, 0, 0, 0, 1]);
+        let to_us = EthernetHeader { destination: local, source: remote, ethertype: ETHERTYPE_IPV4 };
+        let frame = build_frame(&to_us, &[0xAB]);
+        assert_eq!(frame.len(), ETHERNET_MIN_FRAME_LEN);
+        assert_eq!(EthernetHeader::parse(&frame), Some(to_us));
+        assert_eq!(manager.receive(&interface, frame), PacketFate::Consumed);
+
+        let broadcast = EthernetHeader { destination: BROADCAST_MAC, ..to_us };
+        assert_eq!(manager.receive(&interface, build_frame(&broadcast, &[0xAB])), PacketFate::Consumed);
+
+        let to_other = EthernetHeader { destination: MacAddress::new([2, 0, 0, 0, 0, 2]), ..to_us };
+        assert_eq!(
+            manager.receive(&interface, build_frame(&to_other, &[0xAB])),
+            PacketFate::Dropped("Not addressed to the interface")
+        );
+        let arp = EthernetHeader { ethertype: ETHERTYPE_ARP, ..to_us };
+        assert_eq!(manager.receive(&interface, build_frame(&arp, &[0xAB])), PacketFate::Dropped("No processor for the packet"));
+        assert_eq!(manager.receive(&interface, alloc::vec![0; 10]), PacketFate::Dropped("Truncated Ethernet frame"));
+    }
+}
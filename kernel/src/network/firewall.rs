@@ -0,0 +1,453 @@
+//! Packet filter
+//!
+//! IPv4 datagrams pass three hooks, each with a chain of rules:
+//! `prerouting` for every datagram received, before the host checks that it
+//! is addressed to it; `input` for datagrams delivered to the host, once
+//! reassembled; and `output` for datagrams the host sends, once routed.
+//! Datagrams the host sends to itself pass `output`, then `prerouting` and
+//! `input` as they are received.
+//!
+//! The rules of a chain are tried in order. A rule matches on addresses,
+//! protocol, ports and interface; the first matching `accept` or `drop`
+//! rule decides, and `log` rules only report the datagram and go on. A
+//! datagram no rule decides gets the policy of the chain, `accept` unless
+//! set otherwise.
+//!
+//! The rules are shown and changed through `/proc/firewall`, by privileged
+//! tasks, with one command per write:
+//!
+//! - `append CHAIN ACTION [MATCH...]`, `insert CHAIN INDEX ACTION [MATCH...]`
+//! - `delete CHAIN INDEX`, `flush [CHAIN]`, `policy CHAIN accept|drop`
+//!
+//! where a match is `proto tcp|udp|icmp|N`, `src CIDR`, `dst CIDR`,
+//! `sport PORT[:PORT]`, `dport PORT[:PORT]` or `iface NAME`.
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use spin::RwLock;
+
+use crate::task::mytask;
+
+use super::address::{Ipv4Address, Ipv4Cidr};
+use super::ipv4::{PROTOCOL_ICMP, PROTOCOL_TCP, PROTOCOL_UDP};
+
+/// Rules a chain holds at most
+pub const MAX_RULES: usize = 256;
+
+/// Where a datagram meets the filter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirewallHook {
+    Prerouting,
+    Input,
+    Output,
+}
+
+impl FirewallHook {
+    const ALL: [FirewallHook; 3] = [FirewallHook::Prerouting, FirewallHook::Input, FirewallHook::Output];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FirewallHook::Prerouting => "prerouting",
+            FirewallHook::Input => "input",
+            FirewallHook::Output => "output",
+        }
+    }
+
+    fn parse(name: &str) -> Result<Self, &'static str> {
+        Self::ALL.into_iter().find(|hook| hook.name() == name).ok_or("Unknown firewall chain")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FirewallAction {
+    Accept,
+    Drop,
+    /// Report the datagram and go on with the next rule
+    Log,
+}
+
+impl FirewallAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FirewallAction::Accept => "accept",
+            FirewallAction::Drop => "drop",
+            FirewallAction::Log => "log",
+        }
+    }
+
+    fn parse(name: &str) -> Result<Self, &'static str> {
+        match name {
+            "accept" => Ok(FirewallAction::Accept),
+            "drop" => Ok(FirewallAction::Drop),
+            "log" => Ok(FirewallAction::Log),
+            _ => Err("Unknown firewall action"),
+        }
+    }
+}
+
+/// An inclusive range of ports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortRange {
+    pub first: u16,
+    pub last: u16,
+}
+
+impl PortRange {
+    fn parse(text: &str) -> Result<Self, &'static str> {
+        let port = |text: &str| text.parse::<u16>().map_err(|_| "Invalid port");
+        let (first, last) = match text.split_once(':') {
+            Some((first, last)) => (port(first)?, port(last)?),
+            None => (port(text)?, port(text)?),
+        };
+        if first > last {
+            return Err("Invalid port range");
+        }
+        Ok(Self { first, last })
+    }
+
+    fn contains(&self, port: u16) -> bool {
+        (self.first..=self.last).contains(&port)
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.first == self.last {
+            write!(f, "{}", self.first)
+        } else {
+            write!(f, "{}:{}", self.first, self.last)
+        }
+    }
+}
+
+/// What the rules see of a datagram
+#[derive(Debug, Clone, Copy)]
+pub struct PacketInfo<'a> {
+    /// Interface it came in on, or goes out of
+    pub interface: &'a str,
+    pub source: Ipv4Address,
+    pub destination: Ipv4Address,
+    pub protocol: u8,
+    /// Source and destination ports of a TCP or UDP datagram; `None` for
+    /// other protocols and fragments other than the first
+    pub ports: Option<(u16, u16)>,
+}
+
+impl<'a> PacketInfo<'a> {
+    /// The information of a datagram of `protocol` whose payload starts with
+    /// `transport`, or that is a fragment other than the first if `None`
+    pub fn new(
+        interface: &'a str,
+        source: Ipv4Address,
+        destination: Ipv4Address,
+        protocol: u8,
+        transport: Option<&[u8]>,
+    ) -> Self {
+        let ports = transport
+            .filter(|_| protocol == PROTOCOL_TCP || protocol == PROTOCOL_UDP)
+            .and_then(|transport| transport.get(..4))
+            .map(|ports| (u16::from_be_bytes([ports[0], ports[1]]), u16::from_be_bytes([ports[2], ports[3]])));
+        Self { interface, source, destination, protocol, ports }
+    }
+}
+
+pub struct FirewallRule {
+    pub action: FirewallAction,
+    pub protocol: Option<u8>,
+    pub source: Option<Ipv4Cidr>,
+    pub destination: Option<Ipv4Cidr>,
+    pub source_port: Option<PortRange>,
+    pub destination_port: Option<PortRange>,
+    pub interface: Option<String>,
+    /// Datagrams the rule matched
+    packets: AtomicU64,
+}
+
+impl FirewallRule {
+    pub fn new(action: FirewallAction) -> Self {
+        Self {
+            action,
+            protocol: None,
+            source: None,
+            destination: None,
+            source_port: None,
+            destination_port: None,
+            interface: None,
+            packets: AtomicU64::new(0),
+        }
+    }
+
+    /// Parse `ACTION [MATCH...]`
+    fn parse<'a>(mut words: impl Iterator<Item = &'a str>) -> Result<Self, &'static str> {
+        let mut rule = Self::new(FirewallAction::parse(words.next().ok_or("Missing firewall action")?)?);
+        while let Some(key) = words.next() {
+            let value = words.next().ok_or("Missing firewall match value")?;
+            match key {
+                "proto" => {
+                    rule.protocol = Some(match value {
+                        "tcp" => PROTOCOL_TCP,
+                        "udp" => PROTOCOL_UDP,
+                        "icmp" => PROTOCOL_ICMP,
+                        number => number.parse().map_err(|_| "Invalid protocol")?,
+                    })
+                }
+                "src" => rule.source = Some(Ipv4Cidr::parse(value).map_err(|_| "Invalid address")?),
+                "dst" => rule.destination = Some(Ipv4Cidr::parse(value).map_err(|_| "Invalid address")?),
+                "sport" => rule.source_port = Some(PortRange::parse(value)?),
+                "dport" => rule.destination_port = Some(PortRange::parse(value)?),
+                "iface" => rule.interface = Some(value.to_string()),
+                _ => return Err("Unknown firewall match"),
+            }
+        }
+        // Ports are only known for TCP and UDP
+        let has_ports = rule.source_port.is_some() || rule.destination_port.is_some();
+        if has_ports && !matches!(rule.protocol, Some(PROTOCOL_TCP | PROTOCOL_UDP)) {
+            return Err("Port match without proto tcp or udp");
+        }
+        Ok(rule)
+    }
+
+    pub fn matches(&self, packet: &PacketInfo) -> bool {
+        let port_matches = |range: Option<PortRange>, port: Option<u16>| match (range, port) {
+            (None, _) => true,
+            (Some(range), Some(port)) => range.contains(port),
+            (Some(_), None) => false,
+        };
+        self.protocol.is_none_or(|protocol| protocol == packet.protocol)
+            && self.source.is_none_or(|cidr| cidr.contains(packet.source))
+            && self.destination.is_none_or(|cidr| cidr.contains(packet.destination))
+            && port_matches(self.source_port, packet.ports.map(|ports| ports.0))
+            && port_matches(self.destination_port, packet.ports.map(|ports| ports.1))
+            && self.interface.as_ref().is_none_or(|name| name == packet.interface)
+    }
+
+    pub fn packets(&self) -> u64 {
+        self.packets.load(Ordering::Relaxed)
+    }
+}
+
+impl fmt::Display for FirewallRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.action.name())?;
+        match self.protocol {
+            Some(PROTOCOL_TCP) => f.write_str(" proto tcp")?,
+            Some(PROTOCOL_UDP) => f.write_str(" proto udp")?,
+            Some(PROTOCOL_ICMP) => f.write_str(" proto icmp")?,
+            Some(protocol) => write!(f, " proto {}", protocol)?,
+            None => {}
+        }
+        if let Some(source) = self.source {
+            write!(f, " src {}", source)?;
+        }
+        if let Some(destination) = self.destination {
+            write!(f, " dst {}", destination)?;
+        }
+        if let Some(port) = self.source_port {
+            write!(f, " sport {}", port)?;
+        }
+        if let Some(port) = self.destination_port {
+            write!(f, " dport {}", port)?;
+        }
+        if let Some(interface) = &self.interface {
+            write!(f, " iface {}", interface)?;
+        }
+        Ok(())
+    }
+}
+
+struct Chain {
+    /// `Accept` or `Drop`
+    policy: FirewallAction,
+    rules: Vec<FirewallRule>,
+}
+
+impl Chain {
+    const fn new() -> Self {
+        Self { policy: FirewallAction::Accept, rules: Vec::new() }
+    }
+}
+
+/// The chains of the three hooks
+pub struct Firewall {
+    chains: RwLock<[Chain; 3]>,
+}
+
+static FIREWALL: Firewall = Firewall::new();
+
+/// The packet filter of the system
+pub fn firewall() -> &'static Firewall {
+    &FIREWALL
+}
+
+impl Firewall {
+    pub const fn new() -> Self {
+        Self { chains: RwLock::new([Chain::new(), Chain::new(), Chain::new()]) }
+    }
+
+    fn index(hook: FirewallHook) -> usize {
+        match hook {
+            FirewallHook::Prerouting => 0,
+            FirewallHook::Input => 1,
+            FirewallHook::Output => 2,
+        }
+    }
+
+    /// Whether `packet` passes `hook`
+    pub fn accepts(&self, hook: FirewallHook, packet: &PacketInfo) -> bool {
+        let chains = self.chains.read();
+        let chain = &chains[Self::index(hook)];
+        for rule in chain.rules.iter().filter(|rule| rule.matches(packet)) {
+            rule.packets.fetch_add(1, Ordering::Relaxed);
+            match rule.action {
+                FirewallAction::Accept => return true,
+                FirewallAction::Drop => return false,
+                FirewallAction::Log => crate::early_println!(
+                    "[firewall] {} if={} proto={} src={} dst={}{}",
+                    hook.name(),
+                    packet.interface,
+                    packet.protocol,
+                    packet.source,
+                    packet.destination,
+                    PortsField(packet.ports)
+                ),
+            }
+        }
+        chain.policy == FirewallAction::Accept
+    }
+
+    /// Apply one command; see the module documentation
+    pub fn apply(&self, command: &str) -> Result<(), &'static str> {
+        let mut words = command.split_whitespace();
+        let verb = words.next().ok_or("Empty command")?;
+        let mut chains = self.chains.write();
+        if verb == "flush" {
+            match words.next() {
+                Some(name) => chains[Self::index(FirewallHook::parse(name)?)].rules.clear(),
+                None => chains.iter_mut().for_each(|chain| chain.rules.clear()),
+            }
+            return Ok(());
+        }
+        let chain = &mut chains[Self::index(FirewallHook::parse(words.next().ok_or("Missing firewall chain")?)?)];
+        let mut index = || -> Result<usize, &'static str> {
+            words.next().and_then(|index| index.parse().ok()).ok_or("Invalid rule index")
+        };
+        match verb {
+            "append" | "insert" => {
+                let position = if verb == "insert" { index()? } else { chain.rules.len() };
+                if position > chain.rules.len() {
+                    return Err("Invalid rule index");
+                }
+                if chain.rules.len() >= MAX_RULES {
+                    return Err("Too many firewall rules");
+                }
+                chain.rules.insert(position, FirewallRule::parse(words)?);
+            }
+            "delete" => {
+                let position = index()?;
+                if position >= chain.rules.len() {
+                    return Err("Invalid rule index");
+                }
+                chain.rules.remove(position);
+            }
+            "policy" => {
+                chain.policy = match FirewallAction::parse(words.next().ok_or("Missing firewall policy")?)? {
+                    FirewallAction::Log => return Err("A policy is accept or drop"),
+                    action => action,
+                };
+            }
+            _ => return Err("Unknown firewall command"),
+        }
+        Ok(())
+    }
+
+    /// The policies and rules of every chain, one per line
+    pub fn format(&self) -> String {
+        let chains = self.chains.read();
+        let mut out = String::new();
+        for hook in FirewallHook::ALL {
+            let chain = &chains[Self::index(hook)];
+            let _ = writeln!(out, "policy {} {}", hook.name(), chain.policy.name());
+            for (index, rule) in chain.rules.iter().enumerate() {
+                let _ = writeln!(out, "rule {} {} {} packets={}", hook.name(), index, rule, rule.packets());
+            }
+        }
+        out
+    }
+}
+
+/// Ports of a logged datagram, if it has any
+struct PortsField(Option<(u16, u16)>);
+
+impl fmt::Display for PortsField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some((source, destination)) => write!(f, " sport={} dport={}", source, destination),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Whether `packet` passes `hook` of the system filter
+pub fn accepts(hook: FirewallHook, packet: &PacketInfo) -> bool {
+    FIREWALL.accepts(hook, packet)
+}
+
+/// Render `/proc/firewall`
+pub fn format_rules() -> String {
+    FIREWALL.format()
+}
+
+/// Apply a command written to `/proc/firewall`
+pub fn control(command: &[u8]) -> Result<(), &'static str> {
+    if mytask().is_some_and(|task| !task.cred.is_privileged()) {
+        crate::audit::privilege_denied("firewall");
+        return Err("Permission denied");
+    }
+    let command = core::str::from_utf8(command).map_err(|_| "Invalid firewall command")?;
+    FIREWALL.apply(command.trim_end_matches('\n'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_firewall_rules() {
+        let firewall = Firewall::new();
+        let client = Ipv4Address::new(10, 0, 0, 2);
+        let host = Ipv4Address::new(10, 0, 0, 1);
+        let ssh = [0xC0, 0x00, 0, 22];
+        let web = [0xC0, 0x00, 0, 80];
+        let input = |transport: &[u8], interface| {
+            firewall.accepts(FirewallHook::Input, &PacketInfo::new(interface, client, host, PROTOCOL_TCP, Some(transport)))
+        };
+
+        // Everything passes until told otherwise
+        assert!(input(&ssh, "eth0"));
+        firewall.apply("append input accept proto tcp dport 22 src 10.0.0.0/24").unwrap();
+        firewall.apply("append input drop iface eth0").unwrap();
+        firewall.apply("insert input 0 log proto tcp dport 1:1023").unwrap();
+        assert!(input(&ssh, "eth0"));
+        assert!(!input(&web, "eth0"));
+        assert!(input(&web, "eth1"));
+        firewall.apply("policy input drop").unwrap();
+        assert!(!input(&web, "eth1"));
+        // Other chains are left alone
+        assert!(firewall.accepts(FirewallHook::Output, &PacketInfo::new("eth0", host, client, PROTOCOL_TCP, Some(&web))));
+
+        let listing = firewall.format();
+        assert!(listing.contains("policy input drop\n"));
+        assert!(listing.contains("rule input 0 log proto tcp dport 1:1023 packets=4\n"));
+        assert!(listing.contains("rule input 1 accept proto tcp src 10.0.0.0/24 dport 22 packets=1\n"));
+
+        assert_eq!(firewall.apply("append input accept dport 22"), Err("Port match without proto tcp or udp"));
+        assert_eq!(firewall.apply("policy input log"), Err("A policy is accept or drop"));
+        assert_eq!(firewall.apply("delete input 3"), Err("Invalid rule index"));
+        firewall.apply("delete input 2").unwrap();
+        firewall.apply("flush").unwrap();
+        assert!(!firewall.format().contains("rule"));
+    }
+}
@@ -0,0 +1,451 @@
+//! ICMP for IPv4
+//!
+//! The ICMP processor sits at the "ipv4" stage under protocol 1. It answers
+//! echo requests to the addresses of the host and hands echo replies to the
+//! [`EchoEndpoint`] whose identifier they carry, which is how the host
+//! pings others. Error messages are passed to the "icmp-error" stage keyed
+//! by the protocol of the datagram they are about, with the quoted datagram
+//! as payload, so transports learn about unreachable ports and the like.
+//!
+//! Errors are generated with [`send_error`]: protocol unreachable by the
+//! default processor of the "ipv4" stage for protocols no raw socket
+//! took, reassembly timeouts by IPv4 and
+//! port unreachable by the transports. As RFC 1122 asks, none is sent about
+//! an ICMP error, a broadcast or multicast datagram or a fragment other
+//! than the first.
+
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::{Mutex, RwLock};
+
+use crate::abi::error::KernelError;
+use crate::object::capability::poll::{PollOps, POLLIN};
+use crate::sync::waker::Waker;
+
+use super::address::Ipv4Address;
+use super::checksum::checksum;
+use super::ipv4::{self, Ipv4Header, SendOptions, IPV4_STAGE, PROTOCOL_ICMP};
+use super::manager::{NetworkInterface, NetworkManager};
+use super::packet::{MetadataValue, NetworkPacket};
+use super::pipeline::{PacketProcessor, ProcessResult};
+use super::raw::META_RAW_TAKEN;
+use super::stats::{self, Counter};
+
+pub const ICMP_HEADER_LEN: usize = 8;
+
+pub const ICMP_ECHO_REPLY: u8 = 0;
+pub const ICMP_DEST_UNREACHABLE: u8 = 3;
+pub const ICMP_ECHO_REQUEST: u8 = 8;
+pub const ICMP_TIME_EXCEEDED: u8 = 11;
+pub const ICMP_PARAMETER_PROBLEM: u8 = 12;
+
+/// Codes of `ICMP_DEST_UNREACHABLE`
+pub const UNREACH_NET: u8 = 0;
+pub const UNREACH_HOST: u8 = 1;
+pub const UNREACH_PROTOCOL: u8 = 2;
+pub const UNREACH_PORT: u8 = 3;
+pub const UNREACH_FRAGMENTATION_NEEDED: u8 = 4;
+
+/// Codes of `ICMP_TIME_EXCEEDED`
+pub const TIME_EXCEEDED_TTL: u8 = 0;
+pub const TIME_EXCEEDED_REASSEMBLY: u8 = 1;
+
+/// Stage of the handlers of ICMP errors, keyed by the protocol of the
+/// datagram the error is about
+pub const ICMP_ERROR_STAGE: &str = "icmp-error";
+
+/// Metadata set on ICMP errors passed to the "icmp-error" stage
+pub const META_ICMP_TYPE: &str = "icmp.type";
+pub const META_ICMP_CODE: &str = "icmp.code";
+
+/// Responses an echo endpoint keeps before dropping new ones
+pub const MAX_ECHO_RESPONSES: usize = 64;
+
+/// An ICMP message: the header with `rest` as its last four bytes, then
+/// `body`
+pub fn build_message(icmp_type: u8, code: u8, rest: [u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(ICMP_HEADER_LEN + body.len());
+    message.extend_from_slice(&[icmp_type, code, 0, 0]);
+    message.extend_from_slice(&rest);
+    message.extend_from_slice(body);
+    let sum = checksum(&message);
+    message[2..4].copy_from_slice(&sum.to_be_bytes());
+    message
+}
+
+fn is_error(icmp_type: u8) -> bool {
+    matches!(icmp_type, ICMP_DEST_UNREACHABLE | ICMP_TIME_EXCEEDED | ICMP_PARAMETER_PROBLEM)
+}
+
+/// Report a problem with the datagram `original` (its header and at least
+/// the start of its payload) received on `interface` to its sender
+///
+/// `info` is the last word of the ICMP header, such as the next-hop MTU of
+/// `UNREACH_FRAGMENTATION_NEEDED`. Datagrams no error may be sent about
+/// are ignored.
+pub fn send_error(
+    interface: &Arc<NetworkInterface>,
+    original: &[u8],
+    icmp_type: u8,
+    code: u8,
+    info: u32,
+) -> Result<(), KernelError> {
+    let header = Ipv4Header::parse(original).ok_or(KernelError::InvalidArgument)?;
+    let unicast = |address: Ipv4Address| !(address.is_unspecified() || address.is_broadcast() || address.is_multicast());
+    if header.fragment_offset != 0 || !unicast(header.source) || !unicast(header.destination) {
+        return Ok(());
+    }
+    if header.protocol == PROTOCOL_ICMP && original.get(header.header_len).copied().map_or(true, is_error) {
+        return Ok(());
+    }
+    let quoted = &original[..original.len().min(header.header_len + 8)];
+    let message = build_message(icmp_type, code, info.to_be_bytes(), quoted);
+    let options = SendOptions {
+        source: interface.has_ipv4_address(header.destination).then_some(header.destination.into()),
+        interface: Some(interface.clone()),
+        ..SendOptions::default()
+    };
+    send(header.source, &message, &options)
+}
+
+/// Send the ICMP `message` to `destination`, counting it
+fn send(destination: Ipv4Address, message: &[u8], options: &SendOptions) -> Result<(), KernelError> {
+    let result = ipv4::send(destination, PROTOCOL_ICMP, message, options);
+    stats::count_icmp_out(message[0], result.is_ok());
+    result
+}
+
+/// What came back for an echo request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EchoResponse {
+    Reply { source: Ipv4Address, sequence: u16, ttl: u8, data: Vec<u8> },
+    /// An ICMP error about the request, from the host that sent it
+    Error { source: Ipv4Address, sequence: u16, icmp_type: u8, code: u8 },
+}
+
+/// Sends echo requests under an identifier and receives what comes back
+pub struct EchoEndpoint {
+    identifier: u16,
+    responses: Mutex<VecDeque<EchoResponse>>,
+    waker: Waker,
+}
+
+static ECHO_ENDPOINTS: RwLock<BTreeMap<u16, Weak<EchoEndpoint>>> = RwLock::new(BTreeMap::new());
+static NEXT_ECHO_IDENTIFIER: AtomicU16 = AtomicU16::new(1);
+
+/// Open an endpoint under an identifier no other uses
+///
+/// Fails with `AddressInUse` if every identifier is taken.
+pub fn open_echo_endpoint() -> Result<Arc<EchoEndpoint>, KernelError> {
+    let mut endpoints = ECHO_ENDPOINTS.write();
+    for _ in 0..=u16::MAX as usize {
+        let identifier = NEXT_ECHO_IDENTIFIER.fetch_add(1, Ordering::Relaxed);
+        if endpoints.get(&identifier).is_some_and(|endpoint| endpoint.strong_count() > 0) {
+            continue;
+        }
+        let endpoint = Arc::new(EchoEndpoint {
+            identifier,
+            responses: Mutex::new(VecDeque::new()),
+            waker: Waker::new_interruptible("icmp_echo"),
+        });
+        endpoints.insert(identifier, Arc::downgrade(&endpoint));
+        return Ok(endpoint);
+    }
+    Err(KernelError::AddressInUse)
+}
+
+impl EchoEndpoint {
+    pub fn identifier(&self) -> u16 {
+        self.identifier
+    }
+
+    /// Send an echo request for `sequence` with `data` to `destination`
+    pub fn send_request(
+        &self,
+        destination: Ipv4Address,
+        sequence: u16,
+        data: &[u8],
+        options: &SendOptions,
+    ) -> Result<(), KernelError> {
+        let mut rest = [0; 4];
+        rest[..2].copy_from_slice(&self.identifier.to_be_bytes());
+        rest[2..].copy_from_slice(&sequence.to_be_bytes());
+        let message = build_message(ICMP_ECHO_REQUEST, 0, rest, data);
+        send(destination, &message, options)
+    }
+
+    /// The oldest response not taken yet
+    pub fn take_response(&self) -> Option<EchoResponse> {
+        self.responses.lock().pop_front()
+    }
+
+    fn deliver(&self, response: EchoResponse) -> bool {
+        let mut responses = self.responses.lock();
+        if responses.len() >= MAX_ECHO_RESPONSES {
+            return false;
+        }
+        responses.push_back(response);
+        drop(responses);
+        self.waker.wake_all();
+        true
+    }
+}
+
+impl PollOps for EchoEndpoint {
+    fn poll_events(&self) -> u32 {
+        if self.responses.lock().is_empty() { 0 } else { POLLIN }
+    }
+
+    fn poll_register(&self, task_id: usize) -> bool {
+        self.waker.register(task_id);
+        true
+    }
+
+    fn poll_unregister(&self, task_id: usize) {
+        self.waker.unregister(task_id);
+    }
+}
+
+impl Drop for EchoEndpoint {
+    fn drop(&mut self) {
+        let mut endpoints = ECHO_ENDPOINTS.write();
+        if endpoints.get(&self.identifier).is_some_and(|endpoint| endpoint.strong_count() == 0) {
+            endpoints.remove(&self.identifier);
+        }
+    }
+}
+
+fn deliver_echo(identifier: u16, response: EchoResponse) -> ProcessResult {
+    let endpoint = ECHO_ENDPOINTS.read().get(&identifier).and_then(Weak::upgrade);
+    match endpoint {
+        Some(endpoint) if endpoint.deliver(response) => ProcessResult::Consumed,
+        Some(_) => ProcessResult::Dropped("Echo endpoint full"),
+        None => ProcessResult::Dropped("No echo endpoint"),
+    }
+}
+
+/// Processor of ICMP at the "ipv4" stage
+pub struct IcmpProcessor;
+
+impl IcmpProcessor {
+    fn answer_echo(&self, packet: &NetworkPacket, rest: [u8; 4]) -> ProcessResult {
+        let (Some(source), Some(destination), Some(interface)) =
+            (ipv4::source(packet), ipv4::destination(packet), packet.interface())
+        else {
+            return ProcessResult::Dropped("Echo request without addresses");
+        };
+        // Requests to broadcast and multicast addresses are ignored
+        if !interface.has_ipv4_address(destination) {
+            return ProcessResult::Consumed;
+        }
+        let reply = build_message(ICMP_ECHO_REPLY, 0, rest, packet.payload());
+        let options = SendOptions {
+            source: Some(destination.into()),
+            interface: Some(interface.clone()),
+            ..SendOptions::default()
+        };
+        match send(source, &reply, &options) {
+            Ok(()) => ProcessResult::Consumed,
+            Err(_) => ProcessResult::Dropped("Cannot send echo reply"),
+        }
+    }
+}
+
+impl PacketProcessor for IcmpProcessor {
+    fn name(&self) -> &'static str {
+        "icmp"
+    }
+
+    fn process(&self, packet: &mut NetworkPacket) -> ProcessResult {
+        if packet.payload().len() < ICMP_HEADER_LEN {
+            stats::count(Counter::IcmpInErrors);
+            return ProcessResult::Dropped("Truncated ICMP message");
+        }
+        if checksum(packet.payload()) != 0 {
+            stats::count(Counter::IcmpInErrors);
+            stats::count(Counter::IcmpInCsumErrors);
+            return ProcessResult::Dropped("Bad ICMP checksum");
+        }
+        let Ok(header) = packet.consume_header("icmp", ICMP_HEADER_LEN) else {
+            return ProcessResult::Dropped("Truncated ICMP message");
+        };
+        let (icmp_type, code) = (header[0], header[1]);
+        stats::count_icmp_in(icmp_type);
+        let rest = [header[4], header[5], header[6], header[7]];
+        let identifier = u16::from_be_bytes([rest[0], rest[1]]);
+        let sequence = u16::from_be_bytes([rest[2], rest[3]]);
+        match icmp_type {
+            ICMP_ECHO_REQUEST => self.answer_echo(packet, rest),
+            ICMP_ECHO_REPLY => {
+                let response = EchoResponse::Reply {
+                    source: ipv4::source(packet).unwrap_or(Ipv4Address::UNSPECIFIED),
+                    sequence,
+                    ttl: packet.metadata_int(ipv4::META_TTL).unwrap_or(0) as u8,
+                    data: packet.payload().to_vec(),
+                };
+                deliver_echo(identifier, response)
+            }
+            icmp_type if is_error(icmp_type) => {
+                let Some(original) = Ipv4Header::parse(packet.payload()) else {
+                    return ProcessResult::Dropped("Malformed ICMP error");
+                };
+                packet.set_metadata(META_ICMP_TYPE, MetadataValue::Int(icmp_type as u64));
+                packet.set_metadata(META_ICMP_CODE, MetadataValue::Int(code as u64));
+                ProcessResult::Forward { stage: ICMP_ERROR_STAGE, key: original.protocol as u64 }
+            }
+            _ => ProcessResult::Dropped("Unsupported ICMP message"),
+        }
+    }
+}
+
+/// Handles ICMP errors about echo requests, at the "icmp-error" stage
+struct EchoErrorProcessor;
+
+impl PacketProcessor for EchoErrorProcessor {
+    fn name(&self) -> &'static str {
+        "icmp-echo-error"
+    }
+
+    fn process(&self, packet: &mut NetworkPacket) -> ProcessResult {
+        let quoted = packet.payload();
+        let Some(original) = Ipv4Header::parse(quoted) else {
+            return ProcessResult::Dropped("Malformed ICMP error");
+        };
+        let Some(echo) = quoted.get(original.header_len..original.header_len + ICMP_HEADER_LEN) else {
+            return ProcessResult::Dropped("Malformed ICMP error");
+        };
+        if echo[0] != ICMP_ECHO_REQUEST {
+            return ProcessResult::Dropped("ICMP error about ICMP");
+        }
+        let response = EchoResponse::Error {
+            source: ipv4::source(packet).unwrap_or(Ipv4Address::UNSPECIFIED),
+            sequence: u16::from_be_bytes([echo[6], echo[7]]),
+            icmp_type: packet.metadata_int(META_ICMP_TYPE).unwrap_or(0) as u8,
+            code: packet.metadata_int(META_ICMP_CODE).unwrap_or(0) as u8,
+        };
+        deliver_echo(u16::from_be_bytes([echo[4], echo[5]]), response)
+    }
+}
+
+/// Default processor of the "ipv4" stage: reports protocols nobody handles
+struct ProtocolUnreachable;
+
+impl PacketProcessor for ProtocolUnreachable {
+    fn name(&self) -> &'static str {
+        "protocol-unreachable"
+    }
+
+    fn process(&self, packet: &mut NetworkPacket) -> ProcessResult {
+        if packet.metadata_int(META_RAW_TAKEN) == Some(1) {
+            return ProcessResult::Consumed;
+        }
+        if let (Some(interface), Some(header)) = (packet.interface(), packet.header("ipv4")) {
+            let mut original = header.to_vec();
+            original.extend_from_slice(&packet.payload()[..packet.payload().len().min(8)]);
+            let _ = send_error(interface, &original, ICMP_DEST_UNREACHABLE, UNREACH_PROTOCOL, 0);
+        }
+        stats::count(Counter::IpInUnknownProtos);
+        ProcessResult::Dropped("Protocol unreachable")
+    }
+}
+
+/// Register ICMP with the pipeline of `manager`
+pub fn register(manager: &NetworkManager) -> Result<(), KernelError> {
+    let pipeline = manager.pipeline();
+    pipeline.add_stage(ICMP_ERROR_STAGE)?;
+    pipeline.register_processor(IPV4_STAGE, PROTOCOL_ICMP as u64, Arc::new(IcmpProcessor))?;
+    pipeline.register_processor(ICMP_ERROR_STAGE, PROTOCOL_ICMP as u64, Arc::new(EchoErrorProcessor))?;
+    pipeline.set_default_processor(IPV4_STAGE, Some(Arc::new(ProtocolUnreachable)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::network::{GenericNetworkDevice, MacAddress, NetworkDevice};
+    use crate::network::address::Ipv4Cidr;
+    use crate::network::arp::{ArpPacket, ARP_OP_REPLY};
+    use crate::network::ethernet::{self, EthernetHeader, ETHERNET_HEADER_LEN, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+    use crate::network::ipv4::IPV4_HEADER_LEN;
+    use crate::network::pipeline::PacketFate;
+
+    const REMOTE_MAC: MacAddress = MacAddress::new([2, 0, 0, 0, 0, 9]);
+    const REMOTE_IP: Ipv4Address = Ipv4Address::new(192, 0, 2, 9);
+    const LOCAL_IP: Ipv4Address = Ipv4Address::new(192, 0, 2, 15);
+
+    static MANAGER: NetworkManager = NetworkManager::new();
+
+    fn datagram_frame(local: MacAddress, icmp: &[u8]) -> Vec<u8> {
+        let mut datagram = Ipv4Header::new(REMOTE_IP, LOCAL_IP, PROTOCOL_ICMP, icmp.len()).to_bytes().to_vec();
+        datagram.extend_from_slice(icmp);
+        let header = EthernetHeader { destination: local, source: REMOTE_MAC, ethertype: ETHERTYPE_IPV4 };
+        ethernet::build_frame(&header, &datagram)
+    }
+
+    #[test_case]
+    fn test_icmp_echo() {
+        ethernet::register(&MANAGER).unwrap();
+        crate::network::arp::register(&MANAGER).unwrap();
+        ipv4::register(&MANAGER).unwrap();
+        register(&MANAGER).unwrap();
+        let mut device = GenericNetworkDevice::new("test0");
+        device.init_network().unwrap();
+        let device = Arc::new(device);
+        let local_mac = device.get_mac_address().unwrap();
+        let interface = MANAGER.attach_device("test0", device.clone(), None, ethernet::ETHERNET_STAGE).unwrap();
+        interface.add_ipv4_address(Ipv4Cidr::new(LOCAL_IP, 24).unwrap()).unwrap();
+
+        // The reply waits for the address of the sender to be resolved
+        let request = build_message(ICMP_ECHO_REQUEST, 0, [0, 7, 0, 1], b"ping");
+        assert_eq!(MANAGER.receive(&interface, datagram_frame(local_mac, &request)), PacketFate::Consumed);
+        let sent = device.take_transmitted_packets();
+        assert_eq!(EthernetHeader::parse(sent[0].as_slice()).unwrap().ethertype, ETHERTYPE_ARP);
+        let arp_reply = ArpPacket {
+            operation: ARP_OP_REPLY,
+            sender_mac: REMOTE_MAC,
+            sender_ip: REMOTE_IP,
+            target_mac: local_mac,
+            target_ip: LOCAL_IP,
+        };
+        let header = EthernetHeader { destination: local_mac, source: REMOTE_MAC, ethertype: ETHERTYPE_ARP };
+        MANAGER.receive(&interface, ethernet::build_frame(&header, &arp_reply.to_bytes()));
+
+        let sent = device.take_transmitted_packets();
+        assert_eq!(sent.len(), 1);
+        let datagram = &sent[0].as_slice()[ETHERNET_HEADER_LEN..];
+        let reply = Ipv4Header::parse(datagram).unwrap();
+        assert_eq!((reply.source, reply.destination, reply.protocol), (LOCAL_IP, REMOTE_IP, PROTOCOL_ICMP));
+        let message = &datagram[reply.header_len..reply.total_len];
+        assert_eq!(checksum(message), 0);
+        assert_eq!((message[0], &message[4..8], &message[8..]), (ICMP_ECHO_REPLY, &[0u8, 7, 0, 1][..], &b"ping"[..]));
+
+        // Replies reach the endpoint of their identifier
+        let endpoint = open_echo_endpoint().unwrap();
+        let [high, low] = endpoint.identifier().to_be_bytes();
+        let reply = build_message(ICMP_ECHO_REPLY, 0, [high, low, 0, 3], b"pong");
+        assert_eq!(MANAGER.receive(&interface, datagram_frame(local_mac, &reply)), PacketFate::Consumed);
+        assert_eq!(endpoint.poll_events(), POLLIN);
+        assert_eq!(
+            endpoint.take_response(),
+            Some(EchoResponse::Reply { source: REMOTE_IP, sequence: 3, ttl: ipv4::DEFAULT_TTL, data: b"pong".to_vec() })
+        );
+        let identifier = endpoint.identifier();
+        drop(endpoint);
+        assert!(!ECHO_ENDPOINTS.read().contains_key(&identifier));
+
+        // An unknown protocol is reported to the sender
+        let mut datagram = Ipv4Header::new(REMOTE_IP, LOCAL_IP, 253, 4).to_bytes().to_vec();
+        datagram.extend_from_slice(&[1, 2, 3, 4]);
+        let header = EthernetHeader { destination: local_mac, source: REMOTE_MAC, ethertype: ETHERTYPE_IPV4 };
+        assert_eq!(
+            MANAGER.receive(&interface, ethernet::build_frame(&header, &datagram)),
+            PacketFate::Dropped("Protocol unreachable")
+        );
+        let sent = device.take_transmitted_packets();
+        let error = &sent[0].as_slice()[ETHERNET_HEADER_LEN + IPV4_HEADER_LEN..];
+        assert_eq!((error[0], error[1]), (ICMP_DEST_UNREACHABLE, UNREACH_PROTOCOL));
+        assert_eq!(&error[ICMP_HEADER_LEN..ICMP_HEADER_LEN + IPV4_HEADER_LEN + 4], &datagram[..]);
+    }
+}
@@ -0,0 +1,251 @@
+//! ICMP for IPv6 (RFC 4443)
+//!
+//! The ICMPv6 processor sits at the "ipv6" stage under next header 58. It
+//! answers echo requests to the addresses of the host and hands neighbor
+//! discovery messages to [`ndp`]. Error messages are passed to the
+//! "icmp-error" stage of ICMP keyed by the protocol of the datagram they
+//! are about, with their type and code translated into the ICMP ones that
+//! mean the same, so transports handle the errors of both versions alike.
+//! Echo endpoints are IPv4 only; echo replies are dropped.
+//!
+//! Errors are generated with [`send_error`]: unrecognized next headers by
+//! the default processor of the "ipv6" stage, unless a raw socket took
+//! the datagram, and port unreachable by the
+//! transports. As RFC 4443 asks, none is sent about an ICMPv6 error, a
+//! datagram to a multicast address or one from an address that does not
+//! name a single node.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::abi::error::KernelError;
+
+use super::address::Ipv6Address;
+use super::checksum::Checksum;
+use super::icmp::{self, ICMP_ERROR_STAGE, META_ICMP_CODE, META_ICMP_TYPE};
+use super::ip::SendOptions;
+use super::ipv6::{self, Ipv6Header, IPV6_HEADER_LEN, IPV6_MIN_MTU, IPV6_STAGE, NEXT_HEADER_ICMPV6};
+use super::manager::{NetworkInterface, NetworkManager};
+use super::ndp;
+use super::packet::{MetadataValue, NetworkPacket, META_CHECKSUM_VERIFIED};
+use super::pipeline::{PacketProcessor, ProcessResult};
+use super::raw::META_RAW_TAKEN;
+
+pub const ICMPV6_HEADER_LEN: usize = 8;
+
+/// Error messages
+pub const ICMPV6_DEST_UNREACHABLE: u8 = 1;
+pub const ICMPV6_PACKET_TOO_BIG: u8 = 2;
+pub const ICMPV6_TIME_EXCEEDED: u8 = 3;
+pub const ICMPV6_PARAMETER_PROBLEM: u8 = 4;
+
+/// Informational messages
+pub const ICMPV6_ECHO_REQUEST: u8 = 128;
+pub const ICMPV6_ECHO_REPLY: u8 = 129;
+
+/// Codes of `ICMPV6_DEST_UNREACHABLE`
+pub const UNREACH6_NO_ROUTE: u8 = 0;
+pub const UNREACH6_PROHIBITED: u8 = 1;
+pub const UNREACH6_ADDRESS: u8 = 3;
+pub const UNREACH6_PORT: u8 = 4;
+
+/// Codes of `ICMPV6_PARAMETER_PROBLEM`
+pub const PARAMETER_ERRONEOUS_FIELD: u8 = 0;
+pub const PARAMETER_UNRECOGNIZED_NEXT_HEADER: u8 = 1;
+
+/// Error messages have the types below 128
+fn is_error(icmp_type: u8) -> bool {
+    icmp_type < 128
+}
+
+/// An ICMPv6 message from `source` to `destination`: the header with `rest`
+/// as its last four bytes, then `body`
+pub fn build_message(
+    source: Ipv6Address,
+    destination: Ipv6Address,
+    icmp_type: u8,
+    code: u8,
+    rest: [u8; 4],
+    body: &[u8],
+) -> Vec<u8> {
+    let mut message = Vec::with_capacity(ICMPV6_HEADER_LEN + body.len());
+    message.extend_from_slice(&[icmp_type, code, 0, 0]);
+    message.extend_from_slice(&rest);
+    message.extend_from_slice(body);
+    let mut sum = Checksum::new();
+    sum.add_ipv6_pseudo_header(source, destination, NEXT_HEADER_ICMPV6, message.len());
+    sum.add_bytes(&message);
+    message[2..4].copy_from_slice(&sum.finish().to_be_bytes());
+    message
+}
+
+/// Send a message to `destination` with `options`, from the address they
+/// name or the one IPv6 chooses
+pub fn send_message(
+    destination: Ipv6Address,
+    icmp_type: u8,
+    code: u8,
+    rest: [u8; 4],
+    body: &[u8],
+    options: &SendOptions,
+) -> Result<(), KernelError> {
+    let source = ipv6::source_for(destination, options)?;
+    let message = build_message(source, destination, icmp_type, code, rest, body);
+    let options = SendOptions { source: Some(source.into()), ..options.clone() };
+    ipv6::send(destination, NEXT_HEADER_ICMPV6, &message, &options)
+}
+
+/// Report a problem with the datagram `original` (its header and as much
+/// of its payload as there is) received on `interface` to its sender
+///
+/// `info` is the last word of the ICMPv6 header, such as the MTU of
+/// `ICMPV6_PACKET_TOO_BIG` or the pointer of `ICMPV6_PARAMETER_PROBLEM`.
+/// Datagrams no error may be sent about are ignored.
+pub fn send_error(
+    interface: &Arc<NetworkInterface>,
+    original: &[u8],
+    icmp_type: u8,
+    code: u8,
+    info: u32,
+) -> Result<(), KernelError> {
+    let header = Ipv6Header::parse(original).ok_or(KernelError::InvalidArgument)?;
+    if header.source.is_unspecified() || header.source.is_multicast() || header.destination.is_multicast() {
+        return Ok(());
+    }
+    let upper = ipv6::upper_layer(&original[IPV6_HEADER_LEN..], header.next_header);
+    if let Ok((NEXT_HEADER_ICMPV6, offset)) = upper {
+        if original.get(IPV6_HEADER_LEN + offset).copied().map_or(true, is_error) {
+            return Ok(());
+        }
+    }
+    // The error fits in the smallest MTU
+    let quoted = &original[..original.len().min(IPV6_MIN_MTU - IPV6_HEADER_LEN - ICMPV6_HEADER_LEN)];
+    let options = SendOptions {
+        source: interface.has_ipv6_address(header.destination).then_some(header.destination.into()),
+        interface: Some(interface.clone()),
+        ..SendOptions::default()
+    };
+    send_message(header.source, icmp_type, code, info.to_be_bytes(), quoted, &options)
+}
+
+/// The ICMP type and code that mean what an ICMPv6 error does
+fn as_icmp(icmp_type: u8, code: u8) -> (u8, u8) {
+    match (icmp_type, code) {
+        (ICMPV6_DEST_UNREACHABLE, UNREACH6_PORT) => (icmp::ICMP_DEST_UNREACHABLE, icmp::UNREACH_PORT),
+        (ICMPV6_DEST_UNREACHABLE, UNREACH6_NO_ROUTE) => (icmp::ICMP_DEST_UNREACHABLE, icmp::UNREACH_NET),
+        (ICMPV6_DEST_UNREACHABLE, _) => (icmp::ICMP_DEST_UNREACHABLE, icmp::UNREACH_HOST),
+        (ICMPV6_PACKET_TOO_BIG, _) => (icmp::ICMP_DEST_UNREACHABLE, icmp::UNREACH_FRAGMENTATION_NEEDED),
+        (ICMPV6_TIME_EXCEEDED, code) => (icmp::ICMP_TIME_EXCEEDED, code),
+        (ICMPV6_PARAMETER_PROBLEM, PARAMETER_UNRECOGNIZED_NEXT_HEADER) => {
+            (icmp::ICMP_DEST_UNREACHABLE, icmp::UNREACH_PROTOCOL)
+        }
+        _ => (icmp::ICMP_PARAMETER_PROBLEM, 0),
+    }
+}
+
+/// Processor of ICMPv6 at the "ipv6" stage
+pub struct Icmpv6Processor;
+
+impl Icmpv6Processor {
+    fn answer_echo(&self, packet: &NetworkPacket, destination: Ipv6Address, source: Ipv6Address, rest: [u8; 4]) -> ProcessResult {
+        let Some(interface) = packet.interface() else {
+            return ProcessResult::Dropped("No interface");
+        };
+        // Requests to multicast addresses are ignored, as those of IPv4
+        if !interface.has_ipv6_address(destination) {
+            return ProcessResult::Consumed;
+        }
+        let options = SendOptions {
+            source: Some(destination.into()),
+            interface: Some(interface.clone()),
+            ..SendOptions::default()
+        };
+        match send_message(source, ICMPV6_ECHO_REPLY, 0, rest, packet.payload(), &options) {
+            Ok(()) => ProcessResult::Consumed,
+            Err(_) => ProcessResult::Dropped("Cannot send echo reply"),
+        }
+    }
+}
+
+impl PacketProcessor for Icmpv6Processor {
+    fn name(&self) -> &'static str {
+        "icmpv6"
+    }
+
+    fn process(&self, packet: &mut NetworkPacket) -> ProcessResult {
+        let (Some(source), Some(destination)) = (ipv6::source(packet), ipv6::destination(packet)) else {
+            return ProcessResult::Dropped("ICMPv6 message without addresses");
+        };
+        if packet.payload().len() < ICMPV6_HEADER_LEN {
+            return ProcessResult::Dropped("Truncated ICMPv6 message");
+        }
+        if packet.metadata_int(META_CHECKSUM_VERIFIED) != Some(1) {
+            let mut sum = Checksum::new();
+            sum.add_ipv6_pseudo_header(source, destination, NEXT_HEADER_ICMPV6, packet.payload().len());
+            sum.add_bytes(packet.payload());
+            if sum.finish() != 0 {
+                return ProcessResult::Dropped("Bad ICMPv6 checksum");
+            }
+        }
+        let Ok(header) = packet.consume_header("icmpv6", ICMPV6_HEADER_LEN) else {
+            return ProcessResult::Dropped("Truncated ICMPv6 message");
+        };
+        let (icmp_type, code) = (header[0], header[1]);
+        let rest = [header[4], header[5], header[6], header[7]];
+        match icmp_type {
+            ICMPV6_ECHO_REQUEST => self.answer_echo(packet, destination, source, rest),
+            ICMPV6_ECHO_REPLY => ProcessResult::Dropped("No echo endpoint"),
+            ndp::NDP_ROUTER_SOLICITATION..=ndp::NDP_REDIRECT => ndp::handle(packet, icmp_type, code, rest),
+            icmp_type if is_error(icmp_type) => {
+                let Some(original) = Ipv6Header::parse(packet.payload()) else {
+                    return ProcessResult::Dropped("Malformed ICMPv6 error");
+                };
+                let Ok((protocol, _)) = ipv6::upper_layer(&packet.payload()[IPV6_HEADER_LEN..], original.next_header) else {
+                    return ProcessResult::Dropped("Malformed ICMPv6 error");
+                };
+                let (icmp_type, code) = as_icmp(icmp_type, code);
+                packet.set_metadata(META_ICMP_TYPE, MetadataValue::Int(icmp_type as u64));
+                packet.set_metadata(META_ICMP_CODE, MetadataValue::Int(code as u64));
+                ProcessResult::Forward { stage: ICMP_ERROR_STAGE, key: protocol as u64 }
+            }
+            _ => ProcessResult::Dropped("Unsupported ICMPv6 message"),
+        }
+    }
+}
+
+/// Default processor of the "ipv6" stage: reports next headers nobody
+/// handles
+struct NextHeaderUnrecognized;
+
+impl PacketProcessor for NextHeaderUnrecognized {
+    fn name(&self) -> &'static str {
+        "next-header-unrecognized"
+    }
+
+    fn process(&self, packet: &mut NetworkPacket) -> ProcessResult {
+        if packet.metadata_int(META_RAW_TAKEN) == Some(1) {
+            return ProcessResult::Consumed;
+        }
+        if let (Some(interface), Some(header)) = (packet.interface(), packet.header("ipv6")) {
+            // The pointer is to the field naming the unrecognized header,
+            // in the fixed header or the last extension header
+            let mut field = 6;
+            let mut offset = IPV6_HEADER_LEN;
+            while offset + 2 <= header.len() {
+                field = offset;
+                offset += (header[offset + 1] as usize + 1) * 8;
+            }
+            let mut original = header.to_vec();
+            original.extend_from_slice(packet.payload());
+            let _ = send_error(interface, &original, ICMPV6_PARAMETER_PROBLEM, PARAMETER_UNRECOGNIZED_NEXT_HEADER, field as u32);
+        }
+        ProcessResult::Dropped("Next header unrecognized")
+    }
+}
+
+/// Register ICMPv6 with the pipeline of `manager`
+pub fn register(manager: &NetworkManager) -> Result<(), KernelError> {
+    let pipeline = manager.pipeline();
+    pipeline.register_processor(IPV6_STAGE, NEXT_HEADER_ICMPV6 as u64, Arc::new(Icmpv6Processor))?;
+    pipeline.set_default_processor(IPV6_STAGE, Some(Arc::new(NextHeaderUnrecognized)))
+}
@@ -0,0 +1,176 @@
+//! The IP layer of the transports
+//!
+//! UDP and TCP are dual-stack: their endpoints are [`IpEndpoint`]s, their
+//! processors sit at both the "ipv4" and the "ipv6" stage, and they send
+//! through this module, which hands each datagram to IPv4 or IPv6 by the
+//! version of its destination. Both versions take the same
+//! [`SendOptions`].
+//!
+//! [`IpEndpoint`]: super::address::IpEndpoint
+
+use alloc::sync::Arc;
+
+use crate::abi::error::KernelError;
+
+use super::address::{IpAddress, IpEndpoint, IpFamily};
+use super::checksum::checksum;
+use super::icmp;
+use super::icmpv6;
+use super::ipv4::{self, Ipv4Header, PROTOCOL_UDP};
+use super::ipv6::{self, Ipv6Header};
+use super::manager::NetworkInterface;
+use super::namespace::{NetNamespaceId, INIT_NET_NS};
+use super::packet::NetworkPacket;
+
+/// How to send a datagram
+#[derive(Clone)]
+pub struct SendOptions {
+    /// Address to send from; chosen from the outgoing interface if `None`
+    pub source: Option<IpAddress>,
+    /// Interface to send out of, whatever the routes say
+    pub interface: Option<Arc<NetworkInterface>>,
+    /// Network namespace whose interfaces and routes are used without
+    /// `interface`
+    pub namespace: NetNamespaceId,
+    /// Time to live, the hop limit of IPv6
+    pub ttl: u8,
+    /// Type of service, the traffic class of IPv6
+    pub tos: u8,
+    /// IPv4 only: IPv6 datagrams are never fragmented on the way
+    pub dont_fragment: bool,
+    /// Offset in the payload of a checksum that covers the payload from its
+    /// start and holds only the sum of the pseudo-header yet
+    pub partial_checksum: Option<usize>,
+    /// Payload bytes per segment of a TCP payload too long for the MTU,
+    /// for the device to cut it up
+    pub segment_size: Option<usize>,
+}
+
+impl Default for SendOptions {
+    fn default() -> Self {
+        Self {
+            source: None,
+            interface: None,
+            namespace: INIT_NET_NS,
+            ttl: ipv4::DEFAULT_TTL,
+            tos: 0,
+            dont_fragment: false,
+            partial_checksum: None,
+            segment_size: None,
+        }
+    }
+}
+
+/// Complete the partial checksum at `offset` of the payload of `protocol`
+pub(super) fn complete_checksum(payload: &mut [u8], offset: usize, protocol: u8) -> Result<(), KernelError> {
+    if offset + 2 > payload.len() {
+        return Err(KernelError::InvalidArgument);
+    }
+    let sum = match checksum(payload) {
+        // A computed 0 is sent as all ones; 0 means no UDP checksum
+        0 if protocol == PROTOCOL_UDP => 0xFFFF,
+        sum => sum,
+    };
+    payload[offset..offset + 2].copy_from_slice(&sum.to_be_bytes());
+    Ok(())
+}
+
+/// The endpoint `name` is to a socket of `family`: IPv4-mapped addresses
+/// are the IPv4 addresses they map, and an IPv4 socket takes no IPv6 one
+pub fn socket_endpoint(family: IpFamily, name: &str) -> Result<IpEndpoint, KernelError> {
+    let endpoint = IpEndpoint::parse(name)?.canonical();
+    if family == IpFamily::V4 && endpoint.address.family() != IpFamily::V4 {
+        return Err(KernelError::InvalidArgument);
+    }
+    Ok(endpoint)
+}
+
+/// The source address of a received datagram of either version
+pub fn source(packet: &NetworkPacket) -> Option<IpAddress> {
+    ipv4::source(packet).map(IpAddress::V4).or_else(|| ipv6::source(packet).map(IpAddress::V6))
+}
+
+/// The destination address of a received datagram of either version
+pub fn destination(packet: &NetworkPacket) -> Option<IpAddress> {
+    ipv4::destination(packet).map(IpAddress::V4).or_else(|| ipv6::destination(packet).map(IpAddress::V6))
+}
+
+/// The address a datagram to `destination` sent with `options` comes from
+pub fn source_for(destination: IpAddress, options: &SendOptions) -> Result<IpAddress, KernelError> {
+    match destination {
+        IpAddress::V4(destination) => ipv4::source_for(destination, options).map(IpAddress::V4),
+        IpAddress::V6(destination) => ipv6::source_for(destination, options).map(IpAddress::V6),
+    }
+}
+
+/// The interface a datagram to `destination` sent with `options` goes out
+/// of; `None` if it is for the host itself
+pub fn interface_for(destination: IpAddress, options: &SendOptions) -> Result<Option<Arc<NetworkInterface>>, KernelError> {
+    match destination {
+        IpAddress::V4(destination) => ipv4::interface_for(destination, options),
+        IpAddress::V6(destination) => ipv6::interface_for(destination, options),
+    }
+}
+
+/// Send `payload` to `destination` as a datagram of `protocol`, a protocol
+/// number of IPv4 or a next header of IPv6
+pub fn send(destination: IpAddress, protocol: u8, payload: &[u8], options: &SendOptions) -> Result<(), KernelError> {
+    match destination {
+        IpAddress::V4(destination) => ipv4::send(destination, protocol, payload, options),
+        IpAddress::V6(destination) => ipv6::send(destination, protocol, payload, options),
+    }
+}
+
+/// The header of the datagram a received packet came in, for the errors
+/// about it
+pub fn header(packet: &NetworkPacket) -> Option<&[u8]> {
+    packet.header("ipv4").or_else(|| packet.header("ipv6"))
+}
+
+/// Tell the sender of the datagram `original` (its header and the start of
+/// its payload) received on `interface` that nothing takes its port
+pub fn send_port_unreachable(interface: &Arc<NetworkInterface>, original: &[u8]) -> Result<(), KernelError> {
+    match original.first().map(|byte| byte >> 4) {
+        Some(4) => icmp::send_error(interface, original, icmp::ICMP_DEST_UNREACHABLE, icmp::UNREACH_PORT, 0),
+        Some(6) => icmpv6::send_error(interface, original, icmpv6::ICMPV6_DEST_UNREACHABLE, icmpv6::UNREACH6_PORT, 0),
+        _ => Err(KernelError::InvalidArgument),
+    }
+}
+
+/// What a datagram of either version carries: one quoted by an ICMP or
+/// ICMPv6 error, or one captured
+pub struct Datagram<'a> {
+    pub source: IpAddress,
+    pub destination: IpAddress,
+    pub protocol: u8,
+    /// The start of its transport header; empty in IPv4 fragments other
+    /// than the first
+    pub transport: &'a [u8],
+}
+
+/// The datagram of either version in `bytes`, whose payload may be cut
+/// short
+pub fn parse_datagram(bytes: &[u8]) -> Option<Datagram<'_>> {
+    match bytes.first().map(|byte| byte >> 4) {
+        Some(4) => {
+            let header = Ipv4Header::parse(bytes)?;
+            Some(Datagram {
+                source: header.source.into(),
+                destination: header.destination.into(),
+                protocol: header.protocol,
+                transport: if header.fragment_offset == 0 { bytes.get(header.header_len..)? } else { &[] },
+            })
+        }
+        Some(6) => {
+            let header = Ipv6Header::parse(bytes)?;
+            let (protocol, offset) = ipv6::upper_layer(&bytes[ipv6::IPV6_HEADER_LEN..], header.next_header).ok()?;
+            Some(Datagram {
+                source: header.source.into(),
+                destination: header.destination.into(),
+                protocol,
+                transport: bytes.get(ipv6::IPV6_HEADER_LEN + offset..)?,
+            })
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,656 @@
+//! IPv4
+//!
+//! The IPv4 processor sits at the "ethertype" stage under 0x0800. It checks
+//! the header of a received datagram, drops those not addressed to the
+//! host (there is no forwarding), reassembles fragments and passes the
+//! datagram to the "ipv4" stage keyed by its protocol number, with its
+//! addresses in the metadata of the packet, once the [`raw`] sockets have
+//! had their copy.
+//!
+//! [`send`] routes a datagram, fragments it to the MTU of the interface
+//! and hands the fragments to ARP for the next hop. Datagrams for an
+//! address of the host are delivered through the pipeline without
+//! touching a device.
+//!
+//! A transport may leave its checksum partial and its segments uncut;
+//! `send` leaves them to the device when it offloads them, and otherwise
+//! completes the checksum itself.
+//!
+//! Datagrams pass the [`firewall`] hooks: received ones `prerouting` before
+//! the address check and `input` once reassembled, sent ones `output` once
+//! routed.
+
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::abi::error::KernelError;
+use crate::timer::ms_to_ticks;
+
+use super::address::{IpAddress, Ipv4Address, Ipv4Cidr};
+use super::arp;
+use super::buffer::{BufferSlice, PacketBuffer, DEFAULT_HEADROOM};
+use super::checksum::checksum;
+use super::ethernet::{ETHERTYPE_IPV4, ETHERTYPE_STAGE};
+use super::firewall::{self, FirewallHook, PacketInfo};
+use super::icmp;
+use super::ip;
+use super::manager::{get_network_manager, NetworkInterface, NetworkManager};
+use super::namespace::NetNamespaceId;
+use super::packet::{
+    MetadataValue, NetworkPacket, META_CHECKSUM_OFFSET, META_CHECKSUM_START, META_CHECKSUM_VERIFIED,
+    META_SEGMENT_HEADER_LEN, META_SEGMENT_SIZE,
+};
+use super::pipeline::{PacketProcessor, ProcessResult};
+use super::raw;
+use super::route::{routing_table, Route};
+use super::stats::{self, Counter};
+
+pub use super::ip::SendOptions;
+
+pub const IPV4_HEADER_LEN: usize = 20;
+/// Longest datagram, header included
+pub const IPV4_MAX_DATAGRAM_LEN: usize = 65535;
+
+/// Stage of the protocols above IPv4, keyed by protocol number
+pub const IPV4_STAGE: &str = "ipv4";
+
+pub const PROTOCOL_ICMP: u8 = 1;
+pub const PROTOCOL_TCP: u8 = 6;
+pub const PROTOCOL_UDP: u8 = 17;
+
+pub const DEFAULT_TTL: u8 = 64;
+
+/// How long the fragments of a datagram wait for the others
+pub const REASSEMBLY_TIMEOUT_MS: u64 = 30_000;
+/// Datagrams reassembled at the same time
+pub const MAX_REASSEMBLIES: usize = 64;
+
+const FLAG_DONT_FRAGMENT: u16 = 0x4000;
+const FLAG_MORE_FRAGMENTS: u16 = 0x2000;
+const FRAGMENT_OFFSET_MASK: u16 = 0x1FFF;
+
+/// Metadata set on received datagrams
+pub const META_SOURCE: &str = "ipv4.source";
+pub const META_DESTINATION: &str = "ipv4.destination";
+pub const META_PROTOCOL: &str = "ipv4.protocol";
+pub const META_TTL: &str = "ipv4.ttl";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Header {
+    pub header_len: usize,
+    pub tos: u8,
+    pub total_len: usize,
+    pub identification: u16,
+    pub dont_fragment: bool,
+    pub more_fragments: bool,
+    /// Offset of the fragment in the datagram, in bytes
+    pub fragment_offset: usize,
+    pub ttl: u8,
+    pub protocol: u8,
+    pub source: Ipv4Address,
+    pub destination: Ipv4Address,
+}
+
+impl Ipv4Header {
+    /// A header without options for `payload_len` bytes
+    pub fn new(source: Ipv4Address, destination: Ipv4Address, protocol: u8, payload_len: usize) -> Self {
+        Self {
+            header_len: IPV4_HEADER_LEN,
+            tos: 0,
+            total_len: IPV4_HEADER_LEN + payload_len,
+            identification: 0,
+            dont_fragment: false,
+            more_fragments: false,
+            fragment_offset: 0,
+            ttl: DEFAULT_TTL,
+            protocol,
+            source,
+            destination,
+        }
+    }
+
+    /// Parse a header, options included; the checksum is not checked
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < IPV4_HEADER_LEN || bytes[0] >> 4 != 4 {
+            return None;
+        }
+        let header_len = (bytes[0] & 0x0F) as usize * 4;
+        let total_len = u16::from_be_bytes([bytes[2], bytes[3]]) as usize;
+        if header_len < IPV4_HEADER_LEN || bytes.len() < header_len || total_len < header_len {
+            return None;
+        }
+        let flags = u16::from_be_bytes([bytes[6], bytes[7]]);
+        Some(Self {
+            header_len,
+            tos: bytes[1],
+            total_len,
+            identification: u16::from_be_bytes([bytes[4], bytes[5]]),
+            dont_fragment: flags & FLAG_DONT_FRAGMENT != 0,
+            more_fragments: flags & FLAG_MORE_FRAGMENTS != 0,
+            fragment_offset: (flags & FRAGMENT_OFFSET_MASK) as usize * 8,
+            ttl: bytes[8],
+            protocol: bytes[9],
+            source: Ipv4Address::from_slice(&bytes[12..16])?,
+            destination: Ipv4Address::from_slice(&bytes[16..20])?,
+        })
+    }
+
+    /// The header without options, checksum filled in
+    pub fn to_bytes(&self) -> [u8; IPV4_HEADER_LEN] {
+        let mut bytes = [0; IPV4_HEADER_LEN];
+        bytes[0] = 0x45;
+        bytes[1] = self.tos;
+        bytes[2..4].copy_from_slice(&(self.total_len as u16).to_be_bytes());
+        bytes[4..6].copy_from_slice(&self.identification.to_be_bytes());
+        let mut flags = (self.fragment_offset / 8) as u16 & FRAGMENT_OFFSET_MASK;
+        if self.dont_fragment {
+            flags |= FLAG_DONT_FRAGMENT;
+        }
+        if self.more_fragments {
+            flags |= FLAG_MORE_FRAGMENTS;
+        }
+        bytes[6..8].copy_from_slice(&flags.to_be_bytes());
+        bytes[8] = self.ttl;
+        bytes[9] = self.protocol;
+        bytes[12..16].copy_from_slice(&self.source.octets());
+        bytes[16..20].copy_from_slice(&self.destination.octets());
+        let sum = checksum(&bytes);
+        bytes[10..12].copy_from_slice(&sum.to_be_bytes());
+        bytes
+    }
+
+    pub fn is_fragment(&self) -> bool {
+        self.more_fragments || self.fragment_offset != 0
+    }
+}
+
+/// The source address of a received datagram
+pub fn source(packet: &NetworkPacket) -> Option<Ipv4Address> {
+    packet.metadata_int(META_SOURCE).map(|value| Ipv4Address::from_u32(value as u32))
+}
+
+/// The destination address of a received datagram
+pub fn destination(packet: &NetworkPacket) -> Option<Ipv4Address> {
+    packet.metadata_int(META_DESTINATION).map(|value| Ipv4Address::from_u32(value as u32))
+}
+
+/// Whether datagrams to `address` received on `interface` are for the host
+fn accepts(interface: &NetworkInterface, address: Ipv4Address) -> bool {
+    address.is_broadcast()
+        || address.is_multicast()
+        || interface.ipv4_addresses().iter().any(|cidr| cidr.address == address || cidr.broadcast() == address)
+}
+
+/// Identifies the fragments of a datagram
+type FragmentKey = (Ipv4Address, Ipv4Address, u8, u16);
+
+struct Reassembly {
+    /// Received pieces by their offset
+    fragments: BTreeMap<usize, Vec<u8>>,
+    /// Length of the datagram payload, once the last fragment came
+    total_len: Option<usize>,
+    expires: u64,
+    /// Interface and header plus first 8 bytes of the first fragment, for
+    /// the ICMP error if the datagram is not completed in time
+    first: Option<(Arc<NetworkInterface>, Vec<u8>)>,
+}
+
+impl Reassembly {
+    /// The payload, if every piece of it is there
+    fn assemble(&self) -> Option<Vec<u8>> {
+        let total_len = self.total_len?;
+        let mut covered = 0;
+        for (&offset, data) in &self.fragments {
+            if offset > covered {
+                return None;
+            }
+            covered = covered.max(offset + data.len());
+        }
+        if covered < total_len {
+            return None;
+        }
+        let mut payload = alloc::vec![0; total_len];
+        for (&offset, data) in &self.fragments {
+            let end = (offset + data.len()).min(total_len);
+            if offset < end {
+                payload[offset..end].copy_from_slice(&data[..end - offset]);
+            }
+        }
+        Some(payload)
+    }
+}
+
+/// Fragments waiting for the rest of their datagram
+pub struct Reassembler {
+    pending: Mutex<BTreeMap<FragmentKey, Reassembly>>,
+}
+
+static REASSEMBLER: Reassembler = Reassembler::new();
+
+impl Reassembler {
+    pub const fn new() -> Self {
+        Self { pending: Mutex::new(BTreeMap::new()) }
+    }
+
+    /// Add the payload of a fragment; returns the payload of the datagram
+    /// once it is complete
+    ///
+    /// `first` is the interface and start of the datagram of a first
+    /// fragment, kept to report a reassembly timeout.
+    fn insert(
+        &self,
+        header: &Ipv4Header,
+        data: &[u8],
+        first: Option<(Arc<NetworkInterface>, Vec<u8>)>,
+        now: u64,
+    ) -> Result<Option<Vec<u8>>, &'static str> {
+        if header.fragment_offset + data.len() > IPV4_MAX_DATAGRAM_LEN - IPV4_HEADER_LEN {
+            return Err("Oversized IPv4 datagram");
+        }
+        let key = (header.source, header.destination, header.protocol, header.identification);
+        let mut pending = self.pending.lock();
+        if !pending.contains_key(&key) && pending.len() >= MAX_REASSEMBLIES {
+            return Err("Too many IPv4 reassemblies");
+        }
+        let reassembly = pending.entry(key).or_insert_with(|| Reassembly {
+            fragments: BTreeMap::new(),
+            total_len: None,
+            expires: now + ms_to_ticks(REASSEMBLY_TIMEOUT_MS),
+            first: None,
+        });
+        if !header.more_fragments {
+            reassembly.total_len = Some(header.fragment_offset + data.len());
+        }
+        if first.is_some() {
+            reassembly.first = first;
+        }
+        reassembly.fragments.insert(header.fragment_offset, data.to_vec());
+        let Some(payload) = reassembly.assemble() else {
+            return Ok(None);
+        };
+        pending.remove(&key);
+        Ok(Some(payload))
+    }
+
+    /// Drop the datagrams that timed out; returns the first fragments of
+    /// those that had one
+    fn expire(&self, now: u64) -> Vec<(Arc<NetworkInterface>, Vec<u8>)> {
+        let mut expired = Vec::new();
+        self.pending.lock().retain(|_, reassembly| {
+            if reassembly.expires > now {
+                return true;
+            }
+            stats::count(Counter::IpReasmFails);
+            expired.extend(reassembly.first.take());
+            false
+        });
+        expired
+    }
+}
+
+/// Processor of IPv4 at the "ethertype" stage
+pub struct Ipv4Processor;
+
+impl PacketProcessor for Ipv4Processor {
+    fn name(&self) -> &'static str {
+        "ipv4"
+    }
+
+    fn process(&self, packet: &mut NetworkPacket) -> ProcessResult {
+        stats::count(Counter::IpInReceives);
+        let Some(header) = Ipv4Header::parse(packet.payload()) else {
+            stats::count(Counter::IpInHdrErrors);
+            return ProcessResult::Dropped("Malformed IPv4 header");
+        };
+        if checksum(&packet.payload()[..header.header_len]) != 0 {
+            stats::count(Counter::IpInHdrErrors);
+            return ProcessResult::Dropped("Bad IPv4 checksum");
+        }
+        if header.total_len > packet.payload().len() {
+            stats::count(Counter::IpInHdrErrors);
+            return ProcessResult::Dropped("Truncated IPv4 datagram");
+        }
+        let Some(interface) = packet.interface().cloned() else {
+            return ProcessResult::Dropped("No interface");
+        };
+        let transport = (header.fragment_offset == 0).then(|| &packet.payload()[header.header_len..header.total_len]);
+        let info = PacketInfo::new(interface.name(), header.source, header.destination, header.protocol, transport);
+        if !firewall::accepts(FirewallHook::Prerouting, &info) {
+            stats::count(Counter::IpInDiscards);
+            return ProcessResult::Dropped("Filtered at prerouting");
+        }
+        if !accepts(&interface, header.destination) {
+            stats::count(Counter::IpInAddrErrors);
+            return ProcessResult::Dropped("Not addressed to the host");
+        }
+        let first = (header.is_fragment() && header.fragment_offset == 0).then(|| {
+            let len = packet.payload().len().min(header.header_len + 8);
+            (interface.clone(), packet.payload()[..len].to_vec())
+        });
+        let _ = packet.consume_header("ipv4", header.header_len);
+        packet.truncate_payload(header.total_len - header.header_len);
+
+        if header.is_fragment() {
+            stats::count(Counter::IpReasmReqds);
+            match REASSEMBLER.insert(&header, packet.payload(), first, crate::timer::get_tick()) {
+                Ok(Some(payload)) => {
+                    stats::count(Counter::IpReasmOKs);
+                    // What the device checked was one fragment, not this
+                    packet.replace_payload(payload);
+                    packet.set_metadata(META_CHECKSUM_VERIFIED, MetadataValue::Int(0));
+                }
+                Ok(None) => return ProcessResult::Consumed,
+                Err(reason) => {
+                    stats::count(Counter::IpReasmFails);
+                    return ProcessResult::Dropped(reason);
+                }
+            }
+        }
+        let info = PacketInfo::new(interface.name(), header.source, header.destination, header.protocol, Some(packet.payload()));
+        if !firewall::accepts(FirewallHook::Input, &info) {
+            stats::count(Counter::IpInDiscards);
+            return ProcessResult::Dropped("Filtered at input");
+        }
+        stats::count(Counter::IpInDelivers);
+        packet.set_metadata(META_SOURCE, MetadataValue::Int(header.source.to_u32() as u64));
+        packet.set_metadata(META_DESTINATION, MetadataValue::Int(header.destination.to_u32() as u64));
+        packet.set_metadata(META_PROTOCOL, MetadataValue::Int(header.protocol as u64));
+        packet.set_metadata(META_TTL, MetadataValue::Int(header.ttl as u64));
+        raw::deliver(packet, header.protocol);
+        ProcessResult::Forward { stage: IPV4_STAGE, key: header.protocol as u64 }
+    }
+}
+
+static NEXT_IDENTIFICATION: AtomicU16 = AtomicU16::new(1);
+
+/// Split a datagram into fragments that fit in `mtu`, with headroom for
+/// the link header
+///
+/// Fails with `MessageTooLong` if it does not fit and must not be
+/// fragmented, or is longer than a datagram can be.
+pub fn fragment(header: &Ipv4Header, payload: &[u8], mtu: usize) -> Result<Vec<PacketBuffer>, KernelError> {
+    if IPV4_HEADER_LEN + payload.len() > IPV4_MAX_DATAGRAM_LEN {
+        return Err(KernelError::MessageTooLong);
+    }
+    if IPV4_HEADER_LEN + payload.len() <= mtu {
+        let mut header = *header;
+        header.total_len = IPV4_HEADER_LEN + payload.len();
+        let mut datagram = PacketBuffer::copy_from(payload);
+        datagram.push_front(&header.to_bytes());
+        return Ok(alloc::vec![datagram]);
+    }
+    // Every fragment but the last carries a multiple of 8 bytes
+    let chunk = mtu.saturating_sub(IPV4_HEADER_LEN) & !7;
+    if header.dont_fragment || chunk == 0 {
+        return Err(KernelError::MessageTooLong);
+    }
+    // The fragments share one copy of the payload
+    let shared = BufferSlice::from(payload.to_vec());
+    Ok((0..payload.len())
+        .step_by(chunk)
+        .map(|offset| {
+            let end = (offset + chunk).min(payload.len());
+            let mut header = *header;
+            header.fragment_offset = offset;
+            header.more_fragments = end < payload.len();
+            header.total_len = IPV4_HEADER_LEN + end - offset;
+            let mut datagram = PacketBuffer::with_room(DEFAULT_HEADROOM, IPV4_HEADER_LEN);
+            datagram.extend_back(&header.to_bytes());
+            datagram.append(shared.slice(offset..end).unwrap());
+            datagram
+        })
+        .collect())
+}
+
+/// The interface and next hop for `destination`
+fn route_for(
+    destination: Ipv4Address,
+    options: &SendOptions,
+) -> Result<(Arc<NetworkInterface>, Ipv4Address), KernelError> {
+    if let Some(interface) = &options.interface {
+        let on_link = destination.is_broadcast()
+            || destination.is_multicast()
+            || interface.ipv4_addresses().iter().any(|cidr| cidr.contains(destination));
+        if on_link {
+            return Ok((interface.clone(), destination));
+        }
+        let route = routing_table()
+            .routes()
+            .into_iter()
+            .filter(|route| route.interface == interface.id() && route.destination.contains(destination))
+            .max_by_key(|route| route.destination.prefix_len)
+            .ok_or(KernelError::NetworkUnreachable)?;
+        return Ok((interface.clone(), route.next_hop(destination)));
+    }
+    let manager = get_network_manager();
+    let in_namespace = |id| manager.interface(id).filter(|interface| interface.namespace() == options.namespace);
+    let route = routing_table().lookup_where(destination, |route| in_namespace(route.interface).is_some());
+    let interface = match route.and_then(|route| in_namespace(route.interface)) {
+        Some(interface) => interface,
+        // The limited broadcast goes out of the first configured interface
+        None if destination.is_broadcast() => manager
+            .interfaces_in(options.namespace)
+            .into_iter()
+            .find(|interface| !interface.ipv4_addresses().is_empty())
+            .ok_or(KernelError::NetworkUnreachable)?,
+        None => return Err(KernelError::NetworkUnreachable),
+    };
+    Ok((interface, route.map_or(destination, |route| route.next_hop(destination))))
+}
+
+/// The interface of the host in `namespace` that has `address`
+fn local_interface(manager: &NetworkManager, namespace: NetNamespaceId, address: Ipv4Address) -> Option<Arc<NetworkInterface>> {
+    manager.interfaces_in(namespace).into_iter().find(|interface| interface.has_ipv4_address(address))
+}
+
+/// The interface a datagram to `destination` sent with `options` goes out
+/// of; `None` if it is for the host itself
+pub fn interface_for(destination: Ipv4Address, options: &SendOptions) -> Result<Option<Arc<NetworkInterface>>, KernelError> {
+    if options.interface.is_none() && local_interface(get_network_manager(), options.namespace, destination).is_some() {
+        return Ok(None);
+    }
+    route_for(destination, options).map(|(interface, _)| Some(interface))
+}
+
+/// Pass a datagram to send out of `interface` through the output hook
+fn output_filter(interface: &NetworkInterface, header: &Ipv4Header, payload: &[u8]) -> Result<(), KernelError> {
+    let info = PacketInfo::new(interface.name(), header.source, header.destination, header.protocol, Some(payload));
+    if firewall::accepts(FirewallHook::Output, &info) {
+        Ok(())
+    } else {
+        Err(KernelError::NotPermitted)
+    }
+}
+
+/// The IPv4 address `options` asks to send from
+fn source_option(options: &SendOptions) -> Result<Option<Ipv4Address>, KernelError> {
+    match options.source {
+        None => Ok(None),
+        Some(IpAddress::V4(address)) => Ok(Some(address)),
+        Some(IpAddress::V6(_)) => Err(KernelError::InvalidArgument),
+    }
+}
+
+/// The address a datagram to `destination` sent with `options` comes from
+///
+/// Transports need it before sending for the checksum of their
+/// pseudo-header.
+pub fn source_for(destination: Ipv4Address, options: &SendOptions) -> Result<Ipv4Address, KernelError> {
+    if let Some(source) = source_option(options)? {
+        return Ok(source);
+    }
+    if options.interface.is_none() && local_interface(get_network_manager(), options.namespace, destination).is_some() {
+        return Ok(destination);
+    }
+    let (interface, next_hop) = route_for(destination, options)?;
+    Ok(interface.ipv4_source_for(next_hop).unwrap_or(Ipv4Address::UNSPECIFIED))
+}
+
+/// Send `payload` to `destination` as a datagram of `protocol`
+///
+/// Fails with `NotPermitted` if the firewall drops it.
+pub fn send(destination: Ipv4Address, protocol: u8, payload: &[u8], options: &SendOptions) -> Result<(), KernelError> {
+    stats::count(Counter::IpOutRequests);
+    let manager = get_network_manager();
+    let mut header = Ipv4Header::new(Ipv4Address::UNSPECIFIED, destination, protocol, payload.len());
+    header.identification = NEXT_IDENTIFICATION.fetch_add(1, Ordering::Relaxed);
+    header.ttl = options.ttl;
+    header.tos = options.tos;
+    header.dont_fragment = options.dont_fragment;
+    let completed: Vec<u8>;
+    let mut payload = payload;
+
+    if options.interface.is_none() {
+        if let Some(interface) = local_interface(manager, options.namespace, destination) {
+            header.source = source_option(options)?.unwrap_or(destination);
+            output_filter(&interface, &header, payload).inspect_err(|_| stats::count(Counter::IpOutDiscards))?;
+            if let Some(offset) = options.partial_checksum {
+                completed = {
+                    let mut completed = payload.to_vec();
+                    ip::complete_checksum(&mut completed, offset, protocol)?;
+                    completed
+                };
+                payload = &completed;
+            }
+            let datagram = fragment(&header, payload, IPV4_MAX_DATAGRAM_LEN)?.remove(0);
+            let mut packet = NetworkPacket::incoming(datagram, interface);
+            manager.pipeline().process(&mut packet, ETHERTYPE_STAGE, ETHERTYPE_IPV4 as u64);
+            return Ok(());
+        }
+    }
+    let (interface, next_hop) = route_for(destination, options).inspect_err(|_| stats::count(Counter::IpOutNoRoutes))?;
+    header.source = source_option(options)?
+        .or_else(|| interface.ipv4_source_for(next_hop))
+        .unwrap_or(Ipv4Address::UNSPECIFIED);
+    output_filter(&interface, &header, payload).inspect_err(|_| stats::count(Counter::IpOutDiscards))?;
+    let offloads = interface.offloads();
+    let mut mtu = interface.mtu()?;
+    let mut segmentation = None;
+    if let Some(segment_size) = options.segment_size.filter(|_| IPV4_HEADER_LEN + payload.len() > mtu) {
+        if !offloads.tso4 || !offloads.tx_checksum || protocol != PROTOCOL_TCP || options.partial_checksum.is_none() {
+            return Err(KernelError::NotSupported);
+        }
+        // The data offset of the TCP header, in words
+        let transport_len = payload.get(12).map_or(0, |&offset| (offset >> 4) as usize * 4);
+        segmentation = Some((segment_size, IPV4_HEADER_LEN + transport_len));
+        header.dont_fragment = true;
+        mtu = IPV4_MAX_DATAGRAM_LEN;
+    }
+    let offloaded = offloads.tx_checksum && IPV4_HEADER_LEN + payload.len() <= mtu;
+    if let Some(offset) = options.partial_checksum.filter(|_| !offloaded) {
+        completed = {
+            let mut completed = payload.to_vec();
+            ip::complete_checksum(&mut completed, offset, protocol)?;
+            completed
+        };
+        payload = &completed;
+    }
+    let datagrams = fragment(&header, payload, mtu).inspect_err(|_| stats::count(Counter::IpFragFails))?;
+    if datagrams.len() > 1 {
+        stats::count(Counter::IpFragOKs);
+        stats::add(Counter::IpFragCreates, datagrams.len() as u64);
+    }
+    for datagram in datagrams {
+        let mut packet = NetworkPacket::outgoing(datagram);
+        if let Some(offset) = options.partial_checksum.filter(|_| offloaded) {
+            packet.set_metadata(META_CHECKSUM_START, MetadataValue::Int(IPV4_HEADER_LEN as u64));
+            packet.set_metadata(META_CHECKSUM_OFFSET, MetadataValue::Int(offset as u64));
+        }
+        if let Some((segment_size, header_len)) = segmentation {
+            packet.set_metadata(META_SEGMENT_SIZE, MetadataValue::Int(segment_size as u64));
+            packet.set_metadata(META_SEGMENT_HEADER_LEN, MetadataValue::Int(header_len as u64));
+        }
+        arp::send_ipv4(&interface, next_hop, packet)?;
+    }
+    Ok(())
+}
+
+/// Give `interface` the address `cidr`, with a route to its network, and
+/// announce it on the link
+pub fn add_address(interface: &NetworkInterface, cidr: Ipv4Cidr) -> Result<(), KernelError> {
+    interface.add_ipv4_address(cidr)?;
+    let route = Route { destination: cidr, gateway: None, interface: interface.id(), metric: 0 };
+    match routing_table().add(route) {
+        Ok(()) | Err(KernelError::Exists) => {}
+        Err(error) => return Err(error),
+    }
+    let _ = arp::announce(interface, cidr.address);
+    Ok(())
+}
+
+/// Take `address` from `interface`, with the route to its network if no
+/// other address of the interface is on it
+pub fn remove_address(interface: &NetworkInterface, address: Ipv4Address) -> Result<(), KernelError> {
+    let cidr = interface
+        .ipv4_addresses()
+        .into_iter()
+        .find(|cidr| cidr.address == address)
+        .ok_or(KernelError::NotFound)?;
+    interface.remove_ipv4_address(address)?;
+    let shared = interface
+        .ipv4_addresses()
+        .iter()
+        .any(|other| other.prefix_len == cidr.prefix_len && other.network() == cidr.network());
+    if !shared {
+        let _ = routing_table().remove(cidr, Some(interface.id()));
+    }
+    Ok(())
+}
+
+/// Timer of reassembly: report datagrams that were not completed in time
+fn expire_reassemblies(_manager: &NetworkManager, now: u64) {
+    for (interface, original) in REASSEMBLER.expire(now) {
+        let _ = icmp::send_error(&interface, &original, icmp::ICMP_TIME_EXCEEDED, icmp::TIME_EXCEEDED_REASSEMBLY, 0);
+    }
+}
+
+/// Register IPv4 with the pipeline of `manager`
+pub fn register(manager: &NetworkManager) -> Result<(), KernelError> {
+    let pipeline = manager.pipeline();
+    pipeline.add_stage(IPV4_STAGE)?;
+    pipeline.register_processor(ETHERTYPE_STAGE, ETHERTYPE_IPV4 as u64, Arc::new(Ipv4Processor))?;
+    manager.register_timer(expire_reassemblies);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_fragment_and_reassemble() {
+        let source = Ipv4Address::new(192, 0, 2, 1);
+        let destination = Ipv4Address::new(192, 0, 2, 2);
+        let payload: Vec<u8> = (0..3000u32).map(|i| i as u8).collect();
+        let mut header = Ipv4Header::new(source, destination, PROTOCOL_UDP, payload.len());
+        header.identification = 77;
+
+        let fragments: Vec<Vec<u8>> = fragment(&header, &payload, 1500).unwrap().iter().map(PacketBuffer::to_vec).collect();
+        assert_eq!(fragments.len(), 3);
+        assert!(fragments.iter().all(|datagram| datagram.len() <= 1500 && checksum(&datagram[..IPV4_HEADER_LEN]) == 0));
+        header.dont_fragment = true;
+        assert_eq!(fragment(&header, &payload, 1500), Err(KernelError::MessageTooLong));
+
+        // Out of order, with a duplicate, the datagram comes back whole
+        let reassembler = Reassembler::new();
+        let mut result = None;
+        for index in [2, 0, 0, 1] {
+            let datagram = &fragments[index];
+            let header = Ipv4Header::parse(datagram).unwrap();
+            assert_eq!(header.identification, 77);
+            assert!(result.is_none());
+            result = reassembler.insert(&header, &datagram[IPV4_HEADER_LEN..], None, 0).unwrap();
+        }
+        assert_eq!(result, Some(payload));
+
+        // An incomplete datagram times out
+        let header = Ipv4Header::parse(&fragments[0]).unwrap();
+        assert_eq!(reassembler.insert(&header, &fragments[0][IPV4_HEADER_LEN..], None, 0), Ok(None));
+        assert!(reassembler.expire(ms_to_ticks(REASSEMBLY_TIMEOUT_MS)).is_empty());
+        assert!(reassembler.pending.lock().is_empty());
+    }
+}
@@ -0,0 +1,376 @@
+//! IPv6
+//!
+//! The IPv6 processor sits at the "ethertype" stage under 0x86DD. It checks
+//! the header of a received datagram, drops those not addressed to the
+//! host, steps over the hop-by-hop, routing and destination options
+//! headers and passes the datagram to the "ipv6" stage keyed by the next
+//! header of its upper layer, with its addresses in the metadata of the
+//! packet, once the [`raw`] sockets have had their copy. Fragmented datagrams are dropped: there is no reassembly, and
+//! [`send`] never fragments either, failing with `MessageTooLong` what the
+//! MTU of the link does not carry.
+//!
+//! Routes come from neighbor discovery ([`ndp`]): destinations on a prefix
+//! of the link, on-link or of an address of the interface, are sent to
+//! directly, others to a default router. Link-local and multicast
+//! destinations are on the link of the interface given, or of the first
+//! interface with a link-local address. Datagrams for an address of the
+//! host are delivered through the pipeline without touching a device.
+//!
+//! The [`firewall`](super::firewall) filters IPv4 only.
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::abi::error::KernelError;
+
+use super::address::{IpAddress, Ipv6Address};
+use super::buffer::PacketBuffer;
+use super::ethernet::{ETHERTYPE_IPV6, ETHERTYPE_STAGE};
+use super::ip::{self, SendOptions};
+use super::manager::{get_network_manager, NetworkInterface, NetworkManager};
+use super::namespace::NetNamespaceId;
+use super::ndp;
+use super::packet::{MetadataValue, NetworkPacket, META_CHECKSUM_OFFSET, META_CHECKSUM_START};
+use super::pipeline::{PacketProcessor, ProcessResult};
+use super::raw;
+
+pub const IPV6_HEADER_LEN: usize = 40;
+/// Longest payload, extension headers included, without jumbograms
+pub const IPV6_MAX_PAYLOAD_LEN: usize = 65535;
+/// Smallest MTU a link of IPv6 may have
+pub const IPV6_MIN_MTU: usize = 1280;
+
+/// Stage of the protocols above IPv6, keyed by next header
+pub const IPV6_STAGE: &str = "ipv6";
+
+/// Next headers
+pub const NEXT_HEADER_HOP_BY_HOP: u8 = 0;
+pub const NEXT_HEADER_ROUTING: u8 = 43;
+pub const NEXT_HEADER_FRAGMENT: u8 = 44;
+pub const NEXT_HEADER_ICMPV6: u8 = 58;
+pub const NEXT_HEADER_NONE: u8 = 59;
+pub const NEXT_HEADER_DESTINATION_OPTIONS: u8 = 60;
+
+pub const DEFAULT_HOP_LIMIT: u8 = 64;
+
+/// Metadata set on received datagrams; the addresses are bytes
+pub const META_SOURCE: &str = "ipv6.source";
+pub const META_DESTINATION: &str = "ipv6.destination";
+pub const META_NEXT_HEADER: &str = "ipv6.next_header";
+pub const META_HOP_LIMIT: &str = "ipv6.hop_limit";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv6Header {
+    pub traffic_class: u8,
+    pub flow_label: u32,
+    /// Length after the fixed header, extension headers included
+    pub payload_len: usize,
+    pub next_header: u8,
+    pub hop_limit: u8,
+    pub source: Ipv6Address,
+    pub destination: Ipv6Address,
+}
+
+impl Ipv6Header {
+    pub fn new(source: Ipv6Address, destination: Ipv6Address, next_header: u8, payload_len: usize) -> Self {
+        Self {
+            traffic_class: 0,
+            flow_label: 0,
+            payload_len,
+            next_header,
+            hop_limit: DEFAULT_HOP_LIMIT,
+            source,
+            destination,
+        }
+    }
+
+    /// Parse the fixed header; `None` if it is short or not version 6
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < IPV6_HEADER_LEN || bytes[0] >> 4 != 6 {
+            return None;
+        }
+        let word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        Some(Self {
+            traffic_class: (word >> 20) as u8,
+            flow_label: word & 0xF_FFFF,
+            payload_len: u16::from_be_bytes([bytes[4], bytes[5]]) as usize,
+            next_header: bytes[6],
+            hop_limit: bytes[7],
+            source: Ipv6Address::from_slice(&bytes[8..24])?,
+            destination: Ipv6Address::from_slice(&bytes[24..40])?,
+        })
+    }
+
+    pub fn to_bytes(&self) -> [u8; IPV6_HEADER_LEN] {
+        let mut bytes = [0; IPV6_HEADER_LEN];
+        let word = 6 << 28 | (self.traffic_class as u32) << 20 | (self.flow_label & 0xF_FFFF);
+        bytes[0..4].copy_from_slice(&word.to_be_bytes());
+        bytes[4..6].copy_from_slice(&(self.payload_len as u16).to_be_bytes());
+        bytes[6] = self.next_header;
+        bytes[7] = self.hop_limit;
+        bytes[8..24].copy_from_slice(&self.source.octets());
+        bytes[24..40].copy_from_slice(&self.destination.octets());
+        bytes
+    }
+}
+
+/// Step over the extension headers at the start of `payload`, the first
+/// of which is `next_header`
+///
+/// # Returns
+/// The next header of the upper layer and the offset of its header
+pub fn upper_layer(payload: &[u8], mut next_header: u8) -> Result<(u8, usize), &'static str> {
+    let mut offset = 0;
+    loop {
+        match next_header {
+            NEXT_HEADER_HOP_BY_HOP | NEXT_HEADER_ROUTING | NEXT_HEADER_DESTINATION_OPTIONS => {
+                let header = payload.get(offset..offset + 8).ok_or("Truncated IPv6 extension header")?;
+                // A routing header still to be followed is for a router
+                if next_header == NEXT_HEADER_ROUTING && header[3] != 0 {
+                    return Err("IPv6 routing header with segments left");
+                }
+                next_header = header[0];
+                offset += (header[1] as usize + 1) * 8;
+            }
+            NEXT_HEADER_FRAGMENT => return Err("IPv6 fragment"),
+            _ => return Ok((next_header, offset)),
+        }
+    }
+}
+
+fn address_metadata(packet: &NetworkPacket, key: &str) -> Option<Ipv6Address> {
+    match packet.metadata(key) {
+        Some(MetadataValue::Bytes(bytes)) => Ipv6Address::from_slice(bytes),
+        _ => None,
+    }
+}
+
+/// The source address of a received datagram
+pub fn source(packet: &NetworkPacket) -> Option<Ipv6Address> {
+    address_metadata(packet, META_SOURCE)
+}
+
+/// The destination address of a received datagram
+pub fn destination(packet: &NetworkPacket) -> Option<Ipv6Address> {
+    address_metadata(packet, META_DESTINATION)
+}
+
+/// Whether datagrams to `address` received on `interface` are for the host
+fn accepts(interface: &NetworkInterface, address: Ipv6Address) -> bool {
+    address.is_multicast() || interface.has_ipv6_address(address)
+}
+
+/// Processor of IPv6 at the "ethertype" stage
+pub struct Ipv6Processor;
+
+impl PacketProcessor for Ipv6Processor {
+    fn name(&self) -> &'static str {
+        "ipv6"
+    }
+
+    fn process(&self, packet: &mut NetworkPacket) -> ProcessResult {
+        let Some(header) = Ipv6Header::parse(packet.payload()) else {
+            return ProcessResult::Dropped("Malformed IPv6 header");
+        };
+        if IPV6_HEADER_LEN + header.payload_len > packet.payload().len() {
+            return ProcessResult::Dropped("Truncated IPv6 datagram");
+        }
+        if header.source.is_multicast() {
+            return ProcessResult::Dropped("IPv6 datagram from a multicast address");
+        }
+        let Some(interface) = packet.interface().cloned() else {
+            return ProcessResult::Dropped("No interface");
+        };
+        if !accepts(&interface, header.destination) {
+            return ProcessResult::Dropped("Not addressed to the host");
+        }
+        let payload = &packet.payload()[IPV6_HEADER_LEN..IPV6_HEADER_LEN + header.payload_len];
+        let (next_header, offset) = match upper_layer(payload, header.next_header) {
+            Ok(upper) => upper,
+            Err(reason) => return ProcessResult::Dropped(reason),
+        };
+        if next_header == NEXT_HEADER_NONE {
+            return ProcessResult::Consumed;
+        }
+        let _ = packet.consume_header("ipv6", IPV6_HEADER_LEN + offset);
+        packet.truncate_payload(header.payload_len - offset);
+        packet.set_metadata(META_SOURCE, MetadataValue::Bytes(header.source.octets().to_vec()));
+        packet.set_metadata(META_DESTINATION, MetadataValue::Bytes(header.destination.octets().to_vec()));
+        packet.set_metadata(META_NEXT_HEADER, MetadataValue::Int(next_header as u64));
+        packet.set_metadata(META_HOP_LIMIT, MetadataValue::Int(header.hop_limit as u64));
+        raw::deliver(packet, next_header);
+        ProcessResult::Forward { stage: IPV6_STAGE, key: next_header as u64 }
+    }
+}
+
+/// The IPv6 address `options` asks to send from
+fn source_option(options: &SendOptions) -> Result<Option<Ipv6Address>, KernelError> {
+    match options.source {
+        None => Ok(None),
+        Some(IpAddress::V6(address)) => Ok(Some(address)),
+        Some(IpAddress::V4(_)) => Err(KernelError::InvalidArgument),
+    }
+}
+
+/// Whether `destination` is reached on the link of `interface`
+fn on_link(interface: &NetworkInterface, destination: Ipv6Address) -> bool {
+    destination.is_multicast()
+        || destination.is_link_local()
+        || interface.ipv6_addresses().iter().any(|cidr| cidr.contains(destination))
+        || ndp::on_link(interface.id(), destination)
+}
+
+/// The interface and next hop for `destination`
+fn route_for(
+    destination: Ipv6Address,
+    options: &SendOptions,
+) -> Result<(Arc<NetworkInterface>, Ipv6Address), KernelError> {
+    if let Some(interface) = &options.interface {
+        if on_link(interface, destination) {
+            return Ok((interface.clone(), destination));
+        }
+        let router = ndp::default_router(Some(interface.id())).ok_or(KernelError::NetworkUnreachable)?;
+        return Ok((interface.clone(), router.1));
+    }
+    let manager = get_network_manager();
+    let interfaces = manager.interfaces_in(options.namespace);
+    if destination.is_multicast() || destination.is_link_local() {
+        // The scope is the link, but which link is not said
+        return interfaces
+            .into_iter()
+            .find(|interface| interface.ipv6_addresses().iter().any(|cidr| cidr.address.is_link_local()))
+            .map(|interface| (interface, destination))
+            .ok_or(KernelError::NetworkUnreachable);
+    }
+    if let Some(interface) = interfaces.iter().find(|interface| on_link(interface, destination)) {
+        return Ok((interface.clone(), destination));
+    }
+    let router = ndp::routers().into_iter().find_map(|router| {
+        let interface = interfaces.iter().find(|interface| interface.id() == router.interface)?;
+        Some((interface.clone(), router.address))
+    });
+    router.ok_or(KernelError::NetworkUnreachable)
+}
+
+/// The interface of the host in `namespace` that has `address`
+fn local_interface(manager: &NetworkManager, namespace: NetNamespaceId, address: Ipv6Address) -> Option<Arc<NetworkInterface>> {
+    manager.interfaces_in(namespace).into_iter().find(|interface| interface.has_ipv6_address(address))
+}
+
+/// The interface a datagram to `destination` sent with `options` goes out
+/// of; `None` if it is for the host itself
+pub fn interface_for(destination: Ipv6Address, options: &SendOptions) -> Result<Option<Arc<NetworkInterface>>, KernelError> {
+    if options.interface.is_none() && local_interface(get_network_manager(), options.namespace, destination).is_some() {
+        return Ok(None);
+    }
+    route_for(destination, options).map(|(interface, _)| Some(interface))
+}
+
+/// The address a datagram to `destination` sent with `options` comes from
+///
+/// Transports need it before sending for the checksum of their
+/// pseudo-header. It is the unspecified address while the interface has
+/// none to send from, as in a router solicitation.
+pub fn source_for(destination: Ipv6Address, options: &SendOptions) -> Result<Ipv6Address, KernelError> {
+    if let Some(source) = source_option(options)? {
+        return Ok(source);
+    }
+    if options.interface.is_none() && local_interface(get_network_manager(), options.namespace, destination).is_some() {
+        return Ok(destination);
+    }
+    let (interface, _) = route_for(destination, options)?;
+    Ok(interface.ipv6_source_for(destination).unwrap_or(Ipv6Address::UNSPECIFIED))
+}
+
+/// Send `payload` to `destination` as a datagram with `next_header`
+///
+/// Fails with `MessageTooLong` if the datagram is longer than the MTU, and
+/// with `NotSupported` if it asks for segmentation, which IPv6 leaves to
+/// the transport.
+pub fn send(destination: Ipv6Address, next_header: u8, payload: &[u8], options: &SendOptions) -> Result<(), KernelError> {
+    if payload.len() > IPV6_MAX_PAYLOAD_LEN {
+        return Err(KernelError::MessageTooLong);
+    }
+    let manager = get_network_manager();
+    let mut header = Ipv6Header::new(Ipv6Address::UNSPECIFIED, destination, next_header, payload.len());
+    header.hop_limit = options.ttl;
+    header.traffic_class = options.tos;
+    let completed: Vec<u8>;
+    let mut payload = payload;
+
+    if options.interface.is_none() {
+        if let Some(interface) = local_interface(manager, options.namespace, destination) {
+            header.source = source_option(options)?.unwrap_or(destination);
+            if let Some(offset) = options.partial_checksum {
+                completed = {
+                    let mut completed = payload.to_vec();
+                    ip::complete_checksum(&mut completed, offset, next_header)?;
+                    completed
+                };
+                payload = &completed;
+            }
+            let mut datagram = header.to_bytes().to_vec();
+            datagram.extend_from_slice(payload);
+            let mut packet = NetworkPacket::incoming(datagram, interface);
+            manager.pipeline().process(&mut packet, ETHERTYPE_STAGE, ETHERTYPE_IPV6 as u64);
+            return Ok(());
+        }
+    }
+    let (interface, next_hop) = route_for(destination, options)?;
+    header.source = match source_option(options)? {
+        Some(source) => source,
+        None => interface.ipv6_source_for(destination).unwrap_or(Ipv6Address::UNSPECIFIED),
+    };
+    let mtu = interface.mtu()?;
+    if IPV6_HEADER_LEN + payload.len() > mtu {
+        return Err(if options.segment_size.is_some() { KernelError::NotSupported } else { KernelError::MessageTooLong });
+    }
+    let offloaded = interface.offloads().tx_checksum;
+    if let Some(offset) = options.partial_checksum.filter(|_| !offloaded) {
+        completed = {
+            let mut completed = payload.to_vec();
+            ip::complete_checksum(&mut completed, offset, next_header)?;
+            completed
+        };
+        payload = &completed;
+    }
+    let mut datagram = PacketBuffer::copy_from(payload);
+    datagram.push_front(&header.to_bytes());
+    let mut packet = NetworkPacket::outgoing(datagram);
+    if let Some(offset) = options.partial_checksum.filter(|_| offloaded) {
+        packet.set_metadata(META_CHECKSUM_START, MetadataValue::Int(IPV6_HEADER_LEN as u64));
+        packet.set_metadata(META_CHECKSUM_OFFSET, MetadataValue::Int(offset as u64));
+    }
+    ndp::send_ipv6(&interface, next_hop, packet)
+}
+
+/// Register IPv6 with the pipeline of `manager`
+pub fn register(manager: &NetworkManager) -> Result<(), KernelError> {
+    let pipeline = manager.pipeline();
+    pipeline.add_stage(IPV6_STAGE)?;
+    pipeline.register_processor(ETHERTYPE_STAGE, ETHERTYPE_IPV6 as u64, Arc::new(Ipv6Processor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_ipv6_header() {
+        let source = Ipv6Address::parse("fe80::1").unwrap();
+        let mut header = Ipv6Header::new(source, Ipv6Address::ALL_NODES, NEXT_HEADER_ICMPV6, 8);
+        header.traffic_class = 0xB8;
+        header.flow_label = 0x12345;
+        let bytes = header.to_bytes();
+        assert_eq!(bytes[0] >> 4, 6);
+        assert_eq!(Ipv6Header::parse(&bytes), Some(header));
+        assert_eq!(Ipv6Header::parse(&bytes[..39]), None);
+
+        // Extension headers are stepped over up to the upper layer
+        let mut payload = alloc::vec![NEXT_HEADER_DESTINATION_OPTIONS, 0, 1, 4, 0, 0, 0, 0];
+        payload.extend_from_slice(&[NEXT_HEADER_ICMPV6, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(upper_layer(&payload, NEXT_HEADER_HOP_BY_HOP), Ok((NEXT_HEADER_ICMPV6, 24)));
+        assert_eq!(upper_layer(&payload[..20], NEXT_HEADER_HOP_BY_HOP), Err("Truncated IPv6 extension header"));
+        assert_eq!(upper_layer(&payload, NEXT_HEADER_FRAGMENT), Err("IPv6 fragment"));
+    }
+}
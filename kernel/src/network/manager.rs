@@ -0,0 +1,597 @@
+//! Network manager
+//!
+//! The manager owns the protocol pipeline and the interfaces of the
+//! system. An interface is a network device attached to the stack under a
+//! name, with the pipeline stage its received frames enter at. Network
+//! devices registered with the device manager are attached as `ethN` when
+//! the stack starts or when they are hot-plugged, and detached when they
+//! are removed. An interface brought down receives and sends nothing
+//! until it is brought up again. An interface can be given an
+//! [`RxHandler`] that takes its frames instead of the pipeline, as a bridge
+//! takes those of its ports. Every interface is in a network namespace
+//! ([`namespace`](super::namespace)), the initial one once attached.
+//!
+//! Received packets are taken from the devices by the `netrx` kernel
+//! thread. It polls every interface at least every
+//! [`RX_POLL_INTERVAL_MS`], and at once when a driver reports received
+//! packets with [`NetworkManager::notify_rx`]. The same thread runs the
+//! timers protocols register (retransmissions, cache expiry) after every
+//! poll.
+
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::{Once, RwLock};
+
+use crate::abi::error::KernelError;
+use crate::device::events::{DeviceEvent, DeviceEventListener, DeviceHotplugEvent, HotplugAction};
+use crate::device::manager::DeviceManager;
+use crate::device::network::{
+    DevicePacket, MacAddress, NetworkDevice, NetworkOffloads, PacketChecksum, PacketSegmentation,
+};
+use crate::device::DeviceType;
+use crate::late_initcall;
+use crate::object::capability::poll::{wait_for, PollOps, POLLIN};
+use crate::sync::waker::Waker;
+use crate::task::kthread;
+use crate::timer::{get_tick, ms_to_ticks};
+
+use super::address::{Ipv4Address, Ipv4Cidr, Ipv6Address, Ipv6Cidr};
+use super::buffer::PacketBuffer;
+use super::capture;
+use super::namespace::{NetNamespaceId, INIT_NET_NS};
+use super::packet::{
+    MetadataValue, NetworkPacket, PacketDirection, META_CHECKSUM_OFFSET, META_CHECKSUM_START, META_CHECKSUM_VERIFIED,
+    META_SEGMENT_HEADER_LEN, META_SEGMENT_SIZE,
+};
+use super::pipeline::{FlexiblePipeline, PacketFate};
+use super::stats::{InterfaceStats, InterfaceStatsSnapshot};
+use super::InterfaceId;
+
+/// Stage that frames received on devices found by the device manager
+/// enter at
+pub const DEFAULT_ENTRY_STAGE: &str = "ethernet";
+
+/// Longest time between two polls of the devices
+pub const RX_POLL_INTERVAL_MS: u64 = 10;
+
+/// A protocol timer, called with the current tick
+pub type NetworkTimer = fn(&NetworkManager, u64);
+
+/// Takes the frames received on an interface before the pipeline does
+pub trait RxHandler: Send + Sync {
+    /// Handle a frame received on `interface`, still at its first header
+    fn handle(&self, manager: &NetworkManager, interface: &Arc<NetworkInterface>, packet: NetworkPacket) -> PacketFate;
+}
+
+/// A network device attached to the stack
+pub struct NetworkInterface {
+    id: InterfaceId,
+    name: String,
+    device: Arc<dyn NetworkDevice>,
+    /// Id of the device in the device manager, if it is registered there
+    device_id: Option<usize>,
+    entry_stage: &'static str,
+    /// IPv4 addresses of the interface, the first one preferred as source
+    ipv4_addresses: RwLock<Vec<Ipv4Cidr>>,
+    /// IPv6 addresses of the interface, link-local and global
+    ipv6_addresses: RwLock<Vec<Ipv6Cidr>>,
+    /// Whether the interface is up: one that is down receives nothing and
+    /// sends nothing
+    up: AtomicBool,
+    /// Packets and bytes received and sent, and those lost
+    stats: InterfaceStats,
+    /// Takes the received frames instead of the pipeline, if set
+    rx_handler: RwLock<Option<Arc<dyn RxHandler>>>,
+    /// Network namespace the interface is in
+    namespace: AtomicU32,
+}
+
+impl NetworkInterface {
+    pub fn id(&self) -> InterfaceId {
+        self.id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn device(&self) -> &Arc<dyn NetworkDevice> {
+        &self.device
+    }
+
+    pub fn device_id(&self) -> Option<usize> {
+        self.device_id
+    }
+
+    /// Stage the packets received on the interface enter the pipeline at
+    pub fn entry_stage(&self) -> &'static str {
+        self.entry_stage
+    }
+
+    pub fn mac_address(&self) -> Result<MacAddress, KernelError> {
+        self.device.get_mac_address().map_err(KernelError::from_message)
+    }
+
+    pub fn mtu(&self) -> Result<usize, KernelError> {
+        self.device.get_mtu().map_err(KernelError::from_message)
+    }
+
+    pub fn offloads(&self) -> NetworkOffloads {
+        self.device.offloads()
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.up.load(Ordering::Relaxed)
+    }
+
+    /// Bring the interface up or down; interfaces are up once attached
+    pub fn set_up(&self, up: bool) {
+        self.up.store(up, Ordering::Relaxed);
+    }
+
+    pub fn namespace(&self) -> NetNamespaceId {
+        self.namespace.load(Ordering::Relaxed)
+    }
+
+    /// Put the interface in another namespace; see
+    /// [`namespace::move_interface`](super::namespace::move_interface)
+    pub(super) fn set_namespace(&self, namespace: NetNamespaceId) {
+        self.namespace.store(namespace, Ordering::Relaxed);
+    }
+
+    pub fn ipv4_addresses(&self) -> Vec<Ipv4Cidr> {
+        self.ipv4_addresses.read().clone()
+    }
+
+    /// Add an IPv4 address; fails with `Exists` if the interface has it
+    pub fn add_ipv4_address(&self, cidr: Ipv4Cidr) -> Result<(), KernelError> {
+        let mut addresses = self.ipv4_addresses.write();
+        if addresses.iter().any(|existing| existing.address == cidr.address) {
+            return Err(KernelError::Exists);
+        }
+        addresses.push(cidr);
+        Ok(())
+    }
+
+    pub fn remove_ipv4_address(&self, address: Ipv4Address) -> Result<(), KernelError> {
+        let mut addresses = self.ipv4_addresses.write();
+        let index = addresses.iter().position(|cidr| cidr.address == address).ok_or(KernelError::NotFound)?;
+        addresses.remove(index);
+        Ok(())
+    }
+
+    pub fn has_ipv4_address(&self, address: Ipv4Address) -> bool {
+        self.ipv4_addresses.read().iter().any(|cidr| cidr.address == address)
+    }
+
+    /// Address to send from to `destination`: the first one on its network,
+    /// or else the first one
+    pub fn ipv4_source_for(&self, destination: Ipv4Address) -> Option<Ipv4Address> {
+        let addresses = self.ipv4_addresses.read();
+        addresses
+            .iter()
+            .find(|cidr| cidr.contains(destination))
+            .or(addresses.first())
+            .map(|cidr| cidr.address)
+    }
+
+    pub fn ipv6_addresses(&self) -> Vec<Ipv6Cidr> {
+        self.ipv6_addresses.read().clone()
+    }
+
+    /// Add an IPv6 address; fails with `Exists` if the interface has it
+    pub fn add_ipv6_address(&self, cidr: Ipv6Cidr) -> Result<(), KernelError> {
+        let mut addresses = self.ipv6_addresses.write();
+        if addresses.iter().any(|existing| existing.address == cidr.address) {
+            return Err(KernelError::Exists);
+        }
+        addresses.push(cidr);
+        Ok(())
+    }
+
+    pub fn remove_ipv6_address(&self, address: Ipv6Address) -> Result<(), KernelError> {
+        let mut addresses = self.ipv6_addresses.write();
+        let index = addresses.iter().position(|cidr| cidr.address == address).ok_or(KernelError::NotFound)?;
+        addresses.remove(index);
+        Ok(())
+    }
+
+    pub fn has_ipv6_address(&self, address: Ipv6Address) -> bool {
+        self.ipv6_addresses.read().iter().any(|cidr| cidr.address == address)
+    }
+
+    /// Address to send from to `destination`: the link-local one within the
+    /// link, else the first global one on its network, or else the first
+    /// global one
+    pub fn ipv6_source_for(&self, destination: Ipv6Address) -> Option<Ipv6Address> {
+        let addresses = self.ipv6_addresses.read();
+        // Multicast scopes 1 and 2 are the node and the link
+        let link_scope = destination.is_link_local() || (destination.is_multicast() && destination.0[1] & 0x0F <= 2);
+        if link_scope {
+            return addresses.iter().find(|cidr| cidr.address.is_link_local()).map(|cidr| cidr.address);
+        }
+        let mut global = addresses.iter().filter(|cidr| !cidr.address.is_link_local());
+        global.clone().find(|cidr| cidr.contains(destination)).or_else(|| global.next()).map(|cidr| cidr.address)
+    }
+
+    /// Have `handler` take the frames the interface receives
+    ///
+    /// Fails with `Busy` if another handler has them.
+    pub fn set_rx_handler(&self, handler: Arc<dyn RxHandler>) -> Result<(), KernelError> {
+        let mut current = self.rx_handler.write();
+        if current.is_some() {
+            return Err(KernelError::Busy);
+        }
+        *current = Some(handler);
+        Ok(())
+    }
+
+    /// Give the received frames back to the pipeline
+    pub fn clear_rx_handler(&self) {
+        *self.rx_handler.write() = None;
+    }
+
+    /// Number of received packets the pipeline dropped
+    pub fn dropped(&self) -> u64 {
+        self.stats.snapshot().rx_dropped
+    }
+
+    /// The counters of the interface
+    pub fn stats(&self) -> InterfaceStatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    /// Send the bytes of `packet` as they are, with the offloads its
+    /// metadata asks for
+    pub fn transmit(&self, packet: NetworkPacket) -> Result<(), KernelError> {
+        if !self.is_up() {
+            self.stats.record_tx_dropped();
+            return Err(KernelError::NetworkUnreachable);
+        }
+        let link_len = packet.headers_len();
+        let checksum = match (packet.metadata_int(META_CHECKSUM_START), packet.metadata_int(META_CHECKSUM_OFFSET)) {
+            (Some(start), Some(offset)) => PacketChecksum::Partial { start: link_len + start as usize, offset: offset as usize },
+            _ => PacketChecksum::None,
+        };
+        let segmentation = match (packet.metadata_int(META_SEGMENT_SIZE), packet.metadata_int(META_SEGMENT_HEADER_LEN)) {
+            (Some(size), Some(header_len)) => Some(PacketSegmentation {
+                segment_size: size as usize,
+                header_len: link_len + header_len as usize,
+            }),
+            _ => None,
+        };
+        let buffer = packet.into_buffer();
+        capture::tap_buffer(self, PacketDirection::Outgoing, &buffer);
+        let len = buffer.len();
+        let mut device_packet = DevicePacket::with_buffer(buffer);
+        device_packet.checksum = checksum;
+        device_packet.segmentation = segmentation;
+        let result = self.device.send_packet(device_packet);
+        self.stats.record_tx(len, result.is_ok());
+        result.map_err(KernelError::from_message)
+    }
+}
+
+/// Set when a driver has received packets, to wake the `netrx` thread
+struct RxSignal {
+    pending: AtomicBool,
+    waker: Waker,
+}
+
+impl RxSignal {
+    fn take(&self) -> bool {
+        self.pending.swap(false, Ordering::AcqRel)
+    }
+}
+
+impl PollOps for RxSignal {
+    fn poll_events(&self) -> u32 {
+        if self.pending.load(Ordering::Acquire) { POLLIN } else { 0 }
+    }
+
+    fn poll_register(&self, task_id: usize) -> bool {
+        self.waker.register(task_id);
+        true
+    }
+
+    fn poll_unregister(&self, task_id: usize) {
+        self.waker.unregister(task_id);
+    }
+}
+
+pub struct NetworkManager {
+    interfaces: RwLock<BTreeMap<InterfaceId, Arc<NetworkInterface>>>,
+    next_interface_id: AtomicU32,
+    pipeline: FlexiblePipeline,
+    rx_signal: RxSignal,
+    timers: RwLock<Vec<NetworkTimer>>,
+}
+
+static NETWORK_MANAGER: NetworkManager = NetworkManager::new();
+
+/// The network manager of the system
+pub fn get_network_manager() -> &'static NetworkManager {
+    &NETWORK_MANAGER
+}
+
+impl NetworkManager {
+    pub const fn new() -> Self {
+        Self {
+            interfaces: RwLock::new(BTreeMap::new()),
+            next_interface_id: AtomicU32::new(0),
+            pipeline: FlexiblePipeline::new(),
+            rx_signal: RxSignal {
+                pending: AtomicBool::new(false),
+                waker: Waker::new_interruptible("netrx"),
+            },
+            timers: RwLock::new(Vec::new()),
+        }
+    }
+
+    pub fn pipeline(&self) -> &FlexiblePipeline {
+        &self.pipeline
+    }
+
+    /// Attach `device` as the interface `name`
+    ///
+    /// Frames received on it enter the pipeline at `entry_stage` with key 0.
+    /// Fails with `Exists` if the name is taken or the device is attached.
+    pub fn attach_device(
+        &self,
+        name: &str,
+        device: Arc<dyn NetworkDevice>,
+        device_id: Option<usize>,
+        entry_stage: &'static str,
+    ) -> Result<Arc<NetworkInterface>, KernelError> {
+        let mut interfaces = self.interfaces.write();
+        if interfaces.values().any(|interface| interface.name == name || Arc::ptr_eq(&interface.device, &device)) {
+            return Err(KernelError::Exists);
+        }
+        let interface = Arc::new(NetworkInterface {
+            id: self.next_interface_id.fetch_add(1, Ordering::Relaxed),
+            name: name.to_string(),
+            device,
+            device_id,
+            entry_stage,
+            ipv4_addresses: RwLock::new(Vec::new()),
+            ipv6_addresses: RwLock::new(Vec::new()),
+            up: AtomicBool::new(true),
+            stats: InterfaceStats::new(),
+            rx_handler: RwLock::new(None),
+            namespace: AtomicU32::new(INIT_NET_NS),
+        });
+        interfaces.insert(interface.id, interface.clone());
+        Ok(interface)
+    }
+
+    /// Detach the interface `id`; packets it received are no longer taken
+    pub fn detach(&self, id: InterfaceId) -> Result<Arc<NetworkInterface>, KernelError> {
+        self.interfaces.write().remove(&id).ok_or(KernelError::NotFound)
+    }
+
+    /// Detach the interfaces of the device `device_id` of the device manager
+    pub fn detach_device(&self, device_id: usize) -> usize {
+        let mut interfaces = self.interfaces.write();
+        let before = interfaces.len();
+        interfaces.retain(|_, interface| interface.device_id != Some(device_id));
+        before - interfaces.len()
+    }
+
+    pub fn interface(&self, id: InterfaceId) -> Option<Arc<NetworkInterface>> {
+        self.interfaces.read().get(&id).cloned()
+    }
+
+    pub fn interface_by_name(&self, name: &str) -> Option<Arc<NetworkInterface>> {
+        self.interfaces.read().values().find(|interface| interface.name == name).cloned()
+    }
+
+    pub fn interfaces(&self) -> Vec<Arc<NetworkInterface>> {
+        self.interfaces.read().values().cloned().collect()
+    }
+
+    /// The interfaces of the namespace `namespace`
+    pub fn interfaces_in(&self, namespace: NetNamespaceId) -> Vec<Arc<NetworkInterface>> {
+        self.interfaces.read().values().filter(|interface| interface.namespace() == namespace).cloned().collect()
+    }
+
+    /// The interface `name` if it is in the namespace `namespace`
+    pub fn interface_by_name_in(&self, namespace: NetNamespaceId, name: &str) -> Option<Arc<NetworkInterface>> {
+        self.interface_by_name(name).filter(|interface| interface.namespace() == namespace)
+    }
+
+    /// Run a frame received on `interface` through the pipeline
+    pub fn receive(&self, interface: &Arc<NetworkInterface>, data: impl Into<PacketBuffer>) -> PacketFate {
+        self.dispatch(interface, NetworkPacket::incoming(data, interface.clone()))
+    }
+
+    fn dispatch(&self, interface: &Arc<NetworkInterface>, mut packet: NetworkPacket) -> PacketFate {
+        interface.stats.record_rx(packet.data().len());
+        let fate = if interface.is_up() {
+            capture::tap(interface, PacketDirection::Incoming, packet.data());
+            let handler = interface.rx_handler.read().clone();
+            match handler {
+                Some(handler) => handler.handle(self, interface, packet),
+                None => self.pipeline.process(&mut packet, interface.entry_stage, 0),
+            }
+        } else {
+            PacketFate::Dropped("Interface down")
+        };
+        if let PacketFate::Dropped(_) = fate {
+            interface.stats.record_rx_dropped();
+        }
+        fate
+    }
+
+    /// Send `packet` on the interface `id`
+    pub fn transmit(&self, id: InterfaceId, packet: NetworkPacket) -> Result<(), KernelError> {
+        self.interface(id).ok_or(KernelError::NotFound)?.transmit(packet)
+    }
+
+    /// Take the packets received on every interface through the pipeline
+    ///
+    /// Returns the number of packets taken.
+    pub fn poll(&self) -> usize {
+        let mut received = 0;
+        for interface in self.interfaces() {
+            let Ok(packets) = interface.device.receive_packets() else {
+                interface.stats.record_rx_error();
+                continue;
+            };
+            for device_packet in packets {
+                let mut packet = NetworkPacket::incoming(device_packet.buffer, interface.clone());
+                if device_packet.checksum != PacketChecksum::None {
+                    packet.set_metadata(META_CHECKSUM_VERIFIED, MetadataValue::Int(1));
+                }
+                self.dispatch(&interface, packet);
+                received += 1;
+            }
+        }
+        received
+    }
+
+    /// Have `timer` called by the `netrx` thread after every poll
+    pub fn register_timer(&self, timer: NetworkTimer) {
+        self.timers.write().push(timer);
+    }
+
+    /// Call the registered timers with the tick `now`
+    pub fn run_timers(&self, now: u64) {
+        let timers = self.timers.read().clone();
+        for timer in timers {
+            timer(self, now);
+        }
+    }
+
+    /// Report packets received by a driver, so that they are taken at once
+    pub fn notify_rx(&self) {
+        self.rx_signal.pending.store(true, Ordering::Release);
+        self.rx_signal.waker.wake_all();
+    }
+}
+
+/// Body of the `netrx` kernel thread
+fn rx_thread() {
+    let manager = get_network_manager();
+    let sources: [&dyn PollOps; 1] = [&manager.rx_signal];
+    while !kthread::should_stop() {
+        manager.poll();
+        manager.run_timers(get_tick());
+        wait_for(&sources, Some(ms_to_ticks(RX_POLL_INTERVAL_MS)), || manager.rx_signal.take());
+    }
+}
+
+/// Attaches hot-plugged network devices and detaches removed ones
+struct NetworkHotplugListener {
+    /// Number of `ethN` names given out
+    next_index: AtomicU32,
+}
+
+impl NetworkHotplugListener {
+    fn attach(&self, device_id: usize, device: Arc<dyn NetworkDevice>) {
+        let name = format!("eth{}", self.next_index.fetch_add(1, Ordering::Relaxed));
+        match get_network_manager().attach_device(&name, device, Some(device_id), DEFAULT_ENTRY_STAGE) {
+            Ok(_) => crate::early_println!("[network] Attached device {} as {}", device_id, name),
+            Err(error) => crate::early_println!("[network] Cannot attach device {}: {:?}", device_id, error),
+        }
+    }
+}
+
+impl DeviceEventListener for NetworkHotplugListener {
+    fn on_device_event(&self, event: &dyn DeviceEvent) {
+        let Some(event) = event.as_any().downcast_ref::<DeviceHotplugEvent>() else {
+            return;
+        };
+        if event.device.device_type() != DeviceType::Network {
+            return;
+        }
+        match event.action {
+            HotplugAction::Added => {
+                if let Some(device) = event.device.clone().into_network_device() {
+                    self.attach(event.device_id, device);
+                }
+            }
+            HotplugAction::Removed => {
+                get_network_manager().detach_device(event.device_id);
+            }
+            HotplugAction::Changed => {}
+        }
+    }
+
+    fn interested_in(&self, event_type: &str) -> bool {
+        event_type == "hotplug"
+    }
+}
+
+static NETWORK_HOTPLUG_LISTENER: Once<Arc<NetworkHotplugListener>> = Once::new();
+
+/// Attach the network devices found so far, follow hotplug for the others
+/// and start taking received packets
+fn init_network() {
+    if let Err(error) = super::register_protocols(get_network_manager()) {
+        crate::early_println!("[network] Cannot register the protocols: {:?}", error);
+    }
+    let listener = NETWORK_HOTPLUG_LISTENER.call_once(|| Arc::new(NetworkHotplugListener { next_index: AtomicU32::new(0) }));
+    let device_manager = DeviceManager::get_manager();
+    for (device_id, device) in device_manager.get_devices_by_type(DeviceType::Network) {
+        if let Some(device) = device.into_network_device() {
+            listener.attach(device_id, device);
+        }
+    }
+    let weak = Arc::downgrade(listener);
+    device_manager.register_hotplug_listener(weak);
+
+    kthread::spawn("netrx", rx_thread);
+}
+
+late_initcall!(init_network);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::network::GenericNetworkDevice;
+    use crate::network::pipeline::{PacketProcessor, ProcessResult};
+    use alloc::vec;
+
+    struct Sink;
+
+    impl PacketProcessor for Sink {
+        fn name(&self) -> &'static str {
+            "sink"
+        }
+
+        fn process(&self, _packet: &mut NetworkPacket) -> ProcessResult {
+            ProcessResult::Consumed
+        }
+    }
+
+    #[test_case]
+    fn test_manager_attach_and_receive() {
+        let manager = NetworkManager::new();
+        let mut device = GenericNetworkDevice::new("test0");
+        device.init_network().unwrap();
+        let device: Arc<dyn NetworkDevice> = Arc::new(device);
+
+        let interface = manager.attach_device("test0", device.clone(), Some(42), "link").unwrap();
+        assert_eq!(manager.attach_device("test1", device.clone(), None, "link").err(), Some(KernelError::Exists));
+        assert!(manager.interface_by_name("test0").is_some());
+
+        // Without the stage the frame is dropped and counted
+        assert!(matches!(manager.receive(&interface, vec![1, 2, 3]), PacketFate::Dropped(_)));
+        assert_eq!(interface.dropped(), 1);
+        manager.pipeline().add_stage("link").unwrap();
+        manager.pipeline().register_processor("link", 0, Arc::new(Sink)).unwrap();
+        assert_eq!(manager.receive(&interface, vec![1, 2, 3]), PacketFate::Consumed);
+
+        manager.transmit(interface.id(), NetworkPacket::outgoing(vec![7; 4])).unwrap();
+        assert_eq!(device.get_stats().tx_bytes, 4);
+
+        assert_eq!(manager.detach_device(42), 1);
+        assert!(manager.interface(interface.id()).is_none());
+        assert_eq!(manager.detach(interface.id()).err(), Some(KernelError::NotFound));
+    }
+}
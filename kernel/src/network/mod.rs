@@ -0,0 +1,101 @@
+//! Network stack
+//!
+//! The stack is built around a protocol-agnostic core: the
+//! [`NetworkManager`](manager::NetworkManager) attaches network devices as
+//! interfaces and runs every packet they receive through a
+//! [`FlexiblePipeline`](pipeline::FlexiblePipeline). The pipeline knows no
+//! protocols itself; it is a set of named stages ("ethernet", "ipv4", ...)
+//! to which protocol processors are registered under a key, such as an
+//! EtherType or an IP protocol number. A processor consumes its header from
+//! the packet and names the stage and key of the next processor, so
+//! protocols are added and removed without the core knowing about them.
+//!
+//! - `buffer`: Shared packet buffers with headroom and fragments
+//! - `packet`: Packets with a header cursor and metadata shared by stages
+//! - `pipeline`: Stages, processor registration and dispatch
+//! - `manager`: Interfaces, device attach/detach and packet reception
+//! - `address`: IPv4 and IPv6 addresses, networks and endpoints
+//! - `ethernet`: Ethernet II framing and EtherType demultiplexing
+//! - `arp`: Address resolution and the neighbor cache
+//! - `checksum`: The internet checksum and the pseudo-headers of both IP
+//!   versions
+//! - `route`: The IPv4 routing table
+//! - `ipv4`: IPv4 receive, send, fragmentation and reassembly
+//! - `icmp`: Echo, error generation and echo endpoints
+//! - `ipv6`: IPv6 receive and send over extension headers
+//! - `ndp`: Neighbor discovery and stateless address autoconfiguration
+//! - `icmpv6`: ICMP for IPv6: echo and error generation
+//! - `ip`: Sending for the dual-stack transports by IP version
+//! - `udp`: UDP port demultiplexing and datagram sockets
+//! - `tcp`: TCP connections and stream sockets
+//! - `dhcp`: Automatic configuration of interfaces by DHCP
+//! - `firewall`: Filtering of IPv4 datagrams at the prerouting, input and
+//!   output hooks
+//! - `capture`: The tap on every interface, packet sockets and capture
+//!   filters
+//! - `raw`: Raw IP sockets
+//! - `netconfig`: The control object through which interfaces, addresses,
+//!   routes and neighbors are listed and changed
+//! - `syscall`: System calls of the network configuration
+//! - `stats`: Counters of the interfaces and protocols, published in procfs
+//! - `veth`: Virtual ethernet pairs
+//! - `bridge`: The software bridge and its forwarding database
+//! - `tap`: Interfaces whose frames are read and written through a
+//!   character device
+//! - `namespace`: Network namespaces: the interfaces, routes and sockets
+//!   each task sees
+
+pub mod buffer;
+pub mod packet;
+pub mod pipeline;
+pub mod manager;
+pub mod address;
+pub mod ethernet;
+pub mod arp;
+pub mod checksum;
+pub mod route;
+pub mod ipv4;
+pub mod icmp;
+pub mod ipv6;
+pub mod ndp;
+pub mod icmpv6;
+pub mod ip;
+pub mod udp;
+pub mod tcp;
+pub mod dhcp;
+pub mod firewall;
+pub mod capture;
+pub mod raw;
+pub mod netconfig;
+pub mod syscall;
+pub mod stats;
+pub mod veth;
+pub mod bridge;
+pub mod tap;
+pub mod namespace;
+
+use crate::abi::error::KernelError;
+
+pub use address::{IpAddress, IpEndpoint, IpFamily, Ipv4Address, Ipv4Cidr, Ipv6Address, Ipv6Cidr};
+pub use manager::{get_network_manager, NetworkInterface, NetworkManager};
+pub use packet::{MetadataValue, NetworkPacket, PacketDirection};
+pub use pipeline::{FlexiblePipeline, PacketFate, PacketProcessor, ProcessResult};
+
+/// Identifies an attached interface
+pub type InterfaceId = u32;
+
+/// Register the protocols of the stack with the pipeline of `manager`
+fn register_protocols(manager: &NetworkManager) -> Result<(), KernelError> {
+    ethernet::register(manager)?;
+    arp::register(manager)?;
+    ipv4::register(manager)?;
+    icmp::register(manager)?;
+    ipv6::register(manager)?;
+    icmpv6::register(manager)?;
+    ndp::register(manager)?;
+    udp::register(manager)?;
+    tcp::register(manager)?;
+    dhcp::register(manager)?;
+    bridge::register(manager)?;
+    Ok(())
+}
@@ -0,0 +1,157 @@
+//! Network namespaces
+//!
+//! A network namespace is a view of the stack of its own: the interfaces
+//! in it, the routes through them and the sockets opened in it. Every task
+//! refers to one namespace; tasks start in the initial namespace and share
+//! their parent's, and a child cloned with `CloneFlagsDef::NewNet` gets a
+//! new one without interfaces, which lets each container carry a network
+//! of its own.
+//!
+//! The protocols, their tables and the interfaces stay those of the
+//! system; a namespace is the tag they are kept apart by:
+//!
+//! - an interface belongs to one namespace, the initial one when it is
+//!   attached, and is moved to another by [`move_interface`], losing its
+//!   addresses and routes on the way. A veth pair with its ends in two
+//!   namespaces links them.
+//! - a socket belongs to the namespace of the task that opened it: it takes
+//!   only the packets received on the interfaces of that namespace, binds
+//!   ports apart from the sockets of others and sends by the routes of its
+//!   interfaces.
+//! - the network configuration shows and changes the interfaces of the
+//!   namespace of the task that opened it, and `net/dev` in procfs those of
+//!   the reader's.
+//!
+//! Interface names are unique across namespaces. The firewall, the
+//! protocol counters and the DHCP client are shared; the DHCP client of an
+//! interface stops when it moves. The interfaces of a namespace nobody
+//! refers to any more go back to the initial one.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use alloc::sync::Arc;
+use spin::Once;
+
+use crate::abi::error::KernelError;
+use crate::task::mytask;
+
+use super::manager::{get_network_manager, NetworkInterface};
+use super::route::routing_table;
+use super::{arp, bridge, dhcp, ipv4, ndp};
+
+/// Identifies a network namespace
+pub type NetNamespaceId = u32;
+
+/// The namespace tasks start in and interfaces are attached to
+pub const INIT_NET_NS: NetNamespaceId = 0;
+
+static NEXT_NAMESPACE_ID: AtomicU32 = AtomicU32::new(INIT_NET_NS + 1);
+
+/// A network namespace
+#[derive(Debug)]
+pub struct NetNamespace {
+    id: NetNamespaceId,
+}
+
+impl NetNamespace {
+    /// A new namespace without interfaces
+    pub fn new() -> Self {
+        NetNamespace { id: NEXT_NAMESPACE_ID.fetch_add(1, Ordering::Relaxed) }
+    }
+
+    pub fn id(&self) -> NetNamespaceId {
+        self.id
+    }
+}
+
+impl Drop for NetNamespace {
+    fn drop(&mut self) {
+        for interface in get_network_manager().interfaces_in(self.id) {
+            let _ = move_interface(&interface, INIT_NET_NS);
+        }
+    }
+}
+
+static INIT_NET_NS_REF: Once<Arc<NetNamespace>> = Once::new();
+
+/// The namespace tasks start in
+pub fn init_net_ns() -> Arc<NetNamespace> {
+    INIT_NET_NS_REF.call_once(|| Arc::new(NetNamespace { id: INIT_NET_NS })).clone()
+}
+
+/// The namespace of the current task; the initial one outside of tasks
+pub fn current() -> Arc<NetNamespace> {
+    mytask().map_or_else(init_net_ns, |task| task.net_ns.clone())
+}
+
+/// Move `interface` to the namespace `namespace`
+///
+/// The interface is brought down and loses its addresses, routes,
+/// neighbors and bridge. Fails with `NotSupported` for a bridge, whose
+/// ports would be left behind.
+pub fn move_interface(interface: &Arc<NetworkInterface>, namespace: NetNamespaceId) -> Result<(), KernelError> {
+    if interface.namespace() == namespace {
+        return Ok(());
+    }
+    if bridge::is_bridge(interface) {
+        return Err(KernelError::NotSupported);
+    }
+    interface.set_up(false);
+    bridge::release(interface);
+    let _ = dhcp::stop(interface);
+    for cidr in interface.ipv4_addresses() {
+        let _ = ipv4::remove_address(interface, cidr.address);
+    }
+    for cidr in interface.ipv6_addresses() {
+        let _ = interface.remove_ipv6_address(cidr.address);
+    }
+    routing_table().flush_interface(interface.id());
+    ndp::forget_interface(interface.id());
+    arp::neighbor_cache().flush_interface(interface.id());
+    ndp::neighbor_cache().flush_interface(interface.id());
+    interface.set_namespace(namespace);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network::address::{IpAddress, Ipv4Address, Ipv4Cidr};
+    use crate::network::ip::{self, SendOptions};
+    use crate::network::veth;
+
+    #[test_case]
+    fn test_net_namespace_move() {
+        let namespace = Arc::new(NetNamespace::new());
+        assert_ne!(namespace.id(), INIT_NET_NS);
+        let (host, guest) = veth::create_pair("nsv0", "nsv1").unwrap();
+        ipv4::add_address(&host, Ipv4Cidr::new(Ipv4Address::new(203, 0, 113, 1), 24).unwrap()).unwrap();
+        ipv4::add_address(&guest, Ipv4Cidr::new(Ipv4Address::new(203, 0, 113, 2), 24).unwrap()).unwrap();
+
+        move_interface(&guest, namespace.id()).unwrap();
+        assert_eq!(guest.namespace(), namespace.id());
+        assert!(!guest.is_up() && guest.ipv4_addresses().is_empty());
+        assert!(routing_table().routes().iter().all(|route| route.interface != guest.id()));
+        let manager = get_network_manager();
+        assert!(manager.interfaces_in(INIT_NET_NS).iter().all(|interface| interface.id() != guest.id()));
+        assert!(manager.interface_by_name_in(namespace.id(), "nsv1").is_some());
+        assert!(manager.interface_by_name_in(namespace.id(), "nsv0").is_none());
+
+        // Each namespace routes by its own interfaces
+        ipv4::add_address(&guest, Ipv4Cidr::new(Ipv4Address::new(203, 0, 113, 2), 24).unwrap()).unwrap();
+        let destination = IpAddress::V4(Ipv4Address::new(203, 0, 113, 9));
+        let inside = SendOptions { namespace: namespace.id(), ..SendOptions::default() };
+        assert_eq!(ip::interface_for(destination, &inside).unwrap().map(|interface| interface.id()), Some(guest.id()));
+        let outside = SendOptions::default();
+        assert_eq!(ip::interface_for(destination, &outside).unwrap().map(|interface| interface.id()), Some(host.id()));
+        let local = IpAddress::V4(Ipv4Address::new(203, 0, 113, 2));
+        assert!(ip::interface_for(local, &inside).unwrap().is_none());
+        assert!(ip::interface_for(local, &outside).unwrap().is_some());
+
+        // The interfaces of a namespace nobody refers to come back
+        drop(namespace);
+        assert_eq!(guest.namespace(), INIT_NET_NS);
+        assert!(guest.ipv4_addresses().is_empty());
+        veth::delete(&host).unwrap();
+    }
+}
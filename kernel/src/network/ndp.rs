@@ -0,0 +1,474 @@
+//! Neighbor discovery (RFC 4861) and stateless address autoconfiguration
+//! (RFC 4862)
+//!
+//! Neighbor discovery is to IPv6 what ARP is to IPv4. IPv6 packets are sent
+//! to their next hop with [`send_ipv6`], which resolves the hardware
+//! address in a [`NeighborCache`] of IPv6 neighbors: neighbor
+//! solicitations go to the solicited-node group of the address, and the
+//! packets wait in the incomplete entry until an advertisement comes, on
+//! the timing of ARP. Solicitations for the addresses of the host are
+//! answered, and the neighbors they come from learned.
+//!
+//! Every Ethernet interface gets the link-local address fe80::/64 with the
+//! interface identifier formed from its hardware address (modified EUI-64)
+//! when the network timer first sees it, and solicits routers
+//! [`MAX_ROUTER_SOLICITATIONS`] times. Router advertisements fill the
+//! default router list and the prefix list; a prefix with the autonomous
+//! flag gives the interface an address of the same interface identifier in
+//! it. Routers, prefixes and their addresses go when their lifetime runs
+//! out. `noslaac` on the kernel command line leaves interfaces alone;
+//! [`configure`] still sets up one.
+//!
+//! Addresses are used at once, without duplicate address detection, and
+//! redirects and the MTU option are ignored.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::abi::error::KernelError;
+use crate::device::network::MacAddress;
+use crate::timer::{get_tick, ms_to_ticks};
+
+use super::address::{Ipv6Address, Ipv6Cidr};
+use super::arp::{NeighborCache, Resolution};
+use super::ethernet::{self, ETHERNET_STAGE, ETHERTYPE_IPV6};
+use super::icmpv6;
+use super::ip::SendOptions;
+use super::ipv6::{self, META_HOP_LIMIT};
+use super::manager::{NetworkInterface, NetworkManager};
+use super::packet::NetworkPacket;
+use super::pipeline::ProcessResult;
+use super::InterfaceId;
+
+/// Messages, types of ICMPv6
+pub const NDP_ROUTER_SOLICITATION: u8 = 133;
+pub const NDP_ROUTER_ADVERTISEMENT: u8 = 134;
+pub const NDP_NEIGHBOR_SOLICITATION: u8 = 135;
+pub const NDP_NEIGHBOR_ADVERTISEMENT: u8 = 136;
+pub const NDP_REDIRECT: u8 = 137;
+
+/// Options
+pub const OPTION_SOURCE_LINK_ADDRESS: u8 = 1;
+pub const OPTION_TARGET_LINK_ADDRESS: u8 = 2;
+pub const OPTION_PREFIX_INFORMATION: u8 = 3;
+
+/// Flags of neighbor advertisements
+const ADVERT_SOLICITED: u8 = 0x40;
+const ADVERT_OVERRIDE: u8 = 0x20;
+/// Flags of prefix information
+const PREFIX_ON_LINK: u8 = 0x80;
+const PREFIX_AUTONOMOUS: u8 = 0x40;
+/// A lifetime that does not run out
+const INFINITE_LIFETIME: u32 = u32::MAX;
+
+/// Messages are sent, and only taken, with the largest hop limit, so that
+/// they cannot have come from off the link
+pub const NDP_HOP_LIMIT: u8 = 255;
+
+/// Router solicitations sent for an interface that hears from no router
+pub const MAX_ROUTER_SOLICITATIONS: u32 = 3;
+pub const ROUTER_SOLICITATION_INTERVAL_MS: u64 = 4_000;
+
+/// A router of the default router list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Router {
+    pub interface: InterfaceId,
+    pub address: Ipv6Address,
+    expires: u64,
+}
+
+/// A prefix of the prefix list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Prefix {
+    pub interface: InterfaceId,
+    pub prefix: Ipv6Cidr,
+    /// Addresses in it are on the link
+    pub on_link: bool,
+    /// The address formed in it
+    pub address: Option<Ipv6Address>,
+    expires: u64,
+}
+
+/// Router solicitation of an interface
+struct Solicitation {
+    sent: u32,
+    next: u64,
+}
+
+struct NdpState {
+    routers: Vec<Router>,
+    prefixes: Vec<Prefix>,
+    solicitations: BTreeMap<InterfaceId, Solicitation>,
+}
+
+static STATE: Mutex<NdpState> = Mutex::new(NdpState {
+    routers: Vec::new(),
+    prefixes: Vec::new(),
+    solicitations: BTreeMap::new(),
+});
+
+static NEIGHBOR_CACHE: NeighborCache<Ipv6Address> = NeighborCache::new();
+
+/// Whether interfaces are configured when they come
+static AUTO_CONFIGURE: AtomicBool = AtomicBool::new(true);
+/// Interfaces the network timer has seen
+static SEEN: Mutex<Vec<InterfaceId>> = Mutex::new(Vec::new());
+
+/// The cache of IPv6 neighbors
+pub fn neighbor_cache() -> &'static NeighborCache<Ipv6Address> {
+    &NEIGHBOR_CACHE
+}
+
+/// Apply neighbor discovery options from the kernel command line
+pub fn parse_cmdline(cmdline: &str) {
+    if cmdline.split_whitespace().any(|arg| arg == "noslaac") {
+        AUTO_CONFIGURE.store(false, Ordering::Relaxed);
+    }
+}
+
+/// The interface identifier formed from `mac` (RFC 4291, appendix A)
+pub fn interface_id(mac: MacAddress) -> [u8; 8] {
+    let mac = mac.as_bytes();
+    [mac[0] ^ 0x02, mac[1], mac[2], 0xFF, 0xFE, mac[3], mac[4], mac[5]]
+}
+
+/// The hardware address IPv6 multicast `address` maps to (RFC 2464)
+fn multicast_mac(address: Ipv6Address) -> MacAddress {
+    let bytes = address.octets();
+    MacAddress::new([0x33, 0x33, bytes[12], bytes[13], bytes[14], bytes[15]])
+}
+
+/// The default routers
+pub fn routers() -> Vec<Router> {
+    STATE.lock().routers.clone()
+}
+
+/// The prefixes learned from routers
+pub fn prefixes() -> Vec<Prefix> {
+    STATE.lock().prefixes.clone()
+}
+
+/// Whether a router advertised `address` on the link of `interface`
+pub fn on_link(interface: InterfaceId, address: Ipv6Address) -> bool {
+    STATE
+        .lock()
+        .prefixes
+        .iter()
+        .any(|prefix| prefix.interface == interface && prefix.on_link && prefix.prefix.contains(address))
+}
+
+/// The first default router, of `interface` if it is given
+pub fn default_router(interface: Option<InterfaceId>) -> Option<(InterfaceId, Ipv6Address)> {
+    STATE
+        .lock()
+        .routers
+        .iter()
+        .find(|router| interface.map_or(true, |id| router.interface == id))
+        .map(|router| (router.interface, router.address))
+}
+
+/// Forget the routers, prefixes and solicitation of `interface`; it is
+/// configured again by the next timer, as a new interface
+pub fn forget_interface(interface: InterfaceId) {
+    let mut state = STATE.lock();
+    state.routers.retain(|router| router.interface != interface);
+    state.prefixes.retain(|prefix| prefix.interface != interface);
+    state.solicitations.remove(&interface);
+    drop(state);
+    SEEN.lock().retain(|id| *id != interface);
+}
+
+/// Options of a neighbor discovery message
+fn options(mut bytes: &[u8]) -> Option<Vec<(u8, &[u8])>> {
+    let mut options = Vec::new();
+    while bytes.len() >= 2 {
+        let len = bytes[1] as usize * 8;
+        if len == 0 || len > bytes.len() {
+            return None;
+        }
+        options.push((bytes[0], &bytes[2..len]));
+        bytes = &bytes[len..];
+    }
+    Some(options)
+}
+
+fn link_address_option(kind: u8, mac: MacAddress) -> [u8; 8] {
+    let mut option = [kind, 1, 0, 0, 0, 0, 0, 0];
+    option[2..].copy_from_slice(mac.as_bytes());
+    option
+}
+
+/// The hardware address in the option of `kind`
+fn link_address(options: &[(u8, &[u8])], kind: u8) -> Option<MacAddress> {
+    options
+        .iter()
+        .find(|(option, _)| *option == kind)
+        .and_then(|(_, data)| MacAddress::from_slice(data.get(..6)?).ok())
+}
+
+fn ndp_options(interface: &Arc<NetworkInterface>) -> SendOptions {
+    SendOptions { interface: Some(interface.clone()), ttl: NDP_HOP_LIMIT, ..SendOptions::default() }
+}
+
+/// Send a neighbor solicitation for `target` to its solicited-node group
+pub fn send_solicitation(interface: &Arc<NetworkInterface>, target: Ipv6Address) -> Result<(), KernelError> {
+    let mut body = target.octets().to_vec();
+    body.extend_from_slice(&link_address_option(OPTION_SOURCE_LINK_ADDRESS, interface.mac_address()?));
+    let destination = target.solicited_node();
+    icmpv6::send_message(destination, NDP_NEIGHBOR_SOLICITATION, 0, [0; 4], &body, &ndp_options(interface))
+}
+
+fn send_advertisement(
+    interface: &Arc<NetworkInterface>,
+    destination: Ipv6Address,
+    target: Ipv6Address,
+    flags: u8,
+) -> Result<(), KernelError> {
+    let mut body = target.octets().to_vec();
+    body.extend_from_slice(&link_address_option(OPTION_TARGET_LINK_ADDRESS, interface.mac_address()?));
+    let options = SendOptions { source: Some(target.into()), ..ndp_options(interface) };
+    icmpv6::send_message(destination, NDP_NEIGHBOR_ADVERTISEMENT, 0, [flags, 0, 0, 0], &body, &options)
+}
+
+fn send_router_solicitation(interface: &Arc<NetworkInterface>) -> Result<(), KernelError> {
+    let options = ndp_options(interface);
+    let source = ipv6::source_for(Ipv6Address::ALL_ROUTERS, &options)?;
+    // There is no link address to give from the unspecified address
+    let body = match source.is_unspecified() {
+        true => Vec::new(),
+        false => link_address_option(OPTION_SOURCE_LINK_ADDRESS, interface.mac_address()?).to_vec(),
+    };
+    icmpv6::send_message(Ipv6Address::ALL_ROUTERS, NDP_ROUTER_SOLICITATION, 0, [0; 4], &body, &options)
+}
+
+/// Send the IPv6 `packet` to `next_hop` on `interface`
+///
+/// If the hardware address of the next hop is not known, the packet is
+/// queued and sent once an advertisement comes in.
+pub fn send_ipv6(interface: &Arc<NetworkInterface>, next_hop: Ipv6Address, packet: NetworkPacket) -> Result<(), KernelError> {
+    if next_hop.is_multicast() {
+        return ethernet::send_frame(interface, multicast_mac(next_hop), ETHERTYPE_IPV6, packet);
+    }
+    match neighbor_cache().resolve(interface.id(), next_hop, packet, get_tick()) {
+        Resolution::Resolved(mac, packet) => ethernet::send_frame(interface, mac, ETHERTYPE_IPV6, packet),
+        Resolution::Queued { request: true } => send_solicitation(interface, next_hop),
+        Resolution::Queued { request: false } => Ok(()),
+    }
+}
+
+/// Record that `address` is at `mac` and send what waited for it
+fn learn(interface: &Arc<NetworkInterface>, address: Ipv6Address, mac: MacAddress, create: bool) {
+    for packet in neighbor_cache().update(interface.id(), address, mac, create, get_tick()) {
+        let _ = ethernet::send_frame(interface, mac, ETHERTYPE_IPV6, packet);
+    }
+}
+
+/// The tick at which a lifetime of `secs` from `now` runs out
+fn expiry(now: u64, secs: u32) -> u64 {
+    match secs {
+        INFINITE_LIFETIME => u64::MAX,
+        secs => now.saturating_add(ms_to_ticks(secs as u64 * 1000)),
+    }
+}
+
+/// Take the prefix information of an advertisement from a router
+fn add_prefix(interface: &Arc<NetworkInterface>, option: &[u8], now: u64) {
+    // Prefix length, flags, valid and preferred lifetimes, reserved, prefix
+    if option.len() < 30 {
+        return;
+    }
+    let (prefix_len, flags) = (option[0], option[1]);
+    let valid = u32::from_be_bytes([option[2], option[3], option[4], option[5]]);
+    let Some(prefix) = Ipv6Address::from_slice(&option[14..30]).and_then(|address| Ipv6Cidr::new(address, prefix_len).ok()) else {
+        return;
+    };
+    let prefix = Ipv6Cidr { address: prefix.network(), ..prefix };
+    if prefix.address.is_link_local() {
+        return;
+    }
+    let mut state = STATE.lock();
+    let existing = state.prefixes.iter().position(|entry| entry.interface == interface.id() && entry.prefix == prefix);
+    if valid == 0 {
+        if let Some(index) = existing {
+            let removed = state.prefixes.remove(index);
+            drop(state);
+            if let Some(address) = removed.address {
+                let _ = interface.remove_ipv6_address(address);
+            }
+        }
+        return;
+    }
+    let expires = expiry(now, valid);
+    if let Some(index) = existing {
+        let entry = &mut state.prefixes[index];
+        entry.expires = expires;
+        entry.on_link |= flags & PREFIX_ON_LINK != 0;
+        return;
+    }
+    // Interface identifiers are 64 bits
+    let address = (flags & PREFIX_AUTONOMOUS != 0 && prefix_len == 64)
+        .then(|| interface.mac_address().ok())
+        .flatten()
+        .map(|mac| Ipv6Address::with_interface_id(prefix.address, interface_id(mac)));
+    state.prefixes.push(Prefix { interface: interface.id(), prefix, on_link: flags & PREFIX_ON_LINK != 0, address, expires });
+    drop(state);
+    if let Some(address) = address {
+        if interface.add_ipv6_address(Ipv6Cidr { address, prefix_len }).is_ok() {
+            crate::early_println!("[ndp] {}: configured {}/{}", interface.name(), address, prefix_len);
+        }
+    }
+}
+
+fn router_advertisement(interface: &Arc<NetworkInterface>, source: Ipv6Address, rest: [u8; 4], body: &[u8]) -> ProcessResult {
+    // Reachable time and retransmission timer come before the options
+    let Some(options) = body.get(8..).and_then(options) else {
+        return ProcessResult::Dropped("Malformed router advertisement");
+    };
+    if !source.is_link_local() {
+        return ProcessResult::Dropped("Router advertisement from off the link");
+    }
+    if let Some(mac) = link_address(&options, OPTION_SOURCE_LINK_ADDRESS) {
+        learn(interface, source, mac, true);
+    }
+    let now = get_tick();
+    let lifetime = u16::from_be_bytes([rest[2], rest[3]]);
+    {
+        let mut state = STATE.lock();
+        state.routers.retain(|router| router.interface != interface.id() || router.address != source);
+        if lifetime != 0 {
+            state.routers.push(Router { interface: interface.id(), address: source, expires: expiry(now, lifetime as u32) });
+        }
+        state.solicitations.remove(&interface.id());
+    }
+    for (_, option) in options.iter().filter(|(kind, _)| *kind == OPTION_PREFIX_INFORMATION) {
+        add_prefix(interface, option, now);
+    }
+    ProcessResult::Consumed
+}
+
+/// Handle the neighbor discovery message in `packet`, whose ICMPv6 header
+/// is consumed
+pub fn handle(packet: &NetworkPacket, icmp_type: u8, code: u8, rest: [u8; 4]) -> ProcessResult {
+    let (Some(interface), Some(source)) = (packet.interface(), ipv6::source(packet)) else {
+        return ProcessResult::Dropped("Neighbor discovery without addresses");
+    };
+    if code != 0 || packet.metadata_int(META_HOP_LIMIT) != Some(NDP_HOP_LIMIT as u64) {
+        return ProcessResult::Dropped("Neighbor discovery from off the link");
+    }
+    let body = packet.payload();
+    match icmp_type {
+        NDP_NEIGHBOR_SOLICITATION | NDP_NEIGHBOR_ADVERTISEMENT => {
+            let (Some(target), Some(options)) = (Ipv6Address::from_slice(body), body.get(16..).and_then(options)) else {
+                return ProcessResult::Dropped("Malformed neighbor discovery message");
+            };
+            if target.is_multicast() {
+                return ProcessResult::Dropped("Malformed neighbor discovery message");
+            }
+            if icmp_type == NDP_NEIGHBOR_ADVERTISEMENT {
+                if let Some(mac) = link_address(&options, OPTION_TARGET_LINK_ADDRESS) {
+                    learn(interface, target, mac, false);
+                }
+                return ProcessResult::Consumed;
+            }
+            if !interface.has_ipv6_address(target) {
+                return ProcessResult::Dropped("Neighbor solicitation for another node");
+            }
+            // A node checking that the address is free has none to answer
+            if source.is_unspecified() {
+                let _ = send_advertisement(interface, Ipv6Address::ALL_NODES, target, ADVERT_OVERRIDE);
+                return ProcessResult::Consumed;
+            }
+            if let Some(mac) = link_address(&options, OPTION_SOURCE_LINK_ADDRESS) {
+                learn(interface, source, mac, true);
+            }
+            let _ = send_advertisement(interface, source, target, ADVERT_SOLICITED | ADVERT_OVERRIDE);
+            ProcessResult::Consumed
+        }
+        NDP_ROUTER_ADVERTISEMENT => router_advertisement(interface, source, rest, body),
+        // Hosts take no part in router solicitation or redirects
+        _ => ProcessResult::Consumed,
+    }
+}
+
+/// Give `interface` its link-local address and start soliciting routers
+///
+/// Fails with `Exists` if it has a link-local address already.
+pub fn configure(interface: &Arc<NetworkInterface>) -> Result<(), KernelError> {
+    if interface.ipv6_addresses().iter().any(|cidr| cidr.address.is_link_local()) {
+        return Err(KernelError::Exists);
+    }
+    let prefix = Ipv6Address::new([0xFE80, 0, 0, 0, 0, 0, 0, 0]);
+    let address = Ipv6Address::with_interface_id(prefix, interface_id(interface.mac_address()?));
+    interface.add_ipv6_address(Ipv6Cidr { address, prefix_len: 64 })?;
+    STATE.lock().solicitations.insert(interface.id(), Solicitation { sent: 0, next: get_tick() });
+    Ok(())
+}
+
+/// Network timer: configure new interfaces, repeat solicitations and age
+/// routers, prefixes and neighbors
+fn tick(manager: &NetworkManager, now: u64) {
+    let interfaces = manager.interfaces();
+    if AUTO_CONFIGURE.load(Ordering::Relaxed) {
+        let mut seen = SEEN.lock();
+        seen.retain(|id| interfaces.iter().any(|interface| interface.id() == *id));
+        for interface in &interfaces {
+            if !seen.contains(&interface.id()) {
+                seen.push(interface.id());
+                if interface.entry_stage() == ETHERNET_STAGE {
+                    let _ = configure(interface);
+                }
+            }
+        }
+    }
+
+    for (id, address) in neighbor_cache().expire(now) {
+        if let Some(interface) = manager.interface(id) {
+            let _ = send_solicitation(&interface, address);
+        }
+    }
+
+    let mut solicit = Vec::new();
+    let mut expired = Vec::new();
+    {
+        let mut state = STATE.lock();
+        let live = |id: &InterfaceId| interfaces.iter().any(|interface| interface.id() == *id);
+        state.solicitations.retain(|id, _| live(id));
+        for (id, solicitation) in state.solicitations.iter_mut() {
+            if solicitation.next <= now {
+                solicitation.sent += 1;
+                solicitation.next = now + ms_to_ticks(ROUTER_SOLICITATION_INTERVAL_MS);
+                solicit.push(*id);
+            }
+        }
+        state.solicitations.retain(|_, solicitation| solicitation.sent < MAX_ROUTER_SOLICITATIONS);
+        state.routers.retain(|router| live(&router.interface) && router.expires > now);
+        state.prefixes.retain(|prefix| {
+            if live(&prefix.interface) && prefix.expires > now {
+                return true;
+            }
+            expired.push(*prefix);
+            false
+        });
+    }
+    for id in solicit {
+        if let Some(interface) = manager.interface(id) {
+            let _ = send_router_solicitation(&interface);
+        }
+    }
+    for prefix in expired {
+        if let (Some(interface), Some(address)) = (manager.interface(prefix.interface), prefix.address) {
+            let _ = interface.remove_ipv6_address(address);
+        }
+    }
+}
+
+/// Register neighbor discovery with the timers of `manager`; its messages
+/// come through ICMPv6
+pub fn register(manager: &NetworkManager) -> Result<(), KernelError> {
+    manager.register_timer(tick);
+    Ok(())
+}
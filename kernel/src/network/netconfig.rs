@@ -0,0 +1,659 @@
+//! Network configuration control object
+//!
+//! A [`NetConfigObject`] is how user space reads and changes the
+//! configuration of the stack: the interfaces and whether they are up,
+//! their addresses, the routes and the neighbor caches. Like netlink, it
+//! carries messages: a request written to the object is answered by the
+//! replies read from it, one message per read.
+//!
+//! A message is a header followed by attributes, all little-endian:
+//!
+//! - header (16 bytes): length of the whole message (u32), kind (u16),
+//!   flags (u16), sequence number (u32, copied from the request to its
+//!   replies) and status (u32, [`STATUS_OK`] or why a request failed)
+//! - attribute: length including its 4-byte header (u16), kind (u16) and
+//!   the value, padded to 4 bytes
+//!
+//! Names, addresses and hardware addresses are attributes of text
+//! (`eth0`, `192.0.2.1/24`, `fe80::1`, `02:00:00:00:00:01`), numbers
+//! integers of their width. A `GET` request is answered by one message per
+//! entry, flagged [`FLAG_MULTI`], then [`NETCONFIG_DONE`]; giving
+//! [`ATTR_NAME`] lists only the entries of that interface. Any other
+//! request is answered by one [`NETCONFIG_STATUS`], and takes privilege.
+//!
+//! Virtual links are created and deleted here too: veth pairs, bridges
+//! and TAP devices, named by [`ATTR_KIND`]. A link is enslaved to a bridge
+//! by giving its [`ATTR_MASTER`], and released by giving an empty one.
+//!
+//! An object sees and changes the interfaces of the network namespace of
+//! the task that opened it, and the links it creates are in that
+//! namespace. A link moves to the namespace of another task by giving the
+//! id of the task as [`ATTR_NETNS_PID`]; this is how the end of a veth pair
+//! is handed to a container.
+//!
+//! IPv6 routes are those neighbor discovery learned: on-link prefixes and
+//! default routers. They are listed but not changed here.
+
+use core::mem::size_of;
+
+use alloc::collections::VecDeque;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::abi::error::KernelError;
+use crate::object::capability::poll::{PollOps, POLLIN, POLLOUT};
+use crate::object::capability::{StreamError, StreamOps};
+use crate::sched::scheduler::get_scheduler;
+use crate::task::mytask;
+
+use super::address::{IpAddress, Ipv4Address, Ipv4Cidr, Ipv6Cidr};
+use super::manager::{get_network_manager, NetworkInterface};
+use super::namespace::{self, NetNamespace, NetNamespaceId};
+use super::route::{routing_table, Route};
+use super::{arp, bridge, ipv4, ndp, tap, veth};
+
+pub const NETCONFIG_HEADER_LEN: usize = 16;
+/// Longest request
+pub const NETCONFIG_MESSAGE_MAX: usize = 4096;
+
+/// Requests
+pub const NETCONFIG_GET_LINKS: u16 = 1;
+/// Bring the interface of `ATTR_NAME` up or down by `ATTR_UP`, enslave it
+/// to the bridge `ATTR_MASTER` and move it to the namespace of the task
+/// `ATTR_NETNS_PID`
+pub const NETCONFIG_SET_LINK: u16 = 2;
+pub const NETCONFIG_GET_ADDRESSES: u16 = 3;
+/// Add or remove the `ATTR_ADDRESS` (with its prefix) of `ATTR_NAME`
+pub const NETCONFIG_ADD_ADDRESS: u16 = 4;
+pub const NETCONFIG_DELETE_ADDRESS: u16 = 5;
+pub const NETCONFIG_GET_ROUTES: u16 = 6;
+/// Add a route to the network `ATTR_ADDRESS` out of `ATTR_NAME`, through
+/// `ATTR_GATEWAY` if given, with `ATTR_METRIC`
+pub const NETCONFIG_ADD_ROUTE: u16 = 7;
+/// Remove the routes to `ATTR_ADDRESS`, only out of `ATTR_NAME` if given
+pub const NETCONFIG_DELETE_ROUTE: u16 = 8;
+pub const NETCONFIG_GET_NEIGHBORS: u16 = 9;
+/// Forget the neighbors of `ATTR_NAME`
+pub const NETCONFIG_FLUSH_NEIGHBORS: u16 = 10;
+/// Create the link `ATTR_NAME` of `ATTR_KIND`, a veth pair with the other
+/// end `ATTR_PEER`
+pub const NETCONFIG_ADD_LINK: u16 = 11;
+/// Delete the virtual link `ATTR_NAME`, both ends of a veth pair
+pub const NETCONFIG_DELETE_LINK: u16 = 12;
+
+/// Replies
+pub const NETCONFIG_STATUS: u16 = 32;
+pub const NETCONFIG_LINK: u16 = 33;
+pub const NETCONFIG_ADDRESS: u16 = 34;
+pub const NETCONFIG_ROUTE: u16 = 35;
+pub const NETCONFIG_NEIGHBOR: u16 = 36;
+/// Ends the replies to a `GET` request
+pub const NETCONFIG_DONE: u16 = 37;
+
+/// The message is one of the replies to a `GET` request
+pub const FLAG_MULTI: u16 = 0x1;
+
+/// Attributes
+pub const ATTR_NAME: u16 = 1;
+pub const ATTR_INDEX: u16 = 2;
+pub const ATTR_MAC: u16 = 3;
+pub const ATTR_MTU: u16 = 4;
+pub const ATTR_UP: u16 = 5;
+pub const ATTR_ADDRESS: u16 = 6;
+pub const ATTR_GATEWAY: u16 = 7;
+pub const ATTR_METRIC: u16 = 8;
+pub const ATTR_DROPPED: u16 = 9;
+pub const ATTR_KIND: u16 = 10;
+pub const ATTR_MASTER: u16 = 11;
+pub const ATTR_PEER: u16 = 12;
+pub const ATTR_NETNS_PID: u16 = 13;
+
+/// Kinds of virtual links
+pub const LINK_VETH: &str = "veth";
+pub const LINK_BRIDGE: &str = "bridge";
+pub const LINK_TAP: &str = "tap";
+
+/// Statuses
+pub const STATUS_OK: u32 = 0;
+pub const STATUS_NOT_PERMITTED: u32 = 1;
+pub const STATUS_NOT_FOUND: u32 = 2;
+pub const STATUS_EXISTS: u32 = 3;
+pub const STATUS_INVALID: u32 = 4;
+pub const STATUS_NOT_SUPPORTED: u32 = 5;
+pub const STATUS_FAILED: u32 = 6;
+
+fn status_of(error: KernelError) -> u32 {
+    match error {
+        KernelError::NotPermitted => STATUS_NOT_PERMITTED,
+        KernelError::NotFound => STATUS_NOT_FOUND,
+        KernelError::Exists => STATUS_EXISTS,
+        KernelError::InvalidArgument => STATUS_INVALID,
+        KernelError::NotSupported => STATUS_NOT_SUPPORTED,
+        _ => STATUS_FAILED,
+    }
+}
+
+/// A message being built
+pub struct MessageBuilder {
+    bytes: Vec<u8>,
+}
+
+impl MessageBuilder {
+    pub fn new(kind: u16, flags: u16, sequence: u32, status: u32) -> Self {
+        let mut bytes = Vec::with_capacity(64);
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&kind.to_le_bytes());
+        bytes.extend_from_slice(&flags.to_le_bytes());
+        bytes.extend_from_slice(&sequence.to_le_bytes());
+        bytes.extend_from_slice(&status.to_le_bytes());
+        Self { bytes }
+    }
+
+    pub fn attribute(mut self, kind: u16, value: &[u8]) -> Self {
+        self.bytes.extend_from_slice(&((4 + value.len()) as u16).to_le_bytes());
+        self.bytes.extend_from_slice(&kind.to_le_bytes());
+        self.bytes.extend_from_slice(value);
+        self.bytes.resize(self.bytes.len().next_multiple_of(4), 0);
+        self
+    }
+
+    pub fn text(self, kind: u16, value: &str) -> Self {
+        self.attribute(kind, value.as_bytes())
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        let len = self.bytes.len() as u32;
+        self.bytes[..4].copy_from_slice(&len.to_le_bytes());
+        self.bytes
+    }
+}
+
+/// A message read from bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message<'a> {
+    pub kind: u16,
+    pub flags: u16,
+    pub sequence: u32,
+    pub status: u32,
+    attributes: &'a [u8],
+}
+
+impl<'a> Message<'a> {
+    /// The message at the start of `bytes` and its length
+    pub fn parse(bytes: &'a [u8]) -> Option<(Self, usize)> {
+        let field = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        if bytes.len() < NETCONFIG_HEADER_LEN {
+            return None;
+        }
+        let len = field(0) as usize;
+        if len < NETCONFIG_HEADER_LEN || len > bytes.len() {
+            return None;
+        }
+        let message = Self {
+            kind: u16::from_le_bytes([bytes[4], bytes[5]]),
+            flags: u16::from_le_bytes([bytes[6], bytes[7]]),
+            sequence: field(8),
+            status: field(12),
+            attributes: &bytes[NETCONFIG_HEADER_LEN..len],
+        };
+        let well_formed = message.attributes().all(|attribute| attribute.is_some());
+        well_formed.then_some((message, len))
+    }
+
+    /// The attributes as (kind, value); `None` ends a malformed list
+    fn attributes(&self) -> impl Iterator<Item = Option<(u16, &'a [u8])>> + '_ {
+        let mut rest = self.attributes;
+        core::iter::from_fn(move || {
+            if rest.is_empty() {
+                return None;
+            }
+            let len = rest.get(..2).map(|len| u16::from_le_bytes([len[0], len[1]]) as usize);
+            let Some(len) = len.filter(|len| (4..=rest.len()).contains(len)) else {
+                rest = &[];
+                return Some(None);
+            };
+            let attribute = (u16::from_le_bytes([rest[2], rest[3]]), &rest[4..len]);
+            rest = &rest[len.next_multiple_of(4).min(rest.len())..];
+            Some(Some(attribute))
+        })
+    }
+
+    pub fn attribute(&self, kind: u16) -> Option<&'a [u8]> {
+        self.attributes().map_while(|attribute| attribute).find(|(found, _)| *found == kind).map(|(_, value)| value)
+    }
+
+    pub fn text(&self, kind: u16) -> Option<&'a str> {
+        core::str::from_utf8(self.attribute(kind)?).ok()
+    }
+
+    pub fn integer(&self, kind: u16) -> Option<u64> {
+        let value = self.attribute(kind)?;
+        let mut bytes = [0; size_of::<u64>()];
+        bytes.get_mut(..value.len())?.copy_from_slice(value);
+        Some(u64::from_le_bytes(bytes))
+    }
+}
+
+/// Whether a request changes the configuration
+fn changes(kind: u16) -> bool {
+    !matches!(kind, NETCONFIG_GET_LINKS | NETCONFIG_GET_ADDRESSES | NETCONFIG_GET_ROUTES | NETCONFIG_GET_NEIGHBORS)
+}
+
+/// The interface of `namespace` a request names
+fn interface_of(namespace: NetNamespaceId, request: &Message) -> Result<Arc<NetworkInterface>, KernelError> {
+    let name = request.text(ATTR_NAME).ok_or(KernelError::InvalidArgument)?;
+    get_network_manager().interface_by_name_in(namespace, name).ok_or(KernelError::NotFound)
+}
+
+/// The network namespace of the task `pid`
+fn namespace_of_task(pid: u64) -> Result<Arc<NetNamespace>, KernelError> {
+    let task = get_scheduler().get_task_by_id(pid as usize).ok_or(KernelError::NotFound)?;
+    Ok(task.net_ns.clone())
+}
+
+/// The kind of a virtual link, `None` for other interfaces
+fn link_kind(interface: &NetworkInterface) -> Option<&'static str> {
+    if veth::is_veth(interface) {
+        Some(LINK_VETH)
+    } else if bridge::is_bridge(interface) {
+        Some(LINK_BRIDGE)
+    } else if tap::is_tap(interface) {
+        Some(LINK_TAP)
+    } else {
+        None
+    }
+}
+
+fn link_message(interface: &NetworkInterface, sequence: u32) -> Vec<u8> {
+    let mut message = MessageBuilder::new(NETCONFIG_LINK, FLAG_MULTI, sequence, STATUS_OK)
+        .text(ATTR_NAME, interface.name())
+        .attribute(ATTR_INDEX, &interface.id().to_le_bytes())
+        .attribute(ATTR_UP, &[interface.is_up() as u8])
+        .attribute(ATTR_DROPPED, &interface.dropped().to_le_bytes());
+    if let Ok(mac) = interface.mac_address() {
+        message = message.text(ATTR_MAC, &mac.to_string());
+    }
+    if let Ok(mtu) = interface.mtu() {
+        message = message.attribute(ATTR_MTU, &(mtu as u32).to_le_bytes());
+    }
+    if let Some(kind) = link_kind(interface) {
+        message = message.text(ATTR_KIND, kind);
+    }
+    if let Some(master) = bridge::master_of(interface) {
+        message = message.text(ATTR_MASTER, master.name());
+    }
+    if let Some(peer) = veth::peer_of(interface) {
+        message = message.text(ATTR_PEER, peer.name());
+    }
+    message.finish()
+}
+
+fn route_message(destination: &str, gateway: Option<IpAddress>, interface: &str, metric: u32, sequence: u32) -> Vec<u8> {
+    let mut message = MessageBuilder::new(NETCONFIG_ROUTE, FLAG_MULTI, sequence, STATUS_OK)
+        .text(ATTR_ADDRESS, destination)
+        .text(ATTR_NAME, interface)
+        .attribute(ATTR_METRIC, &metric.to_le_bytes());
+    if let Some(gateway) = gateway {
+        message = message.text(ATTR_GATEWAY, &gateway.to_string());
+    }
+    message.finish()
+}
+
+/// The replies of a `GET` request in `namespace`, but the last
+fn dump(namespace: NetNamespaceId, request: &Message) -> Result<Vec<Vec<u8>>, KernelError> {
+    let manager = get_network_manager();
+    let interfaces = match request.text(ATTR_NAME) {
+        Some(_) => alloc::vec![interface_of(namespace, request)?],
+        None => manager.interfaces_in(namespace),
+    };
+    let name_of = |id| interfaces.iter().find(|interface| interface.id() == id).map(|interface| interface.name());
+    let sequence = request.sequence;
+    let mut replies = Vec::new();
+    match request.kind {
+        NETCONFIG_GET_LINKS => {
+            replies.extend(interfaces.iter().map(|interface| link_message(interface, sequence)));
+        }
+        NETCONFIG_GET_ADDRESSES => {
+            for interface in &interfaces {
+                let v4 = interface.ipv4_addresses().into_iter().map(|cidr| cidr.to_string());
+                let v6 = interface.ipv6_addresses().into_iter().map(|cidr| cidr.to_string());
+                for address in v4.chain(v6) {
+                    let message = MessageBuilder::new(NETCONFIG_ADDRESS, FLAG_MULTI, sequence, STATUS_OK)
+                        .text(ATTR_NAME, interface.name())
+                        .text(ATTR_ADDRESS, &address);
+                    replies.push(message.finish());
+                }
+            }
+        }
+        NETCONFIG_GET_ROUTES => {
+            for route in routing_table().routes() {
+                if let Some(name) = name_of(route.interface) {
+                    let gateway = route.gateway.map(IpAddress::V4);
+                    replies.push(route_message(&route.destination.to_string(), gateway, name, route.metric, sequence));
+                }
+            }
+            for prefix in ndp::prefixes().into_iter().filter(|prefix| prefix.on_link) {
+                if let Some(name) = name_of(prefix.interface) {
+                    replies.push(route_message(&prefix.prefix.to_string(), None, name, 0, sequence));
+                }
+            }
+            for router in ndp::routers() {
+                if let Some(name) = name_of(router.interface) {
+                    replies.push(route_message("::/0", Some(router.address.into()), name, 0, sequence));
+                }
+            }
+        }
+        NETCONFIG_GET_NEIGHBORS => {
+            let v4 = arp::neighbor_cache().neighbors().into_iter().map(|neighbor| (neighbor.interface, IpAddress::V4(neighbor.address), neighbor.mac));
+            let v6 = ndp::neighbor_cache().neighbors().into_iter().map(|neighbor| (neighbor.interface, IpAddress::V6(neighbor.address), neighbor.mac));
+            for (interface, address, mac) in v4.chain(v6) {
+                let Some(name) = name_of(interface) else {
+                    continue;
+                };
+                let mut message = MessageBuilder::new(NETCONFIG_NEIGHBOR, FLAG_MULTI, sequence, STATUS_OK)
+                    .text(ATTR_NAME, name)
+                    .text(ATTR_ADDRESS, &address.to_string());
+                if let Some(mac) = mac {
+                    message = message.text(ATTR_MAC, &mac.to_string());
+                }
+                replies.push(message.finish());
+            }
+        }
+        _ => return Err(KernelError::NotSupported),
+    }
+    Ok(replies)
+}
+
+/// Carry out a request that changes the configuration of `namespace`
+fn change(namespace: NetNamespaceId, request: &Message) -> Result<(), KernelError> {
+    let manager = get_network_manager();
+    let address = || request.text(ATTR_ADDRESS).ok_or(KernelError::InvalidArgument);
+    match request.kind {
+        NETCONFIG_SET_LINK => {
+            let (up, master, pid) = (request.integer(ATTR_UP), request.text(ATTR_MASTER), request.integer(ATTR_NETNS_PID));
+            if up.is_none() && master.is_none() && pid.is_none() {
+                return Err(KernelError::InvalidArgument);
+            }
+            let interface = interface_of(namespace, request)?;
+            // Moved first, so that the rest applies in the namespace moved to
+            if let Some(pid) = pid {
+                namespace::move_interface(&interface, namespace_of_task(pid)?.id())?;
+            }
+            let namespace = interface.namespace();
+            match master {
+                Some("") => bridge::release(&interface),
+                Some(master) => {
+                    let master = manager.interface_by_name_in(namespace, master).ok_or(KernelError::NotFound)?;
+                    if bridge::master_of(&interface).is_none_or(|current| current.id() != master.id()) {
+                        bridge::release(&interface);
+                        bridge::add_port(&master, &interface)?;
+                    }
+                }
+                None => {}
+            }
+            if let Some(up) = up {
+                interface.set_up(up != 0);
+            }
+            Ok(())
+        }
+        NETCONFIG_ADD_LINK => {
+            let name = request.text(ATTR_NAME).filter(|name| !name.is_empty()).ok_or(KernelError::InvalidArgument)?;
+            let links = match request.text(ATTR_KIND).ok_or(KernelError::InvalidArgument)? {
+                LINK_VETH => {
+                    let peer = request.text(ATTR_PEER).filter(|peer| !peer.is_empty()).ok_or(KernelError::InvalidArgument)?;
+                    let (link, peer) = veth::create_pair(name, peer)?;
+                    alloc::vec![link, peer]
+                }
+                LINK_BRIDGE => alloc::vec![bridge::create(name)?],
+                LINK_TAP => alloc::vec![tap::create(name)?],
+                _ => return Err(KernelError::NotSupported),
+            };
+            // New links have nothing to lose on the way
+            for link in links {
+                link.set_namespace(namespace);
+            }
+            Ok(())
+        }
+        NETCONFIG_DELETE_LINK => {
+            let interface = interface_of(namespace, request)?;
+            match link_kind(&interface) {
+                Some(LINK_VETH) => veth::delete(&interface),
+                Some(LINK_BRIDGE) => bridge::delete(&interface),
+                Some(LINK_TAP) => tap::delete(&interface),
+                _ => Err(KernelError::NotSupported),
+            }
+        }
+        NETCONFIG_ADD_ADDRESS | NETCONFIG_DELETE_ADDRESS => {
+            let interface = interface_of(namespace, request)?;
+            let add = request.kind == NETCONFIG_ADD_ADDRESS;
+            match (Ipv4Cidr::parse(address()?), Ipv6Cidr::parse(address()?)) {
+                (Ok(cidr), _) if add => ipv4::add_address(&interface, cidr),
+                (Ok(cidr), _) => ipv4::remove_address(&interface, cidr.address),
+                (_, Ok(cidr)) if add => interface.add_ipv6_address(cidr),
+                (_, Ok(cidr)) => interface.remove_ipv6_address(cidr.address),
+                _ => Err(KernelError::InvalidArgument),
+            }
+        }
+        NETCONFIG_ADD_ROUTE => {
+            let destination = Ipv4Cidr::parse(address()?).map_err(|_| KernelError::NotSupported)?;
+            let gateway = match request.text(ATTR_GATEWAY) {
+                Some(gateway) => Some(Ipv4Address::parse(gateway)?),
+                None => None,
+            };
+            let metric = request.integer(ATTR_METRIC).unwrap_or(0) as u32;
+            let interface = interface_of(namespace, request)?.id();
+            routing_table().add(Route { destination, gateway, interface, metric })
+        }
+        NETCONFIG_DELETE_ROUTE => {
+            let destination = Ipv4Cidr::parse(address()?).map_err(|_| KernelError::NotSupported)?;
+            if request.text(ATTR_NAME).is_some() {
+                return routing_table().remove(destination, Some(interface_of(namespace, request)?.id()));
+            }
+            // Only the routes through the interfaces of the namespace
+            let mut removed = false;
+            for interface in manager.interfaces_in(namespace) {
+                removed |= routing_table().remove(destination, Some(interface.id())).is_ok();
+            }
+            if removed { Ok(()) } else { Err(KernelError::NotFound) }
+        }
+        NETCONFIG_FLUSH_NEIGHBORS => {
+            let interface = interface_of(namespace, request)?.id();
+            arp::neighbor_cache().flush_interface(interface);
+            ndp::neighbor_cache().flush_interface(interface);
+            Ok(())
+        }
+        _ => Err(KernelError::NotSupported),
+    }
+}
+
+/// The replies to `request` in `namespace`
+fn answer(namespace: NetNamespaceId, request: &Message) -> Vec<Vec<u8>> {
+    let status = |status| MessageBuilder::new(NETCONFIG_STATUS, 0, request.sequence, status).finish();
+    if !changes(request.kind) {
+        return match dump(namespace, request) {
+            Ok(mut replies) => {
+                replies.push(MessageBuilder::new(NETCONFIG_DONE, 0, request.sequence, STATUS_OK).finish());
+                replies
+            }
+            Err(error) => alloc::vec![status(status_of(error))],
+        };
+    }
+    if mytask().is_some_and(|task| !task.cred.is_privileged()) {
+        crate::audit::privilege_denied("netconfig");
+        return alloc::vec![status(STATUS_NOT_PERMITTED)];
+    }
+    alloc::vec![status(change(namespace, request).err().map_or(STATUS_OK, status_of))]
+}
+
+/// A channel of requests and replies to the network configuration
+pub struct NetConfigObject {
+    replies: Mutex<VecDeque<Vec<u8>>>,
+    /// Namespace of the task that opened the object
+    namespace: Arc<NetNamespace>,
+}
+
+impl NetConfigObject {
+    /// A channel to the configuration of the namespace of the current task
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self { replies: Mutex::new(VecDeque::new()), namespace: namespace::current() })
+    }
+}
+
+impl StreamOps for NetConfigObject {
+    /// Take the next reply; the buffer must hold it whole, and nothing is
+    /// read while no reply is pending
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, StreamError> {
+        let mut replies = self.replies.lock();
+        let Some(reply) = replies.front() else {
+            return Ok(0);
+        };
+        let len = reply.len();
+        if len > buffer.len() {
+            return Err(StreamError::InvalidArgument);
+        }
+        buffer[..len].copy_from_slice(reply);
+        replies.pop_front();
+        Ok(len)
+    }
+
+    /// Carry out the requests in `buffer`, whole messages only
+    fn write(&self, buffer: &[u8]) -> Result<usize, StreamError> {
+        if buffer.len() > NETCONFIG_MESSAGE_MAX {
+            return Err(StreamError::InvalidArgument);
+        }
+        let mut requests = Vec::new();
+        let mut rest = buffer;
+        while !rest.is_empty() {
+            let (request, len) = Message::parse(rest).ok_or(StreamError::InvalidArgument)?;
+            requests.push(request);
+            rest = &rest[len..];
+        }
+        for request in requests {
+            let replies = answer(self.namespace.id(), &request);
+            self.replies.lock().extend(replies);
+        }
+        Ok(buffer.len())
+    }
+}
+
+impl PollOps for NetConfigObject {
+    fn poll_events(&self) -> u32 {
+        if self.replies.lock().is_empty() { POLLOUT } else { POLLIN | POLLOUT }
+    }
+}
+
+impl core::fmt::Debug for NetConfigObject {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("NetConfigObject").field("replies", &self.replies.lock().len()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::network::{GenericNetworkDevice, NetworkDevice};
+    use crate::network::ethernet::ETHERNET_STAGE;
+
+    /// The replies to a request, as [`NetConfigObject`] gives them
+    fn request(kind: u16, attributes: &[(u16, &[u8])]) -> Vec<Vec<u8>> {
+        let object = NetConfigObject::new();
+        let mut message = MessageBuilder::new(kind, 0, 7, STATUS_OK);
+        for (kind, value) in attributes {
+            message = message.attribute(*kind, value);
+        }
+        object.write(&message.finish()).unwrap();
+        let mut replies = Vec::new();
+        let mut buffer = [0; 512];
+        while let Ok(len @ 1..) = object.read(&mut buffer) {
+            replies.push(buffer[..len].to_vec());
+        }
+        replies
+    }
+
+    #[test_case]
+    fn test_netconfig_messages() {
+        let message = MessageBuilder::new(NETCONFIG_LINK, FLAG_MULTI, 3, STATUS_OK)
+            .text(ATTR_NAME, "eth0")
+            .attribute(ATTR_MTU, &1500u32.to_le_bytes())
+            .attribute(ATTR_UP, &[1])
+            .finish();
+        assert_eq!(message.len(), NETCONFIG_HEADER_LEN + 8 + 8 + 8);
+        let (parsed, len) = Message::parse(&message).unwrap();
+        assert_eq!((parsed.kind, parsed.flags, parsed.sequence, len), (NETCONFIG_LINK, FLAG_MULTI, 3, message.len()));
+        assert_eq!((parsed.text(ATTR_NAME), parsed.integer(ATTR_MTU), parsed.integer(ATTR_UP)), (Some("eth0"), Some(1500), Some(1)));
+        assert_eq!(parsed.attribute(ATTR_MAC), None);
+
+        // Attributes running past the message, or messages past the bytes
+        let mut broken = message.clone();
+        broken[NETCONFIG_HEADER_LEN] = 0xFF;
+        assert_eq!(Message::parse(&broken), None);
+        assert_eq!(Message::parse(&message[..message.len() - 4]), None);
+        assert!(matches!(NetConfigObject::new().write(&message[..8]), Err(StreamError::InvalidArgument)));
+    }
+
+    #[test_case]
+    fn test_netconfig_requests() {
+        let mut device = GenericNetworkDevice::new("nc0");
+        device.init_network().unwrap();
+        let interface = get_network_manager().attach_device("nc0", Arc::new(device), None, ETHERNET_STAGE).unwrap();
+        let name: &[u8] = b"nc0";
+
+        let status = |replies: Vec<Vec<u8>>| {
+            assert_eq!(replies.len(), 1);
+            let (reply, _) = Message::parse(&replies[0]).unwrap();
+            assert_eq!((reply.kind, reply.sequence), (NETCONFIG_STATUS, 7));
+            reply.status
+        };
+        assert_eq!(status(request(NETCONFIG_ADD_ADDRESS, &[(ATTR_NAME, name), (ATTR_ADDRESS, b"198.51.100.7/24")])), STATUS_OK);
+        assert_eq!(status(request(NETCONFIG_ADD_ADDRESS, &[(ATTR_NAME, name), (ATTR_ADDRESS, b"2001:db8::7/64")])), STATUS_OK);
+        assert_eq!(status(request(NETCONFIG_ADD_ADDRESS, &[(ATTR_NAME, name), (ATTR_ADDRESS, b"198.51.100.7/24")])), STATUS_EXISTS);
+        assert_eq!(status(request(NETCONFIG_ADD_ADDRESS, &[(ATTR_NAME, b"none"), (ATTR_ADDRESS, b"198.51.100.8")])), STATUS_NOT_FOUND);
+        assert_eq!(status(request(NETCONFIG_ADD_ROUTE, &[(ATTR_NAME, name), (ATTR_ADDRESS, b"2001:db8:1::/48")])), STATUS_NOT_SUPPORTED);
+        assert_eq!(status(request(NETCONFIG_SET_LINK, &[(ATTR_NAME, name), (ATTR_UP, &[0])])), STATUS_OK);
+        assert!(!interface.is_up());
+
+        // Dumps end with DONE, filtered by interface
+        let replies = request(NETCONFIG_GET_ADDRESSES, &[(ATTR_NAME, name)]);
+        let addresses: Vec<_> = replies.iter().map(|reply| Message::parse(reply).unwrap().0).collect();
+        assert_eq!(addresses.iter().map(|reply| reply.kind).collect::<Vec<_>>(), [NETCONFIG_ADDRESS, NETCONFIG_ADDRESS, NETCONFIG_DONE]);
+        assert_eq!((addresses[0].text(ATTR_ADDRESS), addresses[1].text(ATTR_ADDRESS)), (Some("198.51.100.7/24"), Some("2001:db8::7/64")));
+        let routes = request(NETCONFIG_GET_ROUTES, &[(ATTR_NAME, name)]);
+        assert_eq!(Message::parse(&routes[0]).unwrap().0.text(ATTR_ADDRESS), Some("198.51.100.0/24"));
+        let links = request(NETCONFIG_GET_LINKS, &[(ATTR_NAME, name)]);
+        assert_eq!(Message::parse(&links[0]).unwrap().0.integer(ATTR_UP), Some(0));
+
+        assert_eq!(status(request(NETCONFIG_DELETE_ADDRESS, &[(ATTR_NAME, name), (ATTR_ADDRESS, b"198.51.100.7")])), STATUS_OK);
+        assert_eq!(status(request(NETCONFIG_DELETE_ROUTE, &[(ATTR_ADDRESS, b"198.51.100.0/24")])), STATUS_NOT_FOUND);
+        get_network_manager().detach(interface.id()).unwrap();
+    }
+
+    #[test_case]
+    fn test_netconfig_links() {
+        let status = |replies: Vec<Vec<u8>>| Message::parse(&replies[0]).unwrap().0.status;
+        let link = |name: &[u8]| {
+            let replies = request(NETCONFIG_GET_LINKS, &[(ATTR_NAME, name)]);
+            replies[0].clone()
+        };
+        assert_eq!(status(request(NETCONFIG_ADD_LINK, &[(ATTR_NAME, b"ncv0"), (ATTR_KIND, b"veth"), (ATTR_PEER, b"ncv1")])), STATUS_OK);
+        assert_eq!(status(request(NETCONFIG_ADD_LINK, &[(ATTR_NAME, b"ncb0"), (ATTR_KIND, b"bridge")])), STATUS_OK);
+        assert_eq!(status(request(NETCONFIG_ADD_LINK, &[(ATTR_NAME, b"ncb0"), (ATTR_KIND, b"bridge")])), STATUS_EXISTS);
+        assert_eq!(status(request(NETCONFIG_ADD_LINK, &[(ATTR_NAME, b"ncx0"), (ATTR_KIND, b"vlan")])), STATUS_NOT_SUPPORTED);
+        assert_eq!(status(request(NETCONFIG_ADD_LINK, &[(ATTR_NAME, b"ncv2"), (ATTR_KIND, b"veth")])), STATUS_INVALID);
+
+        assert_eq!(status(request(NETCONFIG_SET_LINK, &[(ATTR_NAME, b"ncv0"), (ATTR_MASTER, b"ncb0")])), STATUS_OK);
+        assert_eq!(status(request(NETCONFIG_SET_LINK, &[(ATTR_NAME, b"ncb0"), (ATTR_MASTER, b"ncb0")])), STATUS_NOT_SUPPORTED);
+        let veth = link(b"ncv0");
+        let (veth, _) = Message::parse(&veth).unwrap();
+        assert_eq!((veth.text(ATTR_KIND), veth.text(ATTR_MASTER), veth.text(ATTR_PEER)), (Some("veth"), Some("ncb0"), Some("ncv1")));
+        assert_eq!(status(request(NETCONFIG_SET_LINK, &[(ATTR_NAME, b"ncv0"), (ATTR_MASTER, b"")])), STATUS_OK);
+        let veth = link(b"ncv0");
+        assert_eq!(Message::parse(&veth).unwrap().0.text(ATTR_MASTER), None);
+
+        assert_eq!(status(request(NETCONFIG_DELETE_LINK, &[(ATTR_NAME, b"ncv1")])), STATUS_OK);
+        assert_eq!(status(request(NETCONFIG_DELETE_LINK, &[(ATTR_NAME, b"ncb0")])), STATUS_OK);
+        assert!(get_network_manager().interface_by_name("ncv0").is_none());
+        assert!(get_network_manager().interface_by_name("ncb0").is_none());
+    }
+}
@@ -0,0 +1,242 @@
+//! Packets moving through the network stack
+//!
+//! A `NetworkPacket` holds the bytes of a frame, in a shared
+//! [`PacketBuffer`], and a cursor into them. On the way in, each stage
+//! consumes the header in front of the cursor, which is recorded under the
+//! name of its layer, so what follows the cursor is the payload for the
+//! next stage. On the way out, stages push their headers in front of the
+//! payload instead, into the headroom of the buffer. Cloning a packet
+//! shares its bytes.
+//!
+//! A received packet is linear. A packet being sent may carry its payload
+//! as fragments of its buffer; [`NetworkPacket::data`] is then the linear
+//! part, the headers pushed, only.
+//!
+//! Stages tell later stages what they found (addresses, protocol numbers,
+//! the interface a packet came in on) through the metadata of the packet.
+//! The metadata also carries the offloads of a packet between the stack
+//! and its device.
+
+use core::ops::Range;
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use crate::abi::error::KernelError;
+
+use super::buffer::PacketBuffer;
+use super::manager::NetworkInterface;
+use super::InterfaceId;
+
+/// Whether a packet was received or is being sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketDirection {
+    Incoming,
+    Outgoing,
+}
+
+/// A received packet whose transport checksum the device checked, if 1
+pub const META_CHECKSUM_VERIFIED: &str = "checksum.verified";
+/// Where the partial transport checksum of an outgoing datagram starts and
+/// is put, from the start of the datagram and from that start
+pub const META_CHECKSUM_START: &str = "checksum.start";
+pub const META_CHECKSUM_OFFSET: &str = "checksum.offset";
+/// TCP payload bytes per segment of an outgoing datagram the device cuts
+/// up, and the length of its IPv4 and TCP headers
+pub const META_SEGMENT_SIZE: &str = "segment.size";
+pub const META_SEGMENT_HEADER_LEN: &str = "segment.header_len";
+
+/// A value of the metadata of a packet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MetadataValue {
+    Int(u64),
+    Bytes(Vec<u8>),
+}
+
+#[derive(Clone)]
+pub struct NetworkPacket {
+    data: PacketBuffer,
+    /// Start of the payload of the current stage
+    offset: usize,
+    direction: PacketDirection,
+    /// Interface the packet came in on or goes out of
+    interface: Option<Arc<NetworkInterface>>,
+    /// Where the header of each layer is in `data`
+    headers: Vec<(&'static str, Range<usize>)>,
+    metadata: BTreeMap<&'static str, MetadataValue>,
+}
+
+impl NetworkPacket {
+    /// A packet received on `interface`, with the whole frame as payload
+    pub fn incoming(data: impl Into<PacketBuffer>, interface: Arc<NetworkInterface>) -> Self {
+        let mut data = data.into();
+        data.linearize();
+        Self {
+            data,
+            offset: 0,
+            direction: PacketDirection::Incoming,
+            interface: Some(interface),
+            headers: Vec::new(),
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    /// A packet to send with `payload`, headers to be pushed in front
+    pub fn outgoing(payload: impl Into<PacketBuffer>) -> Self {
+        Self {
+            data: payload.into(),
+            offset: 0,
+            direction: PacketDirection::Outgoing,
+            interface: None,
+            headers: Vec::new(),
+            metadata: BTreeMap::new(),
+        }
+    }
+
+    pub fn direction(&self) -> PacketDirection {
+        self.direction
+    }
+
+    pub fn interface(&self) -> Option<&Arc<NetworkInterface>> {
+        self.interface.as_ref()
+    }
+
+    pub fn interface_id(&self) -> Option<InterfaceId> {
+        self.interface.as_ref().map(|interface| interface.id())
+    }
+
+    pub fn set_interface(&mut self, interface: Arc<NetworkInterface>) {
+        self.interface = Some(interface);
+    }
+
+    /// All bytes of the packet, headers included, but its fragments
+    pub fn data(&self) -> &[u8] {
+        self.data.linear()
+    }
+
+    /// The buffer of the packet
+    pub fn buffer(&self) -> &PacketBuffer {
+        &self.data
+    }
+
+    /// The bytes after the headers consumed so far
+    pub fn payload(&self) -> &[u8] {
+        &self.data.linear()[self.offset..]
+    }
+
+    /// The payload to write; the packet is made linear and unshared
+    pub fn payload_mut(&mut self) -> &mut [u8] {
+        &mut self.data.linearize()[self.offset..]
+    }
+
+    /// Consume the header of `layer`, the first `len` bytes of the payload
+    ///
+    /// Fails with `InvalidArgument` if the payload is shorter than `len`.
+    pub fn consume_header(&mut self, layer: &'static str, len: usize) -> Result<&[u8], KernelError> {
+        let start = self.offset;
+        let data = self.data.linear();
+        let end = start.checked_add(len).filter(|&end| end <= data.len()).ok_or(KernelError::InvalidArgument)?;
+        self.headers.push((layer, start..end));
+        self.offset = end;
+        Ok(&self.data.linear()[start..end])
+    }
+
+    /// Put the header of `layer` in front of the packet
+    pub fn push_header(&mut self, layer: &'static str, header: &[u8]) {
+        let len = header.len();
+        self.data.push_front(header);
+        for (_, range) in &mut self.headers {
+            *range = range.start + len..range.end + len;
+        }
+        self.headers.push((layer, 0..len));
+    }
+
+    /// The header consumed or pushed for `layer`
+    pub fn header(&self, layer: &str) -> Option<&[u8]> {
+        self.headers
+            .iter()
+            .rev()
+            .find(|(name, _)| *name == layer)
+            .map(|(_, range)| &self.data.linear()[range.clone()])
+    }
+
+    /// Bytes of the headers consumed or pushed so far
+    pub fn headers_len(&self) -> usize {
+        self.headers.iter().map(|(_, range)| range.len()).sum()
+    }
+
+    /// Cut the payload down to `len` bytes, dropping padding after it
+    pub fn truncate_payload(&mut self, len: usize) {
+        self.data.truncate(self.offset.saturating_add(len));
+    }
+
+    /// Replace the payload with `payload`, keeping the headers consumed
+    pub fn replace_payload(&mut self, payload: Vec<u8>) {
+        self.data.truncate(self.offset);
+        self.data.extend_back(&payload);
+    }
+
+    /// Append zeros until the packet is `len` bytes long
+    pub fn pad(&mut self, len: usize) {
+        if self.data.len() < len {
+            self.data.extend_back(&alloc::vec![0; len - self.data.len()]);
+        }
+    }
+
+    pub fn set_metadata(&mut self, key: &'static str, value: MetadataValue) {
+        self.metadata.insert(key, value);
+    }
+
+    pub fn metadata(&self, key: &str) -> Option<&MetadataValue> {
+        self.metadata.get(key)
+    }
+
+    /// The metadata `key` if it is an integer
+    pub fn metadata_int(&self, key: &str) -> Option<u64> {
+        match self.metadata.get(key) {
+            Some(MetadataValue::Int(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// The bytes of the packet in one piece
+    pub fn into_data(self) -> Vec<u8> {
+        self.data.into_vec()
+    }
+
+    /// The buffer of the packet, to hand to a device
+    pub fn into_buffer(self) -> PacketBuffer {
+        self.data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test_case]
+    fn test_packet_headers() {
+        let mut packet = NetworkPacket::outgoing(vec![1, 2, 3, 4, 5, 6, 0, 0]);
+        assert_eq!(packet.interface_id(), None);
+        assert_eq!(packet.consume_header("link", 2).unwrap(), &[1, 2]);
+        assert_eq!(packet.consume_header("net", 1).unwrap(), &[3]);
+        assert!(packet.consume_header("transport", 6).is_err());
+        packet.truncate_payload(3);
+        assert_eq!(packet.payload(), &[4, 5, 6]);
+        assert_eq!(packet.header("link"), Some(&[1u8, 2][..]));
+        assert_eq!(packet.header("transport"), None);
+
+        let mut packet = NetworkPacket::outgoing(vec![9, 9]);
+        packet.push_header("net", &[3]);
+        packet.push_header("link", &[1, 2]);
+        assert_eq!(packet.header("net"), Some(&[3u8][..]));
+        assert_eq!(packet.headers_len(), 3);
+        assert_eq!(packet.clone().into_data(), vec![1, 2, 3, 9, 9]);
+
+        packet.set_metadata("protocol", MetadataValue::Int(17));
+        assert_eq!(packet.metadata_int("protocol"), Some(17));
+        assert_eq!(packet.metadata_int("missing"), None);
+    }
+}
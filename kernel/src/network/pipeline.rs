@@ -0,0 +1,246 @@
+//! Protocol pipeline
+//!
+//! A `FlexiblePipeline` is a set of named stages. Each stage maps keys to
+//! the processors registered there, and may have a default processor for
+//! keys nobody registered. A packet enters at a stage with a key; the
+//! processor found there handles it and either passes it on to another
+//! stage and key, consumes it, or drops it with a reason.
+//!
+//! Stages are looked up again at every step and processors are called
+//! without the pipeline locked, so processors may register others, and
+//! send packets, while packets are being processed.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::RwLock;
+
+use crate::abi::error::KernelError;
+
+use super::packet::NetworkPacket;
+
+/// Most steps a packet takes through the pipeline, against loops between
+/// stages
+pub const MAX_PIPELINE_STEPS: usize = 16;
+
+/// What a processor did with a packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessResult {
+    /// Hand the packet to the processor for `key` at `stage`
+    Forward { stage: &'static str, key: u64 },
+    /// The packet reached its destination (or was answered)
+    Consumed,
+    /// The packet is dropped
+    Dropped(&'static str),
+}
+
+/// How a packet left the pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketFate {
+    Consumed,
+    Dropped(&'static str),
+}
+
+/// A protocol handler registered at a stage
+pub trait PacketProcessor: Send + Sync {
+    /// Name of the processor, for diagnostics
+    fn name(&self) -> &'static str;
+
+    fn process(&self, packet: &mut NetworkPacket) -> ProcessResult;
+}
+
+#[derive(Default)]
+struct PipelineStage {
+    processors: BTreeMap<u64, Arc<dyn PacketProcessor>>,
+    default: Option<Arc<dyn PacketProcessor>>,
+}
+
+/// Counters of a pipeline
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipelineStats {
+    pub consumed: u64,
+    pub dropped: u64,
+}
+
+pub struct FlexiblePipeline {
+    stages: RwLock<BTreeMap<&'static str, PipelineStage>>,
+    consumed: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl FlexiblePipeline {
+    pub const fn new() -> Self {
+        Self {
+            stages: RwLock::new(BTreeMap::new()),
+            consumed: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Add an empty stage; fails with `Exists` if there is one by `name`
+    pub fn add_stage(&self, name: &'static str) -> Result<(), KernelError> {
+        let mut stages = self.stages.write();
+        if stages.contains_key(name) {
+            return Err(KernelError::Exists);
+        }
+        stages.insert(name, PipelineStage::default());
+        Ok(())
+    }
+
+    /// Remove a stage with all its processors
+    pub fn remove_stage(&self, name: &str) -> Result<(), KernelError> {
+        self.stages.write().remove(name).map(|_| ()).ok_or(KernelError::NotFound)
+    }
+
+    pub fn has_stage(&self, name: &str) -> bool {
+        self.stages.read().contains_key(name)
+    }
+
+    pub fn stage_names(&self) -> Vec<&'static str> {
+        self.stages.read().keys().copied().collect()
+    }
+
+    /// Register `processor` for `key` at `stage`
+    ///
+    /// Fails with `NotFound` without the stage and with `Exists` if the key
+    /// is taken.
+    pub fn register_processor(&self, stage: &str, key: u64, processor: Arc<dyn PacketProcessor>) -> Result<(), KernelError> {
+        let mut stages = self.stages.write();
+        let stage = stages.get_mut(stage).ok_or(KernelError::NotFound)?;
+        if stage.processors.contains_key(&key) {
+            return Err(KernelError::Exists);
+        }
+        stage.processors.insert(key, processor);
+        Ok(())
+    }
+
+    /// Remove the processor for `key` at `stage`
+    pub fn unregister_processor(&self, stage: &str, key: u64) -> Option<Arc<dyn PacketProcessor>> {
+        self.stages.write().get_mut(stage)?.processors.remove(&key)
+    }
+
+    /// Set the processor of `stage` for keys without one, `None` to drop
+    /// such packets
+    pub fn set_default_processor(&self, stage: &str, processor: Option<Arc<dyn PacketProcessor>>) -> Result<(), KernelError> {
+        let mut stages = self.stages.write();
+        stages.get_mut(stage).ok_or(KernelError::NotFound)?.default = processor;
+        Ok(())
+    }
+
+    fn processor(&self, stage: &str, key: u64) -> Result<Arc<dyn PacketProcessor>, &'static str> {
+        let stages = self.stages.read();
+        let stage = stages.get(stage).ok_or("No such stage")?;
+        stage.processors.get(&key).or(stage.default.as_ref()).cloned().ok_or("No processor for the packet")
+    }
+
+    /// Run `packet` through the pipeline from the processor for `key` at
+    /// `stage`
+    pub fn process(&self, packet: &mut NetworkPacket, stage: &str, key: u64) -> PacketFate {
+        let fate = self.run(packet, stage, key);
+        match fate {
+            PacketFate::Consumed => self.consumed.fetch_add(1, Ordering::Relaxed),
+            PacketFate::Dropped(_) => self.dropped.fetch_add(1, Ordering::Relaxed),
+        };
+        fate
+    }
+
+    fn run(&self, packet: &mut NetworkPacket, stage: &str, key: u64) -> PacketFate {
+        let mut processor = match self.processor(stage, key) {
+            Ok(processor) => processor,
+            Err(reason) => return PacketFate::Dropped(reason),
+        };
+        for _ in 0..MAX_PIPELINE_STEPS {
+            match processor.process(packet) {
+                ProcessResult::Forward { stage, key } => match self.processor(stage, key) {
+                    Ok(next) => processor = next,
+                    Err(reason) => return PacketFate::Dropped(reason),
+                },
+                ProcessResult::Consumed => return PacketFate::Consumed,
+                ProcessResult::Dropped(reason) => return PacketFate::Dropped(reason),
+            }
+        }
+        PacketFate::Dropped("Too many pipeline steps")
+    }
+
+    pub fn stats(&self) -> PipelineStats {
+        PipelineStats {
+            consumed: self.consumed.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// Consumes a one-byte header and forwards by its value
+    struct ByteDemux {
+        next_stage: &'static str,
+    }
+
+    impl PacketProcessor for ByteDemux {
+        fn name(&self) -> &'static str {
+            "byte-demux"
+        }
+
+        fn process(&self, packet: &mut NetworkPacket) -> ProcessResult {
+            match packet.consume_header(self.next_stage, 1) {
+                Ok(header) => ProcessResult::Forward { stage: self.next_stage, key: header[0] as u64 },
+                Err(_) => ProcessResult::Dropped("Truncated"),
+            }
+        }
+    }
+
+    struct Sink;
+
+    impl PacketProcessor for Sink {
+        fn name(&self) -> &'static str {
+            "sink"
+        }
+
+        fn process(&self, _packet: &mut NetworkPacket) -> ProcessResult {
+            ProcessResult::Consumed
+        }
+    }
+
+    #[test_case]
+    fn test_pipeline_dispatch() {
+        let pipeline = FlexiblePipeline::new();
+        pipeline.add_stage("link").unwrap();
+        pipeline.add_stage("net").unwrap();
+        assert_eq!(pipeline.add_stage("net"), Err(KernelError::Exists));
+        pipeline.register_processor("link", 0, Arc::new(ByteDemux { next_stage: "net" })).unwrap();
+        pipeline.register_processor("net", 4, Arc::new(Sink)).unwrap();
+        assert_eq!(pipeline.register_processor("net", 4, Arc::new(Sink)), Err(KernelError::Exists));
+        assert_eq!(pipeline.register_processor("transport", 0, Arc::new(Sink)), Err(KernelError::NotFound));
+
+        let mut packet = NetworkPacket::outgoing(vec![4, 1, 2]);
+        assert_eq!(pipeline.process(&mut packet, "link", 0), PacketFate::Consumed);
+        assert_eq!(packet.payload(), &[1, 2]);
+
+        let mut packet = NetworkPacket::outgoing(vec![6]);
+        assert_eq!(pipeline.process(&mut packet, "link", 0), PacketFate::Dropped("No processor for the packet"));
+        pipeline.set_default_processor("net", Some(Arc::new(Sink))).unwrap();
+        let mut packet = NetworkPacket::outgoing(vec![6]);
+        assert_eq!(pipeline.process(&mut packet, "link", 0), PacketFate::Consumed);
+
+        assert!(pipeline.unregister_processor("net", 4).is_some());
+        pipeline.remove_stage("net").unwrap();
+        let mut packet = NetworkPacket::outgoing(vec![4]);
+        assert_eq!(pipeline.process(&mut packet, "link", 0), PacketFate::Dropped("No such stage"));
+        assert_eq!(pipeline.stats(), PipelineStats { consumed: 2, dropped: 2 });
+    }
+
+    #[test_case]
+    fn test_pipeline_loop_is_dropped() {
+        let pipeline = FlexiblePipeline::new();
+        pipeline.add_stage("loop").unwrap();
+        pipeline.set_default_processor("loop", Some(Arc::new(ByteDemux { next_stage: "loop" }))).unwrap();
+        let mut packet = NetworkPacket::outgoing(vec![0; MAX_PIPELINE_STEPS * 2]);
+        assert_eq!(pipeline.process(&mut packet, "loop", 0), PacketFate::Dropped("Too many pipeline steps"));
+    }
+}
@@ -0,0 +1,463 @@
+//! Raw IP sockets
+//!
+//! A [`RawSocket`] sends and receives the datagrams of one IP protocol
+//! without a transport between it and IP. IPv4 and IPv6 hand every
+//! datagram addressed to the host to [`deliver`] before the processor of
+//! its protocol sees it, so a raw socket gets a copy of what the stack
+//! handles too; a protocol only raw sockets take is not reported
+//! unreachable. As on other systems, an IPv4 socket receives the datagram
+//! with its header and an IPv6 one only the payload, and both send the
+//! payload alone. The kernel fills in the checksum of ICMPv6 messages
+//! sent, but not that of ICMP ones.
+//!
+//! A raw socket is named by an address, `a.b.c.d` or `v6`, or by an
+//! endpoint whose port is ignored; it receives from `a.b.c.d:0` or
+//! `[v6]:0`. Binding limits what it takes to datagrams to the address
+//! bound, connecting to those from the peer, and a [`PacketFilter`]
+//! narrows it further. A socket takes only the datagrams received in its
+//! network namespace, and sends by the routes of its interfaces.
+//!
+//! [`PacketFilter`]: super::capture::PacketFilter
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::{Mutex, RwLock};
+
+use crate::abi::error::KernelError;
+use crate::ipc::socket::{Received, Shutdown, SocketDomain, SocketObject, SocketType, SOCKET_BUFFER_SIZE};
+use crate::ipc::StreamIpcOps;
+use crate::object::capability::poll::{wait_for, PollOps, PollWait, POLLIN, POLLOUT};
+use crate::object::capability::{StreamError, StreamOps};
+use crate::object::KernelObject;
+use crate::sync::waker::Waker;
+
+use super::address::{IpAddress, IpEndpoint, IpFamily};
+use super::capture::{CapturedPacket, PacketFilter};
+use super::checksum::Checksum;
+use super::ethernet::{ETHERTYPE_IPV4, ETHERTYPE_IPV6};
+use super::ip::{self, SendOptions};
+use super::ipv4::IPV4_MAX_DATAGRAM_LEN;
+use super::ipv6::NEXT_HEADER_ICMPV6;
+use super::namespace::{self, NetNamespace};
+use super::packet::{MetadataValue, NetworkPacket, PacketDirection};
+
+/// Set to 1 on a datagram a raw socket took
+pub const META_RAW_TAKEN: &str = "raw.taken";
+
+/// Largest payload a raw socket sends
+pub const RAW_MAX_PAYLOAD: usize = IPV4_MAX_DATAGRAM_LEN - 20;
+
+/// Raw sockets, for [`deliver`] to find
+static SOCKETS: RwLock<Vec<Weak<RawSocket>>> = RwLock::new(Vec::new());
+/// Open raw sockets: [`deliver`] does nothing while there is none
+static OPEN: AtomicUsize = AtomicUsize::new(0);
+
+/// Copy the datagram of `protocol` that `packet` carries, its IP header
+/// consumed, to the raw sockets that take it
+///
+/// Called by IPv4 and IPv6 once the datagram is for the host and whole;
+/// marks the packet with [`META_RAW_TAKEN`] if a socket took it.
+pub fn deliver(packet: &mut NetworkPacket, protocol: u8) {
+    if OPEN.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    let (Some(interface), Some(source), Some(destination)) = (packet.interface().cloned(), ip::source(packet), ip::destination(packet)) else {
+        return;
+    };
+    let Some(header) = ip::header(packet) else {
+        return;
+    };
+    let header_len = header.len();
+    let mut datagram = header.to_vec();
+    datagram.extend_from_slice(packet.payload());
+    let family = source.family();
+    let captured = CapturedPacket {
+        interface: &interface,
+        direction: PacketDirection::Incoming,
+        data: &datagram,
+        ethertype: Some(if family == IpFamily::V4 { ETHERTYPE_IPV4 } else { ETHERTYPE_IPV6 }),
+        network_offset: 0,
+    };
+    // IPv6 sockets get the payload alone
+    let data = if family == IpFamily::V4 { &datagram[..] } else { &datagram[header_len..] };
+    let sockets: Vec<Arc<RawSocket>> = SOCKETS.read().iter().filter_map(Weak::upgrade).collect();
+    let mut taken = false;
+    for socket in sockets {
+        if socket.takes(protocol, source, destination, &captured) {
+            socket.enqueue(data, source);
+            taken = true;
+        }
+    }
+    if taken {
+        packet.set_metadata(META_RAW_TAKEN, MetadataValue::Int(1));
+    }
+}
+
+/// The address `name` is to a socket of `family`: an address or an
+/// endpoint, IPv4-mapped addresses being the IPv4 ones they map
+fn socket_address(family: IpFamily, name: &str) -> Result<IpAddress, KernelError> {
+    let address = match IpEndpoint::parse(name) {
+        Ok(endpoint) => endpoint.address,
+        Err(_) => IpAddress::parse(name)?,
+    }
+    .canonical();
+    if family == IpFamily::V4 && address.family() != IpFamily::V4 {
+        return Err(KernelError::InvalidArgument);
+    }
+    Ok(address)
+}
+
+struct Datagram {
+    data: Vec<u8>,
+    from: IpAddress,
+}
+
+struct Inbox {
+    datagrams: VecDeque<Datagram>,
+    bytes: usize,
+}
+
+struct RawState {
+    local: Option<IpAddress>,
+    remote: Option<IpAddress>,
+    read_shutdown: bool,
+    write_shutdown: bool,
+}
+
+/// A raw IP socket
+pub struct RawSocket {
+    family: IpFamily,
+    /// Protocol number of IPv4, next header of IPv6
+    protocol: u8,
+    nonblocking: bool,
+    /// Namespace of the task that opened the socket
+    namespace: Arc<NetNamespace>,
+    state: Mutex<RawState>,
+    filter: RwLock<Option<Arc<dyn PacketFilter>>>,
+    inbox: Mutex<Inbox>,
+    waker: Waker,
+}
+
+impl RawSocket {
+    /// An unbound socket of `family` for the datagrams of `protocol`, in
+    /// the namespace of the current task; an IPv6 socket takes IPv4
+    /// addresses too
+    pub fn new(family: IpFamily, protocol: u8, nonblocking: bool) -> Arc<Self> {
+        let socket = Arc::new(Self {
+            family,
+            protocol,
+            nonblocking,
+            namespace: namespace::current(),
+            state: Mutex::new(RawState { local: None, remote: None, read_shutdown: false, write_shutdown: false }),
+            filter: RwLock::new(None),
+            inbox: Mutex::new(Inbox { datagrams: VecDeque::new(), bytes: 0 }),
+            waker: Waker::new_interruptible("raw"),
+        });
+        let mut sockets = SOCKETS.write();
+        sockets.retain(|socket| socket.strong_count() > 0);
+        sockets.push(Arc::downgrade(&socket));
+        OPEN.fetch_add(1, Ordering::Relaxed);
+        socket
+    }
+
+    fn takes(&self, protocol: u8, source: IpAddress, destination: IpAddress, packet: &CapturedPacket) -> bool {
+        if protocol != self.protocol
+            || (self.family == IpFamily::V4 && source.family() != IpFamily::V4)
+            || packet.interface.namespace() != self.namespace.id()
+        {
+            return false;
+        }
+        {
+            let state = self.state.lock();
+            if state.read_shutdown
+                || state.local.is_some_and(|local| !local.covers(destination))
+                || state.remote.is_some_and(|remote| remote != source)
+            {
+                return false;
+            }
+        }
+        self.filter.read().as_ref().map_or(true, |filter| filter.accepts(packet))
+    }
+
+    /// Queue a received datagram, dropping it if there is no room
+    fn enqueue(&self, data: &[u8], from: IpAddress) {
+        let mut inbox = self.inbox.lock();
+        if inbox.bytes + data.len() > SOCKET_BUFFER_SIZE {
+            return;
+        }
+        inbox.bytes += data.len();
+        inbox.datagrams.push_back(Datagram { data: data.to_vec(), from });
+        drop(inbox);
+        self.waker.wake_all();
+    }
+
+    /// Take a datagram into `buffer`, `None` while nothing is there
+    fn take(&self, buffer: &mut [u8]) -> Option<Received> {
+        let read_shutdown = self.state.lock().read_shutdown;
+        let mut inbox = self.inbox.lock();
+        let Some(datagram) = inbox.datagrams.pop_front() else {
+            return read_shutdown.then(Received::default);
+        };
+        inbox.bytes -= datagram.data.len();
+        let len = datagram.data.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&datagram.data[..len]);
+        Some(Received {
+            len,
+            truncated: len < datagram.data.len(),
+            objects: Vec::new(),
+            from: Some(IpEndpoint::new(datagram.from, 0).to_string()),
+        })
+    }
+}
+
+impl SocketObject for RawSocket {
+    fn domain(&self) -> SocketDomain {
+        match self.family {
+            IpFamily::V4 => SocketDomain::Inet,
+            IpFamily::V6 => SocketDomain::Inet6,
+        }
+    }
+
+    fn socket_type(&self) -> SocketType {
+        SocketType::Raw
+    }
+
+    /// Receive only the datagrams to the address `name`, and send from it
+    fn bind(&self, name: &str) -> Result<(), KernelError> {
+        let local = socket_address(self.family, name)?;
+        let mut state = self.state.lock();
+        if state.local.is_some() {
+            return Err(KernelError::InvalidArgument);
+        }
+        state.local = Some(local);
+        Ok(())
+    }
+
+    fn listen(&self, _backlog: usize) -> Result<(), KernelError> {
+        Err(KernelError::NotSupported)
+    }
+
+    /// Send to `name` by default and receive only from it
+    fn connect(&self, name: &str) -> Result<(), KernelError> {
+        let remote = socket_address(self.family, name)?;
+        self.state.lock().remote = Some(remote);
+        Ok(())
+    }
+
+    fn accept(&self) -> Result<Arc<dyn SocketObject>, KernelError> {
+        Err(KernelError::NotSupported)
+    }
+
+    /// Send `data` as the payload of one datagram of the protocol
+    fn send(&self, data: &[u8], objects: Vec<KernelObject>, to: Option<&str>) -> Result<usize, KernelError> {
+        if !objects.is_empty() {
+            return Err(KernelError::NotSupported);
+        }
+        if data.len() > RAW_MAX_PAYLOAD {
+            return Err(KernelError::MessageTooLong);
+        }
+        let (local, destination) = {
+            let state = self.state.lock();
+            if state.write_shutdown {
+                return Err(KernelError::BrokenPipe);
+            }
+            let destination = match to {
+                Some(name) => socket_address(self.family, name)?,
+                None => state.remote.ok_or(KernelError::NotConnected)?,
+            };
+            (state.local, destination)
+        };
+        let mut options = SendOptions { namespace: self.namespace.id(), ..SendOptions::default() };
+        if let Some(local) = local.filter(|local| !local.is_unspecified()) {
+            options.source = Some(local);
+        }
+        let source = ip::source_for(destination, &options)?;
+        options.source = Some(source);
+        match (source, destination) {
+            (IpAddress::V6(source), IpAddress::V6(destination)) if self.protocol == NEXT_HEADER_ICMPV6 => {
+                if data.len() < 4 {
+                    return Err(KernelError::InvalidArgument);
+                }
+                let mut message = data.to_vec();
+                message[2..4].fill(0);
+                let mut sum = Checksum::new();
+                sum.add_ipv6_pseudo_header(source, destination, NEXT_HEADER_ICMPV6, message.len());
+                sum.add_bytes(&message);
+                message[2..4].copy_from_slice(&sum.finish().to_be_bytes());
+                ip::send(destination.into(), self.protocol, &message, &options)?;
+            }
+            _ => ip::send(destination, self.protocol, data, &options)?,
+        }
+        Ok(data.len())
+    }
+
+    fn receive(&self, buffer: &mut [u8]) -> Result<Received, KernelError> {
+        let mut outcome = None;
+        let mut attempt = || {
+            outcome = self.take(buffer);
+            outcome.is_some()
+        };
+        if self.nonblocking {
+            if !attempt() {
+                return Err(KernelError::WouldBlock);
+            }
+        } else {
+            let sources: [&dyn PollOps; 1] = [self];
+            match wait_for(&sources, None, attempt) {
+                PollWait::Ready => {}
+                PollWait::Interrupted => return Err(KernelError::Interrupted),
+                PollWait::TimedOut => return Err(KernelError::WouldBlock),
+            }
+        }
+        Ok(outcome.unwrap_or_default())
+    }
+
+    fn shutdown(&self, how: Shutdown) -> Result<(), KernelError> {
+        let mut state = self.state.lock();
+        if how != Shutdown::Write {
+            state.read_shutdown = true;
+        }
+        if how != Shutdown::Read {
+            state.write_shutdown = true;
+        }
+        drop(state);
+        self.waker.wake_all();
+        Ok(())
+    }
+
+    fn name(&self) -> Option<String> {
+        self.state.lock().local.map(|local| IpEndpoint::new(local, 0).to_string())
+    }
+
+    fn peer_name(&self) -> Option<String> {
+        self.state.lock().remote.map(|remote| IpEndpoint::new(remote, 0).to_string())
+    }
+
+    fn set_filter(&self, filter: Option<Arc<dyn PacketFilter>>) -> Result<(), KernelError> {
+        *self.filter.write() = filter;
+        Ok(())
+    }
+}
+
+/// The stream error of a socket error
+fn stream_error(error: KernelError) -> StreamError {
+    match error {
+        KernelError::WouldBlock => StreamError::WouldBlock,
+        KernelError::Interrupted => StreamError::Interrupted,
+        KernelError::BrokenPipe => StreamError::BrokenPipe,
+        KernelError::NotConnected => StreamError::Closed,
+        _ => StreamError::InvalidArgument,
+    }
+}
+
+impl StreamOps for RawSocket {
+    /// Receive a datagram
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, StreamError> {
+        self.receive(buffer).map(|received| received.len).map_err(stream_error)
+    }
+
+    /// Send a datagram to the peer
+    fn write(&self, buffer: &[u8]) -> Result<usize, StreamError> {
+        self.send(buffer, Vec::new(), None).map_err(stream_error)
+    }
+}
+
+impl StreamIpcOps for RawSocket {
+    fn is_connected(&self) -> bool {
+        self.state.lock().remote.is_some()
+    }
+
+    fn peer_count(&self) -> usize {
+        self.state.lock().remote.is_some() as usize
+    }
+
+    fn description(&self) -> String {
+        format!("raw({})", self.protocol)
+    }
+}
+
+impl PollOps for RawSocket {
+    fn poll_events(&self) -> u32 {
+        let state = self.state.lock();
+        let mut events = if state.write_shutdown { 0 } else { POLLOUT };
+        if state.read_shutdown || !self.inbox.lock().datagrams.is_empty() {
+            events |= POLLIN;
+        }
+        events
+    }
+
+    fn poll_register(&self, task_id: usize) -> bool {
+        self.waker.register(task_id);
+        true
+    }
+
+    fn poll_unregister(&self, task_id: usize) {
+        self.waker.unregister(task_id);
+    }
+}
+
+impl Drop for RawSocket {
+    fn drop(&mut self) {
+        OPEN.fetch_sub(1, Ordering::Relaxed);
+        SOCKETS.write().retain(|socket| socket.strong_count() > 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::network::{GenericNetworkDevice, MacAddress, NetworkDevice};
+    use crate::network::address::{Ipv4Address, Ipv4Cidr};
+    use crate::network::capture::FilterExpression;
+    use crate::network::ethernet::{self, EthernetHeader};
+    use crate::network::ipv4::{self, Ipv4Header};
+    use crate::network::manager::NetworkManager;
+    use crate::network::pipeline::PacketFate;
+    use crate::network::{arp, icmp};
+
+    #[test_case]
+    fn test_raw_receive() {
+        static MANAGER: NetworkManager = NetworkManager::new();
+        ethernet::register(&MANAGER).unwrap();
+        arp::register(&MANAGER).unwrap();
+        ipv4::register(&MANAGER).unwrap();
+        icmp::register(&MANAGER).unwrap();
+        let mut device = GenericNetworkDevice::new("test0");
+        device.init_network().unwrap();
+        let local_mac = device.get_mac_address().unwrap();
+        let interface = MANAGER.attach_device("test0", Arc::new(device), None, ethernet::ETHERNET_STAGE).unwrap();
+        let local = Ipv4Address::new(192, 0, 2, 25);
+        interface.add_ipv4_address(Ipv4Cidr::new(local, 24).unwrap()).unwrap();
+
+        let frame = |protocol: u8, payload: &[u8]| {
+            let mut packet = Ipv4Header::new(Ipv4Address::new(192, 0, 2, 30), local, protocol, payload.len()).to_bytes().to_vec();
+            packet.extend_from_slice(payload);
+            let header = EthernetHeader { destination: local_mac, source: MacAddress::new([2, 0, 0, 0, 0, 30]), ethertype: ETHERTYPE_IPV4 };
+            ethernet::build_frame(&header, &packet)
+        };
+        assert_eq!(MANAGER.receive(&interface, frame(253, b"experiment")), PacketFate::Dropped("Protocol unreachable"));
+
+        // A protocol a raw socket takes is no longer unreachable
+        let socket = RawSocket::new(IpFamily::V4, 253, true);
+        assert_eq!(MANAGER.receive(&interface, frame(253, b"experiment")), PacketFate::Consumed);
+        let mut buffer = [0; 64];
+        let received = socket.receive(&mut buffer).unwrap();
+        assert_eq!((buffer[0], &buffer[20..received.len]), (0x45, &b"experiment"[..]));
+        assert_eq!(received.from.as_deref(), Some("192.0.2.30:0"));
+
+        // The filter and the address bound narrow what it takes
+        socket.set_filter(Some(Arc::new(FilterExpression::parse("not src host 192.0.2.30").unwrap()))).unwrap();
+        MANAGER.receive(&interface, frame(253, b"filtered"));
+        socket.set_filter(None).unwrap();
+        socket.bind("192.0.2.26").unwrap();
+        MANAGER.receive(&interface, frame(253, b"elsewhere"));
+        assert_eq!(socket.receive(&mut buffer).err(), Some(KernelError::WouldBlock));
+        assert_eq!(socket.name().as_deref(), Some("192.0.2.26:0"));
+        assert_eq!(socket.connect("[2001:db8::1]:0"), Err(KernelError::InvalidArgument));
+    }
+}
@@ -0,0 +1,152 @@
+//! IPv4 routing table
+//!
+//! A route sends the packets for a network out of an interface, either
+//! straight to their destination (a route of a network the interface is
+//! on) or through a gateway. The most specific route wins; among routes of
+//! the same length, the one with the lowest metric. The default route is
+//! the one for 0.0.0.0/0.
+//!
+//! The table is that of the system: the routes of a network namespace are
+//! those through its interfaces.
+//!
+//! Routes of the networks of interface addresses are added and removed with
+//! the addresses by [`ipv4::add_address`](super::ipv4::add_address) and
+//! [`ipv4::remove_address`](super::ipv4::remove_address).
+
+use alloc::vec::Vec;
+use spin::RwLock;
+
+use crate::abi::error::KernelError;
+
+use super::address::{Ipv4Address, Ipv4Cidr};
+use super::InterfaceId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Route {
+    pub destination: Ipv4Cidr,
+    /// Router to send through; `None` if the destination is on the link
+    pub gateway: Option<Ipv4Address>,
+    pub interface: InterfaceId,
+    pub metric: u32,
+}
+
+impl Route {
+    /// Where a packet for `destination` is sent on the link
+    pub fn next_hop(&self, destination: Ipv4Address) -> Ipv4Address {
+        self.gateway.unwrap_or(destination)
+    }
+}
+
+pub struct RoutingTable {
+    routes: RwLock<Vec<Route>>,
+}
+
+static ROUTING_TABLE: RoutingTable = RoutingTable::new();
+
+/// The IPv4 routing table of the system
+pub fn routing_table() -> &'static RoutingTable {
+    &ROUTING_TABLE
+}
+
+impl RoutingTable {
+    pub const fn new() -> Self {
+        Self { routes: RwLock::new(Vec::new()) }
+    }
+
+    /// Add `route`; fails with `Exists` if there is one for the same
+    /// network through the same interface and gateway
+    pub fn add(&self, mut route: Route) -> Result<(), KernelError> {
+        route.destination = Ipv4Cidr::new(route.destination.network(), route.destination.prefix_len)?;
+        let mut routes = self.routes.write();
+        let same = |other: &Route| {
+            other.destination == route.destination && other.interface == route.interface && other.gateway == route.gateway
+        };
+        if routes.iter().any(same) {
+            return Err(KernelError::Exists);
+        }
+        routes.push(route);
+        Ok(())
+    }
+
+    /// Remove the routes for `destination`, only those through `interface`
+    /// if one is given
+    pub fn remove(&self, destination: Ipv4Cidr, interface: Option<InterfaceId>) -> Result<(), KernelError> {
+        let network = destination.network();
+        let mut routes = self.routes.write();
+        let before = routes.len();
+        routes.retain(|route| {
+            !(route.destination.network() == network
+                && route.destination.prefix_len == destination.prefix_len
+                && interface.map_or(true, |id| route.interface == id))
+        });
+        if routes.len() == before { Err(KernelError::NotFound) } else { Ok(()) }
+    }
+
+    /// Replace the default route with one through `gateway`
+    pub fn set_default_gateway(&self, gateway: Ipv4Address, interface: InterfaceId) -> Result<(), KernelError> {
+        let default = Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 0)?;
+        let _ = self.remove(default, None);
+        self.add(Route { destination: default, gateway: Some(gateway), interface, metric: 0 })
+    }
+
+    pub fn default_gateway(&self) -> Option<Route> {
+        self.routes.read().iter().filter(|route| route.destination.prefix_len == 0).min_by_key(|route| route.metric).copied()
+    }
+
+    /// Forget the routes through `interface`
+    pub fn flush_interface(&self, interface: InterfaceId) {
+        self.routes.write().retain(|route| route.interface != interface);
+    }
+
+    /// The route for `destination`
+    pub fn lookup(&self, destination: Ipv4Address) -> Option<Route> {
+        self.lookup_where(destination, |_| true)
+    }
+
+    /// The route for `destination` among those `usable` takes, such as the
+    /// routes of the interfaces of a namespace
+    pub fn lookup_where(&self, destination: Ipv4Address, usable: impl Fn(&Route) -> bool) -> Option<Route> {
+        self.routes
+            .read()
+            .iter()
+            .filter(|route| route.destination.contains(destination) && usable(route))
+            .max_by_key(|route| (route.destination.prefix_len, u32::MAX - route.metric))
+            .copied()
+    }
+
+    pub fn routes(&self) -> Vec<Route> {
+        self.routes.read().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_routing_lookup() {
+        let table = RoutingTable::new();
+        let lan = Ipv4Cidr::new(Ipv4Address::new(10, 0, 2, 15), 24).unwrap();
+        table.add(Route { destination: lan, gateway: None, interface: 0, metric: 0 }).unwrap();
+        assert_eq!(table.add(Route { destination: lan, gateway: None, interface: 0, metric: 5 }), Err(KernelError::Exists));
+        assert_eq!(table.lookup(Ipv4Address::new(8, 8, 8, 8)), None);
+
+        table.set_default_gateway(Ipv4Address::new(10, 0, 2, 2), 0).unwrap();
+        table.set_default_gateway(Ipv4Address::new(10, 0, 2, 1), 0).unwrap();
+        assert_eq!(table.routes().len(), 2);
+        let route = table.lookup(Ipv4Address::new(8, 8, 8, 8)).unwrap();
+        assert_eq!(route.next_hop(Ipv4Address::new(8, 8, 8, 8)), Ipv4Address::new(10, 0, 2, 1));
+        let route = table.lookup(Ipv4Address::new(10, 0, 2, 3)).unwrap();
+        assert_eq!(route.next_hop(Ipv4Address::new(10, 0, 2, 3)), Ipv4Address::new(10, 0, 2, 3));
+
+        // Among routes of the same length the lower metric wins
+        let other = Ipv4Cidr::new(Ipv4Address::new(10, 0, 2, 0), 24).unwrap();
+        table.add(Route { destination: other, gateway: None, interface: 1, metric: 10 }).unwrap();
+        assert_eq!(table.lookup(Ipv4Address::new(10, 0, 2, 3)).unwrap().interface, 0);
+        table.flush_interface(0);
+        assert_eq!(table.lookup(Ipv4Address::new(10, 0, 2, 3)).unwrap().interface, 1);
+        assert_eq!(table.default_gateway(), None);
+        assert_eq!(table.remove(lan, Some(0)), Err(KernelError::NotFound));
+        table.remove(lan, None).unwrap();
+    }
+}
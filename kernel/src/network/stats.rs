@@ -0,0 +1,371 @@
+//! Network statistics
+//!
+//! Every interface counts the packets and bytes it receives and sends and
+//! those lost on the way, and the protocols count what they take in and
+//! send out in [`Counter`]s. Both are published in procfs as `net/dev` and
+//! `net/snmp`, in the layouts of Linux `/proc/net/dev` and
+//! `/proc/net/snmp`, so monitoring tools that read those work unchanged.
+//! TCP and UDP count segments and datagrams of both IP versions, IP and
+//! ICMP only those of IPv4, as Linux does.
+//!
+//! The counters are lock-free and updated on the packet path.
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use alloc::string::String;
+
+use super::manager::get_network_manager;
+use super::{icmp, ipv4, namespace, tcp};
+
+/// Live counters of an interface
+pub struct InterfaceStats {
+    rx_packets: AtomicU64,
+    rx_bytes: AtomicU64,
+    rx_errors: AtomicU64,
+    rx_dropped: AtomicU64,
+    tx_packets: AtomicU64,
+    tx_bytes: AtomicU64,
+    tx_errors: AtomicU64,
+    tx_dropped: AtomicU64,
+}
+
+/// Point-in-time copy of the counters of an interface
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InterfaceStatsSnapshot {
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+    /// Times the device failed to hand over received packets
+    pub rx_errors: u64,
+    /// Received packets the stack dropped, also while the interface is down
+    pub rx_dropped: u64,
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
+    /// Packets the device failed to send
+    pub tx_errors: u64,
+    /// Packets not sent because the interface is down
+    pub tx_dropped: u64,
+}
+
+impl InterfaceStats {
+    pub const fn new() -> Self {
+        Self {
+            rx_packets: AtomicU64::new(0),
+            rx_bytes: AtomicU64::new(0),
+            rx_errors: AtomicU64::new(0),
+            rx_dropped: AtomicU64::new(0),
+            tx_packets: AtomicU64::new(0),
+            tx_bytes: AtomicU64::new(0),
+            tx_errors: AtomicU64::new(0),
+            tx_dropped: AtomicU64::new(0),
+        }
+    }
+
+    pub fn record_rx(&self, bytes: usize) {
+        self.rx_packets.fetch_add(1, Ordering::Relaxed);
+        self.rx_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_rx_error(&self) {
+        self.rx_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_rx_dropped(&self) {
+        self.rx_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a packet handed to the device, `sent` if it took it
+    pub fn record_tx(&self, bytes: usize, sent: bool) {
+        if sent {
+            self.tx_packets.fetch_add(1, Ordering::Relaxed);
+            self.tx_bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+        } else {
+            self.tx_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn record_tx_dropped(&self) {
+        self.tx_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> InterfaceStatsSnapshot {
+        InterfaceStatsSnapshot {
+            rx_packets: self.rx_packets.load(Ordering::Relaxed),
+            rx_bytes: self.rx_bytes.load(Ordering::Relaxed),
+            rx_errors: self.rx_errors.load(Ordering::Relaxed),
+            rx_dropped: self.rx_dropped.load(Ordering::Relaxed),
+            tx_packets: self.tx_packets.load(Ordering::Relaxed),
+            tx_bytes: self.tx_bytes.load(Ordering::Relaxed),
+            tx_errors: self.tx_errors.load(Ordering::Relaxed),
+            tx_dropped: self.tx_dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A counter of a protocol, named after its field in `/proc/net/snmp`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Counter {
+    IpInReceives,
+    IpInHdrErrors,
+    IpInAddrErrors,
+    IpInUnknownProtos,
+    IpInDiscards,
+    IpInDelivers,
+    IpOutRequests,
+    IpOutDiscards,
+    IpOutNoRoutes,
+    IpReasmReqds,
+    IpReasmOKs,
+    IpReasmFails,
+    IpFragOKs,
+    IpFragFails,
+    IpFragCreates,
+    IcmpInMsgs,
+    IcmpInErrors,
+    IcmpInCsumErrors,
+    IcmpInDestUnreachs,
+    IcmpInTimeExcds,
+    IcmpInParmProbs,
+    IcmpInEchos,
+    IcmpInEchoReps,
+    IcmpOutMsgs,
+    IcmpOutErrors,
+    IcmpOutDestUnreachs,
+    IcmpOutTimeExcds,
+    IcmpOutParmProbs,
+    IcmpOutEchos,
+    IcmpOutEchoReps,
+    TcpActiveOpens,
+    TcpPassiveOpens,
+    TcpAttemptFails,
+    TcpEstabResets,
+    TcpInSegs,
+    TcpOutSegs,
+    TcpRetransSegs,
+    TcpInErrs,
+    TcpOutRsts,
+    TcpInCsumErrors,
+    UdpInDatagrams,
+    UdpNoPorts,
+    UdpInErrors,
+    UdpOutDatagrams,
+    UdpRcvbufErrors,
+    UdpSndbufErrors,
+    UdpInCsumErrors,
+}
+
+const COUNTER_COUNT: usize = Counter::UdpInCsumErrors as usize + 1;
+
+static COUNTERS: [AtomicU64; COUNTER_COUNT] = [const { AtomicU64::new(0) }; COUNTER_COUNT];
+
+/// Add one to `counter`
+pub fn count(counter: Counter) {
+    add(counter, 1);
+}
+
+pub fn add(counter: Counter, value: u64) {
+    COUNTERS[counter as usize].fetch_add(value, Ordering::Relaxed);
+}
+
+pub fn get(counter: Counter) -> u64 {
+    COUNTERS[counter as usize].load(Ordering::Relaxed)
+}
+
+/// Count a received ICMP message of `icmp_type`
+pub fn count_icmp_in(icmp_type: u8) {
+    count(Counter::IcmpInMsgs);
+    let counter = match icmp_type {
+        icmp::ICMP_DEST_UNREACHABLE => Counter::IcmpInDestUnreachs,
+        icmp::ICMP_TIME_EXCEEDED => Counter::IcmpInTimeExcds,
+        icmp::ICMP_PARAMETER_PROBLEM => Counter::IcmpInParmProbs,
+        icmp::ICMP_ECHO_REQUEST => Counter::IcmpInEchos,
+        icmp::ICMP_ECHO_REPLY => Counter::IcmpInEchoReps,
+        _ => return,
+    };
+    count(counter);
+}
+
+/// Count an ICMP message of `icmp_type` sent, or that could not be
+pub fn count_icmp_out(icmp_type: u8, sent: bool) {
+    if !sent {
+        return count(Counter::IcmpOutErrors);
+    }
+    count(Counter::IcmpOutMsgs);
+    let counter = match icmp_type {
+        icmp::ICMP_DEST_UNREACHABLE => Counter::IcmpOutDestUnreachs,
+        icmp::ICMP_TIME_EXCEEDED => Counter::IcmpOutTimeExcds,
+        icmp::ICMP_PARAMETER_PROBLEM => Counter::IcmpOutParmProbs,
+        icmp::ICMP_ECHO_REQUEST => Counter::IcmpOutEchos,
+        icmp::ICMP_ECHO_REPLY => Counter::IcmpOutEchoReps,
+        _ => return,
+    };
+    count(counter);
+}
+
+/// A field of `/proc/net/snmp`
+enum Field {
+    Counter(Counter),
+    Constant(i64),
+    Gauge(fn() -> u64),
+}
+
+/// The groups of `/proc/net/snmp`, in its order
+const SNMP_GROUPS: [(&str, &[(&str, Field)]); 4] = [
+    ("Ip", &[
+        // Datagrams are not forwarded
+        ("Forwarding", Field::Constant(2)),
+        ("DefaultTTL", Field::Constant(ipv4::DEFAULT_TTL as i64)),
+        ("InReceives", Field::Counter(Counter::IpInReceives)),
+        ("InHdrErrors", Field::Counter(Counter::IpInHdrErrors)),
+        ("InAddrErrors", Field::Counter(Counter::IpInAddrErrors)),
+        ("ForwDatagrams", Field::Constant(0)),
+        ("InUnknownProtos", Field::Counter(Counter::IpInUnknownProtos)),
+        ("InDiscards", Field::Counter(Counter::IpInDiscards)),
+        ("InDelivers", Field::Counter(Counter::IpInDelivers)),
+        ("OutRequests", Field::Counter(Counter::IpOutRequests)),
+        ("OutDiscards", Field::Counter(Counter::IpOutDiscards)),
+        ("OutNoRoutes", Field::Counter(Counter::IpOutNoRoutes)),
+        ("ReasmTimeout", Field::Constant((ipv4::REASSEMBLY_TIMEOUT_MS / 1000) as i64)),
+        ("ReasmReqds", Field::Counter(Counter::IpReasmReqds)),
+        ("ReasmOKs", Field::Counter(Counter::IpReasmOKs)),
+        ("ReasmFails", Field::Counter(Counter::IpReasmFails)),
+        ("FragOKs", Field::Counter(Counter::IpFragOKs)),
+        ("FragFails", Field::Counter(Counter::IpFragFails)),
+        ("FragCreates", Field::Counter(Counter::IpFragCreates)),
+    ]),
+    ("Icmp", &[
+        ("InMsgs", Field::Counter(Counter::IcmpInMsgs)),
+        ("InErrors", Field::Counter(Counter::IcmpInErrors)),
+        ("InCsumErrors", Field::Counter(Counter::IcmpInCsumErrors)),
+        ("InDestUnreachs", Field::Counter(Counter::IcmpInDestUnreachs)),
+        ("InTimeExcds", Field::Counter(Counter::IcmpInTimeExcds)),
+        ("InParmProbs", Field::Counter(Counter::IcmpInParmProbs)),
+        ("InEchos", Field::Counter(Counter::IcmpInEchos)),
+        ("InEchoReps", Field::Counter(Counter::IcmpInEchoReps)),
+        ("OutMsgs", Field::Counter(Counter::IcmpOutMsgs)),
+        ("OutErrors", Field::Counter(Counter::IcmpOutErrors)),
+        ("OutDestUnreachs", Field::Counter(Counter::IcmpOutDestUnreachs)),
+        ("OutTimeExcds", Field::Counter(Counter::IcmpOutTimeExcds)),
+        ("OutParmProbs", Field::Counter(Counter::IcmpOutParmProbs)),
+        ("OutEchos", Field::Counter(Counter::IcmpOutEchos)),
+        ("OutEchoReps", Field::Counter(Counter::IcmpOutEchoReps)),
+    ]),
+    ("Tcp", &[
+        // Van Jacobson's algorithm; connections are not limited
+        ("RtoAlgorithm", Field::Constant(4)),
+        ("RtoMin", Field::Constant(tcp::MIN_RTO_MS as i64)),
+        ("RtoMax", Field::Constant(tcp::MAX_RTO_MS as i64)),
+        ("MaxConn", Field::Constant(-1)),
+        ("ActiveOpens", Field::Counter(Counter::TcpActiveOpens)),
+        ("PassiveOpens", Field::Counter(Counter::TcpPassiveOpens)),
+        ("AttemptFails", Field::Counter(Counter::TcpAttemptFails)),
+        ("EstabResets", Field::Counter(Counter::TcpEstabResets)),
+        ("CurrEstab", Field::Gauge(tcp::established_count)),
+        ("InSegs", Field::Counter(Counter::TcpInSegs)),
+        ("OutSegs", Field::Counter(Counter::TcpOutSegs)),
+        ("RetransSegs", Field::Counter(Counter::TcpRetransSegs)),
+        ("InErrs", Field::Counter(Counter::TcpInErrs)),
+        ("OutRsts", Field::Counter(Counter::TcpOutRsts)),
+        ("InCsumErrors", Field::Counter(Counter::TcpInCsumErrors)),
+    ]),
+    ("Udp", &[
+        ("InDatagrams", Field::Counter(Counter::UdpInDatagrams)),
+        ("NoPorts", Field::Counter(Counter::UdpNoPorts)),
+        ("InErrors", Field::Counter(Counter::UdpInErrors)),
+        ("OutDatagrams", Field::Counter(Counter::UdpOutDatagrams)),
+        ("RcvbufErrors", Field::Counter(Counter::UdpRcvbufErrors)),
+        ("SndbufErrors", Field::Counter(Counter::UdpSndbufErrors)),
+        ("InCsumErrors", Field::Counter(Counter::UdpInCsumErrors)),
+    ]),
+];
+
+/// Render `net/dev`: the counters of the interfaces in the namespace of
+/// the reader
+pub fn format_interface_stats() -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "Inter-|   Receive                                                |  Transmit");
+    let _ = writeln!(
+        out,
+        " face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed"
+    );
+    for interface in get_network_manager().interfaces_in(namespace::current().id()) {
+        let stats = interface.stats();
+        let _ = writeln!(
+            out,
+            "{:>6}:{:>8} {:>7} {:>4} {:>4} {:>4} {:>5} {:>10} {:>9} {:>8} {:>7} {:>4} {:>4} {:>4} {:>5} {:>7} {:>10}",
+            interface.name(),
+            stats.rx_bytes,
+            stats.rx_packets,
+            stats.rx_errors,
+            stats.rx_dropped,
+            0,
+            0,
+            0,
+            0,
+            stats.tx_bytes,
+            stats.tx_packets,
+            stats.tx_errors,
+            stats.tx_dropped,
+            0,
+            0,
+            0,
+            0
+        );
+    }
+    out
+}
+
+/// Render `net/snmp`: for every protocol a line of field names and a line
+/// of their values
+pub fn format_protocol_stats() -> String {
+    let mut out = String::new();
+    for (group, fields) in SNMP_GROUPS.iter() {
+        let _ = write!(out, "{}:", group);
+        for (name, _) in fields.iter() {
+            let _ = write!(out, " {}", name);
+        }
+        let _ = write!(out, "\n{}:", group);
+        for (_, field) in fields.iter() {
+            let value = match field {
+                Field::Counter(counter) => get(*counter) as i64,
+                Field::Constant(value) => *value,
+                Field::Gauge(read) => read() as i64,
+            };
+            let _ = write!(out, " {}", value);
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_network_stats_format() {
+        let before = get(Counter::UdpNoPorts);
+        count(Counter::UdpNoPorts);
+        assert_eq!(get(Counter::UdpNoPorts), before + 1);
+
+        let snmp = format_protocol_stats();
+        let lines: alloc::vec::Vec<&str> = snmp.lines().collect();
+        assert_eq!(lines.len(), 2 * SNMP_GROUPS.len());
+        let names: alloc::vec::Vec<&str> = lines[6].split(' ').collect();
+        let values: alloc::vec::Vec<&str> = lines[7].split(' ').collect();
+        assert_eq!((names[0], values[0]), ("Udp:", "Udp:"));
+        assert_eq!(names.len(), values.len());
+        let no_ports = names.iter().position(|name| *name == "NoPorts").unwrap();
+        assert!(values[no_ports].parse::<u64>().unwrap() > before);
+
+        let stats = InterfaceStats::new();
+        stats.record_rx(60);
+        stats.record_tx(1500, true);
+        stats.record_tx(1500, false);
+        stats.record_tx_dropped();
+        let snapshot = stats.snapshot();
+        assert_eq!((snapshot.rx_packets, snapshot.rx_bytes), (1, 60));
+        assert_eq!((snapshot.tx_packets, snapshot.tx_bytes, snapshot.tx_errors, snapshot.tx_dropped), (1, 1500, 1, 1));
+        assert!(format_interface_stats().starts_with("Inter-|"));
+    }
+}
@@ -0,0 +1,33 @@
+//! Network system calls
+//!
+//! Native interface of the network configuration: a task opens a
+//! [`NetConfigObject`] and writes requests to it and reads the replies
+//! through the stream system calls.
+
+use crate::{
+    abi::error::{fail, KernelError},
+    arch::Trapframe,
+    object::KernelObject,
+    task::mytask,
+};
+
+use super::netconfig::NetConfigObject;
+
+/// sys_netconfig_open - Open a channel to the network configuration
+///
+/// Anyone may list the configuration; requests that change it take
+/// privilege.
+///
+/// Returns: the handle of the channel, usize::MAX on error
+pub fn sys_netconfig_open(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+    trapframe.increment_pc_next(task);
+
+    match task.handle_table.write().insert(KernelObject::from_netconfig(NetConfigObject::new())) {
+        Ok(handle) => handle as usize,
+        Err(_) => fail(KernelError::TooManyHandles),
+    }
+}
@@ -0,0 +1,283 @@
+//! TAP devices
+//!
+//! A TAP device is an interface whose wire is a character device: every
+//! frame the stack sends on the interface `NAME` is read whole from
+//! `/dev/NAME`, and every write to `/dev/NAME` is a frame received on the
+//! interface. The program holding the device, such as an emulator or a
+//! VPN, is the host at the other end. A reader blocks until a frame is
+//! sent; frames beyond [`TAP_QUEUE_LEN`] unread ones are dropped.
+
+use core::any::Any;
+
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::{Mutex, Once};
+
+use crate::abi::error::KernelError;
+use crate::device::char::CharDevice;
+use crate::device::manager::DeviceManager;
+use crate::device::network::{
+    DevicePacket, MacAddress, NetworkDevice, NetworkInterfaceConfig, NetworkStats,
+};
+use crate::device::{Device, DeviceType};
+use crate::object::capability::{ControlOps, MemoryMappingOps};
+use crate::sync::waker::Waker;
+use crate::task::mytask;
+
+use super::bridge;
+use super::ethernet::{ETHERNET_HEADER_LEN, ETHERNET_STAGE};
+use super::manager::{get_network_manager, NetworkInterface};
+
+/// MTU of a TAP interface
+pub const TAP_MTU: usize = 1500;
+
+/// Most frames sent by the stack and not read yet
+pub const TAP_QUEUE_LEN: usize = 256;
+
+/// A TAP device, both the network device of its interface and the
+/// character device of its reader
+pub struct TapDevice {
+    mac: MacAddress,
+    /// Id of the character device in the device manager
+    device_id: Once<usize>,
+    /// Frames sent by the stack, for the reader
+    outgoing: Mutex<VecDeque<Vec<u8>>>,
+    /// Frames written by the reader, for the stack
+    incoming: Mutex<Vec<DevicePacket>>,
+    /// Readers waiting for a frame
+    waker: Waker,
+    stats: Mutex<NetworkStats>,
+}
+
+impl TapDevice {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            mac: MacAddress::random_local(),
+            device_id: Once::new(),
+            outgoing: Mutex::new(VecDeque::new()),
+            incoming: Mutex::new(Vec::new()),
+            waker: Waker::new_interruptible("tap"),
+            stats: Mutex::new(NetworkStats::default()),
+        })
+    }
+}
+
+impl Device for TapDevice {
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Char
+    }
+
+    fn name(&self) -> &'static str {
+        "tap"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn as_char_device(&self) -> Option<&dyn CharDevice> {
+        Some(self)
+    }
+}
+
+impl CharDevice for TapDevice {
+    fn read_byte(&self) -> Option<u8> {
+        // Frames are read whole
+        None
+    }
+
+    fn write_byte(&self, _byte: u8) -> Result<(), &'static str> {
+        Err("Frames are written whole")
+    }
+
+    /// Read the next frame sent on the interface, blocking while there is
+    /// none; a frame longer than `buffer` is cut
+    fn read(&self, buffer: &mut [u8]) -> usize {
+        if buffer.is_empty() {
+            return 0;
+        }
+        loop {
+            if let Some(frame) = self.outgoing.lock().pop_front() {
+                let len = frame.len().min(buffer.len());
+                buffer[..len].copy_from_slice(&frame[..len]);
+                return len;
+            }
+            let Some(task) = mytask() else {
+                return 0;
+            };
+            let task_id = task.get_id();
+            self.waker.wait_unless(task_id, task.get_trapframe(), || {
+                !self.outgoing.lock().is_empty() || mytask().is_some_and(|task| task.signals.has_pending())
+            });
+            if task.signals.has_pending() {
+                return 0;
+            }
+        }
+    }
+
+    /// Receive `buffer` as one frame on the interface
+    fn write(&self, buffer: &[u8]) -> Result<usize, &'static str> {
+        if buffer.len() < ETHERNET_HEADER_LEN || buffer.len() > ETHERNET_HEADER_LEN + TAP_MTU {
+            return Err("Not an Ethernet frame");
+        }
+        {
+            let mut stats = self.stats.lock();
+            stats.rx_packets += 1;
+            stats.rx_bytes += buffer.len() as u64;
+        }
+        self.incoming.lock().push(DevicePacket::with_data(buffer.to_vec()));
+        get_network_manager().notify_rx();
+        Ok(buffer.len())
+    }
+
+    fn can_read(&self) -> bool {
+        !self.outgoing.lock().is_empty()
+    }
+
+    fn can_write(&self) -> bool {
+        true
+    }
+}
+
+impl ControlOps for TapDevice {
+    fn control(&self, _command: u32, _arg: usize) -> Result<i32, &'static str> {
+        Err("Control operations not supported")
+    }
+}
+
+impl MemoryMappingOps for TapDevice {
+    fn get_mapping_info(&self, _offset: usize, _length: usize)
+                       -> Result<(usize, usize, bool), &'static str> {
+        Err("Memory mapping not supported by TAP devices")
+    }
+
+    fn on_mapped(&self, _vaddr: usize, _paddr: usize, _length: usize, _offset: usize) {}
+
+    fn on_unmapped(&self, _vaddr: usize, _length: usize) {}
+
+    fn supports_mmap(&self) -> bool {
+        false
+    }
+}
+
+impl NetworkDevice for TapDevice {
+    fn get_interface_name(&self) -> &'static str {
+        "tap"
+    }
+
+    fn get_mac_address(&self) -> Result<MacAddress, &'static str> {
+        Ok(self.mac)
+    }
+
+    fn get_mtu(&self) -> Result<usize, &'static str> {
+        Ok(TAP_MTU)
+    }
+
+    fn get_interface_config(&self) -> Result<NetworkInterfaceConfig, &'static str> {
+        Ok(NetworkInterfaceConfig::new(self.mac, TAP_MTU, "tap"))
+    }
+
+    /// Queue the frame for the reader
+    fn send_packet(&self, packet: DevicePacket) -> Result<(), &'static str> {
+        let mut outgoing = self.outgoing.lock();
+        let mut stats = self.stats.lock();
+        if outgoing.len() >= TAP_QUEUE_LEN {
+            stats.dropped += 1;
+            return Err("TAP queue full");
+        }
+        stats.tx_packets += 1;
+        stats.tx_bytes += packet.len() as u64;
+        outgoing.push_back(packet.into_data());
+        drop(stats);
+        drop(outgoing);
+        self.waker.wake_all();
+        Ok(())
+    }
+
+    fn receive_packets(&self) -> Result<Vec<DevicePacket>, &'static str> {
+        Ok(core::mem::take(&mut *self.incoming.lock()))
+    }
+
+    fn set_promiscuous_mode(&self, _enabled: bool) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn init_network(&mut self) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn is_link_up(&self) -> bool {
+        true
+    }
+
+    fn get_stats(&self) -> NetworkStats {
+        self.stats.lock().clone()
+    }
+}
+
+/// The TAP device of `interface`, if it is one
+fn tap_of(interface: &NetworkInterface) -> Option<&TapDevice> {
+    interface.device().as_any().downcast_ref::<TapDevice>()
+}
+
+pub fn is_tap(interface: &NetworkInterface) -> bool {
+    tap_of(interface).is_some()
+}
+
+/// Create the TAP interface `name` and its device `/dev/name`
+pub fn create(name: &str) -> Result<Arc<NetworkInterface>, KernelError> {
+    let device_manager = DeviceManager::get_manager();
+    let manager = get_network_manager();
+    if device_manager.get_device_by_name(name).is_some() || manager.interface_by_name(name).is_some() {
+        return Err(KernelError::Exists);
+    }
+    let tap = TapDevice::new();
+    let device_id = device_manager.register_device_with_name(name.into(), tap.clone());
+    tap.device_id.call_once(|| device_id);
+    manager.attach_device(name, tap, Some(device_id), ETHERNET_STAGE).inspect_err(|_| {
+        device_manager.unregister_device(device_id);
+    })
+}
+
+/// Detach the TAP interface `interface` and remove its device
+pub fn delete(interface: &Arc<NetworkInterface>) -> Result<(), KernelError> {
+    let device_id = tap_of(interface).ok_or(KernelError::InvalidArgument)?.device_id.get().copied();
+    bridge::release(interface);
+    get_network_manager().detach(interface.id())?;
+    if let Some(device_id) = device_id {
+        DeviceManager::get_manager().unregister_device(device_id);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test_case]
+    fn test_tap_frames() {
+        let tap = TapDevice::new();
+        // What the stack sends is read whole, or cut to the buffer
+        tap.send_packet(DevicePacket::with_data(vec![7; 60])).unwrap();
+        tap.send_packet(DevicePacket::with_data(vec![8; 60])).unwrap();
+        assert!(tap.can_read());
+        let mut buffer = [0u8; 100];
+        assert_eq!(CharDevice::read(&*tap, &mut buffer), 60);
+        assert_eq!(CharDevice::read(&*tap, &mut buffer[..10]), 10);
+        assert_eq!(buffer[..10], [8; 10]);
+        assert!(!tap.can_read());
+
+        // What is written is received, frames only
+        assert_eq!(CharDevice::write(&*tap, &[1; 60]), Ok(60));
+        assert!(CharDevice::write(&*tap, &[1; 4]).is_err());
+        let received = tap.receive_packets().unwrap();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].as_slice(), &[1; 60]);
+    }
+}
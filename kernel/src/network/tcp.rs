@@ -0,0 +1,1928 @@
+//! TCP
+//!
+//! The TCP processor sits at the "ipv4" stage under protocol 6, and at the
+//! "ipv6" stage under next header 6, and hands
+//! each segment to the connection of its endpoints, or to the listener of
+//! its destination port when it opens a connection. Connections follow the
+//! state machine of RFC 793:
+//!
+//! - Connections are opened by a three-way handshake, actively by
+//!   `connect` or passively through a listener. A listener queues the
+//!   connections it completes for `accept`, at most `backlog` of them,
+//!   counting those still in the handshake.
+//! - Sent data stays buffered until it is acknowledged. Segments are sent
+//!   as far as the window of the peer allows, and the receive window
+//!   advertised is the room left in the receive buffer.
+//! - A segment not acknowledged within the retransmission timeout is sent
+//!   again from the first unacknowledged byte, and the timeout doubles. The
+//!   timeout is estimated from round trip times as RFC 6298 describes
+//!   (Karn's algorithm: retransmitted segments are not timed). The same
+//!   timer probes a zero window. The connection fails with `TimedOut`
+//!   after [`MAX_RETRANSMISSIONS`].
+//! - Segments received out of order are kept until the gap is filled.
+//! - A segment shorter than the segment size waits while data is
+//!   unacknowledged (Nagle's algorithm), unless the socket asks for no
+//!   delay. With keepalive on, an idle connection is probed, and fails
+//!   with `TimedOut` once the probes go unanswered.
+//!
+//! The options of a socket ([`SocketOption`]) also size its buffers and let
+//! it bind a port still held by connections in TIME-WAIT; the connections
+//! accepted by a listener take its options.
+//!
+//! A connection outlives its socket: closing the socket sends a FIN once
+//! the buffered data is out, and the connection goes through the closing
+//! states, TIME-WAIT included, on its own. Closing a socket with unread
+//! data resets the connection instead.
+//!
+//! A [`TcpSocket`] is a stream socket of `crate::ipc::socket`, named by its
+//! `a.b.c.d:port` or `[v6]:port` endpoint; a socket of IPv6 listening on
+//! :: takes the connections to the IPv4 addresses too. Connections opened by a peer send through the
+//! interface their SYN came in on; others follow the routing table. Ports
+//! and connections are kept apart in each network namespace. There
+//! is no window scaling, congestion control, selective acknowledgment or
+//! urgent data.
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU16, Ordering};
+use spin::{Mutex, RwLock};
+
+use crate::abi::error::KernelError;
+use crate::ipc::socket::{Received, Shutdown, SocketDomain, SocketObject, SocketOption, SocketType, SOCKET_BUFFER_SIZE, SOCKET_MAX_BACKLOG};
+use crate::ipc::StreamIpcOps;
+use crate::object::capability::poll::{wait_for, PollOps, PollWait, POLLERR, POLLHUP, POLLIN, POLLOUT};
+use crate::object::capability::{StreamError, StreamOps};
+use crate::object::KernelObject;
+use crate::random::get_random_u64;
+use crate::sync::waker::Waker;
+use crate::timer::{get_tick, ms_to_ticks};
+
+use super::address::{IpAddress, IpEndpoint, IpFamily, Ipv4Address};
+use super::checksum::{checksum, Checksum};
+use super::icmp::{ICMP_DEST_UNREACHABLE, ICMP_ERROR_STAGE, META_ICMP_CODE, META_ICMP_TYPE, UNREACH_PORT, UNREACH_PROTOCOL};
+use super::ip::{self, SendOptions};
+use super::ipv4::{IPV4_HEADER_LEN, IPV4_MAX_DATAGRAM_LEN, IPV4_STAGE, PROTOCOL_TCP};
+use super::ipv6::{IPV6_HEADER_LEN, IPV6_STAGE};
+use super::manager::{NetworkInterface, NetworkManager};
+use super::namespace::{self, NetNamespace, NetNamespaceId, INIT_NET_NS};
+use super::packet::{NetworkPacket, META_CHECKSUM_VERIFIED};
+use super::pipeline::{PacketProcessor, ProcessResult};
+use super::stats::{self, Counter};
+
+pub const TCP_HEADER_LEN: usize = 20;
+
+/// Control flags
+pub const TCP_FIN: u8 = 0x01;
+pub const TCP_SYN: u8 = 0x02;
+pub const TCP_RST: u8 = 0x04;
+pub const TCP_PSH: u8 = 0x08;
+pub const TCP_ACK: u8 = 0x10;
+
+/// Segment size sent to a peer that gives none (RFC 1122)
+pub const DEFAULT_MSS: usize = 536;
+/// Segment size advertised: an Ethernet frame less the IPv4 and TCP headers
+pub const ADVERTISED_MSS: usize = 1460;
+/// Segment size advertised over IPv6, whose header is longer
+pub const ADVERTISED_MSS6: usize = ADVERTISED_MSS - (IPV6_HEADER_LEN - IPV4_HEADER_LEN);
+/// Longest payload of a segment the device cuts up
+const TSO_MAX_LEN: usize = IPV4_MAX_DATAGRAM_LEN - IPV4_HEADER_LEN - TCP_HEADER_LEN;
+/// Bytes a connection buffers each way unless its socket sizes them; the
+/// window ends at 65535 without window scaling
+pub const TCP_BUFFER_SIZE: usize = SOCKET_BUFFER_SIZE;
+
+pub const INITIAL_RTO_MS: u64 = 1000;
+pub const MIN_RTO_MS: u64 = 200;
+pub const MAX_RTO_MS: u64 = 60_000;
+/// Timeouts in a row after which a connection is given up
+pub const MAX_RETRANSMISSIONS: u32 = 8;
+/// Twice the maximum segment lifetime
+pub const TIME_WAIT_MS: u64 = 60_000;
+/// How long a closed socket waits in FIN-WAIT-2 for the peer to close
+pub const FIN_WAIT2_TIMEOUT_MS: u64 = 60_000;
+
+/// Segments received out of order kept per connection
+const MAX_OUT_OF_ORDER: usize = 64;
+
+/// Bounds of the buffer sizes a socket sets
+pub const TCP_MIN_BUFFER_SIZE: usize = 2048;
+pub const TCP_MAX_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// Keepalive defaults of RFC 1122 and Linux: two hours idle, then nine
+/// probes 75 seconds apart
+pub const KEEPALIVE_IDLE_S: u64 = 7200;
+pub const KEEPALIVE_INTERVAL_S: u64 = 75;
+pub const KEEPALIVE_COUNT: u32 = 9;
+/// Bound of the keepalive times and count a socket sets
+const KEEPALIVE_MAX: u64 = 32767;
+
+/// Ports taken when a socket does not choose one (RFC 6335)
+pub const EPHEMERAL_PORTS: core::ops::RangeInclusive<u16> = 49152..=65535;
+
+/// The segment size advertised to a peer at `address`, and the largest one
+/// sent to it
+fn advertised_mss(address: IpAddress) -> usize {
+    match address.family() {
+        IpFamily::V4 => ADVERTISED_MSS,
+        IpFamily::V6 => ADVERTISED_MSS6,
+    }
+}
+
+/// An endpoint not yet known
+const UNSPECIFIED: IpEndpoint = IpEndpoint::new(IpAddress::V4(Ipv4Address::UNSPECIFIED), 0);
+
+/// `a` comes before `b` in sequence space
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+fn seq_le(a: u32, b: u32) -> bool {
+    !seq_lt(b, a)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpHeader {
+    pub source_port: u16,
+    pub destination_port: u16,
+    pub seq: u32,
+    pub ack: u32,
+    /// Length with options; computed when building a segment
+    pub header_len: usize,
+    pub flags: u8,
+    pub window: u16,
+    /// Maximum segment size option
+    pub mss: Option<u16>,
+}
+
+impl TcpHeader {
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < TCP_HEADER_LEN {
+            return None;
+        }
+        let header_len = (bytes[12] >> 4) as usize * 4;
+        if header_len < TCP_HEADER_LEN || header_len > bytes.len() {
+            return None;
+        }
+        let mut mss = None;
+        let mut offset = TCP_HEADER_LEN;
+        while offset < header_len {
+            match bytes[offset] {
+                0 => break,
+                1 => offset += 1,
+                kind => {
+                    let len = *bytes.get(offset + 1)? as usize;
+                    if len < 2 || offset + len > header_len {
+                        return None;
+                    }
+                    if kind == 2 && len == 4 {
+                        mss = Some(u16::from_be_bytes([bytes[offset + 2], bytes[offset + 3]]));
+                    }
+                    offset += len;
+                }
+            }
+        }
+        Some(Self {
+            source_port: u16::from_be_bytes([bytes[0], bytes[1]]),
+            destination_port: u16::from_be_bytes([bytes[2], bytes[3]]),
+            seq: u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+            ack: u32::from_be_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]),
+            header_len,
+            flags: bytes[13] & 0x3F,
+            window: u16::from_be_bytes([bytes[14], bytes[15]]),
+            mss,
+        })
+    }
+
+    pub fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+}
+
+/// A segment from `source` to `destination` with `header` and `payload`,
+/// checksum filled in
+pub fn build_segment(source: IpAddress, destination: IpAddress, header: &TcpHeader, payload: &[u8]) -> Vec<u8> {
+    let mut segment = build_partial_segment(source, destination, header, payload);
+    let checksum = checksum(&segment);
+    segment[16..18].copy_from_slice(&checksum.to_be_bytes());
+    segment
+}
+
+/// A segment like [`build_segment`], with only the sum of the
+/// pseudo-header in its checksum for the device or `ip::send` to complete
+pub fn build_partial_segment(source: IpAddress, destination: IpAddress, header: &TcpHeader, payload: &[u8]) -> Vec<u8> {
+    let header_len = TCP_HEADER_LEN + if header.mss.is_some() { 4 } else { 0 };
+    let mut segment = Vec::with_capacity(header_len + payload.len());
+    segment.extend_from_slice(&header.source_port.to_be_bytes());
+    segment.extend_from_slice(&header.destination_port.to_be_bytes());
+    segment.extend_from_slice(&header.seq.to_be_bytes());
+    segment.extend_from_slice(&header.ack.to_be_bytes());
+    segment.extend_from_slice(&[((header_len / 4) as u8) << 4, header.flags]);
+    segment.extend_from_slice(&header.window.to_be_bytes());
+    segment.extend_from_slice(&[0, 0, 0, 0]);
+    if let Some(mss) = header.mss {
+        segment.extend_from_slice(&[2, 4]);
+        segment.extend_from_slice(&mss.to_be_bytes());
+    }
+    segment.extend_from_slice(payload);
+    let mut sum = Checksum::new();
+    sum.add_ip_pseudo_header(source, destination, PROTOCOL_TCP, segment.len());
+    segment[16..18].copy_from_slice(&sum.partial().to_be_bytes());
+    segment
+}
+
+/// Cut a partial segment into segments carrying at most `segment_size`
+/// bytes each, for a device that cannot
+fn split_segment(source: IpAddress, destination: IpAddress, segment: &[u8], segment_size: usize) -> Vec<Vec<u8>> {
+    let Some(header) = TcpHeader::parse(segment) else {
+        return alloc::vec![segment.to_vec()];
+    };
+    let data = &segment[header.header_len..];
+    let count = data.len().div_ceil(segment_size);
+    data.chunks(segment_size)
+        .enumerate()
+        .map(|(index, piece)| {
+            let mut header = header;
+            header.seq = header.seq.wrapping_add((index * segment_size) as u32);
+            if index + 1 < count {
+                header.flags &= !(TCP_PSH | TCP_FIN);
+            }
+            build_partial_segment(source, destination, &header, piece)
+        })
+        .collect()
+}
+
+/// States of a connection (RFC 793)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpState {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+impl TcpState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TcpState::Closed => "CLOSED",
+            TcpState::Listen => "LISTEN",
+            TcpState::SynSent => "SYN-SENT",
+            TcpState::SynReceived => "SYN-RECEIVED",
+            TcpState::Established => "ESTABLISHED",
+            TcpState::FinWait1 => "FIN-WAIT-1",
+            TcpState::FinWait2 => "FIN-WAIT-2",
+            TcpState::CloseWait => "CLOSE-WAIT",
+            TcpState::Closing => "CLOSING",
+            TcpState::LastAck => "LAST-ACK",
+            TcpState::TimeWait => "TIME-WAIT",
+        }
+    }
+
+    /// Whether the FIN of this side is out and unacknowledged or about to be
+    fn sends(&self) -> bool {
+        matches!(self, TcpState::Established | TcpState::CloseWait | TcpState::FinWait1 | TcpState::Closing | TcpState::LastAck)
+    }
+}
+
+/// A segment to send once the connection is unlocked
+struct Outgoing {
+    source: IpAddress,
+    destination: IpAddress,
+    interface: Option<Arc<NetworkInterface>>,
+    namespace: NetNamespaceId,
+    /// Partial, see [`build_partial_segment`]
+    segment: Vec<u8>,
+    /// Bytes per segment for the device to cut `segment` into
+    segment_size: Option<usize>,
+}
+
+fn transmit(outgoing: Vec<Outgoing>) {
+    for outgoing in outgoing {
+        stats::count(Counter::TcpOutSegs);
+        if outgoing.segment.get(13).is_some_and(|flags| flags & TCP_RST != 0) {
+            stats::count(Counter::TcpOutRsts);
+        }
+        let options = SendOptions {
+            source: Some(outgoing.source),
+            interface: outgoing.interface,
+            namespace: outgoing.namespace,
+            partial_checksum: Some(16),
+            segment_size: outgoing.segment_size,
+            ..SendOptions::default()
+        };
+        // Lost segments are retransmitted
+        let result = ip::send(outgoing.destination, PROTOCOL_TCP, &outgoing.segment, &options);
+        if let (Err(KernelError::NotSupported), Some(segment_size)) = (result, outgoing.segment_size) {
+            // The route moved to an interface without segmentation offload
+            let options = SendOptions { segment_size: None, ..options };
+            for segment in split_segment(outgoing.source, outgoing.destination, &outgoing.segment, segment_size) {
+                let _ = ip::send(outgoing.destination, PROTOCOL_TCP, &segment, &options);
+            }
+        }
+    }
+}
+
+/// The reset answering a segment that belongs to no connection
+fn reset_for(header: &TcpHeader, local: IpEndpoint, remote: IpEndpoint, seg_len: usize, interface: Option<Arc<NetworkInterface>>) -> Outgoing {
+    let (seq, ack, flags) = if header.has(TCP_ACK) {
+        (header.ack, 0, TCP_RST)
+    } else {
+        (0, header.seq.wrapping_add(seg_len as u32), TCP_RST | TCP_ACK)
+    };
+    let reply = TcpHeader {
+        source_port: local.port,
+        destination_port: remote.port,
+        seq,
+        ack,
+        header_len: TCP_HEADER_LEN,
+        flags,
+        window: 0,
+        mss: None,
+    };
+    Outgoing {
+        source: local.address,
+        destination: remote.address,
+        namespace: interface.as_ref().map_or(INIT_NET_NS, |interface| interface.namespace()),
+        interface,
+        segment: build_partial_segment(local.address, remote.address, &reply, &[]),
+        segment_size: None,
+    }
+}
+
+/// Options of a socket, inherited by the connections of a listener
+#[derive(Debug, Clone, Copy)]
+struct TcpOptions {
+    reuse_address: bool,
+    no_delay: bool,
+    receive_buffer: usize,
+    send_buffer: usize,
+    keepalive: bool,
+    /// In seconds
+    keepalive_idle: u64,
+    keepalive_interval: u64,
+    keepalive_count: u32,
+}
+
+impl TcpOptions {
+    fn new() -> Self {
+        Self {
+            reuse_address: false,
+            no_delay: false,
+            receive_buffer: TCP_BUFFER_SIZE,
+            send_buffer: TCP_BUFFER_SIZE,
+            keepalive: false,
+            keepalive_idle: KEEPALIVE_IDLE_S,
+            keepalive_interval: KEEPALIVE_INTERVAL_S,
+            keepalive_count: KEEPALIVE_COUNT,
+        }
+    }
+}
+
+/// What is left to do once a connection is unlocked
+#[derive(Default)]
+struct Actions {
+    segments: Vec<Outgoing>,
+    /// A passive connection completed its handshake
+    established: bool,
+    /// The connection is over and leaves the tables
+    closed: bool,
+}
+
+/// The state of a connection
+struct Control {
+    state: TcpState,
+    local: Option<IpEndpoint>,
+    remote: Option<IpEndpoint>,
+    /// Interface to send through instead of the routes
+    interface: Option<Arc<NetworkInterface>>,
+    /// Namespace whose routes and port table the connection uses
+    namespace: NetNamespaceId,
+
+    iss: u32,
+    snd_una: u32,
+    snd_nxt: u32,
+    /// Highest `snd_nxt`; it goes back when retransmitting
+    snd_max: u32,
+    snd_wnd: usize,
+    snd_wl1: u32,
+    snd_wl2: u32,
+    mss: usize,
+    /// The interface of the connection cuts up segments longer than `mss`;
+    /// looked up when data is first sent
+    tso: Option<bool>,
+    /// Data from `snd_una` on: first what is sent and unacknowledged, then
+    /// what is not sent yet
+    send_buffer: VecDeque<u8>,
+    /// Sending is closed; a FIN follows the buffered data
+    fin_queued: bool,
+    /// Sequence number of the FIN, once sent
+    fin_seq: Option<u32>,
+
+    rcv_nxt: u32,
+    receive_buffer: VecDeque<u8>,
+    out_of_order: Vec<(u32, Vec<u8>)>,
+    fin_received: bool,
+    read_shutdown: bool,
+    /// Window in the last segment sent
+    advertised: usize,
+
+    /// Round trip estimates and the timeout, in ticks
+    srtt: Option<u64>,
+    rttvar: u64,
+    rto: u64,
+    /// Sequence number whose acknowledgment is timed, and when it was sent
+    rtt_sample: Option<(u32, u64)>,
+    retransmit_at: Option<u64>,
+    retransmissions: u32,
+    /// End of TIME-WAIT, or of FIN-WAIT-2 after the socket is closed
+    linger_until: Option<u64>,
+
+    backlog: usize,
+    /// Connections of a listener in their handshake
+    half_open: Vec<Weak<Tcb>>,
+    /// Connections of a listener waiting for `accept`
+    accept_queue: VecDeque<Arc<Tcb>>,
+    /// Listener of a connection in its handshake
+    parent: Option<Weak<Tcb>>,
+
+    options: TcpOptions,
+    /// When a segment last came from the peer
+    last_heard: u64,
+    /// Keepalive probes sent since
+    keepalive_probes: u32,
+
+    /// The socket is closed; received data is dropped
+    orphaned: bool,
+    /// Reported once by the next operation
+    error: Option<KernelError>,
+}
+
+impl Control {
+    fn new() -> Self {
+        Self {
+            state: TcpState::Closed,
+            local: None,
+            remote: None,
+            interface: None,
+            namespace: INIT_NET_NS,
+            iss: 0,
+            snd_una: 0,
+            snd_nxt: 0,
+            snd_max: 0,
+            snd_wnd: 0,
+            snd_wl1: 0,
+            snd_wl2: 0,
+            mss: DEFAULT_MSS,
+            tso: None,
+            send_buffer: VecDeque::new(),
+            fin_queued: false,
+            fin_seq: None,
+            rcv_nxt: 0,
+            receive_buffer: VecDeque::new(),
+            out_of_order: Vec::new(),
+            fin_received: false,
+            read_shutdown: false,
+            advertised: 0,
+            srtt: None,
+            rttvar: 0,
+            rto: ms_to_ticks(INITIAL_RTO_MS),
+            rtt_sample: None,
+            retransmit_at: None,
+            retransmissions: 0,
+            linger_until: None,
+            backlog: 0,
+            half_open: Vec::new(),
+            accept_queue: VecDeque::new(),
+            parent: None,
+            options: TcpOptions::new(),
+            last_heard: 0,
+            keepalive_probes: 0,
+            orphaned: false,
+            error: None,
+        }
+    }
+
+    /// Start the send sequence space and arm the timer for the SYN
+    fn open(&mut self, now: u64) {
+        self.iss = get_random_u64() as u32;
+        self.snd_una = self.iss;
+        self.snd_nxt = self.iss.wrapping_add(1);
+        self.snd_max = self.snd_nxt;
+        self.rtt_sample = Some((self.snd_nxt, now));
+        self.retransmit_at = Some(now + self.rto);
+        self.last_heard = now;
+    }
+
+    fn receive_window(&self) -> usize {
+        self.receive_room().min(u16::MAX as usize)
+    }
+
+    /// Bytes the receive buffer takes before it is full
+    fn receive_room(&self) -> usize {
+        self.options.receive_buffer.saturating_sub(self.receive_buffer.len())
+    }
+
+    fn segment(&mut self, flags: u8, seq: u32, payload: &[u8]) -> Outgoing {
+        let (local, remote) = (self.local.unwrap_or(UNSPECIFIED), self.remote.unwrap_or(UNSPECIFIED));
+        self.advertised = self.receive_window();
+        let header = TcpHeader {
+            source_port: local.port,
+            destination_port: remote.port,
+            seq,
+            ack: if flags & TCP_ACK != 0 { self.rcv_nxt } else { 0 },
+            header_len: TCP_HEADER_LEN,
+            flags,
+            window: self.advertised as u16,
+            mss: (flags & TCP_SYN != 0).then_some(advertised_mss(remote.address) as u16),
+        };
+        Outgoing {
+            source: local.address,
+            destination: remote.address,
+            interface: self.interface.clone(),
+            namespace: self.namespace,
+            segment: build_partial_segment(local.address, remote.address, &header, payload),
+            segment_size: (payload.len() > self.mss).then_some(self.mss),
+        }
+    }
+
+    fn send_ack(&mut self, actions: &mut Actions) {
+        let segment = self.segment(TCP_ACK, self.snd_nxt, &[]);
+        actions.segments.push(segment);
+    }
+
+    /// Send the SYN of an active open, or with `TCP_ACK` of a passive one
+    fn send_syn(&mut self, flags: u8, actions: &mut Actions) {
+        let segment = self.segment(TCP_SYN | flags, self.iss, &[]);
+        actions.segments.push(segment);
+    }
+
+    fn send_reset(&mut self, actions: &mut Actions) {
+        let segment = self.segment(TCP_RST | TCP_ACK, self.snd_nxt, &[]);
+        actions.segments.push(segment);
+    }
+
+    fn close_now(&mut self, actions: &mut Actions) {
+        // Failed opens and resets as RFC 1213 counts them
+        match self.state {
+            TcpState::SynSent | TcpState::SynReceived => stats::count(Counter::TcpAttemptFails),
+            TcpState::Established | TcpState::CloseWait => stats::count(Counter::TcpEstabResets),
+            _ => {}
+        }
+        self.state = TcpState::Closed;
+        self.retransmit_at = None;
+        self.linger_until = None;
+        actions.closed = true;
+    }
+
+    fn enter_time_wait(&mut self, now: u64) {
+        self.state = TcpState::TimeWait;
+        self.retransmit_at = None;
+        self.linger_until = Some(now + ms_to_ticks(TIME_WAIT_MS));
+    }
+
+    /// Send what the window allows; only the first segment if `once`, into
+    /// a window of at least one byte to probe a zero window
+    ///
+    /// Unless the socket asked for no delay, a segment shorter than `mss`
+    /// waits while data is unacknowledged, gathering what follows (Nagle's
+    /// algorithm, RFC 896); the FIN does not wait.
+    fn output(&mut self, actions: &mut Actions, now: u64, once: bool) {
+        if !self.state.sends() {
+            return;
+        }
+        let tso = *self.tso.get_or_insert_with(|| {
+            let Some(remote) = self.remote else {
+                return false;
+            };
+            let options = SendOptions { interface: self.interface.clone(), namespace: self.namespace, ..SendOptions::default() };
+            // Segmentation offload is for IPv4 alone
+            remote.address.family() == IpFamily::V4
+                && ip::interface_for(remote.address, &options).ok().flatten().is_some_and(|interface| {
+                    let offloads = interface.offloads();
+                    offloads.tso4 && offloads.tx_checksum
+                })
+        });
+        // Segments the device cuts up stay below the longest datagram
+        let max_len = if tso { (TSO_MAX_LEN / self.mss).max(1) * self.mss } else { self.mss };
+        loop {
+            let offset = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+            let window = if once { self.snd_wnd.max(1) } else { self.snd_wnd };
+            if offset < self.send_buffer.len() {
+                let len = (self.send_buffer.len() - offset).min(max_len).min(window.saturating_sub(offset));
+                if len == 0 {
+                    // The window is closed: the timer probes it
+                    self.retransmit_at.get_or_insert(now + self.rto);
+                    return;
+                }
+                let last = offset + len == self.send_buffer.len();
+                if !once && !self.options.no_delay && len < self.mss && offset > 0 && last && !self.fin_queued {
+                    return;
+                }
+                let data: Vec<u8> = self.send_buffer.range(offset..offset + len).copied().collect();
+                let push = if last { TCP_PSH } else { 0 };
+                let segment = self.segment(TCP_ACK | push, self.snd_nxt, &data);
+                actions.segments.push(segment);
+                self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+                if self.rtt_sample.is_none() && !once {
+                    self.rtt_sample = Some((self.snd_nxt, now));
+                }
+            } else if offset == self.send_buffer.len() && self.fin_queued {
+                let segment = self.segment(TCP_FIN | TCP_ACK, self.snd_nxt, &[]);
+                actions.segments.push(segment);
+                self.fin_seq.get_or_insert(self.snd_nxt);
+                self.snd_nxt = self.snd_nxt.wrapping_add(1);
+                self.state = match self.state {
+                    TcpState::Established => TcpState::FinWait1,
+                    TcpState::CloseWait => TcpState::LastAck,
+                    state => state,
+                };
+            } else {
+                return;
+            }
+            if seq_lt(self.snd_max, self.snd_nxt) {
+                self.snd_max = self.snd_nxt;
+            }
+            self.retransmit_at.get_or_insert(now + self.rto);
+            if once {
+                return;
+            }
+        }
+    }
+
+    fn update_rtt(&mut self, rtt: u64) {
+        match self.srtt {
+            None => {
+                self.srtt = Some(rtt);
+                self.rttvar = rtt / 2;
+            }
+            Some(srtt) => {
+                self.rttvar = (3 * self.rttvar + srtt.abs_diff(rtt)) / 4;
+                self.srtt = Some((7 * srtt + rtt) / 8);
+            }
+        }
+        let rto = self.srtt.unwrap_or(rtt) + (4 * self.rttvar).max(1);
+        self.rto = rto.clamp(ms_to_ticks(MIN_RTO_MS), ms_to_ticks(MAX_RTO_MS));
+    }
+
+    /// Process the acknowledgment and window of `header`; false if it
+    /// acknowledges what was never sent
+    fn acknowledge(&mut self, header: &TcpHeader, now: u64, actions: &mut Actions) -> bool {
+        let ack = header.ack;
+        if seq_lt(self.snd_max, ack) {
+            self.send_ack(actions);
+            return false;
+        }
+        if seq_lt(self.snd_una, ack) {
+            let acked = ack.wrapping_sub(self.snd_una) as usize;
+            let data = acked.min(self.send_buffer.len());
+            self.send_buffer.drain(..data);
+            self.snd_una = ack;
+            if seq_lt(self.snd_nxt, ack) {
+                self.snd_nxt = ack;
+            }
+            if let Some((seq, sent)) = self.rtt_sample {
+                if seq_le(seq, ack) {
+                    self.update_rtt(now.saturating_sub(sent));
+                    self.rtt_sample = None;
+                }
+            }
+            self.retransmit_at = (self.snd_una != self.snd_max).then(|| now + self.rto);
+        }
+        if seq_le(self.snd_una, ack) {
+            // The peer is there, even if its window is closed
+            self.retransmissions = 0;
+        }
+        if seq_lt(self.snd_wl1, header.seq) || (self.snd_wl1 == header.seq && seq_le(self.snd_wl2, ack)) {
+            self.snd_wnd = header.window as usize;
+            self.snd_wl1 = header.seq;
+            self.snd_wl2 = ack;
+        }
+        true
+    }
+
+
+    /// Take in-order data into the receive buffer, or drop it if nobody
+    /// reads anymore
+    fn take_data(&mut self, data: &[u8]) {
+        if !self.orphaned && !self.read_shutdown {
+            self.receive_buffer.extend(data);
+        }
+        self.rcv_nxt = self.rcv_nxt.wrapping_add(data.len() as u32);
+    }
+
+    /// Take the data and FIN of a segment starting at `seq`
+    fn receive_data(&mut self, mut seq: u32, mut data: &[u8], mut fin: bool, now: u64) {
+        if seq_lt(seq, self.rcv_nxt) {
+            let skip = self.rcv_nxt.wrapping_sub(seq) as usize;
+            if skip > data.len() {
+                return;
+            }
+            data = &data[skip..];
+            seq = self.rcv_nxt;
+        }
+        if seq != self.rcv_nxt {
+            if !data.is_empty() && self.out_of_order.len() < MAX_OUT_OF_ORDER {
+                self.out_of_order.push((seq, data.to_vec()));
+            }
+            return;
+        }
+        let len = data.len().min(self.receive_room());
+        fin &= len == data.len();
+        self.take_data(&data[..len]);
+        while let Some(index) = self.out_of_order.iter().position(|(seq, _)| seq_le(*seq, self.rcv_nxt)) {
+            let (seq, data) = self.out_of_order.swap_remove(index);
+            let skip = self.rcv_nxt.wrapping_sub(seq) as usize;
+            if skip < data.len() {
+                let len = (data.len() - skip).min(self.receive_room());
+                self.take_data(&data[skip..skip + len]);
+            }
+        }
+        if fin && self.out_of_order.is_empty() {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.fin_received = true;
+            match self.state {
+                TcpState::SynReceived | TcpState::Established => self.state = TcpState::CloseWait,
+                TcpState::FinWait1 => self.state = TcpState::Closing,
+                TcpState::FinWait2 => self.enter_time_wait(now),
+                _ => {}
+            }
+        }
+    }
+
+    /// Whether a segment starting at `seq` and taking `seg_len` of sequence
+    /// space falls into the receive window
+    fn acceptable(&self, seq: u32, seg_len: usize) -> bool {
+        let window = self.receive_window() as u32;
+        let in_window = |seq: u32| seq_le(self.rcv_nxt, seq) && seq_lt(seq, self.rcv_nxt.wrapping_add(window));
+        match (seg_len, window) {
+            (0, 0) => seq == self.rcv_nxt,
+            (0, _) => in_window(seq),
+            (_, 0) => false,
+            _ => in_window(seq) || in_window(seq.wrapping_add(seg_len as u32 - 1)),
+        }
+    }
+
+    /// A segment for an active open waiting for its SYN to be answered
+    fn syn_sent(&mut self, header: &TcpHeader, now: u64, actions: &mut Actions) {
+        let ack_ok = header
+            .has(TCP_ACK)
+            .then(|| seq_lt(self.iss, header.ack) && seq_le(header.ack, self.snd_nxt));
+        if ack_ok == Some(false) {
+            if !header.has(TCP_RST) {
+                let remote = self.remote.unwrap_or(UNSPECIFIED);
+                let local = self.local.unwrap_or(UNSPECIFIED);
+                actions.segments.push(reset_for(header, local, remote, 0, self.interface.clone()));
+            }
+            return;
+        }
+        if header.has(TCP_RST) {
+            if ack_ok == Some(true) {
+                self.error = Some(KernelError::ConnectionRefused);
+                self.close_now(actions);
+            }
+            return;
+        }
+        if !header.has(TCP_SYN) {
+            return;
+        }
+        self.rcv_nxt = header.seq.wrapping_add(1);
+        let max_mss = advertised_mss(self.remote.unwrap_or(UNSPECIFIED).address);
+        self.mss = header.mss.map_or(DEFAULT_MSS, |mss| mss as usize).clamp(64, max_mss);
+        self.snd_wnd = header.window as usize;
+        self.snd_wl1 = header.seq;
+        self.snd_wl2 = header.ack;
+        if ack_ok == Some(true) {
+            self.acknowledge(header, now, actions);
+            self.state = TcpState::Established;
+            self.send_ack(actions);
+            self.output(actions, now, false);
+        } else {
+            // Both sides opened at once
+            self.state = TcpState::SynReceived;
+            self.send_syn(TCP_ACK, actions);
+        }
+    }
+
+    /// A segment for a connection past LISTEN and SYN-SENT
+    fn segment_arrives(&mut self, header: &TcpHeader, data: &[u8], now: u64, actions: &mut Actions) {
+        match self.state {
+            TcpState::Closed | TcpState::Listen => return,
+            TcpState::SynSent => return self.syn_sent(header, now, actions),
+            _ => {}
+        }
+        self.last_heard = now;
+        self.keepalive_probes = 0;
+        let fin = header.has(TCP_FIN);
+        let seg_len = data.len() + header.has(TCP_SYN) as usize + fin as usize;
+        if !self.acceptable(header.seq, seg_len) {
+            if !header.has(TCP_RST) {
+                self.send_ack(actions);
+            }
+            return;
+        }
+        if header.has(TCP_RST) {
+            self.error = match self.state {
+                TcpState::SynReceived if self.parent.is_none() => Some(KernelError::ConnectionRefused),
+                TcpState::SynReceived | TcpState::Closing | TcpState::LastAck | TcpState::TimeWait => None,
+                _ => Some(KernelError::ConnectionReset),
+            };
+            self.close_now(actions);
+            return;
+        }
+        if header.has(TCP_SYN) {
+            self.send_reset(actions);
+            self.error = Some(KernelError::ConnectionReset);
+            self.close_now(actions);
+            return;
+        }
+        if !header.has(TCP_ACK) {
+            return;
+        }
+        if self.state == TcpState::SynReceived {
+            if !(seq_lt(self.snd_una, header.ack) && seq_le(header.ack, self.snd_nxt)) {
+                let (local, remote) = (self.local, self.remote);
+                if let (Some(local), Some(remote)) = (local, remote) {
+                    actions.segments.push(reset_for(header, local, remote, seg_len, self.interface.clone()));
+                }
+                return;
+            }
+            self.state = TcpState::Established;
+            self.snd_wl1 = header.seq.wrapping_sub(1);
+            actions.established = true;
+        }
+        if !self.acknowledge(header, now, actions) {
+            return;
+        }
+        let fin_acked = self.fin_seq.is_some_and(|seq| seq_lt(seq, self.snd_una));
+        match self.state {
+            TcpState::FinWait1 if fin_acked => {
+                self.state = TcpState::FinWait2;
+                if self.orphaned {
+                    self.linger_until = Some(now + ms_to_ticks(FIN_WAIT2_TIMEOUT_MS));
+                }
+            }
+            TcpState::Closing if fin_acked => self.enter_time_wait(now),
+            TcpState::LastAck if fin_acked => return self.close_now(actions),
+            _ => {}
+        }
+        match self.state {
+            TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2 => self.receive_data(header.seq, data, fin, now),
+            // Only a retransmitted FIN can come now
+            TcpState::TimeWait if fin => self.enter_time_wait(now),
+            _ => {}
+        }
+        let sent = actions.segments.len();
+        self.output(actions, now, false);
+        if seg_len > 0 && actions.segments.len() == sent {
+            self.send_ack(actions);
+        }
+    }
+
+    /// Probe the peer of a connection idle for the keepalive time, with a
+    /// segment it must acknowledge: one whose sequence number is already
+    /// acknowledged (RFC 1122)
+    fn keepalive(&mut self, now: u64, actions: &mut Actions) {
+        let options = self.options;
+        let idle = self.state == TcpState::Established && self.snd_una == self.snd_max && self.retransmit_at.is_none();
+        if !options.keepalive || !idle {
+            return;
+        }
+        let wait = options.keepalive_idle + self.keepalive_probes as u64 * options.keepalive_interval;
+        let due = self.last_heard + ms_to_ticks(wait * 1000);
+        if now < due {
+            return;
+        }
+        if self.keepalive_probes >= options.keepalive_count {
+            self.error = Some(KernelError::TimedOut);
+            self.send_reset(actions);
+            return self.close_now(actions);
+        }
+        self.keepalive_probes += 1;
+        let segment = self.segment(TCP_ACK, self.snd_una.wrapping_sub(1), &[]);
+        actions.segments.push(segment);
+    }
+
+    fn on_timer(&mut self, now: u64, actions: &mut Actions) {
+        if self.linger_until.is_some_and(|until| now >= until) {
+            return self.close_now(actions);
+        }
+        self.keepalive(now, actions);
+        if self.state == TcpState::Closed {
+            return;
+        }
+        if !self.retransmit_at.is_some_and(|at| now >= at) {
+            return;
+        }
+        self.retransmissions += 1;
+        if self.retransmissions > MAX_RETRANSMISSIONS {
+            self.error = Some(KernelError::TimedOut);
+            return self.close_now(actions);
+        }
+        self.rto = (self.rto * 2).min(ms_to_ticks(MAX_RTO_MS));
+        self.rtt_sample = None;
+        self.retransmit_at = Some(now + self.rto);
+        let sent = actions.segments.len();
+        match self.state {
+            TcpState::SynSent => self.send_syn(0, actions),
+            TcpState::SynReceived => self.send_syn(TCP_ACK, actions),
+            _ => {
+                self.snd_nxt = self.snd_una;
+                self.output(actions, now, true);
+            }
+        }
+        stats::add(Counter::TcpRetransSegs, (actions.segments.len() - sent) as u64);
+    }
+}
+
+/// A connection or listener: the transmission control block
+struct Tcb {
+    control: Mutex<Control>,
+    waker: Waker,
+}
+
+impl Tcb {
+    fn new() -> Arc<Self> {
+        Arc::new(Self { control: Mutex::new(Control::new()), waker: Waker::new_interruptible("tcp") })
+    }
+}
+
+/// A user of a port: a bound socket
+struct PortUser {
+    namespace: NetNamespaceId,
+    address: IpAddress,
+    listening: bool,
+    /// The socket shares the port with others that allow it
+    reuse_address: bool,
+    tcb: Weak<Tcb>,
+}
+
+impl PortUser {
+    /// Whether this user keeps a socket of `namespace` from binding
+    /// `address`
+    fn conflicts(&self, namespace: NetNamespaceId, address: IpAddress, reuse_address: bool) -> bool {
+        self.tcb.strong_count() > 0
+            && self.namespace == namespace
+            && (self.address.covers(address) || address.covers(self.address))
+            && !(reuse_address && self.reuse_address && !self.listening)
+    }
+}
+
+/// Bound sockets by port
+static PORTS: RwLock<BTreeMap<u16, Vec<PortUser>>> = RwLock::new(BTreeMap::new());
+/// Connections by their namespace and their local and remote endpoints
+static CONNECTIONS: RwLock<BTreeMap<(NetNamespaceId, IpEndpoint, IpEndpoint), Arc<Tcb>>> = RwLock::new(BTreeMap::new());
+static NEXT_EPHEMERAL_PORT: AtomicU16 = AtomicU16::new(*EPHEMERAL_PORTS.start());
+
+/// Number of connections in ESTABLISHED or CLOSE-WAIT
+pub fn established_count() -> u64 {
+    let connections: Vec<Arc<Tcb>> = CONNECTIONS.read().values().cloned().collect();
+    connections
+        .iter()
+        .filter(|tcb| matches!(tcb.control.lock().state, TcpState::Established | TcpState::CloseWait))
+        .count() as u64
+}
+
+/// Enter `tcb` in the port table of `namespace` under `local`, choosing an
+/// ephemeral port for port 0; with `reuse_address`, a port chosen is
+/// shared with the users that do not listen and allow it too
+fn bind_port(tcb: &Arc<Tcb>, namespace: NetNamespaceId, mut local: IpEndpoint, reuse_address: bool) -> Result<IpEndpoint, KernelError> {
+    let mut ports = PORTS.write();
+    let taken = |ports: &BTreeMap<u16, Vec<PortUser>>, local: &IpEndpoint, reuse_address: bool| {
+        ports.get(&local.port).is_some_and(|users| {
+            users.iter().any(|user| user.conflicts(namespace, local.address, reuse_address))
+        })
+    };
+    if local.port == 0 {
+        let count = EPHEMERAL_PORTS.end() - EPHEMERAL_PORTS.start() + 1;
+        local.port = (0..count)
+            .map(|_| {
+                let port = NEXT_EPHEMERAL_PORT.fetch_add(1, Ordering::Relaxed);
+                if EPHEMERAL_PORTS.contains(&port) {
+                    port
+                } else {
+                    NEXT_EPHEMERAL_PORT.store(EPHEMERAL_PORTS.start() + 1, Ordering::Relaxed);
+                    *EPHEMERAL_PORTS.start()
+                }
+            })
+            .find(|&port| !taken(&ports, &IpEndpoint::new(local.address, port), false))
+            .ok_or(KernelError::AddressInUse)?;
+    } else if taken(&ports, &local, reuse_address) {
+        return Err(KernelError::AddressInUse);
+    }
+    let users = ports.entry(local.port).or_default();
+    users.retain(|user| user.tcb.strong_count() > 0);
+    users.push(PortUser { namespace, address: local.address, listening: false, reuse_address, tcb: Arc::downgrade(tcb) });
+    Ok(local)
+}
+
+/// Mark `tcb` as listening on `local` in `namespace`, unless a socket
+/// sharing the port already listens there
+fn set_listening(tcb: &Arc<Tcb>, namespace: NetNamespaceId, local: IpEndpoint) -> Result<(), KernelError> {
+    let weak = Arc::downgrade(tcb);
+    let mut ports = PORTS.write();
+    let Some(users) = ports.get_mut(&local.port) else {
+        return Ok(());
+    };
+    let listened = users.iter().any(|user| {
+        !user.tcb.ptr_eq(&weak) && user.listening && user.conflicts(namespace, local.address, false)
+    });
+    if listened {
+        return Err(KernelError::AddressInUse);
+    }
+    if let Some(user) = users.iter_mut().find(|user| user.tcb.ptr_eq(&weak)) {
+        user.listening = true;
+    }
+    Ok(())
+}
+
+/// The listener of `namespace` for connections to `local`, one bound to
+/// the address before one bound to 0.0.0.0 or ::
+fn find_listener(namespace: NetNamespaceId, local: IpEndpoint) -> Option<Arc<Tcb>> {
+    PORTS
+        .read()
+        .get(&local.port)?
+        .iter()
+        .filter(|user| user.listening && user.namespace == namespace && user.address.covers(local.address))
+        .max_by_key(|user| !user.address.is_unspecified())
+        .and_then(|user| user.tcb.upgrade())
+}
+
+/// Take `tcb` out of the tables
+fn remove(tcb: &Arc<Tcb>) {
+    let (namespace, local, remote) = {
+        let control = tcb.control.lock();
+        (control.namespace, control.local, control.remote)
+    };
+    if let (Some(local), Some(remote)) = (local, remote) {
+        let key = (namespace, local, remote);
+        let mut connections = CONNECTIONS.write();
+        if connections.get(&key).is_some_and(|other| Arc::ptr_eq(other, tcb)) {
+            connections.remove(&key);
+        }
+    }
+    if let Some(local) = local {
+        let weak = Arc::downgrade(tcb);
+        let mut ports = PORTS.write();
+        if let Some(users) = ports.get_mut(&local.port) {
+            users.retain(|user| !user.tcb.ptr_eq(&weak) && user.tcb.strong_count() > 0);
+            if users.is_empty() {
+                ports.remove(&local.port);
+            }
+        }
+    }
+}
+
+/// Do what `actions` asks of `tcb`, now that it is unlocked
+fn finish(tcb: &Arc<Tcb>, actions: Actions) {
+    transmit(actions.segments);
+    if actions.established {
+        hand_to_listener(tcb);
+    }
+    if actions.closed {
+        remove(tcb);
+    }
+    tcb.waker.wake_all();
+}
+
+/// Queue a connection that completed its handshake for `accept`, or reset
+/// it if its listener is gone
+fn hand_to_listener(tcb: &Arc<Tcb>) {
+    let parent = tcb.control.lock().parent.take().and_then(|parent| parent.upgrade());
+    if let Some(listener) = parent {
+        let mut control = listener.control.lock();
+        if control.state == TcpState::Listen {
+            let weak = Arc::downgrade(tcb);
+            control.half_open.retain(|other| !other.ptr_eq(&weak));
+            control.accept_queue.push_back(tcb.clone());
+            drop(control);
+            listener.waker.wake_all();
+            return;
+        }
+    }
+    abort(tcb);
+}
+
+/// Reset the connection of `tcb`
+fn abort(tcb: &Arc<Tcb>) {
+    let mut actions = Actions::default();
+    {
+        let mut control = tcb.control.lock();
+        if !matches!(control.state, TcpState::Closed | TcpState::Listen | TcpState::SynSent) {
+            control.send_reset(&mut actions);
+        }
+        control.close_now(&mut actions);
+    }
+    finish(tcb, actions);
+}
+
+/// Close the socket of `tcb`: the connection closes in the background
+fn close(tcb: &Arc<Tcb>) {
+    let now = get_tick();
+    let mut actions = Actions::default();
+    let mut children = Vec::new();
+    {
+        let mut control = tcb.control.lock();
+        control.orphaned = true;
+        match control.state {
+            TcpState::Listen => {
+                children.extend(control.half_open.drain(..).filter_map(|child| child.upgrade()));
+                children.extend(control.accept_queue.drain(..));
+                control.close_now(&mut actions);
+            }
+            TcpState::Closed | TcpState::SynSent => control.close_now(&mut actions),
+            TcpState::SynReceived | TcpState::Established | TcpState::CloseWait => {
+                if control.receive_buffer.is_empty() {
+                    control.fin_queued = true;
+                    control.output(&mut actions, now, false);
+                } else {
+                    control.send_reset(&mut actions);
+                    control.close_now(&mut actions);
+                }
+            }
+            TcpState::FinWait2 => control.linger_until = Some(now + ms_to_ticks(FIN_WAIT2_TIMEOUT_MS)),
+            _ => {}
+        }
+        control.receive_buffer.clear();
+    }
+    finish(tcb, actions);
+    for child in children {
+        abort(&child);
+    }
+}
+
+/// Retransmit and expire connections, every time the network thread polls
+fn tick(_manager: &NetworkManager, now: u64) {
+    let connections: Vec<Arc<Tcb>> = CONNECTIONS.read().values().cloned().collect();
+    for tcb in connections {
+        let mut actions = Actions::default();
+        tcb.control.lock().on_timer(now, &mut actions);
+        if !actions.segments.is_empty() || actions.closed {
+            finish(&tcb, actions);
+        }
+    }
+}
+
+/// A TCP socket
+pub struct TcpSocket {
+    tcb: Arc<Tcb>,
+    family: IpFamily,
+    nonblocking: bool,
+    /// Namespace of the task that opened the socket, or of its listener
+    namespace: Arc<NetNamespace>,
+}
+
+impl TcpSocket {
+    /// A socket of `family` neither bound nor connected, in the namespace
+    /// of the current task; an IPv6 socket takes IPv4 endpoints too
+    pub fn new(family: IpFamily, nonblocking: bool) -> Arc<Self> {
+        let namespace = namespace::current();
+        let tcb = Tcb::new();
+        tcb.control.lock().namespace = namespace.id();
+        Arc::new(Self { tcb, family, nonblocking, namespace })
+    }
+
+    pub fn state(&self) -> TcpState {
+        self.tcb.control.lock().state
+    }
+
+    fn wait(&self, attempt: impl FnMut() -> bool) -> Result<(), KernelError> {
+        if self.nonblocking {
+            let mut attempt = attempt;
+            return if attempt() { Ok(()) } else { Err(KernelError::WouldBlock) };
+        }
+        let sources: [&dyn PollOps; 1] = [self];
+        match wait_for(&sources, None, attempt) {
+            PollWait::Ready => Ok(()),
+            PollWait::Interrupted => Err(KernelError::Interrupted),
+            PollWait::TimedOut => Err(KernelError::WouldBlock),
+        }
+    }
+}
+
+impl SocketObject for TcpSocket {
+    fn domain(&self) -> SocketDomain {
+        match self.family {
+            IpFamily::V4 => SocketDomain::Inet,
+            IpFamily::V6 => SocketDomain::Inet6,
+        }
+    }
+
+    fn socket_type(&self) -> SocketType {
+        SocketType::Stream
+    }
+
+    /// Bind to the endpoint `name`; port 0 takes an ephemeral port
+    fn bind(&self, name: &str) -> Result<(), KernelError> {
+        let local = ip::socket_endpoint(self.family, name)?;
+        let mut control = self.tcb.control.lock();
+        if control.local.is_some() || control.state != TcpState::Closed {
+            return Err(KernelError::InvalidArgument);
+        }
+        control.local = Some(bind_port(&self.tcb, control.namespace, local, control.options.reuse_address)?);
+        Ok(())
+    }
+
+    /// Listen for connections, binding an ephemeral port if the socket is
+    /// not bound
+    fn listen(&self, backlog: usize) -> Result<(), KernelError> {
+        let mut control = self.tcb.control.lock();
+        match control.state {
+            TcpState::Closed if control.remote.is_none() => {}
+            TcpState::Listen => {}
+            _ => return Err(KernelError::InvalidArgument),
+        }
+        let local = match control.local {
+            Some(local) => local,
+            None => bind_port(&self.tcb, control.namespace, IpEndpoint::new(self.family.unspecified(), 0), false)?,
+        };
+        control.local = Some(local);
+        set_listening(&self.tcb, control.namespace, local)?;
+        control.state = TcpState::Listen;
+        control.backlog = backlog.clamp(1, SOCKET_MAX_BACKLOG);
+        Ok(())
+    }
+
+    /// Open a connection to `name`; a non-blocking socket fails with
+    /// `InProgress` and is writable once the connection is made
+    fn connect(&self, name: &str) -> Result<(), KernelError> {
+        match self.start_connect(name) {
+            Err(KernelError::InProgress) if !self.nonblocking => {}
+            result => return result,
+        }
+        let mut outcome = Ok(());
+        self.wait(|| {
+            let mut control = self.tcb.control.lock();
+            match control.state {
+                TcpState::SynSent | TcpState::SynReceived => return false,
+                TcpState::Closed => outcome = Err(control.error.take().unwrap_or(KernelError::ConnectionRefused)),
+                _ => {}
+            }
+            true
+        })?;
+        outcome
+    }
+
+    /// Send the SYN of a connection to `name`
+    fn start_connect(&self, name: &str) -> Result<(), KernelError> {
+        let remote = ip::socket_endpoint(self.family, name)?;
+        if remote.port == 0 || remote.address.is_unspecified() || remote.address.is_group() {
+            return Err(KernelError::InvalidArgument);
+        }
+        let now = get_tick();
+        let mut actions = Actions::default();
+        {
+            let mut control = self.tcb.control.lock();
+            match control.state {
+                TcpState::Closed if control.remote.is_none() => {}
+                TcpState::SynSent => return Err(KernelError::InProgress),
+                TcpState::Listen => return Err(KernelError::InvalidArgument),
+                _ => return Err(KernelError::AlreadyConnected),
+            }
+            let mut options = SendOptions { namespace: control.namespace, ..SendOptions::default() };
+            options.source = control.local.map(|local| local.address).filter(|address| !address.is_unspecified());
+            let source = ip::source_for(remote.address, &options)?;
+            let local = match control.local {
+                Some(local) => IpEndpoint::new(source, local.port),
+                None => bind_port(&self.tcb, control.namespace, IpEndpoint::new(source, 0), false)?,
+            };
+            let key = (control.namespace, local, remote);
+            let mut connections = CONNECTIONS.write();
+            if connections.contains_key(&key) {
+                return Err(KernelError::AddressInUse);
+            }
+            connections.insert(key, self.tcb.clone());
+            drop(connections);
+            control.local = Some(local);
+            control.remote = Some(remote);
+            control.state = TcpState::SynSent;
+            stats::count(Counter::TcpActiveOpens);
+            control.open(now);
+            control.send_syn(0, &mut actions);
+        }
+        finish(&self.tcb, actions);
+        Err(KernelError::InProgress)
+    }
+
+    fn accept(&self) -> Result<Arc<dyn SocketObject>, KernelError> {
+        let mut outcome = Err(KernelError::InvalidArgument);
+        self.wait(|| {
+            let mut control = self.tcb.control.lock();
+            if control.state != TcpState::Listen {
+                return true;
+            }
+            match control.accept_queue.pop_front() {
+                Some(tcb) => {
+                    outcome = Ok(tcb);
+                    true
+                }
+                None => false,
+            }
+        })?;
+        Ok(Arc::new(TcpSocket { tcb: outcome?, family: self.family, nonblocking: false, namespace: self.namespace.clone() }))
+    }
+
+    /// Queue what fits of `data` for sending; TCP carries no objects, and
+    /// the destination is the peer whatever `_to` says
+    fn send(&self, data: &[u8], objects: Vec<KernelObject>, _to: Option<&str>) -> Result<usize, KernelError> {
+        if !objects.is_empty() {
+            return Err(KernelError::NotSupported);
+        }
+        let mut actions = Actions::default();
+        let mut outcome = Ok(0);
+        self.wait(|| {
+            let mut control = self.tcb.control.lock();
+            if let Some(error) = control.error.take() {
+                outcome = Err(error);
+                return true;
+            }
+            match control.state {
+                TcpState::SynSent | TcpState::SynReceived => false,
+                TcpState::Established | TcpState::CloseWait if !control.fin_queued => {
+                    let room = control.options.send_buffer.saturating_sub(control.send_buffer.len());
+                    if room == 0 && !data.is_empty() {
+                        return false;
+                    }
+                    let len = room.min(data.len());
+                    control.send_buffer.extend(&data[..len]);
+                    control.output(&mut actions, get_tick(), false);
+                    outcome = Ok(len);
+                    true
+                }
+                _ => {
+                    outcome = Err(if control.remote.is_some() { KernelError::BrokenPipe } else { KernelError::NotConnected });
+                    true
+                }
+            }
+        })?;
+        finish(&self.tcb, actions);
+        outcome
+    }
+
+    fn receive(&self, buffer: &mut [u8]) -> Result<Received, KernelError> {
+        let mut actions = Actions::default();
+        let mut outcome = Ok(0);
+        self.wait(|| {
+            let mut control = self.tcb.control.lock();
+            if !control.receive_buffer.is_empty() {
+                let len = buffer.len().min(control.receive_buffer.len());
+                for (byte, data) in buffer.iter_mut().zip(control.receive_buffer.drain(..len)) {
+                    *byte = data;
+                }
+                // Tell the peer the window opened again
+                if control.advertised < control.mss && control.receive_window() >= control.mss && control.state.sends() {
+                    control.send_ack(&mut actions);
+                }
+                outcome = Ok(len);
+                return true;
+            }
+            if let Some(error) = control.error.take() {
+                outcome = Err(error);
+                return true;
+            }
+            if buffer.is_empty() || control.fin_received || control.read_shutdown {
+                return true;
+            }
+            match control.state {
+                TcpState::SynSent | TcpState::SynReceived | TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2 => false,
+                TcpState::Closed if control.remote.is_none() => {
+                    outcome = Err(KernelError::NotConnected);
+                    true
+                }
+                _ => true,
+            }
+        })?;
+        finish(&self.tcb, actions);
+        Ok(Received { len: outcome?, ..Received::default() })
+    }
+
+    fn shutdown(&self, how: Shutdown) -> Result<(), KernelError> {
+        let mut actions = Actions::default();
+        {
+            let mut control = self.tcb.control.lock();
+            if matches!(control.state, TcpState::Closed | TcpState::Listen) {
+                return Err(KernelError::NotConnected);
+            }
+            if how != Shutdown::Write {
+                control.read_shutdown = true;
+                control.receive_buffer.clear();
+            }
+            if how != Shutdown::Read && !control.fin_queued {
+                control.fin_queued = true;
+                control.output(&mut actions, get_tick(), false);
+            }
+        }
+        finish(&self.tcb, actions);
+        Ok(())
+    }
+
+    fn name(&self) -> Option<String> {
+        self.tcb.control.lock().local.map(|local| local.to_string())
+    }
+
+    fn peer_name(&self) -> Option<String> {
+        self.tcb.control.lock().remote.map(|remote| remote.to_string())
+    }
+
+    fn take_error(&self) -> Option<KernelError> {
+        self.tcb.control.lock().error.take()
+    }
+
+    fn get_option(&self, option: SocketOption) -> Result<usize, KernelError> {
+        let options = self.tcb.control.lock().options;
+        Ok(match option {
+            SocketOption::ReuseAddress => options.reuse_address as usize,
+            SocketOption::NoDelay => options.no_delay as usize,
+            SocketOption::ReceiveBuffer => options.receive_buffer,
+            SocketOption::SendBuffer => options.send_buffer,
+            SocketOption::KeepAlive => options.keepalive as usize,
+            SocketOption::KeepAliveIdle => options.keepalive_idle as usize,
+            SocketOption::KeepAliveInterval => options.keepalive_interval as usize,
+            SocketOption::KeepAliveCount => options.keepalive_count as usize,
+        })
+    }
+
+    /// Set `option`; reuse applies to the next bind, and the keepalive
+    /// times and count are at least 1
+    fn set_option(&self, option: SocketOption, value: usize) -> Result<(), KernelError> {
+        let mut actions = Actions::default();
+        {
+            let mut control = self.tcb.control.lock();
+            let bounded = (value as u64).clamp(1, KEEPALIVE_MAX);
+            let options = &mut control.options;
+            match option {
+                SocketOption::ReuseAddress => options.reuse_address = value != 0,
+                SocketOption::NoDelay => options.no_delay = value != 0,
+                SocketOption::ReceiveBuffer => options.receive_buffer = value.clamp(TCP_MIN_BUFFER_SIZE, TCP_MAX_BUFFER_SIZE),
+                SocketOption::SendBuffer => options.send_buffer = value.clamp(TCP_MIN_BUFFER_SIZE, TCP_MAX_BUFFER_SIZE),
+                SocketOption::KeepAlive => options.keepalive = value != 0,
+                SocketOption::KeepAliveIdle => options.keepalive_idle = bounded,
+                SocketOption::KeepAliveInterval => options.keepalive_interval = bounded,
+                SocketOption::KeepAliveCount => options.keepalive_count = bounded as u32,
+            }
+            // What Nagle held back goes out once no delay is asked for
+            if option == SocketOption::NoDelay && value != 0 {
+                control.output(&mut actions, get_tick(), false);
+            }
+        }
+        finish(&self.tcb, actions);
+        Ok(())
+    }
+}
+
+/// The stream error of a socket error
+fn stream_error(error: KernelError) -> StreamError {
+    match error {
+        KernelError::WouldBlock => StreamError::WouldBlock,
+        KernelError::Interrupted => StreamError::Interrupted,
+        KernelError::BrokenPipe | KernelError::ConnectionReset => StreamError::BrokenPipe,
+        KernelError::NotConnected => StreamError::Closed,
+        _ => StreamError::InvalidArgument,
+    }
+}
+
+impl StreamOps for TcpSocket {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, StreamError> {
+        self.receive(buffer).map(|received| received.len).map_err(stream_error)
+    }
+
+    fn write(&self, buffer: &[u8]) -> Result<usize, StreamError> {
+        self.send(buffer, Vec::new(), None).map_err(stream_error)
+    }
+}
+
+impl StreamIpcOps for TcpSocket {
+    fn is_connected(&self) -> bool {
+        matches!(self.state(), TcpState::Established | TcpState::CloseWait)
+    }
+
+    fn peer_count(&self) -> usize {
+        self.is_connected() as usize
+    }
+
+    fn description(&self) -> String {
+        let control = self.tcb.control.lock();
+        match (control.local, control.remote) {
+            (Some(local), Some(remote)) => format!("tcp({} -> {}, {})", local, remote, control.state.name()),
+            (Some(local), None) => format!("tcp({}, {})", local, control.state.name()),
+            _ => "tcp".to_string(),
+        }
+    }
+}
+
+impl PollOps for TcpSocket {
+    fn poll_events(&self) -> u32 {
+        let control = self.tcb.control.lock();
+        if control.state == TcpState::Listen {
+            return if control.accept_queue.is_empty() { 0 } else { POLLIN };
+        }
+        let closed = control.state == TcpState::Closed && control.remote.is_some();
+        let mut events = 0;
+        if !control.receive_buffer.is_empty() || control.fin_received || control.read_shutdown || closed {
+            events |= POLLIN;
+        }
+        if matches!(control.state, TcpState::Established | TcpState::CloseWait)
+            && !control.fin_queued
+            && control.send_buffer.len() < control.options.send_buffer
+        {
+            events |= POLLOUT;
+        }
+        if control.error.is_some() {
+            events |= POLLERR;
+        }
+        if closed || (control.fin_received && control.fin_queued) {
+            events |= POLLHUP;
+        }
+        events
+    }
+
+    fn poll_register(&self, task_id: usize) -> bool {
+        self.tcb.waker.register(task_id);
+        true
+    }
+
+    fn poll_unregister(&self, task_id: usize) {
+        self.tcb.waker.unregister(task_id);
+    }
+}
+
+impl Drop for TcpSocket {
+    fn drop(&mut self) {
+        close(&self.tcb);
+    }
+}
+
+/// A SYN for `listener`: start a connection in SYN-RECEIVED, unless the
+/// backlog is full
+fn accept_syn(listener: &Arc<Tcb>, header: &TcpHeader, local: IpEndpoint, remote: IpEndpoint, interface: Option<Arc<NetworkInterface>>, now: u64) -> Option<(Arc<Tcb>, Actions)> {
+    let mut control = listener.control.lock();
+    if control.state != TcpState::Listen {
+        return None;
+    }
+    control.half_open.retain(|child| child.strong_count() > 0);
+    if control.half_open.len() + control.accept_queue.len() >= control.backlog {
+        return None;
+    }
+    let tcb = Tcb::new();
+    let mut actions = Actions::default();
+    {
+        let mut child = tcb.control.lock();
+        child.state = TcpState::SynReceived;
+        child.local = Some(local);
+        child.remote = Some(remote);
+        child.interface = interface;
+        child.namespace = control.namespace;
+        child.parent = Some(Arc::downgrade(listener));
+        child.options = control.options;
+        child.open(now);
+        child.rcv_nxt = header.seq.wrapping_add(1);
+        child.mss = header.mss.map_or(DEFAULT_MSS, |mss| mss as usize).clamp(64, advertised_mss(remote.address));
+        child.snd_wnd = header.window as usize;
+        child.snd_wl1 = header.seq;
+        child.send_syn(TCP_ACK, &mut actions);
+    }
+    control.half_open.push(Arc::downgrade(&tcb));
+    stats::count(Counter::TcpPassiveOpens);
+    Some((tcb, actions))
+}
+
+/// Processor of TCP at the "ipv4" and "ipv6" stages
+pub struct TcpProcessor;
+
+impl PacketProcessor for TcpProcessor {
+    fn name(&self) -> &'static str {
+        "tcp"
+    }
+
+    fn process(&self, packet: &mut NetworkPacket) -> ProcessResult {
+        let (Some(source), Some(destination)) = (ip::source(packet), ip::destination(packet)) else {
+            return ProcessResult::Dropped("TCP segment without addresses");
+        };
+        stats::count(Counter::TcpInSegs);
+        let Some(header) = TcpHeader::parse(packet.payload()) else {
+            stats::count(Counter::TcpInErrs);
+            return ProcessResult::Dropped("Malformed TCP header");
+        };
+        if packet.metadata_int(META_CHECKSUM_VERIFIED) != Some(1) {
+            let mut sum = Checksum::new();
+            sum.add_ip_pseudo_header(source, destination, PROTOCOL_TCP, packet.payload().len());
+            sum.add_bytes(packet.payload());
+            if sum.finish() != 0 {
+                stats::count(Counter::TcpInErrs);
+                stats::count(Counter::TcpInCsumErrors);
+                return ProcessResult::Dropped("Bad TCP checksum");
+            }
+        }
+        if destination.is_group() {
+            return ProcessResult::Dropped("TCP segment to a group address");
+        }
+        let _ = packet.consume_header("tcp", header.header_len);
+        let interface = packet.interface().cloned();
+        let namespace = interface.as_ref().map_or(INIT_NET_NS, |interface| interface.namespace());
+        let data = packet.payload();
+        let local = IpEndpoint::new(destination, header.destination_port);
+        let remote = IpEndpoint::new(source, header.source_port);
+        let now = get_tick();
+
+        let connection = CONNECTIONS.read().get(&(namespace, local, remote)).cloned();
+        if let Some(tcb) = connection {
+            let mut actions = Actions::default();
+            tcb.control.lock().segment_arrives(&header, data, now, &mut actions);
+            finish(&tcb, actions);
+            return ProcessResult::Consumed;
+        }
+        let seg_len = data.len() + header.has(TCP_SYN) as usize + header.has(TCP_FIN) as usize;
+        if header.has(TCP_RST) {
+            return ProcessResult::Dropped("Reset for no TCP connection");
+        }
+        if let Some(listener) = find_listener(namespace, local).filter(|_| header.has(TCP_SYN) && !header.has(TCP_ACK)) {
+            let Some((tcb, actions)) = accept_syn(&listener, &header, local, remote, interface, now) else {
+                return ProcessResult::Dropped("Listen backlog full");
+            };
+            CONNECTIONS.write().insert((namespace, local, remote), tcb.clone());
+            finish(&tcb, actions);
+            return ProcessResult::Consumed;
+        }
+        transmit(alloc::vec![reset_for(&header, local, remote, seg_len, interface)]);
+        ProcessResult::Dropped("No TCP connection")
+    }
+}
+
+/// Fails connections in SYN-SENT on ICMP destination unreachable errors, at
+/// the "icmp-error" stage; other errors are only hints (RFC 1122)
+struct TcpErrorProcessor;
+
+impl PacketProcessor for TcpErrorProcessor {
+    fn name(&self) -> &'static str {
+        "tcp-error"
+    }
+
+    fn process(&self, packet: &mut NetworkPacket) -> ProcessResult {
+        let Some(original) = ip::parse_datagram(packet.payload()) else {
+            return ProcessResult::Dropped("Malformed ICMP error");
+        };
+        let Some(ports) = original.transport.get(..4) else {
+            return ProcessResult::Dropped("Malformed ICMP error");
+        };
+        let local = IpEndpoint::new(original.source.canonical(), u16::from_be_bytes([ports[0], ports[1]]));
+        let remote = IpEndpoint::new(original.destination.canonical(), u16::from_be_bytes([ports[2], ports[3]]));
+        let namespace = packet.interface().map_or(INIT_NET_NS, |interface| interface.namespace());
+        let Some(tcb) = CONNECTIONS.read().get(&(namespace, local, remote)).cloned() else {
+            return ProcessResult::Dropped("No connection for the ICMP error");
+        };
+        if packet.metadata_int(META_ICMP_TYPE) != Some(ICMP_DEST_UNREACHABLE as u64) {
+            return ProcessResult::Consumed;
+        }
+        let refused = matches!(packet.metadata_int(META_ICMP_CODE), Some(code) if code == UNREACH_PORT as u64 || code == UNREACH_PROTOCOL as u64);
+        let mut actions = Actions::default();
+        {
+            let mut control = tcb.control.lock();
+            if control.state == TcpState::SynSent {
+                control.error = Some(if refused { KernelError::ConnectionRefused } else { KernelError::NetworkUnreachable });
+                control.close_now(&mut actions);
+            }
+        }
+        finish(&tcb, actions);
+        ProcessResult::Consumed
+    }
+}
+
+/// Register TCP with the pipeline and timers of `manager`
+pub fn register(manager: &NetworkManager) -> Result<(), KernelError> {
+    let pipeline = manager.pipeline();
+    pipeline.register_processor(IPV4_STAGE, PROTOCOL_TCP as u64, Arc::new(TcpProcessor))?;
+    pipeline.register_processor(IPV6_STAGE, PROTOCOL_TCP as u64, Arc::new(TcpProcessor))?;
+    pipeline.register_processor(ICMP_ERROR_STAGE, PROTOCOL_TCP as u64, Arc::new(TcpErrorProcessor))?;
+    manager.register_timer(tick);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::network::{GenericNetworkDevice, MacAddress, NetworkDevice};
+    use crate::network::address::Ipv4Cidr;
+    use crate::network::arp;
+    use crate::network::ethernet::{self, EthernetHeader, ETHERNET_HEADER_LEN, ETHERTYPE_IPV4};
+    use crate::network::icmp;
+    use crate::network::ipv4::{self, Ipv4Header};
+    use crate::network::pipeline::PacketFate;
+
+    const REMOTE_MAC: MacAddress = MacAddress::new([2, 0, 0, 0, 0, 41]);
+    const REMOTE_IP: Ipv4Address = Ipv4Address::new(192, 0, 2, 41);
+    const REMOTE: IpEndpoint = IpEndpoint::new(IpAddress::V4(REMOTE_IP), 40000);
+    const LOCAL_IP: Ipv4Address = Ipv4Address::new(192, 0, 2, 40);
+    const LOCAL: IpAddress = IpAddress::V4(LOCAL_IP);
+
+    static MANAGER: NetworkManager = NetworkManager::new();
+
+    fn header(seq: u32, ack: u32, flags: u8) -> TcpHeader {
+        TcpHeader {
+            source_port: REMOTE.port,
+            destination_port: 8080,
+            seq,
+            ack,
+            header_len: TCP_HEADER_LEN,
+            flags,
+            window: 8192,
+            mss: None,
+        }
+    }
+
+    fn frame(local_mac: MacAddress, header: &TcpHeader, payload: &[u8]) -> Vec<u8> {
+        let segment = build_segment(REMOTE.address, LOCAL, header, payload);
+        let mut datagram = Ipv4Header::new(REMOTE_IP, LOCAL_IP, PROTOCOL_TCP, segment.len()).to_bytes().to_vec();
+        datagram.extend_from_slice(&segment);
+        let ethernet = EthernetHeader { destination: local_mac, source: REMOTE_MAC, ethertype: ETHERTYPE_IPV4 };
+        ethernet::build_frame(&ethernet, &datagram)
+    }
+
+    /// The segments sent by the device
+    fn sent(device: &GenericNetworkDevice) -> Vec<(TcpHeader, Vec<u8>)> {
+        device
+            .take_transmitted_packets()
+            .iter()
+            .filter_map(|packet| {
+                let datagram = &packet.as_slice()[ETHERNET_HEADER_LEN..];
+                let ip = Ipv4Header::parse(datagram)?;
+                let segment = &datagram[ip.header_len..ip.total_len];
+                let header = TcpHeader::parse(segment)?;
+                Some((header, segment[header.header_len..].to_vec()))
+            })
+            .collect()
+    }
+
+    #[test_case]
+    fn test_tcp_segment() {
+        assert!(seq_lt(u32::MAX - 1, 2) && seq_le(5, 5) && !seq_lt(2, u32::MAX - 1));
+        let mut syn = header(7, 0, TCP_SYN);
+        syn.mss = Some(1200);
+        let segment = build_segment(REMOTE.address, LOCAL, &syn, b"data");
+        let mut sum = Checksum::new();
+        sum.add_pseudo_header(REMOTE_IP, LOCAL_IP, PROTOCOL_TCP, segment.len());
+        sum.add_bytes(&segment);
+        assert_eq!(sum.finish(), 0);
+        let parsed = TcpHeader::parse(&segment).unwrap();
+        assert_eq!(parsed, TcpHeader { header_len: 24, ..syn });
+        assert_eq!(&segment[parsed.header_len..], b"data");
+        assert_eq!(TcpHeader::parse(&segment[..19]), None);
+
+        // A segment the device would cut up, cut in software instead
+        let data: Vec<u8> = (0..10u8).collect();
+        let long = build_partial_segment(REMOTE.address, LOCAL, &header(100, 1, TCP_ACK | TCP_PSH), &data);
+        let pieces = split_segment(REMOTE.address, LOCAL, &long, 4);
+        assert_eq!(pieces.len(), 3);
+        for (index, piece) in pieces.iter().enumerate() {
+            let parsed = TcpHeader::parse(piece).unwrap();
+            assert_eq!(parsed.seq, 100 + 4 * index as u32);
+            assert_eq!(parsed.has(TCP_PSH), index == 2);
+            // Completed as a device would, the checksum holds
+            let mut piece = piece.clone();
+            let completed = checksum(&piece);
+            piece[16..18].copy_from_slice(&completed.to_be_bytes());
+            let mut sum = Checksum::new();
+            sum.add_pseudo_header(REMOTE_IP, LOCAL_IP, PROTOCOL_TCP, piece.len());
+            sum.add_bytes(&piece);
+            assert_eq!(sum.finish(), 0);
+        }
+    }
+
+    #[test_case]
+    fn test_tcp_connection() {
+        ethernet::register(&MANAGER).unwrap();
+        arp::register(&MANAGER).unwrap();
+        ipv4::register(&MANAGER).unwrap();
+        icmp::register(&MANAGER).unwrap();
+        register(&MANAGER).unwrap();
+        let mut device = GenericNetworkDevice::new("test0");
+        device.init_network().unwrap();
+        let device = Arc::new(device);
+        let local_mac = device.get_mac_address().unwrap();
+        let interface = MANAGER.attach_device("test0", device.clone(), None, ethernet::ETHERNET_STAGE).unwrap();
+        interface.add_ipv4_address(Ipv4Cidr::new(LOCAL_IP, 24).unwrap()).unwrap();
+        arp::neighbor_cache().update(interface.id(), REMOTE_IP, REMOTE_MAC, true, get_tick());
+
+        let listener = TcpSocket::new(IpFamily::V4, true);
+        listener.bind("0.0.0.0:8080").unwrap();
+        listener.listen(4).unwrap();
+        assert_eq!(listener.accept().err(), Some(KernelError::WouldBlock));
+        assert_eq!(TcpSocket::new(IpFamily::V4, true).bind("192.0.2.40:8080"), Err(KernelError::AddressInUse));
+
+        // Handshake
+        let mut syn = header(1000, 0, TCP_SYN);
+        syn.mss = Some(1000);
+        assert_eq!(MANAGER.receive(&interface, frame(local_mac, &syn, &[])), PacketFate::Consumed);
+        let segments = sent(&device);
+        let (syn_ack, _) = segments[0];
+        assert_eq!((syn_ack.flags, syn_ack.ack, syn_ack.mss), (TCP_SYN | TCP_ACK, 1001, Some(ADVERTISED_MSS as u16)));
+        let iss = syn_ack.seq;
+        assert_eq!(MANAGER.receive(&interface, frame(local_mac, &header(1001, iss + 1, TCP_ACK), &[])), PacketFate::Consumed);
+        assert_eq!(listener.poll_events(), POLLIN);
+        let socket = listener.accept().unwrap();
+        assert_eq!(socket.name().as_deref(), Some("192.0.2.40:8080"));
+        assert_eq!(socket.peer_name().as_deref(), Some("192.0.2.41:40000"));
+
+        // Data both ways
+        let request = header(1001, iss + 1, TCP_ACK | TCP_PSH);
+        assert_eq!(MANAGER.receive(&interface, frame(local_mac, &request, b"GET /")), PacketFate::Consumed);
+        assert_eq!(sent(&device)[0].0.ack, 1006);
+        let mut buffer = [0; 16];
+        assert_eq!(socket.receive(&mut buffer).unwrap().len, 5);
+        assert_eq!(&buffer[..5], b"GET /");
+        assert_eq!(socket.send(b"hello", Vec::new(), None), Ok(5));
+        let segments = sent(&device);
+        assert_eq!((segments[0].0.seq, segments[0].1.as_slice()), (iss + 1, &b"hello"[..]));
+
+        // The peer closes, then the socket
+        let fin = header(1006, iss + 6, TCP_FIN | TCP_ACK);
+        assert_eq!(MANAGER.receive(&interface, frame(local_mac, &fin, &[])), PacketFate::Consumed);
+        assert_eq!(sent(&device)[0].0.ack, 1007);
+        assert_eq!(socket.receive(&mut buffer).unwrap().len, 0);
+        assert_eq!(socket.description(), "tcp(192.0.2.40:8080 -> 192.0.2.41:40000, CLOSE-WAIT)");
+        drop(socket);
+        let (fin, _) = sent(&device)[0];
+        assert_eq!((fin.flags, fin.seq), (TCP_FIN | TCP_ACK, iss + 6));
+        let local = IpEndpoint::new(LOCAL, 8080);
+        assert!(CONNECTIONS.read().contains_key(&(INIT_NET_NS, local, REMOTE)));
+        MANAGER.receive(&interface, frame(local_mac, &header(1007, iss + 7, TCP_ACK), &[]));
+        assert!(!CONNECTIONS.read().contains_key(&(INIT_NET_NS, local, REMOTE)));
+
+        // Nothing listens on another port
+        let mut other = header(5000, 0, TCP_SYN);
+        other.destination_port = 8081;
+        assert_eq!(MANAGER.receive(&interface, frame(local_mac, &other, &[])), PacketFate::Dropped("No TCP connection"));
+        let (reset, _) = sent(&device)[0];
+        assert_eq!((reset.flags, reset.ack), (TCP_RST | TCP_ACK, 5001));
+    }
+
+    /// An established connection with nothing sent yet
+    fn established(mss: usize) -> Control {
+        let mut control = Control::new();
+        control.state = TcpState::Established;
+        control.local = Some(IpEndpoint::new(LOCAL, 8080));
+        control.remote = Some(REMOTE);
+        control.open(0);
+        control.snd_una = control.snd_nxt;
+        control.retransmit_at = None;
+        control.snd_wnd = 8192;
+        control.mss = mss;
+        control.tso = Some(false);
+        control
+    }
+
+    #[test_case]
+    fn test_tcp_options() {
+        // Small segments wait for the data in flight, unless no delay
+        let mut control = established(100);
+        let mut actions = Actions::default();
+        control.send_buffer.extend(&[1; 10]);
+        control.output(&mut actions, 0, false);
+        control.send_buffer.extend(&[2; 10]);
+        control.output(&mut actions, 0, false);
+        assert_eq!(actions.segments.len(), 1);
+        control.options.no_delay = true;
+        control.output(&mut actions, 0, false);
+        assert_eq!(actions.segments.len(), 2);
+
+        // An idle connection is probed, then given up
+        let mut control = established(100);
+        control.options = TcpOptions { keepalive: true, keepalive_idle: 10, keepalive_interval: 2, keepalive_count: 2, ..control.options };
+        let mut actions = Actions::default();
+        control.on_timer(ms_to_ticks(9_000), &mut actions);
+        assert!(actions.segments.is_empty());
+        control.on_timer(ms_to_ticks(10_000), &mut actions);
+        control.on_timer(ms_to_ticks(11_000), &mut actions);
+        control.on_timer(ms_to_ticks(12_000), &mut actions);
+        let probes: Vec<TcpHeader> = actions.segments.iter().map(|out| TcpHeader::parse(&out.segment).unwrap()).collect();
+        assert_eq!(probes.len(), 2);
+        assert!(probes.iter().all(|probe| probe.seq == control.snd_una.wrapping_sub(1) && probe.flags == TCP_ACK));
+        control.on_timer(ms_to_ticks(14_000), &mut actions);
+        assert_eq!((control.state, control.error, actions.closed), (TcpState::Closed, Some(KernelError::TimedOut), true));
+
+        // Sockets that allow it share a port, but one of them listens
+        let first = TcpSocket::new(IpFamily::V4, true);
+        let second = TcpSocket::new(IpFamily::V4, true);
+        for socket in [&first, &second] {
+            socket.set_option(SocketOption::ReuseAddress, 1).unwrap();
+            socket.bind("0.0.0.0:9090").unwrap();
+        }
+        assert_eq!(TcpSocket::new(IpFamily::V4, true).bind("0.0.0.0:9090"), Err(KernelError::AddressInUse));
+        second.listen(1).unwrap();
+        assert_eq!(first.listen(1), Err(KernelError::AddressInUse));
+        let third = TcpSocket::new(IpFamily::V4, true);
+        third.set_option(SocketOption::ReuseAddress, 1).unwrap();
+        assert_eq!(third.bind("0.0.0.0:9090"), Err(KernelError::AddressInUse));
+
+        // Sizes are clamped
+        first.set_option(SocketOption::ReceiveBuffer, 1).unwrap();
+        assert_eq!(first.get_option(SocketOption::ReceiveBuffer), Ok(TCP_MIN_BUFFER_SIZE));
+        assert_eq!(first.get_option(SocketOption::KeepAliveCount), Ok(KEEPALIVE_COUNT as usize));
+    }
+}
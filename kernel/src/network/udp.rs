@@ -0,0 +1,685 @@
+//! UDP
+//!
+//! The UDP processor sits at the "ipv4" stage under protocol 17 and at the
+//! "ipv6" stage under next header 17. It checks the length and checksum of
+//! a datagram and queues it on the socket bound to its destination port; a
+//! datagram nobody is bound for is answered with a port unreachable of ICMP
+//! or ICMPv6.
+//!
+//! A [`UdpSocket`] is a datagram socket of `crate::ipc::socket`, named by
+//! its `a.b.c.d:port` or `[v6]:port` endpoint. Binding port 0, or sending
+//! from an unbound socket, takes an ephemeral port. A socket bound to
+//! 0.0.0.0 receives on every IPv4 address of the host and one bound to ::
+//! on every address of both versions; one bound to a single address is
+//! preferred for datagrams to that address, and a connected socket for
+//! datagrams from its peer. A connected socket learns from ICMP errors that
+//! its peer is unreachable: its next send or receive fails once with the
+//! error. Ports are bound apart in each network namespace, and a socket
+//! takes only the datagrams received in its own.
+
+use core::sync::atomic::{AtomicU16, Ordering};
+
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::{Mutex, RwLock};
+
+use crate::abi::error::KernelError;
+use crate::ipc::socket::{Received, Shutdown, SocketDomain, SocketObject, SocketType, SOCKET_BUFFER_SIZE};
+use crate::ipc::StreamIpcOps;
+use crate::object::capability::poll::{wait_for, PollOps, PollWait, POLLERR, POLLIN, POLLOUT};
+use crate::object::capability::{StreamError, StreamOps};
+use crate::object::KernelObject;
+use crate::sync::waker::Waker;
+
+use super::address::{IpAddress, IpEndpoint, IpFamily};
+use super::checksum::Checksum;
+use super::icmp::{self, ICMP_DEST_UNREACHABLE, ICMP_ERROR_STAGE, UNREACH_PORT};
+use super::ip::{self, SendOptions};
+use super::ipv4::{IPV4_STAGE, PROTOCOL_UDP};
+use super::ipv6::IPV6_STAGE;
+use super::manager::NetworkManager;
+use super::namespace::{self, NetNamespace, NetNamespaceId, INIT_NET_NS};
+use super::packet::{NetworkPacket, META_CHECKSUM_VERIFIED};
+use super::pipeline::{PacketProcessor, ProcessResult};
+use super::stats::{self, Counter};
+
+pub const UDP_HEADER_LEN: usize = 8;
+/// Largest payload of a datagram that is not fragmented beyond IPv4 limits
+pub const UDP_MAX_PAYLOAD: usize = 65507;
+
+/// Ports taken when a socket does not choose one (RFC 6335)
+pub const EPHEMERAL_PORTS: core::ops::RangeInclusive<u16> = 49152..=65535;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UdpHeader {
+    pub source_port: u16,
+    pub destination_port: u16,
+    /// Length of header and payload
+    pub len: usize,
+    pub checksum: u16,
+}
+
+impl UdpHeader {
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < UDP_HEADER_LEN {
+            return None;
+        }
+        Some(Self {
+            source_port: u16::from_be_bytes([bytes[0], bytes[1]]),
+            destination_port: u16::from_be_bytes([bytes[2], bytes[3]]),
+            len: u16::from_be_bytes([bytes[4], bytes[5]]) as usize,
+            checksum: u16::from_be_bytes([bytes[6], bytes[7]]),
+        })
+    }
+}
+
+/// A datagram from `source` to `destination` with `payload`, checksum
+/// filled in
+pub fn build_datagram(source: IpEndpoint, destination: IpEndpoint, payload: &[u8]) -> Vec<u8> {
+    let len = UDP_HEADER_LEN + payload.len();
+    let mut datagram = Vec::with_capacity(len);
+    datagram.extend_from_slice(&source.port.to_be_bytes());
+    datagram.extend_from_slice(&destination.port.to_be_bytes());
+    datagram.extend_from_slice(&(len as u16).to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(payload);
+    let mut sum = Checksum::new();
+    sum.add_ip_pseudo_header(source.address, destination.address, PROTOCOL_UDP, len);
+    sum.add_bytes(&datagram);
+    // A computed 0 is sent as all ones; 0 means no checksum
+    let checksum = match sum.finish() {
+        0 => 0xFFFF,
+        checksum => checksum,
+    };
+    datagram[6..8].copy_from_slice(&checksum.to_be_bytes());
+    datagram
+}
+
+/// A socket in the port table
+struct Binding {
+    namespace: NetNamespaceId,
+    local: IpEndpoint,
+    remote: Option<IpEndpoint>,
+    socket: Weak<UdpSocket>,
+}
+
+/// Bound sockets by port
+static BINDINGS: RwLock<BTreeMap<u16, Vec<Binding>>> = RwLock::new(BTreeMap::new());
+static NEXT_EPHEMERAL_PORT: AtomicU16 = AtomicU16::new(*EPHEMERAL_PORTS.start());
+
+/// Whether a socket bound to `local` could take datagrams for another on
+/// `other`
+fn overlaps(local: IpAddress, other: IpAddress) -> bool {
+    local.covers(other) || other.covers(local)
+}
+
+/// Enter `socket` in the port table of `namespace` under `local`, choosing
+/// an ephemeral port for port 0
+fn bind_port(socket: &Weak<UdpSocket>, namespace: NetNamespaceId, mut local: IpEndpoint) -> Result<IpEndpoint, KernelError> {
+    let mut bindings = BINDINGS.write();
+    let taken = |bindings: &BTreeMap<u16, Vec<Binding>>, local: &IpEndpoint| {
+        bindings.get(&local.port).is_some_and(|entries| {
+            entries.iter().any(|entry| {
+                entry.namespace == namespace && entry.socket.strong_count() > 0 && overlaps(entry.local.address, local.address)
+            })
+        })
+    };
+    if local.port == 0 {
+        let count = EPHEMERAL_PORTS.end() - EPHEMERAL_PORTS.start() + 1;
+        let port = (0..count)
+            .map(|_| {
+                let port = NEXT_EPHEMERAL_PORT.fetch_add(1, Ordering::Relaxed);
+                if EPHEMERAL_PORTS.contains(&port) {
+                    port
+                } else {
+                    NEXT_EPHEMERAL_PORT.store(EPHEMERAL_PORTS.start() + 1, Ordering::Relaxed);
+                    *EPHEMERAL_PORTS.start()
+                }
+            })
+            .find(|&port| !taken(&bindings, &IpEndpoint::new(local.address, port)))
+            .ok_or(KernelError::AddressInUse)?;
+        local.port = port;
+    } else if taken(&bindings, &local) {
+        return Err(KernelError::AddressInUse);
+    }
+    let entries = bindings.entry(local.port).or_default();
+    entries.retain(|entry| entry.socket.strong_count() > 0);
+    entries.push(Binding { namespace, local, remote: None, socket: socket.clone() });
+    Ok(local)
+}
+
+fn update_binding(socket: &Weak<UdpSocket>, port: u16, remote: Option<IpEndpoint>) {
+    if let Some(entry) = BINDINGS.write().get_mut(&port).and_then(|entries| entries.iter_mut().find(|entry| entry.socket.ptr_eq(socket))) {
+        entry.remote = remote;
+    }
+}
+
+/// The socket a datagram from `source` to `destination` received in
+/// `namespace` is for
+fn lookup(namespace: NetNamespaceId, destination: IpEndpoint, source: IpEndpoint) -> Option<Arc<UdpSocket>> {
+    let bindings = BINDINGS.read();
+    bindings
+        .get(&destination.port)?
+        .iter()
+        .filter(|entry| entry.namespace == namespace && entry.local.address.covers(destination.address))
+        .filter(|entry| entry.remote.map_or(true, |remote| remote == source))
+        .filter_map(|entry| {
+            let score = !entry.local.address.is_unspecified() as u8 + 2 * entry.remote.is_some() as u8;
+            Some((score, entry.socket.upgrade()?))
+        })
+        .max_by_key(|(score, _)| *score)
+        .map(|(_, socket)| socket)
+}
+
+struct Datagram {
+    data: Vec<u8>,
+    from: IpEndpoint,
+}
+
+struct Inbox {
+    datagrams: VecDeque<Datagram>,
+    bytes: usize,
+}
+
+struct UdpState {
+    local: Option<IpEndpoint>,
+    remote: Option<IpEndpoint>,
+    read_shutdown: bool,
+    write_shutdown: bool,
+    /// Reported by an ICMP error, returned by the next send or receive
+    error: Option<KernelError>,
+}
+
+/// A UDP socket
+pub struct UdpSocket {
+    family: IpFamily,
+    /// Namespace of the task that opened the socket
+    namespace: Arc<NetNamespace>,
+    nonblocking: bool,
+    state: Mutex<UdpState>,
+    inbox: Mutex<Inbox>,
+    waker: Waker,
+    this: Weak<UdpSocket>,
+}
+
+impl UdpSocket {
+    /// An unbound, unconnected socket of `family` in the namespace of the
+    /// current task; an IPv6 socket takes IPv4 endpoints too
+    pub fn new(family: IpFamily, nonblocking: bool) -> Arc<Self> {
+        Arc::new_cyclic(|this| Self {
+            family,
+            namespace: namespace::current(),
+            nonblocking,
+            state: Mutex::new(UdpState {
+                local: None,
+                remote: None,
+                read_shutdown: false,
+                write_shutdown: false,
+                error: None,
+            }),
+            inbox: Mutex::new(Inbox { datagrams: VecDeque::new(), bytes: 0 }),
+            waker: Waker::new_interruptible("udp"),
+            this: this.clone(),
+        })
+    }
+
+    /// The local endpoint, binding an ephemeral port first if there is none
+    fn local_or_bind(&self, state: &mut UdpState) -> Result<IpEndpoint, KernelError> {
+        if let Some(local) = state.local {
+            return Ok(local);
+        }
+        let local = bind_port(&self.this, self.namespace.id(), IpEndpoint::new(self.family.unspecified(), 0))?;
+        state.local = Some(local);
+        Ok(local)
+    }
+
+    /// Queue a received datagram; false if there is no room for it
+    fn deliver(&self, data: &[u8], from: IpEndpoint) -> bool {
+        if self.state.lock().read_shutdown {
+            return false;
+        }
+        let mut inbox = self.inbox.lock();
+        if inbox.bytes + data.len() > SOCKET_BUFFER_SIZE {
+            return false;
+        }
+        inbox.bytes += data.len();
+        inbox.datagrams.push_back(Datagram { data: data.to_vec(), from });
+        drop(inbox);
+        self.waker.wake_all();
+        true
+    }
+
+    fn report_error(&self, error: KernelError) {
+        self.state.lock().error = Some(error);
+        self.waker.wake_all();
+    }
+
+    /// Take a datagram into `buffer`, `None` while nothing is there
+    fn take(&self, buffer: &mut [u8]) -> Option<Result<Received, KernelError>> {
+        let mut state = self.state.lock();
+        if let Some(error) = state.error.take() {
+            return Some(Err(error));
+        }
+        let mut inbox = self.inbox.lock();
+        let Some(datagram) = inbox.datagrams.pop_front() else {
+            return state.read_shutdown.then(|| Ok(Received::default()));
+        };
+        inbox.bytes -= datagram.data.len();
+        let len = datagram.data.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&datagram.data[..len]);
+        Some(Ok(Received {
+            len,
+            truncated: len < datagram.data.len(),
+            objects: Vec::new(),
+            from: Some(datagram.from.to_string()),
+        }))
+    }
+}
+
+impl SocketObject for UdpSocket {
+    fn domain(&self) -> SocketDomain {
+        match self.family {
+            IpFamily::V4 => SocketDomain::Inet,
+            IpFamily::V6 => SocketDomain::Inet6,
+        }
+    }
+
+    fn socket_type(&self) -> SocketType {
+        SocketType::Datagram
+    }
+
+    /// Bind to the endpoint `name`; port 0 takes an ephemeral port
+    fn bind(&self, name: &str) -> Result<(), KernelError> {
+        let local = ip::socket_endpoint(self.family, name)?;
+        let mut state = self.state.lock();
+        if state.local.is_some() {
+            return Err(KernelError::InvalidArgument);
+        }
+        state.local = Some(bind_port(&self.this, self.namespace.id(), local)?);
+        Ok(())
+    }
+
+    fn listen(&self, _backlog: usize) -> Result<(), KernelError> {
+        Err(KernelError::NotSupported)
+    }
+
+    /// Send to `name` by default and receive only from it
+    fn connect(&self, name: &str) -> Result<(), KernelError> {
+        let remote = ip::socket_endpoint(self.family, name)?;
+        if remote.port == 0 {
+            return Err(KernelError::InvalidArgument);
+        }
+        let mut state = self.state.lock();
+        let local = self.local_or_bind(&mut state)?;
+        state.remote = Some(remote);
+        state.error = None;
+        update_binding(&self.this, local.port, Some(remote));
+        Ok(())
+    }
+
+    fn accept(&self) -> Result<Arc<dyn SocketObject>, KernelError> {
+        Err(KernelError::NotSupported)
+    }
+
+    /// Send `data` as one datagram; UDP carries no objects
+    fn send(&self, data: &[u8], objects: Vec<KernelObject>, to: Option<&str>) -> Result<usize, KernelError> {
+        if !objects.is_empty() {
+            return Err(KernelError::NotSupported);
+        }
+        if data.len() > UDP_MAX_PAYLOAD {
+            return Err(KernelError::MessageTooLong);
+        }
+        let (local, destination) = {
+            let mut state = self.state.lock();
+            if state.write_shutdown {
+                return Err(KernelError::BrokenPipe);
+            }
+            if let Some(error) = state.error.take() {
+                return Err(error);
+            }
+            let destination = match to {
+                Some(name) => ip::socket_endpoint(self.family, name)?,
+                None => state.remote.ok_or(KernelError::NotConnected)?,
+            };
+            if destination.port == 0 {
+                return Err(KernelError::InvalidArgument);
+            }
+            (self.local_or_bind(&mut state)?, destination)
+        };
+        let mut options = SendOptions { namespace: self.namespace.id(), ..SendOptions::default() };
+        if !local.address.is_unspecified() {
+            options.source = Some(local.address);
+        }
+        let source = ip::source_for(destination.address, &options)?;
+        options.source = Some(source);
+        let datagram = build_datagram(IpEndpoint::new(source, local.port), destination, data);
+        ip::send(destination.address, PROTOCOL_UDP, &datagram, &options)?;
+        stats::count(Counter::UdpOutDatagrams);
+        Ok(data.len())
+    }
+
+    fn receive(&self, buffer: &mut [u8]) -> Result<Received, KernelError> {
+        let mut outcome = None;
+        let mut attempt = || {
+            outcome = self.take(buffer);
+            outcome.is_some()
+        };
+        if self.nonblocking {
+            if !attempt() {
+                return Err(KernelError::WouldBlock);
+            }
+        } else {
+            let sources: [&dyn PollOps; 1] = [self];
+            match wait_for(&sources, None, attempt) {
+                PollWait::Ready => {}
+                PollWait::Interrupted => return Err(KernelError::Interrupted),
+                PollWait::TimedOut => return Err(KernelError::WouldBlock),
+            }
+        }
+        outcome.unwrap_or_else(|| Ok(Received::default()))
+    }
+
+    fn shutdown(&self, how: Shutdown) -> Result<(), KernelError> {
+        let mut state = self.state.lock();
+        if state.remote.is_none() {
+            return Err(KernelError::NotConnected);
+        }
+        if how != Shutdown::Write {
+            state.read_shutdown = true;
+        }
+        if how != Shutdown::Read {
+            state.write_shutdown = true;
+        }
+        drop(state);
+        self.waker.wake_all();
+        Ok(())
+    }
+
+    fn name(&self) -> Option<String> {
+        self.state.lock().local.map(|local| local.to_string())
+    }
+
+    fn peer_name(&self) -> Option<String> {
+        self.state.lock().remote.map(|remote| remote.to_string())
+    }
+
+    fn take_error(&self) -> Option<KernelError> {
+        self.state.lock().error.take()
+    }
+}
+
+/// The stream error of a socket error
+fn stream_error(error: KernelError) -> StreamError {
+    match error {
+        KernelError::WouldBlock => StreamError::WouldBlock,
+        KernelError::Interrupted => StreamError::Interrupted,
+        KernelError::BrokenPipe => StreamError::BrokenPipe,
+        KernelError::NotConnected => StreamError::Closed,
+        _ => StreamError::InvalidArgument,
+    }
+}
+
+impl StreamOps for UdpSocket {
+    /// Receive a datagram from the peer
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, StreamError> {
+        self.receive(buffer).map(|received| received.len).map_err(stream_error)
+    }
+
+    /// Send a datagram to the peer
+    fn write(&self, buffer: &[u8]) -> Result<usize, StreamError> {
+        self.send(buffer, Vec::new(), None).map_err(stream_error)
+    }
+}
+
+impl StreamIpcOps for UdpSocket {
+    fn is_connected(&self) -> bool {
+        self.state.lock().remote.is_some()
+    }
+
+    fn peer_count(&self) -> usize {
+        self.state.lock().remote.is_some() as usize
+    }
+
+    fn description(&self) -> String {
+        match self.name() {
+            Some(name) => format!("udp({})", name),
+            None => "udp".to_string(),
+        }
+    }
+}
+
+impl PollOps for UdpSocket {
+    fn poll_events(&self) -> u32 {
+        let state = self.state.lock();
+        let mut events = if state.write_shutdown { 0 } else { POLLOUT };
+        if state.error.is_some() {
+            events |= POLLERR;
+        }
+        if state.read_shutdown || !self.inbox.lock().datagrams.is_empty() {
+            events |= POLLIN;
+        }
+        events
+    }
+
+    fn poll_register(&self, task_id: usize) -> bool {
+        self.waker.register(task_id);
+        true
+    }
+
+    fn poll_unregister(&self, task_id: usize) {
+        self.waker.unregister(task_id);
+    }
+}
+
+impl Drop for UdpSocket {
+    fn drop(&mut self) {
+        if let Some(local) = self.state.get_mut().local {
+            let mut bindings = BINDINGS.write();
+            if let Some(entries) = bindings.get_mut(&local.port) {
+                entries.retain(|entry| !entry.socket.ptr_eq(&self.this) && entry.socket.strong_count() > 0);
+                if entries.is_empty() {
+                    bindings.remove(&local.port);
+                }
+            }
+        }
+    }
+}
+
+/// Processor of UDP at the "ipv4" and "ipv6" stages
+pub struct UdpProcessor;
+
+impl PacketProcessor for UdpProcessor {
+    fn name(&self) -> &'static str {
+        "udp"
+    }
+
+    fn process(&self, packet: &mut NetworkPacket) -> ProcessResult {
+        let (Some(source), Some(destination)) = (ip::source(packet), ip::destination(packet)) else {
+            return ProcessResult::Dropped("UDP datagram without addresses");
+        };
+        let Some(header) = UdpHeader::parse(packet.payload()) else {
+            stats::count(Counter::UdpInErrors);
+            return ProcessResult::Dropped("Truncated UDP datagram");
+        };
+        if header.len < UDP_HEADER_LEN || header.len > packet.payload().len() {
+            stats::count(Counter::UdpInErrors);
+            return ProcessResult::Dropped("Bad UDP length");
+        }
+        // Only IPv4 datagrams may go without a checksum
+        if header.checksum == 0 && source.family() == IpFamily::V6 {
+            stats::count(Counter::UdpInErrors);
+            return ProcessResult::Dropped("UDP datagram without checksum");
+        }
+        // The device may have checked it already
+        if header.checksum != 0 && packet.metadata_int(META_CHECKSUM_VERIFIED) != Some(1) {
+            let mut sum = Checksum::new();
+            sum.add_ip_pseudo_header(source, destination, PROTOCOL_UDP, header.len);
+            sum.add_bytes(&packet.payload()[..header.len]);
+            if sum.finish() != 0 {
+                stats::count(Counter::UdpInErrors);
+                stats::count(Counter::UdpInCsumErrors);
+                return ProcessResult::Dropped("Bad UDP checksum");
+            }
+        }
+        let _ = packet.consume_header("udp", UDP_HEADER_LEN);
+        packet.truncate_payload(header.len - UDP_HEADER_LEN);
+
+        let from = IpEndpoint::new(source, header.source_port);
+        let namespace = packet.interface().map_or(INIT_NET_NS, |interface| interface.namespace());
+        match lookup(namespace, IpEndpoint::new(destination, header.destination_port), from) {
+            Some(socket) if socket.deliver(packet.payload(), from) => {
+                stats::count(Counter::UdpInDatagrams);
+                ProcessResult::Consumed
+            }
+            Some(_) => {
+                stats::count(Counter::UdpInErrors);
+                stats::count(Counter::UdpRcvbufErrors);
+                ProcessResult::Dropped("Socket buffer full")
+            }
+            None => {
+                stats::count(Counter::UdpNoPorts);
+                if let (Some(interface), Some(ip_header), Some(udp_header)) =
+                    (packet.interface(), ip::header(packet), packet.header("udp"))
+                {
+                    let original = [ip_header, udp_header, packet.payload()].concat();
+                    let _ = ip::send_port_unreachable(interface, &original);
+                }
+                ProcessResult::Dropped("Port unreachable")
+            }
+        }
+    }
+}
+
+/// Reports ICMP errors about sent datagrams to connected sockets, at the
+/// "icmp-error" stage
+struct UdpErrorProcessor;
+
+impl PacketProcessor for UdpErrorProcessor {
+    fn name(&self) -> &'static str {
+        "udp-error"
+    }
+
+    fn process(&self, packet: &mut NetworkPacket) -> ProcessResult {
+        let Some(original) = ip::parse_datagram(packet.payload()) else {
+            return ProcessResult::Dropped("Malformed ICMP error");
+        };
+        let Some(header) = UdpHeader::parse(original.transport) else {
+            return ProcessResult::Dropped("Malformed ICMP error");
+        };
+        let remote = IpEndpoint::new(original.destination.canonical(), header.destination_port);
+        let namespace = packet.interface().map_or(INIT_NET_NS, |interface| interface.namespace());
+        let socket = BINDINGS.read().get(&header.source_port).and_then(|entries| {
+            entries
+                .iter()
+                .find(|entry| entry.namespace == namespace && entry.remote == Some(remote))
+                .and_then(|entry| entry.socket.upgrade())
+        });
+        let Some(socket) = socket else {
+            return ProcessResult::Dropped("No socket for the ICMP error");
+        };
+        let refused = packet.metadata_int(icmp::META_ICMP_TYPE) == Some(ICMP_DEST_UNREACHABLE as u64)
+            && packet.metadata_int(icmp::META_ICMP_CODE) == Some(UNREACH_PORT as u64);
+        socket.report_error(if refused { KernelError::ConnectionRefused } else { KernelError::NetworkUnreachable });
+        ProcessResult::Consumed
+    }
+}
+
+/// Register UDP with the pipeline of `manager`
+pub fn register(manager: &NetworkManager) -> Result<(), KernelError> {
+    let pipeline = manager.pipeline();
+    pipeline.register_processor(IPV4_STAGE, PROTOCOL_UDP as u64, Arc::new(UdpProcessor))?;
+    pipeline.register_processor(IPV6_STAGE, PROTOCOL_UDP as u64, Arc::new(UdpProcessor))?;
+    pipeline.register_processor(ICMP_ERROR_STAGE, PROTOCOL_UDP as u64, Arc::new(UdpErrorProcessor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::network::{GenericNetworkDevice, MacAddress, NetworkDevice};
+    use crate::network::address::{Ipv4Address, Ipv4Cidr};
+    use crate::network::ipv4::{self, Ipv4Header};
+    use crate::network::ethernet::{self, EthernetHeader, ETHERTYPE_IPV4};
+    use crate::network::pipeline::PacketFate;
+
+    #[test_case]
+    fn test_udp_bind() {
+        let a = UdpSocket::new(IpFamily::V4, true);
+        let b = UdpSocket::new(IpFamily::V4, true);
+        a.bind("0.0.0.0:7").unwrap();
+        assert_eq!(b.bind("192.0.2.1:7"), Err(KernelError::AddressInUse));
+        assert_eq!(b.bind("192.0.2.1"), Err(KernelError::InvalidArgument));
+        b.bind("192.0.2.1:0").unwrap();
+        let port = IpEndpoint::parse(&b.name().unwrap()).unwrap().port;
+        assert!(EPHEMERAL_PORTS.contains(&port));
+        assert_eq!(a.bind("0.0.0.0:8"), Err(KernelError::InvalidArgument));
+
+        assert_eq!(b.connect("[2001:db8::1]:53"), Err(KernelError::InvalidArgument));
+
+        // The unspecified IPv6 address covers the IPv4 ones, and mapped
+        // addresses are the IPv4 ones
+        let v6 = UdpSocket::new(IpFamily::V6, true);
+        assert_eq!(v6.bind("[::]:7"), Err(KernelError::AddressInUse));
+        assert_eq!(v6.bind("[::ffff:192.0.2.1]:7"), Err(KernelError::AddressInUse));
+        v6.bind("[2001:db8::1]:7").unwrap();
+        assert_eq!(v6.domain(), SocketDomain::Inet6);
+
+        // Closing a socket frees its port
+        drop(a);
+        let c = UdpSocket::new(IpFamily::V4, true);
+        c.bind("0.0.0.0:7").unwrap();
+        assert_eq!(c.receive(&mut [0; 4]).err(), Some(KernelError::WouldBlock));
+        assert_eq!(c.poll_events(), POLLOUT);
+        assert_eq!(c.send(b"x", Vec::new(), None), Err(KernelError::NotConnected));
+    }
+
+    #[test_case]
+    fn test_udp_receive() {
+        static MANAGER: NetworkManager = NetworkManager::new();
+        ethernet::register(&MANAGER).unwrap();
+        crate::network::arp::register(&MANAGER).unwrap();
+        ipv4::register(&MANAGER).unwrap();
+        icmp::register(&MANAGER).unwrap();
+        register(&MANAGER).unwrap();
+        let mut device = GenericNetworkDevice::new("test0");
+        device.init_network().unwrap();
+        let local_mac = device.get_mac_address().unwrap();
+        let interface = MANAGER.attach_device("test0", Arc::new(device), None, ethernet::ETHERNET_STAGE).unwrap();
+        let local = Ipv4Address::new(192, 0, 2, 25);
+        interface.add_ipv4_address(Ipv4Cidr::new(local, 24).unwrap()).unwrap();
+
+        let remote_address = Ipv4Address::new(192, 0, 2, 30);
+        let remote = IpEndpoint::new(remote_address.into(), 1234);
+        let frame = |port: u16, corrupt: bool| {
+            let mut datagram = build_datagram(remote, IpEndpoint::new(local.into(), port), b"hello");
+            if corrupt {
+                datagram[UDP_HEADER_LEN] ^= 1;
+            }
+            let mut packet = Ipv4Header::new(remote_address, local, PROTOCOL_UDP, datagram.len()).to_bytes().to_vec();
+            packet.extend_from_slice(&datagram);
+            let header = EthernetHeader { destination: local_mac, source: MacAddress::new([2, 0, 0, 0, 0, 30]), ethertype: ETHERTYPE_IPV4 };
+            ethernet::build_frame(&header, &packet)
+        };
+
+        let socket = UdpSocket::new(IpFamily::V4, true);
+        socket.bind("0.0.0.0:5353").unwrap();
+        assert_eq!(MANAGER.receive(&interface, frame(5353, false)), PacketFate::Consumed);
+        assert_eq!(MANAGER.receive(&interface, frame(5353, true)), PacketFate::Dropped("Bad UDP checksum"));
+        assert_eq!(MANAGER.receive(&interface, frame(5354, false)), PacketFate::Dropped("Port unreachable"));
+        assert_eq!(socket.poll_events(), POLLIN | POLLOUT);
+
+        let mut buffer = [0; 3];
+        let received = socket.receive(&mut buffer).unwrap();
+        assert_eq!((&buffer, received.len, received.truncated), (b"hel", 3, true));
+        assert_eq!(received.from.as_deref(), Some("192.0.2.30:1234"));
+
+        // A socket connected to another peer does not take the datagram
+        let connected = UdpSocket::new(IpFamily::V4, true);
+        connected.bind("192.0.2.25:5355").unwrap();
+        connected.connect("192.0.2.31:1234").unwrap();
+        assert_eq!(MANAGER.receive(&interface, frame(5355, false)), PacketFate::Dropped("Port unreachable"));
+        assert_eq!(connected.peer_name().as_deref(), Some("192.0.2.31:1234"));
+    }
+}
@@ -0,0 +1,237 @@
+//! Virtual ethernet pairs
+//!
+//! A veth pair is two interfaces joined by a virtual cable: a frame sent on
+//! one end is received on the other. Giving one end to a container and
+//! enslaving the other to a bridge connects the container to the network
+//! of the bridge. The ends are attached at the ethernet stage with random
+//! locally administered addresses, and a pair is deleted as a whole.
+
+use core::any::Any;
+
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::{Mutex, Once};
+
+use crate::abi::error::KernelError;
+use crate::device::network::{
+    DevicePacket, MacAddress, NetworkDevice, NetworkInterfaceConfig, NetworkStats, PacketChecksum,
+};
+use crate::device::{Device, DeviceType};
+use crate::object::capability::{ControlOps, MemoryMappingOps};
+
+use super::bridge;
+use super::ethernet::ETHERNET_STAGE;
+use super::manager::{get_network_manager, NetworkInterface};
+
+/// MTU of both ends of a pair
+pub const VETH_MTU: usize = 1500;
+
+/// One end of a veth pair
+pub struct VethDevice {
+    mac: MacAddress,
+    /// The other end; frames sent here are received there
+    peer: Once<Weak<VethDevice>>,
+    /// Frames sent by the peer, not taken yet
+    rx_queue: Mutex<Vec<DevicePacket>>,
+    stats: Mutex<NetworkStats>,
+}
+
+impl VethDevice {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            mac: MacAddress::random_local(),
+            peer: Once::new(),
+            rx_queue: Mutex::new(Vec::new()),
+            stats: Mutex::new(NetworkStats::default()),
+        })
+    }
+
+    fn peer(&self) -> Option<Arc<VethDevice>> {
+        self.peer.get().and_then(Weak::upgrade)
+    }
+}
+
+impl Device for VethDevice {
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Network
+    }
+
+    fn name(&self) -> &'static str {
+        "veth"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn as_network_device(&self) -> Option<&dyn NetworkDevice> {
+        Some(self)
+    }
+
+    fn into_network_device(self: Arc<Self>) -> Option<Arc<dyn NetworkDevice>> {
+        Some(self)
+    }
+}
+
+impl ControlOps for VethDevice {
+    fn control(&self, _command: u32, _arg: usize) -> Result<i32, &'static str> {
+        Err("Control operations not supported")
+    }
+}
+
+impl MemoryMappingOps for VethDevice {
+    fn get_mapping_info(&self, _offset: usize, _length: usize)
+                       -> Result<(usize, usize, bool), &'static str> {
+        Err("Memory mapping not supported by veth devices")
+    }
+
+    fn on_mapped(&self, _vaddr: usize, _paddr: usize, _length: usize, _offset: usize) {}
+
+    fn on_unmapped(&self, _vaddr: usize, _length: usize) {}
+
+    fn supports_mmap(&self) -> bool {
+        false
+    }
+}
+
+impl NetworkDevice for VethDevice {
+    fn get_interface_name(&self) -> &'static str {
+        "veth"
+    }
+
+    fn get_mac_address(&self) -> Result<MacAddress, &'static str> {
+        Ok(self.mac)
+    }
+
+    fn get_mtu(&self) -> Result<usize, &'static str> {
+        Ok(VETH_MTU)
+    }
+
+    fn get_interface_config(&self) -> Result<NetworkInterfaceConfig, &'static str> {
+        Ok(NetworkInterfaceConfig::new(self.mac, VETH_MTU, "veth"))
+    }
+
+    /// Hand the frame to the peer and have it taken at once
+    fn send_packet(&self, mut packet: DevicePacket) -> Result<(), &'static str> {
+        let peer = self.peer().ok_or("The peer of the veth device is gone")?;
+        packet.checksum = PacketChecksum::None;
+        packet.segmentation = None;
+        {
+            let mut stats = self.stats.lock();
+            stats.tx_packets += 1;
+            stats.tx_bytes += packet.len() as u64;
+        }
+        peer.rx_queue.lock().push(packet);
+        get_network_manager().notify_rx();
+        Ok(())
+    }
+
+    fn receive_packets(&self) -> Result<Vec<DevicePacket>, &'static str> {
+        let packets = core::mem::take(&mut *self.rx_queue.lock());
+        let mut stats = self.stats.lock();
+        stats.rx_packets += packets.len() as u64;
+        stats.rx_bytes += packets.iter().map(|packet| packet.len() as u64).sum::<u64>();
+        Ok(packets)
+    }
+
+    fn set_promiscuous_mode(&self, _enabled: bool) -> Result<(), &'static str> {
+        // Every frame the peer sends is received
+        Ok(())
+    }
+
+    fn init_network(&mut self) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn is_link_up(&self) -> bool {
+        self.peer().is_some()
+    }
+
+    fn get_stats(&self) -> NetworkStats {
+        self.stats.lock().clone()
+    }
+}
+
+/// The veth device of `interface`, if it is one end of a pair
+fn veth_of(interface: &NetworkInterface) -> Option<&VethDevice> {
+    interface.device().as_any().downcast_ref::<VethDevice>()
+}
+
+pub fn is_veth(interface: &NetworkInterface) -> bool {
+    veth_of(interface).is_some()
+}
+
+/// The interface at the other end of the pair of `interface`
+pub fn peer_of(interface: &NetworkInterface) -> Option<Arc<NetworkInterface>> {
+    let peer = veth_of(interface)?.peer()?;
+    get_network_manager().interfaces().into_iter().find(|other| {
+        veth_of(other).is_some_and(|device| core::ptr::eq(device, Arc::as_ptr(&peer)))
+    })
+}
+
+/// Create a veth pair as the interfaces `name` and `peer_name`
+pub fn create_pair(name: &str, peer_name: &str) -> Result<(Arc<NetworkInterface>, Arc<NetworkInterface>), KernelError> {
+    if name == peer_name {
+        return Err(KernelError::InvalidArgument);
+    }
+    let (device, peer) = (VethDevice::new(), VethDevice::new());
+    device.peer.call_once(|| Arc::downgrade(&peer));
+    peer.peer.call_once(|| Arc::downgrade(&device));
+
+    let manager = get_network_manager();
+    let interface = manager.attach_device(name, device, None, ETHERNET_STAGE)?;
+    match manager.attach_device(peer_name, peer, None, ETHERNET_STAGE) {
+        Ok(peer) => Ok((interface, peer)),
+        Err(error) => {
+            manager.detach(interface.id())?;
+            Err(error)
+        }
+    }
+}
+
+/// Delete the pair `interface` is an end of
+pub fn delete(interface: &Arc<NetworkInterface>) -> Result<(), KernelError> {
+    if !is_veth(interface) {
+        return Err(KernelError::InvalidArgument);
+    }
+    let manager = get_network_manager();
+    for end in core::iter::once(interface.clone()).chain(peer_of(interface)) {
+        bridge::release(&end);
+        manager.detach(end.id())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test_case]
+    fn test_veth_pair() {
+        // What one end sends the other receives
+        let (left, right) = (VethDevice::new(), VethDevice::new());
+        left.peer.call_once(|| Arc::downgrade(&right));
+        right.peer.call_once(|| Arc::downgrade(&left));
+        left.send_packet(DevicePacket::with_data(vec![1, 2, 3])).unwrap();
+        let received = right.receive_packets().unwrap();
+        assert_eq!(received.iter().map(|packet| packet.as_slice()).collect::<Vec<_>>(), [&[1u8, 2, 3][..]]);
+        assert!(left.receive_packets().unwrap().is_empty());
+        drop(right);
+        assert!(!left.is_link_up());
+        assert!(left.send_packet(DevicePacket::with_data(vec![4])).is_err());
+
+        let (left, right) = create_pair("vetha0", "vethb0").unwrap();
+        assert_eq!(create_pair("vetha0", "vethc0").err(), Some(KernelError::Exists));
+        assert!(get_network_manager().interface_by_name("vethc0").is_none());
+        assert_eq!(peer_of(&left).map(|peer| peer.id()), Some(right.id()));
+        assert_ne!(left.mac_address().unwrap(), right.mac_address().unwrap());
+        delete(&right).unwrap();
+        assert!(get_network_manager().interface_by_name("vetha0").is_none());
+        assert!(get_network_manager().interface_by_name("vethb0").is_none());
+    }
+}
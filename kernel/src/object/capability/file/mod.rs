@@ -8,6 +8,7 @@ use core::any::Any;
 use crate::object::capability::stream::{StreamOps, StreamError};
 use crate::object::capability::control::ControlOps;
 use crate::object::capability::memory_mapping::MemoryMappingOps;
+use crate::object::capability::poll::PollOps;
 
 pub mod syscall;
 
@@ -77,6 +78,14 @@ pub trait FileObject: StreamOps + ControlOps + MemoryMappingOps {
         // don't need to sync
         Ok(())
     }
+
+    /// Readiness of the file, for files that can make a reader or writer wait
+    ///
+    /// Regular files and directories are always ready and return `None`;
+    /// device files return the readiness of their device.
+    fn as_pollable(&self) -> Option<&dyn PollOps> {
+        None
+    }
     
     fn as_any(&self) -> &dyn Any;
 }
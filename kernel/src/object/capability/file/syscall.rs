@@ -31,7 +31,7 @@ pub fn sys_file_seek(trapframe: &mut Trapframe) -> usize {
     trapframe.increment_pc_next(task);
 
     // Get KernelObject from handle table
-    let kernel_obj = match task.handle_table.get(handle) {
+    let kernel_obj = match task.handle_table.read().get(handle).cloned() {
         Some(obj) => obj,
         None => return usize::MAX, // Invalid handle
     };
@@ -79,7 +79,7 @@ pub fn sys_file_truncate(trapframe: &mut Trapframe) -> usize {
     trapframe.increment_pc_next(task);
     
     // Get KernelObject from handle table
-    let kernel_obj = match task.handle_table.get(handle) {
+    let kernel_obj = match task.handle_table.read().get(handle).cloned() {
         Some(obj) => obj,
         None => return usize::MAX, // Invalid handle
     };
@@ -119,13 +119,13 @@ pub fn sys_file_truncate(trapframe: &mut Trapframe) -> usize {
 //     trapframe.increment_pc_next(task);
     
 //     // Translate the pointer to get access to the metadata structure
-//     let metadata_vaddr = match task.vm_manager.translate_vaddr(metadata_ptr) {
+//     let metadata_vaddr = match task.vm_manager.read().translate_vaddr(metadata_ptr) {
 //         Some(addr) => addr as *mut crate::fs::FileMetadata,
 //         None => return usize::MAX, // Invalid pointer
 //     };
     
 //     // Get KernelObject from handle table
-//     let kernel_obj = match task.handle_table.get(handle) {
+//     let kernel_obj = match task.handle_table.read().get(handle).cloned() {
 //         Some(obj) => obj,
 //         None => return usize::MAX, // Invalid handle
 //     };
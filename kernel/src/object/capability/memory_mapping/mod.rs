@@ -5,7 +5,7 @@
 
 pub mod syscall;
 
-pub use syscall::{sys_memory_map, sys_memory_unmap};
+pub use syscall::{sys_memory_map, sys_memory_unmap, sys_memory_protect, sys_memory_advise};
 
 /// Memory mapping operations capability
 /// 
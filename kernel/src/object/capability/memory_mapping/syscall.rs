@@ -5,15 +5,20 @@
 //! while all other mappings (including FIXED) are delegated to KernelObjects
 //! with MemoryMappingOps capability.
 
+use crate::abi::error::{fail, KernelError};
 use crate::arch::Trapframe;
-use crate::task::mytask;
-use crate::vm::vmem::{MemoryArea, VirtualMemoryMap};
+use crate::arch::vm::mmu::MEGAPAGE_SIZE;
+use crate::task::{mytask, Task};
+use crate::vm::manager::MemoryAdvice;
+use crate::vm::vmem::{MemoryArea, VirtualMemoryMap, VirtualMemoryPermission};
+use crate::vm::vdso;
+use crate::vm::wx::{self, WxPolicy, WX_KILL_EXIT_STATUS};
 use crate::environment::PAGE_SIZE;
-use crate::mem::page::allocate_raw_pages;
-use alloc::boxed::Box;
+use alloc::vec::Vec;
 
 // Memory mapping flags (MAP_*)
 const MAP_SHARED: usize = 0x01;
+const MAP_FIXED: usize = 0x10;
 const MAP_ANONYMOUS: usize = 0x20;
 
 // Protection flags (PROT_*)
@@ -21,6 +26,11 @@ const PROT_READ: usize = 0x1;
 const PROT_WRITE: usize = 0x2;
 const PROT_EXEC: usize = 0x4;
 
+// Memory advice (MADV_*)
+const MADV_WILLNEED: usize = 3;
+const MADV_DONTNEED: usize = 4;
+const MADV_FREE: usize = 8;
+
 /// System call for memory mapping a KernelObject with MemoryMappingOps capability
 /// or creating anonymous mappings
 /// 
@@ -57,208 +67,206 @@ pub fn sys_memory_map(trapframe: &mut Trapframe) -> usize {
 
     // Input validation
     if length == 0 {
-        return usize::MAX;
+        return fail(KernelError::InvalidArgument);
     }
 
     // Round up length to page boundary
     let aligned_length = (length + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
-    let num_pages = aligned_length / PAGE_SIZE;
+
+    // Writable and executable memory is never handed out
+    if let Err(policy) = wx::check_user_mapping(task.get_id(), vaddr, aligned_length, prot_to_permissions(prot), "mmap") {
+        return reject_wx(task, trapframe, policy);
+    }
 
     // Handle ANONYMOUS mappings specially - these are handled entirely in the syscall
     if (flags & MAP_ANONYMOUS) != 0 {
-        return handle_anonymous_mapping(task, vaddr, aligned_length, num_pages, prot, flags);
+        return handle_anonymous_mapping(task, vaddr, aligned_length, prot, flags);
     }
 
     // All other mappings are handled through the new MemoryMappingOps design
-    let kernel_obj = match task.handle_table.get(handle) {
+    let kernel_obj = match task.handle_table.read().get(handle).cloned() {
         Some(obj) => obj,
-        None => return usize::MAX, // Invalid handle
+        None => return fail(KernelError::BadHandle),
     };
 
     // Check if object supports MemoryMappingOps
     let memory_mappable = match kernel_obj.as_memory_mappable() {
         Some(mappable) => mappable,
-        None => return usize::MAX, // Object doesn't support memory mapping operations
+        None => return fail(KernelError::NotSupported), // Object doesn't support memory mapping operations
     };
 
     // Check if the object supports mmap
     if !memory_mappable.supports_mmap() {
-        return usize::MAX;
+        return fail(KernelError::NotSupported);
     }
 
     // Get mapping information from the object
     let (paddr, obj_permissions, is_shared) = match memory_mappable.get_mapping_info(offset, length) {
         Ok(info) => info,
-        Err(_) => return usize::MAX,
+        Err(e) => return fail(KernelError::from_message(e)),
     };
 
     // Determine final address
-    let final_vaddr = if vaddr == 0 {
-        match task.vm_manager.find_unmapped_area(aligned_length, PAGE_SIZE) {
-            Some(addr) => addr,
-            None => return usize::MAX,
-        }
-    } else {
-        if vaddr % PAGE_SIZE != 0 {
-            return usize::MAX;
-        }
-        vaddr
+    let final_vaddr = match choose_address(task, vaddr, aligned_length, flags) {
+        Some(addr) => addr,
+        None => return fail(KernelError::NoMemory),
     };
 
+    // Replaced mappings are not credited, so MAP_FIXED over existing memory
+    // is checked conservatively
+    if task.check_memory_limits(aligned_length, false).is_err() {
+        return fail(KernelError::NoMemory);
+    }
+
     // Create memory areas
     let vmarea = MemoryArea::new(final_vaddr, final_vaddr + aligned_length - 1);
     let pmarea = MemoryArea::new(paddr, paddr + aligned_length - 1);
 
     // Combine object permissions with requested permissions
-    let final_permissions = obj_permissions & {
-        let mut perm = 0;
-        if (prot & PROT_READ) != 0 { perm |= 0x1; }
-        if (prot & PROT_WRITE) != 0 { perm |= 0x2; }
-        if (prot & PROT_EXEC) != 0 { perm |= 0x4; }
-        perm
-    } | 0x08; // Access from user space
+    let final_permissions = obj_permissions & prot_to_permissions(prot) | VirtualMemoryPermission::User as usize;
 
     // Create virtual memory map with weak reference to the object
     let owner = kernel_obj.as_memory_mappable_weak();
     let vm_map = VirtualMemoryMap::new(pmarea, vmarea, final_permissions, is_shared, owner);
 
     // Add the mapping to VM manager
-    match task.vm_manager.add_memory_map_fixed(vm_map) {
+    let added = task.vm_manager.write().add_memory_map_fixed(vm_map);
+    match added {
         Ok(removed_mappings) => {
             // Notify the object that mapping was created
             memory_mappable.on_mapped(final_vaddr, paddr, aligned_length, offset);
-
-            // First, notify object owners about removed mappings
-            for removed_map in &removed_mappings {
-                if let Some(owner_weak) = &removed_map.owner {
-                    if let Some(owner) = owner_weak.upgrade() {
-                        owner.on_unmapped(removed_map.vmarea.start, removed_map.vmarea.size());
-                    }
-                }
-            }
-
-            // Then, handle managed page cleanup (MMU cleanup is already handled by VmManager.add_memory_map_fixed)
-            for removed_map in removed_mappings {
-                // Remove managed pages only for private mappings
-                if !removed_map.is_shared {
-                    let mapping_start = removed_map.vmarea.start;
-                    let mapping_end = removed_map.vmarea.end;
-                    let num_removed_pages = (mapping_end - mapping_start + 1 + PAGE_SIZE - 1) / PAGE_SIZE;
-                    
-                    for i in 0..num_removed_pages {
-                        let page_vaddr = mapping_start + i * PAGE_SIZE;
-                        if let Some(_managed_page) = task.remove_managed_page(page_vaddr) {
-                            // The managed page is automatically freed when dropped
-                        }
-                    }
-                }
-            }
-
+            release_removed_mappings(task, removed_mappings);
             final_vaddr
         }
-        Err(_) => usize::MAX,
+        Err(_) => fail(KernelError::NoMemory),
     }
 }
 
 /// Handle anonymous memory mapping
 fn handle_anonymous_mapping(
     task: &mut crate::task::Task,
-    mut vaddr: usize,
+    vaddr: usize,
     aligned_length: usize,
-    num_pages: usize,
     prot: usize,
     flags: usize,
 ) -> usize {
-    // For anonymous mappings, allocate physical memory directly
-    let pages = allocate_raw_pages(num_pages);
-    let pages_ptr = pages as usize;
-
-    // If vaddr is 0, kernel chooses the address
-    if vaddr == 0 {
-        // Use VMManager's find_unmapped_area for consistent virtual address allocation
-        match task.vm_manager.find_unmapped_area(aligned_length, PAGE_SIZE) {
-            Some(addr) => vaddr = addr,
-            None => return usize::MAX, // No suitable address found
+    let vaddr = match choose_address(task, vaddr, aligned_length, flags) {
+        Some(addr) => addr,
+        None => return fail(KernelError::NoMemory), // No suitable address found
+    };
+
+    // Writable anonymous memory counts towards the data limit as well
+    if task.check_memory_limits(aligned_length, (prot & PROT_WRITE) != 0).is_err() {
+        return fail(KernelError::NoMemory);
+    }
+
+    // Convert protection flags to kernel permissions
+    let permissions = prot_to_permissions(prot) | VirtualMemoryPermission::User as usize;
+
+    // Anonymous mappings are always private. No memory is allocated here:
+    // pages read as the shared zero page until they are first written
+    let vmarea = MemoryArea::new(vaddr, vaddr + aligned_length - 1);
+    let vm_map = VirtualMemoryMap::new_zero_fill(vmarea, permissions);
+
+    // Use add_memory_map_fixed for both FIXED and non-FIXED mappings to handle overlaps consistently
+    let added = task.vm_manager.write().add_memory_map_fixed(vm_map);
+    match added {
+        Ok(removed_mappings) => {
+            release_removed_mappings(task, removed_mappings);
+            vaddr
         }
-    } else {
-        // Validate the requested address is page-aligned
-        if vaddr % PAGE_SIZE != 0 {
-            return usize::MAX;
+        Err(_) => fail(KernelError::NoMemory),
+    }
+}
+
+/// Pick the virtual address for a new mapping
+/// 
+/// With MAP_FIXED the requested address is used as-is (existing mappings are
+/// replaced). Otherwise a non-zero address is only a hint: it is used when the
+/// range is free, and the kernel picks an address when it is not.
+fn choose_address(task: &Task, vaddr: usize, aligned_length: usize, flags: usize) -> Option<usize> {
+    if vaddr % PAGE_SIZE != 0 {
+        return None;
+    }
+    if (flags & MAP_FIXED) != 0 {
+        return if vaddr == 0 { None } else { Some(vaddr) };
+    }
+    if vaddr != 0 && task.vm_manager.read().is_range_free(vaddr, aligned_length) {
+        return Some(vaddr);
+    }
+    // Large mappings are placed on a megapage boundary so that they can be
+    // mapped with megapages
+    if aligned_length >= MEGAPAGE_SIZE {
+        if let Some(addr) = task.vm_manager.read().find_unmapped_area(aligned_length, MEGAPAGE_SIZE) {
+            return Some(addr);
         }
     }
+    // Use VMManager's find_unmapped_area for consistent virtual address allocation
+    task.vm_manager.read().find_unmapped_area(aligned_length, PAGE_SIZE)
+}
 
-    // Convert protection flags to kernel permissions
+/// Fail a request that violates W^X, killing the task if the policy says so
+fn reject_wx(task: &mut Task, trapframe: &mut Trapframe, policy: WxPolicy) -> usize {
+    if policy == WxPolicy::Kill {
+        task.vcpu.store(trapframe);
+        task.exit(WX_KILL_EXIT_STATUS);
+    }
+    fail(KernelError::AccessDenied)
+}
+
+/// Convert PROT_* flags to VirtualMemoryPermission bits (without the User bit)
+fn prot_to_permissions(prot: usize) -> usize {
     let mut permissions = 0;
     if (prot & PROT_READ) != 0 {
-        permissions |= 0x1; // Readable
+        permissions |= VirtualMemoryPermission::Read as usize;
     }
     if (prot & PROT_WRITE) != 0 {
-        permissions |= 0x2; // Writable
+        permissions |= VirtualMemoryPermission::Write as usize;
     }
     if (prot & PROT_EXEC) != 0 {
-        permissions |= 0x4; // Executable
+        permissions |= VirtualMemoryPermission::Execute as usize;
     }
+    permissions
+}
 
-    // Create memory areas
-    let vmarea = MemoryArea::new(vaddr, vaddr + aligned_length - 1);
-    let pmarea = MemoryArea::new(pages_ptr, pages_ptr + aligned_length - 1);
-    
-    // Create virtual memory map  
-    // let is_shared = (flags & MAP_SHARED) != 0;
-    let is_shared = false; // Anonymous mappings are always private
-    let vm_map = VirtualMemoryMap::new(pmarea, vmarea, permissions, is_shared, None); // Anonymous mappings have no owner
+/// Notify owners of removed mappings and free the private pages behind them
+/// 
+/// MMU cleanup is already handled by the VirtualMemoryManager; this takes
+/// care of the parts it cannot see.
+fn release_removed_mappings(task: &mut Task, removed_mappings: Vec<VirtualMemoryMap>) {
+    // First, notify object owners about removed mappings
+    // If an object is no longer available, we just proceed with VM cleanup
+    for removed_map in &removed_mappings {
+        removed_map.notify_owner_unmapped();
+    }
 
-    // Use add_memory_map_fixed for both FIXED and non-FIXED mappings to handle overlaps consistently
-    match task.vm_manager.add_memory_map_fixed(vm_map) {
-        Ok(removed_mappings) => {
-            // First, process notifications for object owners
-            for removed_map in &removed_mappings {
-                if let Some(owner_weak) = &removed_map.owner {
-                    if let Some(owner) = owner_weak.upgrade() {
-                        owner.on_unmapped(removed_map.vmarea.start, removed_map.vmarea.size());
-                    }
-                }
-            }
-            
-            // Then, handle managed page cleanup (MMU cleanup is already handled by VmManager.add_memory_map_fixed)
-            for removed_map in removed_mappings {
-                // Remove managed pages only for private mappings
-                if !removed_map.is_shared {
-                    let mapping_start = removed_map.vmarea.start;
-                    let mapping_end = removed_map.vmarea.end;
-                    let num_removed_pages = (mapping_end - mapping_start + 1 + PAGE_SIZE - 1) / PAGE_SIZE;
-                    
-                    for i in 0..num_removed_pages {
-                        let page_vaddr = mapping_start + i * PAGE_SIZE;
-                        if let Some(_managed_page) = task.remove_managed_page(page_vaddr) {
-                            // The managed page is automatically freed when dropped
-                        }
-                    }
-                }
-            }
-            
-            // Add managed pages for the new anonymous mapping
+    // Remove managed pages only for private mappings
+    // Shared mappings should not have their physical pages freed here
+    // as they might be used by other processes
+    for removed_map in removed_mappings {
+        if let Some(pages) = &removed_map.zero_fill {
+            pages.release_range(removed_map.vmarea.start, removed_map.vmarea.end);
+        } else if !removed_map.is_shared {
+            let num_pages = removed_map.vmarea.size().div_ceil(PAGE_SIZE);
             for i in 0..num_pages {
-                let page_vaddr = vaddr + i * crate::environment::PAGE_SIZE;
-                let page_ptr = unsafe { (pages as *mut crate::mem::page::Page).add(i) };
-                task.add_managed_page(crate::task::ManagedPage {
-                    vaddr: page_vaddr,
-                    page: unsafe { Box::from_raw(page_ptr) },
-                });
+                let page_vaddr = removed_map.vmarea.start + i * PAGE_SIZE;
+                if let Some(_managed_page) = task.remove_managed_page(page_vaddr) {
+                    // The managed page is automatically freed when dropped
+                }
             }
-            
-            vaddr
         }
-        Err(_) => usize::MAX,
     }
 }
 
 
 /// System call for unmapping memory from a KernelObject or anonymous mapping
 /// 
+/// Any page-aligned range may be unmapped; mappings that only partially
+/// overlap the range are split and keep their remaining parts.
+/// 
 /// # Arguments
-/// - vaddr: Virtual address of the mapping to unmap
-/// - length: Length of the mapping to unmap
+/// - vaddr: Start address of the range to unmap
+/// - length: Length of the range to unmap
 /// 
 /// # Returns
 /// - On success: 0
@@ -275,40 +283,88 @@ pub fn sys_memory_unmap(trapframe: &mut Trapframe) -> usize {
     // Increment PC to avoid infinite loop if munmap fails
     trapframe.increment_pc_next(task);
 
-    // Input validation
-    if length == 0 || vaddr % PAGE_SIZE != 0 {
-        return usize::MAX;
+    let unmapped = task.vm_manager.write().unmap_range(vaddr, length);
+    match unmapped {
+        Ok(removed_mappings) => {
+            release_removed_mappings(task, removed_mappings);
+            0
+        }
+        Err(_) => fail(KernelError::InvalidArgument),
     }
+}
 
-    // Remove the mapping regardless of whether it's anonymous or object-based
-    if let Some(removed_map) = task.vm_manager.remove_memory_map_by_addr(vaddr) {
-        // Notify the object owner if available (for object-based mappings)
-        if let Some(owner_weak) = &removed_map.owner {
-            if let Some(owner) = owner_weak.upgrade() {
-                owner.on_unmapped(vaddr, length);
-            }
-            // If the object is no longer available, we just proceed with VM cleanup
-        }
-        
-        // Remove managed pages only for private mappings
-        // Shared mappings should not have their physical pages freed here
-        // as they might be used by other processes
-        // (MMU cleanup is already handled by VmManager.remove_memory_map_by_addr)
-        if !removed_map.is_shared {
-            let mapping_start = removed_map.vmarea.start;
-            let mapping_end = removed_map.vmarea.end;
-            let num_pages = (mapping_end - mapping_start + 1 + PAGE_SIZE - 1) / PAGE_SIZE;
-            
-            for i in 0..num_pages {
-                let page_vaddr = mapping_start + i * PAGE_SIZE;
-                if let Some(_managed_page) = task.remove_managed_page(page_vaddr) {
-                    // The managed page is automatically freed when dropped
-                }
-            }
-        }
-        
-        0
-    } else {
-        usize::MAX // No mapping found at this address
+/// System call for changing the protection of a memory range (mprotect)
+/// 
+/// # Arguments
+/// - vaddr: Start address of the range (must be page aligned)
+/// - length: Length of the range
+/// - prot: New protection flags (PROT_READ, PROT_WRITE, PROT_EXEC)
+/// 
+/// # Returns
+/// - On success: 0
+/// - On error: usize::MAX (invalid range, part of the range is not mapped, or the range touches the vDSO)
+pub fn sys_memory_protect(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let vaddr = trapframe.get_arg(0) as usize;
+    let length = trapframe.get_arg(1) as usize;
+    let prot = trapframe.get_arg(2) as usize;
+
+    trapframe.increment_pc_next(task);
+
+    // The vDSO clock page is kernel memory shared by every task
+    if vdso::overlaps(vaddr, length) {
+        return fail(KernelError::AccessDenied);
+    }
+    let permissions = prot_to_permissions(prot) | VirtualMemoryPermission::User as usize;
+    if let Err(policy) = wx::check_user_mapping(task.get_id(), vaddr, length, permissions, "mprotect") {
+        return reject_wx(task, trapframe, policy);
+    }
+    let range_end = vaddr.saturating_add(length.max(1) - 1);
+    let was_writable = task.vm_manager.read().memmap_iter()
+        .filter(|map| map.vmarea.start <= range_end && map.vmarea.end >= vaddr)
+        .any(|map| VirtualMemoryPermission::Write.contained_in(map.permissions));
+    wx::audit_protect(task.get_id(), vaddr, length, was_writable, permissions);
+
+    match task.vm_manager.write().protect_range(vaddr, length, permissions) {
+        Ok(()) => 0,
+        Err(_) => fail(KernelError::NoMemory),
+    }
+}
+
+/// System call for giving advice about the use of a memory range (madvise)
+/// 
+/// # Arguments
+/// - vaddr: Start address of the range (must be page aligned)
+/// - length: Length of the range
+/// - advice: MADV_WILLNEED, MADV_DONTNEED or MADV_FREE
+/// 
+/// # Returns
+/// - On success: 0
+/// - On error: usize::MAX (unknown advice, invalid range, or part of the range is not mapped)
+pub fn sys_memory_advise(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let vaddr = trapframe.get_arg(0) as usize;
+    let length = trapframe.get_arg(1) as usize;
+    let advice = trapframe.get_arg(2) as usize;
+
+    trapframe.increment_pc_next(task);
+
+    let advice = match advice {
+        MADV_WILLNEED => MemoryAdvice::WillNeed,
+        MADV_DONTNEED => MemoryAdvice::DontNeed,
+        MADV_FREE => MemoryAdvice::Free,
+        _ => return fail(KernelError::InvalidArgument),
+    };
+    match task.vm_manager.write().advise_range(vaddr, length, advice) {
+        Ok(()) => 0,
+        Err(_) => fail(KernelError::NoMemory),
     }
-}
\ No newline at end of file
+}
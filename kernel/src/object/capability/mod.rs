@@ -8,6 +8,7 @@ pub mod file;
 pub mod control;
 pub mod memory_mapping;
 pub mod ipc;
+pub mod poll;
 
 #[cfg(test)]
 mod control_tests;
@@ -29,6 +30,9 @@ pub use memory_mapping::MemoryMappingOps;
 // Re-export IPC types
 pub use ipc::{EventSender, EventReceiver, EventSubscriber};
 
+// Re-export poll types
+pub use poll::PollOps;
+
 /// Clone operations capability
 /// 
 /// This trait represents the ability to properly clone an object
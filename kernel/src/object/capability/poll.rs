@@ -0,0 +1,202 @@
+//! Readiness of kernel objects
+//!
+//! Objects a task may wait on together with others implement [`PollOps`]:
+//! they report the events that are ready now and wake registered tasks
+//! when these may have changed. [`wait_for`] blocks the current task on a
+//! set of such objects; [`poll`] and the epoll object
+//! ([`crate::object::epoll`]) are built on it.
+//!
+//! Events are the bits of `poll(2)`, so the Linux ABI passes them through.
+//! Objects without readiness (regular files, shared memory) never block:
+//! they are always readable and writable.
+
+use alloc::{sync::Arc, vec, vec::Vec};
+
+use crate::object::{KernelObject, WeakKernelObject};
+use crate::sched::scheduler::get_scheduler;
+use crate::task::{mytask, BlockedType, TaskState};
+use crate::timer::{add_timer, cancel_timer, get_tick, TimerHandler, TICK_INTERVAL_US};
+
+use super::StreamError;
+
+/// Data can be read
+pub const POLLIN: u32 = 0x001;
+/// Urgent data can be read
+pub const POLLPRI: u32 = 0x002;
+/// Data can be written
+pub const POLLOUT: u32 = 0x004;
+/// An error occurred, or the read end of a pipe is closed
+pub const POLLERR: u32 = 0x008;
+/// The peer hung up: the write end of a pipe is closed
+pub const POLLHUP: u32 = 0x010;
+/// The handle is not open
+pub const POLLNVAL: u32 = 0x020;
+
+/// Events reported whether they are asked for or not
+pub const POLL_ALWAYS: u32 = POLLERR | POLLHUP | POLLNVAL;
+
+/// Poll capability
+///
+/// Readiness of an object that a task can wait on together with others.
+pub trait PollOps: Send + Sync {
+    /// The events ready now (`POLLIN`, `POLLOUT`, ...)
+    fn poll_events(&self) -> u32;
+
+    /// Wake `task_id` when the events of the object may have changed
+    ///
+    /// # Returns
+    /// `false` if the object cannot wake waiters; the waiter then checks it
+    /// again at every tick
+    fn poll_register(&self, task_id: usize) -> bool {
+        let _ = task_id;
+        false
+    }
+
+    /// Stop waking `task_id`, undoing `poll_register`
+    fn poll_unregister(&self, task_id: usize) {
+        let _ = task_id;
+    }
+}
+
+/// Ticks of a timeout of `ns` nanoseconds
+///
+/// Rounded up: a timeout shorter than a tick still waits for one.
+pub fn timeout_ticks(ns: u64) -> u64 {
+    ns.div_ceil(TICK_INTERVAL_US * 1_000)
+}
+
+/// How [`wait_for`] ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PollWait {
+    /// The condition holds
+    Ready,
+    /// The timeout expired first
+    TimedOut,
+    /// A signal is pending
+    Interrupted,
+}
+
+/// Wakes a waiting task at its timeout, or at the next tick when one of
+/// its objects cannot wake it
+struct PollTimer {
+    task_id: usize,
+}
+
+impl TimerHandler for PollTimer {
+    fn on_timer_expired(self: Arc<Self>, _context: usize) {
+        get_scheduler().wake_task(self.task_id);
+    }
+}
+
+/// Block the current task until `ready` holds
+///
+/// `ready` is checked at once and again whenever one of `sources` wakes the
+/// task. As with `Waker::wait_unless`, the second check is made after the
+/// task is registered and marked blocked, so no wakeup is missed. `ready`
+/// is called again only after it returned `false`, so it may consume what
+/// it reports.
+///
+/// # Arguments
+/// * `sources` - Objects whose changes may make `ready` hold
+/// * `timeout_ticks` - Give up after this many ticks, `None` to wait
+///   forever; `Some(0)` only checks
+/// * `ready` - The condition waited for
+///
+/// Without a current task nothing is waited for.
+pub fn wait_for(sources: &[&dyn PollOps], timeout_ticks: Option<u64>, mut ready: impl FnMut() -> bool) -> PollWait {
+    let deadline = timeout_ticks.map(|ticks| get_tick().saturating_add(ticks));
+    loop {
+        if ready() {
+            return PollWait::Ready;
+        }
+        let now = get_tick();
+        if deadline.is_some_and(|deadline| now >= deadline) {
+            return PollWait::TimedOut;
+        }
+        let Some(task) = mytask() else {
+            return PollWait::TimedOut;
+        };
+        if task.signals.has_pending() {
+            return PollWait::Interrupted;
+        }
+
+        let task_id = task.get_id();
+        let mut tick = false;
+        for source in sources {
+            tick |= !source.poll_register(task_id);
+        }
+        task.set_state(TaskState::Blocked(BlockedType::Interruptible));
+        let became_ready = ready();
+        if became_ready || task.signals.has_pending() {
+            task.set_state(TaskState::Running);
+        } else {
+            let wake_at = match (deadline, tick) {
+                (Some(deadline), true) => Some(deadline.min(now + 1)),
+                (Some(deadline), false) => Some(deadline),
+                (None, true) => Some(now + 1),
+                (None, false) => None,
+            };
+            let timer = wake_at.map(|expires| {
+                let handler: Arc<dyn TimerHandler> = Arc::new(PollTimer { task_id });
+                (add_timer(expires, &handler, 0), handler)
+            });
+            get_scheduler().schedule(task.get_trapframe());
+            if let Some((id, _handler)) = timer {
+                cancel_timer(id);
+            }
+        }
+        for source in sources {
+            source.poll_unregister(task_id);
+        }
+        if became_ready {
+            return PollWait::Ready;
+        }
+    }
+}
+
+/// The events of `object` that are reported for a request of `events`
+pub fn ready_events(object: Option<&KernelObject>, events: u32) -> u32 {
+    match object {
+        Some(object) => object.poll_events() & (events | POLL_ALWAYS),
+        None => POLLNVAL,
+    }
+}
+
+/// One object of a [`poll`]
+pub struct PollRequest {
+    /// The object, `None` for a handle that is not open
+    pub object: Option<WeakKernelObject>,
+    /// Events asked for
+    pub events: u32,
+}
+
+/// Wait until one of the requested objects has an event, as `poll(2)` does
+///
+/// A request without an object, or whose object is already closed, is
+/// reported with `POLLNVAL`.
+///
+/// # Returns
+/// The events reported for each request, all zero if the timeout expired
+///
+/// # Errors
+/// `Interrupted` if a signal arrived first
+pub fn poll(requests: &[PollRequest], timeout_ticks: Option<u64>) -> Result<Vec<u32>, StreamError> {
+    // Kept open while waiting, so that registrations are undone on the same objects
+    let objects: Vec<Option<KernelObject>> = requests
+        .iter()
+        .map(|request| request.object.as_ref().and_then(WeakKernelObject::upgrade))
+        .collect();
+    let sources: Vec<&dyn PollOps> = objects.iter().flatten().filter_map(KernelObject::as_pollable).collect();
+
+    let mut revents = vec![0u32; requests.len()];
+    let wait = wait_for(&sources, timeout_ticks, || {
+        for (revents, (request, object)) in revents.iter_mut().zip(requests.iter().zip(&objects)) {
+            *revents = ready_events(object.as_ref(), request.events);
+        }
+        revents.iter().any(|&revents| revents != 0)
+    });
+    match wait {
+        PollWait::Ready | PollWait::TimedOut => Ok(revents),
+        PollWait::Interrupted => Err(StreamError::Interrupted),
+    }
+}
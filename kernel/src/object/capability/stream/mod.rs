@@ -27,6 +27,9 @@ pub enum StreamError {
     Interrupted,
     /// Permission denied for this operation
     PermissionDenied,
+    /// Operation not permitted on this object whoever asks (e.g. a write
+    /// to a sealed file)
+    NotPermitted,
     /// Device-specific error
     DeviceError,
     /// Operation not supported by this stream type
@@ -3,8 +3,16 @@
 //! This module implements system calls that operate on KernelObjects
 //! with StreamOps capability (read/write operations).
 
+use alloc::vec;
+
 use crate::arch::Trapframe;
 use crate::task::mytask;
+use crate::task::signal::{copy_from_user, copy_to_user, interrupt_syscall};
+
+use super::StreamError;
+
+/// Largest transfer of a single call; longer ones come back short
+const MAX_IO_SIZE: usize = 64 * 1024;
 
 /// System call for reading from a KernelObject with StreamOps capability
 /// 
@@ -14,8 +22,11 @@ use crate::task::mytask;
 /// - count: Number of bytes to read
 /// 
 /// # Returns
-/// - On success: number of bytes read
+/// - On success: number of bytes read, at most `MAX_IO_SIZE`
 /// - On error: usize::MAX
+///
+/// A read interrupted by a signal is restarted or fails as described in
+/// `crate::task::signal`.
 pub fn sys_stream_read(trapframe: &mut Trapframe) -> usize {
     let task = match mytask() {
         Some(task) => task,
@@ -23,17 +34,14 @@ pub fn sys_stream_read(trapframe: &mut Trapframe) -> usize {
     };
     
     let handle = trapframe.get_arg(0) as u32;
-    let buf_ptr = match task.vm_manager.translate_vaddr(trapframe.get_arg(1)) {
-        Some(ptr) => ptr as *mut u8,
-        None => return usize::MAX, // Invalid buffer pointer
-    };
-    let count = trapframe.get_arg(2) as usize;
+    let buf_ptr = trapframe.get_arg(1);
+    let count = (trapframe.get_arg(2) as usize).min(MAX_IO_SIZE);
 
     // Increment PC to avoid infinite loop if read fails
     trapframe.increment_pc_next(task);
 
     // Get KernelObject from handle table
-    let kernel_obj = match task.handle_table.get(handle) {
+    let kernel_obj = match task.handle_table.read().get(handle).cloned() {
         Some(obj) => obj,
         None => return usize::MAX, // Invalid handle
     };
@@ -44,10 +52,14 @@ pub fn sys_stream_read(trapframe: &mut Trapframe) -> usize {
         None => return usize::MAX, // Object doesn't support stream operations
     };
 
-    // Perform read operation
-    let buffer = unsafe { core::slice::from_raw_parts_mut(buf_ptr, count) };
-    match stream.read(buffer) {
-        Ok(bytes_read) => bytes_read,
+    // Perform read operation; the user buffer may span several frames
+    let mut buffer = vec![0u8; count];
+    match stream.read(&mut buffer) {
+        Ok(bytes_read) => match copy_to_user(task, buf_ptr, &buffer[..bytes_read]) {
+            Ok(()) => bytes_read,
+            Err(_) => usize::MAX, // Invalid buffer pointer
+        },
+        Err(StreamError::Interrupted) => interrupt_syscall(task, trapframe),
         Err(_) => usize::MAX, // Read error
     }
 }
@@ -60,8 +72,11 @@ pub fn sys_stream_read(trapframe: &mut Trapframe) -> usize {
 /// - count: Number of bytes to write
 /// 
 /// # Returns
-/// - On success: number of bytes written
+/// - On success: number of bytes written, at most `MAX_IO_SIZE`
 /// - On error: usize::MAX
+///
+/// A write interrupted by a signal is restarted or fails as described in
+/// `crate::task::signal`.
 pub fn sys_stream_write(trapframe: &mut Trapframe) -> usize {
     let task = match mytask() {
         Some(task) => task,
@@ -69,17 +84,14 @@ pub fn sys_stream_write(trapframe: &mut Trapframe) -> usize {
     };
     
     let handle = trapframe.get_arg(0) as u32;
-    let buf_ptr = match task.vm_manager.translate_vaddr(trapframe.get_arg(1)) {
-        Some(ptr) => ptr as *const u8,
-        None => return usize::MAX, // Invalid buffer pointer
-    };
-    let count = trapframe.get_arg(2) as usize;
+    let buf_ptr = trapframe.get_arg(1);
+    let count = (trapframe.get_arg(2) as usize).min(MAX_IO_SIZE);
 
     // Increment PC to avoid infinite loop if write fails
     trapframe.increment_pc_next(task);
 
     // Get KernelObject from handle table
-    let kernel_obj = match task.handle_table.get(handle) {
+    let kernel_obj = match task.handle_table.read().get(handle).cloned() {
         Some(obj) => obj,
         None => return usize::MAX, // Invalid handle
     };
@@ -90,10 +102,20 @@ pub fn sys_stream_write(trapframe: &mut Trapframe) -> usize {
         None => return usize::MAX, // Object doesn't support stream operations
     };
 
+    // Files may not grow past RLIMIT_FSIZE
+    let count = match task.check_file_write(&kernel_obj, count) {
+        Ok(count) => count,
+        Err(_) => return usize::MAX,
+    };
+
     // Perform write operation
-    let buffer = unsafe { core::slice::from_raw_parts(buf_ptr, count) };
-    match stream.write(buffer) {
+    let mut buffer = vec![0u8; count];
+    if copy_from_user(task, buf_ptr, &mut buffer).is_err() {
+        return usize::MAX; // Invalid buffer pointer
+    }
+    match stream.write(&buffer) {
         Ok(bytes_written) => bytes_written,
+        Err(StreamError::Interrupted) => interrupt_syscall(task, trapframe),
         Err(_) => usize::MAX, // Write error
     }
 }
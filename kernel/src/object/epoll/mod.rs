@@ -0,0 +1,352 @@
+//! Epoll objects
+//!
+//! An epoll object is a set of kernel objects a task waits on together, so
+//! that one task can serve many pipes, terminals and processes. Each
+//! object of the set is registered under a key (the handle or the file
+//! descriptor it was added with) with the events of interest and a word of
+//! user data, which is returned with its events.
+//!
+//! - **Level-triggered** (default): an object is reported by every wait
+//!   while its events are ready
+//! - **Edge-triggered** ([`EPOLLET`]): an object is reported when an event
+//!   becomes ready that was not ready at the previous report
+//! - **One-shot** ([`EPOLLONESHOT`]): after one report the object is
+//!   disabled until its interest is modified
+//!
+//! The set holds weak references: an object closed everywhere else leaves
+//! the set, as on Linux. Waits report objects in turn, starting after the
+//! last one reported, so a busy object does not starve the others. Objects
+//! that are always ready (regular files and directories) cannot be added.
+//! An epoll object is itself pollable and can be added to another set.
+
+pub mod syscall;
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::ops::Bound;
+use spin::Mutex;
+
+use crate::abi::error::KernelError;
+use crate::object::capability::poll::{wait_for, PollOps, PollWait, POLLIN, POLL_ALWAYS};
+use crate::object::capability::StreamError;
+use crate::object::{KernelObject, WeakKernelObject};
+use crate::sync::waker::Waker;
+
+/// Add an object to the set
+pub const EPOLL_CTL_ADD: usize = 1;
+/// Remove an object from the set
+pub const EPOLL_CTL_DEL: usize = 2;
+/// Change the events and data of an object in the set
+pub const EPOLL_CTL_MOD: usize = 3;
+
+/// Report the object once, then disable it
+pub const EPOLLONESHOT: u32 = 1 << 30;
+/// Report an event only when it becomes ready
+pub const EPOLLET: u32 = 1 << 31;
+/// Accepted and ignored: every waiter is woken
+pub const EPOLLEXCLUSIVE: u32 = 1 << 28;
+/// Accepted and ignored: there is no suspend to prevent
+pub const EPOLLWAKEUP: u32 = 1 << 29;
+
+/// How deep epoll objects may be nested in each other
+const MAX_NESTING: usize = 4;
+
+/// Events of one object, as `struct epoll_event`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EpollEvent {
+    pub events: u32,
+    pub data: u64,
+}
+
+impl EpollEvent {
+    /// Size in user memory: the events, 4 bytes of padding, the data
+    pub const SIZE: usize = 16;
+
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        EpollEvent {
+            events: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            data: u64::from_le_bytes(bytes[8..16].try_into().unwrap()),
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; Self::SIZE] {
+        let mut bytes = [0u8; Self::SIZE];
+        bytes[0..4].copy_from_slice(&self.events.to_le_bytes());
+        bytes[8..16].copy_from_slice(&self.data.to_le_bytes());
+        bytes
+    }
+}
+
+/// An object of the set
+struct Interest {
+    object: WeakKernelObject,
+    /// Events asked for, with the EPOLLET and EPOLLONESHOT flags
+    events: u32,
+    data: u64,
+    /// Events ready at the last report, for edge-triggered interests
+    reported: u32,
+    /// Reported once and not modified since, for one-shot interests
+    disabled: bool,
+}
+
+impl Interest {
+    /// The events of interest ready on the object, `None` once it is closed
+    fn ready(&self) -> Option<u32> {
+        Some(self.object.upgrade()?.poll_events() & (self.events | POLL_ALWAYS))
+    }
+
+    /// Whether the events `ready` are reported
+    fn reports(&self, ready: u32) -> bool {
+        if self.disabled || ready == 0 {
+            return false;
+        }
+        self.events & EPOLLET == 0 || ready & !self.reported != 0
+    }
+}
+
+struct EpollState {
+    interests: BTreeMap<u32, Interest>,
+    /// Key of the last object reported, where the next wait starts
+    cursor: u32,
+}
+
+/// A set of objects waited on together
+pub struct EpollObject {
+    state: Mutex<EpollState>,
+    /// Woken when the set changes
+    waker: Waker,
+}
+
+impl EpollObject {
+    pub fn new() -> Arc<Self> {
+        Arc::new(EpollObject {
+            state: Mutex::new(EpollState { interests: BTreeMap::new(), cursor: 0 }),
+            waker: Waker::new_interruptible("epoll"),
+        })
+    }
+
+    /// Add `object` to the set under `key`
+    ///
+    /// # Errors
+    /// - `Exists` if `key` is in the set
+    /// - `NotPermitted` if the object is always ready
+    /// - `InvalidArgument` for this epoll object itself
+    /// - `Loop` if the object is an epoll object that contains this one, or
+    ///   nesting it would be too deep
+    pub fn add(&self, key: u32, object: &KernelObject, event: EpollEvent) -> Result<(), KernelError> {
+        if object.as_pollable().is_none() {
+            return Err(KernelError::NotPermitted);
+        }
+        if let Some(epoll) = object.as_epoll() {
+            if core::ptr::eq(epoll, self) {
+                return Err(KernelError::InvalidArgument);
+            }
+            if epoll.reaches(self, 1) {
+                return Err(KernelError::Loop);
+            }
+        }
+        {
+            let mut state = self.state.lock();
+            if state.interests.get(&key).is_some_and(|interest| interest.object.upgrade().is_some()) {
+                return Err(KernelError::Exists);
+            }
+            state.interests.insert(key, Interest {
+                object: object.downgrade(),
+                events: event.events,
+                data: event.data,
+                reported: 0,
+                disabled: false,
+            });
+        }
+        self.waker.wake_all();
+        Ok(())
+    }
+
+    /// Change the events and data of the object under `key`
+    ///
+    /// A one-shot interest is enabled again.
+    ///
+    /// # Errors
+    /// `NotFound` if `key` is not in the set
+    pub fn modify(&self, key: u32, event: EpollEvent) -> Result<(), KernelError> {
+        {
+            let mut state = self.state.lock();
+            let interest = state.interests.get_mut(&key).ok_or(KernelError::NotFound)?;
+            interest.events = event.events;
+            interest.data = event.data;
+            interest.reported = 0;
+            interest.disabled = false;
+        }
+        self.waker.wake_all();
+        Ok(())
+    }
+
+    /// Remove the object under `key` from the set
+    ///
+    /// # Errors
+    /// `NotFound` if `key` is not in the set
+    pub fn remove(&self, key: u32) -> Result<(), KernelError> {
+        self.state.lock().interests.remove(&key).map(|_| ()).ok_or(KernelError::NotFound)
+    }
+
+    /// Whether this set contains `target`, directly or through nested sets
+    ///
+    /// Nesting deeper than `MAX_NESTING` counts as containing it.
+    fn reaches(&self, target: &EpollObject, depth: usize) -> bool {
+        if depth > MAX_NESTING {
+            return true;
+        }
+        let nested: Vec<KernelObject> = self.state.lock().interests.values()
+            .filter_map(|interest| interest.object.upgrade())
+            .collect();
+        nested.iter().filter_map(KernelObject::as_epoll).any(|epoll| {
+            core::ptr::eq(epoll, target) || epoll.reaches(target, depth + 1)
+        })
+    }
+
+    /// The objects of the set that are still open
+    fn objects(&self) -> Vec<KernelObject> {
+        self.state.lock().interests.values().filter_map(|interest| interest.object.upgrade()).collect()
+    }
+
+    /// Take up to `max` events, marking what is reported
+    ///
+    /// Closed objects leave the set here.
+    fn collect(&self, max: usize) -> Vec<EpollEvent> {
+        let mut state = self.state.lock();
+        let cursor = state.cursor;
+        let keys: Vec<u32> = state.interests.range((Bound::Excluded(cursor), Bound::Unbounded))
+            .chain(state.interests.range(..=cursor))
+            .map(|(&key, _)| key)
+            .collect();
+
+        let mut events = Vec::new();
+        for key in keys {
+            if events.len() >= max {
+                break;
+            }
+            let Some(interest) = state.interests.get_mut(&key) else { continue };
+            let Some(ready) = interest.ready() else {
+                state.interests.remove(&key);
+                continue;
+            };
+            if !interest.reports(ready) {
+                // Events that went away are reported again when they return
+                interest.reported &= ready;
+                continue;
+            }
+            interest.reported = ready;
+            if interest.events & EPOLLONESHOT != 0 {
+                interest.disabled = true;
+            }
+            events.push(EpollEvent { events: ready, data: interest.data });
+            state.cursor = key;
+        }
+        events
+    }
+
+    /// Wait for events of the objects of the set
+    ///
+    /// # Arguments
+    /// * `max` - Most events to return
+    /// * `timeout_ticks` - Give up after this many ticks, `None` to wait
+    ///   forever
+    ///
+    /// # Returns
+    /// The events, none if the timeout expired
+    ///
+    /// # Errors
+    /// `Interrupted` if a signal arrived first
+    pub fn wait(&self, max: usize, timeout_ticks: Option<u64>) -> Result<Vec<EpollEvent>, StreamError> {
+        let sources: [&dyn PollOps; 1] = [self];
+        let mut events = Vec::new();
+        match wait_for(&sources, timeout_ticks, || {
+            events = self.collect(max);
+            !events.is_empty()
+        }) {
+            PollWait::Ready | PollWait::TimedOut => Ok(events),
+            PollWait::Interrupted => Err(StreamError::Interrupted),
+        }
+    }
+}
+
+impl PollOps for EpollObject {
+    /// Readable while an object of the set has an event to report
+    fn poll_events(&self) -> u32 {
+        let state = self.state.lock();
+        let ready = state.interests.values().any(|interest| interest.ready().is_some_and(|ready| interest.reports(ready)));
+        if ready { POLLIN } else { 0 }
+    }
+
+    /// Registered with the set itself and with every object in it
+    fn poll_register(&self, task_id: usize) -> bool {
+        self.waker.register(task_id);
+        let mut wakes = true;
+        for object in self.objects() {
+            if let Some(pollable) = object.as_pollable() {
+                wakes &= pollable.poll_register(task_id);
+            }
+        }
+        wakes
+    }
+
+    fn poll_unregister(&self, task_id: usize) {
+        self.waker.unregister(task_id);
+        for object in self.objects() {
+            if let Some(pollable) = object.as_pollable() {
+                pollable.poll_unregister(task_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::pipe::UnidirectionalPipe;
+    use crate::object::capability::poll::POLLOUT;
+
+    #[test_case]
+    fn test_epoll_level_and_edge_triggered() {
+        let (read_end, write_end) = UnidirectionalPipe::create_pair(16);
+        let epoll = EpollObject::new();
+        epoll.add(3, &read_end, EpollEvent { events: POLLIN, data: 30 }).unwrap();
+        epoll.add(4, &write_end, EpollEvent { events: POLLOUT | EPOLLET, data: 40 }).unwrap();
+        assert_eq!(epoll.add(3, &read_end, EpollEvent { events: POLLIN, data: 0 }), Err(KernelError::Exists));
+
+        // The write end is reported once, the empty read end not at all
+        assert_eq!(epoll.collect(8), [EpollEvent { events: POLLOUT, data: 40 }]);
+        assert!(epoll.collect(8).is_empty());
+
+        write_end.as_stream().unwrap().write(b"data").unwrap();
+        assert_eq!(epoll.poll_events(), POLLIN);
+        assert_eq!(epoll.collect(8), [EpollEvent { events: POLLIN, data: 30 }]);
+        assert_eq!(epoll.collect(8), [EpollEvent { events: POLLIN, data: 30 }]);
+
+        epoll.remove(3).unwrap();
+        assert_eq!(epoll.remove(3), Err(KernelError::NotFound));
+        assert!(epoll.collect(8).is_empty());
+    }
+
+    #[test_case]
+    fn test_epoll_oneshot_and_nesting() {
+        let (read_end, write_end) = UnidirectionalPipe::create_pair(16);
+        write_end.as_stream().unwrap().write(b"x").unwrap();
+        let epoll = EpollObject::new();
+        epoll.add(3, &read_end, EpollEvent { events: POLLIN | EPOLLONESHOT, data: 1 }).unwrap();
+        assert_eq!(epoll.collect(8).len(), 1);
+        assert!(epoll.collect(8).is_empty());
+        epoll.modify(3, EpollEvent { events: POLLIN | EPOLLONESHOT, data: 2 }).unwrap();
+        assert_eq!(epoll.collect(8), [EpollEvent { events: POLLIN, data: 2 }]);
+
+        let outer = KernelObject::from_epoll(EpollObject::new());
+        let inner = KernelObject::from_epoll(epoll.clone());
+        outer.as_epoll().unwrap().add(1, &inner, EpollEvent { events: POLLIN, data: 0 }).unwrap();
+        assert_eq!(epoll.add(9, &outer, EpollEvent { events: POLLIN, data: 0 }), Err(KernelError::Loop));
+        assert_eq!(epoll.add(9, &inner, EpollEvent { events: POLLIN, data: 0 }), Err(KernelError::InvalidArgument));
+
+        // Closed objects leave the set
+        drop(read_end);
+        assert!(epoll.collect(8).is_empty());
+        assert_eq!(epoll.remove(3), Err(KernelError::NotFound));
+    }
+}
@@ -0,0 +1,143 @@
+//! Epoll system calls
+//!
+//! Native interface of epoll objects: objects are added by handle, and
+//! events are read and written as `struct epoll_event` of Linux on 64-bit
+//! RISC-V (see [`EpollEvent::SIZE`]).
+
+use alloc::vec::Vec;
+
+use crate::{
+    abi::error::{fail, KernelError},
+    arch::Trapframe,
+    object::capability::poll::timeout_ticks,
+    object::KernelObject,
+    task::{
+        mytask,
+        signal::{copy_from_user, copy_to_user, interrupt_syscall},
+    },
+};
+
+use super::{EpollEvent, EpollObject, EPOLL_CTL_ADD, EPOLL_CTL_DEL, EPOLL_CTL_MOD};
+
+/// Most events returned by one wait
+pub const EPOLL_MAX_EVENTS: usize = 1024;
+
+/// Timeout in ticks of a timeout in milliseconds, usize::MAX to wait forever
+pub fn timeout_from_ms(timeout_ms: usize) -> Option<u64> {
+    (timeout_ms != usize::MAX).then(|| timeout_ticks((timeout_ms as u64).saturating_mul(1_000_000)))
+}
+
+/// sys_epoll_create - Create an epoll object
+///
+/// Returns: the handle of the epoll object, usize::MAX on error
+pub fn sys_epoll_create(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+    trapframe.increment_pc_next(task);
+
+    match task.handle_table.write().insert(KernelObject::from_epoll(EpollObject::new())) {
+        Ok(handle) => handle as usize,
+        Err(_) => fail(KernelError::TooManyHandles),
+    }
+}
+
+/// sys_epoll_control - Add, change or remove an object of an epoll object
+///
+/// Arguments:
+/// - epoll: handle of the epoll object
+/// - op: EPOLL_CTL_ADD / EPOLL_CTL_DEL / EPOLL_CTL_MOD
+/// - handle: handle of the object, which is also its key in the set
+/// - event_ptr: `struct epoll_event` with the events and data (ignored
+///   by EPOLL_CTL_DEL)
+///
+/// Returns: 0 on success, usize::MAX on error
+pub fn sys_epoll_control(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+    let epoll_handle = trapframe.get_arg(0) as u32;
+    let op = trapframe.get_arg(1);
+    let handle = trapframe.get_arg(2) as u32;
+    let event_ptr = trapframe.get_arg(3);
+    trapframe.increment_pc_next(task);
+
+    let Some(epoll) = task.handle_table.read().get(epoll_handle).cloned() else {
+        return fail(KernelError::BadHandle);
+    };
+    let Some(epoll) = epoll.as_epoll() else {
+        return fail(KernelError::InvalidArgument);
+    };
+    let Some(object) = task.handle_table.read().get(handle).cloned() else {
+        return fail(KernelError::BadHandle);
+    };
+    let event = if op == EPOLL_CTL_DEL {
+        EpollEvent { events: 0, data: 0 }
+    } else {
+        let mut bytes = [0u8; EpollEvent::SIZE];
+        if copy_from_user(task, event_ptr, &mut bytes).is_err() {
+            return fail(KernelError::BadAddress);
+        }
+        EpollEvent::from_bytes(&bytes)
+    };
+    let result = match op {
+        EPOLL_CTL_ADD => epoll.add(handle, object, event),
+        EPOLL_CTL_DEL => epoll.remove(handle),
+        EPOLL_CTL_MOD => epoll.modify(handle, event),
+        _ => Err(KernelError::InvalidArgument),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(error) => fail(error),
+    }
+}
+
+/// Write `events` to user memory as an array of `struct epoll_event`
+pub fn write_events(events_ptr: usize, events: &[EpollEvent]) -> Result<(), KernelError> {
+    let task = mytask().ok_or(KernelError::BadAddress)?;
+    let bytes: Vec<u8> = events.iter().flat_map(|event| event.to_bytes()).collect();
+    copy_to_user(task, events_ptr, &bytes).map_err(|_| KernelError::BadAddress)
+}
+
+/// sys_epoll_wait - Wait for events of the objects of an epoll object
+///
+/// Arguments:
+/// - epoll: handle of the epoll object
+/// - events_ptr: array receiving the events as `struct epoll_event`
+/// - max_events: length of the array, 1 to `EPOLL_MAX_EVENTS`
+/// - timeout: in milliseconds, 0 to only check, usize::MAX to wait forever
+///
+/// Returns: the number of events, 0 if the timeout expired, usize::MAX on
+/// error
+///
+/// A wait interrupted by a signal is restarted or fails as described in
+/// `crate::task::signal`.
+pub fn sys_epoll_wait(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+    let epoll_handle = trapframe.get_arg(0) as u32;
+    let events_ptr = trapframe.get_arg(1);
+    let max_events = trapframe.get_arg(2);
+    let timeout = trapframe.get_arg(3);
+    trapframe.increment_pc_next(task);
+
+    if !(1..=EPOLL_MAX_EVENTS).contains(&max_events) {
+        return fail(KernelError::InvalidArgument);
+    }
+    let epoll = match task.handle_table.read().get(epoll_handle).cloned() {
+        Some(KernelObject::Epoll(epoll)) => epoll,
+        Some(_) => return fail(KernelError::InvalidArgument),
+        None => return fail(KernelError::BadHandle),
+    };
+    match epoll.wait(max_events, timeout_from_ms(timeout)) {
+        Ok(events) => match write_events(events_ptr, &events) {
+            Ok(()) => events.len(),
+            Err(error) => fail(error),
+        },
+        Err(_) => interrupt_syscall(task, trapframe),
+    }
+}
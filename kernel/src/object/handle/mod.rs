@@ -17,10 +17,12 @@ pub struct HandleTable {
     metadata: Box<[Option<HandleMetadata>; Self::MAX_HANDLES]>,
     /// Stack of available handle numbers for O(1) allocation
     free_handles: Vec<Handle>,
+    /// Most handles that may be open at once (RLIMIT_NOFILE)
+    limit: usize,
 }
 
 impl HandleTable {
-    const MAX_HANDLES: usize = 1024; // POSIX standard limit (fd)
+    pub const MAX_HANDLES: usize = 1024; // POSIX standard limit (fd)
     
 pub fn new() -> Self {
     // Initialize free handle stack in forward order (0 will be allocated first)
@@ -42,8 +44,16 @@ pub fn new() -> Self {
         handles,
         metadata,
         free_handles,
+        limit: Self::MAX_HANDLES,
     }
 }
+
+    /// Limit the number of handles open at once, at most `MAX_HANDLES`
+    ///
+    /// Handles already open stay open; only new ones are refused.
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit.min(Self::MAX_HANDLES);
+    }
     
     /// O(1) allocation with automatic metadata inference
     pub fn insert(&mut self, obj: KernelObject) -> Result<Handle, &'static str> {
@@ -53,6 +63,9 @@ pub fn new() -> Self {
     
     /// O(1) allocation with explicit metadata
     pub fn insert_with_metadata(&mut self, obj: KernelObject, metadata: HandleMetadata) -> Result<Handle, &'static str> {
+        if self.open_count() >= self.limit {
+            return Err("Too many open KernelObjects, limit reached");
+        }
         if let Some(handle) = self.free_handles.pop() {
             self.handles[handle as usize] = Some(obj);
             self.metadata[handle as usize] = Some(metadata);
@@ -62,6 +75,24 @@ pub fn new() -> Self {
         }
     }
     
+    /// Install an object at a given handle number, like `dup2`
+    ///
+    /// An object already open at `handle` is closed.
+    pub fn insert_at(&mut self, handle: Handle, obj: KernelObject, metadata: HandleMetadata) -> Result<(), &'static str> {
+        if handle as usize >= Self::MAX_HANDLES {
+            return Err("Invalid handle");
+        }
+        if self.handles[handle as usize].is_none() {
+            if self.open_count() >= self.limit {
+                return Err("Too many open KernelObjects, limit reached");
+            }
+            self.free_handles.retain(|&free| free != handle);
+        }
+        self.handles[handle as usize] = Some(obj);
+        self.metadata[handle as usize] = Some(metadata);
+        Ok(())
+    }
+
     /// Infer metadata from KernelObject type and usage context
     /// 
     /// This function provides reasonable defaults for handle roles based on the KernelObject type.
@@ -88,6 +119,54 @@ pub fn new() -> Self {
                 // Event subscriptions are used for receiving events
                 HandleType::EventSubscription
             }
+            KernelObject::SharedMemory(_) => {
+                // Shared memory is used to exchange data between tasks
+                HandleType::IpcChannel
+            }
+            KernelObject::Process(_) => {
+                // Process handles are waited on and signalled, not shared data
+                HandleType::Regular
+            }
+            KernelObject::Epoll(_) => {
+                // Epoll objects are waited on, not shared data
+                HandleType::Regular
+            }
+            KernelObject::EventFd(_) => {
+                // Event counters carry wakeups between tasks
+                HandleType::IpcChannel
+            }
+            KernelObject::TimerFd(_) => {
+                // Timers are waited on, not shared data
+                HandleType::Regular
+            }
+            KernelObject::Socket(_) => {
+                // Sockets carry data and handles between tasks
+                HandleType::IpcChannel
+            }
+            KernelObject::Semaphore(_) => {
+                // Semaphores synchronize tasks
+                HandleType::IpcChannel
+            }
+            KernelObject::Ring(_) => {
+                // Rings carry the I/O of the task that enters them
+                HandleType::Regular
+            }
+            KernelObject::Bus(_) => {
+                // Bus connections carry messages and handles between tasks
+                HandleType::IpcChannel
+            }
+            KernelObject::NetConfig(_) => {
+                // Network configuration channels carry requests to the kernel
+                HandleType::IpcChannel
+            }
+            KernelObject::DeviceEvents(_) => {
+                // Device event channels carry notifications from the kernel
+                HandleType::IpcChannel
+            }
+            KernelObject::GpioLines(_) => {
+                // GPIO line requests drive the hardware of the task that holds them
+                HandleType::Regular
+            }
         };
 
         HandleMetadata {
@@ -211,6 +290,42 @@ pub fn new() -> Self {
                 KernelObject::EventSubscription(_) => {
                     Some(introspection::KernelObjectInfo::for_event_subscription(handle_role))
                 }
+                KernelObject::SharedMemory(_) => {
+                    Some(introspection::KernelObjectInfo::for_shared_memory(handle_role, readable, writable))
+                }
+                KernelObject::Process(_) => {
+                    Some(introspection::KernelObjectInfo::for_process(handle_role))
+                }
+                KernelObject::Epoll(_) => {
+                    Some(introspection::KernelObjectInfo::for_epoll(handle_role))
+                }
+                KernelObject::EventFd(_) => {
+                    Some(introspection::KernelObjectInfo::for_eventfd(handle_role))
+                }
+                KernelObject::TimerFd(_) => {
+                    Some(introspection::KernelObjectInfo::for_timerfd(handle_role))
+                }
+                KernelObject::Socket(_) => {
+                    Some(introspection::KernelObjectInfo::for_socket(handle_role, readable, writable))
+                }
+                KernelObject::Semaphore(_) => {
+                    Some(introspection::KernelObjectInfo::for_semaphore(handle_role))
+                }
+                KernelObject::Ring(_) => {
+                    Some(introspection::KernelObjectInfo::for_ring(handle_role))
+                }
+                KernelObject::Bus(_) => {
+                    Some(introspection::KernelObjectInfo::for_bus(handle_role))
+                }
+                KernelObject::NetConfig(_) => {
+                    Some(introspection::KernelObjectInfo::for_netconfig(handle_role))
+                }
+                KernelObject::DeviceEvents(_) => {
+                    Some(introspection::KernelObjectInfo::for_device_events(handle_role))
+                }
+                KernelObject::GpioLines(_) => {
+                    Some(introspection::KernelObjectInfo::for_gpio_lines(handle_role))
+                }
             }
         } else {
             None
@@ -237,7 +352,7 @@ impl Default for HandleTable {
 /// let log_file = file_obj.clone();
 /// 
 /// // Handle for reading configuration
-/// let config_handle = task.handle_table.insert_with_metadata(
+/// let config_handle = task.handle_table.write().insert_with_metadata(
 ///     KernelObject::File(config_file),
 ///     HandleMetadata {
 ///         handle_type: HandleType::ConfigFile,
@@ -247,7 +362,7 @@ impl Default for HandleTable {
 /// )?;
 /// 
 /// // Handle for writing logs
-/// let log_handle = task.handle_table.insert_with_metadata(
+/// let log_handle = task.handle_table.write().insert_with_metadata(
 ///     KernelObject::File(log_file),
 ///     HandleMetadata {
 ///         handle_type: HandleType::LogOutput,
@@ -275,6 +390,7 @@ impl Clone for HandleTable {
             handles: handles_clone,
             metadata: metadata_clone,
             free_handles: self.free_handles.clone(),
+            limit: self.limit,
         }
     }
 }
@@ -5,6 +5,7 @@
 use crate::{
     arch::Trapframe, 
     task::mytask, 
+    task::signal::put_user,
     object::{
         introspection::KernelObjectInfo,
         handle::HandleType,
@@ -37,20 +38,14 @@ pub fn sys_handle_query(trapframe: &mut Trapframe) -> usize {
     // Increment PC to avoid infinite loop
     trapframe.increment_pc_next(task);
     
-    // Translate the pointer to get access to the info structure
-    let info_vaddr = match task.vm_manager.translate_vaddr(info_ptr) {
-        Some(addr) => addr as *mut KernelObjectInfo,
-        None => return usize::MAX, // Invalid pointer
-    };
-    
     // Get object information
-    match task.handle_table.get_object_info(handle) {
+    match task.handle_table.read().get_object_info(handle) {
         Some(info) => {
             // Write the information to user space
-            unsafe {
-                *info_vaddr = info;
+            match put_user::<KernelObjectInfo>(task, info_ptr, info) {
+                Ok(()) => 0, // Success
+                Err(_) => usize::MAX, // Invalid pointer
             }
-            0 // Success
         }
         None => usize::MAX, // Invalid handle
     }
@@ -85,7 +80,7 @@ pub fn sys_handle_set_role(trapframe: &mut Trapframe) -> usize {
     };
     
     // Get current metadata and verify handle exists
-    let current_metadata = match task.handle_table.get_metadata(handle) {
+    let current_metadata = match task.handle_table.read().get_metadata(handle) {
         Some(meta) => meta.clone(),
         None => return usize::MAX, // Invalid handle
     };
@@ -98,7 +93,7 @@ pub fn sys_handle_set_role(trapframe: &mut Trapframe) -> usize {
     };
     
     // Update metadata in handle table
-    if let Err(_) = task.handle_table.update_metadata(handle, new_metadata) {
+    if let Err(_) = task.handle_table.write().update_metadata(handle, new_metadata) {
         return usize::MAX; // Update failed
     }
     
@@ -124,7 +119,7 @@ pub fn sys_handle_close(trapframe: &mut Trapframe) -> usize {
     let handle = trapframe.get_arg(0) as u32;
     trapframe.increment_pc_next(task);
     
-    if task.handle_table.remove(handle).is_some() {
+    if task.handle_table.write().remove(handle).is_some() {
         0 // Success
     } else {
         usize::MAX // Invalid handle
@@ -152,14 +147,14 @@ pub fn sys_handle_duplicate(trapframe: &mut Trapframe) -> usize {
     trapframe.increment_pc_next(task);
     
     // Check if the handle exists and get the kernel object
-    if let Some(kernel_obj) = task.handle_table.get(handle) {
-        // Insert a new handle for the same object
-        match task.handle_table.insert(kernel_obj.clone()) {
-            Ok(new_handle) => new_handle as usize,
-            Err(_) => usize::MAX, // Handle table full
-        }
-    } else {
-        usize::MAX // Invalid handle
+    let Some(kernel_obj) = task.handle_table.read().get(handle).cloned() else {
+        return usize::MAX; // Invalid handle
+    };
+
+    // Insert a new handle for the same object
+    match task.handle_table.write().insert(kernel_obj) {
+        Ok(new_handle) => new_handle as usize,
+        Err(_) => usize::MAX, // Handle table full
     }
 }
 
@@ -217,8 +212,8 @@ pub fn sys_handle_control(trapframe: &mut Trapframe) -> usize {
     trapframe.increment_pc_next(task);
     
     // Get the kernel object from the handle table
-    let kernel_object = match task.handle_table.get(handle) {
-        Some(obj) => obj.clone(),
+    let kernel_object = match task.handle_table.read().get(handle).cloned() {
+        Some(obj) => obj,
         None => return usize::MAX, // Invalid handle
     };
     
@@ -238,3 +233,81 @@ pub fn sys_handle_control(trapframe: &mut Trapframe) -> usize {
         Err(_) => usize::MAX, // Error
     }
 }
+
+/// Most handles of one sys_handle_poll
+pub const HANDLE_POLL_MAX: usize = 1024;
+
+/// Size of one entry of sys_handle_poll: `{ handle: i32, events: u16, revents: u16 }`,
+/// the layout of Linux's `struct pollfd`
+pub const HANDLE_POLL_ENTRY_SIZE: usize = 8;
+
+/// sys_handle_poll - Wait until one of a set of handles has an event
+///
+/// # Arguments
+/// - entries_ptr: Array of `{ handle: i32, events: u16, revents: u16 }`;
+///   entries with a negative handle are ignored
+/// - count: Number of entries
+/// - timeout: In milliseconds, 0 to only check, usize::MAX to wait forever
+///
+/// # Returns
+/// - The number of entries with events, written to their `revents`
+/// - 0 if the timeout expired
+/// - usize::MAX on error
+pub fn sys_handle_poll(trapframe: &mut Trapframe) -> usize {
+    use crate::object::capability::poll::{poll, PollRequest};
+    use crate::object::epoll::syscall::timeout_from_ms;
+    use crate::task::signal::{copy_from_user, copy_to_user, interrupt_syscall};
+
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let entries_ptr = trapframe.get_arg(0);
+    let count = trapframe.get_arg(1);
+    let timeout = trapframe.get_arg(2);
+
+    trapframe.increment_pc_next(task);
+
+    if count > HANDLE_POLL_MAX {
+        return usize::MAX;
+    }
+    let mut entries = alloc::vec![0u8; count * HANDLE_POLL_ENTRY_SIZE];
+    if copy_from_user(task, entries_ptr, &mut entries).is_err() {
+        return usize::MAX;
+    }
+    // Entries with a negative handle are ignored: they ask for and report nothing
+    let mut polled = alloc::vec::Vec::new();
+    let mut requests = alloc::vec::Vec::new();
+    for (index, entry) in entries.chunks_exact(HANDLE_POLL_ENTRY_SIZE).enumerate() {
+        let handle = i32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]);
+        if handle < 0 {
+            continue;
+        }
+        polled.push(index);
+        requests.push(PollRequest {
+            object: task.handle_table.read().get(handle as u32).map(|object| object.downgrade()),
+            events: u16::from_le_bytes([entry[4], entry[5]]) as u32,
+        });
+    }
+
+    let revents = match poll(&requests, timeout_from_ms(timeout)) {
+        Ok(revents) => revents,
+        Err(_) => return interrupt_syscall(task, trapframe),
+    };
+    for entry in entries.chunks_exact_mut(HANDLE_POLL_ENTRY_SIZE) {
+        entry[6..8].fill(0);
+    }
+    let mut ready = 0;
+    for (index, revents) in polled.into_iter().zip(revents) {
+        let offset = index * HANDLE_POLL_ENTRY_SIZE;
+        entries[offset + 6..offset + 8].copy_from_slice(&(revents as u16).to_le_bytes());
+        if revents != 0 {
+            ready += 1;
+        }
+    }
+    if copy_to_user(task, entries_ptr, &entries).is_err() {
+        return usize::MAX;
+    }
+    ready
+}
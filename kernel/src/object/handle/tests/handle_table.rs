@@ -126,6 +126,55 @@ fn test_handle_table_limits() {
     assert_eq!(result.unwrap_err(), "Too many open KernelObjects, limit reached");
 }
 
+#[test_case]
+fn test_handle_table_set_limit() {
+    let mut table = HandleTable::new();
+    table.set_limit(2);
+
+    let insert = |table: &mut HandleTable| {
+        let mock_file = Arc::new(MockFileObject::new(b"limited".to_vec()));
+        table.insert(KernelObject::File(mock_file))
+    };
+    let first = insert(&mut table).unwrap();
+    insert(&mut table).unwrap();
+    assert!(insert(&mut table).is_err());
+
+    // Closing a handle makes room again
+    table.remove(first).unwrap();
+    assert!(insert(&mut table).is_ok());
+
+    // Lowering the limit keeps open handles but refuses new ones
+    table.set_limit(1);
+    assert_eq!(table.open_count(), 2);
+    assert!(insert(&mut table).is_err());
+
+    // A clone keeps the limit
+    let mut cloned = table.clone();
+    assert!(insert(&mut cloned).is_err());
+}
+
+#[test_case]
+fn test_handle_table_insert_at() {
+    let mut table = HandleTable::new();
+    let object = |data: &[u8]| KernelObject::File(Arc::new(MockFileObject::new(data.to_vec())));
+
+    table.insert_at(3, object(b"three"), Default::default()).unwrap();
+    assert!(table.is_valid_handle(3));
+    assert_eq!(table.open_count(), 1);
+    // Handle 3 is no longer handed out by insert
+    for expected in [0, 1, 2, 4] {
+        assert_eq!(table.insert(object(b"other")).unwrap(), expected);
+    }
+
+    // Replacing an open handle keeps the count
+    table.insert_at(3, object(b"replaced"), Default::default()).unwrap();
+    assert_eq!(table.open_count(), 5);
+    assert!(table.insert_at(HandleTable::MAX_HANDLES as Handle, object(b"x"), Default::default()).is_err());
+
+    table.set_limit(5);
+    assert!(table.insert_at(7, object(b"x"), Default::default()).is_err());
+}
+
 #[test_case]
 fn test_handle_table_handle_reuse() {
     let mut table = HandleTable::new();
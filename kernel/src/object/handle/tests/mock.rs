@@ -120,6 +120,7 @@ impl crate::fs::FileObject for MockFileObject {
             accessed_time: 0,
             file_id: 1,
             link_count: 1,
+            owner: None,
         })
     }
 
@@ -223,6 +224,7 @@ impl crate::fs::FileObject for MockTaskFileObject {
             accessed_time: 0,
             file_id: 1,
             link_count: 1,
+            owner: None,
         })
     }
 
@@ -308,6 +310,13 @@ impl crate::ipc::StreamIpcOps for MockPipeObject {
     }
 }
 
+impl crate::object::capability::PollOps for MockPipeObject {
+    fn poll_events(&self) -> u32 {
+        use crate::object::capability::poll::{POLLIN, POLLOUT};
+        if self.read_buffer.lock().is_empty() { POLLOUT } else { POLLIN | POLLOUT }
+    }
+}
+
 impl crate::ipc::pipe::PipeObject for MockPipeObject {
     fn has_readers(&self) -> bool {
         true // Mock implementation
@@ -332,4 +341,28 @@ impl crate::ipc::pipe::PipeObject for MockPipeObject {
     fn is_writable(&self) -> bool {
         true // Mock implementation
     }
+
+    fn set_buffer_size(&self, _size: usize) -> Result<usize, crate::abi::error::KernelError> {
+        Ok(1024) // Mock buffer size
+    }
+
+    fn set_nonblocking(&self, _nonblocking: bool) {
+        // Mock implementation
+    }
+
+    fn is_nonblocking(&self) -> bool {
+        false // Mock implementation
+    }
+
+    fn splice_to(&self, sink: &dyn StreamOps, len: usize, _nonblocking: bool) -> Result<usize, StreamError> {
+        let mut buffer = alloc::vec![0u8; len];
+        let count = self.read(&mut buffer)?;
+        sink.write(&buffer[..count])
+    }
+
+    fn splice_from(&self, source: &dyn StreamOps, len: usize, _nonblocking: bool) -> Result<usize, StreamError> {
+        let mut buffer = alloc::vec![0u8; len];
+        let count = source.read(&mut buffer)?;
+        self.write(&buffer[..count])
+    }
 }
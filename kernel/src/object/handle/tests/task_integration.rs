@@ -27,16 +27,16 @@ fn test_task_handle_table_integration() {
     let kernel_obj2 = KernelObject::File(mock_file2);
 
     // Insert objects into task's handle table
-    let handle1 = task.handle_table.insert(kernel_obj1).unwrap();
-    let handle2 = task.handle_table.insert(kernel_obj2).unwrap();
+    let handle1 = task.handle_table.write().insert(kernel_obj1).unwrap();
+    let handle2 = task.handle_table.write().insert(kernel_obj2).unwrap();
 
-    assert_eq!(task.handle_table.open_count(), 2);
+    assert_eq!(task.handle_table.read().open_count(), 2);
     assert_eq!(handle1, 0); // First handle should be 0
     assert_eq!(handle2, 1); // Second handle should be 1
 
     // Test accessing files through handles
-    let retrieved_obj1 = task.handle_table.get(handle1).unwrap();
-    let retrieved_obj2 = task.handle_table.get(handle2).unwrap();
+    let retrieved_obj1 = task.handle_table.read().get(handle1).cloned().unwrap();
+    let retrieved_obj2 = task.handle_table.read().get(handle2).cloned().unwrap();
 
     assert!(retrieved_obj1.as_stream().is_some());
     assert!(retrieved_obj2.as_stream().is_some());
@@ -57,8 +57,8 @@ fn test_task_handle_table_integration() {
     }
 
     // Clean up
-    task.handle_table.close_all();
-    assert_eq!(task.handle_table.open_count(), 0);
+    task.handle_table.write().close_all();
+    assert_eq!(task.handle_table.read().open_count(), 0);
 }
 
 #[test_case]
@@ -75,7 +75,7 @@ fn test_task_handle_table_fd_compatibility() {
             format!("file_{}", i).into_bytes()
         ));
         let kernel_obj = KernelObject::File(mock_file);
-        let handle = task.handle_table.insert(kernel_obj).unwrap();
+        let handle = task.handle_table.write().insert(kernel_obj).unwrap();
         handles.push(handle);
     }
 
@@ -85,13 +85,13 @@ fn test_task_handle_table_fd_compatibility() {
     }
 
     // Close some handles (like closing FDs)
-    task.handle_table.remove(handles[3]).unwrap();
-    task.handle_table.remove(handles[7]).unwrap();
+    task.handle_table.write().remove(handles[3]).unwrap();
+    task.handle_table.write().remove(handles[7]).unwrap();
 
     // Next allocation should reuse freed handles
     let mock_file = Arc::new(MockTaskFileObject::new(b"reused_file".to_vec()));
     let kernel_obj = KernelObject::File(mock_file);
-    let reused_handle = task.handle_table.insert(kernel_obj).unwrap();
+    let reused_handle = task.handle_table.write().insert(kernel_obj).unwrap();
 
     // Should reuse either handle 3 or 7 (stack-based allocation)
     assert!(reused_handle == 3 || reused_handle == 7);
@@ -110,20 +110,20 @@ fn test_task_handle_table_process_lifecycle() {
             format!("process_file_{}", i).into_bytes()
         ));
         let kernel_obj = KernelObject::File(mock_file);
-        let handle = task.handle_table.insert(kernel_obj).unwrap();
+        let handle = task.handle_table.write().insert(kernel_obj).unwrap();
         open_handles.push(handle);
     }
 
-    assert_eq!(task.handle_table.open_count(), 5);
+    assert_eq!(task.handle_table.read().open_count(), 5);
 
     // Simulate process termination - all handles should be closed
-    task.handle_table.close_all();
+    task.handle_table.write().close_all();
 
-    assert_eq!(task.handle_table.open_count(), 0);
-    assert_eq!(task.handle_table.active_handles().len(), 0);
+    assert_eq!(task.handle_table.read().open_count(), 0);
+    assert_eq!(task.handle_table.read().active_handles().len(), 0);
 
     // All handles should now be available for reuse
-    assert_eq!(task.handle_table.free_handles.len(), HandleTable::MAX_HANDLES);
+    assert_eq!(task.handle_table.read().free_handles.len(), HandleTable::MAX_HANDLES);
 }
 
 #[test_case]
@@ -132,9 +132,9 @@ fn test_task_handle_table_error_conditions() {
     task.init();
 
     // Test invalid handle operations
-    assert!(task.handle_table.get(999).is_none());
-    assert!(!task.handle_table.is_valid_handle(999));
-    assert!(task.handle_table.remove(999).is_none());
+    assert!(task.handle_table.read().get(999).cloned().is_none());
+    assert!(!task.handle_table.read().is_valid_handle(999));
+    assert!(task.handle_table.write().remove(999).is_none());
 
     // Test handle limit enforcement
     let mut handles = Vec::new();
@@ -145,14 +145,14 @@ fn test_task_handle_table_error_conditions() {
             format!("limit_test_{}", i).into_bytes()
         ));
         let kernel_obj = KernelObject::File(mock_file);
-        let handle = task.handle_table.insert(kernel_obj).unwrap();
+        let handle = task.handle_table.write().insert(kernel_obj).unwrap();
         handles.push(handle);
     }
 
     // Next insertion should fail
     let mock_file = Arc::new(MockTaskFileObject::new(b"overflow".to_vec()));
     let kernel_obj = KernelObject::File(mock_file);
-    let result = task.handle_table.insert(kernel_obj);
+    let result = task.handle_table.write().insert(kernel_obj);
     
     assert!(result.is_err());
     assert_eq!(result.unwrap_err(), "Too many open KernelObjects, limit reached");
@@ -171,47 +171,47 @@ fn test_task_handle_table_clone_behavior() {
     let kernel_obj1 = KernelObject::File(mock_file1);
     let kernel_obj2 = KernelObject::File(mock_file2);
 
-    let handle1 = parent_task.handle_table.insert(kernel_obj1).unwrap();
-    let handle2 = parent_task.handle_table.insert(kernel_obj2).unwrap();
+    let handle1 = parent_task.handle_table.write().insert(kernel_obj1).unwrap();
+    let handle2 = parent_task.handle_table.write().insert(kernel_obj2).unwrap();
 
-    assert_eq!(parent_task.handle_table.open_count(), 2);
+    assert_eq!(parent_task.handle_table.read().open_count(), 2);
 
     // Clone the task (this should clone the handle table)
     let mut child_task = parent_task.clone_task(CloneFlags::default()).unwrap();
 
     // Child should inherit parent's handle table (Linux fork() behavior)
-    assert_eq!(child_task.handle_table.open_count(), 2);
+    assert_eq!(child_task.handle_table.read().open_count(), 2);
     
     // Parent's handle table should be unaffected
-    assert_eq!(parent_task.handle_table.open_count(), 2);
-    assert!(parent_task.handle_table.is_valid_handle(handle1));
-    assert!(parent_task.handle_table.is_valid_handle(handle2));
+    assert_eq!(parent_task.handle_table.read().open_count(), 2);
+    assert!(parent_task.handle_table.read().is_valid_handle(handle1));
+    assert!(parent_task.handle_table.read().is_valid_handle(handle2));
 
     // Child should have inherited the same handles
-    assert!(child_task.handle_table.is_valid_handle(handle1));
-    assert!(child_task.handle_table.is_valid_handle(handle2));
+    assert!(child_task.handle_table.read().is_valid_handle(handle1));
+    assert!(child_task.handle_table.read().is_valid_handle(handle2));
 
     // Verify that child and parent have independent handle tables (closing in one doesn't affect the other)
-    child_task.handle_table.remove(handle1);
-    assert_eq!(child_task.handle_table.open_count(), 1);
-    assert_eq!(parent_task.handle_table.open_count(), 2); // Parent still has both handles
-    assert!(parent_task.handle_table.is_valid_handle(handle1)); // Parent's handle1 still valid
+    child_task.handle_table.write().remove(handle1);
+    assert_eq!(child_task.handle_table.read().open_count(), 1);
+    assert_eq!(parent_task.handle_table.read().open_count(), 2); // Parent still has both handles
+    assert!(parent_task.handle_table.read().is_valid_handle(handle1)); // Parent's handle1 still valid
 
     // Child and parent should have independent handle tables for new allocations
     let mock_child_file = Arc::new(MockTaskFileObject::new(b"child_file".to_vec()));
     let child_kernel_obj = KernelObject::File(mock_child_file);
-    let child_handle = child_task.handle_table.insert(child_kernel_obj).unwrap();
+    let child_handle = child_task.handle_table.write().insert(child_kernel_obj).unwrap();
 
-    assert_eq!(child_task.handle_table.open_count(), 2); // handle2 + new child_handle
-    assert_eq!(parent_task.handle_table.open_count(), 2); // Still has both original handles
+    assert_eq!(child_task.handle_table.read().open_count(), 2); // handle2 + new child_handle
+    assert_eq!(parent_task.handle_table.read().open_count(), 2); // Still has both original handles
     
     // New child handle should reuse the freed handle1 slot
     assert_eq!(child_handle, handle1); // Should reuse handle1 (0)
 
     // Verify that the file objects are still accessible from both tasks
     // and contain the same data (Arc sharing), but positions are also shared
-    if let Some(parent_obj) = parent_task.handle_table.get(handle2) {
-        if let Some(child_obj) = child_task.handle_table.get(handle2) {
+    if let Some(parent_obj) = parent_task.handle_table.read().get(handle2).cloned() {
+        if let Some(child_obj) = child_task.handle_table.read().get(handle2).cloned() {
             if let (Some(parent_stream), Some(child_stream)) = (parent_obj.as_stream(), child_obj.as_stream()) {
                 // Read from parent first - this will advance the shared position
                 let mut parent_buffer = [0u8; 13];
@@ -253,23 +253,23 @@ fn test_task_handle_table_memory_efficiency() {
                 format!("iter_{}_file_{}", iteration, i).into_bytes()
             ));
             let kernel_obj = KernelObject::File(mock_file);
-            let handle = task.handle_table.insert(kernel_obj).unwrap();
+            let handle = task.handle_table.write().insert(kernel_obj).unwrap();
             temp_handles.push(handle);
         }
 
-        assert_eq!(task.handle_table.open_count(), 20);
+        assert_eq!(task.handle_table.read().open_count(), 20);
 
         // Free all handles
         for handle in temp_handles {
-            assert!(task.handle_table.remove(handle).is_some());
+            assert!(task.handle_table.write().remove(handle).is_some());
         }
 
-        assert_eq!(task.handle_table.open_count(), 0);
+        assert_eq!(task.handle_table.read().open_count(), 0);
     }
 
     // After all iterations, handle table should be in clean state
-    assert_eq!(task.handle_table.open_count(), 0);
-    assert_eq!(task.handle_table.free_handles.len(), HandleTable::MAX_HANDLES);
+    assert_eq!(task.handle_table.read().open_count(), 0);
+    assert_eq!(task.handle_table.read().free_handles.len(), HandleTable::MAX_HANDLES);
 }
 
 #[test_case]
@@ -280,10 +280,10 @@ fn test_task_handle_table_capability_access() {
     // Test accessing different capabilities through handles
     let mock_file = Arc::new(MockTaskFileObject::new(b"capability_test_data".to_vec()));
     let kernel_obj = KernelObject::File(mock_file);
-    let handle = task.handle_table.insert(kernel_obj).unwrap();
+    let handle = task.handle_table.write().insert(kernel_obj).unwrap();
 
     // Test stream capability
-    if let Some(obj) = task.handle_table.get(handle) {
+    if let Some(obj) = task.handle_table.read().get(handle).cloned() {
         if let Some(stream) = obj.as_stream() {
             let mut buffer = [0u8; 10];
             let bytes_read = stream.read(&mut buffer).unwrap();
@@ -33,8 +33,30 @@ pub enum KernelObjectType {
     CharDevice = 5,
     /// Block device (future)
     BlockDevice = 6,
-    /// Socket (future)
+    /// Local socket
     Socket = 7,
+    /// Shared memory segment
+    SharedMemory = 8,
+    /// Process handle
+    Process = 9,
+    /// Set of objects waited on together
+    Epoll = 10,
+    /// Counter of events between tasks (eventfd)
+    EventFd = 11,
+    /// Timer whose expiries are read (timerfd)
+    TimerFd = 12,
+    /// Counting semaphore
+    Semaphore = 13,
+    /// Submission and completion queues of batched I/O
+    Ring = 14,
+    /// Connection to the message bus
+    Bus = 15,
+    /// Channel of requests to the network configuration
+    NetConfig = 16,
+    /// Channel of device hotplug events
+    DeviceEvents = 17,
+    /// Lines of a GPIO controller held by a task
+    GpioLines = 18,
     /// Unknown or unsupported type
     Unknown = 0,
 }
@@ -138,6 +160,210 @@ impl KernelObjectInfo {
         }
     }
     
+    /// Create info for a SharedMemory KernelObject
+    pub fn for_shared_memory(handle_role: HandleRole, readable: bool, writable: bool) -> Self {
+        Self {
+            object_type: KernelObjectType::SharedMemory,
+            capabilities: ObjectCapabilities {
+                stream_ops: false,
+                file_ops: false,
+                pipe_ops: false,
+                event_ops: false,
+                clone_ops: false,
+                reserved: [false; 3],
+            },
+            handle_role,
+            access_mode: Self::encode_access_mode(readable, writable),
+        }
+    }
+    
+    /// Create info for a Process KernelObject
+    pub fn for_process(handle_role: HandleRole) -> Self {
+        Self {
+            object_type: KernelObjectType::Process,
+            capabilities: ObjectCapabilities {
+                stream_ops: true,  // Reading waits for the exit status
+                file_ops: false,
+                pipe_ops: false,
+                event_ops: false,
+                clone_ops: false,
+                reserved: [false; 3],
+            },
+            handle_role,
+            access_mode: Self::encode_access_mode(true, false), // Process handles are read-only
+        }
+    }
+
+    /// Create info for an Epoll KernelObject
+    pub fn for_epoll(handle_role: HandleRole) -> Self {
+        Self {
+            object_type: KernelObjectType::Epoll,
+            capabilities: ObjectCapabilities {
+                stream_ops: false,
+                file_ops: false,
+                pipe_ops: false,
+                event_ops: false,
+                clone_ops: false,
+                reserved: [false; 3],
+            },
+            handle_role,
+            access_mode: Self::encode_access_mode(true, false),
+        }
+    }
+
+    /// Create info for an EventFd KernelObject
+    pub fn for_eventfd(handle_role: HandleRole) -> Self {
+        Self {
+            object_type: KernelObjectType::EventFd,
+            capabilities: ObjectCapabilities {
+                stream_ops: true,  // Reading takes the counter, writing adds to it
+                file_ops: false,
+                pipe_ops: false,
+                event_ops: false,
+                clone_ops: false,
+                reserved: [false; 3],
+            },
+            handle_role,
+            access_mode: Self::encode_access_mode(true, true),
+        }
+    }
+
+    /// Create info for a TimerFd KernelObject
+    pub fn for_timerfd(handle_role: HandleRole) -> Self {
+        Self {
+            object_type: KernelObjectType::TimerFd,
+            capabilities: ObjectCapabilities {
+                stream_ops: true,  // Reading takes the number of expiries
+                file_ops: false,
+                pipe_ops: false,
+                event_ops: false,
+                clone_ops: false,
+                reserved: [false; 3],
+            },
+            handle_role,
+            access_mode: Self::encode_access_mode(true, false),
+        }
+    }
+
+    /// Create info for a Socket KernelObject
+    pub fn for_socket(handle_role: HandleRole, readable: bool, writable: bool) -> Self {
+        Self {
+            object_type: KernelObjectType::Socket,
+            capabilities: ObjectCapabilities {
+                stream_ops: true,  // Reading and writing receive and send data
+                file_ops: false,
+                pipe_ops: false,
+                event_ops: false,
+                clone_ops: false,
+                reserved: [false; 3],
+            },
+            handle_role,
+            access_mode: Self::encode_access_mode(readable, writable),
+        }
+    }
+
+    /// Create info for a Semaphore KernelObject
+    pub fn for_semaphore(handle_role: HandleRole) -> Self {
+        Self {
+            object_type: KernelObjectType::Semaphore,
+            capabilities: ObjectCapabilities {
+                stream_ops: false,
+                file_ops: false,
+                pipe_ops: false,
+                event_ops: false,
+                clone_ops: false,
+                reserved: [false; 3],
+            },
+            handle_role,
+            access_mode: Self::encode_access_mode(true, true),
+        }
+    }
+
+    /// Create info for a Ring KernelObject
+    pub fn for_ring(handle_role: HandleRole) -> Self {
+        Self {
+            object_type: KernelObjectType::Ring,
+            capabilities: ObjectCapabilities {
+                stream_ops: false,
+                file_ops: false,
+                pipe_ops: false,
+                event_ops: false,
+                clone_ops: false,
+                reserved: [false; 3],
+            },
+            handle_role,
+            access_mode: Self::encode_access_mode(true, true),
+        }
+    }
+    
+    /// Create info for a Bus KernelObject
+    pub fn for_bus(handle_role: HandleRole) -> Self {
+        Self {
+            object_type: KernelObjectType::Bus,
+            capabilities: ObjectCapabilities {
+                stream_ops: false,
+                file_ops: false,
+                pipe_ops: false,
+                event_ops: false,
+                clone_ops: false,
+                reserved: [false; 3],
+            },
+            handle_role,
+            access_mode: Self::encode_access_mode(true, true),
+        }
+    }
+    
+    /// Create info for a NetConfig KernelObject
+    pub fn for_netconfig(handle_role: HandleRole) -> Self {
+        Self {
+            object_type: KernelObjectType::NetConfig,
+            capabilities: ObjectCapabilities {
+                stream_ops: true,
+                file_ops: false,
+                pipe_ops: false,
+                event_ops: false,
+                clone_ops: false,
+                reserved: [false; 3],
+            },
+            handle_role,
+            access_mode: Self::encode_access_mode(true, true),
+        }
+    }
+
+    /// Create info for a DeviceEvents KernelObject
+    pub fn for_device_events(handle_role: HandleRole) -> Self {
+        Self {
+            object_type: KernelObjectType::DeviceEvents,
+            capabilities: ObjectCapabilities {
+                stream_ops: true,
+                file_ops: false,
+                pipe_ops: false,
+                event_ops: false,
+                clone_ops: false,
+                reserved: [false; 3],
+            },
+            handle_role,
+            access_mode: Self::encode_access_mode(true, false),
+        }
+    }
+
+    /// Create info for a GpioLines KernelObject
+    pub fn for_gpio_lines(handle_role: HandleRole) -> Self {
+        Self {
+            object_type: KernelObjectType::GpioLines,
+            capabilities: ObjectCapabilities {
+                stream_ops: true,
+                file_ops: false,
+                pipe_ops: false,
+                event_ops: false,
+                clone_ops: false,
+                reserved: [false; 3],
+            },
+            handle_role,
+            access_mode: Self::encode_access_mode(true, true),
+        }
+    }
+
     /// Create info for unknown KernelObject
     pub fn unknown() -> Self {
         Self {
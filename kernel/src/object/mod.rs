@@ -4,15 +4,30 @@
 //! including files, pipes, devices, and other IPC mechanisms.
 
 pub mod capability;
+pub mod epoll;
 pub mod introspection;
 pub mod handle;
+pub mod ring;
+pub mod timerfd;
 
-use alloc::{sync::Arc, vec::Vec};
+use alloc::{sync::{Arc, Weak}, vec::Vec};
 use crate::fs::FileObject;
 use crate::ipc::pipe::PipeObject;
 use crate::ipc::event::{EventChannelObject, EventSubscriptionObject};
-use crate::ipc::StreamIpcOps;
-use capability::{StreamOps, CloneOps, ControlOps, MemoryMappingOps};
+use crate::ipc::shm::SharedMemoryObject;
+use crate::ipc::eventfd::EventFdObject;
+use crate::ipc::semaphore::SemaphoreObject;
+use crate::ipc::{SocketObject, StreamIpcOps};
+use crate::task::process_handle::ProcessObject;
+use capability::{StreamOps, CloneOps, ControlOps, MemoryMappingOps, PollOps};
+use capability::poll::{POLLIN, POLLOUT};
+use epoll::EpollObject;
+use ring::RingObject;
+use crate::ipc::bus::BusConnection;
+use crate::network::netconfig::NetConfigObject;
+use crate::device::uevent::DeviceEventsObject;
+use crate::device::gpio::GpioLineRequest;
+use timerfd::TimerFdObject;
 
 /// Unified representation of all kernel-managed resources
 pub enum KernelObject {
@@ -20,10 +35,20 @@ pub enum KernelObject {
     Pipe(Arc<dyn PipeObject>),
     EventChannel(Arc<EventChannelObject>),
     EventSubscription(Arc<EventSubscriptionObject>),
+    SharedMemory(Arc<SharedMemoryObject>),
+    Process(Arc<ProcessObject>),
+    Epoll(Arc<EpollObject>),
+    EventFd(Arc<EventFdObject>),
+    TimerFd(Arc<TimerFdObject>),
+    Socket(Arc<dyn SocketObject>),
+    Semaphore(Arc<SemaphoreObject>),
+    Ring(Arc<RingObject>),
+    Bus(Arc<BusConnection>),
+    NetConfig(Arc<NetConfigObject>),
+    DeviceEvents(Arc<DeviceEventsObject>),
+    GpioLines(Arc<GpioLineRequest>),
     // Future variants will be added here:
     // MessageQueue(Arc<dyn MessageQueueObject>),
-    // SharedMemory(Arc<dyn SharedMemoryObject>),
-    // Socket(Arc<dyn SocketObject>),
     // CharDevice(Arc<dyn CharDevice>),
 }
 
@@ -47,6 +72,66 @@ impl KernelObject {
     pub fn from_event_subscription(event_subscription: Arc<EventSubscriptionObject>) -> Self {
         KernelObject::EventSubscription(event_subscription)
     }
+
+    /// Create a KernelObject from a SharedMemoryObject
+    pub fn from_shared_memory(shared_memory: Arc<SharedMemoryObject>) -> Self {
+        KernelObject::SharedMemory(shared_memory)
+    }
+
+    /// Create a KernelObject from a ProcessObject
+    pub fn from_process(process: Arc<ProcessObject>) -> Self {
+        KernelObject::Process(process)
+    }
+
+    /// Create a KernelObject from an EpollObject
+    pub fn from_epoll(epoll: Arc<EpollObject>) -> Self {
+        KernelObject::Epoll(epoll)
+    }
+
+    /// Create a KernelObject from an EventFdObject
+    pub fn from_eventfd(eventfd: Arc<EventFdObject>) -> Self {
+        KernelObject::EventFd(eventfd)
+    }
+
+    /// Create a KernelObject from a TimerFdObject
+    pub fn from_timerfd(timer: Arc<TimerFdObject>) -> Self {
+        KernelObject::TimerFd(timer)
+    }
+
+    /// Create a KernelObject from a SocketObject
+    pub fn from_socket(socket: Arc<dyn SocketObject>) -> Self {
+        KernelObject::Socket(socket)
+    }
+
+    /// Create a KernelObject from a SemaphoreObject
+    pub fn from_semaphore(semaphore: Arc<SemaphoreObject>) -> Self {
+        KernelObject::Semaphore(semaphore)
+    }
+
+    /// Create a KernelObject from a RingObject
+    pub fn from_ring(ring: Arc<RingObject>) -> Self {
+        KernelObject::Ring(ring)
+    }
+
+    /// Create a KernelObject from a BusConnection
+    pub fn from_bus(bus: Arc<BusConnection>) -> Self {
+        KernelObject::Bus(bus)
+    }
+
+    /// Create a KernelObject from a NetConfigObject
+    pub fn from_netconfig(netconfig: Arc<NetConfigObject>) -> Self {
+        KernelObject::NetConfig(netconfig)
+    }
+
+    /// Create a KernelObject from a DeviceEventsObject
+    pub fn from_device_events(device_events: Arc<DeviceEventsObject>) -> Self {
+        KernelObject::DeviceEvents(device_events)
+    }
+
+    /// Create a KernelObject from a GpioLineRequest
+    pub fn from_gpio_lines(gpio_lines: Arc<GpioLineRequest>) -> Self {
+        KernelObject::GpioLines(gpio_lines)
+    }
     
     /// Try to get StreamOps capability
     pub fn as_stream(&self) -> Option<&dyn StreamOps> {
@@ -69,6 +154,61 @@ impl KernelObject {
                 // Event subscriptions don't provide stream operations
                 None
             }
+            KernelObject::SharedMemory(_) => {
+                // Shared memory doesn't provide stream operations
+                None
+            }
+            KernelObject::Process(process) => {
+                // Reading a process handle waits for the exit status
+                let stream_ops: &dyn StreamOps = process.as_ref();
+                Some(stream_ops)
+            }
+            KernelObject::Epoll(_) => {
+                // Epoll objects are waited on, not read
+                None
+            }
+            KernelObject::EventFd(eventfd) => {
+                // Reading takes the counter, writing adds to it
+                let stream_ops: &dyn StreamOps = eventfd.as_ref();
+                Some(stream_ops)
+            }
+            KernelObject::TimerFd(timer) => {
+                // Reading takes the number of expiries
+                let stream_ops: &dyn StreamOps = timer.as_ref();
+                Some(stream_ops)
+            }
+            KernelObject::Socket(socket) => {
+                // Reading and writing receive and send data
+                let stream_ops: &dyn StreamOps = socket.as_ref();
+                Some(stream_ops)
+            }
+            KernelObject::Semaphore(_) => {
+                // Semaphores are waited on and posted, not read
+                None
+            }
+            KernelObject::Ring(_) => {
+                // Rings are entered, not read
+                None
+            }
+            KernelObject::Bus(_) => {
+                // Bus messages keep their boundaries and carry handles
+                None
+            }
+            KernelObject::NetConfig(netconfig) => {
+                // Requests are written and replies read, a message at a time
+                let stream_ops: &dyn StreamOps = netconfig.as_ref();
+                Some(stream_ops)
+            }
+            KernelObject::DeviceEvents(device_events) => {
+                // Device events are read a record at a time
+                let stream_ops: &dyn StreamOps = device_events.as_ref();
+                Some(stream_ops)
+            }
+            KernelObject::GpioLines(gpio_lines) => {
+                // Edge events are read as whole records
+                let stream_ops: &dyn StreamOps = gpio_lines.as_ref();
+                Some(stream_ops)
+            }
         }
     }
     
@@ -92,6 +232,55 @@ impl KernelObject {
                 // Event subscriptions don't provide stream IPC operations
                 None
             }
+            KernelObject::SharedMemory(_) => {
+                // Shared memory doesn't provide stream IPC operations
+                None
+            }
+            KernelObject::Process(_) => {
+                // Process handles don't provide stream IPC operations
+                None
+            }
+            KernelObject::Epoll(_) => {
+                // Epoll objects don't provide stream IPC operations
+                None
+            }
+            KernelObject::EventFd(_) => {
+                // Event counters don't provide stream IPC operations
+                None
+            }
+            KernelObject::TimerFd(_) => {
+                // Timers don't provide stream IPC operations
+                None
+            }
+            KernelObject::Socket(socket) => {
+                // SocketObject implements StreamIpcOps
+                let stream_ipc_ops: &dyn StreamIpcOps = socket.as_ref();
+                Some(stream_ipc_ops)
+            }
+            KernelObject::Semaphore(_) => {
+                // Semaphores don't provide stream IPC operations
+                None
+            }
+            KernelObject::Ring(_) => {
+                // Rings don't provide stream IPC operations
+                None
+            }
+            KernelObject::Bus(_) => {
+                // Bus connections don't provide stream IPC operations
+                None
+            }
+            KernelObject::NetConfig(_) => {
+                // Network configuration channels don't provide stream IPC operations
+                None
+            }
+            KernelObject::DeviceEvents(_) => {
+                // Device event channels don't provide stream IPC operations
+                None
+            }
+            KernelObject::GpioLines(_) => {
+                // GPIO line requests don't provide stream IPC operations
+                None
+            }
         }
     }
     
@@ -115,6 +304,54 @@ impl KernelObject {
                 // Event subscriptions don't provide file operations
                 None
             }
+            KernelObject::SharedMemory(_) => {
+                // Shared memory doesn't provide file operations
+                None
+            }
+            KernelObject::Process(_) => {
+                // Process handles don't provide file operations
+                None
+            }
+            KernelObject::Epoll(_) => {
+                // Epoll objects don't provide file operations
+                None
+            }
+            KernelObject::EventFd(_) => {
+                // Event counters don't provide file operations
+                None
+            }
+            KernelObject::TimerFd(_) => {
+                // Timers don't provide file operations
+                None
+            }
+            KernelObject::Socket(_) => {
+                // Sockets don't provide file operations
+                None
+            }
+            KernelObject::Semaphore(_) => {
+                // Semaphores don't provide file operations
+                None
+            }
+            KernelObject::Ring(_) => {
+                // Rings don't provide file operations
+                None
+            }
+            KernelObject::Bus(_) => {
+                // Bus connections don't provide file operations
+                None
+            }
+            KernelObject::NetConfig(_) => {
+                // Network configuration channels don't provide file operations
+                None
+            }
+            KernelObject::DeviceEvents(_) => {
+                // Device event channels don't provide file operations
+                None
+            }
+            KernelObject::GpioLines(_) => {
+                // GPIO line requests don't provide file operations
+                None
+            }
         }
     }
     
@@ -137,6 +374,54 @@ impl KernelObject {
                 // Event subscriptions don't provide pipe operations
                 None
             }
+            KernelObject::SharedMemory(_) => {
+                // Shared memory doesn't provide pipe operations
+                None
+            }
+            KernelObject::Process(_) => {
+                // Process handles don't provide pipe operations
+                None
+            }
+            KernelObject::Epoll(_) => {
+                // Epoll objects don't provide pipe operations
+                None
+            }
+            KernelObject::EventFd(_) => {
+                // Event counters don't provide pipe operations
+                None
+            }
+            KernelObject::TimerFd(_) => {
+                // Timers don't provide pipe operations
+                None
+            }
+            KernelObject::Socket(_) => {
+                // Sockets don't provide pipe operations
+                None
+            }
+            KernelObject::Semaphore(_) => {
+                // Semaphores don't provide pipe operations
+                None
+            }
+            KernelObject::Ring(_) => {
+                // Rings don't provide pipe operations
+                None
+            }
+            KernelObject::Bus(_) => {
+                // Bus connections don't provide pipe operations
+                None
+            }
+            KernelObject::NetConfig(_) => {
+                // Network configuration channels don't provide pipe operations
+                None
+            }
+            KernelObject::DeviceEvents(_) => {
+                // Device event channels don't provide pipe operations
+                None
+            }
+            KernelObject::GpioLines(_) => {
+                // GPIO line requests don't provide pipe operations
+                None
+            }
         }
     }
     
@@ -161,6 +446,42 @@ impl KernelObject {
                 let cloneable: &dyn CloneOps = event_subscription.as_ref();
                 Some(cloneable)
             }
+            KernelObject::SharedMemory(_) => {
+                None // Shared memory handles share the object via Arc::clone
+            }
+            KernelObject::Process(_) => {
+                None // Process handles share the object via Arc::clone
+            }
+            KernelObject::Epoll(_) => {
+                None // Epoll handles share the interest list via Arc::clone
+            }
+            KernelObject::EventFd(_) => {
+                None // Event counter handles share the counter via Arc::clone
+            }
+            KernelObject::TimerFd(_) => {
+                None // Timer handles share the timer via Arc::clone
+            }
+            KernelObject::Socket(_) => {
+                None // Socket handles share the socket via Arc::clone
+            }
+            KernelObject::Semaphore(_) => {
+                None // Semaphore handles share the semaphore via Arc::clone
+            }
+            KernelObject::Ring(_) => {
+                None // Ring handles share the ring via Arc::clone
+            }
+            KernelObject::Bus(_) => {
+                None // Bus handles share the connection via Arc::clone
+            }
+            KernelObject::NetConfig(_) => {
+                None // Network configuration handles share the channel via Arc::clone
+            }
+            KernelObject::DeviceEvents(_) => {
+                None // Device event handles share the channel via Arc::clone
+            }
+            KernelObject::GpioLines(_) => {
+                None // GPIO line handles share the request via Arc::clone
+            }
         }
     }
     
@@ -184,6 +505,55 @@ impl KernelObject {
                 // Event subscriptions don't provide control operations
                 None
             }
+            KernelObject::SharedMemory(_) => {
+                // Shared memory doesn't provide control operations
+                None
+            }
+            KernelObject::Process(_) => {
+                // Process handles don't provide control operations
+                None
+            }
+            KernelObject::Epoll(_) => {
+                // Epoll objects don't provide control operations
+                None
+            }
+            KernelObject::EventFd(_) => {
+                // Event counters don't provide control operations
+                None
+            }
+            KernelObject::TimerFd(_) => {
+                // Timers don't provide control operations
+                None
+            }
+            KernelObject::Socket(_) => {
+                // Sockets don't provide control operations
+                None
+            }
+            KernelObject::Semaphore(_) => {
+                // Semaphores don't provide control operations
+                None
+            }
+            KernelObject::Ring(_) => {
+                // Rings don't provide control operations
+                None
+            }
+            KernelObject::Bus(_) => {
+                // Bus connections don't provide control operations
+                None
+            }
+            KernelObject::NetConfig(_) => {
+                // Network configuration channels don't provide control operations
+                None
+            }
+            KernelObject::DeviceEvents(_) => {
+                // Device event channels don't provide control operations
+                None
+            }
+            KernelObject::GpioLines(gpio_lines) => {
+                // Line values and configuration go through control commands
+                let control_ops: &dyn ControlOps = gpio_lines.as_ref();
+                Some(control_ops)
+            }
         }
     }
     
@@ -207,6 +577,56 @@ impl KernelObject {
                 // Event subscriptions don't provide memory mapping operations
                 None
             }
+            KernelObject::SharedMemory(shared_memory) => {
+                // Mappings are owned by the segment so they outlive the handle
+                let memory_mapping_ops: &dyn MemoryMappingOps = shared_memory.segment().as_ref();
+                Some(memory_mapping_ops)
+            }
+            KernelObject::Process(_) => {
+                // Process handles don't provide memory mapping operations
+                None
+            }
+            KernelObject::Epoll(_) => {
+                // Epoll objects don't provide memory mapping operations
+                None
+            }
+            KernelObject::EventFd(_) => {
+                // Event counters don't provide memory mapping operations
+                None
+            }
+            KernelObject::TimerFd(_) => {
+                // Timers don't provide memory mapping operations
+                None
+            }
+            KernelObject::Socket(_) => {
+                // Sockets don't provide memory mapping operations
+                None
+            }
+            KernelObject::Semaphore(_) => {
+                // Semaphores don't provide memory mapping operations
+                None
+            }
+            KernelObject::Ring(ring) => {
+                // The queues are mapped from the memory of the ring
+                let memory_mapping_ops: &dyn MemoryMappingOps = ring.memory().segment().as_ref();
+                Some(memory_mapping_ops)
+            }
+            KernelObject::Bus(_) => {
+                // Bus connections don't provide memory mapping operations
+                None
+            }
+            KernelObject::NetConfig(_) => {
+                // Network configuration channels don't provide memory mapping operations
+                None
+            }
+            KernelObject::DeviceEvents(_) => {
+                // Device event channels don't provide memory mapping operations
+                None
+            }
+            KernelObject::GpioLines(_) => {
+                // GPIO line requests don't provide memory mapping operations
+                None
+            }
         }
     }
 
@@ -231,6 +651,52 @@ impl KernelObject {
                 // Event subscriptions don't provide memory mapping operations
                 None
             }
+            KernelObject::SharedMemory(shared_memory) => {
+                Some(shared_memory.segment_weak())
+            }
+            KernelObject::Process(_) => {
+                // Process handles don't provide memory mapping operations
+                None
+            }
+            KernelObject::Epoll(_) => {
+                // Epoll objects don't provide memory mapping operations
+                None
+            }
+            KernelObject::EventFd(_) => {
+                // Event counters don't provide memory mapping operations
+                None
+            }
+            KernelObject::TimerFd(_) => {
+                // Timers don't provide memory mapping operations
+                None
+            }
+            KernelObject::Socket(_) => {
+                // Sockets don't provide memory mapping operations
+                None
+            }
+            KernelObject::Semaphore(_) => {
+                // Semaphores don't provide memory mapping operations
+                None
+            }
+            KernelObject::Ring(ring) => {
+                Some(ring.memory().segment_weak())
+            }
+            KernelObject::Bus(_) => {
+                // Bus connections don't provide memory mapping operations
+                None
+            }
+            KernelObject::NetConfig(_) => {
+                // Network configuration channels don't provide memory mapping operations
+                None
+            }
+            KernelObject::DeviceEvents(_) => {
+                // Device event channels don't provide memory mapping operations
+                None
+            }
+            KernelObject::GpioLines(_) => {
+                // GPIO line requests don't provide memory mapping operations
+                None
+            }
         }
     }
 
@@ -245,6 +711,126 @@ impl KernelObject {
         }
     }
     
+    /// Try to get SharedMemoryObject
+    pub fn as_shared_memory(&self) -> Option<&SharedMemoryObject> {
+        match self {
+            KernelObject::SharedMemory(shared_memory) => Some(shared_memory.as_ref()),
+            _ => None
+        }
+    }
+
+    /// Try to get ProcessObject
+    pub fn as_process(&self) -> Option<&ProcessObject> {
+        match self {
+            KernelObject::Process(process) => Some(process.as_ref()),
+            _ => None
+        }
+    }
+
+    /// Try to get EpollObject
+    pub fn as_epoll(&self) -> Option<&EpollObject> {
+        match self {
+            KernelObject::Epoll(epoll) => Some(epoll.as_ref()),
+            _ => None
+        }
+    }
+
+    /// Try to get SocketObject
+    pub fn as_socket(&self) -> Option<&dyn SocketObject> {
+        match self {
+            KernelObject::Socket(socket) => Some(socket.as_ref()),
+            _ => None
+        }
+    }
+
+    /// Try to get PollOps capability
+    ///
+    /// `None` for objects that never make a task wait (regular files,
+    /// shared memory) and for those that cannot be polled yet.
+    pub fn as_pollable(&self) -> Option<&dyn PollOps> {
+        match self {
+            KernelObject::File(file_object) => file_object.as_pollable(),
+            KernelObject::Pipe(pipe_object) => {
+                let poll_ops: &dyn PollOps = pipe_object.as_ref();
+                Some(poll_ops)
+            }
+            KernelObject::EventChannel(_) | KernelObject::EventSubscription(_) | KernelObject::SharedMemory(_) => None,
+            KernelObject::Ring(_) => None,
+            KernelObject::Bus(bus) => {
+                let poll_ops: &dyn PollOps = bus.as_ref();
+                Some(poll_ops)
+            }
+            KernelObject::Process(process) => {
+                let poll_ops: &dyn PollOps = process.as_ref();
+                Some(poll_ops)
+            }
+            KernelObject::Epoll(epoll) => {
+                let poll_ops: &dyn PollOps = epoll.as_ref();
+                Some(poll_ops)
+            }
+            KernelObject::EventFd(eventfd) => {
+                let poll_ops: &dyn PollOps = eventfd.as_ref();
+                Some(poll_ops)
+            }
+            KernelObject::TimerFd(timer) => {
+                let poll_ops: &dyn PollOps = timer.as_ref();
+                Some(poll_ops)
+            }
+            KernelObject::Socket(socket) => {
+                let poll_ops: &dyn PollOps = socket.as_ref();
+                Some(poll_ops)
+            }
+            KernelObject::Semaphore(semaphore) => {
+                let poll_ops: &dyn PollOps = semaphore.as_ref();
+                Some(poll_ops)
+            }
+            KernelObject::NetConfig(netconfig) => {
+                let poll_ops: &dyn PollOps = netconfig.as_ref();
+                Some(poll_ops)
+            }
+            KernelObject::DeviceEvents(device_events) => {
+                let poll_ops: &dyn PollOps = device_events.as_ref();
+                Some(poll_ops)
+            }
+            KernelObject::GpioLines(gpio_lines) => {
+                let poll_ops: &dyn PollOps = gpio_lines.as_ref();
+                Some(poll_ops)
+            }
+        }
+    }
+
+    /// The poll events ready on the object
+    ///
+    /// Objects without the PollOps capability are always readable and
+    /// writable.
+    pub fn poll_events(&self) -> u32 {
+        self.as_pollable().map_or(POLLIN | POLLOUT, |pollable| pollable.poll_events())
+    }
+
+    /// A reference to the object that does not keep it open
+    pub fn downgrade(&self) -> WeakKernelObject {
+        match self {
+            KernelObject::File(file_object) => WeakKernelObject::File(Arc::downgrade(file_object)),
+            KernelObject::Pipe(pipe_object) => WeakKernelObject::Pipe(Arc::downgrade(pipe_object)),
+            KernelObject::EventChannel(event_channel) => WeakKernelObject::EventChannel(Arc::downgrade(event_channel)),
+            KernelObject::EventSubscription(event_subscription) => {
+                WeakKernelObject::EventSubscription(Arc::downgrade(event_subscription))
+            }
+            KernelObject::SharedMemory(shared_memory) => WeakKernelObject::SharedMemory(Arc::downgrade(shared_memory)),
+            KernelObject::Process(process) => WeakKernelObject::Process(Arc::downgrade(process)),
+            KernelObject::Epoll(epoll) => WeakKernelObject::Epoll(Arc::downgrade(epoll)),
+            KernelObject::EventFd(eventfd) => WeakKernelObject::EventFd(Arc::downgrade(eventfd)),
+            KernelObject::TimerFd(timer) => WeakKernelObject::TimerFd(Arc::downgrade(timer)),
+            KernelObject::Socket(socket) => WeakKernelObject::Socket(Arc::downgrade(socket)),
+            KernelObject::Semaphore(semaphore) => WeakKernelObject::Semaphore(Arc::downgrade(semaphore)),
+            KernelObject::Ring(ring) => WeakKernelObject::Ring(Arc::downgrade(ring)),
+            KernelObject::Bus(bus) => WeakKernelObject::Bus(Arc::downgrade(bus)),
+            KernelObject::NetConfig(netconfig) => WeakKernelObject::NetConfig(Arc::downgrade(netconfig)),
+            KernelObject::DeviceEvents(device_events) => WeakKernelObject::DeviceEvents(Arc::downgrade(device_events)),
+            KernelObject::GpioLines(gpio_lines) => WeakKernelObject::GpioLines(Arc::downgrade(gpio_lines)),
+        }
+    }
+
     /// Try to get EventSubscriptionObject
     pub fn as_event_subscription(&self) -> Option<&EventSubscriptionObject> {
         match self {
@@ -277,10 +863,97 @@ impl Clone for KernelObject {
                 KernelObject::EventSubscription(event_subscription) => {
                     KernelObject::EventSubscription(Arc::clone(event_subscription))
                 }
+                KernelObject::SharedMemory(shared_memory) => {
+                    KernelObject::SharedMemory(Arc::clone(shared_memory))
+                }
+                KernelObject::Process(process) => {
+                    KernelObject::Process(Arc::clone(process))
+                }
+                KernelObject::Epoll(epoll) => {
+                    KernelObject::Epoll(Arc::clone(epoll))
+                }
+                KernelObject::EventFd(eventfd) => {
+                    KernelObject::EventFd(Arc::clone(eventfd))
+                }
+                KernelObject::TimerFd(timer) => {
+                    KernelObject::TimerFd(Arc::clone(timer))
+                }
+                KernelObject::Socket(socket) => {
+                    KernelObject::Socket(Arc::clone(socket))
+                }
+                KernelObject::Semaphore(semaphore) => {
+                    KernelObject::Semaphore(Arc::clone(semaphore))
+                }
+                KernelObject::Ring(ring) => {
+                    KernelObject::Ring(Arc::clone(ring))
+                }
+                KernelObject::Bus(bus) => {
+                    KernelObject::Bus(Arc::clone(bus))
+                }
+                KernelObject::NetConfig(netconfig) => {
+                    KernelObject::NetConfig(Arc::clone(netconfig))
+                }
+                KernelObject::DeviceEvents(device_events) => {
+                    KernelObject::DeviceEvents(Arc::clone(device_events))
+                }
+                KernelObject::GpioLines(gpio_lines) => {
+                    KernelObject::GpioLines(Arc::clone(gpio_lines))
+                }
             }
         }
     }
 }
 
+/// A KernelObject that is not kept open, see [`KernelObject::downgrade`]
+///
+/// Unlike cloning a KernelObject, taking and upgrading a weak reference
+/// never runs the CloneOps of the object: a pipe end watched through it is
+/// not counted as one more reader or writer.
+#[derive(Clone)]
+pub enum WeakKernelObject {
+    File(Weak<dyn FileObject>),
+    Pipe(Weak<dyn PipeObject>),
+    EventChannel(Weak<EventChannelObject>),
+    EventSubscription(Weak<EventSubscriptionObject>),
+    SharedMemory(Weak<SharedMemoryObject>),
+    Process(Weak<ProcessObject>),
+    Epoll(Weak<EpollObject>),
+    EventFd(Weak<EventFdObject>),
+    TimerFd(Weak<TimerFdObject>),
+    Socket(Weak<dyn SocketObject>),
+    Semaphore(Weak<SemaphoreObject>),
+    Ring(Weak<RingObject>),
+    Bus(Weak<BusConnection>),
+    NetConfig(Weak<NetConfigObject>),
+    DeviceEvents(Weak<DeviceEventsObject>),
+    GpioLines(Weak<GpioLineRequest>),
+}
+
+impl WeakKernelObject {
+    /// The object, if it is still open somewhere
+    pub fn upgrade(&self) -> Option<KernelObject> {
+        Some(match self {
+            WeakKernelObject::File(file_object) => KernelObject::File(file_object.upgrade()?),
+            WeakKernelObject::Pipe(pipe_object) => KernelObject::Pipe(pipe_object.upgrade()?),
+            WeakKernelObject::EventChannel(event_channel) => KernelObject::EventChannel(event_channel.upgrade()?),
+            WeakKernelObject::EventSubscription(event_subscription) => {
+                KernelObject::EventSubscription(event_subscription.upgrade()?)
+            }
+            WeakKernelObject::SharedMemory(shared_memory) => KernelObject::SharedMemory(shared_memory.upgrade()?),
+            WeakKernelObject::Process(process) => KernelObject::Process(process.upgrade()?),
+            WeakKernelObject::Epoll(epoll) => KernelObject::Epoll(epoll.upgrade()?),
+            WeakKernelObject::EventFd(eventfd) => KernelObject::EventFd(eventfd.upgrade()?),
+            WeakKernelObject::TimerFd(timer) => KernelObject::TimerFd(timer.upgrade()?),
+            WeakKernelObject::Socket(socket) => KernelObject::Socket(socket.upgrade()?),
+            WeakKernelObject::Semaphore(semaphore) => KernelObject::Semaphore(semaphore.upgrade()?),
+            WeakKernelObject::Ring(ring) => KernelObject::Ring(ring.upgrade()?),
+            WeakKernelObject::Bus(bus) => KernelObject::Bus(bus.upgrade()?),
+            WeakKernelObject::NetConfig(netconfig) => KernelObject::NetConfig(netconfig.upgrade()?),
+            WeakKernelObject::DeviceEvents(device_events) => KernelObject::DeviceEvents(device_events.upgrade()?),
+            WeakKernelObject::GpioLines(gpio_lines) => KernelObject::GpioLines(gpio_lines.upgrade()?),
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests;
\ No newline at end of file
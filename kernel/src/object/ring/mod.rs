@@ -0,0 +1,410 @@
+//! Submission rings
+//!
+//! A ring lets a task issue a batch of I/O operations with one system call
+//! and collect their results without one: the task writes submissions to a
+//! queue in memory it shares with the kernel, and the kernel writes a
+//! completion for each of them to a second queue. The memory is mapped by
+//! mapping the handle of the ring (MAP_SHARED); it starts with a header of
+//! `u32` fields at the `RING_*` offsets:
+//!
+//! - the submission queue: head (kernel), tail (task), mask and size
+//! - the completion queue: head (task), tail (kernel), mask and size
+//! - the offsets of the submission and completion entries
+//!
+//! Entries are used in ring order at `index & mask`. A task publishes
+//! submissions by advancing the submission tail, and consumes completions
+//! by advancing the completion head; both sides order their accesses with
+//! acquire and release.
+//!
+//! Submissions are taken by [`RingObject::enter`]. Operations on objects
+//! that are not ready (an empty pipe, a socket without data) stay pending
+//! in the kernel holding the object, and run when a later enter finds them
+//! ready, or while an enter waits for completions. Nothing runs between
+//! enters: operations proceed in the context of the task entering the
+//! ring, with its handles and memory. Files are always ready: their reads
+//! and writes run in the enter that takes them, as the block layer
+//! completes requests synchronously. Completions that do not fit the
+//! completion queue are kept until the task makes room.
+
+pub mod syscall;
+
+use alloc::{collections::VecDeque, sync::Arc, vec, vec::Vec};
+use core::sync::atomic::{AtomicU32, Ordering};
+use spin::Mutex;
+
+use crate::abi::error::KernelError;
+use crate::ipc::shm::SharedMemoryObject;
+use crate::object::capability::poll::{wait_for, PollOps, PollWait, POLLERR, POLLHUP, POLLIN, POLLOUT};
+use crate::object::capability::{SeekFrom, StreamError};
+use crate::object::KernelObject;
+use crate::task::signal::{copy_from_user, copy_to_user};
+use crate::task::Task;
+
+/// Do nothing; completes with 0
+pub const RING_OP_NOP: u8 = 0;
+/// Read up to `len` bytes of the object to `addr`
+pub const RING_OP_READ: u8 = 1;
+/// Write `len` bytes at `addr` to the object
+pub const RING_OP_WRITE: u8 = 2;
+/// Wait until one of the poll events in `len` is ready; completes with the
+/// ready events
+pub const RING_OP_POLL: u8 = 3;
+/// Write the cached data of a file to storage
+pub const RING_OP_FSYNC: u8 = 4;
+
+/// Offset of a read or write at the current position of the object
+pub const RING_OFFSET_CURRENT: u64 = u64::MAX;
+
+/// Result of a failed operation
+pub const RING_RESULT_ERROR: i64 = -1;
+
+/// Most submission entries of a ring; the completion queue has twice as many
+pub const RING_MAX_ENTRIES: usize = 4096;
+/// Most bytes moved by one read or write
+pub const RING_MAX_TRANSFER: usize = 1 << 20;
+
+/// Offsets of the `u32` fields of the header
+pub const RING_SQ_HEAD: usize = 0;
+pub const RING_SQ_TAIL: usize = 4;
+pub const RING_SQ_MASK: usize = 8;
+pub const RING_SQ_ENTRIES: usize = 12;
+pub const RING_CQ_HEAD: usize = 16;
+pub const RING_CQ_TAIL: usize = 20;
+pub const RING_CQ_MASK: usize = 24;
+pub const RING_CQ_ENTRIES: usize = 28;
+pub const RING_SQ_OFFSET: usize = 32;
+pub const RING_CQ_OFFSET: usize = 36;
+/// Size of the header; the submission entries follow it
+pub const RING_HEADER_SIZE: usize = 64;
+
+/// An operation as the task submits it
+///
+/// In memory: `opcode: u8`, three reserved bytes, `handle: u32`,
+/// `offset: u64`, `addr: u64`, `len: u32`, four reserved bytes and
+/// `user_data: u64`, little-endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Submission {
+    pub opcode: u8,
+    pub handle: u32,
+    pub offset: u64,
+    pub addr: u64,
+    pub len: u32,
+    pub user_data: u64,
+}
+
+impl Submission {
+    pub const SIZE: usize = 40;
+
+    pub fn from_bytes(bytes: &[u8; Self::SIZE]) -> Self {
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let u64_at = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+        Self {
+            opcode: bytes[0],
+            handle: u32_at(4),
+            offset: u64_at(8),
+            addr: u64_at(16),
+            len: u32_at(24),
+            user_data: u64_at(32),
+        }
+    }
+}
+
+/// The result of an operation: `user_data: u64` of the submission and
+/// `result: i64`, the value of the operation or [`RING_RESULT_ERROR`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Completion {
+    pub user_data: u64,
+    pub result: i64,
+}
+
+impl Completion {
+    pub const SIZE: usize = 16;
+}
+
+/// An operation waiting for its object to be ready
+struct Pending {
+    submission: Submission,
+    /// Held until the operation completes
+    object: KernelObject,
+}
+
+struct RingState {
+    pending: Vec<Pending>,
+    /// Completions not yet in the completion queue, which was full
+    completed: VecDeque<Completion>,
+}
+
+pub struct RingObject {
+    /// The memory shared with the task
+    memory: Arc<SharedMemoryObject>,
+    entries: u32,
+    state: Mutex<RingState>,
+}
+
+impl RingObject {
+    /// Create a ring of `entries` submission entries, a power of two
+    pub fn new(entries: usize) -> Result<Arc<Self>, KernelError> {
+        if !entries.is_power_of_two() || entries > RING_MAX_ENTRIES {
+            return Err(KernelError::InvalidArgument);
+        }
+        let sq_offset = RING_HEADER_SIZE;
+        let cq_offset = sq_offset + entries * Submission::SIZE;
+        let size = cq_offset + 2 * entries * Completion::SIZE;
+        let memory = SharedMemoryObject::create_anonymous(size).map_err(|_| KernelError::NoMemory)?;
+        let ring = Arc::new(Self {
+            memory,
+            entries: entries as u32,
+            state: Mutex::new(RingState { pending: Vec::new(), completed: VecDeque::new() }),
+        });
+        for (field, value) in [
+            (RING_SQ_MASK, entries - 1),
+            (RING_SQ_ENTRIES, entries),
+            (RING_CQ_MASK, 2 * entries - 1),
+            (RING_CQ_ENTRIES, 2 * entries),
+            (RING_SQ_OFFSET, sq_offset),
+            (RING_CQ_OFFSET, cq_offset),
+        ] {
+            ring.field(field).store(value as u32, Ordering::Relaxed);
+        }
+        Ok(ring)
+    }
+
+    /// The memory shared with the task, mapped through the handle
+    pub fn memory(&self) -> &Arc<SharedMemoryObject> {
+        &self.memory
+    }
+
+    fn base(&self) -> *mut u8 {
+        self.memory.segment().paddr() as *mut u8
+    }
+
+    /// A field of the header
+    ///
+    /// The memory is the ring's for as long as it lives, and the header is
+    /// aligned to a page.
+    fn field(&self, offset: usize) -> &AtomicU32 {
+        unsafe { &*(self.base().add(offset) as *const AtomicU32) }
+    }
+
+    /// Number of completions in the completion queue
+    pub fn completions(&self) -> usize {
+        let head = self.field(RING_CQ_HEAD).load(Ordering::Acquire);
+        let tail = self.field(RING_CQ_TAIL).load(Ordering::Relaxed);
+        tail.wrapping_sub(head) as usize
+    }
+
+    /// Take up to `count` submissions from the submission queue
+    ///
+    /// Returns the number taken. Each operation is started: it runs at
+    /// once if its object is ready, otherwise it stays pending.
+    pub fn submit(&self, task: &mut Task, count: usize) -> usize {
+        let head = self.field(RING_SQ_HEAD).load(Ordering::Relaxed);
+        let tail = self.field(RING_SQ_TAIL).load(Ordering::Acquire);
+        // A tail beyond the queue publishes no more than the queue holds
+        let taken = (tail.wrapping_sub(head) as usize).min(self.entries as usize).min(count);
+
+        let sq_offset = RING_HEADER_SIZE;
+        let mut state = self.state.lock();
+        for index in 0..taken {
+            let slot = head.wrapping_add(index as u32) & (self.entries - 1);
+            let mut bytes = [0u8; Submission::SIZE];
+            unsafe {
+                let entry = self.base().add(sq_offset + slot as usize * Submission::SIZE);
+                core::ptr::copy_nonoverlapping(entry, bytes.as_mut_ptr(), Submission::SIZE);
+            }
+            let submission = Submission::from_bytes(&bytes);
+            let user_data = submission.user_data;
+            if submission.opcode == RING_OP_NOP {
+                state.completed.push_back(Completion { user_data, result: 0 });
+                continue;
+            }
+            match task.handle_table.read().get(submission.handle).cloned() {
+                Some(object) => state.pending.push(Pending { submission, object }),
+                None => state.completed.push_back(Completion { user_data, result: RING_RESULT_ERROR }),
+            }
+        }
+        self.field(RING_SQ_HEAD).store(head.wrapping_add(taken as u32), Ordering::Release);
+        drop(state);
+        self.progress(task);
+        taken
+    }
+
+    /// Run the pending operations whose objects are ready, and move the
+    /// completions that fit to the completion queue
+    pub fn progress(&self, task: &mut Task) {
+        let mut state = self.state.lock();
+        let pending = core::mem::take(&mut state.pending);
+        for pending in pending {
+            let result = match ready(&pending) {
+                true => execute(task, &pending.submission, &pending.object),
+                false => Err(KernelError::WouldBlock),
+            };
+            match result {
+                Err(KernelError::WouldBlock) => state.pending.push(pending),
+                result => state.completed.push_back(Completion {
+                    user_data: pending.submission.user_data,
+                    result: result.map_or(RING_RESULT_ERROR, |value| value as i64),
+                }),
+            }
+        }
+        self.flush(&mut state);
+    }
+
+    fn flush(&self, state: &mut RingState) {
+        let cq_offset = self.field(RING_CQ_OFFSET).load(Ordering::Relaxed) as usize;
+        let cq_entries = 2 * self.entries;
+        let mut tail = self.field(RING_CQ_TAIL).load(Ordering::Relaxed);
+        while self.completions() < cq_entries as usize {
+            let Some(completion) = state.completed.pop_front() else { break };
+            let slot = (tail & (cq_entries - 1)) as usize;
+            let mut bytes = [0u8; Completion::SIZE];
+            bytes[..8].copy_from_slice(&completion.user_data.to_le_bytes());
+            bytes[8..].copy_from_slice(&completion.result.to_le_bytes());
+            unsafe {
+                let entry = self.base().add(cq_offset + slot * Completion::SIZE);
+                core::ptr::copy_nonoverlapping(bytes.as_ptr(), entry, Completion::SIZE);
+            }
+            tail = tail.wrapping_add(1);
+            self.field(RING_CQ_TAIL).store(tail, Ordering::Release);
+        }
+    }
+
+    /// Take up to `to_submit` submissions, then wait until the completion
+    /// queue holds `min_complete` completions
+    ///
+    /// The wait ends early when no operation is left to complete.
+    ///
+    /// # Arguments
+    /// * `timeout_ticks` - Stop waiting after this many ticks, `None` to
+    ///   wait as long as it takes
+    ///
+    /// # Returns
+    /// The number of submissions taken, or `Interrupted`
+    pub fn enter(
+        &self,
+        task: &mut Task,
+        to_submit: usize,
+        min_complete: usize,
+        timeout_ticks: Option<u64>,
+    ) -> Result<usize, KernelError> {
+        let submitted = self.submit(task, to_submit);
+        let min_complete = min_complete.min(2 * self.entries as usize);
+        if self.completions() >= min_complete {
+            return Ok(submitted);
+        }
+
+        // Waiting holds the objects without cloning them again
+        let objects: Vec<KernelObject> = self
+            .state
+            .lock()
+            .pending
+            .iter()
+            .filter_map(|pending| pending.object.downgrade().upgrade())
+            .collect();
+        let sources: Vec<&dyn PollOps> = objects.iter().filter_map(|object| object.as_pollable()).collect();
+        let waited = wait_for(&sources, timeout_ticks, || {
+            self.progress(task);
+            let state = self.state.lock();
+            self.completions() >= min_complete || (state.pending.is_empty() && state.completed.is_empty())
+        });
+        match waited {
+            PollWait::Interrupted => Err(KernelError::Interrupted),
+            PollWait::Ready | PollWait::TimedOut => Ok(submitted),
+        }
+    }
+}
+
+/// Whether the object of an operation is ready for it
+fn ready(pending: &Pending) -> bool {
+    let events = match pending.submission.opcode {
+        RING_OP_READ => POLLIN,
+        RING_OP_WRITE => POLLOUT,
+        RING_OP_POLL => pending.submission.len,
+        _ => return true,
+    };
+    pending.object.poll_events() & (events | POLLERR | POLLHUP) != 0
+}
+
+fn stream_error(error: StreamError) -> KernelError {
+    KernelError::from(&error)
+}
+
+/// Run an operation whose object is ready
+fn execute(task: &mut Task, submission: &Submission, object: &KernelObject) -> Result<usize, KernelError> {
+    let len = (submission.len as usize).min(RING_MAX_TRANSFER);
+    let addr = submission.addr as usize;
+    match submission.opcode {
+        RING_OP_READ => {
+            let stream = object.as_stream().ok_or(KernelError::NotSupported)?;
+            let mut buffer = vec![0u8; len];
+            let read = at_offset(object, submission.offset, || stream.read(&mut buffer))?;
+            copy_to_user(task, addr, &buffer[..read]).map_err(|_| KernelError::BadAddress)?;
+            Ok(read)
+        }
+        RING_OP_WRITE => {
+            let stream = object.as_stream().ok_or(KernelError::NotSupported)?;
+            let mut buffer = vec![0u8; len];
+            copy_from_user(task, addr, &mut buffer).map_err(|_| KernelError::BadAddress)?;
+            at_offset(object, submission.offset, || stream.write(&buffer))
+        }
+        RING_OP_POLL => Ok((object.poll_events() & (submission.len | POLLERR | POLLHUP)) as usize),
+        RING_OP_FSYNC => {
+            let file = object.as_file().ok_or(KernelError::InvalidArgument)?;
+            file.sync().map_err(stream_error)?;
+            Ok(0)
+        }
+        _ => Err(KernelError::InvalidArgument),
+    }
+}
+
+/// Run `transfer` at `offset` of the object
+///
+/// An explicit offset needs a file: its position is moved to the offset
+/// for the transfer and back afterwards.
+fn at_offset(
+    object: &KernelObject,
+    offset: u64,
+    transfer: impl FnOnce() -> Result<usize, StreamError>,
+) -> Result<usize, KernelError> {
+    if offset == RING_OFFSET_CURRENT {
+        return transfer().map_err(stream_error);
+    }
+    let file = object.as_file().ok_or(KernelError::NotSeekable)?;
+    let position = file.seek(SeekFrom::Current(0)).map_err(stream_error)?;
+    file.seek(SeekFrom::Start(offset)).map_err(stream_error)?;
+    let result = transfer();
+    let _ = file.seek(SeekFrom::Start(position));
+    result.map_err(stream_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_ring_layout() {
+        let ring = RingObject::new(8).unwrap();
+        assert_eq!(ring.field(RING_SQ_MASK).load(Ordering::Relaxed), 7);
+        assert_eq!(ring.field(RING_CQ_ENTRIES).load(Ordering::Relaxed), 16);
+        assert_eq!(ring.field(RING_CQ_OFFSET).load(Ordering::Relaxed) as usize, RING_HEADER_SIZE + 8 * Submission::SIZE);
+        assert!(ring.memory().size() >= RING_HEADER_SIZE + 8 * Submission::SIZE + 16 * Completion::SIZE);
+        assert_eq!(ring.completions(), 0);
+        assert!(RingObject::new(6).is_err());
+        assert!(RingObject::new(2 * RING_MAX_ENTRIES).is_err());
+    }
+
+    #[test_case]
+    fn test_ring_submission_bytes() {
+        let mut bytes = [0u8; Submission::SIZE];
+        bytes[0] = RING_OP_WRITE;
+        bytes[4..8].copy_from_slice(&3u32.to_le_bytes());
+        bytes[8..16].copy_from_slice(&RING_OFFSET_CURRENT.to_le_bytes());
+        bytes[16..24].copy_from_slice(&0x1000u64.to_le_bytes());
+        bytes[24..28].copy_from_slice(&16u32.to_le_bytes());
+        bytes[32..40].copy_from_slice(&42u64.to_le_bytes());
+        assert_eq!(
+            Submission::from_bytes(&bytes),
+            Submission { opcode: RING_OP_WRITE, handle: 3, offset: RING_OFFSET_CURRENT, addr: 0x1000, len: 16, user_data: 42 }
+        );
+    }
+}
@@ -0,0 +1,80 @@
+//! Submission ring system calls
+//!
+//! Native interface of submission rings: a ring is created with a handle,
+//! its memory is mapped with sys_memory_map (MAP_SHARED, offset 0), and
+//! sys_ring_enter hands over the submissions written to it.
+
+use crate::{
+    abi::error::{fail, KernelError},
+    arch::Trapframe,
+    object::KernelObject,
+    task::{mytask, signal::interrupt_syscall},
+    timer::ns_to_ticks,
+};
+
+use super::RingObject;
+
+/// sys_ring_create - Create a submission ring
+///
+/// Arguments:
+/// - entries: number of submission entries, a power of two at most
+///   RING_MAX_ENTRIES; the completion queue has twice as many
+///
+/// Returns: the handle of the ring, usize::MAX on error
+pub fn sys_ring_create(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let entries = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    match RingObject::new(entries) {
+        Ok(ring) => match task.handle_table.write().insert(KernelObject::from_ring(ring)) {
+            Ok(handle) => handle as usize,
+            Err(_) => fail(KernelError::TooManyHandles),
+        },
+        Err(error) => fail(error),
+    }
+}
+
+/// sys_ring_enter - Submit operations and wait for completions
+///
+/// Arguments:
+/// - handle: handle of the ring
+/// - to_submit: most submissions to take from the submission queue
+/// - min_complete: completions to wait for in the completion queue, 0 not
+///   to wait
+/// - timeout: relative timeout of the wait in nanoseconds, 0 to wait
+///   forever
+///
+/// Returns: the number of submissions taken, usize::MAX on error. A wait
+/// that times out is not an error.
+///
+/// A wait interrupted by a signal is restarted or fails as described in
+/// `crate::task::signal`; the submissions it took stay taken.
+pub fn sys_ring_enter(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+
+    let handle = trapframe.get_arg(0);
+    let to_submit = trapframe.get_arg(1);
+    let min_complete = trapframe.get_arg(2);
+    let timeout = trapframe.get_arg(3);
+    trapframe.increment_pc_next(task);
+
+    let ring = match task.handle_table.read().get(handle as u32).cloned() {
+        Some(KernelObject::Ring(ring)) => ring,
+        Some(_) => return fail(KernelError::InvalidArgument),
+        None => return fail(KernelError::BadHandle),
+    };
+    let timeout_ticks = (timeout != 0).then(|| ns_to_ticks(timeout as u64));
+    match ring.enter(task, to_submit, min_complete, timeout_ticks) {
+        Ok(submitted) => submitted,
+        Err(KernelError::Interrupted) => interrupt_syscall(task, trapframe),
+        Err(error) => fail(error),
+    }
+}
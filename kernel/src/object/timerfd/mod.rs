@@ -0,0 +1,270 @@
+//! Timer objects (timerfd)
+//!
+//! A timer object is a kernel timer behind a handle. It is armed with the
+//! time of its first expiry, relative to now or absolute (nanoseconds since
+//! boot), and an optional interval after which it expires again. Reading
+//! it returns the number of expiries since the last read as an 8-byte
+//! count, waiting for the first one unless the timer is non-blocking.
+//!
+//! The timer is pollable: readable while expiries are pending, so an event
+//! loop can wait for timeouts together with I/O. Expiries are counted from
+//! the time of the tick that fires them: an interval shorter than a tick
+//! makes a read return several.
+
+pub mod syscall;
+
+use core::mem::size_of;
+
+use alloc::sync::{Arc, Weak};
+use spin::Mutex;
+
+use crate::object::capability::poll::{timeout_ticks, wait_for, PollOps, PollWait, POLLIN};
+use crate::object::capability::{StreamError, StreamOps};
+use crate::sync::waker::Waker;
+use crate::timer::{add_timer, cancel_timer, get_tick, get_time_ns, TimerHandler};
+
+/// When the timer expires and how often
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TimerSetting {
+    /// Nanoseconds until the next expiry, 0 if the timer is disarmed
+    pub value: u64,
+    /// Nanoseconds between expiries, 0 to expire once
+    pub interval: u64,
+}
+
+struct TimerState {
+    /// Time of the next expiry in nanoseconds since boot, `None` if disarmed
+    deadline: Option<u64>,
+    interval: u64,
+    /// Expiries not read yet
+    expirations: u64,
+    /// Software timer of the next expiry
+    timer: Option<u64>,
+    /// Changed by every setting, so that a timer of an older one is ignored
+    generation: usize,
+}
+
+pub struct TimerFdObject {
+    state: Mutex<TimerState>,
+    nonblocking: bool,
+    /// Absolute times are wall-clock times (see [`crate::time`])
+    realtime: bool,
+    /// Woken when the timer expires
+    waker: Waker,
+    /// The object itself, registered as the handler of its software timers
+    this: Weak<TimerFdObject>,
+}
+
+impl TimerFdObject {
+    /// A disarmed timer
+    pub fn new(nonblocking: bool) -> Arc<Self> {
+        Self::create(nonblocking, false)
+    }
+
+    /// A disarmed timer whose absolute times are wall-clock times, in
+    /// nanoseconds since the Unix epoch
+    ///
+    /// They are converted when the timer is armed: setting the clock later
+    /// does not move the expiry.
+    pub fn new_realtime(nonblocking: bool) -> Arc<Self> {
+        Self::create(nonblocking, true)
+    }
+
+    fn create(nonblocking: bool, realtime: bool) -> Arc<Self> {
+        Arc::new_cyclic(|this| Self {
+            state: Mutex::new(TimerState { deadline: None, interval: 0, expirations: 0, timer: None, generation: 0 }),
+            nonblocking,
+            realtime,
+            waker: Waker::new_interruptible("timerfd"),
+            this: this.clone(),
+        })
+    }
+
+    fn setting(state: &TimerState, now: u64) -> TimerSetting {
+        TimerSetting {
+            value: state.deadline.map_or(0, |deadline| deadline.saturating_sub(now).max(1)),
+            interval: state.interval,
+        }
+    }
+
+    /// The current setting
+    pub fn get(&self) -> TimerSetting {
+        Self::setting(&self.state.lock(), get_time_ns())
+    }
+
+    /// Arm the timer, or disarm it with a `value` of 0
+    ///
+    /// # Arguments
+    /// * `value` - Nanoseconds until the first expiry, or its time in
+    ///   nanoseconds since boot (since the epoch for a realtime timer) if
+    ///   `absolute`; a time that has passed expires at once
+    /// * `interval` - Nanoseconds between expiries, 0 to expire once
+    ///
+    /// Expiries not read yet are discarded.
+    ///
+    /// # Returns
+    /// The previous setting
+    pub fn set(&self, value: u64, interval: u64, absolute: bool) -> TimerSetting {
+        let now = get_time_ns();
+        let mut state = self.state.lock();
+        let old = Self::setting(&state, now);
+        if let Some(id) = state.timer.take() {
+            cancel_timer(id);
+        }
+        state.generation = state.generation.wrapping_add(1);
+        state.expirations = 0;
+        state.interval = interval;
+        state.deadline = match value {
+            0 => None,
+            value if absolute && self.realtime => Some(value.saturating_sub(crate::time::boot_epoch_ns()).max(1)),
+            value if absolute => Some(value),
+            value => Some(now.saturating_add(value)),
+        };
+        let fired = self.update(&mut state, now);
+        drop(state);
+        if fired {
+            self.waker.wake_all();
+        }
+        old
+    }
+
+    /// Count the expiries due at `now` and start the software timer of the
+    /// next one, returning whether any were due
+    fn update(&self, state: &mut TimerState, now: u64) -> bool {
+        let mut fired = false;
+        if let Some(deadline) = state.deadline.filter(|&deadline| deadline <= now) {
+            let periods = match state.interval {
+                0 => 1,
+                interval => 1 + (now - deadline) / interval,
+            };
+            state.expirations = state.expirations.saturating_add(periods);
+            state.deadline = (state.interval != 0)
+                .then(|| deadline.saturating_add(periods.saturating_mul(state.interval)));
+            fired = true;
+        }
+        if let (Some(deadline), Some(this)) = (state.deadline, self.this.upgrade()) {
+            let handler: Arc<dyn TimerHandler> = this;
+            let ticks = timeout_ticks(deadline - now).max(1);
+            state.timer = Some(add_timer(get_tick() + ticks, &handler, state.generation));
+        }
+        fired
+    }
+
+    /// Take the expiries not read yet, `None` if there are none
+    fn take(&self) -> Option<u64> {
+        let mut state = self.state.lock();
+        match core::mem::take(&mut state.expirations) {
+            0 => None,
+            expirations => Some(expirations),
+        }
+    }
+}
+
+impl TimerHandler for TimerFdObject {
+    fn on_timer_expired(self: Arc<Self>, context: usize) {
+        let mut state = self.state.lock();
+        if context != state.generation {
+            return;
+        }
+        state.timer = None;
+        let fired = self.update(&mut state, get_time_ns());
+        drop(state);
+        if fired {
+            self.waker.wake_all();
+        }
+    }
+}
+
+impl StreamOps for TimerFdObject {
+    /// Read the number of expiries since the last read as a native-endian
+    /// `u64`
+    ///
+    /// A buffer shorter than 8 bytes is an `InvalidArgument`.
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, StreamError> {
+        if buffer.len() < size_of::<u64>() {
+            return Err(StreamError::InvalidArgument);
+        }
+        let mut expirations = 0;
+        let mut attempt = || match self.take() {
+            Some(taken) => {
+                expirations = taken;
+                true
+            }
+            None => false,
+        };
+        if self.nonblocking {
+            if !attempt() {
+                return Err(StreamError::WouldBlock);
+            }
+        } else {
+            let sources: [&dyn PollOps; 1] = [self];
+            match wait_for(&sources, None, attempt) {
+                PollWait::Ready => {}
+                PollWait::Interrupted => return Err(StreamError::Interrupted),
+                PollWait::TimedOut => return Err(StreamError::WouldBlock),
+            }
+        }
+        buffer[..size_of::<u64>()].copy_from_slice(&expirations.to_ne_bytes());
+        Ok(size_of::<u64>())
+    }
+
+    /// Timers are armed with [`TimerFdObject::set`], not written
+    fn write(&self, _buffer: &[u8]) -> Result<usize, StreamError> {
+        Err(StreamError::NotSupported)
+    }
+}
+
+impl PollOps for TimerFdObject {
+    fn poll_events(&self) -> u32 {
+        if self.state.lock().expirations > 0 { POLLIN } else { 0 }
+    }
+
+    fn poll_register(&self, task_id: usize) -> bool {
+        self.waker.register(task_id);
+        true
+    }
+
+    fn poll_unregister(&self, task_id: usize) {
+        self.waker.unregister(task_id);
+    }
+}
+
+impl Drop for TimerFdObject {
+    fn drop(&mut self) {
+        if let Some(id) = self.state.lock().timer.take() {
+            cancel_timer(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_timerfd_set_and_read() {
+        let timer = TimerFdObject::new(true);
+        let mut buffer = [0u8; 8];
+        assert_eq!(timer.get(), TimerSetting::default());
+        assert!(matches!(timer.read(&mut buffer), Err(StreamError::WouldBlock)));
+
+        // A time that has passed expires at once
+        timer.set(1, 0, true);
+        assert_eq!(timer.poll_events(), POLLIN);
+        assert!(matches!(timer.read(&mut buffer), Ok(8)));
+        assert_eq!(u64::from_ne_bytes(buffer), 1);
+        assert_eq!(timer.get(), TimerSetting::default());
+        assert_eq!(timer.poll_events(), 0);
+
+        // Relative, periodic, then disarmed
+        let second = 1_000_000_000;
+        timer.set(10 * second, second, false);
+        let setting = timer.get();
+        assert!(setting.value > 9 * second && setting.value <= 10 * second);
+        assert_eq!(setting.interval, second);
+        let old = timer.set(0, 0, false);
+        assert_eq!(old.interval, second);
+        assert_eq!(timer.get(), TimerSetting::default());
+        assert!(matches!(timer.read(&mut buffer), Err(StreamError::WouldBlock)));
+    }
+}
@@ -0,0 +1,127 @@
+//! Timer object system calls
+//!
+//! Settings are passed as `{ value: u64, interval: u64 }` in nanoseconds
+//! (see [`TimerSetting`]).
+
+use crate::{
+    arch::Trapframe,
+    object::KernelObject,
+    task::{
+        mytask,
+        signal::{copy_from_user, copy_to_user},
+    },
+};
+
+use super::{TimerFdObject, TimerSetting};
+
+/// Reads fail instead of waiting for an expiry (sys_timerfd_create flag)
+pub const TIMERFD_NONBLOCK: usize = 0x1;
+/// The value is a time since boot (sys_timerfd_set flag)
+pub const TIMERFD_ABSTIME: usize = 0x1;
+
+/// Size of a setting in user memory
+const SETTING_SIZE: usize = 16;
+
+fn setting_to_bytes(setting: TimerSetting) -> [u8; SETTING_SIZE] {
+    let mut bytes = [0u8; SETTING_SIZE];
+    bytes[0..8].copy_from_slice(&setting.value.to_le_bytes());
+    bytes[8..16].copy_from_slice(&setting.interval.to_le_bytes());
+    bytes
+}
+
+/// The timer object of `handle` in the current task
+fn timer_object(handle: usize) -> Option<alloc::sync::Arc<TimerFdObject>> {
+    match mytask()?.handle_table.read().get(handle as u32).cloned()? {
+        KernelObject::TimerFd(timer) => Some(timer),
+        _ => None,
+    }
+}
+
+/// sys_timerfd_create - Create a disarmed timer object
+///
+/// Arguments:
+/// - flags: TIMERFD_NONBLOCK
+///
+/// Returns: the handle of the timer object, usize::MAX on error
+pub fn sys_timerfd_create(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+    let flags = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    if flags & !TIMERFD_NONBLOCK != 0 {
+        return usize::MAX;
+    }
+    let timer = TimerFdObject::new(flags & TIMERFD_NONBLOCK != 0);
+    match task.handle_table.write().insert(KernelObject::from_timerfd(timer)) {
+        Ok(handle) => handle as usize,
+        Err(_) => usize::MAX,
+    }
+}
+
+/// sys_timerfd_set - Arm or disarm a timer object
+///
+/// Arguments:
+/// - handle: handle of the timer object
+/// - flags: TIMERFD_ABSTIME
+/// - setting_ptr: the new setting; a value of 0 disarms the timer
+/// - old_ptr: receives the previous setting, 0 if not wanted
+///
+/// Returns: 0 on success, usize::MAX on error
+pub fn sys_timerfd_set(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+    let handle = trapframe.get_arg(0);
+    let flags = trapframe.get_arg(1);
+    let setting_ptr = trapframe.get_arg(2);
+    let old_ptr = trapframe.get_arg(3);
+    trapframe.increment_pc_next(task);
+
+    if flags & !TIMERFD_ABSTIME != 0 {
+        return usize::MAX;
+    }
+    let Some(timer) = timer_object(handle) else {
+        return usize::MAX;
+    };
+    let mut bytes = [0u8; SETTING_SIZE];
+    if copy_from_user(task, setting_ptr, &mut bytes).is_err() {
+        return usize::MAX;
+    }
+    let value = u64::from_le_bytes(bytes[0..8].try_into().unwrap());
+    let interval = u64::from_le_bytes(bytes[8..16].try_into().unwrap());
+    let old = timer.set(value, interval, flags & TIMERFD_ABSTIME != 0);
+    if old_ptr != 0 && copy_to_user(task, old_ptr, &setting_to_bytes(old)).is_err() {
+        return usize::MAX;
+    }
+    0
+}
+
+/// sys_timerfd_get - Get the setting of a timer object
+///
+/// Arguments:
+/// - handle: handle of the timer object
+/// - setting_ptr: receives the setting, with the time left until the next
+///   expiry
+///
+/// Returns: 0 on success, usize::MAX on error
+pub fn sys_timerfd_get(trapframe: &mut Trapframe) -> usize {
+    let task = match mytask() {
+        Some(task) => task,
+        None => return usize::MAX,
+    };
+    let handle = trapframe.get_arg(0);
+    let setting_ptr = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    let Some(timer) = timer_object(handle) else {
+        return usize::MAX;
+    };
+    match copy_to_user(task, setting_ptr, &setting_to_bytes(timer.get())) {
+        Ok(()) => 0,
+        Err(_) => usize::MAX,
+    }
+}
@@ -0,0 +1,442 @@
+//! Kernel random number generator
+//!
+//! A ChaCha20-based CSPRNG. Entropy is gathered in a pool, which is folded
+//! into the key of the generator once it holds [`SEED_BITS`] bits of
+//! credited entropy: the first time seeds the generator, later ones reseed
+//! it at most every [`RESEED_INTERVAL_US`].
+//!
+//! What an input is credited with depends on where it comes from: a
+//! hardware generator such as virtio-rng ([`add_credited_entropy`],
+//! [`register_source`]) fully, timing jitter measured at boot sparingly,
+//! and what [`add_entropy`] mixes in, like writes to `/dev/random`, not at
+//! all. Output uses fast key erasure: each request replaces the key with
+//! the first block of its keystream before the rest is handed out, so the
+//! state of the generator does not give away what it produced before.
+//!
+//! Drawing before the generator is seeded does not block; the output then
+//! depends on the timer and whatever was mixed in so far. Callers that need
+//! full strength, like `/dev/random` and `getrandom`, wait with
+//! [`wait_until_seeded`] first.
+
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::abi::error::KernelError;
+use crate::late_initcall;
+use crate::sync::waker::Waker;
+use crate::task::mytask;
+
+/// Credited entropy in bits that seeds the generator
+pub const SEED_BITS: usize = 256;
+
+/// Least time between two reseeds of a seeded generator
+pub const RESEED_INTERVAL_US: u64 = 60_000_000;
+
+/// `getrandom` flags: fail with `WouldBlock` instead of waiting for the
+/// generator to be seeded
+pub const GRND_NONBLOCK: usize = 0x1;
+/// `getrandom` flags: draw from the blocking pool; the same generator here
+pub const GRND_RANDOM: usize = 0x2;
+/// `getrandom` flags: do not wait for the generator to be seeded
+pub const GRND_INSECURE: usize = 0x4;
+
+const CHACHA_CONSTANTS: [u32; 4] = [0x6170_7865, 0x3320_646e, 0x7962_2d32, 0x6b20_6574];
+const BLOCK_LEN: usize = 64;
+const KEY_LEN: usize = 32;
+
+/// Largest output produced under one key
+const MAX_REQUEST: usize = 4096;
+
+/// Uses of the key kept apart by the last nonce word
+const DOMAIN_OUTPUT: u32 = 0;
+const DOMAIN_POOL: u32 = 1;
+const DOMAIN_RESEED: u32 = 2;
+
+fn quarter_round(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(16);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(12);
+    state[a] = state[a].wrapping_add(state[b]);
+    state[d] = (state[d] ^ state[a]).rotate_left(8);
+    state[c] = state[c].wrapping_add(state[d]);
+    state[b] = (state[b] ^ state[c]).rotate_left(7);
+}
+
+/// Block `counter` of the ChaCha20 keystream of `key` and `nonce` (RFC 8439)
+fn chacha20_block(key: &[u32; 8], counter: u32, nonce: [u32; 3]) -> [u8; BLOCK_LEN] {
+    let mut state = [0u32; 16];
+    state[..4].copy_from_slice(&CHACHA_CONSTANTS);
+    state[4..12].copy_from_slice(key);
+    state[12] = counter;
+    state[13..].copy_from_slice(&nonce);
+
+    let mut working = state;
+    for _ in 0..10 {
+        quarter_round(&mut working, 0, 4, 8, 12);
+        quarter_round(&mut working, 1, 5, 9, 13);
+        quarter_round(&mut working, 2, 6, 10, 14);
+        quarter_round(&mut working, 3, 7, 11, 15);
+        quarter_round(&mut working, 0, 5, 10, 15);
+        quarter_round(&mut working, 1, 6, 11, 12);
+        quarter_round(&mut working, 2, 7, 8, 13);
+        quarter_round(&mut working, 3, 4, 9, 14);
+    }
+
+    let mut block = [0u8; BLOCK_LEN];
+    for (index, (word, initial)) in working.iter().zip(state.iter()).enumerate() {
+        block[index * 4..index * 4 + 4].copy_from_slice(&word.wrapping_add(*initial).to_le_bytes());
+    }
+    block
+}
+
+/// The key in the first `KEY_LEN` bytes of `bytes`
+fn key_from(bytes: &[u8]) -> [u32; 8] {
+    let mut key = [0u32; 8];
+    for (word, chunk) in key.iter_mut().zip(bytes.chunks_exact(4)) {
+        *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    key
+}
+
+struct Crng {
+    key: [u32; 8],
+    /// Requests served so far, the nonce of the next one
+    generation: u64,
+    pool: [u32; 8],
+    /// Credited bits in the pool, at most `SEED_BITS`
+    pool_bits: usize,
+    last_reseed_us: u64,
+}
+
+static CRNG: Mutex<Crng> = Mutex::new(Crng {
+    key: [0; 8],
+    generation: 0,
+    pool: [0; 8],
+    pool_bits: 0,
+    last_reseed_us: 0,
+});
+
+static SEEDED: AtomicBool = AtomicBool::new(false);
+static SEEDED_WAKER: Waker = Waker::new_interruptible("random");
+
+impl Crng {
+    fn nonce(&self, domain: u32) -> [u32; 3] {
+        [self.generation as u32, (self.generation >> 32) as u32, domain]
+    }
+
+    /// Fold `bytes` into the pool
+    fn absorb(&mut self, bytes: &[u8]) {
+        for chunk in bytes.chunks(KEY_LEN) {
+            let mut key = self.pool;
+            for (word, input) in key.iter_mut().zip(key_from(&pad(chunk))) {
+                *word ^= input;
+            }
+            // The length tells a short chunk from one ending in zeros
+            let block = chacha20_block(&key, chunk.len() as u32, [0, 0, DOMAIN_POOL]);
+            self.pool = key_from(&block);
+        }
+    }
+
+    /// Fold the pool into the key
+    fn reseed(&mut self, now: u64) {
+        let mut key = self.key;
+        for (word, pooled) in key.iter_mut().zip(self.pool) {
+            *word ^= pooled;
+        }
+        let block = chacha20_block(&key, 0, self.nonce(DOMAIN_RESEED));
+        self.key = key_from(&block);
+        self.generation += 1;
+        self.pool_bits = 0;
+        self.last_reseed_us = now;
+    }
+
+    /// Credit the pool with `bits`, reseeding when it holds enough; returns
+    /// whether this seeded the generator
+    fn credit(&mut self, bits: usize, now: u64) -> bool {
+        self.pool_bits = (self.pool_bits + bits).min(SEED_BITS);
+        if self.pool_bits < SEED_BITS {
+            return false;
+        }
+        let seeded = SEEDED.load(Ordering::Acquire);
+        if seeded && now.saturating_sub(self.last_reseed_us) < RESEED_INTERVAL_US {
+            return false;
+        }
+        self.reseed(now);
+        !seeded
+    }
+
+    /// The key and nonce of the next request, erasing the key from the
+    /// generator: block 0 of their keystream becomes the new key, and the
+    /// output starts at block 1
+    fn next_request(&mut self) -> ([u32; 8], [u32; 3]) {
+        let key = self.key;
+        let nonce = self.nonce(DOMAIN_OUTPUT);
+        self.key = key_from(&chacha20_block(&key, 0, nonce));
+        self.generation += 1;
+        (key, nonce)
+    }
+}
+
+fn pad(chunk: &[u8]) -> [u8; KEY_LEN] {
+    let mut padded = [0u8; KEY_LEN];
+    padded[..chunk.len()].copy_from_slice(chunk);
+    padded
+}
+
+/// Timer-derived sample, uncredited
+fn timer_sample() -> [u8; 16] {
+    let now = crate::time::current_time();
+    // The address of a stack slot differs between tasks and CPUs
+    let marker = 0u8;
+    let mut sample = [0u8; 16];
+    sample[..8].copy_from_slice(&now.to_le_bytes());
+    sample[8..].copy_from_slice(&(&marker as *const u8 as u64).to_le_bytes());
+    sample
+}
+
+/// A hardware random number generator the kernel draws from when it
+/// reseeds
+pub trait EntropySource: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Fill `buffer` with random bytes, returning how many were written;
+    /// 0 when the source is busy or has failed
+    fn read_entropy(&self, buffer: &mut [u8]) -> usize;
+}
+
+static SOURCES: Mutex<Vec<Arc<dyn EntropySource>>> = Mutex::new(Vec::new());
+/// When the sources were last drawn from
+static LAST_PULL_US: AtomicU64 = AtomicU64::new(0);
+
+/// Draw from `source` at every reseed from now on, crediting it fully
+pub fn register_source(source: Arc<dyn EntropySource>) {
+    crate::early_println!("[random] Drawing entropy from {}", source.name());
+    pull(source.as_ref());
+    SOURCES.lock().push(source);
+}
+
+/// Draw a seed's worth from `source`
+fn pull(source: &dyn EntropySource) {
+    let mut seed = [0u8; KEY_LEN];
+    let len = source.read_entropy(&mut seed);
+    add_credited_entropy(&seed[..len], len * 8);
+    seed.fill(0);
+}
+
+/// Draw from the registered sources if a reseed is due
+fn pull_sources_if_due() {
+    let now = crate::time::current_time();
+    let last = LAST_PULL_US.load(Ordering::Relaxed);
+    if !is_seeded() || now.saturating_sub(last) < RESEED_INTERVAL_US {
+        return;
+    }
+    if LAST_PULL_US.compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed).is_err() {
+        return;
+    }
+    // Drawing may happen in an interrupt taken while the list is held
+    let Some(sources) = SOURCES.try_lock().map(|sources| sources.clone()) else {
+        return;
+    };
+    for source in sources {
+        pull(source.as_ref());
+    }
+}
+
+/// Mix `bytes` into the generator without crediting any entropy
+pub fn add_entropy(bytes: &[u8]) {
+    CRNG.lock().absorb(bytes);
+}
+
+/// Mix `bytes` into the generator, crediting them with `bits` bits of
+/// entropy
+///
+/// Only sources whose output is known to be unpredictable, like hardware
+/// generators, should credit anything.
+pub fn add_credited_entropy(bytes: &[u8], bits: usize) {
+    let now = crate::time::current_time();
+    let newly_seeded = {
+        let mut crng = CRNG.lock();
+        crng.absorb(bytes);
+        crng.credit(bits, now)
+    };
+    if newly_seeded {
+        SEEDED.store(true, Ordering::Release);
+        LAST_PULL_US.store(now, Ordering::Relaxed);
+        SEEDED_WAKER.wake_all();
+    }
+}
+
+/// Whether the generator has been seeded with `SEED_BITS` of credited
+/// entropy
+pub fn is_seeded() -> bool {
+    SEEDED.load(Ordering::Acquire)
+}
+
+/// Wait for the generator to be seeded
+///
+/// Fails with `WouldBlock` if it is not and `nonblock` is set or there is
+/// no task to block, and with `Interrupted` if a signal arrives first.
+pub fn wait_until_seeded(nonblock: bool) -> Result<(), KernelError> {
+    loop {
+        if is_seeded() {
+            return Ok(());
+        }
+        if nonblock {
+            return Err(KernelError::WouldBlock);
+        }
+        let Some(task) = mytask() else {
+            return Err(KernelError::WouldBlock);
+        };
+        SEEDED_WAKER.wait_unless(task.get_id(), task.get_trapframe(), || {
+            is_seeded() || mytask().is_some_and(|task| task.signals.has_pending())
+        });
+        if !is_seeded() && task.signals.has_pending() {
+            return Err(KernelError::Interrupted);
+        }
+    }
+}
+
+/// Get a random 64-bit value
+pub fn get_random_u64() -> u64 {
+    let mut bytes = [0u8; 8];
+    fill_random_bytes(&mut bytes);
+    u64::from_le_bytes(bytes)
+}
+
+/// Get a random value in `0..bound` (returns 0 if `bound` is 0)
+pub fn get_random_below(bound: u64) -> u64 {
+    if bound == 0 {
+        return 0;
+    }
+    // Widening multiply keeps the bias negligible without a rejection loop
+    ((get_random_u64() as u128 * bound as u128) >> 64) as u64
+}
+
+/// Fill a buffer with random bytes
+pub fn fill_random_bytes(buf: &mut [u8]) {
+    pull_sources_if_due();
+    for request in buf.chunks_mut(MAX_REQUEST) {
+        let (key, nonce) = {
+            let mut crng = CRNG.lock();
+            if !is_seeded() {
+                // Until seeded, at least the timing of each draw differs
+                crng.absorb(&timer_sample());
+                let now = crate::time::current_time();
+                crng.reseed(now);
+            }
+            crng.next_request()
+        };
+        for (index, chunk) in request.chunks_mut(BLOCK_LEN).enumerate() {
+            let block = chacha20_block(&key, index as u32 + 1, nonce);
+            chunk.copy_from_slice(&block[..chunk.len()]);
+        }
+    }
+}
+
+/// Timer samples taken to credit one bit of jitter entropy
+const JITTER_SAMPLES_PER_BIT: usize = 16;
+/// Most timer samples taken at boot
+const JITTER_MAX_SAMPLES: usize = 65536;
+
+/// Seed the generator from timing jitter
+///
+/// Each sample times a fixed amount of work. How long it takes varies with
+/// caches, interrupts and, under emulation, the host; a sample counts only
+/// when its duration differs from the one before, and is credited with a
+/// small fraction of a bit. Gives up after `JITTER_MAX_SAMPLES`, leaving a
+/// machine with a coarse, steady timer to a hardware source.
+fn seed_from_jitter() {
+    let mut work = [0u32; 8];
+    let mut previous = 0u64;
+    let mut varied = 0usize;
+    for sample in 0..JITTER_MAX_SAMPLES {
+        if is_seeded() {
+            return;
+        }
+        let start = crate::time::current_time();
+        work = key_from(&chacha20_block(&work, sample as u32, [0, 0, DOMAIN_POOL]));
+        let duration = crate::time::current_time().wrapping_sub(start);
+        if duration != previous {
+            varied += 1;
+        }
+        previous = duration;
+        let mut input = [0u8; 16];
+        input[..8].copy_from_slice(&start.to_le_bytes());
+        input[8..].copy_from_slice(&duration.to_le_bytes());
+        let bits = if varied == JITTER_SAMPLES_PER_BIT {
+            varied = 0;
+            1
+        } else {
+            0
+        };
+        add_credited_entropy(&input, bits);
+    }
+    crate::early_println!("[random] Not enough timing jitter to seed the generator");
+}
+
+fn seed_at_boot() {
+    if !is_seeded() {
+        seed_from_jitter();
+    }
+}
+
+late_initcall!(seed_at_boot);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_random_values_differ() {
+        let a = get_random_u64();
+        let b = get_random_u64();
+        let c = get_random_u64();
+        assert!(a != b || b != c);
+    }
+
+    #[test_case]
+    fn test_random_below_bound() {
+        for _ in 0..64 {
+            assert!(get_random_below(10) < 10);
+        }
+        assert_eq!(get_random_below(0), 0);
+    }
+
+    #[test_case]
+    fn test_fill_random_bytes_partial_chunk() {
+        let mut buf = [0u8; 13];
+        fill_random_bytes(&mut buf);
+        assert!(buf.iter().any(|&b| b != 0));
+
+        // Requests longer than one key's worth still fill every byte
+        let mut long = alloc::vec![0u8; MAX_REQUEST + 77];
+        fill_random_bytes(&mut long);
+        assert!(long[MAX_REQUEST..].iter().any(|&b| b != 0));
+    }
+
+    #[test_case]
+    fn test_chacha20_block() {
+        // RFC 8439, section 2.3.2
+        let mut key_bytes = [0u8; KEY_LEN];
+        for (index, byte) in key_bytes.iter_mut().enumerate() {
+            *byte = index as u8;
+        }
+        let block = chacha20_block(&key_from(&key_bytes), 1, [0x0900_0000, 0x4a00_0000, 0]);
+        assert_eq!(
+            block[..16],
+            [0x10, 0xf1, 0xe7, 0xe4, 0xd1, 0x3b, 0x59, 0x15, 0x50, 0x0f, 0xdd, 0x1f, 0xa3, 0x20, 0x71, 0xc4]
+        );
+        assert_eq!(block[60..], [0xa2, 0x50, 0x3c, 0x4e]);
+    }
+
+    #[test_case]
+    fn test_credited_entropy_seeds() {
+        add_credited_entropy(&[0x5a; KEY_LEN], SEED_BITS);
+        assert!(is_seeded());
+        assert_eq!(wait_until_seeded(true), Ok(()));
+    }
+}
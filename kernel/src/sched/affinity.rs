@@ -0,0 +1,85 @@
+//! CPU affinity masks
+//!
+//! Every task carries a mask of the CPUs it may run on. It allows every CPU
+//! by default and is inherited on clone. The scheduler honours it wherever
+//! it places a task:
+//!
+//! - new tasks only go to CPUs in their mask (see
+//!   [`crate::sched::scheduler::Scheduler::select_cpu`])
+//! - load balancing never pulls a task to a CPU outside its mask
+//! - a task found on a CPU it is no longer allowed on (because its mask was
+//!   changed) is not dispatched there; it is moved to an allowed CPU once
+//!   the CPU has switched away from it
+//!
+//! Idle tasks are pinned to their own CPU.
+
+use crate::environment::NUM_OF_CPUS;
+
+/// A set of CPUs, one bit per CPU ID
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct CpuMask(usize);
+
+impl CpuMask {
+    /// Every CPU of the system
+    pub const fn all() -> Self {
+        if NUM_OF_CPUS >= usize::BITS as usize {
+            Self(usize::MAX)
+        } else {
+            Self((1 << NUM_OF_CPUS) - 1)
+        }
+    }
+
+    /// Only `cpu_id`
+    pub const fn single(cpu_id: usize) -> Self {
+        Self(1 << cpu_id)
+    }
+
+    /// Build a mask from its bits, dropping CPUs that do not exist
+    pub const fn from_bits(bits: usize) -> Self {
+        Self(bits & Self::all().0)
+    }
+
+    pub const fn bits(self) -> usize {
+        self.0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn contains(self, cpu_id: usize) -> bool {
+        cpu_id < usize::BITS as usize && self.0 & (1 << cpu_id) != 0
+    }
+
+    /// CPUs in both masks
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    /// Lowest CPU in the mask
+    pub fn first(self) -> Option<usize> {
+        (!self.is_empty()).then(|| self.0.trailing_zeros() as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_cpu_mask() {
+        let all = CpuMask::all();
+        assert!((0..NUM_OF_CPUS).all(|cpu_id| all.contains(cpu_id)));
+        assert!(!all.contains(NUM_OF_CPUS));
+
+        // CPUs that do not exist are dropped
+        assert_eq!(CpuMask::from_bits(usize::MAX), all);
+        assert!(CpuMask::from_bits(1 << NUM_OF_CPUS).is_empty());
+
+        let last = CpuMask::single(NUM_OF_CPUS - 1);
+        assert_eq!(last.first(), Some(NUM_OF_CPUS - 1));
+        assert_eq!(all.intersection(last), last);
+        assert_eq!(CpuMask::from_bits(0).first(), None);
+    }
+}
@@ -4,4 +4,8 @@
 //! managing tasks and their execution.
 //! 
 
-pub mod scheduler;
\ No newline at end of file
+pub mod scheduler;
+pub mod priority;
+pub mod affinity;
+pub mod stats;
+pub mod preempt;
\ No newline at end of file
@@ -0,0 +1,102 @@
+//! Kernel preemption points
+//!
+//! The kernel runs with interrupts disabled: a task in the kernel keeps its
+//! CPU until it blocks or returns to user space, and timer ticks that fall
+//! due in the meantime wait. Long-running kernel paths (following a FAT
+//! cluster chain, reading a large file) call [`cond_resched`] where they
+//! hold no locks. If the tick of the CPU is overdue it is taken right there,
+//! so the time slice of the task is accounted and, once it runs out, other
+//! tasks get the CPU just as after a tick from user space.
+//!
+//! Code that holds a spin lock across a call that may reach a preemption
+//! point disables preemption with a [`PreemptGuard`]; switching away with
+//! the lock held would leave the next task on the CPU spinning on it. Like
+//! the guards, the count nests, and it is kept per task so that it follows
+//! a task that blocks and resumes on another CPU.
+
+use crate::arch::get_cpu;
+use crate::sched::scheduler::get_scheduler;
+use crate::task::mytask;
+use crate::timer::{tick, tick_due};
+
+/// Disable preemption of the current task until [`preempt_enable`]
+pub fn preempt_disable() {
+    if let Some(task) = mytask() {
+        task.preempt_count += 1;
+    }
+}
+
+/// Undo one [`preempt_disable`]
+pub fn preempt_enable() {
+    if let Some(task) = mytask() {
+        task.preempt_count = task.preempt_count.saturating_sub(1);
+    }
+}
+
+/// Whether the current task may be switched away at a preemption point
+pub fn preemptible() -> bool {
+    mytask().is_none_or(|task| task.preempt_count == 0)
+}
+
+/// Preemption stays disabled while the guard lives
+pub struct PreemptGuard(());
+
+impl PreemptGuard {
+    pub fn new() -> Self {
+        preempt_disable();
+        PreemptGuard(())
+    }
+}
+
+impl Drop for PreemptGuard {
+    fn drop(&mut self) {
+        preempt_enable();
+    }
+}
+
+/// A preemption point: take the tick of this CPU if it is overdue
+///
+/// Must not be called with a spin lock held, unless preemption is disabled.
+///
+/// # Returns
+/// `true` if the tick was taken; the task may have been switched away and
+/// back since, possibly to another CPU.
+pub fn cond_resched() -> bool {
+    let cpu_id = get_cpu().get_cpuid();
+    let Some(task) = get_scheduler().get_current_task(cpu_id) else {
+        return false;
+    };
+    if task.preempt_count > 0 || !tick_due(cpu_id) {
+        return false;
+    }
+    tick(task.get_trapframe());
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+    use alloc::string::ToString;
+    use crate::task::{clear_mock_current_task, new_user_task, set_mock_current_task};
+
+    #[test_case]
+    fn test_preempt_guard_nesting() {
+        let task = Box::leak(Box::new(new_user_task("preempt".to_string(), 0)));
+        unsafe { set_mock_current_task(task) };
+        assert!(preemptible());
+        {
+            let _outer = PreemptGuard::new();
+            {
+                let _inner = PreemptGuard::new();
+                assert_eq!(mytask().unwrap().preempt_count, 2);
+            }
+            assert!(!preemptible());
+        }
+        assert!(preemptible());
+        // Unbalanced enables do not wrap around
+        preempt_enable();
+        assert!(preemptible());
+        unsafe { clear_mock_current_task() };
+    }
+}
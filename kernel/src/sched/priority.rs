@@ -0,0 +1,195 @@
+//! Scheduling policies, nice values and weighted time slices
+//!
+//! Every task has a nice value from [`NICE_MIN`] (most favoured) to
+//! [`NICE_MAX`] (least favoured). It is 0 for new tasks and inherited on
+//! clone, so a job started at a high nice value keeps it for everything it
+//! spawns.
+//!
+//! The scheduler stays round-robin, but the time slice a task gets on each
+//! dispatch is scaled by the weight of its nice value: a task at nice 0 runs
+//! for [`DEFAULT_TIME_SLICE`] ticks, a CPU-bound background job at nice 19
+//! only for one. The weights are the ones Linux uses, where each nice level
+//! is worth about 25% of CPU time against its neighbour.
+//!
+//! Tasks can instead be put in a real-time class ([`SchedPolicy::Fifo`] or
+//! [`SchedPolicy::RoundRobin`]) with a priority from [`RT_PRIO_MIN`] to
+//! [`RT_PRIO_MAX`]. A runnable real-time task always preempts normal tasks
+//! and lower priority real-time tasks. A FIFO task runs until it blocks or
+//! a higher priority task becomes runnable; a round-robin task additionally
+//! gives way to tasks of the same priority after [`RR_TIME_SLICE`] ticks.
+//!
+//! To keep a runaway real-time loop from locking up a CPU, real-time tasks
+//! may use at most [`RT_RUNTIME_TICKS`] of every [`RT_PERIOD_TICKS`] ticks on
+//! each CPU. Once a CPU has used its share it is throttled: only normal
+//! tasks run on it until the period ends.
+
+/// Most favoured nice value
+pub const NICE_MIN: i32 = -20;
+
+/// Least favoured nice value
+pub const NICE_MAX: i32 = 19;
+
+/// Time slice of a task at nice 0, in ticks
+pub const DEFAULT_TIME_SLICE: u32 = 4;
+
+/// Longest time slice any task gets, in ticks
+pub const MAX_TIME_SLICE: u32 = 40;
+
+/// Weight of nice 0
+const NICE_0_WEIGHT: u64 = 1024;
+
+/// Weight of each nice value, from `NICE_MIN` to `NICE_MAX`
+const NICE_TO_WEIGHT: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291,
+    29154, 23254, 18705, 14949, 11916,
+    9548, 7620, 6100, 4904, 3906,
+    3121, 2501, 1991, 1586, 1277,
+    1024, 820, 655, 526, 423,
+    335, 272, 215, 172, 137,
+    110, 87, 70, 56, 45,
+    36, 29, 23, 18, 15,
+];
+
+/// Lowest real-time priority
+pub const RT_PRIO_MIN: u32 = 1;
+
+/// Highest real-time priority
+pub const RT_PRIO_MAX: u32 = 99;
+
+/// Time slice of a round-robin real-time task, in ticks
+pub const RR_TIME_SLICE: u32 = 10;
+
+/// Length of the real-time throttling period, in ticks
+pub const RT_PERIOD_TICKS: u32 = 100;
+
+/// Ticks of each period real-time tasks may use on a CPU
+pub const RT_RUNTIME_TICKS: u32 = 95;
+
+/// Scheduling policy of a task
+///
+/// The values match the Linux `SCHED_*` constants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum SchedPolicy {
+    /// Time-shared, weighted by the nice value (SCHED_OTHER)
+    Normal = 0,
+    /// Real-time, first in first out (SCHED_FIFO)
+    Fifo = 1,
+    /// Real-time, round-robin among tasks of the same priority (SCHED_RR)
+    RoundRobin = 2,
+}
+
+impl SchedPolicy {
+    /// Convert a policy number from user space
+    pub fn from_raw(raw: usize) -> Option<Self> {
+        match raw {
+            0 => Some(SchedPolicy::Normal),
+            1 => Some(SchedPolicy::Fifo),
+            2 => Some(SchedPolicy::RoundRobin),
+            _ => None,
+        }
+    }
+
+    pub fn is_realtime(self) -> bool {
+        self != SchedPolicy::Normal
+    }
+
+    /// Check that `priority` is valid for the policy: 0 for normal tasks,
+    /// `RT_PRIO_MIN..=RT_PRIO_MAX` for real-time ones
+    pub fn is_valid_priority(self, priority: u32) -> bool {
+        if self.is_realtime() {
+            (RT_PRIO_MIN..=RT_PRIO_MAX).contains(&priority)
+        } else {
+            priority == 0
+        }
+    }
+}
+
+/// Real-time runtime accounting of a CPU
+#[derive(Debug, Clone, Copy)]
+pub struct RtBandwidth {
+    /// Ticks of the current period used by real-time tasks
+    used: u32,
+    /// Ticks elapsed in the current period
+    elapsed: u32,
+}
+
+impl RtBandwidth {
+    pub const fn new() -> Self {
+        Self { used: 0, elapsed: 0 }
+    }
+
+    /// Account one tick; `realtime` tells whether a real-time task ran
+    pub fn tick(&mut self, realtime: bool) {
+        self.elapsed += 1;
+        if self.elapsed >= RT_PERIOD_TICKS {
+            // A new period starts
+            self.elapsed = 0;
+            self.used = 0;
+        } else if realtime {
+            self.used += 1;
+        }
+    }
+
+    /// Whether real-time tasks have used up their share of the period
+    pub fn is_throttled(&self) -> bool {
+        self.used >= RT_RUNTIME_TICKS
+    }
+}
+
+/// Clamp a requested nice value into `NICE_MIN..=NICE_MAX`
+pub fn clamp_nice(nice: isize) -> i32 {
+    nice.clamp(NICE_MIN as isize, NICE_MAX as isize) as i32
+}
+
+/// Scheduling weight of a nice value
+pub fn nice_to_weight(nice: i32) -> u64 {
+    NICE_TO_WEIGHT[(clamp_nice(nice as isize) - NICE_MIN) as usize]
+}
+
+/// Time slice in ticks for a task at `nice`
+///
+/// Scaled from [`DEFAULT_TIME_SLICE`] by the weight of `nice`; every task
+/// gets at least one tick and at most [`MAX_TIME_SLICE`].
+pub fn time_slice_for_nice(nice: i32) -> u32 {
+    let ticks = (DEFAULT_TIME_SLICE as u64 * nice_to_weight(nice) + NICE_0_WEIGHT / 2) / NICE_0_WEIGHT;
+    (ticks as u32).clamp(1, MAX_TIME_SLICE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_time_slice_follows_nice() {
+        assert_eq!(time_slice_for_nice(0), DEFAULT_TIME_SLICE);
+        assert_eq!(time_slice_for_nice(NICE_MAX), 1);
+        assert_eq!(time_slice_for_nice(NICE_MIN), MAX_TIME_SLICE);
+        assert!(time_slice_for_nice(-5) > time_slice_for_nice(0));
+
+        // Out of range values are clamped
+        assert_eq!(clamp_nice(100), NICE_MAX);
+        assert_eq!(nice_to_weight(-100), nice_to_weight(NICE_MIN));
+    }
+
+    #[test_case]
+    fn test_rt_bandwidth_throttles_until_next_period() {
+        let mut bandwidth = RtBandwidth::new();
+        for _ in 0..RT_RUNTIME_TICKS {
+            assert!(!bandwidth.is_throttled());
+            bandwidth.tick(true);
+        }
+        assert!(bandwidth.is_throttled());
+
+        // Normal ticks finish the period, which lifts the throttle
+        for _ in RT_RUNTIME_TICKS..RT_PERIOD_TICKS {
+            bandwidth.tick(false);
+        }
+        assert!(!bandwidth.is_throttled());
+
+        assert!(SchedPolicy::Fifo.is_valid_priority(RT_PRIO_MAX));
+        assert!(!SchedPolicy::RoundRobin.is_valid_priority(0));
+        assert!(!SchedPolicy::Normal.is_valid_priority(1));
+        assert_eq!(SchedPolicy::from_raw(2), Some(SchedPolicy::RoundRobin));
+    }
+}
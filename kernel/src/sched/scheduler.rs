@@ -1,8 +1,9 @@
 //! Scheduler module
 //! 
 //! The scheduler module is responsible for scheduling tasks on the CPU.
-//! Currently, the scheduler is a simple round-robin scheduler with separate
-//! queues for different task states to improve efficiency:
+//! Currently, the scheduler is a round-robin scheduler whose time slices are
+//! weighted by each task's nice value (see [`crate::sched::priority`]), with
+//! separate queues for different task states to improve efficiency:
 //! 
 //! - `ready_queue`: Tasks that are ready to run
 //! - `blocked_queue`: Tasks waiting for I/O or other events  
@@ -10,19 +11,40 @@
 //! 
 //! This separation avoids unnecessary iteration over blocked/zombie tasks
 //! during normal scheduling operations.
+//!
+//! Every CPU has its own runqueue (its set of the queues above) behind its
+//! own lock, so CPUs only contend when they touch each other's tasks. New
+//! processes go to the least loaded online CPU (see [`Scheduler::select_cpu`]),
+//! and the load is evened out by migrating ready tasks:
+//!
+//! - periodically, every [`BALANCE_INTERVAL_TICKS`] ticks, each CPU pulls a
+//!   task from the busiest CPU if that one has at least two tasks more
+//! - a CPU that has nothing but its idle task to run pulls a task right away
+//!
+//! Tasks are only ever placed on CPUs in their affinity mask (see
+//! [`crate::sched::affinity`]).
+//!
+//! A runqueue stays locked across a context switch until the incoming task
+//! calls [`Scheduler::finish_task_switch`], so a task is never migrated
+//! before its kernel context is saved.
 
 extern crate alloc;
 
 use core::panic;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 
 use alloc::{boxed::Box, collections::vec_deque::VecDeque, string::ToString, vec::Vec};
 use hashbrown::HashMap;
 
-use crate::{arch::{Arch, Trapframe, enable_interrupt, get_cpu, get_user_trap_handler, instruction::idle, interrupt::enable_external_interrupts, set_arch, set_next_mode, set_trapvector, trap::{user::arch_switch_to_user_space}}, environment::NUM_OF_CPUS, task::{TaskState, new_kernel_task, wake_parent_waiters, wake_task_waiters}, timer::get_kernel_timer, vm::{get_kernel_vm_manager, get_trampoline_arch, get_trampoline_trap_vector}};
+use crate::{arch::{Arch, Trapframe, enable_interrupt, get_cpu, get_user_trap_handler, disable_interrupt, instruction::{idle, wait_for_interrupt}, interrupt::{enable_external_interrupts, enable_software_interrupts, send_ipi}, set_arch, set_next_mode, set_trapvector, set_user_xlen, trap::{user::arch_switch_to_user_space}}, environment::NUM_OF_CPUS, task::{TaskState, new_kernel_task, wake_parent_waiters, wake_task_waiters}, timer::{get_kernel_timer, get_time_us, is_tick_stopped, restart_tick, stop_tick}, vm::{get_kernel_vm_manager, get_trampoline_arch, get_trampoline_trap_vector}};
 use crate::println;
 use crate::print;
+use crate::sched::affinity::CpuMask;
+use crate::sched::priority::{time_slice_for_nice, RtBandwidth, SchedPolicy, NICE_MAX, RR_TIME_SLICE};
+use crate::sched::stats::CpuSchedStats;
 
 use crate::task::Task;
+use crate::task::cgroup::{scale_time_slice, ROOT_GROUP};
 
 /// Task pool that stores tasks in fixed positions
 /// With each Task being 824 bytes, 1024 tasks consume approximately 824 KiB of memory,
@@ -94,6 +116,79 @@ impl TaskPool {
     }
 }
 
+/// Recursive spin lock owned by a CPU
+///
+/// The scheduler calls back into itself (e.g. `run()` spawning an idle task
+/// through `add_task()`, or an allocation in the scheduler invoking the OOM
+/// killer), so the CPU holding a lock may take it again. Kernel code runs
+/// with interrupts disabled, so an interrupt never finds its own CPU holding
+/// a lock.
+struct CpuLock {
+    /// CPU ID + 1 of the holder, 0 when free
+    owner: AtomicUsize,
+    /// Number of times the holder has taken the lock
+    depth: AtomicUsize,
+}
+
+struct CpuLockGuard<'a> {
+    lock: &'a CpuLock,
+}
+
+impl CpuLock {
+    const fn new() -> Self {
+        Self { owner: AtomicUsize::new(0), depth: AtomicUsize::new(0) }
+    }
+
+    fn acquire(&self) {
+        let me = get_cpu().get_cpuid() + 1;
+        if self.owner.load(Ordering::Acquire) != me {
+            while self.owner.compare_exchange_weak(0, me, Ordering::Acquire, Ordering::Relaxed).is_err() {
+                core::hint::spin_loop();
+            }
+        }
+        self.depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn release(&self) {
+        if self.depth.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.owner.store(0, Ordering::Release);
+        }
+    }
+
+    /// Release the lock however often the current CPU has taken it
+    fn release_all(&self) {
+        if self.owner.load(Ordering::Relaxed) == get_cpu().get_cpuid() + 1 {
+            self.depth.store(0, Ordering::Relaxed);
+            self.owner.store(0, Ordering::Release);
+        }
+    }
+
+    fn lock(&self) -> CpuLockGuard<'_> {
+        self.acquire();
+        CpuLockGuard { lock: self }
+    }
+}
+
+impl Drop for CpuLockGuard<'_> {
+    fn drop(&mut self) {
+        self.lock.release();
+    }
+}
+
+/// Scheduler ticks between two periodic load balancing passes on a CPU
+pub const BALANCE_INTERVAL_TICKS: usize = 10;
+
+/// CPUs that have entered the scheduler
+static CPU_ONLINE: [AtomicBool; NUM_OF_CPUS] = [const { AtomicBool::new(false) }; NUM_OF_CPUS];
+
+/// CPUs that have entered the scheduler
+pub fn online_cpus() -> CpuMask {
+    let bits = (0..NUM_OF_CPUS)
+        .filter(|&cpu_id| CPU_ONLINE[cpu_id].load(Ordering::Acquire))
+        .fold(0, |bits, cpu_id| bits | CpuMask::single(cpu_id).bits());
+    CpuMask::from_bits(bits)
+}
+
 static mut SCHEDULER: Option<Scheduler> = None;
 
 pub fn get_scheduler() -> &'static mut Scheduler {
@@ -111,6 +206,8 @@ pub fn get_scheduler() -> &'static mut Scheduler {
 pub struct Scheduler {
     /// Task pool storing all tasks in fixed positions
     task_pool: TaskPool,
+    /// Protects `task_pool`
+    pool_lock: CpuLock,
     /// Queue for ready-to-run task IDs
     ready_queue: [VecDeque<usize>; NUM_OF_CPUS],
     /// Queue for blocked task IDs (waiting for I/O, etc.)
@@ -118,27 +215,187 @@ pub struct Scheduler {
     /// Queue for zombie task IDs (finished but not yet cleaned up)
     zombie_queue: [VecDeque<usize>; NUM_OF_CPUS],
     current_task_id: [Option<usize>; NUM_OF_CPUS],
+    /// Idle task of each CPU
+    idle_task_id: [Option<usize>; NUM_OF_CPUS],
+    /// Tasks that became zombies and whose waiters are still to be woken,
+    /// paired with their parent ID
+    exited_tasks: [Vec<(usize, Option<usize>)>; NUM_OF_CPUS],
+    /// Tasks found on a CPU outside their affinity mask, to be moved once
+    /// the runqueue is unlocked
+    misplaced_tasks: [Vec<usize>; NUM_OF_CPUS],
+    /// Ticks since the last periodic load balancing pass
+    balance_ticks: [usize; NUM_OF_CPUS],
+    /// Real-time runtime accounting of each CPU
+    rt_bandwidth: [RtBandwidth; NUM_OF_CPUS],
+    /// Busy and idle time of each CPU
+    cpu_stats: [CpuSchedStats; NUM_OF_CPUS],
+    /// Protects the queues and the per-CPU state of each CPU
+    ///
+    /// Lock order: runqueue locks in ascending CPU order, then `pool_lock`.
+    rq_lock: [CpuLock; NUM_OF_CPUS],
 }
 
 impl Scheduler {
     pub fn new() -> Self {
         Scheduler {
             task_pool: TaskPool::new(),
+            pool_lock: CpuLock::new(),
             ready_queue: [const { VecDeque::new() }; NUM_OF_CPUS],
             blocked_queue: [const { VecDeque::new() }; NUM_OF_CPUS],
             zombie_queue: [const { VecDeque::new() }; NUM_OF_CPUS],
             current_task_id: [const { None }; NUM_OF_CPUS],
+            idle_task_id: [const { None }; NUM_OF_CPUS],
+            exited_tasks: [const { Vec::new() }; NUM_OF_CPUS],
+            misplaced_tasks: [const { Vec::new() }; NUM_OF_CPUS],
+            balance_ticks: [0; NUM_OF_CPUS],
+            rt_bandwidth: [const { RtBandwidth::new() }; NUM_OF_CPUS],
+            cpu_stats: [const { CpuSchedStats::new() }; NUM_OF_CPUS],
+            rq_lock: [const { CpuLock::new() }; NUM_OF_CPUS],
         }
     }
 
-    pub fn add_task(&mut self, task: Task, cpu_id: usize) {
+    pub fn add_task(&mut self, mut task: Task, cpu_id: usize) {
         let task_id = task.get_id();
+        task.sched_stats.enqueue(get_time_us());
         // Add task to the task pool
-        if let Err(e) = self.task_pool.add_task(task) {
-            panic!("Failed to add task {}: {}", task_id, e);
+        {
+            let _pool = self.pool_lock.lock();
+            if let Err(e) = self.task_pool.add_task(task) {
+                panic!("Failed to add task {}: {}", task_id, e);
+            }
         }
         // Add task state info to ready queue
-        self.ready_queue[cpu_id].push_back(task_id);
+        {
+            let _rq = self.rq_lock[cpu_id].lock();
+            self.ready_queue[cpu_id].push_back(task_id);
+        }
+        self.kick_cpu(cpu_id);
+    }
+
+    /// Wake `cpu_id` if it idles with its tick stopped
+    ///
+    /// Call this after queueing a task for `cpu_id`. The idle loop marks its
+    /// tick stopped before it checks for work for the last time, so either
+    /// it sees the task or this sees the mark.
+    pub fn kick_cpu(&self, cpu_id: usize) {
+        core::sync::atomic::fence(Ordering::SeqCst);
+        if cpu_id != get_cpu().get_cpuid() && is_tick_stopped(cpu_id) {
+            send_ipi(cpu_id);
+        }
+    }
+
+    /// Number of runnable tasks on `cpu_id`, not counting its idle task
+    pub fn nr_running(&mut self, cpu_id: usize) -> usize {
+        let _rq = self.rq_lock[cpu_id].lock();
+        let idle_task_id = self.idle_task_id[cpu_id];
+        self.ready_queue[cpu_id].iter().filter(|&&id| Some(id) != idle_task_id).count()
+    }
+
+    /// Choose the CPU a new task with the given affinity should run on
+    ///
+    /// Returns the online CPU in `affinity` with the fewest runnable tasks,
+    /// preferring the current CPU on a tie. Before the other CPUs have come
+    /// up this is the current CPU if `affinity` allows it. If no allowed CPU
+    /// is online yet, the task waits on the first allowed one.
+    pub fn select_cpu(&mut self, affinity: CpuMask) -> usize {
+        let current = get_cpu().get_cpuid();
+        (0..NUM_OF_CPUS)
+            .filter(|&cpu_id| affinity.contains(cpu_id))
+            .filter(|&cpu_id| cpu_id == current || CPU_ONLINE[cpu_id].load(Ordering::Acquire))
+            .min_by_key(|&cpu_id| (self.nr_running(cpu_id), cpu_id != current))
+            .or(affinity.first())
+            .unwrap_or(current)
+    }
+
+    /// Move one waiting task from `src_cpu` to `dst_cpu`
+    ///
+    /// Only tasks that are ready to run, not currently running and allowed on
+    /// `dst_cpu` are moved; the task that has waited longest goes first. The
+    /// idle task stays.
+    ///
+    /// # Returns
+    /// The ID of the migrated task, if any
+    fn pull_task(&mut self, src_cpu: usize, dst_cpu: usize) -> Option<usize> {
+        if src_cpu == dst_cpu {
+            return None;
+        }
+        let (first, second) = (src_cpu.min(dst_cpu), src_cpu.max(dst_cpu));
+        let _first = self.rq_lock[first].lock();
+        let _second = self.rq_lock[second].lock();
+
+        let current = self.current_task_id[src_cpu];
+        let idle = self.idle_task_id[src_cpu];
+        let pos = {
+            let _pool = self.pool_lock.lock();
+            let queue = &self.ready_queue[src_cpu];
+            let pool = &mut self.task_pool;
+            queue.iter().position(|&id| {
+                Some(id) != current && Some(id) != idle
+                    && pool.get_task(id).is_some_and(|task| {
+                        matches!(task.state, TaskState::Ready | TaskState::Running)
+                            && task.cpu_affinity.contains(dst_cpu)
+                    })
+            })
+        }?;
+        let task_id = self.ready_queue[src_cpu].remove(pos)?;
+        self.ready_queue[dst_cpu].push_back(task_id);
+        Some(task_id)
+    }
+
+    /// Move `task_id` from the ready queue of `src_cpu` to `dst_cpu`
+    ///
+    /// Nothing is done if the task is no longer waiting on `src_cpu`, is not
+    /// ready to run or shares its address space.
+    ///
+    /// # Returns
+    /// `true` if the task was moved
+    fn migrate_task(&mut self, task_id: usize, src_cpu: usize, dst_cpu: usize) -> bool {
+        if src_cpu == dst_cpu {
+            return false;
+        }
+        let (first, second) = (src_cpu.min(dst_cpu), src_cpu.max(dst_cpu));
+        let _first = self.rq_lock[first].lock();
+        let _second = self.rq_lock[second].lock();
+
+        if self.current_task_id[src_cpu] == Some(task_id) {
+            return false;
+        }
+        // A blocked task stays where its wakeup will look for it
+        let runnable = {
+            let _pool = self.pool_lock.lock();
+            self.task_pool.get_task(task_id).is_some_and(|task| {
+                matches!(task.state, TaskState::Ready | TaskState::Running)
+            })
+        };
+        if !runnable {
+            return false;
+        }
+        let Some(pos) = self.ready_queue[src_cpu].iter().position(|&id| id == task_id) else {
+            return false;
+        };
+        self.ready_queue[src_cpu].remove(pos);
+        self.ready_queue[dst_cpu].push_back(task_id);
+        true
+    }
+
+    /// Pull a task to `cpu_id` from the busiest online CPU
+    ///
+    /// A task is only moved if the busiest CPU has at least two runnable
+    /// tasks more than `cpu_id`, so that moving one does not just swap the
+    /// imbalance.
+    ///
+    /// # Returns
+    /// `true` if a task was migrated
+    fn load_balance(&mut self, cpu_id: usize) -> bool {
+        let this_load = self.nr_running(cpu_id);
+        let busiest = (0..NUM_OF_CPUS)
+            .filter(|&id| id != cpu_id && CPU_ONLINE[id].load(Ordering::Acquire))
+            .map(|id| (self.nr_running(id), id))
+            .max();
+        match busiest {
+            Some((load, busiest)) if load >= this_load + 2 => self.pull_task(busiest, cpu_id).is_some(),
+            _ => false,
+        }
     }
 
     /// Determines the next task to run and returns current and next task IDs
@@ -155,6 +412,17 @@ impl Scheduler {
         let cpu_id = cpu.get_cpuid();
         let old_current_task_id = self.current_task_id[cpu_id];
 
+        // Real-time tasks go first, unless they have used up their share
+        let throttled = self.rt_bandwidth[cpu_id].is_throttled();
+        if !throttled {
+            if let Some(next_task_id) = self.pick_rt_task(cpu_id) {
+                return (old_current_task_id, Some(next_task_id));
+            }
+        }
+        // Real-time tasks while throttled and tasks not allowed on this CPU
+        // are passed over (at most once each)
+        let mut skips = self.ready_queue[cpu_id].len();
+
         // Continue trying to find a suitable task to run
         loop {
             let task_id = self.ready_queue[cpu_id].pop_front();
@@ -173,12 +441,8 @@ impl Scheduler {
                                 let parent_id = t.get_parent_id();
                                 self.zombie_queue[cpu_id].push_back(task_id);
                                 self.current_task_id[cpu_id] = None;
-                                // Wake up any processes waiting for this specific task
-                                wake_task_waiters(task_id);
-                                // Also wake up parent process for waitpid(-1)
-                                if let Some(parent_id) = parent_id {
-                                    wake_parent_waiters(parent_id);
-                                }
+                                // Its waiters are woken once the runqueue is unlocked
+                                self.exited_tasks[cpu_id].push((task_id, parent_id));
                                 continue;
                             },
                             TaskState::Terminated => {
@@ -196,7 +460,9 @@ impl Scheduler {
                             TaskState::Ready | TaskState::Running => {
                                 t.state = TaskState::Running;
                                 // Task is ready to run
-                                t.time_slice = 1; // Reset time slice on dispatch
+                                let time_slice = self.dispatch_time_slice(cpu_id, task_id);
+                                let t = self.get_task_by_id(task_id).expect("Task must exist in task pool");
+                                t.time_slice = time_slice; // Reset time slice on dispatch
                                 let next_task_id = t.get_id();
                                 self.current_task_id[cpu_id] = Some(next_task_id);
                                 self.ready_queue[cpu_id].push_back(task_id);
@@ -205,19 +471,7 @@ impl Scheduler {
                         }
                     }
                     // If no tasks are ready, create an idle task
-                    None => {
-                        let mut kernel_task = new_kernel_task("idle".to_string(), 0, || {
-                            // Idle loop
-                            loop {
-                                // Wait for an interrupt to wake up
-                                enable_external_interrupts();
-                                idle();
-                            }
-                        });
-                        kernel_task.init();
-                        // Add idle task to the ready queue
-                        self.add_task(kernel_task, cpu_id);
-                    }
+                    None => self.spawn_idle_task(cpu_id),
                 }
             } else {
                 match task_id {
@@ -231,15 +485,12 @@ impl Scheduler {
                                 let task_id = t.get_id();
                                 let parent_id = t.get_parent_id();
                                 self.zombie_queue[cpu_id].push_back(task_id);
-                                // Wake up any processes waiting for this specific task
-                                wake_task_waiters(task_id);
-                                // Also wake up parent process for waitpid(-1)
-                                if let Some(parent_id) = parent_id {
-                                    wake_parent_waiters(parent_id);
-                                }
+                                // Its waiters are woken once the runqueue is unlocked
+                                self.exited_tasks[cpu_id].push((task_id, parent_id));
                                 continue;
                             },
                             TaskState::Terminated => {
+                                let _pool = self.pool_lock.lock();
                                 self.task_pool.remove_task(task_id);
                                 continue;
                             },
@@ -253,8 +504,22 @@ impl Scheduler {
                                 continue;
                             },
                             TaskState::Ready | TaskState::Running => {
-
-                                t.time_slice = 1; // Reset time slice on dispatch
+                                let misplaced = !t.cpu_affinity.contains(cpu_id);
+                                if (misplaced || throttled && t.sched_policy.is_realtime()) && skips > 0 {
+                                    skips -= 1;
+                                    if self.current_task_id[cpu_id] == Some(task_id) {
+                                        self.current_task_id[cpu_id] = None;
+                                    }
+                                    if misplaced {
+                                        // Moved away once the runqueue is unlocked
+                                        self.misplaced_tasks[cpu_id].push(task_id);
+                                    }
+                                    self.ready_queue[cpu_id].push_back(task_id);
+                                    continue;
+                                }
+                                let time_slice = self.dispatch_time_slice(cpu_id, task_id);
+                                let t = self.get_task_by_id(task_id).expect("Task must exist in task pool");
+                                t.time_slice = time_slice; // Reset time slice on dispatch
                                 let next_task_id = t.get_id();
                                 self.current_task_id[cpu_id] = Some(next_task_id);
                                 self.ready_queue[cpu_id].push_back(task_id);
@@ -268,20 +533,189 @@ impl Scheduler {
         }
     }
 
+    /// Create an idle task on `cpu_id`
+    ///
+    /// The idle task runs whenever nothing else on the CPU is runnable. It
+    /// stops the periodic tick and sleeps in `wfi` until an interrupt or an
+    /// IPI from [`Scheduler::kick_cpu`] brings work or a software timer
+    /// expires (see [`crate::timer::stop_tick`]).
+    fn spawn_idle_task(&mut self, cpu_id: usize) {
+        let mut kernel_task = new_kernel_task("idle".to_string(), 0, || {
+            let cpu_id = get_cpu().get_cpuid();
+            // Idle loop
+            loop {
+                // Wait for an interrupt to wake up
+                enable_external_interrupts();
+                enable_software_interrupts();
+                // The check for work and the wait must not miss a wakeup
+                // in between: a pending interrupt ends wfi even while
+                // interrupts are disabled
+                disable_interrupt();
+                if get_scheduler().nr_running(cpu_id) == 0 {
+                    // Marked stopped before looking again, so that a task
+                    // queued meanwhile comes with an IPI
+                    stop_tick(cpu_id);
+                    if get_scheduler().nr_running(cpu_id) == 0 {
+                        wait_for_interrupt();
+                    }
+                    // Take the interrupt that ended the wait
+                    enable_interrupt();
+                    if get_scheduler().nr_running(cpu_id) > 0 {
+                        // Tick right away, so that the scheduler runs it
+                        restart_tick(cpu_id);
+                    }
+                } else {
+                    // Tasks this CPU may not run now wait for the next tick
+                    wait_for_interrupt();
+                    enable_interrupt();
+                }
+            }
+        });
+        kernel_task.init();
+        // The idle task only runs for a single tick at a time
+        kernel_task.nice = NICE_MAX;
+        kernel_task.cpu_affinity = CpuMask::single(cpu_id);
+        let task_id = kernel_task.get_id();
+        // Add idle task to the ready queue
+        self.add_task(kernel_task, cpu_id);
+        let _rq = self.rq_lock[cpu_id].lock();
+        self.idle_task_id[cpu_id] = Some(task_id);
+    }
+
+    /// Highest real-time priority among the runnable tasks of `cpu_id` that
+    /// could preempt the current task
+    ///
+    /// The caller holds the runqueue lock of `cpu_id` and the pool lock.
+    fn highest_waiting_rt_priority(&mut self, cpu_id: usize) -> Option<u32> {
+        let current = self.current_task_id[cpu_id];
+        let pool = &mut self.task_pool;
+        self.ready_queue[cpu_id].iter()
+            .filter(|&&id| Some(id) != current)
+            .filter_map(|&id| {
+                let task = pool.get_task(id)?;
+                let runnable = matches!(task.state, TaskState::Ready | TaskState::Running)
+                    && task.cpu_affinity.contains(cpu_id);
+                (task.sched_policy.is_realtime() && runnable).then_some(task.rt_priority)
+            })
+            .max()
+    }
+
+    /// Time slice of the normal task `task_id` dispatched on `cpu_id`
+    ///
+    /// The slice for its nice value, scaled by the CPU shares of its
+    /// resource group and shared among the group's runnable tasks on the
+    /// CPU (see [`crate::task::cgroup::scale_time_slice`]).
+    fn dispatch_time_slice(&mut self, cpu_id: usize, task_id: usize) -> u32 {
+        let _pool = self.pool_lock.lock();
+        let pool = &mut self.task_pool;
+        let Some(task) = pool.get_task(task_id) else { return 1 };
+        let (group, slice) = (task.cgroup, time_slice_for_nice(task.nice));
+        if group == ROOT_GROUP {
+            return slice;
+        }
+        // The dispatched task is off the runqueue
+        let runnable = 1 + self.ready_queue[cpu_id].iter()
+            .filter(|&&id| id != task_id)
+            .filter(|&&id| pool.get_task(id).is_some_and(|task| {
+                task.cgroup == group && matches!(task.state, TaskState::Ready | TaskState::Running)
+            }))
+            .count();
+        scale_time_slice(group, slice, runnable)
+    }
+
+    /// Dispatch the highest priority runnable real-time task of `cpu_id`
+    ///
+    /// Among tasks of the same priority the current task keeps the CPU if it
+    /// is FIFO or has time slice left; otherwise the one that has waited
+    /// longest runs. The caller holds the runqueue lock of `cpu_id`.
+    ///
+    /// # Returns
+    /// The ID of the dispatched task, or `None` if no real-time task is
+    /// runnable
+    fn pick_rt_task(&mut self, cpu_id: usize) -> Option<usize> {
+        let _pool = self.pool_lock.lock();
+        let current = self.current_task_id[cpu_id];
+        let pool = &mut self.task_pool;
+        let mut best: Option<((u32, bool), usize)> = None;
+        for (pos, &id) in self.ready_queue[cpu_id].iter().enumerate() {
+            let Some(task) = pool.get_task(id) else { continue };
+            if !task.sched_policy.is_realtime() || !matches!(task.state, TaskState::Ready | TaskState::Running)
+                || !task.cpu_affinity.contains(cpu_id) {
+                continue;
+            }
+            let keeps_cpu = Some(id) == current
+                && (task.sched_policy == SchedPolicy::Fifo || task.time_slice > 0);
+            let key = (task.rt_priority, keeps_cpu);
+            // Strictly greater, so the longest waiting task wins a tie
+            if best.is_none_or(|(best_key, _)| key > best_key) {
+                best = Some((key, pos));
+            }
+        }
+
+        let ((_, keeps_cpu), pos) = best?;
+        let task_id = self.ready_queue[cpu_id].remove(pos)?;
+        self.ready_queue[cpu_id].push_back(task_id);
+        let task = self.task_pool.get_task(task_id)?;
+        task.state = TaskState::Running;
+        if !keeps_cpu {
+            task.time_slice = RR_TIME_SLICE;
+        }
+        self.current_task_id[cpu_id] = Some(task_id);
+        Some(task_id)
+    }
+
     /// Called every timer tick. Decrements the current task's time_slice.
     /// If time_slice reaches 0, triggers a reschedule.
     pub fn on_tick(&mut self, cpu_id: usize, trapframe: &mut Trapframe) {
-        if let Some(task_id) = self.get_current_task_id(cpu_id) {
-            if let Some(task) = self.task_pool.get_task(task_id) {
-                if task.time_slice > 0 {
-                    task.time_slice -= 1;
-                }
-                if task.time_slice == 0 {
-                    // Time slice expired, trigger reschedule
-                    self.schedule(trapframe);
-                }
-            }
-        } else {
+        self.balance_ticks[cpu_id] += 1;
+        if self.balance_ticks[cpu_id] >= BALANCE_INTERVAL_TICKS {
+            self.balance_ticks[cpu_id] = 0;
+            self.load_balance(cpu_id);
+        }
+
+        let mut cpu_limit_signal = None;
+        let reschedule = {
+            self.rq_lock[cpu_id].acquire();
+            self.pool_lock.acquire();
+            let current_task_id = self.current_task_id[cpu_id];
+            let realtime = current_task_id.and_then(|id| self.task_pool.get_task(id))
+                .is_some_and(|task| task.sched_policy.is_realtime());
+            self.rt_bandwidth[cpu_id].tick(realtime);
+            let throttled = self.rt_bandwidth[cpu_id].is_throttled();
+            // A waiting real-time task of higher priority preempts right away
+            let waiting_rt = if throttled { None } else { self.highest_waiting_rt_priority(cpu_id) };
+
+            let reschedule = match current_task_id {
+                Some(task_id) => match self.task_pool.get_task(task_id) {
+                    Some(task) => {
+                        cpu_limit_signal = task.check_cpu_limit().map(|sig| (task_id, sig));
+                        if task.sched_policy != SchedPolicy::Fifo && task.time_slice > 0 {
+                            task.time_slice -= 1;
+                        }
+                        let preempted = waiting_rt.is_some_and(|prio| prio > task.rt_priority)
+                            // No longer allowed on this CPU
+                            || !task.cpu_affinity.contains(cpu_id);
+                        match task.sched_policy {
+                            // FIFO tasks have no time slice
+                            SchedPolicy::Fifo => throttled || preempted,
+                            SchedPolicy::RoundRobin => throttled || preempted || task.time_slice == 0,
+                            // Time slice expired, trigger reschedule
+                            SchedPolicy::Normal => preempted || task.time_slice == 0,
+                        }
+                    }
+                    None => false,
+                },
+                None => true,
+            };
+            self.pool_lock.release();
+            self.rq_lock[cpu_id].release();
+            reschedule
+        };
+        // Sent without the locks, which sending takes itself
+        if let Some((task_id, sig)) = cpu_limit_signal {
+            let _ = crate::task::signal::send_signal(task_id, sig);
+        }
+        if reschedule {
             self.schedule(trapframe);
         }
     }
@@ -298,7 +732,16 @@ impl Scheduler {
         let cpu = get_cpu();
         let cpu_id = cpu.get_cpuid();
 
+        // Step 0: A CPU with nothing to run pulls work from a busy one
+        if self.nr_running(cpu_id) == 0 {
+            self.load_balance(cpu_id);
+        }
+
         // Step 1: Run scheduling algorithm to get current and next task IDs
+        // The runqueue stays locked until the context switch is complete (see
+        // `finish_task_switch()`), so that no other CPU takes the outgoing
+        // task before its context is saved.
+        self.rq_lock[cpu_id].acquire();
         let (current_task_id, next_task_id) = self.run(cpu);
 
         // Debug output for monitoring scheduler behavior
@@ -320,32 +763,83 @@ impl Scheduler {
 
             // Store current task's user state to VCPU
             if let Some(current_task_id) = current_task_id {
+                let now = get_time_us();
                 let current_task = self.get_task_by_id(current_task_id).unwrap();
                 current_task.vcpu.store(trapframe);
+                current_task.cpu_times.switch_out(now);
+                let preempted = matches!(current_task.state, TaskState::Ready | TaskState::Running);
+                current_task.sched_stats.deschedule(now, preempted);
+                let next_task = self.get_task_by_id(next_task_id).unwrap();
+                next_task.cpu_times.switch_in(now);
+                next_task.sched_stats.dispatch(now);
+                self.cpu_stats[cpu_id].switch_to(now, self.idle_task_id[cpu_id] == Some(next_task_id));
 
                 // Perform kernel context switch
                 self.kernel_context_switch(cpu_id, current_task_id, next_task_id);
-                // NOTE: After this point, the current task will not execute until it is scheduled again
+                // NOTE: After this point, the current task will not execute until it is scheduled again,
+                // possibly on another CPU
+                self.finish_task_switch();
 
                 // Restore trapframe of same task
                 let current_task = self.get_task_by_id(current_task_id).unwrap();
                 Self::setup_task_execution(get_cpu(), current_task);
             } else {            // No current task (e.g., first scheduling), just switch to next task
+                // Nothing is switched out, so the runqueue can be unlocked right away
+                self.finish_task_switch();
+                let now = get_time_us();
+                self.cpu_stats[cpu_id].switch_to(now, self.idle_task_id[cpu_id] == Some(next_task_id));
                 let next_task = self.get_task_by_id(next_task_id).unwrap();
+                next_task.cpu_times.switch_in(now);
+                next_task.sched_stats.dispatch(now);
                 // crate::println!("[SCHED] Setting up task {} for execution", next_task_id);
                 Self::setup_task_execution(get_cpu(), next_task);
                 arch_switch_to_user_space(next_task.get_trapframe()); // Force switch to user space
             }
+        } else {
+            self.finish_task_switch();
         }
 
         // Step 3: Setup task execution and process events (after context switch)
-        if let Some(current_task) = self.get_current_task(cpu_id) {
+        if let Some(current_task) = self.get_current_task(get_cpu().get_cpuid()) {
             // Process pending events before dispatching task
             let _ = current_task.process_pending_events();
         }
         // Schedule returns - trap handler will call arch_switch_to_user_space()
     }
 
+    /// Complete a pass of `schedule()` on the current CPU
+    ///
+    /// Unlocks the runqueue locked for the context switch (possibly by the
+    /// task switched away from), moves tasks that are not allowed on this
+    /// CPU to one they are allowed on and wakes the waiters of tasks that
+    /// exited meanwhile. A task running for the first time does not return into
+    /// `schedule()`; it calls this from `task_initial_kernel_entrypoint()`.
+    pub fn finish_task_switch(&mut self) {
+        let cpu_id = get_cpu().get_cpuid();
+        let exited = core::mem::take(&mut self.exited_tasks[cpu_id]);
+        let misplaced = core::mem::take(&mut self.misplaced_tasks[cpu_id]);
+        self.rq_lock[cpu_id].release_all();
+
+        for task_id in misplaced {
+            let Some(affinity) = self.get_task_by_id(task_id).map(|task| task.cpu_affinity) else {
+                continue;
+            };
+            let dst_cpu = self.select_cpu(affinity);
+            if self.migrate_task(task_id, cpu_id, dst_cpu) {
+                self.kick_cpu(dst_cpu);
+            }
+        }
+
+        for (task_id, parent_id) in exited {
+            // Wake up any processes waiting for this specific task
+            wake_task_waiters(task_id);
+            // Also wake up parent process for waitpid(-1)
+            if let Some(parent_id) = parent_id {
+                wake_parent_waiters(parent_id);
+            }
+        }
+    }
+
 
     /* MUST NOT raise any exception in this function before the idle loop */
     pub fn start_scheduler(&mut self) {
@@ -361,6 +855,12 @@ impl Scheduler {
         cpu.set_trap_handler(get_user_trap_handler());
         cpu.set_next_address_space(get_kernel_vm_manager().get_asid());
 
+        /* Every CPU starts with its own idle task */
+        self.spawn_idle_task(cpu_id);
+        CPU_ONLINE[cpu_id].store(true, Ordering::Release);
+        /* Share the device interrupts with this CPU */
+        crate::interrupt::InterruptManager::with_manager(|mgr| mgr.rebalance_external_interrupts());
+
         /* Jump to trap handler immediately */
         timer.set_interval_us(cpu_id, 0);
         enable_interrupt();
@@ -369,6 +869,7 @@ impl Scheduler {
     }
 
     pub fn get_current_task(&mut self, cpu_id: usize) -> Option<&mut Task> {
+        let _pool = self.pool_lock.lock();
         match self.current_task_id[cpu_id] {
             Some(task_id) => self.task_pool.get_task(task_id),
             None => None
@@ -379,6 +880,20 @@ impl Scheduler {
         self.current_task_id[cpu_id]
     }
 
+    /// Busy and idle time of `cpu_id`, `None` if it has not come online
+    pub fn cpu_sched_stats(&mut self, cpu_id: usize) -> Option<CpuSchedStats> {
+        if !CPU_ONLINE[cpu_id].load(Ordering::Acquire) {
+            return None;
+        }
+        let _rq = self.rq_lock[cpu_id].lock();
+        Some(self.cpu_stats[cpu_id])
+    }
+
+    /// Whether the task is the current task of some CPU
+    pub fn is_running(&self, task_id: usize) -> bool {
+        self.current_task_id.contains(&Some(task_id))
+    }
+
     /// Returns a mutable reference to the task with the specified ID, if found.
     /// 
     /// This method searches the TaskPool to find the task with the specified ID.
@@ -390,6 +905,7 @@ impl Scheduler {
     /// # Returns
     /// A mutable reference to the task if found, or None otherwise.
     pub fn get_task_by_id(&mut self, task_id: usize) -> Option<&mut Task> {
+        let _pool = self.pool_lock.lock();
         self.task_pool.get_task(task_id)
     }
 
@@ -403,35 +919,59 @@ impl Scheduler {
     /// # Returns
     /// true if the task was found and moved, false otherwise
     pub fn wake_task(&mut self, task_id: usize) -> bool {
-        // Search for the task in blocked queues
-        for cpu_id in 0..self.blocked_queue.len() {
-            if let Some(pos) = self.blocked_queue[cpu_id].iter().position(|&id| id == task_id) {
-                // Remove from blocked queue
-                self.blocked_queue[cpu_id].remove(pos);
-                
-                // Get task from TaskPool and set state to Running
-                if let Some(task) = self.task_pool.get_task(task_id) {
-                    task.state = TaskState::Running;
-                    // Move to ready queue
-                    self.ready_queue[cpu_id].push_back(task_id);
-                    return true;
+        // A blocked task is never migrated, so it stays in the queues of one
+        // CPU while they are searched one after another
+        for cpu_id in 0..NUM_OF_CPUS {
+            self.rq_lock[cpu_id].acquire();
+            let woken = self.wake_task_on(cpu_id, task_id);
+            self.rq_lock[cpu_id].release();
+            if let Some(woken) = woken {
+                if woken {
+                    self.kick_cpu(cpu_id);
                 }
+                return woken;
             }
         }
-        // Not found in blocked queues. This can happen if a wake occurs between
+        false
+    }
+
+    /// Wake `task_id` if it belongs to `cpu_id`
+    ///
+    /// The caller holds the runqueue lock of `cpu_id`.
+    ///
+    /// # Returns
+    /// `None` if the task is not queued on `cpu_id`, otherwise whether it was
+    /// woken up
+    fn wake_task_on(&mut self, cpu_id: usize, task_id: usize) -> Option<bool> {
+        let _pool = self.pool_lock.lock();
+        if let Some(pos) = self.blocked_queue[cpu_id].iter().position(|&id| id == task_id) {
+            // Remove from blocked queue
+            self.blocked_queue[cpu_id].remove(pos);
+
+            // Get task from TaskPool and set state to Running
+            let task = self.task_pool.get_task(task_id)?;
+            task.state = TaskState::Running;
+            task.sched_stats.enqueue(get_time_us());
+            // Move to ready queue
+            self.ready_queue[cpu_id].push_back(task_id);
+            return Some(true);
+        }
+        if !self.ready_queue[cpu_id].contains(&task_id) {
+            return None;
+        }
+        // Not in the blocked queue. This can happen if a wake occurs between
         // a task marking itself Blocked and the scheduler moving it to the
         // blocked_queue. In that case, ensure the task state is set back to
         // Running so that the scheduler does not park it.
-        if let Some(task) = self.task_pool.get_task(task_id) {
-            if let TaskState::Blocked(_) = task.state {
-                task.state = TaskState::Running;
-                // Do not enqueue here to avoid duplicating entries: the task is
-                // still present in the ready_queue (or is current) and will be
-                // handled as Running by the scheduler.
-                return true;
-            }
+        let task = self.task_pool.get_task(task_id)?;
+        if let TaskState::Blocked(_) = task.state {
+            task.state = TaskState::Running;
+            // Do not enqueue here to avoid duplicating entries: the task is
+            // still present in the ready_queue (or is current) and will be
+            // handled as Running by the scheduler.
+            return Some(true);
         }
-        false
+        Some(false)
     }
 
     /// Get IDs of all tasks across ready, blocked, and zombie queues
@@ -441,25 +981,35 @@ impl Scheduler {
     /// reference to the scheduler during delivery.
     pub fn get_all_task_ids(&self) -> alloc::vec::Vec<usize> {
         let mut ids = alloc::vec::Vec::new();
-        // Ready tasks
-        for q in &self.ready_queue {
-            for t in q.iter() {
-                ids.push(*t);
-            }
-        }
-        // Blocked tasks
-        for q in &self.blocked_queue {
-            for t in q.iter() {
-                ids.push(*t);
-            }
+        for cpu_id in 0..NUM_OF_CPUS {
+            let _rq = self.rq_lock[cpu_id].lock();
+            // Ready tasks
+            ids.extend(self.ready_queue[cpu_id].iter());
+            // Blocked tasks
+            ids.extend(self.blocked_queue[cpu_id].iter());
+            // Zombie tasks
+            ids.extend(self.zombie_queue[cpu_id].iter());
         }
-        // Zombie tasks
-        for q in &self.zombie_queue {
-            for t in q.iter() {
-                ids.push(*t);
+        ids
+    }
+
+    /// Call `f` on every task in the ready, blocked and zombie queues
+    ///
+    /// Unlike [`Scheduler::get_all_task_ids`] this does not allocate, so the
+    /// OOM killer can use it when the heap is exhausted. The queues are
+    /// walked in place with the runqueue of each CPU locked.
+    pub fn for_each_task(&mut self, mut f: impl FnMut(&mut Task)) {
+        let Scheduler { task_pool, pool_lock, ready_queue, blocked_queue, zombie_queue, rq_lock, .. } = self;
+        for cpu_id in 0..NUM_OF_CPUS {
+            let _rq = rq_lock[cpu_id].lock();
+            let _pool = pool_lock.lock();
+            let queued = ready_queue[cpu_id].iter().chain(&blocked_queue[cpu_id]).chain(&zombie_queue[cpu_id]);
+            for &task_id in queued {
+                if let Some(task) = task_pool.get_task(task_id) {
+                    f(task);
+                }
             }
         }
-        ids
     }
 
     /// Perform kernel context switch between tasks
@@ -478,11 +1028,14 @@ impl Scheduler {
             let mut from_ctx_ptr: *mut crate::arch::KernelContext = core::ptr::null_mut();
             let mut to_ctx_ptr: *const crate::arch::KernelContext = core::ptr::null();
             
-            if let Some(from_task) = self.task_pool.get_task(from_task_id) {
-                from_ctx_ptr = &mut from_task.kernel_context
-            }
-            if let Some(to_task) = self.task_pool.get_task(to_task_id) {
-                to_ctx_ptr = &to_task.kernel_context
+            {
+                let _pool = self.pool_lock.lock();
+                if let Some(from_task) = self.task_pool.get_task(from_task_id) {
+                    from_ctx_ptr = &mut from_task.kernel_context
+                }
+                if let Some(to_task) = self.task_pool.get_task(to_task_id) {
+                    to_ctx_ptr = &to_task.kernel_context
+                }
             }
             
             if !from_ctx_ptr.is_null() && !to_ctx_ptr.is_null() {
@@ -522,8 +1075,9 @@ impl Scheduler {
         }
 
         cpu.set_trap_handler(get_user_trap_handler());
-        cpu.set_next_address_space(task.vm_manager.get_asid());
+        cpu.set_next_address_space(task.vm_manager.read().get_asid());
         set_next_mode(task.vcpu.get_mode());
+        set_user_xlen(task.vcpu.get_xlen());
         // Setup trap vector
         set_trapvector(get_trampoline_trap_vector());
 
@@ -607,10 +1161,52 @@ pub fn make_test_tasks() {
 
 #[cfg(test)]
 mod tests {
+    use alloc::format;
+
     use crate::task::TaskType;
 
     use super::*;
 
+    #[test_case]
+    fn test_pull_task_migrates_waiting_task() {
+        let mut scheduler = Scheduler::new();
+        let mut ids = Vec::new();
+        for i in 0..3 {
+            let mut task = Task::new(format!("Balance{}", i), 1, TaskType::Kernel);
+            task.set_state(TaskState::Ready);
+            ids.push(task.get_id());
+            scheduler.add_task(task, 0);
+        }
+        scheduler.current_task_id[0] = Some(ids[0]);
+
+        // The running task stays, the longest waiting one moves
+        assert_eq!(scheduler.pull_task(0, 1), Some(ids[1]));
+        assert_eq!(scheduler.nr_running(0), 2);
+        assert_eq!(scheduler.ready_queue[1].front(), Some(&ids[1]));
+        assert_eq!(scheduler.pull_task(0, 1), Some(ids[2]));
+        assert_eq!(scheduler.pull_task(0, 1), None);
+    }
+
+    #[test_case]
+    fn test_migration_honours_affinity() {
+        let mut scheduler = Scheduler::new();
+        let mut pinned = Task::new("Pinned".to_string(), 1, TaskType::Kernel);
+        pinned.set_state(TaskState::Ready);
+        pinned.set_affinity(CpuMask::single(0)).unwrap();
+        let pinned_id = pinned.get_id();
+        scheduler.add_task(pinned, 0);
+
+        // A pinned task is never pulled to another CPU
+        assert_eq!(scheduler.pull_task(0, 1), None);
+        assert_eq!(scheduler.select_cpu(CpuMask::single(1)), 1);
+
+        // Once allowed elsewhere, the misplaced task is moved explicitly
+        scheduler.get_task_by_id(pinned_id).unwrap().set_affinity(CpuMask::single(1)).unwrap();
+        assert!(scheduler.migrate_task(pinned_id, 0, 1));
+        assert_eq!(scheduler.ready_queue[1].front(), Some(&pinned_id));
+        assert!(!scheduler.migrate_task(pinned_id, 0, 1));
+    }
+
     #[test_case]
     fn test_add_task() {
         let mut scheduler = Scheduler::new();
@@ -0,0 +1,222 @@
+//! Scheduler statistics
+//!
+//! Every task keeps [`TaskSchedStats`]: how long it ran, how long it sat in
+//! a runqueue ready to run before it was dispatched (its scheduling
+//! latency), and how often it gave up the CPU by blocking (voluntary) or
+//! was preempted while still runnable (involuntary). Every CPU keeps
+//! [`CpuSchedStats`]: the time it spent running tasks and running its idle
+//! task, and the number of context switches.
+//!
+//! The scheduler updates them on every context switch and wakeup; all
+//! times are in microseconds of the kernel clock. They are published in
+//! procfs as `/proc/schedstat` (per CPU) and `/proc/<pid>/sched`.
+
+extern crate alloc;
+
+use alloc::{format, string::String};
+
+use crate::environment::NUM_OF_CPUS;
+use crate::sched::scheduler::get_scheduler;
+use crate::timer::get_time_us;
+
+/// Run and wait times and context switches of a task
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TaskSchedStats {
+    /// Time spent on a CPU
+    pub run_us: u64,
+    /// Time spent runnable in a runqueue
+    pub wait_us: u64,
+    /// Longest single wait in a runqueue
+    pub max_wait_us: u64,
+    /// Number of times the task was dispatched
+    pub dispatches: u64,
+    /// Switches away from the task because it blocked or exited
+    pub voluntary_switches: u64,
+    /// Switches away from the task while it was still runnable
+    pub involuntary_switches: u64,
+    /// Since when the task waits in a runqueue, if it does
+    ready_since_us: Option<u64>,
+    /// Since when the task runs, if it does
+    running_since_us: Option<u64>,
+}
+
+impl TaskSchedStats {
+    pub const fn new() -> Self {
+        TaskSchedStats {
+            run_us: 0,
+            wait_us: 0,
+            max_wait_us: 0,
+            dispatches: 0,
+            voluntary_switches: 0,
+            involuntary_switches: 0,
+            ready_since_us: None,
+            running_since_us: None,
+        }
+    }
+
+    /// The task was queued to run (created or woken up)
+    pub fn enqueue(&mut self, now_us: u64) {
+        if self.ready_since_us.is_none() && self.running_since_us.is_none() {
+            self.ready_since_us = Some(now_us);
+        }
+    }
+
+    /// The task was switched onto a CPU
+    pub fn dispatch(&mut self, now_us: u64) {
+        if let Some(since) = self.ready_since_us.take() {
+            let wait = now_us.saturating_sub(since);
+            self.wait_us += wait;
+            self.max_wait_us = self.max_wait_us.max(wait);
+        }
+        self.dispatches += 1;
+        self.running_since_us = Some(now_us);
+    }
+
+    /// The task was switched out; `runnable` if it was preempted
+    pub fn deschedule(&mut self, now_us: u64, runnable: bool) {
+        if let Some(since) = self.running_since_us.take() {
+            self.run_us += now_us.saturating_sub(since);
+        }
+        if runnable {
+            self.involuntary_switches += 1;
+            self.ready_since_us = Some(now_us);
+        } else {
+            self.voluntary_switches += 1;
+        }
+    }
+
+    /// Run and wait time including the interval in progress at `now_us`
+    pub fn current(&self, now_us: u64) -> (u64, u64) {
+        let running = self.running_since_us.map_or(0, |since| now_us.saturating_sub(since));
+        let waiting = self.ready_since_us.map_or(0, |since| now_us.saturating_sub(since));
+        (self.run_us + running, self.wait_us + waiting)
+    }
+}
+
+/// Busy and idle time and context switches of a CPU
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CpuSchedStats {
+    /// Time spent running tasks other than the idle task
+    pub busy_us: u64,
+    /// Time spent running the idle task
+    pub idle_us: u64,
+    /// Number of context switches
+    pub context_switches: u64,
+    /// Clock reading the times are charged up to, 0 before the first task
+    last_us: u64,
+    /// Whether the idle task runs since `last_us`
+    in_idle: bool,
+}
+
+impl CpuSchedStats {
+    pub const fn new() -> Self {
+        CpuSchedStats { busy_us: 0, idle_us: 0, context_switches: 0, last_us: 0, in_idle: false }
+    }
+
+    /// Charge the time up to `now_us` to what the CPU was running
+    fn charge(&mut self, now_us: u64) {
+        if self.last_us != 0 {
+            let elapsed = now_us.saturating_sub(self.last_us);
+            if self.in_idle {
+                self.idle_us += elapsed;
+            } else {
+                self.busy_us += elapsed;
+            }
+        }
+        self.last_us = now_us.max(self.last_us);
+    }
+
+    /// The CPU switches to a task; `idle` if it is the idle task
+    pub fn switch_to(&mut self, now_us: u64, idle: bool) {
+        if self.last_us != 0 {
+            self.context_switches += 1;
+        }
+        self.charge(now_us);
+        self.in_idle = idle;
+    }
+
+    /// Busy and idle time including the interval in progress at `now_us`
+    pub fn current(&self, now_us: u64) -> (u64, u64) {
+        let mut stats = *self;
+        stats.charge(now_us);
+        (stats.busy_us, stats.idle_us)
+    }
+
+    /// Share of the time the CPU was busy, in tenths of a percent
+    pub fn utilization_permille(&self, now_us: u64) -> u64 {
+        let (busy, idle) = self.current(now_us);
+        (busy * 1000).checked_div(busy + idle).unwrap_or(0)
+    }
+}
+
+/// Render `/proc/schedstat`: one line per online CPU
+pub fn format_schedstat() -> String {
+    let now = get_time_us();
+    let mut out = String::from("cpu busy_us idle_us utilization context_switches\n");
+    let scheduler = get_scheduler();
+    for cpu_id in 0..NUM_OF_CPUS {
+        let Some(stats) = scheduler.cpu_sched_stats(cpu_id) else {
+            continue;
+        };
+        let (busy, idle) = stats.current(now);
+        let permille = stats.utilization_permille(now);
+        out += &format!(
+            "cpu{} {} {} {}.{}% {}\n",
+            cpu_id, busy, idle, permille / 10, permille % 10, stats.context_switches
+        );
+    }
+    out
+}
+
+/// Render `/proc/<pid>/sched`, `None` if the task is gone
+pub fn format_task_sched(pid: usize) -> Option<String> {
+    let task = get_scheduler().get_task_by_id(pid)?;
+    let stats = task.sched_stats;
+    let (run_us, wait_us) = stats.current(get_time_us());
+    Some(format!(
+        "{} ({})\n\
+         run_us: {}\n\
+         wait_us: {}\n\
+         max_wait_us: {}\n\
+         dispatches: {}\n\
+         voluntary_switches: {}\n\
+         involuntary_switches: {}\n",
+        pid, task.name, run_us, wait_us, stats.max_wait_us, stats.dispatches,
+        stats.voluntary_switches, stats.involuntary_switches,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_task_sched_stats() {
+        let mut stats = TaskSchedStats::new();
+        stats.enqueue(100);
+        stats.dispatch(130);
+        // Preempted after running for 70us
+        stats.deschedule(200, true);
+        stats.dispatch(260);
+        // Blocks; off the runqueue until woken
+        stats.deschedule(300, false);
+        stats.enqueue(1000);
+        stats.dispatch(1010);
+
+        assert_eq!((stats.run_us, stats.wait_us, stats.max_wait_us), (110, 100, 60));
+        assert_eq!((stats.voluntary_switches, stats.involuntary_switches), (1, 1));
+        assert_eq!(stats.dispatches, 3);
+        assert_eq!(stats.current(1050), (150, 100));
+    }
+
+    #[test_case]
+    fn test_cpu_sched_stats() {
+        let mut stats = CpuSchedStats::new();
+        stats.switch_to(1000, false);
+        stats.switch_to(1300, true);
+        stats.switch_to(1400, false);
+        assert_eq!(stats.current(1500), (400, 100));
+        assert_eq!(stats.context_switches, 2);
+        assert_eq!(stats.utilization_permille(1500), 800);
+    }
+}
@@ -4,5 +4,7 @@
 //! including the Waker mechanism for asynchronous task waiting and waking.
 
 pub mod waker;
+pub mod task_shared;
 
 pub use waker::Waker;
+pub use task_shared::TaskShared;
@@ -0,0 +1,68 @@
+//! State shared between the threads of a process
+//!
+//! `TaskShared<T>` holds a piece of task state (the address space, the
+//! handle table, ...) that may be shared by several tasks. Cloning it with
+//! [`TaskShared::share`] gives another task access to the same value; a task
+//! that wants its own copy uses [`TaskShared::new`] with a clone of the value.
+//! The value is dropped when the last task using it goes away.
+//!
+//! The threads of a process run on any CPU, and other parts of the kernel
+//! (the OOM killer, cgroups, ...) look at the state of tasks that are not
+//! their own, so the value is behind a read-write lock. The lock is a
+//! spinlock: a guard must not be held across anything that blocks or
+//! reschedules. Take what is needed out of it first, e.g. clone a
+//! `KernelObject` out of the handle table before reading from it.
+
+extern crate alloc;
+
+use core::fmt;
+
+use alloc::sync::Arc;
+use spin::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+pub struct TaskShared<T> {
+    inner: Arc<RwLock<T>>,
+}
+
+impl<T> TaskShared<T> {
+    /// Wrap a value used by a single task for now
+    pub fn new(value: T) -> Self {
+        Self { inner: Arc::new(RwLock::new(value)) }
+    }
+
+    /// Give another task access to the same value
+    pub fn share(&self) -> Self {
+        Self { inner: self.inner.clone() }
+    }
+
+    /// Whether other tasks use the value too
+    pub fn is_shared(&self) -> bool {
+        Arc::strong_count(&self.inner) > 1
+    }
+
+    /// Whether `self` and `other` are the same value
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.inner, &other.inner)
+    }
+
+    /// Lock the value for reading
+    pub fn read(&self) -> RwLockReadGuard<'_, T> {
+        self.inner.read()
+    }
+
+    /// Lock the value for reading unless a writer holds it
+    pub fn try_read(&self) -> Option<RwLockReadGuard<'_, T>> {
+        self.inner.try_read()
+    }
+
+    /// Lock the value for writing
+    pub fn write(&self) -> RwLockWriteGuard<'_, T> {
+        self.inner.write()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for TaskShared<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.read().fmt(f)
+    }
+}
@@ -118,6 +118,13 @@ impl Waker {
         if let Some(task) = get_scheduler().get_task_by_id(task_id) {
             // Set task state to blocked
             task.set_state(TaskState::Blocked(self.block_type));
+            // A signal that arrived before the task was marked blocked did
+            // not wake it; an interruptible wait ends right away instead
+            if self.block_type == BlockedType::Interruptible && task.signals.has_pending() {
+                self.wait_queue.lock().retain(|&id| id != task_id);
+                task.set_state(TaskState::Running);
+                return;
+            }
         } else {
             panic!("[WAKER] Task ID {} not found in scheduler", task_id);
         }
@@ -129,8 +136,55 @@ impl Waker {
         // crate::println!("[WAKER] Task {} woken up from waker '{}'", task_id, self.name);
     }
 
+    /// Block the current task until woken, unless `done` already holds
+    ///
+    /// Unlike checking a condition before calling `wait()`, this cannot miss
+    /// a wakeup: `done` is evaluated after the task is queued and marked
+    /// blocked, so a waker that makes it true and then calls `wake_one()` or
+    /// `wake_all()` either is seen by the check or finds the task waiting.
+    ///
+    /// # Returns
+    ///
+    /// `true` if `done` already held and the task did not block
+    pub fn wait_unless(&self, task_id: usize, trapframe: &mut Trapframe, done: impl Fn() -> bool) -> bool {
+        self.wait_queue.lock().push_back(task_id);
+        match get_scheduler().get_task_by_id(task_id) {
+            Some(task) => task.set_state(TaskState::Blocked(self.block_type)),
+            None => panic!("[WAKER] Task ID {} not found in scheduler", task_id),
+        }
+
+        if done() {
+            self.wait_queue.lock().retain(|&id| id != task_id);
+            if let Some(task) = get_scheduler().get_task_by_id(task_id) {
+                task.set_state(TaskState::Running);
+            }
+            return true;
+        }
+
+        // Yield CPU to scheduler - this will return when the task is woken up
+        get_scheduler().schedule(trapframe);
+        false
+    }
+
+    /// Queue a task to be woken without blocking it
+    ///
+    /// A task waiting on several wakers at once registers with each of them,
+    /// blocks itself and calls `unregister()` on all of them once it runs
+    /// again (see `crate::object::capability::poll`).
+    pub fn register(&self, task_id: usize) {
+        let mut queue = self.wait_queue.lock();
+        if !queue.contains(&task_id) {
+            queue.push_back(task_id);
+        }
+    }
+
+    /// Remove a task queued with `register()` that was not woken
+    pub fn unregister(&self, task_id: usize) {
+        self.wait_queue.lock().retain(|&id| id != task_id);
+    }
+
     /// Wake up one waiting task
-    /// 
+    ///
     /// This method removes one task from the wait queue and moves it from
     /// the blocked queue to the ready queue, making it eligible for scheduling again.
     /// 
@@ -414,6 +468,17 @@ mod tests {
         assert!(waker.is_empty());
     }
 
+    #[test_case]
+    fn test_register_unregister() {
+        let waker = Waker::new_interruptible("register_test");
+        waker.register(7);
+        waker.register(7);
+        assert_eq!(waker.waiting_count(), 1);
+        assert!(waker.is_task_waiting(7));
+        waker.unregister(7);
+        assert!(waker.is_empty());
+    }
+
     #[test_case]
     fn test_waker_stats_debug() {
         let waker = Waker::new_interruptible("stats_test");
@@ -23,15 +23,29 @@
 //! ## Current Implementation Status
 //! 
 //! ### Process Management (1-99)
-//! - Exit (1), Clone (2), Execve (3), ExecveABI (4), Waitpid (5)
-//! - Getpid (7), Getppid (8), Brk (12), Sbrk (13)
+//! - Exit (1), Clone (2), Execve (3), ExecveABI (4), Waitpid (5), Kill (6)
+//! - Getpid (7), Getppid (8), Brk (12), Sbrk (13), GetRlimit (14), SetRlimit (15)
 //! - Basic I/O: Putchar (16), Getchar (17)
+//! - Sleep (20), GetPriority (21), SetPriority (22)
+//! - SchedSetScheduler (23), SchedGetScheduler (24), SchedGetParam (25)
+//! - SchedSetAffinity (26), SchedGetAffinity (27)
+//! - SigAction (28), SigProcMask (29), SigPending (30), SigReturn (31)
+//! - SetPgid (32), GetPgid (33), SetSid (34), GetSid (35)
+//! - GetRusage (36), Times (37), Spawn (38)
+//! - SetHostname (39), GetHostname (40), Uname (41)
+//! - GetUid (42), GetEuid (43), GetGid (44), GetEgid (45), SetUid (46), SetGid (47)
+//! - SetReuid (48), SetRegid (49), SetResuid (50), SetResgid (51)
+//! - GetResuid (52), GetResgid (53), GetGroups (54), SetGroups (55)
+//! - ProcessOpen (56), ProcessSignal (57), ClockGetTime (58), GetRandom (59)
+//! - ClockGetRealtime (60), ClockSetRealtime (61)
 //! 
 //! ### Handle Management (100-199)
 //! - HandleQuery (100), HandleSetRole (101), HandleClose (102), HandleDuplicate (103)
+//! - HandleControl (110), HandlePoll (111)
 //! 
 //! ### StreamOps Capability (200-299)
 //! - StreamRead (200), StreamWrite (201)
+//! - Submission Rings: Create (210), Enter (211)
 //! 
 //! ### FileObject Capability (300-399)
 //! - FileSeek (300), FileTruncate (301), FileMetadata (302)
@@ -46,9 +60,18 @@
 //! - Pipe (600)
 //! - Event Channels: Subscribe (610), Unsubscribe (611), Publish (612)
 //! - Process Groups: Join (620), Leave (621), Send (622)
+//! - Shared Memory: Open (630), Unlink (631), SetSize (632), GetSize (633)
+//! - Memory Files: Create (634), AddSeals (635), GetSeals (636)
+//! - Futex: Futex (640)
+//! - Epoll: Create (650), Control (651), Wait (652)
+//! - Event Counters: Create (660)
+//! - Timers: Create (670), Set (671), Get (672)
+//! - Sockets: Create (680), Pair (681), Bind (682), Listen (683), Connect (684), Accept (685), Send (686), Receive (687), Shutdown (688), GetOption (689), SetOption (696)
+//! - Semaphores: Open (690), Unlink (691), Wait (692), TryWait (693), Post (694), GetValue (695)
+//! - Network Configuration: Open (697)
 //! 
 //! ### Memory Mapping Operations (700-799)
-//! - MemoryMap (700), MemoryUnmap (701)
+//! - MemoryMap (700), MemoryUnmap (701), MemoryProtect (702), MemoryAdvise (703)
 //! 
 //! ### Task Event Operations (800-899)  
 //! - Basic Events: Send (800), SetAction (801), Block (802)
@@ -70,12 +93,17 @@
 
 use crate::arch::Trapframe;
 use crate::fs::vfs_v2::syscall::{sys_vfs_remove, sys_vfs_open, sys_vfs_create_file, sys_vfs_create_directory, sys_vfs_change_directory, sys_fs_mount, sys_fs_umount, sys_fs_pivot_root, sys_vfs_truncate, sys_vfs_create_symlink, sys_vfs_readlink};
-use crate::task::syscall::{sys_brk, sys_clone, sys_execve, sys_execve_abi, sys_exit, sys_getchar, sys_getpid, sys_getppid, sys_putchar, sys_sbrk, sys_sleep, sys_waitpid, sys_register_abi_zone, sys_unregister_abi_zone};
-use crate::ipc::syscall::{sys_pipe, sys_event_channel_create, sys_event_subscribe, sys_event_unsubscribe, sys_event_publish, sys_event_handler_register, sys_event_send_direct};
-use crate::object::handle::syscall::{sys_handle_query, sys_handle_set_role, sys_handle_close, sys_handle_duplicate, sys_handle_control};
+use crate::task::syscall::{sys_brk, sys_clone, sys_execve, sys_execve_abi, sys_exit, sys_getchar, sys_getpgid, sys_getpid, sys_getppid, sys_getsid, sys_getpriority, sys_getrlimit, sys_getrusage, sys_kill, sys_putchar, sys_sbrk, sys_sched_getaffinity, sys_sched_getparam, sys_sched_getscheduler, sys_sched_setaffinity, sys_sched_setscheduler, sys_setpgid, sys_setpriority, sys_setrlimit, sys_setsid, sys_sigaction, sys_sigpending, sys_sigprocmask, sys_sigreturn, sys_sleep, sys_spawn, sys_times, sys_sethostname, sys_gethostname, sys_uname, sys_getuid, sys_geteuid, sys_getgid, sys_getegid, sys_setuid, sys_setgid, sys_setreuid, sys_setregid, sys_setresuid, sys_setresgid, sys_getresuid, sys_getresgid, sys_getgroups, sys_setgroups, sys_process_open, sys_process_signal, sys_clock_gettime, sys_clock_get_realtime, sys_clock_set_realtime, sys_getrandom, sys_waitpid, sys_register_abi_zone, sys_unregister_abi_zone};
+use crate::ipc::syscall::{sys_pipe, sys_pipe2, sys_pipe_set_size, sys_pipe_get_size, sys_pipe_set_nonblocking, sys_splice, sys_event_channel_create, sys_event_subscribe, sys_event_unsubscribe, sys_event_publish, sys_event_handler_register, sys_event_send_direct, sys_shm_open, sys_shm_unlink, sys_shm_set_size, sys_shm_get_size, sys_memfd_create, sys_memfd_add_seals, sys_memfd_get_seals, sys_futex, sys_eventfd_create, sys_socket_create, sys_socket_pair, sys_socket_bind, sys_socket_listen, sys_socket_connect, sys_socket_accept, sys_socket_send, sys_socket_receive, sys_socket_shutdown, sys_socket_get_option, sys_socket_set_option, sys_sem_open, sys_sem_unlink, sys_sem_wait, sys_sem_try_wait, sys_sem_post, sys_sem_get_value, sys_bus_connect, sys_bus_subscribe, sys_bus_unsubscribe, sys_bus_register, sys_bus_publish, sys_bus_request, sys_bus_reply, sys_bus_receive, sys_bus_wait_reply};
+use crate::object::handle::syscall::{sys_handle_query, sys_handle_set_role, sys_handle_close, sys_handle_duplicate, sys_handle_control, sys_handle_poll};
+use crate::object::epoll::syscall::{sys_epoll_create, sys_epoll_control, sys_epoll_wait};
+use crate::network::syscall::sys_netconfig_open;
+use crate::device::syscall::sys_device_events_open;
+use crate::object::ring::syscall::{sys_ring_create, sys_ring_enter};
+use crate::object::timerfd::syscall::{sys_timerfd_create, sys_timerfd_set, sys_timerfd_get};
 use crate::object::capability::stream::{sys_stream_read, sys_stream_write};
 use crate::object::capability::file::{sys_file_seek, sys_file_truncate};
-use crate::object::capability::memory_mapping::{sys_memory_map, sys_memory_unmap};
+use crate::object::capability::memory_mapping::{sys_memory_map, sys_memory_unmap, sys_memory_protect, sys_memory_advise};
 
 #[macro_use]
 mod macros;
@@ -102,24 +130,64 @@ syscall_table! {
     Invalid = 0 => |_: &mut Trapframe| {
         0
     },
-    Exit = 1 => sys_exit,
+    Exit = 1 (Int) -> Int => sys_exit,
     Clone = 2 => sys_clone,
-    Execve = 3 => sys_execve,
-    ExecveABI = 4 => sys_execve_abi,
-    Waitpid = 5 => sys_waitpid,
-    Kill = 6 => |_: &mut Trapframe| {
-        // Kill syscall is not implemented yet
-        usize::MAX // -1
-    },
+    Execve = 3 (Str, StrArray, StrArray, Hex) -> Int => sys_execve,
+    ExecveABI = 4 (Str, StrArray, StrArray, Str) -> Int => sys_execve_abi,
+    Waitpid = 5 (Int, Hex, Hex) -> Int => sys_waitpid,
+    Kill = 6 (Int, Int) -> Int => sys_kill,
     Getpid = 7 => sys_getpid,
     Getppid = 8 => sys_getppid,
-    Brk = 12 => sys_brk,
-    Sbrk = 13 => sys_sbrk,
+    Brk = 12 (Hex) -> Hex => sys_brk,
+    Sbrk = 13 (Int) -> Hex => sys_sbrk,
+    GetRlimit = 14 => sys_getrlimit,
+    SetRlimit = 15 => sys_setrlimit,
     // BASIC I/O
     Putchar = 16 => sys_putchar,
     Getchar = 17 => sys_getchar,
 
     Sleep = 20 => sys_sleep,
+    GetPriority = 21 => sys_getpriority,
+    SetPriority = 22 => sys_setpriority,
+    SchedSetScheduler = 23 => sys_sched_setscheduler,
+    SchedGetScheduler = 24 => sys_sched_getscheduler,
+    SchedGetParam = 25 => sys_sched_getparam,
+    SchedSetAffinity = 26 => sys_sched_setaffinity,
+    SchedGetAffinity = 27 => sys_sched_getaffinity,
+    SigAction = 28 => sys_sigaction,
+    SigProcMask = 29 => sys_sigprocmask,
+    SigPending = 30 => sys_sigpending,
+    SigReturn = 31 => sys_sigreturn,
+    SetPgid = 32 => sys_setpgid,
+    GetPgid = 33 => sys_getpgid,
+    SetSid = 34 => sys_setsid,
+    GetSid = 35 => sys_getsid,
+    GetRusage = 36 => sys_getrusage,
+    Times = 37 => sys_times,
+    Spawn = 38 => sys_spawn,
+    SetHostname = 39 => sys_sethostname,
+    GetHostname = 40 => sys_gethostname,
+    Uname = 41 => sys_uname,
+    GetUid = 42 => sys_getuid,
+    GetEuid = 43 => sys_geteuid,
+    GetGid = 44 => sys_getgid,
+    GetEgid = 45 => sys_getegid,
+    SetUid = 46 => sys_setuid,
+    SetGid = 47 => sys_setgid,
+    SetReuid = 48 => sys_setreuid,
+    SetRegid = 49 => sys_setregid,
+    SetResuid = 50 => sys_setresuid,
+    SetResgid = 51 => sys_setresgid,
+    GetResuid = 52 => sys_getresuid,
+    GetResgid = 53 => sys_getresgid,
+    GetGroups = 54 => sys_getgroups,
+    SetGroups = 55 => sys_setgroups,
+    ProcessOpen = 56 => sys_process_open,
+    ProcessSignal = 57 => sys_process_signal,
+    ClockGetTime = 58 => sys_clock_gettime,
+    GetRandom = 59 (Hex, Uint, Hex) -> Int => sys_getrandom,
+    ClockGetRealtime = 60 => sys_clock_get_realtime,
+    ClockSetRealtime = 61 (Uint) -> Int => sys_clock_set_realtime,
     
     // ABI Zone Management
     RegisterAbiZone = 90 => sys_register_abi_zone,
@@ -131,23 +199,26 @@ syscall_table! {
     HandleClose = 102 => sys_handle_close,     // Close any handle (files, pipes, etc.)
     HandleDuplicate = 103 => sys_handle_duplicate, // Duplicate any handle  
     HandleControl = 110 => sys_handle_control,  // Control operations on handles (ioctl-equivalent)  
+    HandlePoll = 111 (Hex, Uint, Int) -> Int => sys_handle_poll, // Wait for events on a set of handles
     
     // === StreamOps Capability ===
     // Stream operations for any KernelObject with StreamOps capability
-    StreamRead = 200 => sys_stream_read,   // StreamOps::read
-    StreamWrite = 201 => sys_stream_write, // StreamOps::write
+    StreamRead = 200 (Int, Hex, Uint) -> Int => sys_stream_read,   // StreamOps::read
+    StreamWrite = 201 (Int, Buf, Uint) -> Int => sys_stream_write, // StreamOps::write
+    RingCreate = 210 (Uint) -> Int => sys_ring_create, // Create a submission ring
+    RingEnter = 211 (Int, Uint, Uint, Uint) -> Int => sys_ring_enter, // Submit operations and wait for completions
     
     // === FileObject Capability ===
     // File operations for any KernelObject with FileObject capability
-    FileSeek = 300 => sys_file_seek,       // FileObject::seek
+    FileSeek = 300 (Int, Int, Int) -> Int => sys_file_seek,       // FileObject::seek
     FileTruncate = 301 => sys_file_truncate, // FileObject::truncate
     // FileMetadata = 302 => sys_file_metadata, // FileObject::metadata
     
     // === VFS Operations ===
-    VfsOpen = 400 => sys_vfs_open,             // VFS file/directory open
-    VfsRemove = 401 => sys_vfs_remove,         // Remove files or directories (unified)
-    VfsCreateFile = 402 => sys_vfs_create_file, // Create regular files through VFS
-    VfsCreateDirectory = 403 => sys_vfs_create_directory, // Create directories through VFS
+    VfsOpen = 400 (Str, Hex, Hex) -> Int => sys_vfs_open,             // VFS file/directory open
+    VfsRemove = 401 (Str) -> Int => sys_vfs_remove,         // Remove files or directories (unified)
+    VfsCreateFile = 402 (Str, Hex) -> Int => sys_vfs_create_file, // Create regular files through VFS
+    VfsCreateDirectory = 403 (Str) -> Int => sys_vfs_create_directory, // Create directories through VFS
     VfsChangeDirectory = 404 => sys_vfs_change_directory, // Change current working directory
     VfsTruncate = 405 => sys_vfs_truncate,     // Truncate file by path
     VfsCreateSymlink = 406 => sys_vfs_create_symlink, // Create symbolic links through VFS
@@ -160,6 +231,11 @@ syscall_table! {
     
     // === IPC Operations ===
     Pipe = 600 => sys_pipe,                // Create pipe handles
+    Pipe2 = 601 (Hex, Hex) -> Int => sys_pipe2, // Create pipe handles with flags
+    PipeSetSize = 602 (Int, Uint) -> Uint => sys_pipe_set_size, // Change the capacity of a pipe
+    PipeGetSize = 603 (Int) -> Uint => sys_pipe_get_size, // Get the capacity of a pipe
+    PipeSetNonblocking = 604 (Int, Int) -> Int => sys_pipe_set_nonblocking, // Make a pipe end fail instead of waiting
+    Splice = 605 (Int, Int, Uint, Hex) -> Int => sys_splice, // Move data between a pipe and a stream
     
     // Event System (Handle-based, ABI-layer only)
     EventChannelCreate = 610 => sys_event_channel_create,      // Create/open event channel (ABI use)
@@ -169,10 +245,77 @@ syscall_table! {
     EventHandlerRegister = 614 => sys_event_handler_register,  // Register event filter (ABI use)
     EventSendDirect = 615 => sys_event_send_direct,            // Send direct event to task (ABI use)
 
+    // Message bus
+    BusConnect = 620 (Hex) -> Int => sys_bus_connect, // Connect to the message bus
+    BusSubscribe = 621 (Int, Str) -> Int => sys_bus_subscribe, // Receive what is published on a channel
+    BusUnsubscribe = 622 (Int, Str) -> Int => sys_bus_unsubscribe, // Stop receiving from a channel
+    BusRegister = 623 (Int, Str) -> Int => sys_bus_register, // Take a service name
+    BusPublish = 624 (Int, Str, Hex, Uint, Hex, Uint) -> Int => sys_bus_publish, // Publish data and handles on a channel
+    BusRequest = 625 (Int, Str, Hex, Uint, Hex, Uint) -> Uint => sys_bus_request, // Send a request to a service
+    BusReply = 626 (Int, Uint, Hex, Uint, Hex, Uint) -> Int => sys_bus_reply, // Answer a request
+    BusReceive = 627 (Int, Hex, Uint, Hex, Hex, Hex) -> Int => sys_bus_receive, // Receive the next message
+    BusWaitReply = 628 (Int, Uint, Hex, Uint, Hex, Hex) -> Int => sys_bus_wait_reply, // Wait for the reply to a request
+
+    // Shared Memory
+    ShmOpen = 630 => sys_shm_open,         // Create/open shared memory object
+    ShmUnlink = 631 => sys_shm_unlink,     // Remove shared memory name
+    ShmSetSize = 632 (Int, Uint) -> Int => sys_shm_set_size, // Size an empty shared memory object
+    ShmGetSize = 633 (Int) -> Uint => sys_shm_get_size, // Get the size of a shared memory object
+    MemFdCreate = 634 (Str, Hex) -> Int => sys_memfd_create, // Create an anonymous memory file
+    MemFdAddSeals = 635 (Int, Hex) -> Int => sys_memfd_add_seals, // Seal a memory file
+    MemFdGetSeals = 636 (Int) -> Hex => sys_memfd_get_seals, // Get the seals of a memory file
+
+    // Futex
+    Futex = 640 => sys_futex,              // Wait on / wake a futex word
+
+    // Epoll
+    EpollCreate = 650 => sys_epoll_create,   // Create an epoll object
+    EpollControl = 651 (Int, Int, Int, Hex) -> Int => sys_epoll_control, // Add, change or remove an object of an epoll object
+    EpollWait = 652 (Int, Hex, Uint, Int) -> Int => sys_epoll_wait, // Wait for events of an epoll object
+
+    // Event counters
+    EventFdCreate = 660 (Uint, Hex) -> Int => sys_eventfd_create, // Create a pollable event counter
+
+    // Timers
+    TimerFdCreate = 670 (Hex) -> Int => sys_timerfd_create, // Create a pollable timer
+    TimerFdSet = 671 (Int, Hex, Hex, Hex) -> Int => sys_timerfd_set, // Arm or disarm a timer
+    TimerFdGet = 672 (Int, Hex) -> Int => sys_timerfd_get, // Get the setting of a timer
+
+    // Sockets
+    SocketCreate = 680 (Uint, Uint, Hex, Uint) -> Int => sys_socket_create, // Create a socket
+    SocketPair = 681 (Uint, Hex, Hex) -> Int => sys_socket_pair, // Create two connected sockets
+    SocketBind = 682 (Int, Str) -> Int => sys_socket_bind, // Name a socket
+    SocketListen = 683 (Int, Uint) -> Int => sys_socket_listen, // Accept connections to the name of a socket
+    SocketConnect = 684 (Int, Str) -> Int => sys_socket_connect, // Connect to a named socket
+    SocketAccept = 685 (Int) -> Int => sys_socket_accept, // Take a connection to a listening socket
+    SocketSend = 686 (Int, Hex, Uint, Hex, Uint, Str) -> Int => sys_socket_send, // Send data and handles
+    SocketReceive = 687 (Int, Hex, Uint, Hex, Hex, Hex) -> Int => sys_socket_receive, // Receive data and handles
+    SocketShutdown = 688 (Int, Int) -> Int => sys_socket_shutdown, // Stop receiving or sending
+    SocketGetOption = 689 (Int, Uint) -> Int => sys_socket_get_option, // Get an option of a socket
+
+    // Semaphores
+    SemOpen = 690 (Str, Uint, Hex) -> Int => sys_sem_open, // Create/open a semaphore
+    SemUnlink = 691 (Str) -> Int => sys_sem_unlink, // Remove the name of a semaphore
+    SemWait = 692 (Int, Uint) -> Int => sys_sem_wait, // Take one, waiting while zero
+    SemTryWait = 693 (Int) -> Int => sys_sem_try_wait, // Take one if not zero
+    SemPost = 694 (Int) -> Int => sys_sem_post, // Add one
+    SemGetValue = 695 (Int) -> Int => sys_sem_get_value, // Get the value
+
+    // Sockets, continued
+    SocketSetOption = 696 (Int, Uint, Hex) -> Int => sys_socket_set_option, // Set an option of a socket
+
+    // Network configuration
+    NetConfigOpen = 697 => sys_netconfig_open, // Open a channel to the network configuration
+
+    // Devices
+    DeviceEventsOpen = 698 => sys_device_events_open, // Open a channel of the device hotplug events
+
     
     // === Memory Mapping Operations ===
     MemoryMap = 700 => sys_memory_map,     // Memory map operation (mmap)
     MemoryUnmap = 701 => sys_memory_unmap, // Memory unmap operation (munmap)
+    MemoryProtect = 702 => sys_memory_protect, // Change memory protection (mprotect)
+    MemoryAdvise = 703 => sys_memory_advise, // Advise on memory usage (madvise)
     
     // === Task Event Operations ===
     
@@ -0,0 +1,405 @@
+//! Hierarchical resource groups (cgroup-like controller)
+//!
+//! Tasks are organised in a tree of resource groups. Every task belongs to
+//! exactly one group, the root group at boot; children inherit the group of
+//! their parent on clone and spawn. A task is moved by writing its ID to the
+//! `cgroup.procs` file of another group (see the `cgroupfs` driver).
+//!
+//! Each group below the root has two controls:
+//!
+//! - **CPU shares**: the time slices of a group's normal tasks are scaled by
+//!   the group's shares relative to [`DEFAULT_CPU_SHARES`] and divided among
+//!   its runnable tasks on the CPU, so that each group gets CPU time in
+//!   proportion to its shares however many tasks it runs (see
+//!   [`scale_time_slice`]). Shares of nested groups multiply. Real-time
+//!   tasks are not affected.
+//! - **Memory ceiling**: anonymous memory is charged to the group of the
+//!   task that first touches it, and to all of its ancestors. When a group
+//!   goes over its ceiling, the largest task in it (or in a group below it)
+//!   is killed, as the OOM killer does for the whole system.
+//!
+//! Memory stays charged to the group it was charged to when its task moves
+//! on. A group can only be removed once it has no tasks, no child groups and
+//! no memory charged.
+
+extern crate alloc;
+
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::{Once, RwLock};
+
+use crate::environment::PAGE_SIZE;
+use crate::mem::oom::{task_rss_pages, OOM_SCORE_ADJ_MIN};
+use crate::sched::priority::MAX_TIME_SLICE;
+use crate::sched::scheduler::get_scheduler;
+use crate::task::signal::{send_signal, SIGKILL};
+use crate::task::{mytask, TaskState, TaskType};
+
+/// Identifies a resource group
+pub type GroupId = usize;
+
+/// The group every task starts in
+pub const ROOT_GROUP: GroupId = 0;
+
+/// CPU shares of a new group
+pub const DEFAULT_CPU_SHARES: u32 = 1024;
+/// Fewest CPU shares a group can have
+pub const MIN_CPU_SHARES: u32 = 2;
+/// Most CPU shares a group can have
+pub const MAX_CPU_SHARES: u32 = 262144;
+
+/// A node of the group tree
+struct ResourceGroup {
+    /// Parent group; the root has none
+    parent: Option<GroupId>,
+    /// Child groups by name
+    children: BTreeMap<String, GroupId>,
+    /// Weight of the group's tasks on the CPU
+    cpu_shares: u32,
+    /// Ceiling of charged memory in pages, `None` for no ceiling
+    memory_max: Option<usize>,
+    /// Pages charged to the group and the groups below it
+    memory_pages: usize,
+    /// Tasks killed for going over the ceiling
+    oom_kills: usize,
+    /// Task last killed for the group, until it is gone
+    oom_victim: Option<usize>,
+}
+
+impl ResourceGroup {
+    fn new(parent: Option<GroupId>) -> Self {
+        ResourceGroup {
+            parent,
+            children: BTreeMap::new(),
+            cpu_shares: DEFAULT_CPU_SHARES,
+            memory_max: None,
+            memory_pages: 0,
+            oom_kills: 0,
+            oom_victim: None,
+        }
+    }
+}
+
+/// All groups by ID
+static GROUPS: Once<RwLock<BTreeMap<GroupId, ResourceGroup>>> = Once::new();
+
+/// ID of the next group created
+static NEXT_GROUP_ID: AtomicUsize = AtomicUsize::new(ROOT_GROUP + 1);
+
+/// Set when a charge took a group over its ceiling
+static OVER_LIMIT: AtomicBool = AtomicBool::new(false);
+
+fn groups() -> &'static RwLock<BTreeMap<GroupId, ResourceGroup>> {
+    GROUPS.call_once(|| {
+        let mut groups = BTreeMap::new();
+        groups.insert(ROOT_GROUP, ResourceGroup::new(None));
+        RwLock::new(groups)
+    })
+}
+
+/// `group` and its ancestors, innermost first
+fn ancestry(groups: &BTreeMap<GroupId, ResourceGroup>, group: GroupId) -> impl Iterator<Item = GroupId> + '_ {
+    core::iter::successors(Some(group), |id| groups.get(id).and_then(|group| group.parent))
+        .filter(|id| groups.contains_key(id))
+}
+
+/// Whether `group` is `ancestor` or lies below it
+fn is_within(groups: &BTreeMap<GroupId, ResourceGroup>, group: GroupId, ancestor: GroupId) -> bool {
+    ancestry(groups, group).any(|id| id == ancestor)
+}
+
+/// Whether a group exists
+pub fn exists(group: GroupId) -> bool {
+    groups().read().contains_key(&group)
+}
+
+/// Create a group named `name` below `parent`
+///
+/// # Returns
+/// The ID of the new group
+pub fn create_group(parent: GroupId, name: &str) -> Result<GroupId, &'static str> {
+    if name.is_empty() || name.contains('/') || name.contains('.') {
+        return Err("Invalid group name");
+    }
+    let mut groups = groups().write();
+    let parent_group = groups.get_mut(&parent).ok_or("No such group")?;
+    if parent_group.children.contains_key(name) {
+        return Err("Group already exists");
+    }
+    let id = NEXT_GROUP_ID.fetch_add(1, Ordering::Relaxed);
+    parent_group.children.insert(name.to_string(), id);
+    groups.insert(id, ResourceGroup::new(Some(parent)));
+    Ok(id)
+}
+
+/// Remove the group named `name` below `parent`
+///
+/// # Errors
+/// If the group still has tasks, child groups or charged memory.
+pub fn remove_group(parent: GroupId, name: &str) -> Result<(), &'static str> {
+    let id = lookup_child(parent, name).ok_or("No such group")?;
+    if !group_tasks(id).is_empty() {
+        return Err("Group has tasks");
+    }
+    let mut groups = groups().write();
+    let group = groups.get(&id).ok_or("No such group")?;
+    if !group.children.is_empty() {
+        return Err("Group has child groups");
+    }
+    if group.memory_pages > 0 {
+        return Err("Group has memory charged");
+    }
+    groups.remove(&id);
+    if let Some(parent) = groups.get_mut(&parent) {
+        parent.children.remove(name);
+    }
+    Ok(())
+}
+
+/// The child group named `name` of `parent`
+pub fn lookup_child(parent: GroupId, name: &str) -> Option<GroupId> {
+    groups().read().get(&parent)?.children.get(name).copied()
+}
+
+/// Parent of `group`, `None` for the root group
+pub fn parent(group: GroupId) -> Option<GroupId> {
+    groups().read().get(&group)?.parent
+}
+
+/// Child groups of `group` with their names
+pub fn children(group: GroupId) -> Vec<(String, GroupId)> {
+    groups().read().get(&group)
+        .map(|group| group.children.iter().map(|(name, &id)| (name.clone(), id)).collect())
+        .unwrap_or_default()
+}
+
+/// Tasks that belong to `group` itself (not to the groups below it)
+pub fn group_tasks(group: GroupId) -> Vec<usize> {
+    let scheduler = get_scheduler();
+    scheduler.get_all_task_ids().into_iter()
+        .filter(|&id| scheduler.get_task_by_id(id).is_some_and(|task| {
+            task.cgroup == group && !matches!(task.get_state(), TaskState::Zombie | TaskState::Terminated)
+        }))
+        .collect()
+}
+
+/// Group of the running task, the root group outside of any task
+pub fn current_group() -> GroupId {
+    mytask().map_or(ROOT_GROUP, |task| task.cgroup)
+}
+
+/// Move task `task_id` into `group`
+pub fn attach_task(group: GroupId, task_id: usize) -> Result<(), &'static str> {
+    if !exists(group) {
+        return Err("No such group");
+    }
+    let task = get_scheduler().get_task_by_id(task_id).ok_or("No such task")?;
+    if task.task_type != TaskType::User {
+        return Err("Kernel tasks stay in the root group");
+    }
+    task.cgroup = group;
+    Ok(())
+}
+
+/// CPU shares of `group`
+pub fn cpu_shares(group: GroupId) -> Option<u32> {
+    groups().read().get(&group).map(|group| group.cpu_shares)
+}
+
+/// Set the CPU shares of `group`
+pub fn set_cpu_shares(group: GroupId, shares: u32) -> Result<(), &'static str> {
+    if group == ROOT_GROUP {
+        return Err("The root group has no CPU shares");
+    }
+    if !(MIN_CPU_SHARES..=MAX_CPU_SHARES).contains(&shares) {
+        return Err("CPU shares out of range");
+    }
+    groups().write().get_mut(&group).ok_or("No such group")?.cpu_shares = shares;
+    Ok(())
+}
+
+/// Time slice of a normal task of `group` on a CPU where `runnable` tasks
+/// of the group (including this one) can run
+///
+/// Tasks of the root group keep `slice`. Otherwise the slice is scaled by
+/// the shares of the group and its ancestors and divided among the
+/// runnable tasks of the group, but is at least one tick.
+pub fn scale_time_slice(group: GroupId, slice: u32, runnable: usize) -> u32 {
+    if group == ROOT_GROUP {
+        return slice;
+    }
+    let groups = groups().read();
+    let weight = ancestry(&groups, group)
+        .filter(|&id| id != ROOT_GROUP)
+        .fold(DEFAULT_CPU_SHARES as u64, |weight, id| {
+            weight * groups[&id].cpu_shares as u64 / DEFAULT_CPU_SHARES as u64
+        });
+    let scaled = slice as u64 * weight / DEFAULT_CPU_SHARES as u64 / runnable.max(1) as u64;
+    scaled.clamp(1, MAX_TIME_SLICE as u64) as u32
+}
+
+/// Memory ceiling of `group` in bytes, `None` for no ceiling
+pub fn memory_max(group: GroupId) -> Option<usize> {
+    groups().read().get(&group).and_then(|group| group.memory_max).map(|pages| pages * PAGE_SIZE)
+}
+
+/// Set the memory ceiling of `group` in bytes, `None` to remove it
+///
+/// The ceiling is rounded up to whole pages. A ceiling below the memory
+/// already charged takes effect with the next charge.
+pub fn set_memory_max(group: GroupId, bytes: Option<usize>) -> Result<(), &'static str> {
+    if group == ROOT_GROUP {
+        return Err("The root group has no memory ceiling");
+    }
+    groups().write().get_mut(&group).ok_or("No such group")?.memory_max = bytes.map(|bytes| bytes.div_ceil(PAGE_SIZE));
+    Ok(())
+}
+
+/// Memory charged to `group` and the groups below it, in bytes
+pub fn memory_current(group: GroupId) -> Option<usize> {
+    groups().read().get(&group).map(|group| group.memory_pages * PAGE_SIZE)
+}
+
+/// Number of tasks killed for `group` going over its ceiling
+pub fn oom_kills(group: GroupId) -> Option<usize> {
+    groups().read().get(&group).map(|group| group.oom_kills)
+}
+
+/// Charge `pages` pages to `group` and its ancestors
+///
+/// Charging never fails; a group that goes over its ceiling is dealt with
+/// by [`enforce_memory_limits`].
+pub fn charge_memory(group: GroupId, pages: usize) {
+    if pages == 0 {
+        return;
+    }
+    let mut groups = groups().write();
+    let path: Vec<GroupId> = ancestry(&groups, group).collect();
+    for id in path {
+        let group = groups.get_mut(&id).unwrap();
+        group.memory_pages += pages;
+        if group.memory_max.is_some_and(|max| group.memory_pages > max) {
+            OVER_LIMIT.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Return `pages` pages charged to `group` and its ancestors
+pub fn uncharge_memory(group: GroupId, pages: usize) {
+    if pages == 0 {
+        return;
+    }
+    let mut groups = groups().write();
+    let path: Vec<GroupId> = ancestry(&groups, group).collect();
+    for id in path {
+        let group = groups.get_mut(&id).unwrap();
+        group.memory_pages = group.memory_pages.saturating_sub(pages);
+    }
+}
+
+/// Kill a task in every group that is over its memory ceiling
+///
+/// The victim is the user task with the largest resident set in the group
+/// or below it that is not exempt from the OOM killer. No further task is
+/// killed for a group while its last victim is still around. Called after
+/// page faults, which is where charges come from; cheap if no group went
+/// over its ceiling.
+pub fn enforce_memory_limits() {
+    if !OVER_LIMIT.swap(false, Ordering::Relaxed) {
+        return;
+    }
+    let over: Vec<GroupId> = groups().read().iter()
+        .filter(|(_, group)| group.memory_max.is_some_and(|max| group.memory_pages > max))
+        .map(|(&id, _)| id)
+        .collect();
+    for group in over {
+        if let Some(victim) = groups().read().get(&group).and_then(|group| group.oom_victim) {
+            let alive = get_scheduler().get_task_by_id(victim)
+                .is_some_and(|task| !matches!(task.get_state(), TaskState::Zombie | TaskState::Terminated));
+            if alive {
+                // Still over the ceiling once the victim is gone: check again then
+                OVER_LIMIT.store(true, Ordering::Relaxed);
+                continue;
+            }
+        }
+        let Some(victim) = select_victim(group) else {
+            continue;
+        };
+        crate::println!("[cgroup] Group {} over its memory ceiling, killing task {}", group, victim);
+        if send_signal(victim, SIGKILL).is_ok() {
+            if let Some(group) = groups().write().get_mut(&group) {
+                group.oom_kills += 1;
+                group.oom_victim = Some(victim);
+            }
+        }
+    }
+}
+
+/// Task with the largest resident set in `group` or below it
+fn select_victim(group: GroupId) -> Option<usize> {
+    let scheduler = get_scheduler();
+    // Tasks are looked at before the group tree is locked
+    let candidates: Vec<(usize, usize, GroupId)> = scheduler.get_all_task_ids().into_iter()
+        .filter_map(|id| {
+            let task = scheduler.get_task_by_id(id)?;
+            let eligible = task.task_type == TaskType::User
+                && task.oom_score_adj > OOM_SCORE_ADJ_MIN
+                && !matches!(task.get_state(), TaskState::Zombie | TaskState::Terminated);
+            eligible.then(|| (task_rss_pages(task), id, task.cgroup))
+        })
+        .collect();
+    let groups = groups().read();
+    candidates.into_iter()
+        .filter(|&(_, _, task_group)| is_within(&groups, task_group, group))
+        .max()
+        .map(|(_, id, _)| id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_group_tree() {
+        let parent = create_group(ROOT_GROUP, "test_tree").unwrap();
+        let child = create_group(parent, "child").unwrap();
+        assert!(create_group(parent, "child").is_err());
+        assert!(create_group(parent, "a/b").is_err());
+        assert_eq!(lookup_child(parent, "child"), Some(child));
+        assert_eq!(children(parent), [("child".to_string(), child)]);
+
+        // Charges go up the tree
+        charge_memory(child, 3);
+        assert_eq!(memory_current(parent), Some(3 * PAGE_SIZE));
+        assert!(remove_group(parent, "child").is_err());
+        uncharge_memory(child, 3);
+        assert_eq!(memory_current(parent), Some(0));
+
+        assert!(remove_group(ROOT_GROUP, "test_tree").is_err());
+        remove_group(parent, "child").unwrap();
+        remove_group(ROOT_GROUP, "test_tree").unwrap();
+        assert!(!exists(child));
+    }
+
+    #[test_case]
+    fn test_scale_time_slice() {
+        let group = create_group(ROOT_GROUP, "test_slice").unwrap();
+        assert_eq!(scale_time_slice(ROOT_GROUP, 4, 8), 4);
+        assert_eq!(scale_time_slice(group, 4, 1), 4);
+        set_cpu_shares(group, 2048).unwrap();
+        assert_eq!(scale_time_slice(group, 4, 1), 8);
+        assert_eq!(scale_time_slice(group, 4, 2), 4);
+        // Nested shares multiply
+        let child = create_group(group, "child").unwrap();
+        set_cpu_shares(child, 512).unwrap();
+        assert_eq!(scale_time_slice(child, 4, 1), 4);
+        // At least one tick
+        assert_eq!(scale_time_slice(child, 4, 100), 1);
+        assert!(set_cpu_shares(group, 1).is_err());
+        assert!(set_cpu_shares(ROOT_GROUP, 2048).is_err());
+        remove_group(group, "child").unwrap();
+        remove_group(ROOT_GROUP, "test_slice").unwrap();
+    }
+}
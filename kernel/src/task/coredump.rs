@@ -0,0 +1,295 @@
+//! ELF core dumps.
+//!
+//! A task killed by a signal whose default action dumps core (SIGSEGV,
+//! SIGILL, SIGABRT, ...) can leave an ELF core file behind, which gdb opens
+//! together with the executable to inspect the crash offline.
+//!
+//! Dumping is off until a core pattern is written to `/proc/core_pattern`.
+//! A relative pattern (e.g. `core`) names a file in the working directory of
+//! the task, an absolute one a fixed location; the task ID is appended to
+//! the name either way. Writing an empty line turns dumping off again.
+//! RLIMIT_CORE caps the size of each file; memory past the limit is left
+//! out.
+//!
+//! The file holds a PT_NOTE segment with the NT_PRSTATUS (registers and
+//! signal) and NT_PRPSINFO (name and IDs) notes in the Linux layout,
+//! followed by one PT_LOAD segment per user memory mapping. Pages the task
+//! never touched read as zeros and are not allocated for the dump.
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use spin::RwLock;
+
+use crate::arch::Trapframe;
+use crate::environment::PAGE_SIZE;
+use crate::fs::vfs_v2::manager::get_global_vfs_manager;
+use crate::fs::{FileSystemErrorKind, FileType};
+use crate::vm::vmem::{VirtualMemoryMap, VirtualMemoryPermission};
+
+use super::elf_loader::{PF_R, PF_W, PF_X};
+use super::rlimit::{Resource, RLIM_INFINITY};
+use super::rusage::Timeval;
+use super::Task;
+
+const ET_CORE: u16 = 4;
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+const NT_PRPSINFO: u32 = 3;
+
+const ELF_HEADER_SIZE: usize = 64;
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+/// Where core files go; empty when dumping is off
+static CORE_PATTERN: RwLock<String> = RwLock::new(String::new());
+
+/// Render `/proc/core_pattern`
+pub fn format_core_pattern() -> String {
+    format!("{}\n", CORE_PATTERN.read())
+}
+
+/// Set the core pattern from a write to `/proc/core_pattern`
+pub fn set_core_pattern(data: &[u8]) -> Result<(), &'static str> {
+    let pattern = core::str::from_utf8(data).map_err(|_| "Core pattern is not UTF-8")?;
+    *CORE_PATTERN.write() = String::from(pattern.trim());
+    Ok(())
+}
+
+/// Appends little-endian fields to a byte buffer
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u16(&mut self, value: u16) { self.0.extend_from_slice(&value.to_le_bytes()); }
+    fn u32(&mut self, value: u32) { self.0.extend_from_slice(&value.to_le_bytes()); }
+    fn u64(&mut self, value: u64) { self.0.extend_from_slice(&value.to_le_bytes()); }
+    fn bytes(&mut self, bytes: &[u8]) { self.0.extend_from_slice(bytes); }
+    fn timeval(&mut self, us: u64) {
+        let time = Timeval::from_us(us);
+        self.u64(time.sec as u64);
+        self.u64(time.usec as u64);
+    }
+    fn align(&mut self, align: usize) {
+        self.0.resize(self.0.len().next_multiple_of(align), 0);
+    }
+    /// Append a note with the name "CORE"
+    fn note(&mut self, note_type: u32, desc: &[u8]) {
+        self.u32(5);
+        self.u32(desc.len() as u32);
+        self.u32(note_type);
+        self.bytes(b"CORE\0");
+        self.align(4);
+        self.bytes(desc);
+        self.align(4);
+    }
+}
+
+/// `struct elf_prstatus` of riscv64
+fn prstatus(task: &Task, sig: usize, trapframe: &Trapframe) -> Vec<u8> {
+    let mut w = Writer(Vec::new());
+    // pr_info: signo, code, errno
+    w.u32(sig as u32);
+    w.u32(0);
+    w.u32(0);
+    w.u16(sig as u16);
+    w.u16(0);
+    w.u64(task.signals.pending().bits());
+    w.u64(task.signals.mask.bits());
+    w.u32(task.get_id() as u32);
+    w.u32(task.get_parent_id().unwrap_or(0) as u32);
+    w.u32(task.get_pgid() as u32);
+    w.u32(task.get_sid() as u32);
+    w.timeval(task.cpu_times.user_us);
+    w.timeval(task.cpu_times.system_us);
+    w.timeval(task.cpu_times.children.user_us);
+    w.timeval(task.cpu_times.children.system_us);
+    // pr_reg: pc followed by x1-x31
+    w.u64(trapframe.epc);
+    for reg in &trapframe.regs.reg[1..] {
+        w.u64(*reg as u64);
+    }
+    // pr_fpvalid
+    w.u32(0);
+    w.align(8);
+    w.0
+}
+
+/// `struct elf_prpsinfo` of riscv64
+fn prpsinfo(task: &Task) -> Vec<u8> {
+    let mut w = Writer(Vec::new());
+    w.bytes(&[0, b'R', 0, task.nice as i8 as u8]);
+    w.align(8);
+    // pr_flag, pr_uid, pr_gid
+    w.u64(0);
+    w.u32(0);
+    w.u32(0);
+    w.u32(task.get_id() as u32);
+    w.u32(task.get_parent_id().unwrap_or(0) as u32);
+    w.u32(task.get_pgid() as u32);
+    w.u32(task.get_sid() as u32);
+    let mut fname = [0u8; 16];
+    let name = task.name.rsplit('/').next().unwrap_or("").as_bytes();
+    let len = name.len().min(fname.len() - 1);
+    fname[..len].copy_from_slice(&name[..len]);
+    w.bytes(&fname);
+    let mut psargs = [0u8; 80];
+    let len = task.name.len().min(psargs.len() - 1);
+    psargs[..len].copy_from_slice(&task.name.as_bytes()[..len]);
+    w.bytes(&psargs);
+    w.0
+}
+
+/// Segment flags for the permissions of a mapping
+fn segment_flags(permissions: usize) -> u32 {
+    let mut flags = 0;
+    if permissions & VirtualMemoryPermission::Read as usize != 0 { flags |= PF_R; }
+    if permissions & VirtualMemoryPermission::Write as usize != 0 { flags |= PF_W; }
+    if permissions & VirtualMemoryPermission::Execute as usize != 0 { flags |= PF_X; }
+    flags
+}
+
+/// Build the headers and notes of a core file
+///
+/// # Returns
+/// The headers and the maps whose contents follow them, each with the
+/// number of bytes written to the file
+fn core_headers(task: &Task, sig: usize, trapframe: &Trapframe, limit: usize) -> (Vec<u8>, Vec<(VirtualMemoryMap, usize)>) {
+    let maps: Vec<VirtualMemoryMap> = task.vm_manager.read().memmap_iter()
+        .filter(|map| VirtualMemoryPermission::User.contained_in(map.permissions))
+        .cloned()
+        .collect();
+
+    let mut notes = Writer(Vec::new());
+    notes.note(NT_PRSTATUS, &prstatus(task, sig, trapframe));
+    notes.note(NT_PRPSINFO, &prpsinfo(task));
+
+    let phnum = maps.len() + 1;
+    let notes_offset = ELF_HEADER_SIZE + phnum * PROGRAM_HEADER_SIZE;
+    let data_offset = (notes_offset + notes.0.len()).next_multiple_of(PAGE_SIZE);
+
+    let mut w = Writer(Vec::new());
+    w.bytes(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    w.u16(ET_CORE);
+    w.u16(EM_RISCV);
+    w.u32(1);
+    w.u64(0); // e_entry
+    w.u64(ELF_HEADER_SIZE as u64);
+    w.u64(0); // e_shoff
+    w.u32(0);
+    w.u16(ELF_HEADER_SIZE as u16);
+    w.u16(PROGRAM_HEADER_SIZE as u16);
+    w.u16(phnum as u16);
+    w.u16(0);
+    w.u16(0);
+    w.u16(0);
+
+    let mut program_header = |p_type: u32, flags: u32, offset: usize, vaddr: usize, filesz: usize, memsz: usize, align: usize| {
+        w.u32(p_type);
+        w.u32(flags);
+        w.u64(offset as u64);
+        w.u64(vaddr as u64);
+        w.u64(0);
+        w.u64(filesz as u64);
+        w.u64(memsz as u64);
+        w.u64(align as u64);
+    };
+    program_header(PT_NOTE, 0, notes_offset, 0, notes.0.len(), 0, 4);
+
+    let mut offset = data_offset;
+    let mut segments = Vec::new();
+    for map in maps {
+        let size = map.vmarea.size();
+        // Memory past RLIMIT_CORE is left out of the file
+        let filesz = size.min(limit.saturating_sub(offset));
+        program_header(PT_LOAD, segment_flags(map.permissions), offset, map.vmarea.start, filesz, size, PAGE_SIZE);
+        offset += filesz;
+        segments.push((map, filesz));
+    }
+
+    w.bytes(&notes.0);
+    w.0.resize(data_offset, 0);
+    (w.0, segments)
+}
+
+/// Write a core file for `task`, killed by `sig` at `trapframe`
+///
+/// # Errors
+/// If dumping is off, RLIMIT_CORE is 0 or the file cannot be written.
+pub fn write_core(task: &Task, sig: usize, trapframe: &Trapframe) -> Result<String, &'static str> {
+    let pattern = CORE_PATTERN.read().clone();
+    if pattern.is_empty() {
+        return Err("Core dumps are disabled");
+    }
+    let limit = task.rlimits.get(Resource::Core).cur;
+    if limit == 0 {
+        return Err("RLIMIT_CORE is 0");
+    }
+
+    let vfs = task.get_vfs().cloned().unwrap_or_else(get_global_vfs_manager);
+    let path = format!("{}.{}", vfs.resolve_path_to_absolute(&pattern), task.get_id());
+    match vfs.create_file(&path, FileType::RegularFile) {
+        Ok(()) => {}
+        Err(e) if e.kind == FileSystemErrorKind::AlreadyExists => {}
+        Err(_) => return Err("Cannot create the core file"),
+    }
+    let object = vfs.open(&path, 0).map_err(|_| "Cannot open the core file")?;
+    let file = object.as_file().ok_or("Core file is not a file")?;
+    file.truncate(0).map_err(|_| "Cannot truncate the core file")?;
+
+    let limit = if limit == RLIM_INFINITY { usize::MAX } else { limit };
+    let (headers, segments) = core_headers(task, sig, trapframe, limit);
+    if headers.len() > limit {
+        return Err("Core file would exceed RLIMIT_CORE");
+    }
+    file.write(&headers).map_err(|_| "Cannot write the core file")?;
+
+    let zeros = [0u8; PAGE_SIZE];
+    for (map, filesz) in segments {
+        let mut written = 0;
+        while written < filesz {
+            let vaddr = map.vmarea.start + written;
+            let len = (PAGE_SIZE - vaddr % PAGE_SIZE).min(filesz - written);
+            // Untouched demand-zero pages read as zeros
+            let data = match map.get_paddr(vaddr) {
+                Some(paddr) => unsafe { core::slice::from_raw_parts(paddr as *const u8, len) },
+                None => &zeros[..len],
+            };
+            file.write(data).map_err(|_| "Cannot write the core file")?;
+            written += len;
+        }
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    #[test_case]
+    fn test_core_headers_layout() {
+        let mut task = super::super::new_user_task("core_test".to_string(), 0);
+        task.init();
+        let trapframe = Trapframe::new();
+        let (headers, segments) = core_headers(&task, crate::task::signal::SIGSEGV, &trapframe, usize::MAX);
+
+        assert_eq!(&headers[..4], b"\x7fELF");
+        assert_eq!(u16::from_le_bytes([headers[16], headers[17]]), ET_CORE);
+        assert_eq!(u16::from_le_bytes([headers[18], headers[19]]), EM_RISCV);
+        let phnum = u16::from_le_bytes([headers[56], headers[57]]) as usize;
+        assert_eq!(phnum, segments.len() + 1);
+        // The memory contents start on a page boundary after the notes
+        assert_eq!(headers.len() % PAGE_SIZE, 0);
+
+        // The note segment comes first
+        let note = &headers[ELF_HEADER_SIZE..ELF_HEADER_SIZE + PROGRAM_HEADER_SIZE];
+        assert_eq!(u32::from_le_bytes(note[..4].try_into().unwrap()), PT_NOTE);
+
+        // RLIMIT_CORE leaves the memory out
+        let (_, segments) = core_headers(&task, crate::task::signal::SIGSEGV, &trapframe, headers.len());
+        assert!(segments.iter().all(|(_, filesz)| *filesz == 0));
+    }
+}
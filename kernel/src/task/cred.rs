@@ -0,0 +1,364 @@
+//! Task credentials
+//!
+//! Every task carries [`Credentials`]: real, effective and saved user and
+//! group IDs plus a list of supplementary groups. Children inherit them on
+//! clone and spawn, and exec keeps them (the saved IDs take the effective
+//! ones). The effective user ID decides privilege: a task with effective
+//! UID 0 is privileged and may take any IDs; other tasks may only switch
+//! between the IDs they already hold, following the POSIX `set*id` rules.
+//!
+//! Files are checked against the effective user ID, the effective group ID
+//! and the supplementary groups with [`Credentials::may_access`]. Subsystems
+//! that enforce ownership of other objects check the same credentials.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+
+use crate::fs::{FileMetadata, FileType, VfsManager};
+
+use super::mytask;
+
+pub type Uid = u32;
+pub type Gid = u32;
+
+/// User ID of the superuser
+pub const ROOT_UID: Uid = 0;
+/// Group ID of the superuser
+pub const ROOT_GID: Gid = 0;
+
+/// ID argument of `setre*id` and `setres*id` that leaves the ID unchanged
+pub const ID_UNCHANGED: u32 = u32::MAX;
+
+/// Most supplementary groups a task can have
+pub const NGROUPS_MAX: usize = 32;
+
+/// Access to a file: read
+pub const MAY_READ: u32 = 0o4;
+/// Access to a file: write
+pub const MAY_WRITE: u32 = 0o2;
+/// Access to a file: execute, or search a directory
+pub const MAY_EXEC: u32 = 0o1;
+
+/// User and group IDs of a task
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    /// Real user ID
+    pub uid: Uid,
+    /// Effective user ID, used for permission checks
+    pub euid: Uid,
+    /// Saved set-user-ID
+    pub suid: Uid,
+    /// Real group ID
+    pub gid: Gid,
+    /// Effective group ID, used for permission checks
+    pub egid: Gid,
+    /// Saved set-group-ID
+    pub sgid: Gid,
+    /// Supplementary groups
+    pub groups: Vec<Gid>,
+}
+
+impl Credentials {
+    /// Credentials of the superuser, which the first tasks run with
+    pub const fn root() -> Self {
+        Credentials {
+            uid: ROOT_UID,
+            euid: ROOT_UID,
+            suid: ROOT_UID,
+            gid: ROOT_GID,
+            egid: ROOT_GID,
+            sgid: ROOT_GID,
+            groups: Vec::new(),
+        }
+    }
+
+    /// Whether the task may bypass permission checks and take any IDs
+    pub fn is_privileged(&self) -> bool {
+        self.euid == ROOT_UID
+    }
+
+    /// Whether `gid` is the effective group or a supplementary group
+    pub fn in_group(&self, gid: Gid) -> bool {
+        self.egid == gid || self.groups.contains(&gid)
+    }
+
+    /// Whether `uid` is the real, effective or saved user ID
+    fn holds_uid(&self, uid: Uid) -> bool {
+        uid == self.uid || uid == self.euid || uid == self.suid
+    }
+
+    /// Whether `gid` is the real, effective or saved group ID
+    fn holds_gid(&self, gid: Gid) -> bool {
+        gid == self.gid || gid == self.egid || gid == self.sgid
+    }
+
+    /// `setuid`: a privileged task sets all three user IDs, others only
+    /// the effective one, to their real or saved user ID
+    pub fn setuid(&mut self, uid: Uid) -> Result<(), &'static str> {
+        if uid == ID_UNCHANGED {
+            return Err("Invalid user ID");
+        }
+        if self.is_privileged() {
+            self.uid = uid;
+            self.suid = uid;
+        } else if uid != self.uid && uid != self.suid {
+            return Err("Operation not permitted");
+        }
+        self.euid = uid;
+        Ok(())
+    }
+
+    /// `setgid`: like [`Self::setuid`] for the group IDs
+    pub fn setgid(&mut self, gid: Gid) -> Result<(), &'static str> {
+        if gid == ID_UNCHANGED {
+            return Err("Invalid group ID");
+        }
+        if self.is_privileged() {
+            self.gid = gid;
+            self.sgid = gid;
+        } else if gid != self.gid && gid != self.sgid {
+            return Err("Operation not permitted");
+        }
+        self.egid = gid;
+        Ok(())
+    }
+
+    /// `setreuid`: set the real and effective user IDs
+    ///
+    /// Without privilege the real ID can only become the real or effective
+    /// ID and the effective ID one of the three IDs. If the real ID is set,
+    /// or the effective ID is set to something other than the old real ID,
+    /// the saved ID follows the new effective ID.
+    pub fn setreuid(&mut self, ruid: Uid, euid: Uid) -> Result<(), &'static str> {
+        if !self.is_privileged() {
+            let ruid_ok = ruid == ID_UNCHANGED || ruid == self.uid || ruid == self.euid;
+            let euid_ok = euid == ID_UNCHANGED || self.holds_uid(euid);
+            if !ruid_ok || !euid_ok {
+                return Err("Operation not permitted");
+            }
+        }
+        let old_uid = self.uid;
+        if ruid != ID_UNCHANGED {
+            self.uid = ruid;
+        }
+        if euid != ID_UNCHANGED {
+            self.euid = euid;
+        }
+        if ruid != ID_UNCHANGED || (euid != ID_UNCHANGED && euid != old_uid) {
+            self.suid = self.euid;
+        }
+        Ok(())
+    }
+
+    /// `setregid`: like [`Self::setreuid`] for the group IDs
+    pub fn setregid(&mut self, rgid: Gid, egid: Gid) -> Result<(), &'static str> {
+        if !self.is_privileged() {
+            let rgid_ok = rgid == ID_UNCHANGED || rgid == self.gid || rgid == self.egid;
+            let egid_ok = egid == ID_UNCHANGED || self.holds_gid(egid);
+            if !rgid_ok || !egid_ok {
+                return Err("Operation not permitted");
+            }
+        }
+        let old_gid = self.gid;
+        if rgid != ID_UNCHANGED {
+            self.gid = rgid;
+        }
+        if egid != ID_UNCHANGED {
+            self.egid = egid;
+        }
+        if rgid != ID_UNCHANGED || (egid != ID_UNCHANGED && egid != old_gid) {
+            self.sgid = self.egid;
+        }
+        Ok(())
+    }
+
+    /// `setresuid`: set the real, effective and saved user IDs
+    ///
+    /// Without privilege each new ID must be one of the current three.
+    pub fn setresuid(&mut self, ruid: Uid, euid: Uid, suid: Uid) -> Result<(), &'static str> {
+        if !self.is_privileged()
+            && [ruid, euid, suid].iter().any(|&id| id != ID_UNCHANGED && !self.holds_uid(id)) {
+            return Err("Operation not permitted");
+        }
+        if ruid != ID_UNCHANGED {
+            self.uid = ruid;
+        }
+        if euid != ID_UNCHANGED {
+            self.euid = euid;
+        }
+        if suid != ID_UNCHANGED {
+            self.suid = suid;
+        }
+        Ok(())
+    }
+
+    /// `setresgid`: like [`Self::setresuid`] for the group IDs
+    pub fn setresgid(&mut self, rgid: Gid, egid: Gid, sgid: Gid) -> Result<(), &'static str> {
+        if !self.is_privileged()
+            && [rgid, egid, sgid].iter().any(|&id| id != ID_UNCHANGED && !self.holds_gid(id)) {
+            return Err("Operation not permitted");
+        }
+        if rgid != ID_UNCHANGED {
+            self.gid = rgid;
+        }
+        if egid != ID_UNCHANGED {
+            self.egid = egid;
+        }
+        if sgid != ID_UNCHANGED {
+            self.sgid = sgid;
+        }
+        Ok(())
+    }
+
+    /// `setgroups`: replace the supplementary groups (privileged only)
+    pub fn setgroups(&mut self, groups: &[Gid]) -> Result<(), &'static str> {
+        if !self.is_privileged() {
+            return Err("Operation not permitted");
+        }
+        if groups.len() > NGROUPS_MAX {
+            return Err("Too many groups");
+        }
+        self.groups = groups.to_vec();
+        Ok(())
+    }
+
+    /// Update the IDs for exec: the saved IDs take the effective ones
+    pub fn exec(&mut self) {
+        self.suid = self.euid;
+        self.sgid = self.egid;
+    }
+
+    /// Whether the task may access a file as `want` (`MAY_*` bits)
+    ///
+    /// With the owner and mode of the file known, the owner, group or other
+    /// bits apply depending on who the task is. A privileged task may do
+    /// anything except execute a file without any execute bit. Otherwise
+    /// the permissions the filesystem reports apply to everyone and a
+    /// privileged task is not checked.
+    pub fn may_access(&self, metadata: &FileMetadata, want: u32) -> bool {
+        let Some(owner) = metadata.owner else {
+            if self.is_privileged() {
+                return true;
+            }
+            let permissions = &metadata.permissions;
+            return (want & MAY_READ == 0 || permissions.read)
+                && (want & MAY_WRITE == 0 || permissions.write)
+                && (want & MAY_EXEC == 0 || permissions.execute);
+        };
+        if self.is_privileged() {
+            return want & MAY_EXEC == 0 || metadata.file_type == FileType::Directory || owner.mode & 0o111 != 0;
+        }
+        let bits = if self.euid == owner.uid {
+            owner.mode >> 6
+        } else if self.in_group(owner.gid) {
+            owner.mode >> 3
+        } else {
+            owner.mode
+        } & 0o7;
+        bits & want == want
+    }
+
+    /// Check access to the file at `path` (see [`Self::may_access`])
+    ///
+    /// A denial is recorded in the audit log.
+    ///
+    /// # Errors
+    /// If the file does not exist or access is denied.
+    pub fn check_path(&self, vfs: &VfsManager, path: &str, want: u32) -> Result<(), &'static str> {
+        let metadata = vfs.metadata(path).map_err(|_| "No such file or directory")?;
+        if self.may_access(&metadata, want) {
+            Ok(())
+        } else {
+            crate::audit::access_denied(path, want);
+            Err("Permission denied")
+        }
+    }
+
+    /// Check that an entry may be created in or removed from the directory
+    /// containing `path` (write and search access)
+    pub fn check_parent(&self, vfs: &VfsManager, path: &str) -> Result<(), &'static str> {
+        let parent = match path.trim_end_matches('/').rsplit_once('/') {
+            Some(("", _)) | None => "/",
+            Some((parent, _)) => parent,
+        };
+        self.check_path(vfs, parent, MAY_WRITE | MAY_EXEC)
+    }
+}
+
+impl Default for Credentials {
+    fn default() -> Self {
+        Self::root()
+    }
+}
+
+/// Effective user and group ID of the running task, the superuser's
+/// outside of any task; the owner of files the task creates
+pub fn current_fs_ids() -> (Uid, Gid) {
+    mytask().map_or((ROOT_UID, ROOT_GID), |task| (task.cred.euid, task.cred.egid))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::{FileOwner, FilePermission};
+
+    fn user(uid: Uid, gid: Gid) -> Credentials {
+        Credentials { uid, euid: uid, suid: uid, gid, egid: gid, sgid: gid, groups: Vec::new() }
+    }
+
+    #[test_case]
+    fn test_set_ids() {
+        // A privileged task that drops to a user cannot come back
+        let mut cred = Credentials::root();
+        cred.setuid(1000).unwrap();
+        assert_eq!((cred.uid, cred.euid, cred.suid), (1000, 1000, 1000));
+        assert!(cred.setuid(0).is_err());
+
+        // Swapping real and effective IDs keeps the way back
+        let mut cred = Credentials::root();
+        cred.setresuid(1000, 2000, 0).unwrap();
+        assert!(!cred.is_privileged());
+        cred.setreuid(2000, 1000).unwrap();
+        assert_eq!((cred.uid, cred.euid, cred.suid), (2000, 1000, 1000));
+        assert!(cred.setuid(3000).is_err());
+        cred.setuid(2000).unwrap();
+        assert_eq!(cred.euid, 2000);
+
+        let mut cred = user(1000, 100);
+        assert!(cred.setresuid(ID_UNCHANGED, ID_UNCHANGED, 2000).is_err());
+        assert!(cred.setgroups(&[10]).is_err());
+        assert!(cred.setgid(0).is_err());
+        cred.setregid(ID_UNCHANGED, 100).unwrap();
+    }
+
+    #[test_case]
+    fn test_may_access() {
+        let mut metadata = crate::fs::FileMetadata {
+            file_type: FileType::RegularFile,
+            size: 0,
+            permissions: FilePermission { read: true, write: false, execute: false },
+            created_time: 0,
+            modified_time: 0,
+            accessed_time: 0,
+            file_id: 1,
+            link_count: 1,
+            owner: None,
+        };
+        // Without an owner the permissions apply to everyone but root
+        assert!(user(1000, 100).may_access(&metadata, MAY_READ));
+        assert!(!user(1000, 100).may_access(&metadata, MAY_WRITE));
+        assert!(Credentials::root().may_access(&metadata, MAY_WRITE | MAY_EXEC));
+
+        metadata.owner = Some(FileOwner { uid: 1000, gid: 100, mode: 0o640 });
+        assert!(user(1000, 1).may_access(&metadata, MAY_READ | MAY_WRITE));
+        assert!(user(2000, 100).may_access(&metadata, MAY_READ));
+        assert!(!user(2000, 100).may_access(&metadata, MAY_WRITE));
+        let mut member = user(2000, 1);
+        member.groups.push(100);
+        assert!(member.may_access(&metadata, MAY_READ));
+        assert!(!user(2000, 1).may_access(&metadata, MAY_READ));
+        assert!(Credentials::root().may_access(&metadata, MAY_WRITE));
+        assert!(!Credentials::root().may_access(&metadata, MAY_EXEC));
+    }
+}
@@ -1,7 +1,8 @@
 //! ELF Loading Module
 //!
 //! This module provides functionality for loading ELF (Executable and Linkable Format)
-//! executables into a task's memory space. It supports 64-bit ELF files with full dynamic
+//! executables into a task's memory space. It supports 64-bit and 32-bit ELF files (the
+//! latter for compat ABIs that run 32-bit programs) with full dynamic
 //! linking capabilities and handles the parsing of ELF headers and program headers, as well
 //! as the mapping of loadable segments into memory.
 //!
@@ -17,6 +18,9 @@
 //!
 //! - `load_elf_into_task`: Loads an ELF file from a file object into a task's memory space
 //! - `map_elf_segment`: Maps an ELF segment into a task's virtual memory
+//! - `find_tls_template`: Records the program's TLS template (PT_TLS) so that
+//!   the task and its threads get TLS blocks (see `Task::setup_tls`)
+//! - `note::read_abi_note`: Finds the note naming the ABI of a program
 //! - Dynamic linker integration for shared library resolution
 //!
 //! # Dynamic Linking Support
@@ -31,7 +35,7 @@
 //!
 //! The module defines various constants for ELF parsing, including:
 //! - Magic numbers for identifying ELF files
-//! - ELF class identifiers (64-bit)
+//! - ELF class identifiers (32-bit and 64-bit)
 //! - Data encoding formats (little/big endian)
 //! - Program header types and segment flags (Read/Write/Execute)
 //!
@@ -43,19 +47,25 @@
 use crate::environment::PAGE_SIZE;
 use crate::fs::{FileObject, SeekFrom};
 use crate::mem::page::{allocate_raw_pages, free_raw_pages};
+use crate::vm::aslr::load_base;
+use crate::vm::wx;
 use crate::vm::vmem::{MemoryArea, VirtualMemoryMap, VirtualMemoryPermission, VirtualMemoryRegion};
 use alloc::boxed::Box;
 use alloc::{format, vec};
 use alloc::string::{String, ToString};
 use crate::task::Task;
 
+use super::cred::Credentials;
+use super::signal::copy_to_user;
 use super::{ManagedPage, TaskType};
 
+pub mod note;
+
 // ELF Magic Number
 const ELFMAG: [u8; 4] = [0x7F, b'E', b'L', b'F', ];
 // ELF Class
-// const ELFCLASS32: u8 = 1; // 32-bit
-const ELFCLASS64: u8 = 2; // 64-bit
+pub const ELFCLASS32: u8 = 1; // 32-bit
+pub const ELFCLASS64: u8 = 2; // 64-bit
 // ELF Data Endian
 const ELFDATA2LSB: u8 = 1; // Little Endian
 // const ELFDATA2MSB: u8 = 2; // Big Endian
@@ -67,6 +77,8 @@ pub const ET_DYN: u16 = 3;  // Shared object file / Position Independent Executa
 // Program Header Type
 const PT_LOAD: u32 = 1; // Loadable segment
 const PT_INTERP: u32 = 3; // Interpreter path
+const PT_PHDR: u32 = 6; // Program header table
+const PT_TLS: u32 = 7; // Thread-local storage template
 
 /// Target type for ELF loading (determines base address strategy)
 #[derive(Debug, Clone, Copy)]
@@ -91,9 +103,9 @@ impl Default for LoadStrategy {
             choose_base_address: |target, needs_relocation| {
                 match (target, needs_relocation) {
                     (LoadTarget::MainProgram, false) => 0,        // Absolute addresses
-                    (LoadTarget::MainProgram, true) => 0x10000,   // PIE executable
-                    (LoadTarget::Interpreter, _) => 0x40000000,   // Dynamic linker
-                    (LoadTarget::SharedLib, _) => 0x50000000,     // Shared libraries
+                    (LoadTarget::MainProgram, true) => load_base(0x10000),   // PIE executable
+                    (LoadTarget::Interpreter, _) => load_base(0x40000000),   // Dynamic linker
+                    (LoadTarget::SharedLib, _) => load_base(0x50000000),     // Shared libraries
                 }
             },
             resolve_interpreter: |requested| requested.map(|s| s.to_string()),
@@ -137,6 +149,18 @@ pub struct ProgramHeadersInfo {
     pub phdr_count: u64,   // Number of program headers
 }
 
+/// Thread-local storage template of a program (its PT_TLS segment)
+///
+/// Each thread gets a TLS block initialized from the template: `filesz`
+/// bytes are copied from the image and the rest up to `memsz` is zeroed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TlsTemplate {
+    pub image_addr: u64,   // Address of the initialization image in the loaded program
+    pub filesz: u64,       // Size of the initialization image
+    pub memsz: u64,        // Size of the TLS block
+    pub align: u64,        // Alignment of the TLS block
+}
+
 // Auxiliary Vector (auxv) types for dynamic linking
 /// Auxiliary Vector entry type constants
 pub const AT_NULL: u64 = 0;     // End of vector
@@ -157,7 +181,9 @@ pub const AT_EGID: u64 = 14;    // Effective gid
 pub const AT_PLATFORM: u64 = 15; // String identifying platform
 pub const AT_HWCAP: u64 = 16;   // Machine dependent hints about processor capabilities
 pub const AT_CLKTCK: u64 = 17;  // Frequency of times()
+pub const AT_SECURE: u64 = 23;  // The program runs with privileges it was not started with
 pub const AT_RANDOM: u64 = 25;  // Address of 16 random bytes
+pub const AT_EXECFN: u64 = 31;  // Path of the program
 
 /// Auxiliary Vector entry
 #[derive(Debug, Clone, Copy)]
@@ -278,7 +304,7 @@ pub struct ElfLoaderError {
 
 impl ElfHeader {
     pub fn parse(buffer: &[u8]) -> Result<Self, ElfHeaderParseError> {
-        if buffer.len() < 64 {
+        if buffer.len() <= EI_DATA {
             return Err(ElfHeaderParseError {
                 kind: ElfHeaderParseErrorKind::InvalidData,
                 message: "ELF header too small".to_string(),
@@ -294,10 +320,18 @@ impl ElfHeader {
         }
 
         let ei_class = buffer[EI_CLASS];
-        if ei_class != ELFCLASS64 {
-            return Err(ElfHeaderParseError {
+        let header_size = match ei_class {
+            ELFCLASS64 => 64,
+            ELFCLASS32 => 52,
+            _ => return Err(ElfHeaderParseError {
                 kind: ElfHeaderParseErrorKind::UnsupportedClass,
-                message: "Only 64-bit ELF is supported".to_string(),
+                message: "Only 32-bit and 64-bit ELF are supported".to_string(),
+            }),
+        };
+        if buffer.len() < header_size {
+            return Err(ElfHeaderParseError {
+                kind: ElfHeaderParseErrorKind::InvalidData,
+                message: "ELF header too small".to_string(),
             });
         }
 
@@ -307,16 +341,29 @@ impl ElfHeader {
         let e_type = read_u16(buffer, 16, is_little_endian);
         let e_machine = read_u16(buffer, 18, is_little_endian);
         let e_version = read_u32(buffer, 20, is_little_endian);
-        let e_entry = read_u64(buffer, 24, is_little_endian);
-        let e_phoff = read_u64(buffer, 32, is_little_endian);
-        let e_shoff = read_u64(buffer, 40, is_little_endian);
-        let e_flags = read_u32(buffer, 48, is_little_endian);
-        let e_ehsize = read_u16(buffer, 52, is_little_endian);
-        let e_phentsize = read_u16(buffer, 54, is_little_endian);
-        let e_phnum = read_u16(buffer, 56, is_little_endian);
-        let e_shentsize = read_u16(buffer, 58, is_little_endian);
-        let e_shnum = read_u16(buffer, 60, is_little_endian);
-        let e_shstrndx = read_u16(buffer, 62, is_little_endian);
+        // The addresses and offsets are as wide as the class, the rest follows them
+        let (e_entry, e_phoff, e_shoff, rest) = if ei_class == ELFCLASS64 {
+            (
+                read_u64(buffer, 24, is_little_endian),
+                read_u64(buffer, 32, is_little_endian),
+                read_u64(buffer, 40, is_little_endian),
+                48,
+            )
+        } else {
+            (
+                read_u32(buffer, 24, is_little_endian) as u64,
+                read_u32(buffer, 28, is_little_endian) as u64,
+                read_u32(buffer, 32, is_little_endian) as u64,
+                36,
+            )
+        };
+        let e_flags = read_u32(buffer, rest, is_little_endian);
+        let e_ehsize = read_u16(buffer, rest + 4, is_little_endian);
+        let e_phentsize = read_u16(buffer, rest + 6, is_little_endian);
+        let e_phnum = read_u16(buffer, rest + 8, is_little_endian);
+        let e_shentsize = read_u16(buffer, rest + 10, is_little_endian);
+        let e_shnum = read_u16(buffer, rest + 12, is_little_endian);
+        let e_shstrndx = read_u16(buffer, rest + 14, is_little_endian);
 
         Ok(Self {
             ei_class,
@@ -336,6 +383,11 @@ impl ElfHeader {
             e_shstrndx,
         })
     }
+
+    /// Whether the file is a 64-bit ELF (otherwise it is 32-bit)
+    pub fn is_64bit(&self) -> bool {
+        self.ei_class == ELFCLASS64
+    }
 }
 
 impl ProgramHeader {
@@ -368,6 +420,27 @@ impl ProgramHeader {
             p_align,
         })
     }
+
+    /// Parse a program header of a 32-bit ELF, whose flags come after the sizes
+    pub fn parse32(buffer: &[u8], is_little_endian: bool) -> Result<Self, ProgramHeaderParseError> {
+        if buffer.len() < 32 {
+            return Err(ProgramHeaderParseError {
+                kind: ProgramHeaderParseErrorKind::InvalidSize,
+                message: "Program header too small".to_string(),
+            });
+        }
+
+        Ok(Self {
+            p_type: read_u32(buffer, 0, is_little_endian),
+            p_offset: read_u32(buffer, 4, is_little_endian) as u64,
+            p_vaddr: read_u32(buffer, 8, is_little_endian) as u64,
+            p_paddr: read_u32(buffer, 12, is_little_endian) as u64,
+            p_filesz: read_u32(buffer, 16, is_little_endian) as u64,
+            p_memsz: read_u32(buffer, 20, is_little_endian) as u64,
+            p_flags: read_u32(buffer, 24, is_little_endian),
+            p_align: read_u32(buffer, 28, is_little_endian) as u64,
+        })
+    }
 }
 
 /// Read and parse a program header at the specified index
@@ -386,7 +459,13 @@ fn read_program_header(
         message: format!("Failed to read program header {}: {:?}", index, e),
     })?;
     
-    ProgramHeader::parse(&ph_buffer, header.ei_data == ELFDATA2LSB).map_err(|e| ElfLoaderError {
+    let is_little_endian = header.ei_data == ELFDATA2LSB;
+    let parsed = if header.is_64bit() {
+        ProgramHeader::parse(&ph_buffer, is_little_endian)
+    } else {
+        ProgramHeader::parse32(&ph_buffer, is_little_endian)
+    };
+    parsed.map_err(|e| ElfLoaderError {
         message: format!("Failed to parse program header {}: {:?}", index, e),
     })
 }
@@ -521,18 +600,20 @@ pub fn analyze_and_load_elf_with_strategy(
             if let Some(final_interp_path) = actual_interpreter {
                 crate::println!("Using interpreter: {}", final_interp_path);
                 let base_address = load_elf_segments_for_interpreter(&header, file_obj, task, strategy)?;
-                let interpreter_entry = load_interpreter(&final_interp_path, task, strategy)?;
+                task.tls_template = find_tls_template(&header, file_obj, base_address)?;
+                let (interpreter_entry, interpreter_base) = load_interpreter(&final_interp_path, task, strategy, header.ei_class)?;
                 
                 // Prepare program headers info for auxiliary vector
+                let phdr_addr = match find_phdr_address(&header, file_obj, base_address)? {
+                    Some(addr) => addr,
+                    None => load_program_headers_into_memory(&header, file_obj, task)?,
+                };
                 let phdr_info = ProgramHeadersInfo {
-                    phdr_addr: base_address + header.e_phoff,
+                    phdr_addr,
                     phdr_size: header.e_phentsize as u64,
                     phdr_count: header.e_phnum as u64,
                 };
                 
-                // TODO: Get actual interpreter base address from load_interpreter result
-                let interpreter_base = 0x40000000u64; // Hardcoded for now
-                
                 Ok(LoadElfResult {
                     mode: ExecutionMode::Dynamic { interpreter_path: final_interp_path },
                     entry_point: interpreter_entry,
@@ -551,24 +632,19 @@ pub fn analyze_and_load_elf_with_strategy(
         None => {
             // Static linking - use existing implementation
             let base_address = (strategy.choose_base_address)(LoadTarget::MainProgram, needs_relocation);
-            let entry_point = load_elf_into_task_static(&header, file_obj, task, strategy)?;
+            let entry_point = load_elf_into_task_static(&header, file_obj, task, base_address)?;
+            task.tls_template = find_tls_template(&header, file_obj, base_address)?;
             
-            // For static executables, load program headers into memory if needed
-            let phdr_info = if needs_relocation {
-                // PIE static executable - program headers are loaded with the executable
-                ProgramHeadersInfo {
-                    phdr_addr: base_address + header.e_phoff,
-                    phdr_size: header.e_phentsize as u64,
-                    phdr_count: header.e_phnum as u64,
-                }
-            } else {
-                // Traditional static executable - load program headers into memory
-                let phdr_mem_addr = load_program_headers_into_memory(&header, file_obj, task)?;
-                ProgramHeadersInfo {
-                    phdr_addr: phdr_mem_addr,
-                    phdr_size: header.e_phentsize as u64,
-                    phdr_count: header.e_phnum as u64,
-                }
+            // The program headers are usually loaded with the executable;
+            // otherwise they get pages of their own
+            let phdr_addr = match find_phdr_address(&header, file_obj, base_address)? {
+                Some(addr) => addr,
+                None => load_program_headers_into_memory(&header, file_obj, task)?,
+            };
+            let phdr_info = ProgramHeadersInfo {
+                phdr_addr,
+                phdr_size: header.e_phentsize as u64,
+                phdr_count: header.e_phnum as u64,
             };
             
             Ok(LoadElfResult {
@@ -619,6 +695,29 @@ fn find_interpreter_path(header: &ElfHeader, file_obj: &dyn FileObject) -> Resul
     Ok(result)
 }
 
+/// Find the PT_TLS segment of a program loaded at `base_address`
+fn find_tls_template(header: &ElfHeader, file_obj: &dyn FileObject, base_address: u64) -> Result<Option<TlsTemplate>, ElfLoaderError> {
+    let mut template = None;
+    for_each_program_header(header, file_obj, |_i, ph| {
+        if ph.p_type == PT_TLS {
+            if ph.p_filesz > ph.p_memsz || (ph.p_align > 1 && !ph.p_align.is_power_of_two()) {
+                return Err(ElfLoaderError {
+                    message: format!("Invalid TLS segment (filesz {:#x}, memsz {:#x}, align {:#x})", ph.p_filesz, ph.p_memsz, ph.p_align),
+                });
+            }
+            template = Some(TlsTemplate {
+                image_addr: base_address + ph.p_vaddr,
+                filesz: ph.p_filesz,
+                memsz: ph.p_memsz,
+                align: ph.p_align.max(1),
+            });
+            return Ok(false); // Only one TLS segment per program
+        }
+        Ok(true)
+    })?;
+    Ok(template)
+}
+
 /// Load ELF segments for dynamic execution (without executing)
 fn load_elf_segments_for_interpreter(header: &ElfHeader, file_obj: &dyn FileObject, task: &mut Task, strategy: &LoadStrategy) -> Result<u64, ElfLoaderError> {
     // Use strategy to determine base address
@@ -626,27 +725,42 @@ fn load_elf_segments_for_interpreter(header: &ElfHeader, file_obj: &dyn FileObje
     let base_address = (strategy.choose_base_address)(LoadTarget::MainProgram, needs_relocation);
     
     // Load PT_LOAD segments using simplified approach
+    let mut image_end = 0;
     for_each_program_header(header, file_obj, |_i, ph| {
         if ph.p_type == PT_LOAD {
             let segment_addr = base_address + ph.p_vaddr;
             load_elf_segment_at_address(ph, file_obj, task, segment_addr)?;
+            image_end = image_end.max(segment_addr + ph.p_memsz);
         }
         Ok(true) // Continue iteration
     })?;
+    set_initial_brk(task, image_end);
     
     Ok(base_address)
 }
 
-/// Load interpreter (dynamic linker) into task memory  
+/// Start the (empty) heap of `task` right after the main program's image
+fn set_initial_brk(task: &mut Task, image_end: u64) {
+    let image_end = image_end as usize;
+    task.vm_manager.write().set_program_break(image_end, image_end);
+}
+
 /// Maximum recursion depth for interpreter loading to prevent infinite loops
 const MAX_INTERPRETER_DEPTH: usize = 5;
 
-fn load_interpreter(interpreter_path: &str, task: &mut Task, strategy: &LoadStrategy) -> Result<u64, ElfLoaderError> {
-    load_interpreter_recursive(interpreter_path, task, strategy, 0)
+/// Load interpreter (dynamic linker) into task memory
+///
+/// # Returns
+/// The entry point of the interpreter and the base address it was loaded at
+fn load_interpreter(interpreter_path: &str, task: &mut Task, strategy: &LoadStrategy, ei_class: u8) -> Result<(u64, u64), ElfLoaderError> {
+    load_interpreter_recursive(interpreter_path, task, strategy, ei_class, 0)
 }
 
 /// Recursive interpreter loading with depth limiting
-fn load_interpreter_recursive(interpreter_path: &str, task: &mut Task, strategy: &LoadStrategy, depth: usize) -> Result<u64, ElfLoaderError> {
+///
+/// The interpreter must be of the program's ELF class (`ei_class`), as it
+/// runs with the same register width.
+fn load_interpreter_recursive(interpreter_path: &str, task: &mut Task, strategy: &LoadStrategy, ei_class: u8, depth: usize) -> Result<(u64, u64), ElfLoaderError> {
     // Check recursion depth to prevent infinite loops
     if depth >= MAX_INTERPRETER_DEPTH {
         return Err(ElfLoaderError {
@@ -700,16 +814,21 @@ fn load_interpreter_recursive(interpreter_path: &str, task: &mut Task, strategy:
     let interp_header = ElfHeader::parse(&header_buffer).map_err(|e| ElfLoaderError {
         message: format!("Failed to parse interpreter ELF header: {}", e.message),
     })?;
+    if interp_header.ei_class != ei_class {
+        return Err(ElfLoaderError {
+            message: format!("Interpreter '{}' does not match the ELF class of the program", interpreter_path),
+        });
+    }
     
     // Step 3: Check if this interpreter itself has an interpreter (recursive case)
     let nested_interpreter_path = find_interpreter_path(&interp_header, file_object)?;
-    let final_entry_point = if let Some(nested_path) = nested_interpreter_path {
+    let (final_entry_point, final_base) = if let Some(nested_path) = nested_interpreter_path {
         let resolved_nested_path = (strategy.resolve_interpreter)(Some(&nested_path))
             .unwrap_or(nested_path);
         crate::println!("Interpreter {} requests nested interpreter: {}", interpreter_path, resolved_nested_path);
         
         // Recursively load the nested interpreter first
-        load_interpreter_recursive(&resolved_nested_path, task, strategy, depth + 1)?
+        load_interpreter_recursive(&resolved_nested_path, task, strategy, ei_class, depth + 1)?
     } else {
         // No nested interpreter, load this interpreter normally
         let interp_needs_relocation = interp_header.e_type == ET_DYN;
@@ -723,14 +842,38 @@ fn load_interpreter_recursive(interpreter_path: &str, task: &mut Task, strategy:
         
         // Calculate actual entry point
         if interp_needs_relocation {
-            interpreter_base + interp_header.e_entry as u64
+            (interpreter_base + interp_header.e_entry as u64, interpreter_base)
         } else {
-            interp_header.e_entry
+            (interp_header.e_entry, 0)
         }
     };
     
     crate::println!("Interpreter entry point (depth {}): {:#x}", depth, final_entry_point);
-    Ok(final_entry_point)
+    Ok((final_entry_point, final_base))
+}
+
+/// Find where the program header table of a program is in memory once the
+/// program is loaded at `base_address`: at its PT_PHDR segment, or inside
+/// the PT_LOAD segment that covers it in the file
+///
+/// # Returns
+/// `None` if no loaded segment contains the table
+fn find_phdr_address(header: &ElfHeader, file_obj: &dyn FileObject, base_address: u64) -> Result<Option<u64>, ElfLoaderError> {
+    let table_size = header.e_phentsize as u64 * header.e_phnum as u64;
+    let mut phdr_segment = None;
+    let mut covering_load = None;
+    for_each_program_header(header, file_obj, |_i, ph| {
+        if ph.p_type == PT_PHDR {
+            phdr_segment = Some(base_address + ph.p_vaddr);
+            return Ok(false);
+        }
+        let covers = ph.p_offset <= header.e_phoff && header.e_phoff + table_size <= ph.p_offset + ph.p_filesz;
+        if ph.p_type == PT_LOAD && covers && covering_load.is_none() {
+            covering_load = Some(base_address + ph.p_vaddr + (header.e_phoff - ph.p_offset));
+        }
+        Ok(true)
+    })?;
+    Ok(phdr_segment.or(covering_load))
 }
 
 /// Load ELF segments for interpreter with specified base address
@@ -747,61 +890,26 @@ fn load_elf_segments_with_base(header: &ElfHeader, file_obj: &dyn FileObject, ta
     Ok(())
 }
 
-/// Load ELF using the static linking logic with strategy support
-fn load_elf_into_task_static(header: &ElfHeader, file_obj: &dyn FileObject, task: &mut Task, strategy: &LoadStrategy) -> Result<u64, ElfLoaderError> {
-    // Use strategy to determine base address for main program
+/// Load ELF using the static linking logic at the base address chosen by the strategy
+fn load_elf_into_task_static(header: &ElfHeader, file_obj: &dyn FileObject, task: &mut Task, base_address: u64) -> Result<u64, ElfLoaderError> {
     let needs_relocation = header.e_type == ET_DYN;
-    // let needs_relocation = false;
-
-    let base_address = (strategy.choose_base_address)(LoadTarget::MainProgram, needs_relocation);
+    let mut image_end = 0;
     // Read program headers and load LOAD segments (existing logic)
     for_each_program_header(header, file_obj, |_i, ph| {
         // For LOAD segments, load them into memory
         if ph.p_type == PT_LOAD {
             // Calculate proper alignment-aware mapping with base address
             let segment_addr = base_address + ph.p_vaddr;
+            image_end = image_end.max(segment_addr + ph.p_memsz);
             let align = ph.p_align as usize;
             
-            // Calculate the final mapping parameters according to ELF specification
-            let (mapping_addr, aligned_size, effective_align) = if align == 0 || align == 1 {
-                // No specific alignment requirement, use page alignment
-                let page_offset = (segment_addr as usize) % PAGE_SIZE;
-                let mapping_start = (segment_addr as usize) - page_offset;
-                let mapping_size = (ph.p_memsz as usize) + page_offset;
-                let aligned_size = (mapping_size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
-                (mapping_start, aligned_size, PAGE_SIZE)
-            } else {
-                // Use p_align, but ensure it's at least PAGE_SIZE for memory mapping
-                let effective_align = core::cmp::max(align, PAGE_SIZE);
-                
-                // Calculate aligned base address following ELF specification:
-                // The aligned base should satisfy: (vaddr % p_align) == (offset % p_align)
-                let vaddr_offset = (segment_addr as usize) % align;
-                let file_offset = (ph.p_offset as usize) % align;
-                
-                // Ensure the alignment relationship is preserved
-                if vaddr_offset != file_offset {
-                    // Adjust the base address to maintain the required relationship
-                    let adjustment = if vaddr_offset >= file_offset {
-                        vaddr_offset - file_offset
-                    } else {
-                        align - (file_offset - vaddr_offset)
-                    };
-                    let adjusted_segment_addr = (segment_addr as usize) - adjustment;
-                    let aligned_addr = (adjusted_segment_addr) & !(effective_align - 1);
-                    let offset = (segment_addr as usize) - aligned_addr;
-                    let mapping_size = (ph.p_memsz as usize) + offset;
-                    let aligned_size = (mapping_size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
-                    (aligned_addr, aligned_size, effective_align)
-                } else {
-                    // Normal case: alignment relationship is already correct
-                    let aligned_addr = (segment_addr as usize) & !(effective_align - 1);
-                    let offset = (segment_addr as usize) - aligned_addr;
-                    let mapping_size = (ph.p_memsz as usize) + offset;
-                    let aligned_size = (mapping_size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
-                    (aligned_addr, aligned_size, effective_align)
-                }
-            };
+            // Map exactly the pages the segment occupies: widening the mapping
+            // to p_align would give parts of the neighbouring segments this
+            // segment's permissions
+            let effective_align = if align == 0 || align == 1 { PAGE_SIZE } else { core::cmp::max(align, PAGE_SIZE) };
+            let page_offset = (segment_addr as usize) % PAGE_SIZE;
+            let mapping_addr = (segment_addr as usize) - page_offset;
+            let aligned_size = ((ph.p_memsz as usize) + page_offset + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
             
             // Map the segment with calculated parameters
             map_elf_segment(task, mapping_addr, aligned_size, effective_align, ph.p_flags).map_err(|e| ElfLoaderError {
@@ -824,11 +932,8 @@ fn load_elf_into_task_static(header: &ElfHeader, file_obj: &dyn FileObject, task
                 VirtualMemoryRegion::Data => {
                     task.data_size += aligned_size as usize;
                 },
-                _ => {
-                    return Err(ElfLoaderError {
-                        message: format!("Unknown segment type: {:#x}", ph.p_flags),
-                    });
-                }
+                // A segment without R/W/X flags is mapped inaccessible
+                _ => {}
             }
             
             // Load segment data using common function (if there's file data)
@@ -865,7 +970,7 @@ fn load_elf_into_task_static(header: &ElfHeader, file_obj: &dyn FileObject, task
                 //     crate::println!("  data: {}", hex_str);
                 // }
                 
-                match task.vm_manager.translate_vaddr(target_vaddr) {
+                match task.vm_manager.read().translate_vaddr(target_vaddr) {
                     Some(paddr) => {
                         unsafe {
                             core::ptr::copy_nonoverlapping(
@@ -886,6 +991,8 @@ fn load_elf_into_task_static(header: &ElfHeader, file_obj: &dyn FileObject, task
         Ok(true) // Continue iteration
     })?;
 
+    set_initial_brk(task, image_end);
+
     // Return entry point adjusted for base address
     let final_entry_point = if needs_relocation {
         base_address + header.e_entry
@@ -950,7 +1057,7 @@ fn load_program_headers_into_memory(
     })?;
     
     // Copy program headers to task memory
-    match task.vm_manager.translate_vaddr(phdr_vaddr as usize) {
+    match task.vm_manager.read().translate_vaddr(phdr_vaddr as usize) {
         Some(paddr) => {
             unsafe {
                 core::ptr::copy_nonoverlapping(
@@ -1003,6 +1110,10 @@ fn map_elf_segment(task: &mut Task, vaddr: usize, size: usize, align: usize, fla
     }
     if task.task_type == TaskType::User {
         permissions |= VirtualMemoryPermission::User as usize;
+        // Segments are mapped with their flags as-is, so an RWX segment
+        // cannot be loaded while W^X is enforced
+        wx::check_user_mapping(task.get_id(), vaddr, size, permissions, "ELF segment")
+            .map_err(|_| "Writable and executable segment violates W^X")?;
     }
 
     // Create memory area
@@ -1012,7 +1123,7 @@ fn map_elf_segment(task: &mut Task, vaddr: usize, size: usize, align: usize, fla
     };
 
     // Check if the area is overlapping with existing mappings
-    if task.vm_manager.search_memory_map(vaddr).is_some() {
+    if task.vm_manager.read().search_memory_map(vaddr).is_some() {
         return Err("Memory area overlaps with existing mapping");
     }
 
@@ -1035,10 +1146,11 @@ fn map_elf_segment(task: &mut Task, vaddr: usize, size: usize, align: usize, fla
         permissions,
         is_shared: false, // User program memory should not be shared
         owner: None,
+        zero_fill: None,
     };
 
     // Add to VM manager
-     if let Err(e) = task.vm_manager.add_memory_map(map) {
+     if let Err(e) = task.vm_manager.write().add_memory_map(map) {
         free_raw_pages(pages, num_of_pages);
         return Err(e);
     }
@@ -1054,9 +1166,15 @@ fn map_elf_segment(task: &mut Task, vaddr: usize, size: usize, align: usize, fla
     Ok(())
 }
 
-/// Build auxiliary vector for dynamic linking
+/// Build the auxiliary vector of a program, for static and dynamic
+/// executables alike
+///
+/// The IDs and `AT_SECURE` come from `cred`. `AT_RANDOM` and `AT_EXECFN`
+/// point into the initial stack, so they are left 0 here and filled in by
+/// whoever lays out the stack (see [`setup_auxiliary_vector_on_stack`]).
 pub fn build_auxiliary_vector(
     load_result: &LoadElfResult,
+    cred: &Credentials,
 ) -> alloc::vec::Vec<AuxVec> {
     use crate::environment::PAGE_SIZE;
     
@@ -1072,36 +1190,29 @@ pub fn build_auxiliary_vector(
     
     // Entry point of main program (not the interpreter)
     // For dynamic executables, AT_ENTRY should be the original program's entry point
-    match &load_result.mode {
-        ExecutionMode::Dynamic { .. } => {
-            // For dynamic executables, use the original program's entry point
-            if let Some(orig_entry) = load_result.original_entry_point {
-                auxv.push(AuxVec::new(AT_ENTRY, orig_entry));
-            }
-        }
-        ExecutionMode::Static => {
-            // For static executables, entry point is load_result.entry_point
-            auxv.push(AuxVec::new(AT_ENTRY, load_result.entry_point));
-        }
-    }
+    let program_entry = match &load_result.mode {
+        ExecutionMode::Dynamic { .. } => load_result.original_entry_point.unwrap_or(load_result.entry_point),
+        ExecutionMode::Static => load_result.entry_point,
+    };
+    auxv.push(AuxVec::new(AT_ENTRY, program_entry));
     
     // Base address of interpreter (if dynamically linked)
     if let Some(interp_base) = load_result.interpreter_base {
         auxv.push(AuxVec::new(AT_BASE, interp_base));
     }
     
-    // Add UID/GID entries to prevent musl secure mode
-    // Set all IDs to 0 (root) to make real and effective IDs equal
-    // This prevents libc.secure from being set to true
-    auxv.push(AuxVec::new(AT_UID, 0));    // Real user ID
-    auxv.push(AuxVec::new(AT_EUID, 0));   // Effective user ID  
-    auxv.push(AuxVec::new(AT_GID, 0));    // Real group ID
-    auxv.push(AuxVec::new(AT_EGID, 0));   // Effective group ID
+    // The C library enters secure mode (ignoring LD_* variables and the
+    // like) when the real and effective IDs differ
+    auxv.push(AuxVec::new(AT_UID, cred.uid as u64));
+    auxv.push(AuxVec::new(AT_EUID, cred.euid as u64));
+    auxv.push(AuxVec::new(AT_GID, cred.gid as u64));
+    auxv.push(AuxVec::new(AT_EGID, cred.egid as u64));
+    let secure = cred.uid != cred.euid || cred.gid != cred.egid;
+    auxv.push(AuxVec::new(AT_SECURE, secure as u64));
     
-    // TODO: Add more auxiliary vector entries as needed:
-    // - AT_RANDOM: Random bytes for stack canaries
-    // - AT_PLATFORM: Platform string
-    // - AT_HWCAP: Hardware capabilities
+    // Filled in when the stack is laid out
+    auxv.push(AuxVec::new(AT_RANDOM, 0));
+    auxv.push(AuxVec::new(AT_EXECFN, 0));
     
     // Terminate auxiliary vector
     auxv.push(AuxVec::new(AT_NULL, 0));
@@ -1112,30 +1223,55 @@ pub fn build_auxiliary_vector(
 /// Setup auxiliary vector on the task's stack
 /// 
 /// This function places the auxiliary vector at the top of the stack,
-/// which is expected by the dynamic linker and C runtime.
+/// which is expected by the dynamic linker and C runtime. The path of the
+/// program (`task.exec_path`) and 16 random bytes are placed above it for
+/// `AT_EXECFN` and `AT_RANDOM`.
+/// 
+/// # Arguments
+/// * `task` - The task whose stack receives the vector
+/// * `auxv` - The auxiliary vector entries
+/// * `stack_top` - Top of the user stack (as returned by `setup_user_stack`)
+///
+/// # Returns
+/// The address of the vector, below which the stack continues
 pub fn setup_auxiliary_vector_on_stack(
     task: &mut Task,
     auxv: &[AuxVec],
+    stack_top: usize,
 ) -> Result<usize, ElfLoaderError> {
-    // Calculate size needed for auxiliary vector
+    let copy_error = |_| ElfLoaderError { message: "Failed to write the initial stack".to_string() };
+    let execfn = task.exec_path.clone();
+    let execfn_addr = stack_top - (execfn.len() + 1);
+    copy_to_user(task, execfn_addr, execfn.as_bytes()).map_err(copy_error)?;
+    copy_to_user(task, execfn_addr + execfn.len(), &[0]).map_err(copy_error)?;
+
+    let mut random = [0u8; 16];
+    crate::random::fill_random_bytes(&mut random);
+    let random_addr = (execfn_addr - random.len()) & !15;
+    copy_to_user(task, random_addr, &random).map_err(copy_error)?;
+
     // Each AuxVec entry is 16 bytes (two u64 values)
     let auxv_size = auxv.len() * core::mem::size_of::<AuxVec>();
     
-    // Find the top of the stack
-    let stack_top = crate::environment::USER_STACK_END;
-    let auxv_start = stack_top - auxv_size;
+    let auxv_start = random_addr - auxv_size;
     
     // Write auxiliary vector to stack
     for (i, entry) in auxv.iter().enumerate() {
         let offset = i * core::mem::size_of::<AuxVec>();
         let vaddr = auxv_start + offset;
         
+        let entry = match entry.a_type {
+            AT_RANDOM => AuxVec::new(AT_RANDOM, random_addr as u64),
+            AT_EXECFN => AuxVec::new(AT_EXECFN, execfn_addr as u64),
+            _ => *entry,
+        };
+        
         // Translate to physical address and write
-        match task.vm_manager.translate_vaddr(vaddr) {
+        match task.vm_manager.read().translate_vaddr(vaddr) {
             Some(paddr) => {
                 unsafe {
                     let ptr = paddr as *mut AuxVec;
-                    ptr.write(*entry);
+                    ptr.write(entry);
                 }
             },
             None => {
@@ -1189,7 +1325,7 @@ fn load_elf_segment_at_address(
         let data_offset = (segment_addr as usize) - mapping_start;
         let target_vaddr = mapping_start + data_offset;
         
-        match task.vm_manager.translate_vaddr(target_vaddr) {
+        match task.vm_manager.read().translate_vaddr(target_vaddr) {
             Some(paddr) => {
                 unsafe {
                     core::ptr::copy_nonoverlapping(
@@ -0,0 +1,111 @@
+//! ABI notes of ELF programs
+//!
+//! A program may say which ABI it is built for in a note of a PT_NOTE
+//! segment, which settles the ABI choice that would otherwise rest on the
+//! OSABI byte and the path of the file:
+//!
+//! - **GNU ABI tag** (`NT_GNU_ABI_TAG` of owner `GNU`, the `.note.ABI-tag`
+//!   section of glibc programs): the OS and the oldest kernel version the
+//!   program runs on
+//! - **Scarlet ABI tag** (`NT_SCARLET_ABI_TAG` of owner `Scarlet`): the
+//!   version the program expects, as three 32-bit words, then the name of
+//!   the ABI, NUL-terminated
+//!
+//! The note found is offered to the ABIs, which negotiate the version the
+//! program runs with (see `AbiModule::negotiate_version`).
+
+use alloc::string::String;
+use alloc::vec;
+
+use crate::abi::AbiVersion;
+use crate::fs::{FileObject, SeekFrom};
+
+use super::{for_each_program_header, read_u32, ElfHeader, ELFDATA2LSB};
+
+/// Segment of notes
+pub const PT_NOTE: u32 = 4;
+
+/// Note type of the GNU ABI tag
+pub const NT_GNU_ABI_TAG: u32 = 1;
+/// Note type of the Scarlet ABI tag
+pub const NT_SCARLET_ABI_TAG: u32 = 1;
+
+/// OS of a GNU ABI tag for Linux programs
+pub const GNU_ABI_TAG_LINUX: u32 = 0;
+
+/// Largest note segment read
+const MAX_NOTE_SEGMENT: u64 = 4096;
+
+/// ABI named by a note
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AbiNote {
+    /// GNU ABI tag: the OS and the oldest kernel version to run on
+    Gnu { os: u32, version: AbiVersion },
+    /// Scarlet ABI tag: the ABI by name and the version it is built for
+    Scarlet { abi: String, version: AbiVersion },
+}
+
+/// Round a note field size up to the 4-byte alignment of notes
+fn note_align(size: usize) -> usize {
+    (size + 3) & !3
+}
+
+/// Find the first ABI note in the content of a note segment
+pub fn parse_abi_note(data: &[u8], is_little_endian: bool) -> Option<AbiNote> {
+    let mut offset = 0;
+    while offset + 12 <= data.len() {
+        let namesz = read_u32(data, offset, is_little_endian) as usize;
+        let descsz = read_u32(data, offset + 4, is_little_endian) as usize;
+        let n_type = read_u32(data, offset + 8, is_little_endian);
+        let name_start = offset + 12;
+        let desc_start = name_start.checked_add(note_align(namesz))?;
+        let desc_end = desc_start.checked_add(descsz)?;
+        let desc = data.get(desc_start..desc_end)?;
+        // The name is NUL-terminated
+        let name = data.get(name_start..name_start + namesz)?;
+        let name = name.strip_suffix(&[0]).unwrap_or(name);
+        let word = |index: usize| read_u32(desc, index * 4, is_little_endian);
+
+        match (name, n_type) {
+            (b"GNU", NT_GNU_ABI_TAG) if descsz >= 16 => {
+                return Some(AbiNote::Gnu { os: word(0), version: AbiVersion::new(word(1), word(2), word(3)) });
+            }
+            (b"Scarlet", NT_SCARLET_ABI_TAG) if descsz > 12 => {
+                let abi = &desc[12..];
+                let abi = &abi[..abi.iter().position(|&byte| byte == 0).unwrap_or(abi.len())];
+                if let Ok(abi) = core::str::from_utf8(abi) {
+                    return Some(AbiNote::Scarlet {
+                        abi: String::from(abi),
+                        version: AbiVersion::new(word(0), word(1), word(2)),
+                    });
+                }
+            }
+            _ => {}
+        }
+        offset = desc_start + note_align(descsz);
+    }
+    None
+}
+
+/// Read the ABI note of an ELF program, if it has one
+pub fn read_abi_note(file_obj: &dyn FileObject) -> Option<AbiNote> {
+    let mut header_buffer = [0u8; 64];
+    file_obj.seek(SeekFrom::Start(0)).ok()?;
+    let read = file_obj.read(&mut header_buffer).ok()?;
+    let header = ElfHeader::parse(&header_buffer[..read]).ok()?;
+    let is_little_endian = header.ei_data == ELFDATA2LSB;
+
+    let mut note = None;
+    for_each_program_header(&header, file_obj, |_i, ph| {
+        if ph.p_type != PT_NOTE || ph.p_filesz > MAX_NOTE_SEGMENT {
+            return Ok(true);
+        }
+        let mut data = vec![0u8; ph.p_filesz as usize];
+        let read = file_obj.seek(SeekFrom::Start(ph.p_offset))
+            .and_then(|_| file_obj.read(&mut data))
+            .unwrap_or(0);
+        note = parse_abi_note(&data[..read], is_little_endian);
+        Ok(note.is_none())
+    }).ok()?;
+    note
+}
@@ -115,7 +115,7 @@ fn test_load_elf() {
     let entry_point = load_elf_into_task(file, &mut task).expect("Failed to load ELF file");
     
     // Translate the entry point virtual address to a physical address
-    let paddr = task.vm_manager.translate_vaddr(entry_point as usize).expect(format!("Failed to translate entry point address: {:#x}", entry_point).as_str());
+    let paddr = task.vm_manager.read().translate_vaddr(entry_point as usize).expect(format!("Failed to translate entry point address: {:#x}", entry_point).as_str());
 
     // Read the instruction at the entry point
     let instruction: u32;
@@ -130,6 +130,55 @@ fn test_load_elf() {
     assert_eq!(instruction, expected_instruction, "Entry point instruction does not match expected value");
 }
 
+#[test_case]
+fn test_parse_elf32_headers() {
+    // A 52-byte ELF32 header followed by one 32-byte program header
+    let mut elf_data = vec![0u8; 52];
+    elf_data[EI_MAG0] = ELFMAG[0];
+    elf_data[EI_MAG1] = ELFMAG[1];
+    elf_data[EI_MAG2] = ELFMAG[2];
+    elf_data[EI_MAG3] = ELFMAG[3];
+    elf_data[EI_CLASS] = ELFCLASS32;
+    elf_data[EI_DATA] = ELFDATA2LSB;
+    elf_data[16] = 0x2; // e_type
+    elf_data[18] = 0xF3; // e_machine
+    elf_data[20] = 0x1; // e_version
+    elf_data[24..28].copy_from_slice(&0x10074u32.to_le_bytes()); // e_entry
+    elf_data[28] = 52; // e_phoff
+    elf_data[40] = 52; // e_ehsize
+    elf_data[42] = 32; // e_phentsize
+    elf_data[44] = 1; // e_phnum
+
+    elf_data.extend_from_slice(&PT_LOAD.to_le_bytes()); // p_type
+    elf_data.extend_from_slice(&0u32.to_le_bytes()); // p_offset
+    elf_data.extend_from_slice(&0x10000u32.to_le_bytes()); // p_vaddr
+    elf_data.extend_from_slice(&0x10000u32.to_le_bytes()); // p_paddr
+    elf_data.extend_from_slice(&0x100u32.to_le_bytes()); // p_filesz
+    elf_data.extend_from_slice(&0x200u32.to_le_bytes()); // p_memsz
+    elf_data.extend_from_slice(&(PF_R | PF_X).to_le_bytes()); // p_flags
+    elf_data.extend_from_slice(&0x1000u32.to_le_bytes()); // p_align
+
+    let header = ElfHeader::parse(&elf_data).expect("Failed to parse ELF32 header");
+    assert!(!header.is_64bit());
+    assert_eq!(header.e_entry, 0x10074, "Unexpected entry point address");
+    assert_eq!(header.e_phoff, 52, "Unexpected program header offset");
+    assert_eq!(header.e_phentsize, 32, "Unexpected program header entry size");
+    assert_eq!(header.e_phnum, 1, "Unexpected number of program headers");
+
+    let program_header = ProgramHeader::parse32(&elf_data[52..84], true)
+        .expect("Failed to parse ELF32 program header");
+    assert_eq!(program_header.p_type, PT_LOAD);
+    assert_eq!(program_header.p_vaddr, 0x10000);
+    assert_eq!(program_header.p_filesz, 0x100);
+    assert_eq!(program_header.p_memsz, 0x200);
+    assert_eq!(program_header.p_flags, PF_R | PF_X);
+    assert_eq!(program_header.p_align, 0x1000);
+
+    // Other classes are rejected
+    elf_data[EI_CLASS] = 3;
+    assert!(ElfHeader::parse(&elf_data).is_err());
+}
+
 #[test_case]
 fn test_load_elf_invalid_magic() {
     use crate::task::elf_loader::load_elf_into_task;
@@ -259,7 +308,7 @@ fn test_load_elf_bss_zeroed() {
     // Verify that the .bss section is zeroed
     let bss_start = 0x1000; // Virtual address of .bss section (aligned to PAGE_SIZE)
     let bss_size = 0x2000; // Size of .bss section (2 * PAGE_SIZE)
-    let paddr = task.vm_manager.translate_vaddr(bss_start).expect("Failed to translate .bss start address");
+    let paddr = task.vm_manager.read().translate_vaddr(bss_start).expect("Failed to translate .bss start address");
 
     for i in 0..bss_size {
         let byte: u8;
@@ -270,3 +319,68 @@ fn test_load_elf_bss_zeroed() {
     }
 }
 
+
+#[test_case]
+fn test_parse_abi_notes() {
+    use crate::abi::AbiVersion;
+    use alloc::vec::Vec;
+    use note::{parse_abi_note, AbiNote, GNU_ABI_TAG_LINUX};
+
+    fn push_note(data: &mut Vec<u8>, name: &[u8], n_type: u32, desc: &[u8]) {
+        data.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        data.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+        data.extend_from_slice(&n_type.to_le_bytes());
+        for field in [name, desc] {
+            data.extend_from_slice(field);
+            data.resize((data.len() + 3) & !3, 0);
+        }
+    }
+
+    // A note of another kind comes first, then the GNU ABI tag
+    let mut data = Vec::new();
+    push_note(&mut data, b"GNU\0", 3, &[0xab; 20]);
+    let words: Vec<u8> = [GNU_ABI_TAG_LINUX, 4, 15, 0].iter().flat_map(|word| word.to_le_bytes()).collect();
+    push_note(&mut data, b"GNU\0", 1, &words);
+    assert_eq!(
+        parse_abi_note(&data, true),
+        Some(AbiNote::Gnu { os: GNU_ABI_TAG_LINUX, version: AbiVersion::new(4, 15, 0) })
+    );
+
+    let mut data = Vec::new();
+    let mut desc: Vec<u8> = [1u32, 2, 3].iter().flat_map(|word| word.to_le_bytes()).collect();
+    desc.extend_from_slice(b"xv6-riscv64\0");
+    push_note(&mut data, b"Scarlet\0", 1, &desc);
+    assert_eq!(
+        parse_abi_note(&data, true),
+        Some(AbiNote::Scarlet { abi: "xv6-riscv64".to_string(), version: AbiVersion::new(1, 2, 3) })
+    );
+
+    // A truncated note is ignored
+    assert_eq!(parse_abi_note(&data[..data.len() - 8], true), None);
+    assert!(AbiVersion::new(4, 15, 0) < AbiVersion::new(6, 1, 0));
+}
+
+#[test_case]
+fn test_build_auxiliary_vector() {
+    let result = LoadElfResult {
+        mode: ExecutionMode::Dynamic { interpreter_path: "/lib/ld.so".to_string() },
+        entry_point: 0x4000_1000,
+        original_entry_point: Some(0x1_1000),
+        base_address: Some(0x1_0000),
+        interpreter_base: Some(0x4000_0000),
+        program_headers: ProgramHeadersInfo { phdr_addr: 0x1_0040, phdr_size: 56, phdr_count: 9 },
+    };
+    let mut cred = crate::task::cred::Credentials::root();
+    cred.euid = 1000;
+    let auxv = build_auxiliary_vector(&result, &cred);
+    let value = |a_type| auxv.iter().find(|entry| entry.a_type == a_type).map(|entry| entry.a_val);
+
+    assert_eq!(value(AT_PHDR), Some(0x1_0040));
+    assert_eq!(value(AT_PHNUM), Some(9));
+    // The program's entry, not the interpreter's
+    assert_eq!(value(AT_ENTRY), Some(0x1_1000));
+    assert_eq!(value(AT_BASE), Some(0x4000_0000));
+    assert_eq!(value(AT_SECURE), Some(1));
+    assert!(value(AT_RANDOM).is_some() && value(AT_EXECFN).is_some());
+    assert_eq!(auxv.last().map(|entry| entry.a_type), Some(AT_NULL));
+}
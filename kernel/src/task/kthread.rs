@@ -0,0 +1,211 @@
+//! Kernel threads
+//!
+//! [`spawn`] runs a closure in a new kernel-mode task managed by the
+//! scheduler like any other task, so subsystems that need a background
+//! worker do not have to set up the task themselves:
+//!
+//! ```
+//! let worker = kthread::spawn("flush", || {
+//!     while !kthread::should_stop() {
+//!         if kthread::should_park() {
+//!             kthread::parkme();
+//!             continue;
+//!         }
+//!         // do some work, then wait for more
+//!     }
+//! });
+//! worker.stop();
+//! ```
+//!
+//! Stopping and parking are cooperative: the owner of a [`KThread`] only
+//! requests them, and the thread checks [`should_stop`] and [`should_park`]
+//! at points where it is safe to do so. A parked thread sleeps in
+//! [`parkme`] until it is unparked or stopped. When the closure returns the
+//! task exits.
+
+extern crate alloc;
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::ToString;
+use alloc::sync::Arc;
+use spin::Mutex;
+
+use crate::sched::scheduler::get_scheduler;
+use crate::sync::waker::Waker;
+
+use super::{mytask, new_kernel_task};
+
+/// State shared between a kernel thread and its handle
+struct KThreadControl {
+    /// The closure to run, taken when the thread starts
+    func: Mutex<Option<Box<dyn FnOnce() + Send>>>,
+    stop: AtomicBool,
+    park: AtomicBool,
+    exited: AtomicBool,
+    /// Woken on unpark and stop
+    park_waker: Waker,
+    /// Woken when the thread exits
+    exit_waker: Waker,
+}
+
+impl KThreadControl {
+    fn new(func: Box<dyn FnOnce() + Send>) -> Self {
+        Self {
+            func: Mutex::new(Some(func)),
+            stop: AtomicBool::new(false),
+            park: AtomicBool::new(false),
+            exited: AtomicBool::new(false),
+            park_waker: Waker::new_interruptible("kthread_park"),
+            exit_waker: Waker::new_uninterruptible("kthread_exit"),
+        }
+    }
+}
+
+/// Controls of the running kernel threads, by task ID
+static KTHREADS: Mutex<BTreeMap<usize, Arc<KThreadControl>>> = Mutex::new(BTreeMap::new());
+
+/// Handle to a kernel thread created by [`spawn`]
+pub struct KThread {
+    task_id: usize,
+    control: Arc<KThreadControl>,
+}
+
+impl KThread {
+    /// Task ID of the thread
+    pub fn id(&self) -> usize {
+        self.task_id
+    }
+
+    /// Ask the thread to park at its next [`parkme`]
+    pub fn park(&self) {
+        self.control.park.store(true, Ordering::Release);
+    }
+
+    /// Let a parked thread continue
+    pub fn unpark(&self) {
+        self.control.park.store(false, Ordering::Release);
+        self.control.park_waker.wake_all();
+    }
+
+    /// Whether the thread has returned from its closure
+    pub fn is_finished(&self) -> bool {
+        self.control.exited.load(Ordering::Acquire)
+    }
+
+    fn request_stop(&self) {
+        self.control.stop.store(true, Ordering::Release);
+        self.unpark();
+    }
+
+    /// Ask the thread to stop and wait until it has exited
+    ///
+    /// A parked thread is unparked so that it can see the request. When not
+    /// called from a task (e.g. during boot) or from the thread itself, this
+    /// only makes the request.
+    pub fn stop(&self) {
+        self.request_stop();
+
+        let Some(task) = mytask() else { return };
+        let task_id = task.get_id();
+        if task_id == self.task_id {
+            return;
+        }
+        while !self.control.exit_waker.wait_unless(task_id, task.get_trapframe(), || self.is_finished()) {}
+    }
+}
+
+/// Start a kernel thread running `func`
+///
+/// The thread goes to the least loaded CPU and exits when `func` returns.
+pub fn spawn<F>(name: &str, func: F) -> KThread
+where
+    F: FnOnce() + Send + 'static,
+{
+    let mut task = new_kernel_task(name.to_string(), 0, kthread_entry);
+    task.init();
+    let task_id = task.get_id();
+    let control = Arc::new(KThreadControl::new(Box::new(func)));
+    // Registered before the task can run, so that it finds its closure
+    KTHREADS.lock().insert(task_id, control.clone());
+
+    let cpu_id = get_scheduler().select_cpu(task.cpu_affinity);
+    get_scheduler().add_task(task, cpu_id);
+    KThread { task_id, control }
+}
+
+/// Control of the calling kernel thread
+fn current_control() -> Option<Arc<KThreadControl>> {
+    let task_id = mytask()?.get_id();
+    KTHREADS.lock().get(&task_id).cloned()
+}
+
+/// Whether the calling kernel thread has been asked to stop
+///
+/// Always `false` outside a kernel thread.
+pub fn should_stop() -> bool {
+    current_control().is_some_and(|control| control.stop.load(Ordering::Acquire))
+}
+
+/// Whether the calling kernel thread has been asked to park
+///
+/// Always `false` outside a kernel thread.
+pub fn should_park() -> bool {
+    current_control().is_some_and(|control| control.park.load(Ordering::Acquire))
+}
+
+/// Sleep while the calling kernel thread is parked
+///
+/// Returns once the thread is unparked or asked to stop. Does nothing if no
+/// park was requested or when not called from a kernel thread.
+pub fn parkme() {
+    let Some(control) = current_control() else { return };
+    let task = mytask().unwrap();
+    let task_id = task.get_id();
+    let released = || !control.park.load(Ordering::Acquire) || control.stop.load(Ordering::Acquire);
+    while !control.park_waker.wait_unless(task_id, task.get_trapframe(), released) {}
+}
+
+/// Entry point of every kernel thread
+fn kthread_entry() {
+    let task = mytask().unwrap();
+    let task_id = task.get_id();
+    let control = current_control().expect("Kernel thread must be registered");
+
+    let func = control.func.lock().take();
+    if let Some(func) = func {
+        func();
+    }
+
+    KTHREADS.lock().remove(&task_id);
+    control.exited.store(true, Ordering::Release);
+    control.exit_waker.wake_all();
+    drop(control);
+    task.exit(0);
+    unreachable!("Kernel thread {} was scheduled after exit", task_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_kthread_stop_and_park_requests() {
+        let control = Arc::new(KThreadControl::new(Box::new(|| {})));
+        let thread = KThread { task_id: usize::MAX, control: control.clone() };
+
+        thread.park();
+        assert!(control.park.load(Ordering::Acquire));
+        thread.unpark();
+        assert!(!control.park.load(Ordering::Acquire));
+
+        // Stopping also releases a parked thread
+        thread.park();
+        thread.request_stop();
+        assert!(control.stop.load(Ordering::Acquire));
+        assert!(!control.park.load(Ordering::Acquire));
+        assert!(!thread.is_finished());
+    }
+}
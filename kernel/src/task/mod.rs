@@ -4,15 +4,46 @@
 
 pub mod syscall;
 pub mod elf_loader;
+pub mod rlimit;
+pub mod kthread;
+pub mod signal;
+pub mod rusage;
+pub mod coredump;
+pub mod spawn;
+pub mod cgroup;
+pub mod uts;
+pub mod cred;
+pub mod process_handle;
+pub mod strace;
 
 extern crate alloc;
 
 use alloc::{boxed::Box, string::{String, ToString}, sync::Arc, vec::Vec};
 use spin::Mutex;
 
-use crate::{arch::{Arch, KernelContext, Trapframe, get_cpu, trap::user::arch_switch_to_user_space, vcpu::Vcpu, vm::alloc_virtual_address_space}, environment::{DEAFAULT_MAX_TASK_DATA_SIZE, DEAFAULT_MAX_TASK_STACK_SIZE, DEAFAULT_MAX_TASK_TEXT_SIZE, KERNEL_VM_STACK_END, PAGE_SIZE, TASK_KERNEL_STACK_SIZE, USER_STACK_END}, fs::VfsManager, ipc::{EventContent, event::ProcessControlType}, mem::page::{Page, allocate_raw_pages, free_boxed_page}, object::handle::HandleTable, sched::scheduler::{Scheduler, get_scheduler}, timer::{TimerHandler, add_timer, get_tick}, vm::{manager::VirtualMemoryManager, user_kernel_vm_init, user_vm_init, vmem::{MemoryArea, VirtualMemoryMap, VirtualMemoryRegion}}};
-use crate::abi::{scarlet::ScarletAbi, AbiModule};
+use crate::{arch::{Arch, KernelContext, Trapframe, get_cpu, trap::user::arch_switch_to_user_space, vcpu::Vcpu, vm::alloc_virtual_address_space}, environment::{DEAFAULT_MAX_TASK_DATA_SIZE, DEAFAULT_MAX_TASK_STACK_SIZE, DEAFAULT_MAX_TASK_TEXT_SIZE, KERNEL_VM_STACK_END, NUM_OF_CPUS, PAGE_SIZE, TASK_KERNEL_STACK_SIZE, USER_STACK_END}, fs::VfsManager, ipc::{EventContent, event::ProcessControlType}, mem::page::{Page, allocate_raw_pages, free_boxed_page}, object::handle::HandleTable, sched::scheduler::{Scheduler, get_scheduler}, timer::{TimerHandler, add_timer, cancel_timer, get_tick}, vm::{manager::VirtualMemoryManager, user_kernel_vm_init, user_vm_init, vmem::{MemoryArea, VirtualMemoryMap, VirtualMemoryRegion}}};
+use crate::abi::{error::KernelError, scarlet::ScarletAbi, AbiModule, AbiVersion};
+use crate::vm::vmem::VirtualMemoryPermission;
+use crate::vm::vdso;
+use rlimit::{RLimit, Resource, ResourceLimits};
+use crate::sched::affinity::CpuMask;
+use crate::sched::priority::{clamp_nice, SchedPolicy};
+use crate::sched::stats::TaskSchedStats;
+use rlimit::RLIM_INFINITY;
+use signal::SignalState;
+use crate::fs::FileType;
+use crate::object::capability::file::SeekFrom;
+use crate::object::KernelObject;
+use crate::timer::get_time_us;
+use rusage::CpuTimes;
+use elf_loader::TlsTemplate;
+use cgroup::{GroupId, ROOT_GROUP};
+use uts::{init_uts_ns, UtsNamespace};
+use crate::network::namespace::{init_net_ns, NetNamespace};
+use cred::Credentials;
+use process_handle::ExitNotifier;
 use crate::sync::waker::Waker;
+use crate::sync::TaskShared;
 use alloc::collections::BTreeMap;
 use core::ops::Range;
 use spin::Once;
@@ -187,6 +218,9 @@ pub struct AbiZone {
 pub struct Task {
     id: usize,
     pub name: String,
+    /// Path of the program as given to the exec that started it
+    /// (`AT_EXECFN`), empty for a task that never exec'd
+    pub exec_path: String,
     pub priority: u32,
     pub vcpu: Vcpu,
     /// Kernel context for context switching
@@ -194,25 +228,86 @@ pub struct Task {
     pub state: TaskState,
     pub task_type: TaskType,
     pub entry: usize,
-    pub brk: Option<usize>, /* Program break (NOT work in Kernel task) */
     pub stack_size: usize, /* Size of the stack in bytes */
+    pub stack_top: usize, /* Top (exclusive end) of the user stack, 0 if the task has none */
     pub data_size: usize, /* Size of the data segment in bytes (page unit) (NOT work in Kernel task) */
     pub text_size: usize, /* Size of the text segment in bytes (NOT work in Kernel task) */
     pub max_stack_size: usize, /* Maximum size of the stack in bytes */
     pub max_data_size: usize, /* Maximum size of the data segment in bytes */
     pub max_text_size: usize, /* Maximum size of the text segment in bytes */
-    pub vm_manager: VirtualMemoryManager,
+    /// Adjustment added to the OOM badness score (-1000..=1000)
+    ///
+    /// -1000 makes the task immune to the OOM killer; 1000 makes it the
+    /// preferred victim. See `crate::mem::oom`.
+    pub oom_score_adj: i32,
+    /// Nice value (`NICE_MIN..=NICE_MAX`), scales the time slice
+    ///
+    /// See `crate::sched::priority`.
+    pub nice: i32,
+    /// Scheduling policy (normal or one of the real-time classes)
+    pub sched_policy: SchedPolicy,
+    /// Real-time priority (`RT_PRIO_MIN..=RT_PRIO_MAX`), 0 for normal tasks
+    pub rt_priority: u32,
+    /// CPUs the task may run on
+    ///
+    /// See `crate::sched::affinity`.
+    pub cpu_affinity: CpuMask,
+    /// Resource group the task belongs to
+    ///
+    /// See `crate::task::cgroup`.
+    pub cgroup: GroupId,
+    /// Resource limits (inherited by children and kept across exec)
+    ///
+    /// Change them with [`Task::set_rlimit`] so that limits enforced
+    /// elsewhere (the handle table) follow.
+    pub rlimits: ResourceLimits,
+    /// User and kernel time used by the task and its reaped children
+    ///
+    /// See `crate::task::rusage`.
+    pub cpu_times: CpuTimes,
+    /// Run and wait times and context switches
+    ///
+    /// See `crate::sched::stats`.
+    pub sched_stats: TaskSchedStats,
+    /// Whole seconds of CPU time already checked against RLIMIT_CPU
+    cpu_seconds_checked: u64,
+    /// Signal mask, pending signals and handlers
+    ///
+    /// See `crate::task::signal`.
+    pub signals: SignalState,
+    /// TLS template of the program (its PT_TLS segment), kept by threads
+    /// and children so that new threads get a TLS block
+    ///
+    /// See [`Task::setup_tls`].
+    pub tls_template: Option<TlsTemplate>,
+    /// TLS block mapped by the kernel for this task
+    tls_block: Option<MemoryArea>,
+    /// Address space, shared with the task's threads
+    pub vm_manager: TaskShared<VirtualMemoryManager>,
     /// Managed pages
     /// 
-    /// Managed pages are freed automatically when the task is terminated.
-    pub managed_pages: Vec<ManagedPage>,
+    /// Managed pages belong to the address space; they are freed
+    /// automatically when the last task using it is terminated.
+    pub managed_pages: TaskShared<Vec<ManagedPage>>,
     parent_id: Option<usize>,      /* Parent task ID */
     children: Vec<usize>,          /* List of child task IDs */
+    pgid: usize,                   /* Process group ID */
+    sid: usize,                    /* Session ID */
     exit_status: Option<i32>,      /* Exit code (for monitoring child task termination) */
 
     /// Default ABI for this task. Determined from ELF OSABI etc.
     pub default_abi: Box<dyn AbiModule + Send + Sync>,
 
+    /// Version of the default ABI the program runs with, negotiated from
+    /// the ABI note of the program (see `AbiModule::negotiate_version`)
+    ///
+    /// ABIs gate features the program was not built for on it.
+    pub abi_version: AbiVersion,
+
+    /// Why the last native system call of the current system call failed
+    /// (see `crate::abi::error`)
+    pub last_error: Option<KernelError>,
+
     /// ABI zones map. Key is the start address of the range.
     pub abi_zones: BTreeMap<usize, AbiZone>,
 
@@ -241,10 +336,37 @@ pub struct Task {
     /// All internal operations use RwLock for concurrent access protection.
     pub vfs: Option<Arc<VfsManager>>,
 
-    // KernelObject table
-    pub handle_table: HandleTable,
+    /// UTS namespace: host name and kernel identification seen by the task
+    ///
+    /// Shared with the parent unless cloned with `CloneFlagsDef::NewUts`.
+    /// See `crate::task::uts`.
+    pub uts_ns: Arc<UtsNamespace>,
+
+    /// Network namespace: interfaces, routes and sockets seen by the task
+    ///
+    /// Shared with the parent unless cloned with `CloneFlagsDef::NewNet`.
+    /// See `crate::network::namespace`.
+    pub net_ns: Arc<NetNamespace>,
+
+    /// User and group IDs, inherited by children and kept across exec
+    ///
+    /// See `crate::task::cred`.
+    pub cred: Credentials,
+
+    /// Exit state shared with the process handles opened for this task
+    ///
+    /// See `crate::task::process_handle`.
+    pub exit_notifier: Arc<ExitNotifier>,
+
+    /// System call tracing, see `crate::task::strace`
+    pub strace: strace::TraceState,
+
+    // KernelObject table, shared with the task's threads
+    pub handle_table: TaskShared<HandleTable>,
     /// Time slice (in ticks) for round-robin scheduling. Decremented every tick; when it reaches 0, the scheduler is invoked.
     pub time_slice: u32,
+    /// Nesting depth of disabled preemption, see `crate::sched::preempt`
+    pub preempt_count: usize,
     /// Software timer handlers
     pub software_timers_handlers: Vec<Arc<dyn TimerHandler>>,
     /// Task-local event queue with priority ordering
@@ -260,9 +382,13 @@ pub struct ManagedPage {
 }
 
 pub enum CloneFlagsDef {
-    Vm      = 0b00000001, // Clone the VM
+    Vm      = 0b00000001, // Share the VM
     Fs      = 0b00000010, // Clone the filesystem
     Files   = 0b00000100, // Clone the file descriptors
+    Thread  = 0b00001000, // Create a thread: share the VM, file descriptors and filesystem
+    SetTls  = 0b00010000, // Set the thread pointer of the child
+    NewUts  = 0b00100000, // Give the child a copy of the UTS namespace
+    NewNet  = 0b01000000, // Give the child a new network namespace
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -294,6 +420,11 @@ impl CloneFlags {
     pub fn get_raw(&self) -> u64 {
         self.raw
     }
+
+    /// Whether the child shares the address space of the parent
+    pub fn shares_vm(&self) -> bool {
+        self.is_set(CloneFlagsDef::Vm) || self.is_set(CloneFlagsDef::Thread)
+    }
 }
 
 impl Default for CloneFlags {
@@ -312,6 +443,7 @@ impl Task {
         let task = Task {
             id: *taskid,
             name,
+            exec_path: String::new(),
             priority,
             vcpu: Vcpu::new(match task_type {
                 TaskType::Kernel => crate::arch::vcpu::Mode::Kernel,
@@ -321,23 +453,47 @@ impl Task {
             state: TaskState::NotInitialized,
             task_type,
             entry: 0,
-            brk: None,
             stack_size: 0,
+            stack_top: 0,
             data_size: 0,
             text_size: 0,
             max_stack_size: DEAFAULT_MAX_TASK_STACK_SIZE,
             max_data_size: DEAFAULT_MAX_TASK_DATA_SIZE,
             max_text_size: DEAFAULT_MAX_TASK_TEXT_SIZE,
-            vm_manager: VirtualMemoryManager::new(),
-            managed_pages: Vec::new(),
+            oom_score_adj: 0,
+            nice: 0,
+            sched_policy: SchedPolicy::Normal,
+            rt_priority: 0,
+            cpu_affinity: CpuMask::all(),
+            cgroup: ROOT_GROUP,
+            rlimits: ResourceLimits::new(),
+            cpu_times: CpuTimes::new(),
+            sched_stats: TaskSchedStats::new(),
+            cpu_seconds_checked: 0,
+            signals: SignalState::new(),
+            tls_template: None,
+            tls_block: None,
+            vm_manager: TaskShared::new(VirtualMemoryManager::new()),
+            managed_pages: TaskShared::new(Vec::new()),
             parent_id: None,
             children: Vec::new(),
+            // A task without a parent leads its own session and group
+            pgid: *taskid,
+            sid: *taskid,
             exit_status: None,
             default_abi: Box::new(ScarletAbi::default()), // Default ABI
+            abi_version: AbiVersion::new(1, 0, 0),
+            last_error: None,
             abi_zones: BTreeMap::new(),
             vfs: None,
-            handle_table: HandleTable::new(),
+            uts_ns: init_uts_ns(),
+            net_ns: init_net_ns(),
+            cred: Credentials::root(),
+            exit_notifier: Arc::new(ExitNotifier::new(*taskid)),
+            strace: strace::TraceState::new(),
+            handle_table: TaskShared::new(HandleTable::new()),
             time_slice: 10, // Assign 10 ticks by default
+            preempt_count: 0,
             software_timers_handlers: Vec::new(),
             event_queue: spin::Mutex::new(crate::ipc::event::TaskEventQueue::new()),
             events_enabled: spin::Mutex::new(true), // Events enabled by default
@@ -408,52 +564,36 @@ impl Task {
     /// # Returns
     /// The program break address
     pub fn get_brk(&self) -> usize {
-        if self.brk.is_none() {
-            return self.text_size + self.data_size;
-        }
-        self.brk.unwrap()
+        self.vm_manager.read().get_brk()
     }
 
     /// Set the program break (NOT work in Kernel task)
     /// 
+    /// The heap is a demand-zero area managed by the VM manager, so growing
+    /// it only reserves address space; pages are allocated on first touch.
+    /// 
     /// # Arguments
     /// * `brk` - The new program break address
     /// 
     /// # Returns
     /// If successful, returns Ok(()), otherwise returns an error.
     pub fn set_brk(&mut self, brk: usize) -> Result<(), &'static str> {
-        // println!("New brk: {:#x}", brk);
-        if brk < self.text_size {
-            return Err("Invalid address");
-        }
-        let prev_brk = self.get_brk();
-        if brk < prev_brk {
-            /* Free pages */
-            /* Round address to the page boundary */
-            let prev_addr = (prev_brk + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
-            let addr = (brk + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
-            let num_of_pages = (prev_addr - addr) / PAGE_SIZE;
-            self.free_data_pages(addr, num_of_pages);            
-        } else if brk > prev_brk {
-            /* Allocate pages */
-            /* Round address to the page boundary */
-            let prev_addr = (prev_brk + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
-            let addr = (brk + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
-            let num_of_pages = (addr - prev_addr) / PAGE_SIZE;
-
-            if num_of_pages > 0 {
-                match self.vm_manager.search_memory_map(prev_addr) {
-                    Some(_) => {},
-                    None => {
-                        match self.allocate_data_pages(prev_addr, num_of_pages) {
-                            Ok(_) => {},
-                            Err(_) => return Err("Failed to allocate pages"),
-                        }
-                    },
-                }
+        let prev_end = self.get_brk().next_multiple_of(PAGE_SIZE);
+        let new_end = brk.checked_next_multiple_of(PAGE_SIZE).ok_or("Invalid address")?;
+        if new_end > prev_end {
+            self.check_memory_limits(new_end - prev_end, true)?;
+        }
+
+        let removed = self.vm_manager.write().set_brk(brk)?;
+        if new_end > prev_end {
+            self.data_size += new_end - prev_end;
+        }
+        for map in removed {
+            if let Some(pages) = &map.zero_fill {
+                pages.release_range(map.vmarea.start, map.vmarea.end);
             }
+            self.data_size = self.data_size.saturating_sub(map.vmarea.size());
         }
-        self.brk = Some(brk);
         Ok(())
     }
 
@@ -495,8 +635,9 @@ impl Task {
             permissions,
             is_shared: false, // Default to not shared for task-allocated pages
             owner: None,
+            zero_fill: None,
         };
-        self.vm_manager.add_memory_map(mmap.clone()).map_err(|e| panic!("Failed to add memory map: {}", e))?;
+        self.vm_manager.write().add_memory_map(mmap.clone()).map_err(|e| panic!("Failed to add memory map: {}", e))?;
 
         for i in 0..num_of_pages {
             let page = unsafe { Box::from_raw(pages.wrapping_add(i)) };
@@ -520,7 +661,8 @@ impl Task {
         let page = vaddr / PAGE_SIZE;
         for p in 0..num_of_pages {
             let vaddr = (page + p) * PAGE_SIZE;
-            match self.vm_manager.remove_memory_map_by_addr(vaddr) {
+            let removed = self.vm_manager.write().remove_memory_map_by_addr(vaddr);
+            match removed {
                 Some(mmap) => {
                     if p == 0 && mmap.vmarea.start < vaddr {
                         /* Re add the first part of the memory map */
@@ -538,8 +680,9 @@ impl Task {
                             permissions: mmap.permissions,
                             is_shared: mmap.is_shared,
                             owner: mmap.owner.clone(),
+                            zero_fill: mmap.zero_fill.clone(),
                         };
-                        self.vm_manager.add_memory_map(mmap1)
+                        self.vm_manager.write().add_memory_map(mmap1)
                             .map_err(|e| panic!("Failed to add memory map: {}", e)).unwrap();
                         // println!("Removed map : {:#x} - {:#x}", mmap.vmarea.start, mmap.vmarea.end);
                         // println!("Re added map: {:#x} - {:#x}", mmap1.vmarea.start, mmap1.vmarea.end);
@@ -560,8 +703,9 @@ impl Task {
                             permissions: mmap.permissions,
                             is_shared: mmap.is_shared,
                             owner: mmap.owner.clone(),
+                            zero_fill: mmap.zero_fill.clone(),
                         };
-                        self.vm_manager.add_memory_map(mmap2)
+                        self.vm_manager.write().add_memory_map(mmap2)
                             .map_err(|e| panic!("Failed to add memory map: {}", e)).unwrap();
                         // println!("Removed map : {:#x} - {:#x}", mmap.vmarea.start, mmap.vmarea.end);
                         // println!("Re added map: {:#x} - {:#x}", mmap2.vmarea.start, mmap2.vmarea.end);
@@ -569,7 +713,9 @@ impl Task {
                     // let offset = vaddr - mmap.vmarea.start;
                     // free_raw_pages((mmap.pmarea.start + offset) as *mut Page, 1);
 
-                    if let Some(free_page) = self.remove_managed_page(vaddr) {
+                    if let Some(pages) = &mmap.zero_fill {
+                        pages.release_range(vaddr, vaddr + PAGE_SIZE - 1);
+                    } else if let Some(free_page) = self.remove_managed_page(vaddr) {
                         free_boxed_page(free_page.page);
                     }
                     
@@ -579,8 +725,8 @@ impl Task {
             }
         }
         /* Unmap pages */
-        let asid = self.vm_manager.get_asid();
-        let root_pagetable = self.vm_manager.get_root_page_table().unwrap();
+        let asid = self.vm_manager.read().get_asid();
+        let root_pagetable = self.vm_manager.read().get_root_page_table().unwrap();
         for p in 0..num_of_pages {
             let vaddr = (page + p) * PAGE_SIZE;
             root_pagetable.unmap(asid, vaddr);
@@ -638,6 +784,320 @@ impl Task {
         Ok(res)
     }
 
+    /// Grow the user stack downwards so that it covers `vaddr`.
+    ///
+    /// Called when a page fault hits an unmapped address below the stack.
+    /// The stack may grow up to `max_stack_size`, and at least one unmapped
+    /// guard page is kept between the stack and the next mapping below it,
+    /// so that a stack overflow faults instead of corrupting that mapping.
+    ///
+    /// # Arguments
+    /// * `vaddr` - The faulting virtual address
+    ///
+    /// # Errors
+    /// If the address is not below the stack, the stack limit would be
+    /// exceeded, or the stack would run into another mapping.
+    ///
+    pub fn grow_stack(&mut self, vaddr: usize) -> Result<(), &'static str> {
+        if self.task_type != TaskType::User || self.stack_top == 0 {
+            return Err("Task has no growable stack");
+        }
+        let stack_bottom = self.stack_top - self.stack_size;
+        if vaddr >= stack_bottom {
+            return Err("Address is not below the stack");
+        }
+        let new_bottom = vaddr & !(PAGE_SIZE - 1);
+        if self.stack_top - new_bottom > self.max_stack_size
+            || !self.rlimits.get(Resource::Stack).allows(self.stack_top - new_bottom) {
+            return Err("Stack size limit exceeded");
+        }
+        let guard_page = new_bottom.checked_sub(PAGE_SIZE).ok_or("Stack would reach address zero")?;
+        if !self.vm_manager.read().is_range_free(guard_page, stack_bottom - guard_page) {
+            return Err("Stack would collide with another mapping");
+        }
+        self.check_memory_limits(stack_bottom - new_bottom, false)?;
+        self.allocate_stack_pages(new_bottom, (stack_bottom - new_bottom) / PAGE_SIZE)?;
+        Ok(())
+    }
+
+    /// Map a TLS block for the task and point its thread pointer at it
+    ///
+    /// The block is initialized from [`Task::tls_template`] and placed in
+    /// the mmap area like an anonymous mapping. The thread pointer (tp)
+    /// addresses the start of the block, where RISC-V code expects the TLS
+    /// of the main program (TLS variant I without a TCB). A C library that
+    /// sets up TLS itself simply replaces tp.
+    ///
+    /// # Returns
+    /// The thread pointer, or `None` if the program has no TLS
+    ///
+    /// # Errors
+    /// If the memory limits would be exceeded or there is no room for the
+    /// block.
+    pub fn setup_tls(&mut self) -> Result<Option<usize>, &'static str> {
+        // A block left over from the previous program went with its address space
+        self.tls_block = None;
+        let Some(template) = self.tls_template else {
+            return Ok(None);
+        };
+        let size = (template.memsz as usize).max(1).next_multiple_of(PAGE_SIZE);
+        let align = (template.align as usize).max(PAGE_SIZE);
+        self.check_memory_limits(size, true)?;
+        let start = self.vm_manager.read().find_unmapped_area(size, align).ok_or("No room for the TLS block")?;
+        let vmarea = MemoryArea::new(start, start + size - 1);
+        let permissions = VirtualMemoryPermission::Read as usize
+            | VirtualMemoryPermission::Write as usize
+            | VirtualMemoryPermission::User as usize;
+        self.vm_manager.write().add_memory_map(VirtualMemoryMap::new_zero_fill(vmarea, permissions))?;
+        self.tls_block = Some(vmarea);
+
+        // Copy the initialization image; the rest of the block reads as zeros
+        let image = template.image_addr as usize;
+        let mut copied = 0;
+        while copied < template.filesz as usize {
+            let src = image + copied;
+            let len = (PAGE_SIZE - src % PAGE_SIZE)
+                .min(PAGE_SIZE - copied % PAGE_SIZE)
+                .min(template.filesz as usize - copied);
+            let src_paddr = self.vm_manager.read().translate_vaddr(src);
+            let Some(src_paddr) = src_paddr else {
+                self.release_tls_block();
+                return Err("TLS image is not mapped");
+            };
+            let dst_paddr = self.vm_manager.read().translate_vaddr(start + copied).ok_or("TLS block is not mapped")?;
+            unsafe {
+                core::ptr::copy_nonoverlapping(src_paddr as *const u8, dst_paddr as *mut u8, len);
+            }
+            copied += len;
+        }
+
+        self.vcpu.iregs.reg[4] = start; /* tp */
+        Ok(Some(start))
+    }
+
+    /// Unmap the TLS block the kernel mapped for this task
+    ///
+    /// Called when a thread exits; the other threads keep the address space.
+    fn release_tls_block(&mut self) {
+        let Some(block) = self.tls_block.take() else {
+            return;
+        };
+        if let Ok(removed) = self.vm_manager.write().unmap_range(block.start, block.size()) {
+            for map in removed {
+                if let Some(pages) = &map.zero_fill {
+                    pages.release_range(map.vmarea.start, map.vmarea.end);
+                }
+            }
+        }
+    }
+
+    /// Bytes of user address space currently mapped (charged to RLIMIT_AS)
+    pub fn address_space_usage(&self) -> usize {
+        self.vm_manager.read().memmap_iter()
+            .filter(|map| VirtualMemoryPermission::User.contained_in(map.permissions))
+            .map(|map| map.vmarea.size())
+            .sum()
+    }
+
+    /// Bytes of data area currently mapped (charged to RLIMIT_DATA)
+    ///
+    /// The data area is all private, writable, anonymous user memory except
+    /// the stack: the data segment, the heap and anonymous mmaps.
+    pub fn data_usage(&self) -> usize {
+        let stack_floor = self.stack_top.saturating_sub(self.max_stack_size);
+        let writable_user = VirtualMemoryPermission::User as usize | VirtualMemoryPermission::Write as usize;
+        self.vm_manager.read().memmap_iter()
+            .filter(|map| map.permissions & writable_user == writable_user)
+            .filter(|map| !map.is_shared && map.owner.is_none())
+            .filter(|map| self.stack_top == 0 || map.vmarea.end < stack_floor || map.vmarea.start >= self.stack_top)
+            .map(|map| map.vmarea.size())
+            .sum()
+    }
+
+    /// Check that mapping `bytes` more memory keeps the task within its
+    /// resource limits
+    ///
+    /// # Arguments
+    /// * `bytes` - Size of the new memory
+    /// * `is_data` - Whether the memory is part of the data area (see `data_usage`)
+    ///
+    /// # Errors
+    /// If RLIMIT_AS or (for data) RLIMIT_DATA would be exceeded.
+    pub fn check_memory_limits(&self, bytes: usize, is_data: bool) -> Result<(), &'static str> {
+        let address_space = self.address_space_usage().saturating_add(bytes);
+        if !self.rlimits.get(Resource::AddressSpace).allows(address_space) {
+            return Err("Address space limit exceeded");
+        }
+        if is_data {
+            let data = self.data_usage().saturating_add(bytes);
+            if !self.rlimits.get(Resource::Data).allows(data) {
+                return Err("Data size limit exceeded");
+            }
+        }
+        Ok(())
+    }
+
+    /// Change the limits of a resource
+    ///
+    /// See [`ResourceLimits::set`] for the rules.
+    pub fn set_rlimit(&mut self, resource: Resource, limit: RLimit) -> Result<(), &'static str> {
+        self.rlimits.set(resource, limit)?;
+        if resource == Resource::OpenFiles {
+            self.handle_table.write().set_limit(limit.cur);
+        }
+        Ok(())
+    }
+
+    /// Check the CPU time of the running task against RLIMIT_CPU
+    ///
+    /// Called every tick; each whole second of CPU time is checked once.
+    ///
+    /// # Returns
+    /// The signal to send to the task: SIGKILL once the hard limit is
+    /// reached, SIGXCPU on reaching the soft limit and every second after
+    pub fn check_cpu_limit(&mut self) -> Option<usize> {
+        let (user_us, system_us) = self.cpu_times.current(get_time_us(), true);
+        let seconds = (user_us + system_us) / 1_000_000;
+        if seconds <= self.cpu_seconds_checked {
+            return None;
+        }
+        self.cpu_seconds_checked = seconds;
+        let seconds = seconds as usize;
+        let limit = self.rlimits.get(Resource::Cpu);
+        if limit.max != RLIM_INFINITY && seconds >= limit.max {
+            Some(signal::SIGKILL)
+        } else if limit.cur != RLIM_INFINITY && seconds >= limit.cur {
+            Some(signal::SIGXCPU)
+        } else {
+            None
+        }
+    }
+
+    /// User and kernel time of the running task and its threads, in
+    /// microseconds
+    ///
+    /// The threads of a process share the signal handlers. Threads running
+    /// on other CPUs are charged up to now as well.
+    pub fn process_cpu_times(&self) -> (u64, u64) {
+        let now = get_time_us();
+        let scheduler = get_scheduler();
+        let running: [Option<usize>; NUM_OF_CPUS] =
+            core::array::from_fn(|cpu_id| scheduler.get_current_task_id(cpu_id));
+        let (mut user, mut system) = (0, 0);
+        for id in scheduler.get_all_task_ids() {
+            let Some(task) = scheduler.get_task_by_id(id) else { continue };
+            if task.state == TaskState::Terminated
+                || task.id != self.id && !task.is_thread_of(self) {
+                continue;
+            }
+            let (u, s) = task.cpu_times.current(now, task.id == self.id || running.contains(&Some(task.id)));
+            user += u;
+            system += s;
+        }
+        (user, system)
+    }
+
+    /// Whether `other` is a thread of the same process as this task
+    ///
+    /// The threads of a process are the tasks sharing its signal handlers.
+    pub fn is_thread_of(&self, other: &Task) -> bool {
+        self.signals.actions.ptr_eq(&other.signals.actions)
+    }
+
+    /// IDs of the other threads of the task's process that have not exited
+    pub fn thread_ids(&self) -> Vec<usize> {
+        let scheduler = get_scheduler();
+        scheduler.get_all_task_ids().into_iter().filter(|&id| {
+            id != self.id && scheduler.get_task_by_id(id).is_some_and(|task| {
+                !matches!(task.state, TaskState::Zombie | TaskState::Terminated) && task.is_thread_of(self)
+            })
+        }).collect()
+    }
+
+    /// Clip a write of `count` bytes to `object` to RLIMIT_FSIZE
+    ///
+    /// Only regular files are limited. A write starting at or past the
+    /// limit sends SIGXFSZ to the task.
+    ///
+    /// # Returns
+    /// The number of bytes that may be written
+    ///
+    /// # Errors
+    /// If the write position is at or past the limit.
+    pub fn check_file_write(&self, object: &KernelObject, count: usize) -> Result<usize, &'static str> {
+        let limit = self.rlimits.get(Resource::FileSize).cur;
+        if limit == RLIM_INFINITY || count == 0 {
+            return Ok(count);
+        }
+        let Some(file) = object.as_file() else {
+            return Ok(count);
+        };
+        if !file.metadata().is_ok_and(|metadata| metadata.file_type == FileType::RegularFile) {
+            return Ok(count);
+        }
+        let position = file.seek(SeekFrom::Current(0)).map_err(|_| "Cannot get the file position")? as usize;
+        if position >= limit {
+            let _ = signal::send_signal(self.id, signal::SIGXFSZ);
+            return Err("File size limit exceeded");
+        }
+        Ok(count.min(limit - position))
+    }
+
+    /// Change the nice value of the task
+    ///
+    /// Values outside `NICE_MIN..=NICE_MAX` are clamped. Raising the nice
+    /// value is always allowed; lowering it is limited by RLIMIT_NICE.
+    ///
+    /// # Errors
+    /// If the new value is lower than both the current one and the
+    /// RLIMIT_NICE floor.
+    pub fn set_nice(&mut self, nice: isize) -> Result<(), &'static str> {
+        let nice = clamp_nice(nice);
+        if nice < self.nice && nice < self.rlimits.min_nice() {
+            return Err("Nice value below RLIMIT_NICE");
+        }
+        self.nice = nice;
+        Ok(())
+    }
+
+    /// Change the scheduling policy and real-time priority of the task
+    ///
+    /// Raising the real-time priority above the current one is limited by
+    /// RLIMIT_RTPRIO; lowering it or returning to the normal policy is
+    /// always allowed.
+    ///
+    /// # Errors
+    /// If the priority is invalid for the policy or exceeds RLIMIT_RTPRIO.
+    pub fn set_scheduler(&mut self, policy: SchedPolicy, priority: u32) -> Result<(), &'static str> {
+        if !policy.is_valid_priority(priority) {
+            return Err("Invalid priority for scheduling policy");
+        }
+        let limit = self.rlimits.get(Resource::RtPriority).cur;
+        if priority > self.rt_priority && limit != RLIM_INFINITY && priority as usize > limit {
+            return Err("Priority exceeds RLIMIT_RTPRIO");
+        }
+        self.sched_policy = policy;
+        self.rt_priority = priority;
+        Ok(())
+    }
+
+    /// Change the set of CPUs the task may run on
+    ///
+    /// CPUs that do not exist are dropped from the mask. The scheduler moves
+    /// the task off a CPU that is no longer allowed the next time it is
+    /// switched out there.
+    ///
+    /// # Errors
+    /// If the mask contains no existing CPU.
+    pub fn set_affinity(&mut self, mask: CpuMask) -> Result<(), &'static str> {
+        let mask = mask.intersection(CpuMask::all());
+        if mask.is_empty() {
+            return Err("Affinity mask contains no CPU");
+        }
+        self.cpu_affinity = mask;
+        Ok(())
+    }
+
     /// Free stack pages for the task. And decrement the size of the task.
     /// 
     /// # Arguments
@@ -709,6 +1169,7 @@ impl Task {
             permissions,
             is_shared: VirtualMemoryRegion::Guard.is_shareable(), // Guard pages can be shared
             owner: None,
+            zero_fill: None,
         };
         Ok(mmap)
     }
@@ -723,24 +1184,7 @@ impl Task {
     /// So, you must not free them by calling free_raw_pages/free_boxed_pages manually.
     /// 
     pub fn add_managed_page(&mut self, pages: ManagedPage) {
-        self.managed_pages.push(pages);
-    }
-
-    /// Get managed page
-    /// 
-    /// # Arguments
-    /// * `vaddr` - The virtual address of the page
-    /// 
-    /// # Returns
-    /// The managed page if found, otherwise None
-    /// 
-    fn get_managed_page(&self, vaddr: usize) -> Option<&ManagedPage> {
-        for page in &self.managed_pages {
-            if page.vaddr == vaddr {
-                return Some(page);
-            }
-        }
-        None
+        self.managed_pages.write().push(pages);
     }
 
     /// Remove managed page
@@ -752,13 +1196,9 @@ impl Task {
     /// The removed managed page if found, otherwise None
     /// 
     pub fn remove_managed_page(&mut self, vaddr: usize) -> Option<crate::task::ManagedPage> {
-        for i in 0..self.managed_pages.len() {
-            if self.managed_pages[i].vaddr == vaddr {
-                let page = self.managed_pages.remove(i);
-                return Some(page);
-            }
-        }
-        None
+        let mut managed_pages = self.managed_pages.write();
+        let index = managed_pages.iter().position(|page| page.vaddr == vaddr)?;
+        Some(managed_pages.remove(index))
     }
 
 
@@ -782,6 +1222,56 @@ impl Task {
     pub fn set_parent_id(&mut self, parent_id: usize) {
         self.parent_id = Some(parent_id);
     }
+
+    /// Get the process group ID
+    pub fn get_pgid(&self) -> usize {
+        self.pgid
+    }
+
+    /// Get the session ID
+    pub fn get_sid(&self) -> usize {
+        self.sid
+    }
+
+    /// Whether the task created its session with `setsid()`
+    pub fn is_session_leader(&self) -> bool {
+        self.sid == self.id
+    }
+
+    /// Move the task to the process group `pgid`
+    ///
+    /// `pgid` is either the task's own ID, making it a group leader, or an
+    /// existing group of the same session. A session leader cannot change
+    /// its group.
+    pub fn set_pgid(&mut self, pgid: usize) -> Result<(), &'static str> {
+        if self.is_session_leader() {
+            return Err("A session leader cannot change its process group");
+        }
+        if pgid != self.id {
+            let in_session = process_group_members(pgid).into_iter().any(|id| {
+                get_scheduler().get_task_by_id(id).is_some_and(|member| member.sid == self.sid)
+            });
+            if !in_session {
+                return Err("No such process group in the session");
+            }
+        }
+        self.pgid = pgid;
+        Ok(())
+    }
+
+    /// Start a new session led by the task, in a new process group
+    ///
+    /// # Returns
+    /// The ID of the new session
+    pub fn setsid(&mut self) -> Result<usize, &'static str> {
+        // The new group would merge with the one the task leads
+        if !process_group_members(self.id).is_empty() {
+            return Err("The task is a process group leader");
+        }
+        self.sid = self.id;
+        self.pgid = self.id;
+        Ok(self.sid)
+    }
     
     /// Add a child task
     ///
@@ -863,6 +1353,9 @@ impl Task {
     /// Clone this task, creating a near-identical copy
     /// 
     /// # Arguments
+    /// * `flags` - What the child copies or shares. With `Vm` or `Thread`
+    ///   the child shares the address space; with `Thread` it also shares
+    ///   the handle table and the filesystem context.
     /// 
     /// # Returns
     /// The cloned task
@@ -871,6 +1364,11 @@ impl Task {
     /// If the task cannot be cloned, an error is returned.
     ///
     pub fn clone_task(&mut self, flags: CloneFlags) -> Result<Task, &'static str> {
+        if self.task_type == TaskType::User
+            && !self.rlimits.get(Resource::Processes).allows(user_task_count() + 1) {
+            return Err("Process limit exceeded");
+        }
+
         // Create a new task (but don't call init() yet)
         let mut child = Task::new(
             self.name.clone(),
@@ -885,18 +1383,29 @@ impl Task {
                 child.init();
             },
             TaskType::User => {
-                if !flags.is_set(CloneFlagsDef::Vm) {
+                if flags.shares_vm() {
+                    // Threads use the same address space and its pages
+                    child.vm_manager = self.vm_manager.share();
+                    child.managed_pages = self.managed_pages.share();
+                } else {
                     // For user tasks, manually set up VM without calling init()
                     // to avoid creating new stack that would overwrite parent's stack content
                     let asid = alloc_virtual_address_space();
-                    child.vm_manager.set_asid(asid);
+                    let parent_vm = self.vm_manager.read();
+                    let mut child_vm = child.vm_manager.write();
+                    child_vm.set_asid(asid);
+                    // Keep the parent's (possibly randomized) mmap layout
+                    child_vm.set_mmap_base(parent_vm.get_mmap_base());
+                    child_vm.set_mmap_limit(parent_vm.get_mmap_limit());
+                    // The heap maps are copied below
+                    child_vm.set_program_break(parent_vm.get_brk_start(), parent_vm.get_brk());
                 }
             }
         }
         
-        if !flags.is_set(CloneFlagsDef::Vm) {
+        if !flags.shares_vm() {
             // Copy or share memory maps from parent to child
-            for mmap in self.vm_manager.memmap_iter() {
+            for mmap in self.vm_manager.read().memmap_iter() {
                 let num_pages = (mmap.vmarea.end - mmap.vmarea.start + 1 + PAGE_SIZE - 1) / PAGE_SIZE;
                 let vaddr = mmap.vmarea.start;
                 
@@ -909,19 +1418,35 @@ impl Task {
                             permissions: mmap.permissions,
                             is_shared: true,
                             owner: mmap.owner.clone(),
+                            zero_fill: None,
                         };
                         // Add the shared memory map directly to the child task
-                        child.vm_manager.add_memory_map(shared_mmap.clone())
+                        child.vm_manager.write().add_memory_map(shared_mmap.clone())
                             .map_err(|_| "Failed to add shared memory map to child task")?;
+                        // The owner now has one more user of these pages
+                        shared_mmap.notify_owner_mapped();
 
                         // TODO: Add logic to determine if the memory map is a trampoline
                         // If the memory map is the trampoline, pre-map it
                         if mmap.vmarea.start == 0xffff_ffff_ffff_f000 {
                             // Pre-map the trampoline page
-                            let root_pagetable = child.vm_manager.get_root_page_table().unwrap();
-                            root_pagetable.map_memory_area(child.vm_manager.get_asid(), shared_mmap)?;
+                            let child_vm = child.vm_manager.read();
+                            let root_pagetable = child_vm.get_root_page_table().unwrap();
+                            root_pagetable.map_memory_area(child_vm.get_asid(), shared_mmap)?;
                         }
 
+                    } else if let Some(pages) = &mmap.zero_fill {
+                        // Demand-zero regions: copy only the pages written so far
+                        let new_mmap = VirtualMemoryMap {
+                            pmarea: mmap.pmarea,
+                            vmarea: mmap.vmarea,
+                            permissions: mmap.permissions,
+                            is_shared: false,
+                            owner: None,
+                            zero_fill: Some(pages.duplicate_range(mmap.vmarea.start, mmap.vmarea.end)),
+                        };
+                        child.vm_manager.write().add_memory_map(new_mmap)
+                            .map_err(|_| "Failed to add memory map to child task")?;
                     } else {
                         // Private memory regions: allocate new pages and copy contents
                         let permissions = mmap.permissions;
@@ -940,6 +1465,7 @@ impl Task {
                             permissions,
                             is_shared: false,
                             owner: mmap.owner.clone(),
+                            zero_fill: None,
                         };
                         
                         // Copy the contents of the original memory (including stack contents)
@@ -960,11 +1486,14 @@ impl Task {
                             });
                         }
                         // Add the new memory map to the child task
-                        child.vm_manager.add_memory_map(new_mmap)
+                        new_mmap.notify_owner_mapped();
+                        child.vm_manager.write().add_memory_map(new_mmap)
                             .map_err(|_| "Failed to add memory map to child task")?;
                     }
                 }
             }
+            // The copied vDSO task page still holds the parent's ID (unless the parent unmapped it)
+            let _ = vdso::write_task_data(&child);
         }
 
         // Copy register states
@@ -972,6 +1501,8 @@ impl Task {
         
         // Clone the default ABI and ABI zones
         child.default_abi = self.default_abi.clone_boxed();
+        child.abi_version = self.abi_version;
+        child.exec_path = self.exec_path.clone();
         // Clone ABI zones (each zone contains a boxed ABI that needs to be cloned)
         for (start, zone) in &self.abi_zones {
             let new_zone = AbiZone {
@@ -983,22 +1514,60 @@ impl Task {
         
         // Copy state such as data size
         child.stack_size = self.stack_size;
+        child.stack_top = self.stack_top;
         child.data_size = self.data_size;
         child.text_size = self.text_size;
         child.max_stack_size = self.max_stack_size;
         child.max_data_size = self.max_data_size;
+        child.rlimits = self.rlimits;
         child.max_text_size = self.max_text_size;
+        child.oom_score_adj = self.oom_score_adj;
+        child.nice = self.nice;
+        child.sched_policy = self.sched_policy;
+        child.rt_priority = self.rt_priority;
+        child.cpu_affinity = self.cpu_affinity;
+        child.cgroup = self.cgroup;
+        child.cred = self.cred.clone();
+        child.strace = self.strace.inherit();
+        child.uts_ns = if flags.is_set(CloneFlagsDef::NewUts) {
+            crate::audit::namespace("uts", "clone", child.get_id());
+            Arc::new(self.uts_ns.copy())
+        } else {
+            self.uts_ns.clone()
+        };
+        child.net_ns = if flags.is_set(CloneFlagsDef::NewNet) {
+            crate::audit::namespace("net", "clone", child.get_id());
+            Arc::new(NetNamespace::new())
+        } else {
+            self.net_ns.clone()
+        };
+        child.pgid = self.pgid;
+        child.sid = self.sid;
+        // Threads share the signal handlers
+        child.signals = self.signals.clone_for_child(flags.is_set(CloneFlagsDef::Thread));
+        child.tls_template = self.tls_template;
+        if !flags.shares_vm() {
+            // The copied address space holds a copy of the block
+            child.tls_block = self.tls_block;
+        } else if !flags.is_set(CloneFlagsDef::SetTls) {
+            // A thread without a thread pointer of its own gets a fresh block
+            child.setup_tls()?;
+        }
         
         // Set the same entry point and PC
         child.entry = self.entry;
         child.vcpu.set_pc(self.vcpu.get_pc());
+        child.vcpu.set_xlen(self.vcpu.get_xlen());
 
-        if flags.is_set(CloneFlagsDef::Files) {
+        if flags.is_set(CloneFlagsDef::Thread) {
+            // Threads open and close handles in the same table
+            child.handle_table = self.handle_table.share();
+        } else if flags.is_set(CloneFlagsDef::Files) {
             // Clone the file descriptor table
-            child.handle_table = self.handle_table.clone();
+            child.handle_table = TaskShared::new(self.handle_table.read().clone());
         }
         
-        if flags.is_set(CloneFlagsDef::Fs) {
+        if flags.is_set(CloneFlagsDef::Fs) || flags.is_set(CloneFlagsDef::Thread) {
             // Clone the filesystem manager
             if let Some(vfs) = &self.vfs {
                 child.vfs = Some(vfs.clone());
@@ -1026,9 +1595,37 @@ impl Task {
     /// * `status` - The exit status
     /// 
     pub fn exit(&mut self, status: i32) {        
-        // Close all open handles when task exits
-        self.handle_table.close_all();
+        strace::task_exit(self);
+        if self.is_session_leader() {
+            crate::device::char::tty::hangup_session(self.sid);
+        }
+
+        if self.handle_table.is_shared() {
+            // Other threads keep using the handles
+            self.handle_table = TaskShared::new(HandleTable::new());
+        } else {
+            // Close all open handles when task exits
+            self.handle_table.write().close_all();
+        }
+
+        if self.vm_manager.is_shared() {
+            // Other threads keep the address space; it is torn down with the last one
+            self.release_tls_block();
+            self.vm_manager = TaskShared::new(VirtualMemoryManager::new());
+            self.managed_pages = TaskShared::new(Vec::new());
+        } else {
+            // Let mapping owners (e.g. shared memory segments) drop this task's references
+            for mmap in self.vm_manager.read().memmap_iter() {
+                mmap.notify_owner_unmapped();
+            }
+        }
         
+        // Process handles become readable
+        self.exit_notifier.notify(match self.signals.killed_by() {
+            Some(sig) => WaitStatus::Signaled(sig, self.signals.core_dumped()),
+            None => WaitStatus::Exited(status),
+        });
+
         match self.parent_id {
             Some(parent_id) => {
                 if get_scheduler().get_task_by_id(parent_id).is_none() {
@@ -1039,6 +1636,10 @@ impl Task {
                 /* Set the exit status */
                 self.set_exit_status(status);
                 self.state = TaskState::Zombie;
+                // A thread leaving its siblings behind does not notify the parent
+                if !self.signals.actions.is_shared() {
+                    let _ = signal::send_signal(parent_id, signal::SIGCHLD);
+                }
                 
                 // TODO: Notify parent via ABI-specific mechanism
                 // crate::println!("Task {}: Set to Zombie state, parent {}", self.id, parent_id);
@@ -1079,6 +1680,7 @@ impl Task {
         if let Some(child_task) = get_scheduler().get_task_by_id(child_id) {
             if child_task.get_state() == TaskState::Zombie {
                 let status = child_task.get_exit_status().unwrap_or(-1);
+                self.cpu_times.add_child(&child_task.cpu_times);
                 child_task.set_state(TaskState::Terminated);
                 self.remove_child(child_id);
                 Ok(status)
@@ -1090,6 +1692,47 @@ impl Task {
         }
     }
 
+    /// Collect a state change of a child task without blocking
+    ///
+    /// A child that exited is reaped. Stops and continues are reported once
+    /// each, and only when asked for.
+    ///
+    /// # Arguments
+    /// * `child_id` - The ID of the child task
+    /// * `stopped` - Report the child stopping (`WUNTRACED`)
+    /// * `continued` - Report the child continuing (`WCONTINUED`)
+    ///
+    /// # Returns
+    /// The state change of the child, or `None` if there is nothing to report
+    pub fn wait_event(&mut self, child_id: usize, stopped: bool, continued: bool) -> Result<Option<WaitStatus>, WaitError> {
+        if !self.children.contains(&child_id) {
+            return Err(WaitError::NoSuchChild("No such child task".to_string()));
+        }
+        let Some(child_task) = get_scheduler().get_task_by_id(child_id) else {
+            return Err(WaitError::ChildTaskNotFound("Child task not found".to_string()));
+        };
+
+        if child_task.get_state() == TaskState::Zombie {
+            let status = match child_task.signals.killed_by() {
+                Some(sig) => WaitStatus::Signaled(sig, child_task.signals.core_dumped()),
+                None => WaitStatus::Exited(child_task.get_exit_status().unwrap_or(-1)),
+            };
+            self.cpu_times.add_child(&child_task.cpu_times);
+            child_task.set_state(TaskState::Terminated);
+            self.remove_child(child_id);
+            return Ok(Some(status));
+        }
+        if stopped {
+            if let Some(sig) = child_task.signals.take_stop_report() {
+                return Ok(Some(WaitStatus::Stopped(sig)));
+            }
+        }
+        if continued && child_task.signals.take_continue_report() {
+            return Ok(Some(WaitStatus::Continued));
+        }
+        Ok(None)
+    }
+
     /// Sleep the current task for the specified number of ticks.
     /// This blocks the task and registers a timer to wake it up.
     /// 
@@ -1097,7 +1740,9 @@ impl Task {
     /// * `trapframe` - The trapframe of the current CPU state
     /// * `ticks` - The number of ticks to sleep
     /// 
-    pub fn sleep(&mut self, trapframe: &mut Trapframe, ticks: u64) {
+    /// # Returns
+    /// `false` if the sleep was cut short by a signal
+    pub fn sleep(&mut self, trapframe: &mut Trapframe, ticks: u64) -> bool {
 
         struct SleepWakerHandler {
             task_id: usize,
@@ -1121,11 +1766,19 @@ impl Task {
             task_id: self.id,
             start_tick: get_tick(),
         });
-        add_timer(wake_tick, &handler, 0);
+        let timer_id = add_timer(wake_tick, &handler, 0);
 
-        self.add_software_timer_handler(handler);
+        self.add_software_timer_handler(handler.clone());
         let waker = get_waitpid_waker(self.id);
-        waker.wait(self.get_id(), trapframe);
+        while get_tick() < wake_tick {
+            if self.signals.has_pending() {
+                cancel_timer(timer_id);
+                self.remove_software_timer_handler(&handler);
+                return false;
+            }
+            waker.wait(self.get_id(), trapframe);
+        }
+        true
     }
 
     // VFS Helper Methods
@@ -1298,6 +1951,31 @@ impl Task {
     }
 }
 
+/// State change of a child task collected by a wait
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WaitStatus {
+    /// Exited with an exit code
+    Exited(i32),
+    /// Terminated by a signal, with whether a core file was written
+    Signaled(usize, bool),
+    /// Stopped by a signal
+    Stopped(usize),
+    /// Continued by SIGCONT
+    Continued,
+}
+
+impl WaitStatus {
+    /// Encode as the Linux wait status word
+    pub fn encode(&self) -> i32 {
+        match *self {
+            WaitStatus::Exited(code) => (code & 0xff) << 8,
+            WaitStatus::Signaled(sig, core_dumped) => (sig as i32 & 0x7f) | if core_dumped { 0x80 } else { 0 },
+            WaitStatus::Stopped(sig) => ((sig as i32 & 0xff) << 8) | 0x7f,
+            WaitStatus::Continued => 0xffff,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum WaitError {
     NoSuchChild(String),
@@ -1375,6 +2053,34 @@ pub unsafe fn clear_mock_current_task() {
     }
 }
 
+/// IDs of the live tasks in the process group `pgid`
+pub fn process_group_members(pgid: usize) -> Vec<usize> {
+    let scheduler = get_scheduler();
+    scheduler
+        .get_all_task_ids()
+        .into_iter()
+        .filter(|&id| {
+            scheduler.get_task_by_id(id).is_some_and(|task| {
+                task.pgid == pgid && !matches!(task.state, TaskState::Zombie | TaskState::Terminated)
+            })
+        })
+        .collect()
+}
+
+/// Number of live user tasks (charged to RLIMIT_NPROC)
+fn user_task_count() -> usize {
+    let scheduler = get_scheduler();
+    scheduler
+        .get_all_task_ids()
+        .into_iter()
+        .filter(|&id| {
+            scheduler.get_task_by_id(id).is_some_and(|task| {
+                task.task_type == TaskType::User && !matches!(task.state, TaskState::Zombie | TaskState::Terminated)
+            })
+        })
+        .count()
+}
+
 /// Get the current task.
 /// 
 /// # Returns
@@ -1419,6 +2125,8 @@ pub fn set_current_task_cwd(path: String) -> bool {
 /// Internal function to perform kernel context switch between tasks
 /// This function is called when a task is first scheduled.
 pub fn task_initial_kernel_entrypoint() -> ! {
+    // Complete the switch that brought us here
+    get_scheduler().finish_task_switch();
     let cpu = get_cpu();
     let current_task = get_scheduler().get_current_task(cpu.get_cpuid()).unwrap();
     Scheduler::setup_task_execution(cpu, current_task);
@@ -1446,6 +2154,92 @@ mod tests {
         assert_eq!(task.get_brk(), 0x1000);
     }
 
+    #[test_case]
+    fn test_grow_stack() {
+        use crate::environment::PAGE_SIZE;
+
+        let mut task = super::new_user_task("StackTask".to_string(), 0);
+        task.init();
+        let bottom = task.stack_top - task.stack_size;
+
+        // A fault two pages below the stack maps both pages
+        task.grow_stack(bottom - 2 * PAGE_SIZE + 8).unwrap();
+        assert_eq!(task.stack_top - task.stack_size, bottom - 2 * PAGE_SIZE);
+        assert!(task.vm_manager.read().search_memory_map(bottom - PAGE_SIZE).is_some());
+        assert!(task.grow_stack(bottom).is_err());
+
+        // Growth stops at the stack limit
+        task.max_stack_size = task.stack_size;
+        assert!(task.grow_stack(bottom - 3 * PAGE_SIZE).is_err());
+    }
+
+    #[test_case]
+    fn test_memory_limits() {
+        use crate::environment::PAGE_SIZE;
+        use super::rlimit::{RLimit, Resource};
+
+        let mut task = super::new_user_task("LimitTask".to_string(), 0);
+        task.init();
+        let bottom = task.stack_top - task.stack_size;
+
+        // Room for exactly one more page of address space
+        let limit = task.address_space_usage() + PAGE_SIZE;
+        task.rlimits.set(Resource::AddressSpace, RLimit { cur: limit, max: limit }).unwrap();
+        assert!(task.grow_stack(bottom - 2 * PAGE_SIZE).is_err());
+        task.grow_stack(bottom - PAGE_SIZE).unwrap();
+        assert!(task.check_memory_limits(PAGE_SIZE, false).is_err());
+
+        // The data limit only counts the data area
+        let mut task = super::new_user_task("DataLimitTask".to_string(), 0);
+        task.init();
+        assert_eq!(task.data_usage(), 0);
+        task.rlimits.set(Resource::Data, RLimit { cur: PAGE_SIZE, max: PAGE_SIZE }).unwrap();
+        let brk = task.get_brk();
+        assert!(task.set_brk(brk + 2 * PAGE_SIZE).is_err());
+        task.set_brk(brk + PAGE_SIZE).unwrap();
+        assert_eq!(task.data_usage(), PAGE_SIZE);
+        assert!(task.check_memory_limits(PAGE_SIZE, false).is_ok());
+        assert!(task.check_memory_limits(PAGE_SIZE, true).is_err());
+    }
+
+    #[test_case]
+    fn test_set_nice_respects_rlimit() {
+        use super::rlimit::{RLimit, Resource};
+
+        let mut task = super::new_user_task("NiceTask".to_string(), 0);
+        task.init();
+        task.set_nice(10).unwrap();
+        assert_eq!(task.nice, 10);
+        task.set_nice(100).unwrap();
+        assert_eq!(task.nice, 19);
+
+        // RLIMIT_NICE 20 allows going back to nice 0, not below
+        task.rlimits.set(Resource::Nice, RLimit { cur: 20, max: 20 }).unwrap();
+        task.set_nice(0).unwrap();
+        assert!(task.set_nice(-1).is_err());
+        assert_eq!(task.nice, 0);
+
+        // Children inherit the nice value
+        task.set_nice(5).unwrap();
+        assert_eq!(task.clone_task(CloneFlags::default()).unwrap().nice, 5);
+    }
+
+    #[test_case]
+    fn test_set_scheduler_respects_rlimit() {
+        use super::rlimit::{RLimit, Resource};
+        use crate::sched::priority::SchedPolicy;
+
+        let mut task = super::new_user_task("RtTask".to_string(), 0);
+        assert!(task.set_scheduler(SchedPolicy::Fifo, 0).is_err());
+        assert!(task.set_scheduler(SchedPolicy::Normal, 10).is_err());
+
+        task.rlimits.set(Resource::RtPriority, RLimit { cur: 10, max: 10 }).unwrap();
+        task.set_scheduler(SchedPolicy::RoundRobin, 10).unwrap();
+        assert!(task.set_scheduler(SchedPolicy::Fifo, 11).is_err());
+        task.set_scheduler(SchedPolicy::Normal, 0).unwrap();
+        assert_eq!(task.sched_policy, SchedPolicy::Normal);
+    }
+
     #[test_case]
     fn test_task_parent_child_relationship() {
         let mut parent_task = super::new_user_task("ParentTask".to_string(), 0);
@@ -1483,6 +2277,18 @@ mod tests {
         assert_eq!(task.get_exit_status(), Some(1));
     }
 
+    #[test_case]
+    fn test_wait_status_encoding() {
+        assert_eq!(super::WaitStatus::Exited(0).encode(), 0);
+        assert_eq!(super::WaitStatus::Exited(3).encode(), 0x300);
+        // Only the low byte of the exit code is kept
+        assert_eq!(super::WaitStatus::Exited(-1).encode(), 0xff00);
+        assert_eq!(super::WaitStatus::Signaled(super::signal::SIGKILL, false).encode(), 9);
+        assert_eq!(super::WaitStatus::Signaled(super::signal::SIGSEGV, true).encode(), 0x8b);
+        assert_eq!(super::WaitStatus::Stopped(super::signal::SIGTSTP).encode(), 0x147f);
+        assert_eq!(super::WaitStatus::Continued.encode(), 0xffff);
+    }
+
     #[test_case]
     fn test_clone_task_memory_copy() {
         let mut parent_task = super::new_user_task("ParentTask".to_string(), 0);
@@ -1501,14 +2307,14 @@ mod tests {
         }
 
         // Get parent memory map count before cloning
-        let parent_memmap_count = parent_task.vm_manager.memmap_len();
+        let parent_memmap_count = parent_task.vm_manager.read().memmap_len();
         let parent_id = parent_task.get_id();
 
         // Clone the parent task
         let child_task = parent_task.clone_task(CloneFlags::default()).unwrap();
 
         // Get child memory map count after cloning
-        let child_memmap_count = child_task.vm_manager.memmap_len();
+        let child_memmap_count = child_task.vm_manager.read().memmap_len();
 
         // Verify that the number of memory maps are identical
         assert_eq!(child_memmap_count, parent_memmap_count, 
@@ -1525,13 +2331,15 @@ mod tests {
         assert_eq!(child_task.text_size, parent_task.text_size);
 
         // Find the corresponding memory map in child that matches our test allocation
-        let child_mmap = child_task.vm_manager.memmap_iter()
+        let child_mmap = child_task.vm_manager.read().memmap_iter()
             .find(|mmap| mmap.vmarea.start == vaddr && mmap.vmarea.end == vaddr + num_pages * crate::environment::PAGE_SIZE - 1)
+            .cloned()
             .expect("Test memory map not found in child task");
 
         // Verify that our specific memory region exists in both parent and child
-        let parent_test_mmap = parent_task.vm_manager.memmap_iter()
+        let parent_test_mmap = parent_task.vm_manager.read().memmap_iter()
             .find(|mmap| mmap.vmarea.start == vaddr && mmap.vmarea.end == vaddr + num_pages * crate::environment::PAGE_SIZE - 1)
+            .cloned()
             .expect("Test memory map not found in parent task");
 
         // Verify the virtual memory ranges match
@@ -1578,17 +2386,115 @@ mod tests {
         assert_eq!(child_task.state, parent_task.state);
 
         // Verify that both tasks have the correct number of managed pages
-        assert!(child_task.managed_pages.len() >= num_pages, 
+        assert!(child_task.managed_pages.read().len() >= num_pages, 
             "Child should have at least the test pages in managed pages");
     }
 
+    #[test_case]
+    fn test_clone_thread_shares_address_space_and_handles() {
+        use crate::task::CloneFlagsDef;
+
+        let mut parent_task = super::new_user_task("ThreadParent".to_string(), 0);
+        parent_task.init();
+        let mut flags = CloneFlags::new();
+        flags.set(CloneFlagsDef::Thread);
+        let mut thread = parent_task.clone_task(flags).unwrap();
+
+        assert!(thread.vm_manager.ptr_eq(&parent_task.vm_manager));
+        assert!(thread.handle_table.ptr_eq(&parent_task.handle_table));
+        assert_eq!(thread.vm_manager.read().get_asid(), parent_task.vm_manager.read().get_asid());
+
+        // A mapping made through one thread is seen by the other
+        let maps = parent_task.vm_manager.read().memmap_len();
+        thread.allocate_data_pages(0x1000, 1).unwrap();
+        assert_eq!(parent_task.vm_manager.read().memmap_len(), maps + 1);
+
+        // An exiting thread leaves the address space to the others
+        thread.exit(0);
+        assert!(!parent_task.vm_manager.is_shared());
+        assert!(!parent_task.handle_table.is_shared());
+        assert_eq!(parent_task.vm_manager.read().memmap_len(), maps + 1);
+
+        // A forked child gets its own copy
+        let child = parent_task.clone_task(CloneFlags::default()).unwrap();
+        assert!(!child.vm_manager.ptr_eq(&parent_task.vm_manager));
+        assert!(!child.handle_table.ptr_eq(&parent_task.handle_table));
+    }
+
+    #[test_case]
+    fn test_setup_tls() {
+        use crate::environment::PAGE_SIZE;
+        use crate::task::CloneFlagsDef;
+        use super::elf_loader::TlsTemplate;
+
+        let mut task = super::new_user_task("TlsTask".to_string(), 0);
+        task.init();
+        assert_eq!(task.setup_tls(), Ok(None));
+
+        // The initialization image lives in the loaded program
+        task.allocate_data_pages(0x1000, 1).unwrap();
+        let image = task.vm_manager.read().translate_vaddr(0x1008).unwrap() as *mut u8;
+        unsafe { core::ptr::copy_nonoverlapping([1u8, 2, 3, 4].as_ptr(), image, 4) };
+        task.tls_template = Some(TlsTemplate { image_addr: 0x1008, filesz: 4, memsz: 64, align: 8 });
+
+        let maps = task.vm_manager.read().memmap_len();
+        let tp = task.setup_tls().unwrap().unwrap();
+        assert_eq!(task.vcpu.iregs.reg[4], tp);
+        assert_eq!(tp % PAGE_SIZE, 0);
+        assert_eq!(task.vm_manager.read().memmap_len(), maps + 1);
+        let read_block = |task: &super::Task, tp: usize| {
+            let block = task.vm_manager.read().translate_vaddr(tp).unwrap() as *const u8;
+            unsafe { core::slice::from_raw_parts(block, 8).to_vec() }
+        };
+        assert_eq!(read_block(&task, tp), [1, 2, 3, 4, 0, 0, 0, 0]);
+
+        // A new thread gets a block of its own, released when it exits
+        let mut flags = CloneFlags::new();
+        flags.set(CloneFlagsDef::Thread);
+        let mut thread = task.clone_task(flags).unwrap();
+        let thread_tp = thread.vcpu.iregs.reg[4];
+        assert_ne!(thread_tp, tp);
+        assert_eq!(read_block(&thread, thread_tp), [1, 2, 3, 4, 0, 0, 0, 0]);
+        thread.exit(0);
+        assert_eq!(task.vm_manager.read().memmap_len(), maps + 1);
+
+        // A thread given its thread pointer by the caller gets none
+        flags.set(CloneFlagsDef::SetTls);
+        let thread = task.clone_task(flags).unwrap();
+        assert_eq!(task.vm_manager.read().memmap_len(), maps + 1);
+        assert_eq!(thread.tls_template, task.tls_template);
+    }
+
+    #[test_case]
+    fn test_process_group_and_session_inheritance() {
+        let mut parent_task = super::new_user_task("SessionLeader".to_string(), 0);
+        parent_task.init();
+        assert!(parent_task.is_session_leader());
+        assert_eq!(parent_task.get_pgid(), parent_task.get_id());
+
+        let mut child = parent_task.clone_task(CloneFlags::default()).unwrap();
+        assert_eq!(child.get_pgid(), parent_task.get_pgid());
+        assert_eq!(child.get_sid(), parent_task.get_sid());
+        assert!(!child.is_session_leader());
+
+        // A task can lead a new group, but not join one that does not exist
+        let child_id = child.get_id();
+        child.set_pgid(child_id).unwrap();
+        assert_eq!(child.get_pgid(), child_id);
+        assert!(child.set_pgid(usize::MAX).is_err());
+
+        // A session leader stays in its group
+        let parent_id = parent_task.get_id();
+        assert!(parent_task.set_pgid(parent_id).is_err());
+    }
+
     #[test_case]
     fn test_clone_task_stack_copy() {
         let mut parent_task = super::new_user_task("ParentWithStack".to_string(), 0);
         parent_task.init();
 
         // Find the stack memory map in parent
-        let stack_mmap = parent_task.vm_manager.memmap_iter()
+        let stack_mmap = parent_task.vm_manager.read().memmap_iter()
             .find(|mmap| {
                 // Stack should be near USER_STACK_END and have stack permissions
                 use crate::vm::vmem::VirtualMemoryRegion;
@@ -1612,13 +2518,14 @@ mod tests {
         let child_task = parent_task.clone_task(CloneFlags::default()).unwrap();
 
         // Find the corresponding stack memory map in child
-        let child_stack_mmap = child_task.vm_manager.memmap_iter()
+        let child_stack_mmap = child_task.vm_manager.read().memmap_iter()
             .find(|mmap| {
                 use crate::vm::vmem::VirtualMemoryRegion;
                 mmap.vmarea.start == stack_mmap.vmarea.start &&
                 mmap.vmarea.end == stack_mmap.vmarea.end &&
                 mmap.permissions == VirtualMemoryRegion::Stack.default_permissions()
             })
+            .cloned()
             .expect("Stack memory map not found in child task");
 
         // Verify that stack content was copied correctly
@@ -1686,10 +2593,11 @@ mod tests {
             permissions: VirtualMemoryPermission::Read as usize | VirtualMemoryPermission::Write as usize,
             is_shared: true, // This should be shared between parent and child
             owner: None,
+            zero_fill: None,
         };
         
         // Add shared memory map to parent
-        parent_task.vm_manager.add_memory_map(shared_mmap.clone()).unwrap();
+        parent_task.vm_manager.write().add_memory_map(shared_mmap.clone()).unwrap();
         
         // Write test data to shared memory
         let test_data: [u8; 8] = [0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF, 0x11, 0x22];
@@ -1702,8 +2610,9 @@ mod tests {
         let child_task = parent_task.clone_task(CloneFlags::default()).unwrap();
 
         // Find the shared memory map in child
-        let child_shared_mmap = child_task.vm_manager.memmap_iter()
+        let child_shared_mmap = child_task.vm_manager.read().memmap_iter()
             .find(|mmap| mmap.vmarea.start == shared_vaddr && mmap.is_shared)
+            .cloned()
             .expect("Shared memory map not found in child task");
 
         // Verify that the physical addresses are the same (shared memory)
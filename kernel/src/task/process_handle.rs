@@ -0,0 +1,183 @@
+//! Process handles
+//!
+//! A process handle is a [`KernelObject`](crate::object::KernelObject) that
+//! refers to one task for as long as the handle is open. It is a handle-based
+//! alternative to waiting on and signalling a task ID:
+//!
+//! - Reading from the handle blocks until the task exits and then returns its
+//!   wait status word (4 bytes, see [`WaitStatus::encode`]). After the exit the
+//!   handle stays readable, so it can be polled like any other stream: it
+//!   reports `POLLIN` once the task has exited.
+//! - Signals sent through the handle reach the task it was opened for and no
+//!   other: the handle holds the task's [`ExitNotifier`] rather than its ID,
+//!   and the target is checked against it before the signal is posted.
+//!
+//! Reading does not reap the task; its parent still collects it with
+//! `waitpid`.
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use spin::Mutex;
+
+use crate::object::capability::poll::POLLIN;
+use crate::object::capability::{PollOps, StreamError, StreamOps};
+use crate::sched::scheduler::get_scheduler;
+use crate::sync::waker::Waker;
+
+use super::{mytask, signal, TaskState, WaitStatus};
+
+/// Size of what a read from a process handle returns
+pub const EXIT_STATUS_SIZE: usize = core::mem::size_of::<i32>();
+
+/// Exit state of a task, shared by the task and the process handles opened for it
+pub struct ExitNotifier {
+    task_id: usize,
+    status: Mutex<Option<WaitStatus>>,
+    waker: Waker,
+}
+
+impl ExitNotifier {
+    pub fn new(task_id: usize) -> Self {
+        ExitNotifier {
+            task_id,
+            status: Mutex::new(None),
+            waker: Waker::new_interruptible("process_exit"),
+        }
+    }
+
+    pub fn task_id(&self) -> usize {
+        self.task_id
+    }
+
+    /// How the task ended, `None` while it runs
+    pub fn status(&self) -> Option<WaitStatus> {
+        *self.status.lock()
+    }
+
+    pub fn has_exited(&self) -> bool {
+        self.status.lock().is_some()
+    }
+
+    /// Record the exit of the task and wake the readers of its handles
+    ///
+    /// Only the first call counts.
+    pub fn notify(&self, status: WaitStatus) {
+        self.status.lock().get_or_insert(status);
+        self.waker.wake_all();
+    }
+}
+
+/// A process handle
+pub struct ProcessObject {
+    notifier: Arc<ExitNotifier>,
+}
+
+impl ProcessObject {
+    /// Open a handle for the task `task_id`
+    ///
+    /// Tasks that exited but were not reaped yet can still be opened.
+    pub fn open(task_id: usize) -> Result<Arc<Self>, &'static str> {
+        let task = get_scheduler().get_task_by_id(task_id).ok_or("No such process")?;
+        if task.get_state() == TaskState::Terminated {
+            return Err("No such process");
+        }
+        Ok(Arc::new(ProcessObject { notifier: task.exit_notifier.clone() }))
+    }
+
+    pub fn task_id(&self) -> usize {
+        self.notifier.task_id()
+    }
+
+    /// Whether a read would return without blocking
+    pub fn is_readable(&self) -> bool {
+        self.notifier.has_exited()
+    }
+
+    /// Send `sig` to the task of this handle
+    ///
+    /// # Errors
+    /// If the task has exited, or on an invalid signal number.
+    pub fn send_signal(&self, sig: usize) -> Result<(), &'static str> {
+        if self.notifier.has_exited() {
+            return Err("Process has exited");
+        }
+        let same_task = get_scheduler()
+            .get_task_by_id(self.task_id())
+            .is_some_and(|task| Arc::ptr_eq(&task.exit_notifier, &self.notifier));
+        if !same_task {
+            return Err("Process has exited");
+        }
+        signal::send_signal(self.task_id(), sig)
+    }
+}
+
+impl StreamOps for ProcessObject {
+    fn read(&self, buffer: &mut [u8]) -> Result<usize, StreamError> {
+        if buffer.len() < EXIT_STATUS_SIZE {
+            return Err(StreamError::InvalidArgument);
+        }
+        loop {
+            if let Some(status) = self.notifier.status() {
+                buffer[..EXIT_STATUS_SIZE].copy_from_slice(&status.encode().to_ne_bytes());
+                return Ok(EXIT_STATUS_SIZE);
+            }
+            let Some(task) = mytask() else {
+                return Err(StreamError::WouldBlock);
+            };
+            if task.signals.has_pending() {
+                return Err(StreamError::Interrupted);
+            }
+            let notifier = &self.notifier;
+            notifier.waker.wait_unless(task.get_id(), task.get_trapframe(), || notifier.has_exited());
+        }
+    }
+
+    fn write(&self, _buffer: &[u8]) -> Result<usize, StreamError> {
+        Err(StreamError::NotSupported)
+    }
+}
+
+impl PollOps for ProcessObject {
+    fn poll_events(&self) -> u32 {
+        if self.is_readable() { POLLIN } else { 0 }
+    }
+
+    fn poll_register(&self, task_id: usize) -> bool {
+        self.notifier.waker.register(task_id);
+        true
+    }
+
+    fn poll_unregister(&self, task_id: usize) {
+        self.notifier.waker.unregister(task_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_exit_notifier() {
+        let notifier = ExitNotifier::new(42);
+        assert!(!notifier.has_exited());
+        notifier.notify(WaitStatus::Exited(3));
+        notifier.notify(WaitStatus::Signaled(9, false));
+        assert_eq!(notifier.status(), Some(WaitStatus::Exited(3)));
+    }
+
+    #[test_case]
+    fn test_read_after_exit() {
+        let process = ProcessObject { notifier: Arc::new(ExitNotifier::new(42)) };
+        process.notifier.notify(WaitStatus::Signaled(9, false));
+        assert!(process.is_readable());
+
+        let mut buffer = [0u8; EXIT_STATUS_SIZE];
+        assert_eq!(process.read(&mut buffer).unwrap(), EXIT_STATUS_SIZE);
+        assert_eq!(i32::from_ne_bytes(buffer), 9);
+        // Level-triggered: the status can be read again
+        assert_eq!(process.read(&mut buffer).unwrap(), EXIT_STATUS_SIZE);
+        assert!(process.read(&mut buffer[..2]).is_err());
+        assert!(process.send_signal(15).is_err());
+    }
+}
@@ -0,0 +1,188 @@
+//! Per-task resource limits.
+//!
+//! Limits follow the POSIX `getrlimit`/`setrlimit` model: each resource has
+//! a soft limit that is enforced and a hard limit that caps the soft one.
+//! A task may lower either limit or raise the soft limit up to the hard
+//! limit, but never raise the hard limit. Limits are inherited on clone and
+//! kept across exec, so a limit placed on a container's first task applies
+//! to everything it starts.
+//!
+//! Resource numbers match the Linux `RLIMIT_*` values so that ABI modules
+//! can pass them through unchanged.
+
+use crate::object::handle::HandleTable;
+use crate::sched::priority::{clamp_nice, NICE_MIN};
+
+/// Value meaning "no limit"
+pub const RLIM_INFINITY: usize = usize::MAX;
+
+/// Resources that can be limited
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum Resource {
+    /// CPU time in seconds: SIGXCPU is sent every second past the soft
+    /// limit and SIGKILL at the hard limit (RLIMIT_CPU)
+    Cpu = 0,
+    /// Largest regular file a task may write; writes past it fail and send
+    /// SIGXFSZ (RLIMIT_FSIZE)
+    FileSize = 1,
+    /// Size of the data area: the program break and private writable
+    /// anonymous mappings (RLIMIT_DATA)
+    Data = 2,
+    /// Size the user stack may grow to (RLIMIT_STACK)
+    Stack = 3,
+    /// Largest core file written when a task is killed; 0 disables core
+    /// dumps (RLIMIT_CORE)
+    Core = 4,
+    /// Number of live user tasks, counted system-wide since there are no
+    /// user IDs (RLIMIT_NPROC)
+    Processes = 6,
+    /// Number of open handles (RLIMIT_NOFILE)
+    OpenFiles = 7,
+    /// Total size of the user address space (RLIMIT_AS)
+    AddressSpace = 9,
+    /// Ceiling of the priority a task may raise itself to: the nice value
+    /// may not be lowered below `20 - soft limit` (RLIMIT_NICE)
+    Nice = 13,
+    /// Highest real-time priority a task may set itself (RLIMIT_RTPRIO)
+    RtPriority = 14,
+}
+
+impl Resource {
+    /// Convert a resource number from user space
+    pub fn from_raw(raw: usize) -> Option<Self> {
+        match raw {
+            0 => Some(Resource::Cpu),
+            1 => Some(Resource::FileSize),
+            2 => Some(Resource::Data),
+            3 => Some(Resource::Stack),
+            4 => Some(Resource::Core),
+            6 => Some(Resource::Processes),
+            7 => Some(Resource::OpenFiles),
+            9 => Some(Resource::AddressSpace),
+            13 => Some(Resource::Nice),
+            14 => Some(Resource::RtPriority),
+            _ => None,
+        }
+    }
+
+    const fn index(self) -> usize {
+        match self {
+            Resource::Data => 0,
+            Resource::AddressSpace => 1,
+            Resource::Nice => 2,
+            Resource::RtPriority => 3,
+            Resource::Cpu => 4,
+            Resource::FileSize => 5,
+            Resource::Stack => 6,
+            Resource::Processes => 7,
+            Resource::OpenFiles => 8,
+            Resource::Core => 9,
+        }
+    }
+}
+
+/// Number of resources in [`Resource`]
+const RESOURCE_COUNT: usize = 10;
+
+/// Soft and hard limit of a resource, in bytes (or the unit of the
+/// resource, see [`Resource`])
+///
+/// The layout matches `struct rlimit` so it can be copied to and from user
+/// space directly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct RLimit {
+    /// Soft limit (the enforced one)
+    pub cur: usize,
+    /// Hard limit (ceiling for the soft limit)
+    pub max: usize,
+}
+
+impl RLimit {
+    pub const fn unlimited() -> Self {
+        RLimit { cur: RLIM_INFINITY, max: RLIM_INFINITY }
+    }
+
+    /// Check whether `usage` bytes are within the soft limit
+    pub fn allows(&self, usage: usize) -> bool {
+        self.cur == RLIM_INFINITY || usage <= self.cur
+    }
+}
+
+/// The resource limits of a task
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceLimits {
+    limits: [RLimit; RESOURCE_COUNT],
+}
+
+impl ResourceLimits {
+    /// Limits of a new task: everything unlimited except the number of
+    /// open handles, which the handle table caps
+    pub const fn new() -> Self {
+        let mut limits = [RLimit::unlimited(); RESOURCE_COUNT];
+        limits[Resource::OpenFiles.index()] = RLimit { cur: HandleTable::MAX_HANDLES, max: HandleTable::MAX_HANDLES };
+        ResourceLimits { limits }
+    }
+
+    /// Get the limits of a resource
+    pub fn get(&self, resource: Resource) -> RLimit {
+        self.limits[resource.index()]
+    }
+
+    /// Lowest nice value the task may set itself (from RLIMIT_NICE)
+    pub fn min_nice(&self) -> i32 {
+        let limit = self.get(Resource::Nice).cur;
+        if limit == RLIM_INFINITY {
+            return NICE_MIN;
+        }
+        clamp_nice(20 - limit.min(40) as isize)
+    }
+
+    /// Change the limits of a resource
+    ///
+    /// # Errors
+    /// If the soft limit exceeds the hard limit or the hard limit would be
+    /// raised.
+    pub fn set(&mut self, resource: Resource, limit: RLimit) -> Result<(), &'static str> {
+        if limit.cur > limit.max {
+            return Err("Soft limit exceeds hard limit");
+        }
+        let current = &mut self.limits[resource.index()];
+        if limit.max > current.max {
+            return Err("Hard limit cannot be raised");
+        }
+        *current = limit;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_rlimit_set_rules() {
+        let mut limits = ResourceLimits::new();
+        assert_eq!(limits.get(Resource::AddressSpace), RLimit::unlimited());
+
+        assert!(limits.set(Resource::AddressSpace, RLimit { cur: 0x10000, max: 0x20000 }).is_ok());
+        assert!(limits.get(Resource::AddressSpace).allows(0x10000));
+        assert!(!limits.get(Resource::AddressSpace).allows(0x10001));
+
+        // The soft limit may move up to the hard limit, the hard limit only down
+        assert!(limits.set(Resource::AddressSpace, RLimit { cur: 0x20000, max: 0x20000 }).is_ok());
+        assert!(limits.set(Resource::AddressSpace, RLimit { cur: 0x20000, max: 0x30000 }).is_err());
+        assert!(limits.set(Resource::AddressSpace, RLimit { cur: 0x30000, max: 0x20000 }).is_err());
+
+        // Other resources are unaffected
+        assert_eq!(limits.get(Resource::Data), RLimit::unlimited());
+        assert_eq!(Resource::from_raw(2), Some(Resource::Data));
+        assert_eq!(Resource::from_raw(7), Some(Resource::OpenFiles));
+        assert_eq!(Resource::from_raw(4), Some(Resource::Core));
+        assert_eq!(Resource::from_raw(5), None);
+
+        // The handle table bounds the number of open handles
+        assert_eq!(limits.get(Resource::OpenFiles).max, HandleTable::MAX_HANDLES);
+    }
+}
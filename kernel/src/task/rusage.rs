@@ -0,0 +1,197 @@
+//! CPU time accounting and resource usage reports.
+//!
+//! Every task keeps [`CpuTimes`]: the time it spent in user mode and in the
+//! kernel. The clock is read when the task traps into the kernel from user
+//! space, when it returns to user space and when it is switched out or in,
+//! and the time since the previous reading is charged to the mode the task
+//! was in. Time spent waiting off the CPU is not charged at all.
+//!
+//! When a parent reaps a child, the child's times, including those of its
+//! own reaped children, are added to the parent's child totals. The
+//! `getrusage` and `times` system calls report both.
+
+use crate::timer::ticks_to_us;
+
+/// Report the calling process (all of its threads)
+pub const RUSAGE_SELF: isize = 0;
+/// Report the reaped children of the calling process
+pub const RUSAGE_CHILDREN: isize = -1;
+/// Report the calling thread only
+pub const RUSAGE_THREAD: isize = 1;
+
+/// User and kernel time of a task, in microseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CpuTimes {
+    /// Time spent in user mode
+    pub user_us: u64,
+    /// Time spent in the kernel
+    pub system_us: u64,
+    /// Clock reading the times are charged up to
+    last_us: u64,
+    /// Whether the task is in user mode since `last_us`
+    in_user: bool,
+    /// Reaped children's times
+    pub children: ChildTimes,
+}
+
+/// Times of reaped children, in microseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ChildTimes {
+    pub user_us: u64,
+    pub system_us: u64,
+}
+
+impl CpuTimes {
+    pub const fn new() -> Self {
+        CpuTimes {
+            user_us: 0,
+            system_us: 0,
+            last_us: 0,
+            in_user: false,
+            children: ChildTimes { user_us: 0, system_us: 0 },
+        }
+    }
+
+    /// Charge the time up to `now_us` to the mode the task was in
+    fn charge(&mut self, now_us: u64) {
+        let elapsed = now_us.saturating_sub(self.last_us);
+        if self.in_user {
+            self.user_us += elapsed;
+        } else {
+            self.system_us += elapsed;
+        }
+        self.last_us = now_us.max(self.last_us);
+    }
+
+    /// The task trapped into the kernel from user space
+    pub fn enter_kernel(&mut self, now_us: u64) {
+        self.charge(now_us);
+        self.in_user = false;
+    }
+
+    /// The task returns to user space
+    pub fn exit_kernel(&mut self, now_us: u64) {
+        self.charge(now_us);
+        self.in_user = true;
+    }
+
+    /// The task is switched out of the CPU (always from the kernel)
+    pub fn switch_out(&mut self, now_us: u64) {
+        self.charge(now_us);
+    }
+
+    /// The task is switched onto the CPU; the time off it is not charged
+    pub fn switch_in(&mut self, now_us: u64) {
+        self.last_us = now_us;
+        self.in_user = false;
+    }
+
+    /// User and kernel time including the running interval up to `now_us`
+    pub fn current(&self, now_us: u64, running: bool) -> (u64, u64) {
+        let elapsed = if running { now_us.saturating_sub(self.last_us) } else { 0 };
+        if self.in_user {
+            (self.user_us + elapsed, self.system_us)
+        } else {
+            (self.user_us, self.system_us + elapsed)
+        }
+    }
+
+    /// Add the times of a reaped child (and of its own reaped children)
+    pub fn add_child(&mut self, child: &CpuTimes) {
+        self.children.user_us += child.user_us + child.children.user_us;
+        self.children.system_us += child.system_us + child.children.system_us;
+    }
+}
+
+/// `struct timeval`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(C)]
+pub struct Timeval {
+    pub sec: i64,
+    pub usec: i64,
+}
+
+impl Timeval {
+    pub fn from_us(us: u64) -> Self {
+        Timeval { sec: (us / 1_000_000) as i64, usec: (us % 1_000_000) as i64 }
+    }
+}
+
+/// `struct rusage`; only the times are filled in, the other fields are 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(C)]
+pub struct RUsage {
+    pub utime: Timeval,
+    pub stime: Timeval,
+    pub maxrss: i64,
+    pub ixrss: i64,
+    pub idrss: i64,
+    pub isrss: i64,
+    pub minflt: i64,
+    pub majflt: i64,
+    pub nswap: i64,
+    pub inblock: i64,
+    pub oublock: i64,
+    pub msgsnd: i64,
+    pub msgrcv: i64,
+    pub nsignals: i64,
+    pub nvcsw: i64,
+    pub nivcsw: i64,
+}
+
+impl RUsage {
+    pub fn from_times(user_us: u64, system_us: u64) -> Self {
+        RUsage { utime: Timeval::from_us(user_us), stime: Timeval::from_us(system_us), ..Default::default() }
+    }
+}
+
+/// `struct tms`, in clock ticks (the kernel tick)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(C)]
+pub struct Tms {
+    pub utime: i64,
+    pub stime: i64,
+    pub cutime: i64,
+    pub cstime: i64,
+}
+
+impl Tms {
+    pub fn from_times(user_us: u64, system_us: u64, children: ChildTimes) -> Self {
+        let ticks = |us: u64| (us / ticks_to_us(1)) as i64;
+        Tms {
+            utime: ticks(user_us),
+            stime: ticks(system_us),
+            cutime: ticks(children.user_us),
+            cstime: ticks(children.system_us),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_cpu_times_accounting() {
+        let mut times = CpuTimes::new();
+        times.switch_in(100);
+        times.exit_kernel(150);
+        times.enter_kernel(400);
+        times.switch_out(420);
+        // Off the CPU: not charged
+        times.switch_in(1000);
+        times.exit_kernel(1010);
+        assert_eq!((times.user_us, times.system_us), (250, 80));
+        assert_eq!(times.current(1100, true), (340, 80));
+        assert_eq!(times.current(1100, false), (250, 80));
+
+        let mut parent = CpuTimes::new();
+        parent.add_child(&times);
+        let mut grandparent = CpuTimes::new();
+        grandparent.add_child(&parent);
+        assert_eq!(grandparent.children, ChildTimes { user_us: 250, system_us: 80 });
+
+        let tms = Tms::from_times(ticks_to_us(3), 0, grandparent.children);
+        assert_eq!(tms.utime, 3);
+    }
+}
@@ -0,0 +1,1001 @@
+//! POSIX signals
+//!
+//! Every task has a signal mask and a set of pending signals; the handlers
+//! are shared with the task's threads. [`send_signal`] marks a signal
+//! pending and wakes the target from an interruptible wait. Pending signals
+//! are acted on when the task is about to return to user space
+//! ([`deliver_signals`]): a signal is either ignored, handled by its default
+//! action (terminate, ignore, stop or continue) or passed to a user handler.
+//!
+//! A user handler runs on the user stack with a [`SignalFrame`] below it
+//! that saves the interrupted registers and signal mask. The handler
+//! returns to the restorer given to `sigaction`, which issues the
+//! `sigreturn` system call to restore the frame.
+//!
+//! A system call that blocks interruptibly returns early when a signal
+//! arrives and records itself with [`interrupt_syscall`] (Linux's
+//! ERESTARTSYS). It is restarted transparently unless the signal runs a
+//! handler registered without [`SA_RESTART`], in which case the call fails
+//! with the interrupted error of its ABI (`EINTR` for Linux, -1 for the
+//! others). Sleeps record themselves with [`interrupt_sleep`] instead
+//! (ERESTARTNOHAND): they are restarted for the remaining time only if no
+//! handler runs, and fail otherwise whatever the flags of the handler.
+//!
+//! Signal numbers and flags match Linux so that ABI modules can pass them
+//! through unchanged. Signals are directed at a single task or, with
+//! [`send_signal_to_group`], at every task of a process group; there is no
+//! process-wide pending set. Terminating one thread by a signal terminates
+//! all threads of its process, which then report that signal.
+//!
+//! The signal of a synchronous fault (SIGSEGV, SIGBUS, SIGILL, SIGTRAP) is
+//! delivered like any other if a handler catches it
+//! ([`SignalState::post_fault`]); otherwise the task is terminated at once.
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+
+use crate::arch::Trapframe;
+use crate::environment::PAGE_SIZE;
+use crate::sched::scheduler::get_scheduler;
+use crate::sync::TaskShared;
+use crate::vm::vmem::VirtualMemoryPermission;
+
+use super::coredump;
+use super::{mytask, process_group_members, wake_parent_waiters, wake_task_waiters, BlockedType, Task, TaskState};
+
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGBUS: usize = 7;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+pub const SIGSTKFLT: usize = 16;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+pub const SIGTTIN: usize = 21;
+pub const SIGTTOU: usize = 22;
+pub const SIGURG: usize = 23;
+pub const SIGXCPU: usize = 24;
+pub const SIGXFSZ: usize = 25;
+pub const SIGVTALRM: usize = 26;
+pub const SIGPROF: usize = 27;
+pub const SIGWINCH: usize = 28;
+pub const SIGIO: usize = 29;
+pub const SIGPWR: usize = 30;
+pub const SIGSYS: usize = 31;
+
+/// Highest signal number
+pub const NSIG: usize = 64;
+
+/// Handler value: take the default action
+pub const SIG_DFL: usize = 0;
+/// Handler value: ignore the signal
+pub const SIG_IGN: usize = 1;
+
+/// Do not send SIGCHLD to the parent when a child stops
+pub const SA_NOCLDSTOP: usize = 0x0000_0001;
+/// The restorer field is set
+pub const SA_RESTORER: usize = 0x0400_0000;
+/// Restart system calls interrupted by the handler
+pub const SA_RESTART: usize = 0x1000_0000;
+/// Do not block the signal while its handler runs
+pub const SA_NODEFER: usize = 0x4000_0000;
+/// Reset the handler to `SIG_DFL` when the signal is delivered
+pub const SA_RESETHAND: usize = 0x8000_0000;
+
+/// `sigprocmask` operations
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+
+/// A set of signals, bit `n - 1` standing for signal `n`
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SigSet(u64);
+
+impl SigSet {
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn from_bits(bits: u64) -> Self {
+        Self(bits)
+    }
+
+    pub const fn bits(&self) -> u64 {
+        self.0
+    }
+
+    /// The set holding only `sig`
+    pub const fn single(sig: usize) -> Self {
+        Self(1 << (sig - 1))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(&self, sig: usize) -> bool {
+        self.0 & Self::single(sig).0 != 0
+    }
+
+    pub fn add(&mut self, sig: usize) {
+        self.0 |= Self::single(sig).0;
+    }
+
+    pub fn remove(&mut self, sig: usize) {
+        self.0 &= !Self::single(sig).0;
+    }
+
+    pub const fn union(&self, other: SigSet) -> SigSet {
+        Self(self.0 | other.0)
+    }
+
+    /// Signals of `self` that are not in `other`
+    pub fn difference(&self, other: SigSet) -> SigSet {
+        Self(self.0 & !other.0)
+    }
+
+    /// Lowest signal number in the set
+    pub fn first(&self) -> Option<usize> {
+        (!self.is_empty()).then(|| self.0.trailing_zeros() as usize + 1)
+    }
+}
+
+/// Signals that cannot be blocked, caught or ignored
+const UNBLOCKABLE: SigSet = SigSet::single(SIGKILL).union(SigSet::single(SIGSTOP));
+/// Signals whose default action stops the task
+const STOP_SIGNALS: SigSet = SigSet::single(SIGSTOP)
+    .union(SigSet::single(SIGTSTP))
+    .union(SigSet::single(SIGTTIN))
+    .union(SigSet::single(SIGTTOU));
+
+/// Whether `sig` is a valid signal number
+pub fn is_valid(sig: usize) -> bool {
+    (1..=NSIG).contains(&sig)
+}
+
+/// Disposition of a signal, laid out as the `sigaction` structure passed to
+/// and from user space
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SigAction {
+    /// `SIG_DFL`, `SIG_IGN` or the address of the handler
+    pub handler: usize,
+    /// `SA_*` flags
+    pub flags: usize,
+    /// Code the handler returns to; it must call `sigreturn`
+    pub restorer: usize,
+    /// Signals blocked in addition while the handler runs
+    pub mask: SigSet,
+}
+
+/// What happens to a signal without a handler
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultAction {
+    Terminate,
+    Ignore,
+    Stop,
+    Continue,
+}
+
+pub fn default_action(sig: usize) -> DefaultAction {
+    match sig {
+        SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ignore,
+        SIGCONT => DefaultAction::Continue,
+        _ if STOP_SIGNALS.contains(sig) => DefaultAction::Stop,
+        // Real-time signals and everything else
+        _ => DefaultAction::Terminate,
+    }
+}
+
+/// Whether terminating a task with `sig` writes a core file
+pub fn dumps_core(sig: usize) -> bool {
+    matches!(sig, SIGQUIT | SIGILL | SIGTRAP | SIGABRT | SIGBUS | SIGFPE | SIGSEGV | SIGXCPU | SIGXFSZ | SIGSYS)
+}
+
+/// Whether `action` discards `sig` on arrival
+fn is_ignored(action: &SigAction, sig: usize) -> bool {
+    match action.handler {
+        SIG_IGN => true,
+        SIG_DFL => default_action(sig) == DefaultAction::Ignore,
+        _ => false,
+    }
+}
+
+/// Exit status of a task terminated by `sig`
+pub fn termination_status(sig: usize) -> i32 {
+    128 + sig as i32
+}
+
+/// Signal state of a task
+pub struct SignalState {
+    /// Blocked signals
+    pub mask: SigSet,
+    /// Signals sent but not delivered yet, set by other tasks and CPUs
+    pending: AtomicU64,
+    /// Dispositions, indexed by signal number - 1, shared with the task's threads
+    pub actions: TaskShared<[SigAction; NSIG]>,
+    /// Stopped by a stop signal until SIGCONT
+    stopped: AtomicBool,
+    /// Signal of a stop not reported to the parent yet, 0 if none
+    stop_report: AtomicUsize,
+    /// Continued by SIGCONT, not reported to the parent yet
+    continue_report: AtomicBool,
+    /// Signal the task was terminated by
+    killed_by: Option<usize>,
+    /// Signal the process was terminated by, 0 while it runs; shared with
+    /// the task's threads
+    group_exit: Arc<AtomicUsize>,
+    /// A core file was written when the task was terminated
+    core_dumped: bool,
+    /// System call interrupted by a signal, to restart or fail on delivery
+    restart: Option<InterruptedSyscall>,
+    /// Mask replaced by [`SignalState::set_temporary_mask`], restored once
+    /// the signals are delivered
+    saved_mask: Option<SigSet>,
+}
+
+/// How a system call interrupted by a signal is resumed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RestartPolicy {
+    /// Restarted unless a handler registered without [`SA_RESTART`] runs
+    Restart,
+    /// Restarted only if no handler runs
+    NoHandler,
+}
+
+impl RestartPolicy {
+    /// Whether the call is issued again once the signals are delivered,
+    /// `handler` being the action of the handler that runs if any
+    fn restarts(self, handler: Option<&SigAction>) -> bool {
+        match (self, handler) {
+            (_, None) => true,
+            (RestartPolicy::Restart, Some(action)) => action.flags & SA_RESTART != 0,
+            (RestartPolicy::NoHandler, Some(_)) => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct InterruptedSyscall {
+    policy: RestartPolicy,
+    /// First argument of the call when it is issued again
+    arg0: usize,
+}
+
+impl SignalState {
+    pub fn new() -> Self {
+        Self {
+            mask: SigSet::empty(),
+            pending: AtomicU64::new(0),
+            actions: TaskShared::new([SigAction::default(); NSIG]),
+            stopped: AtomicBool::new(false),
+            stop_report: AtomicUsize::new(0),
+            continue_report: AtomicBool::new(false),
+            killed_by: None,
+            group_exit: Arc::new(AtomicUsize::new(0)),
+            core_dumped: false,
+            restart: None,
+            saved_mask: None,
+        }
+    }
+
+    /// Signal state of a child created by clone
+    ///
+    /// The child keeps the mask and starts with nothing pending. A thread
+    /// shares the handlers and is terminated with its process, other
+    /// children get a copy.
+    pub fn clone_for_child(&self, share_handlers: bool) -> Self {
+        if share_handlers {
+            Self {
+                mask: self.mask,
+                actions: self.actions.share(),
+                group_exit: self.group_exit.clone(),
+                ..Self::new()
+            }
+        } else {
+            Self { mask: self.mask, actions: TaskShared::new(*self.actions.read()), ..Self::new() }
+        }
+    }
+
+    /// Reset caught signals to their default action, as exec does
+    ///
+    /// The mask, pending signals and ignored signals are kept.
+    pub fn reset_on_exec(&mut self) {
+        for action in self.actions.write().iter_mut() {
+            if action.handler != SIG_IGN {
+                *action = SigAction::default();
+            }
+        }
+    }
+
+    pub fn action(&self, sig: usize) -> SigAction {
+        self.actions.read()[sig - 1]
+    }
+
+    /// Change the disposition of `sig`, returning the previous one
+    pub fn set_action(&mut self, sig: usize, action: SigAction) -> Result<SigAction, &'static str> {
+        if !is_valid(sig) {
+            return Err("Invalid signal number");
+        }
+        if UNBLOCKABLE.contains(sig) {
+            return Err("Signal cannot be caught or ignored");
+        }
+        let old = core::mem::replace(&mut self.actions.write()[sig - 1], action);
+        if is_ignored(&action, sig) {
+            // Setting a signal to be ignored discards it if pending
+            self.pending.fetch_and(!SigSet::single(sig).bits(), Ordering::AcqRel);
+        }
+        Ok(old)
+    }
+
+    /// Change the mask as `sigprocmask` does (`SIG_BLOCK`, `SIG_UNBLOCK` or
+    /// `SIG_SETMASK`), returning the previous mask
+    pub fn set_mask(&mut self, how: usize, set: SigSet) -> Result<SigSet, &'static str> {
+        let old = self.mask;
+        let mask = match how {
+            SIG_BLOCK => old.union(set),
+            SIG_UNBLOCK => old.difference(set),
+            SIG_SETMASK => set,
+            _ => return Err("Invalid sigprocmask operation"),
+        };
+        self.mask = mask.difference(UNBLOCKABLE);
+        Ok(old)
+    }
+
+    /// Block `mask` instead of the mask while the current system call waits,
+    /// as `ppoll` and `pselect6` do
+    ///
+    /// The call puts the mask back with [`SignalState::restore_mask`] unless
+    /// a signal interrupts it. Then the signals are delivered under `mask`
+    /// and the mask is restored by delivery: a handler returns to the
+    /// original mask.
+    pub fn set_temporary_mask(&mut self, mask: SigSet) {
+        self.saved_mask.get_or_insert(self.mask);
+        self.mask = mask.difference(UNBLOCKABLE);
+    }
+
+    /// Put back the mask replaced by [`SignalState::set_temporary_mask`]
+    pub fn restore_mask(&mut self) {
+        if let Some(mask) = self.saved_mask.take() {
+            self.mask = mask;
+        }
+    }
+
+    pub fn pending(&self) -> SigSet {
+        SigSet::from_bits(self.pending.load(Ordering::Acquire))
+    }
+
+    /// Pending signals that are not blocked
+    fn deliverable(&self) -> SigSet {
+        self.pending().difference(self.mask)
+    }
+
+    /// Whether a signal is waiting to be acted on
+    ///
+    /// Interruptible waits end early when this holds.
+    pub fn has_pending(&self) -> bool {
+        !self.deliverable().is_empty()
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Acquire)
+    }
+
+    /// Take the signal of a stop that the parent has not been told about
+    pub fn take_stop_report(&self) -> Option<usize> {
+        match self.stop_report.swap(0, Ordering::AcqRel) {
+            0 => None,
+            sig => Some(sig),
+        }
+    }
+
+    /// Take a continue that the parent has not been told about
+    pub fn take_continue_report(&self) -> bool {
+        self.continue_report.swap(false, Ordering::AcqRel)
+    }
+
+    /// Signal the task was terminated by, if it was
+    pub fn killed_by(&self) -> Option<usize> {
+        self.killed_by
+    }
+
+    /// Whether a core file was written when the task was terminated
+    pub fn core_dumped(&self) -> bool {
+        self.core_dumped
+    }
+
+    /// Make `sig` pending, handling the interplay of stop signals and SIGCONT
+    ///
+    /// # Returns
+    /// `true` if the task should be woken from an interruptible wait
+    fn post(&self, sig: usize) -> bool {
+        if sig == SIGCONT {
+            // Continuing discards pending stops, even if SIGCONT is ignored
+            self.pending.fetch_and(!STOP_SIGNALS.bits(), Ordering::AcqRel);
+            if self.stopped.swap(false, Ordering::AcqRel) {
+                self.stop_report.store(0, Ordering::Release);
+                self.continue_report.store(true, Ordering::Release);
+            }
+        } else if STOP_SIGNALS.contains(sig) {
+            self.pending.fetch_and(!SigSet::single(SIGCONT).bits(), Ordering::AcqRel);
+        }
+        if is_ignored(&self.action(sig), sig) {
+            return sig == SIGCONT;
+        }
+        self.pending.fetch_or(SigSet::single(sig).bits(), Ordering::AcqRel);
+        true
+    }
+
+    /// Make SIGKILL pending without waking the task
+    ///
+    /// For the OOM killer, which runs inside the allocator where nothing
+    /// can be woken; [`send_signal`] wakes the task later.
+    pub fn post_kill(&self) {
+        self.pending.fetch_or(SigSet::single(SIGKILL).bits(), Ordering::AcqRel);
+    }
+
+    /// Make the signal of a synchronous fault pending if a handler catches it
+    ///
+    /// The faulting instruction cannot go on, so the fault cannot be
+    /// deferred or ignored: if `sig` is blocked, ignored or at its default
+    /// action, nothing is posted and the caller terminates the task.
+    ///
+    /// # Returns
+    /// `true` if the handler runs when the task returns to user space
+    pub fn post_fault(&self, sig: usize) -> bool {
+        let handler = self.action(sig).handler;
+        if self.mask.contains(sig) || handler == SIG_IGN || handler == SIG_DFL {
+            return false;
+        }
+        self.pending.fetch_or(SigSet::single(sig).bits(), Ordering::AcqRel);
+        true
+    }
+
+    /// Take the lowest deliverable signal off the pending set
+    fn take_deliverable(&self) -> Option<usize> {
+        let sig = self.deliverable().first()?;
+        self.pending.fetch_and(!SigSet::single(sig).bits(), Ordering::AcqRel);
+        Some(sig)
+    }
+}
+
+/// Send `sig` to the task `task_id`
+///
+/// Signal 0 only checks that the task exists. Exited tasks silently accept
+/// signals.
+pub fn send_signal(task_id: usize, sig: usize) -> Result<(), &'static str> {
+    if sig != 0 && !is_valid(sig) {
+        return Err("Invalid signal number");
+    }
+    let scheduler = get_scheduler();
+    let task = scheduler.get_task_by_id(task_id).ok_or("No such task")?;
+    if sig == 0 {
+        return Ok(());
+    }
+    match task.get_state() {
+        TaskState::Zombie | TaskState::Terminated => return Ok(()),
+        _ => {}
+    }
+    let was_stopped = task.signals.is_stopped();
+    let wake = task.signals.post(sig) && task.get_state() == TaskState::Blocked(BlockedType::Interruptible);
+    let continued = was_stopped && !task.signals.is_stopped();
+    if wake {
+        scheduler.wake_task(task_id);
+    }
+    if continued {
+        if let Some(task) = get_scheduler().get_task_by_id(task_id) {
+            notify_parent(task);
+        }
+    }
+    Ok(())
+}
+
+/// Tell the parent of `task` that it stopped or continued
+///
+/// The parent gets SIGCHLD unless it asked not to with `SA_NOCLDSTOP`,
+/// and its waits are woken to collect the report.
+fn notify_parent(task: &Task) {
+    let Some(parent_id) = task.get_parent_id() else { return };
+    let notify = get_scheduler()
+        .get_task_by_id(parent_id)
+        .is_some_and(|parent| parent.signals.action(SIGCHLD).flags & SA_NOCLDSTOP == 0);
+    if notify {
+        let _ = send_signal(parent_id, SIGCHLD);
+    }
+    wake_parent_waiters(parent_id);
+    wake_task_waiters(task.get_id());
+}
+
+/// Terminate `task` and its threads as the default action of `sig`
+///
+/// The parent sees the signal in the wait status instead of an exit code.
+/// For signals like SIGSEGV a core file is written first if core dumps are
+/// enabled (see [`coredump`](super::coredump)). The other threads are sent
+/// SIGKILL; when they go they report `sig` as well.
+pub fn terminate(task: &mut Task, trapframe: &Trapframe, sig: usize) {
+    let sig = match task.signals.group_exit.compare_exchange(0, sig, Ordering::AcqRel, Ordering::Acquire) {
+        Ok(_) => {
+            if dumps_core(sig) {
+                if let Ok(path) = coredump::write_core(task, sig, trapframe) {
+                    crate::println!("[Task {}] Core dumped to {}", task.get_id(), path);
+                    task.signals.core_dumped = true;
+                }
+            }
+            for thread_id in task.thread_ids() {
+                let _ = send_signal(thread_id, SIGKILL);
+            }
+            sig
+        }
+        // Going with a thread that was terminated first
+        Err(group_sig) => group_sig,
+    };
+    task.signals.killed_by = Some(sig);
+    task.exit(termination_status(sig));
+}
+
+/// Send `sig` to every task in the process group `pgid`
+pub fn send_signal_to_group(pgid: usize, sig: usize) -> Result<(), &'static str> {
+    let members = process_group_members(pgid);
+    if members.is_empty() {
+        return Err("No such process group");
+    }
+    for task_id in members {
+        send_signal(task_id, sig)?;
+    }
+    Ok(())
+}
+
+/// End a system call interrupted by a signal
+///
+/// Call this from a system call that stopped waiting because
+/// [`SignalState::has_pending`] holds, after the program counter has been
+/// advanced. Whether the call is restarted or fails is decided when the
+/// signal is delivered.
+///
+/// # Returns
+/// The error value the system call returns if it is not restarted; the
+/// dispatcher replaces it with the interrupted error of the ABI
+pub fn interrupt_syscall(task: &mut Task, trapframe: &Trapframe) -> usize {
+    task.signals.restart = Some(InterruptedSyscall {
+        policy: RestartPolicy::Restart,
+        arg0: trapframe.get_arg(0),
+    });
+    usize::MAX
+}
+
+/// End a sleep interrupted by a signal
+///
+/// Like [`interrupt_syscall`], but the sleep fails whenever a handler runs,
+/// as `SA_RESTART` does not apply to sleeps. `arg0` is the first argument
+/// the sleep is issued again with, normally what remains of the duration.
+pub fn interrupt_sleep(task: &mut Task, arg0: usize) -> usize {
+    task.signals.restart = Some(InterruptedSyscall {
+        policy: RestartPolicy::NoHandler,
+        arg0,
+    });
+    usize::MAX
+}
+
+/// Whether the system call `task` is returning from was interrupted by a
+/// signal
+pub fn syscall_interrupted(task: &Task) -> bool {
+    task.signals.restart.is_some()
+}
+
+/// Registers and signal mask saved on the user stack while a handler runs
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SignalFrame {
+    pub regs: [usize; 32],
+    pub epc: usize,
+    /// Signal mask to restore
+    pub mask: SigSet,
+    pub signo: usize,
+}
+
+/// Act on the pending signals of `task` before it returns to user space
+///
+/// Signals without a handler are handled here: the task may exit or stop.
+/// For the first caught signal a [`SignalFrame`] is pushed and the task
+/// resumes in the handler; further signals are delivered when it returns
+/// to user space the next time.
+pub fn deliver_signals(task: &mut Task, trapframe: &mut Trapframe) {
+    let mut restart = task.signals.restart.take();
+
+    while let Some(sig) = task.signals.take_deliverable() {
+        let action = task.signals.action(sig);
+        match action.handler {
+            SIG_IGN => {}
+            SIG_DFL => match default_action(sig) {
+                DefaultAction::Ignore | DefaultAction::Continue => {}
+                DefaultAction::Terminate => {
+                    terminate(task, trapframe, sig);
+                    return;
+                }
+                DefaultAction::Stop => stop(task, trapframe, sig),
+            },
+            handler => {
+                if let Some(call) = restart.take() {
+                    if call.policy.restarts(Some(&action)) {
+                        restart_syscall(trapframe, call.arg0);
+                    }
+                }
+                if setup_frame(task, trapframe, sig, handler, &action).is_err() {
+                    // No room for the frame on the user stack
+                    terminate(task, trapframe, SIGSEGV);
+                    return;
+                }
+                if action.flags & SA_RESETHAND != 0 {
+                    task.signals.actions.write()[sig - 1] = SigAction::default();
+                }
+                return;
+            }
+        }
+    }
+
+    // No handler ran: an interrupted call is restarted
+    if let Some(call) = restart.filter(|call| call.policy.restarts(None)) {
+        restart_syscall(trapframe, call.arg0);
+    }
+    task.signals.restore_mask();
+}
+
+/// Make the task issue the interrupted system call again
+fn restart_syscall(trapframe: &mut Trapframe, arg0: usize) {
+    trapframe.set_arg(0, arg0);
+    // `ecall` has no compressed form
+    trapframe.epc -= 4;
+}
+
+/// Stop `task` by `sig` until SIGCONT or SIGKILL arrives
+fn stop(task: &mut Task, trapframe: &mut Trapframe, sig: usize) {
+    task.signals.continue_report.store(false, Ordering::Release);
+    task.signals.stop_report.store(sig, Ordering::Release);
+    task.signals.stopped.store(true, Ordering::Release);
+    notify_parent(task);
+
+    loop {
+        // Marked blocked before checking, so that a SIGCONT sent meanwhile
+        // finds the task blocked and wakes it
+        task.set_state(TaskState::Blocked(BlockedType::Interruptible));
+        if !task.signals.is_stopped() || task.signals.pending().contains(SIGKILL) {
+            task.set_state(TaskState::Running);
+            break;
+        }
+        get_scheduler().schedule(trapframe);
+    }
+}
+
+/// Push a signal frame and point the task at `handler`
+fn setup_frame(
+    task: &mut Task,
+    trapframe: &mut Trapframe,
+    sig: usize,
+    handler: usize,
+    action: &SigAction,
+) -> Result<(), &'static str> {
+    let frame = SignalFrame {
+        regs: trapframe.regs.reg,
+        epc: trapframe.epc as usize,
+        // A temporary mask is not returned to
+        mask: task.signals.saved_mask.unwrap_or(task.signals.mask),
+        signo: sig,
+    };
+    let sp = trapframe.regs.reg[2];
+    let frame_addr = sp
+        .checked_sub(core::mem::size_of::<SignalFrame>())
+        .ok_or("User stack overflow")?
+        & !0xf;
+    put_user(task, frame_addr, frame)?;
+
+    let mut mask = task.signals.mask.union(action.mask);
+    if action.flags & SA_NODEFER == 0 {
+        mask.add(sig);
+    }
+    task.signals.mask = mask.difference(UNBLOCKABLE);
+    task.signals.saved_mask = None;
+
+    trapframe.regs.reg[1] = action.restorer; // ra
+    trapframe.regs.reg[2] = frame_addr; // sp
+    trapframe.set_arg(0, sig);
+    trapframe.set_arg(1, 0);
+    trapframe.set_arg(2, frame_addr);
+    trapframe.epc = handler as u64;
+    Ok(())
+}
+
+/// Restore the state saved by [`deliver_signals`] when a handler returns
+///
+/// The frame is expected at the stack pointer, where the restorer finds it.
+///
+/// # Returns
+/// The restored first argument register, to be passed back as the return
+/// value of `sigreturn`
+pub fn sigreturn(task: &mut Task, trapframe: &mut Trapframe) -> Result<usize, &'static str> {
+    let frame_addr = trapframe.regs.reg[2];
+    let frame: SignalFrame = get_user(task, frame_addr)?;
+
+    trapframe.regs.reg = frame.regs;
+    trapframe.epc = frame.epc as u64;
+    task.signals.mask = frame.mask.difference(UNBLOCKABLE);
+    Ok(trapframe.get_return_value())
+}
+
+/// Copy `bytes` to user memory at `vaddr`, page by page
+pub fn copy_to_user(task: &Task, vaddr: usize, bytes: &[u8]) -> Result<(), &'static str> {
+    let mut done = 0;
+    while done < bytes.len() {
+        let addr = vaddr + done;
+        let len = (PAGE_SIZE - addr % PAGE_SIZE).min(bytes.len() - done);
+        let paddr = user_paddr(task, addr, true)?;
+        unsafe { core::ptr::copy_nonoverlapping(bytes[done..].as_ptr(), paddr as *mut u8, len) };
+        done += len;
+    }
+    Ok(())
+}
+
+/// Copy user memory at `vaddr` into `bytes`, page by page
+pub fn copy_from_user(task: &Task, vaddr: usize, bytes: &mut [u8]) -> Result<(), &'static str> {
+    let mut done = 0;
+    while done < bytes.len() {
+        let addr = vaddr + done;
+        let len = (PAGE_SIZE - addr % PAGE_SIZE).min(bytes.len() - done);
+        let paddr = user_paddr(task, addr, false)?;
+        unsafe { core::ptr::copy_nonoverlapping(paddr as *const u8, bytes[done..].as_mut_ptr(), len) };
+        done += len;
+    }
+    Ok(())
+}
+
+/// Whether `len` bytes at `vaddr` could be copied to (`write`) or from
+/// user memory, for checks that must come before an irreversible step
+pub fn user_access_ok(task: &Task, vaddr: usize, len: usize, write: bool) -> bool {
+    let Some(end) = vaddr.checked_add(len) else {
+        return false;
+    };
+    let mut addr = vaddr;
+    while addr < end {
+        if user_paddr(task, addr, write).is_err() {
+            return false;
+        }
+        addr = (addr & !(PAGE_SIZE - 1)) + PAGE_SIZE;
+    }
+    true
+}
+
+/// Write the structure `value` to user memory at `vaddr`, see
+/// [`copy_to_user`]
+pub fn put_user<T: Copy>(task: &Task, vaddr: usize, value: T) -> Result<(), &'static str> {
+    let bytes = unsafe { core::slice::from_raw_parts(&value as *const T as *const u8, core::mem::size_of::<T>()) };
+    copy_to_user(task, vaddr, bytes)
+}
+
+/// Read a structure from user memory at `vaddr`, see [`copy_from_user`]
+///
+/// `T` must be plain data that any bit pattern is valid for.
+pub fn get_user<T: Copy>(task: &Task, vaddr: usize) -> Result<T, &'static str> {
+    let mut value = core::mem::MaybeUninit::<T>::uninit();
+    let bytes = unsafe { core::slice::from_raw_parts_mut(value.as_mut_ptr() as *mut u8, core::mem::size_of::<T>()) };
+    copy_from_user(task, vaddr, bytes)?;
+    Ok(unsafe { value.assume_init() })
+}
+
+/// Copy `bytes` to the user buffer `arg` of the current task, or to `arg`
+/// itself in kernel context
+///
+/// For device control calls, which are also issued from kernel code.
+pub fn write_user(arg: usize, bytes: &[u8]) -> Result<(), &'static str> {
+    if arg == 0 {
+        return Err("Invalid argument pointer");
+    }
+    match mytask() {
+        Some(task) => copy_to_user(task, arg, bytes),
+        None => {
+            unsafe { core::ptr::copy_nonoverlapping(bytes.as_ptr(), arg as *mut u8, bytes.len()) };
+            Ok(())
+        }
+    }
+}
+
+/// Fill `bytes` from the user buffer `arg`, see [`write_user`]
+pub fn read_user(arg: usize, bytes: &mut [u8]) -> Result<(), &'static str> {
+    if arg == 0 {
+        return Err("Invalid argument pointer");
+    }
+    match mytask() {
+        Some(task) => copy_from_user(task, arg, bytes),
+        None => {
+            unsafe { core::ptr::copy_nonoverlapping(arg as *const u8, bytes.as_mut_ptr(), bytes.len()) };
+            Ok(())
+        }
+    }
+}
+
+/// Translate `vaddr` for a copy, refusing maps the task itself could not
+/// access: kernel-only maps such as the trampoline, and read-only maps when
+/// writing
+fn user_paddr(task: &Task, vaddr: usize, write: bool) -> Result<usize, &'static str> {
+    let vm_manager = task.vm_manager.read();
+    let map = vm_manager.search_memory_map(vaddr).ok_or("Bad user address")?;
+    if !VirtualMemoryPermission::User.contained_in(map.permissions)
+        || (write && !VirtualMemoryPermission::Write.contained_in(map.permissions))
+    {
+        return Err("Bad user address");
+    }
+    vm_manager.translate_vaddr(vaddr).ok_or("Bad user address")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_signal_post_and_mask() {
+        let mut signals = SignalState::new();
+
+        // Ignored by default: discarded on arrival
+        assert!(!signals.post(SIGCHLD));
+        assert!(signals.pending().is_empty());
+
+        signals.set_mask(SIG_BLOCK, SigSet::single(SIGUSR1).union(SigSet::single(SIGKILL))).unwrap();
+        assert!(!signals.mask.contains(SIGKILL));
+        assert!(signals.post(SIGUSR1));
+        assert!(!signals.has_pending());
+        assert!(signals.post(SIGTERM));
+        assert_eq!(signals.take_deliverable(), Some(SIGTERM));
+        assert_eq!(signals.take_deliverable(), None);
+
+        // SIGCONT discards a pending stop and ends a stop
+        assert!(signals.post(SIGTSTP));
+        signals.stopped.store(true, Ordering::Release);
+        assert!(signals.post(SIGCONT));
+        assert!(!signals.is_stopped());
+        assert!(!signals.pending().contains(SIGTSTP));
+
+        assert!(signals.set_action(SIGKILL, SigAction { handler: SIG_IGN, ..Default::default() }).is_err());
+        signals.set_action(SIGUSR1, SigAction { handler: SIG_IGN, ..Default::default() }).unwrap();
+        assert!(!signals.pending().contains(SIGUSR1));
+    }
+
+    #[test_case]
+    fn test_signal_restart_policy() {
+        let plain = SigAction { handler: 0x1000, ..Default::default() };
+        let restart = SigAction { handler: 0x1000, flags: SA_RESTART, ..Default::default() };
+
+        // Without a handler both kinds of calls go on
+        assert!(RestartPolicy::Restart.restarts(None));
+        assert!(RestartPolicy::NoHandler.restarts(None));
+
+        // A handler fails a blocking call unless it asks for a restart
+        assert!(!RestartPolicy::Restart.restarts(Some(&plain)));
+        assert!(RestartPolicy::Restart.restarts(Some(&restart)));
+
+        // and always fails a sleep
+        assert!(!RestartPolicy::NoHandler.restarts(Some(&plain)));
+        assert!(!RestartPolicy::NoHandler.restarts(Some(&restart)));
+    }
+
+    #[test_case]
+    fn test_signal_temporary_mask() {
+        let mut signals = SignalState::new();
+        signals.set_mask(SIG_SETMASK, SigSet::single(SIGUSR1)).unwrap();
+
+        // Unblocks SIGUSR1 while waiting; the original mask comes back
+        signals.set_temporary_mask(SigSet::single(SIGUSR2).union(SigSet::single(SIGKILL)));
+        assert!(!signals.mask.contains(SIGUSR1));
+        assert!(!signals.mask.contains(SIGKILL));
+        signals.set_temporary_mask(SigSet::empty());
+        signals.restore_mask();
+        assert_eq!(signals.mask, SigSet::single(SIGUSR1));
+        signals.restore_mask();
+        assert_eq!(signals.mask, SigSet::single(SIGUSR1));
+    }
+
+    #[test_case]
+    fn test_signal_post_fault() {
+        let mut signals = SignalState::new();
+        let handler = SigAction { handler: 0x1000, ..Default::default() };
+
+        // Without a handler the task is terminated instead
+        assert!(!signals.post_fault(SIGSEGV));
+        signals.set_action(SIGBUS, SigAction { handler: SIG_IGN, ..Default::default() }).unwrap();
+        assert!(!signals.post_fault(SIGBUS));
+        assert!(signals.pending().is_empty());
+
+        // A blocked fault cannot wait for the mask to change
+        signals.set_action(SIGSEGV, handler).unwrap();
+        signals.set_mask(SIG_BLOCK, SigSet::single(SIGSEGV)).unwrap();
+        assert!(!signals.post_fault(SIGSEGV));
+        signals.set_mask(SIG_SETMASK, SigSet::empty()).unwrap();
+        assert!(signals.post_fault(SIGSEGV));
+        assert_eq!(signals.take_deliverable(), Some(SIGSEGV));
+
+        // Threads share the signal their process is terminated by
+        let thread = signals.clone_for_child(true);
+        let child = signals.clone_for_child(false);
+        signals.group_exit.store(SIGSEGV, Ordering::Release);
+        assert_eq!(thread.group_exit.load(Ordering::Acquire), SIGSEGV);
+        assert_eq!(child.group_exit.load(Ordering::Acquire), 0);
+    }
+
+    #[test_case]
+    fn test_copy_user_permissions() {
+        use alloc::string::ToString;
+        use crate::vm::vmem::{MemoryArea, VirtualMemoryMap};
+
+        let mut task = crate::task::new_user_task("UaccessTask".to_string(), 0);
+        task.init();
+        let data = 0x4000_0000;
+        task.allocate_data_pages(data, 1).unwrap();
+        let text = data + PAGE_SIZE;
+        task.allocate_text_pages(text, 1).unwrap();
+        let kernel = text + PAGE_SIZE;
+        let vmarea = MemoryArea::new(kernel, kernel + PAGE_SIZE - 1);
+        let rw = VirtualMemoryPermission::Read as usize | VirtualMemoryPermission::Write as usize;
+        task.vm_manager.write().add_memory_map(VirtualMemoryMap::new_zero_fill(vmarea, rw)).unwrap();
+
+        let mut buf = [0u8; 4];
+        copy_to_user(&task, data, b"abcd").unwrap();
+        copy_from_user(&task, data, &mut buf).unwrap();
+        assert_eq!(&buf, b"abcd");
+
+        // Text reads but does not write; a kernel-only map does neither
+        assert!(copy_from_user(&task, text, &mut buf).is_ok());
+        assert!(copy_to_user(&task, text, b"abcd").is_err());
+        assert!(copy_to_user(&task, kernel, b"abcd").is_err());
+        assert!(copy_from_user(&task, kernel, &mut buf).is_err());
+
+        // A copy running off the data page into text fails as a whole
+        assert!(copy_to_user(&task, text - 2, b"abcd").is_err());
+    }
+
+    #[test_case]
+    fn test_copy_user_across_zero_fill_pages() {
+        use alloc::string::ToString;
+        use alloc::vec;
+        use crate::library::std::string::parse_c_string_from_userspace;
+        use crate::vm::vmem::{MemoryArea, VirtualMemoryMap};
+
+        // An anonymous mapping as mmap makes it: each page gets its own frame
+        let mut task = crate::task::new_user_task("ZeroFillCopyTask".to_string(), 0);
+        task.init();
+        let start = 0x4000_0000;
+        let vmarea = MemoryArea::new(start, start + 2 * PAGE_SIZE - 1);
+        let rw = VirtualMemoryPermission::Read as usize
+            | VirtualMemoryPermission::Write as usize
+            | VirtualMemoryPermission::User as usize;
+        task.vm_manager.write().add_memory_map(VirtualMemoryMap::new_zero_fill(vmarea, rw)).unwrap();
+
+        // A read landing across the page boundary
+        let data: alloc::vec::Vec<u8> = (0..64u8).collect();
+        let addr = start + PAGE_SIZE - 32;
+        copy_to_user(&task, addr, &data).unwrap();
+        let mut back = vec![0u8; data.len()];
+        copy_from_user(&task, addr, &mut back).unwrap();
+        assert_eq!(back, data);
+
+        // Each half is in the frame of its own page
+        let second = task.vm_manager.read().translate_vaddr(start + PAGE_SIZE).unwrap();
+        assert_eq!(unsafe { *(second as *const u8) }, 32);
+
+        // Strings are read across the boundary too
+        let string = start + PAGE_SIZE - 6;
+        copy_to_user(&task, string, b"across pages\0").unwrap();
+        assert_eq!(parse_c_string_from_userspace(&task, string, 64).unwrap(), "across pages");
+    }
+}
@@ -0,0 +1,196 @@
+//! Starting programs without fork.
+//!
+//! [`spawn`] starts a program in a new child task in one step: the task is
+//! created with a fresh address space, given its handles and working
+//! directory, and the program is executed in it. Unlike clone followed by
+//! execve, the caller's address space is never copied, so starting a
+//! program from a large process costs no more than from a small one.
+//!
+//! The child keeps what a forked child would keep across exec: resource
+//! limits, scheduling parameters, process group and session, credentials,
+//! the signal mask and ignored signals. Its handles are set up from [`SpawnAttr`]:
+//! with [`SPAWN_INHERIT_HANDLES`] it starts with copies of the caller's
+//! handles, otherwise with none. The handle mappings then install handles
+//! of the caller at given numbers in the child, like the `dup2` actions of
+//! `posix_spawn`. A working directory given in the attributes applies to
+//! the child only.
+
+extern crate alloc;
+
+use alloc::sync::Arc;
+
+use crate::arch::Trapframe;
+use crate::executor::executor::TransparentExecutor;
+use crate::fs::vfs_v2::manager::get_global_vfs_manager;
+use crate::fs::VfsManager;
+use crate::object::handle::{Handle, HandleTable};
+use crate::sched::scheduler::get_scheduler;
+use crate::sync::TaskShared;
+
+use super::rlimit::Resource;
+use super::{new_user_task, user_task_count, Task, TaskType};
+
+/// Start the child with copies of the caller's handles
+pub const SPAWN_INHERIT_HANDLES: usize = 0x1;
+
+/// A handle of the caller installed in the child
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct SpawnHandle {
+    /// Handle in the caller
+    pub parent: Handle,
+    /// Handle number in the child
+    pub child: Handle,
+}
+
+/// Attributes of a spawn, as passed from user space
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct SpawnAttr {
+    /// `SPAWN_*` flags
+    pub flags: usize,
+    /// Working directory of the child (C string), 0 for the caller's
+    pub cwd: usize,
+    /// Array of [`SpawnHandle`]
+    pub handles: usize,
+    /// Number of entries in `handles`
+    pub handle_count: usize,
+}
+
+impl Default for SpawnAttr {
+    /// Inherit the handles and the working directory
+    fn default() -> Self {
+        SpawnAttr { flags: SPAWN_INHERIT_HANDLES, cwd: 0, handles: 0, handle_count: 0 }
+    }
+}
+
+/// Build the handle table of a spawned child
+///
+/// # Arguments
+/// * `parent` - Handle table of the caller
+/// * `inherit` - Start from copies of all of the caller's handles
+/// * `mappings` - Handles of the caller to install in the child
+///
+/// # Errors
+/// If a mapping names a handle the caller does not have.
+pub fn child_handle_table(parent: &HandleTable, inherit: bool, mappings: &[SpawnHandle]) -> Result<HandleTable, &'static str> {
+    let mut table = if inherit { parent.clone() } else { HandleTable::new() };
+    for mapping in mappings {
+        let object = parent.get(mapping.parent).ok_or("No such handle")?;
+        let metadata = parent.get_metadata(mapping.parent).ok_or("No such handle")?;
+        table.insert_at(mapping.child, object.clone(), metadata.clone())?;
+    }
+    Ok(table)
+}
+
+/// Start the program at `path` in a new child of `parent`
+///
+/// # Arguments
+/// * `parent` - The calling task
+/// * `path` - Path of the program, relative to the child's working directory
+/// * `argv` - Arguments of the program
+/// * `envp` - Environment of the program
+/// * `attr` - Spawn attributes
+/// * `cwd` - Working directory of the child, `None` for the caller's
+/// * `mappings` - Handles of the caller to install in the child
+///
+/// # Returns
+/// The ID of the child
+///
+/// # Errors
+/// If the process limit is reached, the handles or the working directory
+/// cannot be set up, or the program cannot be executed. No child is left
+/// behind in that case.
+pub fn spawn(
+    parent: &mut Task,
+    path: &str,
+    argv: &[&str],
+    envp: &[&str],
+    attr: &SpawnAttr,
+    cwd: Option<&str>,
+    mappings: &[SpawnHandle],
+) -> Result<usize, &'static str> {
+    if parent.task_type != TaskType::User {
+        return Err("Only user tasks can spawn programs");
+    }
+    if !parent.rlimits.get(Resource::Processes).allows(user_task_count() + 1) {
+        return Err("Process limit exceeded");
+    }
+
+    let mut child = new_user_task(path.into(), parent.priority);
+    child.init();
+    child.rlimits = parent.rlimits;
+    child.oom_score_adj = parent.oom_score_adj;
+    child.nice = parent.nice;
+    child.sched_policy = parent.sched_policy;
+    child.rt_priority = parent.rt_priority;
+    child.cpu_affinity = parent.cpu_affinity;
+    child.cgroup = parent.cgroup;
+    child.cred = parent.cred.clone();
+    child.uts_ns = parent.uts_ns.clone();
+    child.net_ns = parent.net_ns.clone();
+    child.pgid = parent.pgid;
+    child.sid = parent.sid;
+    // Exec resets the handlers and keeps the mask and ignored signals
+    child.signals = parent.signals.clone_for_child(false);
+    child.default_abi = parent.default_abi.clone_boxed();
+
+    let mut handle_table = child_handle_table(&parent.handle_table.read(), attr.flags & SPAWN_INHERIT_HANDLES != 0, mappings)?;
+    handle_table.set_limit(child.rlimits.get(Resource::OpenFiles).cur);
+    child.handle_table = TaskShared::new(handle_table);
+
+    child.vfs = match cwd {
+        None => parent.vfs.clone(),
+        Some(cwd) => {
+            // A namespace of its own, so that the caller keeps its directory
+            let vfs = parent.vfs.clone().unwrap_or_else(get_global_vfs_manager);
+            let child_vfs = VfsManager::share_mounts(&vfs);
+            child_vfs.set_cwd_by_path(&vfs.resolve_path_to_absolute(cwd))
+                .map_err(|_| "Invalid working directory")?;
+            Some(Arc::new(child_vfs))
+        }
+    };
+
+    // The child has not run yet: exec leaves its registers in the vCPU
+    let mut trapframe = Trapframe::new();
+    TransparentExecutor::execute_binary(path, argv, envp, &mut child, &mut trapframe, false)
+        .map_err(|_| "Failed to execute the program")?;
+
+    let child_id = child.get_id();
+    child.set_parent_id(parent.get_id());
+    parent.add_child(child_id);
+    let cpu_id = get_scheduler().select_cpu(child.cpu_affinity);
+    get_scheduler().add_task(child, cpu_id);
+    Ok(child_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ipc::pipe::UnidirectionalPipe;
+
+    #[test_case]
+    fn test_child_handle_table() {
+        let mut parent = HandleTable::new();
+        let (read_end, write_end) = UnidirectionalPipe::create_pair(64);
+        let stdin = parent.insert(read_end.clone()).unwrap();
+        let stdout = parent.insert(write_end).unwrap();
+        let pipe = parent.insert(read_end).unwrap();
+
+        // Inherited handles keep their numbers
+        let table = child_handle_table(&parent, true, &[]).unwrap();
+        assert_eq!(table.active_handles(), [stdin, stdout, pipe]);
+
+        // Without inheritance only the mapped handles are open
+        let mappings = [SpawnHandle { parent: pipe, child: 1 }];
+        let table = child_handle_table(&parent, false, &mappings).unwrap();
+        assert_eq!(table.active_handles(), [1]);
+
+        // A mapping replaces an inherited handle
+        let table = child_handle_table(&parent, true, &mappings).unwrap();
+        assert_eq!(table.open_count(), 3);
+
+        let missing = [SpawnHandle { parent: 42, child: 0 }];
+        assert!(child_handle_table(&parent, true, &missing).is_err());
+    }
+}
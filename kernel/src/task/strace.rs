@@ -0,0 +1,499 @@
+//! System call tracing.
+//!
+//! Selected tasks have every system call they make recorded as a line like
+//! `openat(-100, "/etc/passwd", 0x0, 0) = -1 ENOENT`, which makes it clear
+//! what a program ported to one of the ABIs expects of the kernel.
+//!
+//! Each ABI describes its system calls (see `AbiModule::syscall_info`):
+//! the name and how to show each argument and the result. Strings are read
+//! from user memory when the call is made, the result when it returns, and
+//! the line is then appended to a ring buffer of [`TRACE_BUFFER_RECORDS`]
+//! records shared by all traced tasks. Calls an ABI does not describe show
+//! their number and all six argument registers.
+//!
+//! The buffer is read and the tracing is controlled through `/proc/strace`.
+//! Reading it gives a `dropped=<n>` line with the number of records that
+//! were overwritten before anyone read them, then one record per line:
+//! `<seq> <task ID> <time since boot in us> <call>`. Writing it accepts:
+//! - `trace <id>`: trace a task
+//! - `follow <id>`: trace a task and the tasks it creates from now on
+//! - `untrace <id>`: stop tracing a task
+//! - `clear`: empty the buffer
+//!
+//! Only the root user may trace the tasks of other users.
+//!
+//! A task that exits inside a system call (`exit` itself) gets `= ?` as
+//! the result.
+
+extern crate alloc;
+
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use spin::Mutex;
+
+use crate::arch::Trapframe;
+use crate::environment::PAGE_SIZE;
+use crate::sched::scheduler::get_scheduler;
+use crate::timer::get_time_us;
+
+use super::signal::copy_from_user;
+use super::{mytask, Task};
+
+/// Records kept in the ring buffer
+pub const TRACE_BUFFER_RECORDS: usize = 1024;
+
+/// Bytes of a string argument shown before it is cut with `...`
+const MAX_STRING_SHOWN: usize = 64;
+/// Strings of an array argument shown before it is cut with `...`
+const MAX_ARRAY_SHOWN: usize = 8;
+
+/// How an argument or a result is shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    /// Signed decimal (file descriptors, IDs, counts that may be -1)
+    Int,
+    /// Unsigned decimal
+    Uint,
+    /// Hexadecimal (flags, addresses)
+    Hex,
+    /// NUL-terminated string in user memory
+    Str,
+    /// NULL-terminated array of strings in user memory (argv)
+    StrArray,
+    /// Buffer in user memory whose length is the next argument
+    Buf,
+}
+
+/// Description of a system call of an ABI
+#[derive(Debug, Clone, Copy)]
+pub struct SyscallInfo {
+    /// Name as in the syscall table (CamelCase), shown in snake_case
+    pub name: &'static str,
+    /// The arguments, `None` if they are not described
+    pub args: Option<&'static [ArgKind]>,
+    /// The result when it is not an error
+    pub ret: ArgKind,
+}
+
+/// Build the [`SyscallInfo`] of an entry of a `syscall_table!`
+///
+/// The entry is the name, optionally followed by the argument kinds and
+/// the result kind: `Close (Int) -> Int`.
+#[macro_export]
+macro_rules! syscall_info {
+    ($name:ident) => {
+        $crate::task::strace::SyscallInfo {
+            name: stringify!($name),
+            args: None,
+            ret: $crate::task::strace::ArgKind::Int,
+        }
+    };
+    ($name:ident ( $($kind:ident),* ) -> $ret:ident) => {
+        $crate::task::strace::SyscallInfo {
+            name: stringify!($name),
+            args: Some(&[$($crate::task::strace::ArgKind::$kind),*]),
+            ret: $crate::task::strace::ArgKind::$ret,
+        }
+    };
+}
+
+/// Render the result of a system call if it is an error, e.g. `-1 ENOENT`
+pub type SyscallErrorFn = fn(usize) -> Option<String>;
+
+/// The error convention of the native ABI: usize::MAX
+pub fn minus_one_error(ret: usize) -> Option<String> {
+    (ret == usize::MAX).then(|| String::from("-1"))
+}
+
+/// Whether and how a task is traced
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceMode {
+    Off,
+    /// Only the task itself
+    Task,
+    /// The task and the tasks it creates
+    Follow,
+}
+
+/// A system call being made by a traced task
+struct PendingSyscall {
+    /// The call up to the result, e.g. `close(3)`
+    call: String,
+    ret: ArgKind,
+    error: SyscallErrorFn,
+}
+
+/// Tracing state of a task
+pub struct TraceState {
+    pub mode: TraceMode,
+    pending: Option<PendingSyscall>,
+}
+
+impl TraceState {
+    pub const fn new() -> Self {
+        TraceState { mode: TraceMode::Off, pending: None }
+    }
+
+    pub fn is_traced(&self) -> bool {
+        self.mode != TraceMode::Off
+    }
+
+    /// The state of a task created by the owner of this one
+    pub fn inherit(&self) -> Self {
+        match self.mode {
+            TraceMode::Follow => TraceState { mode: TraceMode::Follow, pending: None },
+            _ => TraceState::new(),
+        }
+    }
+}
+
+struct TraceRecord {
+    seq: u64,
+    task_id: usize,
+    time_us: u64,
+    text: String,
+}
+
+struct TraceBuffer {
+    records: VecDeque<TraceRecord>,
+    next_seq: u64,
+    dropped: u64,
+}
+
+static TRACE_BUFFER: Mutex<TraceBuffer> = Mutex::new(TraceBuffer {
+    records: VecDeque::new(),
+    next_seq: 0,
+    dropped: 0,
+});
+
+fn push_record(task_id: usize, text: String) {
+    let mut buffer = TRACE_BUFFER.lock();
+    if buffer.records.len() >= TRACE_BUFFER_RECORDS {
+        buffer.records.pop_front();
+        buffer.dropped += 1;
+    }
+    let seq = buffer.next_seq;
+    buffer.next_seq += 1;
+    buffer.records.push_back(TraceRecord { seq, task_id, time_us: get_time_us(), text });
+}
+
+/// Read a string of at most `limit` bytes from user memory
+///
+/// # Returns
+/// The bytes and whether the string was longer, `None` if it is not mapped
+fn read_user_string(task: &Task, ptr: usize, limit: usize) -> Option<(Vec<u8>, bool)> {
+    let mut bytes = Vec::new();
+    let mut addr = ptr;
+    while bytes.len() <= limit {
+        let mut chunk = [0u8; 32];
+        let len = chunk.len().min(PAGE_SIZE - addr % PAGE_SIZE);
+        copy_from_user(task, addr, &mut chunk[..len]).ok()?;
+        if let Some(end) = chunk[..len].iter().position(|&b| b == 0) {
+            bytes.extend_from_slice(&chunk[..end]);
+            break;
+        }
+        bytes.extend_from_slice(&chunk[..len]);
+        addr += len;
+    }
+    let truncated = bytes.len() > limit;
+    bytes.truncate(limit);
+    Some((bytes, truncated))
+}
+
+/// Append bytes as a quoted, escaped string
+fn write_quoted(out: &mut String, bytes: &[u8], truncated: bool) {
+    out.push('"');
+    for &b in bytes {
+        match b {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            b'\n' => out.push_str("\\n"),
+            b'\t' => out.push_str("\\t"),
+            0x20..=0x7e => out.push(b as char),
+            _ => { let _ = write!(out, "\\x{:02x}", b); }
+        }
+    }
+    out.push('"');
+    if truncated {
+        out.push_str("...");
+    }
+}
+
+/// Append a user pointer that could not be read
+fn write_pointer(out: &mut String, ptr: usize) {
+    if ptr == 0 {
+        out.push_str("NULL");
+    } else {
+        let _ = write!(out, "{:#x}", ptr);
+    }
+}
+
+fn write_value(out: &mut String, value: usize, kind: ArgKind) {
+    match kind {
+        ArgKind::Int => { let _ = write!(out, "{}", value as isize); }
+        ArgKind::Uint => { let _ = write!(out, "{}", value); }
+        _ => { let _ = write!(out, "{:#x}", value); }
+    }
+}
+
+/// Append an argument of a call made by `task`
+fn write_arg(out: &mut String, task: &Task, args: &[usize; 6], index: usize, kind: ArgKind) {
+    let value = args[index];
+    match kind {
+        ArgKind::Str => match read_user_string(task, value, MAX_STRING_SHOWN) {
+            Some((bytes, truncated)) if value != 0 => write_quoted(out, &bytes, truncated),
+            _ => write_pointer(out, value),
+        },
+        ArgKind::Buf => {
+            let len = args.get(index + 1).copied().unwrap_or(0);
+            let mut bytes = alloc::vec![0u8; len.min(MAX_STRING_SHOWN)];
+            if value != 0 && copy_from_user(task, value, &mut bytes).is_ok() {
+                write_quoted(out, &bytes, len > MAX_STRING_SHOWN);
+            } else {
+                write_pointer(out, value);
+            }
+        }
+        ArgKind::StrArray => {
+            if value == 0 {
+                return write_pointer(out, value);
+            }
+            out.push('[');
+            for i in 0..=MAX_ARRAY_SHOWN {
+                let mut entry = [0u8; 8];
+                if copy_from_user(task, value + i * 8, &mut entry).is_err() {
+                    break;
+                }
+                let ptr = usize::from_le_bytes(entry);
+                if ptr == 0 {
+                    break;
+                }
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                if i == MAX_ARRAY_SHOWN {
+                    out.push_str("...");
+                    break;
+                }
+                match read_user_string(task, ptr, MAX_STRING_SHOWN) {
+                    Some((bytes, truncated)) => write_quoted(out, &bytes, truncated),
+                    None => write_pointer(out, ptr),
+                }
+            }
+            out.push(']');
+        }
+        _ => write_value(out, value, kind),
+    }
+}
+
+/// Append a CamelCase name in snake_case (`RtSigaction` -> `rt_sigaction`)
+fn write_snake_case(out: &mut String, name: &str) {
+    let mut prev_lower = false;
+    for c in name.chars() {
+        if c.is_ascii_uppercase() && prev_lower {
+            out.push('_');
+        }
+        prev_lower = c.is_ascii_lowercase() || c.is_ascii_digit();
+        out.push(c.to_ascii_lowercase());
+    }
+}
+
+/// Render a call (without its result) from the argument registers
+fn format_call(task: &Task, number: usize, info: Option<SyscallInfo>, args: &[usize; 6]) -> String {
+    let mut out = String::new();
+    let kinds: &[ArgKind] = match info {
+        Some(info) => {
+            write_snake_case(&mut out, info.name);
+            info.args.unwrap_or(&[ArgKind::Hex; 6])
+        }
+        None => {
+            let _ = write!(out, "syscall_{}", number);
+            &[ArgKind::Hex; 6]
+        }
+    };
+    out.push('(');
+    for (i, &kind) in kinds.iter().enumerate().take(args.len()) {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        write_arg(&mut out, task, args, i, kind);
+    }
+    out.push(')');
+    out
+}
+
+/// Record the start of a system call made by a traced task
+///
+/// Call this before the ABI handles the call, with the description of the
+/// call and the error convention of that ABI.
+pub fn syscall_enter(task: &mut Task, info: Option<SyscallInfo>, error: SyscallErrorFn, trapframe: &Trapframe) {
+    let number = trapframe.get_arg(7);
+    let args: [usize; 6] = core::array::from_fn(|i| trapframe.get_arg(i));
+    let call = format_call(task, number, info, &args);
+    task.strace.pending = Some(PendingSyscall {
+        call,
+        ret: info.map_or(ArgKind::Int, |info| info.ret),
+        error,
+    });
+}
+
+/// Record the result of the system call started by [`syscall_enter`]
+pub fn syscall_exit(task: &mut Task, ret: usize) {
+    let Some(pending) = task.strace.pending.take() else {
+        return;
+    };
+    let mut text = pending.call;
+    text.push_str(" = ");
+    match (pending.error)(ret) {
+        Some(error) => text.push_str(&error),
+        None => write_value(&mut text, ret, pending.ret),
+    }
+    push_record(task.get_id(), text);
+}
+
+/// Record the call a traced task was making when it exited
+pub fn task_exit(task: &mut Task) {
+    if let Some(pending) = task.strace.pending.take() {
+        let mut text = pending.call;
+        text.push_str(" = ?");
+        push_record(task.get_id(), text);
+    }
+}
+
+/// Start or stop tracing a task
+///
+/// # Errors
+/// If the task does not exist, or the caller may not trace it
+pub fn set_trace_mode(task_id: usize, mode: TraceMode) -> Result<(), &'static str> {
+    let caller_cred = mytask().map(|task| task.cred.clone());
+    let task = get_scheduler().get_task_by_id(task_id).ok_or("No such task")?;
+    if let Some(cred) = caller_cred {
+        if !cred.is_privileged() && cred.euid != task.cred.uid {
+            crate::audit::privilege_denied("strace");
+            return Err("Permission denied");
+        }
+    }
+    task.strace.mode = mode;
+    if mode == TraceMode::Off {
+        task.strace.pending = None;
+    }
+    Ok(())
+}
+
+/// Render `/proc/strace`
+pub fn format_trace_buffer() -> String {
+    let buffer = TRACE_BUFFER.lock();
+    let mut out = String::new();
+    let _ = writeln!(out, "dropped={}", buffer.dropped);
+    for record in buffer.records.iter() {
+        let _ = writeln!(out, "{} {} {} {}", record.seq, record.task_id, record.time_us, record.text);
+    }
+    out
+}
+
+/// Apply a command written to `/proc/strace`
+pub fn control(command: &[u8]) -> Result<(), &'static str> {
+    let command = core::str::from_utf8(command).map_err(|_| "Invalid command")?;
+    let mut words = command.split_whitespace();
+    let verb = words.next().ok_or("Empty command")?;
+    if verb == "clear" {
+        let mut buffer = TRACE_BUFFER.lock();
+        buffer.records.clear();
+        buffer.dropped = 0;
+        return Ok(());
+    }
+    let mode = match verb {
+        "trace" => TraceMode::Task,
+        "follow" => TraceMode::Follow,
+        "untrace" => TraceMode::Off,
+        _ => return Err("Unknown strace command"),
+    };
+    let task_id = words.next().and_then(|id| id.parse::<usize>().ok()).ok_or("Invalid task ID")?;
+    set_trace_mode(task_id, mode)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+    use crate::task::{new_user_task, CloneFlags};
+
+    static ARGS: [ArgKind; 3] = [ArgKind::Int, ArgKind::Str, ArgKind::Hex];
+
+    #[test_case]
+    fn test_strace_formats_calls() {
+        let mut task = new_user_task("StraceTest".to_string(), 0);
+        task.init();
+        let mmap = task.allocate_data_pages(0x1000, 1).unwrap();
+        unsafe {
+            core::ptr::copy_nonoverlapping(b"/etc/\"x\"\n\0".as_ptr(), mmap.pmarea.start as *mut u8, 10);
+            // argv = ["/etc/\"x\"\n"] at 0x1100
+            *((mmap.pmarea.start + 0x100) as *mut usize) = 0x1000;
+            *((mmap.pmarea.start + 0x108) as *mut usize) = 0;
+        }
+
+        let info = SyscallInfo { name: "Openat", args: Some(&ARGS), ret: ArgKind::Int };
+        let call = format_call(&task, 56, Some(info), &[(-100isize) as usize, 0x1000, 0x42, 0, 0, 0]);
+        assert_eq!(call, "openat(-100, \"/etc/\\\"x\\\"\\n\", 0x42)");
+
+        // Unreadable strings show their address; unknown calls all registers
+        let call = format_call(&task, 56, Some(info), &[1, 0x9000_0000, 0, 0, 0, 0]);
+        assert_eq!(call, "openat(1, 0x90000000, 0x0)");
+        assert_eq!(format_call(&task, 7, None, &[0; 6]), "syscall_7(0x0, 0x0, 0x0, 0x0, 0x0, 0x0)");
+
+        static EXEC: [ArgKind; 2] = [ArgKind::Str, ArgKind::StrArray];
+        let info = SyscallInfo { name: "Execve", args: Some(&EXEC), ret: ArgKind::Int };
+        let call = format_call(&task, 221, Some(info), &[0, 0x1100, 0, 0, 0, 0]);
+        assert_eq!(call, "execve(NULL, [\"/etc/\\\"x\\\"\\n\"])");
+
+        static WRITE: [ArgKind; 3] = [ArgKind::Int, ArgKind::Buf, ArgKind::Uint];
+        let info = SyscallInfo { name: "Write", args: Some(&WRITE), ret: ArgKind::Int };
+        assert_eq!(format_call(&task, 64, Some(info), &[1, 0x1000, 4, 0, 0, 0]), "write(1, \"/etc\", 4)");
+
+        // Names from the syscall tables
+        let info = crate::syscall_info!(RtSigaction);
+        assert_eq!(format_call(&task, 134, Some(info), &[0; 6]), "rt_sigaction(0x0, 0x0, 0x0, 0x0, 0x0, 0x0)");
+        let info = crate::syscall_info!(ExecveABI (Str) -> Hex);
+        assert_eq!(format_call(&task, 4, Some(info), &[0; 6]), "execve_abi(NULL)");
+    }
+
+    #[test_case]
+    fn test_strace_records_and_inheritance() {
+        let mut task = new_user_task("StraceRecord".to_string(), 0);
+        task.init();
+        task.strace.mode = TraceMode::Follow;
+        let before = TRACE_BUFFER.lock().next_seq;
+
+        task.strace.pending = Some(PendingSyscall {
+            call: "close(3)".to_string(),
+            ret: ArgKind::Int,
+            error: minus_one_error,
+        });
+        syscall_exit(&mut task, usize::MAX);
+        task.strace.pending = Some(PendingSyscall {
+            call: "exit(0)".to_string(),
+            ret: ArgKind::Int,
+            error: minus_one_error,
+        });
+        task_exit(&mut task);
+
+        let content = format_trace_buffer();
+        let id = task.get_id();
+        assert!(content.contains(&alloc::format!("{} {} ", before, id)));
+        assert!(content.contains("close(3) = -1\n"));
+        assert!(content.contains("exit(0) = ?\n"));
+        assert!(TRACE_BUFFER.lock().next_seq >= before + 2);
+
+        // Children of a followed task are traced, those of a traced one not
+        let child = task.clone_task(CloneFlags::default()).unwrap();
+        assert_eq!(child.strace.mode, TraceMode::Follow);
+        task.strace.mode = TraceMode::Task;
+        let child = task.clone_task(CloneFlags::default()).unwrap();
+        assert!(!child.strace.is_traced());
+
+        assert!(control(b"bogus 1").is_err());
+        assert!(control(b"trace").is_err());
+    }
+}
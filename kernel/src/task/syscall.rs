@@ -16,16 +16,23 @@ use core::usize;
 
 use alloc::vec::Vec;
 
+use crate::abi::error::{fail, KernelError};
 use crate::abi::MAX_ABI_LENGTH;
 use crate::device::manager::DeviceManager;
+use crate::environment::PAGE_SIZE;
 use crate::executor::executor::TransparentExecutor;
 use crate::fs::MAX_PATH_LENGTH;
+use crate::object::handle::HandleTable;
+use crate::object::KernelObject;
+use crate::random::{GRND_INSECURE, GRND_NONBLOCK, GRND_RANDOM};
 use crate::library::std::string::{parse_c_string_from_userspace, parse_string_array_from_userspace};
 
 use crate::arch::{get_cpu, Trapframe};
-use crate::sched::scheduler::get_scheduler;
-use crate::task::{get_parent_waitpid_waker, get_waitpid_waker, CloneFlags, WaitError};
-use crate::timer::{get_tick, ms_to_ticks, ns_to_ticks};
+use crate::sched::affinity::CpuMask;
+use crate::sched::priority::SchedPolicy;
+use crate::sched::scheduler::{get_scheduler, online_cpus};
+use crate::task::{get_parent_waitpid_waker, get_waitpid_waker, CloneFlags, CloneFlagsDef, Task, TaskType};
+use crate::timer::{get_tick, get_time_ns, get_time_us, ms_to_ticks, ns_to_ticks};
 
 const MAX_ARG_COUNT: usize = 256; // Maximum number of arguments for execve
 
@@ -33,6 +40,13 @@ const MAX_ARG_COUNT: usize = 256; // Maximum number of arguments for execve
 pub const EXECVE_FORCE_ABI_REBUILD: usize = 0x1; // Force ABI environment reconstruction
 
 use super::mytask;
+use super::rlimit::{RLimit, Resource};
+use super::rusage::{RUsage, Tms, RUSAGE_CHILDREN, RUSAGE_SELF, RUSAGE_THREAD};
+use super::spawn::{spawn, SpawnAttr, SpawnHandle};
+use super::uts::{validate_name, UtsName, UTS_NAME_LEN};
+use super::cred::{Gid, Uid, NGROUPS_MAX};
+use super::process_handle::ProcessObject;
+use super::signal::{self, interrupt_syscall, send_signal, send_signal_to_group, SigAction, SigSet};
 
 pub fn sys_brk(trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
@@ -55,6 +69,681 @@ pub fn sys_sbrk(trapframe: &mut Trapframe) -> usize {
     }
 }
 
+/// Get the limits of a resource
+///
+/// # Arguments
+/// * arg0 - Resource number (`rlimit::Resource`)
+/// * arg1 - Pointer to a `struct rlimit` that receives the limits
+///
+/// # Returns
+/// 0 on success, usize::MAX on error
+pub fn sys_getrlimit(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let resource = trapframe.get_arg(0);
+    let rlim_ptr = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    let Some(resource) = Resource::from_raw(resource) else {
+        return usize::MAX;
+    };
+    match signal::put_user(task, rlim_ptr, task.rlimits.get(resource)) {
+        Ok(()) => 0,
+        Err(_) => usize::MAX,
+    }
+}
+
+/// Set the limits of a resource
+///
+/// Limits only take effect for future allocations; memory that is already
+/// mapped is never taken away.
+///
+/// # Arguments
+/// * arg0 - Resource number (`rlimit::Resource`)
+/// * arg1 - Pointer to a `struct rlimit` with the new limits
+///
+/// # Returns
+/// 0 on success, usize::MAX on error
+pub fn sys_setrlimit(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let resource = trapframe.get_arg(0);
+    let rlim_ptr = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    let Some(resource) = Resource::from_raw(resource) else {
+        return usize::MAX;
+    };
+    let Ok(limit) = signal::get_user::<RLimit>(task, rlim_ptr) else {
+        return usize::MAX;
+    };
+    match task.set_rlimit(resource, limit) {
+        Ok(()) => 0,
+        Err(_) => usize::MAX,
+    }
+}
+
+/// `which` value selecting a single process for getpriority/setpriority
+pub const PRIO_PROCESS: usize = 0;
+
+/// Find the task a getpriority/setpriority call refers to
+///
+/// `who` is a task ID, 0 meaning the calling task. Other selectors than
+/// `PRIO_PROCESS` (process groups, users) are not supported.
+fn priority_target(which: usize, who: usize) -> Option<&'static mut super::Task> {
+    if which != PRIO_PROCESS {
+        return None;
+    }
+    if who == 0 {
+        mytask()
+    } else {
+        get_scheduler().get_task_by_id(who)
+    }
+}
+
+/// Check whether `task_id` is `caller_id` or one of its descendants
+fn is_self_or_descendant(caller_id: usize, task_id: usize) -> bool {
+    let mut ancestor = Some(task_id);
+    while let Some(id) = ancestor {
+        if id == caller_id {
+            return true;
+        }
+        ancestor = get_scheduler().get_task_by_id(id).and_then(|t| t.get_parent_id());
+    }
+    false
+}
+
+/// Get the nice value of a task
+///
+/// # Arguments
+/// * arg0 - Selector (`PRIO_PROCESS`)
+/// * arg1 - Task ID, 0 for the calling task
+///
+/// # Returns
+/// `20 - nice` (1 to 40, so that no valid result looks like an error),
+/// usize::MAX on error
+pub fn sys_getpriority(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let which = trapframe.get_arg(0);
+    let who = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    match priority_target(which, who) {
+        Some(target) => (20 - target.nice) as usize,
+        None => usize::MAX,
+    }
+}
+
+/// Set the nice value of a task
+///
+/// A task may only change its own nice value and the ones of its
+/// descendants. Raising the nice value is always allowed, lowering it is
+/// limited by the target's RLIMIT_NICE. Out of range values are clamped.
+///
+/// # Arguments
+/// * arg0 - Selector (`PRIO_PROCESS`)
+/// * arg1 - Task ID, 0 for the calling task
+/// * arg2 - New nice value
+///
+/// # Returns
+/// 0 on success, usize::MAX on error
+pub fn sys_setpriority(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let which = trapframe.get_arg(0);
+    let who = trapframe.get_arg(1);
+    let nice = trapframe.get_arg(2) as isize;
+    trapframe.increment_pc_next(task);
+
+    let caller_id = task.get_id();
+    let Some(target) = priority_target(which, who) else {
+        return usize::MAX;
+    };
+    if !is_self_or_descendant(caller_id, target.get_id()) {
+        return usize::MAX;
+    }
+    match target.set_nice(nice) {
+        Ok(()) => 0,
+        Err(_) => usize::MAX,
+    }
+}
+
+/// Find the task a sched_* call refers to: a task ID, 0 meaning the caller
+fn sched_target(pid: usize) -> Option<&'static mut super::Task> {
+    priority_target(PRIO_PROCESS, pid)
+}
+
+/// Set the scheduling policy and real-time priority of a task
+///
+/// The same targets as for setpriority are allowed. Raising the real-time
+/// priority is limited by the target's RLIMIT_RTPRIO.
+///
+/// # Arguments
+/// * arg0 - Task ID, 0 for the calling task
+/// * arg1 - Policy (`SchedPolicy`: 0 normal, 1 FIFO, 2 round-robin)
+/// * arg2 - Real-time priority (1 to 99, 0 for the normal policy)
+///
+/// # Returns
+/// 0 on success, usize::MAX on error
+pub fn sys_sched_setscheduler(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let pid = trapframe.get_arg(0);
+    let policy = trapframe.get_arg(1);
+    let priority = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    let caller_id = task.get_id();
+    let Some(policy) = SchedPolicy::from_raw(policy) else {
+        return usize::MAX;
+    };
+    let Some(target) = sched_target(pid) else {
+        return usize::MAX;
+    };
+    if !is_self_or_descendant(caller_id, target.get_id()) {
+        return usize::MAX;
+    }
+    match target.set_scheduler(policy, priority.min(u32::MAX as usize) as u32) {
+        Ok(()) => 0,
+        Err(_) => usize::MAX,
+    }
+}
+
+/// Get the scheduling policy of a task
+///
+/// # Arguments
+/// * arg0 - Task ID, 0 for the calling task
+///
+/// # Returns
+/// The policy number, usize::MAX on error
+pub fn sys_sched_getscheduler(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let pid = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    match sched_target(pid) {
+        Some(target) => target.sched_policy as usize,
+        None => usize::MAX,
+    }
+}
+
+/// Get the real-time priority of a task
+///
+/// # Arguments
+/// * arg0 - Task ID, 0 for the calling task
+///
+/// # Returns
+/// The real-time priority (0 for normal tasks), usize::MAX on error
+pub fn sys_sched_getparam(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let pid = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    match sched_target(pid) {
+        Some(target) => target.rt_priority as usize,
+        None => usize::MAX,
+    }
+}
+
+/// Set the CPUs a task may run on
+///
+/// The same targets as for setpriority are allowed. If the calling task is
+/// no longer allowed on the current CPU it is moved right away.
+///
+/// # Arguments
+/// * arg0 - Task ID, 0 for the calling task
+/// * arg1 - Size of the mask in bytes
+/// * arg2 - Pointer to the mask, one bit per CPU
+///
+/// # Returns
+/// 0 on success, usize::MAX on error (also if the mask contains no online
+/// CPU)
+pub fn sys_sched_setaffinity(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let pid = trapframe.get_arg(0);
+    let size = trapframe.get_arg(1);
+    let mask_ptr = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    let caller_id = task.get_id();
+    // Bytes past the size of a mask can only name CPUs that do not exist
+    let mut bits = [0u8; core::mem::size_of::<usize>()];
+    let len = size.min(bits.len());
+    if signal::copy_from_user(task, mask_ptr, &mut bits[..len]).is_err() {
+        return usize::MAX;
+    }
+    let mask = CpuMask::from_bits(usize::from_le_bytes(bits));
+    if mask.intersection(online_cpus()).is_empty() {
+        return usize::MAX;
+    }
+
+    let Some(target) = sched_target(pid) else {
+        return usize::MAX;
+    };
+    let target_id = target.get_id();
+    if !is_self_or_descendant(caller_id, target_id) {
+        return usize::MAX;
+    }
+    if target.set_affinity(mask).is_err() {
+        return usize::MAX;
+    }
+    if target_id == caller_id && !mask.contains(get_cpu().get_cpuid()) {
+        // Switch away so that the scheduler moves us to an allowed CPU
+        get_scheduler().schedule(trapframe);
+    }
+    0
+}
+
+/// Get the CPUs a task may run on
+///
+/// # Arguments
+/// * arg0 - Task ID, 0 for the calling task
+/// * arg1 - Size of the buffer in bytes, at least the size of a mask
+/// * arg2 - Pointer to the buffer for the mask
+///
+/// # Returns
+/// The size of the mask in bytes, usize::MAX on error
+pub fn sys_sched_getaffinity(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let pid = trapframe.get_arg(0);
+    let size = trapframe.get_arg(1);
+    let mask_ptr = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    let bits_len = core::mem::size_of::<usize>();
+    if size < bits_len {
+        return usize::MAX;
+    }
+    let Some(target) = sched_target(pid) else {
+        return usize::MAX;
+    };
+    let bits = target.cpu_affinity.bits().to_le_bytes();
+    match signal::copy_to_user(task, mask_ptr, &bits) {
+        Ok(()) => bits_len,
+        Err(_) => usize::MAX,
+    }
+}
+
+/// Send a signal to a task or process group
+///
+/// # Arguments
+/// * arg0 - Target: a task ID if positive, 0 for the caller's process
+///   group, -1 for every user task but the caller, `-pgid` for the group
+///   `pgid`
+/// * arg1 - Signal number, 0 to only check that the target exists
+///
+/// # Returns
+/// 0 on success, usize::MAX on error
+pub fn sys_kill(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let pid = trapframe.get_arg(0) as isize;
+    let sig = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    let result = match pid {
+        0 => send_signal_to_group(task.get_pgid(), sig),
+        -1 => {
+            let scheduler = get_scheduler();
+            for task_id in scheduler.get_all_task_ids() {
+                let is_other_user_task = task_id != task.get_id()
+                    && scheduler.get_task_by_id(task_id).is_some_and(|t| t.task_type == TaskType::User);
+                if is_other_user_task {
+                    let _ = send_signal(task_id, sig);
+                }
+            }
+            Ok(())
+        }
+        pid if pid < 0 => send_signal_to_group(pid.unsigned_abs(), sig),
+        pid => send_signal(pid as usize, sig),
+    };
+    match result {
+        Ok(()) => 0,
+        Err("Invalid signal number") => fail(KernelError::InvalidArgument),
+        Err(_) => fail(KernelError::NoSuchTask),
+    }
+}
+
+/// Set the process group of a task
+///
+/// The caller may move itself or one of its children in the same session.
+///
+/// # Arguments
+/// * arg0 - Task ID, 0 for the calling task
+/// * arg1 - Process group ID, 0 to use the task ID
+///
+/// # Returns
+/// 0 on success, usize::MAX on error
+pub fn sys_setpgid(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let pid = trapframe.get_arg(0);
+    let pgid = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    let target_id = if pid == 0 { task.get_id() } else { pid };
+    if target_id != task.get_id() && !task.get_children().contains(&target_id) {
+        return fail(KernelError::NoSuchTask);
+    }
+    let caller_sid = task.get_sid();
+    let Some(target) = get_scheduler().get_task_by_id(target_id) else {
+        return fail(KernelError::NoSuchTask);
+    };
+    if target.get_sid() != caller_sid {
+        return fail(KernelError::NotPermitted);
+    }
+    let pgid = if pgid == 0 { target_id } else { pgid };
+    match target.set_pgid(pgid) {
+        Ok(()) => 0,
+        Err(_) => fail(KernelError::NotPermitted),
+    }
+}
+
+/// Get the process group of a task
+///
+/// # Arguments
+/// * arg0 - Task ID, 0 for the calling task
+///
+/// # Returns
+/// The process group ID, usize::MAX on error
+pub fn sys_getpgid(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let pid = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    if pid == 0 {
+        return task.get_pgid();
+    }
+    get_scheduler().get_task_by_id(pid).map_or_else(|| fail(KernelError::NoSuchTask), |target| target.get_pgid())
+}
+
+/// Start a new session led by the calling task
+///
+/// The task also becomes the leader of a new process group and has no
+/// controlling terminal.
+///
+/// # Returns
+/// The new session ID, usize::MAX if the caller leads a process group
+pub fn sys_setsid(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    trapframe.increment_pc_next(task);
+
+    task.setsid().unwrap_or_else(|_| fail(KernelError::NotPermitted))
+}
+
+/// Get the session of a task
+///
+/// # Arguments
+/// * arg0 - Task ID, 0 for the calling task
+///
+/// # Returns
+/// The session ID, usize::MAX on error
+pub fn sys_getsid(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let pid = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    if pid == 0 {
+        return task.get_sid();
+    }
+    get_scheduler().get_task_by_id(pid).map_or_else(|| fail(KernelError::NoSuchTask), |target| target.get_sid())
+}
+
+/// Get the resource usage of the calling process, thread or its children
+///
+/// Only the user and system times are reported; the other fields are 0.
+///
+/// # Arguments
+/// * arg0 - `RUSAGE_SELF`, `RUSAGE_THREAD` or `RUSAGE_CHILDREN`
+/// * arg1 - Pointer to a `struct rusage` that receives the usage
+///
+/// # Returns
+/// 0 on success, usize::MAX on error
+pub fn sys_getrusage(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let who = trapframe.get_arg(0) as isize;
+    let usage_ptr = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    let (user_us, system_us) = match who {
+        RUSAGE_SELF => task.process_cpu_times(),
+        RUSAGE_THREAD => task.cpu_times.current(get_time_us(), true),
+        RUSAGE_CHILDREN => (task.cpu_times.children.user_us, task.cpu_times.children.system_us),
+        _ => return usize::MAX,
+    };
+    match signal::put_user(task, usage_ptr, RUsage::from_times(user_us, system_us)) {
+        Ok(()) => 0,
+        Err(_) => usize::MAX,
+    }
+}
+
+/// Get the process times of the calling process and its children
+///
+/// # Arguments
+/// * arg0 - Pointer to a `struct tms` that receives the times (in ticks), or 0
+///
+/// # Returns
+/// Ticks since boot, usize::MAX on error
+pub fn sys_times(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let tms_ptr = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    if tms_ptr != 0 {
+        let (user_us, system_us) = task.process_cpu_times();
+        if signal::put_user(task, tms_ptr, Tms::from_times(user_us, system_us, task.cpu_times.children)).is_err() {
+            return usize::MAX;
+        }
+    }
+    get_tick() as usize
+}
+
+/// Get the time since boot
+///
+/// This is the clock of the vDSO (see [`crate::vm::vdso`]) at full
+/// resolution, for programs that cannot use the vDSO.
+///
+/// # Returns
+/// Nanoseconds since boot
+pub fn sys_clock_gettime(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    trapframe.increment_pc_next(task);
+    get_time_ns() as usize
+}
+
+/// Get the wall-clock time
+///
+/// Counts from the Unix epoch once an RTC or `sys_clock_set_realtime` has
+/// told the time, from boot before (see [`crate::time`]).
+///
+/// # Returns
+/// Nanoseconds since the Unix epoch
+pub fn sys_clock_get_realtime(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    trapframe.increment_pc_next(task);
+    crate::time::realtime_ns() as usize
+}
+
+/// Set the wall-clock time, and the RTC if there is one
+///
+/// # Arguments
+/// * arg0 - Nanoseconds since the Unix epoch
+///
+/// # Returns
+/// 0 on success, usize::MAX on error
+pub fn sys_clock_set_realtime(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let ns = trapframe.get_arg(0) as u64;
+    trapframe.increment_pc_next(task);
+
+    if !task.cred.is_privileged() {
+        crate::audit::privilege_denied("clock_set_realtime");
+        return fail(KernelError::NotPermitted);
+    }
+    crate::time::set_realtime_ns(ns);
+    0
+}
+
+/// Largest request `sys_getrandom` serves in one call
+const GETRANDOM_MAX: usize = (1 << 25) - 1;
+
+/// Fill a buffer with random bytes from the kernel CSPRNG
+///
+/// Unless `GRND_INSECURE` is given, waits until the generator is seeded,
+/// or fails with `WouldBlock` if `GRND_NONBLOCK` is. A large request may be
+/// cut short by a signal.
+///
+/// # Arguments
+/// * arg0 - Pointer to the buffer
+/// * arg1 - Length of the buffer
+/// * arg2 - Flags (`GRND_NONBLOCK`, `GRND_RANDOM`, `GRND_INSECURE`)
+///
+/// # Returns
+/// The number of bytes written, or usize::MAX on error
+pub fn sys_getrandom(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let buf_ptr = trapframe.get_arg(0);
+    let len = trapframe.get_arg(1).min(GETRANDOM_MAX);
+    let flags = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    if flags & !(GRND_NONBLOCK | GRND_RANDOM | GRND_INSECURE) != 0
+        || flags & (GRND_RANDOM | GRND_INSECURE) == (GRND_RANDOM | GRND_INSECURE)
+    {
+        return fail(KernelError::InvalidArgument);
+    }
+    if flags & GRND_INSECURE == 0 {
+        if let Err(error) = crate::random::wait_until_seeded(flags & GRND_NONBLOCK != 0) {
+            return fail(error);
+        }
+    }
+
+    let mut chunk = [0u8; 256];
+    let mut written = 0;
+    while written < len {
+        let n = chunk.len().min(len - written);
+        crate::random::fill_random_bytes(&mut chunk[..n]);
+        if signal::copy_to_user(task, buf_ptr + written, &chunk[..n]).is_err() {
+            break;
+        }
+        written += n;
+        if written % PAGE_SIZE == 0 && task.signals.has_pending() {
+            break;
+        }
+    }
+    chunk.fill(0);
+    match written {
+        0 if len > 0 && task.signals.has_pending() => fail(KernelError::Interrupted),
+        0 if len > 0 => fail(KernelError::BadAddress),
+        _ => written,
+    }
+}
+
+/// Examine and change the disposition of a signal
+///
+/// # Arguments
+/// * arg0 - Signal number
+/// * arg1 - Pointer to the new `SigAction`, or 0 to keep it
+/// * arg2 - Pointer receiving the previous `SigAction`, or 0
+///
+/// # Returns
+/// 0 on success, usize::MAX on error
+pub fn sys_sigaction(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let sig = trapframe.get_arg(0);
+    let act_ptr = trapframe.get_arg(1);
+    let oldact_ptr = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    if !signal::is_valid(sig) {
+        return usize::MAX;
+    }
+    let old = if act_ptr != 0 {
+        let Ok(act) = signal::get_user::<SigAction>(task, act_ptr) else {
+            return usize::MAX;
+        };
+        match task.signals.set_action(sig, act) {
+            Ok(old) => old,
+            Err(_) => return usize::MAX,
+        }
+    } else {
+        task.signals.action(sig)
+    };
+    if oldact_ptr != 0 {
+        if signal::put_user(task, oldact_ptr, old).is_err() {
+            return usize::MAX;
+        }
+    }
+    0
+}
+
+/// Examine and change the signal mask of the calling task
+///
+/// # Arguments
+/// * arg0 - `SIG_BLOCK`, `SIG_UNBLOCK` or `SIG_SETMASK`
+/// * arg1 - Pointer to the signal set (u64), or 0 to keep the mask
+/// * arg2 - Pointer receiving the previous mask, or 0
+///
+/// # Returns
+/// 0 on success, usize::MAX on error
+pub fn sys_sigprocmask(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let how = trapframe.get_arg(0);
+    let set_ptr = trapframe.get_arg(1);
+    let oldset_ptr = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    let old = if set_ptr != 0 {
+        let Ok(bits) = signal::get_user::<u64>(task, set_ptr) else {
+            return fail(KernelError::BadAddress);
+        };
+        let set = SigSet::from_bits(bits);
+        match task.signals.set_mask(how, set) {
+            Ok(old) => old,
+            Err(_) => return fail(KernelError::InvalidArgument),
+        }
+    } else {
+        task.signals.mask
+    };
+    if oldset_ptr != 0 {
+        if signal::put_user(task, oldset_ptr, old.bits()).is_err() {
+            return fail(KernelError::BadAddress);
+        }
+    }
+    0
+}
+
+/// Get the signals pending for the calling task
+///
+/// # Arguments
+/// * arg0 - Pointer receiving the signal set (u64)
+///
+/// # Returns
+/// 0 on success, usize::MAX on error
+pub fn sys_sigpending(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let set_ptr = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    match signal::put_user(task, set_ptr, task.signals.pending().bits()) {
+        Ok(()) => 0,
+        Err(_) => usize::MAX,
+    }
+}
+
+/// Return from a signal handler
+///
+/// Called by the restorer with the stack pointer at the signal frame.
+/// Restores the registers and signal mask saved when the handler was
+/// entered; a task with a corrupt frame is terminated as with SIGSEGV.
+///
+/// # Returns
+/// The restored a0, so that the interrupted code sees it unchanged
+pub fn sys_sigreturn(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    match signal::sigreturn(task, trapframe) {
+        Ok(a0) => a0,
+        Err(_) => {
+            signal::terminate(task, trapframe, signal::SIGSEGV);
+            usize::MAX
+        }
+    }
+}
+
 pub fn sys_putchar(trapframe: &mut Trapframe) -> usize {
     let c = trapframe.get_arg(0) as u32;
     let task = mytask().unwrap();
@@ -102,12 +791,29 @@ pub fn sys_exit(trapframe: &mut Trapframe) -> usize {
     usize::MAX // -1 (If exit is successful, this will not be reached)
 }
 
+/// Create a new task from the calling one
+///
+/// # Arguments
+/// * arg0 - Clone flags (`CloneFlagsDef`)
+/// * arg1 - Stack pointer of the child, 0 to keep the caller's (required
+///   for threads, which share the address space)
+/// * arg2 - Thread pointer (TLS) of the child, used with `SetTls`; a thread
+///   created without it gets a TLS block of its own (see `Task::setup_tls`)
+///
+/// # Returns
+/// The child task ID to the caller and 0 to the child, usize::MAX on error
 pub fn sys_clone(trapframe: &mut Trapframe) -> usize {
     let parent_task = mytask().unwrap();
     trapframe.increment_pc_next(parent_task); /* Increment the program counter */
     /* Save the trapframe to the task before cloning */
     parent_task.vcpu.store(trapframe);
     let clone_flags = CloneFlags::from_raw(trapframe.get_arg(0) as u64);
+    let stack = trapframe.get_arg(1);
+    let tls = trapframe.get_arg(2);
+    if clone_flags.shares_vm() && stack == 0 {
+        // Both tasks would push onto the same stack
+        return usize::MAX;
+    }
 
     // crate::println!("[CLONE] Parent task {} cloning with flags: 0x{:x}", parent_task.get_id(), clone_flags.get_raw());
 
@@ -118,7 +824,15 @@ pub fn sys_clone(trapframe: &mut Trapframe) -> usize {
             // crate::println!("[CLONE] Successfully created child task {}, state: {:?}, PC: 0x{:x}", 
             //     child_id, child_task.get_state(), child_task.vcpu.get_pc());
             child_task.vcpu.iregs.reg[10] = 0; /* Set the return value to 0 in the child task */
-            get_scheduler().add_task(child_task, get_cpu().get_cpuid());
+            if stack != 0 {
+                child_task.vcpu.set_sp(stack);
+            }
+            if clone_flags.is_set(CloneFlagsDef::SetTls) {
+                child_task.vcpu.iregs.reg[4] = tls; /* tp */
+            }
+            /* Threads and new processes alike go to the least loaded CPU */
+            let cpu_id = get_scheduler().select_cpu(child_task.cpu_affinity);
+            get_scheduler().add_task(child_task, cpu_id);
             // crate::println!("[CLONE] Child task {} added to scheduler", child_id);
             /* Return the child task ID to the parent task */
             child_id
@@ -272,83 +986,513 @@ pub fn sys_execve_abi(trapframe: &mut Trapframe) -> usize {
     }
 }
 
+/// Start a program in a new child without copying the caller
+///
+/// # Arguments
+/// * arg0 - Path of the program (C string)
+/// * arg1 - argv (NULL-terminated array of C strings)
+/// * arg2 - envp (NULL-terminated array of C strings)
+/// * arg3 - Pointer to a `SpawnAttr`, or 0 to inherit the handles and the
+///   working directory
+///
+/// # Returns
+/// The ID of the child, usize::MAX on error
+pub fn sys_spawn(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let path_ptr = trapframe.get_arg(0);
+    let argv_ptr = trapframe.get_arg(1);
+    let envp_ptr = trapframe.get_arg(2);
+    let attr_ptr = trapframe.get_arg(3);
+    trapframe.increment_pc_next(task);
+
+    let Ok(path) = parse_c_string_from_userspace(task, path_ptr, MAX_PATH_LENGTH) else {
+        return usize::MAX;
+    };
+    let Ok(argv) = parse_string_array_from_userspace(task, argv_ptr, MAX_ARG_COUNT, MAX_PATH_LENGTH) else {
+        return usize::MAX;
+    };
+    let Ok(envp) = parse_string_array_from_userspace(task, envp_ptr, MAX_ARG_COUNT, MAX_PATH_LENGTH) else {
+        return usize::MAX;
+    };
+
+    let attr = if attr_ptr == 0 {
+        SpawnAttr::default()
+    } else {
+        match signal::get_user::<SpawnAttr>(task, attr_ptr) {
+            Ok(attr) => attr,
+            Err(_) => return usize::MAX,
+        }
+    };
+    let cwd = if attr.cwd == 0 {
+        None
+    } else {
+        match parse_c_string_from_userspace(task, attr.cwd, MAX_PATH_LENGTH) {
+            Ok(cwd) => Some(cwd),
+            Err(_) => return usize::MAX,
+        }
+    };
+    if attr.handle_count > HandleTable::MAX_HANDLES {
+        return usize::MAX;
+    }
+    let mut mappings = Vec::with_capacity(attr.handle_count);
+    for i in 0..attr.handle_count {
+        let entry = attr.handles + i * core::mem::size_of::<SpawnHandle>();
+        match signal::get_user::<SpawnHandle>(task, entry) {
+            Ok(mapping) => mappings.push(mapping),
+            Err(_) => return usize::MAX,
+        }
+    }
+
+    let argv_refs: Vec<&str> = argv.iter().map(|s| s.as_str()).collect();
+    let envp_refs: Vec<&str> = envp.iter().map(|s| s.as_str()).collect();
+    match spawn(task, &path, &argv_refs, &envp_refs, &attr, cwd.as_deref(), &mappings) {
+        Ok(child_id) => child_id,
+        Err(_) => usize::MAX,
+    }
+}
+
+/// Set the host name of the caller's UTS namespace (privileged tasks only)
+///
+/// # Arguments
+/// * arg0 - Pointer to the name (not NUL-terminated)
+/// * arg1 - Length of the name, at most `UTS_NAME_LEN`
+///
+/// # Returns
+/// 0 on success, usize::MAX on error
+pub fn sys_sethostname(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let name_ptr = trapframe.get_arg(0);
+    let len = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    if !task.cred.is_privileged() {
+        crate::audit::privilege_denied("sethostname");
+        return fail(KernelError::NotPermitted);
+    }
+    if len > UTS_NAME_LEN {
+        return fail(KernelError::InvalidArgument);
+    }
+    let mut name = [0u8; UTS_NAME_LEN];
+    if signal::copy_from_user(task, name_ptr, &mut name[..len]).is_err() {
+        return fail(KernelError::BadAddress);
+    }
+    match validate_name(&name[..len]) {
+        Ok(name) if task.uts_ns.set_hostname(name).is_ok() => 0,
+        _ => fail(KernelError::InvalidArgument),
+    }
+}
+
+/// Get the host name of the caller's UTS namespace
+///
+/// # Arguments
+/// * arg0 - Buffer receiving the NUL-terminated name
+/// * arg1 - Size of the buffer
+///
+/// # Returns
+/// 0 on success, usize::MAX on error (including a buffer too small for the
+/// name and its NUL)
+pub fn sys_gethostname(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let buf_ptr = trapframe.get_arg(0);
+    let len = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    let mut hostname = task.uts_ns.hostname().into_bytes();
+    hostname.push(0);
+    if hostname.len() > len || signal::copy_to_user(task, buf_ptr, &hostname).is_err() {
+        return usize::MAX;
+    }
+    0
+}
+
+/// Get the names of the caller's UTS namespace
+///
+/// # Arguments
+/// * arg0 - Pointer to a `UtsName` that receives the names
+///
+/// # Returns
+/// 0 on success, usize::MAX on error
+pub fn sys_uname(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let buf_ptr = trapframe.get_arg(0);
+    trapframe.increment_pc_next(task);
+
+    let name = task.uts_ns.uname();
+    let bytes = unsafe {
+        core::slice::from_raw_parts(&name as *const UtsName as *const u8, core::mem::size_of::<UtsName>())
+    };
+    match signal::copy_to_user(task, buf_ptr, bytes) {
+        Ok(()) => 0,
+        Err(_) => fail(KernelError::BadAddress),
+    }
+}
+
+/// Get the real user ID of the caller
+pub fn sys_getuid(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    trapframe.increment_pc_next(task);
+    task.cred.uid as usize
+}
+
+/// Get the effective user ID of the caller
+pub fn sys_geteuid(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    trapframe.increment_pc_next(task);
+    task.cred.euid as usize
+}
+
+/// Get the real group ID of the caller
+pub fn sys_getgid(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    trapframe.increment_pc_next(task);
+    task.cred.gid as usize
+}
+
+/// Get the effective group ID of the caller
+pub fn sys_getegid(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    trapframe.increment_pc_next(task);
+    task.cred.egid as usize
+}
+
+/// Map the result of a credential change to a syscall return value
+///
+/// A change refused for lack of privilege is audited as `op`.
+fn cred_result(op: &str, result: Result<(), &'static str>) -> usize {
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            let error = KernelError::from_message(e);
+            if error == KernelError::NotPermitted {
+                crate::audit::privilege_denied(op);
+            }
+            fail(error)
+        }
+    }
+}
+
+/// Set the user ID of the caller (see `Credentials::setuid`)
+///
+/// # Arguments
+/// * arg0 - User ID
+///
+/// # Returns
+/// 0 on success, usize::MAX on error
+pub fn sys_setuid(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let uid = trapframe.get_arg(0) as Uid;
+    trapframe.increment_pc_next(task);
+    cred_result("setuid", task.cred.setuid(uid))
+}
+
+/// Set the group ID of the caller (see `Credentials::setgid`)
+///
+/// # Arguments
+/// * arg0 - Group ID
+///
+/// # Returns
+/// 0 on success, usize::MAX on error
+pub fn sys_setgid(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let gid = trapframe.get_arg(0) as Gid;
+    trapframe.increment_pc_next(task);
+    cred_result("setgid", task.cred.setgid(gid))
+}
+
+/// Set the real and effective user IDs of the caller
+///
+/// # Arguments
+/// * arg0 - Real user ID, `ID_UNCHANGED` to keep it
+/// * arg1 - Effective user ID, `ID_UNCHANGED` to keep it
+///
+/// # Returns
+/// 0 on success, usize::MAX on error
+pub fn sys_setreuid(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let ruid = trapframe.get_arg(0) as Uid;
+    let euid = trapframe.get_arg(1) as Uid;
+    trapframe.increment_pc_next(task);
+    cred_result("setreuid", task.cred.setreuid(ruid, euid))
+}
+
+/// Set the real and effective group IDs of the caller
+///
+/// # Arguments
+/// * arg0 - Real group ID, `ID_UNCHANGED` to keep it
+/// * arg1 - Effective group ID, `ID_UNCHANGED` to keep it
+///
+/// # Returns
+/// 0 on success, usize::MAX on error
+pub fn sys_setregid(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let rgid = trapframe.get_arg(0) as Gid;
+    let egid = trapframe.get_arg(1) as Gid;
+    trapframe.increment_pc_next(task);
+    cred_result("setregid", task.cred.setregid(rgid, egid))
+}
+
+/// Set the real, effective and saved user IDs of the caller
+///
+/// # Arguments
+/// * arg0 - Real user ID, `ID_UNCHANGED` to keep it
+/// * arg1 - Effective user ID, `ID_UNCHANGED` to keep it
+/// * arg2 - Saved user ID, `ID_UNCHANGED` to keep it
+///
+/// # Returns
+/// 0 on success, usize::MAX on error
+pub fn sys_setresuid(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let ruid = trapframe.get_arg(0) as Uid;
+    let euid = trapframe.get_arg(1) as Uid;
+    let suid = trapframe.get_arg(2) as Uid;
+    trapframe.increment_pc_next(task);
+    cred_result("setresuid", task.cred.setresuid(ruid, euid, suid))
+}
+
+/// Set the real, effective and saved group IDs of the caller
+///
+/// # Arguments
+/// * arg0 - Real group ID, `ID_UNCHANGED` to keep it
+/// * arg1 - Effective group ID, `ID_UNCHANGED` to keep it
+/// * arg2 - Saved group ID, `ID_UNCHANGED` to keep it
+///
+/// # Returns
+/// 0 on success, usize::MAX on error
+pub fn sys_setresgid(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let rgid = trapframe.get_arg(0) as Gid;
+    let egid = trapframe.get_arg(1) as Gid;
+    let sgid = trapframe.get_arg(2) as Gid;
+    trapframe.increment_pc_next(task);
+    cred_result("setresgid", task.cred.setresgid(rgid, egid, sgid))
+}
+
+/// Write three IDs to user memory
+fn copy_ids_to_user(task: &Task, ptrs: [usize; 3], ids: [u32; 3]) -> Result<(), &'static str> {
+    for (ptr, id) in ptrs.into_iter().zip(ids) {
+        signal::copy_to_user(task, ptr, &id.to_ne_bytes())?;
+    }
+    Ok(())
+}
+
+/// Get the real, effective and saved user IDs of the caller
+///
+/// # Arguments
+/// * arg0, arg1, arg2 - Pointers to `u32`s receiving the real, effective
+///   and saved user ID
+///
+/// # Returns
+/// 0 on success, usize::MAX on error
+pub fn sys_getresuid(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let ptrs = [trapframe.get_arg(0), trapframe.get_arg(1), trapframe.get_arg(2)];
+    trapframe.increment_pc_next(task);
+    let ids = [task.cred.uid, task.cred.euid, task.cred.suid];
+    cred_result("getresuid", copy_ids_to_user(task, ptrs, ids))
+}
+
+/// Get the real, effective and saved group IDs of the caller
+///
+/// # Arguments
+/// * arg0, arg1, arg2 - Pointers to `u32`s receiving the real, effective
+///   and saved group ID
+///
+/// # Returns
+/// 0 on success, usize::MAX on error
+pub fn sys_getresgid(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let ptrs = [trapframe.get_arg(0), trapframe.get_arg(1), trapframe.get_arg(2)];
+    trapframe.increment_pc_next(task);
+    let ids = [task.cred.gid, task.cred.egid, task.cred.sgid];
+    cred_result("getresgid", copy_ids_to_user(task, ptrs, ids))
+}
+
+/// Get the supplementary groups of the caller
+///
+/// # Arguments
+/// * arg0 - Size of the array in entries; 0 only returns the count
+/// * arg1 - Array of `u32` receiving the groups
+///
+/// # Returns
+/// The number of groups, usize::MAX on error (including an array too small)
+pub fn sys_getgroups(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let size = trapframe.get_arg(0);
+    let list_ptr = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    let groups = &task.cred.groups;
+    if size == 0 {
+        return groups.len();
+    }
+    if size < groups.len() {
+        return fail(KernelError::InvalidArgument);
+    }
+    let bytes: Vec<u8> = groups.iter().flat_map(|gid| gid.to_ne_bytes()).collect();
+    match signal::copy_to_user(task, list_ptr, &bytes) {
+        Ok(()) => groups.len(),
+        Err(_) => fail(KernelError::BadAddress),
+    }
+}
+
+/// Set the supplementary groups of the caller (privileged tasks only)
+///
+/// # Arguments
+/// * arg0 - Number of groups, at most `NGROUPS_MAX`
+/// * arg1 - Array of `u32` group IDs
+///
+/// # Returns
+/// 0 on success, usize::MAX on error
+pub fn sys_setgroups(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let size = trapframe.get_arg(0);
+    let list_ptr = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    if size > NGROUPS_MAX {
+        return fail(KernelError::InvalidArgument);
+    }
+    let mut bytes = [0u8; NGROUPS_MAX * 4];
+    if signal::copy_from_user(task, list_ptr, &mut bytes[..size * 4]).is_err() {
+        return fail(KernelError::BadAddress);
+    }
+    let groups: Vec<Gid> = bytes[..size * 4].chunks_exact(4)
+        .map(|chunk| u32::from_ne_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect();
+    cred_result("setgroups", task.cred.setgroups(&groups))
+}
+
+/// Open a process handle for a task
+///
+/// Reading the handle waits for the task to exit and returns its wait
+/// status; see `crate::task::process_handle`.
+///
+/// # Arguments
+/// * arg0 - Task ID, 0 for the calling task
+/// * arg1 - Flags, must be 0
+///
+/// # Returns
+/// The handle, usize::MAX on error
+pub fn sys_process_open(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let pid = trapframe.get_arg(0);
+    let flags = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    if flags != 0 {
+        return usize::MAX;
+    }
+    let pid = if pid == 0 { task.get_id() } else { pid };
+    let process = match ProcessObject::open(pid) {
+        Ok(process) => process,
+        Err(_) => return usize::MAX,
+    };
+    match task.handle_table.write().insert(KernelObject::from_process(process)) {
+        Ok(handle) => handle as usize,
+        Err(_) => usize::MAX,
+    }
+}
+
+/// Send a signal to the task of a process handle
+///
+/// Unlike `sys_kill`, the signal cannot reach another task that was given
+/// the same ID after the one of the handle was gone.
+///
+/// # Arguments
+/// * arg0 - Process handle
+/// * arg1 - Signal number, 0 to only check that the task is alive
+///
+/// # Returns
+/// 0 on success, usize::MAX on error (including a task that has exited)
+pub fn sys_process_signal(trapframe: &mut Trapframe) -> usize {
+    let task = mytask().unwrap();
+    let handle = trapframe.get_arg(0) as u32;
+    let sig = trapframe.get_arg(1);
+    trapframe.increment_pc_next(task);
+
+    let Some(object) = task.handle_table.read().get(handle).cloned() else {
+        return usize::MAX;
+    };
+    let Some(process) = object.as_process() else {
+        return usize::MAX;
+    };
+    match process.send_signal(sig) {
+        Ok(()) => 0,
+        Err(_) => usize::MAX,
+    }
+}
+
+/// Return immediately if no child has changed state
+pub const WNOHANG: usize = 1;
+/// Also report children that stopped
+pub const WUNTRACED: usize = 2;
+/// Also report stopped children continued by SIGCONT
+pub const WCONTINUED: usize = 8;
+
+/// Wait for a child to change state
+///
+/// `pid` selects the children as on Linux: the child with that ID if
+/// positive, any child in the caller's process group if 0, any child if -1
+/// and any child in the process group `-pid` otherwise. The status is
+/// written as a Linux wait status word (see [`crate::task::WaitStatus::encode`]).
+///
+/// # Returns
+/// The ID of the child, 0 if `WNOHANG` is given and no child changed state,
+/// or usize::MAX on error
 pub fn sys_waitpid(trapframe: &mut Trapframe) -> usize {
     let task = mytask().unwrap();
     let pid = trapframe.get_arg(0) as i32;
-    let status_ptr = trapframe.get_arg(1) as *mut i32;
-    let _options = trapframe.get_arg(2) as i32; // Not used in this implementation
+    let status_ptr = trapframe.get_arg(1);
+    let options = trapframe.get_arg(2);
+    trapframe.increment_pc_next(task);
+
+    let stopped = options & WUNTRACED != 0;
+    let continued = options & WCONTINUED != 0;
 
-    // Loop until a child exits or an error occurs
+    // Loop until a child changes state or an error occurs
     loop {
-        if pid == -1 {
-            // Wait for any child process
-            for child_pid in task.get_children().clone() {
-                match task.wait(child_pid) {
-                    Ok(status) => {
-                        // Child has exited, return the status
-                        if status_ptr != core::ptr::null_mut() {
-                            let status_ptr = task.vm_manager.translate_vaddr(status_ptr as usize).unwrap() as *mut i32;
-                            unsafe {
-                                *status_ptr = status;
-                            }
-                        }
-                        trapframe.increment_pc_next(task);
-                        return child_pid;
-                    },
-                    Err(error) => {
-                        match error {
-                            WaitError::ChildNotExited(_) => continue,
-                            _ => {
-                                trapframe.increment_pc_next(task);
-                                return usize::MAX;
-                            },
-                        }
-                    }
-                }
+        let children: Vec<usize> = task.get_children().iter().copied().filter(|&child_id| match pid {
+            pid if pid > 0 => child_id == pid as usize,
+            -1 => true,
+            pid => {
+                let pgid = if pid == 0 { task.get_pgid() } else { pid.unsigned_abs() as usize };
+                get_scheduler().get_task_by_id(child_id).is_some_and(|child| child.get_pgid() == pgid)
             }
-            
-            // No child has exited yet, block until one does
-            let parent_waker = get_parent_waitpid_waker(task.get_id());
-            parent_waker.wait(task.get_id(), trapframe);
-            // Continue the loop to re-check after waking up
-            continue;
-        }
-        
-        // Wait for specific child process
-        match task.wait(pid as usize) {
-            Ok(status) => {
-                // Child has exited, return the status
-                if status_ptr != core::ptr::null_mut() {
-                    let status_ptr = task.vm_manager.translate_vaddr(status_ptr as usize).unwrap() as *mut i32;
-                    unsafe {
-                        *status_ptr = status;
+        }).collect();
+        if children.is_empty() {
+            return usize::MAX;
+        }
+
+        // Checked before a child is reaped, so that its status is not lost
+        if status_ptr != 0 && !signal::user_access_ok(task, status_ptr, core::mem::size_of::<i32>(), true) {
+            return usize::MAX;
+        }
+
+        for child_id in children {
+            match task.wait_event(child_id, stopped, continued) {
+                Ok(Some(status)) => {
+                    if status_ptr != 0 {
+                        let _ = signal::put_user(task, status_ptr, status.encode());
                     }
+                    return child_id;
                 }
-                trapframe.increment_pc_next(task);
-                return pid as usize;
-            }
-            Err(error) => {
-                match error {
-                    WaitError::NoSuchChild(_) => {
-                        trapframe.increment_pc_next(task);
-                        return usize::MAX;
-                    },
-                    WaitError::ChildTaskNotFound(_) => {
-                        trapframe.increment_pc_next(task);
-                        crate::print!("Child task with PID {} not found", pid);
-                        return usize::MAX;
-                    },
-                    WaitError::ChildNotExited(_) => {
-                        // If the child task is not exited, we need to wait for it
-                        let child_waker = get_waitpid_waker(pid as usize);
-                        child_waker.wait(task.get_id(), trapframe);
-                        assert_eq!(mytask().unwrap().get_id(), task.get_id());
-                        // Continue the loop to re-check after waking up
-                        continue;
-                    },
-                }
+                Ok(None) => continue,
+                Err(_) => return usize::MAX,
             }
         }
+
+        if options & WNOHANG != 0 {
+            return 0;
+        }
+        // No child has changed state yet, block until one does
+        if task.signals.has_pending() {
+            return interrupt_syscall(task, trapframe);
+        }
+        if pid > 0 {
+            get_waitpid_waker(pid as usize).wait(task.get_id(), trapframe);
+        } else {
+            get_parent_waitpid_waker(task.get_id()).wait(task.get_id(), trapframe);
+        }
     }
 }
 
@@ -375,7 +1519,13 @@ pub fn sys_sleep(trapframe: &mut Trapframe) -> usize {
     trapframe.increment_pc_next(task);
 
     // Call the blocking sleep method - this will return when sleep completes
-    task.sleep(trapframe, ticks);
+    // or a signal arrives, in which case it goes on for the rest of the time
+    // if restarted
+    let deadline = get_time_ns().saturating_add(nanosecs);
+    if !task.sleep(trapframe, ticks) {
+        let remaining = deadline.saturating_sub(get_time_ns());
+        return signal::interrupt_sleep(task, remaining as usize);
+    }
 
     // Set return value to 0 for successful sleep
     0
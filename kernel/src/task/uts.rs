@@ -0,0 +1,194 @@
+//! UTS namespaces
+//!
+//! A UTS namespace holds the names a task sees through `uname`: the kernel
+//! identification (system name, release, version, machine) and the host and
+//! domain names. Every task refers to one namespace; tasks start in the
+//! initial namespace and share their parent's, so `sethostname` changes the
+//! name for every task in the namespace. A child cloned with
+//! `CloneFlagsDef::NewUts` gets a copy of its parent's namespace instead,
+//! which lets each container carry a hostname of its own.
+//!
+//! The kernel identification of a namespace can be replaced from inside the
+//! kernel (for example by an ABI that reports itself as another system);
+//! tasks can only change the host and domain names.
+
+extern crate alloc;
+
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use spin::{Once, RwLock};
+
+/// Longest name in a UTS field, without the terminating NUL
+pub const UTS_NAME_LEN: usize = 64;
+
+/// Host name of the initial namespace
+pub const DEFAULT_HOSTNAME: &str = "scarlet";
+
+/// Domain name of a namespace that has none
+pub const DEFAULT_DOMAINNAME: &str = "(none)";
+
+/// The names of a UTS namespace, laid out as `struct utsname`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct UtsName {
+    pub sysname: [u8; UTS_NAME_LEN + 1],
+    pub nodename: [u8; UTS_NAME_LEN + 1],
+    pub release: [u8; UTS_NAME_LEN + 1],
+    pub version: [u8; UTS_NAME_LEN + 1],
+    pub machine: [u8; UTS_NAME_LEN + 1],
+    pub domainname: [u8; UTS_NAME_LEN + 1],
+}
+
+/// Copy `name` into a NUL-terminated UTS field
+fn uts_field(name: &str) -> [u8; UTS_NAME_LEN + 1] {
+    let mut field = [0; UTS_NAME_LEN + 1];
+    let len = name.len().min(UTS_NAME_LEN);
+    field[..len].copy_from_slice(&name.as_bytes()[..len]);
+    field
+}
+
+/// Check a host or domain name given by a task
+///
+/// # Errors
+/// If the name is longer than [`UTS_NAME_LEN`] or contains a NUL byte.
+pub fn validate_name(name: &[u8]) -> Result<&str, &'static str> {
+    if name.len() > UTS_NAME_LEN {
+        return Err("Name too long");
+    }
+    if name.contains(&0) {
+        return Err("Name contains a NUL byte");
+    }
+    core::str::from_utf8(name).map_err(|_| "Name is not valid UTF-8")
+}
+
+/// Kernel identification reported by `uname`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KernelIdentity {
+    pub sysname: String,
+    pub release: String,
+    pub version: String,
+    pub machine: String,
+}
+
+impl KernelIdentity {
+    /// The identification of this kernel
+    pub fn scarlet() -> Self {
+        KernelIdentity {
+            sysname: "Scarlet".to_string(),
+            release: env!("CARGO_PKG_VERSION").to_string(),
+            version: "#1 SMP".to_string(),
+            machine: if cfg!(target_arch = "riscv64") { "riscv64" } else { "unknown" }.to_string(),
+        }
+    }
+}
+
+/// Names of a UTS namespace
+#[derive(Debug, Clone)]
+struct UtsNames {
+    identity: KernelIdentity,
+    hostname: String,
+    domainname: String,
+}
+
+/// A UTS namespace
+#[derive(Debug)]
+pub struct UtsNamespace {
+    names: RwLock<UtsNames>,
+}
+
+impl UtsNamespace {
+    /// A namespace with the given identification and host name
+    pub fn new(identity: KernelIdentity, hostname: &str) -> Self {
+        UtsNamespace {
+            names: RwLock::new(UtsNames {
+                identity,
+                hostname: hostname.to_string(),
+                domainname: DEFAULT_DOMAINNAME.to_string(),
+            }),
+        }
+    }
+
+    /// A new namespace starting out with the names of this one
+    pub fn copy(&self) -> Self {
+        UtsNamespace { names: RwLock::new(self.names.read().clone()) }
+    }
+
+    pub fn hostname(&self) -> String {
+        self.names.read().hostname.clone()
+    }
+
+    /// Set the host name; see [`validate_name`]
+    pub fn set_hostname(&self, hostname: &str) -> Result<(), &'static str> {
+        validate_name(hostname.as_bytes())?;
+        self.names.write().hostname = hostname.to_string();
+        Ok(())
+    }
+
+    pub fn domainname(&self) -> String {
+        self.names.read().domainname.clone()
+    }
+
+    /// Set the domain name; see [`validate_name`]
+    pub fn set_domainname(&self, domainname: &str) -> Result<(), &'static str> {
+        validate_name(domainname.as_bytes())?;
+        self.names.write().domainname = domainname.to_string();
+        Ok(())
+    }
+
+    pub fn identity(&self) -> KernelIdentity {
+        self.names.read().identity.clone()
+    }
+
+    /// Replace the kernel identification reported in this namespace
+    pub fn set_identity(&self, identity: KernelIdentity) {
+        self.names.write().identity = identity;
+    }
+
+    /// The names as `struct utsname`
+    pub fn uname(&self) -> UtsName {
+        let names = self.names.read();
+        UtsName {
+            sysname: uts_field(&names.identity.sysname),
+            nodename: uts_field(&names.hostname),
+            release: uts_field(&names.identity.release),
+            version: uts_field(&names.identity.version),
+            machine: uts_field(&names.identity.machine),
+            domainname: uts_field(&names.domainname),
+        }
+    }
+}
+
+static INIT_UTS_NS: Once<Arc<UtsNamespace>> = Once::new();
+
+/// The namespace tasks start in
+pub fn init_uts_ns() -> Arc<UtsNamespace> {
+    INIT_UTS_NS.call_once(|| Arc::new(UtsNamespace::new(KernelIdentity::scarlet(), DEFAULT_HOSTNAME))).clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_uts_namespace_copy() {
+        let parent = UtsNamespace::new(KernelIdentity::scarlet(), "host");
+        let child = parent.copy();
+        child.set_hostname("container").unwrap();
+        assert_eq!(parent.hostname(), "host");
+        assert_eq!(child.hostname(), "container");
+        assert_eq!(child.identity(), parent.identity());
+
+        let name = child.uname();
+        assert_eq!(&name.nodename[..10], b"container\0");
+        assert_eq!(&name.sysname[..8], b"Scarlet\0");
+        assert_eq!(&name.domainname[..7], b"(none)\0");
+    }
+
+    #[test_case]
+    fn test_validate_name() {
+        assert!(validate_name(b"box-1").is_ok());
+        assert!(validate_name(&[b'a'; UTS_NAME_LEN]).is_ok());
+        assert!(validate_name(&[b'a'; UTS_NAME_LEN + 1]).is_err());
+        assert!(validate_name(b"a\0b").is_err());
+    }
+}
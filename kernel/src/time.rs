@@ -2,8 +2,24 @@
 //! 
 //! This module provides time-related functionality for the kernel,
 //! including current time access for filesystem operations.
+//!
+//! ## Wall-clock time
+//!
+//! The realtime clock is the time since boot plus the boot epoch, the
+//! wall-clock time at boot. A [`RealTimeClock`] driver, like the goldfish
+//! RTC, provides the boot epoch when it is probed, and setting the time
+//! moves the boot epoch and writes the time back to the RTC. Without an
+//! RTC the realtime clock falls back to the SBI timer alone: it starts at
+//! the Unix epoch at boot until the time is set.
+//!
+//! Filesystems stamp files with [`realtime_s`].
 
-use crate::timer::get_kernel_timer;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use alloc::sync::Arc;
+use spin::Mutex;
+
+use crate::timer::{get_kernel_timer, get_time_ns};
 
 /// Get the current time in microseconds
 /// 
@@ -25,6 +41,59 @@ pub fn current_time_s() -> u64 {
     current_time() / 1_000_000
 }
 
+/// A clock that keeps the wall-clock time while the machine is off
+pub trait RealTimeClock: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// The time in nanoseconds since the Unix epoch
+    fn read_ns(&self) -> u64;
+
+    /// Set the time in nanoseconds since the Unix epoch
+    fn write_ns(&self, ns: u64) -> Result<(), &'static str>;
+}
+
+/// Wall-clock time at boot in nanoseconds since the Unix epoch
+static BOOT_EPOCH_NS: AtomicU64 = AtomicU64::new(0);
+
+static RTC: Mutex<Option<Arc<dyn RealTimeClock>>> = Mutex::new(None);
+
+/// Take the wall-clock time from `rtc`, and write it there when it is set
+pub fn register_rtc(rtc: Arc<dyn RealTimeClock>) {
+    let now = rtc.read_ns();
+    BOOT_EPOCH_NS.store(now.saturating_sub(get_time_ns()), Ordering::Relaxed);
+    crate::early_println!("[time] Wall clock from {}: {} s since the epoch", rtc.name(), now / 1_000_000_000);
+    *RTC.lock() = Some(rtc);
+}
+
+/// The wall-clock time at boot in nanoseconds since the Unix epoch
+pub fn boot_epoch_ns() -> u64 {
+    BOOT_EPOCH_NS.load(Ordering::Relaxed)
+}
+
+/// The wall-clock time in nanoseconds since the Unix epoch
+pub fn realtime_ns() -> u64 {
+    boot_epoch_ns().saturating_add(get_time_ns())
+}
+
+/// The wall-clock time in seconds since the Unix epoch
+pub fn realtime_s() -> u64 {
+    realtime_ns() / 1_000_000_000
+}
+
+/// Set the wall-clock time to `ns` nanoseconds since the Unix epoch
+///
+/// The time since boot is not affected. The time is written to the RTC,
+/// if there is one; failing that is reported but leaves the clock set.
+pub fn set_realtime_ns(ns: u64) {
+    BOOT_EPOCH_NS.store(ns.saturating_sub(get_time_ns()), Ordering::Relaxed);
+    let rtc = RTC.lock().clone();
+    if let Some(rtc) = rtc {
+        if let Err(error) = rtc.write_ns(ns) {
+            crate::early_println!("[time] Cannot set {}: {}", rtc.name(), error);
+        }
+    }
+}
+
 /// Convert microseconds to a human-readable format (for debugging)
 pub fn format_time_us(time_us: u64) -> (u64, u64, u64) {
     let seconds = time_us / 1_000_000;
@@ -50,4 +119,14 @@ mod tests {
         assert_eq!(minutes, 2);
         assert_eq!(seconds, 3);
     }
+
+    #[test_case]
+    fn test_set_realtime() {
+        let saved = realtime_ns();
+        set_realtime_ns(1_700_000_000_000_000_000);
+        let now = realtime_s();
+        assert!((1_700_000_000..1_700_000_010).contains(&now));
+        assert!(boot_epoch_ns() <= 1_700_000_000_000_000_000);
+        set_realtime_ns(saved);
+    }
 }
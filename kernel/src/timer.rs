@@ -3,12 +3,24 @@
 //! This module provides the kernel timer functionality, which is responsible for
 //! managing the system timer and scheduling tasks based on time intervals.
 //! 
+//! Each CPU takes a periodic tick every [`TICK_INTERVAL_US`] while it has
+//! tasks to run. A CPU with nothing but its idle task stops its tick (see
+//! [`stop_tick`]): its timer is programmed for the next software timer, or
+//! not at all, and the CPU sleeps in `wfi` until an interrupt arrives.
+//! The tick count is derived from the clock, so ticks that no CPU took
+//! while all of them were idle are caught up when the next one ticks.
+//!
+//! The kernel runs with interrupts disabled, so a tick that falls due while
+//! a CPU is in the kernel waits until it returns to user space, or until the
+//! kernel reaches a preemption point (see [`crate::sched::preempt`]), which
+//! takes the tick early when [`tick_due`] says it is late.
+//!
 
 use crate::arch::Trapframe;
 use crate::arch::timer::ArchTimer;
 use crate::environment::NUM_OF_CPUS;
 use crate::sched::scheduler::get_scheduler;
-use core::sync::atomic::{AtomicU64, Ordering};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 extern crate alloc;
 use alloc::sync::{Arc, Weak};
 use alloc::collections::BinaryHeap;
@@ -42,6 +54,7 @@ impl KernelTimer {
         }
     }
 
+    /// Stop every CPU's timer
     pub fn init(&mut self) {
         for i in 0..NUM_OF_CPUS {
             self.core_local_timer[i].stop();
@@ -71,17 +84,38 @@ impl KernelTimer {
     }
 }
 
-// Global tick counter (monotonic, incremented by timer interrupt)
+/// Last tick whose software timers have run
 static TICK_COUNT: AtomicU64 = AtomicU64::new(0);
 
-/// Increment the global tick counter. Call this from the timer interrupt handler.
+/// CPUs whose periodic tick is stopped while they idle
+static TICK_STOPPED: [AtomicBool; NUM_OF_CPUS] = [const { AtomicBool::new(false) }; NUM_OF_CPUS];
+
+/// When the next periodic tick of each CPU is due
+static TICK_DEADLINE_US: [AtomicU64; NUM_OF_CPUS] = [const { AtomicU64::new(0) }; NUM_OF_CPUS];
+
+/// Handle a timer interrupt. Call this from the timer interrupt handler,
+/// or from a preemption point when the tick is overdue.
+///
+/// Every CPU rearms its own timer and accounts the tick to its current
+/// task. The first CPU to see the clock reach a new tick runs the software
+/// timers that expired up to it.
 pub fn tick(trapframe: &mut Trapframe) {
     let cpu_id = crate::arch::get_cpu().get_cpuid();
     let timer = get_kernel_timer();
+    TICK_STOPPED[cpu_id].store(false, Ordering::SeqCst);
+    TICK_DEADLINE_US[cpu_id].store(get_time_us() + TICK_INTERVAL_US, Ordering::Relaxed);
     timer.set_interval_us(cpu_id, TICK_INTERVAL_US);
     timer.start(cpu_id);
-    let now = TICK_COUNT.fetch_add(1, Ordering::Relaxed) + 1;
-    check_software_timers(now);
+    let now = get_tick();
+    crate::vm::vdso::update_clock(get_time_ns(), now);
+    let prev = TICK_COUNT.fetch_max(now, Ordering::Relaxed);
+    if now > prev {
+        check_software_timers(now);
+        let interval = crate::mem::magazine::REBALANCE_INTERVAL_TICKS;
+        if now / interval != prev / interval {
+            crate::mem::allocator::rebalance_magazines();
+        }
+    }
     // Call scheduler tick handler to manage time slices
     let scheduler = get_scheduler();
     // crate::println!("[timer] Tick: {}, CPU: {}", now, cpu_id);
@@ -90,7 +124,45 @@ pub fn tick(trapframe: &mut Trapframe) {
 
 /// Get the current tick count (monotonic, since boot)
 pub fn get_tick() -> u64 {
-    TICK_COUNT.load(Ordering::Relaxed)
+    get_time_us() / TICK_INTERVAL_US
+}
+
+/// Stop the periodic tick of `cpu_id`, which is about to idle
+///
+/// The timer is programmed for the earliest software timer instead, or
+/// stopped if there is none. The tick is marked stopped before the timer is
+/// touched, so that whoever queues a task for `cpu_id` from now on sees it
+/// and wakes the CPU (see [`crate::sched::scheduler::Scheduler::kick_cpu`]).
+pub fn stop_tick(cpu_id: usize) {
+    TICK_STOPPED[cpu_id].store(true, Ordering::SeqCst);
+    let timer = get_kernel_timer();
+    match next_timer_expiry() {
+        Some(expires) => {
+            let deadline_us = ticks_to_us(expires);
+            timer.set_interval_us(cpu_id, deadline_us.saturating_sub(get_time_us()));
+            timer.start(cpu_id);
+        }
+        None => timer.stop(cpu_id),
+    }
+}
+
+/// Restart the periodic tick of `cpu_id` with a tick right away
+pub fn restart_tick(cpu_id: usize) {
+    TICK_STOPPED[cpu_id].store(false, Ordering::SeqCst);
+    TICK_DEADLINE_US[cpu_id].store(get_time_us(), Ordering::Relaxed);
+    let timer = get_kernel_timer();
+    timer.set_interval_us(cpu_id, 0);
+    timer.start(cpu_id);
+}
+
+/// Whether `cpu_id` idles with its periodic tick stopped
+pub fn is_tick_stopped(cpu_id: usize) -> bool {
+    TICK_STOPPED[cpu_id].load(Ordering::SeqCst)
+}
+
+/// Whether the periodic tick of `cpu_id` is due but was not taken yet
+pub fn tick_due(cpu_id: usize) -> bool {
+    !is_tick_stopped(cpu_id) && get_time_us() >= TICK_DEADLINE_US[cpu_id].load(Ordering::Relaxed)
 }
 
 pub fn get_time_ns() -> u64 {
@@ -174,6 +246,11 @@ pub fn cancel_timer(id: u64) {
     }
 }
 
+/// Expiration tick of the earliest software timer
+fn next_timer_expiry() -> Option<u64> {
+    SOFTWARE_TIMER_HEAP.lock().peek().map(|timer| timer.expires)
+}
+
 /// Call this from tick() to check and fire expired timers
 fn check_software_timers(now: u64) {
     use alloc::vec::Vec;
@@ -0,0 +1,150 @@
+//! Address space layout randomization
+//!
+//! Randomizes where the user stack, the heap (program break), the mmap
+//! search base and relocatable (PIE) images are placed for each exec. The
+//! random offsets are page granular and drawn from the kernel RNG.
+//!
+//! The behaviour is controlled by a sysctl-style level, `randomize_va_space`:
+//! - 0: randomization disabled (useful for debugging)
+//! - 1: randomize stack, mmap base and PIE/interpreter load addresses
+//! - 2: additionally randomize the start of the heap (default)
+//!
+//! The level can be set on the kernel command line with
+//! `randomize_va_space=<n>` (or disabled with `norandmaps`) and changed at
+//! runtime with [`set_randomize_va_space`].
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+use crate::environment::{COMPAT_USER_STACK_END, PAGE_SIZE, USER_STACK_END};
+use crate::random::get_random_below;
+use crate::task::Task;
+use super::manager::DEFAULT_MMAP_BASE;
+
+/// Maximum randomization level
+pub const RANDOMIZE_VA_SPACE_MAX: u8 = 2;
+
+/// Range of the stack top offset in pages (8 MiB)
+pub const STACK_RND_PAGES: usize = 1 << 11;
+/// Range of the mmap base offset in pages (128 MiB)
+pub const MMAP_RND_PAGES: usize = 1 << 15;
+/// Range of the heap start offset in pages (32 MiB)
+pub const HEAP_RND_PAGES: usize = 1 << 13;
+/// Range of the load address offset of relocatable images in pages (16 MiB)
+pub const LOAD_RND_PAGES: usize = 1 << 12;
+
+static RANDOMIZE_VA_SPACE: AtomicU8 = AtomicU8::new(RANDOMIZE_VA_SPACE_MAX);
+
+/// Get the current randomization level
+pub fn randomize_va_space() -> u8 {
+    RANDOMIZE_VA_SPACE.load(Ordering::Relaxed)
+}
+
+/// Set the randomization level
+///
+/// # Returns
+/// `Err` if `level` is larger than [`RANDOMIZE_VA_SPACE_MAX`]
+pub fn set_randomize_va_space(level: u8) -> Result<(), &'static str> {
+    if level > RANDOMIZE_VA_SPACE_MAX {
+        return Err("Invalid randomize_va_space level");
+    }
+    RANDOMIZE_VA_SPACE.store(level, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Apply ASLR options from the kernel command line
+pub fn parse_cmdline(cmdline: &str) {
+    for arg in cmdline.split_whitespace() {
+        if arg == "norandmaps" {
+            let _ = set_randomize_va_space(0);
+        } else if let Some(value) = arg.strip_prefix("randomize_va_space=") {
+            match value.parse::<u8>().ok().map(set_randomize_va_space) {
+                Some(Ok(())) => {}
+                _ => crate::early_println!("[aslr] Ignoring invalid randomize_va_space={}", value),
+            }
+        }
+    }
+}
+
+/// Random page-aligned offset below `max_pages` pages, or 0 when `level`
+/// is not enabled
+fn random_offset(level: u8, max_pages: usize) -> usize {
+    if randomize_va_space() < level {
+        return 0;
+    }
+    get_random_below(max_pages as u64) as usize * PAGE_SIZE
+}
+
+/// Top of the user stack for a new image
+pub fn stack_top() -> usize {
+    USER_STACK_END - random_offset(1, STACK_RND_PAGES)
+}
+
+/// Top of the user stack for a new image of 32-bit code
+///
+/// The stack stays below 2 GiB so that its addresses read the same whether
+/// the upper half of a register is taken as sign or zero extension.
+pub fn compat_stack_top() -> usize {
+    COMPAT_USER_STACK_END - random_offset(1, STACK_RND_PAGES)
+}
+
+/// Mmap search base for a new image
+pub fn mmap_base() -> usize {
+    DEFAULT_MMAP_BASE + random_offset(1, MMAP_RND_PAGES)
+}
+
+/// Load address of a relocatable image (PIE executable, interpreter or
+/// shared library)
+pub fn load_base(default: u64) -> u64 {
+    default + random_offset(1, LOAD_RND_PAGES) as u64
+}
+
+/// Randomize the mmap base and the heap start of a freshly loaded image
+///
+/// Must be called after the image has been loaded, since the heap starts
+/// after the loaded segments.
+pub fn randomize_task_layout(task: &mut Task) {
+    task.vm_manager.write().set_mmap_base(mmap_base());
+
+    let heap_offset = random_offset(2, HEAP_RND_PAGES);
+    if heap_offset != 0 {
+        let heap_start = (task.get_brk() + PAGE_SIZE - 1) & !(PAGE_SIZE - 1);
+        task.vm_manager.write().set_program_break(heap_start + heap_offset, heap_start + heap_offset);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_aslr_level_controls_offsets() {
+        let saved = randomize_va_space();
+
+        set_randomize_va_space(0).unwrap();
+        assert_eq!(stack_top(), USER_STACK_END);
+        assert_eq!(load_base(0x10000), 0x10000);
+
+        set_randomize_va_space(1).unwrap();
+        let top = stack_top();
+        assert!(top <= USER_STACK_END && top > USER_STACK_END - STACK_RND_PAGES * PAGE_SIZE);
+        assert_eq!(top % PAGE_SIZE, 0);
+        assert_eq!(random_offset(2, HEAP_RND_PAGES), 0);
+
+        assert!(set_randomize_va_space(RANDOMIZE_VA_SPACE_MAX + 1).is_err());
+        set_randomize_va_space(saved).unwrap();
+    }
+
+    #[test_case]
+    fn test_aslr_parse_cmdline() {
+        let saved = randomize_va_space();
+
+        parse_cmdline("console=ttyS0 norandmaps");
+        assert_eq!(randomize_va_space(), 0);
+        parse_cmdline("randomize_va_space=1");
+        assert_eq!(randomize_va_space(), 1);
+        parse_cmdline("randomize_va_space=9");
+        assert_eq!(randomize_va_space(), 1);
+
+        set_randomize_va_space(saved).unwrap();
+    }
+}
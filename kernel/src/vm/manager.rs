@@ -38,15 +38,37 @@
 extern crate alloc;
 use alloc::{sync::Arc, vec::Vec, collections::BTreeMap};
 
-use crate::{arch::vm::{free_virtual_address_space, get_root_pagetable, is_asid_used, mmu::PageTable}, environment::PAGE_SIZE};
+use crate::{arch::vm::{free_virtual_address_space, get_root_pagetable, is_asid_used, mmu::{PageTable, MEGAPAGE_SIZE}}, environment::PAGE_SIZE};
 
-use super::vmem::{VirtualMemoryMap, MemoryArea};
+use super::vmem::{VirtualMemoryMap, MemoryArea, VirtualMemoryPermission};
+use super::zero_fill::zero_page_paddr;
+
+/// Default base address of the mmap search area (1 GB)
+pub const DEFAULT_MMAP_BASE: usize = 0x40000000;
+/// Default end of the mmap search area (2 GB)
+pub const DEFAULT_MMAP_LIMIT: usize = 0x80000000;
+
+/// Memory usage advice (see `VirtualMemoryManager::advise_range`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryAdvice {
+    /// The pages will be used soon: map what is present ahead of time
+    WillNeed,
+    /// The contents are no longer needed: free the pages now, they read as
+    /// zero afterwards
+    DontNeed,
+    /// The contents are no longer needed: the pages may be freed under
+    /// memory pressure, until they are accessed again
+    Free,
+}
 
 #[derive(Debug, Clone)]
 pub struct VirtualMemoryManager {
     memmap: BTreeMap<usize, VirtualMemoryMap>, // start_addr -> VirtualMemoryMap
     asid: u16,
     mmap_base: usize,        // Mmap from this base address
+    mmap_limit: usize,       // and below this one
+    brk_start: usize,        // Start of the heap (initial program break)
+    brk: usize,              // Current program break
     page_tables: Vec<Arc<PageTable>>,
     
     /// Cache for the last searched memory map to accelerate repeated accesses
@@ -63,7 +85,10 @@ impl VirtualMemoryManager {
         VirtualMemoryManager {
             memmap: BTreeMap::new(),
             asid: 0,
-            mmap_base: 0x40000000, // 1 GB base address for mmap (Default)
+            mmap_base: DEFAULT_MMAP_BASE,
+            mmap_limit: DEFAULT_MMAP_LIMIT,
+            brk_start: 0,
+            brk: 0,
             page_tables: Vec::new(),
             last_search_cache: None,
         }
@@ -236,6 +261,28 @@ impl VirtualMemoryManager {
         memmap.into_values()
     }
 
+    /// Removes all user memory maps and unmaps them from the MMU.
+    ///
+    /// Unlike `remove_all_memory_maps`, the page table entries are cleared
+    /// (flushing the TLB) before the maps are dropped, so the address space
+    /// cannot reach their frames any more once they are freed. Kernel-only
+    /// maps such as the trampoline are kept.
+    ///
+    /// # Returns
+    /// The number of removed memory maps.
+    pub fn unmap_user_memory_maps(&mut self) -> usize {
+        self.last_search_cache = None;
+
+        let (user, kernel): (BTreeMap<_, _>, BTreeMap<_, _>) = core::mem::take(&mut self.memmap)
+            .into_iter()
+            .partition(|(_, map)| VirtualMemoryPermission::User.contained_in(map.permissions));
+        self.memmap = kernel;
+        for map in user.values() {
+            self.unmap_range_from_mmu(map.vmarea.start, map.vmarea.end);
+        }
+        user.len()
+    }
+
     /// Restores the memory maps from a given iterator.
     ///
     /// # Arguments
@@ -355,15 +402,20 @@ impl VirtualMemoryManager {
     /// Returns the root page table for the current address space.
     /// 
     /// # Returns
-    /// The root page table for the current address space, if it exists.
-    pub fn get_root_page_table(&self) -> Option<&mut PageTable> {
+    /// The root page table for the current address space, if it exists. It
+    /// belongs to the ASID, not to this manager.
+    pub fn get_root_page_table(&self) -> Option<&'static mut PageTable> {
         get_root_pagetable(self.asid)
     }
 
     /// Lazy map a virtual address to MMU on demand (called from page fault handler)
     /// 
     /// This method finds the memory mapping for the given virtual address and
-    /// maps only the specific page to the MMU on demand.
+    /// maps only the specific page to the MMU on demand. Private anonymous
+    /// mappings are mapped a whole megapage at a time where the megapage lies
+    /// inside the mapping and its virtual and physical addresses line up.
+    /// Untouched pages of a demand-zero mapping are mapped read-only to the
+    /// shared zero page; use `lazy_map_page_for_write` for write faults.
     /// 
     /// # Arguments
     /// * `vaddr` - The virtual address that caused the page fault
@@ -378,20 +430,86 @@ impl VirtualMemoryManager {
             None => return Err("No memory mapping found for virtual address"),
         };
         
+        // PROT_NONE mappings must never reach the MMU: a valid PTE without
+        // R/W/X bits would be interpreted as a pointer to the next level
+        let access_bits = VirtualMemoryPermission::Read as usize | VirtualMemoryPermission::Write as usize | VirtualMemoryPermission::Execute as usize;
+        if memory_map.permissions & access_bits == 0 {
+            return Err("Memory mapping does not permit access");
+        }
+
         // Calculate the page-aligned virtual and physical addresses
         let page_vaddr = vaddr & !(PAGE_SIZE - 1);
         let offset_in_mapping = page_vaddr - memory_map.vmarea.start;
-        let page_paddr = memory_map.pmarea.start + offset_in_mapping;
+        let mut permissions = memory_map.permissions;
+        let page_paddr = match &memory_map.zero_fill {
+            None => memory_map.pmarea.start + offset_in_mapping,
+            Some(pages) => {
+                let read_only = permissions & !(VirtualMemoryPermission::Write as usize);
+                match pages.frame(page_vaddr) {
+                    Some(paddr) => paddr,
+                    // Write-only memory cannot share the zero page
+                    None if read_only & access_bits == 0 => pages.populate(page_vaddr).0,
+                    None => {
+                        permissions = read_only;
+                        zero_page_paddr()
+                    }
+                }
+            }
+        };
         
-        // Map this single page to the MMU
+        let megapage = Self::megapage_for(memory_map, vaddr);
+
         if let Some(root_pagetable) = self.get_root_page_table() {
-            root_pagetable.map(self.asid, page_vaddr, page_paddr, memory_map.permissions);
+            match megapage {
+                Some((mega_vaddr, mega_paddr)) => root_pagetable.map_megapage(self.asid, mega_vaddr, mega_paddr, permissions),
+                // Map this single page to the MMU
+                None => root_pagetable.map(self.asid, page_vaddr, page_paddr, permissions),
+            }
             Ok(())
         } else {
             Err("No root page table available")
         }
     }
 
+    /// Lazy map a virtual address to MMU for a write access
+    /// 
+    /// Like `lazy_map_page`, but an untouched page of a demand-zero mapping
+    /// first gets its own zeroed frame, replacing the zero page.
+    /// 
+    /// # Arguments
+    /// * `vaddr` - The virtual address that caused the page fault
+    /// 
+    /// # Returns
+    /// * `Ok(())` - Successfully mapped the page writable
+    /// * `Err(&'static str)` - No mapping found, the mapping is read-only or MMU error
+    pub fn lazy_map_page_for_write(&mut self, vaddr: usize) -> Result<(), &'static str> {
+        let memory_map = self.search_memory_map(vaddr).ok_or("No memory mapping found for virtual address")?;
+        if !VirtualMemoryPermission::Write.contained_in(memory_map.permissions) {
+            return Err("Memory mapping is not writable");
+        }
+        if let Some(pages) = &memory_map.zero_fill {
+            pages.populate(vaddr & !(PAGE_SIZE - 1));
+        }
+        self.lazy_map_page(vaddr)
+    }
+
+    /// Megapage (virtual, physical) covering `vaddr` if `map` may be mapped
+    /// with megapages there
+    fn megapage_for(map: &VirtualMemoryMap, vaddr: usize) -> Option<(usize, usize)> {
+        if map.owner.is_some() || map.is_shared || map.zero_fill.is_some() {
+            return None;
+        }
+        let mega_vaddr = vaddr & !(MEGAPAGE_SIZE - 1);
+        if mega_vaddr < map.vmarea.start || mega_vaddr.checked_add(MEGAPAGE_SIZE - 1)? > map.vmarea.end {
+            return None;
+        }
+        let mega_paddr = map.pmarea.start + (mega_vaddr - map.vmarea.start);
+        if mega_paddr % MEGAPAGE_SIZE != 0 {
+            return None;
+        }
+        Some((mega_vaddr, mega_paddr))
+    }
+
     /// Unmap a virtual address range from MMU
     /// 
     /// This method unmaps the specified virtual address range from the MMU.
@@ -416,6 +534,9 @@ impl VirtualMemoryManager {
     /// Translate a virtual address to physical address
     /// 
     /// This method uses efficient search with caching for optimal performance.
+    /// The kernel may write through the returned address, so an untouched
+    /// page of a demand-zero mapping gets its own frame here instead of
+    /// resolving to the shared zero page.
     /// 
     /// # Arguments
     /// 
@@ -426,14 +547,22 @@ impl VirtualMemoryManager {
     /// The translated physical address. Returns None if no mapping exists for the address
     pub fn translate_vaddr(&self, vaddr: usize) -> Option<usize> {
         // Use our optimized search method
-        if let Some(map) = self.search_memory_map(vaddr) {
-            // Calculate offset within the memory area
-            let offset = vaddr - map.vmarea.start;
-            // Calculate and return physical address
-            Some(map.pmarea.start + offset)
-        } else {
-            None
+        let map = self.search_memory_map(vaddr)?;
+        if let Some(pages) = &map.zero_fill {
+            let page_vaddr = vaddr & !(PAGE_SIZE - 1);
+            let (paddr, created) = pages.populate(page_vaddr);
+            if created {
+                // Drop a zero page mapping so the task sees the new frame
+                if let Some(root_pagetable) = self.get_root_page_table() {
+                    root_pagetable.unmap(self.asid, page_vaddr);
+                }
+            }
+            return Some(paddr + (vaddr - page_vaddr));
         }
+        // Calculate offset within the memory area
+        let offset = vaddr - map.vmarea.start;
+        // Calculate and return physical address
+        Some(map.pmarea.start + offset)
     }
 
     /// Gets the mmap base address
@@ -452,6 +581,100 @@ impl VirtualMemoryManager {
     pub fn set_mmap_base(&mut self, base: usize) {
         self.mmap_base = base;
     }
+
+    /// Gets the end of the mmap region (exclusive)
+    pub fn get_mmap_limit(&self) -> usize {
+        self.mmap_limit
+    }
+
+    /// Sets the end of the mmap region (exclusive)
+    ///
+    /// A task whose code only sees 32-bit addresses gets its mappings kept
+    /// below its stack this way.
+    pub fn set_mmap_limit(&mut self, limit: usize) {
+        self.mmap_limit = limit;
+    }
+
+    /// Gets the start of the heap (the initial program break)
+    pub fn get_brk_start(&self) -> usize {
+        self.brk_start
+    }
+
+    /// Gets the current program break
+    pub fn get_brk(&self) -> usize {
+        self.brk
+    }
+
+    /// Sets the heap start and the program break without touching any mapping
+    ///
+    /// Used when an image is loaded (`start == brk`, empty heap) and when the
+    /// heap mappings are carried over separately (fork, exec rollback).
+    ///
+    /// # Arguments
+    /// * `start` - Start of the heap
+    /// * `brk` - Program break
+    pub fn set_program_break(&mut self, start: usize, brk: usize) {
+        self.brk_start = start;
+        self.brk = brk;
+    }
+
+    /// Move the program break
+    ///
+    /// The heap consists of demand-zero mappings from the page-aligned heap
+    /// start up to the page-aligned break. Growing extends the mapping that
+    /// ends at the old break (or adds a new one) and fails if the new pages
+    /// would collide with another mapping. Shrinking removes the pages above
+    /// the new break.
+    ///
+    /// # Arguments
+    /// * `brk` - The new program break
+    ///
+    /// # Returns
+    /// * `Ok(Vec<VirtualMemoryMap>)` - The heap mappings removed by shrinking
+    /// * `Err(&'static str)` - The new break is invalid or collides with another mapping
+    ///
+    /// The caller is responsible for releasing the pages of the removed mappings.
+    pub fn set_brk(&mut self, brk: usize) -> Result<Vec<VirtualMemoryMap>, &'static str> {
+        if brk < self.brk_start {
+            return Err("Program break below the start of the heap");
+        }
+        let old_end = Self::page_align_up(self.brk)?;
+        let new_end = Self::page_align_up(brk)?;
+
+        let mut removed = Vec::new();
+        if new_end > old_end {
+            if !self.is_range_free(old_end, new_end - old_end) {
+                return Err("Heap would overlap an existing mapping");
+            }
+            self.extend_heap(old_end, new_end);
+        } else if new_end < old_end {
+            removed = self.carve_range(new_end, old_end - 1);
+        }
+        self.brk = brk;
+        Ok(removed)
+    }
+
+    /// Map `[old_end, new_end)` as heap, growing the heap's top mapping if possible
+    fn extend_heap(&mut self, old_end: usize, new_end: usize) {
+        let permissions = super::vmem::VirtualMemoryRegion::Heap.default_permissions();
+        let heap_start = self.brk_start.next_multiple_of(PAGE_SIZE);
+        self.last_search_cache = None;
+
+        if let Some((_, top)) = self.memmap.range_mut(heap_start..old_end).next_back() {
+            if top.vmarea.end + 1 == old_end && top.zero_fill.is_some()
+                && top.permissions == permissions && !top.is_shared && top.owner.is_none() {
+                top.vmarea.end = new_end - 1;
+                top.pmarea.end = top.pmarea.start + top.vmarea.size() - 1;
+                return;
+            }
+        }
+        let vmarea = MemoryArea::new(old_end, new_end - 1);
+        self.memmap.insert(old_end, VirtualMemoryMap::new_zero_fill(vmarea, permissions));
+    }
+
+    fn page_align_up(addr: usize) -> Result<usize, &'static str> {
+        addr.checked_next_multiple_of(PAGE_SIZE).ok_or("Range overflows address space")
+    }
     
     /// Find a suitable address for new memory mapping
     /// 
@@ -465,10 +688,13 @@ impl VirtualMemoryManager {
         let aligned_size = (size + alignment - 1) & !(alignment - 1);
         
         // Start search from mmap_base
-        let mut search_addr = self.mmap_base;
+        let mut search_addr = (self.mmap_base + alignment - 1) & !(alignment - 1);
         
         // Simple first-fit algorithm
         for (_start, memory_map) in self.memmap.range(self.mmap_base..) {
+            if search_addr + aligned_size > self.mmap_limit {
+                return None;
+            }
             // Check if there's enough space before this memory map
             if search_addr + aligned_size <= memory_map.vmarea.start {
                 return Some(search_addr);
@@ -482,9 +708,7 @@ impl VirtualMemoryManager {
         }
         
         // Check if there's space after the last memory map
-        // For simplicity, we assume a reasonable upper limit for the address space
-        const MAX_USER_ADDR: usize = 0x80000000; // 2GB limit for user space
-        if search_addr + aligned_size <= MAX_USER_ADDR {
+        if search_addr + aligned_size <= self.mmap_limit {
             Some(search_addr)
         } else {
             None
@@ -522,18 +746,161 @@ impl VirtualMemoryManager {
             return Err("Address or size is not aligned to PAGE_SIZE");
         }
 
-        let new_start = map.vmarea.start;
-        let new_end = map.vmarea.end;
+        let overwritten_mappings = self.carve_range(map.vmarea.start, map.vmarea.end);
+
+        // crate::println!("Adding new mapping: {:x?}", map);
+        // Add the new mapping (MMU mapping will be done lazily on page fault)
+        self.memmap.insert(map.vmarea.start, map);
+
+        Ok(overwritten_mappings)
+    }
+
+    /// Unmap an arbitrary page-aligned range
+    /// 
+    /// Mappings that straddle the range boundaries are split and only the
+    /// part inside the range is removed. Holes inside the range are ignored,
+    /// matching munmap semantics.
+    /// 
+    /// # Arguments
+    /// * `start` - Start address of the range (must be page aligned)
+    /// * `length` - Length of the range in bytes (rounded up to PAGE_SIZE)
+    /// 
+    /// # Returns
+    /// * `Ok(Vec<VirtualMemoryMap>)` - The removed parts of the existing mappings
+    /// * `Err(&'static str)` - The range is invalid
+    /// 
+    /// The caller is responsible for handling any managed pages associated with the removed mappings.
+    pub fn unmap_range(&mut self, start: usize, length: usize) -> Result<Vec<VirtualMemoryMap>, &'static str> {
+        let end = Self::range_end(start, length)?;
+        Ok(self.carve_range(start, end))
+    }
+
+    /// Change the permissions of an arbitrary page-aligned range
+    /// 
+    /// Mappings are split at the range boundaries so that only the pages
+    /// inside the range change. Pages already present in the MMU are unmapped
+    /// and will be mapped again with the new permissions on the next fault.
+    /// 
+    /// # Arguments
+    /// * `start` - Start address of the range (must be page aligned)
+    /// * `length` - Length of the range in bytes (rounded up to PAGE_SIZE)
+    /// * `permissions` - New permissions (VirtualMemoryPermission bits)
+    /// 
+    /// # Returns
+    /// * `Ok(())` - Permissions changed
+    /// * `Err(&'static str)` - The range is invalid or not fully mapped
+    pub fn protect_range(&mut self, start: usize, length: usize, permissions: usize) -> Result<(), &'static str> {
+        let end = Self::range_end(start, length)?;
+
+        // The whole range must be mapped; check before modifying anything
+        if !self.is_range_mapped(start, end) {
+            return Err("Range is not fully mapped");
+        }
+
+        for mut map in self.carve_range(start, end) {
+            map.permissions = permissions;
+            self.memmap.insert(map.vmarea.start, map);
+        }
+        Ok(())
+    }
+
+    /// Apply memory usage advice (madvise) to an arbitrary page-aligned range
+    /// 
+    /// Advice only affects demand-zero memory; other mappings accept it
+    /// without effect, except that `WillNeed` maps them into the MMU ahead
+    /// of use.
+    /// 
+    /// # Arguments
+    /// * `start` - Start address of the range (must be page aligned)
+    /// * `length` - Length of the range in bytes (rounded up to PAGE_SIZE)
+    /// * `advice` - The advice
+    /// 
+    /// # Returns
+    /// * `Ok(())` - Advice applied
+    /// * `Err(&'static str)` - The range is invalid or not fully mapped
+    pub fn advise_range(&mut self, start: usize, length: usize, advice: MemoryAdvice) -> Result<(), &'static str> {
+        let end = Self::range_end(start, length)?;
+        if !self.is_range_mapped(start, end) {
+            return Err("Range is not fully mapped");
+        }
+
+        let parts = self.memmap.range(..=end)
+            .map(|(_, map)| map)
+            .filter(|map| map.vmarea.end >= start)
+            .map(|map| (map.vmarea.start.max(start), map.vmarea.end.min(end), map.zero_fill.clone()))
+            .collect::<Vec<_>>();
+        for (first, last, zero_fill) in parts {
+            match (advice, zero_fill) {
+                (MemoryAdvice::WillNeed, zero_fill) => {
+                    for page in (first..=last).step_by(PAGE_SIZE) {
+                        // Pages that were never written have nothing to bring in
+                        if zero_fill.as_ref().is_some_and(|pages| pages.frame(page).is_none()) {
+                            continue;
+                        }
+                        // Mappings without access permissions are skipped
+                        let _ = self.lazy_map_page(page);
+                    }
+                }
+                (MemoryAdvice::DontNeed, Some(pages)) => {
+                    self.unmap_range_from_mmu(first, last);
+                    pages.release_range(first, last);
+                }
+                (MemoryAdvice::Free, Some(pages)) => {
+                    self.unmap_range_from_mmu(first, last);
+                    pages.lazy_free_range(first, last);
+                }
+                (_, None) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Check whether every page of `[start, end]` is mapped
+    fn is_range_mapped(&self, start: usize, end: usize) -> bool {
+        let mut next = start;
+        for map in self.memmap.range(..=end).map(|(_, map)| map).filter(|map| map.vmarea.end >= start) {
+            if map.vmarea.start > next {
+                return false;
+            }
+            next = map.vmarea.end.saturating_add(1);
+        }
+        next > end
+    }
+
+    /// Check whether no mapping overlaps the given range
+    pub fn is_range_free(&self, start: usize, length: usize) -> bool {
+        let end = match Self::range_end(start, length) {
+            Ok(end) => end,
+            Err(_) => return false,
+        };
+        !self.memmap.range(..=end).any(|(_, map)| map.vmarea.end >= start)
+    }
+
+    /// Validate a (start, length) range and return its inclusive end address
+    fn range_end(start: usize, length: usize) -> Result<usize, &'static str> {
+        if start % PAGE_SIZE != 0 {
+            return Err("Address is not aligned to PAGE_SIZE");
+        }
+        if length == 0 {
+            return Err("Length must not be zero");
+        }
+        let aligned_length = length.checked_add(PAGE_SIZE - 1).ok_or("Range overflows address space")? & !(PAGE_SIZE - 1);
+        start.checked_add(aligned_length - 1).ok_or("Range overflows address space")
+    }
+
+    /// Remove `[new_start, new_end]` from the memory maps
+    /// 
+    /// Overlapping mappings are split so that the parts outside the range are
+    /// kept. The removed parts are unmapped from the MMU and returned.
+    fn carve_range(&mut self, new_start: usize, new_end: usize) -> Vec<VirtualMemoryMap> {
         let mut overwritten_mappings = Vec::new();
         let mut mappings_to_add = Vec::new();
 
         // Find all overlapping mappings and process them
         let overlapping_keys: alloc::vec::Vec<usize> = self.memmap
-            .range(..)
+            .range(..=new_end)
             .filter_map(|(start_addr, existing_map)| {
-                let existing_start = existing_map.vmarea.start;
-                let existing_end = existing_map.vmarea.end;
-                if new_start <= existing_end && new_end >= existing_start {
+                if existing_map.vmarea.end >= new_start {
                     Some(*start_addr)
                 } else {
                     None
@@ -565,18 +932,19 @@ impl VirtualMemoryManager {
                         permissions: existing_map.permissions,
                         is_shared: existing_map.is_shared,
                         owner: existing_map.owner.clone(),
+                        zero_fill: existing_map.zero_fill.clone(),
                     };
                     overwritten_mappings.push(overwritten_map);
                 }
 
-                // Case 1: New mapping completely contains the existing mapping
+                // Case 1: The range completely contains the existing mapping
                 if new_start <= existing_start && new_end >= existing_end {
                     // Remove entire existing mapping
                     continue;
                 }
 
                 // Case 2: Partial overlap - need to split
-                // Keep the part before the new mapping (if any)
+                // Keep the part before the range (if any)
                 if existing_start < new_start {
                     let before_map = VirtualMemoryMap {
                         vmarea: MemoryArea {
@@ -590,11 +958,12 @@ impl VirtualMemoryManager {
                         permissions: existing_map.permissions,
                         is_shared: existing_map.is_shared,
                         owner: existing_map.owner.clone(),
+                        zero_fill: existing_map.zero_fill.clone(),
                     };
                     mappings_to_add.push(before_map);
                 }
 
-                // Keep the part after the new mapping (if any)
+                // Keep the part after the range (if any)
                 if existing_end > new_end {
                     let after_offset = (new_end + 1) - existing_start;
                     let after_map = VirtualMemoryMap {
@@ -609,6 +978,7 @@ impl VirtualMemoryManager {
                         permissions: existing_map.permissions,
                         is_shared: existing_map.is_shared,
                         owner: existing_map.owner.clone(),
+                        zero_fill: existing_map.zero_fill.clone(),
                     };
                     mappings_to_add.push(after_map);
                 }
@@ -630,11 +1000,7 @@ impl VirtualMemoryManager {
             self.memmap.insert(split_map.vmarea.start, split_map);
         }
 
-        // crate::println!("Adding new mapping: {:x?}", map);
-        // Add the new mapping (MMU mapping will be done lazily on page fault)
-        self.memmap.insert(map.vmarea.start, map);
-
-        Ok(overwritten_mappings)
+        overwritten_mappings
     }
     
     /// Get memory statistics and usage information
@@ -697,6 +1063,7 @@ impl VirtualMemoryManager {
                         permissions: prev_memory_map.permissions, // Use permissions from first map
                         is_shared: prev_memory_map.is_shared,
                         owner: prev_memory_map.owner.clone(),
+                        zero_fill: prev_memory_map.zero_fill.clone(),
                     };
                     
                     // Mark old memory maps for removal and add merged map
@@ -745,9 +1112,15 @@ impl VirtualMemoryManager {
         // 1. They have the same permissions
         // 2. They have the same sharing status
         // 3. Physical addresses are also contiguous
+        // 4. They share the same demand-zero frames, if any
         map1.permissions == map2.permissions &&
         map1.is_shared == map2.is_shared &&
-        map1.pmarea.end + 1 == map2.pmarea.start
+        map1.pmarea.end + 1 == map2.pmarea.start &&
+        match (&map1.zero_fill, &map2.zero_fill) {
+            (Some(pages1), Some(pages2)) => Arc::ptr_eq(pages1, pages2),
+            (None, None) => true,
+            _ => false,
+        }
     }
 }
 
@@ -763,10 +1136,10 @@ impl Drop for VirtualMemoryManager {
 
 #[cfg(test)]
 mod tests {
-    use crate::arch::vm::{alloc_virtual_address_space, get_root_pagetable};
+    use crate::arch::vm::{alloc_virtual_address_space, get_root_pagetable, mmu::MEGAPAGE_SIZE};
     use crate::environment::PAGE_SIZE;
     use crate::vm::VirtualMemoryMap;
-    use crate::vm::{manager::VirtualMemoryManager, vmem::MemoryArea};
+    use crate::vm::{manager::{VirtualMemoryManager, DEFAULT_MMAP_LIMIT}, vmem::MemoryArea, zero_fill::zero_page_paddr};
 
     #[test_case]
     fn test_new_virtual_memory_manager() {
@@ -785,7 +1158,7 @@ mod tests {
     fn test_add_and_get_memory_map() {
         let mut vmm = VirtualMemoryManager::new();
         let vma = MemoryArea { start: 0x1000, end: 0x1fff };
-        let map = VirtualMemoryMap { vmarea: vma, pmarea: vma, permissions: 0, is_shared: false, owner: None };
+        let map = VirtualMemoryMap { vmarea: vma, pmarea: vma, permissions: 0, is_shared: false, owner: None, zero_fill: None };
         vmm.add_memory_map(map).unwrap();
         
         // Use new efficient API instead of deprecated get_memory_map(0)
@@ -802,7 +1175,7 @@ mod tests {
     fn test_remove_memory_map() {
         let mut vmm = VirtualMemoryManager::new();
         let vma = MemoryArea { start: 0x1000, end: 0x1fff };
-        let map = VirtualMemoryMap { vmarea: vma, pmarea: vma, permissions: 0, is_shared: false, owner: None };
+        let map = VirtualMemoryMap { vmarea: vma, pmarea: vma, permissions: 0, is_shared: false, owner: None, zero_fill: None };
         vmm.add_memory_map(map).unwrap();
         
         // Use address-based removal instead of index-based
@@ -819,9 +1192,9 @@ mod tests {
     fn test_search_memory_map() {
         let mut vmm = VirtualMemoryManager::new();
         let vma1 = MemoryArea { start: 0x1000, end: 0x1fff };
-        let map1 = VirtualMemoryMap { vmarea: vma1, pmarea: vma1, permissions: 0, is_shared: false, owner: None };
+        let map1 = VirtualMemoryMap { vmarea: vma1, pmarea: vma1, permissions: 0, is_shared: false, owner: None, zero_fill: None };
         let vma2 = MemoryArea { start: 0x3000, end: 0x3fff };
-        let map2 = VirtualMemoryMap { vmarea: vma2, pmarea: vma2, permissions: 0, is_shared: false, owner: None };
+        let map2 = VirtualMemoryMap { vmarea: vma2, pmarea: vma2, permissions: 0, is_shared: false, owner: None, zero_fill: None };
         vmm.add_memory_map(map1).unwrap();
         vmm.add_memory_map(map2).unwrap();
         let found_map = vmm.search_memory_map(0x3500).unwrap();
@@ -872,6 +1245,13 @@ mod tests {
         let addr2 = manager.find_unmapped_area(size, alignment);
         assert!(addr2.is_some());
         assert!(addr2.unwrap() > 0x50000fff);
+
+        // Nothing is placed at or above the mmap limit
+        assert_eq!(manager.get_mmap_limit(), DEFAULT_MMAP_LIMIT);
+        manager.set_mmap_limit(0x50002000);
+        assert_eq!(manager.find_unmapped_area(size, alignment), Some(0x50001000));
+        assert_eq!(manager.find_unmapped_area(size * 2, alignment), None);
+        manager.set_mmap_limit(DEFAULT_MMAP_LIMIT);
         
         // Test memory statistics
         let (total_maps, total_size, gaps) = manager.get_memory_stats();
@@ -1383,7 +1763,7 @@ mod tests {
     fn test_lazy_mapping_and_unmapping() {
         let mut manager = VirtualMemoryManager::new();
         let vma = MemoryArea { start: 0x1000, end: 0x1fff };
-        let map = VirtualMemoryMap { vmarea: vma, pmarea: vma, permissions: 0o644, is_shared: false, owner: None };
+        let map = VirtualMemoryMap { vmarea: vma, pmarea: vma, permissions: 0o644, is_shared: false, owner: None, zero_fill: None };
         let asid = alloc_virtual_address_space();
         manager.set_asid(asid);
         manager.add_memory_map(map).unwrap();
@@ -1405,4 +1785,208 @@ mod tests {
         let translated_addr_after_unmap = manager.translate_vaddr(0x1500);
         assert!(translated_addr_after_unmap.is_none());
     }
-}
\ No newline at end of file
+
+    #[test_case]
+    fn test_lazy_mapping_uses_megapages() {
+        let mut manager = VirtualMemoryManager::new();
+        let asid = alloc_virtual_address_space();
+        manager.set_asid(asid);
+        // Two megapages of private memory; only the second one lines up physically
+        let vmarea = MemoryArea { start: 0x4000_0000, end: 0x4000_0000 + 2 * MEGAPAGE_SIZE - 1 };
+        let pmarea = MemoryArea { start: 0x9000_0000 - MEGAPAGE_SIZE + PAGE_SIZE, end: 0x9000_0000 + MEGAPAGE_SIZE + PAGE_SIZE - 1 };
+        let map = VirtualMemoryMap::new(pmarea, vmarea, 0x3, false, None);
+        assert_eq!(VirtualMemoryManager::megapage_for(&map, 0x4000_1000), None);
+        assert_eq!(VirtualMemoryManager::megapage_for(&map, 0x4020_0000), None);
+
+        let pmarea = MemoryArea { start: 0x9000_0000, end: 0x9000_0000 + 2 * MEGAPAGE_SIZE - 1 };
+        let map = VirtualMemoryMap::new(pmarea, vmarea, 0x3, false, None);
+        assert_eq!(VirtualMemoryManager::megapage_for(&map, 0x4020_1234), Some((0x4020_0000, 0x9020_0000)));
+        manager.add_memory_map(map).unwrap();
+
+        assert!(manager.lazy_map_page(0x4020_1234).is_ok());
+        let root = manager.get_root_page_table().unwrap();
+        assert!(root.is_megapage_mapped(0x4020_0000));
+        assert!(!root.is_megapage_mapped(0x4000_0000));
+
+        // Unmapping one page splits the megapage and keeps its neighbours
+        manager.unmap_range_from_mmu(0x4020_1000, 0x4020_1fff);
+        let root = manager.get_root_page_table().unwrap();
+        assert!(!root.is_megapage_mapped(0x4020_0000));
+        assert!(!root.walk(0x4020_1000, false, asid).unwrap().is_valid());
+        let neighbour = root.walk(0x4020_2000, false, asid).unwrap();
+        assert!(neighbour.is_valid());
+        assert_eq!(neighbour.get_ppn() << 12, 0x9020_2000);
+    }
+
+    #[test_case]
+    fn test_zero_fill_mapping_allocates_on_write() {
+        let mut manager = VirtualMemoryManager::new();
+        let asid = alloc_virtual_address_space();
+        manager.set_asid(asid);
+        // User read/write, far larger than the pages touched below
+        let vmarea = MemoryArea { start: 0x4000_0000, end: 0x4000_0000 + 4 * MEGAPAGE_SIZE - 1 };
+        manager.add_memory_map(VirtualMemoryMap::new_zero_fill(vmarea, 0x0b)).unwrap();
+        let pages = manager.search_memory_map(0x4000_0000).unwrap().zero_fill.clone().unwrap();
+
+        // A read maps the shared zero page without write permission
+        assert!(manager.lazy_map_page(0x4000_1008).is_ok());
+        let pte = *manager.get_root_page_table().unwrap().walk(0x4000_1000, false, asid).unwrap();
+        assert_eq!(pte.get_ppn() << 12, zero_page_paddr());
+        assert_eq!(pte.get_flags() & 0b100, 0);
+        assert_eq!(pages.resident_pages(vmarea.start, vmarea.end), 0);
+
+        // A write gives the page its own frame
+        assert!(manager.lazy_map_page_for_write(0x4000_1008).is_ok());
+        let pte = *manager.get_root_page_table().unwrap().walk(0x4000_1000, false, asid).unwrap();
+        assert_eq!(pte.get_ppn() << 12, pages.frame(0x4000_1000).unwrap());
+        assert_ne!(pte.get_flags() & 0b100, 0);
+
+        // Kernel accesses never resolve to the zero page
+        let paddr = manager.translate_vaddr(0x4030_0010).unwrap();
+        assert_eq!(paddr, pages.frame(0x4030_0000).unwrap() + 0x10);
+        assert_eq!(pages.resident_pages(vmarea.start, vmarea.end), 2);
+
+        // Unmapped parts keep sharing the frames with the rest of the mapping
+        let removed = manager.unmap_range(0x4030_0000, PAGE_SIZE).unwrap();
+        assert!(alloc::sync::Arc::ptr_eq(removed[0].zero_fill.as_ref().unwrap(), &pages));
+        assert!(manager.search_memory_map(0x4030_1000).unwrap().zero_fill.is_some());
+    }
+
+    #[test_case]
+    fn test_unmap_user_memory_maps_clears_page_table() {
+        let mut manager = VirtualMemoryManager::new();
+        let asid = alloc_virtual_address_space();
+        manager.set_asid(asid);
+        let vmarea = MemoryArea { start: 0x4000_0000, end: 0x4000_0000 + 2 * PAGE_SIZE - 1 };
+        manager.add_memory_map(VirtualMemoryMap::new_zero_fill(vmarea, 0x0b)).unwrap();
+        assert!(manager.lazy_map_page_for_write(0x4000_1000).is_ok());
+        assert!(manager.get_root_page_table().unwrap().walk(0x4000_1000, false, asid).unwrap().is_valid());
+
+        let kernel_area = MemoryArea { start: 0x8000_0000, end: 0x8000_0fff };
+        manager.add_memory_map(VirtualMemoryMap::new(kernel_area, kernel_area, 0x3, false, None)).unwrap();
+
+        assert_eq!(manager.unmap_user_memory_maps(), 1);
+        assert_eq!(manager.memmap_len(), 1);
+        assert!(manager.search_memory_map(0x8000_0000).is_some());
+        let root = manager.get_root_page_table().unwrap();
+        assert!(root.walk(0x4000_1000, false, asid).map_or(true, |pte| !pte.is_valid()));
+    }
+
+    #[test_case]
+    fn test_unmap_range_splits_mapping() {
+        let mut manager = VirtualMemoryManager::new();
+        let map = VirtualMemoryMap::new(
+            MemoryArea { start: 0x80000000, end: 0x80003fff },
+            MemoryArea { start: 0x10000, end: 0x13fff },
+            0x0b,
+            false,
+            None
+        );
+        manager.add_memory_map(map).unwrap();
+
+        // Punch a hole in the middle of the mapping
+        let removed = manager.unmap_range(0x11000, 2 * PAGE_SIZE).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].vmarea, MemoryArea { start: 0x11000, end: 0x12fff });
+        assert_eq!(removed[0].pmarea.start, 0x80001000);
+
+        assert_eq!(manager.memmap_len(), 2);
+        assert!(manager.search_memory_map(0x11000).is_none());
+        assert_eq!(manager.translate_vaddr(0x13000), Some(0x80003000));
+
+        // Unmapping a range with no mappings is not an error
+        assert!(manager.unmap_range(0x20000, PAGE_SIZE).unwrap().is_empty());
+        assert!(manager.unmap_range(0x20001, PAGE_SIZE).is_err());
+        assert!(manager.unmap_range(0x20000, 0).is_err());
+    }
+
+    #[test_case]
+    fn test_protect_range() {
+        let mut manager = VirtualMemoryManager::new();
+        let map = VirtualMemoryMap::new(
+            MemoryArea { start: 0x80000000, end: 0x80002fff },
+            MemoryArea { start: 0x10000, end: 0x12fff },
+            0x0b,
+            false,
+            None
+        );
+        manager.add_memory_map(map).unwrap();
+
+        // Make the middle page read-only
+        manager.protect_range(0x11000, PAGE_SIZE, 0x09).unwrap();
+        assert_eq!(manager.memmap_len(), 3);
+        assert_eq!(manager.search_memory_map(0x10000).unwrap().permissions, 0x0b);
+        assert_eq!(manager.search_memory_map(0x11000).unwrap().permissions, 0x09);
+        assert_eq!(manager.search_memory_map(0x12000).unwrap().permissions, 0x0b);
+        assert_eq!(manager.translate_vaddr(0x11234), Some(0x80001234));
+
+        // Ranges with unmapped pages are rejected without changing anything
+        assert!(manager.protect_range(0x12000, 2 * PAGE_SIZE, 0x09).is_err());
+        assert_eq!(manager.search_memory_map(0x12000).unwrap().permissions, 0x0b);
+    }
+
+    #[test_case]
+    fn test_is_range_free() {
+        let mut manager = VirtualMemoryManager::new();
+        let vma = MemoryArea { start: 0x2000, end: 0x2fff };
+        manager.add_memory_map(VirtualMemoryMap::new(vma, vma, 0x0b, false, None)).unwrap();
+
+        assert!(manager.is_range_free(0x1000, PAGE_SIZE));
+        assert!(!manager.is_range_free(0x1000, 2 * PAGE_SIZE));
+        assert!(!manager.is_range_free(0x2000, 1));
+        assert!(manager.is_range_free(0x3000, PAGE_SIZE));
+    }
+
+    #[test_case]
+    fn test_set_brk_grows_one_heap_map() {
+        let mut manager = VirtualMemoryManager::new();
+        manager.set_program_break(0x10800, 0x10800);
+
+        manager.set_brk(0x12000).unwrap();
+        manager.set_brk(0x13004).unwrap();
+        assert_eq!(manager.get_brk(), 0x13004);
+        assert_eq!(manager.memmap_len(), 1);
+        let heap = manager.search_memory_map(0x11000).unwrap();
+        assert_eq!((heap.vmarea.start, heap.vmarea.end), (0x11000, 0x13fff));
+        assert!(heap.zero_fill.is_some());
+
+        // Shrinking hands back the pages above the new break
+        let removed = manager.set_brk(0x12000).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].vmarea.start, 0x12000);
+        assert!(manager.search_memory_map(0x12000).is_none());
+
+        // The heap cannot run into another mapping or below its start
+        let vma = MemoryArea { start: 0x14000, end: 0x14fff };
+        manager.add_memory_map(VirtualMemoryMap::new(vma, vma, 0x0b, false, None)).unwrap();
+        assert!(manager.set_brk(0x14001).is_err());
+        assert!(manager.set_brk(0x10000).is_err());
+        assert_eq!(manager.get_brk(), 0x12000);
+    }
+
+    #[test_case]
+    fn test_advise_range_releases_zero_fill_pages() {
+        use crate::vm::manager::MemoryAdvice;
+
+        let mut manager = VirtualMemoryManager::new();
+        manager.set_asid(alloc_virtual_address_space());
+        let vmarea = MemoryArea { start: 0x5000_0000, end: 0x5000_3fff };
+        manager.add_memory_map(VirtualMemoryMap::new_zero_fill(vmarea, 0x0b)).unwrap();
+        let pages = manager.search_memory_map(0x5000_0000).unwrap().zero_fill.clone().unwrap();
+        for page in (vmarea.start..vmarea.end).step_by(PAGE_SIZE) {
+            manager.lazy_map_page_for_write(page).unwrap();
+        }
+
+        manager.advise_range(0x5000_0000, PAGE_SIZE, MemoryAdvice::DontNeed).unwrap();
+        assert_eq!(pages.frame(0x5000_0000), None);
+        assert_eq!(pages.resident_pages(vmarea.start, vmarea.end), 3);
+
+        // Lazily freed pages keep their frame until memory runs short
+        manager.advise_range(0x5000_1000, 2 * PAGE_SIZE, MemoryAdvice::Free).unwrap();
+        assert_eq!(pages.resident_pages(vmarea.start, vmarea.end), 3);
+        manager.advise_range(0x5000_1000, PAGE_SIZE, MemoryAdvice::WillNeed).unwrap();
+
+        // The whole range must be mapped
+        assert!(manager.advise_range(0x5000_3000, 2 * PAGE_SIZE, MemoryAdvice::DontNeed).is_err());
+    }
+}
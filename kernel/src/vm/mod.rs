@@ -8,7 +8,7 @@ use vmem::MemoryArea;
 use vmem::VirtualMemoryMap;
 use vmem::VirtualMemoryPermission;
 
-use crate::arch::get_cpu;
+use crate::arch::get_paddr_cpu;
 use crate::arch::get_kernel_trapvector_paddr;
 use crate::arch::get_user_trapvector_paddr;
 use crate::arch::set_trapvector;
@@ -28,8 +28,12 @@ use crate::task::Task;
 
 extern crate alloc;
 
+pub mod aslr;
 pub mod manager;
+pub mod vdso;
 pub mod vmem;
+pub mod wx;
+pub mod zero_fill;
 
 unsafe extern "C" {
     static __KERNEL_SPACE_START: usize;
@@ -92,6 +96,7 @@ pub fn kernel_vm_init(kernel_area: MemoryArea) {
             VirtualMemoryPermission::Execute as usize,
         is_shared: true, // Kernel memory should be shared across all processes
         owner: None,
+        zero_fill: None,
     };
     manager.add_memory_map(kernel_map.clone()).map_err(|e| panic!("Failed to add kernel memory map: {}", e)).unwrap();
     /* Pre-map the kernel space */
@@ -111,6 +116,7 @@ pub fn kernel_vm_init(kernel_area: MemoryArea) {
             VirtualMemoryPermission::Write as usize,
         is_shared: true, // Device memory should be shared
         owner: None,
+        zero_fill: None,
     };
     manager.add_memory_map(dev_map.clone()).map_err(|e| panic!("Failed to add device memory map: {}", e)).unwrap();
 
@@ -125,23 +131,25 @@ pub fn kernel_vm_init(kernel_area: MemoryArea) {
 
 pub fn user_vm_init(task: &mut Task) {
     let asid = alloc_virtual_address_space();
-    task.vm_manager.set_asid(asid);
+    task.vm_manager.write().set_asid(asid);
 
     /* User stack page */
     let num_of_stack_page = 16; // 4 pages for user stack
     let stack_start = USER_STACK_END - num_of_stack_page * PAGE_SIZE;
     task.allocate_stack_pages(stack_start, num_of_stack_page).map_err(|e| panic!("Failed to allocate user stack pages: {}", e)).unwrap();
+    task.stack_top = USER_STACK_END;
 
     /* Guard page */
    task.allocate_guard_pages(stack_start - PAGE_SIZE, 1).map_err(|e| panic!("Failed to allocate guard page: {}", e)).unwrap();
 
-    setup_trampoline(&mut task.vm_manager);
+    setup_trampoline(&mut task.vm_manager.write());
+    vdso::map_vdso(task).map_err(|e| panic!("Failed to map vDSO: {}", e)).unwrap();
 }
 
 pub fn user_kernel_vm_init(task: &mut Task) {
     let asid = alloc_virtual_address_space();
     let root_page_table = get_root_pagetable(asid).unwrap();
-    task.vm_manager.set_asid(asid);
+    task.vm_manager.write().set_asid(asid);
 
     let kernel_area = unsafe { KERNEL_AREA.unwrap() };
 
@@ -154,8 +162,9 @@ pub fn user_kernel_vm_init(task: &mut Task) {
             VirtualMemoryPermission::Execute as usize,
         is_shared: true, // Kernel memory should be shared across all processes
         owner: None,
+        zero_fill: None,
     };
-    task.vm_manager.add_memory_map(kernel_map.clone()).map_err(|e| {
+    task.vm_manager.write().add_memory_map(kernel_map.clone()).map_err(|e| {
         panic!("Failed to add kernel memory map: {}", e);
     }).unwrap();
     /* Pre-map the kernel space */
@@ -181,21 +190,39 @@ pub fn user_kernel_vm_init(task: &mut Task) {
             VirtualMemoryPermission::Write as usize,
         is_shared: true, // Device memory should be shared
         owner: None,
+        zero_fill: None,
     };
-    task.vm_manager.add_memory_map(dev_map).map_err(|e| panic!("Failed to add device memory map: {}", e)).unwrap();
+    task.vm_manager.write().add_memory_map(dev_map).map_err(|e| panic!("Failed to add device memory map: {}", e)).unwrap();
 
-    setup_trampoline(&mut task.vm_manager);
+    setup_trampoline(&mut task.vm_manager.write());
 }
 
+/// Set up the initial user stack of a task
+///
+/// Only the initial pages are allocated; the stack grows on demand (see
+/// `Task::grow_stack`) up to the task's stack limit.
+///
+/// # Returns
+/// The (base, top) addresses of the stack
 pub fn setup_user_stack(task: &mut Task) -> (usize, usize) {
+    /* The top of the stack is randomized unless ASLR is disabled */
+    setup_user_stack_at(task, aslr::stack_top())
+}
+
+/// Set up the initial user stack of a task below `stack_top`
+///
+/// # Returns
+/// The (base, top) addresses of the stack
+pub fn setup_user_stack_at(task: &mut Task, stack_top: usize) -> (usize, usize) {
     /* User stack page */
-    let num_of_stack_page = 16; // 4 pages for user stack
-    let stack_base = USER_STACK_END - num_of_stack_page * PAGE_SIZE;
+    let num_of_stack_page = 16; // Initial user stack (64KiB)
+    let stack_base = stack_top - num_of_stack_page * PAGE_SIZE;
     task.allocate_stack_pages(stack_base, num_of_stack_page).map_err(|e| panic!("Failed to allocate user stack pages: {}", e)).unwrap();
+    task.stack_top = stack_top;
     /* Guard page */
     task.allocate_guard_pages(stack_base - PAGE_SIZE, 1).map_err(|e| panic!("Failed to allocate guard page: {}", e)).unwrap();
     
-    (stack_base, USER_STACK_END)
+    (stack_base, stack_top)
 }
 
 static mut TRAMPOLINE_TRAP_VECTOR: Option<usize> = None;
@@ -206,26 +233,19 @@ pub fn setup_trampoline(manager: &mut VirtualMemoryManager) {
     let trampoline_end = unsafe { &__TRAMPOLINE_END as *const usize as usize } - 1;
     let trampoline_size = trampoline_end - trampoline_start;
 
-    let arch = get_cpu().as_paddr_cpu();
     let trampoline_vaddr_start = VMMAX - trampoline_size;
     let trampoline_vaddr_end = VMMAX;
 
     let trap_entry_paddr = get_user_trapvector_paddr();
-    // let trapframe_paddr = arch.get_trapframe_paddr();
-    let arch_paddr = arch as *const Arch as usize;
     let trap_entry_offset = trap_entry_paddr - trampoline_start;
-    let arch_offset = arch_paddr - trampoline_start;
 
     let trap_entry_vaddr = trampoline_vaddr_start + trap_entry_offset;
-    let arch_vaddr = trampoline_vaddr_start + arch_offset;
     
     // early_println!("Trampoline space mapped   : {:#x} - {:#x}", trampoline_vaddr_start, trampoline_vaddr_end);
     // early_println!("  Trampoline paddr  : {:#x} - {:#x}", trampoline_start, trampoline_end);
     // early_println!("  Trap entry paddr  : {:#x}", trap_entry_paddr);
-    // early_println!("  Arch paddr        : {:#x}", arch_paddr);
     // early_println!("  Trampoline vaddr  : {:#x} - {:#x}", trampoline_vaddr_start, trampoline_vaddr_end);
     // early_println!("  Trap entry vaddr  : {:#x}", trap_entry_vaddr);
-    // early_println!("  Arch vaddr        : {:#x}", arch_vaddr);
     
     let trampoline_map = VirtualMemoryMap {
         vmarea: MemoryArea {
@@ -242,6 +262,7 @@ pub fn setup_trampoline(manager: &mut VirtualMemoryManager) {
             VirtualMemoryPermission::Execute as usize,
         is_shared: true, // Trampoline should be shared across all processes
         owner: None,
+        zero_fill: None,
     };
 
     manager.add_memory_map(trampoline_map.clone())
@@ -251,7 +272,11 @@ pub fn setup_trampoline(manager: &mut VirtualMemoryManager) {
         .map_err(|e| panic!("Failed to map trampoline memory area: {}", e)).unwrap();
 
     set_trampoline_trap_vector(trap_entry_vaddr);
-    set_trampoline_arch(arch.get_cpuid(), arch_vaddr);
+    /* Every CPU structure lives in the trampoline, so each CPU gets its own view */
+    for cpu_id in 0..NUM_OF_CPUS {
+        let arch_paddr = get_paddr_cpu(cpu_id) as *const Arch as usize;
+        set_trampoline_arch(cpu_id, trampoline_vaddr_start + (arch_paddr - trampoline_start));
+    }
 }
 
 pub fn set_trampoline_trap_vector(trap_vector: usize) {
@@ -294,7 +319,7 @@ pub fn switch_to_kernel_vm() {
 pub fn switch_to_user_vm(cpu: &mut Arch) {
     let cpu_id = cpu.get_cpuid();
     let task = get_scheduler().get_current_task(cpu_id).expect("No current task found");
-    let manager = &task.vm_manager;
+    let manager = task.vm_manager.read();
     let root_page_table = manager.get_root_page_table().expect("Root page table is not set");
     set_trapvector(get_trampoline_trap_vector());
     root_page_table.switch(manager.get_asid());
@@ -0,0 +1,206 @@
+//! vDSO data pages
+//!
+//! Every user address space gets two read-only pages at [`VDSO_BASE`] that
+//! let programs query the clock and their process ID without a trap:
+//! - The clock page ([`VdsoData`]) is a single kernel page shared by all
+//!   tasks. The timer tick stores the time since boot in it, so the clock
+//!   it shows has the resolution of a tick.
+//! - The task page ([`VdsoTaskData`]) is private to the address space and
+//!   holds the ID of the task that created it by exec or fork. Threads
+//!   share the address space, so they see the ID of that task, which is
+//!   the process ID in the POSIX sense.
+//!
+//! The clock is published under a sequence counter: it is odd while the
+//! clock is being updated, and a reader retries when the counter is odd or
+//! changed while it read.
+//!
+//! Scarlet programs find the pages through `a2` at their entry point.
+
+use core::sync::atomic::{fence, AtomicU32, AtomicU64, Ordering};
+
+use crate::environment::{PAGE_SIZE, VDSO_BASE};
+use crate::task::Task;
+use crate::timer::TICK_INTERVAL_US;
+use super::vmem::{MemoryArea, VirtualMemoryMap, VirtualMemoryPermission};
+
+/// Identifies the clock page ("SVDS")
+pub const VDSO_MAGIC: u32 = 0x5344_5653;
+/// Layout version of the pages
+pub const VDSO_VERSION: u32 = 1;
+/// Address of the task page
+pub const VDSO_TASK_DATA: usize = VDSO_BASE + PAGE_SIZE;
+/// Size of the vDSO in bytes
+pub const VDSO_SIZE: usize = 2 * PAGE_SIZE;
+
+/// The clock page
+#[repr(C, align(4096))]
+pub struct VdsoData {
+    pub magic: u32,
+    pub version: u32,
+    /// Sequence counter, odd while the clock is updated
+    pub seq: AtomicU32,
+    _reserved: u32,
+    /// Nanoseconds since boot at the last tick
+    pub time_ns: AtomicU64,
+    /// Ticks since boot
+    pub tick: AtomicU64,
+    /// Resolution of `time_ns` in nanoseconds
+    pub resolution_ns: u64,
+}
+
+/// The task page
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct VdsoTaskData {
+    /// ID of the task that created the address space
+    pub pid: u64,
+}
+
+static VDSO_DATA: VdsoData = VdsoData {
+    magic: VDSO_MAGIC,
+    version: VDSO_VERSION,
+    seq: AtomicU32::new(0),
+    _reserved: 0,
+    time_ns: AtomicU64::new(0),
+    tick: AtomicU64::new(0),
+    resolution_ns: TICK_INTERVAL_US * 1_000,
+};
+
+/// Get the clock page
+pub fn vdso_data() -> &'static VdsoData {
+    &VDSO_DATA
+}
+
+/// Publish the time in the clock page
+///
+/// Called by each CPU on its tick. A CPU that finds the page being updated
+/// by another one leaves it to that one, and the clock never goes back.
+pub fn update_clock(time_ns: u64, tick: u64) {
+    let data = &VDSO_DATA;
+    let seq = data.seq.load(Ordering::Relaxed);
+    if seq & 1 != 0
+        || data.seq.compare_exchange(seq, seq.wrapping_add(1), Ordering::Acquire, Ordering::Relaxed).is_err() {
+        return;
+    }
+    // The odd counter must be visible before the new time
+    fence(Ordering::Release);
+    if time_ns > data.time_ns.load(Ordering::Relaxed) {
+        data.time_ns.store(time_ns, Ordering::Relaxed);
+        data.tick.store(tick, Ordering::Relaxed);
+    }
+    data.seq.store(seq.wrapping_add(2), Ordering::Release);
+}
+
+/// Read the clock page as a user program does
+///
+/// # Returns
+/// (nanoseconds, ticks) since boot at the last tick
+pub fn read_clock() -> (u64, u64) {
+    let data = &VDSO_DATA;
+    loop {
+        let seq = data.seq.load(Ordering::Acquire);
+        if seq & 1 != 0 {
+            core::hint::spin_loop();
+            continue;
+        }
+        let time_ns = data.time_ns.load(Ordering::Relaxed);
+        let tick = data.tick.load(Ordering::Relaxed);
+        fence(Ordering::Acquire);
+        if data.seq.load(Ordering::Relaxed) == seq {
+            return (time_ns, tick);
+        }
+    }
+}
+
+/// Whether a range of user addresses touches the vDSO
+pub fn overlaps(vaddr: usize, length: usize) -> bool {
+    let end = vaddr.saturating_add(length.max(1));
+    vaddr < VDSO_BASE + VDSO_SIZE && end > VDSO_BASE
+}
+
+/// Map the vDSO into the address space of a task
+///
+/// Call this when a new user address space is set up (at exec). The clock
+/// page is shared, and the task page is allocated for the task.
+pub fn map_vdso(task: &mut Task) -> Result<(), &'static str> {
+    let paddr = &VDSO_DATA as *const VdsoData as usize;
+    let permissions = VirtualMemoryPermission::Read as usize | VirtualMemoryPermission::User as usize;
+    let data_map = VirtualMemoryMap {
+        vmarea: MemoryArea {
+            start: VDSO_BASE,
+            end: VDSO_BASE + PAGE_SIZE - 1,
+        },
+        pmarea: MemoryArea {
+            start: paddr,
+            end: paddr + PAGE_SIZE - 1,
+        },
+        permissions,
+        is_shared: true, // The clock page is shared across all processes
+        owner: None,
+        zero_fill: None,
+    };
+    task.vm_manager.write().add_memory_map(data_map)?;
+    task.allocate_pages(VDSO_TASK_DATA, 1, permissions)?;
+    write_task_data(task)
+}
+
+/// Store the ID of a task in the task page of its address space
+///
+/// A forked child gets a copy of its parent's task page, so it must be
+/// rewritten for the child.
+pub fn write_task_data(task: &Task) -> Result<(), &'static str> {
+    let paddr = task.vm_manager.read().translate_vaddr(VDSO_TASK_DATA).ok_or("vDSO is not mapped")?;
+    let data = VdsoTaskData { pid: task.get_id() as u64 };
+    unsafe { (paddr as *mut VdsoTaskData).write(data) };
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+
+    use super::*;
+    use crate::task::{new_user_task, CloneFlags};
+
+    fn task_pid(task: &Task) -> u64 {
+        let paddr = task.vm_manager.read().translate_vaddr(VDSO_TASK_DATA).unwrap();
+        unsafe { (*(paddr as *const VdsoTaskData)).pid }
+    }
+
+    #[test_case]
+    fn test_vdso_clock_update() {
+        // Other CPUs may tick meanwhile, so the clock is only known to advance
+        let (time_ns, tick) = read_clock();
+        let seq = vdso_data().seq.load(Ordering::Relaxed);
+        update_clock(time_ns + 1_000, tick + 1);
+        assert!(read_clock().0 >= time_ns + 1_000);
+        assert!(vdso_data().seq.load(Ordering::Relaxed) != seq);
+
+        // The clock does not go back
+        update_clock(time_ns, tick);
+        assert!(read_clock().0 >= time_ns + 1_000);
+        assert_eq!(vdso_data().seq.load(Ordering::Relaxed) & 1, 0);
+        assert_eq!(vdso_data().magic, VDSO_MAGIC);
+    }
+
+    #[test_case]
+    fn test_vdso_mapped_per_process() {
+        let mut parent = new_user_task("VdsoParent".to_string(), 0);
+        parent.init();
+        assert_eq!(task_pid(&parent), parent.get_id() as u64);
+        let paddr = parent.vm_manager.read().translate_vaddr(VDSO_BASE).unwrap();
+        assert_eq!(paddr, vdso_data() as *const VdsoData as usize);
+        let map = parent.vm_manager.read().search_memory_map(VDSO_TASK_DATA).cloned().unwrap();
+        assert!(!VirtualMemoryPermission::Write.contained_in(map.permissions));
+
+        // A forked child sees its own ID and the same clock page
+        let child = parent.clone_task(CloneFlags::default()).unwrap();
+        assert_eq!(task_pid(&child), child.get_id() as u64);
+        assert_eq!(task_pid(&parent), parent.get_id() as u64);
+        assert_eq!(child.vm_manager.read().translate_vaddr(VDSO_BASE), Some(paddr));
+
+        assert!(overlaps(VDSO_BASE + PAGE_SIZE, 1));
+        assert!(overlaps(VDSO_BASE - PAGE_SIZE, 2 * PAGE_SIZE));
+        assert!(!overlaps(VDSO_BASE + VDSO_SIZE, PAGE_SIZE));
+    }
+}
@@ -1,5 +1,7 @@
-use alloc::sync::Weak;
+use alloc::sync::{Arc, Weak};
 use crate::object::capability::memory_mapping::MemoryMappingOps;
+use crate::environment::PAGE_SIZE;
+use super::zero_fill::ZeroFillPages;
 
 /// Represents a mapping between physical and virtual memory areas.
 ///
@@ -13,6 +15,7 @@ use crate::object::capability::memory_mapping::MemoryMappingOps;
 /// * `permissions` - The access permissions for this mapping
 /// * `is_shared` - Whether this mapping is shared between processes
 /// * `owner` - Optional weak reference to the object that created this mapping (None for anonymous mappings)
+/// * `zero_fill` - Frames of a demand-zero anonymous mapping; `pmarea` is not used for such mappings
 #[derive(Debug, Clone)]
 pub struct VirtualMemoryMap {
     pub pmarea: MemoryArea,
@@ -20,6 +23,7 @@ pub struct VirtualMemoryMap {
     pub permissions: usize,
     pub is_shared: bool,
     pub owner: Option<Weak<dyn MemoryMappingOps>>,
+    pub zero_fill: Option<Arc<ZeroFillPages>>,
 }
 
 impl VirtualMemoryMap {
@@ -41,6 +45,27 @@ impl VirtualMemoryMap {
             permissions,
             is_shared,
             owner,
+            zero_fill: None,
+        }
+    }
+
+    /// Creates a demand-zero anonymous mapping.
+    ///
+    /// No memory is allocated up front: pages read as zero until they are
+    /// first written, which gives them a private frame.
+    ///
+    /// # Arguments
+    /// * `vmarea` - The virtual memory area to map
+    /// * `permissions` - The permissions to set for the virtual memory area
+    pub fn new_zero_fill(vmarea: MemoryArea, permissions: usize) -> Self {
+        VirtualMemoryMap {
+            // Only kept so that splitting and size checks work as for other maps
+            pmarea: MemoryArea::new(0, vmarea.size() - 1),
+            vmarea,
+            permissions,
+            is_shared: false,
+            owner: None,
+            zero_fill: Some(ZeroFillPages::new()),
         }
     }
 
@@ -52,11 +77,36 @@ impl VirtualMemoryMap {
     /// # Returns
     /// The physical address corresponding to the given virtual address, if it exists.
     /// If the virtual address is not part of the memory map, `None` is returned.
+    /// For a demand-zero mapping, `None` is also returned for pages that have
+    /// not been written yet.
     pub fn get_paddr(&self, vaddr: usize) -> Option<usize> {
-        if self.vmarea.start <= vaddr && vaddr <= self.vmarea.end {
-            Some(self.pmarea.start + (vaddr - self.vmarea.start))
-        } else {
-            None
+        if vaddr < self.vmarea.start || vaddr > self.vmarea.end {
+            return None;
+        }
+        match &self.zero_fill {
+            Some(pages) => {
+                let page_vaddr = vaddr & !(PAGE_SIZE - 1);
+                pages.frame(page_vaddr).map(|paddr| paddr + (vaddr - page_vaddr))
+            }
+            None => Some(self.pmarea.start + (vaddr - self.vmarea.start)),
+        }
+    }
+
+    /// Tells the owning object (if it is still alive) that this map was
+    /// installed in another address space, e.g. when a task is cloned.
+    ///
+    /// The offset into the object is not tracked by the map, so 0 is passed.
+    pub fn notify_owner_mapped(&self) {
+        if let Some(owner) = self.owner.as_ref().and_then(|owner| owner.upgrade()) {
+            owner.on_mapped(self.vmarea.start, self.pmarea.start, self.vmarea.size(), 0);
+        }
+    }
+
+    /// Tells the owning object (if it is still alive) that this map is gone,
+    /// e.g. when the address space holding it is torn down.
+    pub fn notify_owner_unmapped(&self) {
+        if let Some(owner) = self.owner.as_ref().and_then(|owner| owner.upgrade()) {
+            owner.on_unmapped(self.vmarea.start, self.vmarea.size());
         }
     }
 }
@@ -0,0 +1,192 @@
+//! W^X (write xor execute) enforcement for user mappings
+//!
+//! User memory may be writable or executable, but never both at once.
+//! Requests that would create a writable and executable mapping (mmap,
+//! mprotect or an ELF segment flagged RWX) are rejected, and what else
+//! happens to the offender depends on the policy:
+//! - `off`: W^X is not enforced (for old binaries that need RWX memory)
+//! - `log`: the request fails and is logged (default)
+//! - `kill`: the request fails and the task is killed
+//!
+//! Besides the hard rule, mprotect calls that make memory executable which
+//! was writable before are audited: they are legal, but writing code and
+//! then executing it is what exploits do, so they are logged and counted.
+//!
+//! The policy can be set on the kernel command line with
+//! `wx_policy=<off|log|kill>` and at runtime through `/proc/wx`, which also
+//! reports the counters.
+
+use core::fmt::Write;
+use core::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use alloc::string::String;
+
+use crate::println;
+use super::vmem::VirtualMemoryPermission;
+
+/// Exit status of a task killed for violating W^X (128 + SIGKILL)
+pub const WX_KILL_EXIT_STATUS: i32 = 137;
+
+/// What happens when a task asks for writable and executable memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum WxPolicy {
+    /// W^X is not enforced
+    Off = 0,
+    /// The request is rejected and logged
+    Log = 1,
+    /// The request is rejected and the task is killed
+    Kill = 2,
+}
+
+impl WxPolicy {
+    fn from_raw(raw: u8) -> Self {
+        match raw {
+            0 => WxPolicy::Off,
+            2 => WxPolicy::Kill,
+            _ => WxPolicy::Log,
+        }
+    }
+
+    /// Parse a policy name (`off`, `log` or `kill`)
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "off" => Some(WxPolicy::Off),
+            "log" => Some(WxPolicy::Log),
+            "kill" => Some(WxPolicy::Kill),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WxPolicy::Off => "off",
+            WxPolicy::Log => "log",
+            WxPolicy::Kill => "kill",
+        }
+    }
+}
+
+static WX_POLICY: AtomicU8 = AtomicU8::new(WxPolicy::Log as u8);
+
+/// Number of rejected writable and executable requests
+static WX_REJECTED: AtomicU64 = AtomicU64::new(0);
+
+/// Number of audited mprotect calls that made writable memory executable
+static WX_EXEC_AFTER_WRITE: AtomicU64 = AtomicU64::new(0);
+
+/// Get the current policy
+pub fn policy() -> WxPolicy {
+    WxPolicy::from_raw(WX_POLICY.load(Ordering::Relaxed))
+}
+
+/// Set the policy
+pub fn set_policy(policy: WxPolicy) {
+    WX_POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
+/// Apply W^X options from the kernel command line
+pub fn parse_cmdline(cmdline: &str) {
+    for arg in cmdline.split_whitespace() {
+        if let Some(value) = arg.strip_prefix("wx_policy=") {
+            match WxPolicy::from_name(value) {
+                Some(policy) => set_policy(policy),
+                None => crate::early_println!("[wx] Ignoring invalid wx_policy={}", value),
+            }
+        }
+    }
+}
+
+/// Check whether `permissions` are both writable and executable
+pub fn is_writable_executable(permissions: usize) -> bool {
+    VirtualMemoryPermission::Write.contained_in(permissions)
+        && VirtualMemoryPermission::Execute.contained_in(permissions)
+}
+
+/// Check the permissions requested for a user mapping
+///
+/// # Arguments
+/// * `task_id` - The requesting task (for the log)
+/// * `vaddr` - Start of the mapping
+/// * `length` - Length of the mapping in bytes
+/// * `permissions` - Requested VirtualMemoryPermission bits
+/// * `origin` - What made the request (e.g. "mmap"), for the log
+///
+/// # Returns
+/// `Ok(())` if the request may proceed, otherwise `Err` with the policy
+/// that decides what happens to the task
+pub fn check_user_mapping(task_id: usize, vaddr: usize, length: usize, permissions: usize, origin: &str) -> Result<(), WxPolicy> {
+    let policy = policy();
+    if policy == WxPolicy::Off || !is_writable_executable(permissions) {
+        return Ok(());
+    }
+    WX_REJECTED.fetch_add(1, Ordering::Relaxed);
+    println!(
+        "[wx] Task {}: rejected writable and executable {} at {:#x}-{:#x}{}",
+        task_id, origin, vaddr, vaddr.saturating_add(length.saturating_sub(1)),
+        if policy == WxPolicy::Kill { ", killing task" } else { "" },
+    );
+    Err(policy)
+}
+
+/// Audit an mprotect call that is about to be applied
+///
+/// # Arguments
+/// * `task_id` - The requesting task
+/// * `vaddr` - Start of the range
+/// * `length` - Length of the range in bytes
+/// * `was_writable` - Whether any part of the range is writable now
+/// * `permissions` - The new VirtualMemoryPermission bits
+pub fn audit_protect(task_id: usize, vaddr: usize, length: usize, was_writable: bool, permissions: usize) {
+    if policy() == WxPolicy::Off || !was_writable || !VirtualMemoryPermission::Execute.contained_in(permissions) {
+        return;
+    }
+    WX_EXEC_AFTER_WRITE.fetch_add(1, Ordering::Relaxed);
+    println!(
+        "[wx] Task {}: mprotect makes writable memory executable at {:#x}-{:#x}",
+        task_id, vaddr, vaddr.saturating_add(length.saturating_sub(1)),
+    );
+}
+
+/// Render the policy and counters as text (the format of `/proc/wx`)
+pub fn format_status() -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "policy={}", policy().name());
+    let _ = writeln!(out, "rejected={}", WX_REJECTED.load(Ordering::Relaxed));
+    let _ = writeln!(out, "exec_after_write={}", WX_EXEC_AFTER_WRITE.load(Ordering::Relaxed));
+    out
+}
+
+/// Apply a policy name written to `/proc/wx`
+pub fn control(command: &[u8]) -> Result<(), &'static str> {
+    let command = core::str::from_utf8(command).map_err(|_| "Invalid command")?;
+    let policy = WxPolicy::from_name(command.trim()).ok_or("Unknown W^X policy")?;
+    set_policy(policy);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_wx_check_follows_policy() {
+        let rwx = VirtualMemoryPermission::Read as usize | VirtualMemoryPermission::Write as usize
+            | VirtualMemoryPermission::Execute as usize | VirtualMemoryPermission::User as usize;
+        let rx = rwx & !(VirtualMemoryPermission::Write as usize);
+        let saved = policy();
+
+        set_policy(WxPolicy::Log);
+        assert!(check_user_mapping(1, 0x1000, 0x1000, rx, "test").is_ok());
+        assert_eq!(check_user_mapping(1, 0x1000, 0x1000, rwx, "test"), Err(WxPolicy::Log));
+
+        assert!(control(b"kill\n").is_ok());
+        assert_eq!(check_user_mapping(1, 0x1000, 0x1000, rwx, "test"), Err(WxPolicy::Kill));
+
+        assert!(control(b"off").is_ok());
+        assert!(check_user_mapping(1, 0x1000, 0x1000, rwx, "test").is_ok());
+        assert!(control(b"bogus").is_err());
+
+        set_policy(saved);
+    }
+}
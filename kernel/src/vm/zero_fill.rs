@@ -0,0 +1,322 @@
+//! Demand-zero anonymous memory
+//!
+//! Anonymous mappings start out without any memory behind them. Until a page
+//! is written it reads as the shared zero page, which is mapped read-only
+//! into every address space that needs it. The first write to a page faults
+//! and gives just that page a private, zeroed frame, so a large sparse
+//! allocation only costs the pages that are actually used.
+//!
+//! The frames of a mapping are kept in a `ZeroFillPages` store keyed by
+//! virtual address. When a mapping is split (by munmap, mprotect or a
+//! MAP_FIXED mapping on top of it) the pieces keep sharing the store, and
+//! each piece only looks at the addresses it covers.
+//!
+//! Pages given back with `MADV_FREE` keep their frame but are marked lazily
+//! freed and unmapped from the MMU. The next access to such a page takes it
+//! back as it is; under memory pressure the reclaimer frees the frames that
+//! are still marked, and the pages read as zero again.
+//!
+//! Every frame is charged to the resource group of the task it was
+//! allocated for (see `crate::task::cgroup`) until it is freed.
+
+extern crate alloc;
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use spin::{Mutex, Once};
+
+use crate::mem::page::{allocate_raw_pages, Page};
+use crate::mem::reclaim::{self, Shrinker};
+use crate::task::cgroup::{self, GroupId};
+
+/// The page every untouched anonymous page reads as
+static ZERO_PAGE: Page = Page::new();
+
+/// Physical address of the shared zero page
+///
+/// The page must only ever be mapped without write permission.
+pub fn zero_page_paddr() -> usize {
+    &ZERO_PAGE as *const Page as usize
+}
+
+/// A private frame of a demand-zero mapping
+struct Frame {
+    page: Box<Page>,
+    /// Given back with MADV_FREE and not accessed since
+    lazy_free: bool,
+    /// Resource group the frame is charged to
+    group: GroupId,
+}
+
+impl Frame {
+    /// A frame charged to `group`
+    fn new(page: Box<Page>, group: GroupId) -> Self {
+        cgroup::charge_memory(group, 1);
+        Self { page, lazy_free: false, group }
+    }
+
+    fn paddr(&self) -> usize {
+        self.page.as_ref() as *const Page as usize
+    }
+}
+
+/// Private frames of a demand-zero mapping
+pub struct ZeroFillPages {
+    /// Page-aligned virtual address -> frame
+    frames: Mutex<BTreeMap<usize, Frame>>,
+}
+
+impl ZeroFillPages {
+    /// Create an empty store: every page reads as zero
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self { frames: Mutex::new(BTreeMap::new()) })
+    }
+
+    /// Physical address of the frame behind the page at `page_vaddr`, or
+    /// `None` if the page has not been written yet
+    ///
+    /// A lazily freed page is taken back: its frame is kept from now on.
+    pub fn frame(&self, page_vaddr: usize) -> Option<usize> {
+        let mut frames = self.frames.lock();
+        let frame = frames.get_mut(&page_vaddr)?;
+        if core::mem::take(&mut frame.lazy_free) {
+            LAZY_FREE_PAGES.fetch_sub(1, Ordering::Relaxed);
+        }
+        Some(frame.paddr())
+    }
+
+    /// Give the page at `page_vaddr` a private frame if it has none yet
+    ///
+    /// # Returns
+    /// The physical address of the frame and whether it was just allocated
+    pub fn populate(&self, page_vaddr: usize) -> (usize, bool) {
+        if let Some(paddr) = self.frame(page_vaddr) {
+            return (paddr, false);
+        }
+        // Allocate outside the lock: the allocation may invoke the OOM killer
+        let page = unsafe { Box::from_raw(allocate_raw_pages(1)) };
+        let mut frames = self.frames.lock();
+        if let Some(existing) = frames.get(&page_vaddr) {
+            // Populated concurrently; `page` is freed on return
+            return (existing.paddr(), false);
+        }
+        let frame = Frame::new(page, cgroup::current_group());
+        let paddr = frame.paddr();
+        frames.insert(page_vaddr, frame);
+        reclaim::anon_pages_added(1);
+        (paddr, true)
+    }
+
+    /// Number of pages in `[start, end]` that have a frame
+    pub fn resident_pages(&self, start: usize, end: usize) -> usize {
+        self.frames.lock().range(start..=end).count()
+    }
+
+    /// Free the frames of the pages in `[start, end]`
+    ///
+    /// Called when that part of the mapping is unmapped.
+    pub fn release_range(&self, start: usize, end: usize) {
+        let mut frames = self.frames.lock();
+        let mut released = frames.split_off(&start);
+        if let Some(after) = end.checked_add(1) {
+            frames.append(&mut released.split_off(&after));
+        }
+        reclaim::anon_pages_removed(released.len());
+        LAZY_FREE_PAGES.fetch_sub(count_lazy_free(released.values()), Ordering::Relaxed);
+        uncharge(released.values());
+    }
+
+    /// Mark the frames of the pages in `[start, end]` as lazily freed
+    /// (MADV_FREE)
+    ///
+    /// The caller must unmap the pages from the MMU, so that the next access
+    /// faults and takes the page back through [`Self::frame`].
+    pub fn lazy_free_range(self: &Arc<Self>, start: usize, end: usize) {
+        let mut marked = 0;
+        for (_, frame) in self.frames.lock().range_mut(start..=end) {
+            if !frame.lazy_free {
+                frame.lazy_free = true;
+                marked += 1;
+            }
+        }
+        LAZY_FREE_PAGES.fetch_add(marked, Ordering::Relaxed);
+        if marked > 0 {
+            lazy_free_shrinker().track(self);
+        }
+    }
+
+    /// Free up to `nr_pages` lazily freed frames
+    ///
+    /// # Returns
+    /// The number of frames freed
+    fn reclaim_lazy_free(&self, nr_pages: usize) -> usize {
+        let Some(mut frames) = self.frames.try_lock() else { return 0 };
+        let victims = frames.iter()
+            .filter(|(_, frame)| frame.lazy_free)
+            .map(|(&vaddr, _)| vaddr)
+            .take(nr_pages)
+            .collect::<Vec<_>>();
+        for vaddr in &victims {
+            if let Some(frame) = frames.remove(vaddr) {
+                cgroup::uncharge_memory(frame.group, 1);
+            }
+        }
+        reclaim::anon_pages_removed(victims.len());
+        LAZY_FREE_PAGES.fetch_sub(victims.len(), Ordering::Relaxed);
+        victims.len()
+    }
+
+    /// Copy the frames of the pages in `[start, end]` into a new store
+    ///
+    /// Used when an address space is cloned: the child gets its own copy of
+    /// the written pages and keeps reading the rest as zero. The copies are
+    /// charged to the group of the cloning task, which the child starts in.
+    pub fn duplicate_range(&self, start: usize, end: usize) -> Arc<Self> {
+        let group = cgroup::current_group();
+        let frames = self.frames.lock();
+        let copies = frames.range(start..=end)
+            .map(|(&vaddr, frame)| {
+                let copy = allocate_raw_pages(1);
+                unsafe { core::ptr::copy_nonoverlapping(frame.page.as_ref() as *const Page, copy, 1) };
+                (vaddr, Frame::new(unsafe { Box::from_raw(copy) }, group))
+            })
+            .collect::<BTreeMap<_, _>>();
+        reclaim::anon_pages_added(copies.len());
+        Arc::new(Self { frames: Mutex::new(copies) })
+    }
+}
+
+impl Drop for ZeroFillPages {
+    fn drop(&mut self) {
+        let frames = self.frames.get_mut();
+        reclaim::anon_pages_removed(frames.len());
+        LAZY_FREE_PAGES.fetch_sub(count_lazy_free(frames.values()), Ordering::Relaxed);
+        uncharge(frames.values());
+    }
+}
+
+/// Uncharge freed frames from their resource groups
+fn uncharge<'a>(frames: impl Iterator<Item = &'a Frame>) {
+    let mut pages = BTreeMap::new();
+    for frame in frames {
+        *pages.entry(frame.group).or_insert(0) += 1;
+    }
+    for (group, pages) in pages {
+        cgroup::uncharge_memory(group, pages);
+    }
+}
+
+fn count_lazy_free<'a>(frames: impl Iterator<Item = &'a Frame>) -> usize {
+    frames.filter(|frame| frame.lazy_free).count()
+}
+
+/// Number of lazily freed anonymous pages
+static LAZY_FREE_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+/// Reclaims the frames of lazily freed pages
+///
+/// Lazily freed pages are counted as inactive pages in `/proc/vmstat`.
+struct LazyFreeShrinker {
+    /// Stores that have had pages lazily freed
+    stores: Mutex<Vec<Weak<ZeroFillPages>>>,
+}
+
+impl LazyFreeShrinker {
+    fn track(&self, pages: &Arc<ZeroFillPages>) {
+        let pages = Arc::downgrade(pages);
+        let mut stores = self.stores.lock();
+        stores.retain(|store| store.strong_count() > 0);
+        if !stores.iter().any(|store| store.ptr_eq(&pages)) {
+            stores.push(pages);
+        }
+    }
+}
+
+impl Shrinker for LazyFreeShrinker {
+    fn name(&self) -> &str {
+        "lazyfree"
+    }
+
+    fn lru_pages(&self) -> (usize, usize) {
+        (0, LAZY_FREE_PAGES.load(Ordering::Relaxed))
+    }
+
+    fn reclaim(&self, nr_pages: usize) -> usize {
+        let Some(stores) = self.stores.try_lock() else { return 0 };
+        let mut freed = 0;
+        for pages in stores.iter().filter_map(Weak::upgrade) {
+            if freed >= nr_pages {
+                break;
+            }
+            freed += pages.reclaim_lazy_free(nr_pages - freed);
+        }
+        freed
+    }
+}
+
+static LAZY_FREE_SHRINKER: Once<Arc<LazyFreeShrinker>> = Once::new();
+
+fn lazy_free_shrinker() -> &'static Arc<LazyFreeShrinker> {
+    LAZY_FREE_SHRINKER.call_once(|| {
+        let shrinker = Arc::new(LazyFreeShrinker { stores: Mutex::new(Vec::new()) });
+        reclaim::register_shrinker(Arc::downgrade(&(shrinker.clone() as Arc<dyn Shrinker>)));
+        shrinker
+    })
+}
+
+impl fmt::Debug for ZeroFillPages {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ZeroFillPages")
+            .field("resident_pages", &self.frames.lock().len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test_case]
+    fn test_zero_fill_populate_and_release() {
+        let pages = ZeroFillPages::new();
+        assert_eq!(pages.frame(0x1000), None);
+
+        let (paddr, created) = pages.populate(0x1000);
+        assert!(created);
+        assert_ne!(paddr, zero_page_paddr());
+        assert!(unsafe { (*(paddr as *const Page)).data.iter().all(|&b| b == 0) });
+        assert_eq!(pages.populate(0x1000), (paddr, false));
+
+        pages.populate(0x3000);
+        pages.populate(0x5000);
+        assert_eq!(pages.resident_pages(0x1000, 0x5fff), 3);
+
+        // The copy is independent of the original
+        let copy = pages.duplicate_range(0x3000, 0x5fff);
+        assert_eq!(copy.resident_pages(0, usize::MAX), 2);
+        assert_ne!(copy.frame(0x3000), pages.frame(0x3000));
+
+        pages.release_range(0x2000, 0x3fff);
+        assert_eq!(pages.frame(0x3000), None);
+        assert_eq!(pages.resident_pages(0, usize::MAX), 2);
+    }
+
+    #[test_case]
+    fn test_lazy_free_pages_are_reclaimed_unless_accessed() {
+        let pages = ZeroFillPages::new();
+        let (paddr, _) = pages.populate(0x1000);
+        pages.populate(0x2000);
+        pages.lazy_free_range(0x1000, 0x2fff);
+
+        // Accessing a lazily freed page takes it back with its contents
+        assert_eq!(pages.frame(0x1000), Some(paddr));
+        assert_eq!(pages.reclaim_lazy_free(usize::MAX), 1);
+        assert_eq!(pages.frame(0x2000), None);
+        assert_eq!(pages.resident_pages(0, usize::MAX), 1);
+    }
+}
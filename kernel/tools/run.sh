@@ -57,6 +57,7 @@ qemu-system-riscv64 \
     -machine virt \
     -bios default \
     -m 4G \
+    -smp "${SCARLET_SMP:-2}" \
     -nographic \
     -serial mon:stdio \
     --no-reboot \
@@ -67,6 +68,7 @@ qemu-system-riscv64 \
     -device virtio-gpu-device,bus=virtio-mmio-bus.1 \
     -netdev user,id=net0 \
     -device virtio-net-device,netdev=net0,bus=virtio-mmio-bus.2 \
+    -device virtio-rng-device,bus=virtio-mmio-bus.6 \
     $DEBUG_FLAGS \
     -initrd "$INITRAMFS_PATH" \
     -kernel "$KERNEL_PATH" | tee "$TEMP_OUTPUT"
@@ -0,0 +1,60 @@
+#!/bin/bash
+
+# Integration test for the Linux ABI
+# Boots the kernel, starts a static busybox shell through the `linux`
+# launcher and checks that it runs a command.
+
+SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
+KERNEL_DIR="$(dirname "$SCRIPT_DIR")"
+PROJECT_ROOT="$(dirname "$KERNEL_DIR")"
+KERNEL_PATH="${1:-$KERNEL_DIR/target/riscv64gc-unknown-none-elf/debug/kernel}"
+BUSYBOX_PATH="${BUSYBOX:-/opt/busybox/busybox}"
+INITRAMFS_PATH="$PROJECT_ROOT/mkfs/dist/initramfs.cpio"
+ROOTFS_PATH="$PROJECT_ROOT/mkfs/dist/rootfs.img"
+
+if [ ! -f "$BUSYBOX_PATH" ]; then
+    echo "busybox not found at $BUSYBOX_PATH (set BUSYBOX to a static riscv64 busybox)"
+    exit 1
+fi
+
+# Rebuild the root file system with busybox in the Linux system directory
+cp "$BUSYBOX_PATH" "$PROJECT_ROOT/mkfs/rootfs/system/linux-riscv64/bin/busybox"
+rm -f "$ROOTFS_PATH"
+sh "$PROJECT_ROOT/mkfs/make_rootfs.sh" > /dev/null || exit 1
+
+TEMP_OUTPUT=$(mktemp)
+
+# Start the Linux shell from the Scarlet shell and let it compute the
+# marker, so that the echo of the typed command does not match
+{
+    sleep 10
+    printf 'linux\n'
+    sleep 5
+    printf 'echo linux-$((40 + 2))\n'
+    sleep 3
+    printf 'exit\n'
+    sleep 2
+} | timeout 120 qemu-system-riscv64 \
+    -machine virt \
+    -bios default \
+    -m 4G \
+    -smp "${SCARLET_SMP:-2}" \
+    -nographic \
+    -serial stdio \
+    -monitor none \
+    --no-reboot \
+    -global virtio-mmio.force-legacy=false \
+    -drive id=x0,file="$ROOTFS_PATH",format=raw,if=none \
+    -device virtio-blk-device,drive=x0,bus=virtio-mmio-bus.0 \
+    -initrd "$INITRAMFS_PATH" \
+    -kernel "$KERNEL_PATH" | tee "$TEMP_OUTPUT"
+
+if grep -q "linux-42" "$TEMP_OUTPUT"; then
+    echo "busybox shell test passed"
+    rm -f "$TEMP_OUTPUT"
+    exit 0
+else
+    echo "busybox shell test failed"
+    rm -f "$TEMP_OUTPUT"
+    exit 1
+fi
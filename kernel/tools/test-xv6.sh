@@ -0,0 +1,73 @@
+#!/bin/bash
+
+# Integration test for the xv6 ABI
+# Boots the kernel, starts xv6 through the `xv6` launcher and runs the
+# stock usertests suite in its shell.
+
+SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
+KERNEL_DIR="$(dirname "$SCRIPT_DIR")"
+PROJECT_ROOT="$(dirname "$KERNEL_DIR")"
+KERNEL_PATH="${1:-$KERNEL_DIR/target/riscv64gc-unknown-none-elf/debug/kernel}"
+XV6_DIR="${XV6_DIR:-/opt/xv6-riscv}"
+XV6_SYSTEM_DIR="$PROJECT_ROOT/mkfs/rootfs/system/xv6-riscv64"
+INITRAMFS_PATH="$PROJECT_ROOT/mkfs/dist/initramfs.cpio"
+ROOTFS_PATH="$PROJECT_ROOT/mkfs/dist/rootfs.img"
+
+# Use the user programs of an xv6 build when there is one, such as the
+# one in the development image; the rootfs ships prebuilt copies otherwise
+if [ -d "$XV6_DIR/user" ]; then
+    for prog in "$XV6_DIR"/user/_*; do
+        [ -f "$prog" ] || continue
+        cp "$prog" "$XV6_SYSTEM_DIR/$(basename "$prog" | sed 's/^_//')"
+    done
+fi
+
+rm -f "$ROOTFS_PATH"
+sh "$PROJECT_ROOT/mkfs/make_rootfs.sh" > /dev/null || exit 1
+
+TEMP_OUTPUT=$(mktemp)
+TEST_TIMEOUT="${XV6_TEST_TIMEOUT:-840}"
+
+# Start xv6 from the Scarlet shell and run the test suite
+timeout "$TEST_TIMEOUT" qemu-system-riscv64 \
+    -machine virt \
+    -bios default \
+    -m 4G \
+    -smp "${SCARLET_SMP:-2}" \
+    -nographic \
+    -serial stdio \
+    -monitor none \
+    --no-reboot \
+    -global virtio-mmio.force-legacy=false \
+    -drive id=x0,file="$ROOTFS_PATH",format=raw,if=none \
+    -device virtio-blk-device,drive=x0,bus=virtio-mmio-bus.0 \
+    -initrd "$INITRAMFS_PATH" \
+    -kernel "$KERNEL_PATH" > "$TEMP_OUTPUT" < <(
+        sleep 10
+        printf 'xv6\n'
+        sleep 5
+        printf 'usertests -q\n'
+        sleep "$TEST_TIMEOUT"
+    ) &
+QEMU_PID=$!
+
+# usertests ends with one of two summary lines
+while kill -0 "$QEMU_PID" 2>/dev/null; do
+    if grep -q -e "ALL TESTS PASSED" -e "SOME TESTS FAILED" "$TEMP_OUTPUT"; then
+        kill "$QEMU_PID"
+        break
+    fi
+    sleep 5
+done
+wait "$QEMU_PID" 2>/dev/null
+cat "$TEMP_OUTPUT"
+
+if grep -q "ALL TESTS PASSED" "$TEMP_OUTPUT"; then
+    echo "xv6 usertests passed"
+    rm -f "$TEMP_OUTPUT"
+    exit 0
+else
+    echo "xv6 usertests failed"
+    rm -f "$TEMP_OUTPUT"
+    exit 1
+fi
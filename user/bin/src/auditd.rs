@@ -0,0 +1,58 @@
+#![no_std]
+#![no_main]
+
+extern crate scarlet_std as std;
+
+use std::println;
+use std::fs::{File, OpenOptions};
+use std::string::String;
+use std::vec::Vec;
+
+const AUDIT_DEVICE: &str = "/dev/audit";
+
+/// Audit daemon: copy the kernel audit records to a log file
+///
+/// usage: auditd [LOGFILE]
+///
+/// Without a log file the records are printed. The daemon runs until the
+/// device can no longer be read, e.g. when it is interrupted by a signal.
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    let args: Vec<String> = std::env::args().collect();
+
+    let mut device = match File::open(AUDIT_DEVICE) {
+        Ok(file) => file,
+        Err(_) => {
+            println!("auditd: cannot open {}", AUDIT_DEVICE);
+            return 1;
+        }
+    };
+    let mut log = match args.get(1) {
+        Some(path) => match OpenOptions::new().append(true).create(true).open(path) {
+            Ok(file) => Some(file),
+            Err(_) => {
+                println!("auditd: cannot open {}", path);
+                return 1;
+            }
+        },
+        None => None,
+    };
+
+    let mut buffer = [0u8; 4096];
+    loop {
+        // Blocks until the kernel queues a record
+        let n = match device.read(&mut buffer) {
+            Ok(0) | Err(_) => return 0,
+            Ok(n) => n,
+        };
+        match log.as_mut() {
+            Some(file) => {
+                if file.write_all(&buffer[..n]).is_err() {
+                    println!("auditd: write to log failed");
+                    return 1;
+                }
+            }
+            None => std::print!("{}", String::from_utf8_lossy(&buffer[..n])),
+        }
+    }
+}
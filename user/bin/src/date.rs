@@ -0,0 +1,131 @@
+#![no_std]
+#![no_main]
+
+extern crate scarlet_std as std;
+
+use std::println;
+use std::string::String;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::vec::Vec;
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+const SECONDS_PER_DAY: u64 = 86400;
+
+fn usage() -> i32 {
+    println!("usage: date [+%s] [-s @SECONDS | -s 'YYYY-MM-DD[ HH:MM[:SS]]']");
+    2
+}
+
+/// The civil date of `days` since 1970-01-01 (proleptic Gregorian)
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    (year, month, day)
+}
+
+/// Days since 1970-01-01 of a civil date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let day_of_year = (153 * month_index + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// `seconds` since the epoch as `Thu Oct 16 12:34:56 UTC 2026`
+fn format(seconds: u64) -> String {
+    let days = (seconds / SECONDS_PER_DAY) as i64;
+    let time = seconds % SECONDS_PER_DAY;
+    let (year, month, day) = civil_from_days(days);
+    std::format!(
+        "{} {} {:2} {:02}:{:02}:{:02} UTC {}",
+        WEEKDAYS[((days + 4) % 7) as usize],
+        MONTHS[month as usize - 1],
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60,
+        year
+    )
+}
+
+fn number<T: core::str::FromStr + PartialOrd>(text: &str, range: core::ops::RangeInclusive<T>) -> Option<T> {
+    text.parse().ok().filter(|value| range.contains(value))
+}
+
+/// Seconds since the epoch of `@SECONDS` or `YYYY-MM-DD[ HH:MM[:SS]]`
+/// (also with a `T` between date and time)
+fn parse(spec: &str) -> Option<u64> {
+    if let Some(seconds) = spec.strip_prefix('@') {
+        return seconds.parse().ok();
+    }
+    let (date, time) = spec.split_once([' ', 'T']).unwrap_or((spec, "00:00"));
+    let mut date = date.split('-');
+    let year: i64 = number(date.next()?, 1970..=9999)?;
+    let month: u32 = number(date.next()?, 1..=12)?;
+    let day: u32 = number(date.next()?, 1..=31)?;
+    let mut time = time.split(':');
+    let hour: u64 = number(time.next()?, 0..=23)?;
+    let minute: u64 = number(time.next()?, 0..=59)?;
+    let second: u64 = time.next().map_or(Some(0), |second| number(second, 0..=59))?;
+    if date.next().is_some() || time.next().is_some() {
+        return None;
+    }
+    // Rejects days past the end of the month
+    let days = days_from_civil(year, month, day);
+    if civil_from_days(days) != (year, month, day) {
+        return None;
+    }
+    Some(days as u64 * SECONDS_PER_DAY + hour * 3600 + minute * 60 + second)
+}
+
+/// Print or set the wall-clock time
+///
+/// usage: date [+%s] [-s @SECONDS | -s 'YYYY-MM-DD[ HH:MM[:SS]]']
+///
+/// Times are in UTC. `+%s` prints the seconds since the epoch; `-s` sets
+/// the clock, and the RTC with it, which takes a privileged user.
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    let args: Vec<String> = std::env::args().collect();
+    let args: Vec<&str> = args.iter().skip(1).map(|arg| arg.as_str()).collect();
+    let mut epoch_seconds = false;
+    let mut set = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match *arg {
+            "+%s" => epoch_seconds = true,
+            "-s" => match args.next().and_then(|spec| parse(spec)) {
+                Some(seconds) => set = Some(seconds),
+                None => return usage(),
+            },
+            _ => return usage(),
+        }
+    }
+
+    if let Some(seconds) = set {
+        let time = SystemTime::from_unix(Duration::from_secs(seconds));
+        if time.is_none_or(|time| time.set().is_err()) {
+            println!("date: cannot set the time (permission denied?)");
+            return 1;
+        }
+    }
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    if epoch_seconds {
+        println!("{}", seconds);
+    } else {
+        println!("{}", format(seconds));
+    }
+    0
+}
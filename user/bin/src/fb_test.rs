@@ -9,7 +9,7 @@
 extern crate scarlet_std as std;
 
 use std::println;
-use framebuffer::Framebuffer;
+use framebuffer::{FbRect, Framebuffer};
 
 #[unsafe(no_mangle)]
 fn main() -> i32 {
@@ -45,7 +45,7 @@ fn main() -> i32 {
         }
     };
     
-    let _fix_info = match framebuffer.get_fix_screen_info() {
+    let fix_info = match framebuffer.get_fix_screen_info() {
         Ok(fix_info) => {
             println!("Fixed Screen Info:");
             let id_str = core::str::from_utf8(&fix_info.id).unwrap_or("(invalid)");
@@ -193,7 +193,89 @@ fn main() -> i32 {
         return 1;
     }
     println!("Rectangles and gradient rectangles completed and flushed");
-    
+
+    // Test 7: Draw a checkerboard straight into the mapped memory
+    println!("Test 7: Drawing a checkerboard into the mapped framebuffer...");
+    let line_length = fix_info.line_length as usize;
+    let bytes_per_pixel = (var_info.bits_per_pixel / 8) as usize;
+    match framebuffer.map() {
+        Ok(pixels) => {
+            for y in 0..height as usize {
+                for x in 0..width as usize {
+                    let value = if (x / 32 + y / 32) % 2 == 0 { 0xff } else { 0x00 };
+                    let offset = y * line_length + x * bytes_per_pixel;
+                    pixels[offset..offset + bytes_per_pixel].fill(value);
+                }
+            }
+        }
+        Err(e) => {
+            println!("Failed to map framebuffer: {:?}", e);
+            return 1;
+        }
+    }
+
+    if let Err(e) = framebuffer.flush() {
+        println!("Failed to flush framebuffer: {:?}", e);
+        return 1;
+    }
+    println!("Checkerboard completed and flushed");
+
+    // Test 8: Update two small squares and flush only them
+    println!("Test 8: Flushing two changed rectangles...");
+    let rects = [FbRect::new(0, 0, 32, 32), FbRect::new(64, 64, 32, 32)];
+    for rect in &rects {
+        if let Err(e) = framebuffer.fill_rect(rect.x, rect.y, rect.width, rect.height, [0, 0, 255, 255]) {
+            println!("Failed to draw rectangle: {:?}", e);
+            return 1;
+        }
+    }
+    if let Err(e) = framebuffer.flush_rects(&rects) {
+        println!("Failed to flush rectangles: {:?}", e);
+        return 1;
+    }
+    println!("Rectangles flushed");
+
+    // Test 9: Move a bar across the screen, drawing each frame into the
+    // screen not shown and panning to it
+    if var_info.yres_virtual >= height * 2 {
+        println!("Test 9: Animating a bar with double buffering...");
+        for frame in 0..60usize {
+            let back = (frame % 2) as u32 * height;
+            let bar = frame * (width as usize - 32) / 59;
+            match framebuffer.map() {
+                Ok(pixels) => {
+                    for y in 0..height as usize {
+                        let line = (back as usize + y) * line_length;
+                        for x in 0..width as usize {
+                            let value = if x >= bar && x < bar + 32 { 0xff } else { 0x00 };
+                            let offset = line + x * bytes_per_pixel;
+                            pixels[offset..offset + bytes_per_pixel].fill(value);
+                        }
+                    }
+                }
+                Err(e) => {
+                    println!("Failed to map framebuffer: {:?}", e);
+                    return 1;
+                }
+            }
+            if let Err(e) = framebuffer.flush_rects(&[FbRect::new(0, back, width, height)]) {
+                println!("Failed to flush back buffer: {:?}", e);
+                return 1;
+            }
+            if let Err(e) = framebuffer.pan_display(back) {
+                println!("Failed to pan display: {:?}", e);
+                return 1;
+            }
+        }
+        if let Err(e) = framebuffer.pan_display(0) {
+            println!("Failed to pan display: {:?}", e);
+            return 1;
+        }
+        println!("Animation completed");
+    } else {
+        println!("Test 9: Skipped, the framebuffer holds a single screen");
+    }
+
     println!("All framebuffer tests completed successfully!");
     0
 }
\ No newline at end of file
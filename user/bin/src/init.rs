@@ -71,6 +71,30 @@ fn setup_devfs() -> Result<(), &'static str> {
     }
 }
 
+fn setup_procfs() -> Result<(), &'static str> {
+    let _ = create_directory("/proc"); // Create /proc directory if it doesn't exist
+
+    // Mount procfs at /proc
+    if mount("procfs", "/proc", "procfs", 0, None).is_ok() {
+        Ok(())
+    } else {
+        Err("Failed to mount procfs")
+    }
+}
+
+fn setup_cgroupfs() -> Result<(), &'static str> {
+    let _ = create_directory("/sys");
+    let _ = create_directory("/sys/fs");
+    let _ = create_directory("/sys/fs/cgroup");
+
+    // Mount cgroupfs at /sys/fs/cgroup
+    if mount("cgroupfs", "/sys/fs/cgroup", "cgroupfs", 0, None).is_ok() {
+        Ok(())
+    } else {
+        Err("Failed to mount cgroupfs")
+    }
+}
+
 fn check_block_devices() -> bool {
     println!("init: Checking for available block devices...");
     
@@ -344,6 +368,15 @@ fn main() -> i32 {
                     // Continue anyway, but devices might not be accessible
                 }
             }
+
+            match setup_procfs() {
+                Ok(_) => println!("init: Kernel information filesystem mounted at /proc"),
+                Err(e) => println!("init: Failed to setup proc filesystem: {}", e),
+            }
+            match setup_cgroupfs() {
+                Ok(_) => println!("init: Resource group filesystem mounted at /sys/fs/cgroup"),
+                Err(e) => println!("init: Failed to setup cgroup filesystem: {}", e),
+            }
             
             // Verify the new root by trying to access files
             println!("init: Current working directory after pivot_root");
@@ -0,0 +1,202 @@
+#![no_std]
+#![no_main]
+
+extern crate scarlet_std as std;
+
+use std::println;
+use std::netconfig::*;
+use std::string::String;
+use std::vec::Vec;
+
+fn usage() -> i32 {
+    println!("usage: ip link [show [dev NAME]]");
+    println!("       ip link set dev NAME [up|down] [master BRIDGE|nomaster] [netns PID]");
+    println!("       ip link add NAME type veth peer name PEER");
+    println!("       ip link add NAME type bridge|tap");
+    println!("       ip link del NAME");
+    println!("       ip addr [show [dev NAME]]");
+    println!("       ip addr add|del ADDRESS/LEN dev NAME");
+    println!("       ip route [show [dev NAME]]");
+    println!("       ip route add NETWORK/LEN|default [via GATEWAY] dev NAME [metric N]");
+    println!("       ip route del NETWORK/LEN|default [dev NAME]");
+    println!("       ip neigh [show [dev NAME]]");
+    println!("       ip neigh flush dev NAME");
+    1
+}
+
+/// The values of the `KEY VALUE` pairs of `args`
+struct Options<'a> {
+    args: &'a [&'a str],
+}
+
+impl<'a> Options<'a> {
+    fn get(&self, key: &str) -> Option<&'a str> {
+        self.args.iter().position(|arg| *arg == key).and_then(|i| self.args.get(i + 1).copied())
+    }
+
+    fn has(&self, word: &str) -> bool {
+        self.args.contains(&word)
+    }
+}
+
+/// `default` is the network of every address of IPv4
+fn network(text: &str) -> &str {
+    if text == "default" { "0.0.0.0/0" } else { text }
+}
+
+fn show(config: &mut NetConfig, object: &str, options: &Options) -> Result<(), NetConfigError> {
+    let dev = options.get("dev");
+    match object {
+        "link" => {
+            for link in config.list(GET_LINKS, dev)? {
+                let up = if link.integer(ATTR_UP) == Some(1) { "UP" } else { "DOWN" };
+                let mut name = String::from(link.text(ATTR_NAME).unwrap_or("?"));
+                if let Some(peer) = link.text(ATTR_PEER) {
+                    name += "@";
+                    name += peer;
+                }
+                let master = link.text(ATTR_MASTER).map_or(String::new(), |master| std::format!(" master {}", master));
+                println!(
+                    "{}: {}: <{}> mtu {}{} dropped {}",
+                    link.integer(ATTR_INDEX).unwrap_or(0) + 1,
+                    name,
+                    up,
+                    link.integer(ATTR_MTU).unwrap_or(0),
+                    master,
+                    link.integer(ATTR_DROPPED).unwrap_or(0)
+                );
+                if let Some(kind) = link.text(ATTR_KIND) {
+                    println!("    {}", kind);
+                }
+                if let Some(mac) = link.text(ATTR_MAC) {
+                    println!("    link/ether {}", mac);
+                }
+            }
+        }
+        "addr" => {
+            for address in config.list(GET_ADDRESSES, dev)? {
+                let family = if address.text(ATTR_ADDRESS).is_some_and(|text| text.contains(':')) { "inet6" } else { "inet" };
+                println!("{} {} dev {}", family, address.text(ATTR_ADDRESS).unwrap_or("?"), address.text(ATTR_NAME).unwrap_or("?"));
+            }
+        }
+        "route" => {
+            for route in config.list(GET_ROUTES, dev)? {
+                let mut line = String::from(match route.text(ATTR_ADDRESS) {
+                    Some("0.0.0.0/0") | Some("::/0") => "default",
+                    Some(destination) => destination,
+                    None => "?",
+                });
+                if let Some(gateway) = route.text(ATTR_GATEWAY) {
+                    line += " via ";
+                    line += gateway;
+                }
+                println!("{} dev {} metric {}", line, route.text(ATTR_NAME).unwrap_or("?"), route.integer(ATTR_METRIC).unwrap_or(0));
+            }
+        }
+        _ => {
+            for neighbor in config.list(GET_NEIGHBORS, dev)? {
+                let mac = neighbor.text(ATTR_MAC).map_or(String::from("INCOMPLETE"), |mac| std::format!("lladdr {}", mac));
+                println!("{} dev {} {}", neighbor.text(ATTR_ADDRESS).unwrap_or("?"), neighbor.text(ATTR_NAME).unwrap_or("?"), mac);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The request of `ip OBJECT COMMAND ARGS...`, `None` if it is not one
+fn request(object: &str, command: &str, args: &[&str]) -> Option<Request> {
+    let options = Options { args };
+    let dev = options.get("dev");
+    let request = match (object, command) {
+        ("link", "set") => {
+            let mut request = Request::new(SET_LINK).text(ATTR_NAME, dev?);
+            match (options.has("up"), options.has("down")) {
+                (true, false) => request = request.attribute(ATTR_UP, &[1]),
+                (false, true) => request = request.attribute(ATTR_UP, &[0]),
+                (false, false) => {}
+                _ => return None,
+            }
+            if let Some(master) = options.get("master") {
+                request = request.text(ATTR_MASTER, master);
+            } else if options.has("nomaster") {
+                request = request.text(ATTR_MASTER, "");
+            }
+            if let Some(pid) = options.get("netns") {
+                request = request.attribute(ATTR_NETNS_PID, &pid.parse::<u32>().ok()?.to_le_bytes());
+            }
+            request
+        }
+        ("link", "add") => {
+            let kind = options.get("type")?;
+            let request = Request::new(ADD_LINK).text(ATTR_NAME, args.first()?).text(ATTR_KIND, kind);
+            match kind {
+                "veth" => request.text(ATTR_PEER, options.get("name")?),
+                _ => request,
+            }
+        }
+        ("link", "del") | ("link", "delete") => Request::new(DELETE_LINK).text(ATTR_NAME, dev.or(args.first().copied())?),
+        ("addr", "add") | ("addr", "del") => {
+            let kind = if command == "add" { ADD_ADDRESS } else { DELETE_ADDRESS };
+            Request::new(kind).text(ATTR_NAME, dev?).text(ATTR_ADDRESS, args.first()?)
+        }
+        ("route", "add") => {
+            let mut request = Request::new(ADD_ROUTE).text(ATTR_NAME, dev?).text(ATTR_ADDRESS, network(args.first()?));
+            if let Some(gateway) = options.get("via") {
+                request = request.text(ATTR_GATEWAY, gateway);
+            }
+            if let Some(metric) = options.get("metric") {
+                request = request.attribute(ATTR_METRIC, &metric.parse::<u32>().ok()?.to_le_bytes());
+            }
+            request
+        }
+        ("route", "del") => {
+            let request = Request::new(DELETE_ROUTE).text(ATTR_ADDRESS, network(args.first()?));
+            match dev {
+                Some(dev) => request.text(ATTR_NAME, dev),
+                None => request,
+            }
+        }
+        ("neigh", "flush") => Request::new(FLUSH_NEIGHBORS).text(ATTR_NAME, dev?),
+        _ => return None,
+    };
+    Some(request)
+}
+
+/// Show and change the network configuration
+///
+/// usage: ip OBJECT [COMMAND [ARGS...]], see `usage`
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    let args: Vec<String> = std::env::args().collect();
+    let args: Vec<&str> = args.iter().map(|arg| arg.as_str()).collect();
+
+    let object = match args.get(1).copied() {
+        Some("link") => "link",
+        Some("addr") | Some("address") | Some("a") => "addr",
+        Some("route") | Some("r") => "route",
+        Some("neigh") | Some("neighbor") | Some("n") => "neigh",
+        _ => return usage(),
+    };
+    let mut config = match NetConfig::open() {
+        Ok(config) => config,
+        Err(err) => {
+            println!("ip: {}", err.as_str());
+            return 1;
+        }
+    };
+
+    let result = match args.get(2).copied() {
+        None | Some("show") | Some("list") => show(&mut config, object, &Options { args: &args[2..] }),
+        Some(command) => match request(object, command, &args[3..]) {
+            Some(request) => config.request(request).map(|_| ()),
+            None => return usage(),
+        },
+    };
+    match result {
+        Ok(()) => 0,
+        Err(err) => {
+            println!("ip: {}", err.as_str());
+            1
+        }
+    }
+}
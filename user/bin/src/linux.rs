@@ -0,0 +1,21 @@
+#![no_std]
+#![no_main]
+
+extern crate scarlet_std as std;
+
+use std::{println, task::execve_abi};
+
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    println!("Linux container");
+    println!("Preparing to execute busybox sh...");
+
+    let envp = ["PATH=/bin:/usr/bin", "HOME=/", "TERM=vt100"];
+    if execve_abi("/scarlet/system/linux-riscv64/bin/busybox", &["sh"], &envp, "linux-riscv64") != 0 {
+        println!("Failed to execve busybox");
+        return -1;
+    }
+
+    return 0;
+}
@@ -0,0 +1,291 @@
+#![no_std]
+#![no_main]
+
+extern crate scarlet_std as std;
+
+use std::handle::{poll, PollEntry, POLLIN};
+use std::net::*;
+use std::println;
+use std::string::String;
+use std::time::{Duration, Instant};
+use std::vec::Vec;
+
+const ICMP_ECHO_REPLY: u8 = 0;
+const ICMP_ECHO_REQUEST: u8 = 8;
+const ICMPV6_ECHO_REQUEST: u8 = 128;
+const ICMPV6_ECHO_REPLY: u8 = 129;
+
+const ICMP_HEADER_LEN: usize = 8;
+/// Largest payload sent, which keeps requests in one datagram
+const MAX_SIZE: usize = 65507 - ICMP_HEADER_LEN;
+
+fn usage() -> i32 {
+    println!("usage: ping [-c COUNT] [-i INTERVAL] [-W TIMEOUT] [-s SIZE] ADDRESS");
+    2
+}
+
+/// What the command line asks for
+struct Settings {
+    address: String,
+    count: u32,
+    interval: Duration,
+    /// How long to wait for the replies after the last request
+    timeout: Duration,
+    size: usize,
+}
+
+impl Settings {
+    fn parse(args: &[&str]) -> Option<Self> {
+        let mut settings = Settings {
+            address: String::new(),
+            count: 4,
+            interval: Duration::from_secs(1),
+            timeout: Duration::from_secs(2),
+            size: 56,
+        };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match *arg {
+                "-c" => settings.count = args.next()?.parse().ok().filter(|&count| count > 0)?,
+                "-i" => settings.interval = Duration::from_millis(milliseconds(args.next()?)?),
+                "-W" => settings.timeout = Duration::from_millis(milliseconds(args.next()?)?),
+                "-s" => settings.size = args.next()?.parse().ok().filter(|&size| size <= MAX_SIZE)?,
+                address if !address.starts_with('-') && settings.address.is_empty() => settings.address = address.into(),
+                _ => return None,
+            }
+        }
+        (!settings.address.is_empty()).then_some(settings)
+    }
+}
+
+/// Milliseconds in `text`, seconds with up to three decimals
+fn milliseconds(text: &str) -> Option<u64> {
+    let (seconds, fraction) = text.split_once('.').unwrap_or((text, ""));
+    if fraction.len() > 3 || !fraction.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    let mut millis: u64 = if fraction.is_empty() { 0 } else { fraction.parse().ok()? };
+    for _ in fraction.len()..3 {
+        millis *= 10;
+    }
+    seconds.parse::<u64>().ok()?.checked_mul(1000)?.checked_add(millis)
+}
+
+/// The Internet checksum of `bytes` (RFC 1071)
+fn checksum(bytes: &[u8]) -> u16 {
+    let mut sum: u32 = bytes
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)]) as u32)
+        .sum();
+    while sum > 0xffff {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
+
+/// `duration` in milliseconds with three decimals
+fn millis(duration: Duration) -> String {
+    let micros = duration.as_micros();
+    std::format!("{}.{:03}", micros / 1000, micros % 1000)
+}
+
+/// A reply read from the socket
+struct Reply {
+    sequence: u16,
+    len: usize,
+    /// Hop limit of the reply, known over IPv4
+    ttl: Option<u8>,
+}
+
+/// The echo reply with `identifier` in `bytes`: an IPv4 datagram, or an
+/// ICMPv6 message alone
+fn parse_reply(bytes: &[u8], v6: bool, identifier: u16) -> Option<Reply> {
+    let (message, ttl) = if v6 {
+        (bytes, None)
+    } else {
+        let header_len = (*bytes.first()? as usize & 0xf) * 4;
+        (bytes.get(header_len..)?, Some(*bytes.get(8)?))
+    };
+    let expected = if v6 { ICMPV6_ECHO_REPLY } else { ICMP_ECHO_REPLY };
+    if message.len() < ICMP_HEADER_LEN || message[0] != expected {
+        return None;
+    }
+    if u16::from_be_bytes([message[4], message[5]]) != identifier {
+        return None;
+    }
+    Some(Reply { sequence: u16::from_be_bytes([message[6], message[7]]), len: message.len(), ttl })
+}
+
+/// The address of the name a raw socket received from: `a.b.c.d:0` or
+/// `[v6]:0`
+fn host(name: &str) -> &str {
+    let address = name.rsplit_once(':').map_or(name, |(address, _)| address);
+    address.trim_start_matches('[').trim_end_matches(']')
+}
+
+/// Round trip times of the replies
+#[derive(Default)]
+struct Statistics {
+    received: u32,
+    min: u64,
+    max: u64,
+    sum: u64,
+    sum_squares: u128,
+}
+
+impl Statistics {
+    fn add(&mut self, rtt: Duration) {
+        let micros = rtt.as_micros() as u64;
+        self.min = if self.received == 0 { micros } else { self.min.min(micros) };
+        self.max = self.max.max(micros);
+        self.sum += micros;
+        self.sum_squares += micros as u128 * micros as u128;
+        self.received += 1;
+    }
+
+    /// Mean and mean deviation in microseconds
+    fn mean_and_deviation(&self) -> (u64, u64) {
+        let count = self.received.max(1) as u128;
+        let mean = self.sum as u128 / count;
+        let variance = (self.sum_squares / count).saturating_sub(mean * mean);
+        (mean as u64, isqrt(variance) as u64)
+    }
+}
+
+fn isqrt(value: u128) -> u128 {
+    if value < 2 {
+        return value;
+    }
+    let mut root = value;
+    let mut next = (root + value / root) / 2;
+    while next < root {
+        root = next;
+        next = (root + value / root) / 2;
+    }
+    root
+}
+
+/// Send echo requests to an address and report the replies
+///
+/// usage: ping [-c COUNT] [-i INTERVAL] [-W TIMEOUT] [-s SIZE] ADDRESS
+///
+/// COUNT requests (4 by default) are sent INTERVAL seconds apart, and
+/// replies are awaited TIMEOUT seconds after the last one. The exit status
+/// is 0 if a reply came back, 1 if none did and 2 on errors, so that a
+/// script can tell whether the network works end to end.
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    let args: Vec<String> = std::env::args().collect();
+    let args: Vec<&str> = args.iter().skip(1).map(|arg| arg.as_str()).collect();
+    let Some(settings) = Settings::parse(&args) else {
+        return usage();
+    };
+    let v6 = settings.address.contains(':');
+    let socket = match v6 {
+        true => Socket::new(DOMAIN_INET6, RAW, NONBLOCK, PROTOCOL_ICMPV6),
+        false => Socket::new(DOMAIN_INET, RAW, NONBLOCK, PROTOCOL_ICMP),
+    };
+    let socket = match socket {
+        Ok(socket) => socket,
+        Err(_) => {
+            println!("ping: cannot open a raw socket (permission denied?)");
+            return 2;
+        }
+    };
+
+    let identifier = std::task::getpid() as u16;
+    let request_type = if v6 { ICMPV6_ECHO_REQUEST } else { ICMP_ECHO_REQUEST };
+    let mut request = Vec::with_capacity(ICMP_HEADER_LEN + settings.size);
+    request.extend_from_slice(&[request_type, 0, 0, 0]);
+    request.extend_from_slice(&identifier.to_be_bytes());
+    request.extend_from_slice(&[0, 0]);
+    request.extend((0..settings.size).map(|index| index as u8));
+
+    println!("PING {} {} data bytes", settings.address, settings.size);
+    let mut sent_at: Vec<Option<Instant>> = Vec::new();
+    let mut answered: Vec<bool> = Vec::new();
+    let mut statistics = Statistics::default();
+    let mut buffer = std::vec![0u8; 65536];
+    let start = Instant::now_precise();
+
+    for sequence in 1..=settings.count {
+        request[6..8].copy_from_slice(&(sequence as u16).to_be_bytes());
+        request[2..4].copy_from_slice(&[0, 0]);
+        if !v6 {
+            // The kernel fills in the checksum of ICMPv6 alone
+            let sum = checksum(&request);
+            request[2..4].copy_from_slice(&sum.to_be_bytes());
+        }
+        let now = Instant::now_precise();
+        match socket.send_to(&request, Some(&settings.address)) {
+            Ok(_) => sent_at.push(Some(now)),
+            Err(_) => {
+                println!("ping: cannot send to {}", settings.address);
+                sent_at.push(None);
+            }
+        }
+        answered.push(false);
+
+        let last = sequence == settings.count;
+        let deadline = now.checked_add(if last { settings.timeout } else { settings.interval }).unwrap_or(now);
+        loop {
+            let now = Instant::now_precise();
+            if now >= deadline || (last && statistics.received == settings.count) {
+                break;
+            }
+            let wait = deadline.duration_since(now).as_millis().max(1) as usize;
+            let mut entries = [PollEntry::new(socket.handle(), POLLIN)];
+            if !poll(&mut entries, Some(wait)).is_ok_and(|ready| ready > 0) {
+                continue;
+            }
+            let Ok((len, from)) = socket.receive_from(&mut buffer) else {
+                continue;
+            };
+            let Some(reply) = parse_reply(&buffer[..len], v6, identifier) else {
+                continue;
+            };
+            let index = reply.sequence as usize;
+            let Some(Some(sent)) = index.checked_sub(1).and_then(|index| sent_at.get(index)) else {
+                continue;
+            };
+            let rtt = Instant::now_precise().duration_since(*sent);
+            let duplicate = core::mem::replace(&mut answered[index - 1], true);
+            let ttl = reply.ttl.map_or(String::new(), |ttl| std::format!(" ttl={}", ttl));
+            println!(
+                "{} bytes from {}: icmp_seq={}{} time={} ms{}",
+                reply.len,
+                host(&from),
+                reply.sequence,
+                ttl,
+                millis(rtt),
+                if duplicate { " (DUP!)" } else { "" }
+            );
+            if !duplicate {
+                statistics.add(rtt);
+            }
+        }
+    }
+
+    let transmitted = sent_at.iter().filter(|sent| sent.is_some()).count() as u32;
+    let loss = (transmitted - statistics.received.min(transmitted)) * 100 / transmitted.max(1);
+    println!("--- {} ping statistics ---", settings.address);
+    println!(
+        "{} packets transmitted, {} received, {}% packet loss, time {} ms",
+        transmitted,
+        statistics.received,
+        loss,
+        start.elapsed().as_millis()
+    );
+    if statistics.received == 0 {
+        return 1;
+    }
+    let (mean, deviation) = statistics.mean_and_deviation();
+    println!(
+        "rtt min/avg/max/mdev = {}/{}/{}/{} ms",
+        millis(Duration::from_micros(statistics.min)),
+        millis(Duration::from_micros(mean)),
+        millis(Duration::from_micros(statistics.max)),
+        millis(Duration::from_micros(deviation))
+    );
+    0
+}
@@ -3,7 +3,7 @@
 
 extern crate scarlet_std as std;
 
-use std::{format, print, println, string::String, vec::Vec, task::{execve, exit, fork, waitpid}};
+use std::{format, print, println, string::String, vec::Vec, task::{execve, exit, fork, waitpid, wexitstatus, wifsignaled, wtermsig}};
 use std::io::Read;
 
 /// Parse a command line into a program and arguments
@@ -121,7 +121,10 @@ fn execute_command(program: &str, args: &[String]) -> i32 {
         }
         pid => {
             let (_, status) = waitpid(pid, 0);
-            return status;
+            if wifsignaled(status) {
+                return 128 + wtermsig(status);
+            }
+            return wexitstatus(status);
         }
     }
 }
@@ -0,0 +1,142 @@
+#![no_std]
+#![no_main]
+
+extern crate scarlet_std as std;
+
+use std::{println, format};
+use std::fs::{File, OpenOptions};
+use std::string::String;
+use std::vec::Vec;
+use std::task::{execve, execve_abi, exit, fork, getpid, waitpid, wexitstatus, wifsignaled, wtermsig, WNOHANG};
+use std::thread::sleep;
+use std::time::Duration;
+
+const STRACE_PATH: &str = "/proc/strace";
+
+fn usage() -> i32 {
+    println!("usage: strace [-f] [-a ABI] PROGRAM [ARGS...]");
+    println!("  -f      also trace the tasks the program creates");
+    println!("  -a ABI  run the program with the given ABI (e.g. linux-riscv64)");
+    1
+}
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    let args: Vec<String> = std::env::args().collect();
+
+    let mut follow = false;
+    let mut abi: Option<String> = None;
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "-f" => follow = true,
+            "-a" => {
+                i += 1;
+                match args.get(i) {
+                    Some(name) => abi = Some(name.clone()),
+                    None => return usage(),
+                }
+            }
+            _ => break,
+        }
+        i += 1;
+    }
+    if i >= args.len() {
+        return usage();
+    }
+    let command: Vec<&str> = args[i..].iter().map(|s| s.as_str()).collect();
+
+    let pid = fork();
+    if pid < 0 {
+        println!("strace: fork failed");
+        return 1;
+    }
+    if pid == 0 {
+        // Start tracing ourselves so that the exec is the first call recorded
+        let verb = if follow { "follow" } else { "trace" };
+        if let Err(err) = write_control(&format!("{} {}", verb, getpid())) {
+            println!("strace: {}: {}", STRACE_PATH, err);
+            exit(1);
+        }
+        let envp: [&str; 0] = [];
+        let result = match &abi {
+            Some(abi) => execve_abi(command[0], &command, &envp, abi),
+            None => execve(command[0], &command, &envp),
+        };
+        if result != 0 {
+            println!("strace: {}: cannot execute", command[0]);
+        }
+        exit(127);
+    }
+
+    let mut next_seq = 0;
+    loop {
+        let (waited, status) = waitpid(pid, WNOHANG);
+        // Print what is left in the buffer once the program has exited
+        next_seq = print_records(pid as usize, follow, next_seq);
+        if waited == pid {
+            if wifsignaled(status) {
+                println!("+++ killed by signal {} +++", wtermsig(status));
+                return 128 + wtermsig(status);
+            }
+            println!("+++ exited with {} +++", wexitstatus(status));
+            return wexitstatus(status);
+        }
+        if waited < 0 {
+            println!("strace: waitpid failed");
+            return 1;
+        }
+        sleep(Duration::from_millis(10));
+    }
+}
+
+fn write_control(command: &str) -> Result<(), String> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .open(STRACE_PATH)
+        .map_err(|_| String::from("cannot open"))?;
+    file.write_all(command.as_bytes()).map_err(|_| String::from("command rejected"))
+}
+
+/// Print the records from `next_seq` on and return the sequence number to
+/// continue from
+///
+/// Without `follow` only the records of `pid` are printed; with it, those of
+/// every traced task, since the buffer does not tell who created whom.
+fn print_records(pid: usize, follow: bool, next_seq: u64) -> u64 {
+    let mut file = match File::open(STRACE_PATH) {
+        Ok(file) => file,
+        Err(_) => return next_seq,
+    };
+    let mut content = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while let Ok(n) = file.read(&mut buffer) {
+        if n == 0 {
+            break;
+        }
+        content.extend_from_slice(&buffer[..n]);
+    }
+    let content = String::from_utf8_lossy(&content);
+
+    let mut seen = next_seq;
+    for line in content.lines() {
+        // <seq> <task ID> <time in us> <call>
+        let mut fields = line.splitn(4, ' ');
+        let (Some(seq), Some(task_id), Some(_), Some(call)) = (fields.next(), fields.next(), fields.next(), fields.next()) else {
+            continue;
+        };
+        let (Ok(seq), Ok(task_id)) = (seq.parse::<u64>(), task_id.parse::<usize>()) else {
+            continue;
+        };
+        if seq < next_seq {
+            continue;
+        }
+        seen = seen.max(seq + 1);
+        if follow {
+            println!("[{}] {}", task_id, call);
+        } else if task_id == pid {
+            println!("{}", call);
+        }
+    }
+    seen
+}
@@ -24,8 +24,37 @@ pub mod commands {
     pub const FBIOPUT_VSCREENINFO: u32 = 0x4601;
     /// Get fixed screen information
     pub const FBIOGET_FSCREENINFO: u32 = 0x4602;
+    /// Pan the display to another part of the framebuffer
+    pub const FBIOPAN_DISPLAY: u32 = 0x4606;
     /// Flush framebuffer to display
     pub const FBIO_FLUSH: u32 = 0x4620;
+    /// Flush changed rectangles of the framebuffer to display
+    pub const FBIO_FLUSH_RECTS: u32 = 0x4621;
+}
+
+/// A rectangle of the framebuffer, in pixels
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FbRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl FbRect {
+    pub const fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self { x, y, width, height }
+    }
+}
+
+/// Changed rectangles of the framebuffer, as `FBIO_FLUSH_RECTS` takes them
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct FbDamage {
+    rects: u64,
+    count: u32,
+    reserved: u32,
 }
 
 /// Color bit field information
@@ -202,8 +231,14 @@ impl Default for FbFixScreenInfo {
 /// 
 /// Wraps a File handle to provide framebuffer-specific control operations.
 /// Uses memory mapping for efficient framebuffer access when available.
+/// The screen information is read when the device is opened and again
+/// after it is changed, so drawing does not ask the device each time.
 pub struct Framebuffer {
     file: File,
+    /// Variable screen information of the device
+    var_info: FbVarScreenInfo,
+    /// Fixed screen information of the device
+    fix_info: FbFixScreenInfo,
     /// Memory-mapped framebuffer buffer (address, size)
     mapped_buffer: Option<(usize, usize)>,
 }
@@ -219,11 +254,14 @@ impl Framebuffer {
     pub fn open(path: &str) -> HandleResult<Self> {
         let file = File::open(path).map_err(|_| HandleError::NotFound)?;
         
-        // Try to get framebuffer info for memory mapping
         let mut framebuffer = Self { 
             file, 
+            var_info: FbVarScreenInfo::default(),
+            fix_info: FbFixScreenInfo::default(),
             mapped_buffer: None 
         };
+        framebuffer.var_info = framebuffer.get_var_screen_info()?;
+        framebuffer.fix_info = framebuffer.get_fix_screen_info()?;
         
         // Attempt to set up memory mapping
         if let Err(_) = framebuffer.setup_mmap() {
@@ -236,34 +274,52 @@ impl Framebuffer {
     
     /// Attempt to set up memory mapping for the framebuffer
     fn setup_mmap(&mut self) -> HandleResult<()> {
-        // Get framebuffer information
-        let fix_info = self.get_fix_screen_info()?;
+        if self.mapped_buffer.is_some() {
+            return Ok(());
+        }
+        let size = self.fix_info.smem_len as usize;
         
         // Ensure we have valid framebuffer size
-        if fix_info.smem_len == 0 {
+        if size == 0 {
             return Err(HandleError::InvalidParameter);
         }
         
         // Try to map the framebuffer memory
         let handle = self.file.as_handle().as_raw() as u32;
-        match mmap(
+        let mapped_addr = mmap(
             handle,
             0,                                    // Let kernel choose address
-            fix_info.smem_len as usize,          // Map entire framebuffer
+            size,                                 // Map entire framebuffer
             prot::READ | prot::WRITE,            // Read/write permissions
             flags::SHARED,                       // Shared mapping
             0,                                   // Offset 0
-        ) {
-            Ok(mapped_addr) => {
-                self.mapped_buffer = Some((mapped_addr, fix_info.smem_len as usize));
-                Ok(())
-            }
-            Err(e) => {
-                // Debug output to understand why mmap failed
-                std::println!("mmap failed: handle={}, size={}, error={:?}", 
-                    handle, fix_info.smem_len, e);
-                Err(HandleError::SystemError(-1))
-            }
+        ).map_err(|_| HandleError::SystemError(-1))?;
+        self.mapped_buffer = Some((mapped_addr, size));
+        Ok(())
+    }
+
+    /// Map the framebuffer memory into this process
+    /// 
+    /// Pixels drawn into the returned slice are shown after the next
+    /// [`Framebuffer::flush`]. Line `y` starts at `y * line_length` of the
+    /// fixed screen information, and each pixel takes `bits_per_pixel / 8`
+    /// bytes in the layout the variable screen information describes.
+    /// The mapping is made on the first call and kept until the
+    /// framebuffer is dropped or its mode is changed.
+    /// 
+    /// # Returns
+    /// The pixel memory, or HandleError if the device cannot be mapped
+    pub fn map(&mut self) -> HandleResult<&mut [u8]> {
+        self.setup_mmap()?;
+        let (mapped_addr, mapped_size) = self.mapped_buffer.ok_or(HandleError::SystemError(-1))?;
+        // The mapping lives as long as it is borrowed from self
+        Ok(unsafe { core::slice::from_raw_parts_mut(mapped_addr as *mut u8, mapped_size) })
+    }
+
+    /// Remove the memory mapping, if any
+    fn unmap(&mut self) {
+        if let Some((mapped_addr, mapped_size)) = self.mapped_buffer.take() {
+            let _ = munmap(mapped_addr, mapped_size);
         }
     }
 
@@ -299,16 +355,25 @@ impl Framebuffer {
 
     /// Set variable screen information for the framebuffer device
     /// 
+    /// A mapping of the framebuffer memory is removed if the memory
+    /// changed size; [`Framebuffer::map`] maps it again.
+    /// 
     /// # Arguments
     /// * `var_info` - New variable screen information
     /// 
     /// # Returns
     /// Success or HandleError on failure
-    pub fn set_var_screen_info(&self, var_info: &FbVarScreenInfo) -> HandleResult<()> {
+    pub fn set_var_screen_info(&mut self, var_info: &FbVarScreenInfo) -> HandleResult<()> {
         self.file.as_handle().control(
             commands::FBIOPUT_VSCREENINFO,
             var_info as *const _ as usize,
         )?;
+        self.var_info = self.get_var_screen_info()?;
+        let fix_info = self.get_fix_screen_info()?;
+        if fix_info.smem_len != self.fix_info.smem_len {
+            self.unmap();
+        }
+        self.fix_info = fix_info;
         Ok(())
     }
 
@@ -323,6 +388,47 @@ impl Framebuffer {
         Ok(())
     }
 
+    /// Flush the changed rectangles `rects` of the framebuffer to display
+    /// 
+    /// Only these parts of the screen are sent to the display, which is
+    /// much cheaper than [`Framebuffer::flush`] for small changes.
+    /// 
+    /// # Returns
+    /// Success or HandleError on failure
+    pub fn flush_rects(&self, rects: &[FbRect]) -> HandleResult<()> {
+        let damage = FbDamage {
+            rects: rects.as_ptr() as u64,
+            count: rects.len() as u32,
+            reserved: 0,
+        };
+        self.file.as_handle().control(
+            commands::FBIO_FLUSH_RECTS,
+            &damage as *const _ as usize,
+        )?;
+        Ok(())
+    }
+
+    /// Show the framebuffer from line `yoffset` on
+    /// 
+    /// The framebuffer holds `yres_virtual` lines, which may be several
+    /// screens: drawing the next frame into the screen not shown and
+    /// panning to it shows each frame whole. The display changes at the
+    /// next vertical blank, which this waits for.
+    /// 
+    /// # Returns
+    /// Success or HandleError if `yoffset + yres` is past `yres_virtual`
+    pub fn pan_display(&mut self, yoffset: u32) -> HandleResult<()> {
+        let mut var_info = self.var_info;
+        var_info.xoffset = 0;
+        var_info.yoffset = yoffset;
+        self.file.as_handle().control(
+            commands::FBIOPAN_DISPLAY,
+            &var_info as *const _ as usize,
+        )?;
+        self.var_info.yoffset = yoffset;
+        Ok(())
+    }
+
     /// Get the underlying file
     /// 
     /// Provides access to the File for other operations
@@ -344,49 +450,52 @@ impl Framebuffer {
         self.mapped_buffer
     }
 
-    /// Write a single pixel to the framebuffer
-    /// 
-    /// # Arguments
-    /// * `x` - X coordinate
-    /// * `y` - Y coordinate  
-    /// * `color` - Pixel color [B, G, R, A]
-    /// 
-    /// # Returns
-    /// Success or HandleError on failure
-    pub fn write_pixel(&mut self, x: u32, y: u32, color: [u8; 4]) -> HandleResult<()> {
-        let var_info = self.get_var_screen_info()?;
-        let fix_info = self.get_fix_screen_info()?;
-        
-        let bytes_per_pixel = (var_info.bits_per_pixel / 8) as usize;
-        let line_length = fix_info.line_length as usize;
-        
-        // Calculate pixel offset
-        let offset = y as usize * line_length + x as usize * bytes_per_pixel;
-        
+    fn bytes_per_pixel(&self) -> usize {
+        (self.var_info.bits_per_pixel / 8) as usize
+    }
+
+    fn line_length(&self) -> usize {
+        self.fix_info.line_length as usize
+    }
+
+    /// Write `data` at byte `offset` of the framebuffer memory
+    fn write_at(&mut self, offset: usize, data: &[u8]) -> HandleResult<()> {
         if let Some((mapped_addr, mapped_size)) = self.mapped_buffer {
             // Use memory-mapped access for better performance
-            if offset + bytes_per_pixel > mapped_size {
+            if offset + data.len() > mapped_size {
                 return Err(HandleError::InvalidParameter);
             }
             
             unsafe {
-                let pixel_ptr = (mapped_addr + offset) as *mut u8;
-                let write_len = bytes_per_pixel.min(4);
-                core::ptr::copy_nonoverlapping(color.as_ptr(), pixel_ptr, write_len);
+                core::ptr::copy_nonoverlapping(data.as_ptr(), (mapped_addr + offset) as *mut u8, data.len());
             }
         } else {
             // Fallback to file I/O if mmap is not available
             self.file.seek(SeekFrom::Start(offset as u64))
                 .map_err(|_| HandleError::SystemError(-1))?;
-            
-            let write_len = bytes_per_pixel.min(4);
-            self.file.write(&color[..write_len])
+            self.file.write(data)
                 .map_err(|_| HandleError::SystemError(-1))?;
         }
-        
         Ok(())
     }
 
+    /// Write a single pixel to the framebuffer
+    /// 
+    /// # Arguments
+    /// * `x` - X coordinate
+    /// * `y` - Y coordinate  
+    /// * `color` - Pixel color [B, G, R, A]
+    /// 
+    /// # Returns
+    /// Success or HandleError on failure
+    pub fn write_pixel(&mut self, x: u32, y: u32, color: [u8; 4]) -> HandleResult<()> {
+        let bytes_per_pixel = self.bytes_per_pixel();
+        
+        // Calculate pixel offset
+        let offset = y as usize * self.line_length() + x as usize * bytes_per_pixel;
+        self.write_at(offset, &color[..bytes_per_pixel.min(4)])
+    }
+
     /// Write a horizontal line to the framebuffer
     /// 
     /// # Arguments
@@ -396,32 +505,9 @@ impl Framebuffer {
     /// # Returns
     /// Success or HandleError on failure
     pub fn write_line(&mut self, y: u32, data: &[u8]) -> HandleResult<()> {
-        let fix_info = self.get_fix_screen_info()?;
-        let line_length = fix_info.line_length as usize;
-        let offset = y as usize * line_length;
-        
-        if let Some((mapped_addr, mapped_size)) = self.mapped_buffer {
-            // Use memory-mapped access for better performance
-            let write_len = data.len().min(line_length);
-            if offset + write_len > mapped_size {
-                return Err(HandleError::InvalidParameter);
-            }
-            
-            unsafe {
-                let line_ptr = (mapped_addr + offset) as *mut u8;
-                core::ptr::copy_nonoverlapping(data.as_ptr(), line_ptr, write_len);
-            }
-        } else {
-            // Fallback to file I/O if mmap is not available
-            self.file.seek(SeekFrom::Start(offset as u64))
-                .map_err(|_| HandleError::SystemError(-1))?;
-            
-            let write_len = data.len().min(line_length);
-            self.file.write(&data[..write_len])
-                .map_err(|_| HandleError::SystemError(-1))?;
-        }
-        
-        Ok(())
+        let line_length = self.line_length();
+        let write_len = data.len().min(line_length);
+        self.write_at(y as usize * line_length, &data[..write_len])
     }
 
     /// Write a rectangular block of pixels to the framebuffer
@@ -438,58 +524,23 @@ impl Framebuffer {
     /// # Returns
     /// Success or HandleError on failure
     pub fn write_block(&mut self, x: u32, y: u32, width: u32, height: u32, data: &[u8]) -> HandleResult<()> {
-        let var_info = self.get_var_screen_info()?;
-        let fix_info = self.get_fix_screen_info()?;
-        
-        let bytes_per_pixel = (var_info.bits_per_pixel / 8) as usize;
-        let line_length = fix_info.line_length as usize;
-        let block_line_bytes = width as usize * bytes_per_pixel;
-        
-        if let Some((mapped_addr, mapped_size)) = self.mapped_buffer {
-            // Use memory-mapped access for better performance
-            // Write line by line
-            for row in 0..height {
-                let line_y = y + row;
-                let line_offset = line_y as usize * line_length + x as usize * bytes_per_pixel;
-                let data_offset = row as usize * block_line_bytes;
-                let data_end = data_offset + block_line_bytes;
-                
-                if line_offset + block_line_bytes > mapped_size || data_end > data.len() {
-                    continue; // Skip invalid lines
-                }
-                
-                unsafe {
-                    let line_ptr = (mapped_addr + line_offset) as *mut u8;
-                    core::ptr::copy_nonoverlapping(
-                        data[data_offset..data_end].as_ptr(),
-                        line_ptr,
-                        block_line_bytes
-                    );
-                }
-            }
-        } else {
-            // Fallback to file I/O if mmap is not available
-            for row in 0..height {
-                let line_y = y + row;
-                let line_offset = line_y as usize * line_length + x as usize * bytes_per_pixel;
-                let data_offset = row as usize * block_line_bytes;
-                
-                // Seek to start of this line in the block
-                self.file.seek(SeekFrom::Start(line_offset as u64))
-                    .map_err(|_| HandleError::SystemError(-1))?;
-                
-                // Write one line of the block
-                let data_end = data_offset + block_line_bytes;
-                if data_end <= data.len() {
-                    self.file.write(&data[data_offset..data_end])
-                        .map_err(|_| HandleError::SystemError(-1))?;
-                }
-            }
+        let block_line_bytes = width as usize * self.bytes_per_pixel();
+        for (row, line) in data.chunks_exact(block_line_bytes.max(1)).take(height as usize).enumerate() {
+            self.write_span(x, y + row as u32, line)?;
         }
-        
         Ok(())
     }
 
+    /// Write `line` at pixel `x` of line `y`, skipping it if it does not fit
+    fn write_span(&mut self, x: u32, y: u32, line: &[u8]) -> HandleResult<()> {
+        let offset = y as usize * self.line_length() + x as usize * self.bytes_per_pixel();
+        match self.write_at(offset, line) {
+            // Skip invalid lines
+            Err(HandleError::InvalidParameter) => Ok(()),
+            result => result,
+        }
+    }
+
     /// Fill the entire screen with a solid color
     /// 
     /// # Arguments
@@ -498,13 +549,12 @@ impl Framebuffer {
     /// # Returns
     /// Success or HandleError on failure
     pub fn fill_screen(&mut self, color: [u8; 4]) -> HandleResult<()> {
-        let var_info = self.get_var_screen_info()?;
-        let fix_info = self.get_fix_screen_info()?;
+        let var_info = self.var_info;
         
         let width = var_info.xres as usize;
         let height = var_info.yres as usize;
         let bytes_per_pixel = (var_info.bits_per_pixel / 8) as usize;
-        let line_length = fix_info.line_length as usize;
+        let line_length = self.line_length();
         
         // Create a line buffer filled with the color
         let mut line_buffer = vec![0u8; line_length];
@@ -538,7 +588,7 @@ impl Framebuffer {
     /// # Returns
     /// Success or HandleError on failure
     pub fn fill_rect(&mut self, x: u32, y: u32, width: u32, height: u32, color: [u8; 4]) -> HandleResult<()> {
-        let var_info = self.get_var_screen_info()?;
+        let var_info = self.var_info;
         let bytes_per_pixel = (var_info.bits_per_pixel / 8) as usize;
         
         // Create a line buffer for the rectangle width
@@ -554,8 +604,11 @@ impl Framebuffer {
             }
         }
         
-        // Use write_block for efficiency
-        self.write_block(x, y, width, height, &line_buffer)
+        // Write the same line to all rows
+        for row in 0..height {
+            self.write_span(x, y + row, &line_buffer)?;
+        }
+        Ok(())
     }
 
     /// Create a horizontal gradient with specified colors
@@ -567,7 +620,7 @@ impl Framebuffer {
     /// # Returns
     /// Success or HandleError on failure
     pub fn draw_horizontal_gradient(&mut self, start_color: [u8; 4], end_color: [u8; 4]) -> HandleResult<()> {
-        let var_info = self.get_var_screen_info()?;
+        let var_info = self.var_info;
         let width = var_info.xres as usize;
         let height = var_info.yres as usize;
         let bytes_per_pixel = (var_info.bits_per_pixel / 8) as usize;
@@ -611,7 +664,7 @@ impl Framebuffer {
     /// # Returns
     /// Success or HandleError on failure
     pub fn draw_vertical_gradient(&mut self, start_color: [u8; 4], end_color: [u8; 4]) -> HandleResult<()> {
-        let var_info = self.get_var_screen_info()?;
+        let var_info = self.var_info;
         let width = var_info.xres as usize;
         let height = var_info.yres as usize;
         let bytes_per_pixel = (var_info.bits_per_pixel / 8) as usize;
@@ -659,7 +712,7 @@ impl Framebuffer {
     /// Success or HandleError on failure
     pub fn draw_gradient_rect(&mut self, x: u32, y: u32, width: u32, height: u32, 
                              start_color: [u8; 4], end_color: [u8; 4], horizontal: bool) -> HandleResult<()> {
-        let var_info = self.get_var_screen_info()?;
+        let var_info = self.var_info;
         let bytes_per_pixel = (var_info.bits_per_pixel / 8) as usize;
         
         if horizontal {
@@ -684,7 +737,10 @@ impl Framebuffer {
             }
             
             // Write the same line to all rows
-            self.write_block(x, y, width, height, &line_buffer)
+            for row in 0..height {
+                self.write_span(x, y + row, &line_buffer)?;
+            }
+            Ok(())
         } else {
             // Vertical gradient: create each line individually
             for py in 0..height {
@@ -708,8 +764,6 @@ impl Framebuffer {
 impl Drop for Framebuffer {
     fn drop(&mut self) {
         // Clean up memory mapping if it exists
-        if let Some((mapped_addr, mapped_size)) = self.mapped_buffer {
-            let _ = munmap(mapped_addr, mapped_size);
-        }
+        self.unmap();
     }
 }
@@ -3,6 +3,9 @@ use core::alloc::{GlobalAlloc, Layout};
 use core::cell::UnsafeCell;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
+/// Granularity in which the heap grows (one page)
+const HEAP_GROWTH_STEP: usize = 4096;
+
 /// Free-list memory allocator
 #[global_allocator]
 pub static ALLOCATOR: FreeListAllocator = FreeListAllocator::new();
@@ -31,31 +34,47 @@ impl FreeListAllocator {
     }
 
     unsafe fn init(&self) {
-        let initial_size = 4096;
-        let start = sbrk(initial_size);
-        if start == usize::MAX {
+        let brk = sbrk(0);
+        if brk == usize::MAX {
             panic!("Failed to initialize heap");
         }
+        // The heap starts empty at the program break and grows on demand
+        let start = (brk + 15) & !15;
         self.heap_start.store(start, Ordering::SeqCst);
-        self.heap_end.store(start + initial_size, Ordering::SeqCst);
+        self.heap_end.store(start, Ordering::SeqCst);
         unsafe { *self.head.get() = core::ptr::null_mut(); }
     }
 
-    fn extend_heap(&self, size: usize) -> *mut u8 {
-        let aligned_size = (size + 15) & !15;
-        let new_block_addr = sbrk(aligned_size);
-        if new_block_addr == usize::MAX {
-            return core::ptr::null_mut();
+    /// Grow the heap by at least `size` bytes by moving the program break
+    ///
+    /// The heap grows in whole pages. If the free block at the top of the
+    /// heap ends at the old break, the new space is merged into it so that
+    /// a large allocation can use both.
+    fn extend_heap(&self, size: usize) -> bool {
+        let Some(grow) = size.checked_next_multiple_of(HEAP_GROWTH_STEP) else { return false };
+        let old_end = self.heap_end.load(Ordering::SeqCst);
+        let Some(new_end) = old_end.checked_add(grow) else { return false };
+        // brk returns the new break on success
+        if brk(new_end) != new_end {
+            return false;
         }
-        self.heap_end.fetch_add(aligned_size, Ordering::SeqCst);
-        // Add as a new free block to the list
+        self.heap_end.store(new_end, Ordering::SeqCst);
         unsafe {
-            let block = new_block_addr as *mut FreeBlock;
-            (*block).size = aligned_size;
+            let mut curr = *self.head.get();
+            while !curr.is_null() {
+                if curr as usize + (*curr).size == old_end {
+                    (*curr).size += grow;
+                    return true;
+                }
+                curr = (*curr).next;
+            }
+            // Add as a new free block to the list
+            let block = old_end as *mut FreeBlock;
+            (*block).size = grow;
             (*block).next = *self.head.get();
             *self.head.get() = block;
-            block as *mut u8
         }
+        true
     }
 
     unsafe fn find_fit(&self, size: usize, align: usize) -> (*mut FreeBlock, *mut FreeBlock) {
@@ -86,7 +105,9 @@ unsafe impl GlobalAlloc for FreeListAllocator {
         let (mut prev, mut curr) = unsafe { self.find_fit(size, align) };
         if curr.is_null() {
             // Extend and try again
-            self.extend_heap(size.max(4096));
+            if !self.extend_heap(size + align) {
+                return core::ptr::null_mut();
+            }
             let (p, c) = unsafe { self.find_fit(size, align) };
             prev = p;
             curr = c;
@@ -1,6 +1,6 @@
 use core::arch::{asm, naked_asm};
 
-use crate::{syscall::Syscall, task::exit, env};
+use crate::{syscall::Syscall, task::exit, env, vdso};
 
 #[unsafe(link_section = ".init")]
 #[unsafe(export_name = "_entry")]
@@ -23,11 +23,12 @@ unsafe extern "Rust" {
 
 #[unsafe(link_section = ".init")]
 #[unsafe(export_name = "_start")]
-pub fn _start(a0: usize, a1: usize) -> ! {
+pub fn _start(a0: usize, a1: usize, a2: usize) -> ! {
     // Get argc and argv from RISC-V calling convention registers
-    // a0 = argc, a1 = argv (set by kernel's ScarletAbi)
+    // a0 = argc, a1 = argv, a2 = vDSO base (set by kernel's ScarletAbi)
     let argc = a0;
     let argv = a1 as *const *const u8;
+    vdso::init(a2);
 
     unsafe {
         // Calculate envp from stack layout:
@@ -160,4 +161,50 @@ pub fn arch_syscall6(syscall: Syscall, arg1: usize, arg2: usize, arg3: usize, ar
         );
     }
     ret
-}
\ No newline at end of file
+}
+/// Create a thread with `clone`, running `entry(arg)` on `stack`
+///
+/// The child never returns into the caller's frames, which live on the
+/// parent's stack: it calls `entry` right after the system call and exits
+/// with its return value.
+///
+/// # Safety
+/// `stack` must be the top of a stack reserved for the thread, and `flags`
+/// must make the child share the address space.
+pub unsafe fn arch_clone_thread(flags: usize, stack: usize, tls: usize, entry: extern "C" fn(usize) -> i32, arg: usize) -> usize {
+    let mut ret;
+    unsafe {
+        asm!(
+            "ecall",
+            "bnez a0, 1f",
+            // Child: sp already points to the new stack
+            "mv a0, {arg}",
+            "jalr {entry}",
+            "li a7, {exit}",
+            "ecall",
+            "1:",
+            entry = in(reg) entry,
+            arg = in(reg) arg,
+            exit = const Syscall::Exit as usize,
+            in("a7") Syscall::Clone as usize,
+            inlateout("a0") flags => ret,
+            in("a1") stack,
+            in("a2") tls,
+            clobber_abi("C"),
+        );
+    }
+    ret
+}
+
+/// Return address given to signal handlers
+///
+/// The kernel leaves the stack pointer at the signal frame when the handler
+/// returns here, and `sigreturn` restores the interrupted state from it.
+#[unsafe(naked)]
+pub extern "C" fn arch_signal_restorer() -> ! {
+    naked_asm!(
+        "li a7, {sigreturn}",
+        "ecall",
+        sigreturn = const Syscall::SigReturn as usize,
+    )
+}
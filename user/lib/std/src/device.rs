@@ -0,0 +1,123 @@
+//! Device events
+//!
+//! [`DeviceEvents`] is a channel of the devices the kernel adds, removes and
+//! changes, for a daemon that creates device nodes and mounts disks as they
+//! come and go. A new channel first reports every device present as added.
+//! [`DeviceEvents::next`] waits for the next event; the channel can also be
+//! waited on together with other handles through its [`handle`](DeviceEvents::handle).
+
+use crate::handle::capability::StreamError;
+use crate::handle::{poll, Handle, HandleError, PollEntry, POLLIN};
+use crate::syscall::{syscall0, Syscall};
+
+/// Bytes of a record read from the channel
+pub const RECORD_SIZE: usize = 64;
+/// Bytes of the name of a device in a record
+pub const NAME_LEN: usize = 48;
+
+/// What happened to a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceAction {
+    Added,
+    Removed,
+    Changed,
+    /// Events were lost; open a new channel to see the devices again
+    Dropped,
+}
+
+/// Kind of a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceClass {
+    Block,
+    Char,
+    Network,
+    Graphics,
+    Generic,
+}
+
+/// An event of the channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceEvent {
+    pub action: DeviceAction,
+    pub class: DeviceClass,
+    /// Id of the device in the kernel
+    pub id: u64,
+    name: [u8; NAME_LEN],
+}
+
+impl DeviceEvent {
+    fn parse(bytes: &[u8; RECORD_SIZE]) -> Option<Self> {
+        let field = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        let action = match field(0) {
+            1 => DeviceAction::Added,
+            2 => DeviceAction::Removed,
+            3 => DeviceAction::Changed,
+            4 => DeviceAction::Dropped,
+            _ => return None,
+        };
+        let class = match field(4) {
+            1 => DeviceClass::Block,
+            2 => DeviceClass::Char,
+            3 => DeviceClass::Network,
+            4 => DeviceClass::Graphics,
+            _ => DeviceClass::Generic,
+        };
+        let mut name = [0u8; NAME_LEN];
+        name.copy_from_slice(&bytes[16..]);
+        Some(Self { action, class, id: u64::from_le_bytes(bytes[8..16].try_into().unwrap()), name })
+    }
+
+    /// Path of the node of the device below `/dev`, empty if it has none
+    pub fn name(&self) -> &str {
+        let len = self.name.iter().position(|&byte| byte == 0).unwrap_or(NAME_LEN);
+        core::str::from_utf8(&self.name[..len]).unwrap_or("")
+    }
+}
+
+/// Errors of the device event channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceEventsError {
+    /// The channel could not be opened or waited on
+    Handle(HandleError),
+    /// The channel could not be read
+    Stream(StreamError),
+    /// A record was not understood
+    Malformed,
+}
+
+/// A channel of the device events
+pub struct DeviceEvents {
+    handle: Handle,
+}
+
+impl DeviceEvents {
+    pub fn open() -> Result<Self, DeviceEventsError> {
+        let raw = HandleError::from_syscall_result(syscall0(Syscall::DeviceEventsOpen)).map_err(DeviceEventsError::Handle)?;
+        Ok(Self { handle: unsafe { Handle::from_raw(raw) } })
+    }
+
+    pub fn handle(&self) -> &Handle {
+        &self.handle
+    }
+
+    /// The next pending event, without waiting
+    pub fn try_next(&self) -> Result<Option<DeviceEvent>, DeviceEventsError> {
+        let stream = self.handle.as_stream().map_err(DeviceEventsError::Handle)?;
+        let mut record = [0u8; RECORD_SIZE];
+        match stream.read(&mut record).map_err(DeviceEventsError::Stream)? {
+            0 => Ok(None),
+            _ => DeviceEvent::parse(&record).map(Some).ok_or(DeviceEventsError::Malformed),
+        }
+    }
+
+    /// The next event, waiting for one
+    pub fn next(&self) -> Result<DeviceEvent, DeviceEventsError> {
+        loop {
+            if let Some(event) = self.try_next()? {
+                return Ok(event);
+            }
+            let mut entries = [PollEntry::new(&self.handle, POLLIN)];
+            poll(&mut entries, None).map_err(DeviceEventsError::Handle)?;
+        }
+    }
+}
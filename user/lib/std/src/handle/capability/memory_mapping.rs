@@ -3,7 +3,7 @@
 //! This module provides memory mapping functionality for handles that support
 //! memory mapping operations.
 
-use crate::syscall::{syscall6, syscall2, Syscall};
+use crate::syscall::{syscall6, syscall3, syscall2, syscall1, Syscall};
 
 /// Memory mapping protection flags (PROT_*)
 pub mod prot {
@@ -29,6 +29,24 @@ pub mod flags {
     pub const ANONYMOUS: usize = 0x20;
 }
 
+/// Memory advice (MADV_*)
+pub mod advice {
+    /// The pages will be used soon
+    pub const WILLNEED: usize = 3;
+    /// The contents are no longer needed; the pages read as zero afterwards
+    pub const DONTNEED: usize = 4;
+    /// The contents are no longer needed; the pages may be freed lazily
+    pub const FREE: usize = 8;
+}
+
+/// Shared memory open flags (O_*)
+pub mod shm_flags {
+    /// Create the object if it does not exist
+    pub const CREAT: usize = 0x40;
+    /// Fail if the object already exists
+    pub const EXCL: usize = 0x80;
+}
+
 /// Memory map a handle into the current process's address space
 ///
 /// # Arguments
@@ -61,9 +79,11 @@ pub fn mmap(handle: u32, addr: usize, length: usize, prot: usize, flags: usize,
 
 /// Unmap a memory region from the current process's address space
 ///
+/// Any page-aligned range may be unmapped, including parts of a mapping.
+///
 /// # Arguments
-/// * `addr` - Virtual address of the mapping to unmap
-/// * `length` - Length of the mapping to unmap
+/// * `addr` - Start address of the range to unmap
+/// * `length` - Length of the range to unmap
 ///
 /// # Returns
 /// * `Ok(())` - Unmapping successful
@@ -83,4 +103,117 @@ pub fn munmap(addr: usize, length: usize) -> Result<(), ()> {
     } else {
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Change the protection of a memory region
+///
+/// # Arguments
+/// * `addr` - Start address of the region (must be page aligned)
+/// * `length` - Length of the region in bytes
+/// * `prot` - New protection flags (combination of prot::* constants)
+///
+/// # Returns
+/// * `Ok(())` - Protection changed
+/// * `Err(())` - The region is invalid or not entirely mapped
+///
+/// # Examples
+/// ```no_run
+/// use scarlet_std::handle::capability::memory_mapping::{mprotect, prot};
+/// 
+/// // Make a region read-only
+/// mprotect(mapped_addr, 4096, prot::READ)?;
+/// ```
+pub fn mprotect(addr: usize, length: usize, prot: usize) -> Result<(), ()> {
+    let result = syscall3(Syscall::MemoryProtect, addr, length, prot);
+    if result == usize::MAX {
+        Err(())
+    } else {
+        Ok(())
+    }
+}
+
+/// Give the kernel advice about the use of a memory region
+///
+/// `advice::DONTNEED` and `advice::FREE` give anonymous memory back to the
+/// kernel: with `DONTNEED` it is freed right away, with `FREE` only when
+/// the kernel runs short of memory and the pages were not used again.
+///
+/// # Arguments
+/// * `addr` - Start address of the region (must be page aligned)
+/// * `length` - Length of the region in bytes
+/// * `advice` - One of the advice::* constants
+///
+/// # Returns
+/// * `Ok(())` - Advice applied
+/// * `Err(())` - The advice is unknown or the region is invalid or not entirely mapped
+///
+/// # Examples
+/// ```no_run
+/// use scarlet_std::handle::capability::memory_mapping::{madvise, advice};
+/// 
+/// // Release the pages of a buffer that is no longer used
+/// madvise(buffer_addr, 16 * 4096, advice::DONTNEED)?;
+/// ```
+pub fn madvise(addr: usize, length: usize, advice: usize) -> Result<(), ()> {
+    let result = syscall3(Syscall::MemoryAdvise, addr, length, advice);
+    if result == usize::MAX {
+        Err(())
+    } else {
+        Ok(())
+    }
+}
+
+/// Create or open a shared memory object
+///
+/// The returned handle can be mapped with `mmap` and `flags::SHARED`; every
+/// task that maps the same object sees the same memory.
+///
+/// # Arguments
+/// * `name` - Name of the object, or `None` for an anonymous object
+/// * `size` - Size in bytes when the object is created
+/// * `flags` - Combination of shm_flags::* constants
+///
+/// # Returns
+/// * `Ok(handle)` - Handle to the shared memory object
+/// * `Err(())` - The object could not be created or opened
+///
+/// # Examples
+/// ```no_run
+/// use scarlet_std::handle::capability::memory_mapping::{shm_open, shm_flags, mmap, prot, flags};
+///
+/// let handle = shm_open(Some("shared_buffer"), 4096, shm_flags::CREAT)?;
+/// let addr = mmap(handle, 0, 4096, prot::READ | prot::WRITE, flags::SHARED, 0)?;
+/// ```
+pub fn shm_open(name: Option<&str>, size: usize, flags: usize) -> Result<u32, ()> {
+    let name_bytes = match name {
+        Some(name) => Some(crate::ffi::str_to_cstr_bytes(name)?),
+        None => None,
+    };
+    let name_ptr = name_bytes.as_ref().map_or(0, |bytes| bytes.as_ptr() as usize);
+    let result = syscall3(Syscall::ShmOpen, name_ptr, size, flags);
+    if result == usize::MAX {
+        Err(())
+    } else {
+        Ok(result as u32)
+    }
+}
+
+/// Remove the name of a shared memory object
+///
+/// Handles and mappings that already refer to the object stay valid.
+///
+/// # Arguments
+/// * `name` - Name of the object
+///
+/// # Returns
+/// * `Ok(())` - The name was removed
+/// * `Err(())` - No object with this name exists
+pub fn shm_unlink(name: &str) -> Result<(), ()> {
+    let name_bytes = crate::ffi::str_to_cstr_bytes(name)?;
+    let result = syscall1(Syscall::ShmUnlink, name_bytes.as_ptr() as usize);
+    if result == usize::MAX {
+        Err(())
+    } else {
+        Ok(())
+    }
+}
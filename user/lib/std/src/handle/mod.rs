@@ -169,6 +169,54 @@ impl Handle {
     }
 }
 
+/// Data can be read
+pub const POLLIN: u16 = 0x001;
+/// Data can be written
+pub const POLLOUT: u16 = 0x004;
+/// An error occurred
+pub const POLLERR: u16 = 0x008;
+/// The peer hung up
+pub const POLLHUP: u16 = 0x010;
+/// The handle is not open
+pub const POLLNVAL: u16 = 0x020;
+
+/// One handle waited on by [`poll`]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PollEntry {
+    /// Raw handle; entries with a negative handle are ignored
+    pub handle: i32,
+    /// Events asked for
+    pub events: u16,
+    /// Events reported
+    pub revents: u16,
+}
+
+impl PollEntry {
+    /// Ask for `events` of `handle`
+    pub fn new(handle: &Handle, events: u16) -> Self {
+        Self { handle: handle.as_raw(), events, revents: 0 }
+    }
+}
+
+/// Wait until one of `entries` has an event
+///
+/// # Arguments
+/// * `entries` - Handles and events; their `revents` are filled in
+/// * `timeout_ms` - Give up after this many milliseconds, `None` to wait forever
+///
+/// # Returns
+/// The number of entries with events, 0 if the timeout expired
+pub fn poll(entries: &mut [PollEntry], timeout_ms: Option<usize>) -> HandleResult<usize> {
+    let result = syscall3(
+        Syscall::HandlePoll,
+        entries.as_mut_ptr() as usize,
+        entries.len(),
+        timeout_ms.unwrap_or(usize::MAX),
+    );
+    HandleError::from_syscall_result(result).map(|ready| ready as usize)
+}
+
 impl Drop for Handle {
     fn drop(&mut self) {
         // Automatically close the handle when it goes out of scope
@@ -45,7 +45,6 @@ mod core_exports {
     pub use core::ptr;
     pub use core::range;
     pub use core::result;
-    pub use core::time;
     pub use core::u8;
     pub use core::u16;
     pub use core::u32;
@@ -71,14 +70,23 @@ mod alloc_exports {
 
 mod arch;
 mod allocator;
+mod vdso;
 pub mod syscall;
 pub mod io;
 pub mod fs;
 pub mod task;
 pub mod thread;
+pub mod sync;
+pub mod signal;
+pub mod tty;
 pub mod ffi;
 pub mod env;
 pub mod handle;
+pub mod time;
+pub mod netconfig;
+pub mod device;
+pub mod net;
+pub mod random;
 
 /// Debug/profiler utilities
 pub mod profiler {
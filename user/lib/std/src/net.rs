@@ -0,0 +1,164 @@
+//! Sockets
+//!
+//! [`Socket`] owns a socket of the kernel. Local sockets are named in a
+//! namespace of the kernel, internet sockets by their `a.b.c.d:port` or
+//! `[v6]:port` endpoint, raw internet sockets by an address and packet
+//! sockets by an interface. Once connected, a socket is also a stream
+//! ([`Handle::as_stream`]), and it can be waited on with
+//! [`poll`](crate::handle::poll).
+
+use crate::ffi::str_to_cstr_bytes;
+use crate::handle::{Handle, HandleError, HandleResult};
+use crate::string::String;
+use crate::syscall::{syscall1, syscall2, syscall3, syscall4, syscall6, Syscall};
+use crate::vec::Vec;
+
+/// Longest name of a socket
+pub const NAME_MAX: usize = 255;
+
+/// Domains
+pub const DOMAIN_LOCAL: usize = 0;
+pub const DOMAIN_INET: usize = 1;
+pub const DOMAIN_INET6: usize = 2;
+pub const DOMAIN_PACKET: usize = 3;
+
+/// Types
+pub const STREAM: usize = 1;
+pub const DATAGRAM: usize = 2;
+pub const RAW: usize = 3;
+/// Operations fail instead of waiting (creation flag)
+pub const NONBLOCK: usize = 0x1;
+
+/// IP protocols of raw internet sockets
+pub const PROTOCOL_ICMP: usize = 1;
+pub const PROTOCOL_ICMPV6: usize = 58;
+
+/// Directions of [`Socket::shutdown`]
+pub const SHUT_READ: usize = 0;
+pub const SHUT_WRITE: usize = 1;
+pub const SHUT_BOTH: usize = 2;
+
+/// Options read by [`Socket::get_option`]
+pub const OPT_TYPE: usize = 0;
+pub const OPT_DOMAIN: usize = 1;
+pub const OPT_ERROR: usize = 2;
+pub const OPT_BUFFER_SIZE: usize = 3;
+pub const OPT_CONNECTED: usize = 4;
+/// Option set by [`Socket::set_filter`]
+pub const OPT_FILTER: usize = 5;
+/// Options of TCP sockets, read and set
+pub const OPT_REUSE_ADDRESS: usize = 6;
+pub const OPT_NO_DELAY: usize = 7;
+pub const OPT_RECEIVE_BUFFER: usize = 8;
+pub const OPT_SEND_BUFFER: usize = 9;
+pub const OPT_KEEPALIVE: usize = 10;
+pub const OPT_KEEPALIVE_IDLE: usize = 11;
+pub const OPT_KEEPALIVE_INTERVAL: usize = 12;
+pub const OPT_KEEPALIVE_COUNT: usize = 13;
+
+/// A socket
+#[derive(Debug)]
+pub struct Socket {
+    handle: Handle,
+}
+
+fn name_bytes(name: &str) -> HandleResult<Vec<u8>> {
+    str_to_cstr_bytes(name).map_err(|_| HandleError::InvalidParameter)
+}
+
+fn unit(result: usize) -> HandleResult<()> {
+    HandleError::from_syscall_result(result).map(|_| ())
+}
+
+impl Socket {
+    /// A new socket of `domain` and `kind`; `protocol` is the IP protocol
+    /// of a raw internet socket, the EtherType of a packet socket, else 0
+    pub fn new(domain: usize, kind: usize, flags: usize, protocol: usize) -> HandleResult<Self> {
+        let raw = HandleError::from_syscall_result(syscall4(Syscall::SocketCreate, domain, kind, flags, protocol))?;
+        Ok(Self { handle: unsafe { Handle::from_raw(raw) } })
+    }
+
+    pub fn handle(&self) -> &Handle {
+        &self.handle
+    }
+
+    pub fn bind(&self, name: &str) -> HandleResult<()> {
+        let name = name_bytes(name)?;
+        unit(syscall2(Syscall::SocketBind, self.handle.as_raw() as usize, name.as_ptr() as usize))
+    }
+
+    pub fn listen(&self, backlog: usize) -> HandleResult<()> {
+        unit(syscall2(Syscall::SocketListen, self.handle.as_raw() as usize, backlog))
+    }
+
+    pub fn connect(&self, name: &str) -> HandleResult<()> {
+        let name = name_bytes(name)?;
+        unit(syscall2(Syscall::SocketConnect, self.handle.as_raw() as usize, name.as_ptr() as usize))
+    }
+
+    pub fn accept(&self) -> HandleResult<Socket> {
+        let raw = HandleError::from_syscall_result(syscall1(Syscall::SocketAccept, self.handle.as_raw() as usize))?;
+        Ok(Self { handle: unsafe { Handle::from_raw(raw) } })
+    }
+
+    /// Send `data` to the peer, or to the socket named `to`
+    pub fn send_to(&self, data: &[u8], to: Option<&str>) -> HandleResult<usize> {
+        let name = to.map(name_bytes).transpose()?;
+        let result = syscall6(
+            Syscall::SocketSend,
+            self.handle.as_raw() as usize,
+            data.as_ptr() as usize,
+            data.len(),
+            0,
+            0,
+            name.as_ref().map_or(0, |name| name.as_ptr() as usize),
+        );
+        HandleError::from_syscall_result(result).map(|len| len as usize)
+    }
+
+    pub fn send(&self, data: &[u8]) -> HandleResult<usize> {
+        self.send_to(data, None)
+    }
+
+    /// Receive into `buffer`, with the name of the sender (empty if it has
+    /// none)
+    pub fn receive_from(&self, buffer: &mut [u8]) -> HandleResult<(usize, String)> {
+        let mut name = [0u8; NAME_MAX + 1];
+        let result = syscall6(
+            Syscall::SocketReceive,
+            self.handle.as_raw() as usize,
+            buffer.as_mut_ptr() as usize,
+            buffer.len(),
+            0,
+            0,
+            name.as_mut_ptr() as usize,
+        );
+        let len = HandleError::from_syscall_result(result)? as usize;
+        let end = name.iter().position(|&byte| byte == 0).unwrap_or(NAME_MAX);
+        Ok((len, String::from_utf8_lossy(&name[..end]).into_owned()))
+    }
+
+    pub fn receive(&self, buffer: &mut [u8]) -> HandleResult<usize> {
+        self.receive_from(buffer).map(|(len, _)| len)
+    }
+
+    pub fn shutdown(&self, how: usize) -> HandleResult<()> {
+        unit(syscall2(Syscall::SocketShutdown, self.handle.as_raw() as usize, how))
+    }
+
+    pub fn get_option(&self, option: usize) -> HandleResult<usize> {
+        let result = syscall2(Syscall::SocketGetOption, self.handle.as_raw() as usize, option);
+        HandleError::from_syscall_result(result).map(|value| value as usize)
+    }
+
+    pub fn set_option(&self, option: usize, value: usize) -> HandleResult<()> {
+        unit(syscall3(Syscall::SocketSetOption, self.handle.as_raw() as usize, option, value))
+    }
+
+    /// Take only what the capture filter `expression` accepts, or
+    /// everything if `None`; raw and packet sockets only
+    pub fn set_filter(&self, expression: Option<&str>) -> HandleResult<()> {
+        let expression = expression.map(name_bytes).transpose()?;
+        self.set_option(OPT_FILTER, expression.as_ref().map_or(0, |expression| expression.as_ptr() as usize))
+    }
+}
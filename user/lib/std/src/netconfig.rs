@@ -0,0 +1,217 @@
+//! Network configuration
+//!
+//! [`NetConfig`] is a channel to the network configuration of the kernel:
+//! it sends a request message and collects the replies. The kernel answers
+//! a `GET` request with one reply per entry and a `DONE`, any other request
+//! with a `STATUS`. Names and addresses are carried as text, so
+//! `192.0.2.1/24` and `fe80::1/64` are given and read as they are printed.
+
+use crate::handle::capability::StreamError;
+use crate::handle::{Handle, HandleError};
+use crate::syscall::{syscall0, Syscall};
+use crate::vec::Vec;
+
+pub const HEADER_LEN: usize = 16;
+/// Longest reply
+pub const MESSAGE_MAX: usize = 4096;
+
+/// Requests
+pub const GET_LINKS: u16 = 1;
+pub const SET_LINK: u16 = 2;
+pub const GET_ADDRESSES: u16 = 3;
+pub const ADD_ADDRESS: u16 = 4;
+pub const DELETE_ADDRESS: u16 = 5;
+pub const GET_ROUTES: u16 = 6;
+pub const ADD_ROUTE: u16 = 7;
+pub const DELETE_ROUTE: u16 = 8;
+pub const GET_NEIGHBORS: u16 = 9;
+pub const FLUSH_NEIGHBORS: u16 = 10;
+pub const ADD_LINK: u16 = 11;
+pub const DELETE_LINK: u16 = 12;
+
+/// Replies
+pub const STATUS: u16 = 32;
+pub const LINK: u16 = 33;
+pub const ADDRESS: u16 = 34;
+pub const ROUTE: u16 = 35;
+pub const NEIGHBOR: u16 = 36;
+pub const DONE: u16 = 37;
+
+/// The reply is one of those to a `GET` request
+pub const FLAG_MULTI: u16 = 0x1;
+
+/// Attributes
+pub const ATTR_NAME: u16 = 1;
+pub const ATTR_INDEX: u16 = 2;
+pub const ATTR_MAC: u16 = 3;
+pub const ATTR_MTU: u16 = 4;
+pub const ATTR_UP: u16 = 5;
+pub const ATTR_ADDRESS: u16 = 6;
+pub const ATTR_GATEWAY: u16 = 7;
+pub const ATTR_METRIC: u16 = 8;
+pub const ATTR_DROPPED: u16 = 9;
+pub const ATTR_KIND: u16 = 10;
+pub const ATTR_MASTER: u16 = 11;
+pub const ATTR_PEER: u16 = 12;
+pub const ATTR_NETNS_PID: u16 = 13;
+
+/// Statuses
+pub const STATUS_OK: u32 = 0;
+pub const STATUS_NOT_PERMITTED: u32 = 1;
+pub const STATUS_NOT_FOUND: u32 = 2;
+pub const STATUS_EXISTS: u32 = 3;
+pub const STATUS_INVALID: u32 = 4;
+pub const STATUS_NOT_SUPPORTED: u32 = 5;
+pub const STATUS_FAILED: u32 = 6;
+
+/// Errors of network configuration requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetConfigError {
+    /// The channel could not be opened
+    Handle(HandleError),
+    /// The channel could not be written or read
+    Stream(StreamError),
+    /// A reply was not a message
+    Malformed,
+    /// The kernel refused the request with this status
+    Status(u32),
+}
+
+impl NetConfigError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NetConfigError::Handle(_) => "cannot open the network configuration",
+            NetConfigError::Stream(_) => "network configuration I/O failed",
+            NetConfigError::Malformed => "malformed reply",
+            NetConfigError::Status(STATUS_NOT_PERMITTED) => "operation not permitted",
+            NetConfigError::Status(STATUS_NOT_FOUND) => "no such entry",
+            NetConfigError::Status(STATUS_EXISTS) => "entry exists",
+            NetConfigError::Status(STATUS_INVALID) => "invalid argument",
+            NetConfigError::Status(STATUS_NOT_SUPPORTED) => "not supported",
+            NetConfigError::Status(_) => "request failed",
+        }
+    }
+}
+
+/// A reply of the kernel
+#[derive(Debug, Clone)]
+pub struct Reply {
+    pub kind: u16,
+    pub flags: u16,
+    pub status: u32,
+    attributes: Vec<(u16, Vec<u8>)>,
+}
+
+impl Reply {
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        let field = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+        if bytes.len() < HEADER_LEN || field(0) as usize != bytes.len() {
+            return None;
+        }
+        let mut attributes = Vec::new();
+        let mut rest = &bytes[HEADER_LEN..];
+        while rest.len() >= 4 {
+            let len = u16::from_le_bytes([rest[0], rest[1]]) as usize;
+            if len < 4 || len > rest.len() {
+                return None;
+            }
+            attributes.push((u16::from_le_bytes([rest[2], rest[3]]), rest[4..len].to_vec()));
+            rest = &rest[len.next_multiple_of(4).min(rest.len())..];
+        }
+        Some(Self {
+            kind: u16::from_le_bytes([bytes[4], bytes[5]]),
+            flags: u16::from_le_bytes([bytes[6], bytes[7]]),
+            status: field(12),
+            attributes,
+        })
+    }
+
+    pub fn attribute(&self, kind: u16) -> Option<&[u8]> {
+        self.attributes.iter().find(|(found, _)| *found == kind).map(|(_, value)| value.as_slice())
+    }
+
+    pub fn text(&self, kind: u16) -> Option<&str> {
+        core::str::from_utf8(self.attribute(kind)?).ok()
+    }
+
+    pub fn integer(&self, kind: u16) -> Option<u64> {
+        let value = self.attribute(kind)?;
+        let mut bytes = [0u8; 8];
+        bytes.get_mut(..value.len())?.copy_from_slice(value);
+        Some(u64::from_le_bytes(bytes))
+    }
+}
+
+/// A request being built
+pub struct Request {
+    bytes: Vec<u8>,
+}
+
+impl Request {
+    pub fn new(kind: u16) -> Self {
+        let mut bytes = Vec::with_capacity(64);
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&kind.to_le_bytes());
+        bytes.extend_from_slice(&[0; 10]);
+        Self { bytes }
+    }
+
+    pub fn attribute(mut self, kind: u16, value: &[u8]) -> Self {
+        self.bytes.extend_from_slice(&((4 + value.len()) as u16).to_le_bytes());
+        self.bytes.extend_from_slice(&kind.to_le_bytes());
+        self.bytes.extend_from_slice(value);
+        self.bytes.resize(self.bytes.len().next_multiple_of(4), 0);
+        self
+    }
+
+    pub fn text(self, kind: u16, value: &str) -> Self {
+        self.attribute(kind, value.as_bytes())
+    }
+}
+
+/// A channel to the network configuration
+pub struct NetConfig {
+    handle: Handle,
+    sequence: u32,
+}
+
+impl NetConfig {
+    pub fn open() -> Result<Self, NetConfigError> {
+        let raw = HandleError::from_syscall_result(syscall0(Syscall::NetConfigOpen)).map_err(NetConfigError::Handle)?;
+        Ok(Self { handle: unsafe { Handle::from_raw(raw) }, sequence: 0 })
+    }
+
+    /// Send `request` and collect the replies: the entries of a `GET`
+    /// request, nothing for any other
+    pub fn request(&mut self, request: Request) -> Result<Vec<Reply>, NetConfigError> {
+        let stream = self.handle.as_stream().map_err(NetConfigError::Handle)?;
+        self.sequence = self.sequence.wrapping_add(1);
+        let mut bytes = request.bytes;
+        let len = bytes.len() as u32;
+        bytes[..4].copy_from_slice(&len.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.sequence.to_le_bytes());
+        stream.write(&bytes).map_err(NetConfigError::Stream)?;
+
+        let mut replies = Vec::new();
+        let mut buffer = [0u8; MESSAGE_MAX];
+        loop {
+            let len = stream.read(&mut buffer).map_err(NetConfigError::Stream)?;
+            let reply = Reply::parse(&buffer[..len]).ok_or(NetConfigError::Malformed)?;
+            match reply.kind {
+                STATUS if reply.status == STATUS_OK => return Ok(replies),
+                STATUS => return Err(NetConfigError::Status(reply.status)),
+                DONE => return Ok(replies),
+                _ => replies.push(reply),
+            }
+        }
+    }
+
+    /// The entries of a `GET` request, of the interface `name` only if given
+    pub fn list(&mut self, kind: u16, name: Option<&str>) -> Result<Vec<Reply>, NetConfigError> {
+        let request = Request::new(kind);
+        self.request(match name {
+            Some(name) => request.text(ATTR_NAME, name),
+            None => request,
+        })
+    }
+}
@@ -0,0 +1,38 @@
+//! Random bytes
+//!
+//! [`fill`] reads the kernel CSPRNG, which is fit for keys and nonces once
+//! it has been seeded; until then it waits, unless asked not to.
+
+use crate::syscall::{syscall3, Syscall};
+
+/// Fail instead of waiting for the generator to be seeded
+pub const NONBLOCK: usize = 0x1;
+/// Do not wait for the generator to be seeded, at the cost of strength
+pub const INSECURE: usize = 0x4;
+
+/// Fill `buffer` with random bytes, waiting for the generator to be seeded
+///
+/// Fails if a signal arrives first or `buffer` is not writable.
+pub fn fill(buffer: &mut [u8]) -> Result<(), ()> {
+    fill_with(buffer, 0)
+}
+
+/// Fill `buffer` with random bytes according to `flags`
+pub fn fill_with(buffer: &mut [u8], flags: usize) -> Result<(), ()> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        let rest = &mut buffer[filled..];
+        match syscall3(Syscall::GetRandom, rest.as_mut_ptr() as usize, rest.len(), flags) {
+            usize::MAX | 0 => return Err(()),
+            n => filled += n,
+        }
+    }
+    Ok(())
+}
+
+/// A random 64-bit value
+pub fn u64() -> Result<u64, ()> {
+    let mut bytes = [0u8; 8];
+    fill(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
@@ -0,0 +1,145 @@
+//! POSIX-style signals
+//!
+//! Signal numbers and flags match Linux. Handlers installed with
+//! [`sigaction`] return through a restorer provided by this library.
+
+use crate::syscall::{syscall1, syscall2, syscall3, Syscall};
+
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGBUS: usize = 7;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+pub const SIGTTIN: usize = 21;
+pub const SIGTTOU: usize = 22;
+pub const SIGWINCH: usize = 28;
+
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+pub mod sa_flags {
+    pub const NOCLDSTOP: usize = 0x0000_0001;
+    pub const RESTORER: usize = 0x0400_0000;
+    pub const RESTART: usize = 0x1000_0000;
+    pub const NODEFER: usize = 0x4000_0000;
+    pub const RESETHAND: usize = 0x8000_0000;
+}
+
+/// `sigprocmask` operations
+pub const SIG_BLOCK: usize = 0;
+pub const SIG_UNBLOCK: usize = 1;
+pub const SIG_SETMASK: usize = 2;
+
+/// A set of signals, bit `n - 1` standing for signal `n`
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SigSet(pub u64);
+
+impl SigSet {
+    pub const fn empty() -> Self {
+        SigSet(0)
+    }
+
+    pub fn contains(&self, sig: usize) -> bool {
+        self.0 & (1 << (sig - 1)) != 0
+    }
+
+    pub fn add(&mut self, sig: usize) {
+        self.0 |= 1 << (sig - 1);
+    }
+
+    pub fn remove(&mut self, sig: usize) {
+        self.0 &= !(1 << (sig - 1));
+    }
+}
+
+/// Disposition of a signal, as passed to and from the kernel
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SigAction {
+    /// `SIG_DFL`, `SIG_IGN` or the address of an `extern "C" fn(usize)`
+    pub handler: usize,
+    pub flags: usize,
+    pub restorer: usize,
+    pub mask: SigSet,
+}
+
+impl SigAction {
+    /// Run `handler` for the signal
+    pub fn handler(handler: extern "C" fn(usize), flags: usize) -> Self {
+        SigAction { handler: handler as usize, flags, restorer: 0, mask: SigSet::empty() }
+    }
+
+    pub fn ignore() -> Self {
+        SigAction { handler: SIG_IGN, ..Default::default() }
+    }
+
+    pub fn default_action() -> Self {
+        SigAction::default()
+    }
+}
+
+/// Send `sig` to the task `pid`
+///
+/// A `pid` of 0 targets the caller's process group, -1 every other task
+/// and `-pgid` the process group `pgid`.
+pub fn kill(pid: isize, sig: usize) -> Result<(), ()> {
+    let res = syscall2(Syscall::Kill, pid as usize, sig);
+    if res == usize::MAX { Err(()) } else { Ok(()) }
+}
+
+/// Send `sig` to every task in the process group `pgid`
+pub fn killpg(pgid: u32, sig: usize) -> Result<(), ()> {
+    kill(-(pgid as isize), sig)
+}
+
+/// Install `action` for `sig`, returning the previous disposition
+pub fn sigaction(sig: usize, action: &SigAction) -> Result<SigAction, ()> {
+    let mut action = *action;
+    if action.handler != SIG_DFL && action.handler != SIG_IGN {
+        action.restorer = crate::arch::arch_signal_restorer as usize;
+        action.flags |= sa_flags::RESTORER;
+    }
+    let mut old = SigAction::default();
+    let res = syscall3(
+        Syscall::SigAction,
+        sig,
+        &action as *const SigAction as usize,
+        &mut old as *mut SigAction as usize,
+    );
+    if res == usize::MAX { Err(()) } else { Ok(old) }
+}
+
+/// Change the signal mask (`SIG_BLOCK`, `SIG_UNBLOCK` or `SIG_SETMASK`),
+/// returning the previous mask
+pub fn sigprocmask(how: usize, set: SigSet) -> Result<SigSet, ()> {
+    let mut old = SigSet::empty();
+    let res = syscall3(
+        Syscall::SigProcMask,
+        how,
+        &set as *const SigSet as usize,
+        &mut old as *mut SigSet as usize,
+    );
+    if res == usize::MAX { Err(()) } else { Ok(old) }
+}
+
+/// Signals sent to the calling task but not delivered yet
+pub fn sigpending() -> Result<SigSet, ()> {
+    let mut set = SigSet::empty();
+    let res = syscall1(Syscall::SigPending, &mut set as *mut SigSet as usize);
+    if res == usize::MAX { Err(()) } else { Ok(set) }
+}
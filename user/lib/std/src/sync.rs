@@ -0,0 +1,47 @@
+//! Futex wrappers for building blocking synchronization primitives
+
+use crate::syscall::{syscall4, syscall6, Syscall};
+use core::sync::atomic::AtomicU32;
+use core::time::Duration;
+
+pub mod futex_op {
+    pub const WAIT: usize = 0;
+    pub const WAKE: usize = 1;
+    pub const REQUEUE: usize = 3;
+    pub const CMP_REQUEUE: usize = 4;
+    pub const PRIVATE: usize = 128;
+}
+
+/// Sleep while `futex` holds `expected`
+///
+/// Returns `Ok(())` when woken by `futex_wake` and `Err(())` if the value
+/// differed, the timeout expired or the wait was interrupted.
+pub fn futex_wait(futex: &AtomicU32, expected: u32, timeout: Option<Duration>) -> Result<(), ()> {
+    // At least 1ns, since 0 means no timeout
+    let timeout = timeout.map_or(0, |t| (t.as_nanos() as usize).max(1));
+    let res = syscall4(Syscall::Futex, futex.as_ptr() as usize, futex_op::WAIT, expected as usize, timeout);
+    if res == usize::MAX { Err(()) } else { Ok(()) }
+}
+
+/// Wake up to `count` tasks sleeping on `futex`, returning how many woke
+pub fn futex_wake(futex: &AtomicU32, count: usize) -> usize {
+    let res = syscall4(Syscall::Futex, futex.as_ptr() as usize, futex_op::WAKE, count, 0);
+    if res == usize::MAX { 0 } else { res }
+}
+
+/// Wake up to `wake` tasks sleeping on `futex` and move up to `requeue`
+/// others to `target`, if `futex` still holds `expected`
+///
+/// Returns the number of tasks woken or moved.
+pub fn futex_cmp_requeue(futex: &AtomicU32, wake: usize, target: &AtomicU32, requeue: usize, expected: u32) -> Result<usize, ()> {
+    let res = syscall6(
+        Syscall::Futex,
+        futex.as_ptr() as usize,
+        futex_op::CMP_REQUEUE,
+        wake,
+        requeue,
+        target.as_ptr() as usize,
+        expected as usize,
+    );
+    if res == usize::MAX { Err(()) } else { Ok(res) }
+}
@@ -13,11 +13,54 @@ pub enum Syscall {
     Getppid = 8,
     Brk = 12,
     Sbrk = 13,
+    GetRlimit = 14,
+    SetRlimit = 15,
     // BASIC I/O
     Putchar = 16,
     Getchar = 17,
 
     Sleep = 20,
+    GetPriority = 21,
+    SetPriority = 22,
+    SchedSetScheduler = 23,
+    SchedGetScheduler = 24,
+    SchedGetParam = 25,
+    SchedSetAffinity = 26,
+    SchedGetAffinity = 27,
+    SigAction = 28,
+    SigProcMask = 29,
+    SigPending = 30,
+    SigReturn = 31,
+    SetPgid = 32,
+    GetPgid = 33,
+    SetSid = 34,
+    GetSid = 35,
+    GetRusage = 36,
+    Times = 37,
+    Spawn = 38,
+    SetHostname = 39,
+    GetHostname = 40,
+    Uname = 41,
+    GetUid = 42,
+    GetEuid = 43,
+    GetGid = 44,
+    GetEgid = 45,
+    SetUid = 46,
+    SetGid = 47,
+    SetReuid = 48,
+    SetRegid = 49,
+    SetResuid = 50,
+    SetResgid = 51,
+    GetResuid = 52,
+    GetResgid = 53,
+    GetGroups = 54,
+    SetGroups = 55,
+    ProcessOpen = 56,
+    ProcessSignal = 57,
+    ClockGetTime = 58,
+    GetRandom = 59,
+    ClockGetRealtime = 60,
+    ClockSetRealtime = 61,
     
     // === Handle Management ===
     HandleQuery = 100,
@@ -25,11 +68,15 @@ pub enum Syscall {
     HandleClose = 102,      // Close any handle (files, pipes, etc.)
     HandleDuplicate = 103,  // Duplicate any handle
     HandleControl = 110,    // Control operations on handles (ioctl-equivalent)
+    HandlePoll = 111,       // Wait for events on a set of handles
     
     // === Core Capabilities (Object-oriented) ===
     // StreamOps Capability - read/write operations
     StreamRead = 200,
     StreamWrite = 201,
+    // Submission rings - batched stream operations
+    RingCreate = 210,
+    RingEnter = 211,
     
     // FileObject Capability - file-specific operations (extends StreamOps)
     FileSeek = 300,
@@ -53,10 +100,79 @@ pub enum Syscall {
     
     // === IPC Operations ===
     Pipe = 600,             // Create pipe handles
+    Pipe2 = 601,            // Create pipe handles with flags
+    PipeSetSize = 602,      // Change the capacity of a pipe
+    PipeGetSize = 603,      // Get the capacity of a pipe
+    PipeSetNonblocking = 604, // Make a pipe end fail instead of waiting
+    Splice = 605,           // Move data between a pipe and a stream
+
+    // Message bus
+    BusConnect = 620,       // Connect to the message bus
+    BusSubscribe = 621,     // Receive what is published on a channel
+    BusUnsubscribe = 622,   // Stop receiving from a channel
+    BusRegister = 623,      // Take a service name
+    BusPublish = 624,       // Publish data and handles on a channel
+    BusRequest = 625,       // Send a request to a service
+    BusReply = 626,         // Answer a request
+    BusReceive = 627,       // Receive the next message
+    BusWaitReply = 628,     // Wait for the reply to a request
+
+    ShmOpen = 630,          // Create/open shared memory object
+    ShmUnlink = 631,        // Remove shared memory name
+    ShmSetSize = 632,       // Size an empty shared memory object
+    ShmGetSize = 633,       // Get the size of a shared memory object
+    MemFdCreate = 634,      // Create an anonymous memory file
+    MemFdAddSeals = 635,    // Seal a memory file
+    MemFdGetSeals = 636,    // Get the seals of a memory file
+    Futex = 640,            // Wait on / wake a futex word
+
+    // Epoll
+    EpollCreate = 650,      // Create an epoll object
+    EpollControl = 651,     // Add, change or remove an object of an epoll object
+    EpollWait = 652,        // Wait for events of an epoll object
+
+    // Event counters
+    EventFdCreate = 660,    // Create a pollable event counter
+
+    // Timers
+    TimerFdCreate = 670,    // Create a pollable timer
+    TimerFdSet = 671,       // Arm or disarm a timer
+    TimerFdGet = 672,       // Get the setting of a timer
+
+    // Sockets
+    SocketCreate = 680,     // Create a socket
+    SocketPair = 681,       // Create a pair of connected sockets
+    SocketBind = 682,       // Give a socket a name
+    SocketListen = 683,     // Accept connections on a socket
+    SocketConnect = 684,    // Connect a socket to a named socket
+    SocketAccept = 685,     // Take a pending connection
+    SocketSend = 686,       // Send data and handles
+    SocketReceive = 687,    // Receive data and handles
+    SocketShutdown = 688,   // Shut down a direction of a connection
+    SocketGetOption = 689,  // Get an option of a socket
+
+    // Semaphores
+    SemOpen = 690,          // Create/open a semaphore
+    SemUnlink = 691,        // Remove the name of a semaphore
+    SemWait = 692,          // Take one, waiting while zero
+    SemTryWait = 693,       // Take one if not zero
+    SemPost = 694,          // Add one
+    SemGetValue = 695,      // Get the value of a semaphore
+
+    // Sockets, continued
+    SocketSetOption = 696,  // Set an option of a socket
+
+    // Network configuration
+    NetConfigOpen = 697,    // Open a channel to the network configuration
+
+    // Devices
+    DeviceEventsOpen = 698, // Open a channel of the device hotplug events
     
     // === Memory Mapping Operations ===
     MemoryMap = 700,        // Memory map operation (mmap)
     MemoryUnmap = 701,      // Memory unmap operation (munmap)
+    MemoryProtect = 702,    // Change memory protection (mprotect)
+    MemoryAdvise = 703,     // Advise on memory usage (madvise)
     
     // === Debug/Profiler Operations ===
     ProfilerDump = 999,     // Dump profiler statistics (debug only)
@@ -1,15 +1,20 @@
-use crate::syscall::{syscall0, syscall1, syscall3, syscall4, syscall5, Syscall};
+use crate::syscall::{syscall0, syscall1, syscall2, syscall3, syscall4, syscall5, Syscall};
 use crate::vec::Vec;
 use crate::boxed::Box;
+use crate::handle::Handle;
 
 // Flags for execve system calls
 pub const EXECVE_FORCE_ABI_REBUILD: usize = 0x1; // Force ABI environment reconstruction
 
 #[repr(u64)]
 pub enum CloneFlagsDef {
-    Vm      = 0b00000001, // Clone the VM
+    Vm      = 0b00000001, // Share the VM
     Fs      = 0b00000010, // Clone the filesystem
     Files   = 0b00000100, // Clone the file descriptors
+    Thread  = 0b00001000, // Create a thread: share the VM, file descriptors and filesystem
+    SetTls  = 0b00010000, // Set the thread pointer of the child
+    NewUts  = 0b00100000, // Give the child a copy of the UTS namespace
+    NewNet  = 0b01000000, // Give the child a new network namespace
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -60,7 +65,36 @@ impl Default for CloneFlags {
 /// - In the child process: 0
 /// - On error: -1
 pub fn clone(flags: CloneFlags) -> i32 {
-    syscall1(Syscall::Clone, flags.get_raw() as usize) as i32
+    syscall3(Syscall::Clone, flags.get_raw() as usize, 0, 0) as i32
+}
+
+/// Creates a thread of the current process.
+///
+/// The thread shares the address space, the handles and the filesystem
+/// context of the process, and starts by calling `entry(arg)` on `stack`.
+/// It exits with the value `entry` returns.
+///
+/// # Arguments
+/// * `stack` - Top of the stack of the thread
+/// * `tls` - Thread pointer of the thread, 0 to have the kernel set up a
+///   TLS block from the program's TLS template
+/// * `entry` - Function the thread runs
+/// * `arg` - Argument passed to `entry`
+///
+/// # Return Value
+/// - On success: the ID of the thread
+/// - On error: -1
+///
+/// # Safety
+/// `stack` must point to the top of memory reserved for the thread's stack
+/// that stays valid until the thread exits.
+pub unsafe fn clone_thread(stack: usize, tls: usize, entry: extern "C" fn(usize) -> i32, arg: usize) -> i32 {
+    let mut flags = CloneFlags::new();
+    flags.set(CloneFlagsDef::Thread);
+    if tls != 0 {
+        flags.set(CloneFlagsDef::SetTls);
+    }
+    unsafe { crate::arch::arch_clone_thread(flags.get_raw() as usize, stack, tls, entry, arg) as i32 }
 }
 
 /// Fork the current process.
@@ -85,11 +119,14 @@ pub fn exit(code: i32) -> ! {
 
 /// Returns the current process ID.
 ///
+/// The ID is read from the vDSO when present. Threads share it with the
+/// task that created the address space.
+///
 /// # Return Value
 /// - The process ID of the calling process
 /// 
 pub fn getpid() -> u32 {
-    syscall0(Syscall::Getpid) as u32
+    crate::vdso::pid().unwrap_or_else(|| syscall0(Syscall::Getpid) as u32)
 }
 
 /// Returns the parent process ID.
@@ -101,6 +138,296 @@ pub fn getppid() -> u32 {
     syscall0(Syscall::Getppid) as u32
 }
 
+/// Move the task `pid` (0 for the calling task) to the process group `pgid`
+/// (0 to use `pid` itself).
+pub fn setpgid(pid: u32, pgid: u32) -> Result<(), ()> {
+    let res = syscall2(Syscall::SetPgid, pid as usize, pgid as usize);
+    if res == usize::MAX { Err(()) } else { Ok(()) }
+}
+
+/// Returns the process group of the task `pid` (0 for the calling task).
+pub fn getpgid(pid: u32) -> Result<u32, ()> {
+    let res = syscall1(Syscall::GetPgid, pid as usize);
+    if res == usize::MAX { Err(()) } else { Ok(res as u32) }
+}
+
+/// Start a new session led by the calling task, returning its ID.
+pub fn setsid() -> Result<u32, ()> {
+    let res = syscall0(Syscall::SetSid);
+    if res == usize::MAX { Err(()) } else { Ok(res as u32) }
+}
+
+/// Returns the session of the task `pid` (0 for the calling task).
+pub fn getsid(pid: u32) -> Result<u32, ()> {
+    let res = syscall1(Syscall::GetSid, pid as usize);
+    if res == usize::MAX { Err(()) } else { Ok(res as u32) }
+}
+
+/// Value meaning "no limit" in an [`RLimit`]
+pub const RLIM_INFINITY: usize = usize::MAX;
+
+/// Resources that can be limited with [`setrlimit`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum Resource {
+    /// CPU time in seconds: SIGXCPU past the soft limit, SIGKILL at the hard limit
+    Cpu = 0,
+    /// Largest file the process may write; writing past it sends SIGXFSZ
+    FileSize = 1,
+    /// Size of the heap and private writable anonymous mappings
+    Data = 2,
+    /// Size the stack may grow to
+    Stack = 3,
+    /// Largest core file written when the process crashes; 0 disables them
+    Core = 4,
+    /// Number of processes and threads in the system
+    Processes = 6,
+    /// Number of open handles
+    OpenFiles = 7,
+    /// Total size of the address space
+    AddressSpace = 9,
+    /// Ceiling of the priority: the nice value may not be lowered below
+    /// `20 - soft limit`
+    Nice = 13,
+    /// Highest real-time priority the process may set itself
+    RtPriority = 14,
+}
+
+/// Soft and hard limit of a resource, in bytes (or the unit of the resource)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct RLimit {
+    /// Soft limit (the enforced one)
+    pub cur: usize,
+    /// Hard limit (ceiling for the soft limit)
+    pub max: usize,
+}
+
+/// Gets the limits of a resource.
+///
+/// # Return Value
+/// - On success: the current limits
+/// - On error: `Err(())`
+pub fn getrlimit(resource: Resource) -> Result<RLimit, ()> {
+    let mut limit = RLimit { cur: 0, max: 0 };
+    let res = syscall2(Syscall::GetRlimit, resource as usize, &mut limit as *mut RLimit as usize);
+    if res == usize::MAX { Err(()) } else { Ok(limit) }
+}
+
+/// Sets the limits of a resource.
+///
+/// The soft limit may not exceed the hard limit, and the hard limit can
+/// only be lowered. Limits are inherited by children and kept across exec.
+///
+/// # Return Value
+/// - On success: `Ok(())`
+/// - On error: `Err(())`
+pub fn setrlimit(resource: Resource, limit: RLimit) -> Result<(), ()> {
+    let res = syscall2(Syscall::SetRlimit, resource as usize, &limit as *const RLimit as usize);
+    if res == usize::MAX { Err(()) } else { Ok(()) }
+}
+
+/// Report the calling process
+pub const RUSAGE_SELF: isize = 0;
+/// Report the waited-for children of the calling process
+pub const RUSAGE_CHILDREN: isize = -1;
+/// Report the calling thread
+pub const RUSAGE_THREAD: isize = 1;
+
+/// A time in seconds and microseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(C)]
+pub struct Timeval {
+    pub sec: i64,
+    pub usec: i64,
+}
+
+/// Resource usage; only `utime` and `stime` are filled in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(C)]
+pub struct RUsage {
+    /// Time spent in user mode
+    pub utime: Timeval,
+    /// Time spent in the kernel
+    pub stime: Timeval,
+    pub maxrss: i64,
+    pub ixrss: i64,
+    pub idrss: i64,
+    pub isrss: i64,
+    pub minflt: i64,
+    pub majflt: i64,
+    pub nswap: i64,
+    pub inblock: i64,
+    pub oublock: i64,
+    pub msgsnd: i64,
+    pub msgrcv: i64,
+    pub nsignals: i64,
+    pub nvcsw: i64,
+    pub nivcsw: i64,
+}
+
+/// Process times in clock ticks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(C)]
+pub struct Tms {
+    /// User time of the process
+    pub utime: i64,
+    /// Kernel time of the process
+    pub stime: i64,
+    /// User time of the waited-for children
+    pub cutime: i64,
+    /// Kernel time of the waited-for children
+    pub cstime: i64,
+}
+
+/// Gets the resource usage of the process, the thread or the children.
+///
+/// # Arguments
+/// * `who` - `RUSAGE_SELF`, `RUSAGE_THREAD` or `RUSAGE_CHILDREN`
+///
+/// # Return Value
+/// - On success: the usage
+/// - On error: `Err(())`
+pub fn getrusage(who: isize) -> Result<RUsage, ()> {
+    let mut usage = RUsage::default();
+    let res = syscall2(Syscall::GetRusage, who as usize, &mut usage as *mut RUsage as usize);
+    if res == usize::MAX { Err(()) } else { Ok(usage) }
+}
+
+/// Gets the process times of the process and its children.
+///
+/// # Return Value
+/// (ticks, times)
+/// - ticks: Clock ticks since boot
+/// - times: The process times
+pub fn times() -> (u64, Tms) {
+    let mut tms = Tms::default();
+    let ticks = syscall1(Syscall::Times, &mut tms as *mut Tms as usize);
+    (ticks as u64, tms)
+}
+
+/// Gets the nice value of a process.
+///
+/// # Arguments
+/// * `pid` - Process ID, 0 for the calling process
+///
+/// # Return Value
+/// - On success: the nice value (-20 to 19)
+/// - On error: `Err(())`
+pub fn getpriority(pid: u32) -> Result<i32, ()> {
+    let res = syscall2(Syscall::GetPriority, 0, pid as usize);
+    if res == usize::MAX { Err(()) } else { Ok(20 - res as i32) }
+}
+
+/// Sets the nice value of a process.
+///
+/// Higher values get less CPU time. Only the calling process and its
+/// descendants can be changed; lowering the value is limited by
+/// [`Resource::Nice`]. The value is inherited by children.
+///
+/// # Arguments
+/// * `pid` - Process ID, 0 for the calling process
+/// * `nice` - New nice value, clamped to -20..=19
+///
+/// # Return Value
+/// - On success: `Ok(())`
+/// - On error: `Err(())`
+pub fn setpriority(pid: u32, nice: i32) -> Result<(), ()> {
+    let res = syscall3(Syscall::SetPriority, 0, pid as usize, nice as isize as usize);
+    if res == usize::MAX { Err(()) } else { Ok(()) }
+}
+
+/// Scheduling policy of a process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(usize)]
+pub enum SchedPolicy {
+    /// Time-shared, weighted by the nice value
+    Normal = 0,
+    /// Real-time, runs until it blocks or a higher priority task is runnable
+    Fifo = 1,
+    /// Real-time, round-robin among tasks of the same priority
+    RoundRobin = 2,
+}
+
+/// Sets the scheduling policy and real-time priority of a process.
+///
+/// Real-time processes always run before normal ones. Real-time priorities
+/// range from 1 to 99; the normal policy takes priority 0. Raising the
+/// priority is limited by [`Resource::RtPriority`].
+///
+/// # Arguments
+/// * `pid` - Process ID, 0 for the calling process
+/// * `policy` - New policy
+/// * `priority` - New real-time priority
+///
+/// # Return Value
+/// - On success: `Ok(())`
+/// - On error: `Err(())`
+pub fn sched_setscheduler(pid: u32, policy: SchedPolicy, priority: u32) -> Result<(), ()> {
+    let res = syscall3(Syscall::SchedSetScheduler, pid as usize, policy as usize, priority as usize);
+    if res == usize::MAX { Err(()) } else { Ok(()) }
+}
+
+/// Gets the scheduling policy and real-time priority of a process.
+///
+/// # Arguments
+/// * `pid` - Process ID, 0 for the calling process
+///
+/// # Return Value
+/// - On success: the policy and the real-time priority
+/// - On error: `Err(())`
+pub fn sched_getscheduler(pid: u32) -> Result<(SchedPolicy, u32), ()> {
+    let policy = match syscall1(Syscall::SchedGetScheduler, pid as usize) {
+        0 => SchedPolicy::Normal,
+        1 => SchedPolicy::Fifo,
+        2 => SchedPolicy::RoundRobin,
+        _ => return Err(()),
+    };
+    let priority = syscall1(Syscall::SchedGetParam, pid as usize);
+    if priority == usize::MAX { Err(()) } else { Ok((policy, priority as u32)) }
+}
+
+/// Sets the CPUs a process may run on.
+///
+/// Bit `n` of `mask` allows CPU `n`. Bits of CPUs that do not exist are
+/// ignored; the mask must contain at least one online CPU.
+///
+/// # Arguments
+/// * `pid` - Process ID, 0 for the calling process
+/// * `mask` - CPU mask
+///
+/// # Return Value
+/// - On success: `Ok(())`
+/// - On error: `Err(())`
+pub fn sched_setaffinity(pid: u32, mask: usize) -> Result<(), ()> {
+    let res = syscall3(
+        Syscall::SchedSetAffinity,
+        pid as usize,
+        core::mem::size_of::<usize>(),
+        &mask as *const usize as usize,
+    );
+    if res == usize::MAX { Err(()) } else { Ok(()) }
+}
+
+/// Gets the CPUs a process may run on.
+///
+/// # Arguments
+/// * `pid` - Process ID, 0 for the calling process
+///
+/// # Return Value
+/// - On success: the CPU mask, bit `n` standing for CPU `n`
+/// - On error: `Err(())`
+pub fn sched_getaffinity(pid: u32) -> Result<usize, ()> {
+    let mut mask: usize = 0;
+    let res = syscall3(
+        Syscall::SchedGetAffinity,
+        pid as usize,
+        core::mem::size_of::<usize>(),
+        &mut mask as *mut usize as usize,
+    );
+    if res == usize::MAX { Err(()) } else { Ok(mask) }
+}
+
 /// Executes a program, replacing the current process image.
 /// 
 /// # Arguments
@@ -333,16 +660,300 @@ pub fn execve_abi_with_flags(path: &str, argv: &[&str], envp: &[&str], abi: &str
     res as i32
 }
 
-/// Waits for a child process to exit.
+/// Start the child with copies of the caller's handles
+pub const SPAWN_INHERIT_HANDLES: usize = 0x1;
+
+/// A handle of the caller installed in the spawned child
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct SpawnHandle {
+    /// Handle in the caller
+    pub parent: u32,
+    /// Handle number in the child
+    pub child: u32,
+}
+
+/// `SpawnAttr` as read by the kernel
+#[repr(C)]
+struct RawSpawnAttr {
+    flags: usize,
+    cwd: usize,
+    handles: usize,
+    handle_count: usize,
+}
+
+/// Starts a program in a new child process without copying the caller.
+///
+/// # Arguments
+/// * `path` - Path to the executable
+/// * `argv` - Argument array
+/// * `envp` - Environment variable array
+/// * `flags` - `SPAWN_*` flags
+/// * `cwd` - Working directory of the child, `None` for the caller's
+/// * `handles` - Handles of the caller to install in the child
+///
+/// # Return Value
+/// The process ID of the child
+pub fn spawn(path: &str, argv: &[&str], envp: &[&str], flags: usize, cwd: Option<&str>, handles: &[SpawnHandle]) -> Result<u32, ()> {
+    let path_bytes = str_to_cstr_bytes(path).map_err(|_| ())?;
+    let cwd_bytes = match cwd {
+        Some(cwd) => Some(str_to_cstr_bytes(cwd).map_err(|_| ())?),
+        None => None,
+    };
+
+    let (argv_data, argv_ptrs) = if argv.is_empty() {
+        (Vec::new(), create_empty_ptr_array())
+    } else {
+        strarr_to_cstr_ptrs(argv).map_err(|_| ())?
+    };
+    let (envp_data, envp_ptrs) = if envp.is_empty() {
+        (Vec::new(), create_empty_ptr_array())
+    } else {
+        strarr_to_cstr_ptrs(envp).map_err(|_| ())?
+    };
+
+    let attr = RawSpawnAttr {
+        flags,
+        cwd: cwd_bytes.as_ref().map_or(0, |cwd| cwd.as_ptr() as usize),
+        handles: handles.as_ptr() as usize,
+        handle_count: handles.len(),
+    };
+    let res = syscall4(
+        Syscall::Spawn,
+        path_bytes.as_ptr() as usize,
+        argv_ptrs.as_ptr() as usize,
+        envp_ptrs.as_ptr() as usize,
+        &attr as *const RawSpawnAttr as usize,
+    );
+
+    // Keep the strings alive until the syscall completes
+    drop(argv_data);
+    drop(envp_data);
+
+    if res == usize::MAX { Err(()) } else { Ok(res as u32) }
+}
+
+/// Longest name in a UTS field, without the terminating NUL
+pub const UTS_NAME_LEN: usize = 64;
+
+/// System names as reported by `uname`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct UtsName {
+    pub sysname: [u8; UTS_NAME_LEN + 1],
+    pub nodename: [u8; UTS_NAME_LEN + 1],
+    pub release: [u8; UTS_NAME_LEN + 1],
+    pub version: [u8; UTS_NAME_LEN + 1],
+    pub machine: [u8; UTS_NAME_LEN + 1],
+    pub domainname: [u8; UTS_NAME_LEN + 1],
+}
+
+impl UtsName {
+    /// The text of a NUL-terminated field
+    pub fn field(field: &[u8; UTS_NAME_LEN + 1]) -> &str {
+        let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+        core::str::from_utf8(&field[..len]).unwrap_or("")
+    }
+}
+
+/// Sets the host name of the caller's UTS namespace.
+///
+/// # Arguments
+/// * `name` - The new host name, at most `UTS_NAME_LEN` bytes
+pub fn sethostname(name: &str) -> Result<(), ()> {
+    let res = syscall2(Syscall::SetHostname, name.as_ptr() as usize, name.len());
+    if res == usize::MAX { Err(()) } else { Ok(()) }
+}
+
+/// Gets the host name of the caller's UTS namespace.
+///
+/// # Arguments
+/// * `buf` - Buffer receiving the NUL-terminated host name
+///
+/// # Return Value
+/// - On success: the host name within `buf`
+/// - On error (including a buffer too small): `Err(())`
+pub fn gethostname(buf: &mut [u8]) -> Result<&str, ()> {
+    let res = syscall2(Syscall::GetHostname, buf.as_mut_ptr() as usize, buf.len());
+    if res == usize::MAX {
+        return Err(());
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    core::str::from_utf8(&buf[..len]).map_err(|_| ())
+}
+
+/// Gets the system names of the caller's UTS namespace.
+pub fn uname() -> Result<UtsName, ()> {
+    let mut name = UtsName {
+        sysname: [0; UTS_NAME_LEN + 1],
+        nodename: [0; UTS_NAME_LEN + 1],
+        release: [0; UTS_NAME_LEN + 1],
+        version: [0; UTS_NAME_LEN + 1],
+        machine: [0; UTS_NAME_LEN + 1],
+        domainname: [0; UTS_NAME_LEN + 1],
+    };
+    let res = syscall1(Syscall::Uname, &mut name as *mut UtsName as usize);
+    if res == usize::MAX { Err(()) } else { Ok(name) }
+}
+
+/// Pass as an ID to `setreuid`, `setresuid` and friends to keep that ID
+pub const ID_UNCHANGED: u32 = u32::MAX;
+
+/// Get the real user ID of the calling process
+pub fn getuid() -> u32 {
+    syscall0(Syscall::GetUid) as u32
+}
+
+/// Get the effective user ID of the calling process
+pub fn geteuid() -> u32 {
+    syscall0(Syscall::GetEuid) as u32
+}
+
+/// Get the real group ID of the calling process
+pub fn getgid() -> u32 {
+    syscall0(Syscall::GetGid) as u32
+}
+
+/// Get the effective group ID of the calling process
+pub fn getegid() -> u32 {
+    syscall0(Syscall::GetEgid) as u32
+}
+
+fn id_result(res: usize) -> Result<(), ()> {
+    if res == usize::MAX { Err(()) } else { Ok(()) }
+}
+
+/// Set the user ID of the calling process
+///
+/// A privileged process sets its real, effective and saved user IDs; any
+/// other process may only set its effective user ID to its real or saved one.
+pub fn setuid(uid: u32) -> Result<(), ()> {
+    id_result(syscall1(Syscall::SetUid, uid as usize))
+}
+
+/// Set the group ID of the calling process (see `setuid`)
+pub fn setgid(gid: u32) -> Result<(), ()> {
+    id_result(syscall1(Syscall::SetGid, gid as usize))
+}
+
+/// Set the real and effective user IDs; `ID_UNCHANGED` keeps an ID
+pub fn setreuid(ruid: u32, euid: u32) -> Result<(), ()> {
+    id_result(syscall2(Syscall::SetReuid, ruid as usize, euid as usize))
+}
+
+/// Set the real and effective group IDs; `ID_UNCHANGED` keeps an ID
+pub fn setregid(rgid: u32, egid: u32) -> Result<(), ()> {
+    id_result(syscall2(Syscall::SetRegid, rgid as usize, egid as usize))
+}
+
+/// Set the real, effective and saved user IDs; `ID_UNCHANGED` keeps an ID
+pub fn setresuid(ruid: u32, euid: u32, suid: u32) -> Result<(), ()> {
+    id_result(syscall3(Syscall::SetResuid, ruid as usize, euid as usize, suid as usize))
+}
+
+/// Set the real, effective and saved group IDs; `ID_UNCHANGED` keeps an ID
+pub fn setresgid(rgid: u32, egid: u32, sgid: u32) -> Result<(), ()> {
+    id_result(syscall3(Syscall::SetResgid, rgid as usize, egid as usize, sgid as usize))
+}
+
+/// Get the real, effective and saved user IDs
+pub fn getresuid() -> Result<(u32, u32, u32), ()> {
+    let (mut ruid, mut euid, mut suid) = (0u32, 0u32, 0u32);
+    let res = syscall3(
+        Syscall::GetResuid,
+        &mut ruid as *mut u32 as usize,
+        &mut euid as *mut u32 as usize,
+        &mut suid as *mut u32 as usize,
+    );
+    id_result(res).map(|_| (ruid, euid, suid))
+}
+
+/// Get the real, effective and saved group IDs
+pub fn getresgid() -> Result<(u32, u32, u32), ()> {
+    let (mut rgid, mut egid, mut sgid) = (0u32, 0u32, 0u32);
+    let res = syscall3(
+        Syscall::GetResgid,
+        &mut rgid as *mut u32 as usize,
+        &mut egid as *mut u32 as usize,
+        &mut sgid as *mut u32 as usize,
+    );
+    id_result(res).map(|_| (rgid, egid, sgid))
+}
+
+/// Get the supplementary groups of the calling process
+pub fn getgroups() -> Result<Vec<u32>, ()> {
+    let count = syscall2(Syscall::GetGroups, 0, 0);
+    if count == usize::MAX {
+        return Err(());
+    }
+    let mut groups = crate::vec![0u32; count];
+    if count == 0 {
+        return Ok(groups);
+    }
+    let res = syscall2(Syscall::GetGroups, count, groups.as_mut_ptr() as usize);
+    if res == usize::MAX {
+        return Err(());
+    }
+    groups.truncate(res);
+    Ok(groups)
+}
+
+/// Set the supplementary groups (privileged processes only)
+pub fn setgroups(groups: &[u32]) -> Result<(), ()> {
+    id_result(syscall2(Syscall::SetGroups, groups.len(), groups.as_ptr() as usize))
+}
+
+/// Open a process handle for a task
+///
+/// Reading the handle blocks until the task exits and yields its wait
+/// status (see `process_wait`). Signals sent with `process_signal` only
+/// ever reach this task, even once its ID is given to another task.
+///
+/// # Arguments
+/// * `pid` - The task, 0 for the calling task
+pub fn process_open(pid: usize) -> Result<Handle, ()> {
+    let res = syscall2(Syscall::ProcessOpen, pid, 0);
+    if res == usize::MAX { Err(()) } else { Ok(unsafe { Handle::from_raw(res as i32) }) }
+}
+
+/// Send a signal to the task of a process handle
+///
+/// Fails if the task has already exited.
+pub fn process_signal(process: &Handle, sig: usize) -> Result<(), ()> {
+    let res = syscall2(Syscall::ProcessSignal, process.as_raw() as usize, sig);
+    if res == usize::MAX { Err(()) } else { Ok(()) }
+}
+
+/// Wait for the task of a process handle to exit
+///
+/// Returns its wait status word, as `waitpid` would. The task is not
+/// reaped; its parent still has to wait for it.
+pub fn process_wait(process: &Handle) -> Result<i32, ()> {
+    let mut status = [0u8; 4];
+    let res = syscall3(Syscall::StreamRead, process.as_raw() as usize, status.as_mut_ptr() as usize, status.len());
+    if res == status.len() { Ok(i32::from_ne_bytes(status)) } else { Err(()) }
+}
+
+/// Return immediately if no child has changed state
+pub const WNOHANG: i32 = 1;
+/// Also report children that stopped
+pub const WUNTRACED: i32 = 2;
+/// Also report stopped children continued by SIGCONT
+pub const WCONTINUED: i32 = 8;
+
+/// Waits for a child process to change state.
 /// 
 /// # Arguments
-/// * `pid` - Process ID of the child process to wait for. If -1, wait for any child process.
-/// * `options` - Options for the waitpid syscall. (Currently not implemented and always ignored.)
+/// * `pid` - The child process to wait for. If -1, wait for any child process.
+///   If 0, wait for any child in the caller's process group; if less than -1,
+///   for any child in the process group `-pid`.
+/// * `options` - A combination of `WNOHANG`, `WUNTRACED` and `WCONTINUED`.
 /// 
 /// # Return Value
 /// (pid, status)
-/// - pid: The process ID of the child process that exited.
-/// - status: The exit status of the child process.
+/// - pid: The process ID of the child process, 0 if `WNOHANG` is given and
+///   no child has changed state, or -1 on error.
+/// - status: The wait status word; decode it with `wifexited`, `wexitstatus` and friends.
 /// 
 pub fn waitpid(pid: i32, options: i32) -> (i32, i32) {
     let mut status: i32 = 0;
@@ -355,8 +966,49 @@ pub fn waitpid(pid: i32, options: i32) -> (i32, i32) {
 /// # Return Value
 /// (pid, status)
 /// - pid: The process ID of the child process that exited.
-/// - status: The exit status of the child process.
+/// - status: The wait status word of the child process.
 /// 
 pub fn wait() -> (i32, i32) {
     waitpid(-1, 0)
+}
+
+/// Whether the child exited normally
+pub fn wifexited(status: i32) -> bool {
+    status & 0x7f == 0
+}
+
+/// Exit code of a child that exited normally
+pub fn wexitstatus(status: i32) -> i32 {
+    (status >> 8) & 0xff
+}
+
+/// Whether the child was terminated by a signal
+pub fn wifsignaled(status: i32) -> bool {
+    let sig = status & 0x7f;
+    sig != 0 && sig != 0x7f
+}
+
+/// Signal that terminated the child
+pub fn wtermsig(status: i32) -> i32 {
+    status & 0x7f
+}
+
+/// Whether the child left a core file when it was terminated
+pub fn wcoredump(status: i32) -> bool {
+    wifsignaled(status) && status & 0x80 != 0
+}
+
+/// Whether the child is stopped
+pub fn wifstopped(status: i32) -> bool {
+    status & 0xff == 0x7f
+}
+
+/// Signal that stopped the child
+pub fn wstopsig(status: i32) -> i32 {
+    wexitstatus(status)
+}
+
+/// Whether the child was continued by SIGCONT
+pub fn wifcontinued(status: i32) -> bool {
+    status == 0xffff
 }
\ No newline at end of file
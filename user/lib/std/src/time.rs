@@ -0,0 +1,137 @@
+//! Time measurement
+//!
+//! The clock counts from boot. [`Instant::now`] reads it from the vDSO
+//! without a system call when the kernel provides one; that clock advances
+//! once per timer tick (see [`resolution`]). [`Instant::now_precise`]
+//! always asks the kernel and has microsecond resolution.
+//!
+//! [`SystemTime`] is the wall-clock time, which the kernel takes from the
+//! RTC; it can be set, and so can go backwards.
+
+pub use core::time::*;
+
+use core::ops::{Add, Sub};
+
+use crate::syscall::{syscall0, syscall1, Syscall};
+use crate::vdso;
+
+/// A point of the monotonic clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    ns: u64,
+}
+
+impl Instant {
+    /// The current time, from the vDSO if present
+    pub fn now() -> Self {
+        match vdso::clock_ns() {
+            Some(ns) => Instant { ns },
+            None => Self::now_precise(),
+        }
+    }
+
+    /// The current time at full resolution, from the kernel
+    pub fn now_precise() -> Self {
+        Instant { ns: syscall0(Syscall::ClockGetTime) as u64 }
+    }
+
+    /// Time since boot
+    pub fn since_boot(&self) -> Duration {
+        Duration::from_nanos(self.ns)
+    }
+
+    /// Time elapsed since `earlier`, or zero if `earlier` is later
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_nanos(self.ns.saturating_sub(earlier.ns))
+    }
+
+    /// Time elapsed since this instant
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+        let ns = u64::try_from(duration.as_nanos()).ok()?;
+        self.ns.checked_add(ns).map(|ns| Instant { ns })
+    }
+
+    pub fn checked_sub(&self, duration: Duration) -> Option<Instant> {
+        let ns = u64::try_from(duration.as_nanos()).ok()?;
+        self.ns.checked_sub(ns).map(|ns| Instant { ns })
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, duration: Duration) -> Instant {
+        self.checked_add(duration).expect("overflow when adding duration to instant")
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, duration: Duration) -> Instant {
+        self.checked_sub(duration).expect("overflow when subtracting duration from instant")
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+/// Resolution of [`Instant::now`]
+pub fn resolution() -> Duration {
+    match vdso::resolution_ns() {
+        Some(ns) => Duration::from_nanos(ns),
+        None => Duration::from_micros(1),
+    }
+}
+
+/// A point of the wall clock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SystemTime {
+    /// Nanoseconds since the Unix epoch
+    ns: u64,
+}
+
+/// 1970-01-01 00:00:00 UTC
+pub const UNIX_EPOCH: SystemTime = SystemTime { ns: 0 };
+
+impl SystemTime {
+    /// The current wall-clock time
+    pub fn now() -> Self {
+        SystemTime { ns: syscall0(Syscall::ClockGetRealtime) as u64 }
+    }
+
+    /// The time `duration` after the Unix epoch
+    pub fn from_unix(duration: Duration) -> Option<Self> {
+        u64::try_from(duration.as_nanos()).ok().map(|ns| SystemTime { ns })
+    }
+
+    /// Time since the Unix epoch
+    pub fn since_unix_epoch(&self) -> Duration {
+        Duration::from_nanos(self.ns)
+    }
+
+    /// Time elapsed since `earlier`, or `Err` with how much earlier this is
+    pub fn duration_since(&self, earlier: SystemTime) -> Result<Duration, Duration> {
+        match self.ns.checked_sub(earlier.ns) {
+            Some(ns) => Ok(Duration::from_nanos(ns)),
+            None => Err(Duration::from_nanos(earlier.ns - self.ns)),
+        }
+    }
+
+    /// Set the wall clock to this time; the caller must be privileged
+    pub fn set(&self) -> Result<(), ()> {
+        match syscall1(Syscall::ClockSetRealtime, self.ns as usize) {
+            usize::MAX => Err(()),
+            _ => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Terminal job control and window size
+//!
+//! Control commands of TTY handles, with Linux ioctl numbers.
+
+use crate::handle::{Handle, HandleResult};
+
+pub mod tty_commands {
+    pub const TIOCSCTTY: u32 = 0x540E;
+    pub const TIOCGPGRP: u32 = 0x540F;
+    pub const TIOCSPGRP: u32 = 0x5410;
+    pub const TIOCNOTTY: u32 = 0x5422;
+    pub const TIOCGSID: u32 = 0x5429;
+    pub const TCGETS: u32 = 0x5401;
+    pub const TCSETS: u32 = 0x5402;
+    pub const TCSETSW: u32 = 0x5403;
+    pub const TCSETSF: u32 = 0x5404;
+    pub const TCFLSH: u32 = 0x540B;
+    pub const FIONREAD: u32 = 0x541B;
+    pub const TIOCGWINSZ: u32 = 0x5413;
+    pub const TIOCSWINSZ: u32 = 0x5414;
+}
+
+/// `c_lflag` bits
+pub const ISIG: u32 = 0o1;
+pub const ICANON: u32 = 0o2;
+pub const ECHO: u32 = 0o10;
+
+/// `c_iflag` bits
+pub const ICRNL: u32 = 0o400;
+pub const IXON: u32 = 0o2000;
+
+/// `c_oflag` bits
+pub const OPOST: u32 = 0o1;
+
+/// Indices of `c_cc`
+pub const VINTR: usize = 0;
+pub const VERASE: usize = 2;
+pub const VEOF: usize = 4;
+pub const VTIME: usize = 5;
+pub const VMIN: usize = 6;
+pub const VSUSP: usize = 10;
+
+/// When [`tcsetattr`] applies the settings
+pub const TCSANOW: u32 = 0;
+pub const TCSADRAIN: u32 = 1;
+pub const TCSAFLUSH: u32 = 2;
+
+/// Line discipline settings of a terminal (Linux `struct termios`)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Termios {
+    pub c_iflag: u32,
+    pub c_oflag: u32,
+    pub c_cflag: u32,
+    pub c_lflag: u32,
+    pub c_line: u8,
+    pub c_cc: [u8; 19],
+}
+
+impl Termios {
+    /// Turn the settings into raw mode: bytes are read one at a time as
+    /// they are typed, without echo, signals or translation
+    pub fn make_raw(&mut self) {
+        self.c_iflag &= !(ICRNL | IXON);
+        self.c_oflag &= !OPOST;
+        self.c_lflag &= !(ISIG | ICANON | ECHO);
+        self.c_cc[VMIN] = 1;
+        self.c_cc[VTIME] = 0;
+    }
+}
+
+/// Returns the line discipline settings of the terminal
+pub fn tcgetattr(tty: &Handle) -> HandleResult<Termios> {
+    let mut termios = Termios::default();
+    tty.control(tty_commands::TCGETS, &mut termios as *mut Termios as usize)?;
+    Ok(termios)
+}
+
+/// Set the line discipline settings of the terminal
+///
+/// `when` is [`TCSANOW`], [`TCSADRAIN`], or [`TCSAFLUSH`] to discard unread
+/// input too.
+pub fn tcsetattr(tty: &Handle, when: u32, termios: &Termios) -> HandleResult<()> {
+    let command = match when {
+        TCSADRAIN => tty_commands::TCSETSW,
+        TCSAFLUSH => tty_commands::TCSETSF,
+        _ => tty_commands::TCSETS,
+    };
+    tty.control(command, termios as *const Termios as usize).map(|_| ())
+}
+
+/// Window size of a terminal (Linux `struct winsize`)
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WinSize {
+    pub rows: u16,
+    pub cols: u16,
+    pub xpixel: u16,
+    pub ypixel: u16,
+}
+
+/// Returns the window size of the terminal
+pub fn window_size(tty: &Handle) -> HandleResult<WinSize> {
+    let mut winsize = WinSize::default();
+    tty.control(tty_commands::TIOCGWINSZ, &mut winsize as *mut WinSize as usize)?;
+    Ok(winsize)
+}
+
+/// Make the terminal the controlling terminal of the caller's session
+///
+/// The caller must be a session leader (see `task::setsid`).
+pub fn set_controlling_terminal(tty: &Handle) -> HandleResult<()> {
+    tty.control(tty_commands::TIOCSCTTY, 0).map(|_| ())
+}
+
+/// Returns the foreground process group of the terminal
+pub fn tcgetpgrp(tty: &Handle) -> HandleResult<u32> {
+    let mut pgid: i32 = 0;
+    tty.control(tty_commands::TIOCGPGRP, &mut pgid as *mut i32 as usize)?;
+    Ok(pgid as u32)
+}
+
+/// Make `pgid` the foreground process group of the terminal
+pub fn tcsetpgrp(tty: &Handle, pgid: u32) -> HandleResult<()> {
+    let pgid = pgid as i32;
+    tty.control(tty_commands::TIOCSPGRP, &pgid as *const i32 as usize).map(|_| ())
+}
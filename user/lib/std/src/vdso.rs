@@ -0,0 +1,82 @@
+//! Access to the vDSO pages of the kernel
+//!
+//! The kernel maps a clock page and a task page into every address space
+//! and passes their address to the entry point. The layout mirrors the
+//! kernel's `vm::vdso`; pages with another magic or version are not used,
+//! so callers fall back to the system calls.
+
+use core::sync::atomic::{fence, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+const VDSO_MAGIC: u32 = 0x5344_5653;
+const VDSO_VERSION: u32 = 1;
+const PAGE_SIZE: usize = 0x1000;
+
+/// The clock page
+#[repr(C)]
+struct VdsoData {
+    magic: u32,
+    version: u32,
+    seq: AtomicU32,
+    _reserved: u32,
+    time_ns: AtomicU64,
+    tick: AtomicU64,
+    resolution_ns: u64,
+}
+
+/// The task page
+#[repr(C)]
+struct VdsoTaskData {
+    pid: u64,
+}
+
+static VDSO_BASE: AtomicUsize = AtomicUsize::new(0);
+
+/// Record the vDSO address given to the entry point (0 if there is none)
+pub(crate) fn init(base: usize) {
+    if base == 0 || base % PAGE_SIZE != 0 {
+        return;
+    }
+    let data = unsafe { &*(base as *const VdsoData) };
+    if data.magic == VDSO_MAGIC && data.version == VDSO_VERSION {
+        VDSO_BASE.store(base, Ordering::Relaxed);
+    }
+}
+
+fn data() -> Option<&'static VdsoData> {
+    match VDSO_BASE.load(Ordering::Relaxed) {
+        0 => None,
+        base => Some(unsafe { &*(base as *const VdsoData) }),
+    }
+}
+
+/// Nanoseconds since boot at the last timer tick
+pub(crate) fn clock_ns() -> Option<u64> {
+    let data = data()?;
+    loop {
+        let seq = data.seq.load(Ordering::Acquire);
+        if seq & 1 != 0 {
+            core::hint::spin_loop();
+            continue;
+        }
+        let time_ns = data.time_ns.load(Ordering::Relaxed);
+        fence(Ordering::Acquire);
+        if data.seq.load(Ordering::Relaxed) == seq {
+            return Some(time_ns);
+        }
+    }
+}
+
+/// Resolution of [`clock_ns`] in nanoseconds
+pub(crate) fn resolution_ns() -> Option<u64> {
+    data().map(|data| data.resolution_ns)
+}
+
+/// ID of the process
+pub(crate) fn pid() -> Option<u32> {
+    let base = VDSO_BASE.load(Ordering::Relaxed);
+    if base == 0 {
+        return None;
+    }
+    let task = unsafe { &*((base + PAGE_SIZE) as *const VdsoTaskData) };
+    Some(task.pid as u32)
+}